@@ -0,0 +1,624 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::bytes::parse_hex;
+
+use openssl::symm::{Cipher, Crypter, Mode};
+
+use std::sync::Mutex;
+
+use cipher;
+
+// Encrypts (aesenc) or decrypts (aesdec) the stream with AES in CBC mode with PKCS7 padding or
+// in CTR mode.
+//
+// Every stream, i.e. everything from stream-start or a flush until EOS, is processed with a new
+// context starting at the configured IV. Encrypting each HLS segment as separate stream with the
+// key and IV of the segment gives the AES-128 method of HLS.
+//
+// With serialize-iv the IV is written before the encrypted data by aesenc and read from there by
+// aesdec instead of taking it from the iv property. aesenc uses a new random IV for every stream
+// then. As the keystream of CTR mode only depends on key and IV, aesenc requires serialize-iv in
+// CTR mode.
+//
+// The caps of the input are not stored, aesdec outputs application/octet-stream.
+
+const CAPS_NAME: &str = "application/x-aes-encrypted";
+
+const DEFAULT_CIPHER: &str = "aes-128-cbc";
+const DEFAULT_KEY: Option<&str> = None;
+const DEFAULT_IV: Option<&str> = None;
+const DEFAULT_SERIALIZE_IV: bool = false;
+
+static PROPERTIES: [Property; 4] = [
+    Property::String(
+        "cipher",
+        "Cipher",
+        "One of aes-128-cbc, aes-256-cbc, aes-128-ctr or aes-256-ctr",
+        Some(DEFAULT_CIPHER),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "key",
+        "Key",
+        "The key as hex string, 16 bytes for AES-128 and 32 bytes for AES-256",
+        DEFAULT_KEY,
+        PropertyMutability::Writable,
+    ),
+    Property::String(
+        "iv",
+        "IV",
+        "The 16 byte initialization vector as hex string (not allowed for aesenc in CTR mode)",
+        DEFAULT_IV,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "serialize-iv",
+        "Serialize IV",
+        "Store the IV in front of the encrypted data instead of using the iv property",
+        DEFAULT_SERIALIZE_IV,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Encrypt,
+    Decrypt,
+}
+
+#[derive(Debug, Clone)]
+struct Settings {
+    cipher: String,
+    key: Option<String>,
+    iv: Option<String>,
+    serialize_iv: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            cipher: DEFAULT_CIPHER.into(),
+            key: DEFAULT_KEY.map(String::from),
+            iv: DEFAULT_IV.map(String::from),
+            serialize_iv: DEFAULT_SERIALIZE_IV,
+        }
+    }
+}
+
+struct State {
+    cipher: Cipher,
+    key: Vec<u8>,
+    // Fixed IV, or None if it is serialized in the stream
+    iv: Option<Vec<u8>>,
+    // Context of the current stream, None until the IV is known
+    crypter: Option<Crypter>,
+    // Beginning of the stream until the serialized IV is complete
+    pending_iv: Vec<u8>,
+}
+
+struct AesCrypt {
+    cat: gst::DebugCategory,
+    direction: Direction,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl AesCrypt {
+    fn new(_element: &Element, direction: Direction, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        let (name, description) = match direction {
+            Direction::Encrypt => ("aesenc", "AES encrypter"),
+            Direction::Decrypt => ("aesdec", "AES decrypter"),
+        };
+
+        Self {
+            cat: gst::DebugCategory::new(name, gst::DebugColorFlags::empty(), description),
+            direction: direction,
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass, direction: Direction) {
+        let (sink_caps, src_caps) = match direction {
+            Direction::Encrypt => {
+                klass.set_metadata(
+                    "AES Encrypter",
+                    "Encoder/Encryptor",
+                    "Encrypts streams with AES-CBC or AES-CTR",
+                    "Sebastian Dröge <sebastian@centricular.com>",
+                );
+                (gst::Caps::new_any(), gst::Caps::new_simple(CAPS_NAME, &[]))
+            }
+            Direction::Decrypt => {
+                klass.set_metadata(
+                    "AES Decrypter",
+                    "Decoder/Decryptor",
+                    "Decrypts streams encrypted with AES-CBC or AES-CTR",
+                    "Sebastian Dröge <sebastian@centricular.com>",
+                );
+                (gst::Caps::new_simple(CAPS_NAME, &[]), gst::Caps::new_any())
+            }
+        };
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &sink_caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &src_caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element, direction: Direction) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            AesCrypt::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |crypt, element| crypt.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            AesCrypt::catch_panic_pad_function(
+                parent,
+                || false,
+                |crypt, element| crypt.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            AesCrypt::catch_panic_pad_function(
+                parent,
+                || false,
+                |crypt, element| crypt.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            AesCrypt::catch_panic_pad_function(
+                parent,
+                || false,
+                |crypt, element| crypt.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            AesCrypt::catch_panic_pad_function(
+                parent,
+                || false,
+                |crypt, element| crypt.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, direction, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let crypt = element.get_impl().downcast_ref::<AesCrypt>().unwrap();
+        element.catch_panic(fallback, |element| f(crypt, element))
+    }
+
+    fn mode(&self) -> Mode {
+        match self.direction {
+            Direction::Encrypt => Mode::Encrypt,
+            Direction::Decrypt => Mode::Decrypt,
+        }
+    }
+
+    // Starts a new stream, the context is created right away if the IV is known
+    fn reset(&self, state: &mut State) -> Result<Vec<u8>, String> {
+        state.pending_iv.clear();
+        state.crypter = None;
+
+        let mut output = Vec::new();
+        let iv = match (self.direction, state.iv.clone()) {
+            (_, Some(iv)) => iv,
+            (Direction::Encrypt, None) => {
+                // A random IV for every stream that is sent along
+                let mut iv = vec![0; cipher::IV_SIZE];
+                ::openssl::rand::rand_bytes(&mut iv)
+                    .map_err(|err| format!("Failed to generate IV: {}", err))?;
+                output.extend_from_slice(&iv);
+                iv
+            }
+            (Direction::Decrypt, None) => return Ok(output),
+        };
+
+        state.crypter = Some(cipher::new_crypter(
+            state.cipher,
+            self.mode(),
+            &state.key,
+            &iv,
+        )?);
+
+        Ok(output)
+    }
+
+    fn process(&self, state: &mut State, data: &[u8]) -> Result<Vec<u8>, String> {
+        let mut data = data;
+
+        if state.crypter.is_none() {
+            // Only the decrypter has to wait for the IV
+            let missing = cipher::IV_SIZE - state.pending_iv.len();
+            let len = missing.min(data.len());
+            state.pending_iv.extend_from_slice(&data[..len]);
+            data = &data[len..];
+
+            if state.pending_iv.len() < cipher::IV_SIZE {
+                return Ok(Vec::new());
+            }
+
+            state.crypter = Some(cipher::new_crypter(
+                state.cipher,
+                self.mode(),
+                &state.key,
+                &state.pending_iv,
+            )?);
+        }
+
+        let cipher = state.cipher;
+        cipher::update(state.crypter.as_mut().unwrap(), cipher, data)
+    }
+
+    fn finish(&self, state: &mut State) -> Result<Vec<u8>, String> {
+        let cipher = state.cipher;
+        match state.crypter.take() {
+            None if state.pending_iv.is_empty() => Ok(Vec::new()),
+            None => Err(String::from("Stream ended inside the IV")),
+            Some(mut crypter) => cipher::finalize(&mut crypter, cipher),
+        }
+    }
+
+    fn push_output(&self, output: Vec<u8>) -> gst::FlowReturn {
+        if output.is_empty() {
+            return gst::FlowReturn::Ok;
+        }
+
+        let buffer = gst::Buffer::from_slice(output).unwrap();
+        gst_log!(self.cat, obj: &self.srcpad, "Pushing buffer {:?}", buffer);
+        self.srcpad.push(buffer)
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let res = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match *state_guard {
+                None => return gst::FlowReturn::Flushing,
+                Some(ref mut state) => state,
+            };
+
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            self.process(state, map.as_slice())
+        };
+
+        match res {
+            Ok(output) => self.push_output(output),
+            Err(err) => {
+                gst_element_error!(element, gst::StreamError::Failed, ["{}", err]);
+                gst::FlowReturn::Error
+            }
+        }
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        let output = match event.view() {
+            EventView::StreamStart(..) | EventView::FlushStop(..) => {
+                match *self.state.lock().unwrap() {
+                    None => Ok(Vec::new()),
+                    Some(ref mut state) => self.reset(state),
+                }
+            }
+            EventView::Eos(..) => match *self.state.lock().unwrap() {
+                None => Ok(Vec::new()),
+                Some(ref mut state) => self.finish(state),
+            },
+            // The output is a byte stream without relation to the input format
+            EventView::Caps(..) | EventView::Segment(..) => return true,
+            _ => Ok(Vec::new()),
+        };
+
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => {
+                gst_element_error!(element, gst::StreamError::Failed, ["{}", err]);
+                return false;
+            }
+        };
+
+        if let EventView::StreamStart(..) = event.view() {
+            if !self.srcpad.push_event(event.clone()) {
+                return false;
+            }
+
+            let caps = match self.direction {
+                Direction::Encrypt => gst::Caps::new_simple(CAPS_NAME, &[]),
+                Direction::Decrypt => gst::Caps::new_simple("application/octet-stream", &[]),
+            };
+            self.srcpad.push_event(gst::Event::new_caps(&caps).build());
+            let segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+            self.srcpad
+                .push_event(gst::Event::new_segment(&segment).build());
+
+            return self.push_output(output) == gst::FlowReturn::Ok;
+        }
+
+        // The last block is output before EOS
+        if let EventView::Eos(..) = event.view() {
+            let flow_ret = self.push_output(output);
+            if flow_ret != gst::FlowReturn::Ok {
+                gst_debug!(self.cat, obj: pad, "Failed to push last block: {:?}", flow_ret);
+            }
+        }
+
+        let is_flush_stop = match event.view() {
+            EventView::FlushStop(..) => true,
+            _ => false,
+        };
+        if !self.srcpad.push_event(event) {
+            return false;
+        }
+
+        // After a flush the stream restarts with a new segment and, for aesenc, a new IV
+        if is_flush_stop {
+            let segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+            self.srcpad
+                .push_event(gst::Event::new_segment(&segment).build());
+            let _ = self.push_output(output);
+        }
+
+        true
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            // Positions in the output can't be mapped to the input
+            EventView::Seek(..) => false,
+            _ => self.sinkpad.push_event(event),
+        }
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            QueryView::Position(..) | QueryView::Duration(..) | QueryView::Seeking(..) => false,
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+
+    fn start(&self, element: &Element) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let res = cipher::get_cipher(&settings.cipher)
+            .ok_or_else(|| format!("Unsupported cipher '{}'", settings.cipher))
+            .and_then(|cipher| {
+                let key = settings
+                    .key
+                    .as_ref()
+                    .and_then(|key| parse_hex(key))
+                    .ok_or_else(|| String::from("No valid key set"))?;
+
+                let iv = if settings.serialize_iv {
+                    None
+                } else if self.direction == Direction::Encrypt && cipher::is_ctr(&settings.cipher)
+                {
+                    return Err(String::from(
+                        "CTR mode would reuse the keystream with a fixed IV, use serialize-iv",
+                    ));
+                } else {
+                    let iv = settings
+                        .iv
+                        .as_ref()
+                        .and_then(|iv| parse_hex(iv))
+                        .ok_or_else(|| String::from("No valid IV set"))?;
+                    Some(iv)
+                };
+
+                Ok(State {
+                    cipher: cipher,
+                    key: key,
+                    iv: iv,
+                    crypter: None,
+                    pending_iv: Vec::new(),
+                })
+            });
+
+        // Validate key and IV right away instead of failing with the first buffer
+        let res = res.and_then(|mut state| self.reset(&mut state).map(|_| state));
+
+        match res {
+            Err(err) => {
+                gst_element_error!(element, gst::ResourceError::Settings, ["{}", err]);
+                false
+            }
+            Ok(state) => {
+                *self.state.lock().unwrap() = Some(state);
+                true
+            }
+        }
+    }
+}
+
+impl ObjectImpl<Element> for AesCrypt {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("cipher", ..) => {
+                settings.cipher = value.get().unwrap_or_else(|| DEFAULT_CIPHER.into());
+            }
+            Property::String("key", ..) => {
+                settings.key = value.get();
+            }
+            Property::String("iv", ..) => {
+                settings.iv = value.get();
+            }
+            Property::Boolean("serialize-iv", ..) => {
+                settings.serialize_iv = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("cipher", ..) => Ok(settings.cipher.to_value()),
+            Property::String("iv", ..) => Ok(settings.iv.to_value()),
+            Property::Boolean("serialize-iv", ..) => Ok(settings.serialize_iv.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for AesCrypt {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                if !self.start(element) {
+                    return gst::StateChangeReturn::Failure;
+                }
+            }
+            gst::StateChange::PausedToReady => {
+                *self.state.lock().unwrap() = None;
+            }
+            _ => (),
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct AesEncStatic;
+
+impl ImplTypeStatic<Element> for AesEncStatic {
+    fn get_name(&self) -> &str {
+        "AesEnc"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        AesCrypt::init(element, Direction::Encrypt)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        AesCrypt::class_init(klass, Direction::Encrypt);
+    }
+}
+
+struct AesDecStatic;
+
+impl ImplTypeStatic<Element> for AesDecStatic {
+    fn get_name(&self) -> &str {
+        "AesDec"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        AesCrypt::init(element, Direction::Decrypt)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        AesCrypt::class_init(klass, Direction::Decrypt);
+    }
+}
+
+pub fn get_enc_type() -> glib::Type {
+    let aesenc_static = AesEncStatic;
+    register_type(aesenc_static)
+}
+
+pub fn get_dec_type() -> glib::Type {
+    let aesdec_static = AesDecStatic;
+    register_type(aesdec_static)
+}
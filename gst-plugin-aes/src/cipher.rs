@@ -0,0 +1,105 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use openssl::symm::{Cipher, Crypter, Mode};
+
+pub const IV_SIZE: usize = 16;
+
+// In CTR mode the same key and IV give the same keystream, so every stream encrypted with them
+// reveals the XOR of the plaintexts
+pub fn is_ctr(name: &str) -> bool {
+    name.ends_with("-ctr")
+}
+
+pub fn get_cipher(name: &str) -> Option<Cipher> {
+    match name {
+        "aes-128-cbc" => Some(Cipher::aes_128_cbc()),
+        "aes-256-cbc" => Some(Cipher::aes_256_cbc()),
+        "aes-128-ctr" => Some(Cipher::aes_128_ctr()),
+        "aes-256-ctr" => Some(Cipher::aes_256_ctr()),
+        _ => None,
+    }
+}
+
+/// Creates a new encryption or decryption context, CBC uses PKCS7 padding
+pub fn new_crypter(cipher: Cipher, mode: Mode, key: &[u8], iv: &[u8]) -> Result<Crypter, String> {
+    if key.len() != cipher.key_len() {
+        return Err(format!(
+            "Key has {} bytes instead of {}",
+            key.len(),
+            cipher.key_len()
+        ));
+    }
+    if iv.len() != IV_SIZE {
+        return Err(format!("IV has {} bytes instead of {}", iv.len(), IV_SIZE));
+    }
+
+    let mut crypter = Crypter::new(cipher, mode, key, Some(iv))
+        .map_err(|err| format!("Failed to create cipher context: {}", err))?;
+    crypter.pad(true);
+
+    Ok(crypter)
+}
+
+pub fn update(crypter: &mut Crypter, cipher: Cipher, data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = vec![0; data.len() + cipher.block_size()];
+    let len = crypter
+        .update(data, &mut output)
+        .map_err(|err| format!("Failed to process data: {}", err))?;
+    output.truncate(len);
+
+    Ok(output)
+}
+
+// Returns the last block including the padding
+pub fn finalize(crypter: &mut Crypter, cipher: Cipher) -> Result<Vec<u8>, String> {
+    let mut output = vec![0; 2 * cipher.block_size()];
+    let len = crypter
+        .finalize(&mut output)
+        .map_err(|err| format!("Failed to finish, invalid padding or wrong key: {}", err))?;
+    output.truncate(len);
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gst_plugin::bytes::parse_hex;
+
+    #[test]
+    fn test_cbc() {
+        // NIST SP 800-38A F.2.1
+        let key = parse_hex("2b7e151628aed2a6abf7158809cf4f3c").unwrap();
+        let iv = parse_hex("000102030405060708090a0b0c0d0e0f").unwrap();
+        let plaintext = parse_hex("6bc1bee22e409f96e93d7e117393172a").unwrap();
+        let cipher = get_cipher("aes-128-cbc").unwrap();
+
+        let mut enc = new_crypter(cipher, Mode::Encrypt, &key, &iv).unwrap();
+        let mut ciphertext = update(&mut enc, cipher, &plaintext[..7]).unwrap();
+        ciphertext.extend(update(&mut enc, cipher, &plaintext[7..]).unwrap());
+        ciphertext.extend(finalize(&mut enc, cipher).unwrap());
+
+        // One additional block of padding
+        assert_eq!(ciphertext.len(), 32);
+        assert_eq!(
+            &ciphertext[..16],
+            &parse_hex("7649abac8119b246cee98e9b12e9197d").unwrap()[..]
+        );
+
+        let mut dec = new_crypter(cipher, Mode::Decrypt, &key, &iv).unwrap();
+        let mut decrypted = Vec::new();
+        for chunk in ciphertext.chunks(5) {
+            decrypted.extend(update(&mut dec, cipher, chunk).unwrap());
+        }
+        decrypted.extend(finalize(&mut dec, cipher).unwrap());
+        assert_eq!(decrypted, plaintext);
+
+        assert!(new_crypter(cipher, Mode::Encrypt, &key[1..], &iv).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate glib;
+#[macro_use]
+extern crate gst_plugin;
+#[macro_use]
+extern crate gstreamer as gst;
+extern crate openssl;
+
+use gst_plugin::registration::*;
+
+mod cipher;
+
+mod aescrypt;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    ElementRegistration::new(plugin)
+        .element("aesenc", RANK_NONE, aescrypt::get_enc_type())
+        .element("aesdec", RANK_NONE, aescrypt::get_dec_type())
+        .register()
+}
+
+plugin_define!(
+    "rsaes",
+    "Rust AES Plugin",
+    plugin_init,
+    "MIT/X11",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2018-01-22"
+);
@@ -0,0 +1,971 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_audio;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::{i32, u64};
+
+use byte_slice_cast::*;
+
+const DEFAULT_LATENCY: u64 = 200 * gst::MSECOND_VAL;
+const DEFAULT_MASTER_PAD: Option<&str> = None;
+
+// Gaps between buffers of an input that are filled with silence
+const GAP_TOLERANCE: u64 = 20 * gst::MSECOND_VAL;
+// Minimum amount of data before the sample rate of an input is estimated
+const MIN_DRIFT_WINDOW: u64 = gst::SECOND_VAL;
+// Maximum supported drift between two devices
+const MAX_DRIFT: f64 = 0.02;
+
+static PROPERTIES: [Property; 2] = [
+    Property::UInt64(
+        "latency",
+        "Latency",
+        "Time to wait for late inputs before their data is replaced by silence (in nanoseconds)",
+        (0, u64::MAX),
+        DEFAULT_LATENCY,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "master-pad",
+        "Master Pad",
+        "Name of the input whose clock is followed, all others are resampled (default: first)",
+        DEFAULT_MASTER_PAD,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    latency: u64,
+    master_pad: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            latency: DEFAULT_LATENCY,
+            master_pad: DEFAULT_MASTER_PAD.map(String::from),
+        }
+    }
+}
+
+// Measures the actual sample rate of a device against the running time
+#[derive(Debug, Default, Clone, Copy)]
+struct DriftEstimator {
+    start: Option<u64>,
+    end: u64,
+    frames: u64,
+}
+
+impl DriftEstimator {
+    fn add(&mut self, start: u64, end: u64, frames: u64) {
+        if self.start.is_none() {
+            self.start = Some(start);
+        }
+        self.end = end;
+        self.frames += frames;
+    }
+
+    fn rate(&self) -> Option<f64> {
+        let start = self.start?;
+        if self.end < start + MIN_DRIFT_WINDOW {
+            return None;
+        }
+
+        Some(self.frames as f64 * gst::SECOND_VAL as f64 / (self.end - start) as f64)
+    }
+}
+
+// Interleaved samples of one input with a fractional read position for resampling
+#[derive(Debug, Default)]
+struct SampleQueue {
+    channels: usize,
+    samples: VecDeque<f32>,
+    position: f64,
+}
+
+impl SampleQueue {
+    fn frames(&self) -> usize {
+        if self.channels == 0 {
+            0
+        } else {
+            self.samples.len() / self.channels
+        }
+    }
+
+    // Frames that can be read without resampling
+    fn available(&self) -> usize {
+        self.frames().saturating_sub(self.position.ceil() as usize)
+    }
+
+    fn push(&mut self, data: &[f32]) {
+        self.samples.extend(data.iter());
+    }
+
+    fn push_silence(&mut self, frames: usize) {
+        let len = self.samples.len() + frames * self.channels;
+        self.samples.resize(len, 0.0);
+    }
+
+    // Skips frames, or inserts silence for negative values
+    fn skip(&mut self, frames: i64) {
+        if frames >= 0 {
+            self.position += frames as f64;
+            self.drop_consumed();
+        } else {
+            for _ in 0..(-frames * self.channels as i64) {
+                self.samples.push_front(0.0);
+            }
+        }
+    }
+
+    // Whether `n` frames can be read when advancing by `ratio` input frames per output frame
+    fn can_read(&self, n: usize, ratio: f64) -> bool {
+        ((self.position + (n as f64) * ratio).ceil() as usize) < self.frames()
+    }
+
+    // Reads `n` frames with linear interpolation into the channels starting at
+    // `channel_offset` of the interleaved `out`. Missing data is silence.
+    fn read(
+        &mut self,
+        n: usize,
+        ratio: f64,
+        out: &mut [f32],
+        out_channels: usize,
+        channel_offset: usize,
+    ) {
+        let frames = self.frames();
+        for i in 0..n {
+            let pos = self.position + (i as f64) * ratio;
+            let idx = pos.floor() as usize;
+            let frac = (pos - pos.floor()) as f32;
+
+            for c in 0..self.channels {
+                let a = if idx < frames {
+                    self.samples[idx * self.channels + c]
+                } else {
+                    0.0
+                };
+                let b = if idx + 1 < frames {
+                    self.samples[(idx + 1) * self.channels + c]
+                } else {
+                    a
+                };
+                out[i * out_channels + channel_offset + c] = a + (b - a) * frac;
+            }
+        }
+
+        self.position += (n as f64) * ratio;
+        self.drop_consumed();
+    }
+
+    fn drop_consumed(&mut self) {
+        let consumed = self.position.floor() as usize;
+        let frames = self.frames();
+        if consumed >= frames {
+            self.samples.clear();
+            self.position = 0.0;
+        } else {
+            self.samples.drain(..consumed * self.channels);
+            self.position -= consumed as f64;
+        }
+    }
+}
+
+struct Input {
+    sinkpad: gst::Pad,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    rate: u32,
+    queue: SampleQueue,
+    // Running time right after the last queued frame
+    end_time: Option<u64>,
+    drift: DriftEstimator,
+    // Aligned to the output by the timestamps
+    aligned: bool,
+    eos: bool,
+}
+
+impl Input {
+    fn new(sinkpad: gst::Pad) -> Input {
+        Input {
+            sinkpad: sinkpad,
+            segment: gst::FormattedSegment::new(),
+            rate: 0,
+            queue: SampleQueue::default(),
+            end_time: None,
+            drift: DriftEstimator::default(),
+            aligned: false,
+            eos: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Input::new(self.sinkpad.clone());
+    }
+
+    // Running time of the next frame to be read
+    fn head_time(&self) -> Option<u64> {
+        let end_time = self.end_time?;
+        let queued = self.queue.frames() as f64 - self.queue.position;
+        let queued = (queued * gst::SECOND_VAL as f64 / self.rate as f64) as u64;
+        Some(end_time.saturating_sub(queued))
+    }
+
+    fn frames_for(&self, duration: u64) -> u64 {
+        duration * u64::from(self.rate) / gst::SECOND_VAL
+    }
+
+    // Input frames per master frame
+    fn ratio(&self, master_rate: Option<f64>) -> f64 {
+        match (self.drift.rate(), master_rate) {
+            (Some(rate), Some(master_rate)) => (rate / master_rate)
+                .max(1.0 - MAX_DRIFT)
+                .min(1.0 + MAX_DRIFT),
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    inputs: Vec<Input>,
+    pad_count: u32,
+    started: bool,
+    // Channels of the output when the caps were last sent
+    out_channels: usize,
+    // Running time of the next output frame
+    out_time: Option<u64>,
+    last_report: Option<u64>,
+}
+
+struct AudioAligner {
+    cat: gst::DebugCategory,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    output_lock: Mutex<()>,
+}
+
+// Output of one processing step, pushed after the state lock is released
+#[derive(Default)]
+struct Output {
+    events: Vec<gst::Event>,
+    buffers: Vec<gst::Buffer>,
+    messages: Vec<gst::Message>,
+}
+
+// Message posted about once per second for every input except the master:
+//
+// "audioaligner, pad=(string)..., ratio=(double)..."
+//
+// The ratio is the estimated number of input samples per master sample.
+fn create_message(element: &Element, pad: &gst::Pad, ratio: f64) -> gst::Message {
+    let s = gst::Structure::new(
+        "audioaligner",
+        &[("pad", &pad.get_name()), ("ratio", &ratio)],
+    );
+    gst::Message::new_element(s).src(Some(element)).build()
+}
+
+impl AudioAligner {
+    fn new(_element: &Element, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rsaudioaligner",
+                gst::DebugColorFlags::empty(),
+                "Rust audio aligner",
+            ),
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+            output_lock: Mutex::new(()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Audio aligner",
+            "Filter/Audio",
+            "Aligns the same stream captured by multiple devices and compensates their clock drift",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &gst_audio::AUDIO_FORMAT_F32.to_string()),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+        srcpad.set_event_function(|pad, parent, event| {
+            AudioAligner::catch_panic_pad_function(
+                parent,
+                || false,
+                |aligner, element| aligner.src_event(pad, element, event),
+            )
+        });
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let aligner = element.get_impl().downcast_ref::<AudioAligner>().unwrap();
+        element.catch_panic(fallback, |element| f(aligner, element))
+    }
+
+    fn master_index(&self, settings: &Settings, state: &State) -> Option<usize> {
+        match settings.master_pad {
+            Some(ref name) => state
+                .inputs
+                .iter()
+                .position(|i| i.sinkpad.get_name() == *name),
+            None => if state.inputs.is_empty() {
+                None
+            } else {
+                Some(0)
+            },
+        }
+    }
+
+    // Outputs as much as possible. Late inputs are waited for up to the latency unless `drain`
+    // is set.
+    fn process(
+        &self,
+        element: &Element,
+        settings: &Settings,
+        state: &mut State,
+        drain: bool,
+        output: &mut Output,
+    ) {
+        let master_idx = match self.master_index(settings, state) {
+            None => return,
+            Some(idx) => idx,
+        };
+        let rate = state.inputs[master_idx].rate;
+        if rate == 0 {
+            return;
+        }
+
+        let master_rate = state.inputs[master_idx].drift.rate();
+        let master_end = state.inputs[master_idx].end_time;
+
+        loop {
+            let n = state.inputs[master_idx].queue.available();
+            let out_time = match state.inputs[master_idx].head_time() {
+                Some(time) if n > 0 => time,
+                _ => break,
+            };
+
+            // Wait for data from the other inputs, but not longer than the latency
+            let mut ready = true;
+            for (idx, input) in state.inputs.iter_mut().enumerate() {
+                if idx == master_idx || input.eos || input.rate == 0 {
+                    continue;
+                }
+
+                if input.rate != rate {
+                    continue;
+                }
+
+                if !input.aligned {
+                    if let Some(head_time) = input.head_time() {
+                        let diff = out_time as i64 - head_time as i64;
+                        let frames = diff * i64::from(rate) / gst::SECOND_VAL as i64;
+                        gst_debug!(
+                            self.cat,
+                            obj: &input.sinkpad,
+                            "Aligning by {} frames",
+                            frames
+                        );
+                        input.queue.skip(frames);
+                        input.aligned = true;
+                    }
+                }
+
+                let ratio = input.ratio(master_rate);
+                let late = match (master_end, input.end_time) {
+                    (Some(master_end), Some(end)) => master_end > end + settings.latency,
+                    (Some(master_end), None) => master_end > out_time + settings.latency,
+                    _ => false,
+                };
+
+                if !input.queue.can_read(n, ratio) && !late && !drain {
+                    ready = false;
+                }
+            }
+
+            if !ready {
+                break;
+            }
+
+            let out_channels = state
+                .inputs
+                .iter()
+                .filter(|i| i.rate == rate)
+                .map(|i| i.queue.channels)
+                .sum::<usize>();
+
+            if !state.started || out_channels != state.out_channels {
+                if !state.started {
+                    output.events.push(
+                        gst::Event::new_stream_start(&element.get_name()).build(),
+                    );
+                }
+
+                let caps = gst::Caps::new_simple(
+                    "audio/x-raw",
+                    &[
+                        ("format", &gst_audio::AUDIO_FORMAT_F32.to_string()),
+                        ("rate", &(rate as i32)),
+                        ("channels", &(out_channels as i32)),
+                        ("layout", &"interleaved"),
+                        ("channel-mask", &gst::Bitmask::new(0)),
+                    ],
+                );
+                output.events.push(gst::Event::new_caps(&caps).build());
+
+                if !state.started {
+                    // Output buffers are timestamped with the running time
+                    let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+                    output
+                        .events
+                        .push(gst::Event::new_segment(&segment).build());
+                }
+
+                state.started = true;
+                state.out_channels = out_channels;
+            }
+
+            let mut data = vec![0.0f32; n * out_channels];
+            let mut channel_offset = 0;
+            let report = state
+                .last_report
+                .map(|last| out_time >= last + gst::SECOND_VAL)
+                .unwrap_or(true);
+            for (idx, input) in state.inputs.iter_mut().enumerate() {
+                if input.rate != rate {
+                    continue;
+                }
+
+                let ratio = if idx == master_idx {
+                    1.0
+                } else {
+                    input.ratio(master_rate)
+                };
+
+                if report && idx != master_idx {
+                    gst_debug!(self.cat, obj: &input.sinkpad, "Resampling ratio {}", ratio);
+                    output
+                        .messages
+                        .push(create_message(element, &input.sinkpad, ratio));
+                }
+
+                let underrun = !input.queue.can_read(n, ratio);
+                input
+                    .queue
+                    .read(n, ratio, &mut data, out_channels, channel_offset);
+                channel_offset += input.queue.channels;
+
+                // The position is meaningless after missing data, align again with the next data
+                if underrun && idx != master_idx {
+                    input.aligned = false;
+                }
+            }
+            if report {
+                state.last_report = Some(out_time);
+            }
+
+            let duration = n as u64 * gst::SECOND_VAL / u64::from(rate);
+            let mut buffer = gst::Buffer::from_mut_slice(data.as_byte_slice().to_vec()).unwrap();
+            {
+                let buffer = buffer.get_mut().unwrap();
+                buffer.set_pts(gst::ClockTime(Some(out_time)));
+                buffer.set_duration(gst::ClockTime(Some(duration)));
+                if state.out_time.is_none() {
+                    buffer.set_flags(gst::BufferFlags::DISCONT);
+                }
+            }
+            state.out_time = Some(out_time + duration);
+            output.buffers.push(buffer);
+        }
+    }
+
+    fn push_output(&self, element: &Element, output: Output) -> gst::FlowReturn {
+        for message in output.messages {
+            let _ = element.post_message(&message);
+        }
+
+        for event in output.events {
+            self.srcpad.push_event(event);
+        }
+
+        for buffer in output.buffers {
+            let flow_ret = self.srcpad.push(buffer);
+            if flow_ret != gst::FlowReturn::Ok {
+                return flow_ret;
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let settings = self.settings.lock().unwrap().clone();
+        let _output_lock = self.output_lock.lock().unwrap();
+        let mut output = Output::default();
+
+        {
+            let mut state = self.state.lock().unwrap();
+
+            let idx = match state.inputs.iter().position(|i| i.sinkpad == *pad) {
+                None => return gst::FlowReturn::Error,
+                Some(idx) => idx,
+            };
+
+            {
+                let input = &mut state.inputs[idx];
+                if input.rate == 0 {
+                    gst_element_error!(
+                        element,
+                        gst::CoreError::Negotiation,
+                        ["Buffer before caps on {}", pad.get_name()]
+                    );
+                    return gst::FlowReturn::NotNegotiated;
+                }
+
+                let map = match buffer.map_readable() {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Failed,
+                            ["Failed to map buffer"]
+                        );
+                        return gst::FlowReturn::Error;
+                    }
+                    Some(map) => map,
+                };
+                let data = match map.as_slice().as_slice_of::<f32>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Invalid buffer size {}", map.get_size()]
+                        );
+                        return gst::FlowReturn::Error;
+                    }
+                    Ok(data) => data,
+                };
+                let frames = data.len() / input.queue.channels;
+
+                let start = match input.segment.to_running_time(buffer.get_pts()).0 {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Buffer without PTS"]
+                        );
+                        return gst::FlowReturn::Error;
+                    }
+                    Some(start) => start,
+                };
+                let end = start + frames as u64 * gst::SECOND_VAL / u64::from(input.rate);
+
+                // Gaps in the input, e.g. from dropped samples, are filled with silence so
+                // that the data stays aligned to the timestamps
+                if let Some(expected) = input.end_time {
+                    if start > expected + GAP_TOLERANCE {
+                        let gap = input.frames_for(start - expected) as usize;
+                        gst_debug!(self.cat, obj: pad, "Filling gap of {} frames", gap);
+                        input.queue.push_silence(gap);
+                    }
+                }
+
+                input.queue.push(data);
+                input.end_time = Some(end);
+                input.drift.add(start, end, frames as u64);
+            }
+
+            self.process(element, &settings, &mut state, false, &mut output);
+        }
+
+        self.push_output(element, output)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let info = match gst_audio::AudioInfo::from_caps(e.get_caps()) {
+                    None => return false,
+                    Some(info) => info,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                let rates_match = state
+                    .inputs
+                    .iter()
+                    .filter(|i| i.sinkpad != *pad && i.rate != 0)
+                    .all(|i| i.rate == info.rate());
+                if !rates_match {
+                    gst_element_error!(
+                        element,
+                        gst::CoreError::Negotiation,
+                        ["All inputs must have the same sample rate"]
+                    );
+                    return false;
+                }
+
+                if let Some(input) = state.inputs.iter_mut().find(|i| i.sinkpad == *pad) {
+                    gst_debug!(self.cat, obj: pad, "Configured {:?}", info);
+                    input.rate = info.rate();
+                    input.queue = SampleQueue {
+                        channels: info.channels() as usize,
+                        ..SampleQueue::default()
+                    };
+                    input.end_time = None;
+                    input.aligned = false;
+                }
+                true
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                if let Some(input) = state.inputs.iter_mut().find(|i| i.sinkpad == *pad) {
+                    input.segment = segment;
+                }
+                true
+            }
+            EventView::Gap(e) => {
+                let (timestamp, duration) = e.get();
+                let mut state = self.state.lock().unwrap();
+                if let Some(input) = state.inputs.iter_mut().find(|i| i.sinkpad == *pad) {
+                    let start = input.segment.to_running_time(timestamp).0;
+                    if let (Some(start), Some(duration), true) =
+                        (start, duration.0, input.rate != 0)
+                    {
+                        let frames = input.frames_for(duration) as usize;
+                        gst_debug!(self.cat, obj: pad, "Gap of {} frames", frames);
+                        if let Some(expected) = input.end_time {
+                            if start > expected + GAP_TOLERANCE {
+                                let gap = input.frames_for(start - expected) as usize;
+                                input.queue.push_silence(gap);
+                            }
+                        }
+                        input.queue.push_silence(frames);
+                        input.end_time = Some(start + duration);
+                    }
+                }
+                true
+            }
+            // The output has its own stream-start, caps and segment
+            EventView::StreamStart(..) => true,
+            EventView::Eos(..) => {
+                let settings = self.settings.lock().unwrap().clone();
+                let _output_lock = self.output_lock.lock().unwrap();
+                let mut output = Output::default();
+
+                // Output ends with the master input, the others are silent after their EOS
+                let master_eos = {
+                    let mut state = self.state.lock().unwrap();
+                    if let Some(input) = state.inputs.iter_mut().find(|i| i.sinkpad == *pad) {
+                        input.eos = true;
+                    }
+
+                    let master_eos = self.master_index(&settings, &state)
+                        .map(|idx| state.inputs[idx].eos)
+                        .unwrap_or(true);
+                    if master_eos {
+                        self.process(element, &settings, &mut state, true, &mut output);
+                    }
+                    master_eos
+                };
+
+                self.push_output(element, output);
+                if master_eos {
+                    gst_debug!(self.cat, obj: element, "Master input is EOS");
+                    self.srcpad.push_event(event);
+                }
+                true
+            }
+            EventView::FlushStop(..) => {
+                {
+                    let mut state = self.state.lock().unwrap();
+                    if let Some(input) = state.inputs.iter_mut().find(|i| i.sinkpad == *pad) {
+                        let segment = input.segment.clone();
+                        input.reset();
+                        input.segment = segment;
+                    }
+                }
+                self.srcpad.push_event(event)
+            }
+            _ => self.srcpad.push_event(event),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Seek(..) => {
+                gst_debug!(self.cat, obj: pad, "Seeking is not supported");
+                false
+            }
+            _ => pad.event_default(Some(&element.clone().upcast()), event),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for AudioAligner {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt64("latency", ..) => {
+                settings.latency = value.get().unwrap();
+            }
+            Property::String("master-pad", ..) => {
+                settings.master_pad = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt64("latency", ..) => Ok(settings.latency.to_value()),
+            Property::String("master-pad", ..) => Ok(settings.master_pad.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for AudioAligner {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        if transition == gst::StateChange::PausedToReady {
+            let mut state = self.state.lock().unwrap();
+            state.started = false;
+            state.out_channels = 0;
+            state.out_time = None;
+            state.last_report = None;
+            for input in &mut state.inputs {
+                input.reset();
+            }
+        }
+
+        ret
+    }
+
+    fn request_new_pad(
+        &self,
+        element: &Element,
+        templ: &gst::PadTemplate,
+        _name: Option<String>,
+        _caps: Option<&gst::CapsRef>,
+    ) -> Option<gst::Pad> {
+        let mut state = self.state.lock().unwrap();
+
+        let id = state.pad_count;
+        state.pad_count += 1;
+
+        let name = format!("sink_{}", id);
+        let sinkpad = gst::Pad::new_from_template(templ, name.as_str());
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            AudioAligner::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |aligner, element| aligner.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            AudioAligner::catch_panic_pad_function(
+                parent,
+                || false,
+                |aligner, element| aligner.sink_event(pad, element, event),
+            )
+        });
+
+        sinkpad.set_active(true).unwrap();
+        element.add_pad(&sinkpad).unwrap();
+
+        state.inputs.push(Input::new(sinkpad.clone()));
+
+        Some(sinkpad)
+    }
+
+    fn release_pad(&self, element: &Element, pad: &gst::Pad) {
+        let mut state = self.state.lock().unwrap();
+
+        let pos = match state.inputs.iter().position(|i| i.sinkpad == *pad) {
+            None => return,
+            Some(pos) => pos,
+        };
+        let input = state.inputs.remove(pos);
+        drop(state);
+
+        input.sinkpad.set_active(false).unwrap();
+        element.remove_pad(&input.sinkpad).unwrap();
+    }
+}
+
+struct AudioAlignerStatic;
+
+impl ImplTypeStatic<Element> for AudioAlignerStatic {
+    fn get_name(&self) -> &str {
+        "AudioAligner"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        AudioAligner::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        AudioAligner::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let audioaligner_static = AudioAlignerStatic;
+    register_type(audioaligner_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(channels: usize, data: &[f32]) -> SampleQueue {
+        let mut queue = SampleQueue {
+            channels: channels,
+            ..SampleQueue::default()
+        };
+        queue.push(data);
+        queue
+    }
+
+    #[test]
+    fn test_resample() {
+        let mut q = queue(1, &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert!(q.can_read(2, 1.0));
+        let mut out = [0.0; 4];
+        q.read(2, 1.0, &mut out, 2, 1);
+        assert_eq!(out, [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(q.frames(), 4);
+
+        // Input clock running slower than the master
+        let mut out = [0.0; 3];
+        q.read(3, 0.5, &mut out, 1, 0);
+        assert_eq!(out, [2.0, 2.5, 3.0]);
+        assert_eq!(q.available(), 2);
+        assert!((q.position - 0.5).abs() < 1e-9);
+
+        // Silence is inserted before the data
+        q.skip(-1);
+        assert_eq!(q.frames(), 4);
+        assert!(!q.can_read(4, 1.0));
+    }
+
+    #[test]
+    fn test_drift() {
+        let mut drift = DriftEstimator::default();
+        let rate = 48_010;
+        for i in 0..9 {
+            let start = i * 100 * gst::MSECOND_VAL;
+            drift.add(start, start + 100 * gst::MSECOND_VAL, rate / 10);
+        }
+        assert!(drift.rate().is_none());
+        drift.add(
+            900 * gst::MSECOND_VAL,
+            gst::SECOND_VAL,
+            rate / 10,
+        );
+        let measured = drift.rate().unwrap();
+        assert!((measured - rate as f64).abs() < 1.0);
+    }
+}
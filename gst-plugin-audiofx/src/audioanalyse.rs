@@ -0,0 +1,612 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_audio;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+use gst_plugin::meta;
+
+use std::{f64, i32, u64};
+use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
+
+use byte_slice_cast::*;
+
+// Audio analysis for broadcast monitoring:
+//
+// For every buffer the DC offset, the number of clipped samples and the RMS level of each
+// channel are measured. A buffer is silent if the RMS of all channels is below the
+// silence-threshold. The statistics are attached to every buffer as an "audioanalyse"
+// structure meta (see `gst_plugin::meta`), are posted as element messages of the same
+// structure and can additionally be appended to a CSV file for later evaluation.
+//
+// Once the silence lasted for silence-duration, the "silence-detected" signal is emitted once
+// with the running time at which the silence started, so that automation can switch to a
+// fallback source.
+
+const DEFAULT_SILENCE_THRESHOLD: f64 = -60.0;
+const DEFAULT_SILENCE_DURATION: u64 = 2 * gst::SECOND_VAL;
+const DEFAULT_CLIP_LEVEL: f64 = 0.999;
+const DEFAULT_POST_MESSAGES: bool = true;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    silence_threshold: f64,
+    silence_duration: u64,
+    clip_level: f64,
+    post_messages: bool,
+    location: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            silence_threshold: DEFAULT_SILENCE_THRESHOLD,
+            silence_duration: DEFAULT_SILENCE_DURATION,
+            clip_level: DEFAULT_CLIP_LEVEL,
+            post_messages: DEFAULT_POST_MESSAGES,
+            location: None,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 5] = [
+    Property::Double(
+        "silence-threshold",
+        "Silence Threshold",
+        "RMS level in dB below which a buffer is considered silent",
+        (-200.0, 0.0),
+        DEFAULT_SILENCE_THRESHOLD,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "silence-duration",
+        "Silence Duration",
+        "Time in nanoseconds of silence after which silence-detected is emitted",
+        (0, u64::MAX),
+        DEFAULT_SILENCE_DURATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "clip-level",
+        "Clip Level",
+        "Absolute sample value relative to full scale from which samples count as clipped",
+        (0.0, 1.0),
+        DEFAULT_CLIP_LEVEL,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "post-messages",
+        "Post Messages",
+        "Post an element message with the statistics of every buffer",
+        DEFAULT_POST_MESSAGES,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "location",
+        "Location",
+        "CSV file to write the statistics to (can't be changed in PLAYING or PAUSED state)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+const CSV_HEADER: &str = "running-time,duration,channel,dc-offset,clipped,rms,silent\n";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Stats {
+    running_time: u64,
+    duration: u64,
+    // Per channel
+    dc_offset: Vec<f64>,
+    clipped: Vec<u64>,
+    rms: Vec<f64>,
+    silent: bool,
+}
+
+impl Stats {
+    fn new<T: Copy, F: Fn(T) -> f64>(
+        data: &[T],
+        channels: usize,
+        normalize: F,
+        settings: &Settings,
+    ) -> Self {
+        let mut sums = vec![0.0; channels];
+        let mut squares = vec![0.0; channels];
+        let mut clipped = vec![0; channels];
+        let mut frames = 0u32;
+
+        for frame in data.chunks(channels) {
+            for (c, &sample) in frame.iter().enumerate() {
+                let sample = normalize(sample);
+                sums[c] += sample;
+                squares[c] += sample * sample;
+                if sample.abs() >= settings.clip_level {
+                    clipped[c] += 1;
+                }
+            }
+            frames += 1;
+        }
+
+        let frames = f64::from(frames).max(1.0);
+        let rms = squares
+            .iter()
+            .map(|s| {
+                let power = s / frames;
+                if power > 0.0 {
+                    10.0 * power.log10()
+                } else {
+                    f64::NEG_INFINITY
+                }
+            })
+            .collect::<Vec<_>>();
+        let silent = rms.iter().all(|&rms| rms < settings.silence_threshold);
+
+        Stats {
+            running_time: 0,
+            duration: 0,
+            dc_offset: sums.iter().map(|s| s / frames).collect(),
+            clipped: clipped,
+            rms: rms,
+            silent: silent,
+        }
+    }
+
+    // Structure attached to and posted for every buffer:
+    //
+    // "audioanalyse, running-time=(guint64)..., duration=(guint64)...,
+    //  dc-offset=(double)<...>, clipped=(guint64)<...>, rms=(double)<...>, silent=(boolean)..."
+    //
+    // with one value per channel in the arrays.
+    fn to_structure(&self) -> gst::Structure {
+        gst::Structure::new(
+            "audioanalyse",
+            &[
+                ("running-time", &self.running_time),
+                ("duration", &self.duration),
+                (
+                    "dc-offset",
+                    &gst::Array::from_owned(
+                        self.dc_offset.iter().map(|v| v.to_send_value()).collect(),
+                    ),
+                ),
+                (
+                    "clipped",
+                    &gst::Array::from_owned(
+                        self.clipped.iter().map(|v| v.to_send_value()).collect(),
+                    ),
+                ),
+                (
+                    "rms",
+                    &gst::Array::from_owned(self.rms.iter().map(|v| v.to_send_value()).collect()),
+                ),
+                ("silent", &self.silent),
+            ],
+        )
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        for c in 0..self.rms.len() {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{}\n",
+                self.running_time,
+                self.duration,
+                c,
+                self.dc_offset[c],
+                self.clipped[c],
+                self.rms[c],
+                self.silent
+            ));
+        }
+        csv
+    }
+}
+
+struct State {
+    info: Option<gst_audio::AudioInfo>,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    csv: Option<File>,
+    // Running time at which the current silence started and whether it was signalled already
+    silence_start: Option<u64>,
+    silence_signalled: bool,
+    // End of the last buffer, for buffers without timestamp
+    position: u64,
+}
+
+impl State {
+    // Returns the running time of the silence start if silence-detected has to be emitted
+    fn update_silence(&mut self, stats: &Stats, settings: &Settings) -> Option<u64> {
+        if !stats.silent {
+            self.silence_start = None;
+            self.silence_signalled = false;
+            return None;
+        }
+
+        let start = *self.silence_start.get_or_insert(stats.running_time);
+        if !self.silence_signalled
+            && stats.running_time + stats.duration - start >= settings.silence_duration
+        {
+            self.silence_signalled = true;
+            Some(start)
+        } else {
+            None
+        }
+    }
+}
+
+struct AudioAnalyse {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl AudioAnalyse {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "audioanalyse",
+                gst::DebugColorFlags::empty(),
+                "Audio analysis",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Audio Analyse",
+            "Filter/Analyzer/Audio",
+            "Measures DC offset, clipping and silence of an audio stream",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                (
+                    "format",
+                    &gst::List::new(&[
+                        &gst_audio::AUDIO_FORMAT_S16.to_string(),
+                        &gst_audio::AUDIO_FORMAT_S32.to_string(),
+                        &gst_audio::AUDIO_FORMAT_F32.to_string(),
+                        &gst_audio::AUDIO_FORMAT_F64.to_string(),
+                    ]),
+                ),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.add_signal("silence-detected", &[u64::static_type()], glib::Type::Unit);
+
+        // Never passthrough, the buffers have to be writable for attaching the meta
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        meta::register();
+
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseTransform> for AudioAnalyse {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Double("silence-threshold", ..) => {
+                settings.silence_threshold = value.get().unwrap();
+            }
+            Property::UInt64("silence-duration", ..) => {
+                settings.silence_duration = value.get().unwrap();
+            }
+            Property::Double("clip-level", ..) => {
+                settings.clip_level = value.get().unwrap();
+            }
+            Property::Boolean("post-messages", ..) => {
+                settings.post_messages = value.get().unwrap();
+            }
+            Property::String("location", ..) => {
+                if self.state.lock().unwrap().is_none() {
+                    settings.location = value.get();
+                }
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Double("silence-threshold", ..) => Ok(settings.silence_threshold.to_value()),
+            Property::UInt64("silence-duration", ..) => Ok(settings.silence_duration.to_value()),
+            Property::Double("clip-level", ..) => Ok(settings.clip_level.to_value()),
+            Property::Boolean("post-messages", ..) => Ok(settings.post_messages.to_value()),
+            Property::String("location", ..) => Ok(settings.location.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for AudioAnalyse {}
+
+impl BaseTransformImpl<BaseTransform> for AudioAnalyse {
+    fn start(&self, element: &BaseTransform) -> bool {
+        let location = self.settings.lock().unwrap().location.clone();
+
+        let csv = match location {
+            None => None,
+            Some(location) => {
+                let res = File::create(&location)
+                    .and_then(|mut file| file.write_all(CSV_HEADER.as_bytes()).map(|_| file));
+                match res {
+                    Ok(file) => Some(file),
+                    Err(err) => {
+                        gst_element_error!(
+                            element,
+                            gst::ResourceError::OpenWrite,
+                            ["Failed to create {}: {}", location, err]
+                        );
+                        return false;
+                    }
+                }
+            }
+        };
+
+        *self.state.lock().unwrap() = Some(State {
+            info: None,
+            segment: gst::FormattedSegment::new(),
+            csv: csv,
+            silence_start: None,
+            silence_signalled: false,
+            position: 0,
+        });
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseTransform) -> bool {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        true
+    }
+
+    fn set_caps(&self, element: &BaseTransform, incaps: &gst::Caps, outcaps: &gst::Caps) -> bool {
+        if incaps != outcaps {
+            return false;
+        }
+
+        let info = match gst_audio::AudioInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", incaps);
+
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.info = Some(info);
+        }
+
+        true
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(ref mut state) = *state {
+                    state.segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                        Ok(segment) => segment,
+                        Err(_) => {
+                            gst_warning!(self.cat, obj: element, "Not a time segment");
+                            gst::FormattedSegment::new()
+                        }
+                    };
+                }
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(ref mut state) = *state {
+                    state.silence_start = None;
+                    state.silence_signalled = false;
+                    state.position = 0;
+                }
+            }
+            _ => (),
+        }
+
+        element.parent_sink_event(event)
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let (stats, silence_start) = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match *state_guard {
+                None => return gst::FlowReturn::NotNegotiated,
+                Some(ref mut state) => state,
+            };
+            let info = match state.info {
+                None => return gst::FlowReturn::NotNegotiated,
+                Some(ref info) => info.clone(),
+            };
+
+            let map = match buf.map_readable() {
+                None => return gst::FlowReturn::Error,
+                Some(map) => map,
+            };
+
+            let channels = info.channels() as usize;
+            let mut stats = match info.format() {
+                gst_audio::AUDIO_FORMAT_S16 => {
+                    let data = map.as_slice().as_slice_of::<i16>().unwrap();
+                    Stats::new(data, channels, |s| f64::from(s) / 32_768.0, &settings)
+                }
+                gst_audio::AUDIO_FORMAT_S32 => {
+                    let data = map.as_slice().as_slice_of::<i32>().unwrap();
+                    Stats::new(data, channels, |s| f64::from(s) / 2_147_483_648.0, &settings)
+                }
+                gst_audio::AUDIO_FORMAT_F32 => {
+                    let data = map.as_slice().as_slice_of::<f32>().unwrap();
+                    Stats::new(data, channels, f64::from, &settings)
+                }
+                gst_audio::AUDIO_FORMAT_F64 => {
+                    let data = map.as_slice().as_slice_of::<f64>().unwrap();
+                    Stats::new(data, channels, |s| s, &settings)
+                }
+                _ => return gst::FlowReturn::NotNegotiated,
+            };
+
+            let frames = (map.as_slice().len() / info.bpf() as usize) as u64;
+            let pts = buf.get_pts().0.unwrap_or(state.position);
+            stats.duration = frames * gst::SECOND_VAL / u64::from(info.rate());
+            stats.running_time = state
+                .segment
+                .to_running_time(gst::ClockTime::from_nseconds(pts))
+                .0
+                .unwrap_or(pts);
+            state.position = pts + stats.duration;
+
+            if let Some(ref mut csv) = state.csv {
+                if let Err(err) = csv.write_all(stats.to_csv().as_bytes()) {
+                    gst_element_error!(
+                        element,
+                        gst::ResourceError::Write,
+                        ["Failed to write statistics: {}", err]
+                    );
+                    return gst::FlowReturn::Error;
+                }
+            }
+
+            let silence_start = state.update_silence(&stats, &settings);
+            (stats, silence_start)
+        };
+
+        gst_log!(self.cat, obj: element, "Measured {:?}", stats);
+
+        let s = stats.to_structure();
+        if settings.post_messages {
+            let msg = gst::Message::new_element(s.clone())
+                .src(Some(element))
+                .build();
+            let _ = element.post_message(&msg);
+        }
+        meta::add_structure_meta(buf, s);
+
+        if let Some(start) = silence_start {
+            gst_debug!(self.cat, obj: element, "Silence since {}", start);
+            let _ = element.emit("silence-detected", &[&start]);
+        }
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct AudioAnalyseStatic;
+
+impl ImplTypeStatic<BaseTransform> for AudioAnalyseStatic {
+    fn get_name(&self) -> &str {
+        "AudioAnalyse"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        AudioAnalyse::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        AudioAnalyse::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let audioanalyse_static = AudioAnalyseStatic;
+    register_type(audioanalyse_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let settings = Settings::default();
+
+        // Left channel has a DC offset of 0.25 and clips, right channel is silent
+        let data = [32_767i16, 0, -16_384, 0, 32_767, 0, -16_384, 0];
+        let stats = Stats::new(&data, 2, |s| f64::from(s) / 32_768.0, &settings);
+        assert!((stats.dc_offset[0] - 0.25).abs() < 1e-4);
+        assert_eq!(stats.dc_offset[1], 0.0);
+        assert_eq!(stats.clipped, vec![2, 0]);
+        assert_eq!(stats.rms[1], f64::NEG_INFINITY);
+        assert!(!stats.silent);
+
+        let silence = Stats::new(&[0.0001f64; 480], 1, |s| s, &settings);
+        assert!(silence.silent);
+
+        let mut state = State {
+            info: None,
+            segment: gst::FormattedSegment::new(),
+            csv: None,
+            silence_start: None,
+            silence_signalled: false,
+            position: 0,
+        };
+
+        // Signalled once after 2s of silence, and again after the next silence
+        let mut signalled = Vec::new();
+        for i in 0..51 {
+            let mut stats = if i == 30 {
+                stats.clone()
+            } else {
+                silence.clone()
+            };
+            stats.running_time = i * 100 * gst::MSECOND_VAL;
+            stats.duration = 100 * gst::MSECOND_VAL;
+            if let Some(start) = state.update_silence(&stats, &settings) {
+                signalled.push((i, start));
+            }
+        }
+        assert_eq!(signalled, vec![(19, 0), (50, 31 * 100 * gst::MSECOND_VAL)]);
+    }
+}
@@ -304,10 +304,9 @@ impl ImplTypeStatic<BaseTransform> for AudioEchoStatic {
     }
 }
 
-pub fn register(plugin: &gst::Plugin) {
+pub fn get_type() -> glib::Type {
     let audioecho_static = AudioEchoStatic;
-    let type_ = register_type(audioecho_static);
-    gst::Element::register(plugin, "rsaudioecho", 0, type_);
+    register_type(audioecho_static)
 }
 
 struct RingBuffer {
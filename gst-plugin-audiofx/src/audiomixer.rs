@@ -0,0 +1,513 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_audio;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::aggregator::*;
+use gst_plugin::aggregator_pad::*;
+use gst_plugin::audio_aggregator::*;
+use gst_plugin::audio_aggregator_pad::*;
+use gst_plugin::child_proxy::*;
+
+use std::{cmp, i16, i32};
+use std::sync::{Mutex, Once, ONCE_INIT};
+
+use byte_slice_cast::*;
+
+// Audio mixer:
+//
+// Mixes any number of audio streams into one by adding up their samples, after applying the
+// volume of each input. Muted inputs are skipped. The pads are exposed via GstChildProxy, so
+// their properties can be set as sink_0::volume=0.5 from gst-launch-1.0.
+//
+// GstAudioAggregator takes care of timing: inputs that start later than others are mixed in
+// from the sample matching their running time, gaps and inputs that are EOS already are
+// treated as silence, and the output continues until all inputs are EOS.
+//
+// There is no conversion, so all inputs need the same format, rate and number of channels. The
+// output is fixated to the format of the first input that has caps, inputs with a different
+// format are rejected afterwards. F32 samples are added up as is, S16 samples are clamped to
+// the valid range.
+
+const DEFAULT_VOLUME: f64 = 1.0;
+const DEFAULT_MUTE: bool = false;
+
+#[derive(Debug, Clone, Copy)]
+struct PadSettings {
+    volume: f64,
+    mute: bool,
+}
+
+impl Default for PadSettings {
+    fn default() -> Self {
+        PadSettings {
+            volume: DEFAULT_VOLUME,
+            mute: DEFAULT_MUTE,
+        }
+    }
+}
+
+static PAD_PROPERTIES: [Property; 2] = [
+    Property::Double(
+        "volume",
+        "Volume",
+        "Volume of the input",
+        (0.0, 10.0),
+        DEFAULT_VOLUME,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "mute",
+        "Mute",
+        "Mute the input",
+        DEFAULT_MUTE,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+fn mix_f32(input: &[f32], output: &mut [f32], volume: f64) {
+    let volume = volume as f32;
+    for (o, i) in output.iter_mut().zip(input) {
+        *o += *i * volume;
+    }
+}
+
+fn mix_s16(input: &[i16], output: &mut [i16], volume: f64) {
+    // Volume as 16.16 fixed point number
+    let volume = (volume * 65536.0).round() as i64;
+    for (o, i) in output.iter_mut().zip(input) {
+        let sample = i64::from(*o) + ((i64::from(*i) * volume) >> 16);
+        *o = cmp::min(cmp::max(sample, i64::from(i16::MIN)), i64::from(i16::MAX)) as i16;
+    }
+}
+
+struct MixerPad {
+    settings: Mutex<PadSettings>,
+    info: Mutex<Option<gst_audio::AudioInfo>>,
+}
+
+impl MixerPad {
+    fn class_init(klass: &mut AudioAggregatorPadClass) {
+        klass.install_properties(&PAD_PROPERTIES);
+    }
+
+    fn init(_pad: &AudioAggregatorPad) -> Box<AggregatorPadImpl<AudioAggregatorPad>> {
+        let imp = MixerPad {
+            settings: Mutex::new(Default::default()),
+            info: Mutex::new(None),
+        };
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<AudioAggregatorPad> for MixerPad {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PAD_PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Double("volume", ..) => settings.volume = value.get().unwrap(),
+            Property::Boolean("mute", ..) => settings.mute = value.get().unwrap(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PAD_PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Double("volume", ..) => Ok(settings.volume.to_value()),
+            Property::Boolean("mute", ..) => Ok(settings.mute.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl AggregatorPadImpl<AudioAggregatorPad> for MixerPad {}
+
+struct MixerPadStatic;
+
+impl ImplTypeStatic<AudioAggregatorPad> for MixerPadStatic {
+    fn get_name(&self) -> &str {
+        "RsAudioMixerPad"
+    }
+
+    fn new(&self, pad: &AudioAggregatorPad) -> Box<AggregatorPadImpl<AudioAggregatorPad>> {
+        MixerPad::init(pad)
+    }
+
+    fn class_init(&self, klass: &mut AudioAggregatorPadClass) {
+        MixerPad::class_init(klass);
+    }
+}
+
+fn get_pad_type() -> glib::Type {
+    static ONCE: Once = ONCE_INIT;
+    static mut TYPE: glib::Type = glib::Type::Invalid;
+
+    ONCE.call_once(|| {
+        let mixer_pad_static = MixerPadStatic;
+        unsafe {
+            TYPE = register_type(mixer_pad_static);
+        }
+    });
+
+    unsafe { TYPE }
+}
+
+fn with_pad<T, F: FnOnce(&MixerPad) -> T>(pad: &gst::Pad, f: F) -> T {
+    let pad = pad.clone().downcast::<AudioAggregatorPad>().unwrap();
+    let imp = pad.get_impl().downcast_ref::<MixerPad>().unwrap();
+    f(imp)
+}
+
+fn same_format(a: &gst_audio::AudioInfo, b: &gst_audio::AudioInfo) -> bool {
+    a.format() == b.format() && a.rate() == b.rate() && a.channels() == b.channels()
+}
+
+struct AudioMixer {
+    cat: gst::DebugCategory,
+    info: Mutex<Option<gst_audio::AudioInfo>>,
+    next_pad: Mutex<u32>,
+}
+
+impl AudioMixer {
+    fn new(_element: &AudioAggregator) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rsaudiomixer",
+                gst::DebugColorFlags::empty(),
+                "Rust audio mixer",
+            ),
+            info: Mutex::new(None),
+            next_pad: Mutex::new(0),
+        }
+    }
+
+    fn class_init(klass: &mut AudioAggregatorClass) {
+        klass.set_metadata(
+            "Audio mixer",
+            "Generic/Audio",
+            "Mixes multiple audio streams",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                (
+                    "format",
+                    &gst::List::new(&[
+                        &gst_audio::AUDIO_FORMAT_F32.to_string(),
+                        &gst_audio::AUDIO_FORMAT_S16.to_string(),
+                    ]),
+                ),
+                ("layout", &"interleaved"),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            ],
+        );
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+    }
+
+    fn init(element: &AudioAggregator) -> Box<AudioAggregatorImpl<AudioAggregator>> {
+        // Notify GstChildProxy about new and removed inputs
+        element.connect_pad_added(|element, pad| {
+            if pad.get_direction() == gst::PadDirection::Sink {
+                let _ = element.emit("child-added", &[pad, &pad.get_name()]);
+            }
+        });
+        element.connect_pad_removed(|element, pad| {
+            if pad.get_direction() == gst::PadDirection::Sink {
+                let _ = element.emit("child-removed", &[pad, &pad.get_name()]);
+            }
+        });
+
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<AudioAggregator> for AudioMixer {}
+
+impl ElementImpl<AudioAggregator> for AudioMixer {}
+
+impl AggregatorImpl<AudioAggregator> for AudioMixer {
+    fn stop(&self, element: &AudioAggregator) -> bool {
+        *self.info.lock().unwrap() = None;
+
+        element.parent_stop()
+    }
+
+    fn sink_event(&self, element: &AudioAggregator, pad: &gst::Pad, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        if let EventView::Caps(e) = event.view() {
+            let info = match gst_audio::AudioInfo::from_caps(e.get_caps()) {
+                None => return false,
+                Some(info) => info,
+            };
+
+            gst_debug!(self.cat, obj: pad, "Got caps {:?}", e.get_caps());
+
+            match *self.info.lock().unwrap() {
+                Some(ref out_info) if !same_format(&info, out_info) => {
+                    gst_element_error!(
+                        element,
+                        gst::StreamError::Format,
+                        ["Input caps {} differ from output caps", e.get_caps()]
+                    );
+                    return false;
+                }
+                Some(_) => (),
+                // The output format is decided by the first input
+                None => element.get_src_pad().mark_reconfigure(),
+            }
+
+            with_pad(pad, |mpad| *mpad.info.lock().unwrap() = Some(info));
+        }
+
+        element.parent_sink_event(pad, event)
+    }
+
+    fn create_new_pad(
+        &self,
+        _element: &AudioAggregator,
+        templ: &gst::PadTemplate,
+        req_name: Option<String>,
+        _caps: Option<&gst::CapsRef>,
+    ) -> Option<gst::Pad> {
+        let name = {
+            let mut next_pad = self.next_pad.lock().unwrap();
+            let number = req_name
+                .as_ref()
+                .and_then(|name| {
+                    if name.starts_with("sink_") {
+                        name["sink_".len()..].parse::<u32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(*next_pad);
+            *next_pad = cmp::max(*next_pad, number + 1);
+
+            format!("sink_{}", number)
+        };
+
+        let pad = glib::Object::new(
+            get_pad_type(),
+            &[
+                ("name", &name),
+                ("direction", &gst::PadDirection::Sink),
+                ("template", templ),
+            ],
+        );
+
+        pad.ok().and_then(|pad| pad.downcast::<gst::Pad>().ok())
+    }
+
+    fn fixate_src_caps(&self, element: &AudioAggregator, mut caps: gst::Caps) -> gst::Caps {
+        let info = element
+            .get_sink_pads()
+            .iter()
+            .filter_map(|pad| with_pad(pad, |mpad| mpad.info.lock().unwrap().clone()))
+            .next();
+
+        if let Some(info) = info {
+            let caps = caps.make_mut();
+            for i in 0..caps.get_size() {
+                let s = caps.get_mut_structure(i).unwrap();
+                s.fixate_field_str("format", &info.format().to_string());
+                s.fixate_field_nearest_int("rate", info.rate() as i32);
+                s.fixate_field_nearest_int("channels", info.channels() as i32);
+            }
+        }
+
+        element.parent_fixate_src_caps(caps)
+    }
+
+    fn negotiated_src_caps(&self, element: &AudioAggregator, caps: &gst::Caps) -> bool {
+        let info = match gst_audio::AudioInfo::from_caps(caps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        for pad in element.get_sink_pads() {
+            let in_info = with_pad(&pad, |mpad| mpad.info.lock().unwrap().clone());
+            if let Some(in_info) = in_info {
+                if !same_format(&in_info, &info) {
+                    gst_error!(self.cat, obj: &pad, "Caps of input differ from {}", caps);
+                    return false;
+                }
+            }
+        }
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", caps);
+        *self.info.lock().unwrap() = Some(info);
+
+        element.parent_negotiated_src_caps(caps)
+    }
+}
+
+impl AudioAggregatorImpl<AudioAggregator> for AudioMixer {
+    fn aggregate_one_buffer(
+        &self,
+        element: &AudioAggregator,
+        pad: &gst::Pad,
+        inbuf: &gst::Buffer,
+        in_offset: u32,
+        outbuf: &mut gst::BufferRef,
+        out_offset: u32,
+        num_frames: u32,
+    ) -> bool {
+        let settings = with_pad(pad, |mpad| *mpad.settings.lock().unwrap());
+        if settings.mute || settings.volume == 0.0 {
+            gst_trace!(self.cat, obj: pad, "Skipping muted input");
+            return false;
+        }
+
+        let info = match *self.info.lock().unwrap() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Negotiation, ["Not negotiated yet"]);
+                return false;
+            }
+            Some(ref info) => info.clone(),
+        };
+
+        let bpf = info.bpf() as usize;
+        let in_range = (in_offset as usize * bpf)..((in_offset + num_frames) as usize * bpf);
+        let out_range = (out_offset as usize * bpf)..((out_offset + num_frames) as usize * bpf);
+
+        let in_map = match inbuf.map_readable() {
+            None => return false,
+            Some(map) => map,
+        };
+        let mut out_map = match outbuf.map_writable() {
+            None => return false,
+            Some(map) => map,
+        };
+        let input = &in_map.as_slice()[in_range];
+        let output = &mut out_map.as_mut_slice()[out_range];
+
+        match info.format() {
+            gst_audio::AUDIO_FORMAT_F32 => mix_f32(
+                input.as_slice_of::<f32>().unwrap(),
+                output.as_mut_slice_of::<f32>().unwrap(),
+                settings.volume,
+            ),
+            gst_audio::AUDIO_FORMAT_S16 => mix_s16(
+                input.as_slice_of::<i16>().unwrap(),
+                output.as_mut_slice_of::<i16>().unwrap(),
+                settings.volume,
+            ),
+            _ => unreachable!(),
+        }
+
+        true
+    }
+}
+
+impl ChildProxyImpl for AudioMixer {
+    fn get_child_by_index(&self, element: &gst::ChildProxy, index: u32) -> Option<glib::Object> {
+        let element = element.clone().dynamic_cast::<gst::Element>().unwrap();
+        element
+            .get_sink_pads()
+            .into_iter()
+            .nth(index as usize)
+            .map(|pad| pad.upcast())
+    }
+
+    fn get_children_count(&self, element: &gst::ChildProxy) -> u32 {
+        let element = element.clone().dynamic_cast::<gst::Element>().unwrap();
+        element.get_sink_pads().len() as u32
+    }
+}
+
+struct AudioMixerStatic;
+
+impl ImplTypeStatic<AudioAggregator> for AudioMixerStatic {
+    fn get_name(&self) -> &str {
+        "RsAudioMixer"
+    }
+
+    fn new(&self, element: &AudioAggregator) -> Box<AudioAggregatorImpl<AudioAggregator>> {
+        AudioMixer::init(element)
+    }
+
+    fn class_init(&self, klass: &mut AudioAggregatorClass) {
+        AudioMixer::class_init(klass);
+    }
+
+    fn type_init(&self, token: &TypeInitToken, type_: glib::Type) {
+        register_child_proxy(token, type_, self);
+    }
+}
+
+impl ChildProxyImplStatic<AudioAggregator> for AudioMixerStatic {
+    fn get_impl<'a>(
+        &self,
+        imp: &'a Box<AudioAggregatorImpl<AudioAggregator>>,
+    ) -> &'a ChildProxyImpl {
+        imp.downcast_ref::<AudioMixer>().unwrap()
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    // The pad type must exist before the first pad is requested
+    get_pad_type();
+
+    let audio_mixer_static = AudioMixerStatic;
+    register_type(audio_mixer_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mix_f32() {
+        let mut out = [0.0f32, 0.25, -0.5, 0.75];
+        mix_f32(&[0.5, 0.5, 0.5, 0.5], &mut out, 1.0);
+        assert_eq!(out, [0.5, 0.75, 0.0, 1.25]);
+
+        mix_f32(&[1.0, -1.0, 0.0, 0.5], &mut out, 0.5);
+        assert_eq!(out, [1.0, 0.25, 0.0, 1.5]);
+    }
+
+    #[test]
+    fn test_mix_s16() {
+        let mut out = [0i16, 1000, -1000, 30000];
+        mix_s16(&[100, 100, -100, 10000], &mut out, 1.0);
+        assert_eq!(out, [100, 1100, -1100, i16::MAX]);
+
+        mix_s16(&[200, -4000, i16::MIN, 0], &mut out, 0.5);
+        assert_eq!(out, [200, -900, -17484, i16::MAX]);
+    }
+}
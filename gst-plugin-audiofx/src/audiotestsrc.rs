@@ -0,0 +1,600 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_audio;
+use gst_base::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_src::*;
+use gst_plugin::idle::*;
+
+use std::{f64, i16, i32, u32};
+use std::sync::Mutex;
+
+use byte_slice_cast::*;
+
+// Audio test source:
+//
+// Produces a waveform with the configured frequency and volume on all channels. The source is
+// driven by the sample offset of the next buffer: timestamps and durations are calculated from
+// it, so that they are sample-accurate and don't accumulate rounding errors, and seeks simply
+// move it to the corresponding sample.
+//
+// Without is-live the buffers are produced as fast as downstream consumes them. With is-live
+// the base class waits for the clock to reach the timestamp of each buffer before pushing it,
+// and the latency is the duration of one buffer.
+//
+// With idle-when-unlinked no buffers are produced while the src pad is unlinked.
+//
+// Rate and channels are negotiated with downstream and default to 48kHz mono.
+
+const DEFAULT_WAVE: &str = "sine";
+const DEFAULT_FREQ: f64 = 440.0;
+const DEFAULT_VOLUME: f64 = 0.8;
+const DEFAULT_SAMPLES_PER_BUFFER: u32 = 1024;
+const DEFAULT_IS_LIVE: bool = false;
+
+const DEFAULT_RATE: i32 = 48_000;
+const DEFAULT_CHANNELS: i32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Wave {
+    Sine,
+    Square,
+    Saw,
+    Noise,
+}
+
+impl Wave {
+    fn from_str(s: &str) -> Option<Wave> {
+        match s {
+            "sine" => Some(Wave::Sine),
+            "square" => Some(Wave::Square),
+            "saw" => Some(Wave::Saw),
+            "noise" => Some(Wave::Noise),
+            _ => None,
+        }
+    }
+
+    fn to_str(&self) -> &'static str {
+        match *self {
+            Wave::Sine => "sine",
+            Wave::Square => "square",
+            Wave::Saw => "saw",
+            Wave::Noise => "noise",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    wave: Wave,
+    freq: f64,
+    volume: f64,
+    samples_per_buffer: u32,
+    is_live: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            wave: Wave::from_str(DEFAULT_WAVE).unwrap(),
+            freq: DEFAULT_FREQ,
+            volume: DEFAULT_VOLUME,
+            samples_per_buffer: DEFAULT_SAMPLES_PER_BUFFER,
+            is_live: DEFAULT_IS_LIVE,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 6] = [
+    Property::String(
+        "wave",
+        "Wave",
+        "Waveform to produce (sine, square, saw or noise)",
+        Some(DEFAULT_WAVE),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "freq",
+        "Frequency",
+        "Frequency of the waveform in Hz",
+        (1.0, 1_000_000.0),
+        DEFAULT_FREQ,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "volume",
+        "Volume",
+        "Amplitude of the waveform relative to full scale",
+        (0.0, 1.0),
+        DEFAULT_VOLUME,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "samples-per-buffer",
+        "Samples Per Buffer",
+        "Number of samples in each outgoing buffer",
+        (1, u32::MAX),
+        DEFAULT_SAMPLES_PER_BUFFER,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "is-live",
+        "Is Live",
+        "Synchronize the produced buffers to the clock",
+        DEFAULT_IS_LIVE,
+        PropertyMutability::ReadWrite,
+    ),
+    IDLE_WHEN_UNLINKED_PROPERTY,
+];
+
+// Converts a sample offset to a time without overflowing for long running streams
+fn samples_to_time(samples: u64, rate: u32) -> u64 {
+    let rate = u64::from(rate);
+    samples / rate * gst::SECOND_VAL + samples % rate * gst::SECOND_VAL / rate
+}
+
+fn time_to_samples(time: u64, rate: u32) -> u64 {
+    let rate = u64::from(rate);
+    time / gst::SECOND_VAL * rate + time % gst::SECOND_VAL * rate / gst::SECOND_VAL
+}
+
+#[derive(Debug, Clone)]
+struct Generator {
+    // Position in the current period of the waveform, between 0 and 1
+    phase: f64,
+    // State of the xorshift noise generator, never 0
+    seed: u32,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator {
+            phase: 0.0,
+            seed: 0x1234_5678,
+        }
+    }
+}
+
+impl Generator {
+    // Fills the interleaved frames of `data` with the next samples, scaled with `convert`
+    fn generate<T: Copy, F: Fn(f64) -> T>(
+        &mut self,
+        data: &mut [T],
+        channels: usize,
+        rate: u32,
+        settings: &Settings,
+        convert: F,
+    ) {
+        let step = settings.freq / f64::from(rate);
+
+        for frame in data.chunks_mut(channels) {
+            let value = match settings.wave {
+                Wave::Sine => (2.0 * f64::consts::PI * self.phase).sin(),
+                Wave::Square => if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                },
+                Wave::Saw => 2.0 * self.phase - 1.0,
+                Wave::Noise => {
+                    self.seed ^= self.seed << 13;
+                    self.seed ^= self.seed >> 17;
+                    self.seed ^= self.seed << 5;
+                    f64::from(self.seed) / f64::from(u32::MAX) * 2.0 - 1.0
+                }
+            };
+
+            let sample = convert(value * settings.volume);
+            for s in frame {
+                *s = sample;
+            }
+
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+struct State {
+    info: Option<gst_audio::AudioInfo>,
+    generator: Generator,
+    // Sample offset of the next buffer and of the end of the segment
+    sample_offset: u64,
+    sample_stop: Option<u64>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            info: None,
+            generator: Generator::default(),
+            sample_offset: 0,
+            sample_stop: None,
+        }
+    }
+}
+
+struct AudioTestSrc {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    idle: Idle,
+}
+
+impl AudioTestSrc {
+    fn new(_src: &BaseSrc) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rsaudiotestsrc",
+                gst::DebugColorFlags::empty(),
+                "Rust audio test source",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+            idle: Idle::new(),
+        }
+    }
+
+    fn class_init(klass: &mut BaseSrcClass) {
+        klass.set_metadata(
+            "Audio Test Source",
+            "Source/Audio",
+            "Produces sine, square, saw or noise test audio",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                (
+                    "format",
+                    &gst::List::new(&[
+                        &gst_audio::AUDIO_FORMAT_F32.to_string(),
+                        &gst_audio::AUDIO_FORMAT_F64.to_string(),
+                        &gst_audio::AUDIO_FORMAT_S16.to_string(),
+                    ]),
+                ),
+                ("layout", &"interleaved"),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            ],
+        );
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &BaseSrc) -> Box<BaseSrcImpl<BaseSrc>> {
+        element.set_live(DEFAULT_IS_LIVE);
+        element.set_format(gst::Format::Time);
+
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseSrc> for AudioTestSrc {
+    fn set_property(&self, obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let element = obj.clone().downcast::<BaseSrc>().unwrap();
+
+        match *prop {
+            Property::String("wave", ..) => {
+                let wave = value.get::<String>();
+                match wave.as_ref().and_then(|wave| Wave::from_str(wave)) {
+                    Some(wave) => self.settings.lock().unwrap().wave = wave,
+                    None => {
+                        gst_warning!(self.cat, obj: &element, "Invalid wave {:?}", wave);
+                    }
+                }
+            }
+            Property::Double("freq", ..) => {
+                self.settings.lock().unwrap().freq = value.get().unwrap();
+            }
+            Property::Double("volume", ..) => {
+                self.settings.lock().unwrap().volume = value.get().unwrap();
+            }
+            Property::UInt("samples-per-buffer", ..) => {
+                self.settings.lock().unwrap().samples_per_buffer = value.get().unwrap();
+                element.post_latency_message();
+            }
+            Property::Boolean("is-live", ..) => {
+                let is_live = value.get().unwrap();
+                self.settings.lock().unwrap().is_live = is_live;
+                element.set_live(is_live);
+            }
+            Property::Boolean("idle-when-unlinked", ..) => {
+                self.idle.set_enabled(value.get().unwrap());
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("wave", ..) => Ok(settings.wave.to_str().to_value()),
+            Property::Double("freq", ..) => Ok(settings.freq.to_value()),
+            Property::Double("volume", ..) => Ok(settings.volume.to_value()),
+            Property::UInt("samples-per-buffer", ..) => Ok(settings.samples_per_buffer.to_value()),
+            Property::Boolean("is-live", ..) => Ok(settings.is_live.to_value()),
+            Property::Boolean("idle-when-unlinked", ..) => Ok(self.idle.is_enabled().to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseSrc> for AudioTestSrc {}
+
+impl BaseSrcImpl<BaseSrc> for AudioTestSrc {
+    fn start(&self, _element: &BaseSrc) -> bool {
+        *self.state.lock().unwrap() = Default::default();
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseSrc) -> bool {
+        *self.state.lock().unwrap() = Default::default();
+
+        true
+    }
+
+    fn unlock(&self, _element: &BaseSrc) -> bool {
+        self.idle.unlock();
+        true
+    }
+
+    fn unlock_stop(&self, _element: &BaseSrc) -> bool {
+        self.idle.unlock_stop();
+        true
+    }
+
+    fn fixate(&self, element: &BaseSrc, mut caps: gst::Caps) -> gst::Caps {
+        {
+            let caps = caps.make_mut();
+            for i in 0..caps.get_size() {
+                let s = caps.get_mut_structure(i).unwrap();
+                s.fixate_field_nearest_int("rate", DEFAULT_RATE);
+                s.fixate_field_nearest_int("channels", DEFAULT_CHANNELS);
+            }
+        }
+
+        element.parent_fixate(caps)
+    }
+
+    fn set_caps(&self, element: &BaseSrc, caps: &gst::CapsRef) -> bool {
+        let info = match gst_audio::AudioInfo::from_caps(&caps.to_owned()) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", caps);
+
+        let mut state = self.state.lock().unwrap();
+
+        // Keep the current position if the rate changes
+        if let Some(ref old_info) = state.info {
+            let time = samples_to_time(state.sample_offset, old_info.rate());
+            state.sample_offset = time_to_samples(time, info.rate());
+            state.sample_stop = state.sample_stop.map(|stop| {
+                time_to_samples(samples_to_time(stop, old_info.rate()), info.rate())
+            });
+        }
+        state.info = Some(info);
+        drop(state);
+
+        element.post_latency_message();
+
+        true
+    }
+
+    fn is_seekable(&self, _element: &BaseSrc) -> bool {
+        !self.settings.lock().unwrap().is_live
+    }
+
+    fn do_seek(&self, element: &BaseSrc, segment: &mut gst::Segment) -> bool {
+        let segment = match segment.downcast_ref::<gst::ClockTime>() {
+            None => return false,
+            Some(segment) => segment,
+        };
+
+        if segment.get_rate() < 0.0 {
+            gst_error!(self.cat, obj: element, "Reverse playback not supported");
+            return false;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let rate = match state.info {
+            Some(ref info) => info.rate(),
+            None if segment.get_start().0.unwrap_or(0) == 0 => {
+                // Initial seek before negotiation
+                return true;
+            }
+            None => return false,
+        };
+
+        let start = segment.get_start().0.unwrap_or(0);
+        state.sample_offset = time_to_samples(start, rate);
+        state.sample_stop = segment
+            .get_stop()
+            .0
+            .map(|stop| time_to_samples(stop, rate));
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Seeked to sample {} - {:?}",
+            state.sample_offset,
+            state.sample_stop
+        );
+
+        true
+    }
+
+    fn get_latency(&self, _element: &BaseSrc) -> Option<(gst::ClockTime, gst::ClockTime)> {
+        let settings = *self.settings.lock().unwrap();
+        let state = self.state.lock().unwrap();
+
+        match state.info {
+            Some(ref info) if settings.is_live => {
+                let latency = gst::ClockTime::from_nseconds(samples_to_time(
+                    u64::from(settings.samples_per_buffer),
+                    info.rate(),
+                ));
+                Some((latency, latency))
+            }
+            _ => None,
+        }
+    }
+
+    fn create(
+        &self,
+        element: &BaseSrc,
+        _offset: u64,
+        _length: u32,
+    ) -> Result<gst::Buffer, gst::FlowReturn> {
+        self.idle.wait_linked(self.cat, element)?;
+
+        let settings = *self.settings.lock().unwrap();
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = &mut *state_guard;
+        let info = match state.info {
+            None => {
+                gst_element_error!(element, gst::CoreError::Negotiation, ["Have no caps yet"]);
+                return Err(gst::FlowReturn::NotNegotiated);
+            }
+            Some(ref info) => info.clone(),
+        };
+
+        let mut n_samples = u64::from(settings.samples_per_buffer);
+        if let Some(stop) = state.sample_stop {
+            if state.sample_offset >= stop {
+                gst_debug!(self.cat, obj: element, "At the end of the segment");
+                return Err(gst::FlowReturn::Eos);
+            }
+            n_samples = n_samples.min(stop - state.sample_offset);
+        }
+
+        let channels = info.channels() as usize;
+        let size = n_samples as usize * info.bpf() as usize;
+        let mut buffer = gst::Buffer::with_size(size).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+
+            let pts = samples_to_time(state.sample_offset, info.rate());
+            let end = samples_to_time(state.sample_offset + n_samples, info.rate());
+            buffer.set_pts(gst::ClockTime::from_nseconds(pts));
+            buffer.set_duration(gst::ClockTime::from_nseconds(end - pts));
+            buffer.set_offset(state.sample_offset);
+            buffer.set_offset_end(state.sample_offset + n_samples);
+
+            let mut map = buffer.map_writable().unwrap();
+            let data = map.as_mut_slice();
+            let rate = info.rate();
+            match info.format() {
+                gst_audio::AUDIO_FORMAT_F32 => state.generator.generate(
+                    data.as_mut_slice_of::<f32>().unwrap(),
+                    channels,
+                    rate,
+                    &settings,
+                    |v| v as f32,
+                ),
+                gst_audio::AUDIO_FORMAT_F64 => state.generator.generate(
+                    data.as_mut_slice_of::<f64>().unwrap(),
+                    channels,
+                    rate,
+                    &settings,
+                    |v| v,
+                ),
+                gst_audio::AUDIO_FORMAT_S16 => state.generator.generate(
+                    data.as_mut_slice_of::<i16>().unwrap(),
+                    channels,
+                    rate,
+                    &settings,
+                    |v| (v * f64::from(i16::MAX)) as i16,
+                ),
+                _ => return Err(gst::FlowReturn::NotNegotiated),
+            }
+        }
+
+        state.sample_offset += n_samples;
+
+        gst_log!(self.cat, obj: element, "Produced buffer {:?}", buffer);
+
+        Ok(buffer)
+    }
+}
+
+struct AudioTestSrcStatic;
+
+impl ImplTypeStatic<BaseSrc> for AudioTestSrcStatic {
+    fn get_name(&self) -> &str {
+        "RsAudioTestSrc"
+    }
+
+    fn new(&self, element: &BaseSrc) -> Box<BaseSrcImpl<BaseSrc>> {
+        AudioTestSrc::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseSrcClass) {
+        AudioTestSrc::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let audiotestsrc_static = AudioTestSrcStatic;
+    register_type(audiotestsrc_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generator() {
+        let settings = Settings {
+            wave: Wave::Square,
+            freq: 12_000.0,
+            volume: 0.5,
+            ..Settings::default()
+        };
+
+        // Four stereo frames per period
+        let mut generator = Generator::default();
+        let mut data = [0.0f64; 16];
+        generator.generate(&mut data, 2, 48_000, &settings, |v| v);
+        assert_eq!(
+            data,
+            [
+                0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5, 0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5,
+                -0.5,
+            ]
+        );
+
+        // No overflow after a year
+        let year = 3600 * 24 * 365;
+        assert_eq!(samples_to_time(48_000 * year, 48_000), year * gst::SECOND_VAL);
+        assert_eq!(time_to_samples(year * gst::SECOND_VAL, 48_000), 48_000 * year);
+        assert_eq!(samples_to_time(1, 44_100), 22_675);
+    }
+}
@@ -0,0 +1,497 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_audio;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::{i32, u64};
+use std::sync::Mutex;
+
+use byte_slice_cast::*;
+
+use num_traits::float::Float;
+use num_traits::cast::{FromPrimitive, ToPrimitive};
+
+const DEFAULT_WINDOW: u64 = gst::SECOND_VAL;
+const DEFAULT_CORRELATION_THRESHOLD: f64 = 0.95;
+const DEFAULT_SILENCE_THRESHOLD: f64 = -60.0;
+const DEFAULT_FIX: bool = false;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    pub window: u64,
+    pub correlation_threshold: f64,
+    pub silence_threshold: f64,
+    pub fix: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            window: DEFAULT_WINDOW,
+            correlation_threshold: DEFAULT_CORRELATION_THRESHOLD,
+            silence_threshold: DEFAULT_SILENCE_THRESHOLD,
+            fix: DEFAULT_FIX,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    Normal,
+    // Both channels carry the same signal
+    DualMono,
+    // Both channels carry the same signal but one of them is inverted
+    PhaseInverted,
+    SilentLeft,
+    SilentRight,
+    Silent,
+}
+
+impl Layout {
+    fn to_str(self) -> &'static str {
+        match *self {
+            Layout::Normal => "normal",
+            Layout::DualMono => "dual-mono",
+            Layout::PhaseInverted => "phase-inverted",
+            Layout::SilentLeft => "silent-left",
+            Layout::SilentRight => "silent-right",
+            Layout::Silent => "silent",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Analysis {
+    layout: Layout,
+    // Pearson correlation of both channels, 0.0 if one of them is silent
+    correlation: f64,
+    // RMS levels in dB
+    left_level: f64,
+    right_level: f64,
+    // Start of the analysed window
+    timestamp: gst::ClockTime,
+}
+
+#[derive(Debug, Default)]
+struct Accumulator {
+    sum_ll: f64,
+    sum_rr: f64,
+    sum_lr: f64,
+    frames: u64,
+}
+
+impl Accumulator {
+    fn add(&mut self, l: f64, r: f64) {
+        self.sum_ll += l * l;
+        self.sum_rr += r * r;
+        self.sum_lr += l * r;
+        self.frames += 1;
+    }
+
+    fn analyse(
+        &self,
+        correlation_threshold: f64,
+        silence_threshold: f64,
+    ) -> (Layout, f64, f64, f64) {
+        let frames = self.frames as f64;
+        let left_level = 10.0 * (self.sum_ll / frames).log10();
+        let right_level = 10.0 * (self.sum_rr / frames).log10();
+
+        let left_silent = left_level < silence_threshold;
+        let right_silent = right_level < silence_threshold;
+
+        let correlation = if left_silent || right_silent {
+            0.0
+        } else {
+            self.sum_lr / (self.sum_ll * self.sum_rr).sqrt()
+        };
+
+        let layout = match (left_silent, right_silent) {
+            (true, true) => Layout::Silent,
+            (true, false) => Layout::SilentLeft,
+            (false, true) => Layout::SilentRight,
+            _ if correlation >= correlation_threshold => Layout::DualMono,
+            _ if correlation <= -correlation_threshold => Layout::PhaseInverted,
+            _ => Layout::Normal,
+        };
+
+        (layout, correlation, left_level, right_level)
+    }
+}
+
+struct State {
+    info: gst_audio::AudioInfo,
+    accumulator: Accumulator,
+    window_start: gst::ClockTime,
+    // Layout of the last analysed window, used for fixing the following samples
+    layout: Layout,
+}
+
+struct ChannelFix {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+static PROPERTIES: [Property; 4] = [
+    Property::UInt64(
+        "window",
+        "Window",
+        "Duration in nanoseconds over which the channels are analysed",
+        (1, u64::MAX),
+        DEFAULT_WINDOW,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "correlation-threshold",
+        "Correlation Threshold",
+        "Absolute correlation above which both channels are considered to carry the same signal",
+        (0.0, 1.0),
+        DEFAULT_CORRELATION_THRESHOLD,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "silence-threshold",
+        "Silence Threshold",
+        "Level in dB below which a channel is considered silent",
+        (-200.0, 0.0),
+        DEFAULT_SILENCE_THRESHOLD,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "fix",
+        "Fix",
+        "Invert phase inverted channels and replace silent channels with the other channel",
+        DEFAULT_FIX,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+impl ChannelFix {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rschannelfix",
+                gst::DebugColorFlags::empty(),
+                "Rust channel layout fixer",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Channel layout fixer",
+            "Filter/Analyzer/Audio",
+            "Detects and fixes phase inverted, duplicated and silent stereo channels",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                (
+                    "format",
+                    &gst::List::new(&[
+                        &gst_audio::AUDIO_FORMAT_F32.to_string(),
+                        &gst_audio::AUDIO_FORMAT_F64.to_string(),
+                    ]),
+                ),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &2i32),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    // Returns the analysis results of all windows that ended in this buffer
+    fn process<F: Float + ToPrimitive + FromPrimitive>(
+        data: &mut [F],
+        pts: gst::ClockTime,
+        state: &mut State,
+        settings: &Settings,
+    ) -> Vec<Analysis> {
+        let rate = u64::from(state.info.rate());
+        let window_frames = ((settings.window as f64 * rate as f64 / gst::SECOND_VAL as f64)
+            as u64)
+            .max(1);
+        let mut analyses = Vec::new();
+
+        for (idx, frame) in data.chunks_mut(2).enumerate() {
+            if frame.len() < 2 {
+                break;
+            }
+
+            if state.accumulator.frames == 0 {
+                state.window_start =
+                    gst::ClockTime(pts.0.map(|pts| pts + idx as u64 * gst::SECOND_VAL / rate));
+            }
+
+            let l = frame[0].to_f64().unwrap();
+            let r = frame[1].to_f64().unwrap();
+            state.accumulator.add(l, r);
+
+            if state.accumulator.frames >= window_frames {
+                let (layout, correlation, left_level, right_level) = state
+                    .accumulator
+                    .analyse(settings.correlation_threshold, settings.silence_threshold);
+                analyses.push(Analysis {
+                    layout: layout,
+                    correlation: correlation,
+                    left_level: left_level,
+                    right_level: right_level,
+                    timestamp: state.window_start,
+                });
+                state.layout = layout;
+                state.accumulator = Accumulator::default();
+            }
+
+            if !settings.fix {
+                continue;
+            }
+
+            match state.layout {
+                Layout::PhaseInverted => frame[1] = -frame[1],
+                Layout::SilentLeft => frame[0] = frame[1],
+                Layout::SilentRight => frame[1] = frame[0],
+                Layout::Normal | Layout::DualMono | Layout::Silent => (),
+            }
+        }
+
+        analyses
+    }
+}
+
+// Message posted after every analysed window:
+//
+// "channelfix, layout=(string){normal,dual-mono,phase-inverted,silent-left,silent-right,silent},
+//  correlation=(double)..., left-level=(double)..., right-level=(double)...,
+//  timestamp=(guint64)..."
+fn create_message(element: &BaseTransform, analysis: &Analysis) -> gst::Message {
+    let mut s = gst::Structure::new(
+        "channelfix",
+        &[
+            ("layout", &analysis.layout.to_str()),
+            ("correlation", &analysis.correlation),
+            ("left-level", &analysis.left_level),
+            ("right-level", &analysis.right_level),
+        ],
+    );
+    if let Some(timestamp) = analysis.timestamp.0 {
+        s.get_mut().unwrap().set("timestamp", &timestamp);
+    }
+
+    gst::Message::new_element(s).src(Some(element)).build()
+}
+
+impl ObjectImpl<BaseTransform> for ChannelFix {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt64("window", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.window = value.get().unwrap();
+            }
+            Property::Double("correlation-threshold", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.correlation_threshold = value.get().unwrap();
+            }
+            Property::Double("silence-threshold", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.silence_threshold = value.get().unwrap();
+            }
+            Property::Boolean("fix", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.fix = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt64("window", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.window.to_value())
+            }
+            Property::Double("correlation-threshold", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.correlation_threshold.to_value())
+            }
+            Property::Double("silence-threshold", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.silence_threshold.to_value())
+            }
+            Property::Boolean("fix", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.fix.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for ChannelFix {}
+
+impl BaseTransformImpl<BaseTransform> for ChannelFix {
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+
+        let analyses = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match *state_guard {
+                None => return gst::FlowReturn::NotNegotiated,
+                Some(ref mut state) => state,
+            };
+
+            let pts = buf.get_pts();
+            let mut map = match buf.map_writable() {
+                None => return gst::FlowReturn::Error,
+                Some(map) => map,
+            };
+
+            match state.info.format() {
+                gst_audio::AUDIO_FORMAT_F64 => {
+                    let data = map.as_mut_slice().as_mut_slice_of::<f64>().unwrap();
+                    Self::process(data, pts, state, &settings)
+                }
+                gst_audio::AUDIO_FORMAT_F32 => {
+                    let data = map.as_mut_slice().as_mut_slice_of::<f32>().unwrap();
+                    Self::process(data, pts, state, &settings)
+                }
+                _ => return gst::FlowReturn::NotNegotiated,
+            }
+        };
+
+        for analysis in &analyses {
+            gst_debug!(self.cat, obj: element, "Analysed window: {:?}", analysis);
+            let _ = element.post_message(&create_message(element, analysis));
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn set_caps(&self, _element: &BaseTransform, incaps: &gst::Caps, outcaps: &gst::Caps) -> bool {
+        if incaps != outcaps {
+            return false;
+        }
+
+        let info = match gst_audio::AudioInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        *self.state.lock().unwrap() = Some(State {
+            info: info,
+            accumulator: Accumulator::default(),
+            window_start: gst::CLOCK_TIME_NONE,
+            layout: Layout::Normal,
+        });
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseTransform) -> bool {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        true
+    }
+}
+
+struct ChannelFixStatic;
+
+impl ImplTypeStatic<BaseTransform> for ChannelFixStatic {
+    fn get_name(&self) -> &str {
+        "ChannelFix"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        ChannelFix::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        ChannelFix::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let channelfix_static = ChannelFixStatic;
+    register_type(channelfix_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyse<F: Fn(f64) -> (f64, f64)>(func: F) -> (Layout, f64) {
+        let mut accumulator = Accumulator::default();
+        for i in 0..4800 {
+            let (l, r) = func((i as f64 * 0.1).sin() * 0.5);
+            accumulator.add(l, r);
+        }
+        let (layout, correlation, _, _) = accumulator.analyse(0.95, -60.0);
+        (layout, correlation)
+    }
+
+    #[test]
+    fn test_analyse() {
+        let (layout, correlation) = analyse(|s| (s, s));
+        assert_eq!(layout, Layout::DualMono);
+        assert!((correlation - 1.0).abs() < 1e-9);
+
+        let (layout, correlation) = analyse(|s| (s, -s));
+        assert_eq!(layout, Layout::PhaseInverted);
+        assert!((correlation + 1.0).abs() < 1e-9);
+
+        assert_eq!(analyse(|s| (0.0, s)).0, Layout::SilentLeft);
+        assert_eq!(analyse(|s| (s, 0.0)).0, Layout::SilentRight);
+        assert_eq!(analyse(|_| (0.0, 0.0)).0, Layout::Silent);
+
+        // Uncorrelated signals
+        let (layout, correlation) = analyse(|s| (s, (s * 7.0).cos() * 0.5));
+        assert_eq!(layout, Layout::Normal);
+        assert!(correlation.abs() < 0.95);
+    }
+}
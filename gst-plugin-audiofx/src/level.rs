@@ -0,0 +1,579 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_audio;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::{cmp, f64, i32, u64};
+use std::sync::Mutex;
+
+use byte_slice_cast::*;
+
+// Level measurement:
+//
+// For every interval the RMS and the peak of each channel are measured in dB relative to full
+// scale. The decaying peak holds the highest peak for the peak-ttl and then falls off with
+// peak-falloff dB per second until a higher peak is reached, like the needle of a VU meter.
+
+const DEFAULT_INTERVAL: u64 = 100 * gst::MSECOND_VAL;
+const DEFAULT_PEAK_TTL: u64 = 300 * gst::MSECOND_VAL;
+const DEFAULT_PEAK_FALLOFF: f64 = 10.0;
+const DEFAULT_POST_MESSAGES: bool = true;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    interval: u64,
+    peak_ttl: u64,
+    peak_falloff: f64,
+    post_messages: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            interval: DEFAULT_INTERVAL,
+            peak_ttl: DEFAULT_PEAK_TTL,
+            peak_falloff: DEFAULT_PEAK_FALLOFF,
+            post_messages: DEFAULT_POST_MESSAGES,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 4] = [
+    Property::UInt64(
+        "interval",
+        "Interval",
+        "Interval of time between message posts in nanoseconds",
+        (1, u64::MAX),
+        DEFAULT_INTERVAL,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "peak-ttl",
+        "Peak TTL",
+        "Time in nanoseconds the decaying peak is held before it falls off",
+        (0, u64::MAX),
+        DEFAULT_PEAK_TTL,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "peak-falloff",
+        "Peak Falloff",
+        "Falloff of the decaying peak in dB per second",
+        (0.0, f64::MAX),
+        DEFAULT_PEAK_FALLOFF,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "post-messages",
+        "Post Messages",
+        "Post an element message for every interval",
+        DEFAULT_POST_MESSAGES,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+fn to_db(power: f64) -> f64 {
+    if power > 0.0 {
+        10.0 * power.log10()
+    } else {
+        f64::NEG_INFINITY
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    // Sum of the squares and the highest square of the current interval
+    sum: f64,
+    peak: f64,
+    // Last peak of the decaying peak in dB and the time since it was reached
+    decay_peak: f64,
+    decay_age: u64,
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Channel {
+            sum: 0.0,
+            peak: 0.0,
+            decay_peak: f64::NEG_INFINITY,
+            decay_age: 0,
+        }
+    }
+}
+
+impl Channel {
+    fn add(&mut self, sample: f64) {
+        let square = sample * sample;
+        self.sum += square;
+        if square > self.peak {
+            self.peak = square;
+        }
+    }
+
+    // Returns the RMS, peak and decaying peak of the interval and starts the next one
+    fn finish(&mut self, frames: u64, duration: u64, settings: &Settings) -> (f64, f64, f64) {
+        let rms = to_db(self.sum / frames as f64);
+        let peak = to_db(self.peak);
+
+        self.decay_age += duration;
+        let mut decay = self.decay_peak;
+        if self.decay_age > settings.peak_ttl {
+            let age = (self.decay_age - settings.peak_ttl) as f64 / gst::SECOND_VAL as f64;
+            decay -= settings.peak_falloff * age;
+        }
+        if peak >= decay {
+            self.decay_peak = peak;
+            self.decay_age = 0;
+            decay = peak;
+        }
+
+        self.sum = 0.0;
+        self.peak = 0.0;
+
+        (rms, peak, decay)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Level {
+    timestamp: u64,
+    duration: u64,
+    // Per channel, in dB
+    rms: Vec<f64>,
+    peak: Vec<f64>,
+    decay: Vec<f64>,
+}
+
+impl Level {
+    // Message posted for every interval:
+    //
+    // "level, endtime=(guint64)..., timestamp=(guint64)..., stream-time=(guint64)...,
+    //  running-time=(guint64)..., duration=(guint64)..., rms=(double)<...>, peak=(double)<...>,
+    //  decay=(double)<...>"
+    //
+    // with one value per channel in the arrays.
+    fn to_message(
+        &self,
+        element: &BaseTransform,
+        segment: &gst::FormattedSegment<gst::ClockTime>,
+    ) -> gst::Message {
+        let timestamp = gst::ClockTime::from_nseconds(self.timestamp);
+        let values = |values: &[f64]| {
+            gst::Array::from_owned(values.iter().map(|v| v.to_send_value()).collect())
+        };
+
+        let s = gst::Structure::new(
+            "level",
+            &[
+                ("endtime", &(self.timestamp + self.duration)),
+                ("timestamp", &self.timestamp),
+                (
+                    "stream-time",
+                    &segment.to_stream_time(timestamp).0.unwrap_or(self.timestamp),
+                ),
+                (
+                    "running-time",
+                    &segment.to_running_time(timestamp).0.unwrap_or(self.timestamp),
+                ),
+                ("duration", &self.duration),
+                ("rms", &values(&self.rms)),
+                ("peak", &values(&self.peak)),
+                ("decay", &values(&self.decay)),
+            ],
+        );
+
+        gst::Message::new_element(s).src(Some(element)).build()
+    }
+}
+
+struct State {
+    info: gst_audio::AudioInfo,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    channels: Vec<Channel>,
+    // Frames measured in the current interval and the timestamp of the first one
+    frames: u64,
+    interval_start: u64,
+    // End of the last buffer, for buffers without timestamp
+    position: u64,
+}
+
+impl State {
+    fn new(info: gst_audio::AudioInfo, segment: gst::FormattedSegment<gst::ClockTime>) -> Self {
+        let channels = info.channels() as usize;
+        State {
+            info: info,
+            segment: segment,
+            channels: vec![Channel::default(); channels],
+            frames: 0,
+            interval_start: 0,
+            position: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        for channel in &mut self.channels {
+            *channel = Channel::default();
+        }
+        self.frames = 0;
+        self.position = 0;
+    }
+
+    fn finish_interval(&mut self, settings: &Settings) -> Level {
+        let duration = self.frames * gst::SECOND_VAL / u64::from(self.info.rate());
+        let mut level = Level {
+            timestamp: self.interval_start,
+            duration: duration,
+            rms: Vec::with_capacity(self.channels.len()),
+            peak: Vec::with_capacity(self.channels.len()),
+            decay: Vec::with_capacity(self.channels.len()),
+        };
+
+        for channel in &mut self.channels {
+            let (rms, peak, decay) = channel.finish(self.frames, duration, settings);
+            level.rms.push(rms);
+            level.peak.push(peak);
+            level.decay.push(decay);
+        }
+        self.frames = 0;
+
+        level
+    }
+
+    fn process<T: Copy, F: Fn(T) -> f64>(
+        &mut self,
+        data: &[T],
+        normalize: F,
+        pts: Option<u64>,
+        settings: &Settings,
+    ) -> Vec<Level> {
+        let rate = u64::from(self.info.rate());
+        let interval_frames = cmp::max(1, settings.interval * rate / gst::SECOND_VAL);
+        let pts = pts.unwrap_or(self.position);
+        let mut levels = Vec::new();
+
+        let mut num_frames = 0;
+        for (idx, frame) in data.chunks(self.channels.len()).enumerate() {
+            if self.frames == 0 {
+                self.interval_start = pts + idx as u64 * gst::SECOND_VAL / rate;
+            }
+
+            for (channel, &sample) in self.channels.iter_mut().zip(frame) {
+                channel.add(normalize(sample));
+            }
+
+            self.frames += 1;
+            if self.frames >= interval_frames {
+                levels.push(self.finish_interval(settings));
+            }
+            num_frames += 1;
+        }
+
+        self.position = pts + num_frames * gst::SECOND_VAL / rate;
+
+        levels
+    }
+}
+
+struct RsLevel {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl RsLevel {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rslevel",
+                gst::DebugColorFlags::empty(),
+                "Rust audio level measurement",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Level",
+            "Filter/Analyzer/Audio",
+            "Measures the RMS, peak and decaying peak levels of an audio stream",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                (
+                    "format",
+                    &gst::List::new(&[
+                        &gst_audio::AUDIO_FORMAT_S16.to_string(),
+                        &gst_audio::AUDIO_FORMAT_S32.to_string(),
+                        &gst_audio::AUDIO_FORMAT_F32.to_string(),
+                        &gst_audio::AUDIO_FORMAT_F64.to_string(),
+                    ]),
+                ),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, true, true);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    fn post_levels(
+        &self,
+        element: &BaseTransform,
+        segment: &gst::FormattedSegment<gst::ClockTime>,
+        levels: &[Level],
+    ) {
+        for level in levels {
+            gst_log!(self.cat, obj: element, "Measured {:?}", level);
+            let _ = element.post_message(&level.to_message(element, segment));
+        }
+    }
+}
+
+impl ObjectImpl<BaseTransform> for RsLevel {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt64("interval", ..) => {
+                settings.interval = value.get().unwrap();
+            }
+            Property::UInt64("peak-ttl", ..) => {
+                settings.peak_ttl = value.get().unwrap();
+            }
+            Property::Double("peak-falloff", ..) => {
+                settings.peak_falloff = value.get().unwrap();
+            }
+            Property::Boolean("post-messages", ..) => {
+                settings.post_messages = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt64("interval", ..) => Ok(settings.interval.to_value()),
+            Property::UInt64("peak-ttl", ..) => Ok(settings.peak_ttl.to_value()),
+            Property::Double("peak-falloff", ..) => Ok(settings.peak_falloff.to_value()),
+            Property::Boolean("post-messages", ..) => Ok(settings.post_messages.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for RsLevel {}
+
+impl BaseTransformImpl<BaseTransform> for RsLevel {
+    fn set_caps(&self, element: &BaseTransform, incaps: &gst::Caps, outcaps: &gst::Caps) -> bool {
+        if incaps != outcaps {
+            return false;
+        }
+
+        let info = match gst_audio::AudioInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", incaps);
+
+        let mut state = self.state.lock().unwrap();
+        let segment = state
+            .take()
+            .map(|state| state.segment)
+            .unwrap_or_else(gst::FormattedSegment::new);
+        *state = Some(State::new(info, segment));
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseTransform) -> bool {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        true
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(ref mut state) = *state {
+                    state.segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                        Ok(segment) => segment,
+                        Err(_) => {
+                            gst_warning!(self.cat, obj: element, "Not a time segment");
+                            gst::FormattedSegment::new()
+                        }
+                    };
+                }
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(ref mut state) = *state {
+                    state.reset();
+                }
+            }
+            EventView::Eos(..) => {
+                // Post the level of the last, incomplete interval
+                let settings = *self.settings.lock().unwrap();
+                let mut state = self.state.lock().unwrap();
+                if let Some(ref mut state) = *state {
+                    if state.frames > 0 {
+                        let level = state.finish_interval(&settings);
+                        if settings.post_messages {
+                            self.post_levels(element, &state.segment, &[level]);
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        element.parent_sink_event(event)
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return gst::FlowReturn::NotNegotiated,
+            Some(ref mut state) => state,
+        };
+
+        let map = match buf.map_readable() {
+            None => return gst::FlowReturn::Error,
+            Some(map) => map,
+        };
+
+        let pts = buf.get_pts().0;
+        let levels = match state.info.format() {
+            gst_audio::AUDIO_FORMAT_S16 => {
+                let data = map.as_slice().as_slice_of::<i16>().unwrap();
+                state.process(data, |s| f64::from(s) / 32_768.0, pts, &settings)
+            }
+            gst_audio::AUDIO_FORMAT_S32 => {
+                let data = map.as_slice().as_slice_of::<i32>().unwrap();
+                state.process(data, |s| f64::from(s) / 2_147_483_648.0, pts, &settings)
+            }
+            gst_audio::AUDIO_FORMAT_F32 => {
+                let data = map.as_slice().as_slice_of::<f32>().unwrap();
+                state.process(data, f64::from, pts, &settings)
+            }
+            gst_audio::AUDIO_FORMAT_F64 => {
+                let data = map.as_slice().as_slice_of::<f64>().unwrap();
+                state.process(data, |s| s, pts, &settings)
+            }
+            _ => return gst::FlowReturn::NotNegotiated,
+        };
+
+        if settings.post_messages {
+            self.post_levels(element, &state.segment, &levels);
+        }
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct RsLevelStatic;
+
+impl ImplTypeStatic<BaseTransform> for RsLevelStatic {
+    fn get_name(&self) -> &str {
+        "RsLevel"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        RsLevel::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        RsLevel::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let rslevel_static = RsLevelStatic;
+    register_type(rslevel_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel() {
+        let settings = Settings::default();
+        let mut channel = Channel::default();
+
+        // Square wave with half amplitude
+        for i in 0..4800 {
+            channel.add(if i % 2 == 0 { 0.5 } else { -0.5 });
+        }
+        let (rms, peak, decay) = channel.finish(4800, DEFAULT_INTERVAL, &settings);
+        assert!((rms + 6.0206).abs() < 1e-4);
+        assert!((peak + 6.0206).abs() < 1e-4);
+        assert_eq!(decay, peak);
+
+        // The decaying peak is held for the peak-ttl and then falls off by 1dB per interval
+        let mut decays = Vec::new();
+        for _ in 0..5 {
+            for _ in 0..4800 {
+                channel.add(0.0);
+            }
+            let (rms, peak, decay) = channel.finish(4800, DEFAULT_INTERVAL, &settings);
+            assert_eq!(rms, f64::NEG_INFINITY);
+            assert_eq!(peak, f64::NEG_INFINITY);
+            decays.push(decay + 6.0206);
+        }
+        assert!(decays[..3].iter().all(|d| d.abs() < 1e-4));
+        assert!((decays[3] + 1.0).abs() < 1e-4);
+        assert!((decays[4] + 2.0).abs() < 1e-4);
+    }
+}
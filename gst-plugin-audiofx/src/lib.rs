@@ -17,21 +17,38 @@ extern crate gstreamer_audio as gst_audio;
 extern crate gstreamer_base as gst_base;
 extern crate num_traits;
 
+use gst_plugin::registration::*;
+
+mod audioaligner;
+mod audioanalyse;
 mod audioecho;
+mod audiomixer;
+mod audiotestsrc;
+mod channelfix;
+mod level;
+mod notedetect;
 
 fn plugin_init(plugin: &gst::Plugin) -> bool {
-    audioecho::register(plugin);
-    true
+    ElementRegistration::new(plugin)
+        .element("rsaudioecho", RANK_NONE, audioecho::get_type())
+        .element("rsaudioaligner", RANK_NONE, audioaligner::get_type())
+        .element("rschannelfix", RANK_NONE, channelfix::get_type())
+        .element("notedetect", RANK_NONE, notedetect::get_type())
+        .element("rslevel", RANK_NONE, level::get_type())
+        .element("audioanalyse", RANK_NONE, audioanalyse::get_type())
+        .element("rsaudiotestsrc", RANK_NONE, audiotestsrc::get_type())
+        .element("rsaudiomixer", RANK_NONE, audiomixer::get_type())
+        .register()
 }
 
 plugin_define!(
-    b"rsaudiofx\0",
-    b"Rust AudioFx Plugin\0",
+    "rsaudiofx",
+    "Rust AudioFx Plugin",
     plugin_init,
-    b"1.0\0",
-    b"MIT/X11\0",
-    b"rsaudiofx\0",
-    b"rsaudiofx\0",
-    b"https://github.com/sdroege/rsplugin\0",
-    b"2016-12-08\0"
+    "1.0",
+    "MIT/X11",
+    "rsaudiofx",
+    "rsaudiofx",
+    "https://github.com/sdroege/rsplugin",
+    "2016-12-08"
 );
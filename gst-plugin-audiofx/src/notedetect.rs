@@ -0,0 +1,766 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_audio;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::{i32, u64};
+use std::sync::Mutex;
+
+use byte_slice_cast::*;
+
+// Note detection behaviour:
+//
+// The mono input is cut into consecutive windows of "window-size" samples and the fundamental
+// frequency of every window is estimated with the YIN algorithm. Windows below the silence
+// threshold or without a clear periodicity don't have a note.
+//
+// A note only starts or ends once the detected note stayed the same for STABLE_WINDOWS windows,
+// which hides octave errors and short glitches. Every start and end is output as a MIDI
+// note-on/note-off event buffer timestamped with the start of the window in which the change
+// was detected, and optionally posted as an element message.
+
+const DEFAULT_WINDOW_SIZE: u32 = 2048;
+const DEFAULT_THRESHOLD: f64 = 0.15;
+const DEFAULT_MIN_FREQUENCY: f64 = 50.0;
+const DEFAULT_MAX_FREQUENCY: f64 = 2000.0;
+const DEFAULT_SILENCE_THRESHOLD: f64 = -50.0;
+const DEFAULT_POST_MESSAGES: bool = true;
+
+const STABLE_WINDOWS: u32 = 2;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    window_size: u32,
+    threshold: f64,
+    min_frequency: f64,
+    max_frequency: f64,
+    silence_threshold: f64,
+    post_messages: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            window_size: DEFAULT_WINDOW_SIZE,
+            threshold: DEFAULT_THRESHOLD,
+            min_frequency: DEFAULT_MIN_FREQUENCY,
+            max_frequency: DEFAULT_MAX_FREQUENCY,
+            silence_threshold: DEFAULT_SILENCE_THRESHOLD,
+            post_messages: DEFAULT_POST_MESSAGES,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 6] = [
+    Property::UInt(
+        "window-size",
+        "Window Size",
+        "Number of samples analysed for every pitch estimate",
+        (256, 16384),
+        DEFAULT_WINDOW_SIZE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "threshold",
+        "Threshold",
+        "Periodicity threshold, lower values only detect cleaner tones",
+        (0.01, 1.0),
+        DEFAULT_THRESHOLD,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "min-frequency",
+        "Minimum Frequency",
+        "Lowest detected frequency in Hz",
+        (10.0, 20000.0),
+        DEFAULT_MIN_FREQUENCY,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "max-frequency",
+        "Maximum Frequency",
+        "Highest detected frequency in Hz",
+        (10.0, 20000.0),
+        DEFAULT_MAX_FREQUENCY,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "silence-threshold",
+        "Silence Threshold",
+        "Level in dB below which no note is detected",
+        (-200.0, 0.0),
+        DEFAULT_SILENCE_THRESHOLD,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "post-messages",
+        "Post Messages",
+        "Post an element message for every note-on and note-off",
+        DEFAULT_POST_MESSAGES,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+// Estimates the fundamental frequency of the samples with the YIN algorithm
+fn detect_pitch(
+    samples: &[f32],
+    rate: u32,
+    threshold: f64,
+    min_frequency: f64,
+    max_frequency: f64,
+) -> Option<f64> {
+    let rate = f64::from(rate);
+    let tau_min = ((rate / max_frequency) as usize).max(2);
+    let tau_max = ((rate / min_frequency) as usize).min(samples.len() / 2);
+    if tau_min + 1 >= tau_max {
+        return None;
+    }
+    let width = samples.len() - tau_max;
+
+    // Cumulative mean normalized difference function
+    let mut cmnd = vec![1.0f64; tau_max + 1];
+    let mut sum = 0.0;
+    for tau in 1..(tau_max + 1) {
+        let mut d = 0.0;
+        for (&a, &b) in samples[..width].iter().zip(samples[tau..].iter()) {
+            let diff = f64::from(a) - f64::from(b);
+            d += diff * diff;
+        }
+        sum += d;
+        cmnd[tau] = if sum > 0.0 { d * tau as f64 / sum } else { 1.0 };
+    }
+
+    let mut tau = tau_min;
+    while tau < tau_max && cmnd[tau] >= threshold {
+        tau += 1;
+    }
+    if tau >= tau_max {
+        return None;
+    }
+    while tau + 1 < tau_max && cmnd[tau + 1] < cmnd[tau] {
+        tau += 1;
+    }
+
+    // Parabolic interpolation around the minimum
+    let (prev, cur, next) = (cmnd[tau - 1], cmnd[tau], cmnd[tau + 1]);
+    let denom = prev - 2.0 * cur + next;
+    let shift = if denom.abs() > 1e-12 {
+        (0.5 * (prev - next) / denom).max(-0.5).min(0.5)
+    } else {
+        0.0
+    };
+
+    Some(rate / (tau as f64 + shift))
+}
+
+fn frequency_to_note(frequency: f64) -> Option<u8> {
+    let note = (69.0 + 12.0 * (frequency / 440.0).log2()).round();
+    if note >= 0.0 && note <= 127.0 {
+        Some(note as u8)
+    } else {
+        None
+    }
+}
+
+// Maps the level between the silence threshold and 0dB to the MIDI velocity range
+fn level_to_velocity(level: f64, silence_threshold: f64) -> u8 {
+    let velocity = 1.0 + 126.0 * (level - silence_threshold) / -silence_threshold;
+    velocity.round().max(1.0).min(127.0) as u8
+}
+
+#[derive(Debug, Default)]
+struct NoteTracker {
+    active: Option<u8>,
+    candidate: Option<u8>,
+    count: u32,
+}
+
+impl NoteTracker {
+    // Returns the note that ended and the note that started, if any
+    fn update(&mut self, note: Option<u8>) -> (Option<u8>, Option<u8>) {
+        if note == self.active {
+            self.candidate = None;
+            self.count = 0;
+            return (None, None);
+        }
+
+        if self.count > 0 && note == self.candidate {
+            self.count += 1;
+        } else {
+            self.candidate = note;
+            self.count = 1;
+        }
+
+        if self.count < STABLE_WINDOWS {
+            return (None, None);
+        }
+
+        self.candidate = None;
+        self.count = 0;
+        let off = self.active.take();
+        self.active = note;
+        (off, note)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct NoteEvent {
+    on: bool,
+    note: u8,
+    velocity: u8,
+    frequency: f64,
+    timestamp: gst::ClockTime,
+}
+
+impl NoteEvent {
+    fn to_buffer(&self) -> gst::Buffer {
+        let data = if self.on {
+            vec![0x90, self.note, self.velocity]
+        } else {
+            vec![0x80, self.note, 0]
+        };
+
+        let mut buffer = gst::Buffer::from_mut_slice(data).unwrap();
+        buffer.get_mut().unwrap().set_pts(self.timestamp);
+        buffer
+    }
+
+    // Message posted for every event:
+    //
+    // "notedetect, type=(string){note-on,note-off}, note=(uint)..., velocity=(uint)...,
+    //  frequency=(double)..., timestamp=(guint64)..."
+    fn to_message(&self, element: &Element) -> gst::Message {
+        let mut s = gst::Structure::new(
+            "notedetect",
+            &[
+                ("type", &if self.on { "note-on" } else { "note-off" }),
+                ("note", &u32::from(self.note)),
+                ("velocity", &u32::from(self.velocity)),
+                ("frequency", &self.frequency),
+            ],
+        );
+        if let Some(timestamp) = self.timestamp.0 {
+            s.get_mut().unwrap().set("timestamp", &timestamp);
+        }
+
+        gst::Message::new_element(s).src(Some(element)).build()
+    }
+}
+
+struct State {
+    info: Option<gst_audio::AudioInfo>,
+    samples: Vec<f32>,
+    // Timestamp of the first sample in samples
+    window_start: gst::ClockTime,
+    // Timestamp after the last sample received
+    position: gst::ClockTime,
+    tracker: NoteTracker,
+    // Frequency and velocity of the active note
+    frequency: f64,
+    velocity: u8,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            info: None,
+            samples: Vec::new(),
+            window_start: gst::CLOCK_TIME_NONE,
+            position: gst::CLOCK_TIME_NONE,
+            tracker: NoteTracker::default(),
+            frequency: 0.0,
+            velocity: 0,
+        }
+    }
+}
+
+impl State {
+    fn reset(&mut self) {
+        self.samples.clear();
+        self.window_start = gst::CLOCK_TIME_NONE;
+        self.position = gst::CLOCK_TIME_NONE;
+        self.tracker = NoteTracker::default();
+    }
+
+    fn analyse(&mut self, rate: u32, settings: &Settings) -> Vec<NoteEvent> {
+        let frames = self.samples.len() as f64;
+        let power = self.samples
+            .iter()
+            .map(|&s| f64::from(s) * f64::from(s))
+            .sum::<f64>() / frames;
+        let level = 10.0 * power.log10();
+
+        let frequency = if level < settings.silence_threshold {
+            None
+        } else {
+            detect_pitch(
+                &self.samples,
+                rate,
+                settings.threshold,
+                settings.min_frequency,
+                settings.max_frequency,
+            )
+        };
+        let note = frequency.and_then(frequency_to_note);
+
+        let mut events = Vec::new();
+        let (off, on) = self.tracker.update(note);
+        if let Some(off) = off {
+            events.push(NoteEvent {
+                on: false,
+                note: off,
+                velocity: 0,
+                frequency: self.frequency,
+                timestamp: self.window_start,
+            });
+        }
+        if let Some(on) = on {
+            self.frequency = frequency.unwrap();
+            self.velocity = level_to_velocity(level, settings.silence_threshold);
+            events.push(NoteEvent {
+                on: true,
+                note: on,
+                velocity: self.velocity,
+                frequency: self.frequency,
+                timestamp: self.window_start,
+            });
+        }
+
+        events
+    }
+
+    fn process(
+        &mut self,
+        data: &[f32],
+        pts: gst::ClockTime,
+        settings: &Settings,
+    ) -> Vec<NoteEvent> {
+        let rate = self.info.as_ref().unwrap().rate();
+        let window_size = settings.window_size as usize;
+        let mut events = Vec::new();
+
+        for (idx, &sample) in data.iter().enumerate() {
+            if self.samples.is_empty() {
+                self.window_start = gst::ClockTime(pts.0.map(|pts| {
+                    pts + idx as u64 * gst::SECOND_VAL / u64::from(rate)
+                }));
+            }
+
+            self.samples.push(sample);
+            if self.samples.len() >= window_size {
+                events.extend(self.analyse(rate, settings));
+                self.samples.clear();
+            }
+        }
+
+        self.position = gst::ClockTime(pts.0.map(|pts| {
+            pts + data.len() as u64 * gst::SECOND_VAL / u64::from(rate)
+        }));
+
+        events
+    }
+
+    // Ends the active note at the current position
+    fn drain(&mut self) -> Option<NoteEvent> {
+        self.samples.clear();
+        let note = self.tracker.active.take();
+        self.tracker = NoteTracker::default();
+
+        note.map(|note| NoteEvent {
+            on: false,
+            note: note,
+            velocity: 0,
+            frequency: self.frequency,
+            timestamp: self.position,
+        })
+    }
+}
+
+struct NoteDetect {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl NoteDetect {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "notedetect",
+                gst::DebugColorFlags::empty(),
+                "Audio to MIDI note detection",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Note Detect",
+            "Filter/Analyzer/Audio",
+            "Detects monophonic notes in audio and outputs them as MIDI events",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &gst_audio::AUDIO_FORMAT_F32.to_string()),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &1i32),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple("audio/x-midi-event", &[]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            NoteDetect::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |notedetect, element| notedetect.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            NoteDetect::catch_panic_pad_function(
+                parent,
+                || false,
+                |notedetect, element| notedetect.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            NoteDetect::catch_panic_pad_function(
+                parent,
+                || false,
+                |notedetect, element| notedetect.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            NoteDetect::catch_panic_pad_function(
+                parent,
+                || false,
+                |notedetect, element| notedetect.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            NoteDetect::catch_panic_pad_function(
+                parent,
+                || false,
+                |notedetect, element| notedetect.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let notedetect = element.get_impl().downcast_ref::<NoteDetect>().unwrap();
+        element.catch_panic(fallback, |element| f(notedetect, element))
+    }
+
+    fn push_events(
+        &self,
+        element: &Element,
+        events: &[NoteEvent],
+        post_messages: bool,
+    ) -> gst::FlowReturn {
+        for event in events {
+            gst_debug!(self.cat, obj: element, "Detected {:?}", event);
+
+            if post_messages {
+                let _ = element.post_message(&event.to_message(element));
+            }
+
+            let flow_ret = self.srcpad.push(event.to_buffer());
+            if flow_ret != gst::FlowReturn::Ok {
+                return flow_ret;
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let events = {
+            let mut state = self.state.lock().unwrap();
+            if state.info.is_none() {
+                gst_element_error!(element, gst::CoreError::Negotiation, ["Have no caps"]);
+                return gst::FlowReturn::NotNegotiated;
+            }
+
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+            let data = map.as_slice().as_slice_of::<f32>().unwrap();
+
+            state.process(data, buffer.get_pts(), &settings)
+        };
+
+        self.push_events(element, &events, settings.post_messages)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let info = match gst_audio::AudioInfo::from_caps(e.get_caps()) {
+                    None => return false,
+                    Some(info) => info,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                state.samples.clear();
+                state.info = Some(info);
+                drop(state);
+
+                return self.srcpad.push_event(
+                    gst::Event::new_caps(&gst::Caps::new_simple("audio/x-midi-event", &[]))
+                        .build(),
+                );
+            }
+            EventView::FlushStop(..) => {
+                self.state.lock().unwrap().reset();
+            }
+            EventView::Eos(..) => {
+                let post_messages = self.settings.lock().unwrap().post_messages;
+                let note_off = self.state.lock().unwrap().drain();
+                if let Some(note_off) = note_off {
+                    let _ = self.push_events(element, &[note_off], post_messages);
+                }
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        // Caps are not related between both pads
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for NoteDetect {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("window-size", ..) => {
+                settings.window_size = value.get().unwrap();
+            }
+            Property::Double("threshold", ..) => {
+                settings.threshold = value.get().unwrap();
+            }
+            Property::Double("min-frequency", ..) => {
+                settings.min_frequency = value.get().unwrap();
+            }
+            Property::Double("max-frequency", ..) => {
+                settings.max_frequency = value.get().unwrap();
+            }
+            Property::Double("silence-threshold", ..) => {
+                settings.silence_threshold = value.get().unwrap();
+            }
+            Property::Boolean("post-messages", ..) => {
+                settings.post_messages = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("window-size", ..) => Ok(settings.window_size.to_value()),
+            Property::Double("threshold", ..) => Ok(settings.threshold.to_value()),
+            Property::Double("min-frequency", ..) => Ok(settings.min_frequency.to_value()),
+            Property::Double("max-frequency", ..) => Ok(settings.max_frequency.to_value()),
+            Property::Double("silence-threshold", ..) => {
+                Ok(settings.silence_threshold.to_value())
+            }
+            Property::Boolean("post-messages", ..) => Ok(settings.post_messages.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for NoteDetect {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct NoteDetectStatic;
+
+impl ImplTypeStatic<Element> for NoteDetectStatic {
+    fn get_name(&self) -> &str {
+        "NoteDetect"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        NoteDetect::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        NoteDetect::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let notedetect_static = NoteDetectStatic;
+    register_type(notedetect_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let t = i as f64 / f64::from(rate);
+                (0.5 * (2.0 * ::std::f64::consts::PI * frequency * t).sin()) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_pitch() {
+        for &frequency in &[82.41, 220.0, 440.0, 1046.5] {
+            let samples = sine(frequency, 48000, 2048);
+            let detected = detect_pitch(&samples, 48000, 0.15, 50.0, 2000.0).unwrap();
+            assert!((detected - frequency).abs() / frequency < 0.01);
+        }
+
+        assert_eq!(frequency_to_note(440.0), Some(69));
+        assert_eq!(frequency_to_note(261.63), Some(60));
+
+        // Silence has no periodicity
+        assert_eq!(detect_pitch(&[0.0; 2048], 48000, 0.15, 50.0, 2000.0), None);
+    }
+
+    #[test]
+    fn test_note_tracker() {
+        let mut tracker = NoteTracker::default();
+
+        assert_eq!(tracker.update(Some(60)), (None, None));
+        assert_eq!(tracker.update(Some(60)), (None, Some(60)));
+        // Single window glitches are ignored
+        assert_eq!(tracker.update(Some(72)), (None, None));
+        assert_eq!(tracker.update(Some(60)), (None, None));
+        assert_eq!(tracker.update(Some(62)), (None, None));
+        assert_eq!(tracker.update(Some(62)), (Some(60), Some(62)));
+        assert_eq!(tracker.update(None), (None, None));
+        assert_eq!(tracker.update(None), (Some(62), None));
+    }
+}
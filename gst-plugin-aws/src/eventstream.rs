@@ -0,0 +1,202 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// AWS event stream encoding as used by the streaming APIs, all fields big endian:
+//
+// +--------------+---------------+-------------+---------+---------+-------------+
+// | total length | header length | prelude CRC | headers | payload | message CRC |
+// |     u32      |      u32      |     u32     |         |         |     u32     |
+// +--------------+---------------+-------------+---------+---------+-------------+
+//
+// The prelude CRC covers the two lengths, the message CRC everything before it. Every header is
+//
+// +-------------+------+------------+-------+
+// | name length | name | value type | value |
+// |     u8      |      |     u8     |       |
+// +-------------+------+------------+-------+
+//
+// Only string values (type 7, prefixed with a u16 length) are used by the APIs, values of the
+// other types are skipped.
+
+use byteorder::{BigEndian, ByteOrder};
+
+const PRELUDE_SIZE: usize = 12;
+const CRC_SIZE: usize = 4;
+
+const TYPE_STRING: u8 = 7;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub headers: Vec<(String, String)>,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&&(ref n, _)| n == name)
+            .map(|&(_, ref value)| value.as_str())
+    }
+}
+
+// CRC-32 with the IEEE polynomial, as used by zlib
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &b in data {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+pub fn encode(headers: &[(&str, &str)], payload: &[u8]) -> Vec<u8> {
+    let mut header_data = Vec::new();
+    for &(name, value) in headers {
+        header_data.push(name.len() as u8);
+        header_data.extend_from_slice(name.as_bytes());
+        header_data.push(TYPE_STRING);
+        let mut len = [0; 2];
+        BigEndian::write_u16(&mut len, value.len() as u16);
+        header_data.extend_from_slice(&len);
+        header_data.extend_from_slice(value.as_bytes());
+    }
+
+    let total_len = PRELUDE_SIZE + header_data.len() + payload.len() + CRC_SIZE;
+    let mut data = vec![0; PRELUDE_SIZE];
+    BigEndian::write_u32(&mut data[0..4], total_len as u32);
+    BigEndian::write_u32(&mut data[4..8], header_data.len() as u32);
+    let prelude_crc = crc32(&data[0..8]);
+    BigEndian::write_u32(&mut data[8..12], prelude_crc);
+
+    data.extend_from_slice(&header_data);
+    data.extend_from_slice(payload);
+    let mut crc = [0; 4];
+    BigEndian::write_u32(&mut crc, crc32(&data));
+    data.extend_from_slice(&crc);
+
+    data
+}
+
+fn value_size(value_type: u8, data: &[u8]) -> Option<usize> {
+    let size = match value_type {
+        // true, false
+        0 | 1 => 0,
+        // byte, short, integer, long
+        2 => 1,
+        3 => 2,
+        4 => 4,
+        5 => 8,
+        // byte array, string
+        6 | 7 => {
+            if data.len() < 2 {
+                return None;
+            }
+            2 + BigEndian::read_u16(data) as usize
+        }
+        // timestamp, UUID
+        8 => 8,
+        9 => 16,
+        _ => return None,
+    };
+
+    if size > data.len() {
+        None
+    } else {
+        Some(size)
+    }
+}
+
+fn parse_headers(mut data: &[u8]) -> Result<Vec<(String, String)>, String> {
+    let mut headers = Vec::new();
+
+    while !data.is_empty() {
+        let name_len = data[0] as usize;
+        if data.len() < 1 + name_len + 1 {
+            return Err(String::from("Truncated header"));
+        }
+        let name = String::from_utf8_lossy(&data[1..(1 + name_len)]).into_owned();
+        let value_type = data[1 + name_len];
+        data = &data[(2 + name_len)..];
+
+        let size = value_size(value_type, data)
+            .ok_or_else(|| format!("Invalid value for header '{}'", name))?;
+        if value_type == TYPE_STRING {
+            let value = String::from_utf8_lossy(&data[2..size]).into_owned();
+            headers.push((name, value));
+        }
+        data = &data[size..];
+    }
+
+    Ok(headers)
+}
+
+pub fn decode(data: &[u8]) -> Result<Message, String> {
+    if data.len() < PRELUDE_SIZE + CRC_SIZE {
+        return Err(String::from("Message too short"));
+    }
+
+    let total_len = BigEndian::read_u32(&data[0..4]) as usize;
+    let header_len = BigEndian::read_u32(&data[4..8]) as usize;
+    if BigEndian::read_u32(&data[8..12]) != crc32(&data[0..8]) {
+        return Err(String::from("Invalid prelude CRC"));
+    }
+    if total_len != data.len() || PRELUDE_SIZE + header_len + CRC_SIZE > total_len {
+        return Err(String::from("Invalid message length"));
+    }
+
+    let crc_offset = total_len - CRC_SIZE;
+    if BigEndian::read_u32(&data[crc_offset..]) != crc32(&data[..crc_offset]) {
+        return Err(String::from("Invalid message CRC"));
+    }
+
+    let payload_offset = PRELUDE_SIZE + header_len;
+    Ok(Message {
+        headers: parse_headers(&data[PRELUDE_SIZE..payload_offset])?,
+        payload: data[payload_offset..crc_offset].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let data = encode(
+            &[(":message-type", "event"), (":event-type", "AudioEvent")],
+            &[1, 2, 3],
+        );
+        assert_eq!(BigEndian::read_u32(&data[0..4]) as usize, data.len());
+
+        let message = decode(&data).unwrap();
+        assert_eq!(message.header(":event-type"), Some("AudioEvent"));
+        assert_eq!(message.header(":content-type"), None);
+        assert_eq!(message.payload, vec![1, 2, 3]);
+
+        let mut corrupted = data.clone();
+        corrupted[20] ^= 0xff;
+        assert!(decode(&corrupted).is_err());
+        assert!(decode(&data[..data.len() - 1]).is_err());
+
+        // Non-string headers are skipped
+        let mut headers = vec![4, b'f', b'l', b'a', b'g', 0];
+        headers.extend_from_slice(&[3, b'n', b'u', b'm', 4, 0, 0, 0, 42]);
+        assert_eq!(parse_headers(&headers), Ok(Vec::new()));
+        assert!(parse_headers(&headers[..8]).is_err());
+    }
+}
@@ -0,0 +1,50 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate byteorder;
+extern crate glib;
+#[macro_use]
+extern crate gst_plugin;
+#[macro_use]
+extern crate gstreamer as gst;
+extern crate openssl;
+extern crate reqwest;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate tungstenite;
+extern crate url;
+
+use gst_plugin::registration::*;
+
+mod eventstream;
+mod signer;
+
+mod polly;
+mod transcriber;
+mod translate;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    ElementRegistration::new(plugin)
+        .element("awstranscriber", RANK_NONE, transcriber::get_type())
+        .element("awspolly", RANK_NONE, polly::get_type())
+        .element("awstranslate", RANK_NONE, translate::get_type())
+        .register()
+}
+
+plugin_define!(
+    "rsaws",
+    "Rust AWS Plugin",
+    plugin_init,
+    "MIT/X11",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2018-01-22"
+);
@@ -0,0 +1,734 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::cmp;
+use std::io::Read;
+use std::sync::Mutex;
+
+use reqwest::Client;
+use reqwest::header::Headers;
+use serde_json;
+
+use signer::{self, Credentials};
+
+// Synthesizes the speech of every text buffer with AWS Polly and outputs it at the position of
+// the text, e.g. for audio description tracks.
+//
+// The speech of a text buffer with a duration is fitted into that duration: Polly is asked to
+// speak faster if needed, and whatever is still too long is cut off. The time before, between and
+// after the texts is filled with silence so that the output is a continuous audio stream. A text
+// that starts while the speech of the previous one is still going on is delayed and only gets the
+// remainder of its duration.
+//
+// The synthesis happens on the streaming thread, so the audio is output late by the time the
+// service needs for each text.
+
+const DEFAULT_VOICE_ID: &str = "Joanna";
+const DEFAULT_REGION: &str = "us-east-1";
+const DEFAULT_SAMPLE_RATE: u32 = 16000;
+
+const SERVICE: &str = "polly";
+const PATH: &str = "/v1/speech";
+
+// Silence is output in buffers of at most this duration
+const SILENCE_DURATION: u64 = 100 * gst::MSECOND_VAL;
+
+static PROPERTIES: [Property; 7] = [
+    Property::String(
+        "voice-id",
+        "Voice ID",
+        "Voice to use, e.g. Joanna or Hans",
+        Some(DEFAULT_VOICE_ID),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "language-code",
+        "Language Code",
+        "Language of bilingual voices, e.g. en-IN or hi-IN (default: the voice's default)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "region",
+        "Region",
+        "AWS region of the service",
+        Some(DEFAULT_REGION),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "sample-rate",
+        "Sample Rate",
+        "Sample rate of the output, 8000 or 16000",
+        (8000, 16000),
+        DEFAULT_SAMPLE_RATE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "access-key",
+        "Access Key",
+        "AWS access key ID (default: from the environment or the credentials file)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "secret-access-key",
+        "Secret Access Key",
+        "AWS secret access key (default: from the environment or the credentials file)",
+        None,
+        PropertyMutability::Writable,
+    ),
+    Property::String(
+        "session-token",
+        "Session Token",
+        "AWS session token of temporary credentials, can be updated at any time and is used for \
+         the next request",
+        None,
+        PropertyMutability::Writable,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    voice_id: String,
+    language_code: Option<String>,
+    region: String,
+    sample_rate: u32,
+    access_key: Option<String>,
+    secret_access_key: Option<String>,
+    session_token: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            voice_id: DEFAULT_VOICE_ID.into(),
+            language_code: None,
+            region: DEFAULT_REGION.into(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            access_key: None,
+            secret_access_key: None,
+            session_token: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SynthesizeSpeechRequest<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language_code: Option<&'a str>,
+    output_format: &'a str,
+    sample_rate: String,
+    text: String,
+    text_type: &'a str,
+    voice_id: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// SSML for the text, spoken faster if it would take longer than the maximum duration in
+// milliseconds
+fn ssml(text: &str, max_duration: Option<u64>) -> String {
+    match max_duration {
+        Some(max_duration) => format!(
+            "<speak><prosody amazon:max-duration=\"{}ms\">{}</prosody></speak>",
+            max_duration,
+            escape(text)
+        ),
+        None => format!("<speak>{}</speak>", escape(text)),
+    }
+}
+
+fn audio_caps(rate: u32) -> gst::Caps {
+    gst::Caps::new_simple(
+        "audio/x-raw",
+        &[
+            ("format", &"S16LE"),
+            ("rate", &(rate as i32)),
+            ("channels", &1),
+            ("layout", &"interleaved"),
+        ],
+    )
+}
+
+struct State {
+    client: Client,
+    rate: u32,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    // Samples output since the start of the segment
+    offset: u64,
+}
+
+impl State {
+    fn time_for(&self, samples: u64) -> u64 {
+        self.segment.get_start().0.unwrap_or(0) + samples * gst::SECOND_VAL / u64::from(self.rate)
+    }
+
+    fn samples_for(&self, pts: u64) -> u64 {
+        let start = self.segment.get_start().0.unwrap_or(0);
+        pts.saturating_sub(start) * u64::from(self.rate) / gst::SECOND_VAL
+    }
+
+    fn output(&mut self, data: Vec<u8>, buffers: &mut Vec<gst::Buffer>) {
+        let samples = (data.len() / 2) as u64;
+        if samples == 0 {
+            return;
+        }
+
+        let pts = self.time_for(self.offset);
+        self.offset += samples;
+        let end = self.time_for(self.offset);
+
+        let mut buffer = gst::Buffer::from_mut_slice(data).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from(pts));
+            buffer.set_duration(gst::ClockTime::from(end - pts));
+        }
+        buffers.push(buffer);
+    }
+
+    // Outputs silence until the given sample offset
+    fn fill_silence(&mut self, until: u64, buffers: &mut Vec<gst::Buffer>) {
+        let chunk = cmp::max(1, u64::from(self.rate) * SILENCE_DURATION / gst::SECOND_VAL);
+        while self.offset < until {
+            let samples = cmp::min(chunk, until - self.offset);
+            self.output(vec![0; samples as usize * 2], buffers);
+        }
+    }
+}
+
+struct Polly {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl Polly {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "awspolly",
+                gst::DebugColorFlags::empty(),
+                "AWS Polly text-to-speech",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "AWS Polly",
+            "Text/Audio/Filter",
+            "Synthesizes speech from text with AWS Polly",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("text/x-raw", &[("format", &"utf8")]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &"S16LE"),
+                ("rate", &gst::IntRange::<i32>::new(8000, 16000)),
+                ("channels", &1),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            Polly::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |polly, element| polly.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            Polly::catch_panic_pad_function(
+                parent,
+                || false,
+                |polly, element| polly.sink_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let polly = element.get_impl().downcast_ref::<Polly>().unwrap();
+        element.catch_panic(fallback, |element| f(polly, element))
+    }
+
+    // Returns the speech as S16LE mono samples
+    fn synthesize(
+        &self,
+        element: &Element,
+        client: &Client,
+        settings: &Settings,
+        text: &str,
+        max_duration: Option<u64>,
+    ) -> Result<Vec<u8>, String> {
+        let credentials = Credentials::load(
+            settings.access_key.as_ref().map(String::as_str),
+            settings.secret_access_key.as_ref().map(String::as_str),
+            settings.session_token.as_ref().map(String::as_str),
+        )?;
+
+        let request = SynthesizeSpeechRequest {
+            language_code: settings.language_code.as_ref().map(String::as_str),
+            output_format: "pcm",
+            sample_rate: settings.sample_rate.to_string(),
+            text: ssml(text, max_duration),
+            text_type: "ssml",
+            voice_id: &settings.voice_id,
+        };
+        let payload = serde_json::to_vec(&request)
+            .map_err(|err| format!("Failed to serialize request: {}", err))?;
+
+        let host = format!("polly.{}.amazonaws.com", settings.region);
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Type", "application/json");
+        for (name, value) in signer::authorization_headers(
+            &credentials,
+            &settings.region,
+            SERVICE,
+            &host,
+            PATH,
+            &payload,
+            signer::now(),
+        ) {
+            headers.set_raw(name, value);
+        }
+
+        gst_debug!(self.cat, obj: element, "Synthesizing {:?}", request.text);
+
+        let url = format!("https://{}{}", host, PATH);
+        let mut response = client
+            .post(&url)
+            .headers(headers)
+            .body(payload)
+            .send()
+            .map_err(|err| format!("Failed to send request to {}: {}", host, err))?;
+
+        let mut data = Vec::new();
+        response
+            .read_to_end(&mut data)
+            .map_err(|err| format!("Failed to read response from {}: {}", host, err))?;
+
+        if !response.status().is_success() {
+            let message = serde_json::from_slice::<ErrorResponse>(&data)
+                .map(|err| err.message)
+                .unwrap_or_else(|_| String::from_utf8_lossy(&data).into_owned());
+            return Err(format!(
+                "Synthesis failed with {}: {}",
+                response.status(),
+                message
+            ));
+        }
+
+        Ok(data)
+    }
+
+    fn push_buffers(&self, buffers: Vec<gst::Buffer>) -> gst::FlowReturn {
+        for buffer in buffers {
+            gst_log!(self.cat, obj: &self.srcpad, "Pushing buffer {:?}", buffer);
+            let ret = self.srcpad.push(buffer);
+            if ret != gst::FlowReturn::Ok {
+                return ret;
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let pts = match buffer.get_pts().0 {
+            None => {
+                gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(pts) => pts,
+        };
+        let duration = buffer.get_duration().0;
+
+        let text = match buffer.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => String::from_utf8_lossy(map.as_slice())
+                .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                .to_string(),
+        };
+
+        // Silence until the text starts, and the number of samples its speech can take
+        let (client, rate, buffers, end, available) = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match *state_guard {
+                None => return gst::FlowReturn::Flushing,
+                Some(ref mut state) => state,
+            };
+
+            let mut buffers = Vec::new();
+            let start = state.samples_for(pts);
+            state.fill_silence(start, &mut buffers);
+
+            let end = duration.map(|duration| state.samples_for(pts + duration));
+            let available = end.map(|end| end.saturating_sub(state.offset));
+
+            (state.client.clone(), state.rate, buffers, end, available)
+        };
+
+        let ret = self.push_buffers(buffers);
+        if ret != gst::FlowReturn::Ok {
+            return ret;
+        }
+
+        let max_duration = available.map(|samples| samples * 1000 / u64::from(rate));
+        let mut data = if text.is_empty() || max_duration == Some(0) {
+            gst_debug!(self.cat, obj: element, "Nothing to synthesize");
+            Vec::new()
+        } else {
+            let settings = self.settings.lock().unwrap().clone();
+            match self.synthesize(element, &client, &settings, &text, max_duration) {
+                Ok(data) => data,
+                Err(err) => {
+                    gst_element_error!(element, gst::ResourceError::Read, ["{}", err]);
+                    return gst::FlowReturn::Error;
+                }
+            }
+        };
+
+        let len = match available {
+            Some(available) => cmp::min(data.len() / 2, available as usize) * 2,
+            None => data.len() / 2 * 2,
+        };
+        if len < data.len() {
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "Cutting off {} samples of speech",
+                (data.len() - len) / 2
+            );
+            data.truncate(len);
+        }
+
+        let buffers = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match *state_guard {
+                None => return gst::FlowReturn::Flushing,
+                Some(ref mut state) => state,
+            };
+
+            let mut buffers = Vec::new();
+            state.output(data, &mut buffers);
+            if let Some(end) = end {
+                state.fill_silence(end, &mut buffers);
+            }
+            buffers
+        };
+
+        self.push_buffers(buffers)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.segment = gst::FormattedSegment::new();
+                    state.offset = 0;
+                }
+            }
+            EventView::StreamStart(..) => {
+                let rate = match *self.state.lock().unwrap() {
+                    None => return false,
+                    Some(ref state) => state.rate,
+                };
+
+                if !self.srcpad.push_event(event.clone()) {
+                    return false;
+                }
+                return self.srcpad
+                    .push_event(gst::Event::new_caps(&audio_caps(rate)).build());
+            }
+            EventView::Caps(..) => {
+                return true;
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                // The audio is timestamped in the same segment as the text
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.segment = segment;
+                    state.offset = 0;
+                }
+            }
+            EventView::Gap(e) => {
+                let (timestamp, duration) = e.get();
+                let buffers = match (
+                    timestamp.0,
+                    duration.0,
+                    self.state.lock().unwrap().as_mut(),
+                ) {
+                    (Some(timestamp), Some(duration), Some(state)) => {
+                        let mut buffers = Vec::new();
+                        let end = state.samples_for(timestamp + duration);
+                        state.fill_silence(end, &mut buffers);
+                        buffers
+                    }
+                    _ => Vec::new(),
+                };
+
+                return self.push_buffers(buffers) == gst::FlowReturn::Ok;
+            }
+            _ => (),
+        }
+
+        pad.event_default(Some(&element.clone().upcast()), event)
+    }
+
+    fn start(&self, element: &Element) -> bool {
+        let sample_rate = self.settings.lock().unwrap().sample_rate;
+        if sample_rate != 8000 && sample_rate != 16000 {
+            gst_element_error!(
+                element,
+                gst::ResourceError::Settings,
+                ["Unsupported sample rate {}", sample_rate]
+            );
+            return false;
+        }
+
+        let client = match Client::builder().build() {
+            Ok(client) => client,
+            Err(err) => {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::OpenRead,
+                    ["Failed to create HTTP client: {}", err]
+                );
+                return false;
+            }
+        };
+
+        *self.state.lock().unwrap() = Some(State {
+            client: client,
+            rate: sample_rate,
+            segment: gst::FormattedSegment::new(),
+            offset: 0,
+        });
+
+        true
+    }
+}
+
+impl ObjectImpl<Element> for Polly {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("voice-id", ..) => {
+                settings.voice_id = value.get().unwrap_or_else(|| DEFAULT_VOICE_ID.into());
+            }
+            Property::String("language-code", ..) => {
+                settings.language_code = value.get();
+            }
+            Property::String("region", ..) => {
+                settings.region = value.get().unwrap_or_else(|| DEFAULT_REGION.into());
+            }
+            Property::UInt("sample-rate", ..) => {
+                settings.sample_rate = value.get().unwrap();
+            }
+            Property::String("access-key", ..) => {
+                settings.access_key = value.get();
+            }
+            Property::String("secret-access-key", ..) => {
+                settings.secret_access_key = value.get();
+            }
+            Property::String("session-token", ..) => {
+                settings.session_token = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("voice-id", ..) => Ok(settings.voice_id.to_value()),
+            Property::String("language-code", ..) => Ok(settings.language_code.to_value()),
+            Property::String("region", ..) => Ok(settings.region.to_value()),
+            Property::UInt("sample-rate", ..) => Ok(settings.sample_rate.to_value()),
+            Property::String("access-key", ..) => Ok(settings.access_key.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for Polly {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if transition == gst::StateChange::ReadyToPaused && !self.start(element) {
+            return gst::StateChangeReturn::Failure;
+        }
+
+        let ret = element.parent_change_state(transition);
+
+        if transition == gst::StateChange::PausedToReady {
+            *self.state.lock().unwrap() = None;
+        }
+
+        ret
+    }
+}
+
+struct PollyStatic;
+
+impl ImplTypeStatic<Element> for PollyStatic {
+    fn get_name(&self) -> &str {
+        "AwsPolly"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        Polly::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        Polly::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let polly_static = PollyStatic;
+    register_type(polly_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request() {
+        assert_eq!(
+            ssml("Tom & Jerry <3", Some(1500)),
+            "<speak><prosody amazon:max-duration=\"1500ms\">Tom &amp; Jerry &lt;3</prosody>\
+             </speak>"
+        );
+        assert_eq!(ssml("It's", None), "<speak>It&apos;s</speak>");
+
+        let request = SynthesizeSpeechRequest {
+            language_code: None,
+            output_format: "pcm",
+            sample_rate: String::from("16000"),
+            text: ssml("Hello", None),
+            text_type: "ssml",
+            voice_id: "Joanna",
+        };
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            "{\"OutputFormat\":\"pcm\",\"SampleRate\":\"16000\",\
+             \"Text\":\"<speak>Hello</speak>\",\"TextType\":\"ssml\",\"VoiceId\":\"Joanna\"}"
+        );
+    }
+}
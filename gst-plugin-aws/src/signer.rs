@@ -0,0 +1,429 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// AWS credentials and Signature Version 4 request signing
+
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+// Presigned URLs only need to be valid until the connection is established
+const URL_EXPIRES: u32 = 300;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+    pub session_token: Option<String>,
+}
+
+impl Credentials {
+    /// Uses the given keys, or the ones from the AWS_* environment variables, or the ones from
+    /// the profile in the shared credentials file, in that order.
+    ///
+    /// This is called for every new connection so that refreshed temporary credentials are
+    /// picked up.
+    pub fn load(
+        access_key: Option<&str>,
+        secret_key: Option<&str>,
+        session_token: Option<&str>,
+    ) -> Result<Credentials, String> {
+        if let (Some(access_key), Some(secret_key)) = (access_key, secret_key) {
+            return Ok(Credentials {
+                access_key: String::from(access_key),
+                secret_key: String::from(secret_key),
+                session_token: session_token.map(String::from),
+            });
+        }
+
+        if let (Ok(access_key), Ok(secret_key)) = (
+            env::var("AWS_ACCESS_KEY_ID"),
+            env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            return Ok(Credentials {
+                access_key: access_key,
+                secret_key: secret_key,
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            });
+        }
+
+        let path = env::var("AWS_SHARED_CREDENTIALS_FILE")
+            .map(PathBuf::from)
+            .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".aws/credentials")))
+            .map_err(|_| String::from("No AWS credentials found"))?;
+        let profile = env::var("AWS_PROFILE").unwrap_or_else(|_| String::from("default"));
+
+        let mut content = String::new();
+        File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .map_err(|err| format!("No AWS credentials found: {}: {}", path.display(), err))?;
+
+        parse_credentials_file(&content, &profile).ok_or_else(|| {
+            format!(
+                "No AWS credentials for profile '{}' in {}",
+                profile,
+                path.display()
+            )
+        })
+    }
+}
+
+// INI style file with one section per profile
+fn parse_credentials_file(content: &str, profile: &str) -> Option<Credentials> {
+    let mut in_profile = false;
+    let mut access_key = None;
+    let mut secret_key = None;
+    let mut session_token = None;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            in_profile = line[1..line.len() - 1].trim() == profile;
+            continue;
+        }
+
+        if !in_profile {
+            continue;
+        }
+
+        let mut split = line.splitn(2, '=');
+        let (key, value) = match (split.next(), split.next()) {
+            (Some(key), Some(value)) => (key.trim(), String::from(value.trim())),
+            _ => continue,
+        };
+        match key {
+            "aws_access_key_id" => access_key = Some(value),
+            "aws_secret_access_key" => secret_key = Some(value),
+            "aws_session_token" => session_token = Some(value),
+            _ => (),
+        }
+    }
+
+    match (access_key, secret_key) {
+        (Some(access_key), Some(secret_key)) => Some(Credentials {
+            access_key: access_key,
+            secret_key: secret_key,
+            session_token: session_token,
+        }),
+        _ => None,
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Formats seconds since the epoch as ISO 8601 basic format in UTC, e.g. 20180122T123456Z
+pub fn format_date(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let secs = timestamp % 86_400;
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&hash(MessageDigest::sha256(), data).unwrap())
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = PKey::hmac(key).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(data).unwrap();
+    signer.sign_to_vec().unwrap()
+}
+
+// Percent-encodes everything but the unreserved characters
+pub fn uri_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if (b as char).is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+fn signature(
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    amz_date: &str,
+    canonical_request: &str,
+) -> String {
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM,
+        amz_date,
+        scope,
+        sha256_hex(canonical_request.as_bytes())
+    );
+
+    let key = signing_key(&credentials.secret_key, date, region, service);
+    hex(&hmac_sha256(&key, string_to_sign.as_bytes()))
+}
+
+/// Creates a wss:// URL that is authenticated by its query parameters, which is the only way to
+/// authenticate WebSocket connections.
+///
+/// `host` includes the port if it's not the default one and `params` are unencoded.
+pub fn presign_url(
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    host: &str,
+    path: &str,
+    params: &[(&str, String)],
+    timestamp: u64,
+) -> String {
+    let amz_date = format_date(timestamp);
+    let mut params: Vec<(String, String)> = params
+        .iter()
+        .map(|&(key, ref value)| (String::from(key), value.clone()))
+        .collect();
+    params.push((String::from("X-Amz-Algorithm"), String::from(ALGORITHM)));
+    params.push((
+        String::from("X-Amz-Credential"),
+        format!(
+            "{}/{}/{}/{}/aws4_request",
+            credentials.access_key,
+            &amz_date[..8],
+            region,
+            service
+        ),
+    ));
+    params.push((String::from("X-Amz-Date"), amz_date.clone()));
+    params.push((String::from("X-Amz-Expires"), URL_EXPIRES.to_string()));
+    if let Some(ref session_token) = credentials.session_token {
+        params.push((String::from("X-Amz-Security-Token"), session_token.clone()));
+    }
+    params.push((String::from("X-Amz-SignedHeaders"), String::from("host")));
+
+    let mut params: Vec<(String, String)> = params
+        .into_iter()
+        .map(|(key, value)| (uri_encode(&key), uri_encode(&value)))
+        .collect();
+    params.sort();
+    let query = params
+        .iter()
+        .map(|&(ref key, ref value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let canonical_request = format!(
+        "GET\n{}\n{}\nhost:{}\n\nhost\n{}",
+        path,
+        query,
+        host,
+        sha256_hex(b"")
+    );
+    let signature = signature(credentials, region, service, &amz_date, &canonical_request);
+
+    format!(
+        "wss://{}{}?{}&X-Amz-Signature={}",
+        host, path, query, signature
+    )
+}
+
+/// Headers that authenticate a POST request with the given payload, to be sent together with the
+/// `Host` header.
+pub fn authorization_headers(
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    host: &str,
+    path: &str,
+    payload: &[u8],
+    timestamp: u64,
+) -> Vec<(&'static str, String)> {
+    let amz_date = format_date(timestamp);
+
+    let mut headers = vec![("host", String::from(host)), ("x-amz-date", amz_date.clone())];
+    if let Some(ref session_token) = credentials.session_token {
+        headers.push(("x-amz-security-token", session_token.clone()));
+    }
+
+    let canonical_headers: String = headers
+        .iter()
+        .map(|&(name, ref value)| format!("{}:{}\n", name, value))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|&(name, _)| name)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "POST\n{}\n\n{}\n{}\n{}",
+        path,
+        canonical_headers,
+        signed_headers,
+        sha256_hex(payload)
+    );
+    let signature = signature(credentials, region, service, &amz_date, &canonical_request);
+
+    let authorization = format!(
+        "{} Credential={}/{}/{}/{}/aws4_request, SignedHeaders={}, Signature={}",
+        ALGORITHM,
+        credentials.access_key,
+        &amz_date[..8],
+        region,
+        service,
+        signed_headers,
+        signature
+    );
+
+    let mut headers: Vec<(&'static str, String)> = headers.into_iter().skip(1).collect();
+    headers.push(("Authorization", authorization));
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "19700101T000000Z");
+        assert_eq!(format_date(1_516_624_496), "20180122T123456Z");
+        assert_eq!(format_date(951_827_696), "20000229T123456Z");
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(
+            uri_encode("AKID/20180122/us-east-1"),
+            "AKID%2F20180122%2Fus-east-1"
+        );
+        assert_eq!(uri_encode("a-b_c.d~e f+g="), "a-b_c.d~e%20f%2Bg%3D");
+    }
+
+    #[test]
+    fn test_signing_key() {
+        // Example from the AWS documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn test_authorization_headers() {
+        let credentials = Credentials {
+            access_key: String::from("AKIDEXAMPLE"),
+            secret_key: String::from("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"),
+            session_token: Some(String::from("TOKEN")),
+        };
+        let headers = authorization_headers(
+            &credentials,
+            "us-east-1",
+            "polly",
+            "polly.us-east-1.amazonaws.com",
+            "/v1/speech",
+            br#"{"Text":"Hello"}"#,
+            1_440_938_160,
+        );
+
+        assert_eq!(
+            headers,
+            vec![
+                ("x-amz-date", String::from("20150830T123600Z")),
+                ("x-amz-security-token", String::from("TOKEN")),
+                (
+                    "Authorization",
+                    String::from(
+                        "AWS4-HMAC-SHA256 \
+                         Credential=AKIDEXAMPLE/20150830/us-east-1/polly/aws4_request, \
+                         SignedHeaders=host;x-amz-date;x-amz-security-token, \
+                         Signature=d69d6f067ef942feb83e7026ead49808939979933179d8cc7e780245801d8d0c",
+                    ),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_credentials_file() {
+        let content = "[default]\n\
+                       aws_access_key_id = AKID\n\
+                       aws_secret_access_key=SECRET\n\
+                       \n\
+                       # Temporary credentials\n\
+                       [other]\n\
+                       aws_access_key_id = AKID2\n\
+                       aws_secret_access_key = SECRET2\n\
+                       aws_session_token = TOKEN\n";
+
+        assert_eq!(
+            parse_credentials_file(content, "default"),
+            Some(Credentials {
+                access_key: String::from("AKID"),
+                secret_key: String::from("SECRET"),
+                session_token: None,
+            })
+        );
+        assert_eq!(
+            parse_credentials_file(content, "other").and_then(|c| c.session_token),
+            Some(String::from("TOKEN"))
+        );
+        assert_eq!(parse_credentials_file(content, "missing"), None);
+    }
+}
@@ -0,0 +1,1153 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::task::start_pad_task;
+
+use std::cmp;
+use std::io;
+use std::mem;
+use std::net::TcpStream;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use std::u32;
+
+use serde_json;
+use tungstenite::{self, Message, WebSocket};
+use tungstenite::client::AutoStream;
+use tungstenite::stream::Stream;
+use url::Url;
+
+use eventstream;
+use signer::{self, Credentials};
+
+// Streams the audio to the AWS Transcribe streaming API and outputs every transcribed word or
+// punctuation mark as a separate text buffer.
+//
+// The audio is sent from the streaming thread, the responses are received by a separate socket
+// thread and the text is output from a pad task. Every connection is a new session of the
+// service, and the times of its results are relative to the first audio buffer sent in it.
+// If the service ends a session, e.g. because of its maximum duration, the next audio buffer
+// starts a new one with freshly loaded credentials.
+//
+// The service sends partial results that are revised until they are final. Items of partial
+// results are output once they are final, once the service marks them as stable (see the
+// results-stability property), or once their running time plus the latency is reached. Output
+// items are never revised.
+
+const DEFAULT_LANGUAGE_CODE: &str = "en-US";
+const DEFAULT_REGION: &str = "us-east-1";
+const DEFAULT_LATENCY: u32 = 8000;
+const DEFAULT_RESULTS_STABILITY: &str = "none";
+
+const SERVICE: &str = "transcribe";
+const PATH: &str = "/stream-transcription-websocket";
+
+const AUDIO_HEADERS: [(&str, &str); 3] = [
+    (":content-type", "application/octet-stream"),
+    (":event-type", "AudioEvent"),
+    (":message-type", "event"),
+];
+
+// Audio is sent in chunks of this duration as recommended for the streaming API
+const CHUNK_DURATION: u64 = 100 * gst::MSECOND_VAL;
+// Maximum time the socket thread blocks on reading before sending pending audio
+const SOCKET_TIMEOUT: u64 = 10;
+// Maximum time the pad task blocks without checking for flushing and timed out items
+const POLL_INTERVAL: u64 = 100;
+
+static PROPERTIES: [Property; 8] = [
+    Property::String(
+        "language-code",
+        "Language Code",
+        "Language of the audio, e.g. en-US or de-DE",
+        Some(DEFAULT_LANGUAGE_CODE),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "region",
+        "Region",
+        "AWS region of the service",
+        Some(DEFAULT_REGION),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "latency",
+        "Latency",
+        "Time in milliseconds after which partial results are output even if they might still \
+         change",
+        (0, u32::MAX),
+        DEFAULT_LATENCY,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "results-stability",
+        "Results Stability",
+        "Stabilization of partial results: \"none\", or \"low\", \"medium\" or \"high\" to output \
+         stable items earlier with more or less accuracy",
+        Some(DEFAULT_RESULTS_STABILITY),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "vocabulary-name",
+        "Vocabulary Name",
+        "Name of a custom vocabulary (default: none)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "access-key",
+        "Access Key",
+        "AWS access key ID (default: from the environment or the credentials file)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "secret-access-key",
+        "Secret Access Key",
+        "AWS secret access key (default: from the environment or the credentials file)",
+        None,
+        PropertyMutability::Writable,
+    ),
+    Property::String(
+        "session-token",
+        "Session Token",
+        "AWS session token of temporary credentials, can be updated at any time and is used for \
+         the next session",
+        None,
+        PropertyMutability::Writable,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    language_code: String,
+    region: String,
+    latency: u32,
+    results_stability: String,
+    vocabulary_name: Option<String>,
+    access_key: Option<String>,
+    secret_access_key: Option<String>,
+    session_token: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            language_code: DEFAULT_LANGUAGE_CODE.into(),
+            region: DEFAULT_REGION.into(),
+            latency: DEFAULT_LATENCY,
+            results_stability: DEFAULT_RESULTS_STABILITY.into(),
+            vocabulary_name: None,
+            access_key: None,
+            secret_access_key: None,
+            session_token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TranscriptItem {
+    content: String,
+    start_time: f64,
+    end_time: f64,
+    #[serde(default)]
+    stable: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TranscriptAlternative {
+    items: Vec<TranscriptItem>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TranscriptResult {
+    alternatives: Vec<TranscriptAlternative>,
+    is_partial: bool,
+    result_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Transcript {
+    results: Vec<TranscriptResult>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TranscriptEvent {
+    transcript: Transcript,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Exception {
+    message: String,
+}
+
+// Results of one event stream message, or the error the service reported
+fn parse_response(data: &[u8]) -> Result<Vec<TranscriptResult>, String> {
+    let message = eventstream::decode(data)?;
+
+    match message.header(":message-type") {
+        Some("event") if message.header(":event-type") == Some("TranscriptEvent") => {
+            let event: TranscriptEvent = serde_json::from_slice(&message.payload)
+                .map_err(|err| format!("Invalid transcript: {}", err))?;
+            Ok(event.transcript.results)
+        }
+        Some("exception") => {
+            let exception_type = message.header(":exception-type").unwrap_or("Exception");
+            let exception: Exception = serde_json::from_slice(&message.payload)
+                .map_err(|err| format!("Invalid exception: {}", err))?;
+            Err(format!("{}: {}", exception_type, exception.message))
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn seconds_to_ns(seconds: f64) -> u64 {
+    if seconds > 0.0 {
+        (seconds * gst::SECOND_VAL as f64) as u64
+    } else {
+        0
+    }
+}
+
+fn text_buffer(pts: u64, duration: u64, text: &str) -> gst::Buffer {
+    let mut buffer = gst::Buffer::from_mut_slice(text.as_bytes().to_vec()).unwrap();
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(gst::ClockTime::from(pts));
+        buffer.set_duration(gst::ClockTime::from(duration));
+    }
+    buffer
+}
+
+// The underlying TCP connection, for setting timeouts
+fn tcp_stream(stream: &AutoStream) -> &TcpStream {
+    match *stream {
+        Stream::Plain(ref stream) => stream,
+        Stream::Tls(ref stream) => stream.get_ref(),
+    }
+}
+
+// Messages of the socket thread, with the session they belong to
+enum Response {
+    Transcript(u32, TranscriptResult),
+    Error(u32, String),
+    Closed(u32),
+}
+
+struct Connection {
+    // Audio to send, an empty chunk ends the session
+    sender: mpsc::Sender<Vec<u8>>,
+    stop: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Connection {
+    fn close(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = self.thread.join();
+    }
+}
+
+struct State {
+    rate: Option<u32>,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    // Serialized events to be output before the next text
+    pending_events: Vec<gst::Event>,
+    // Text to be output by the pad task
+    pending_buffers: Vec<gst::Buffer>,
+    connection: Option<Connection>,
+    results: mpsc::Sender<Response>,
+    // Responses of previous sessions are ignored
+    session: u32,
+    // PTS of the first audio of the session
+    session_start: u64,
+    // Items of the current partial result and how many of them were output already
+    result_id: Option<String>,
+    items: Vec<TranscriptItem>,
+    num_output: usize,
+    // PTS of the last output item, to keep the output in order
+    position: u64,
+    eos: bool,
+    flushing: bool,
+}
+
+impl State {
+    fn new(results: mpsc::Sender<Response>) -> Self {
+        State {
+            rate: None,
+            segment: gst::FormattedSegment::new(),
+            pending_events: Vec::new(),
+            pending_buffers: Vec::new(),
+            connection: None,
+            results: results,
+            session: 0,
+            session_start: 0,
+            result_id: None,
+            items: Vec::new(),
+            num_output: 0,
+            position: 0,
+            eos: false,
+            flushing: true,
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            connection.close();
+        }
+
+        // Items of the last partial result are not going to be final anymore
+        self.drain();
+        self.result_id = None;
+    }
+
+    fn reset(&mut self) {
+        self.close();
+        self.segment = gst::FormattedSegment::new();
+        self.pending_events.clear();
+        self.pending_buffers.clear();
+        self.position = 0;
+        self.eos = false;
+    }
+
+    fn output_next(&mut self) {
+        let item = self.items[self.num_output].clone();
+        self.num_output += 1;
+
+        let start = self.session_start + seconds_to_ns(item.start_time);
+        let end = self.session_start + seconds_to_ns(item.end_time);
+        let pts = cmp::max(start, self.position);
+        self.position = pts;
+
+        let buffer = text_buffer(pts, end.saturating_sub(pts), &item.content);
+        self.pending_buffers.push(buffer);
+    }
+
+    fn drain(&mut self) {
+        while self.num_output < self.items.len() {
+            self.output_next();
+        }
+        self.items.clear();
+        self.num_output = 0;
+    }
+
+    fn handle_result(&mut self, result: TranscriptResult) {
+        if self.result_id.as_ref() != Some(&result.result_id) {
+            self.drain();
+            self.result_id = Some(result.result_id.clone());
+        }
+        self.items = result
+            .alternatives
+            .into_iter()
+            .next()
+            .map(|alternative| alternative.items)
+            .unwrap_or_else(Vec::new);
+
+        while self.num_output < self.items.len() {
+            if result.is_partial && !self.items[self.num_output].stable {
+                break;
+            }
+            self.output_next();
+        }
+
+        if !result.is_partial {
+            self.drain();
+            self.result_id = None;
+        }
+    }
+
+    // Outputs the items of the partial result whose running time plus the latency is reached
+    fn handle_timeout(&mut self, now: u64, latency: u64) {
+        while self.num_output < self.items.len() {
+            let start = self.items[self.num_output].start_time;
+            let pts = self.session_start + seconds_to_ns(start);
+            match self.segment.to_running_time(gst::ClockTime::from(pts)).0 {
+                Some(running_time) if running_time + latency <= now => (),
+                _ => break,
+            }
+            self.output_next();
+        }
+    }
+}
+
+struct Transcriber {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+    receiver: Mutex<Option<mpsc::Receiver<Response>>>,
+}
+
+impl Transcriber {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "awstranscriber",
+                gst::DebugColorFlags::empty(),
+                "AWS Transcribe speech-to-text",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+            receiver: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "AWS Transcriber",
+            "Audio/Text/Filter",
+            "Transcribes speech to text with AWS Transcribe",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &"S16LE"),
+                ("rate", &gst::IntRange::<i32>::new(8000, 48000)),
+                ("channels", &1),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple("text/x-raw", &[("format", &"utf8")]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            Transcriber::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |transcriber, element| transcriber.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            Transcriber::catch_panic_pad_function(
+                parent,
+                || false,
+                |transcriber, element| transcriber.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            Transcriber::catch_panic_pad_function(
+                parent,
+                || false,
+                |transcriber, element| transcriber.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_activatemode_function(|pad, parent, mode, active| {
+            Transcriber::catch_panic_pad_function(
+                parent,
+                || false,
+                |transcriber, _element| transcriber.src_activatemode(pad, mode, active),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            Transcriber::catch_panic_pad_function(
+                parent,
+                || false,
+                |transcriber, element| transcriber.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            Transcriber::catch_panic_pad_function(
+                parent,
+                || false,
+                |transcriber, element| transcriber.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let transcriber = element.get_impl().downcast_ref::<Transcriber>().unwrap();
+        element.catch_panic(fallback, |element| f(transcriber, element))
+    }
+
+    fn start_task(pad: &gst::Pad) -> bool {
+        let task_pad = pad.clone();
+        start_pad_task(pad, move || {
+            let ret = Transcriber::catch_panic_pad_function(
+                &task_pad.get_parent(),
+                || gst::FlowReturn::Error,
+                |transcriber, element| transcriber.src_loop(element),
+            );
+            if ret != gst::FlowReturn::Ok {
+                let _ = task_pad.pause_task();
+            }
+        })
+    }
+
+    fn src_activatemode(&self, pad: &gst::Pad, mode: gst::PadMode, active: bool) -> bool {
+        if mode != gst::PadMode::Push {
+            return false;
+        }
+
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.flushing = !active;
+        }
+
+        if active {
+            Transcriber::start_task(pad)
+        } else {
+            pad.stop_task().is_ok()
+        }
+    }
+
+    fn connect(
+        &self,
+        element: &Element,
+        settings: &Settings,
+        rate: u32,
+    ) -> Result<WebSocket<AutoStream>, String> {
+        let credentials = Credentials::load(
+            settings.access_key.as_ref().map(String::as_str),
+            settings.secret_access_key.as_ref().map(String::as_str),
+            settings.session_token.as_ref().map(String::as_str),
+        )?;
+
+        let mut params = vec![
+            ("language-code", settings.language_code.clone()),
+            ("media-encoding", String::from("pcm")),
+            ("sample-rate", rate.to_string()),
+        ];
+        if let Some(ref vocabulary_name) = settings.vocabulary_name {
+            params.push(("vocabulary-name", vocabulary_name.clone()));
+        }
+        if settings.results_stability != "none" {
+            params.push(("enable-partial-results-stabilization", String::from("true")));
+            params.push((
+                "partial-results-stability",
+                settings.results_stability.clone(),
+            ));
+        }
+
+        let host = format!("transcribestreaming.{}.amazonaws.com:8443", settings.region);
+        let location = signer::presign_url(
+            &credentials,
+            &settings.region,
+            SERVICE,
+            &host,
+            PATH,
+            &params,
+            signer::now(),
+        );
+        let url = Url::parse(&location).map_err(|err| format!("Invalid URL: {}", err))?;
+
+        let (socket, _) = tungstenite::connect(url)
+            .map_err(|err| format!("Failed to connect to {}: {}", host, err))?;
+        tcp_stream(socket.get_ref())
+            .set_read_timeout(Some(Duration::from_millis(SOCKET_TIMEOUT)))
+            .map_err(|err| format!("Failed to set up connection: {}", err))?;
+
+        gst_debug!(self.cat, obj: element, "Connected to {}", host);
+
+        Ok(socket)
+    }
+
+    // Sends the audio and forwards all results until the session ends
+    fn process(
+        cat: gst::DebugCategory,
+        element: &gst::Element,
+        socket: &mut WebSocket<AutoStream>,
+        session: u32,
+        audio: &mpsc::Receiver<Vec<u8>>,
+        results: &mpsc::Sender<Response>,
+        stop: &AtomicBool,
+    ) -> Result<(), String> {
+        while !stop.load(Ordering::SeqCst) {
+            loop {
+                match audio.try_recv() {
+                    Ok(data) => {
+                        let message = eventstream::encode(&AUDIO_HEADERS, &data);
+                        socket
+                            .write_message(Message::Binary(message))
+                            .map_err(|err| format!("Failed to send audio: {}", err))?;
+                    }
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+
+            let data = match socket.read_message() {
+                Ok(Message::Binary(data)) => data,
+                Ok(_) => continue,
+                Err(tungstenite::Error::Io(ref err))
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    continue;
+                }
+                Err(tungstenite::Error::ConnectionClosed(..)) => return Ok(()),
+                Err(err) => return Err(err.to_string()),
+            };
+
+            for result in parse_response(&data)? {
+                gst_log!(cat, obj: element, "Received result {:?}", result);
+                if results.send(Response::Transcript(session, result)).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run(
+        cat: gst::DebugCategory,
+        element: gst::Element,
+        mut socket: WebSocket<AutoStream>,
+        session: u32,
+        audio: mpsc::Receiver<Vec<u8>>,
+        results: mpsc::Sender<Response>,
+        stop: Arc<AtomicBool>,
+    ) {
+        let res =
+            Transcriber::process(cat, &element, &mut socket, session, &audio, &results, &stop);
+        let response = match res {
+            Ok(()) => Response::Closed(session),
+            Err(err) => Response::Error(session, err),
+        };
+        let _ = results.send(response);
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let pts = match buffer.get_pts().0 {
+            None => {
+                gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(pts) => pts,
+        };
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return gst::FlowReturn::Flushing,
+            Some(ref mut state) => state,
+        };
+        if state.flushing {
+            return gst::FlowReturn::Flushing;
+        }
+        if state.eos {
+            return gst::FlowReturn::Eos;
+        }
+
+        let rate = match state.rate {
+            None => {
+                gst_element_error!(element, gst::CoreError::Negotiation, ["No caps set"]);
+                return gst::FlowReturn::NotNegotiated;
+            }
+            Some(rate) => rate,
+        };
+
+        if state.connection.is_none() {
+            let settings = self.settings.lock().unwrap().clone();
+            let socket = match self.connect(element, &settings, rate) {
+                Ok(socket) => socket,
+                Err(err) => {
+                    gst_element_error!(element, gst::ResourceError::OpenReadWrite, ["{}", err]);
+                    return gst::FlowReturn::Error;
+                }
+            };
+
+            state.session = state.session.wrapping_add(1);
+            state.session_start = pts;
+
+            let (sender, receiver) = mpsc::channel();
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread = {
+                let cat = self.cat;
+                let element = element.clone().upcast::<gst::Element>();
+                let session = state.session;
+                let results = state.results.clone();
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    Transcriber::run(cat, element, socket, session, receiver, results, stop);
+                })
+            };
+
+            state.connection = Some(Connection {
+                sender: sender,
+                stop: stop,
+                thread: thread,
+            });
+        }
+
+        let map = match buffer.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map,
+        };
+
+        let chunk_size = (u64::from(rate) * 2 * CHUNK_DURATION / gst::SECOND_VAL) as usize;
+        let sent = {
+            let connection = state.connection.as_ref().unwrap();
+            map.as_slice()
+                .chunks(chunk_size)
+                .all(|chunk| connection.sender.send(chunk.to_vec()).is_ok())
+        };
+
+        // The next buffer starts a new session
+        if !sent {
+            gst_debug!(self.cat, obj: element, "Session ended");
+            state.close();
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn src_loop(&self, element: &Element) -> gst::FlowReturn {
+        let response = {
+            let receiver = self.receiver.lock().unwrap();
+            match *receiver {
+                None => return gst::FlowReturn::Flushing,
+                Some(ref receiver) => receiver.recv_timeout(Duration::from_millis(POLL_INTERVAL)),
+            }
+        };
+
+        // Output a bit early to make up for the polling
+        let latency = u64::from(self.settings.lock().unwrap().latency) * gst::MSECOND_VAL;
+        let latency = latency.saturating_sub(2 * POLL_INTERVAL * gst::MSECOND_VAL);
+        let now = match (element.get_clock(), element.get_base_time().0) {
+            (Some(clock), Some(base_time)) => {
+                clock.get_time().0.map(|now| now.saturating_sub(base_time))
+            }
+            _ => None,
+        };
+
+        let (events, buffers, eos, error) = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match *state_guard {
+                None => return gst::FlowReturn::Flushing,
+                Some(ref mut state) => state,
+            };
+            if state.flushing {
+                return gst::FlowReturn::Flushing;
+            }
+
+            let mut error = None;
+            match response {
+                Ok(Response::Transcript(session, result)) => {
+                    if session == state.session {
+                        state.handle_result(result);
+                    }
+                }
+                Ok(Response::Error(session, err)) => {
+                    if session == state.session {
+                        error = Some(err);
+                    }
+                }
+                Ok(Response::Closed(session)) => {
+                    if session == state.session && state.connection.is_some() {
+                        gst_debug!(self.cat, obj: element, "Session {} closed", session);
+                        state.close();
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return gst::FlowReturn::Flushing,
+            }
+
+            if let Some(now) = now {
+                state.handle_timeout(now, latency);
+            }
+
+            // After EOS the service closes the session once everything is transcribed
+            let eos = state.eos && state.connection.is_none();
+            let events = mem::replace(&mut state.pending_events, Vec::new());
+            let buffers = mem::replace(&mut state.pending_buffers, Vec::new());
+
+            (events, buffers, eos, error)
+        };
+
+        if let Some(err) = error {
+            gst_element_error!(element, gst::LibraryError::Failed, ["{}", err]);
+            return gst::FlowReturn::Error;
+        }
+
+        for event in events {
+            self.srcpad.push_event(event);
+        }
+
+        for buffer in buffers {
+            gst_log!(self.cat, obj: &self.srcpad, "Pushing buffer {:?}", buffer);
+            let ret = self.srcpad.push(buffer);
+            if ret != gst::FlowReturn::Ok {
+                return ret;
+            }
+        }
+
+        if eos {
+            self.srcpad.push_event(gst::Event::new_eos().build());
+            return gst::FlowReturn::Eos;
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::FlushStart(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.flushing = true;
+                }
+
+                let ret = self.srcpad.push_event(event);
+                let _ = self.srcpad.pause_task();
+                return ret;
+            }
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.reset();
+                    state.flushing = false;
+                }
+
+                let ret = self.srcpad.push_event(event);
+                Transcriber::start_task(&self.srcpad);
+                return ret;
+            }
+            EventView::StreamStart(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    let caps = gst::Caps::new_simple("text/x-raw", &[("format", &"utf8")]);
+                    state.pending_events.push(event.clone());
+                    state
+                        .pending_events
+                        .push(gst::Event::new_caps(&caps).build());
+                }
+                return true;
+            }
+            EventView::Caps(e) => {
+                let rate = e.get_caps()
+                    .get_structure(0)
+                    .and_then(|s| s.get::<i32>("rate"))
+                    .map(|rate| rate as u32);
+
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    // A new session is needed for a different rate
+                    if state.rate != rate {
+                        state.close();
+                    }
+                    state.rate = rate;
+                }
+                return true;
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                // The text is timestamped in the same segment as the audio
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.segment = segment;
+                    state.pending_events.push(event.clone());
+                }
+                return true;
+            }
+            EventView::Eos(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.eos = true;
+                    if let Some(ref connection) = state.connection {
+                        let _ = connection.sender.send(Vec::new());
+                    }
+                }
+                return true;
+            }
+            _ => (),
+        }
+
+        // Other serialized events don't apply to the text, which is output asynchronously
+        if event.is_serialized() {
+            true
+        } else {
+            self.srcpad.push_event(event)
+        }
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+        pad.query_default(Some(&element.clone().upcast()), query)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        let is_latency = match query.view() {
+            QueryView::Latency(..) => true,
+            _ => false,
+        };
+        if is_latency {
+            if !self.sinkpad.peer_query(query) {
+                return false;
+            }
+
+            let latency = u64::from(self.settings.lock().unwrap().latency) * gst::MSECOND_VAL;
+            let latency = gst::ClockTime::from(latency);
+            if let QueryView::Latency(ref mut q) = query.view_mut() {
+                let (_, min, max) = q.get_result();
+                q.set(true, min + latency, max + latency);
+            }
+            return true;
+        }
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+
+    fn start(&self, element: &Element) -> bool {
+        let results_stability = self.settings.lock().unwrap().results_stability.clone();
+        match results_stability.as_str() {
+            "none" | "low" | "medium" | "high" => (),
+            _ => {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::Settings,
+                    ["Unsupported results stability '{}'", results_stability]
+                );
+                return false;
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        *self.state.lock().unwrap() = Some(State::new(sender));
+        *self.receiver.lock().unwrap() = Some(receiver);
+
+        true
+    }
+}
+
+impl ObjectImpl<Element> for Transcriber {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("language-code", ..) => {
+                settings.language_code = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_LANGUAGE_CODE.into());
+            }
+            Property::String("region", ..) => {
+                settings.region = value.get().unwrap_or_else(|| DEFAULT_REGION.into());
+            }
+            Property::UInt("latency", ..) => {
+                settings.latency = value.get().unwrap();
+            }
+            Property::String("results-stability", ..) => {
+                settings.results_stability = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_RESULTS_STABILITY.into());
+            }
+            Property::String("vocabulary-name", ..) => {
+                settings.vocabulary_name = value.get();
+            }
+            Property::String("access-key", ..) => {
+                settings.access_key = value.get();
+            }
+            Property::String("secret-access-key", ..) => {
+                settings.secret_access_key = value.get();
+            }
+            Property::String("session-token", ..) => {
+                settings.session_token = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("language-code", ..) => Ok(settings.language_code.to_value()),
+            Property::String("region", ..) => Ok(settings.region.to_value()),
+            Property::UInt("latency", ..) => Ok(settings.latency.to_value()),
+            Property::String("results-stability", ..) => Ok(settings.results_stability.to_value()),
+            Property::String("vocabulary-name", ..) => Ok(settings.vocabulary_name.to_value()),
+            Property::String("access-key", ..) => Ok(settings.access_key.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for Transcriber {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if transition == gst::StateChange::ReadyToPaused && !self.start(element) {
+            return gst::StateChangeReturn::Failure;
+        }
+
+        let ret = element.parent_change_state(transition);
+
+        // The pad task is stopped by now
+        if transition == gst::StateChange::PausedToReady {
+            if let Some(mut state) = self.state.lock().unwrap().take() {
+                state.close();
+            }
+            *self.receiver.lock().unwrap() = None;
+        }
+
+        ret
+    }
+}
+
+struct TranscriberStatic;
+
+impl ImplTypeStatic<Element> for TranscriberStatic {
+    fn get_name(&self) -> &str {
+        "AwsTranscriber"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        Transcriber::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        Transcriber::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let transcriber_static = TranscriberStatic;
+    register_type(transcriber_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        let payload = br#"{"Transcript":{"Results":[{"Alternatives":[{"Items":[
+            {"Content":"Hello","EndTime":0.5,"StartTime":0.1,"Type":"pronunciation","Stable":true},
+            {"Content":"world","EndTime":0.9,"StartTime":0.6,"Type":"pronunciation"}],
+            "Transcript":"Hello world"}],"EndTime":0.9,"IsPartial":true,"ResultId":"abc",
+            "StartTime":0.1}]}}"#;
+        let data = eventstream::encode(
+            &[
+                (":message-type", "event"),
+                (":event-type", "TranscriptEvent"),
+                (":content-type", "application/json"),
+            ],
+            payload,
+        );
+
+        let results = parse_response(&data).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_partial);
+        assert_eq!(results[0].result_id, "abc");
+        let items = &results[0].alternatives[0].items;
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].content, "Hello");
+        assert!(items[0].stable);
+        assert!(!items[1].stable);
+        assert_eq!(seconds_to_ns(items[1].start_time), 600 * gst::MSECOND_VAL);
+
+        let data = eventstream::encode(
+            &[
+                (":message-type", "exception"),
+                (":exception-type", "BadRequestException"),
+            ],
+            br#"{"Message":"Invalid sample rate"}"#,
+        );
+        assert_eq!(
+            parse_response(&data).unwrap_err(),
+            "BadRequestException: Invalid sample rate"
+        );
+    }
+}
@@ -0,0 +1,641 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::io::Read;
+use std::sync::Mutex;
+use std::u32;
+
+use reqwest::Client;
+use reqwest::header::Headers;
+use serde_json;
+
+use signer::{self, Credentials};
+
+// Translates text buffers with AWS Translate.
+//
+// Translating every cue on its own loses the context of the sentence it is part of, which is
+// especially bad for the word by word output of a speech-to-text element. If accumulate-time is
+// set, cues are collected until one of them ends a sentence or they cover the accumulate-time
+// and are then translated together into a single buffer that spans all of them. This delays the
+// output by up to the accumulate-time, which is reported as latency.
+//
+// The translation happens on the streaming thread.
+
+const DEFAULT_SOURCE_LANGUAGE_CODE: &str = "en";
+const DEFAULT_TARGET_LANGUAGE_CODE: &str = "es";
+const DEFAULT_REGION: &str = "us-east-1";
+const DEFAULT_ACCUMULATE_TIME: u32 = 0;
+
+const SERVICE: &str = "translate";
+const TARGET: &str = "AWSShineFrontendService_20170701.TranslateText";
+
+static PROPERTIES: [Property; 7] = [
+    Property::String(
+        "source-language-code",
+        "Source Language Code",
+        "Language of the input text, e.g. en or de, or auto to detect it",
+        Some(DEFAULT_SOURCE_LANGUAGE_CODE),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "target-language-code",
+        "Target Language Code",
+        "Language to translate to, e.g. es or fr",
+        Some(DEFAULT_TARGET_LANGUAGE_CODE),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "region",
+        "Region",
+        "AWS region of the service",
+        Some(DEFAULT_REGION),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "accumulate-time",
+        "Accumulate Time",
+        "Maximum time in milliseconds to collect cues until the end of a sentence before \
+         translating them together (0=translate every cue on its own)",
+        (0, u32::MAX),
+        DEFAULT_ACCUMULATE_TIME,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "access-key",
+        "Access Key",
+        "AWS access key ID (default: from the environment or the credentials file)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "secret-access-key",
+        "Secret Access Key",
+        "AWS secret access key (default: from the environment or the credentials file)",
+        None,
+        PropertyMutability::Writable,
+    ),
+    Property::String(
+        "session-token",
+        "Session Token",
+        "AWS session token of temporary credentials, can be updated at any time and is used for \
+         the next request",
+        None,
+        PropertyMutability::Writable,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    source_language_code: String,
+    target_language_code: String,
+    region: String,
+    accumulate_time: u32,
+    access_key: Option<String>,
+    secret_access_key: Option<String>,
+    session_token: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            source_language_code: DEFAULT_SOURCE_LANGUAGE_CODE.into(),
+            target_language_code: DEFAULT_TARGET_LANGUAGE_CODE.into(),
+            region: DEFAULT_REGION.into(),
+            accumulate_time: DEFAULT_ACCUMULATE_TIME,
+            access_key: None,
+            secret_access_key: None,
+            session_token: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct TranslateTextRequest<'a> {
+    source_language_code: &'a str,
+    target_language_code: &'a str,
+    text: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TranslateTextResponse {
+    translated_text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+// Appends the text of a cue, separated by a space unless it is punctuation
+fn append(accumulated: &mut String, text: &str) {
+    let is_punctuation = text.chars()
+        .next()
+        .map(|c| ",.;:!?)".contains(c))
+        .unwrap_or(false);
+    if !accumulated.is_empty() && !is_punctuation {
+        accumulated.push(' ');
+    }
+    accumulated.push_str(text);
+}
+
+fn ends_sentence(text: &str) -> bool {
+    text.trim_right_matches(|c: char| c == '"' || c == '\'' || c == ')')
+        .ends_with(|c: char| c == '.' || c == '!' || c == '?')
+}
+
+// Cues collected for the next translation
+struct Pending {
+    text: String,
+    pts: u64,
+    end: u64,
+}
+
+struct State {
+    client: Client,
+    pending: Option<Pending>,
+}
+
+struct Translate {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl Translate {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "awstranslate",
+                gst::DebugColorFlags::empty(),
+                "AWS Translate text translation",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "AWS Translate",
+            "Text/Filter",
+            "Translates text with AWS Translate",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("text/x-raw", &[("format", &"utf8")]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            Translate::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |translate, element| translate.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            Translate::catch_panic_pad_function(
+                parent,
+                || false,
+                |translate, element| translate.sink_event(pad, element, event),
+            )
+        });
+
+        srcpad.set_query_function(|pad, parent, query| {
+            Translate::catch_panic_pad_function(
+                parent,
+                || false,
+                |translate, element| translate.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let translate = element.get_impl().downcast_ref::<Translate>().unwrap();
+        element.catch_panic(fallback, |element| f(translate, element))
+    }
+
+    fn translate(
+        &self,
+        element: &Element,
+        client: &Client,
+        settings: &Settings,
+        text: &str,
+    ) -> Result<String, String> {
+        let credentials = Credentials::load(
+            settings.access_key.as_ref().map(String::as_str),
+            settings.secret_access_key.as_ref().map(String::as_str),
+            settings.session_token.as_ref().map(String::as_str),
+        )?;
+
+        let request = TranslateTextRequest {
+            source_language_code: &settings.source_language_code,
+            target_language_code: &settings.target_language_code,
+            text: text,
+        };
+        let payload = serde_json::to_vec(&request)
+            .map_err(|err| format!("Failed to serialize request: {}", err))?;
+
+        let host = format!("translate.{}.amazonaws.com", settings.region);
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Type", "application/x-amz-json-1.1");
+        headers.set_raw("X-Amz-Target", TARGET);
+        for (name, value) in signer::authorization_headers(
+            &credentials,
+            &settings.region,
+            SERVICE,
+            &host,
+            "/",
+            &payload,
+            signer::now(),
+        ) {
+            headers.set_raw(name, value);
+        }
+
+        let url = format!("https://{}/", host);
+        let mut response = client
+            .post(&url)
+            .headers(headers)
+            .body(payload)
+            .send()
+            .map_err(|err| format!("Failed to send request to {}: {}", host, err))?;
+
+        let mut data = Vec::new();
+        response
+            .read_to_end(&mut data)
+            .map_err(|err| format!("Failed to read response from {}: {}", host, err))?;
+
+        if !response.status().is_success() {
+            let message = serde_json::from_slice::<ErrorResponse>(&data)
+                .map(|err| err.message)
+                .unwrap_or_else(|_| String::from_utf8_lossy(&data).into_owned());
+            return Err(format!(
+                "Translation failed with {}: {}",
+                response.status(),
+                message
+            ));
+        }
+
+        let response: TranslateTextResponse = serde_json::from_slice(&data)
+            .map_err(|err| format!("Invalid response from {}: {}", host, err))?;
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Translated {:?} to {:?}",
+            text,
+            response.translated_text
+        );
+
+        Ok(response.translated_text)
+    }
+
+    // Translates the pending cues and pushes the result
+    fn drain(&self, element: &Element) -> gst::FlowReturn {
+        let (client, pending) = match *self.state.lock().unwrap() {
+            None => return gst::FlowReturn::Flushing,
+            Some(ref mut state) => match state.pending.take() {
+                None => return gst::FlowReturn::Ok,
+                Some(pending) => (state.client.clone(), pending),
+            },
+        };
+
+        let settings = self.settings.lock().unwrap().clone();
+        let text = match self.translate(element, &client, &settings, &pending.text) {
+            Ok(text) => text,
+            Err(err) => {
+                gst_element_error!(element, gst::ResourceError::Read, ["{}", err]);
+                return gst::FlowReturn::Error;
+            }
+        };
+
+        let mut buffer = gst::Buffer::from_mut_slice(text.into_bytes()).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from(pending.pts));
+            buffer.set_duration(gst::ClockTime::from(pending.end - pending.pts));
+        }
+
+        gst_log!(self.cat, obj: &self.srcpad, "Pushing buffer {:?}", buffer);
+        self.srcpad.push(buffer)
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let pts = match buffer.get_pts().0 {
+            None => {
+                gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(pts) => pts,
+        };
+        let end = pts + buffer.get_duration().0.unwrap_or(0);
+
+        let text = match buffer.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => String::from_utf8_lossy(map.as_slice())
+                .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                .to_string(),
+        };
+        if text.is_empty() {
+            return gst::FlowReturn::Ok;
+        }
+
+        let accumulate_time =
+            u64::from(self.settings.lock().unwrap().accumulate_time) * gst::MSECOND_VAL;
+
+        let complete = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match *state_guard {
+                None => return gst::FlowReturn::Flushing,
+                Some(ref mut state) => state,
+            };
+
+            if state.pending.is_none() {
+                state.pending = Some(Pending {
+                    text: String::new(),
+                    pts: pts,
+                    end: end,
+                });
+            }
+
+            let pending = state.pending.as_mut().unwrap();
+            append(&mut pending.text, &text);
+            if end > pending.end {
+                pending.end = end;
+            }
+
+            let full = pending.end >= pending.pts + accumulate_time;
+            accumulate_time == 0 || full || ends_sentence(&text)
+        };
+
+        if complete {
+            self.drain(element)
+        } else {
+            gst::FlowReturn::Ok
+        }
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        let drain = match event.view() {
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.pending = None;
+                }
+                false
+            }
+            // Nothing follows that could complete the sentence anytime soon, or it would be in
+            // a different segment
+            EventView::Gap(..) | EventView::Eos(..) | EventView::Segment(..) => true,
+            _ => false,
+        };
+
+        if drain {
+            let ret = self.drain(element);
+            if ret != gst::FlowReturn::Ok && ret != gst::FlowReturn::Flushing {
+                return false;
+            }
+        }
+
+        pad.event_default(Some(&element.clone().upcast()), event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        let is_latency = match query.view() {
+            QueryView::Latency(..) => true,
+            _ => false,
+        };
+        if is_latency {
+            if !self.sinkpad.peer_query(query) {
+                return false;
+            }
+
+            let latency =
+                u64::from(self.settings.lock().unwrap().accumulate_time) * gst::MSECOND_VAL;
+            let latency = gst::ClockTime::from(latency);
+            if let QueryView::Latency(ref mut q) = query.view_mut() {
+                let (live, min, max) = q.get_result();
+                q.set(live, min + latency, max + latency);
+            }
+            return true;
+        }
+
+        pad.query_default(Some(&element.clone().upcast()), query)
+    }
+
+    fn start(&self, element: &Element) -> bool {
+        let client = match Client::builder().build() {
+            Ok(client) => client,
+            Err(err) => {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::OpenRead,
+                    ["Failed to create HTTP client: {}", err]
+                );
+                return false;
+            }
+        };
+
+        *self.state.lock().unwrap() = Some(State {
+            client: client,
+            pending: None,
+        });
+
+        true
+    }
+}
+
+impl ObjectImpl<Element> for Translate {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("source-language-code", ..) => {
+                settings.source_language_code = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_SOURCE_LANGUAGE_CODE.into());
+            }
+            Property::String("target-language-code", ..) => {
+                settings.target_language_code = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_TARGET_LANGUAGE_CODE.into());
+            }
+            Property::String("region", ..) => {
+                settings.region = value.get().unwrap_or_else(|| DEFAULT_REGION.into());
+            }
+            Property::UInt("accumulate-time", ..) => {
+                settings.accumulate_time = value.get().unwrap();
+            }
+            Property::String("access-key", ..) => {
+                settings.access_key = value.get();
+            }
+            Property::String("secret-access-key", ..) => {
+                settings.secret_access_key = value.get();
+            }
+            Property::String("session-token", ..) => {
+                settings.session_token = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("source-language-code", ..) => {
+                Ok(settings.source_language_code.to_value())
+            }
+            Property::String("target-language-code", ..) => {
+                Ok(settings.target_language_code.to_value())
+            }
+            Property::String("region", ..) => Ok(settings.region.to_value()),
+            Property::UInt("accumulate-time", ..) => Ok(settings.accumulate_time.to_value()),
+            Property::String("access-key", ..) => Ok(settings.access_key.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for Translate {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if transition == gst::StateChange::ReadyToPaused && !self.start(element) {
+            return gst::StateChangeReturn::Failure;
+        }
+
+        let ret = element.parent_change_state(transition);
+
+        if transition == gst::StateChange::PausedToReady {
+            *self.state.lock().unwrap() = None;
+        }
+
+        ret
+    }
+}
+
+struct TranslateStatic;
+
+impl ImplTypeStatic<Element> for TranslateStatic {
+    fn get_name(&self) -> &str {
+        "AwsTranslate"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        Translate::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        Translate::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let translate_static = TranslateStatic;
+    register_type(translate_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accumulate() {
+        let mut text = String::new();
+        for word in &["Hello", ",", "how", "are", "you", "?"] {
+            append(&mut text, word);
+        }
+        assert_eq!(text, "Hello, how are you?");
+
+        assert!(ends_sentence("you?"));
+        assert!(ends_sentence("He said \"stop.\""));
+        assert!(!ends_sentence("Hello,"));
+
+        let response: TranslateTextResponse = serde_json::from_slice(
+            br#"{"SourceLanguageCode":"en","TargetLanguageCode":"es",
+                "TranslatedText":"Hola, como estas?"}"#,
+        ).unwrap();
+        assert_eq!(response.translated_text, "Hola, como estas?");
+    }
+}
@@ -0,0 +1,518 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_sink::*;
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::u32;
+
+use protocol::{self, Packet, PacketType, Scheduler, FLAG_FIRST, FLAG_LAST, HEADER_SIZE};
+
+const DEFAULT_LINKS: Option<&str> = None;
+const DEFAULT_MTU: u32 = 1400;
+const DEFAULT_PROBE_INTERVAL: u32 = 100;
+const DEFAULT_LINK_TIMEOUT: u32 = 1000;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    links: Option<String>,
+    mtu: u32,
+    probe_interval: u32,
+    link_timeout: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            links: DEFAULT_LINKS.map(String::from),
+            mtu: DEFAULT_MTU,
+            probe_interval: DEFAULT_PROBE_INTERVAL,
+            link_timeout: DEFAULT_LINK_TIMEOUT,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 5] = [
+    Property::String(
+        "links",
+        "Links",
+        "Comma separated list of links as [local-address/]host:port, the local address selects the network interface",
+        DEFAULT_LINKS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "mtu",
+        "MTU",
+        "Maximum size of the UDP packets, larger buffers are fragmented",
+        (HEADER_SIZE as u32 + 1, 65507),
+        DEFAULT_MTU,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "probe-interval",
+        "Probe Interval",
+        "Interval in milliseconds between probes for measuring the health of each link",
+        (1, u32::MAX),
+        DEFAULT_PROBE_INTERVAL,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "link-timeout",
+        "Link Timeout",
+        "Time in milliseconds without reports after which a link is considered down",
+        (1, u32::MAX),
+        DEFAULT_LINK_TIMEOUT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boxed(
+        "stats",
+        "Statistics",
+        "RTT in nanoseconds, packet loss and state of each link",
+        gst::Structure::static_type,
+        PropertyMutability::Readable,
+    ),
+];
+
+// Parses "[local-address/]host:port"
+fn parse_link(link: &str) -> Result<(Option<IpAddr>, SocketAddr), String> {
+    let (local, remote) = match link.find('/') {
+        Some(idx) => (Some(&link[..idx]), &link[idx + 1..]),
+        None => (None, link),
+    };
+
+    let local = match local {
+        None => None,
+        Some(local) => Some(local
+            .parse::<IpAddr>()
+            .map_err(|_| format!("Invalid local address '{}'", local))?),
+    };
+
+    let remote = remote
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| format!("Can't resolve '{}'", remote))?;
+
+    Ok((local, remote))
+}
+
+fn bind_link(local: Option<IpAddr>, remote: &SocketAddr) -> io::Result<UdpSocket> {
+    let local = match local {
+        Some(local) => SocketAddr::new(local, 0),
+        None if remote.is_ipv4() => "0.0.0.0:0".parse().unwrap(),
+        None => "[::]:0".parse().unwrap(),
+    };
+
+    UdpSocket::bind(local)
+}
+
+fn duration_to_nseconds(duration: Duration) -> u64 {
+    duration.as_secs() * gst::SECOND_VAL + u64::from(duration.subsec_nanos())
+}
+
+struct Link {
+    socket: UdpSocket,
+    remote: SocketAddr,
+    link_seqnum: u32,
+}
+
+// Shared between the streaming thread and the link threads
+struct Links {
+    links: Vec<Link>,
+    scheduler: Scheduler,
+}
+
+impl Links {
+    fn send(
+        &mut self,
+        link: usize,
+        packet_type: PacketType,
+        flags: u8,
+        seqnum: u32,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let link_state = &mut self.links[link];
+
+        let packet = Packet {
+            packet_type: packet_type,
+            flags: flags,
+            link: link as u16,
+            link_seqnum: link_state.link_seqnum,
+            seqnum: seqnum,
+            payload: payload,
+        };
+        link_state.link_seqnum = link_state.link_seqnum.wrapping_add(1);
+
+        link_state
+            .socket
+            .send_to(&packet.write(), &link_state.remote)
+            .map(|_| ())
+    }
+}
+
+struct State {
+    links: Arc<Mutex<Links>>,
+    seqnum: u32,
+    stop: Arc<AtomicBool>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+struct BondSink {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl BondSink {
+    fn new(_sink: &BaseSink) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "bondsink",
+                gst::DebugColorFlags::empty(),
+                "Bonded network sink",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseSinkClass) {
+        klass.set_metadata(
+            "Bonded Network Sink",
+            "Sink/Network",
+            "Sends a stream split over multiple network links to bondsrc",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &BaseSink) -> Box<BaseSinkImpl<BaseSink>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    // Sends probes over one link and handles the reports sent back by the receiver
+    fn run_link(
+        cat: gst::DebugCategory,
+        element: gst::Element,
+        link: usize,
+        socket: UdpSocket,
+        links: Arc<Mutex<Links>>,
+        interval: Duration,
+        stop: Arc<AtomicBool>,
+    ) {
+        let start = Instant::now();
+        let mut next_probe = start;
+        let mut buf = [0; 1500];
+
+        while !stop.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            if now >= next_probe {
+                let payload = protocol::probe_payload(duration_to_nseconds(now - start));
+                let mut links = links.lock().unwrap();
+                if let Err(err) = links.send(link, PacketType::Probe, 0, 0, &payload) {
+                    gst_debug!(cat, obj: &element, "Failed to send probe on {}: {}", link, err);
+                }
+                next_probe = now + interval;
+                continue;
+            }
+
+            socket.set_read_timeout(Some(next_probe - now)).unwrap();
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(_) => continue,
+            };
+
+            let packet = match Packet::parse(&buf[..len]) {
+                None => continue,
+                Some(packet) => packet,
+            };
+            if packet.packet_type != PacketType::Report {
+                continue;
+            }
+            let (time, received) = match protocol::parse_report(packet.payload) {
+                None => continue,
+                Some(report) => report,
+            };
+
+            let now = Instant::now();
+            let rtt_ns = duration_to_nseconds(now - start).saturating_sub(time);
+            let rtt = Duration::new(rtt_ns / gst::SECOND_VAL, (rtt_ns % gst::SECOND_VAL) as u32);
+
+            let mut links = links.lock().unwrap();
+            let was_alive = links.scheduler.links()[link].alive;
+            links
+                .scheduler
+                .report(link, packet.link_seqnum, rtt, received, now);
+            if !was_alive {
+                gst_info!(cat, obj: &element, "Link {} is up again", link);
+            }
+        }
+    }
+
+    fn get_stats(&self) -> gst::Structure {
+        let mut s = gst::Structure::new_empty("bond-stats");
+
+        if let Some(ref state) = *self.state.lock().unwrap() {
+            let links = state.links.lock().unwrap();
+            let s = s.get_mut().unwrap();
+            for (i, stats) in links.scheduler.links().iter().enumerate() {
+                s.set(&format!("link-{}-alive", i), &stats.alive);
+                s.set(&format!("link-{}-loss", i), &stats.loss);
+                if let Some(rtt) = stats.rtt {
+                    s.set(&format!("link-{}-rtt", i), &duration_to_nseconds(rtt));
+                }
+            }
+        }
+
+        s
+    }
+}
+
+impl ObjectImpl<BaseSink> for BondSink {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("links", ..) => {
+                settings.links = value.get();
+            }
+            Property::UInt("mtu", ..) => {
+                settings.mtu = value.get().unwrap();
+            }
+            Property::UInt("probe-interval", ..) => {
+                settings.probe_interval = value.get().unwrap();
+            }
+            Property::UInt("link-timeout", ..) => {
+                settings.link_timeout = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("links", ..) => Ok(self.settings.lock().unwrap().links.to_value()),
+            Property::UInt("mtu", ..) => Ok(self.settings.lock().unwrap().mtu.to_value()),
+            Property::UInt("probe-interval", ..) => {
+                Ok(self.settings.lock().unwrap().probe_interval.to_value())
+            }
+            Property::UInt("link-timeout", ..) => {
+                Ok(self.settings.lock().unwrap().link_timeout.to_value())
+            }
+            Property::Boxed("stats", ..) => Ok(self.get_stats().to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseSink> for BondSink {}
+
+impl BaseSinkImpl<BaseSink> for BondSink {
+    fn start(&self, element: &BaseSink) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let configs = match settings.links {
+            None => Ok(Vec::new()),
+            Some(ref links) => links
+                .split(',')
+                .map(|link| parse_link(link.trim()))
+                .collect::<Result<Vec<_>, _>>(),
+        };
+        let configs = match configs {
+            Err(err) => {
+                gst_element_error!(element, gst::ResourceError::Settings, ["{}", err]);
+                return false;
+            }
+            Ok(ref configs) if configs.is_empty() => {
+                gst_element_error!(element, gst::ResourceError::Settings, ["No links configured"]);
+                return false;
+            }
+            Ok(configs) => configs,
+        };
+
+        let mut links = Vec::new();
+        for (local, remote) in configs {
+            let socket = match bind_link(local, &remote) {
+                Ok(socket) => socket,
+                Err(err) => {
+                    gst_element_error!(
+                        element,
+                        gst::ResourceError::OpenWrite,
+                        ["Failed to bind link to {}: {}", remote, err]
+                    );
+                    return false;
+                }
+            };
+
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "Sending to {} from {:?}",
+                remote,
+                socket.local_addr()
+            );
+            links.push(Link {
+                socket: socket,
+                remote: remote,
+                link_seqnum: 0,
+            });
+        }
+
+        let sockets = match links
+            .iter()
+            .map(|link| link.socket.try_clone())
+            .collect::<io::Result<Vec<_>>>()
+        {
+            Ok(sockets) => sockets,
+            Err(err) => {
+                gst_element_error!(element, gst::ResourceError::OpenWrite, ["{}", err]);
+                return false;
+            }
+        };
+
+        let timeout = Duration::from_millis(u64::from(settings.link_timeout));
+        let interval = Duration::from_millis(u64::from(settings.probe_interval));
+        let links = Arc::new(Mutex::new(Links {
+            scheduler: Scheduler::new(links.len(), timeout, Instant::now()),
+            links: links,
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let threads = sockets
+            .into_iter()
+            .enumerate()
+            .map(|(i, socket)| {
+                let cat = self.cat;
+                let element = element.clone().upcast::<gst::Element>();
+                let links = links.clone();
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    BondSink::run_link(cat, element, i, socket, links, interval, stop);
+                })
+            })
+            .collect();
+
+        *self.state.lock().unwrap() = Some(State {
+            links: links,
+            seqnum: 0,
+            stop: stop,
+            threads: threads,
+        });
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseSink) -> bool {
+        let state = self.state.lock().unwrap().take();
+
+        if let Some(state) = state {
+            state.stop.store(true, Ordering::SeqCst);
+            for thread in state.threads {
+                let _ = thread.join();
+            }
+        }
+
+        true
+    }
+
+    fn render(&self, element: &BaseSink, buffer: &gst::BufferRef) -> gst::FlowReturn {
+        let mtu = self.settings.lock().unwrap().mtu as usize;
+
+        let map = match buffer.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map,
+        };
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return gst::FlowReturn::Flushing,
+            Some(ref mut state) => state,
+        };
+        let mut links = state.links.lock().unwrap();
+
+        if links.scheduler.check_timeouts(Instant::now()) {
+            for (i, stats) in links.scheduler.links().iter().enumerate() {
+                if !stats.alive {
+                    gst_warning!(self.cat, obj: element, "Link {} is down", i);
+                }
+            }
+        }
+
+        let chunks = map.as_slice().chunks(mtu - HEADER_SIZE);
+        let n_chunks = chunks.len();
+        for (i, chunk) in chunks.enumerate() {
+            let mut flags = 0;
+            if i == 0 {
+                flags |= FLAG_FIRST;
+            }
+            if i == n_chunks - 1 {
+                flags |= FLAG_LAST;
+            }
+
+            // Send errors are handled like packet loss, the link is considered down once no
+            // reports are received anymore
+            let link = links.scheduler.next();
+            if let Err(err) = links.send(link, PacketType::Data, flags, state.seqnum, chunk) {
+                gst_debug!(self.cat, obj: element, "Failed to send on link {}: {}", link, err);
+            }
+            state.seqnum = state.seqnum.wrapping_add(1);
+        }
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct BondSinkStatic;
+
+impl ImplTypeStatic<BaseSink> for BondSinkStatic {
+    fn get_name(&self) -> &str {
+        "BondSink"
+    }
+
+    fn new(&self, element: &BaseSink) -> Box<BaseSinkImpl<BaseSink>> {
+        BondSink::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseSinkClass) {
+        BondSink::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let bondsink_static = BondSinkStatic;
+    register_type(bondsink_static)
+}
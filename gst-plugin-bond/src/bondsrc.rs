@@ -0,0 +1,458 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_src::*;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::u32;
+
+use protocol::{self, Packet, PacketType, Reassembler};
+
+const DEFAULT_ADDRESSES: Option<&str> = None;
+const DEFAULT_LATENCY: u32 = 200;
+
+// Maximum time the socket threads and the streaming thread block without checking for
+// stopping
+const POLL_INTERVAL: u64 = 100;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    addresses: Option<String>,
+    latency: u32,
+    caps: Option<gst::Caps>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            addresses: DEFAULT_ADDRESSES.map(String::from),
+            latency: DEFAULT_LATENCY,
+            caps: None,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 3] = [
+    Property::String(
+        "addresses",
+        "Addresses",
+        "Comma separated list of [address:]port to receive the links on",
+        DEFAULT_ADDRESSES,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "latency",
+        "Latency",
+        "Time in milliseconds to wait for reordered or missing packets",
+        (0, u32::MAX),
+        DEFAULT_LATENCY,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boxed(
+        "caps",
+        "Caps",
+        "Caps of the received stream",
+        gst::Caps::static_type,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+fn parse_address(address: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = address.parse::<u16>() {
+        return Ok(SocketAddr::new("0.0.0.0".parse().unwrap(), port));
+    }
+
+    address
+        .parse::<SocketAddr>()
+        .map_err(|_| format!("Invalid address '{}'", address))
+}
+
+enum Item {
+    Data(u32, u8, Vec<u8>, Instant),
+    Unlock,
+}
+
+struct Unlock {
+    flushing: bool,
+    sender: Option<mpsc::Sender<Item>>,
+}
+
+struct State {
+    receiver: mpsc::Receiver<Item>,
+    reassembler: Reassembler,
+    stop: Arc<AtomicBool>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+struct BondSrc {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+    unlock: Mutex<Unlock>,
+}
+
+impl BondSrc {
+    fn new(_src: &BaseSrc) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "bondsrc",
+                gst::DebugColorFlags::empty(),
+                "Bonded network source",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+            unlock: Mutex::new(Unlock {
+                flushing: false,
+                sender: None,
+            }),
+        }
+    }
+
+    fn class_init(klass: &mut BaseSrcClass) {
+        klass.set_metadata(
+            "Bonded Network Source",
+            "Source/Network",
+            "Receives a stream split over multiple network links by bondsink",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &BaseSrc) -> Box<BaseSrcImpl<BaseSrc>> {
+        element.set_live_source(true);
+
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    // Answers probes and forwards the data packets to the streaming thread. The packets
+    // received per link are counted over all sockets as multiple links can go to the same one
+    fn run_socket(
+        cat: gst::DebugCategory,
+        element: gst::Element,
+        socket: UdpSocket,
+        received: Arc<Mutex<HashMap<u16, u32>>>,
+        sender: mpsc::Sender<Item>,
+        stop: Arc<AtomicBool>,
+    ) {
+        let mut buf = vec![0; 65536];
+
+        while !stop.load(Ordering::SeqCst) {
+            let (len, addr) = match socket.recv_from(&mut buf) {
+                Ok(res) => res,
+                Err(_) => continue,
+            };
+
+            let packet = match Packet::parse(&buf[..len]) {
+                None => {
+                    gst_trace!(cat, obj: &element, "Dropping invalid packet from {}", addr);
+                    continue;
+                }
+                Some(packet) => packet,
+            };
+
+            let count = {
+                let mut received = received.lock().unwrap();
+                let count = received.entry(packet.link).or_insert(0);
+                *count = count.wrapping_add(1);
+                *count
+            };
+
+            match packet.packet_type {
+                PacketType::Data => {
+                    let item = Item::Data(
+                        packet.seqnum,
+                        packet.flags,
+                        packet.payload.to_vec(),
+                        Instant::now(),
+                    );
+                    if sender.send(item).is_err() {
+                        break;
+                    }
+                }
+                PacketType::Probe => {
+                    let time = match protocol::parse_probe(packet.payload) {
+                        None => continue,
+                        Some(time) => time,
+                    };
+
+                    let report = Packet {
+                        packet_type: PacketType::Report,
+                        flags: 0,
+                        link: packet.link,
+                        link_seqnum: packet.link_seqnum,
+                        seqnum: 0,
+                        payload: &protocol::report_payload(time, count),
+                    };
+                    if let Err(err) = socket.send_to(&report.write(), &addr) {
+                        gst_debug!(cat, obj: &element, "Failed to send report: {}", err);
+                    }
+                }
+                PacketType::Report => (),
+            }
+        }
+    }
+}
+
+impl ObjectImpl<BaseSrc> for BondSrc {
+    fn set_property(&self, obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let element = obj.clone().downcast::<BaseSrc>().unwrap();
+
+        match *prop {
+            Property::String("addresses", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.addresses = value.get();
+            }
+            Property::UInt("latency", ..) => {
+                self.settings.lock().unwrap().latency = value.get().unwrap();
+                element.post_latency_message();
+            }
+            Property::Boxed("caps", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.caps = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("addresses", ..) => Ok(settings.addresses.to_value()),
+            Property::UInt("latency", ..) => Ok(settings.latency.to_value()),
+            Property::Boxed("caps", ..) => Ok(settings.caps.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseSrc> for BondSrc {}
+
+impl BaseSrcImpl<BaseSrc> for BondSrc {
+    fn start(&self, element: &BaseSrc) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let addresses = match settings.addresses {
+            None => Ok(Vec::new()),
+            Some(ref addresses) => addresses
+                .split(',')
+                .map(|address| parse_address(address.trim()))
+                .collect::<Result<Vec<_>, _>>(),
+        };
+        let addresses = match addresses {
+            Err(err) => {
+                gst_element_error!(element, gst::ResourceError::Settings, ["{}", err]);
+                return false;
+            }
+            Ok(ref addresses) if addresses.is_empty() => {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::Settings,
+                    ["No addresses configured"]
+                );
+                return false;
+            }
+            Ok(addresses) => addresses,
+        };
+
+        let mut sockets = Vec::new();
+        for address in addresses {
+            let socket = match UdpSocket::bind(address) {
+                Ok(socket) => socket,
+                Err(err) => {
+                    gst_element_error!(
+                        element,
+                        gst::ResourceError::OpenRead,
+                        ["Failed to bind to {}: {}", address, err]
+                    );
+                    return false;
+                }
+            };
+            socket
+                .set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL)))
+                .unwrap();
+
+            gst_debug!(self.cat, obj: element, "Receiving on {}", address);
+            sockets.push(socket);
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let received = Arc::new(Mutex::new(HashMap::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let threads = sockets
+            .into_iter()
+            .map(|socket| {
+                let cat = self.cat;
+                let element = element.clone().upcast::<gst::Element>();
+                let received = received.clone();
+                let sender = sender.clone();
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    BondSrc::run_socket(cat, element, socket, received, sender, stop);
+                })
+            })
+            .collect();
+
+        self.unlock.lock().unwrap().sender = Some(sender);
+        *self.state.lock().unwrap() = Some(State {
+            receiver: receiver,
+            reassembler: Reassembler::new(Duration::from_millis(u64::from(settings.latency))),
+            stop: stop,
+            threads: threads,
+        });
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseSrc) -> bool {
+        self.unlock.lock().unwrap().sender = None;
+        let state = self.state.lock().unwrap().take();
+
+        if let Some(state) = state {
+            state.stop.store(true, Ordering::SeqCst);
+            for thread in state.threads {
+                let _ = thread.join();
+            }
+        }
+
+        true
+    }
+
+    fn create(
+        &self,
+        element: &BaseSrc,
+        _offset: u64,
+        _length: u32,
+    ) -> Result<gst::Buffer, gst::FlowReturn> {
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return Err(gst::FlowReturn::Flushing),
+            Some(ref mut state) => state,
+        };
+
+        loop {
+            if self.unlock.lock().unwrap().flushing {
+                gst_debug!(self.cat, obj: element, "Flushing");
+                return Err(gst::FlowReturn::Flushing);
+            }
+
+            let now = Instant::now();
+            if let Some((data, discont)) = state.reassembler.pop(now) {
+                let mut buffer = gst::Buffer::from_mut_slice(data).unwrap();
+                if discont {
+                    buffer
+                        .get_mut()
+                        .unwrap()
+                        .set_flags(gst::BufferFlags::DISCONT);
+                }
+
+                gst_log!(self.cat, obj: element, "Produced buffer {:?}", buffer);
+                return Ok(buffer);
+            }
+
+            let poll_interval = Duration::from_millis(POLL_INTERVAL);
+            let timeout = state
+                .reassembler
+                .timeout(now)
+                .map(|timeout| cmp::min(timeout, poll_interval))
+                .unwrap_or(poll_interval);
+
+            match state.receiver.recv_timeout(timeout) {
+                Ok(Item::Data(seqnum, flags, payload, arrival)) => {
+                    state.reassembler.push(seqnum, flags, &payload, arrival);
+                }
+                // Checked at the beginning of the loop, stale unlocks are ignored
+                Ok(Item::Unlock) | Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(gst::FlowReturn::Flushing);
+                }
+            }
+        }
+    }
+
+    fn get_caps(&self, element: &BaseSrc, filter: Option<&gst::CapsRef>) -> Option<gst::Caps> {
+        let caps = match self.settings.lock().unwrap().caps {
+            None => return element.parent_get_caps(filter),
+            Some(ref caps) => caps.clone(),
+        };
+
+        match filter {
+            None => Some(caps),
+            Some(filter) => Some(filter.intersect_with_mode(&caps, gst::CapsIntersectMode::First)),
+        }
+    }
+
+    fn get_latency(&self, _element: &BaseSrc) -> Option<(gst::ClockTime, gst::ClockTime)> {
+        let latency = u64::from(self.settings.lock().unwrap().latency) * gst::MSECOND_VAL;
+        Some((latency.into(), gst::CLOCK_TIME_NONE))
+    }
+
+    fn unlock(&self, _element: &BaseSrc) -> bool {
+        let mut unlock = self.unlock.lock().unwrap();
+        unlock.flushing = true;
+        if let Some(ref sender) = unlock.sender {
+            let _ = sender.send(Item::Unlock);
+        }
+
+        true
+    }
+
+    fn unlock_stop(&self, _element: &BaseSrc) -> bool {
+        self.unlock.lock().unwrap().flushing = false;
+
+        true
+    }
+}
+
+struct BondSrcStatic;
+
+impl ImplTypeStatic<BaseSrc> for BondSrcStatic {
+    fn get_name(&self) -> &str {
+        "BondSrc"
+    }
+
+    fn new(&self, element: &BaseSrc) -> Box<BaseSrcImpl<BaseSrc>> {
+        BondSrc::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseSrcClass) {
+        BondSrc::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let bondsrc_static = BondSrcStatic;
+    register_type(bondsrc_static)
+}
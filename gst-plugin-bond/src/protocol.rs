@@ -0,0 +1,520 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Bonding protocol
+//
+// All packets are sent over UDP and start with a 12 byte header, all fields big endian:
+//
+// +------+-------+------+-------------+--------+
+// | type | flags | link | link seqnum | seqnum |
+// |  u8  |  u8   | u16  |     u32     |  u32   |
+// +------+-------+------+-------------+--------+
+//
+// The link seqnum counts all packets sent over a link and is used for measuring the packet
+// loss of the link. The seqnum counts the data packets over all links and is used for
+// reordering them at the receiver.
+//
+// Data packets contain a fragment of a buffer. The FIRST and LAST flags mark the first and last
+// fragment, all fragments of a buffer have consecutive seqnums.
+//
+// Probe packets are sent regularly over every link and contain the send time in nanoseconds
+// as u64. The receiver answers each probe with a report packet over the same link, which
+// contains the send time of the probe and the number of packets received on that link as u32,
+// and has the link seqnum of the probe.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+pub const HEADER_SIZE: usize = 12;
+
+pub const FLAG_FIRST: u8 = 0x01;
+pub const FLAG_LAST: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    Data,
+    Probe,
+    Report,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Packet<'a> {
+    pub packet_type: PacketType,
+    pub flags: u8,
+    pub link: u16,
+    pub link_seqnum: u32,
+    pub seqnum: u32,
+    pub payload: &'a [u8],
+}
+
+fn read_u16(data: &[u8]) -> u16 {
+    (u16::from(data[0]) << 8) | u16::from(data[1])
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    (u32::from(read_u16(data)) << 16) | u32::from(read_u16(&data[2..]))
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    (u64::from(read_u32(data)) << 32) | u64::from(read_u32(&data[4..]))
+}
+
+fn write_u16(data: &mut Vec<u8>, v: u16) {
+    data.push((v >> 8) as u8);
+    data.push(v as u8);
+}
+
+fn write_u32(data: &mut Vec<u8>, v: u32) {
+    write_u16(data, (v >> 16) as u16);
+    write_u16(data, v as u16);
+}
+
+fn write_u64(data: &mut Vec<u8>, v: u64) {
+    write_u32(data, (v >> 32) as u32);
+    write_u32(data, v as u32);
+}
+
+impl<'a> Packet<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Packet<'a>> {
+        if data.len() < HEADER_SIZE {
+            return None;
+        }
+
+        let packet_type = match data[0] {
+            0 => PacketType::Data,
+            1 => PacketType::Probe,
+            2 => PacketType::Report,
+            _ => return None,
+        };
+
+        Some(Packet {
+            packet_type: packet_type,
+            flags: data[1],
+            link: read_u16(&data[2..]),
+            link_seqnum: read_u32(&data[4..]),
+            seqnum: read_u32(&data[8..]),
+            payload: &data[HEADER_SIZE..],
+        })
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_SIZE + self.payload.len());
+
+        data.push(match self.packet_type {
+            PacketType::Data => 0,
+            PacketType::Probe => 1,
+            PacketType::Report => 2,
+        });
+        data.push(self.flags);
+        write_u16(&mut data, self.link);
+        write_u32(&mut data, self.link_seqnum);
+        write_u32(&mut data, self.seqnum);
+        data.extend_from_slice(self.payload);
+
+        data
+    }
+}
+
+pub fn probe_payload(time: u64) -> Vec<u8> {
+    let mut data = Vec::with_capacity(8);
+    write_u64(&mut data, time);
+    data
+}
+
+pub fn parse_probe(payload: &[u8]) -> Option<u64> {
+    if payload.len() < 8 {
+        return None;
+    }
+    Some(read_u64(payload))
+}
+
+pub fn report_payload(time: u64, received: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(12);
+    write_u64(&mut data, time);
+    write_u32(&mut data, received);
+    data
+}
+
+pub fn parse_report(payload: &[u8]) -> Option<(u64, u32)> {
+    if payload.len() < 12 {
+        return None;
+    }
+    Some((read_u64(payload), read_u32(&payload[8..])))
+}
+
+// Packets that are further behind than this are considered to be from a restarted sender
+const MAX_REORDER: i32 = 32768;
+
+/// Reorders the data packets received over all links and reassembles the buffers from their
+/// fragments.
+pub struct Reassembler {
+    latency: Duration,
+    // Next seqnum to output and its extended value, None until the first packet
+    next: Option<(u32, u64)>,
+    // Fragments by extended seqnum with their flags and arrival time
+    packets: BTreeMap<u64, (u8, Vec<u8>, Instant)>,
+    discont: bool,
+}
+
+impl Reassembler {
+    /// Missing packets are waited for at most `latency`.
+    pub fn new(latency: Duration) -> Self {
+        Reassembler {
+            latency: latency,
+            next: None,
+            packets: BTreeMap::new(),
+            discont: true,
+        }
+    }
+
+    pub fn push(&mut self, seqnum: u32, flags: u8, payload: &[u8], now: Instant) {
+        let (next_seqnum, next_ext) = match self.next {
+            None => {
+                self.next = Some((seqnum, 0));
+                (seqnum, 0)
+            }
+            Some(next) => next,
+        };
+
+        let diff = seqnum.wrapping_sub(next_seqnum) as i32;
+        if diff < -MAX_REORDER {
+            self.packets.clear();
+            self.next = Some((seqnum, 0));
+            self.discont = true;
+            self.packets.insert(0, (flags, payload.to_vec(), now));
+            return;
+        } else if diff < 0 {
+            // Too late or duplicate
+            return;
+        }
+
+        self.packets
+            .entry(next_ext + diff as u64)
+            .or_insert_with(|| (flags, payload.to_vec(), now));
+    }
+
+    fn advance(&mut self, count: u64) {
+        if let Some((ref mut seqnum, ref mut ext)) = self.next {
+            *seqnum = seqnum.wrapping_add(count as u32);
+            *ext += count;
+        }
+    }
+
+    /// Returns the next complete buffer and whether there was a discontinuity before it.
+    pub fn pop(&mut self, now: Instant) -> Option<(Vec<u8>, bool)> {
+        loop {
+            let next_ext = match self.next {
+                None => return None,
+                Some((_, next_ext)) => next_ext,
+            };
+
+            // Number of consecutive fragments from the next one up to the last of the buffer
+            let complete = match self.packets.get(&next_ext) {
+                Some(&(flags, ..)) if flags & FLAG_FIRST == 0 => {
+                    // Rest of a buffer whose start was lost
+                    self.packets.remove(&next_ext);
+                    self.advance(1);
+                    self.discont = true;
+                    continue;
+                }
+                Some(_) => {
+                    let mut count = None;
+                    for (i, (&ext, &(flags, ..))) in self.packets.range(next_ext..).enumerate() {
+                        if ext != next_ext + i as u64 {
+                            break;
+                        }
+                        if flags & FLAG_LAST != 0 {
+                            count = Some(i as u64 + 1);
+                            break;
+                        }
+                    }
+                    count
+                }
+                None => None,
+            };
+
+            if let Some(count) = complete {
+                let mut data = Vec::new();
+                for ext in next_ext..next_ext + count {
+                    let (_, payload, _) = self.packets.remove(&ext).unwrap();
+                    data.extend_from_slice(&payload);
+                }
+                self.advance(count);
+
+                let discont = self.discont;
+                self.discont = false;
+                return Some((data, discont));
+            }
+
+            // Give up on the missing packets once the oldest waiting packet timed out
+            let timed_out = self.packets
+                .values()
+                .map(|&(_, _, arrival)| arrival)
+                .min()
+                .map(|arrival| now.duration_since(arrival) >= self.latency)
+                .unwrap_or(false);
+            if !timed_out {
+                return None;
+            }
+
+            match self.packets.keys().next().cloned() {
+                // Skip the fragments of the incomplete buffer
+                Some(ext) if ext == next_ext => {
+                    self.packets.remove(&ext);
+                    self.advance(1);
+                }
+                Some(ext) => self.advance(ext - next_ext),
+                None => return None,
+            }
+            self.discont = true;
+        }
+    }
+
+    /// Time until `pop()` has to be called again at the latest to handle timeouts.
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        self.packets
+            .values()
+            .map(|&(_, _, arrival)| arrival)
+            .min()
+            .map(|arrival| {
+                let deadline = arrival + self.latency;
+                if deadline > now {
+                    deadline - now
+                } else {
+                    Duration::from_secs(0)
+                }
+            })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkStats {
+    // Smoothed round-trip time and packet loss between 0 and 1
+    pub rtt: Option<Duration>,
+    pub loss: f64,
+    pub alive: bool,
+    last_report: Instant,
+    // Link seqnum of the last probe and packets received up to it
+    last_probe: Option<(u32, u32)>,
+    credit: f64,
+}
+
+/// Distributes packets over the links according to their health.
+///
+/// Each link gets a weight from its RTT and packet loss, and packets are scheduled with smooth
+/// weighted round-robin. Links without reports for the timeout are not used until they
+/// recover, unless all of them are down.
+pub struct Scheduler {
+    links: Vec<LinkStats>,
+    timeout: Duration,
+}
+
+// Used for links without RTT measurements
+const DEFAULT_RTT: f64 = 0.1;
+
+impl Scheduler {
+    pub fn new(n_links: usize, timeout: Duration, now: Instant) -> Self {
+        Scheduler {
+            links: vec![
+                LinkStats {
+                    rtt: None,
+                    loss: 0.0,
+                    alive: true,
+                    last_report: now,
+                    last_probe: None,
+                    credit: 0.0,
+                };
+                n_links
+            ],
+            timeout: timeout,
+        }
+    }
+
+    pub fn links(&self) -> &[LinkStats] {
+        &self.links
+    }
+
+    /// Updates the link statistics from a report for the probe with `link_seqnum`.
+    pub fn report(
+        &mut self,
+        link: usize,
+        link_seqnum: u32,
+        rtt: Duration,
+        received: u32,
+        now: Instant,
+    ) {
+        let stats = &mut self.links[link];
+
+        stats.rtt = Some(match stats.rtt {
+            None => rtt,
+            Some(srtt) => (srtt * 7 + rtt) / 8,
+        });
+
+        if let Some((last_seqnum, last_received)) = stats.last_probe {
+            let sent = link_seqnum.wrapping_sub(last_seqnum);
+            let delta = received.wrapping_sub(last_received);
+            // Reports can be reordered too
+            if (sent as i32) > 0 {
+                let loss = 1.0 - f64::from(cmp::min(delta, sent)) / f64::from(sent);
+                stats.loss = 0.75 * stats.loss + 0.25 * loss;
+            }
+        }
+        stats.last_probe = Some((link_seqnum, received));
+
+        stats.last_report = now;
+        stats.alive = true;
+    }
+
+    /// Marks links without reports for the timeout as down, returns if any link changed.
+    pub fn check_timeouts(&mut self, now: Instant) -> bool {
+        let mut changed = false;
+
+        for stats in &mut self.links {
+            let alive = now.duration_since(stats.last_report) < self.timeout;
+            if alive != stats.alive {
+                stats.alive = alive;
+                stats.credit = 0.0;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    fn weight(stats: &LinkStats, all_down: bool) -> f64 {
+        if !stats.alive && !all_down {
+            return 0.0;
+        }
+
+        let rtt = stats
+            .rtt
+            .map(|rtt| rtt.as_secs() as f64 + f64::from(rtt.subsec_nanos()) / 1_000_000_000.0)
+            .unwrap_or(DEFAULT_RTT);
+
+        (1.0 - stats.loss).max(0.01) / rtt.max(0.001)
+    }
+
+    /// Link for the next packet.
+    pub fn next(&mut self) -> usize {
+        let all_down = self.links.iter().all(|stats| !stats.alive);
+
+        let mut total = 0.0;
+        let mut best = 0;
+        for i in 0..self.links.len() {
+            let weight = Self::weight(&self.links[i], all_down);
+            total += weight;
+            self.links[i].credit += weight;
+            if self.links[i].credit > self.links[best].credit {
+                best = i;
+            }
+        }
+        self.links[best].credit -= total;
+
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(seqnum: u32, flags: u8, payload: &[u8]) -> (u32, u8, Vec<u8>) {
+        (seqnum, flags, payload.to_vec())
+    }
+
+    #[test]
+    fn test_packet() {
+        let packet = Packet {
+            packet_type: PacketType::Data,
+            flags: FLAG_FIRST | FLAG_LAST,
+            link: 2,
+            link_seqnum: 0x01020304,
+            seqnum: 0xfffffffe,
+            payload: &[1, 2, 3],
+        };
+
+        let data = packet.write();
+        assert_eq!(data.len(), HEADER_SIZE + 3);
+        assert_eq!(Packet::parse(&data), Some(packet));
+        assert_eq!(Packet::parse(&data[..HEADER_SIZE - 1]), None);
+
+        assert_eq!(parse_probe(&probe_payload(1234)), Some(1234));
+        assert_eq!(parse_report(&report_payload(1234, 5)), Some((1234, 5)));
+    }
+
+    #[test]
+    fn test_reorder() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new(Duration::from_millis(100));
+
+        for (seqnum, flags, payload) in vec![
+            data(u32::max_value(), FLAG_FIRST, &[1]),
+            data(1, FLAG_FIRST | FLAG_LAST, &[3]),
+            data(0, FLAG_LAST, &[2]),
+        ] {
+            reassembler.push(seqnum, flags, &payload, now);
+        }
+
+        assert_eq!(reassembler.pop(now), Some((vec![1, 2], true)));
+        assert_eq!(reassembler.pop(now), Some((vec![3], false)));
+        assert_eq!(reassembler.pop(now), None);
+
+        // Late duplicate
+        reassembler.push(0, FLAG_LAST, &[2], now);
+        assert_eq!(reassembler.pop(now), None);
+        assert_eq!(reassembler.timeout(now), None);
+    }
+
+    #[test]
+    fn test_loss() {
+        let now = Instant::now();
+        let later = now + Duration::from_millis(100);
+        let mut reassembler = Reassembler::new(Duration::from_millis(100));
+
+        for (seqnum, flags, payload) in vec![
+            data(0, FLAG_FIRST | FLAG_LAST, &[1]),
+            // 1 and 3 are lost
+            data(2, FLAG_LAST, &[2]),
+            data(4, FLAG_FIRST, &[4]),
+            data(5, FLAG_LAST, &[5]),
+        ] {
+            reassembler.push(seqnum, flags, &payload, now);
+        }
+
+        assert_eq!(reassembler.pop(now), Some((vec![1], true)));
+        assert_eq!(reassembler.pop(now), None);
+        assert_eq!(reassembler.timeout(now), Some(Duration::from_millis(100)));
+        assert_eq!(reassembler.pop(later), Some((vec![4, 5], true)));
+        assert_eq!(reassembler.pop(later), None);
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new(2, Duration::from_secs(1), now);
+
+        scheduler.report(0, 10, Duration::from_millis(10), 11, now);
+        scheduler.report(1, 10, Duration::from_millis(30), 11, now);
+
+        let mut counts = [0; 2];
+        for _ in 0..40 {
+            counts[scheduler.next()] += 1;
+        }
+        assert_eq!(counts, [30, 10]);
+
+        // Only the first link stays alive
+        let later = now + Duration::from_millis(1500);
+        scheduler.report(0, 20, Duration::from_millis(10), 21, later);
+        assert!(scheduler.check_timeouts(later));
+        assert!(!scheduler.links()[1].alive);
+        for _ in 0..10 {
+            assert_eq!(scheduler.next(), 0);
+        }
+    }
+}
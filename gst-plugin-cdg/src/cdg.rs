@@ -0,0 +1,246 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// CD+G subcode packets and the screen they are drawn on
+//
+// Every packet is 24 bytes: command, instruction, 2 bytes parity, 16 bytes data, 4 bytes parity.
+// Only the lower 6 bits of every byte are used. A CD sector carries 4 packets and there are 75
+// sectors per second.
+
+pub const PACKET_SIZE: usize = 24;
+pub const PACKETS_PER_SECTOR: usize = 4;
+pub const SECTOR_SIZE: usize = PACKET_SIZE * PACKETS_PER_SECTOR;
+pub const SECTORS_PER_SECOND: u64 = 75;
+
+pub const WIDTH: usize = 300;
+pub const HEIGHT: usize = 216;
+
+const TILE_WIDTH: usize = 6;
+const TILE_HEIGHT: usize = 12;
+
+const COMMAND_CDG: u8 = 0x09;
+
+const MEMORY_PRESET: u8 = 1;
+const BORDER_PRESET: u8 = 2;
+const TILE_BLOCK: u8 = 6;
+const SCROLL_PRESET: u8 = 20;
+const SCROLL_COPY: u8 = 24;
+const LOAD_COLOR_TABLE_LOW: u8 = 30;
+const LOAD_COLOR_TABLE_HIGH: u8 = 31;
+const TILE_BLOCK_XOR: u8 = 38;
+
+pub fn is_cdg_packet(packet: &[u8]) -> bool {
+    packet.len() >= PACKET_SIZE && packet[0] & 0x3f == COMMAND_CDG
+}
+
+fn is_border(x: usize, y: usize) -> bool {
+    x < TILE_WIDTH || x >= WIDTH - TILE_WIDTH || y < TILE_HEIGHT || y >= HEIGHT - TILE_HEIGHT
+}
+
+pub struct Screen {
+    // Color index of every pixel
+    pixels: Vec<u8>,
+    // RGB
+    palette: [[u8; 3]; 16],
+    border: u8,
+    // Fine scroll offsets of the visible area
+    h_offset: usize,
+    v_offset: usize,
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Screen {
+            pixels: vec![0; WIDTH * HEIGHT],
+            palette: [[0; 3]; 16],
+            border: 0,
+            h_offset: 0,
+            v_offset: 0,
+        }
+    }
+}
+
+impl Screen {
+    pub fn handle_packet(&mut self, packet: &[u8]) {
+        if !is_cdg_packet(packet) {
+            return;
+        }
+
+        let mut data = [0u8; 16];
+        for (d, &p) in data.iter_mut().zip(packet[4..20].iter()) {
+            *d = p & 0x3f;
+        }
+
+        match packet[1] & 0x3f {
+            MEMORY_PRESET => {
+                let color = data[0] & 0x0f;
+                for p in &mut self.pixels {
+                    *p = color;
+                }
+            }
+            BORDER_PRESET => {
+                self.border = data[0] & 0x0f;
+                for y in 0..HEIGHT {
+                    for x in 0..WIDTH {
+                        if is_border(x, y) {
+                            self.pixels[y * WIDTH + x] = self.border;
+                        }
+                    }
+                }
+            }
+            TILE_BLOCK => self.tile_block(&data, false),
+            TILE_BLOCK_XOR => self.tile_block(&data, true),
+            SCROLL_PRESET => self.scroll(&data, false),
+            SCROLL_COPY => self.scroll(&data, true),
+            LOAD_COLOR_TABLE_LOW => self.load_color_table(&data, 0),
+            LOAD_COLOR_TABLE_HIGH => self.load_color_table(&data, 8),
+            // Transparency is not supported, everything else is reserved
+            _ => (),
+        }
+    }
+
+    fn tile_block(&mut self, data: &[u8; 16], xor: bool) {
+        let colors = [data[0] & 0x0f, data[1] & 0x0f];
+        let row = (data[2] & 0x1f) as usize;
+        let column = data[3] as usize;
+        if row >= HEIGHT / TILE_HEIGHT || column >= WIDTH / TILE_WIDTH {
+            return;
+        }
+
+        for (ty, &bits) in data[4..].iter().enumerate() {
+            let y = row * TILE_HEIGHT + ty;
+            for tx in 0..TILE_WIDTH {
+                let x = column * TILE_WIDTH + tx;
+                let color = colors[((bits >> (TILE_WIDTH - 1 - tx)) & 1) as usize];
+                let pixel = &mut self.pixels[y * WIDTH + x];
+                *pixel = if xor { *pixel ^ color } else { color };
+            }
+        }
+    }
+
+    fn scroll(&mut self, data: &[u8; 16], copy: bool) {
+        let color = data[0] & 0x0f;
+        let (h_command, v_command) = ((data[1] >> 4) & 0x03, (data[2] >> 4) & 0x03);
+        self.h_offset = ((data[1] & 0x07) as usize).min(TILE_WIDTH - 1);
+        self.v_offset = ((data[2] & 0x0f) as usize).min(TILE_HEIGHT - 1);
+
+        // Shift to the right and down, a shift to the left or up wraps around by the difference
+        let dx = match h_command {
+            1 => TILE_WIDTH,
+            2 => WIDTH - TILE_WIDTH,
+            _ => 0,
+        };
+        let dy = match v_command {
+            1 => TILE_HEIGHT,
+            2 => HEIGHT - TILE_HEIGHT,
+            _ => 0,
+        };
+        if dx == 0 && dy == 0 {
+            return;
+        }
+
+        let old = self.pixels.clone();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let (sx, sy) = ((x + WIDTH - dx) % WIDTH, (y + HEIGHT - dy) % HEIGHT);
+                // Pixels that wrapped around are filled with the color unless copying
+                let wrapped = (h_command == 1 && x < dx) || (h_command == 2 && x >= dx)
+                    || (v_command == 1 && y < dy) || (v_command == 2 && y >= dy);
+                self.pixels[y * WIDTH + x] = if wrapped && !copy {
+                    color
+                } else {
+                    old[sy * WIDTH + sx]
+                };
+            }
+        }
+    }
+
+    fn load_color_table(&mut self, data: &[u8; 16], first: usize) {
+        for (i, entry) in data.chunks(2).enumerate() {
+            let (high, low) = (entry[0], entry[1]);
+            let r = (high >> 2) & 0x0f;
+            let g = ((high & 0x03) << 2) | ((low >> 4) & 0x03);
+            let b = low & 0x0f;
+            self.palette[first + i] = [r * 17, g * 17, b * 17];
+        }
+    }
+
+    // Renders the screen as RGB, the visible area is moved by the fine scroll offsets
+    pub fn render(&self, out: &mut [u8], stride: usize) {
+        for y in 0..HEIGHT {
+            let row = &mut out[(y * stride)..(y * stride + WIDTH * 3)];
+            for (x, pixel) in row.chunks_mut(3).enumerate() {
+                let color = if is_border(x, y) {
+                    self.border
+                } else {
+                    let sx = (x + self.h_offset).min(WIDTH - 1);
+                    let sy = (y + self.v_offset).min(HEIGHT - 1);
+                    self.pixels[sy * WIDTH + sx]
+                };
+                pixel.copy_from_slice(&self.palette[color as usize]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(instruction: u8, data: &[u8]) -> [u8; PACKET_SIZE] {
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = COMMAND_CDG;
+        packet[1] = instruction;
+        packet[4..(4 + data.len())].copy_from_slice(data);
+        packet
+    }
+
+    #[test]
+    fn test_screen() {
+        let mut screen = Screen::default();
+
+        // Color 0 black, color 1 red, color 2 white
+        screen.handle_packet(&packet(
+            LOAD_COLOR_TABLE_LOW,
+            &[0x00, 0x00, 0x3c, 0x00, 0x3f, 0x3f],
+        ));
+        screen.handle_packet(&packet(BORDER_PRESET, &[2]));
+
+        // Left half of the tile at row 1, column 1 in red
+        let mut data = vec![0, 1, 1, 1];
+        data.extend_from_slice(&[0b111000; 12]);
+        screen.handle_packet(&packet(TILE_BLOCK, &data));
+
+        let mut out = vec![0; WIDTH * HEIGHT * 3];
+        screen.render(&mut out, WIDTH * 3);
+
+        let pixel = |x: usize, y: usize| &out[(y * WIDTH + x) * 3..(y * WIDTH + x) * 3 + 3];
+        assert_eq!(pixel(0, 0), &[255, 255, 255]);
+        assert_eq!(pixel(6, 12), &[255, 0, 0]);
+        assert_eq!(pixel(8, 23), &[255, 0, 0]);
+        assert_eq!(pixel(9, 12), &[0, 0, 0]);
+
+        // XOR with color 1 turns red into black and black into red
+        screen.handle_packet(&packet(TILE_BLOCK_XOR, &data));
+        screen.handle_packet(&packet(
+            TILE_BLOCK_XOR,
+            &[0, 1, 1, 1, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f, 0x3f],
+        ));
+        screen.render(&mut out, WIDTH * 3);
+        let pixel = |x: usize, y: usize| &out[(y * WIDTH + x) * 3..(y * WIDTH + x) * 3 + 3];
+        assert_eq!(pixel(6, 12), &[255, 0, 0]);
+        assert_eq!(pixel(11, 12), &[255, 0, 0]);
+
+        // Scrolling the whole screen right by one tile
+        screen.handle_packet(&packet(SCROLL_COPY, &[0, 0x10, 0]));
+        screen.render(&mut out, WIDTH * 3);
+        let pixel = |x: usize, y: usize| &out[(y * WIDTH + x) * 3..(y * WIDTH + x) * 3 + 3];
+        assert_eq!(pixel(6, 12), &[255, 255, 255]);
+        assert_eq!(pixel(12, 12), &[255, 0, 0]);
+    }
+}
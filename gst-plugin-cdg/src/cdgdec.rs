@@ -0,0 +1,264 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+
+use cdg;
+
+// Applies all packets of every input buffer to the screen and outputs one RGB frame per input
+// buffer with its timestamps. As the screen content depends on all previous packets, decoding
+// after a seek only shows the parts drawn since then.
+
+fn output_info() -> gst_video::VideoInfo {
+    gst_video::VideoInfo::new(
+        gst_video::VideoFormat::Rgb,
+        cdg::WIDTH as u32,
+        cdg::HEIGHT as u32,
+    ).fps(gst::Fraction::new(cdg::SECTORS_PER_SECOND as i32, 1))
+        .build()
+        .unwrap()
+}
+
+struct CdgDec {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    screen: Mutex<cdg::Screen>,
+}
+
+impl CdgDec {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "cdgdec",
+                gst::DebugColorFlags::empty(),
+                "CD+G decoder",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            screen: Mutex::new(cdg::Screen::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "CD+G decoder",
+            "Codec/Decoder/Video",
+            "Renders CD+G karaoke graphics",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("video/x-cdg", &[("parsed", &true)]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &output_info().to_caps().unwrap(),
+        );
+        klass.add_pad_template(src_pad_template);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            CdgDec::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |cdgdec, element| cdgdec.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            CdgDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |cdgdec, element| cdgdec.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            CdgDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |cdgdec, element| cdgdec.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            CdgDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |cdgdec, element| cdgdec.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            CdgDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |cdgdec, element| cdgdec.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let cdgdec = element.get_impl().downcast_ref::<CdgDec>().unwrap();
+        element.catch_panic(fallback, |element| f(cdgdec, element))
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let info = output_info();
+        let mut outbuf = gst::Buffer::with_size(info.size()).unwrap();
+        {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            let outbuf = outbuf.get_mut().unwrap();
+            outbuf.set_pts(buffer.get_pts());
+            outbuf.set_duration(buffer.get_duration());
+
+            let mut screen = self.screen.lock().unwrap();
+            for packet in map.as_slice().chunks(cdg::PACKET_SIZE) {
+                screen.handle_packet(packet);
+            }
+
+            let mut outmap = outbuf.map_writable().unwrap();
+            screen.render(outmap.as_mut_slice(), info.stride()[0] as usize);
+        }
+
+        self.srcpad.push(outbuf)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        if let EventView::Caps(..) = event.view() {
+            let caps = output_info().to_caps().unwrap();
+            return self.srcpad.push_event(gst::Event::new_caps(&caps).build());
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for CdgDec {}
+
+impl ElementImpl<Element> for CdgDec {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.screen.lock().unwrap() = cdg::Screen::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct CdgDecStatic;
+
+impl ImplTypeStatic<Element> for CdgDecStatic {
+    fn get_name(&self) -> &str {
+        "CdgDec"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        CdgDec::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        CdgDec::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let cdgdec_static = CdgDecStatic;
+    register_type(cdgdec_static)
+}
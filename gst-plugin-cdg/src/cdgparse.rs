@@ -0,0 +1,341 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::adapter::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+
+use cdg;
+
+// Splits a raw CD+G stream into buffers of one CD sector (4 packets) and timestamps them by
+// their position in the stream, 75 sectors per second.
+
+struct State {
+    adapter: Adapter,
+    // Number of the next sector
+    sector: u64,
+    need_caps: bool,
+    need_segment: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            adapter: Adapter::new(),
+            sector: 0,
+            need_caps: true,
+            need_segment: true,
+        }
+    }
+}
+
+fn src_caps() -> gst::Caps {
+    gst::Caps::new_simple(
+        "video/x-cdg",
+        &[
+            ("width", &(cdg::WIDTH as i32)),
+            ("height", &(cdg::HEIGHT as i32)),
+            (
+                "framerate",
+                &gst::Fraction::new(cdg::SECTORS_PER_SECOND as i32, 1),
+            ),
+            ("parsed", &true),
+        ],
+    )
+}
+
+fn sector_time(sector: u64) -> gst::ClockTime {
+    gst::ClockTime(Some(sector * gst::SECOND_VAL / cdg::SECTORS_PER_SECOND))
+}
+
+struct CdgParse {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl CdgParse {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "cdgparse",
+                gst::DebugColorFlags::empty(),
+                "CD+G parser",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "CD+G parser",
+            "Codec/Parser/Video",
+            "Splits CD+G streams into sectors and timestamps them",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("video/x-cdg", &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &src_caps(),
+        );
+        klass.add_pad_template(src_pad_template);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            CdgParse::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |cdgparse, element| cdgparse.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            CdgParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |cdgparse, element| cdgparse.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            CdgParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |cdgparse, element| cdgparse.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            CdgParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |cdgparse, element| cdgparse.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            CdgParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |cdgparse, element| cdgparse.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let cdgparse = element.get_impl().downcast_ref::<CdgParse>().unwrap();
+        element.catch_panic(fallback, |element| f(cdgparse, element))
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        _element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let mut events = Vec::new();
+        let mut buffers = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.adapter.push(buffer);
+
+            while state.adapter.get_available() >= cdg::SECTOR_SIZE {
+                let mut buffer = state.adapter.get_buffer(cdg::SECTOR_SIZE).unwrap();
+                {
+                    let buffer = buffer.get_mut().unwrap();
+                    buffer.set_pts(sector_time(state.sector));
+                    buffer.set_duration(gst::ClockTime(Some(
+                        gst::SECOND_VAL / cdg::SECTORS_PER_SECOND,
+                    )));
+                    buffer.set_offset(state.sector);
+                }
+                state.sector += 1;
+                buffers.push(buffer);
+            }
+
+            if buffers.is_empty() {
+                return gst::FlowReturn::Ok;
+            }
+
+            if state.need_caps {
+                state.need_caps = false;
+                events.push(gst::Event::new_caps(&src_caps()).build());
+            }
+            if state.need_segment {
+                state.need_segment = false;
+                let mut segment = gst::FormattedSegment::<gst::ClockTime>::new();
+                segment.set_start(buffers[0].get_pts());
+                segment.set_time(buffers[0].get_pts());
+                events.push(gst::Event::new_segment(&segment).build());
+            }
+        }
+
+        for event in events {
+            self.srcpad.push_event(event);
+        }
+
+        for buffer in buffers {
+            let flow_ret = self.srcpad.push(buffer);
+            if flow_ret != gst::FlowReturn::Ok {
+                return flow_ret;
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(..) => {
+                self.state.lock().unwrap().need_caps = true;
+                return true;
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::format::Bytes>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Bytes segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                // Output continues at the sector the segment starts in
+                let mut state = self.state.lock().unwrap();
+                state.adapter.clear();
+                state.sector = segment.get_start().0.unwrap_or(0) / cdg::SECTOR_SIZE as u64;
+                state.need_segment = true;
+                return true;
+            }
+            EventView::FlushStop(..) => {
+                self.state.lock().unwrap().adapter.clear();
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for CdgParse {}
+
+impl ElementImpl<Element> for CdgParse {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct CdgParseStatic;
+
+impl ImplTypeStatic<Element> for CdgParseStatic {
+    fn get_name(&self) -> &str {
+        "CdgParse"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        CdgParse::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        CdgParse::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let cdgparse_static = CdgParseStatic;
+    register_type(cdgparse_static)
+}
@@ -0,0 +1,39 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate glib;
+#[macro_use]
+extern crate gst_plugin;
+#[macro_use]
+extern crate gstreamer as gst;
+extern crate gstreamer_video as gst_video;
+
+use gst_plugin::registration::*;
+
+mod cdg;
+
+mod cdgdec;
+mod cdgparse;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    ElementRegistration::new(plugin)
+        .element("cdgparse", RANK_NONE, cdgparse::get_type())
+        .element("cdgdec", RANK_NONE, cdgdec::get_type())
+        .register()
+}
+
+plugin_define!(
+    "rscdg",
+    "Rust CD+G Plugin",
+    plugin_init,
+    "MIT/X11",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2018-01-22"
+);
@@ -0,0 +1,321 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+
+use cea608;
+
+// Every input buffer contains one or more CEA-608 byte pairs of the first field. Whenever the
+// displayed captions change, the previous text is output with a duration until the change. The
+// last text is output on EOS without a duration.
+
+struct State {
+    decoder: cea608::Decoder,
+    // Currently displayed text and since when
+    pending: Option<(gst::ClockTime, String)>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            decoder: cea608::Decoder::default(),
+            pending: None,
+        }
+    }
+}
+
+impl State {
+    // Returns the buffer for the previously displayed text if it changed
+    fn update(&mut self, pts: gst::ClockTime) -> Option<gst::Buffer> {
+        let text = self.decoder.text();
+        let changed = match self.pending {
+            None => !text.is_empty(),
+            Some((_, ref pending)) => *pending != text,
+        };
+        if !changed {
+            return None;
+        }
+
+        let buffer = self.pending.take().map(|(start, text)| {
+            let duration = match (start.0, pts.0) {
+                (Some(start), Some(pts)) if pts >= start => gst::ClockTime(Some(pts - start)),
+                _ => gst::CLOCK_TIME_NONE,
+            };
+            text_buffer(start, duration, text)
+        });
+
+        if !text.is_empty() {
+            self.pending = Some((pts, text));
+        }
+
+        buffer
+    }
+}
+
+fn text_buffer(pts: gst::ClockTime, duration: gst::ClockTime, text: String) -> gst::Buffer {
+    let mut buffer = gst::Buffer::from_mut_slice(text.into_bytes()).unwrap();
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(pts);
+        buffer.set_duration(duration);
+    }
+    buffer
+}
+
+struct Cc608ToTt {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl Cc608ToTt {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "cc608tott",
+                gst::DebugColorFlags::empty(),
+                "CEA-608 to timed text",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "CEA-608 to timed text",
+            "Generic",
+            "Converts CEA-608 closed captions to timed text",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("closedcaption/x-cea-608", &[("format", &"raw")]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple("text/x-raw", &[("format", &"utf8")]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            Cc608ToTt::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |cc608tott, element| cc608tott.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            Cc608ToTt::catch_panic_pad_function(
+                parent,
+                || false,
+                |cc608tott, element| cc608tott.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            Cc608ToTt::catch_panic_pad_function(
+                parent,
+                || false,
+                |cc608tott, element| cc608tott.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            Cc608ToTt::catch_panic_pad_function(
+                parent,
+                || false,
+                |cc608tott, element| cc608tott.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            Cc608ToTt::catch_panic_pad_function(
+                parent,
+                || false,
+                |cc608tott, element| cc608tott.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let cc608tott = element.get_impl().downcast_ref::<Cc608ToTt>().unwrap();
+        element.catch_panic(fallback, |element| f(cc608tott, element))
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let outbuf = {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            let mut state = self.state.lock().unwrap();
+            for pair in map.as_slice().chunks(2) {
+                if pair.len() == 2 {
+                    state.decoder.decode_pair(pair[0], pair[1]);
+                }
+            }
+
+            state.update(buffer.get_pts())
+        };
+
+        match outbuf {
+            None => gst::FlowReturn::Ok,
+            Some(outbuf) => {
+                gst_debug!(self.cat, obj: element, "Outputting text {:?}", outbuf);
+                self.srcpad.push(outbuf)
+            }
+        }
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(..) => {
+                let caps = gst::Caps::new_simple("text/x-raw", &[("format", &"utf8")]);
+                return self.srcpad.push_event(gst::Event::new_caps(&caps).build());
+            }
+            EventView::FlushStop(..) => {
+                *self.state.lock().unwrap() = State::default();
+            }
+            EventView::Eos(..) => {
+                let pending = self.state.lock().unwrap().pending.take();
+                if let Some((start, text)) = pending {
+                    let _ = self.srcpad
+                        .push(text_buffer(start, gst::CLOCK_TIME_NONE, text));
+                }
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for Cc608ToTt {}
+
+impl ElementImpl<Element> for Cc608ToTt {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct Cc608ToTtStatic;
+
+impl ImplTypeStatic<Element> for Cc608ToTtStatic {
+    fn get_name(&self) -> &str {
+        "Cc608ToTt"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        Cc608ToTt::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        Cc608ToTt::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let cc608tott_static = Cc608ToTtStatic;
+    register_type(cc608tott_static)
+}
@@ -0,0 +1,471 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::sync::Mutex;
+
+// Conversion between the closed caption formats:
+//
+// - CEA-608 raw: byte pairs of the first field
+// - CEA-608 s334-1a: triplets of a field/line byte and a byte pair
+// - CEA-708 cc_data: triplets of a marker/valid/type byte and a byte pair
+// - CEA-708 cdp: caption distribution packets containing cc_data
+//
+// All input is converted to cc_data first. CEA-608 data is carried in CEA-708 only as the
+// compatibility bytes, no DTVCC services are created from it. Converting to CEA-608 drops
+// all DTVCC data, and converting to raw CEA-608 also drops the second field.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Cea608Raw,
+    Cea608S3341a,
+    Cea708CcData,
+    Cea708Cdp,
+}
+
+type CcData = [u8; 3];
+
+const CC_VALID: u8 = 0x04;
+const CC_TYPE_608_FIELD1: u8 = 0x00;
+const CC_TYPE_608_FIELD2: u8 = 0x01;
+
+const MAX_CDP_CC_COUNT: usize = 31;
+
+// CDP frame rate codes
+const CDP_FRAMERATES: [(u8, i32, i32); 8] = [
+    (1, 24000, 1001),
+    (2, 24, 1),
+    (3, 25, 1),
+    (4, 30000, 1001),
+    (5, 30, 1),
+    (6, 50, 1),
+    (7, 60000, 1001),
+    (8, 60, 1),
+];
+
+fn cdp_framerate_code(numer: i32, denom: i32) -> Option<u8> {
+    CDP_FRAMERATES
+        .iter()
+        .find(|&&(_, n, d)| n as i64 * denom as i64 == d as i64 * numer as i64)
+        .map(|&(code, _, _)| code)
+}
+
+fn parse_cdp(data: &[u8]) -> Result<Vec<CcData>, String> {
+    if data.len() < 11 || data[0] != 0x96 || data[1] != 0x69 {
+        return Err("Invalid CDP header".into());
+    }
+
+    let len = data[2] as usize;
+    if len < 11 || len > data.len() {
+        return Err(format!("Invalid CDP length {}", len));
+    }
+    let data = &data[..len];
+    if data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+        return Err("Invalid CDP checksum".into());
+    }
+
+    let flags = data[4];
+    let mut offset = 7;
+
+    // Time code section
+    if flags & 0x80 != 0 {
+        offset += 5;
+    }
+
+    if flags & 0x40 == 0 {
+        return Ok(Vec::new());
+    }
+
+    if offset + 2 > len || data[offset] != 0x72 {
+        return Err("Missing CDP cc_data section".into());
+    }
+    let cc_count = (data[offset + 1] & 0x1f) as usize;
+    offset += 2;
+    if offset + 3 * cc_count > len {
+        return Err(format!("Invalid CDP cc_count {}", cc_count));
+    }
+
+    Ok(data[offset..(offset + 3 * cc_count)]
+        .chunks(3)
+        .map(|c| [c[0], c[1], c[2]])
+        .collect())
+}
+
+fn write_cdp(cc_data: &[CcData], framerate_code: u8, sequence: u16, out: &mut Vec<u8>) {
+    let cc_data = &cc_data[..cc_data.len().min(MAX_CDP_CC_COUNT)];
+    let start = out.len();
+    let len = 7 + 2 + 3 * cc_data.len() + 4;
+    let (seq_hi, seq_lo) = ((sequence >> 8) as u8, (sequence & 0xff) as u8);
+
+    // cc_data present, caption service active and the reserved bit
+    out.extend_from_slice(&[0x96, 0x69, len as u8, (framerate_code << 4) | 0x0f, 0x43]);
+    out.extend_from_slice(&[seq_hi, seq_lo, 0x72, 0xe0 | cc_data.len() as u8]);
+    for cc in cc_data {
+        out.extend_from_slice(cc);
+    }
+    out.extend_from_slice(&[0x74, seq_hi, seq_lo]);
+
+    let sum = out[start..].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    out.push(0u8.wrapping_sub(sum));
+}
+
+fn to_cc_data(format: Format, data: &[u8]) -> Result<Vec<CcData>, String> {
+    match format {
+        Format::Cea608Raw => Ok(data.chunks(2)
+            .filter(|c| c.len() == 2)
+            .map(|c| [0xf8 | CC_VALID | CC_TYPE_608_FIELD1, c[0], c[1]])
+            .collect()),
+        Format::Cea608S3341a => Ok(data.chunks(3)
+            .filter(|c| c.len() == 3)
+            .map(|c| {
+                let cc_type = if c[0] & 0x80 != 0 {
+                    CC_TYPE_608_FIELD1
+                } else {
+                    CC_TYPE_608_FIELD2
+                };
+                [0xf8 | CC_VALID | cc_type, c[1], c[2]]
+            })
+            .collect()),
+        Format::Cea708CcData => Ok(data.chunks(3)
+            .filter(|c| c.len() == 3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect()),
+        Format::Cea708Cdp => parse_cdp(data),
+    }
+}
+
+fn from_cc_data(
+    format: Format,
+    cc_data: &[CcData],
+    framerate_code: u8,
+    sequence: u16,
+    out: &mut Vec<u8>,
+) {
+    let cea608 = cc_data
+        .iter()
+        .filter(|cc| cc[0] & CC_VALID != 0 && cc[0] & 0x03 <= CC_TYPE_608_FIELD2);
+
+    match format {
+        Format::Cea608Raw => for cc in cea608.filter(|cc| cc[0] & 0x03 == CC_TYPE_608_FIELD1) {
+            out.extend_from_slice(&cc[1..]);
+        },
+        Format::Cea608S3341a => for cc in cea608 {
+            let field = if cc[0] & 0x03 == CC_TYPE_608_FIELD1 {
+                0x80
+            } else {
+                0x00
+            };
+            out.extend_from_slice(&[field, cc[1], cc[2]]);
+        },
+        Format::Cea708CcData => for cc in cc_data {
+            out.extend_from_slice(cc);
+        },
+        Format::Cea708Cdp => write_cdp(cc_data, framerate_code, sequence, out),
+    }
+}
+
+fn format_from_caps(caps: &gst::Caps) -> Option<(Format, Option<u8>)> {
+    let s = caps.get_structure(0)?;
+    let format = match (s.get_name(), s.get::<&str>("format")?) {
+        ("closedcaption/x-cea-608", "raw") => Format::Cea608Raw,
+        ("closedcaption/x-cea-608", "s334-1a") => Format::Cea608S3341a,
+        ("closedcaption/x-cea-708", "cc_data") => Format::Cea708CcData,
+        ("closedcaption/x-cea-708", "cdp") => Format::Cea708Cdp,
+        _ => return None,
+    };
+    let framerate_code = s.get::<gst::Fraction>("framerate")
+        .and_then(|fps| cdp_framerate_code(*fps.numer(), *fps.denom()));
+
+    Some((format, framerate_code))
+}
+
+fn template_caps() -> gst::Caps {
+    let mut caps = gst::Caps::new_simple(
+        "closedcaption/x-cea-608",
+        &[("format", &gst::List::new(&[&"raw", &"s334-1a"]))],
+    );
+    {
+        let caps = caps.get_mut().unwrap();
+        caps.append(gst::Caps::new_simple(
+            "closedcaption/x-cea-708",
+            &[("format", &"cc_data")],
+        ));
+
+        let framerates = CDP_FRAMERATES
+            .iter()
+            .map(|&(_, n, d)| gst::Fraction::new(n, d).to_send_value())
+            .collect::<Vec<_>>();
+        caps.append(gst::Caps::new_simple(
+            "closedcaption/x-cea-708",
+            &[
+                ("format", &"cdp"),
+                ("framerate", &gst::List::from_owned(framerates)),
+            ],
+        ));
+    }
+
+    caps
+}
+
+struct State {
+    in_format: Format,
+    out_format: Format,
+    framerate_code: u8,
+    sequence: u16,
+}
+
+struct CcConverter {
+    cat: gst::DebugCategory,
+    state: Mutex<Option<State>>,
+}
+
+impl CcConverter {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "ccconverter",
+                gst::DebugColorFlags::empty(),
+                "Closed caption converter",
+            ),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Closed Caption Converter",
+            "Generic",
+            "Converts between closed caption formats",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = template_caps();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.configure(BaseTransformMode::NeverInPlace, true, false);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseTransform> for CcConverter {}
+
+impl ElementImpl<BaseTransform> for CcConverter {}
+
+impl BaseTransformImpl<BaseTransform> for CcConverter {
+    fn transform_caps(
+        &self,
+        _element: &BaseTransform,
+        _direction: gst::PadDirection,
+        caps: &gst::Caps,
+        filter: Option<&gst::Caps>,
+    ) -> gst::Caps {
+        // Every format can be converted to every other, keeping the same format is preferred
+        let mut other_caps = caps.clone();
+        other_caps.make_mut().append(template_caps());
+
+        match filter {
+            None => other_caps,
+            Some(filter) => filter.intersect_with_mode(&other_caps, gst::CapsIntersectMode::First),
+        }
+    }
+
+    fn transform_size(
+        &self,
+        _element: &BaseTransform,
+        _direction: gst::PadDirection,
+        _caps: &gst::Caps,
+        size: usize,
+        _othercaps: &gst::Caps,
+    ) -> Option<usize> {
+        // Upper bound, the output buffer is shrunk to the actual size
+        Some(2 * size + 16)
+    }
+
+    fn set_caps(&self, element: &BaseTransform, incaps: &gst::Caps, outcaps: &gst::Caps) -> bool {
+        let (in_format, out_format, framerate_code) =
+            match (format_from_caps(incaps), format_from_caps(outcaps)) {
+                (Some((in_format, _)), Some((out_format, framerate_code))) => {
+                    (in_format, out_format, framerate_code)
+                }
+                _ => return false,
+            };
+
+        let framerate_code = match (out_format, framerate_code) {
+            (Format::Cea708Cdp, None) => {
+                gst_error!(self.cat, obj: element, "CDP output needs a supported framerate");
+                return false;
+            }
+            (_, framerate_code) => framerate_code.unwrap_or(0),
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Converting from {:?} to {:?}",
+            in_format,
+            out_format
+        );
+
+        *self.state.lock().unwrap() = Some(State {
+            in_format: in_format,
+            out_format: out_format,
+            framerate_code: framerate_code,
+            sequence: 0,
+        });
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseTransform) -> bool {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        true
+    }
+
+    fn transform(
+        &self,
+        element: &BaseTransform,
+        inbuf: &gst::Buffer,
+        outbuf: &mut gst::BufferRef,
+    ) -> gst::FlowReturn {
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return gst::FlowReturn::NotNegotiated,
+            Some(ref mut state) => state,
+        };
+
+        let cc_data = {
+            let map = match inbuf.map_readable() {
+                None => return gst::FlowReturn::Error,
+                Some(map) => map,
+            };
+
+            match to_cc_data(state.in_format, map.as_slice()) {
+                Err(err) => {
+                    gst_warning!(self.cat, obj: element, "Dropping invalid input: {}", err);
+                    Vec::new()
+                }
+                Ok(cc_data) => cc_data,
+            }
+        };
+
+        let mut data = Vec::new();
+        from_cc_data(
+            state.out_format,
+            &cc_data,
+            state.framerate_code,
+            state.sequence,
+            &mut data,
+        );
+        state.sequence = state.sequence.wrapping_add(1);
+
+        {
+            let mut map = match outbuf.map_writable() {
+                None => return gst::FlowReturn::Error,
+                Some(map) => map,
+            };
+            if map.get_size() < data.len() {
+                return gst::FlowReturn::Error;
+            }
+            map.as_mut_slice()[..data.len()].copy_from_slice(&data);
+        }
+        outbuf.set_size(data.len());
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct CcConverterStatic;
+
+impl ImplTypeStatic<BaseTransform> for CcConverterStatic {
+    fn get_name(&self) -> &str {
+        "CcConverter"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        CcConverter::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        CcConverter::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let ccconverter_static = CcConverterStatic;
+    register_type(ccconverter_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(from: Format, to: Format, data: &[u8]) -> Vec<u8> {
+        let cc_data = to_cc_data(from, data).unwrap();
+        let mut out = Vec::new();
+        from_cc_data(to, &cc_data, 4, 0x1234, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_convert() {
+        let raw = [0x94, 0x20, 0xc8, 0xe5];
+
+        let cdp = convert(Format::Cea608Raw, Format::Cea708Cdp, &raw);
+        assert_eq!(cdp.len(), 19);
+        assert_eq!(&cdp[..9], &[0x96, 0x69, 19, 0x4f, 0x43, 0x12, 0x34, 0x72, 0xe2]);
+        assert_eq!(&cdp[9..15], &[0xfc, 0x94, 0x20, 0xfc, 0xc8, 0xe5]);
+        assert_eq!(cdp.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)), 0);
+
+        assert_eq!(convert(Format::Cea708Cdp, Format::Cea608Raw, &cdp), raw);
+
+        let s334 = convert(Format::Cea708Cdp, Format::Cea608S3341a, &cdp);
+        assert_eq!(s334, [0x80, 0x94, 0x20, 0x80, 0xc8, 0xe5]);
+
+        // The second field is dropped for raw CEA-608
+        let s334 = [0x80, 0x94, 0x20, 0x00, 0x15, 0x2c];
+        assert_eq!(
+            convert(Format::Cea608S3341a, Format::Cea608Raw, &s334),
+            [0x94, 0x20]
+        );
+        assert_eq!(
+            convert(Format::Cea608S3341a, Format::Cea708CcData, &s334),
+            [0xfc, 0x94, 0x20, 0xfd, 0x15, 0x2c]
+        );
+
+        assert!(parse_cdp(&cdp[..10]).is_err());
+        assert_eq!(cdp_framerate_code(30000, 1001), Some(4));
+        assert_eq!(cdp_framerate_code(15, 1), None);
+    }
+}
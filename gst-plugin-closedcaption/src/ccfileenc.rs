@@ -0,0 +1,497 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+
+use mcc;
+use scc;
+use timecode;
+use timecode::TimeCode;
+
+// Writes caption files with the time codes taken from the buffer timestamps. For SCC, the
+// CEA-608 byte pairs of consecutive frames are collected into one line and padding ends a
+// line. For MCC, every CDP is written as a separate line.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Scc,
+    Mcc,
+}
+
+impl Format {
+    fn media_type(&self) -> &'static str {
+        match *self {
+            Format::Scc => "application/x-scc",
+            Format::Mcc => "application/x-mcc",
+        }
+    }
+}
+
+const SCC_FRAMERATE: (i32, i32) = (30000, 1001);
+
+const MCC_FRAMERATES: [(i32, i32); 7] = [
+    (24, 1),
+    (25, 1),
+    (30000, 1001),
+    (30, 1),
+    (50, 1),
+    (60000, 1001),
+    (60, 1),
+];
+
+struct State {
+    framerate: Option<gst::Fraction>,
+    need_header: bool,
+    // SCC line that is currently collected: first frame and byte pairs
+    line: Option<(u64, Vec<[u8; 2]>)>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            framerate: None,
+            need_header: true,
+            line: None,
+        }
+    }
+}
+
+impl State {
+    // Nominal frame rate and drop-frame flag of the time codes
+    fn time_code_rate(&self) -> (u32, bool) {
+        let framerate = self.framerate.unwrap();
+        let (numer, denom) = (*framerate.numer() as u32, *framerate.denom() as u32);
+        ((numer + denom / 2) / denom, denom == 1001)
+    }
+
+    fn write_header(&mut self, format: Format, out: &mut String) {
+        if !self.need_header {
+            return;
+        }
+        self.need_header = false;
+
+        match format {
+            Format::Scc => {
+                out.push_str(scc::HEADER);
+                out.push_str("\n\n");
+            }
+            Format::Mcc => {
+                let (fps, drop_frame) = self.time_code_rate();
+                mcc::write_header(fps, drop_frame, out);
+            }
+        }
+    }
+
+    fn flush_scc_line(&mut self, out: &mut String) {
+        if let Some((start, pairs)) = self.line.take() {
+            self.write_header(Format::Scc, out);
+            let tc = TimeCode::from_frames(start, scc::FPS, true);
+            scc::write_line(&tc, &pairs, out);
+        }
+    }
+
+    fn handle_scc(&mut self, frame: u64, data: &[u8], out: &mut String) {
+        for (i, pair) in data.chunks(2).filter(|pair| pair.len() == 2).enumerate() {
+            let frame = frame + i as u64;
+
+            // Padding, with or without parity
+            if pair[0] & 0x7f == 0 && pair[1] & 0x7f == 0 {
+                self.flush_scc_line(out);
+                continue;
+            }
+
+            let consecutive = match self.line {
+                Some((start, ref pairs)) => start + pairs.len() as u64 == frame,
+                None => false,
+            };
+            if !consecutive {
+                self.flush_scc_line(out);
+                self.line = Some((frame, Vec::new()));
+            }
+            if let Some((_, ref mut pairs)) = self.line {
+                pairs.push([pair[0], pair[1]]);
+            }
+        }
+    }
+
+    fn handle_mcc(&mut self, frame: u64, data: &[u8], out: &mut String) {
+        self.write_header(Format::Mcc, out);
+        let (fps, drop_frame) = self.time_code_rate();
+        let tc = TimeCode::from_frames(frame, fps, drop_frame);
+        mcc::write_line(&tc, data, out);
+    }
+}
+
+struct CcFileEnc {
+    cat: gst::DebugCategory,
+    format: Format,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl CcFileEnc {
+    fn new(_element: &Element, format: Format, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "ccfileenc",
+                gst::DebugColorFlags::empty(),
+                "Closed caption file encoder",
+            ),
+            format: format,
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass, format: Format) {
+        let caps = match format {
+            Format::Scc => {
+                klass.set_metadata(
+                    "SCC encoder",
+                    "Encoder/ClosedCaption",
+                    "Writes Scenarist SCC closed caption files",
+                    "Sebastian Dröge <sebastian@centricular.com>",
+                );
+
+                gst::Caps::new_simple(
+                    "closedcaption/x-cea-608",
+                    &[
+                        ("format", &"raw"),
+                        (
+                            "framerate",
+                            &gst::Fraction::new(SCC_FRAMERATE.0, SCC_FRAMERATE.1),
+                        ),
+                    ],
+                )
+            }
+            Format::Mcc => {
+                klass.set_metadata(
+                    "MCC encoder",
+                    "Encoder/ClosedCaption",
+                    "Writes MacCaption MCC closed caption files",
+                    "Sebastian Dröge <sebastian@centricular.com>",
+                );
+
+                let framerates = MCC_FRAMERATES
+                    .iter()
+                    .map(|&(n, d)| gst::Fraction::new(n, d).to_send_value())
+                    .collect::<Vec<_>>();
+                gst::Caps::new_simple(
+                    "closedcaption/x-cea-708",
+                    &[
+                        ("format", &"cdp"),
+                        ("framerate", &gst::List::from_owned(framerates)),
+                    ],
+                )
+            }
+        };
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple(format.media_type(), &[]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+    }
+
+    fn init(element: &Element, format: Format) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            CcFileEnc::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |enc, element| enc.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            CcFileEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |enc, element| enc.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            CcFileEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |enc, element| enc.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            CcFileEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |enc, element| enc.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            CcFileEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |enc, element| enc.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, format, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let enc = element.get_impl().downcast_ref::<CcFileEnc>().unwrap();
+        element.catch_panic(fallback, |element| f(enc, element))
+    }
+
+    fn push_text(&self, text: String) -> gst::FlowReturn {
+        if text.is_empty() {
+            return gst::FlowReturn::Ok;
+        }
+
+        self.srcpad
+            .push(gst::Buffer::from_mut_slice(text.into_bytes()).unwrap())
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let pts = match buffer.get_pts().0 {
+            None => {
+                gst_element_error!(
+                    element,
+                    gst::StreamError::Format,
+                    ["Buffers without timestamps are not supported"]
+                );
+                return gst::FlowReturn::Error;
+            }
+            Some(pts) => pts,
+        };
+
+        let mut out = String::new();
+        {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            let mut state = self.state.lock().unwrap();
+            let framerate = match state.framerate {
+                None => return gst::FlowReturn::NotNegotiated,
+                Some(framerate) => framerate,
+            };
+            let frame = timecode::time_to_frames(pts, framerate);
+
+            match self.format {
+                Format::Scc => state.handle_scc(frame, map.as_slice(), &mut out),
+                Format::Mcc => state.handle_mcc(frame, map.as_slice(), &mut out),
+            }
+        }
+
+        self.push_text(out)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let framerate = match e.get_caps()
+                    .get_structure(0)
+                    .and_then(|s| s.get::<gst::Fraction>("framerate"))
+                {
+                    None => return false,
+                    Some(framerate) => framerate,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                if !state.need_header && state.framerate != Some(framerate) {
+                    gst_element_error!(
+                        element,
+                        gst::StreamError::Format,
+                        ["Framerate can't change"]
+                    );
+                    return false;
+                }
+                state.framerate = Some(framerate);
+                drop(state);
+
+                let caps = gst::Caps::new_simple(self.format.media_type(), &[]);
+                self.srcpad.push_event(gst::Event::new_caps(&caps).build());
+                let segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+                return self.srcpad
+                    .push_event(gst::Event::new_segment(&segment).build());
+            }
+            // Output is in bytes and starts with the header
+            EventView::Segment(..) => return true,
+            EventView::FlushStop(..) => {
+                self.state.lock().unwrap().line = None;
+            }
+            EventView::Eos(..) => {
+                let mut out = String::new();
+                self.state.lock().unwrap().flush_scc_line(&mut out);
+                let _ = self.push_text(out);
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for CcFileEnc {}
+
+impl ElementImpl<Element> for CcFileEnc {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct CcFileEncStatic {
+    format: Format,
+}
+
+impl ImplTypeStatic<Element> for CcFileEncStatic {
+    fn get_name(&self) -> &str {
+        match self.format {
+            Format::Scc => "SccEnc",
+            Format::Mcc => "MccEnc",
+        }
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        CcFileEnc::init(element, self.format)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        CcFileEnc::class_init(klass, self.format);
+    }
+}
+
+pub fn get_scc_type() -> glib::Type {
+    let sccenc_static = CcFileEncStatic {
+        format: Format::Scc,
+    };
+    register_type(sccenc_static)
+}
+
+pub fn get_mcc_type() -> glib::Type {
+    let mccenc_static = CcFileEncStatic {
+        format: Format::Mcc,
+    };
+    register_type(mccenc_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scc_lines() {
+        let mut state = State::default();
+        let mut out = String::new();
+
+        state.handle_scc(30, &[0x94, 0x20], &mut out);
+        state.handle_scc(31, &[0x94, 0x20, 0xc8, 0xe5], &mut out);
+        state.handle_scc(33, &[0x80, 0x80], &mut out);
+        state.handle_scc(40, &[0x94, 0x2f], &mut out);
+        state.flush_scc_line(&mut out);
+
+        assert_eq!(
+            out,
+            "Scenarist_SCC V1.0\n\n\
+             00:00:01;00\t9420 9420 c8e5\n\n\
+             00:00:01;10\t942f\n\n"
+        );
+    }
+}
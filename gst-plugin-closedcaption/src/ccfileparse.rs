@@ -0,0 +1,483 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+
+use mcc;
+use scc;
+use timecode;
+
+// Splits caption files into lines and outputs one buffer per frame with captions, timestamped
+// by the time codes of the file. SCC files contain CEA-608 byte pairs of the first field, MCC
+// files CEA-708 CDPs. Invalid lines are skipped.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Scc,
+    Mcc,
+}
+
+impl Format {
+    fn media_type(&self) -> &'static str {
+        match *self {
+            Format::Scc => "application/x-scc",
+            Format::Mcc => "application/x-mcc",
+        }
+    }
+}
+
+// Nominal frame rate and drop-frame flag of the time codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TimeCodeRate(u32, bool);
+
+impl TimeCodeRate {
+    fn framerate(&self) -> gst::Fraction {
+        if self.1 {
+            gst::Fraction::new(self.0 as i32 * 1000, 1001)
+        } else {
+            gst::Fraction::new(self.0 as i32, 1)
+        }
+    }
+}
+
+struct State {
+    // Incomplete last line
+    line: Vec<u8>,
+    // SCC files always use 29.97 fps, MCC files have it in the header
+    rate: Option<TimeCodeRate>,
+    need_caps: bool,
+    need_segment: bool,
+}
+
+impl State {
+    fn new(format: Format) -> Self {
+        State {
+            line: Vec::new(),
+            rate: match format {
+                Format::Scc => Some(TimeCodeRate(scc::FPS, true)),
+                Format::Mcc => None,
+            },
+            need_caps: true,
+            need_segment: true,
+        }
+    }
+}
+
+fn frame_buffer(frame: u64, rate: TimeCodeRate, data: Vec<u8>) -> gst::Buffer {
+    let framerate = rate.framerate();
+    let pts = timecode::frames_to_time(frame, framerate);
+    let end = timecode::frames_to_time(frame + 1, framerate);
+
+    let mut buffer = gst::Buffer::from_mut_slice(data).unwrap();
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(gst::ClockTime(Some(pts)));
+        buffer.set_duration(gst::ClockTime(Some(end - pts)));
+    }
+    buffer
+}
+
+struct CcFileParse {
+    cat: gst::DebugCategory,
+    format: Format,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl CcFileParse {
+    fn new(_element: &Element, format: Format, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "ccfileparse",
+                gst::DebugColorFlags::empty(),
+                "Closed caption file parser",
+            ),
+            format: format,
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            state: Mutex::new(State::new(format)),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass, format: Format) {
+        let caps = match format {
+            Format::Scc => {
+                klass.set_metadata(
+                    "SCC parser",
+                    "Parser/ClosedCaption",
+                    "Parses Scenarist SCC closed caption files",
+                    "Sebastian Dröge <sebastian@centricular.com>",
+                );
+
+                gst::Caps::new_simple(
+                    "closedcaption/x-cea-608",
+                    &[
+                        ("format", &"raw"),
+                        ("framerate", &gst::Fraction::new(30000, 1001)),
+                    ],
+                )
+            }
+            Format::Mcc => {
+                klass.set_metadata(
+                    "MCC parser",
+                    "Parser/ClosedCaption",
+                    "Parses MacCaption MCC closed caption files",
+                    "Sebastian Dröge <sebastian@centricular.com>",
+                );
+
+                gst::Caps::new_simple(
+                    "closedcaption/x-cea-708",
+                    &[
+                        ("format", &"cdp"),
+                        (
+                            "framerate",
+                            &gst::FractionRange::new(
+                                gst::Fraction::new(0, 1),
+                                gst::Fraction::new(i32::max_value(), 1),
+                            ),
+                        ),
+                    ],
+                )
+            }
+        };
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let caps = gst::Caps::new_simple(format.media_type(), &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+    }
+
+    fn init(element: &Element, format: Format) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            CcFileParse::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |parse, element| parse.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            CcFileParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |parse, element| parse.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            CcFileParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |parse, element| parse.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            CcFileParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |parse, element| parse.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            CcFileParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |parse, element| parse.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, format, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let parse = element.get_impl().downcast_ref::<CcFileParse>().unwrap();
+        element.catch_panic(fallback, |element| f(parse, element))
+    }
+
+    fn parse_line(
+        &self,
+        state: &mut State,
+        line: &str,
+        buffers: &mut Vec<gst::Buffer>,
+    ) -> Result<(), String> {
+        // Byte order mark at the start of the file
+        let line = line.trim_left_matches('\u{feff}');
+
+        match self.format {
+            Format::Scc => {
+                if let Some((tc, pairs)) = scc::parse_line(line)? {
+                    let rate = state.rate.unwrap();
+                    let start = tc.to_frames(rate.0);
+                    for (i, pair) in pairs.iter().enumerate() {
+                        buffers.push(frame_buffer(start + i as u64, rate, pair.to_vec()));
+                    }
+                }
+            }
+            Format::Mcc => match mcc::parse_line(line)? {
+                mcc::Line::Header => (),
+                mcc::Line::TimeCodeRate(fps, drop_frame) => {
+                    let rate = TimeCodeRate(fps, drop_frame);
+                    if state.rate.is_some() && state.rate != Some(rate) {
+                        state.need_caps = true;
+                    }
+                    state.rate = Some(rate);
+                }
+                mcc::Line::Caption(tc, cdp) => {
+                    let rate = match state.rate {
+                        None => return Err("Caption before time code rate".into()),
+                        Some(rate) => rate,
+                    };
+                    buffers.push(frame_buffer(tc.to_frames(rate.0), rate, cdp));
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn output_caps(&self, rate: TimeCodeRate) -> gst::Caps {
+        match self.format {
+            Format::Scc => gst::Caps::new_simple(
+                "closedcaption/x-cea-608",
+                &[("format", &"raw"), ("framerate", &rate.framerate())],
+            ),
+            Format::Mcc => gst::Caps::new_simple(
+                "closedcaption/x-cea-708",
+                &[("format", &"cdp"), ("framerate", &rate.framerate())],
+            ),
+        }
+    }
+
+    // Parses all complete lines, or everything if draining
+    fn handle_data(&self, element: &Element, drain: bool) -> gst::FlowReturn {
+        let mut events = Vec::new();
+        let mut buffers = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+
+            loop {
+                let newline = state.line.iter().position(|&b| b == b'\n');
+                let line = match newline {
+                    Some(pos) => state.line.drain(..(pos + 1)).collect::<Vec<_>>(),
+                    None if drain && !state.line.is_empty() => state.line.split_off(0),
+                    None => break,
+                };
+
+                let line = String::from_utf8_lossy(&line);
+                let mut line_buffers = Vec::new();
+                if let Err(err) = self.parse_line(&mut state, &line, &mut line_buffers) {
+                    gst_warning!(self.cat, obj: element, "Skipping invalid line: {}", err);
+                    continue;
+                }
+                if line_buffers.is_empty() {
+                    continue;
+                }
+
+                if state.need_caps {
+                    state.need_caps = false;
+                    let caps = self.output_caps(state.rate.unwrap());
+                    events.push((buffers.len(), gst::Event::new_caps(&caps).build()));
+                }
+                if state.need_segment {
+                    state.need_segment = false;
+                    let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+                    events.push((buffers.len(), gst::Event::new_segment(&segment).build()));
+                }
+                buffers.extend(line_buffers);
+            }
+        }
+
+        let mut events = events.into_iter().peekable();
+        for (i, buffer) in buffers.into_iter().enumerate() {
+            while events.peek().map(|&(pos, _)| pos == i).unwrap_or(false) {
+                self.srcpad.push_event(events.next().unwrap().1);
+            }
+
+            let flow_ret = self.srcpad.push(buffer);
+            if flow_ret != gst::FlowReturn::Ok {
+                return flow_ret;
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+            self.state
+                .lock()
+                .unwrap()
+                .line
+                .extend_from_slice(map.as_slice());
+        }
+
+        self.handle_data(element, false)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(..) => return true,
+            // Output is timestamped by the time codes, partial lines are dropped
+            EventView::Segment(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.line.clear();
+                state.need_segment = true;
+                return true;
+            }
+            EventView::FlushStop(..) => {
+                self.state.lock().unwrap().line.clear();
+            }
+            EventView::Eos(..) => {
+                let _ = self.handle_data(element, true);
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for CcFileParse {}
+
+impl ElementImpl<Element> for CcFileParse {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::new(self.format);
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct CcFileParseStatic {
+    format: Format,
+}
+
+impl ImplTypeStatic<Element> for CcFileParseStatic {
+    fn get_name(&self) -> &str {
+        match self.format {
+            Format::Scc => "SccParse",
+            Format::Mcc => "MccParse",
+        }
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        CcFileParse::init(element, self.format)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        CcFileParse::class_init(klass, self.format);
+    }
+}
+
+pub fn get_scc_type() -> glib::Type {
+    let sccparse_static = CcFileParseStatic {
+        format: Format::Scc,
+    };
+    register_type(sccparse_static)
+}
+
+pub fn get_mcc_type() -> glib::Type {
+    let mccparse_static = CcFileParseStatic {
+        format: Format::Mcc,
+    };
+    register_type(mccparse_static)
+}
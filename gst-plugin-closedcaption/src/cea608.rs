@@ -0,0 +1,316 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// CEA-608 decoder for the first caption channel (CC1)
+//
+// Byte pairs are interpreted as in a caption display with 15 rows of 32 columns. Characters are
+// written to the non-displayed memory in pop-on mode, which becomes visible with an end of
+// caption command, and directly to the displayed memory in roll-up and paint-on mode.
+// Styles and colors are ignored.
+
+const ROWS: usize = 15;
+const COLUMNS: usize = 32;
+
+const SPECIAL: [char; 16] = [
+    '®', '°', '½', '¿', '™', '¢', '£', '♪', 'à', '\u{a0}', 'è', 'â', 'ê', 'î', 'ô', 'û',
+];
+
+const EXTENDED_SPANISH_FRENCH: [char; 32] = [
+    'Á', 'É', 'Ó', 'Ú', 'Ü', 'ü', '‘', '¡', '*', '\'', '—', '©', '℠', '•', '“', '”', 'À', 'Â',
+    'Ç', 'È', 'Ê', 'Ë', 'ë', 'Î', 'Ï', 'ï', 'Ô', 'Ù', 'ù', 'Û', '«', '»',
+];
+
+const EXTENDED_PORTUGUESE_GERMAN: [char; 32] = [
+    'Ã', 'ã', 'Í', 'Ì', 'ì', 'Ò', 'ò', 'Õ', 'õ', '{', '}', '\\', '^', '_', '|', '~', 'Ä', 'ä',
+    'Ö', 'ö', 'ß', '¥', '¤', '¦', 'Å', 'å', 'Ø', 'ø', '┌', '┐', '└', '┘',
+];
+
+// Rows of the preamble address codes by the lower bits of the first byte and bit 5 of the
+// second byte
+const PAC_ROWS: [[usize; 2]; 8] = [
+    [11, 11],
+    [1, 2],
+    [3, 4],
+    [12, 13],
+    [14, 15],
+    [5, 6],
+    [7, 8],
+    [9, 10],
+];
+
+fn basic_char(b: u8) -> char {
+    match b {
+        0x2a => 'á',
+        0x5c => 'é',
+        0x5e => 'í',
+        0x5f => 'ó',
+        0x60 => 'ú',
+        0x7b => 'ç',
+        0x7c => '÷',
+        0x7d => 'Ñ',
+        0x7e => 'ñ',
+        0x7f => '█',
+        b => b as char,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    PopOn,
+    PaintOn,
+    RollUp(usize),
+}
+
+type Memory = [[Option<char>; COLUMNS]; ROWS];
+
+pub struct Decoder {
+    mode: Mode,
+    displayed: Memory,
+    non_displayed: Memory,
+    row: usize,
+    column: usize,
+    // Control codes are usually sent twice, the repetition is ignored
+    last_control: Option<(u8, u8)>,
+    // Data for the second caption channel is skipped
+    other_channel: bool,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder {
+            mode: Mode::PopOn,
+            displayed: [[None; COLUMNS]; ROWS],
+            non_displayed: [[None; COLUMNS]; ROWS],
+            row: ROWS - 1,
+            column: 0,
+            last_control: None,
+            other_channel: false,
+        }
+    }
+}
+
+impl Decoder {
+    // Text of the displayed memory, one line per non-empty row
+    pub fn text(&self) -> String {
+        self.displayed
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|c| c.unwrap_or(' '))
+                    .collect::<String>()
+                    .trim()
+                    .to_string()
+            })
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn memory(&mut self) -> &mut Memory {
+        match self.mode {
+            Mode::PopOn => &mut self.non_displayed,
+            _ => &mut self.displayed,
+        }
+    }
+
+    fn write(&mut self, c: char) {
+        let (row, column) = (self.row, self.column);
+        self.memory()[row][column] = Some(c);
+        if self.column < COLUMNS - 1 {
+            self.column += 1;
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.column > 0 {
+            self.column -= 1;
+        }
+        let (row, column) = (self.row, self.column);
+        self.memory()[row][column] = None;
+    }
+
+    pub fn decode_pair(&mut self, b1: u8, b2: u8) {
+        let (b1, b2) = (b1 & 0x7f, b2 & 0x7f);
+
+        if b1 == 0 && b2 == 0 {
+            return;
+        }
+
+        if b1 >= 0x10 && b1 <= 0x1f {
+            if self.last_control == Some((b1, b2)) {
+                self.last_control = None;
+                return;
+            }
+            self.last_control = Some((b1, b2));
+
+            self.other_channel = b1 & 0x08 != 0;
+            if !self.other_channel {
+                self.control(b1, b2);
+            }
+            return;
+        }
+
+        self.last_control = None;
+        if self.other_channel || b1 < 0x20 {
+            return;
+        }
+
+        self.write(basic_char(b1));
+        if b2 >= 0x20 {
+            self.write(basic_char(b2));
+        }
+    }
+
+    fn control(&mut self, b1: u8, b2: u8) {
+        match (b1, b2) {
+            (0x11, 0x30...0x3f) => self.write(SPECIAL[(b2 - 0x30) as usize]),
+            // Mid-row style changes are displayed as a space
+            (0x11, 0x20...0x2f) => self.write(' '),
+            (0x12, 0x20...0x3f) => {
+                self.backspace();
+                self.write(EXTENDED_SPANISH_FRENCH[(b2 - 0x20) as usize]);
+            }
+            (0x13, 0x20...0x3f) => {
+                self.backspace();
+                self.write(EXTENDED_PORTUGUESE_GERMAN[(b2 - 0x20) as usize]);
+            }
+            // Field 2 uses 0x15 for the miscellaneous control codes
+            (0x14, 0x20...0x2f) | (0x15, 0x20...0x2f) => self.misc(b2),
+            (0x17, 0x21...0x23) => {
+                self.column = (self.column + (b2 - 0x20) as usize).min(COLUMNS - 1);
+            }
+            (0x10...0x17, 0x40...0x7f) => {
+                self.row = PAC_ROWS[(b1 & 0x07) as usize][((b2 & 0x20) >> 5) as usize] - 1;
+                self.column = if b2 & 0x10 != 0 {
+                    ((b2 & 0x0e) >> 1) as usize * 4
+                } else {
+                    0
+                };
+            }
+            _ => (),
+        }
+    }
+
+    fn misc(&mut self, b2: u8) {
+        match b2 {
+            // Resume caption loading
+            0x20 => self.mode = Mode::PopOn,
+            // Backspace
+            0x21 => self.backspace(),
+            // Delete to end of row
+            0x24 => {
+                let (row, column) = (self.row, self.column);
+                for c in &mut self.memory()[row][column..] {
+                    *c = None;
+                }
+            }
+            // Roll-up captions with 2, 3 or 4 rows
+            0x25...0x27 => {
+                match self.mode {
+                    Mode::RollUp(..) => (),
+                    _ => {
+                        self.displayed = [[None; COLUMNS]; ROWS];
+                        self.non_displayed = [[None; COLUMNS]; ROWS];
+                    }
+                }
+                self.mode = Mode::RollUp((b2 - 0x23) as usize);
+                self.column = 0;
+            }
+            // Resume direct captioning
+            0x29 => self.mode = Mode::PaintOn,
+            // Erase displayed memory
+            0x2c => self.displayed = [[None; COLUMNS]; ROWS],
+            // Carriage return
+            0x2d => {
+                if let Mode::RollUp(rows) = self.mode {
+                    let top = (self.row + 1).saturating_sub(rows);
+                    for row in top..self.row {
+                        self.displayed[row] = self.displayed[row + 1];
+                    }
+                    self.displayed[self.row] = [None; COLUMNS];
+                    if top > 0 {
+                        self.displayed[top - 1] = [None; COLUMNS];
+                    }
+                }
+                self.column = 0;
+            }
+            // Erase non-displayed memory
+            0x2e => self.non_displayed = [[None; COLUMNS]; ROWS],
+            // End of caption
+            0x2f => {
+                ::std::mem::swap(&mut self.displayed, &mut self.non_displayed);
+                self.mode = Mode::PopOn;
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(decoder: &mut Decoder, pairs: &[(u8, u8)]) {
+        for &(b1, b2) in pairs {
+            decoder.decode_pair(b1, b2);
+        }
+    }
+
+    #[test]
+    fn test_pop_on() {
+        let mut decoder = Decoder::default();
+
+        // RCL twice, ENM, PAC row 15, "Hello", EOC
+        decode(
+            &mut decoder,
+            &[
+                (0x14, 0x20),
+                (0x14, 0x20),
+                (0x14, 0x2e),
+                (0x14, 0x70),
+                (b'H', b'e'),
+                (b'l', b'l'),
+                (b'o', 0x80),
+            ],
+        );
+        assert_eq!(decoder.text(), "");
+
+        decode(&mut decoder, &[(0x14, 0x2f)]);
+        assert_eq!(decoder.text(), "Hello");
+
+        // Extended character replaces the previous one
+        decode(
+            &mut decoder,
+            &[(0x14, 0x2e), (0x14, 0x70), (b'A', b'e'), (0x13, 0x31), (0x14, 0x2f)],
+        );
+        assert_eq!(decoder.text(), "Aä");
+
+        decode(&mut decoder, &[(0x14, 0x2c)]);
+        assert_eq!(decoder.text(), "");
+    }
+
+    #[test]
+    fn test_roll_up() {
+        let mut decoder = Decoder::default();
+
+        // RU2, "ab", CR, "cd", CR, "ef"
+        decode(&mut decoder, &[(0x14, 0x25), (b'a', b'b')]);
+        assert_eq!(decoder.mode, Mode::RollUp(2));
+        assert_eq!(decoder.text(), "ab");
+
+        decode(&mut decoder, &[(0x14, 0x2d), (b'c', b'd')]);
+        assert_eq!(decoder.text(), "ab\ncd");
+
+        decode(&mut decoder, &[(0x14, 0x2d), (b'e', b'f')]);
+        assert_eq!(decoder.text(), "cd\nef");
+
+        // Second channel is ignored
+        decode(&mut decoder, &[(0x1c, 0x2d), (b'x', b'y'), (0x14, 0x2d)]);
+        assert_eq!(decoder.text(), "ef");
+    }
+}
@@ -0,0 +1,53 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate glib;
+#[macro_use]
+extern crate gst_plugin;
+#[macro_use]
+extern crate gstreamer as gst;
+
+use gst_plugin::registration::*;
+
+mod cea608;
+mod mcc;
+mod scc;
+mod subtitle;
+mod timecode;
+
+mod cc608tott;
+mod ccconverter;
+mod ccfileenc;
+mod ccfileparse;
+mod subparse;
+mod webvttenc;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    ElementRegistration::new(plugin)
+        .element("cc608tott", RANK_NONE, cc608tott::get_type())
+        .element("ccconverter", RANK_NONE, ccconverter::get_type())
+        .element("sccparse", RANK_NONE, ccfileparse::get_scc_type())
+        .element("mccparse", RANK_NONE, ccfileparse::get_mcc_type())
+        .element("sccenc", RANK_NONE, ccfileenc::get_scc_type())
+        .element("mccenc", RANK_NONE, ccfileenc::get_mcc_type())
+        .element("rssrtparse", RANK_NONE, subparse::get_srt_type())
+        .element("rswebvttparse", RANK_NONE, subparse::get_webvtt_type())
+        .element("rswebvttenc", RANK_NONE, webvttenc::get_type())
+        .register()
+}
+
+plugin_define!(
+    "rsclosedcaption",
+    "Rust Closed Caption Plugin",
+    plugin_init,
+    "MIT/X11",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2018-01-22"
+);
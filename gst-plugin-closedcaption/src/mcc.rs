@@ -0,0 +1,256 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// MacCaption MCC files
+//
+// After a header with comments and key/value metadata, every caption line consists of a time
+// code and the SMPTE 334 ANC packet of one frame: DID 0x61, SDID 0x01, the length, a CEA-708
+// CDP and a checksum. The packet is hex encoded, with letters standing for common byte
+// sequences.
+
+use timecode::TimeCode;
+
+const FORMAT_HEADER: &str = "File Format=MacCaption_MCC V1.0";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line {
+    Header,
+    // Nominal frame rate and drop-frame flag
+    TimeCodeRate(u32, bool),
+    Caption(TimeCode, Vec<u8>),
+}
+
+fn expand(c: u8) -> Option<&'static [u8]> {
+    const FA: [u8; 27] = [
+        0xfa, 0x00, 0x00, 0xfa, 0x00, 0x00, 0xfa, 0x00, 0x00, 0xfa, 0x00, 0x00, 0xfa, 0x00, 0x00,
+        0xfa, 0x00, 0x00, 0xfa, 0x00, 0x00, 0xfa, 0x00, 0x00, 0xfa, 0x00, 0x00,
+    ];
+
+    match c {
+        b'G'...b'O' => Some(&FA[..3 * (c - b'F') as usize]),
+        b'P' => Some(&[0xfb, 0x80, 0x80]),
+        b'Q' => Some(&[0xfc, 0x80, 0x80]),
+        b'R' => Some(&[0xfd, 0x80, 0x80]),
+        b'S' => Some(&[0x96, 0x69]),
+        b'T' => Some(&[0x61, 0x01]),
+        b'U' => Some(&[0xe1, 0x00, 0x00, 0x00]),
+        b'Z' => Some(&[0x00]),
+        _ => None,
+    }
+}
+
+fn decode_payload(s: &str) -> Result<Vec<u8>, String> {
+    let s = s.as_bytes();
+    let mut data = Vec::new();
+    let mut i = 0;
+
+    while i < s.len() {
+        if let Some(bytes) = expand(s[i]) {
+            data.extend_from_slice(bytes);
+            i += 1;
+            continue;
+        }
+
+        let byte = if i + 2 <= s.len() {
+            ::std::str::from_utf8(&s[i..(i + 2)])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match byte {
+            Some(byte) => data.push(byte),
+            None => return Err(format!("Invalid payload at position {}", i)),
+        }
+        i += 2;
+    }
+
+    Ok(data)
+}
+
+fn encode_payload(data: &[u8], out: &mut String) {
+    let mut i = 0;
+
+    while i < data.len() {
+        let rest = &data[i..];
+
+        // Runs of padding first as they have the longest codes
+        let mut fa = 0;
+        while fa < 9 && rest[(3 * fa)..].starts_with(&[0xfa, 0x00, 0x00]) {
+            fa += 1;
+        }
+        if fa > 0 {
+            out.push((b'F' + fa as u8) as char);
+            i += 3 * fa;
+            continue;
+        }
+
+        match b"PQRSTUZ"
+            .iter()
+            .find(|&&c| rest.starts_with(expand(c).unwrap()))
+        {
+            Some(&c) => {
+                out.push(c as char);
+                i += expand(c).unwrap().len();
+            }
+            None => {
+                out.push_str(&format!("{:02X}", rest[0]));
+                i += 1;
+            }
+        }
+    }
+}
+
+pub fn parse_line(line: &str) -> Result<Line, String> {
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with("//") || line == FORMAT_HEADER {
+        return Ok(Line::Header);
+    }
+
+    if line.starts_with("Time Code Rate=") {
+        let rate = &line["Time Code Rate=".len()..];
+        let (rate, drop_frame) = if rate.ends_with("DF") {
+            (&rate[..(rate.len() - 2)], true)
+        } else {
+            (rate, false)
+        };
+        return match rate.parse::<u32>() {
+            Ok(fps) if fps > 0 && (!drop_frame || fps % 30 == 0) => {
+                Ok(Line::TimeCodeRate(fps, drop_frame))
+            }
+            _ => Err(format!("Invalid time code rate {:?}", line)),
+        };
+    }
+
+    // Any other metadata
+    if line.contains('=') {
+        return Ok(Line::Header);
+    }
+
+    let mut parts = line.split_whitespace();
+    let tc = TimeCode::parse(parts.next().unwrap())?;
+    let data = match parts.next() {
+        None => return Err(format!("Missing payload in {:?}", line)),
+        Some(payload) => decode_payload(payload)?,
+    };
+
+    if data.len() < 3 || data[0] != 0x61 || data[1] != 0x01 || data.len() < 3 + data[2] as usize {
+        return Err(format!("Invalid ANC packet in {:?}", line));
+    }
+
+    Ok(Line::Caption(tc, data[3..(3 + data[2] as usize)].to_vec()))
+}
+
+pub fn write_header(fps: u32, drop_frame: bool, out: &mut String) {
+    out.push_str(FORMAT_HEADER);
+    out.push_str("\n\n");
+    out.push_str(
+        "///////////////////////////////////////////////////////////////////////////////////\n",
+    );
+    out.push_str("// Computer Prompting and Captioning Company\n");
+    out.push_str("// Ancillary Data Packet Transfer File\n");
+    out.push_str("//\n");
+    out.push_str("// Permission to generate this format is granted provided that\n");
+    out.push_str("//   1. This ANC Transfer file format is used on an as-is basis and no warranty is given, and\n");
+    out.push_str(
+        "//   2. This entire descriptive information text is included in a generated .mcc file.\n",
+    );
+    out.push_str("//\n");
+    out.push_str("// General file format:\n");
+    out.push_str("//   HH:MM:SS:FF(tab)[Hexadecimal ANC data in groups of 2 characters]\n");
+    out.push_str("//     Hexadecimal data starts with the Ancillary Data Packet DID (Data ID defined in S291M)\n");
+    out.push_str("//       and concludes with the Check Sum following the User Data Words.\n");
+    out.push_str(
+        "//     Each time code line must contain at most one complete ancillary data packet.\n",
+    );
+    out.push_str("//     To transfer additional ANC Data successive lines may contain identical time code.\n");
+    out.push_str("//     Time Code Rate=[24, 25, 30, 30DF, 50, 60]\n");
+    out.push_str("//\n");
+    out.push_str("//   ANC data bytes may be represented by one ASCII character according to the following schema:\n");
+    out.push_str("//     G  FAh 00h 00h\n");
+    out.push_str("//     H  2 x (FAh 00h 00h)\n");
+    out.push_str("//     I  3 x (FAh 00h 00h)\n");
+    out.push_str("//     J  4 x (FAh 00h 00h)\n");
+    out.push_str("//     K  5 x (FAh 00h 00h)\n");
+    out.push_str("//     L  6 x (FAh 00h 00h)\n");
+    out.push_str("//     M  7 x (FAh 00h 00h)\n");
+    out.push_str("//     N  8 x (FAh 00h 00h)\n");
+    out.push_str("//     O  9 x (FAh 00h 00h)\n");
+    out.push_str("//     P  FBh 80h 80h\n");
+    out.push_str("//     Q  FCh 80h 80h\n");
+    out.push_str("//     R  FDh 80h 80h\n");
+    out.push_str("//     S  96h 69h\n");
+    out.push_str("//     T  61h 01h\n");
+    out.push_str("//     U  E1h 00h 00h 00h\n");
+    out.push_str("//     Z  00h\n");
+    out.push_str("//\n");
+    out.push_str(
+        "///////////////////////////////////////////////////////////////////////////////////\n",
+    );
+    out.push_str("\n");
+    out.push_str(&format!(
+        "Time Code Rate={}{}\n\n",
+        fps,
+        if drop_frame { "DF" } else { "" }
+    ));
+}
+
+pub fn write_line(tc: &TimeCode, cdp: &[u8], out: &mut String) {
+    let mut data = Vec::with_capacity(cdp.len() + 4);
+    data.extend_from_slice(&[0x61, 0x01, cdp.len() as u8]);
+    data.extend_from_slice(cdp);
+    let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    data.push(0u8.wrapping_sub(sum));
+
+    out.push_str(&tc.to_string());
+    out.push('\t');
+    encode_payload(&data, out);
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload() {
+        let data = [
+            0x61, 0x01, 0x52, 0x96, 0x69, 0x52, 0x4f, 0xfa, 0x00, 0x00, 0xfa, 0x00, 0x00, 0xfc,
+            0x80, 0x80, 0x12, 0x00,
+        ];
+        let mut out = String::new();
+        encode_payload(&data, &mut out);
+        assert_eq!(out, "T52S524FHQ12Z");
+        assert_eq!(decode_payload(&out).unwrap(), data);
+        assert!(decode_payload("T5").is_err());
+    }
+
+    #[test]
+    fn test_line() {
+        let tc = TimeCode::parse("00:00:01;15").unwrap();
+        let cdp = [0x96, 0x69, 0x08, 0x4f, 0x43, 0x00, 0x00, 0x74];
+
+        let mut out = String::new();
+        write_line(&tc, &cdp, &mut out);
+        assert_eq!(parse_line(&out).unwrap(), Line::Caption(tc, cdp.to_vec()));
+
+        let mut out = String::new();
+        write_header(30, true, &mut out);
+        let lines = out
+            .lines()
+            .map(parse_line)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(lines.contains(&Line::TimeCodeRate(30, true)));
+        assert!(lines.iter().all(|line| match *line {
+            Line::Header | Line::TimeCodeRate(..) => true,
+            _ => false,
+        }));
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Scenarist SCC files
+//
+// After the header line, every caption line consists of a 29.97 fps time code and a list of
+// CEA-608 byte pairs of the first field as 4 hex digits each. The byte pairs are sent in
+// consecutive frames starting at the time code. Lines are separated by empty lines.
+
+use timecode::TimeCode;
+
+pub const HEADER: &str = "Scenarist_SCC V1.0";
+
+// Nominal frame rate of the time codes
+pub const FPS: u32 = 30;
+
+pub fn parse_line(line: &str) -> Result<Option<(TimeCode, Vec<[u8; 2]>)>, String> {
+    let line = line.trim();
+    if line.is_empty() || line == HEADER {
+        return Ok(None);
+    }
+
+    let mut words = line.split_whitespace();
+    let tc = TimeCode::parse(words.next().unwrap())?;
+
+    let pairs = words
+        .map(|word| {
+            if word.len() != 4 {
+                return Err(format!("Invalid byte pair {:?}", word));
+            }
+            let b1 = u8::from_str_radix(&word[0..2], 16);
+            let b2 = u8::from_str_radix(&word[2..4], 16);
+            match (b1, b2) {
+                (Ok(b1), Ok(b2)) => Ok([b1, b2]),
+                _ => Err(format!("Invalid byte pair {:?}", word)),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Some((tc, pairs)))
+}
+
+pub fn write_line(tc: &TimeCode, pairs: &[[u8; 2]], out: &mut String) {
+    out.push_str(&tc.to_string());
+    out.push('\t');
+    let words = pairs
+        .iter()
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<_>>();
+    out.push_str(&words.join(" "));
+    out.push_str("\n\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line() {
+        let (tc, pairs) = parse_line("00:00:01;15\t9420 9420 c8e5 ecec\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(tc, TimeCode::parse("00:00:01;15").unwrap());
+        assert_eq!(
+            pairs,
+            [[0x94, 0x20], [0x94, 0x20], [0xc8, 0xe5], [0xec, 0xec]]
+        );
+
+        let mut out = String::new();
+        write_line(&tc, &pairs, &mut out);
+        assert_eq!(out, "00:00:01;15\t9420 9420 c8e5 ecec\n\n");
+
+        assert_eq!(parse_line(HEADER).unwrap(), None);
+        assert!(parse_line("00:00:01;15\t94").is_err());
+    }
+}
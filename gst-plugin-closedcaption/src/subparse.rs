@@ -0,0 +1,456 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+
+use subtitle;
+
+// Splits subtitle files into lines and outputs one text buffer per cue with the cue timing.
+// Markup is removed by default, or converted to Pango markup with italic, bold and underline.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Srt,
+    WebVtt,
+}
+
+impl Format {
+    fn media_type(&self) -> &'static str {
+        match *self {
+            Format::Srt => "application/x-subtitle",
+            Format::WebVtt => "application/x-subtitle-vtt",
+        }
+    }
+}
+
+const DEFAULT_PRESERVE_MARKUP: bool = false;
+
+static PROPERTIES: [Property; 1] = [Property::Boolean(
+    "preserve-markup",
+    "Preserve Markup",
+    "Output italic, bold and underline as Pango markup instead of plain text",
+    DEFAULT_PRESERVE_MARKUP,
+    PropertyMutability::ReadWrite,
+)];
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    preserve_markup: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            preserve_markup: DEFAULT_PRESERVE_MARKUP,
+        }
+    }
+}
+
+struct State {
+    // Incomplete last line
+    line: Vec<u8>,
+    parser: subtitle::Parser,
+    // Whether the current caps are Pango markup
+    markup: Option<bool>,
+    need_segment: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            line: Vec::new(),
+            parser: subtitle::Parser::default(),
+            markup: None,
+            need_segment: true,
+        }
+    }
+}
+
+fn text_caps(markup: bool) -> gst::Caps {
+    let format = if markup { "pango-markup" } else { "utf8" };
+    gst::Caps::new_simple("text/x-raw", &[("format", &format)])
+}
+
+enum Item {
+    Event(gst::Event),
+    Buffer(gst::Buffer),
+}
+
+struct SubParse {
+    cat: gst::DebugCategory,
+    format: Format,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl SubParse {
+    fn new(_element: &Element, format: Format, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rssubparse",
+                gst::DebugColorFlags::empty(),
+                "Rust subtitle parser",
+            ),
+            format: format,
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass, format: Format) {
+        match format {
+            Format::Srt => klass.set_metadata(
+                "SRT parser",
+                "Codec/Parser/Subtitle",
+                "Parses SubRip subtitle files",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            ),
+            Format::WebVtt => klass.set_metadata(
+                "WebVTT parser",
+                "Codec/Parser/Subtitle",
+                "Parses WebVTT subtitle files",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            ),
+        }
+
+        let caps = gst::Caps::new_simple(format.media_type(), &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple(
+            "text/x-raw",
+            &[("format", &gst::List::new(&[&"utf8", &"pango-markup"]))],
+        );
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element, format: Format) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            SubParse::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |subparse, element| subparse.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            SubParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |subparse, element| subparse.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            SubParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |subparse, element| subparse.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            SubParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |subparse, element| subparse.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            SubParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |subparse, element| subparse.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, format, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let subparse = element.get_impl().downcast_ref::<SubParse>().unwrap();
+        element.catch_panic(fallback, |element| f(subparse, element))
+    }
+
+    // Parses all complete lines, or everything if draining
+    fn handle_data(&self, element: &Element, drain: bool) -> gst::FlowReturn {
+        let markup = self.settings.lock().unwrap().preserve_markup;
+
+        let mut items = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+
+            let mut cues = Vec::new();
+            loop {
+                let newline = state.line.iter().position(|&b| b == b'\n');
+                let line = match newline {
+                    Some(pos) => state.line.drain(..(pos + 1)).collect::<Vec<_>>(),
+                    None if drain && !state.line.is_empty() => state.line.split_off(0),
+                    None => break,
+                };
+
+                if let Some(cue) = state.parser.push_line(&String::from_utf8_lossy(&line)) {
+                    cues.push(cue);
+                }
+            }
+            if drain {
+                cues.extend(state.parser.finish());
+            }
+
+            for cue in cues {
+                gst_debug!(self.cat, obj: element, "Parsed cue {:?}", cue);
+
+                if state.markup != Some(markup) {
+                    state.markup = Some(markup);
+                    let caps = text_caps(markup);
+                    items.push(Item::Event(gst::Event::new_caps(&caps).build()));
+                }
+                if state.need_segment {
+                    state.need_segment = false;
+                    let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+                    items.push(Item::Event(gst::Event::new_segment(&segment).build()));
+                }
+
+                let text = if markup {
+                    subtitle::to_pango_markup(&cue.text)
+                } else {
+                    subtitle::strip_markup(&cue.text)
+                };
+                let mut buffer = gst::Buffer::from_mut_slice(text.into_bytes()).unwrap();
+                {
+                    let buffer = buffer.get_mut().unwrap();
+                    buffer.set_pts(gst::ClockTime(Some(cue.start)));
+                    buffer.set_duration(gst::ClockTime(Some(cue.end - cue.start)));
+                }
+                items.push(Item::Buffer(buffer));
+            }
+        }
+
+        // Events and buffers in output order
+        for item in items {
+            match item {
+                Item::Event(event) => {
+                    self.srcpad.push_event(event);
+                }
+                Item::Buffer(buffer) => {
+                    let flow_ret = self.srcpad.push(buffer);
+                    if flow_ret != gst::FlowReturn::Ok {
+                        return flow_ret;
+                    }
+                }
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+            self.state
+                .lock()
+                .unwrap()
+                .line
+                .extend_from_slice(map.as_slice());
+        }
+
+        self.handle_data(element, false)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(..) => return true,
+            // Output is timestamped by the cue timings, partial cues are dropped
+            EventView::Segment(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.line.clear();
+                state.parser = subtitle::Parser::default();
+                state.need_segment = true;
+                return true;
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.line.clear();
+                state.parser = subtitle::Parser::default();
+            }
+            EventView::Eos(..) => {
+                let _ = self.handle_data(element, true);
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for SubParse {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::Boolean("preserve-markup", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.preserve_markup = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::Boolean("preserve-markup", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.preserve_markup.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for SubParse {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct SubParseStatic {
+    format: Format,
+}
+
+impl ImplTypeStatic<Element> for SubParseStatic {
+    fn get_name(&self) -> &str {
+        match self.format {
+            Format::Srt => "RsSrtParse",
+            Format::WebVtt => "RsWebVttParse",
+        }
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        SubParse::init(element, self.format)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        SubParse::class_init(klass, self.format);
+    }
+}
+
+pub fn get_srt_type() -> glib::Type {
+    let srtparse_static = SubParseStatic {
+        format: Format::Srt,
+    };
+    register_type(srtparse_static)
+}
+
+pub fn get_webvtt_type() -> glib::Type {
+    let webvttparse_static = SubParseStatic {
+        format: Format::WebVtt,
+    };
+    register_type(webvttparse_static)
+}
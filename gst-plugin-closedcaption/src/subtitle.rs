@@ -0,0 +1,286 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// SubRip (SRT) and WebVTT cues
+//
+// Both formats consist of blocks separated by empty lines. Cue blocks contain a timing line
+// with "-->", optionally preceded by a cue number or identifier, and are followed by the cue
+// text. All other blocks, like the WebVTT header, comments and style blocks, are ignored.
+
+use std::mem;
+
+const MSECOND_VAL: u64 = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub start: u64,
+    pub end: u64,
+    pub text: String,
+}
+
+// Parses "[HH:]MM:SS.mmm" or "HH:MM:SS,mmm" into nanoseconds
+fn parse_timestamp(s: &str) -> Option<u64> {
+    let parts = s.split(':').collect::<Vec<_>>();
+    let (hours, minutes, seconds) = match parts.len() {
+        2 => ("0", parts[0], parts[1]),
+        3 => (parts[0], parts[1], parts[2]),
+        _ => return None,
+    };
+
+    let mut seconds = seconds.splitn(2, |c| c == '.' || c == ',');
+    let (seconds, millis) = (seconds.next()?, seconds.next()?);
+    if millis.is_empty() || millis.len() > 3 || !millis.chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+
+    let hours = hours.parse::<u64>().ok()?;
+    let minutes = minutes.parse::<u64>().ok()?;
+    let seconds = seconds.parse::<u64>().ok()?;
+    let millis = millis.parse::<u64>().ok()? * 10u64.pow(3 - millis.len() as u32);
+    if minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+
+    Some((((hours * 60 + minutes) * 60 + seconds) * 1000 + millis) * MSECOND_VAL)
+}
+
+// Parses the start and end of a timing line, WebVTT cue settings are ignored
+fn parse_timing_line(line: &str) -> Option<(u64, u64)> {
+    let mut parts = line.splitn(2, "-->");
+    let start = parse_timestamp(parts.next()?.trim())?;
+    let end = parse_timestamp(parts.next()?.split_whitespace().next()?)?;
+
+    if end < start {
+        return None;
+    }
+
+    Some((start, end))
+}
+
+#[derive(Debug, Default)]
+pub struct Parser {
+    timing: Option<(u64, u64)>,
+    text: Vec<String>,
+}
+
+impl Parser {
+    // Returns the cue completed by this line, if any
+    pub fn push_line(&mut self, line: &str) -> Option<Cue> {
+        let line = line.trim_left_matches('\u{feff}')
+            .trim_right_matches(|c| c == '\r' || c == '\n');
+
+        if line.trim().is_empty() {
+            return self.finish();
+        }
+
+        if self.timing.is_none() {
+            if line.contains("-->") {
+                self.timing = parse_timing_line(line);
+            }
+        } else {
+            self.text.push(line.to_string());
+        }
+
+        None
+    }
+
+    // Returns the last cue if it was not followed by an empty line
+    pub fn finish(&mut self) -> Option<Cue> {
+        let text = mem::replace(&mut self.text, Vec::new()).join("\n");
+
+        self.timing.take().map(|(start, end)| Cue {
+            start: start,
+            end: end,
+            text: text,
+        })
+    }
+}
+
+fn unescape(text: &str, out: &mut String) {
+    let mut rest = text;
+
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        let entity = [
+            ("&amp;", '&'),
+            ("&lt;", '<'),
+            ("&gt;", '>'),
+            ("&nbsp;", '\u{a0}'),
+            ("&lrm;", '\u{200e}'),
+            ("&rlm;", '\u{200f}'),
+        ].iter()
+            .find(|&&(entity, _)| rest.starts_with(entity))
+            .cloned();
+
+        match entity {
+            Some((entity, c)) => {
+                out.push(c);
+                rest = &rest[entity.len()..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+}
+
+pub fn escape(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+}
+
+// Splits cue text into text and tags, calling the closure for every tag with its name and
+// whether it is a closing tag
+fn convert_markup<F: FnMut(&str, bool, &mut String)>(text: &str, mut tag: F) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+
+    while let Some(pos) = rest.find('<') {
+        let end = match rest[pos..].find('>') {
+            None => break,
+            Some(end) => pos + end,
+        };
+
+        unescape(&rest[..pos], &mut out);
+
+        // Tag names end at whitespace, attributes or WebVTT classes
+        let content = &rest[(pos + 1)..end];
+        let (closing, content) = if content.starts_with('/') {
+            (true, &content[1..])
+        } else {
+            (false, content)
+        };
+        let name = content
+            .split(|c: char| c.is_whitespace() || c == '.')
+            .next()
+            .unwrap_or("");
+        tag(&name.to_lowercase(), closing, &mut out);
+
+        rest = &rest[(end + 1)..];
+    }
+
+    unescape(rest, &mut out);
+
+    out
+}
+
+// Plain text without any markup
+pub fn strip_markup(text: &str) -> String {
+    convert_markup(text, |_, _, _| ())
+}
+
+// Pango markup with italic, bold and underline, all other tags are removed
+pub fn to_pango_markup(text: &str) -> String {
+    let escaped = convert_markup(text, |name, closing, out| match name {
+        "i" | "b" | "u" => {
+            // Placeholders that are not changed by escaping
+            out.push(if closing { '\u{2}' } else { '\u{1}' });
+            out.push_str(name);
+        }
+        _ => (),
+    });
+
+    let mut out = String::new();
+    escape(&escaped, &mut out);
+
+    out.replace("\u{1}i", "<i>")
+        .replace("\u{1}b", "<b>")
+        .replace("\u{1}u", "<u>")
+        .replace("\u{2}i", "</i>")
+        .replace("\u{2}b", "</b>")
+        .replace("\u{2}u", "</u>")
+}
+
+pub fn format_timestamp(ns: u64) -> String {
+    let millis = ns / MSECOND_VAL;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        (millis / 60_000) % 60,
+        (millis / 1000) % 60,
+        millis % 1000
+    )
+}
+
+pub fn write_cue(start: u64, end: u64, text: &str, out: &mut String) {
+    out.push_str(&format!(
+        "{} --> {}\n",
+        format_timestamp(start),
+        format_timestamp(end)
+    ));
+    // Empty lines would end the cue
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Vec<Cue> {
+        let mut parser = Parser::default();
+        let mut cues = s.lines()
+            .filter_map(|line| parser.push_line(line))
+            .collect::<Vec<_>>();
+        cues.extend(parser.finish());
+        cues
+    }
+
+    #[test]
+    fn test_parse() {
+        let srt = "1\r\n00:00:01,500 --> 00:00:03,000\r\nHello\r\n<i>World</i>\r\n\r\n\
+                   2\r\n00:01:00,000 --> 00:01:02,250\r\nBye";
+        let cues = parse(srt);
+        assert_eq!(
+            cues,
+            vec![
+                Cue {
+                    start: 1_500_000_000,
+                    end: 3_000_000_000,
+                    text: "Hello\n<i>World</i>".into(),
+                },
+                Cue {
+                    start: 60_000_000_000,
+                    end: 62_250_000_000,
+                    text: "Bye".into(),
+                },
+            ]
+        );
+
+        let vtt = "\u{feff}WEBVTT\n\nNOTE a comment\n\nintro\n00:01.000 --> 00:02.000 align:start\n\
+                   <v Bob>Tom &amp; <b>Jerry</b>\n";
+        let cues = parse(vtt);
+        assert_eq!(cues.len(), 1);
+        assert_eq!((cues[0].start, cues[0].end), (1_000_000_000, 2_000_000_000));
+        assert_eq!(strip_markup(&cues[0].text), "Tom & Jerry");
+        assert_eq!(to_pango_markup(&cues[0].text), "Tom &amp; <b>Jerry</b>");
+
+        assert_eq!(parse("00:01.000 --> 00:00.500\nInvalid\n"), vec![]);
+    }
+
+    #[test]
+    fn test_write() {
+        let mut out = String::new();
+        write_cue(3_723_004_000_000, 3_724_000_000_000, "a\n\nb", &mut out);
+        assert_eq!(out, "01:02:03.004 --> 01:02:04.000\na\nb\n\n");
+    }
+}
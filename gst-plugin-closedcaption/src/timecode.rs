@@ -0,0 +1,157 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+
+use gst;
+
+// SMPTE time codes as used by the caption file formats. Drop-frame time codes skip the first
+// frame numbers of every minute except every tenth minute, 2 frames at 30 fps and 4 at 60 fps.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeCode {
+    pub hours: u32,
+    pub minutes: u32,
+    pub seconds: u32,
+    pub frames: u32,
+    pub drop_frame: bool,
+}
+
+impl TimeCode {
+    pub fn parse(s: &str) -> Result<TimeCode, String> {
+        let s = s.trim();
+        if s.len() != 11 || !s.is_ascii() {
+            return Err(format!("Invalid time code {:?}", s));
+        }
+
+        let drop_frame = match &s[8..9] {
+            ";" | "." => true,
+            ":" => false,
+            _ => return Err(format!("Invalid time code {:?}", s)),
+        };
+
+        let mut values = [0u32; 4];
+        for (i, value) in values.iter_mut().enumerate() {
+            let part = &s[(3 * i)..(3 * i + 2)];
+            *value = part
+                .parse::<u32>()
+                .map_err(|_| format!("Invalid time code {:?}", s))?;
+        }
+
+        Ok(TimeCode {
+            hours: values[0],
+            minutes: values[1],
+            seconds: values[2],
+            frames: values[3],
+            drop_frame: drop_frame,
+        })
+    }
+
+    // Number of the frame since 00:00:00:00, fps is the nominal integer frame rate
+    pub fn to_frames(&self, fps: u32) -> u64 {
+        let fps = fps as u64;
+        let total_minutes = 60 * self.hours as u64 + self.minutes as u64;
+        let frames = (60 * total_minutes + self.seconds as u64) * fps + self.frames as u64;
+
+        if self.drop_frame {
+            frames - (fps / 15) * (total_minutes - total_minutes / 10)
+        } else {
+            frames
+        }
+    }
+
+    pub fn from_frames(frames: u64, fps: u32, drop_frame: bool) -> TimeCode {
+        let fps = fps as u64;
+        let mut frames = frames;
+
+        if drop_frame {
+            let dropped = fps / 15;
+            let frames_per_minute = 60 * fps - dropped;
+            let frames_per_10_minutes = 600 * fps - 9 * dropped;
+
+            let tens = frames / frames_per_10_minutes;
+            let rem = frames % frames_per_10_minutes;
+            frames += 9 * dropped * tens;
+            if rem >= dropped {
+                frames += dropped * ((rem - dropped) / frames_per_minute);
+            }
+        }
+
+        TimeCode {
+            hours: (frames / (3600 * fps)) as u32,
+            minutes: ((frames / (60 * fps)) % 60) as u32,
+            seconds: ((frames / fps) % 60) as u32,
+            frames: (frames % fps) as u32,
+            drop_frame: drop_frame,
+        }
+    }
+}
+
+impl fmt::Display for TimeCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{}{:02}",
+            self.hours,
+            self.minutes,
+            self.seconds,
+            if self.drop_frame { ';' } else { ':' },
+            self.frames
+        )
+    }
+}
+
+// Conversion between frame numbers and running time for the actual frame rate
+pub fn frames_to_time(frames: u64, fps: gst::Fraction) -> u64 {
+    let (numer, denom) = (*fps.numer() as u64, *fps.denom() as u64);
+    frames / numer * denom * gst::SECOND_VAL + (frames % numer) * denom * gst::SECOND_VAL / numer
+}
+
+pub fn time_to_frames(time: u64, fps: gst::Fraction) -> u64 {
+    let (numer, denom) = (*fps.numer() as u64, *fps.denom() as u64);
+    let unit = denom * gst::SECOND_VAL;
+    // Rounded to the nearest frame
+    time / unit * numer + ((time % unit) * numer + unit / 2) / unit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_frame() {
+        let tc = TimeCode::parse("00:01:00;02").unwrap();
+        assert!(tc.drop_frame);
+        assert_eq!(tc.to_frames(30), 1800);
+        assert_eq!(TimeCode::from_frames(1800, 30, true), tc);
+        assert_eq!(
+            TimeCode::from_frames(1799, 30, true).to_string(),
+            "00:00:59;29"
+        );
+        assert_eq!(
+            TimeCode::from_frames(17982, 30, true).to_string(),
+            "00:10:00;00"
+        );
+
+        for frames in 0..40000 {
+            assert_eq!(
+                TimeCode::from_frames(frames, 30, true).to_frames(30),
+                frames
+            );
+            assert_eq!(
+                TimeCode::from_frames(frames, 60, true).to_frames(60),
+                frames
+            );
+        }
+
+        let tc = TimeCode::parse("01:02:03:04").unwrap();
+        assert_eq!(tc.to_string(), "01:02:03:04");
+        assert_eq!(tc.to_frames(25), ((3600 + 2 * 60 + 3) * 25 + 4) as u64);
+        assert!(TimeCode::parse("01:02:03-04").is_err());
+    }
+}
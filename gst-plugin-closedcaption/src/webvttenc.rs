@@ -0,0 +1,549 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::u64;
+
+use subtitle;
+
+// Writes every text buffer as a WebVTT cue, buffers without duration are shown for
+// DEFAULT_CUE_DURATION. The header is part of the first output buffer.
+//
+// With a fragment duration, e.g. for HLS subtitle renditions, the output is instead one
+// complete WebVTT document per fragment, starting at multiples of the fragment duration. Cues
+// crossing a fragment boundary are split at it. A fragment is output once a cue or gap after
+// it arrives, or on EOS, and fragments without cues are output as empty documents.
+
+const HEADER: &str = "WEBVTT\n\n";
+
+const DEFAULT_CUE_DURATION: u64 = 2 * gst::SECOND_VAL;
+
+const DEFAULT_FRAGMENT_DURATION: u64 = 0;
+
+static PROPERTIES: [Property; 1] = [Property::UInt64(
+    "fragment-duration",
+    "Fragment Duration",
+    "Duration of the output documents in nanoseconds (0 = a single document)",
+    (0, u64::MAX),
+    DEFAULT_FRAGMENT_DURATION,
+    PropertyMutability::ReadWrite,
+)];
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    fragment_duration: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            fragment_duration: DEFAULT_FRAGMENT_DURATION,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Output {
+    pts: u64,
+    duration: u64,
+    text: String,
+}
+
+struct State {
+    markup: bool,
+    need_header: bool,
+    // Cues of the fragments that are not output yet, by fragment number
+    fragments: BTreeMap<u64, String>,
+    next_fragment: Option<u64>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            markup: false,
+            need_header: true,
+            fragments: BTreeMap::new(),
+            next_fragment: None,
+        }
+    }
+}
+
+impl State {
+    fn cue_text(&self, text: &str) -> String {
+        // Pango markup uses the same tags and entities
+        if self.markup {
+            text.into()
+        } else {
+            let mut escaped = String::new();
+            subtitle::escape(text, &mut escaped);
+            escaped
+        }
+    }
+
+    fn add_cue(&mut self, start: u64, end: u64, text: &str) -> Output {
+        let mut out = String::new();
+        if self.need_header {
+            self.need_header = false;
+            out.push_str(HEADER);
+        }
+        subtitle::write_cue(start, end, &self.cue_text(text), &mut out);
+
+        Output {
+            pts: start,
+            duration: end - start,
+            text: out,
+        }
+    }
+
+    // Outputs all fragments before the given one
+    fn finish_fragments(&mut self, until: u64, fragment_duration: u64) -> Vec<Output> {
+        let first = self.next_fragment.unwrap_or(until);
+        if until <= first {
+            self.next_fragment = Some(first);
+            return Vec::new();
+        }
+        self.next_fragment = Some(until);
+
+        (first..until)
+            .map(|fragment| {
+                let mut text = String::from(HEADER);
+                if let Some(cues) = self.fragments.remove(&fragment) {
+                    text.push_str(&cues);
+                }
+
+                Output {
+                    pts: fragment * fragment_duration,
+                    duration: fragment_duration,
+                    text: text,
+                }
+            })
+            .collect()
+    }
+
+    fn finish_all_fragments(&mut self, fragment_duration: u64) -> Vec<Output> {
+        let until = match self.fragments.keys().next_back() {
+            Some(&last) => last + 1,
+            None => match self.next_fragment {
+                None => return Vec::new(),
+                Some(next) => next,
+            },
+        };
+
+        self.finish_fragments(until, fragment_duration)
+    }
+
+    fn add_fragmented_cue(
+        &mut self,
+        start: u64,
+        end: u64,
+        text: &str,
+        fragment_duration: u64,
+    ) -> Vec<Output> {
+        let first = start / fragment_duration;
+        let outputs = self.finish_fragments(first, fragment_duration);
+
+        // Cues before already output fragments are clipped
+        let next = self.next_fragment.unwrap();
+        let last = if end > start {
+            (end - 1) / fragment_duration
+        } else {
+            first
+        };
+
+        let text = self.cue_text(text);
+        for fragment in first.max(next)..(last + 1) {
+            let fragment_start = fragment * fragment_duration;
+            let fragment_end = fragment_start + fragment_duration;
+
+            let cues = self.fragments.entry(fragment).or_insert_with(String::new);
+            subtitle::write_cue(
+                start.max(fragment_start),
+                end.min(fragment_end),
+                &text,
+                cues,
+            );
+        }
+
+        outputs
+    }
+}
+
+struct WebVttEnc {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl WebVttEnc {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rswebvttenc",
+                gst::DebugColorFlags::empty(),
+                "Rust WebVTT encoder",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "WebVTT encoder",
+            "Codec/Encoder/Subtitle",
+            "Writes timed text as WebVTT",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "text/x-raw",
+            &[("format", &gst::List::new(&[&"utf8", &"pango-markup"]))],
+        );
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple("application/x-subtitle-vtt", &[]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            WebVttEnc::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |webvttenc, element| webvttenc.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            WebVttEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |webvttenc, element| webvttenc.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            WebVttEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |webvttenc, element| webvttenc.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            WebVttEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |webvttenc, element| webvttenc.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            WebVttEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |webvttenc, element| webvttenc.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let webvttenc = element.get_impl().downcast_ref::<WebVttEnc>().unwrap();
+        element.catch_panic(fallback, |element| f(webvttenc, element))
+    }
+
+    fn push_outputs(&self, outputs: Vec<Output>) -> gst::FlowReturn {
+        for output in outputs {
+            let mut buffer = gst::Buffer::from_mut_slice(output.text.into_bytes()).unwrap();
+            {
+                let buffer = buffer.get_mut().unwrap();
+                buffer.set_pts(gst::ClockTime(Some(output.pts)));
+                buffer.set_duration(gst::ClockTime(Some(output.duration)));
+            }
+
+            let flow_ret = self.srcpad.push(buffer);
+            if flow_ret != gst::FlowReturn::Ok {
+                return flow_ret;
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let start = match buffer.get_pts().0 {
+            None => {
+                gst_element_error!(
+                    element,
+                    gst::StreamError::Format,
+                    ["Buffers without timestamps are not supported"]
+                );
+                return gst::FlowReturn::Error;
+            }
+            Some(pts) => pts,
+        };
+        let end = start + buffer.get_duration().0.unwrap_or(DEFAULT_CUE_DURATION);
+
+        let fragment_duration = self.settings.lock().unwrap().fragment_duration;
+
+        let outputs = {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+            let text = String::from_utf8_lossy(map.as_slice());
+
+            let mut state = self.state.lock().unwrap();
+            if fragment_duration == 0 {
+                vec![state.add_cue(start, end, &text)]
+            } else {
+                state.add_fragmented_cue(start, end, &text, fragment_duration)
+            }
+        };
+
+        self.push_outputs(outputs)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        let fragment_duration = self.settings.lock().unwrap().fragment_duration;
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let markup = e.get_caps()
+                    .get_structure(0)
+                    .and_then(|s| s.get::<&str>("format"))
+                    .map(|format| format == "pango-markup")
+                    .unwrap_or(false);
+                self.state.lock().unwrap().markup = markup;
+
+                let caps = gst::Caps::new_simple("application/x-subtitle-vtt", &[]);
+                return self.srcpad.push_event(gst::Event::new_caps(&caps).build());
+            }
+            // Nothing until the end of the gap, all fragments before it are complete
+            EventView::Gap(e) if fragment_duration > 0 => {
+                let (timestamp, duration) = e.get();
+                if let Some(timestamp) = timestamp.0 {
+                    let end = timestamp + duration.0.unwrap_or(0);
+                    let outputs = self.state
+                        .lock()
+                        .unwrap()
+                        .finish_fragments(end / fragment_duration, fragment_duration);
+                    let _ = self.push_outputs(outputs);
+                }
+                return true;
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.fragments.clear();
+                state.next_fragment = None;
+            }
+            EventView::Eos(..) if fragment_duration > 0 => {
+                let outputs = self.state
+                    .lock()
+                    .unwrap()
+                    .finish_all_fragments(fragment_duration);
+                let _ = self.push_outputs(outputs);
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for WebVttEnc {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt64("fragment-duration", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.fragment_duration = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt64("fragment-duration", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.fragment_duration.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for WebVttEnc {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct WebVttEncStatic;
+
+impl ImplTypeStatic<Element> for WebVttEncStatic {
+    fn get_name(&self) -> &str {
+        "RsWebVttEnc"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        WebVttEnc::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        WebVttEnc::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let webvttenc_static = WebVttEncStatic;
+    register_type(webvttenc_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fragments() {
+        let second = gst::SECOND_VAL;
+        let mut state = State::default();
+
+        let outputs = state.add_fragmented_cue(1 * second, 2 * second, "a & b", 4 * second);
+        assert!(outputs.is_empty());
+
+        // Crosses into the third fragment, the empty second one is output too
+        let outputs = state.add_fragmented_cue(11 * second, 13 * second, "c", 4 * second);
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(
+            outputs[0],
+            Output {
+                pts: 0,
+                duration: 4 * second,
+                text: "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\na &amp; b\n\n".into(),
+            }
+        );
+        assert_eq!(outputs[1].text, HEADER);
+
+        let outputs = state.finish_all_fragments(4 * second);
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(
+            outputs[0].text,
+            "WEBVTT\n\n00:00:11.000 --> 00:00:12.000\nc\n\n"
+        );
+        assert_eq!(outputs[1].pts, 12 * second);
+        assert_eq!(
+            outputs[1].text,
+            "WEBVTT\n\n00:00:12.000 --> 00:00:13.000\nc\n\n"
+        );
+    }
+}
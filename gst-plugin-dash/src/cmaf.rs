@@ -0,0 +1,237 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Extraction of the information needed for the MPD from CMAF initialization segments
+
+use byteorder::{BigEndian, ByteOrder};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackInfo {
+    pub codecs: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub sample_rate: Option<u32>,
+}
+
+// Size of the sample entry fields before the child boxes, including the box header
+const VISUAL_SAMPLE_ENTRY_SIZE: usize = 86;
+const AUDIO_SAMPLE_ENTRY_SIZE: usize = 36;
+
+// Returns the content of the first box of the given type
+fn find_box<'a>(mut data: &'a [u8], fourcc: &[u8; 4]) -> Option<&'a [u8]> {
+    while data.len() >= 8 {
+        let size = BigEndian::read_u32(&data[0..4]) as usize;
+        let (header_size, size) = match size {
+            0 => (8, data.len()),
+            1 if data.len() >= 16 => (16, BigEndian::read_u64(&data[8..16]) as usize),
+            _ => (8, size),
+        };
+        if size < header_size || size > data.len() {
+            return None;
+        }
+
+        if &data[4..8] == fourcc {
+            return Some(&data[header_size..size]);
+        }
+        data = &data[size..];
+    }
+
+    None
+}
+
+fn find_path<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter().fold(Some(data), |data, fourcc| {
+        data.and_then(|data| find_box(data, fourcc))
+    })
+}
+
+// Reads the size of an MPEG-4 descriptor
+fn descriptor_size(data: &mut &[u8]) -> Option<usize> {
+    let mut size = 0;
+    for _ in 0..4 {
+        let (&b, rest) = data.split_first()?;
+        *data = rest;
+        size = (size << 7) | (b & 0x7f) as usize;
+        if b & 0x80 == 0 {
+            return Some(size);
+        }
+    }
+    None
+}
+
+// Returns the content of the first descriptor with the given tag
+fn find_descriptor<'a>(mut data: &'a [u8], tag: u8) -> Option<&'a [u8]> {
+    while !data.is_empty() {
+        let t = data[0];
+        data = &data[1..];
+        let size = descriptor_size(&mut data)?;
+        if size > data.len() {
+            return None;
+        }
+        if t == tag {
+            return Some(&data[..size]);
+        }
+        data = &data[size..];
+    }
+    None
+}
+
+// RFC 6381 codecs parameter for AAC, e.g. mp4a.40.2
+fn mp4a_codecs(esds: &[u8]) -> Option<String> {
+    // Skip version and flags
+    let es = find_descriptor(esds.get(4..)?, 0x03)?;
+    let flags = *es.get(2)?;
+    let mut skip = 3;
+    if flags & 0x80 != 0 {
+        skip += 2;
+    }
+    if flags & 0x40 != 0 {
+        skip += 1 + *es.get(skip)? as usize;
+    }
+    if flags & 0x20 != 0 {
+        skip += 2;
+    }
+
+    let decoder_config = find_descriptor(es.get(skip..)?, 0x04)?;
+    let object_type = *decoder_config.get(0)?;
+    match find_descriptor(decoder_config.get(13..)?, 0x05) {
+        Some(specific) if object_type == 0x40 && !specific.is_empty() => {
+            Some(format!("mp4a.{:02x}.{}", object_type, specific[0] >> 3))
+        }
+        _ => Some(format!("mp4a.{:02x}", object_type)),
+    }
+}
+
+fn codecs(fourcc: &[u8], children: &[u8]) -> String {
+    let name = String::from_utf8_lossy(fourcc).into_owned();
+
+    match fourcc {
+        b"avc1" | b"avc3" => match find_box(children, b"avcC") {
+            Some(avcc) if avcc.len() >= 4 => {
+                format!("{}.{:02x}{:02x}{:02x}", name, avcc[1], avcc[2], avcc[3])
+            }
+            _ => name,
+        },
+        b"vp09" => match find_box(children, b"vpcC") {
+            // Version and flags, profile, level, bit depth in the upper 4 bits
+            Some(vpcc) if vpcc.len() >= 7 => format!(
+                "vp09.{:02}.{:02}.{:02}",
+                vpcc[4],
+                vpcc[5],
+                vpcc[6] >> 4
+            ),
+            _ => name,
+        },
+        b"mp4a" => find_box(children, b"esds")
+            .and_then(mp4a_codecs)
+            .unwrap_or(name),
+        b"Opus" => String::from("opus"),
+        b"fLaC" => String::from("flac"),
+        _ => name,
+    }
+}
+
+// Parses the first sample entry of the first track
+pub fn parse_init_segment(data: &[u8]) -> Option<TrackInfo> {
+    let stsd = find_path(
+        data,
+        &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"],
+    )?;
+    // Version, flags and entry count
+    let entry = stsd.get(8..)?;
+    if entry.len() < 8 {
+        return None;
+    }
+    let size = BigEndian::read_u32(&entry[0..4]) as usize;
+    let entry = entry.get(..size)?;
+    let fourcc = &entry[4..8];
+
+    let mut info = TrackInfo::default();
+    let is_video = find_path(data, &[b"moov", b"trak", b"mdia", b"minf", b"vmhd"]).is_some();
+    if is_video && entry.len() >= VISUAL_SAMPLE_ENTRY_SIZE {
+        info.width = Some(u32::from(BigEndian::read_u16(&entry[32..34])));
+        info.height = Some(u32::from(BigEndian::read_u16(&entry[34..36])));
+        info.codecs = Some(codecs(fourcc, &entry[VISUAL_SAMPLE_ENTRY_SIZE..]));
+    } else if !is_video && entry.len() >= AUDIO_SAMPLE_ENTRY_SIZE {
+        // 16.16 fixed point
+        info.sample_rate = Some(BigEndian::read_u32(&entry[32..36]) >> 16);
+        info.codecs = Some(codecs(fourcc, &entry[AUDIO_SAMPLE_ENTRY_SIZE..]));
+    }
+
+    Some(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(fourcc: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut v = vec![0u8; 4];
+        BigEndian::write_u32(&mut v, 8 + content.len() as u32);
+        v.extend_from_slice(fourcc);
+        v.extend_from_slice(content);
+        v
+    }
+
+    fn init_segment(media_header: &[u8], entry: &[u8]) -> Vec<u8> {
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend_from_slice(entry);
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stsd", &stsd));
+        let mut minf = media_header.to_vec();
+        minf.extend_from_slice(&stbl);
+        let trak = mp4_box(
+            b"trak",
+            &mp4_box(b"mdia", &mp4_box(b"minf", &minf)),
+        );
+
+        let mut data = mp4_box(b"ftyp", b"iso6");
+        data.extend_from_slice(&mp4_box(b"moov", &trak));
+        data
+    }
+
+    #[test]
+    fn test_video() {
+        let mut entry = vec![0u8; VISUAL_SAMPLE_ENTRY_SIZE - 8];
+        BigEndian::write_u16(&mut entry[24..26], 1280);
+        BigEndian::write_u16(&mut entry[26..28], 720);
+        entry.extend_from_slice(&mp4_box(b"avcC", &[1, 0x64, 0x00, 0x1f, 0xff]));
+
+        let data = init_segment(&mp4_box(b"vmhd", &[0; 12]), &mp4_box(b"avc1", &entry));
+        assert_eq!(
+            parse_init_segment(&data),
+            Some(TrackInfo {
+                codecs: Some(String::from("avc1.64001f")),
+                width: Some(1280),
+                height: Some(720),
+                sample_rate: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut entry = vec![0u8; AUDIO_SAMPLE_ENTRY_SIZE - 8];
+        BigEndian::write_u32(&mut entry[24..28], 48_000 << 16);
+        let esds = [
+            0, 0, 0, 0, 0x03, 0x19, 0x00, 0x01, 0x00, 0x04, 0x11, 0x40, 0x15, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0x05, 0x02, 0x11, 0x90, 0x06, 0x01, 0x02,
+        ];
+        entry.extend_from_slice(&mp4_box(b"esds", &esds));
+
+        let data = init_segment(&mp4_box(b"smhd", &[0; 8]), &mp4_box(b"mp4a", &entry));
+        assert_eq!(
+            parse_init_segment(&data),
+            Some(TrackInfo {
+                codecs: Some(String::from("mp4a.40.2")),
+                width: None,
+                height: None,
+                sample_rate: Some(48_000),
+            })
+        );
+    }
+}
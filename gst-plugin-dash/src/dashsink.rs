@@ -0,0 +1,784 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::u32;
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::tags::StreamInfo;
+
+use cmaf;
+use mpd::{self, ContentType, Mpd, Representation, Segment};
+
+const DEFAULT_MPD_LOCATION: &str = "manifest.mpd";
+const DEFAULT_TARGET_DURATION: u32 = 4;
+const DEFAULT_WINDOW_SIZE: u32 = 5;
+const DEFAULT_MAX_FILES: u32 = 10;
+
+static PROPERTIES: [Property; 4] = [
+    Property::String(
+        "mpd-location",
+        "MPD Location",
+        "Location of the MPD, segments are written to the same directory",
+        Some(DEFAULT_MPD_LOCATION),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "target-duration",
+        "Target Duration",
+        "Segment duration in seconds, segments start at the next fragment after this",
+        (1, u32::MAX),
+        DEFAULT_TARGET_DURATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "window-size",
+        "Window Size",
+        "Number of segments listed per representation in the MPD (0 = all)",
+        (0, u32::MAX),
+        DEFAULT_WINDOW_SIZE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "max-files",
+        "Max Files",
+        "Number of segments to keep per representation before deleting the oldest (0 = all)",
+        (0, u32::MAX),
+        DEFAULT_MAX_FILES,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    mpd_location: String,
+    target_duration: u32,
+    window_size: u32,
+    max_files: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            mpd_location: DEFAULT_MPD_LOCATION.into(),
+            target_duration: DEFAULT_TARGET_DURATION,
+            window_size: DEFAULT_WINDOW_SIZE,
+            max_files: DEFAULT_MAX_FILES,
+        }
+    }
+}
+
+struct CurrentSegment {
+    data: Vec<u8>,
+    // Running time in nanoseconds
+    start: u64,
+}
+
+struct Stream {
+    sinkpad: gst::Pad,
+    // Header buffers until the first media data
+    init: Option<Vec<u8>>,
+    init_written: bool,
+    current: Option<CurrentSegment>,
+    // End of the last buffer
+    end: Option<u64>,
+    representation: Representation,
+    // Language and role from the tags, written into the adaptation set
+    info: StreamInfo,
+    // Written segments that are deleted once there are more than max-files
+    files: VecDeque<PathBuf>,
+    eos: bool,
+}
+
+impl Stream {
+    fn new(sinkpad: gst::Pad, content_type: ContentType) -> Stream {
+        let id = sinkpad.get_name();
+        Stream {
+            sinkpad: sinkpad,
+            init: None,
+            init_written: false,
+            current: None,
+            end: None,
+            representation: Representation::new(&id, content_type),
+            info: StreamInfo::default(),
+            files: VecDeque::new(),
+            eos: false,
+        }
+    }
+
+    fn is_image(&self) -> bool {
+        self.representation.content_type == ContentType::Image
+    }
+
+    fn reset(&mut self) {
+        let content_type = self.representation.content_type;
+        *self = Stream::new(self.sinkpad.clone(), content_type);
+    }
+}
+
+#[derive(Default)]
+struct State {
+    streams: Vec<Stream>,
+    pad_count: u32,
+    started: bool,
+    // Running time and wall clock time in milliseconds of the first buffer. Segment times are
+    // relative to this.
+    start_time: Option<(u64, u64)>,
+}
+
+struct DashSink {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+fn now_ms() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_secs() * 1000 + u64::from(now.subsec_nanos() / 1_000_000)
+}
+
+fn to_ms(ns: u64) -> u64 {
+    (ns + gst::MSECOND_VAL / 2) / gst::MSECOND_VAL
+}
+
+impl DashSink {
+    fn new(_element: &Element) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "dashrssink",
+                gst::DebugColorFlags::empty(),
+                "DASH sink",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "DASH Sink",
+            "Sink/Network",
+            "Writes CMAF segments and a dynamic MPEG-DASH manifest",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        // Each request pad is one representation, all representations of the same type form
+        // an adaptation set
+        let caps = gst::Caps::new_simple("video/quicktime", &[("variant", &"iso-fragmented")]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "video_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "audio_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        // Thumbnail sheets, e.g. from spritegen, written as DASH-IF thumbnail tiles. The caps
+        // should contain the number of columns and rows of each sheet.
+        let caps = gst::Caps::new_simple("image/jpeg", &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "image_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        // Same as on hlsrssink: if a handler returns true the file is considered handled and
+        // not written or deleted locally
+        klass.add_signal(
+            "write-segment",
+            &[String::static_type(), gst::Buffer::static_type()],
+            bool::static_type(),
+        );
+        klass.add_signal(
+            "write-manifest",
+            &[String::static_type(), String::static_type()],
+            bool::static_type(),
+        );
+        klass.add_signal(
+            "delete-segment",
+            &[String::static_type()],
+            bool::static_type(),
+        );
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        element.set_element_flags(gst::ElementFlags::SINK);
+
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let dashsink = element.get_impl().downcast_ref::<DashSink>().unwrap();
+        element.catch_panic(fallback, |element| f(dashsink, element))
+    }
+
+    fn emit_handled(&self, element: &Element, signal: &str, args: &[&glib::ToValue]) -> bool {
+        element
+            .emit(signal, args)
+            .ok()
+            .and_then(|v| v)
+            .and_then(|v| v.get::<bool>())
+            .unwrap_or(false)
+    }
+
+    fn segment_path(&self, settings: &Settings, name: &str) -> PathBuf {
+        Path::new(&settings.mpd_location)
+            .parent()
+            .map(|dir| dir.join(name))
+            .unwrap_or_else(|| PathBuf::from(name))
+    }
+
+    fn write_file(&self, element: &Element, location: &Path, data: Vec<u8>) -> Result<(), String> {
+        let location_str = location.to_string_lossy().into_owned();
+        let buffer = gst::Buffer::from_mut_slice(data).unwrap();
+        if self.emit_handled(element, "write-segment", &[&location_str, &buffer]) {
+            return Ok(());
+        }
+
+        let map = buffer.map_readable().unwrap();
+        fs::File::create(location)
+            .and_then(|mut file| file.write_all(map.as_slice()))
+            .map_err(|err| format!("Failed to write {}: {}", location_str, err))
+    }
+
+    fn write_mpd(
+        &self,
+        element: &Element,
+        settings: &Settings,
+        state: &State,
+        ended: bool,
+    ) -> Result<(), String> {
+        // Only once all representations are known. Thumbnails are only added once the first
+        // sheet is complete, which can take minutes.
+        if state.streams.iter().any(|s| !s.init_written && !s.is_image()) {
+            return Ok(());
+        }
+        let availability_start_time = match state.start_time {
+            None => return Ok(()),
+            Some((_, wall_clock)) => wall_clock,
+        };
+
+        let duration = if ended {
+            Some(
+                state
+                    .streams
+                    .iter()
+                    .filter(|s| !s.is_image())
+                    .filter_map(|s| s.representation.segments.back())
+                    .map(|s| s.time + s.duration)
+                    .max()
+                    .unwrap_or(0),
+            )
+        } else {
+            None
+        };
+
+        let content = Mpd {
+            availability_start_time: availability_start_time,
+            publish_time: now_ms(),
+            min_update_period: settings.target_duration,
+            duration: duration,
+            representations: state
+                .streams
+                .iter()
+                .filter(|s| s.init_written)
+                .map(|s| s.representation.clone())
+                .collect(),
+        }.render();
+
+        let location = &settings.mpd_location;
+        if self.emit_handled(element, "write-manifest", &[location, &content]) {
+            return Ok(());
+        }
+
+        // Write to a temporary file first so that clients never see a partial manifest
+        let tmp_location = format!("{}.tmp", location);
+        fs::File::create(&tmp_location)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .and_then(|_| fs::rename(&tmp_location, location))
+            .map_err(|err| format!("Failed to write manifest {}: {}", location, err))
+    }
+
+    // Returns true if a segment was finished and the MPD has to be updated
+    fn finish_segment(
+        &self,
+        element: &Element,
+        settings: &Settings,
+        start_time: u64,
+        stream: &mut Stream,
+    ) -> Result<bool, String> {
+        let current = match stream.current.take() {
+            None => return Ok(false),
+            Some(current) => current,
+        };
+        let end = stream.end.unwrap_or(current.start);
+
+        // Start and end are converted separately so that consecutive segments are contiguous
+        let time = to_ms(current.start.saturating_sub(start_time));
+        let duration = to_ms(end.saturating_sub(start_time)).saturating_sub(time);
+        let name = mpd::media_name(
+            &stream.representation.id,
+            stream.representation.content_type,
+            time,
+        );
+        let location = self.segment_path(settings, &name);
+
+        gst_debug!(
+            self.cat,
+            obj: &stream.sinkpad,
+            "Writing segment {} with duration {}ms",
+            location.display(),
+            duration
+        );
+        let size = current.data.len();
+        self.write_file(element, &location, current.data)?;
+
+        stream.representation.add_segment(
+            Segment {
+                time: time,
+                duration: duration,
+            },
+            size,
+            settings.window_size as usize,
+        );
+
+        stream.files.push_back(location);
+        let max_files = settings.max_files as usize;
+        while max_files > 0 && stream.files.len() > max_files {
+            let location = stream.files.pop_front().unwrap();
+            let location_str = location.to_string_lossy().into_owned();
+            gst_debug!(self.cat, obj: element, "Deleting segment {}", location_str);
+
+            if !self.emit_handled(element, "delete-segment", &[&location_str]) {
+                if let Err(err) = fs::remove_file(&location) {
+                    gst_warning!(
+                        self.cat,
+                        obj: element,
+                        "Failed to delete {}: {}",
+                        location_str,
+                        err
+                    );
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let settings = self.settings.lock().unwrap().clone();
+        let mut state = self.state.lock().unwrap();
+        state.started = true;
+
+        let idx = match state.streams.iter().position(|s| s.sinkpad == *pad) {
+            None => return gst::FlowReturn::Error,
+            Some(idx) => idx,
+        };
+
+        let map = match buffer.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map,
+        };
+
+        // Collect the initialization segment until the first media data
+        if buffer.get_flags().contains(gst::BufferFlags::HEADER) {
+            state.streams[idx]
+                .init
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(map.as_slice());
+            return gst::FlowReturn::Ok;
+        }
+
+        let pts = match buffer.get_pts().0 {
+            None => {
+                gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(pts) => pts,
+        };
+
+        let start_time = match state.start_time {
+            Some((start_time, _)) => start_time,
+            None => {
+                gst_debug!(self.cat, obj: element, "Starting at {}", buffer.get_pts());
+                state.start_time = Some((pts, now_ms()));
+                pts
+            }
+        };
+
+        // Thumbnail sheets are complete segments on their own
+        if state.streams[idx].is_image() {
+            let res = {
+                let stream = &mut state.streams[idx];
+                stream.init_written = true;
+                stream.current = Some(CurrentSegment {
+                    data: map.as_slice().to_vec(),
+                    start: pts,
+                });
+                stream.end = Some(pts + buffer.get_duration().0.unwrap_or(0));
+                self.finish_segment(element, &settings, start_time, stream)
+            }.and_then(|_| self.write_mpd(element, &settings, &state, false));
+
+            if let Err(err) = res {
+                gst_element_error!(element, gst::ResourceError::Write, ["{}", err]);
+                return gst::FlowReturn::Error;
+            }
+            return gst::FlowReturn::Ok;
+        }
+
+        let mut update_mpd = false;
+        {
+            let stream = &mut state.streams[idx];
+
+            if !stream.init_written {
+                let init = match stream.init.take() {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["No initialization segment before media data"]
+                        );
+                        return gst::FlowReturn::Error;
+                    }
+                    Some(init) => init,
+                };
+
+                let info = cmaf::parse_init_segment(&init).unwrap_or_default();
+                gst_debug!(self.cat, obj: pad, "Initialization segment with {:?}", info);
+                stream.representation.codecs = info.codecs;
+                stream.representation.width = info.width;
+                stream.representation.height = info.height;
+                stream.representation.sample_rate = info.sample_rate;
+
+                let location =
+                    self.segment_path(&settings, &mpd::init_name(&stream.representation.id));
+                if let Err(err) = self.write_file(element, &location, init) {
+                    gst_element_error!(element, gst::ResourceError::Write, ["{}", err]);
+                    return gst::FlowReturn::Error;
+                }
+                stream.init_written = true;
+            }
+
+            // Every fragment starts with a buffer without the DELTA_UNIT flag, segments start
+            // at the next fragment once the target duration is reached
+            let fragment_start = !buffer.get_flags().contains(gst::BufferFlags::DELTA_UNIT);
+            let target_duration = u64::from(settings.target_duration) * gst::SECOND_VAL;
+            let new_segment = match stream.current {
+                None => true,
+                Some(ref current) => fragment_start && pts >= current.start + target_duration,
+            };
+
+            if new_segment {
+                // The previous segment ends where the new one starts
+                if stream.current.is_some() {
+                    stream.end = Some(pts);
+                }
+                match self.finish_segment(element, &settings, start_time, stream) {
+                    Ok(finished) => update_mpd = finished,
+                    Err(err) => {
+                        gst_element_error!(element, gst::ResourceError::Write, ["{}", err]);
+                        return gst::FlowReturn::Error;
+                    }
+                }
+
+                stream.current = Some(CurrentSegment {
+                    data: Vec::new(),
+                    start: pts,
+                });
+            }
+
+            let end = pts + buffer.get_duration().0.unwrap_or(0);
+            if stream.end.map(|e| end > e).unwrap_or(true) {
+                stream.end = Some(end);
+            }
+
+            let current = stream.current.as_mut().unwrap();
+            current.data.extend_from_slice(map.as_slice());
+        }
+
+        if update_mpd {
+            if let Err(err) = self.write_mpd(element, &settings, &state, false) {
+                gst_element_error!(element, gst::ResourceError::Write, ["{}", err]);
+                return gst::FlowReturn::Error;
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn handle_eos(&self, pad: &gst::Pad, element: &Element) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+        let mut state = self.state.lock().unwrap();
+
+        let start_time = state.start_time.map(|(start_time, _)| start_time);
+        if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+            stream.eos = true;
+            if let Some(start_time) = start_time {
+                if let Err(err) = self.finish_segment(element, &settings, start_time, stream) {
+                    gst_element_error!(element, gst::ResourceError::Write, ["{}", err]);
+                    return false;
+                }
+            }
+        }
+
+        if state.streams.iter().any(|s| !s.eos) {
+            return true;
+        }
+
+        gst_debug!(self.cat, obj: element, "All streams are EOS");
+        if let Err(err) = self.write_mpd(element, &settings, &state, true) {
+            gst_element_error!(element, gst::ResourceError::Write, ["{}", err]);
+            return false;
+        }
+        drop(state);
+
+        element.post_message(&gst::Message::new_eos().src(Some(element)).build());
+
+        true
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                // Thumbnail sheets have no initialization segment to take the dimensions from
+                let mut state = self.state.lock().unwrap();
+                let stream = state.streams.iter_mut().find(|s| s.sinkpad == *pad);
+                if let (Some(stream), Some(s)) = (stream, e.get_caps().get_structure(0)) {
+                    if stream.is_image() {
+                        let representation = &mut stream.representation;
+                        representation.width = s.get::<i32>("width").map(|v| v as u32);
+                        representation.height = s.get::<i32>("height").map(|v| v as u32);
+                        representation.tiles = s.get::<i32>("columns")
+                            .and_then(|columns| s.get::<i32>("rows").map(|rows| (columns, rows)))
+                            .map(|(columns, rows)| (columns as u32, rows as u32));
+                    }
+                }
+                true
+            }
+            EventView::Tag(e) => {
+                // Applied with the next MPD update
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    stream.info.update(e.get_tag());
+                    stream.representation.language = stream.info.language_rfc5646();
+                    stream.representation.role = stream.info.role.map(|role| role.as_str());
+                }
+                true
+            }
+            EventView::Eos(..) => self.handle_eos(pad, element),
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    stream.eos = false;
+                }
+                true
+            }
+            // Segment and everything else is not forwarded anywhere
+            _ => true,
+        }
+    }
+}
+
+impl ObjectImpl<Element> for DashSink {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("mpd-location", ..) => {
+                settings.mpd_location = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_MPD_LOCATION.into());
+            }
+            Property::UInt("target-duration", ..) => {
+                settings.target_duration = value.get().unwrap();
+            }
+            Property::UInt("window-size", ..) => {
+                settings.window_size = value.get().unwrap();
+            }
+            Property::UInt("max-files", ..) => {
+                settings.max_files = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("mpd-location", ..) => Ok(settings.mpd_location.to_value()),
+            Property::UInt("target-duration", ..) => Ok(settings.target_duration.to_value()),
+            Property::UInt("window-size", ..) => Ok(settings.window_size.to_value()),
+            Property::UInt("max-files", ..) => Ok(settings.max_files.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for DashSink {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        // Incomplete segments are dropped, the MPD only becomes static at EOS
+        if transition == gst::StateChange::PausedToReady {
+            let mut state = self.state.lock().unwrap();
+            state.started = false;
+            state.start_time = None;
+            for stream in &mut state.streams {
+                stream.reset();
+            }
+        }
+
+        ret
+    }
+
+    fn request_new_pad(
+        &self,
+        element: &Element,
+        templ: &gst::PadTemplate,
+        _name: Option<String>,
+        _caps: Option<&gst::CapsRef>,
+    ) -> Option<gst::Pad> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.started {
+            gst_error!(self.cat, obj: element, "Can't request pads after streaming started");
+            return None;
+        }
+
+        let id = state.pad_count;
+        state.pad_count += 1;
+
+        let (name, content_type) = if *templ == element.get_pad_template("video_%u").unwrap() {
+            (format!("video_{}", id), ContentType::Video)
+        } else if *templ == element.get_pad_template("audio_%u").unwrap() {
+            (format!("audio_{}", id), ContentType::Audio)
+        } else {
+            (format!("image_{}", id), ContentType::Image)
+        };
+        let sinkpad = gst::Pad::new_from_template(templ, name.as_str());
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            DashSink::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |dashsink, element| dashsink.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            DashSink::catch_panic_pad_function(
+                parent,
+                || false,
+                |dashsink, element| dashsink.sink_event(pad, element, event),
+            )
+        });
+
+        sinkpad.set_active(true).unwrap();
+        element.add_pad(&sinkpad).unwrap();
+
+        state
+            .streams
+            .push(Stream::new(sinkpad.clone(), content_type));
+
+        Some(sinkpad)
+    }
+
+    fn release_pad(&self, element: &Element, pad: &gst::Pad) {
+        let mut state = self.state.lock().unwrap();
+
+        let pos = match state.streams.iter().position(|s| s.sinkpad == *pad) {
+            None => return,
+            Some(pos) => pos,
+        };
+        let stream = state.streams.remove(pos);
+        drop(state);
+
+        stream.sinkpad.set_active(false).unwrap();
+        element.remove_pad(&stream.sinkpad).unwrap();
+    }
+}
+
+struct DashSinkStatic;
+
+impl ImplTypeStatic<Element> for DashSinkStatic {
+    fn get_name(&self) -> &str {
+        "DashSink"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        DashSink::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        DashSink::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let dashsink_static = DashSinkStatic;
+    register_type(dashsink_static)
+}
@@ -0,0 +1,506 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+// All segment times and durations are in milliseconds
+pub const TIMESCALE: u64 = 1000;
+
+pub const INIT_TEMPLATE: &str = "$RepresentationID$_init.mp4";
+pub const MEDIA_TEMPLATE: &str = "$RepresentationID$_$Time$.m4s";
+// Thumbnail sheets are self-contained JPEG files without initialization segment
+pub const IMAGE_TEMPLATE: &str = "$RepresentationID$_$Time$.jpg";
+
+// Scheme of the DASH-IF IOP thumbnail tile descriptor
+const THUMBNAIL_TILE_SCHEME: &str = "http://dashif.org/thumbnail_tile";
+// Scheme of the role descriptor, the values are the names of `gst_plugin::tags::TrackRole`
+const ROLE_SCHEME: &str = "urn:mpeg:dash:role:2011";
+
+pub fn init_name(representation_id: &str) -> String {
+    format!("{}_init.mp4", representation_id)
+}
+
+pub fn media_name(representation_id: &str, content_type: ContentType, time: u64) -> String {
+    match content_type {
+        ContentType::Image => format!("{}_{}.jpg", representation_id, time),
+        _ => format!("{}_{}.m4s", representation_id, time),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Video,
+    Audio,
+    Image,
+}
+
+impl ContentType {
+    fn name(&self) -> &'static str {
+        match *self {
+            ContentType::Video => "video",
+            ContentType::Audio => "audio",
+            ContentType::Image => "image",
+        }
+    }
+
+    fn mime_type(&self) -> &'static str {
+        match *self {
+            ContentType::Video => "video/mp4",
+            ContentType::Audio => "audio/mp4",
+            ContentType::Image => "image/jpeg",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub time: u64,
+    pub duration: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Representation {
+    pub id: String,
+    pub content_type: ContentType,
+    pub codecs: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub sample_rate: Option<u32>,
+    // Columns and rows of thumbnail sheets
+    pub tiles: Option<(u32, u32)>,
+    // RFC 5646 language tag and role, shared by all representations of an adaptation set
+    pub language: Option<String>,
+    pub role: Option<&'static str>,
+    // Peak bitrate of all segments so far
+    pub bandwidth: u64,
+    pub segments: VecDeque<Segment>,
+}
+
+impl Representation {
+    pub fn new(id: &str, content_type: ContentType) -> Representation {
+        Representation {
+            id: String::from(id),
+            content_type: content_type,
+            codecs: None,
+            width: None,
+            height: None,
+            sample_rate: None,
+            tiles: None,
+            language: None,
+            role: None,
+            bandwidth: 0,
+            segments: VecDeque::new(),
+        }
+    }
+
+    // Only the latest `window_size` segments are kept, 0 for all
+    pub fn add_segment(&mut self, segment: Segment, size: usize, window_size: usize) {
+        if segment.duration > 0 {
+            let bitrate = (size as u64 * 8 * TIMESCALE) / segment.duration;
+            if bitrate > self.bandwidth {
+                self.bandwidth = bitrate;
+            }
+        }
+
+        self.segments.push_back(segment);
+        while window_size > 0 && self.segments.len() > window_size {
+            self.segments.pop_front();
+        }
+    }
+
+    fn timeline_duration(&self) -> u64 {
+        match (self.segments.front(), self.segments.back()) {
+            (Some(first), Some(last)) => last.time + last.duration - first.time,
+            _ => 0,
+        }
+    }
+
+    fn render(&self, mpd: &mut String) {
+        write!(
+            mpd,
+            "      <Representation id=\"{}\" bandwidth=\"{}\"",
+            self.id,
+            // Must not be 0 before the first segment is known
+            if self.bandwidth > 0 { self.bandwidth } else { 1 }
+        ).unwrap();
+        if let Some(ref codecs) = self.codecs {
+            write!(mpd, " codecs=\"{}\"", codecs).unwrap();
+        }
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            write!(mpd, " width=\"{}\" height=\"{}\"", width, height).unwrap();
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            write!(mpd, " audioSamplingRate=\"{}\"", sample_rate).unwrap();
+        }
+        mpd.push_str(">\n");
+
+        if self.content_type == ContentType::Image {
+            if let Some((columns, rows)) = self.tiles {
+                writeln!(
+                    mpd,
+                    "        <EssentialProperty schemeIdUri=\"{}\" value=\"{}x{}\"/>",
+                    THUMBNAIL_TILE_SCHEME, columns, rows
+                ).unwrap();
+            }
+            writeln!(
+                mpd,
+                "        <SegmentTemplate timescale=\"{}\" media=\"{}\">",
+                TIMESCALE, IMAGE_TEMPLATE
+            ).unwrap();
+        } else {
+            writeln!(
+                mpd,
+                "        <SegmentTemplate timescale=\"{}\" initialization=\"{}\" media=\"{}\">",
+                TIMESCALE, INIT_TEMPLATE, MEDIA_TEMPLATE
+            ).unwrap();
+        }
+        mpd.push_str("          <SegmentTimeline>\n");
+
+        // Consecutive segments of the same duration are written as a single S element with a
+        // repeat count. The time is only needed for the first one and after gaps.
+        let mut iter = self.segments.iter().peekable();
+        let mut expected_time = None;
+        while let Some(segment) = iter.next() {
+            let mut repeat = 0;
+            let mut end = segment.time + segment.duration;
+            while let Some(&next) = iter.peek() {
+                if next.time != end || next.duration != segment.duration {
+                    break;
+                }
+                repeat += 1;
+                end += next.duration;
+                iter.next();
+            }
+
+            mpd.push_str("            <S");
+            if expected_time != Some(segment.time) {
+                write!(mpd, " t=\"{}\"", segment.time).unwrap();
+            }
+            write!(mpd, " d=\"{}\"", segment.duration).unwrap();
+            if repeat > 0 {
+                write!(mpd, " r=\"{}\"", repeat).unwrap();
+            }
+            mpd.push_str("/>\n");
+
+            expected_time = Some(end);
+        }
+
+        mpd.push_str("          </SegmentTimeline>\n");
+        mpd.push_str("        </SegmentTemplate>\n");
+        mpd.push_str("      </Representation>\n");
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mpd {
+    // Wall clock time of the start of the first segment, in milliseconds since the epoch
+    pub availability_start_time: u64,
+    pub publish_time: u64,
+    // In seconds
+    pub min_update_period: u32,
+    // Duration in milliseconds once the stream has ended, the MPD becomes static then
+    pub duration: Option<u64>,
+    pub representations: Vec<Representation>,
+}
+
+impl Mpd {
+    pub fn render(&self) -> String {
+        let mut mpd = String::new();
+        mpd.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        mpd.push_str("<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\"");
+        mpd.push_str(" profiles=\"urn:mpeg:dash:profile:isoff-live:2011\"");
+        match self.duration {
+            None => {
+                let time_shift_buffer_depth = self.representations
+                    .iter()
+                    .map(|r| r.timeline_duration())
+                    .max()
+                    .unwrap_or(0);
+
+                mpd.push_str(" type=\"dynamic\"");
+                write!(
+                    mpd,
+                    " availabilityStartTime=\"{}\" publishTime=\"{}\"",
+                    format_date_time(self.availability_start_time),
+                    format_date_time(self.publish_time)
+                ).unwrap();
+                write!(
+                    mpd,
+                    " minimumUpdatePeriod=\"PT{}S\" timeShiftBufferDepth=\"{}\"",
+                    self.min_update_period,
+                    format_duration(time_shift_buffer_depth)
+                ).unwrap();
+            }
+            Some(duration) => {
+                write!(
+                    mpd,
+                    " type=\"static\" mediaPresentationDuration=\"{}\"",
+                    format_duration(duration)
+                ).unwrap();
+            }
+        }
+        writeln!(mpd, " minBufferTime=\"PT{}S\">", 2 * self.min_update_period).unwrap();
+
+        mpd.push_str("  <Period id=\"0\" start=\"PT0S\">\n");
+        let content_types = [ContentType::Video, ContentType::Audio, ContentType::Image];
+        let mut id = 0;
+        for &content_type in &content_types {
+            // All representations of the same type, language and role form one adaptation
+            // set, which allows clients to switch between them
+            let mut sets = Vec::new();
+            for representation in &self.representations {
+                let key = (
+                    representation.content_type,
+                    &representation.language,
+                    representation.role,
+                );
+                if representation.content_type == content_type && !sets.contains(&key) {
+                    sets.push(key);
+                }
+            }
+
+            for (_, language, role) in sets {
+                write!(
+                    mpd,
+                    "    <AdaptationSet id=\"{}\" contentType=\"{}\" mimeType=\"{}\"",
+                    id,
+                    content_type.name(),
+                    content_type.mime_type()
+                ).unwrap();
+                if let Some(ref language) = *language {
+                    write!(mpd, " lang=\"{}\"", language).unwrap();
+                }
+                if content_type != ContentType::Image {
+                    mpd.push_str(" segmentAlignment=\"true\" startWithSAP=\"1\"");
+                }
+                mpd.push_str(">\n");
+                if let Some(role) = role {
+                    writeln!(
+                        mpd,
+                        "      <Role schemeIdUri=\"{}\" value=\"{}\"/>",
+                        ROLE_SCHEME, role
+                    ).unwrap();
+                }
+                for representation in self.representations.iter().filter(|r| {
+                    r.content_type == content_type && r.language == *language && r.role == role
+                }) {
+                    representation.render(&mut mpd);
+                }
+                mpd.push_str("    </AdaptationSet>\n");
+                id += 1;
+            }
+        }
+        mpd.push_str("  </Period>\n");
+        mpd.push_str("</MPD>\n");
+
+        mpd
+    }
+}
+
+// xs:duration in seconds
+fn format_duration(ms: u64) -> String {
+    format!("PT{}.{:03}S", ms / 1000, ms % 1000)
+}
+
+// xs:dateTime in UTC from milliseconds since the epoch
+fn format_date_time(ms: u64) -> String {
+    let secs = ms / 1000;
+    let days = secs / 86_400;
+    let secs_of_day = secs % 86_400;
+
+    // Civil date from days since 1970-01-01, see
+    // http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        (secs_of_day / 60) % 60,
+        secs_of_day % 60,
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_time() {
+        assert_eq!(format_date_time(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_date_time(951_782_400_500), "2000-02-29T00:00:00.500Z");
+        assert_eq!(format_date_time(1_516_626_245_123), "2018-01-22T13:04:05.123Z");
+    }
+
+    #[test]
+    fn test_mpd() {
+        let mut video = Representation::new("video_0", ContentType::Video);
+        video.codecs = Some(String::from("avc1.64001f"));
+        video.width = Some(1280);
+        video.height = Some(720);
+        for i in 0..4 {
+            video.add_segment(
+                Segment {
+                    time: i * 2000,
+                    duration: 2000,
+                },
+                250_000,
+                3,
+            );
+        }
+        video.add_segment(
+            Segment {
+                time: 8000,
+                duration: 1500,
+            },
+            100_000,
+            3,
+        );
+
+        let mut audio = Representation::new("audio_1", ContentType::Audio);
+        audio.codecs = Some(String::from("mp4a.40.2"));
+        audio.sample_rate = Some(48_000);
+        audio.add_segment(
+            Segment {
+                time: 0,
+                duration: 2000,
+            },
+            32_000,
+            0,
+        );
+
+        let mut mpd = Mpd {
+            availability_start_time: 1_516_626_245_000,
+            publish_time: 1_516_626_255_000,
+            min_update_period: 2,
+            duration: None,
+            representations: vec![video, audio],
+        };
+
+        assert_eq!(
+            mpd.render(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" \
+             profiles=\"urn:mpeg:dash:profile:isoff-live:2011\" type=\"dynamic\" \
+             availabilityStartTime=\"2018-01-22T13:04:05.000Z\" \
+             publishTime=\"2018-01-22T13:04:15.000Z\" minimumUpdatePeriod=\"PT2S\" \
+             timeShiftBufferDepth=\"PT5.500S\" minBufferTime=\"PT4S\">\n  \
+             <Period id=\"0\" start=\"PT0S\">\n    \
+             <AdaptationSet id=\"0\" contentType=\"video\" mimeType=\"video/mp4\" \
+             segmentAlignment=\"true\" startWithSAP=\"1\">\n      \
+             <Representation id=\"video_0\" bandwidth=\"1000000\" codecs=\"avc1.64001f\" \
+             width=\"1280\" height=\"720\">\n        \
+             <SegmentTemplate timescale=\"1000\" initialization=\"$RepresentationID$_init.mp4\" \
+             media=\"$RepresentationID$_$Time$.m4s\">\n          \
+             <SegmentTimeline>\n            \
+             <S t=\"4000\" d=\"2000\" r=\"1\"/>\n            \
+             <S d=\"1500\"/>\n          \
+             </SegmentTimeline>\n        \
+             </SegmentTemplate>\n      \
+             </Representation>\n    \
+             </AdaptationSet>\n    \
+             <AdaptationSet id=\"1\" contentType=\"audio\" mimeType=\"audio/mp4\" \
+             segmentAlignment=\"true\" startWithSAP=\"1\">\n      \
+             <Representation id=\"audio_1\" bandwidth=\"128000\" codecs=\"mp4a.40.2\" \
+             audioSamplingRate=\"48000\">\n        \
+             <SegmentTemplate timescale=\"1000\" initialization=\"$RepresentationID$_init.mp4\" \
+             media=\"$RepresentationID$_$Time$.m4s\">\n          \
+             <SegmentTimeline>\n            \
+             <S t=\"0\" d=\"2000\"/>\n          \
+             </SegmentTimeline>\n        \
+             </SegmentTemplate>\n      \
+             </Representation>\n    \
+             </AdaptationSet>\n  \
+             </Period>\n\
+             </MPD>\n"
+        );
+
+        let mut thumbnails = Representation::new("image_2", ContentType::Image);
+        thumbnails.width = Some(800);
+        thumbnails.height = Some(450);
+        thumbnails.tiles = Some((5, 5));
+        thumbnails.add_segment(
+            Segment {
+                time: 0,
+                duration: 125_000,
+            },
+            125_000,
+            0,
+        );
+        mpd.representations.push(thumbnails);
+        assert!(mpd.render().contains(
+            "    <AdaptationSet id=\"2\" contentType=\"image\" mimeType=\"image/jpeg\">\n      \
+             <Representation id=\"image_2\" bandwidth=\"8000\" width=\"800\" \
+             height=\"450\">\n        \
+             <EssentialProperty schemeIdUri=\"http://dashif.org/thumbnail_tile\" \
+             value=\"5x5\"/>\n        \
+             <SegmentTemplate timescale=\"1000\" media=\"$RepresentationID$_$Time$.jpg\">\n"
+        ));
+
+        mpd.duration = Some(9500);
+        assert!(
+            mpd.render()
+                .contains(" type=\"static\" mediaPresentationDuration=\"PT9.500S\" ")
+        );
+    }
+
+    #[test]
+    fn test_language_role() {
+        let audio = |id: &str, language: &str, role: Option<&'static str>| {
+            let mut audio = Representation::new(id, ContentType::Audio);
+            audio.language = Some(String::from(language));
+            audio.role = role;
+            audio
+        };
+
+        let mpd = Mpd {
+            availability_start_time: 0,
+            publish_time: 0,
+            min_update_period: 2,
+            duration: None,
+            representations: vec![
+                audio("audio_0", "de", Some("main")),
+                audio("audio_1", "en", None),
+                audio("audio_2", "de", Some("main")),
+                audio("audio_3", "de", Some("description")),
+            ],
+        };
+        let content = mpd.render();
+
+        assert_eq!(content.matches("<AdaptationSet ").count(), 3);
+        assert!(content.contains(
+            "    <AdaptationSet id=\"0\" contentType=\"audio\" mimeType=\"audio/mp4\" \
+             lang=\"de\" segmentAlignment=\"true\" startWithSAP=\"1\">\n      \
+             <Role schemeIdUri=\"urn:mpeg:dash:role:2011\" value=\"main\"/>\n      \
+             <Representation id=\"audio_0\""
+        ));
+        assert!(content.contains(
+            "lang=\"en\" segmentAlignment=\"true\" startWithSAP=\"1\">\n      \
+             <Representation id=\"audio_1\""
+        ));
+        assert!(content.contains(
+            "<Role schemeIdUri=\"urn:mpeg:dash:role:2011\" value=\"description\"/>\n      \
+             <Representation id=\"audio_3\""
+        ));
+        // Representations of the same language and role share the adaptation set
+        let pos = |s: &str| content.find(s).unwrap();
+        assert!(pos("id=\"audio_2\"") < pos("lang=\"en\""));
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Helpers for direct I/O (O_DIRECT), which bypasses the page cache but requires all file
+// offsets, sizes and memory addresses to be aligned to the block size of the filesystem
+
+use std::cmp;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+
+// Large enough for the logical block size of all common filesystems and devices
+pub const ALIGNMENT: usize = 4096;
+
+pub fn align_down(v: u64) -> u64 {
+    v & !(ALIGNMENT as u64 - 1)
+}
+
+pub fn align_up(v: usize) -> usize {
+    (v + ALIGNMENT - 1) & !(ALIGNMENT - 1)
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_direct(options: &mut OpenOptions) -> io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use libc;
+
+    options.custom_flags(libc::O_DIRECT);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_direct(_options: &mut OpenOptions) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Direct I/O not supported on this platform",
+    ))
+}
+
+// Needed for writing the unaligned end of a file
+#[cfg(target_os = "linux")]
+pub fn unset_direct(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    use libc;
+
+    unsafe {
+        let fd = file.as_raw_fd();
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_DIRECT) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn unset_direct(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Memory area with an address and capacity aligned to `ALIGNMENT`.
+pub struct AlignedBuffer {
+    data: Vec<u8>,
+    offset: usize,
+    capacity: usize,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = align_up(capacity);
+        let data = vec![0; capacity + ALIGNMENT];
+        let offset = (ALIGNMENT - data.as_ptr() as usize % ALIGNMENT) % ALIGNMENT;
+
+        AlignedBuffer {
+            data: data,
+            offset: offset,
+            capacity: capacity,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Appends as much of `data` as fits and returns the number of bytes appended.
+    pub fn extend(&mut self, data: &[u8]) -> usize {
+        let size = cmp::min(data.len(), self.capacity - self.len);
+        let start = self.offset + self.len;
+        self.data[start..start + size].copy_from_slice(&data[..size]);
+        self.len += size;
+        size
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[self.offset..self.offset + self.len]
+    }
+
+    /// The whole capacity, independent of the current length.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.data[self.offset..self.offset + self.capacity]
+    }
+}
+
+impl fmt::Debug for AlignedBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AlignedBuffer")
+            .field("capacity", &self.capacity)
+            .field("len", &self.len)
+            .finish()
+    }
+}
@@ -7,27 +7,111 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::fs::File;
+use std::cmp;
+use std::fs::{File, OpenOptions};
 use url::Url;
 
-use std::io::Write;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::u32;
 
+use gst_plugin::properties::*;
 use gst_plugin_simple::error::*;
 use gst_plugin_simple::sink::*;
 use gst_plugin_simple::UriValidator;
 
+use glib;
 use gst;
+use gst::prelude::*;
+
+use directio::{self, AlignedBuffer};
+use location;
+
+const DEFAULT_BUFFER_SIZE: u32 = 0;
+const DEFAULT_DIRECT_IO: bool = false;
+
+pub static PROPERTIES: [Property; 3] = [
+    Property::String(
+        "location",
+        "File Location",
+        "Location of the file to write",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "buffer-size",
+        "Buffer Size",
+        "Size of the write buffer in bytes (0 = unbuffered, rounded up to the block size with direct I/O)",
+        (0, u32::MAX),
+        DEFAULT_BUFFER_SIZE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "direct-io",
+        "Direct I/O",
+        "Bypass the page cache (O_DIRECT) when writing",
+        DEFAULT_DIRECT_IO,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug)]
+enum Writer {
+    Unbuffered(File),
+    Buffered(BufWriter<File>),
+    // Only complete aligned blocks are written until the end
+    Direct(File, AlignedBuffer),
+}
+
+impl Writer {
+    fn write_all(&mut self, mut data: &[u8]) -> io::Result<()> {
+        match *self {
+            Writer::Unbuffered(ref mut file) => file.write_all(data),
+            Writer::Buffered(ref mut writer) => writer.write_all(data),
+            Writer::Direct(ref mut file, ref mut aligned) => {
+                while !data.is_empty() {
+                    let size = aligned.extend(data);
+                    data = &data[size..];
+
+                    if aligned.is_full() {
+                        file.write_all(aligned.as_slice())?;
+                        aligned.clear();
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Writer::Unbuffered(..) => Ok(()),
+            Writer::Buffered(mut writer) => writer.flush(),
+            Writer::Direct(mut file, aligned) => {
+                if aligned.as_slice().is_empty() {
+                    return Ok(());
+                }
+
+                directio::unset_direct(&file)?;
+                file.write_all(aligned.as_slice())
+            }
+        }
+    }
+}
 
 #[derive(Debug)]
 enum StreamingState {
     Stopped,
-    Started { file: File, position: u64 },
+    Started { writer: Writer, position: u64 },
 }
 
 #[derive(Debug)]
 pub struct FileSink {
     streaming_state: StreamingState,
     cat: gst::DebugCategory,
+    buffer_size: u32,
+    direct_io: bool,
 }
 
 impl FileSink {
@@ -37,8 +121,10 @@ impl FileSink {
             cat: gst::DebugCategory::new(
                 "rsfilesink",
                 gst::DebugColorFlags::empty(),
-                "Rust file source",
+                "Rust file sink",
             ),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            direct_io: DEFAULT_DIRECT_IO,
         }
     }
 
@@ -81,7 +167,18 @@ impl SinkImpl for FileSink {
             ))
         }));
 
-        let file = try!(File::create(location.as_path()).or_else(|err| {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        if self.direct_io {
+            directio::set_direct(&mut options).map_err(|err| {
+                gst_error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Can't use direct I/O: {}", err]
+                )
+            })?;
+        }
+
+        let file = try!(options.open(location.as_path()).or_else(|err| {
             gst_error!(
                 self.cat,
                 obj: sink,
@@ -100,18 +197,36 @@ impl SinkImpl for FileSink {
 
         gst_debug!(self.cat, obj: sink, "Opened file {:?}", file);
 
+        let buffer_size = self.buffer_size as usize;
+        let writer = if self.direct_io {
+            Writer::Direct(
+                file,
+                AlignedBuffer::new(cmp::max(buffer_size, directio::ALIGNMENT)),
+            )
+        } else if buffer_size > 0 {
+            Writer::Buffered(BufWriter::with_capacity(buffer_size, file))
+        } else {
+            Writer::Unbuffered(file)
+        };
+
         self.streaming_state = StreamingState::Started {
-            file: file,
+            writer: writer,
             position: 0,
         };
 
         Ok(())
     }
 
-    fn stop(&mut self, _sink: &BaseSink) -> Result<(), gst::ErrorMessage> {
-        self.streaming_state = StreamingState::Stopped;
+    fn stop(&mut self, sink: &BaseSink) -> Result<(), gst::ErrorMessage> {
+        let writer = match mem::replace(&mut self.streaming_state, StreamingState::Stopped) {
+            StreamingState::Started { writer, .. } => writer,
+            StreamingState::Stopped => return Ok(()),
+        };
 
-        Ok(())
+        writer.finish().map_err(|err| {
+            gst_error!(self.cat, obj: sink, "Failed to write: {}", err);
+            gst_error_msg!(gst::ResourceError::Write, ["Failed to write: {}", err])
+        })
     }
 
     fn render(&mut self, sink: &BaseSink, buffer: &gst::BufferRef) -> Result<(), FlowError> {
@@ -120,11 +235,11 @@ impl SinkImpl for FileSink {
 
         gst_trace!(cat, obj: sink, "Rendering {:?}", buffer);
 
-        let (file, position) = match *streaming_state {
+        let (writer, position) = match *streaming_state {
             StreamingState::Started {
-                ref mut file,
+                ref mut writer,
                 ref mut position,
-            } => (file, position),
+            } => (writer, position),
             StreamingState::Stopped => {
                 return Err(FlowError::Error(gst_error_msg!(
                     gst::LibraryError::Failed,
@@ -144,7 +259,7 @@ impl SinkImpl for FileSink {
         };
         let data = map.as_slice();
 
-        try!(file.write_all(data).or_else(|err| {
+        try!(writer.write_all(data).or_else(|err| {
             gst_error!(cat, obj: sink, "Failed to write: {}", err);
             Err(FlowError::Error(gst_error_msg!(
                 gst::ResourceError::Write,
@@ -156,4 +271,28 @@ impl SinkImpl for FileSink {
 
         Ok(())
     }
+
+    fn set_property(&mut self, sink: &BaseSink, property: &Property, value: &glib::Value) {
+        match *property {
+            Property::String("location", ..) => {
+                location::set_location(sink, value.get());
+            }
+            Property::UInt("buffer-size", ..) => {
+                self.buffer_size = value.get().unwrap();
+            }
+            Property::Boolean("direct-io", ..) => {
+                self.direct_io = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, sink: &BaseSink, property: &Property) -> Result<glib::Value, ()> {
+        match *property {
+            Property::String("location", ..) => Ok(location::get_location(sink).to_value()),
+            Property::UInt("buffer-size", ..) => Ok(self.buffer_size.to_value()),
+            Property::Boolean("direct-io", ..) => Ok(self.direct_io.to_value()),
+            _ => unimplemented!(),
+        }
+    }
 }
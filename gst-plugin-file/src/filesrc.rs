@@ -6,27 +6,59 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::cmp;
 use std::u64;
-use std::io::{Read, Seek, SeekFrom};
-use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::fs::{File, OpenOptions};
 use url::Url;
 
+use gst_plugin::properties::*;
 use gst_plugin_simple::error::*;
 use gst_plugin_simple::source::*;
 use gst_plugin_simple::UriValidator;
 
+use glib;
 use gst;
+use gst::prelude::*;
+
+use directio::{self, AlignedBuffer};
+use location;
+
+const DEFAULT_DIRECT_IO: bool = false;
+
+pub static PROPERTIES: [Property; 2] = [
+    Property::String(
+        "location",
+        "File Location",
+        "Location of the file to read",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "direct-io",
+        "Direct I/O",
+        "Bypass the page cache (O_DIRECT) when reading",
+        DEFAULT_DIRECT_IO,
+        PropertyMutability::ReadWrite,
+    ),
+];
 
 #[derive(Debug)]
 enum StreamingState {
     Stopped,
-    Started { file: File, position: u64 },
+    Started {
+        file: File,
+        position: u64,
+        // Only used for direct I/O
+        aligned: Option<AlignedBuffer>,
+    },
 }
 
 #[derive(Debug)]
 pub struct FileSrc {
     streaming_state: StreamingState,
     cat: gst::DebugCategory,
+    direct_io: bool,
 }
 
 impl FileSrc {
@@ -38,6 +70,7 @@ impl FileSrc {
                 gst::DebugColorFlags::empty(),
                 "Rust file source",
             ),
+            direct_io: DEFAULT_DIRECT_IO,
         }
     }
 
@@ -92,7 +125,18 @@ impl SourceImpl for FileSrc {
             ))
         }));
 
-        let file = try!(File::open(location.as_path()).or_else(|err| {
+        let mut options = OpenOptions::new();
+        options.read(true);
+        if self.direct_io {
+            directio::set_direct(&mut options).map_err(|err| {
+                gst_error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Can't use direct I/O: {}", err]
+                )
+            })?;
+        }
+
+        let file = try!(options.open(location.as_path()).or_else(|err| {
             gst_error!(
                 self.cat,
                 obj: src,
@@ -114,6 +158,11 @@ impl SourceImpl for FileSrc {
         self.streaming_state = StreamingState::Started {
             file: file,
             position: 0,
+            aligned: if self.direct_io {
+                Some(AlignedBuffer::new(directio::ALIGNMENT))
+            } else {
+                None
+            },
         };
 
         Ok(())
@@ -135,11 +184,12 @@ impl SourceImpl for FileSrc {
         let cat = self.cat;
         let streaming_state = &mut self.streaming_state;
 
-        let (file, position) = match *streaming_state {
+        let (file, position, aligned) = match *streaming_state {
             StreamingState::Started {
                 ref mut file,
                 ref mut position,
-            } => (file, position),
+                ref mut aligned,
+            } => (file, position, aligned),
             StreamingState::Stopped => {
                 return Err(FlowError::Error(gst_error_msg!(
                     gst::LibraryError::Failed,
@@ -148,15 +198,22 @@ impl SourceImpl for FileSrc {
             }
         };
 
-        if *position != offset {
-            try!(file.seek(SeekFrom::Start(offset)).or_else(|err| {
+        // Reads have to start at an aligned offset with direct I/O
+        let seek_offset = if aligned.is_some() {
+            directio::align_down(offset)
+        } else {
+            offset
+        };
+
+        if *position != seek_offset {
+            try!(file.seek(SeekFrom::Start(seek_offset)).or_else(|err| {
                 gst_error!(cat, obj: src, "Failed to seek to {}: {:?}", offset, err);
                 Err(FlowError::Error(gst_error_msg!(
                     gst::ResourceError::Seek,
                     ["Failed to seek to {}: {}", offset, err.to_string()]
                 )))
             }));
-            *position = offset;
+            *position = seek_offset;
         }
 
         let size = {
@@ -172,7 +229,15 @@ impl SourceImpl for FileSrc {
 
             let data = map.as_mut_slice();
 
-            try!(file.read(data).or_else(|err| {
+            let res = match *aligned {
+                Some(ref mut aligned) => read_direct(file, position, aligned, offset, data),
+                None => file.read(data).map(|size| {
+                    *position += size as u64;
+                    size
+                }),
+            };
+
+            try!(res.or_else(|err| {
                 gst_error!(cat, obj: src, "Failed to read: {:?}", err);
                 Err(FlowError::Error(gst_error_msg!(
                     gst::ResourceError::Read,
@@ -181,8 +246,6 @@ impl SourceImpl for FileSrc {
             }))
         };
 
-        *position += size as u64;
-
         buffer.set_size(size);
 
         Ok(())
@@ -191,4 +254,55 @@ impl SourceImpl for FileSrc {
     fn seek(&mut self, _src: &BaseSrc, _: u64, _: Option<u64>) -> Result<(), gst::ErrorMessage> {
         Ok(())
     }
+
+    fn set_property(&mut self, src: &BaseSrc, property: &Property, value: &glib::Value) {
+        match *property {
+            Property::String("location", ..) => {
+                location::set_location(src, value.get());
+            }
+            Property::Boolean("direct-io", ..) => {
+                self.direct_io = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, src: &BaseSrc, property: &Property) -> Result<glib::Value, ()> {
+        match *property {
+            Property::String("location", ..) => Ok(location::get_location(src).to_value()),
+            Property::Boolean("direct-io", ..) => Ok(self.direct_io.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+// Reads the aligned area around the requested range into `aligned` and copies the requested
+// part from there. `position` is always aligned afterwards unless the end of the file was reached
+fn read_direct(
+    file: &mut File,
+    position: &mut u64,
+    aligned: &mut AlignedBuffer,
+    offset: u64,
+    data: &mut [u8],
+) -> io::Result<usize> {
+    let skip = (offset - directio::align_down(offset)) as usize;
+    let len = directio::align_up(skip + data.len());
+    if aligned.capacity() < len {
+        *aligned = AlignedBuffer::new(len);
+    }
+
+    let mut read = 0;
+    while read < len {
+        let size = file.read(&mut aligned.as_mut_slice()[read..len])?;
+        if size == 0 {
+            break;
+        }
+        read += size;
+    }
+    *position += read as u64;
+
+    let size = cmp::min(read.saturating_sub(skip), data.len());
+    data[..size].copy_from_slice(&aligned.as_mut_slice()[skip..skip + size]);
+
+    Ok(size)
 }
@@ -8,24 +8,30 @@
 
 #![crate_type = "cdylib"]
 
+extern crate glib;
 #[macro_use]
 extern crate gst_plugin;
 extern crate gst_plugin_simple;
 #[macro_use]
 extern crate gstreamer as gst;
+#[cfg(target_os = "linux")]
+extern crate libc;
 extern crate url;
 
 use gst_plugin_simple::source::*;
 use gst_plugin_simple::sink::*;
+use gst_plugin::registration::*;
 
+mod directio;
 mod filesrc;
 mod filesink;
+mod location;
 
 use filesrc::FileSrc;
 use filesink::FileSink;
 
 fn plugin_init(plugin: &gst::Plugin) -> bool {
-    source_register(
+    let source_registered = source_register(
         plugin,
         SourceInfo {
             name: "rsfilesrc".into(),
@@ -33,14 +39,15 @@ fn plugin_init(plugin: &gst::Plugin) -> bool {
             description: "Reads local files".into(),
             classification: "Source/File".into(),
             author: "Sebastian Dröge <sebastian@centricular.com>".into(),
-            rank: 256 + 100,
+            rank: RANK_PRIMARY + 100,
             create_instance: FileSrc::new_boxed,
             protocols: vec!["file".into()],
             push_only: false,
+            properties: &filesrc::PROPERTIES,
         },
     );
 
-    sink_register(
+    let sink_registered = sink_register(
         plugin,
         SinkInfo {
             name: "rsfilesink".into(),
@@ -48,23 +55,24 @@ fn plugin_init(plugin: &gst::Plugin) -> bool {
             description: "Writes to local files".into(),
             classification: "Sink/File".into(),
             author: "Luis de Bethencourt <luisbg@osg.samsung.com>".into(),
-            rank: 256 + 100,
+            rank: RANK_PRIMARY + 100,
             create_instance: FileSink::new_boxed,
             protocols: vec!["file".into()],
+            properties: &filesink::PROPERTIES,
         },
     );
 
-    true
+    source_registered && sink_registered
 }
 
 plugin_define!(
-    b"rsfile\0",
-    b"Rust File Plugin\0",
+    "rsfile",
+    "Rust File Plugin",
     plugin_init,
-    b"1.0\0",
-    b"MIT/X11\0",
-    b"rsfile\0",
-    b"rsfile\0",
-    b"https://github.com/sdroege/rsplugin\0",
-    b"2016-12-08\0"
+    "1.0",
+    "MIT/X11",
+    "rsfile",
+    "rsfile",
+    "https://github.com/sdroege/rsplugin",
+    "2016-12-08"
 );
@@ -0,0 +1,40 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// The location property is only a different view on the URI property of the base classes
+
+use std::env;
+use std::path::Path;
+use url::Url;
+
+use glib;
+use glib::prelude::*;
+
+pub fn set_location<T: IsA<glib::Object>>(obj: &T, location: Option<String>) {
+    let uri = location.and_then(|location| {
+        let path = Path::new(&location);
+        if path.is_absolute() {
+            Url::from_file_path(path).ok()
+        } else {
+            env::current_dir()
+                .ok()
+                .and_then(|dir| Url::from_file_path(dir.join(path)).ok())
+        }
+    });
+
+    obj.set_property("uri", &uri.map(String::from)).unwrap();
+}
+
+pub fn get_location<T: IsA<glib::Object>>(obj: &T) -> Option<String> {
+    obj.get_property("uri")
+        .ok()
+        .and_then(|value| value.get::<String>())
+        .and_then(|uri| Url::parse(&uri).ok())
+        .and_then(|uri| uri.to_file_path().ok())
+        .and_then(|path| path.to_str().map(String::from))
+}
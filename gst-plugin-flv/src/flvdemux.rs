@@ -8,6 +8,7 @@
 
 use std::cmp;
 use std::io::{Cursor, Write};
+use std::u64;
 
 use nom;
 use nom::IResult;
@@ -17,8 +18,10 @@ use flavors::parser as flavors;
 use gst_plugin::adapter::*;
 use gst_plugin::bytes::*;
 use gst_plugin::element::*;
+use gst_plugin::sandbox;
 use gst_plugin_simple::demuxer::*;
 use gst_plugin_simple::error::*;
+use gst_plugin_simple::index::SeekIndex;
 
 use gst;
 
@@ -27,6 +30,9 @@ use num_rational::Rational32;
 const AUDIO_STREAM_ID: u32 = 0;
 const VIDEO_STREAM_ID: u32 = 1;
 
+// GST_BUFFER_OFFSET_NONE
+const BUFFER_OFFSET_NONE: u64 = u64::MAX;
+
 #[derive(Debug)]
 enum State {
     Stopped,
@@ -483,6 +489,14 @@ pub struct FlvDemux {
     cat: gst::DebugCategory,
     state: State,
     adapter: Adapter,
+    // Offset right after the last byte in the adapter
+    end_offset: u64,
+    // Waiting for data from the offset we seeked to
+    seeking: bool,
+    // Offset of the first tag, right after the header
+    first_tag_offset: Option<u64>,
+    // Keyframe positions, or all audio tags at least one second apart for audio-only files
+    index: SeekIndex,
     // Only in >= State::Streaming
     streaming_state: Option<StreamingState>,
 }
@@ -497,10 +511,19 @@ impl FlvDemux {
             ),
             state: State::Stopped,
             adapter: Adapter::new(),
+            end_offset: 0,
+            seeking: false,
+            first_tag_offset: None,
+            index: SeekIndex::new(),
             streaming_state: None,
         }
     }
 
+    // Offset of the first byte in the adapter
+    fn offset(&self) -> u64 {
+        self.end_offset - self.adapter.get_available() as u64
+    }
+
     pub fn new_boxed(demuxer: &Element) -> Box<DemuxerImpl> {
         Box::new(Self::new(demuxer))
     }
@@ -989,6 +1012,7 @@ impl FlvDemux {
             } => {
                 self.state = State::Streaming;
                 self.streaming_state = Some(StreamingState::new(audio, video));
+                self.first_tag_offset = Some(self.offset());
 
                 Ok(HandleBufferResult::Again)
             }
@@ -1006,6 +1030,10 @@ impl FlvDemux {
                     return Ok(HandleBufferResult::NeedMoreData);
                 }
 
+                // Tags start with the size of the previous tag, so this is where parsing can
+                // be resumed from after a seek
+                let tag_offset = self.offset();
+
                 let mut data = [0u8; 16];
                 self.adapter.peek_into(&mut data).unwrap();
 
@@ -1029,6 +1057,14 @@ impl FlvDemux {
                     IResult::Done(_, tag_header) => tag_header,
                 };
 
+                // The whole tag is collected in the adapter before handling it
+                if let Err(err) = sandbox::reserve(15 + tag_header.data_size as usize) {
+                    return Err(FlowError::Error(gst_error_msg!(
+                        gst::StreamError::Demux,
+                        ["Tag too big: {}", err]
+                    )));
+                }
+
                 let res = match tag_header.tag_type {
                     flavors::TagType::Script => {
                         gst_trace!(self.cat, obj: demuxer, "Found script tag");
@@ -1057,9 +1093,25 @@ impl FlvDemux {
                     }
                 };
 
-                if let Ok(HandleBufferResult::BufferForStream(_, ref buffer)) = res {
+                if let Ok(HandleBufferResult::BufferForStream(stream, ref buffer)) = res {
                     let streaming_state = self.streaming_state.as_mut().unwrap();
 
+                    let time = gst::ClockTime::from_mseconds(tag_header.timestamp as u64)
+                        .0
+                        .unwrap();
+                    let index_tag = if stream == VIDEO_STREAM_ID {
+                        !buffer.get_flags().contains(gst::BufferFlags::DELTA_UNIT)
+                    } else {
+                        !streaming_state.expect_video
+                            && self.index
+                                .last()
+                                .map(|last| time >= last.time + gst::SECOND_VAL)
+                                .unwrap_or(true)
+                    };
+                    if index_tag {
+                        self.index.add(time, tag_offset);
+                    }
+
                     if buffer.get_pts() != gst::CLOCK_TIME_NONE {
                         let pts = buffer.get_pts();
                         streaming_state.last_position = streaming_state
@@ -1096,6 +1148,10 @@ impl DemuxerImpl for FlvDemux {
     fn stop(&mut self, demuxer: &Element) -> Result<(), gst::ErrorMessage> {
         self.state = State::Stopped;
         self.adapter.clear();
+        self.end_offset = 0;
+        self.seeking = false;
+        self.first_tag_offset = None;
+        self.index.clear();
         self.streaming_state = None;
 
         Ok(())
@@ -1107,7 +1163,37 @@ impl DemuxerImpl for FlvDemux {
         start: gst::ClockTime,
         stop: gst::ClockTime,
     ) -> Result<SeekResult, gst::ErrorMessage> {
-        unimplemented!();
+        let start = start.0.unwrap_or(0);
+
+        let first_tag_offset = match (self.first_tag_offset, self.streaming_state.as_ref()) {
+            (Some(first_tag_offset), Some(_)) => first_tag_offset,
+            _ => return Ok(SeekResult::TooEarly),
+        };
+
+        // Parts of the file that were not parsed yet are not in the index, in that case
+        // parsing continues from the last known position before the seek position
+        let (time, offset) = match self.index.lookup_time(start) {
+            Some(entry) => (entry.time, entry.offset),
+            None => (0, first_tag_offset),
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: demuxer,
+            "Seeking to {} at offset {} for seek position {}",
+            gst::ClockTime::from_nseconds(time),
+            offset,
+            gst::ClockTime::from_nseconds(start)
+        );
+
+        self.adapter.clear();
+        self.end_offset = offset;
+        self.seeking = true;
+        self.state = State::Streaming;
+        let streaming_state = self.streaming_state.as_mut().unwrap();
+        streaming_state.last_position = gst::ClockTime::from_nseconds(time);
+
+        Ok(SeekResult::Ok(offset))
     }
 
     fn handle_buffer(
@@ -1116,6 +1202,28 @@ impl DemuxerImpl for FlvDemux {
         buffer: Option<gst::Buffer>,
     ) -> Result<HandleBufferResult, FlowError> {
         if let Some(buffer) = buffer {
+            let offset = buffer.get_offset();
+
+            if offset != BUFFER_OFFSET_NONE && offset != self.end_offset {
+                // Data from before the seek was handled upstream
+                if self.seeking {
+                    gst_trace!(self.cat, obj: demuxer, "Dropping buffer at offset {}", offset);
+                    return Ok(HandleBufferResult::NeedMoreData);
+                }
+
+                gst_debug!(
+                    self.cat,
+                    obj: demuxer,
+                    "Discontinuity from offset {} to {}",
+                    self.end_offset,
+                    offset
+                );
+                self.adapter.clear();
+                self.end_offset = offset;
+            }
+
+            self.seeking = false;
+            self.end_offset += buffer.get_size() as u64;
             self.adapter.push(buffer);
         }
 
@@ -1128,7 +1236,7 @@ impl DemuxerImpl for FlvDemux {
     }
 
     fn is_seekable(&self, demuxer: &Element) -> bool {
-        false
+        self.streaming_state.is_some()
     }
 
     fn get_position(&self, demuxer: &Element) -> gst::ClockTime {
@@ -1150,4 +1258,8 @@ impl DemuxerImpl for FlvDemux {
 
         gst::CLOCK_TIME_NONE
     }
+
+    fn get_seek_index(&mut self) -> Option<&mut SeekIndex> {
+        Some(&mut self.index)
+    }
 }
@@ -20,6 +20,7 @@ extern crate num_rational;
 extern crate url;
 
 use gst_plugin_simple::demuxer::*;
+use gst_plugin::registration::*;
 
 mod flvdemux;
 
@@ -34,24 +35,22 @@ fn plugin_init(plugin: &gst::Plugin) -> bool {
             description: "Demuxes FLV Streams".into(),
             classification: "Codec/Demuxer".into(),
             author: "Sebastian Dröge <sebastian@centricular.com>".into(),
-            rank: 256 + 100,
+            rank: RANK_PRIMARY + 100,
             create_instance: FlvDemux::new_boxed,
             input_caps: gst::Caps::new_simple("video/x-flv", &[]),
             output_caps: gst::Caps::new_any(),
         },
-    );
-
-    true
+    )
 }
 
 plugin_define!(
-    b"rsflv\0",
-    b"Rust FLV Plugin\0",
+    "rsflv",
+    "Rust FLV Plugin",
     plugin_init,
-    b"1.0\0",
-    b"MIT/X11\0",
-    b"rsflv\0",
-    b"rsflv\0",
-    b"https://github.com/sdroege/rsplugin\0",
-    b"2016-12-08\0"
+    "1.0",
+    "MIT/X11",
+    "rsflv",
+    "rsflv",
+    "https://github.com/sdroege/rsplugin",
+    "2016-12-08"
 );
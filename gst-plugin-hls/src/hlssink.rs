@@ -0,0 +1,903 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::u32;
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_sink::*;
+use gst_plugin::bytes::parse_hex;
+use gst_plugin::tags::{StreamInfo, TrackRole};
+
+use openssl::symm::{self, Cipher};
+
+use playlist::{self, Key, Playlist, PlaylistType, Rendition, Segment};
+
+const DEFAULT_LOCATION: &str = "segment%05d.ts";
+const DEFAULT_INIT_LOCATION: &str = "init.mp4";
+const DEFAULT_PLAYLIST_LOCATION: &str = "playlist.m3u8";
+const DEFAULT_TARGET_DURATION: u32 = 6;
+const DEFAULT_PLAYLIST_LENGTH: u32 = 5;
+const DEFAULT_MAX_FILES: u32 = 10;
+const DEFAULT_PLAYLIST_TYPE: &str = "live";
+
+static PROPERTIES: [Property; 12] = [
+    Property::String(
+        "location",
+        "Location",
+        "Location of the segment files, %05d is replaced by the segment number",
+        Some(DEFAULT_LOCATION),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "init-location",
+        "Init Location",
+        "Location of the initialization segment for fMP4 input",
+        Some(DEFAULT_INIT_LOCATION),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "playlist-location",
+        "Playlist Location",
+        "Location of the playlist file",
+        Some(DEFAULT_PLAYLIST_LOCATION),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "playlist-root",
+        "Playlist Root",
+        "Base URI for the segments in the playlist (default: relative to the playlist)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "playlist-type",
+        "Playlist Type",
+        "Type of the playlist: \"live\" only lists the latest segments, \"event\" all of them",
+        Some(DEFAULT_PLAYLIST_TYPE),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "target-duration",
+        "Target Duration",
+        "Segment duration in seconds, segments start at the next keyframe after this",
+        (1, u32::MAX),
+        DEFAULT_TARGET_DURATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "playlist-length",
+        "Playlist Length",
+        "Number of segments listed in live playlists (0 = all)",
+        (0, u32::MAX),
+        DEFAULT_PLAYLIST_LENGTH,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "max-files",
+        "Max Files",
+        "Number of segments to keep before deleting the oldest for live playlists (0 = all)",
+        (0, u32::MAX),
+        DEFAULT_MAX_FILES,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "iframe-playlist-location",
+        "I-Frame Playlist Location",
+        "Location of an I-frame only playlist for trick-play, referencing the keyframes in the \
+         segments by byte range (default: none)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "master-playlist-location",
+        "Master Playlist Location",
+        "Location of a master playlist listing the media playlist, as audio rendition with \
+         language and role if the stream is tagged with them (default: none)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "key",
+        "Key",
+        "16 byte AES-128 key as hex string to encrypt the segments with (default: none)",
+        None,
+        PropertyMutability::Writable,
+    ),
+    Property::String(
+        "key-uri",
+        "Key URI",
+        "URI from which clients get the key, written into the playlist",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: String,
+    init_location: String,
+    playlist_location: String,
+    playlist_root: Option<String>,
+    playlist_type: String,
+    target_duration: u32,
+    playlist_length: u32,
+    max_files: u32,
+    iframe_playlist_location: Option<String>,
+    master_playlist_location: Option<String>,
+    key: Option<String>,
+    key_uri: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: DEFAULT_LOCATION.into(),
+            init_location: DEFAULT_INIT_LOCATION.into(),
+            playlist_location: DEFAULT_PLAYLIST_LOCATION.into(),
+            playlist_root: None,
+            playlist_type: DEFAULT_PLAYLIST_TYPE.into(),
+            target_duration: DEFAULT_TARGET_DURATION,
+            playlist_length: DEFAULT_PLAYLIST_LENGTH,
+            max_files: DEFAULT_MAX_FILES,
+            iframe_playlist_location: None,
+            master_playlist_location: None,
+            key: None,
+            key_uri: None,
+        }
+    }
+}
+
+// Replaces a printf style %d or %0<width>d with the segment number
+fn format_location(template: &str, number: u32) -> String {
+    let start = match template.find('%') {
+        None => return String::from(template),
+        Some(start) => start,
+    };
+    let end = match template[start..].find('d') {
+        None => return String::from(template),
+        Some(end) => start + end,
+    };
+
+    let format = &template[(start + 1)..end];
+    let width = if format.is_empty() {
+        Some(0)
+    } else if format.starts_with('0') {
+        format[1..].parse::<usize>().ok()
+    } else {
+        None
+    };
+
+    match width {
+        None => String::from(template),
+        Some(width) => format!(
+            "{}{:0width$}{}",
+            &template[..start],
+            number,
+            &template[(end + 1)..],
+            width = width
+        ),
+    }
+}
+
+// Audio rendition for the language and role of the stream, if it has any
+fn rendition(info: &StreamInfo) -> Option<Rendition> {
+    if info.language.is_none() && info.role.is_none() {
+        return None;
+    }
+
+    let language = info.language_rfc5646();
+    let mut name = language.clone().unwrap_or_else(|| String::from("und"));
+    if let Some(role) = info.role {
+        if role != TrackRole::Main {
+            name.push(' ');
+            name.push_str(role.as_str());
+        }
+    }
+
+    let characteristics = match info.role {
+        Some(TrackRole::Description) => Some("public.accessibility.describes-video"),
+        Some(TrackRole::Caption) => Some(
+            "public.accessibility.transcribes-spoken-dialog,\
+             public.accessibility.describes-music-and-sound",
+        ),
+        _ => None,
+    };
+
+    Some(Rendition {
+        name: name,
+        language: language,
+        default: info.is_default(),
+        characteristics: characteristics,
+    })
+}
+
+fn parse_key(s: &str) -> Option<[u8; 16]> {
+    let data = parse_hex(s)?;
+    if data.len() != 16 {
+        return None;
+    }
+
+    let mut key = [0; 16];
+    key.copy_from_slice(&data);
+    Some(key)
+}
+
+// The IV of a segment is its sequence number as 128 bit big endian integer, like the default
+// of HLS for playlists without explicit IV
+fn segment_iv(number: u32) -> [u8; 16] {
+    let mut iv = [0; 16];
+    for (i, b) in iv[12..].iter_mut().enumerate() {
+        *b = (number >> (8 * (3 - i))) as u8;
+    }
+    iv
+}
+
+// Keyframe inside the current segment
+struct IFrame {
+    pts: u64,
+    offset: usize,
+    length: usize,
+}
+
+struct CurrentSegment {
+    location: String,
+    number: u32,
+    data: Vec<u8>,
+    start: u64,
+    iframes: Vec<IFrame>,
+    // If the last buffer belonged to the last keyframe
+    in_iframe: bool,
+}
+
+struct State {
+    settings: Settings,
+    fmp4: bool,
+    // Header buffers of fMP4 input until the first media data
+    init: Option<Vec<u8>>,
+    number: u32,
+    current: Option<CurrentSegment>,
+    // End of the last buffer
+    end: Option<u64>,
+    playlist: Playlist,
+    iframe_playlist: Option<Playlist>,
+    // AES-128 key and its URI if the segments are encrypted
+    key: Option<([u8; 16], String)>,
+    // Written segments that are deleted once there are more than max-files
+    files: VecDeque<String>,
+    // Language and role from the tags of the stream
+    info: StreamInfo,
+    // Peak bitrate of all segments in bits per second
+    bandwidth: u64,
+}
+
+struct HlsSink {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl HlsSink {
+    fn new(_sink: &BaseSink) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "hlsrssink",
+                gst::DebugColorFlags::empty(),
+                "HLS sink",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseSinkClass) {
+        klass.set_metadata(
+            "HLS Sink",
+            "Sink/Network",
+            "Writes MPEG-TS or fMP4 segments and HLS media and master playlists",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let mut caps = gst::Caps::new_simple("video/mpegts", &[("systemstream", &true)]);
+        caps.get_mut()
+            .unwrap()
+            .append(gst::Caps::new_simple("video/quicktime", &[]));
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        // The following signals allow to store the files somewhere else than the local
+        // file system, e.g. to upload them to S3. If a handler returns true the file is
+        // considered handled and not written or deleted locally.
+        klass.add_signal(
+            "write-segment",
+            &[String::static_type(), gst::Buffer::static_type()],
+            bool::static_type(),
+        );
+        klass.add_signal(
+            "write-playlist",
+            &[String::static_type(), String::static_type()],
+            bool::static_type(),
+        );
+        klass.add_signal(
+            "delete-segment",
+            &[String::static_type()],
+            bool::static_type(),
+        );
+    }
+
+    fn init(element: &BaseSink) -> Box<BaseSinkImpl<BaseSink>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    fn emit_handled(&self, element: &BaseSink, signal: &str, args: &[&glib::ToValue]) -> bool {
+        element
+            .emit(signal, args)
+            .ok()
+            .and_then(|v| v)
+            .and_then(|v| v.get::<bool>())
+            .unwrap_or(false)
+    }
+
+    fn write_file(&self, element: &BaseSink, location: &str, data: Vec<u8>) -> Result<(), String> {
+        let buffer = gst::Buffer::from_mut_slice(data).unwrap();
+        if self.emit_handled(element, "write-segment", &[&location, &buffer]) {
+            return Ok(());
+        }
+
+        let map = buffer.map_readable().unwrap();
+        fs::File::create(location)
+            .and_then(|mut file| file.write_all(map.as_slice()))
+            .map_err(|err| format!("Failed to write {}: {}", location, err))
+    }
+
+    fn write_playlist(
+        &self,
+        element: &BaseSink,
+        location: &str,
+        content: String,
+    ) -> Result<(), String> {
+        if self.emit_handled(element, "write-playlist", &[location, &content]) {
+            return Ok(());
+        }
+
+        // Write to a temporary file first so that clients never see a partial playlist
+        let tmp_location = format!("{}.tmp", location);
+        fs::File::create(&tmp_location)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .and_then(|_| fs::rename(&tmp_location, location))
+            .map_err(|err| format!("Failed to write playlist {}: {}", location, err))
+    }
+
+    fn write_playlists(&self, element: &BaseSink, state: &State) -> Result<(), String> {
+        let settings = &state.settings;
+        self.write_playlist(element, &settings.playlist_location, state.playlist.render())?;
+
+        if let (&Some(ref location), &Some(ref playlist)) =
+            (&settings.iframe_playlist_location, &state.iframe_playlist)
+        {
+            self.write_playlist(element, location, playlist.render())?;
+        }
+
+        if let Some(ref location) = settings.master_playlist_location {
+            // Relative to the master playlist, the playlist root only applies to segments
+            let uri = Path::new(&settings.playlist_location)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| settings.playlist_location.clone());
+            let rendition = rendition(&state.info);
+            let content = playlist::render_master(&uri, state.bandwidth, rendition.as_ref());
+            self.write_playlist(element, location, content)?;
+        }
+
+        Ok(())
+    }
+
+    fn segment_uri(&self, settings: &Settings, location: &str) -> String {
+        let name = Path::new(location)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from(location));
+
+        match settings.playlist_root {
+            None => name,
+            Some(ref root) => format!("{}/{}", root.trim_right_matches('/'), name),
+        }
+    }
+
+    fn finish_segment(&self, element: &BaseSink, state: &mut State) -> Result<(), String> {
+        let current = match state.current.take() {
+            None => return Ok(()),
+            Some(current) => current,
+        };
+        let end = state.end.unwrap_or(current.start);
+        let duration = end.saturating_sub(current.start) as f64 / gst::SECOND_VAL as f64;
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Writing segment {} with duration {:.3}s",
+            current.location,
+            duration
+        );
+
+        // Each segment is encrypted as a whole with AES-128-CBC and PKCS7 padding
+        let (data, key) = match state.key {
+            None => (current.data, None),
+            Some((ref key, ref key_uri)) => {
+                let iv = segment_iv(current.number);
+                let data = symm::encrypt(Cipher::aes_128_cbc(), key, Some(&iv), &current.data)
+                    .map_err(|err| format!("Failed to encrypt {}: {}", current.location, err))?;
+                let key = Key {
+                    uri: key_uri.clone(),
+                    iv: iv,
+                };
+                (data, Some(key))
+            }
+        };
+        if duration > 0.0 {
+            let bandwidth = (data.len() as f64 * 8.0 / duration).ceil() as u64;
+            state.bandwidth = cmp::max(state.bandwidth, bandwidth);
+        }
+        self.write_file(element, &current.location, data)?;
+
+        let uri = self.segment_uri(&state.settings, &current.location);
+        state.playlist.add_segment(Segment {
+            uri: uri.clone(),
+            duration: duration,
+            byte_range: None,
+            key: key,
+        });
+
+        if let Some(ref mut iframe_playlist) = state.iframe_playlist {
+            // Each keyframe lasts until the next one, the last until the end of the segment
+            for (i, iframe) in current.iframes.iter().enumerate() {
+                let next = current.iframes.get(i + 1).map(|next| next.pts).unwrap_or(end);
+                iframe_playlist.add_segment(Segment {
+                    uri: uri.clone(),
+                    duration: next.saturating_sub(iframe.pts) as f64 / gst::SECOND_VAL as f64,
+                    byte_range: Some((iframe.length as u64, iframe.offset as u64)),
+                    key: None,
+                });
+            }
+
+            // Only list keyframes of segments that are still in the media playlist
+            if let Some(first) = state.playlist.segments.front() {
+                iframe_playlist.remove_segments_before(&first.uri);
+            }
+        }
+
+        if state.playlist.playlist_type == PlaylistType::Live {
+            state.files.push_back(current.location);
+
+            let max_files = state.settings.max_files as usize;
+            while max_files > 0 && state.files.len() > max_files {
+                let location = state.files.pop_front().unwrap();
+                gst_debug!(self.cat, obj: element, "Deleting segment {}", location);
+
+                if !self.emit_handled(element, "delete-segment", &[&location]) {
+                    if let Err(err) = fs::remove_file(&location) {
+                        gst_warning!(
+                            self.cat,
+                            obj: element,
+                            "Failed to delete {}: {}",
+                            location,
+                            err
+                        );
+                    }
+                }
+            }
+        }
+
+        self.write_playlists(element, state)
+    }
+}
+
+impl ObjectImpl<BaseSink> for HlsSink {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => {
+                settings.location = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_LOCATION.into());
+            }
+            Property::String("init-location", ..) => {
+                settings.init_location = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_INIT_LOCATION.into());
+            }
+            Property::String("playlist-location", ..) => {
+                settings.playlist_location = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_PLAYLIST_LOCATION.into());
+            }
+            Property::String("playlist-root", ..) => {
+                settings.playlist_root = value.get();
+            }
+            Property::String("playlist-type", ..) => {
+                settings.playlist_type = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_PLAYLIST_TYPE.into());
+            }
+            Property::UInt("target-duration", ..) => {
+                settings.target_duration = value.get().unwrap();
+            }
+            Property::UInt("playlist-length", ..) => {
+                settings.playlist_length = value.get().unwrap();
+            }
+            Property::UInt("max-files", ..) => {
+                settings.max_files = value.get().unwrap();
+            }
+            Property::String("iframe-playlist-location", ..) => {
+                settings.iframe_playlist_location = value.get();
+            }
+            Property::String("master-playlist-location", ..) => {
+                settings.master_playlist_location = value.get();
+            }
+            Property::String("key", ..) => {
+                settings.key = value.get();
+            }
+            Property::String("key-uri", ..) => {
+                settings.key_uri = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => Ok(settings.location.to_value()),
+            Property::String("init-location", ..) => Ok(settings.init_location.to_value()),
+            Property::String("playlist-location", ..) => Ok(settings.playlist_location.to_value()),
+            Property::String("playlist-root", ..) => Ok(settings.playlist_root.to_value()),
+            Property::String("playlist-type", ..) => Ok(settings.playlist_type.to_value()),
+            Property::UInt("target-duration", ..) => Ok(settings.target_duration.to_value()),
+            Property::UInt("playlist-length", ..) => Ok(settings.playlist_length.to_value()),
+            Property::UInt("max-files", ..) => Ok(settings.max_files.to_value()),
+            Property::String("iframe-playlist-location", ..) => {
+                Ok(settings.iframe_playlist_location.to_value())
+            }
+            Property::String("master-playlist-location", ..) => {
+                Ok(settings.master_playlist_location.to_value())
+            }
+            Property::String("key-uri", ..) => Ok(settings.key_uri.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseSink> for HlsSink {}
+
+impl BaseSinkImpl<BaseSink> for HlsSink {
+    fn start(&self, element: &BaseSink) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let playlist_type = match PlaylistType::from_name(&settings.playlist_type) {
+            None => {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::Settings,
+                    ["Unsupported playlist type '{}'", settings.playlist_type]
+                );
+                return false;
+            }
+            Some(playlist_type) => playlist_type,
+        };
+
+        let key = match (settings.key.as_ref(), settings.key_uri.as_ref()) {
+            (None, None) => None,
+            (Some(key), Some(key_uri)) => {
+                let key = match parse_key(key) {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::ResourceError::Settings,
+                            ["Key has to be 16 bytes as hex string"]
+                        );
+                        return false;
+                    }
+                    Some(key) => key,
+                };
+                Some((key, key_uri.clone()))
+            }
+            _ => {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::Settings,
+                    ["Both key and key-uri are needed for encryption"]
+                );
+                return false;
+            }
+        };
+
+        // Byte ranges into encrypted segments can't be decrypted on their own
+        if key.is_some() && settings.iframe_playlist_location.is_some() {
+            gst_element_error!(
+                element,
+                gst::ResourceError::Settings,
+                ["I-frame playlists are not supported with encryption"]
+            );
+            return false;
+        }
+
+        let playlist = Playlist::new(
+            playlist_type,
+            settings.playlist_length as usize,
+            settings.target_duration,
+        );
+        let iframe_playlist = settings.iframe_playlist_location.as_ref().map(|_| {
+            let mut iframe_playlist = playlist.clone();
+            iframe_playlist.iframes_only = true;
+            // Trimmed together with the media playlist
+            iframe_playlist.length = 0;
+            iframe_playlist
+        });
+
+        *self.state.lock().unwrap() = Some(State {
+            settings: settings,
+            fmp4: false,
+            init: None,
+            number: 0,
+            current: None,
+            end: None,
+            playlist: playlist,
+            iframe_playlist: iframe_playlist,
+            key: key,
+            files: VecDeque::new(),
+            info: StreamInfo::default(),
+            bandwidth: 0,
+        });
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseSink) -> bool {
+        // Incomplete segments are dropped, the playlist only ends at EOS
+        *self.state.lock().unwrap() = None;
+        true
+    }
+
+    fn set_caps(&self, element: &BaseSink, caps: &gst::CapsRef) -> bool {
+        let fmp4 = caps.get_structure(0)
+            .map(|s| s.get_name() == "video/quicktime")
+            .unwrap_or(false);
+
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.fmp4 = fmp4;
+        }
+
+        element.parent_set_caps(caps)
+    }
+
+    fn event(&self, element: &BaseSink, event: gst::Event) -> bool {
+        let eos = match event.view() {
+            gst::EventView::Eos(..) => true,
+            gst::EventView::Tag(ref e) => {
+                // Only used for the master playlist, which is written with the next segment
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.info.update(e.get_tag());
+                }
+                false
+            }
+            _ => false,
+        };
+
+        if eos {
+            let mut state_guard = self.state.lock().unwrap();
+            if let Some(ref mut state) = *state_guard {
+                let res = self.finish_segment(element, state).and_then(|_| {
+                    state.playlist.ended = true;
+                    if let Some(ref mut iframe_playlist) = state.iframe_playlist {
+                        iframe_playlist.ended = true;
+                    }
+                    self.write_playlists(element, state)
+                });
+
+                if let Err(err) = res {
+                    gst_element_error!(element, gst::ResourceError::Write, ["{}", err]);
+                    return false;
+                }
+            }
+        }
+
+        element.parent_event(event)
+    }
+
+    fn render(&self, element: &BaseSink, buffer: &gst::BufferRef) -> gst::FlowReturn {
+        let map = match buffer.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map,
+        };
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return gst::FlowReturn::Flushing,
+            Some(ref mut state) => state,
+        };
+
+        // Collect the initialization segment of fMP4 input until the first media data
+        if state.fmp4 && buffer.get_flags().contains(gst::BufferFlags::HEADER) {
+            state
+                .init
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(map.as_slice());
+            return gst::FlowReturn::Ok;
+        }
+
+        if let Some(init) = state.init.take() {
+            let location = state.settings.init_location.clone();
+            gst_debug!(self.cat, obj: element, "Writing init segment {}", location);
+            if let Err(err) = self.write_file(element, &location, init) {
+                gst_element_error!(element, gst::ResourceError::Write, ["{}", err]);
+                return gst::FlowReturn::Error;
+            }
+            state.playlist.init_uri = Some(self.segment_uri(&state.settings, &location));
+        }
+
+        let pts = match buffer.get_pts().0 {
+            None => {
+                gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(pts) => pts,
+        };
+        let keyframe = !buffer.get_flags().contains(gst::BufferFlags::DELTA_UNIT);
+
+        // Segments start at keyframes once the target duration is reached
+        let target_duration = u64::from(state.settings.target_duration) * gst::SECOND_VAL;
+        let new_segment = match state.current {
+            None => true,
+            Some(ref current) => keyframe && pts >= current.start + target_duration,
+        };
+
+        if new_segment {
+            // The previous segment ends where the new one starts
+            if state.current.is_some() {
+                state.end = Some(pts);
+            }
+            if let Err(err) = self.finish_segment(element, state) {
+                gst_element_error!(element, gst::ResourceError::Write, ["{}", err]);
+                return gst::FlowReturn::Error;
+            }
+
+            let number = state.number;
+            let location = format_location(&state.settings.location, number);
+            state.number = state.number.wrapping_add(1);
+            state.current = Some(CurrentSegment {
+                location: location,
+                number: number,
+                data: Vec::new(),
+                start: pts,
+                iframes: Vec::new(),
+                in_iframe: false,
+            });
+        }
+
+        let end = pts + buffer.get_duration().0.unwrap_or(0);
+        if state.end.map(|e| end > e).unwrap_or(true) {
+            state.end = Some(end);
+        }
+
+        let current = state.current.as_mut().unwrap();
+
+        // A keyframe can be split over multiple buffers, the following ones are delta units
+        // with the same PTS
+        let offset = current.data.len();
+        if keyframe {
+            current.iframes.push(IFrame {
+                pts: pts,
+                offset: offset,
+                length: map.len(),
+            });
+            current.in_iframe = true;
+        } else if current.in_iframe {
+            let iframe = current.iframes.last_mut().unwrap();
+            if iframe.pts == pts {
+                iframe.length += map.len();
+            } else {
+                current.in_iframe = false;
+            }
+        }
+
+        current.data.extend_from_slice(map.as_slice());
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct HlsSinkStatic;
+
+impl ImplTypeStatic<BaseSink> for HlsSinkStatic {
+    fn get_name(&self) -> &str {
+        "HlsSink"
+    }
+
+    fn new(&self, element: &BaseSink) -> Box<BaseSinkImpl<BaseSink>> {
+        HlsSink::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseSinkClass) {
+        HlsSink::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let hlssink_static = HlsSinkStatic;
+    register_type(hlssink_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_location() {
+        assert_eq!(format_location("segment%05d.ts", 42), "segment00042.ts");
+        assert_eq!(format_location("/tmp/hls/%d.m4s", 7), "/tmp/hls/7.m4s");
+        assert_eq!(format_location("segment.ts", 1), "segment.ts");
+        assert_eq!(format_location("segment%s.ts", 1), "segment%s.ts");
+    }
+
+    #[test]
+    fn test_key() {
+        let key = parse_key("0x000102030405060708090a0b0c0d0e0F").unwrap();
+        assert_eq!(key[1], 0x01);
+        assert_eq!(key[15], 0x0f);
+        assert_eq!(parse_key("0001"), None);
+        assert_eq!(parse_key(&"zz".repeat(16)), None);
+
+        let iv = segment_iv(0x0102_0304);
+        assert_eq!(&iv[..12], &[0; 12]);
+        assert_eq!(&iv[12..], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_rendition() {
+        assert_eq!(rendition(&StreamInfo::default()), None);
+
+        let info = StreamInfo {
+            language: Some(String::from("ger")),
+            role: Some(TrackRole::Description),
+        };
+        let rendition = rendition(&info).unwrap();
+        assert_eq!(rendition.name, "de description");
+        assert_eq!(rendition.language, Some(String::from("de")));
+        assert!(!rendition.default);
+        assert_eq!(
+            rendition.characteristics,
+            Some("public.accessibility.describes-video")
+        );
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistType {
+    // Only the latest segments are listed
+    Live,
+    // Segments are only ever appended
+    Event,
+}
+
+impl PlaylistType {
+    pub fn from_name(s: &str) -> Option<PlaylistType> {
+        match s {
+            "live" => Some(PlaylistType::Live),
+            "event" => Some(PlaylistType::Event),
+            _ => None,
+        }
+    }
+}
+
+// AES-128 key of an encrypted segment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Key {
+    pub uri: String,
+    pub iv: [u8; 16],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub uri: String,
+    // In seconds
+    pub duration: f64,
+    // Length and offset of the media data in the file, for I-frame playlists
+    pub byte_range: Option<(u64, u64)>,
+    pub key: Option<Key>,
+}
+
+// Language and role of the media playlist, listed as audio rendition in the master playlist
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendition {
+    pub name: String,
+    // RFC 5646 language tag
+    pub language: Option<String>,
+    pub default: bool,
+    // Uniform type identifiers of the accessibility characteristics
+    pub characteristics: Option<&'static str>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Playlist {
+    pub playlist_type: PlaylistType,
+    // Number of segments to keep listed in live playlists, 0 for all
+    pub length: usize,
+    pub target_duration: u32,
+    // URI of the fMP4 initialization segment
+    pub init_uri: Option<String>,
+    pub media_sequence: u64,
+    pub segments: VecDeque<Segment>,
+    // Only lists the keyframes of the segments for trick-play
+    pub iframes_only: bool,
+    pub ended: bool,
+}
+
+impl Playlist {
+    pub fn new(playlist_type: PlaylistType, length: usize, target_duration: u32) -> Playlist {
+        Playlist {
+            playlist_type: playlist_type,
+            length: length,
+            target_duration: target_duration,
+            init_uri: None,
+            media_sequence: 0,
+            segments: VecDeque::new(),
+            iframes_only: false,
+            ended: false,
+        }
+    }
+
+    pub fn add_segment(&mut self, segment: Segment) {
+        self.segments.push_back(segment);
+
+        if self.playlist_type == PlaylistType::Live && self.length > 0 {
+            while self.segments.len() > self.length {
+                self.segments.pop_front();
+                self.media_sequence += 1;
+            }
+        }
+    }
+
+    // Removes all segments before the first one with the given URI
+    pub fn remove_segments_before(&mut self, uri: &str) {
+        while self.segments.front().map(|s| s.uri != uri).unwrap_or(false) {
+            self.segments.pop_front();
+            self.media_sequence += 1;
+        }
+    }
+
+    pub fn render(&self) -> String {
+        // The rounded duration of every segment must not be longer than the target duration
+        let target_duration = self.segments
+            .iter()
+            .map(|s| s.duration.round() as u32)
+            .fold(self.target_duration, cmp::max);
+
+        let mut m3u8 = String::new();
+        m3u8.push_str("#EXTM3U\n");
+        // EXT-X-MAP requires version 6 for media playlists, EXT-X-I-FRAMES-ONLY version 4
+        let version = if self.init_uri.is_some() {
+            6
+        } else if self.iframes_only {
+            4
+        } else {
+            3
+        };
+        writeln!(m3u8, "#EXT-X-VERSION:{}", version).unwrap();
+        writeln!(m3u8, "#EXT-X-TARGETDURATION:{}", target_duration).unwrap();
+        writeln!(m3u8, "#EXT-X-MEDIA-SEQUENCE:{}", self.media_sequence).unwrap();
+        if self.playlist_type == PlaylistType::Event {
+            m3u8.push_str("#EXT-X-PLAYLIST-TYPE:EVENT\n");
+        }
+        if self.iframes_only {
+            m3u8.push_str("#EXT-X-I-FRAMES-ONLY\n");
+        }
+        if let Some(ref init_uri) = self.init_uri {
+            writeln!(m3u8, "#EXT-X-MAP:URI=\"{}\"", init_uri).unwrap();
+        }
+
+        for segment in &self.segments {
+            // Every segment has its own IV, so the key is repeated for each of them
+            if let Some(ref key) = segment.key {
+                write!(m3u8, "#EXT-X-KEY:METHOD=AES-128,URI=\"{}\",IV=0x", key.uri).unwrap();
+                for b in &key.iv {
+                    write!(m3u8, "{:02x}", b).unwrap();
+                }
+                m3u8.push('\n');
+            }
+            writeln!(m3u8, "#EXTINF:{:.3},", segment.duration).unwrap();
+            if let Some((length, offset)) = segment.byte_range {
+                writeln!(m3u8, "#EXT-X-BYTERANGE:{}@{}", length, offset).unwrap();
+            }
+            writeln!(m3u8, "{}", segment.uri).unwrap();
+        }
+
+        if self.ended {
+            m3u8.push_str("#EXT-X-ENDLIST\n");
+        }
+
+        m3u8
+    }
+}
+
+// Renders a master playlist with the media playlist at `uri` as only variant stream, which
+// also references itself as audio rendition if there is one
+pub fn render_master(uri: &str, bandwidth: u64, rendition: Option<&Rendition>) -> String {
+    let mut m3u8 = String::new();
+    m3u8.push_str("#EXTM3U\n");
+    m3u8.push_str("#EXT-X-VERSION:3\n");
+
+    if let Some(rendition) = rendition {
+        write!(
+            m3u8,
+            "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"{}\"",
+            rendition.name
+        ).unwrap();
+        if let Some(ref language) = rendition.language {
+            write!(m3u8, ",LANGUAGE=\"{}\"", language).unwrap();
+        }
+        let default = if rendition.default { "YES" } else { "NO" };
+        write!(m3u8, ",DEFAULT={},AUTOSELECT=YES", default).unwrap();
+        if let Some(characteristics) = rendition.characteristics {
+            write!(m3u8, ",CHARACTERISTICS=\"{}\"", characteristics).unwrap();
+        }
+        writeln!(m3u8, ",URI=\"{}\"", uri).unwrap();
+        writeln!(m3u8, "#EXT-X-STREAM-INF:BANDWIDTH={},AUDIO=\"audio\"", bandwidth).unwrap();
+    } else {
+        writeln!(m3u8, "#EXT-X-STREAM-INF:BANDWIDTH={}", bandwidth).unwrap();
+    }
+    writeln!(m3u8, "{}", uri).unwrap();
+
+    m3u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(uri: &str, duration: f64) -> Segment {
+        Segment {
+            uri: String::from(uri),
+            duration: duration,
+            byte_range: None,
+            key: None,
+        }
+    }
+
+    #[test]
+    fn test_live() {
+        let mut playlist = Playlist::new(PlaylistType::Live, 2, 6);
+        playlist.add_segment(segment("segment00000.ts", 6.0));
+        playlist.add_segment(segment("segment00001.ts", 7.4));
+        playlist.add_segment(segment("segment00002.ts", 5.5));
+
+        assert_eq!(
+            playlist.render(),
+            "#EXTM3U\n\
+             #EXT-X-VERSION:3\n\
+             #EXT-X-TARGETDURATION:7\n\
+             #EXT-X-MEDIA-SEQUENCE:1\n\
+             #EXTINF:7.400,\n\
+             segment00001.ts\n\
+             #EXTINF:5.500,\n\
+             segment00002.ts\n"
+        );
+    }
+
+    #[test]
+    fn test_event() {
+        let mut playlist = Playlist::new(PlaylistType::Event, 2, 4);
+        playlist.init_uri = Some(String::from("init.mp4"));
+        playlist.add_segment(segment("segment00000.m4s", 4.0));
+        playlist.add_segment(segment("segment00001.m4s", 4.0));
+        playlist.add_segment(segment("segment00002.m4s", 2.0));
+        playlist.ended = true;
+
+        assert_eq!(
+            playlist.render(),
+            "#EXTM3U\n\
+             #EXT-X-VERSION:6\n\
+             #EXT-X-TARGETDURATION:4\n\
+             #EXT-X-MEDIA-SEQUENCE:0\n\
+             #EXT-X-PLAYLIST-TYPE:EVENT\n\
+             #EXT-X-MAP:URI=\"init.mp4\"\n\
+             #EXTINF:4.000,\n\
+             segment00000.m4s\n\
+             #EXTINF:4.000,\n\
+             segment00001.m4s\n\
+             #EXTINF:2.000,\n\
+             segment00002.m4s\n\
+             #EXT-X-ENDLIST\n"
+        );
+    }
+
+    #[test]
+    fn test_iframes_only() {
+        let mut playlist = Playlist::new(PlaylistType::Live, 0, 6);
+        playlist.iframes_only = true;
+        for &(uri, duration, length, offset) in &[
+            ("segment00000.ts", 2.0, 9024, 0),
+            ("segment00000.ts", 4.0, 8836, 301_000),
+            ("segment00001.ts", 6.0, 9400, 0),
+        ] {
+            playlist.add_segment(Segment {
+                uri: String::from(uri),
+                duration: duration,
+                byte_range: Some((length, offset)),
+                key: None,
+            });
+        }
+        playlist.remove_segments_before("segment00001.ts");
+
+        assert_eq!(
+            playlist.render(),
+            "#EXTM3U\n\
+             #EXT-X-VERSION:4\n\
+             #EXT-X-TARGETDURATION:6\n\
+             #EXT-X-MEDIA-SEQUENCE:2\n\
+             #EXT-X-I-FRAMES-ONLY\n\
+             #EXTINF:6.000,\n\
+             #EXT-X-BYTERANGE:9400@0\n\
+             segment00001.ts\n"
+        );
+    }
+
+    #[test]
+    fn test_encrypted() {
+        let mut playlist = Playlist::new(PlaylistType::Event, 0, 6);
+        for number in 0..2 {
+            let mut iv = [0; 16];
+            iv[15] = number as u8;
+            playlist.add_segment(Segment {
+                uri: format!("segment{:05}.ts", number),
+                duration: 6.0,
+                byte_range: None,
+                key: Some(Key {
+                    uri: String::from("https://example.com/key"),
+                    iv: iv,
+                }),
+            });
+        }
+
+        assert_eq!(
+            playlist.render(),
+            "#EXTM3U\n\
+             #EXT-X-VERSION:3\n\
+             #EXT-X-TARGETDURATION:6\n\
+             #EXT-X-MEDIA-SEQUENCE:0\n\
+             #EXT-X-PLAYLIST-TYPE:EVENT\n\
+             #EXT-X-KEY:METHOD=AES-128,URI=\"https://example.com/key\",\
+             IV=0x00000000000000000000000000000000\n\
+             #EXTINF:6.000,\n\
+             segment00000.ts\n\
+             #EXT-X-KEY:METHOD=AES-128,URI=\"https://example.com/key\",\
+             IV=0x00000000000000000000000000000001\n\
+             #EXTINF:6.000,\n\
+             segment00001.ts\n"
+        );
+    }
+
+    #[test]
+    fn test_master() {
+        assert_eq!(
+            render_master("playlist.m3u8", 1_200_000, None),
+            "#EXTM3U\n\
+             #EXT-X-VERSION:3\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=1200000\n\
+             playlist.m3u8\n"
+        );
+
+        let rendition = Rendition {
+            name: String::from("de description"),
+            language: Some(String::from("de")),
+            default: false,
+            characteristics: Some("public.accessibility.describes-video"),
+        };
+        assert_eq!(
+            render_master("playlist.m3u8", 128_000, Some(&rendition)),
+            "#EXTM3U\n\
+             #EXT-X-VERSION:3\n\
+             #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"de description\",\
+             LANGUAGE=\"de\",DEFAULT=NO,AUTOSELECT=YES,\
+             CHARACTERISTICS=\"public.accessibility.describes-video\",URI=\"playlist.m3u8\"\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=128000,AUDIO=\"audio\"\n\
+             playlist.m3u8\n"
+        );
+    }
+}
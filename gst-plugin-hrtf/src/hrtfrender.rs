@@ -0,0 +1,710 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_audio;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::ffi::CString;
+use std::i32;
+use std::ptr;
+use std::sync::Mutex;
+
+use byte_slice_cast::*;
+
+use mysofasys::*;
+
+// Binaural rendering:
+//
+// Every input channel is a virtual speaker or sound object around the listener. It is convolved
+// with the head-related impulse responses (HRIRs) of its position from the SOFA file, one for
+// each ear, and the results of all channels are summed up into the stereo output. LFE channels
+// are mixed into both ears without filtering.
+//
+// The positions come from the channel-mask of the input, or from the positions property which
+// also allows moving objects around while playing. After a change the output of the old and new
+// HRIRs is crossfaded over one buffer to avoid clicks.
+//
+// The convolution is done in the time domain, so the processing cost grows with the length of
+// the HRIRs in the file.
+
+const DEFAULT_DISTANCE: f32 = 1.0;
+
+// Interaural delays are limited to this, in milliseconds
+const MAX_DELAY: u64 = 10;
+
+#[derive(Debug, Clone, Default)]
+struct Settings {
+    sofa_file: Option<String>,
+    positions: Option<String>,
+}
+
+static PROPERTIES: [Property; 2] = [
+    Property::String(
+        "sofa-file",
+        "SOFA File",
+        "SOFA file with the HRTFs to use (can't be changed in PLAYING or PAUSED state)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "positions",
+        "Positions",
+        "Comma separated positions of the channels as azimuth:elevation[:distance] in degrees \
+         and meters, counter-clockwise from the front, or lfe (default: from the channel-mask)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Speaker {
+    Lfe,
+    Position {
+        azimuth: f32,
+        elevation: f32,
+        distance: f32,
+    },
+}
+
+impl Speaker {
+    fn at(azimuth: f32, elevation: f32) -> Speaker {
+        Speaker::Position {
+            azimuth: azimuth,
+            elevation: elevation,
+            distance: DEFAULT_DISTANCE,
+        }
+    }
+}
+
+// Positions of the GstAudioChannelPosition values, in the order of the channel-mask bits
+fn speaker_for_channel_position(position: u32) -> Option<Speaker> {
+    let speaker = match position {
+        0 => Speaker::at(30.0, 0.0),     // FRONT_LEFT
+        1 => Speaker::at(-30.0, 0.0),    // FRONT_RIGHT
+        2 => Speaker::at(0.0, 0.0),      // FRONT_CENTER
+        3 | 9 => Speaker::Lfe,           // LFE1, LFE2
+        4 => Speaker::at(150.0, 0.0),    // REAR_LEFT
+        5 => Speaker::at(-150.0, 0.0),   // REAR_RIGHT
+        6 => Speaker::at(15.0, 0.0),     // FRONT_LEFT_OF_CENTER
+        7 => Speaker::at(-15.0, 0.0),    // FRONT_RIGHT_OF_CENTER
+        8 => Speaker::at(180.0, 0.0),    // REAR_CENTER
+        10 => Speaker::at(90.0, 0.0),    // SIDE_LEFT
+        11 => Speaker::at(-90.0, 0.0),   // SIDE_RIGHT
+        12 => Speaker::at(30.0, 45.0),   // TOP_FRONT_LEFT
+        13 => Speaker::at(-30.0, 45.0),  // TOP_FRONT_RIGHT
+        14 => Speaker::at(0.0, 45.0),    // TOP_FRONT_CENTER
+        15 => Speaker::at(0.0, 90.0),    // TOP_CENTER
+        16 => Speaker::at(150.0, 45.0),  // TOP_REAR_LEFT
+        17 => Speaker::at(-150.0, 45.0), // TOP_REAR_RIGHT
+        18 => Speaker::at(90.0, 45.0),   // TOP_SIDE_LEFT
+        19 => Speaker::at(-90.0, 45.0),  // TOP_SIDE_RIGHT
+        20 => Speaker::at(180.0, 45.0),  // TOP_REAR_CENTER
+        21 => Speaker::at(0.0, -45.0),   // BOTTOM_FRONT_CENTER
+        22 => Speaker::at(30.0, -45.0),  // BOTTOM_FRONT_LEFT
+        23 => Speaker::at(-30.0, -45.0), // BOTTOM_FRONT_RIGHT
+        24 => Speaker::at(60.0, 0.0),    // WIDE_LEFT
+        25 => Speaker::at(-60.0, 0.0),   // WIDE_RIGHT
+        26 => Speaker::at(110.0, 0.0),   // SURROUND_LEFT
+        27 => Speaker::at(-110.0, 0.0),  // SURROUND_RIGHT
+        _ => return None,
+    };
+
+    Some(speaker)
+}
+
+fn speakers_from_mask(mask: u64, channels: u32) -> Option<Vec<Speaker>> {
+    if mask == 0 {
+        return match channels {
+            1 => Some(vec![Speaker::at(0.0, 0.0)]),
+            2 => Some(vec![Speaker::at(30.0, 0.0), Speaker::at(-30.0, 0.0)]),
+            _ => None,
+        };
+    }
+
+    let speakers = (0..64)
+        .filter(|bit| mask & (1 << bit) != 0)
+        .map(speaker_for_channel_position)
+        .collect::<Option<Vec<_>>>()?;
+    if speakers.len() == channels as usize {
+        Some(speakers)
+    } else {
+        None
+    }
+}
+
+fn parse_positions(s: &str) -> Result<Vec<Speaker>, String> {
+    s.split(',')
+        .map(|position| {
+            let position = position.trim();
+            if position == "lfe" {
+                return Ok(Speaker::Lfe);
+            }
+
+            let values = position
+                .split(':')
+                .map(|v| v.trim().parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("Invalid position '{}'", position))?;
+            match values.len() {
+                2 => Ok(Speaker::at(values[0], values[1])),
+                3 if values[2] > 0.0 => Ok(Speaker::Position {
+                    azimuth: values[0],
+                    elevation: values[1],
+                    distance: values[2],
+                }),
+                _ => Err(format!("Invalid position '{}'", position)),
+            }
+        })
+        .collect()
+}
+
+// HRIRs of both ears, with the interaural delays as leading zeroes
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+
+fn convolve(ir: &[f32], window: &[f32]) -> f32 {
+    ir.iter()
+        .zip(window.iter().rev())
+        .map(|(&h, &x)| h * x)
+        .sum()
+}
+
+// Renders the stereo output of all channels. Every input starts with `history` samples of the
+// previous buffers, which must be at least the length of the longest filter minus one.
+fn render(filters: &[Option<Filter>], inputs: &[Vec<f32>], history: usize, output: &mut [f32]) {
+    for o in output.iter_mut() {
+        *o = 0.0;
+    }
+
+    for (filter, input) in filters.iter().zip(inputs) {
+        match *filter {
+            None => for (o, &s) in output.chunks_mut(2).zip(&input[history..]) {
+                o[0] += s;
+                o[1] += s;
+            },
+            Some(ref filter) => for (n, o) in output.chunks_mut(2).enumerate() {
+                let end = history + n + 1;
+                o[0] += convolve(&filter.left, &input[(end - filter.left.len())..end]);
+                o[1] += convolve(&filter.right, &input[(end - filter.right.len())..end]);
+            },
+        }
+    }
+}
+
+struct Sofa {
+    easy: *mut MYSOFA_EASY,
+    filter_length: usize,
+    rate: u32,
+}
+
+// Only used from behind the state mutex
+unsafe impl Send for Sofa {}
+
+impl Sofa {
+    fn open(path: &str, rate: u32) -> Result<Sofa, String> {
+        let filename = CString::new(path).map_err(|_| format!("Invalid file name '{}'", path))?;
+
+        let mut filter_length = 0;
+        let mut err = 0;
+        let easy =
+            unsafe { mysofa_open(filename.as_ptr(), rate as f32, &mut filter_length, &mut err) };
+        if easy.is_null() || err != MYSOFA_OK {
+            return Err(format!("Failed to open SOFA file '{}': error {}", path, err));
+        }
+
+        Ok(Sofa {
+            easy: easy,
+            filter_length: filter_length as usize,
+            rate: rate,
+        })
+    }
+
+    fn max_delay(&self) -> usize {
+        (u64::from(self.rate) * MAX_DELAY / 1000) as usize
+    }
+
+    fn filter(&self, speaker: &Speaker) -> Option<Filter> {
+        let (azimuth, elevation, distance) = match *speaker {
+            Speaker::Lfe => return None,
+            Speaker::Position {
+                azimuth,
+                elevation,
+                distance,
+            } => (azimuth.to_radians(), elevation.to_radians(), distance),
+        };
+
+        let x = distance * elevation.cos() * azimuth.cos();
+        let y = distance * elevation.cos() * azimuth.sin();
+        let z = distance * elevation.sin();
+
+        let mut left = vec![0.0; self.filter_length];
+        let mut right = vec![0.0; self.filter_length];
+        let mut delay_left = 0.0;
+        let mut delay_right = 0.0;
+        unsafe {
+            mysofa_getfilter_float(
+                self.easy,
+                x,
+                y,
+                z,
+                left.as_mut_ptr(),
+                right.as_mut_ptr(),
+                &mut delay_left,
+                &mut delay_right,
+            );
+        }
+
+        let max_delay = self.max_delay();
+        let delayed = |ir: Vec<f32>, delay: f32| {
+            let delay = ((delay * self.rate as f32).round().max(0.0) as usize).min(max_delay);
+            let mut delayed = vec![0.0; delay];
+            delayed.extend(ir);
+            delayed
+        };
+
+        Some(Filter {
+            left: delayed(left, delay_left),
+            right: delayed(right, delay_right),
+        })
+    }
+}
+
+impl Drop for Sofa {
+    fn drop(&mut self) {
+        unsafe {
+            mysofa_close(self.easy);
+        }
+        self.easy = ptr::null_mut();
+    }
+}
+
+struct State {
+    sofa: Sofa,
+    // Positions property the speakers were configured from, if any
+    positions: Option<String>,
+    mask_speakers: Vec<Speaker>,
+    filters: Vec<Option<Filter>>,
+    // Filters before the last change, for crossfading
+    previous: Option<Vec<Option<Filter>>>,
+    // Deinterleaved input of every channel, starting with the history
+    inputs: Vec<Vec<f32>>,
+    history: usize,
+}
+
+impl State {
+    fn speakers(&self, positions: Option<&String>) -> Result<Vec<Speaker>, String> {
+        let speakers = match positions {
+            None => return Ok(self.mask_speakers.clone()),
+            Some(positions) => parse_positions(positions)?,
+        };
+        if speakers.len() != self.inputs.len() {
+            return Err(format!(
+                "{} positions for {} channels",
+                speakers.len(),
+                self.inputs.len()
+            ));
+        }
+
+        Ok(speakers)
+    }
+
+    fn set_speakers(&mut self, speakers: &[Speaker]) {
+        let filters = speakers.iter().map(|s| self.sofa.filter(s)).collect();
+        self.previous = Some(::std::mem::replace(&mut self.filters, filters));
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        let channels = self.inputs.len();
+        for (c, samples) in self.inputs.iter_mut().enumerate() {
+            samples.extend(input.chunks(channels).map(|frame| frame[c]));
+        }
+
+        render(&self.filters, &self.inputs, self.history, output);
+
+        if let Some(previous) = self.previous.take() {
+            let mut old = vec![0.0; output.len()];
+            render(&previous, &self.inputs, self.history, &mut old);
+
+            let frames = (output.len() / 2) as f32;
+            for (n, (o, old)) in output.chunks_mut(2).zip(old.chunks(2)).enumerate() {
+                let t = (n + 1) as f32 / frames;
+                o[0] = old[0] + (o[0] - old[0]) * t;
+                o[1] = old[1] + (o[1] - old[1]) * t;
+            }
+        }
+
+        // Keep the last samples for the next buffer
+        for samples in &mut self.inputs {
+            let len = samples.len();
+            samples.drain(..(len - self.history));
+        }
+    }
+
+    fn reset(&mut self) {
+        for samples in &mut self.inputs {
+            for s in samples.iter_mut() {
+                *s = 0.0;
+            }
+        }
+        self.previous = None;
+    }
+}
+
+struct HrtfRender {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl HrtfRender {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "hrtfrender",
+                gst::DebugColorFlags::empty(),
+                "HRTF binaural renderer",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "HRTF Render",
+            "Filter/Effect/Audio",
+            "Renders multi-channel audio to binaural stereo with HRTFs from a SOFA file",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &gst_audio::AUDIO_FORMAT_F32.to_string()),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &2i32),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let caps = gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &gst_audio::AUDIO_FORMAT_F32.to_string()),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &gst::IntRange::<i32>::new(1, 64)),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::NeverInPlace, false, false);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseTransform> for HrtfRender {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("sofa-file", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                if self.state.lock().unwrap().is_none() {
+                    settings.sofa_file = value.get();
+                }
+            }
+            Property::String("positions", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.positions = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("sofa-file", ..) => Ok(settings.sofa_file.to_value()),
+            Property::String("positions", ..) => Ok(settings.positions.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for HrtfRender {}
+
+impl BaseTransformImpl<BaseTransform> for HrtfRender {
+    fn transform_caps(
+        &self,
+        _element: &BaseTransform,
+        direction: gst::PadDirection,
+        caps: &gst::Caps,
+        filter: Option<&gst::Caps>,
+    ) -> gst::Caps {
+        // Any number of channels is rendered to stereo
+        let mut other_caps = caps.clone();
+        {
+            let other_caps = other_caps.make_mut();
+            for i in 0..other_caps.get_size() {
+                let s = other_caps.get_mut_structure(i).unwrap();
+                s.remove_field("channel-mask");
+                if direction == gst::PadDirection::Sink {
+                    s.set("channels", &2i32);
+                    s.set("channel-mask", &gst::Bitmask::new(0x3));
+                } else {
+                    s.set("channels", &gst::IntRange::<i32>::new(1, 64));
+                }
+            }
+        }
+
+        match filter {
+            None => other_caps,
+            Some(filter) => filter.intersect_with_mode(&other_caps, gst::CapsIntersectMode::First),
+        }
+    }
+
+    fn get_unit_size(&self, _element: &BaseTransform, caps: &gst::Caps) -> Option<usize> {
+        gst_audio::AudioInfo::from_caps(caps).map(|info| info.bpf() as usize)
+    }
+
+    fn set_caps(&self, element: &BaseTransform, incaps: &gst::Caps, _outcaps: &gst::Caps) -> bool {
+        let info = match gst_audio::AudioInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+        let mask = incaps
+            .get_structure(0)
+            .and_then(|s| s.get::<gst::Bitmask>("channel-mask"))
+            .map(|mask| mask.0)
+            .unwrap_or(0);
+
+        let settings = self.settings.lock().unwrap().clone();
+        let sofa_file = match settings.sofa_file {
+            None => {
+                gst_element_error!(element, gst::ResourceError::Settings, ["No SOFA file set"]);
+                return false;
+            }
+            Some(ref sofa_file) => sofa_file,
+        };
+
+        let sofa = match Sofa::open(sofa_file, info.rate()) {
+            Ok(sofa) => sofa,
+            Err(err) => {
+                gst_element_error!(element, gst::ResourceError::OpenRead, ["{}", err]);
+                return false;
+            }
+        };
+
+        let history = sofa.filter_length + sofa.max_delay();
+        let mut state = State {
+            sofa: sofa,
+            positions: settings.positions.clone(),
+            mask_speakers: speakers_from_mask(mask, info.channels()).unwrap_or_else(Vec::new),
+            filters: Vec::new(),
+            previous: None,
+            inputs: vec![vec![0.0; history]; info.channels() as usize],
+            history: history,
+        };
+
+        let speakers = match state.speakers(settings.positions.as_ref()) {
+            Ok(ref speakers) if speakers.is_empty() => {
+                gst_element_error!(
+                    element,
+                    gst::CoreError::Negotiation,
+                    ["No positions for channel-mask {:#x}", mask]
+                );
+                return false;
+            }
+            Ok(speakers) => speakers,
+            Err(err) => {
+                gst_element_error!(element, gst::ResourceError::Settings, ["{}", err]);
+                return false;
+            }
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Rendering {} channels at {:?} with HRIRs of length {}",
+            info.channels(),
+            speakers,
+            state.sofa.filter_length
+        );
+
+        state.set_speakers(&speakers);
+        state.previous = None;
+        *self.state.lock().unwrap() = Some(state);
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseTransform) -> bool {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        true
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: gst::Event) -> bool {
+        if let gst::EventView::FlushStop(..) = event.view() {
+            if let Some(ref mut state) = *self.state.lock().unwrap() {
+                state.reset();
+            }
+        }
+
+        element.parent_sink_event(event)
+    }
+
+    fn transform(
+        &self,
+        element: &BaseTransform,
+        inbuf: &gst::Buffer,
+        outbuf: &mut gst::BufferRef,
+    ) -> gst::FlowReturn {
+        let positions = self.settings.lock().unwrap().positions.clone();
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return gst::FlowReturn::NotNegotiated,
+            Some(ref mut state) => state,
+        };
+
+        if positions != state.positions {
+            match state.speakers(positions.as_ref()) {
+                Ok(ref speakers) if !speakers.is_empty() => {
+                    gst_debug!(self.cat, obj: element, "Moving to {:?}", speakers);
+                    state.set_speakers(speakers);
+                }
+                Ok(_) => {
+                    gst_warning!(self.cat, obj: element, "No positions for the channels");
+                }
+                Err(err) => {
+                    gst_warning!(self.cat, obj: element, "Ignoring positions: {}", err);
+                }
+            }
+            state.positions = positions;
+        }
+
+        let in_map = match inbuf.map_readable() {
+            None => return gst::FlowReturn::Error,
+            Some(map) => map,
+        };
+        let mut out_map = match outbuf.map_writable() {
+            None => return gst::FlowReturn::Error,
+            Some(map) => map,
+        };
+
+        let input = in_map.as_slice().as_slice_of::<f32>().unwrap();
+        let output = out_map.as_mut_slice().as_mut_slice_of::<f32>().unwrap();
+        state.process(input, output);
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct HrtfRenderStatic;
+
+impl ImplTypeStatic<BaseTransform> for HrtfRenderStatic {
+    fn get_name(&self) -> &str {
+        "HrtfRender"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        HrtfRender::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        HrtfRender::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let hrtfrender_static = HrtfRenderStatic;
+    register_type(hrtfrender_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speakers() {
+        // 5.1 with FRONT_LEFT, FRONT_RIGHT, FRONT_CENTER, LFE1, REAR_LEFT, REAR_RIGHT
+        let speakers = speakers_from_mask(0x3f, 6).unwrap();
+        assert_eq!(speakers[2], Speaker::at(0.0, 0.0));
+        assert_eq!(speakers[3], Speaker::Lfe);
+        assert_eq!(speakers[5], Speaker::at(-150.0, 0.0));
+        assert_eq!(speakers_from_mask(0x3f, 5), None);
+        assert_eq!(speakers_from_mask(0, 4), None);
+
+        assert_eq!(
+            parse_positions("90:0, -45:30:2.5,lfe"),
+            Ok(vec![
+                Speaker::at(90.0, 0.0),
+                Speaker::Position {
+                    azimuth: -45.0,
+                    elevation: 30.0,
+                    distance: 2.5,
+                },
+                Speaker::Lfe,
+            ])
+        );
+        assert!(parse_positions("90").is_err());
+        assert!(parse_positions("90:0:0").is_err());
+        assert!(parse_positions("left:0").is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        // Left ear gets the signal one sample later and at half the level
+        let filters = vec![
+            Some(Filter {
+                left: vec![0.0, 0.5],
+                right: vec![1.0],
+            }),
+            None,
+        ];
+        let inputs = vec![vec![0.0, 1.0, 2.0, 3.0], vec![0.0, 0.0, 0.0, 1.0]];
+        let mut output = vec![0.0; 6];
+        render(&filters, &inputs, 1, &mut output);
+
+        assert_eq!(output, vec![0.0, 1.0, 0.5, 2.0, 2.0, 4.0]);
+    }
+}
@@ -0,0 +1,47 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Declarations of the parts of libmysofa (mysofa.h) that are used for reading HRTFs from SOFA
+// files. The library has to be installed separately.
+
+#![allow(non_camel_case_types, dead_code)]
+
+use std::os::raw::{c_char, c_int};
+
+pub const MYSOFA_OK: c_int = 0;
+
+// Opaque, only used through pointers
+#[repr(C)]
+pub struct MYSOFA_EASY {
+    _private: [u8; 0],
+}
+
+#[link(name = "mysofa")]
+extern "C" {
+    // Loads the file, resamples the HRIRs to the sample rate and normalizes them. The length of
+    // the HRIRs is stored in `filterlength`.
+    pub fn mysofa_open(
+        filename: *const c_char,
+        samplerate: f32,
+        filterlength: *mut c_int,
+        err: *mut c_int,
+    ) -> *mut MYSOFA_EASY;
+    // Interpolates the HRIRs for the cartesian position, with x to the front, y to the left and
+    // z up. The delays are in seconds.
+    pub fn mysofa_getfilter_float(
+        easy: *mut MYSOFA_EASY,
+        x: f32,
+        y: f32,
+        z: f32,
+        ir_left: *mut f32,
+        ir_right: *mut f32,
+        delay_left: *mut f32,
+        delay_right: *mut f32,
+    );
+    pub fn mysofa_close(easy: *mut MYSOFA_EASY);
+}
@@ -0,0 +1,630 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::u32;
+use std::u64;
+use url::Url;
+use reqwest::{Body, Client, Method};
+use reqwest::header::Headers;
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_sink::*;
+
+use httpsink::{Spool, SpoolReader, SpoolState};
+
+const DEFAULT_METHOD: &str = "PUT";
+const DEFAULT_USER_AGENT: &str = "GStreamer cmafhttpsink";
+const DEFAULT_CONTENT_TYPE: &str = "video/mp4";
+const DEFAULT_SEGMENT_DURATION: u64 = 0;
+const DEFAULT_START_NUMBER: u32 = 1;
+
+static PROPERTIES: [Property; 8] = [
+    Property::String(
+        "location",
+        "Location",
+        "URI template for the segments, $Number$ or $Number%05d$ is replaced by the segment number",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "init-location",
+        "Init Location",
+        "URI for the initialization segment (default: location with \"init\" as segment number)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "segment-duration",
+        "Segment Duration",
+        "Minimum segment duration in nanoseconds, 0 starts a new segment with every fragment",
+        (0, u64::MAX),
+        DEFAULT_SEGMENT_DURATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "start-number",
+        "Start Number",
+        "Number of the first segment",
+        (0, u32::MAX),
+        DEFAULT_START_NUMBER,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "method",
+        "Method",
+        "HTTP method used for the uploads (PUT or POST)",
+        Some(DEFAULT_METHOD),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "user-agent",
+        "User-Agent",
+        "Value of the User-Agent HTTP request header field",
+        Some(DEFAULT_USER_AGENT),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "content-type",
+        "Content Type",
+        "Content type of the uploaded segments",
+        Some(DEFAULT_CONTENT_TYPE),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boxed(
+        "extra-headers",
+        "Extra Headers",
+        "Extra headers to append to every HTTP request",
+        gst::Structure::static_type,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: Option<String>,
+    init_location: Option<String>,
+    segment_duration: u64,
+    start_number: u32,
+    method: String,
+    user_agent: String,
+    content_type: String,
+    extra_headers: Option<gst::Structure>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: None,
+            init_location: None,
+            segment_duration: DEFAULT_SEGMENT_DURATION,
+            start_number: DEFAULT_START_NUMBER,
+            method: DEFAULT_METHOD.into(),
+            user_agent: DEFAULT_USER_AGENT.into(),
+            content_type: DEFAULT_CONTENT_TYPE.into(),
+            extra_headers: None,
+        }
+    }
+}
+
+// Replaces $Number$ and $Number%0<width>d$ like in DASH segment templates
+fn expand_template(template: &str, number: &str) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("$Number") {
+        let after = &rest[(start + "$Number".len())..];
+        let end = match after.find('$') {
+            None => break,
+            Some(end) => end,
+        };
+        result.push_str(&rest[..start]);
+
+        let format = &after[..end];
+        let width = if format.starts_with("%0") && format.ends_with('d') {
+            format[2..(format.len() - 1)].parse::<usize>().ok()
+        } else {
+            None
+        };
+
+        match width {
+            Some(width) => result.push_str(&format!("{:0>width$}", number, width = width)),
+            None if format.is_empty() => result.push_str(number),
+            None => result.push_str(&rest[start..(start + "$Number".len() + end + 1)]),
+        }
+
+        rest = &after[(end + 1)..];
+    }
+    result.push_str(rest);
+
+    result
+}
+
+// One upload request, the body is streamed from the spool while the data is rendered
+struct Upload {
+    uri: Url,
+    header: bool,
+    spool: Spool,
+    result: Arc<Mutex<Option<Result<(), String>>>>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Upload {
+    fn finish(&self) {
+        let &(ref lock, ref cond) = &*self.spool;
+        lock.lock().unwrap().eos = true;
+        cond.notify_all();
+    }
+
+    fn push(&self, data: &[u8]) {
+        let &(ref lock, ref cond) = &*self.spool;
+        lock.lock().unwrap().chunks.push_back(data.to_vec());
+        cond.notify_all();
+    }
+}
+
+struct State {
+    settings: Settings,
+    client: Client,
+    method: Method,
+    number: u32,
+    segment_start: Option<u64>,
+    current: Option<Upload>,
+    // Finished segments whose upload is still running
+    pending: Vec<Upload>,
+}
+
+struct CmafHttpSink {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl CmafHttpSink {
+    fn new(_sink: &BaseSink) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "cmafhttpsink",
+                gst::DebugColorFlags::empty(),
+                "CMAF HTTP ingest sink",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseSinkClass) {
+        klass.set_metadata(
+            "CMAF HTTP Sink",
+            "Sink/Network",
+            "Uploads CMAF segments via HTTP chunked transfer while they are produced",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("video/quicktime", &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        // Called for every request with its URI, the returned structure contains additional
+        // request headers, e.g. for authentication with short-lived tokens
+        klass.add_signal(
+            "request-headers",
+            &[String::static_type()],
+            gst::Structure::static_type(),
+        );
+    }
+
+    fn init(element: &BaseSink) -> Box<BaseSinkImpl<BaseSink>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    fn request_headers(&self, element: &BaseSink, settings: &Settings, uri: &Url) -> Headers {
+        let mut headers = Headers::new();
+
+        headers.set_raw("User-Agent", settings.user_agent.clone());
+        headers.set_raw("Content-Type", settings.content_type.clone());
+
+        let extra_headers = element
+            .emit("request-headers", &[&uri.as_str()])
+            .ok()
+            .and_then(|v| v)
+            .and_then(|v| v.get::<gst::Structure>());
+
+        for s in settings.extra_headers.iter().chain(extra_headers.iter()) {
+            for (field, value) in s.iter() {
+                match value.transform::<String>().and_then(|v| v.get::<String>()) {
+                    Some(value) => headers.append_raw(String::from(field), value),
+                    None => {
+                        gst_warning!(
+                            self.cat,
+                            obj: element,
+                            "Can't convert value of header '{}' to a string",
+                            field
+                        );
+                    }
+                }
+            }
+        }
+
+        headers
+    }
+
+    fn start_upload(
+        &self,
+        element: &BaseSink,
+        state: &State,
+        header: bool,
+    ) -> Result<Upload, gst::ErrorMessage> {
+        let location = state.settings.location.as_ref().unwrap();
+        let uri = if header {
+            match state.settings.init_location {
+                Some(ref init_location) => init_location.clone(),
+                None => expand_template(location, "init"),
+            }
+        } else {
+            expand_template(location, &state.number.to_string())
+        };
+        let uri = Url::parse(&uri).map_err(|err| {
+            gst_error_msg!(
+                gst::ResourceError::Settings,
+                ["Invalid URI '{}': {}", uri, err]
+            )
+        })?;
+
+        let headers = self.request_headers(element, &state.settings, &uri);
+
+        let spool: Spool = Arc::new((Mutex::new(SpoolState::default()), Condvar::new()));
+        let result = Arc::new(Mutex::new(None));
+
+        gst_debug!(self.cat, obj: element, "Starting upload to {}", uri);
+
+        let thread = {
+            let cat = self.cat;
+            let element = element.clone().upcast::<gst::Element>();
+            let client = state.client.clone();
+            let method = state.method.clone();
+            let uri = uri.clone();
+            let spool = spool.clone();
+            let result = result.clone();
+            thread::spawn(move || {
+                let mut req = client.request(method, uri.clone());
+                req.headers(headers);
+                req.body(Body::new(SpoolReader::new(&spool)));
+
+                let res = match req.send() {
+                    Ok(ref response) if response.status().is_success() => {
+                        gst_debug!(cat, obj: &element, "Uploaded {}", uri);
+                        Ok(())
+                    }
+                    Ok(response) => Err(format!("Failed to upload {}: {}", uri, response.status())),
+                    Err(err) => Err(format!("Failed to upload {}: {}", uri, err)),
+                };
+
+                // Let rendering fail from now on if the upload failed
+                if let Err(ref err) = res {
+                    gst_error!(cat, obj: &element, "{}", err);
+                    let &(ref lock, ref cond) = &*spool;
+                    lock.lock().unwrap().error = Some(err.clone());
+                    cond.notify_all();
+                }
+
+                *result.lock().unwrap() = Some(res);
+            })
+        };
+
+        Ok(Upload {
+            uri: uri,
+            header: header,
+            spool: spool,
+            result: result,
+            thread: thread,
+        })
+    }
+
+    // Checks the uploads of previous segments and forgets about the finished ones
+    fn check_pending(&self, element: &BaseSink, state: &mut State) -> Result<(), String> {
+        let pending = mem::replace(&mut state.pending, Vec::new());
+
+        for upload in pending {
+            let res = upload.result.lock().unwrap().take();
+            match res {
+                None => state.pending.push(upload),
+                Some(res) => {
+                    let _ = upload.thread.join();
+                    gst_trace!(self.cat, obj: element, "Finished upload to {}", upload.uri);
+                    res?;
+                }
+            }
+        }
+
+        if let Some(ref upload) = state.current {
+            if let Some(ref err) = (upload.spool.0).lock().unwrap().error {
+                return Err(err.clone());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ObjectImpl<BaseSink> for CmafHttpSink {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => {
+                settings.location = value.get();
+            }
+            Property::String("init-location", ..) => {
+                settings.init_location = value.get();
+            }
+            Property::UInt64("segment-duration", ..) => {
+                settings.segment_duration = value.get().unwrap();
+            }
+            Property::UInt("start-number", ..) => {
+                settings.start_number = value.get().unwrap();
+            }
+            Property::String("method", ..) => {
+                settings.method = value.get().unwrap_or_else(|| DEFAULT_METHOD.into());
+            }
+            Property::String("user-agent", ..) => {
+                settings.user_agent = value.get().unwrap_or_else(|| DEFAULT_USER_AGENT.into());
+            }
+            Property::String("content-type", ..) => {
+                settings.content_type = value.get().unwrap_or_else(|| DEFAULT_CONTENT_TYPE.into());
+            }
+            Property::Boxed("extra-headers", ..) => {
+                settings.extra_headers = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => Ok(settings.location.to_value()),
+            Property::String("init-location", ..) => Ok(settings.init_location.to_value()),
+            Property::UInt64("segment-duration", ..) => Ok(settings.segment_duration.to_value()),
+            Property::UInt("start-number", ..) => Ok(settings.start_number.to_value()),
+            Property::String("method", ..) => Ok(settings.method.to_value()),
+            Property::String("user-agent", ..) => Ok(settings.user_agent.to_value()),
+            Property::String("content-type", ..) => Ok(settings.content_type.to_value()),
+            Property::Boxed("extra-headers", ..) => Ok(settings.extra_headers.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseSink> for CmafHttpSink {}
+
+impl BaseSinkImpl<BaseSink> for CmafHttpSink {
+    fn start(&self, element: &BaseSink) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+
+        match settings.location {
+            None => {
+                gst_element_error!(element, gst::ResourceError::Settings, ["No location set"]);
+                return false;
+            }
+            Some(ref location) => if let Err(err) = Url::parse(&expand_template(location, "0")) {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::Settings,
+                    ["Invalid location '{}': {}", location, err]
+                );
+                return false;
+            },
+        }
+
+        let method = match settings.method.to_uppercase().as_str() {
+            "PUT" => Method::Put,
+            "POST" => Method::Post,
+            method => {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::Settings,
+                    ["Unsupported HTTP method '{}'", method]
+                );
+                return false;
+            }
+        };
+
+        let client = match Client::builder().build() {
+            Ok(client) => client,
+            Err(err) => {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::OpenWrite,
+                    ["Failed to create HTTP client: {}", err]
+                );
+                return false;
+            }
+        };
+
+        *self.state.lock().unwrap() = Some(State {
+            number: settings.start_number,
+            settings: settings,
+            client: client,
+            method: method,
+            segment_start: None,
+            current: None,
+            pending: Vec::new(),
+        });
+
+        true
+    }
+
+    fn stop(&self, element: &BaseSink) -> bool {
+        let mut state = match self.state.lock().unwrap().take() {
+            None => return true,
+            Some(state) => state,
+        };
+
+        if let Some(upload) = state.current.take() {
+            upload.finish();
+            state.pending.push(upload);
+        }
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Waiting for {} uploads to finish",
+            state.pending.len()
+        );
+
+        let mut ret = true;
+        for upload in state.pending {
+            let _ = upload.thread.join();
+            if let Some(Err(err)) = upload.result.lock().unwrap().take() {
+                gst_element_error!(element, gst::ResourceError::Write, ["{}", err]);
+                ret = false;
+            }
+        }
+
+        ret
+    }
+
+    fn render(&self, element: &BaseSink, buffer: &gst::BufferRef) -> gst::FlowReturn {
+        let map = match buffer.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map,
+        };
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return gst::FlowReturn::Flushing,
+            Some(ref mut state) => state,
+        };
+
+        if let Err(err) = self.check_pending(element, state) {
+            gst_element_error!(element, gst::ResourceError::Write, ["{}", err]);
+            return gst::FlowReturn::Error;
+        }
+
+        let header = buffer.get_flags().contains(gst::BufferFlags::HEADER);
+        let fragment_start = !buffer.get_flags().contains(gst::BufferFlags::DELTA_UNIT);
+        let pts = buffer.get_pts().0;
+
+        // The init segment gets its own request, media segments start at a fragment boundary
+        // once the segment duration is reached
+        let new_upload = match state.current {
+            None => true,
+            Some(ref upload) if upload.header != header => true,
+            Some(_) if header => false,
+            Some(_) => {
+                fragment_start && match (pts, state.segment_start) {
+                    (Some(pts), Some(start)) => pts >= start + state.settings.segment_duration,
+                    _ => true,
+                }
+            }
+        };
+
+        if new_upload {
+            if let Some(upload) = state.current.take() {
+                upload.finish();
+                state.pending.push(upload);
+            }
+
+            let upload = match self.start_upload(element, state, header) {
+                Ok(upload) => upload,
+                Err(msg) => {
+                    element.post_error_message(&msg);
+                    return gst::FlowReturn::Error;
+                }
+            };
+
+            if !header {
+                state.number = state.number.wrapping_add(1);
+                state.segment_start = pts;
+            }
+            state.current = Some(upload);
+        }
+
+        gst_trace!(self.cat, obj: element, "Uploading {:?}", buffer);
+        state.current.as_ref().unwrap().push(map.as_slice());
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct CmafHttpSinkStatic;
+
+impl ImplTypeStatic<BaseSink> for CmafHttpSinkStatic {
+    fn get_name(&self) -> &str {
+        "CmafHttpSink"
+    }
+
+    fn new(&self, element: &BaseSink) -> Box<BaseSinkImpl<BaseSink>> {
+        CmafHttpSink::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseSinkClass) {
+        CmafHttpSink::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let cmafhttpsink_static = CmafHttpSinkStatic;
+    register_type(cmafhttpsink_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_template() {
+        assert_eq!(
+            expand_template("http://origin/live/seg_$Number$.m4s", "12"),
+            "http://origin/live/seg_12.m4s"
+        );
+        assert_eq!(
+            expand_template("http://origin/$Number%05d$/$Number$.m4s", "12"),
+            "http://origin/00012/12.m4s"
+        );
+        assert_eq!(
+            expand_template("http://origin/live/seg_$Number$.m4s", "init"),
+            "http://origin/live/seg_init.m4s"
+        );
+        assert_eq!(
+            expand_template("http://origin/$Number%x$.m4s", "12"),
+            "http://origin/$Number%x$.m4s"
+        );
+        assert_eq!(
+            expand_template("http://origin/live.m4s", "12"),
+            "http://origin/live.m4s"
+        );
+        assert_eq!(
+            expand_template("http://origin/$Number.m4s", "12"),
+            "http://origin/$Number.m4s"
+        );
+    }
+}
@@ -0,0 +1,607 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use url::Url;
+use reqwest::{Body, Client, Method, Proxy};
+use reqwest::header::Headers;
+use reqwest::multipart::{Form, Part};
+
+use gst_plugin::properties::*;
+use gst_plugin_simple::error::*;
+use gst_plugin_simple::sink::*;
+use gst_plugin_simple::UriValidator;
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+const DEFAULT_METHOD: &str = "PUT";
+const DEFAULT_USER_AGENT: &str = "GStreamer rshttpsink";
+const DEFAULT_RETRIES: u32 = 0;
+const DEFAULT_RETRY_DELAY: u32 = 1000;
+const DEFAULT_MAX_RETRY_DELAY: u32 = 30000;
+const DEFAULT_MULTIPART: bool = false;
+const DEFAULT_MULTIPART_FIELD_NAME: &str = "file";
+
+pub static PROPERTIES: [Property; 11] = [
+    Property::String(
+        "method",
+        "Method",
+        "HTTP method used for the upload (PUT or POST)",
+        Some(DEFAULT_METHOD),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "user-agent",
+        "User-Agent",
+        "Value of the User-Agent HTTP request header field",
+        Some(DEFAULT_USER_AGENT),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "proxy",
+        "Proxy",
+        "HTTP proxy server URI",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boxed(
+        "extra-headers",
+        "Extra Headers",
+        "Extra headers to append to the HTTP request",
+        gst::Structure::static_type,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "content-type",
+        "Content Type",
+        "Content type of the uploaded data",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "retries",
+        "Retries",
+        "Number of times a failed upload is retried (keeps all data in memory if non-zero)",
+        (0, 100),
+        DEFAULT_RETRIES,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "retry-delay",
+        "Retry Delay",
+        "Delay in milliseconds before the first retry, doubled for every further retry",
+        (0, 3_600_000),
+        DEFAULT_RETRY_DELAY,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "max-retry-delay",
+        "Maximum Retry Delay",
+        "Maximum delay in milliseconds between retries",
+        (0, 3_600_000),
+        DEFAULT_MAX_RETRY_DELAY,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "multipart",
+        "Multipart",
+        "Upload the data as a part of a multipart/form-data request",
+        DEFAULT_MULTIPART,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "multipart-field-name",
+        "Multipart Field Name",
+        "Name of the form field containing the data in multipart mode",
+        Some(DEFAULT_MULTIPART_FIELD_NAME),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "multipart-filename",
+        "Multipart Filename",
+        "Filename of the form field containing the data in multipart mode",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    method: String,
+    user_agent: String,
+    proxy: Option<String>,
+    extra_headers: Option<gst::Structure>,
+    content_type: Option<String>,
+    retries: u32,
+    retry_delay: u32,
+    max_retry_delay: u32,
+    multipart: bool,
+    multipart_field_name: String,
+    multipart_filename: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            method: DEFAULT_METHOD.into(),
+            user_agent: DEFAULT_USER_AGENT.into(),
+            proxy: None,
+            extra_headers: None,
+            content_type: None,
+            retries: DEFAULT_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            max_retry_delay: DEFAULT_MAX_RETRY_DELAY,
+            multipart: DEFAULT_MULTIPART,
+            multipart_field_name: DEFAULT_MULTIPART_FIELD_NAME.into(),
+            multipart_filename: None,
+        }
+    }
+}
+
+// Data that was rendered so far and not consumed yet by the upload thread. Consumed data is
+// kept around if the upload can be retried, as every attempt has to start from the beginning
+#[derive(Debug, Default)]
+pub struct SpoolState {
+    pub chunks: VecDeque<Vec<u8>>,
+    pub keep: bool,
+    pub eos: bool,
+    pub error: Option<String>,
+}
+
+pub type Spool = Arc<(Mutex<SpoolState>, Condvar)>;
+
+// Request body for one upload attempt, blocks until new data is rendered
+pub struct SpoolReader {
+    spool: Spool,
+    index: usize,
+    offset: usize,
+}
+
+impl SpoolReader {
+    pub fn new(spool: &Spool) -> Self {
+        SpoolReader {
+            spool: spool.clone(),
+            index: 0,
+            offset: 0,
+        }
+    }
+}
+
+impl Read for SpoolReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let &(ref lock, ref cond) = &*self.spool;
+        let mut state = lock.lock().unwrap();
+
+        loop {
+            if self.index < state.chunks.len() {
+                let size = {
+                    let chunk = &state.chunks[self.index][self.offset..];
+                    let size = cmp::min(chunk.len(), buf.len());
+                    buf[..size].copy_from_slice(&chunk[..size]);
+                    size
+                };
+
+                self.offset += size;
+                if self.offset == state.chunks[self.index].len() {
+                    if state.keep {
+                        self.index += 1;
+                    } else {
+                        state.chunks.pop_front();
+                    }
+                    self.offset = 0;
+                }
+
+                return Ok(size);
+            } else if state.eos {
+                return Ok(0);
+            }
+
+            state = cond.wait(state).unwrap();
+        }
+    }
+}
+
+#[derive(Debug)]
+enum StreamingState {
+    Stopped,
+    Started {
+        spool: Spool,
+        thread: thread::JoinHandle<Result<(), String>>,
+        position: u64,
+    },
+}
+
+#[derive(Debug)]
+pub struct HttpSink {
+    streaming_state: StreamingState,
+    cat: gst::DebugCategory,
+    settings: Settings,
+}
+
+impl HttpSink {
+    pub fn new(_sink: &BaseSink) -> HttpSink {
+        HttpSink {
+            streaming_state: StreamingState::Stopped,
+            cat: gst::DebugCategory::new(
+                "rshttpsink",
+                gst::DebugColorFlags::empty(),
+                "Rust HTTP sink",
+            ),
+            settings: Default::default(),
+        }
+    }
+
+    pub fn new_boxed(sink: &BaseSink) -> Box<SinkImpl> {
+        Box::new(HttpSink::new(sink))
+    }
+
+    fn create_client(&self) -> Result<Client, gst::ErrorMessage> {
+        let mut builder = Client::builder();
+
+        if let Some(ref proxy) = self.settings.proxy {
+            let proxy = Proxy::all(proxy.as_str()).map_err(|err| {
+                gst_error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Invalid proxy '{}': {}", proxy, err]
+                )
+            })?;
+            builder.proxy(proxy);
+        }
+
+        builder.build().map_err(|err| {
+            gst_error_msg!(
+                gst::ResourceError::OpenWrite,
+                ["Failed to create HTTP client: {}", err]
+            )
+        })
+    }
+
+    fn request_headers(&self, sink: &BaseSink) -> Headers {
+        let mut headers = Headers::new();
+
+        headers.set_raw("User-Agent", self.settings.user_agent.clone());
+
+        if !self.settings.multipart {
+            if let Some(ref content_type) = self.settings.content_type {
+                headers.set_raw("Content-Type", content_type.clone());
+            }
+        }
+
+        if let Some(ref extra_headers) = self.settings.extra_headers {
+            for (field, value) in extra_headers.iter() {
+                match value.transform::<String>().and_then(|v| v.get::<String>()) {
+                    Some(value) => headers.append_raw(String::from(field), value),
+                    None => {
+                        gst_warning!(
+                            self.cat,
+                            obj: sink,
+                            "Can't convert value of extra header '{}' to a string",
+                            field
+                        );
+                    }
+                }
+            }
+        }
+
+        headers
+    }
+
+}
+
+fn create_form(settings: &Settings, spool: &Spool) -> Result<Form, String> {
+    let mut part = Part::reader(SpoolReader::new(spool));
+
+    if let Some(ref filename) = settings.multipart_filename {
+        part = part.file_name(filename.clone());
+    }
+
+    if let Some(ref content_type) = settings.content_type {
+        let mime = content_type
+            .parse()
+            .map_err(|_| format!("Invalid content type '{}'", content_type))?;
+        part = part.mime(mime);
+    }
+
+    Ok(Form::new().part(settings.multipart_field_name.clone(), part))
+}
+
+#[derive(Debug)]
+struct Request {
+    method: Method,
+    uri: Url,
+    headers: Headers,
+}
+
+// Runs all upload attempts on its own thread, the request body is read from the spool while
+// buffers are rendered
+fn upload(
+    cat: gst::DebugCategory,
+    sink: BaseSink,
+    client: Client,
+    request: Request,
+    spool: Spool,
+    settings: Settings,
+) -> Result<(), String> {
+    let uri = &request.uri;
+    let mut attempt = 0;
+
+    loop {
+        let mut req = client.request(request.method.clone(), uri.clone());
+        req.headers(request.headers.clone());
+
+        if settings.multipart {
+            req.multipart(create_form(&settings, &spool)?);
+        } else {
+            req.body(Body::new(SpoolReader::new(&spool)));
+        }
+
+        gst_debug!(cat, obj: &sink, "Doing request {:?} (attempt {})", req, attempt + 1);
+
+        let err = match req.send() {
+            Ok(ref response) if response.status().is_success() => {
+                gst_debug!(cat, obj: &sink, "Upload successful: {:?}", response);
+                return Ok(());
+            }
+            Ok(response) => format!("Failed to upload to {}: {}", uri, response.status()),
+            Err(err) => format!("Failed to upload to {}: {}", uri, err),
+        };
+
+        if attempt >= settings.retries {
+            gst_error!(cat, obj: &sink, "{}", err);
+            return Err(err);
+        }
+
+        let delay = cmp::min(
+            u64::from(settings.retry_delay) << cmp::min(attempt, 16),
+            u64::from(settings.max_retry_delay),
+        );
+        gst_warning!(cat, obj: &sink, "{}, retrying in {}ms", err, delay);
+        thread::sleep(Duration::from_millis(delay));
+
+        attempt += 1;
+    }
+}
+
+fn validate_uri(uri: &Url) -> Result<(), UriError> {
+    if uri.scheme() != "http" && uri.scheme() != "https" {
+        return Err(UriError::new(
+            gst::URIError::UnsupportedProtocol,
+            format!("Unsupported URI '{}'", uri.as_str()),
+        ));
+    }
+
+    Ok(())
+}
+
+impl SinkImpl for HttpSink {
+    fn uri_validator(&self) -> Box<UriValidator> {
+        Box::new(validate_uri)
+    }
+
+    fn start(&mut self, sink: &BaseSink, uri: Url) -> Result<(), gst::ErrorMessage> {
+        if let StreamingState::Started { .. } = self.streaming_state {
+            return Err(gst_error_msg!(
+                gst::LibraryError::Failed,
+                ["Sink already started"]
+            ));
+        }
+
+        let method = match self.settings.method.to_uppercase().as_str() {
+            "PUT" => Method::Put,
+            "POST" => Method::Post,
+            method => {
+                return Err(gst_error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Unsupported HTTP method '{}'", method]
+                ));
+            }
+        };
+
+        let client = self.create_client()?;
+        let request = Request {
+            method: method,
+            uri: uri,
+            headers: self.request_headers(sink),
+        };
+
+        let spool: Spool = Arc::new((
+            Mutex::new(SpoolState {
+                keep: self.settings.retries > 0,
+                ..Default::default()
+            }),
+            Condvar::new(),
+        ));
+
+        if self.settings.multipart {
+            // Check the settings here already to fail early
+            create_form(&self.settings, &spool)
+                .map_err(|err| gst_error_msg!(gst::ResourceError::Settings, ["{}", err]))?;
+        }
+
+        gst_debug!(self.cat, obj: sink, "Starting upload to {}", request.uri);
+
+        let thread = {
+            let cat = self.cat;
+            let sink = sink.clone();
+            let spool = spool.clone();
+            let settings = self.settings.clone();
+            thread::spawn(move || {
+                let res = upload(cat, sink, client, request, spool.clone(), settings);
+
+                // Let rendering fail from now on if the upload failed
+                if let Err(ref err) = res {
+                    let &(ref lock, ref cond) = &*spool;
+                    lock.lock().unwrap().error = Some(err.clone());
+                    cond.notify_all();
+                }
+
+                res
+            })
+        };
+
+        self.streaming_state = StreamingState::Started {
+            spool: spool,
+            thread: thread,
+            position: 0,
+        };
+
+        Ok(())
+    }
+
+    fn stop(&mut self, sink: &BaseSink) -> Result<(), gst::ErrorMessage> {
+        let (spool, thread, position) =
+            match mem::replace(&mut self.streaming_state, StreamingState::Stopped) {
+                StreamingState::Started {
+                    spool,
+                    thread,
+                    position,
+                } => (spool, thread, position),
+                StreamingState::Stopped => return Ok(()),
+            };
+
+        // Everything rendered until now is the complete stream
+        {
+            let &(ref lock, ref cond) = &*spool;
+            lock.lock().unwrap().eos = true;
+            cond.notify_all();
+        }
+
+        gst_debug!(
+            self.cat,
+            obj: sink,
+            "Waiting for upload of {} bytes to finish",
+            position
+        );
+
+        match thread.join() {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(err)) => Err(gst_error_msg!(gst::ResourceError::Write, ["{}", err])),
+            Err(_) => Err(gst_error_msg!(
+                gst::LibraryError::Failed,
+                ["Upload thread panicked"]
+            )),
+        }
+    }
+
+    fn render(&mut self, sink: &BaseSink, buffer: &gst::BufferRef) -> Result<(), FlowError> {
+        let cat = self.cat;
+
+        let (spool, position) = match self.streaming_state {
+            StreamingState::Started {
+                ref spool,
+                ref mut position,
+                ..
+            } => (spool, position),
+            StreamingState::Stopped => {
+                return Err(FlowError::Error(gst_error_msg!(
+                    gst::LibraryError::Failed,
+                    ["Not started yet"]
+                )));
+            }
+        };
+
+        gst_trace!(cat, obj: sink, "Rendering {:?}", buffer);
+
+        let map = match buffer.map_readable() {
+            None => {
+                return Err(FlowError::Error(gst_error_msg!(
+                    gst::LibraryError::Failed,
+                    ["Failed to map buffer"]
+                )));
+            }
+            Some(map) => map,
+        };
+        let data = map.as_slice();
+
+        let &(ref lock, ref cond) = &**spool;
+        let mut state = lock.lock().unwrap();
+        if let Some(ref err) = state.error {
+            return Err(FlowError::Error(gst_error_msg!(
+                gst::ResourceError::Write,
+                ["{}", err]
+            )));
+        }
+
+        state.chunks.push_back(data.to_vec());
+        cond.notify_all();
+
+        *position += data.len() as u64;
+
+        Ok(())
+    }
+
+    fn set_property(&mut self, _sink: &BaseSink, property: &Property, value: &glib::Value) {
+        match *property {
+            Property::String("method", ..) => {
+                self.settings.method = value.get().unwrap_or_else(|| DEFAULT_METHOD.into());
+            }
+            Property::String("user-agent", ..) => {
+                self.settings.user_agent = value.get().unwrap_or_else(|| DEFAULT_USER_AGENT.into());
+            }
+            Property::String("proxy", ..) => {
+                self.settings.proxy = value.get();
+            }
+            Property::Boxed("extra-headers", ..) => {
+                self.settings.extra_headers = value.get();
+            }
+            Property::String("content-type", ..) => {
+                self.settings.content_type = value.get();
+            }
+            Property::UInt("retries", ..) => {
+                self.settings.retries = value.get().unwrap();
+            }
+            Property::UInt("retry-delay", ..) => {
+                self.settings.retry_delay = value.get().unwrap();
+            }
+            Property::UInt("max-retry-delay", ..) => {
+                self.settings.max_retry_delay = value.get().unwrap();
+            }
+            Property::Boolean("multipart", ..) => {
+                self.settings.multipart = value.get().unwrap();
+            }
+            Property::String("multipart-field-name", ..) => {
+                self.settings.multipart_field_name = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_MULTIPART_FIELD_NAME.into());
+            }
+            Property::String("multipart-filename", ..) => {
+                self.settings.multipart_filename = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _sink: &BaseSink, property: &Property) -> Result<glib::Value, ()> {
+        match *property {
+            Property::String("method", ..) => Ok(self.settings.method.to_value()),
+            Property::String("user-agent", ..) => Ok(self.settings.user_agent.to_value()),
+            Property::String("proxy", ..) => Ok(self.settings.proxy.to_value()),
+            Property::Boxed("extra-headers", ..) => Ok(self.settings.extra_headers.to_value()),
+            Property::String("content-type", ..) => Ok(self.settings.content_type.to_value()),
+            Property::UInt("retries", ..) => Ok(self.settings.retries.to_value()),
+            Property::UInt("retry-delay", ..) => Ok(self.settings.retry_delay.to_value()),
+            Property::UInt("max-retry-delay", ..) => Ok(self.settings.max_retry_delay.to_value()),
+            Property::Boolean("multipart", ..) => Ok(self.settings.multipart.to_value()),
+            Property::String("multipart-field-name", ..) => {
+                Ok(self.settings.multipart_field_name.to_value())
+            }
+            Property::String("multipart-filename", ..) => {
+                Ok(self.settings.multipart_filename.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
@@ -8,16 +8,105 @@
 
 use std::u64;
 use std::io::Read;
+use std::str;
+use std::time::Duration;
 use url::Url;
-use reqwest::{Client, Response};
+use reqwest::{Client, Proxy, RedirectPolicy, Response};
 use reqwest::header::{AcceptRanges, ByteRangeSpec, ContentLength, ContentRange, ContentRangeSpec,
-                      Range, RangeUnit};
+                      Headers, Range, RangeUnit};
 
+use gst_plugin::properties::*;
 use gst_plugin_simple::error::*;
 use gst_plugin_simple::source::*;
 use gst_plugin_simple::UriValidator;
 
+use glib;
 use gst;
+use gst::prelude::*;
+use gst_base::prelude::*;
+
+const DEFAULT_USER_AGENT: &str = "GStreamer rshttpsrc";
+const DEFAULT_AUTOMATIC_REDIRECT: bool = true;
+const DEFAULT_TIMEOUT: u32 = 15;
+const DEFAULT_IRADIO_MODE: bool = true;
+
+pub static PROPERTIES: [Property; 7] = [
+    Property::String(
+        "user-agent",
+        "User-Agent",
+        "Value of the User-Agent HTTP request header field",
+        Some(DEFAULT_USER_AGENT),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "automatic-redirect",
+        "Automatic Redirect",
+        "Automatically follow HTTP redirects",
+        DEFAULT_AUTOMATIC_REDIRECT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "proxy",
+        "Proxy",
+        "HTTP proxy server URI",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boxed(
+        "extra-headers",
+        "Extra Headers",
+        "Extra headers to append to the HTTP request",
+        gst::Structure::static_type,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "cookies",
+        "Cookies",
+        "HTTP request cookies, separated by semicolons",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "timeout",
+        "Timeout",
+        "Value in seconds to timeout a blocking I/O (0 = no timeout)",
+        (0, 3600),
+        DEFAULT_TIMEOUT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "iradio-mode",
+        "iradio-mode",
+        "Enable internet radio mode (ask server to send shoutcast/icecast metadata interleaved with the actual stream data)",
+        DEFAULT_IRADIO_MODE,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    user_agent: String,
+    automatic_redirect: bool,
+    proxy: Option<String>,
+    extra_headers: Option<gst::Structure>,
+    cookies: Option<String>,
+    timeout: u32,
+    iradio_mode: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            user_agent: DEFAULT_USER_AGENT.into(),
+            automatic_redirect: DEFAULT_AUTOMATIC_REDIRECT,
+            proxy: None,
+            extra_headers: None,
+            cookies: None,
+            timeout: DEFAULT_TIMEOUT,
+            iradio_mode: DEFAULT_IRADIO_MODE,
+        }
+    }
+}
 
 #[derive(Debug)]
 enum StreamingState {
@@ -30,6 +119,8 @@ enum StreamingState {
         size: Option<u64>,
         start: u64,
         stop: Option<u64>,
+        caps: Option<gst::Caps>,
+        tags: Option<gst::TagList>,
     },
 }
 
@@ -37,7 +128,8 @@ enum StreamingState {
 pub struct HttpSrc {
     streaming_state: StreamingState,
     cat: gst::DebugCategory,
-    client: Client,
+    settings: Settings,
+    client: Option<Client>,
 }
 
 impl HttpSrc {
@@ -49,7 +141,8 @@ impl HttpSrc {
                 gst::DebugColorFlags::empty(),
                 "Rust HTTP source",
             ),
-            client: Client::new(),
+            settings: Default::default(),
+            client: None,
         }
     }
 
@@ -57,6 +150,117 @@ impl HttpSrc {
         Box::new(HttpSrc::new(src))
     }
 
+    fn create_client(&self) -> Result<Client, gst::ErrorMessage> {
+        let mut builder = Client::builder();
+
+        if self.settings.automatic_redirect {
+            builder.redirect(RedirectPolicy::default());
+        } else {
+            builder.redirect(RedirectPolicy::none());
+        }
+
+        if self.settings.timeout != 0 {
+            builder.timeout(Duration::from_secs(u64::from(self.settings.timeout)));
+        }
+
+        if let Some(ref proxy) = self.settings.proxy {
+            let proxy = Proxy::all(proxy.as_str()).map_err(|err| {
+                gst_error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Invalid proxy '{}': {}", proxy, err]
+                )
+            })?;
+            builder.proxy(proxy);
+        }
+
+        builder.build().map_err(|err| {
+            gst_error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Failed to create HTTP client: {}", err]
+            )
+        })
+    }
+
+    fn request_headers(&self, src: &BaseSrc) -> Headers {
+        let mut headers = Headers::new();
+
+        headers.set_raw("User-Agent", self.settings.user_agent.clone());
+
+        if let Some(ref cookies) = self.settings.cookies {
+            headers.set_raw("Cookie", cookies.clone());
+        }
+
+        if self.settings.iradio_mode {
+            headers.set_raw("icy-metadata", "1");
+        }
+
+        if let Some(ref extra_headers) = self.settings.extra_headers {
+            for (field, value) in extra_headers.iter() {
+                match value.transform::<String>().and_then(|v| v.get::<String>()) {
+                    Some(value) => headers.append_raw(String::from(field), value),
+                    None => {
+                        gst_warning!(
+                            self.cat,
+                            obj: src,
+                            "Can't convert value of extra header '{}' to a string",
+                            field
+                        );
+                    }
+                }
+            }
+        }
+
+        headers
+    }
+
+    // Caps and tags for shoutcast/icecast streams, as they are handled by icydemux
+    fn iradio_caps_and_tags(
+        &self,
+        response: &Response,
+    ) -> (Option<gst::Caps>, Option<gst::TagList>) {
+        let headers = response.headers();
+        let get_header = |name: &str| {
+            headers
+                .get_raw(name)
+                .and_then(|raw| raw.one())
+                .and_then(|value| str::from_utf8(value).ok())
+                .map(|value| value.trim().to_string())
+        };
+
+        let caps = get_header("icy-metaint")
+            .and_then(|metaint| metaint.parse::<i32>().ok())
+            .and_then(|metaint| {
+                if metaint > 0 {
+                    Some(gst::Caps::new_simple(
+                        "application/x-icy",
+                        &[("metadata-interval", &metaint)],
+                    ))
+                } else {
+                    None
+                }
+            });
+
+        let mut tags = gst::TagList::new();
+        let mut have_tags = false;
+        {
+            let tags = tags.get_mut().unwrap();
+            if let Some(name) = get_header("icy-name") {
+                tags.add::<gst::tags::Organization>(&name.as_str(), gst::TagMergeMode::Replace);
+                have_tags = true;
+            }
+            if let Some(genre) = get_header("icy-genre") {
+                tags.add::<gst::tags::Genre>(&genre.as_str(), gst::TagMergeMode::Replace);
+                have_tags = true;
+            }
+            if let Some(url) = get_header("icy-url") {
+                tags.add::<gst::tags::Location>(&url.as_str(), gst::TagMergeMode::Replace);
+                have_tags = true;
+            }
+        }
+
+        (caps, if have_tags { Some(tags) } else { None })
+    }
+
     fn do_request(
         &self,
         src: &BaseSrc,
@@ -65,7 +269,17 @@ impl HttpSrc {
         stop: Option<u64>,
     ) -> Result<StreamingState, gst::ErrorMessage> {
         let cat = self.cat;
-        let mut req = self.client.get(uri.clone());
+        let client = match self.client {
+            Some(ref client) => client,
+            None => {
+                return Err(gst_error_msg!(
+                    gst::LibraryError::Failed,
+                    ["Not started yet"]
+                ));
+            }
+        };
+        let mut req = client.get(uri.clone());
+        req.headers(self.request_headers(src));
 
         match (start != 0, stop) {
             (false, None) => (),
@@ -127,8 +341,19 @@ impl HttpSrc {
 
         gst_debug!(cat, obj: src, "Request successful: {:?}", response);
 
+        // Following requests for seeking go directly to the redirection target
+        let uri = response.url().clone();
+
+        let (caps, tags) = if self.settings.iradio_mode {
+            self.iradio_caps_and_tags(&response)
+        } else {
+            (None, None)
+        };
+
         Ok(StreamingState::Started {
             uri: uri,
+            caps: caps,
+            tags: tags,
             response: response,
             seekable: seekable,
             position: 0,
@@ -171,6 +396,7 @@ impl SourceImpl for HttpSrc {
 
     fn start(&mut self, src: &BaseSrc, uri: Url) -> Result<(), gst::ErrorMessage> {
         self.streaming_state = StreamingState::Stopped;
+        self.client = Some(self.create_client()?);
         self.streaming_state = try!(self.do_request(src, uri, 0, None));
 
         Ok(())
@@ -178,6 +404,7 @@ impl SourceImpl for HttpSrc {
 
     fn stop(&mut self, _src: &BaseSrc) -> Result<(), gst::ErrorMessage> {
         self.streaming_state = StreamingState::Stopped;
+        self.client = None;
 
         Ok(())
     }
@@ -226,8 +453,22 @@ impl SourceImpl for HttpSrc {
             StreamingState::Started {
                 ref mut response,
                 ref mut position,
+                ref mut caps,
+                ref mut tags,
                 ..
-            } => (response, position),
+            } => {
+                // Only possible from the streaming thread, after the stream-start event
+                if let Some(caps) = caps.take() {
+                    gst_debug!(cat, obj: src, "Setting caps {:?}", caps);
+                    src.set_caps(&caps);
+                }
+                if let Some(tags) = tags.take() {
+                    gst_debug!(cat, obj: src, "Sending tags {:?}", tags);
+                    src.send_event(gst::Event::new_tag(tags).build());
+                }
+
+                (response, position)
+            }
             StreamingState::Stopped => {
                 return Err(FlowError::Error(gst_error_msg!(
                     gst::LibraryError::Failed,
@@ -275,4 +516,46 @@ impl SourceImpl for HttpSrc {
 
         Ok(())
     }
+
+    fn set_property(&mut self, _src: &BaseSrc, property: &Property, value: &glib::Value) {
+        match *property {
+            Property::String("user-agent", ..) => {
+                self.settings.user_agent = value.get().unwrap_or_else(|| DEFAULT_USER_AGENT.into());
+            }
+            Property::Boolean("automatic-redirect", ..) => {
+                self.settings.automatic_redirect = value.get().unwrap();
+            }
+            Property::String("proxy", ..) => {
+                self.settings.proxy = value.get();
+            }
+            Property::Boxed("extra-headers", ..) => {
+                self.settings.extra_headers = value.get();
+            }
+            Property::String("cookies", ..) => {
+                self.settings.cookies = value.get();
+            }
+            Property::UInt("timeout", ..) => {
+                self.settings.timeout = value.get().unwrap();
+            }
+            Property::Boolean("iradio-mode", ..) => {
+                self.settings.iradio_mode = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _src: &BaseSrc, property: &Property) -> Result<glib::Value, ()> {
+        match *property {
+            Property::String("user-agent", ..) => Ok(self.settings.user_agent.to_value()),
+            Property::Boolean("automatic-redirect", ..) => {
+                Ok(self.settings.automatic_redirect.to_value())
+            }
+            Property::String("proxy", ..) => Ok(self.settings.proxy.to_value()),
+            Property::Boxed("extra-headers", ..) => Ok(self.settings.extra_headers.to_value()),
+            Property::String("cookies", ..) => Ok(self.settings.cookies.to_value()),
+            Property::UInt("timeout", ..) => Ok(self.settings.timeout.to_value()),
+            Property::Boolean("iradio-mode", ..) => Ok(self.settings.iradio_mode.to_value()),
+            _ => unimplemented!(),
+        }
+    }
 }
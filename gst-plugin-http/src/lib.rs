@@ -8,22 +8,29 @@
 
 #![crate_type = "cdylib"]
 
+extern crate glib;
 #[macro_use]
 extern crate gst_plugin;
 extern crate gst_plugin_simple;
 #[macro_use]
 extern crate gstreamer as gst;
+extern crate gstreamer_base as gst_base;
 extern crate reqwest;
 extern crate url;
 
 use gst_plugin_simple::source::*;
+use gst_plugin_simple::sink::*;
+use gst_plugin::registration::*;
 
 mod httpsrc;
+mod httpsink;
+mod cmafhttpsink;
 
 use httpsrc::HttpSrc;
+use httpsink::HttpSink;
 
 fn plugin_init(plugin: &gst::Plugin) -> bool {
-    source_register(
+    let source_registered = source_register(
         plugin,
         SourceInfo {
             name: "rshttpsrc".into(),
@@ -31,24 +38,44 @@ fn plugin_init(plugin: &gst::Plugin) -> bool {
             description: "Reads HTTP/HTTPS streams".into(),
             classification: "Source/File".into(),
             author: "Sebastian Dröge <sebastian@centricular.com>".into(),
-            rank: 256 + 100,
+            rank: RANK_PRIMARY + 100,
             create_instance: HttpSrc::new_boxed,
             protocols: vec!["http".into(), "https".into()],
             push_only: true,
+            properties: &httpsrc::PROPERTIES,
         },
     );
 
-    true
+    let sink_registered = sink_register(
+        plugin,
+        SinkInfo {
+            name: "rshttpsink".into(),
+            long_name: "HTTP/HTTPS Sink".into(),
+            description: "Uploads streams via HTTP/HTTPS PUT or POST requests".into(),
+            classification: "Sink/Network".into(),
+            author: "Sebastian Dröge <sebastian@centricular.com>".into(),
+            rank: RANK_NONE,
+            create_instance: HttpSink::new_boxed,
+            protocols: vec!["http".into(), "https".into()],
+            properties: &httpsink::PROPERTIES,
+        },
+    );
+
+    let cmaf_sink_registered = ElementRegistration::new(plugin)
+        .element("cmafhttpsink", RANK_NONE, cmafhttpsink::get_type())
+        .register();
+
+    source_registered && sink_registered && cmaf_sink_registered
 }
 
 plugin_define!(
-    b"rshttp\0",
-    b"Rust HTTP Plugin\0",
+    "rshttp",
+    "Rust HTTP Plugin",
     plugin_init,
-    b"1.0\0",
-    b"MIT/X11\0",
-    b"rshttp\0",
-    b"rshttp\0",
-    b"https://github.com/sdroege/rsplugin\0",
-    b"2016-12-08\0"
+    "1.0",
+    "MIT/X11",
+    "rshttp",
+    "rshttp",
+    "https://github.com/sdroege/rsplugin",
+    "2016-12-08"
 );
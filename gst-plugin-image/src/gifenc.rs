@@ -0,0 +1,493 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::{i32, io, mem};
+use std::sync::{Arc, Mutex};
+
+use gif;
+use gif::SetParameter;
+
+// Every frame is quantized to its own 256 color palette. The delay of a frame is taken from the
+// buffer duration, or from the framerate if the buffers have no duration.
+//
+// The encoded data is pushed downstream after every frame, the file is finished with the GIF
+// trailer on EOS. All frames have to have the same size.
+
+const DEFAULT_REPEAT: i32 = -1;
+
+static PROPERTIES: [Property; 1] = [Property::Int(
+    "repeat",
+    "Repeat",
+    "Number of times the animation is repeated (-1 = forever)",
+    (-1, 65535),
+    DEFAULT_REPEAT,
+    PropertyMutability::ReadWrite,
+)];
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    repeat: i32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            repeat: DEFAULT_REPEAT,
+        }
+    }
+}
+
+// Collects the output of the encoder until it is pushed downstream
+#[derive(Clone, Default)]
+struct CacheWriter(Arc<Mutex<Vec<u8>>>);
+
+impl CacheWriter {
+    fn take(&self) -> Vec<u8> {
+        mem::replace(&mut *self.0.lock().unwrap(), Vec::new())
+    }
+}
+
+impl io::Write for CacheWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Converts a duration in nanoseconds to the GIF delay in 1/100 seconds
+fn delay(duration: u64) -> u16 {
+    let delay = (duration + 5 * gst::MSECOND_VAL) / (10 * gst::MSECOND_VAL);
+    if delay > u64::from(u16::max_value()) {
+        u16::max_value()
+    } else {
+        delay as u16
+    }
+}
+
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    encoder: Option<gif::Encoder<CacheWriter>>,
+    cache: CacheWriter,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            info: None,
+            encoder: None,
+            cache: CacheWriter::default(),
+        }
+    }
+}
+
+struct GifEnc {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl GifEnc {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rsgifenc",
+                gst::DebugColorFlags::empty(),
+                "Rust GIF encoder",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "GIF encoder",
+            "Codec/Encoder/Video",
+            "Encodes video frames into an animated GIF",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "video/x-raw",
+            &[
+                ("format", &gst_video::VideoFormat::Rgba.to_string()),
+                ("width", &gst::IntRange::<i32>::new(1, 65535)),
+                ("height", &gst::IntRange::<i32>::new(1, 65535)),
+                (
+                    "framerate",
+                    &gst::FractionRange::new(
+                        gst::Fraction::new(0, 1),
+                        gst::Fraction::new(i32::MAX, 1),
+                    ),
+                ),
+            ],
+        );
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple("image/gif", &[]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            GifEnc::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |gifenc, element| gifenc.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            GifEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |gifenc, element| gifenc.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            GifEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |gifenc, element| gifenc.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_query_function(|pad, parent, query| {
+            GifEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |gifenc, element| gifenc.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let gifenc = element.get_impl().downcast_ref::<GifEnc>().unwrap();
+        element.catch_panic(fallback, |element| f(gifenc, element))
+    }
+
+    fn start_output(&self, element: &Element) {
+        self.srcpad.push_event(
+            gst::Event::new_caps(&gst::Caps::new_simple("image/gif", &[])).build(),
+        );
+        let segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+        self.srcpad
+            .push_event(gst::Event::new_segment(&segment).build());
+
+        gst_debug!(self.cat, obj: element, "Started output");
+    }
+
+    fn push_cache(&self, cache: &CacheWriter) -> gst::FlowReturn {
+        let data = cache.take();
+        if data.is_empty() {
+            return gst::FlowReturn::Ok;
+        }
+
+        self.srcpad.push(gst::Buffer::from_mut_slice(data).unwrap())
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let repeat = self.settings.lock().unwrap().repeat;
+
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let (cache, started) = {
+            let mut state = self.state.lock().unwrap();
+            let info = match state.info {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Negotiation, ["Have no caps"]);
+                    return gst::FlowReturn::NotNegotiated;
+                }
+                Some(ref info) => info.clone(),
+            };
+            let (width, height) = (info.width() as u16, info.height() as u16);
+
+            let started = state.encoder.is_none();
+            if started {
+                let cache = state.cache.clone();
+                let res = gif::Encoder::new(cache, width, height, &[]).and_then(|mut encoder| {
+                    if repeat < 0 {
+                        encoder.set(gif::Repeat::Infinite)?;
+                    } else if repeat > 0 {
+                        encoder.set(gif::Repeat::Finite(repeat as u16))?;
+                    }
+                    Ok(encoder)
+                });
+                match res {
+                    Err(err) => {
+                        gst_element_error!(
+                            element,
+                            gst::LibraryError::Encode,
+                            ["Failed to create encoder: {}", err]
+                        );
+                        return gst::FlowReturn::Error;
+                    }
+                    Ok(encoder) => state.encoder = Some(encoder),
+                }
+            }
+
+            let duration = buffer.get_duration().0.or_else(|| {
+                let fps = info.fps();
+                if *fps.numer() > 0 {
+                    Some(gst::SECOND_VAL * *fps.denom() as u64 / *fps.numer() as u64)
+                } else {
+                    None
+                }
+            });
+
+            let mut pixels = {
+                let map = match buffer.map_readable() {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Failed,
+                            ["Failed to map buffer"]
+                        );
+                        return gst::FlowReturn::Error;
+                    }
+                    Some(map) => map,
+                };
+
+                let stride = info.stride()[0] as usize;
+                let row_len = width as usize * 4;
+                let mut pixels = Vec::with_capacity(row_len * height as usize);
+                for row in map.as_slice().chunks(stride).take(height as usize) {
+                    pixels.extend_from_slice(&row[..row_len]);
+                }
+                pixels
+            };
+
+            let mut frame = gif::Frame::from_rgba(width, height, &mut pixels);
+            frame.delay = duration.map(delay).unwrap_or(0);
+
+            if let Err(err) = state.encoder.as_mut().unwrap().write_frame(&frame) {
+                gst_element_error!(
+                    element,
+                    gst::LibraryError::Encode,
+                    ["Failed to encode frame: {}", err]
+                );
+                return gst::FlowReturn::Error;
+            }
+
+            (state.cache.clone(), started)
+        };
+
+        if started {
+            self.start_output(element);
+        }
+
+        self.push_cache(&cache)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let info = match gst_video::VideoInfo::from_caps(e.get_caps()) {
+                    None => return false,
+                    Some(info) => info,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                if let Some(ref old_info) = state.info {
+                    if state.encoder.is_some()
+                        && (old_info.width() != info.width() || old_info.height() != info.height())
+                    {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Frame size can't change"]
+                        );
+                        return false;
+                    }
+                }
+                state.info = Some(info);
+
+                return true;
+            }
+            // Output is in bytes and starts with the first frame
+            EventView::Segment(..) => return true,
+            EventView::Eos(..) => {
+                let cache = {
+                    let mut state = self.state.lock().unwrap();
+                    // Writes the trailer
+                    let _ = state.encoder.take();
+                    state.cache.clone()
+                };
+                let _ = self.push_cache(&cache);
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for GifEnc {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::Int("repeat", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.repeat = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::Int("repeat", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.repeat.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for GifEnc {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused | gst::StateChange::PausedToReady => {
+                *self.state.lock().unwrap() = State::default();
+            }
+            _ => (),
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct GifEncStatic;
+
+impl ImplTypeStatic<Element> for GifEncStatic {
+    fn get_name(&self) -> &str {
+        "RsGifEnc"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        GifEnc::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        GifEnc::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let gifenc_static = GifEncStatic;
+    register_type(gifenc_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        assert_eq!(delay(0), 0);
+        assert_eq!(delay(40 * gst::MSECOND_VAL), 4);
+        assert_eq!(delay(33_333_333), 3);
+        assert_eq!(delay(1000 * gst::SECOND_VAL), u16::max_value());
+    }
+}
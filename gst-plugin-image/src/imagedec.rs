@@ -0,0 +1,446 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::i32;
+use std::sync::Mutex;
+
+use image;
+use image::{DynamicImage, GenericImage};
+
+// Every input buffer has to contain one complete image, as output by pngparse/jpegparse,
+// multifilesrc or a demuxer. Grayscale images are output as GRAY8, color images as RGB and
+// everything else, including images with an alpha channel, as RGBA.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Png,
+    Jpeg,
+}
+
+impl Format {
+    fn media_type(&self) -> &'static str {
+        match *self {
+            Format::Png => "image/png",
+            Format::Jpeg => "image/jpeg",
+        }
+    }
+
+    fn image_format(&self) -> image::ImageFormat {
+        match *self {
+            Format::Png => image::ImageFormat::PNG,
+            Format::Jpeg => image::ImageFormat::JPEG,
+        }
+    }
+}
+
+struct Decoded {
+    format: gst_video::VideoFormat,
+    width: u32,
+    height: u32,
+    bpp: usize,
+    // Tightly packed rows
+    data: Vec<u8>,
+}
+
+fn decode(data: &[u8], format: Format) -> Result<Decoded, image::ImageError> {
+    let img = image::load_from_memory_with_format(data, format.image_format())?;
+    let (width, height) = img.dimensions();
+
+    let (format, bpp, data) = match img {
+        DynamicImage::ImageLuma8(img) => (gst_video::VideoFormat::Gray8, 1, img.into_raw()),
+        DynamicImage::ImageRgb8(img) => (gst_video::VideoFormat::Rgb, 3, img.into_raw()),
+        img => (gst_video::VideoFormat::Rgba, 4, img.to_rgba().into_raw()),
+    };
+
+    Ok(Decoded {
+        format: format,
+        width: width,
+        height: height,
+        bpp: bpp,
+        data: data,
+    })
+}
+
+struct State {
+    framerate: gst::Fraction,
+    info: Option<gst_video::VideoInfo>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            framerate: gst::Fraction::new(0, 1),
+            info: None,
+        }
+    }
+}
+
+struct ImageDec {
+    cat: gst::DebugCategory,
+    format: Format,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl ImageDec {
+    fn new(_element: &Element, format: Format, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rsimagedec",
+                gst::DebugColorFlags::empty(),
+                "Rust image decoder",
+            ),
+            format: format,
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass, format: Format) {
+        match format {
+            Format::Png => klass.set_metadata(
+                "PNG decoder",
+                "Codec/Decoder/Image",
+                "Decodes PNG images",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            ),
+            Format::Jpeg => klass.set_metadata(
+                "JPEG decoder",
+                "Codec/Decoder/Image",
+                "Decodes JPEG images",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            ),
+        }
+
+        let caps = gst::Caps::new_simple(format.media_type(), &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple(
+            "video/x-raw",
+            &[
+                (
+                    "format",
+                    &gst::List::new(&[
+                        &gst_video::VideoFormat::Gray8.to_string(),
+                        &gst_video::VideoFormat::Rgb.to_string(),
+                        &gst_video::VideoFormat::Rgba.to_string(),
+                    ]),
+                ),
+                ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                (
+                    "framerate",
+                    &gst::FractionRange::new(
+                        gst::Fraction::new(0, 1),
+                        gst::Fraction::new(i32::MAX, 1),
+                    ),
+                ),
+            ],
+        );
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+    }
+
+    fn init(element: &Element, format: Format) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            ImageDec::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |imagedec, element| imagedec.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            ImageDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |imagedec, element| imagedec.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            ImageDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |imagedec, element| imagedec.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            ImageDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |imagedec, element| imagedec.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            ImageDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |imagedec, element| imagedec.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, format, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let imagedec = element.get_impl().downcast_ref::<ImageDec>().unwrap();
+        element.catch_panic(fallback, |element| f(imagedec, element))
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let decoded = {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            match decode(map.as_slice(), self.format) {
+                Err(err) => {
+                    gst_element_error!(
+                        element,
+                        gst::StreamError::Decode,
+                        ["Failed to decode image: {}", err]
+                    );
+                    return gst::FlowReturn::Error;
+                }
+                Ok(decoded) => decoded,
+            }
+        };
+
+        let (info, caps) = {
+            let mut state = self.state.lock().unwrap();
+
+            let changed = match state.info {
+                None => true,
+                Some(ref info) => {
+                    info.format() != decoded.format || info.width() != decoded.width
+                        || info.height() != decoded.height
+                }
+            };
+
+            if changed {
+                let info = gst_video::VideoInfo::new(decoded.format, decoded.width, decoded.height)
+                    .fps(state.framerate)
+                    .build()
+                    .unwrap();
+                let caps = info.to_caps().unwrap();
+                state.info = Some(info.clone());
+                (info, Some(caps))
+            } else {
+                (state.info.clone().unwrap(), None)
+            }
+        };
+
+        if let Some(caps) = caps {
+            gst_debug!(self.cat, obj: element, "Output caps changed to {}", caps);
+            self.srcpad.push_event(gst::Event::new_caps(&caps).build());
+        }
+
+        let mut outbuf = gst::Buffer::with_size(info.size()).unwrap();
+        {
+            let outbuf = outbuf.get_mut().unwrap();
+            outbuf.set_pts(buffer.get_pts());
+            outbuf.set_dts(buffer.get_dts());
+            outbuf.set_duration(buffer.get_duration());
+
+            let mut map = outbuf.map_writable().unwrap();
+            let stride = info.stride()[0] as usize;
+            let row_len = decoded.width as usize * decoded.bpp;
+            for (out, row) in map.as_mut_slice()
+                .chunks_mut(stride)
+                .zip(decoded.data.chunks(row_len))
+            {
+                out[..row_len].copy_from_slice(row);
+            }
+        }
+
+        gst_log!(self.cat, obj: pad, "Pushing buffer {:?}", outbuf);
+        self.srcpad.push(outbuf)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        // The output caps are only known after decoding the first image
+        if let EventView::Caps(e) = event.view() {
+            let framerate = e.get_caps()
+                .get_structure(0)
+                .and_then(|s| s.get::<gst::Fraction>("framerate"))
+                .unwrap_or_else(|| gst::Fraction::new(0, 1));
+
+            let mut state = self.state.lock().unwrap();
+            state.framerate = framerate;
+            state.info = None;
+
+            return true;
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for ImageDec {}
+
+impl ElementImpl<Element> for ImageDec {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct ImageDecStatic {
+    format: Format,
+}
+
+impl ImplTypeStatic<Element> for ImageDecStatic {
+    fn get_name(&self) -> &str {
+        match self.format {
+            Format::Png => "RsPngDec",
+            Format::Jpeg => "RsJpegDec",
+        }
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        ImageDec::init(element, self.format)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        ImageDec::class_init(klass, self.format);
+    }
+}
+
+pub fn get_png_type() -> glib::Type {
+    let pngdec_static = ImageDecStatic {
+        format: Format::Png,
+    };
+    register_type(pngdec_static)
+}
+
+pub fn get_jpeg_type() -> glib::Type {
+    let jpegdec_static = ImageDecStatic {
+        format: Format::Jpeg,
+    };
+    register_type(jpegdec_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_png() {
+        let pixels = [
+            255, 0, 0, 0, 255, 0, 0, 0, 255, //
+            1, 2, 3, 4, 5, 6, 7, 8, 9,
+        ];
+
+        let mut data = Vec::new();
+        image::png::PNGEncoder::new(&mut data)
+            .encode(&pixels, 3, 2, image::ColorType::RGB(8))
+            .unwrap();
+
+        let decoded = decode(&data, Format::Png).unwrap();
+        assert_eq!(decoded.format, gst_video::VideoFormat::Rgb);
+        assert_eq!((decoded.width, decoded.height, decoded.bpp), (3, 2, 3));
+        assert_eq!(&decoded.data[..], &pixels[..]);
+
+        assert!(decode(&data[..10], Format::Png).is_err());
+    }
+}
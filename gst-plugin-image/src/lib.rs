@@ -0,0 +1,40 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate gif;
+extern crate glib;
+#[macro_use]
+extern crate gst_plugin;
+#[macro_use]
+extern crate gstreamer as gst;
+extern crate gstreamer_video as gst_video;
+extern crate image;
+
+use gst_plugin::registration::*;
+
+mod gifenc;
+mod imagedec;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    ElementRegistration::new(plugin)
+        .element("rsgifenc", RANK_NONE, gifenc::get_type())
+        .element("rspngdec", RANK_NONE, imagedec::get_png_type())
+        .element("rsjpegdec", RANK_NONE, imagedec::get_jpeg_type())
+        .register()
+}
+
+plugin_define!(
+    "rsimage",
+    "Rust Image Plugin",
+    plugin_init,
+    "MIT/X11",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2018-01-22"
+);
@@ -0,0 +1,291 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+use std::u64;
+
+use line;
+use line::Line;
+
+// Writes every buffer with its metadata as one line, preceded by a header line with the caps
+// whenever they change. The output buffers keep the timestamps of the input buffers.
+
+fn offset(offset: u64) -> Option<u64> {
+    if offset == u64::MAX {
+        None
+    } else {
+        Some(offset)
+    }
+}
+
+struct State {
+    // Caps that were not written yet
+    pending_caps: Option<gst::Caps>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State { pending_caps: None }
+    }
+}
+
+struct JsonGstEnc {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl JsonGstEnc {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "jsongstenc",
+                gst::DebugColorFlags::empty(),
+                "GStreamer JSON encoder",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "GStreamer JSON encoder",
+            "Encoder/Generic",
+            "Serializes buffers and their metadata as newline-delimited JSON",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple("application/x-json", &[]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            JsonGstEnc::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |jsongstenc, element| jsongstenc.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            JsonGstEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |jsongstenc, element| jsongstenc.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            JsonGstEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |jsongstenc, element| jsongstenc.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            JsonGstEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |jsongstenc, element| jsongstenc.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            JsonGstEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |jsongstenc, element| jsongstenc.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let jsongstenc = element.get_impl().downcast_ref::<JsonGstEnc>().unwrap();
+        element.catch_panic(fallback, |element| f(jsongstenc, element))
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let mut text = String::new();
+        if let Some(caps) = self.state.lock().unwrap().pending_caps.take() {
+            let header = Line::Header {
+                caps: caps.to_string(),
+            };
+            text.push_str(&header.to_json());
+        }
+
+        {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            let line = Line::Buffer {
+                pts: buffer.get_pts().0,
+                dts: buffer.get_dts().0,
+                duration: buffer.get_duration().0,
+                offset: offset(buffer.get_offset()),
+                offset_end: offset(buffer.get_offset_end()),
+                flags: buffer.get_flags().bits(),
+                data: line::encode_data(map.as_slice()),
+            };
+            text.push_str(&line.to_json());
+        }
+
+        let mut outbuf = gst::Buffer::from_mut_slice(text.into_bytes()).unwrap();
+        {
+            let outbuf = outbuf.get_mut().unwrap();
+            outbuf.set_pts(buffer.get_pts());
+            outbuf.set_dts(buffer.get_dts());
+            outbuf.set_duration(buffer.get_duration());
+        }
+
+        self.srcpad.push(outbuf)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        if let EventView::Caps(e) = event.view() {
+            let mut state = self.state.lock().unwrap();
+            state.pending_caps = Some(e.get_caps().to_owned());
+            drop(state);
+
+            let caps = gst::Caps::new_simple("application/x-json", &[]);
+            return self.srcpad.push_event(gst::Event::new_caps(&caps).build());
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for JsonGstEnc {}
+
+impl ElementImpl<Element> for JsonGstEnc {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct JsonGstEncStatic;
+
+impl ImplTypeStatic<Element> for JsonGstEncStatic {
+    fn get_name(&self) -> &str {
+        "JsonGstEnc"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        JsonGstEnc::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        JsonGstEnc::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let jsongstenc_static = JsonGstEncStatic;
+    register_type(jsongstenc_static)
+}
@@ -0,0 +1,368 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+use std::u64;
+
+use line;
+use line::Line;
+
+// Splits the input into lines and restores the buffers with their original metadata and the
+// caps from the header lines. The output segment starts at 0, so the original timestamps are
+// also the running times.
+
+struct State {
+    // Incomplete last line
+    line: Vec<u8>,
+    need_segment: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            line: Vec::new(),
+            need_segment: true,
+        }
+    }
+}
+
+enum Item {
+    Event(gst::Event),
+    Buffer(gst::Buffer),
+}
+
+fn parse_line(s: &str) -> Result<Option<Item>, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(None);
+    }
+
+    match Line::parse(s)? {
+        Line::Header { caps } => {
+            let caps = gst::Caps::from_string(&caps)
+                .ok_or_else(|| format!("Invalid caps {:?}", caps))?;
+            Ok(Some(Item::Event(gst::Event::new_caps(&caps).build())))
+        }
+        Line::Buffer {
+            pts,
+            dts,
+            duration,
+            offset,
+            offset_end,
+            flags,
+            data,
+        } => {
+            let mut buffer = gst::Buffer::from_mut_slice(line::decode_data(&data)?).unwrap();
+            {
+                let buffer = buffer.get_mut().unwrap();
+                buffer.set_pts(gst::ClockTime(pts));
+                buffer.set_dts(gst::ClockTime(dts));
+                buffer.set_duration(gst::ClockTime(duration));
+                buffer.set_offset(offset.unwrap_or(u64::MAX));
+                buffer.set_offset_end(offset_end.unwrap_or(u64::MAX));
+                buffer.set_flags(gst::BufferFlags::from_bits_truncate(flags));
+            }
+            Ok(Some(Item::Buffer(buffer)))
+        }
+    }
+}
+
+struct JsonGstParse {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl JsonGstParse {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "jsongstparse",
+                gst::DebugColorFlags::empty(),
+                "GStreamer JSON parser",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "GStreamer JSON parser",
+            "Parser/Generic",
+            "Restores buffers and their metadata from newline-delimited JSON",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("application/x-json", &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_any();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            JsonGstParse::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |jsongstparse, element| jsongstparse.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            JsonGstParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |jsongstparse, element| jsongstparse.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            JsonGstParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |jsongstparse, element| jsongstparse.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            JsonGstParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |jsongstparse, element| jsongstparse.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            JsonGstParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |jsongstparse, element| jsongstparse.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let jsongstparse = element.get_impl().downcast_ref::<JsonGstParse>().unwrap();
+        element.catch_panic(fallback, |element| f(jsongstparse, element))
+    }
+
+    // Parses all complete lines, or everything if draining
+    fn handle_data(&self, element: &Element, drain: bool) -> gst::FlowReturn {
+        let mut items = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+
+            loop {
+                let newline = state.line.iter().position(|&b| b == b'\n');
+                let line = match newline {
+                    Some(pos) => state.line.drain(..(pos + 1)).collect::<Vec<_>>(),
+                    None if drain && !state.line.is_empty() => state.line.split_off(0),
+                    None => break,
+                };
+
+                let item = match parse_line(&String::from_utf8_lossy(&line)) {
+                    Err(err) => {
+                        gst_element_error!(element, gst::StreamError::Decode, ["{}", err]);
+                        return gst::FlowReturn::Error;
+                    }
+                    Ok(None) => continue,
+                    Ok(Some(item)) => item,
+                };
+
+                // The segment has to come after the first caps
+                if let Item::Buffer(..) = item {
+                    if state.need_segment {
+                        state.need_segment = false;
+                        let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+                        items.push(Item::Event(gst::Event::new_segment(&segment).build()));
+                    }
+                }
+                items.push(item);
+            }
+        }
+
+        for item in items {
+            match item {
+                Item::Event(event) => {
+                    self.srcpad.push_event(event);
+                }
+                Item::Buffer(buffer) => {
+                    let flow_ret = self.srcpad.push(buffer);
+                    if flow_ret != gst::FlowReturn::Ok {
+                        return flow_ret;
+                    }
+                }
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+            self.state
+                .lock()
+                .unwrap()
+                .line
+                .extend_from_slice(map.as_slice());
+        }
+
+        self.handle_data(element, false)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            // Caps come from the header lines
+            EventView::Caps(..) => return true,
+            EventView::Segment(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.line.clear();
+                state.need_segment = true;
+                return true;
+            }
+            EventView::FlushStop(..) => {
+                self.state.lock().unwrap().line.clear();
+            }
+            EventView::Eos(..) => {
+                let _ = self.handle_data(element, true);
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for JsonGstParse {}
+
+impl ElementImpl<Element> for JsonGstParse {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct JsonGstParseStatic;
+
+impl ImplTypeStatic<Element> for JsonGstParseStatic {
+    fn get_name(&self) -> &str {
+        "JsonGstParse"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        JsonGstParse::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        JsonGstParse::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let jsongstparse_static = JsonGstParseStatic;
+    register_type(jsongstparse_static)
+}
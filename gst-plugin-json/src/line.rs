@@ -0,0 +1,91 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use base64;
+use serde_json;
+
+// Stream format, one JSON object per line:
+//
+// {"Header":{"caps":"audio/x-raw, format=(string)S16LE, ..."}}
+// {"Buffer":{"pts":0,"dts":null,"duration":20000000,"offset":null,"offset_end":null,
+//            "flags":64,"data":"AAEC..."}}
+//
+// A header is written at the start and whenever the caps change. Times are in nanoseconds,
+// the flags are the GstBufferFlags bits and the data is base64 encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Line {
+    Header {
+        caps: String,
+    },
+    Buffer {
+        pts: Option<u64>,
+        dts: Option<u64>,
+        duration: Option<u64>,
+        offset: Option<u64>,
+        offset_end: Option<u64>,
+        flags: u32,
+        data: String,
+    },
+}
+
+impl Line {
+    pub fn parse(s: &str) -> Result<Line, String> {
+        serde_json::from_str(s).map_err(|err| format!("Failed to parse line: {}", err))
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = serde_json::to_string(self).unwrap();
+        s.push('\n');
+        s
+    }
+}
+
+pub fn encode_data(data: &[u8]) -> String {
+    base64::encode(data)
+}
+
+pub fn decode_data(data: &str) -> Result<Vec<u8>, String> {
+    base64::decode(data).map_err(|err| format!("Invalid data: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let line = Line::Buffer {
+            pts: Some(1_000_000_000),
+            dts: None,
+            duration: Some(20_000_000),
+            offset: None,
+            offset_end: None,
+            flags: 64,
+            data: encode_data(&[0, 1, 2, 255]),
+        };
+
+        let s = line.to_json();
+        assert!(s.ends_with('\n') && !s[..(s.len() - 1)].contains('\n'));
+        assert_eq!(Line::parse(&s).unwrap(), line);
+
+        match line {
+            Line::Buffer { ref data, .. } => {
+                assert_eq!(decode_data(data).unwrap(), vec![0, 1, 2, 255])
+            }
+            _ => unreachable!(),
+        }
+
+        assert_eq!(
+            Line::parse(r#"{"Header":{"caps":"audio/x-raw"}}"#).unwrap(),
+            Line::Header {
+                caps: "audio/x-raw".into(),
+            }
+        );
+        assert!(Line::parse(r#"{"Buffer":{}}"#).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+pub const VOID: u32 = 0xec;
+
+// Number of bytes used for sizes that are filled in or rewritten later
+const FIXED_SIZE_LENGTH: usize = 8;
+const UNKNOWN_SIZE: u64 = 0x01ff_ffff_ffff_ffff;
+
+fn id_length(id: u32) -> usize {
+    if id <= 0xff {
+        1
+    } else if id <= 0xffff {
+        2
+    } else if id <= 0x00ff_ffff {
+        3
+    } else {
+        4
+    }
+}
+
+fn size_length(size: u64) -> usize {
+    // All ones is reserved for the unknown size
+    let mut len = 1;
+    while len < FIXED_SIZE_LENGTH && size >= (1 << (7 * len)) - 1 {
+        len += 1;
+    }
+    len
+}
+
+fn uint_length(value: u64) -> usize {
+    let mut len = 1;
+    while len < 8 && value >> (8 * len) != 0 {
+        len += 1;
+    }
+    len
+}
+
+pub fn write_id(data: &mut Vec<u8>, id: u32) {
+    let len = id_length(id);
+    for i in (0..len).rev() {
+        data.push((id >> (8 * i)) as u8);
+    }
+}
+
+fn write_size_with_length(data: &mut Vec<u8>, size: u64, len: usize) {
+    let value = size | (1 << (7 * len));
+    for i in (0..len).rev() {
+        data.push((value >> (8 * i)) as u8);
+    }
+}
+
+pub fn write_size(data: &mut Vec<u8>, size: u64) {
+    let len = size_length(size);
+    write_size_with_length(data, size, len);
+}
+
+// Writes EBML elements into a growing buffer. Master elements are either closed
+// explicitly with end_master() or, in live mode, written with an unknown size.
+#[derive(Debug, Default)]
+pub struct Writer {
+    data: Vec<u8>,
+    open: Vec<usize>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        assert!(self.open.is_empty());
+        self.data
+    }
+
+    pub fn write_header(&mut self, id: u32, size: u64) {
+        write_id(&mut self.data, id);
+        write_size(&mut self.data, size);
+    }
+
+    pub fn start_master(&mut self, id: u32) {
+        write_id(&mut self.data, id);
+        self.open.push(self.data.len());
+        write_size_with_length(&mut self.data, 0, FIXED_SIZE_LENGTH);
+    }
+
+    pub fn end_master(&mut self) {
+        let pos = self.open.pop().unwrap();
+        let size = (self.data.len() - pos - FIXED_SIZE_LENGTH) as u64;
+        let mut encoded = Vec::with_capacity(FIXED_SIZE_LENGTH);
+        write_size_with_length(&mut encoded, size, FIXED_SIZE_LENGTH);
+        self.data[pos..pos + FIXED_SIZE_LENGTH].copy_from_slice(&encoded);
+    }
+
+    pub fn start_unknown_size(&mut self, id: u32) {
+        write_id(&mut self.data, id);
+        self.data
+            .write_u64::<BigEndian>(UNKNOWN_SIZE)
+            .unwrap();
+    }
+
+    // Writes a master element with a fixed size field, so that it keeps its length
+    // when it is written again later with the final size
+    pub fn write_fixed_size(&mut self, id: u32, size: Option<u64>) {
+        match size {
+            None => self.start_unknown_size(id),
+            Some(size) => {
+                write_id(&mut self.data, id);
+                write_size_with_length(&mut self.data, size, FIXED_SIZE_LENGTH);
+            }
+        }
+    }
+
+    pub fn write_uint(&mut self, id: u32, value: u64) {
+        let len = uint_length(value);
+        self.write_header(id, len as u64);
+        for i in (0..len).rev() {
+            self.data.push((value >> (8 * i)) as u8);
+        }
+    }
+
+    // Always uses 8 bytes, for values that are rewritten later
+    pub fn write_uint_fixed(&mut self, id: u32, value: u64) {
+        self.write_header(id, 8);
+        self.data.write_u64::<BigEndian>(value).unwrap();
+    }
+
+    pub fn write_float(&mut self, id: u32, value: f64) {
+        self.write_header(id, 8);
+        self.data.write_f64::<BigEndian>(value).unwrap();
+    }
+
+    pub fn write_string(&mut self, id: u32, value: &str) {
+        self.write_binary(id, value.as_bytes());
+    }
+
+    pub fn write_binary(&mut self, id: u32, value: &[u8]) {
+        self.write_header(id, value.len() as u64);
+        self.data.extend_from_slice(value);
+    }
+
+    pub fn write_raw(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+
+    // Writes a Void element that takes exactly len bytes in total
+    pub fn write_void(&mut self, len: usize) {
+        assert!(len >= 2);
+        let mut size_len = 1;
+        while size_length((len - 1 - size_len) as u64) > size_len {
+            size_len += 1;
+        }
+        let size = len - 1 - size_len;
+        write_id(&mut self.data, VOID);
+        write_size_with_length(&mut self.data, size as u64, size_len);
+        let new_len = self.data.len() + size;
+        self.data.resize(new_len, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elements() {
+        let mut data = Vec::new();
+        write_size(&mut data, 0);
+        write_size(&mut data, 126);
+        write_size(&mut data, 127);
+        write_size(&mut data, 0x3fff);
+        assert_eq!(data, [0x80, 0xfe, 0x40, 0x7f, 0x20, 0x3f, 0xff]);
+
+        let mut writer = Writer::new();
+        writer.start_master(0x1a45_dfa3);
+        writer.write_uint(0x4286, 1);
+        writer.write_string(0x4282, "webm");
+        writer.end_master();
+        writer.write_uint(0xe7, 0x1234);
+        assert_eq!(
+            writer.into_inner(),
+            [
+                0x1a, 0x45, 0xdf, 0xa3, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0b, 0x42, 0x86,
+                0x81, 0x01, 0x42, 0x82, 0x84, b'w', b'e', b'b', b'm', 0xe7, 0x82, 0x12, 0x34,
+            ]
+        );
+
+        for len in 2..300 {
+            let mut writer = Writer::new();
+            writer.write_void(len);
+            let data = writer.into_inner();
+            assert_eq!(data.len(), len);
+            assert_eq!(data[0], 0xec);
+        }
+    }
+}
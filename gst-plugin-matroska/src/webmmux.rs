@@ -0,0 +1,1464 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::tags::{StreamInfo, TrackRole};
+
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::i16;
+use std::i32;
+use std::io::Cursor;
+use std::mem;
+use std::sync::Mutex;
+use std::u64;
+
+use ebml::{self, Writer};
+
+const EBML: u32 = 0x1a45_dfa3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42f7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42f2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42f3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114d_9b74;
+const SEEK: u32 = 0x4dbb;
+const SEEK_ID: u32 = 0x53ab;
+const SEEK_POSITION: u32 = 0x53ac;
+const INFO: u32 = 0x1549_a966;
+const TIMESTAMP_SCALE: u32 = 0x2a_d7b1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4d80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_ae6b;
+const TRACK_ENTRY: u32 = 0xae;
+const TRACK_NUMBER: u32 = 0xd7;
+const TRACK_UID: u32 = 0x73c5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_DEFAULT: u32 = 0x88;
+const FLAG_LACING: u32 = 0x9c;
+const FLAG_HEARING_IMPAIRED: u32 = 0x55ab;
+const FLAG_VISUAL_IMPAIRED: u32 = 0x55ac;
+const FLAG_COMMENTARY: u32 = 0x55af;
+const LANGUAGE: u32 = 0x22_b59c;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63a2;
+const CODEC_DELAY: u32 = 0x56aa;
+const SEEK_PRE_ROLL: u32 = 0x56bb;
+const VIDEO: u32 = 0xe0;
+const PIXEL_WIDTH: u32 = 0xb0;
+const PIXEL_HEIGHT: u32 = 0xba;
+const AUDIO: u32 = 0xe1;
+const SAMPLING_FREQUENCY: u32 = 0xb5;
+const CHANNELS: u32 = 0x9f;
+const CLUSTER: u32 = 0x1f43_b675;
+const TIMESTAMP: u32 = 0xe7;
+const SIMPLE_BLOCK: u32 = 0xa3;
+const BLOCK_GROUP: u32 = 0xa0;
+const BLOCK: u32 = 0xa1;
+const BLOCK_DURATION: u32 = 0x9b;
+const CUES: u32 = 0x1c53_bb6b;
+const CUE_POINT: u32 = 0xbb;
+const CUE_TIME: u32 = 0xb3;
+const CUE_TRACK_POSITIONS: u32 = 0xb7;
+const CUE_TRACK: u32 = 0xf7;
+const CUE_CLUSTER_POSITION: u32 = 0xf1;
+
+// All timestamps are in milliseconds
+const TIMESTAMP_SCALE_NS: u64 = 1_000_000;
+const OPUS_SEEK_PRE_ROLL: u64 = 80_000_000;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK_TYPE_AUDIO: u64 = 2;
+const TRACK_TYPE_SUBTITLE: u64 = 17;
+
+const DEFAULT_LIVE: bool = false;
+const DEFAULT_CLUSTER_DURATION: u64 = 2 * gst::SECOND_VAL;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    live: bool,
+    cluster_duration: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            live: DEFAULT_LIVE,
+            cluster_duration: DEFAULT_CLUSTER_DURATION,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 2] = [
+    Property::Boolean(
+        "live",
+        "Live",
+        "Write a stream for non-seekable outputs: unknown sizes, no cues and no duration",
+        DEFAULT_LIVE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "cluster-duration",
+        "Cluster Duration",
+        "Start a new cluster at the next keyframe after this much time (in nanoseconds)",
+        (0, u64::MAX),
+        DEFAULT_CLUSTER_DURATION,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Codec {
+    Vp8 { width: u32, height: u32 },
+    Vp9 { width: u32, height: u32 },
+    Av1 { width: u32, height: u32, av1c: Vec<u8> },
+    Opus { channels: u32, pre_skip: u16, opus_head: Vec<u8> },
+    Vorbis { channels: u32, rate: u32, headers: Vec<u8> },
+    // WebVTT cue text, one cue per block
+    WebVtt,
+}
+
+impl Codec {
+    fn track_type(&self) -> u64 {
+        match *self {
+            Codec::Vp8 { .. } | Codec::Vp9 { .. } | Codec::Av1 { .. } => TRACK_TYPE_VIDEO,
+            Codec::Opus { .. } | Codec::Vorbis { .. } => TRACK_TYPE_AUDIO,
+            Codec::WebVtt => TRACK_TYPE_SUBTITLE,
+        }
+    }
+
+    fn is_video(&self) -> bool {
+        self.track_type() == TRACK_TYPE_VIDEO
+    }
+
+    // Sparse streams have no data most of the time, so muxing does not wait for them
+    fn is_sparse(&self) -> bool {
+        self.track_type() == TRACK_TYPE_SUBTITLE
+    }
+
+    fn write_track_entry(&self, writer: &mut Writer, number: u64, info: &StreamInfo) {
+        writer.start_master(TRACK_ENTRY);
+        writer.write_uint(TRACK_NUMBER, number);
+        writer.write_uint(TRACK_UID, number);
+        writer.write_uint(TRACK_TYPE, self.track_type());
+        writer.write_uint(FLAG_LACING, 0);
+
+        // The default language is English, so it has to be written even if unknown
+        let language = info.language_iso_639_2b().unwrap_or_else(|| String::from("und"));
+        writer.write_string(LANGUAGE, &language);
+        writer.write_uint(FLAG_DEFAULT, if info.is_default() { 1 } else { 0 });
+        match info.role {
+            Some(TrackRole::Caption) => writer.write_uint(FLAG_HEARING_IMPAIRED, 1),
+            Some(TrackRole::Description) => writer.write_uint(FLAG_VISUAL_IMPAIRED, 1),
+            Some(TrackRole::Commentary) => writer.write_uint(FLAG_COMMENTARY, 1),
+            _ => (),
+        }
+
+        match *self {
+            Codec::Vp8 { width, height } => {
+                writer.write_string(CODEC_ID, "V_VP8");
+                write_video(writer, width, height);
+            }
+            Codec::Vp9 { width, height } => {
+                writer.write_string(CODEC_ID, "V_VP9");
+                write_video(writer, width, height);
+            }
+            Codec::Av1 {
+                width,
+                height,
+                ref av1c,
+            } => {
+                writer.write_string(CODEC_ID, "V_AV1");
+                writer.write_binary(CODEC_PRIVATE, av1c);
+                write_video(writer, width, height);
+            }
+            Codec::Opus {
+                channels,
+                pre_skip,
+                ref opus_head,
+            } => {
+                writer.write_string(CODEC_ID, "A_OPUS");
+                writer.write_binary(CODEC_PRIVATE, opus_head);
+                writer.write_uint(CODEC_DELAY, u64::from(pre_skip) * gst::SECOND_VAL / 48_000);
+                writer.write_uint(SEEK_PRE_ROLL, OPUS_SEEK_PRE_ROLL);
+                write_audio(writer, channels, 48_000);
+            }
+            Codec::Vorbis {
+                channels,
+                rate,
+                ref headers,
+            } => {
+                writer.write_string(CODEC_ID, "A_VORBIS");
+                writer.write_binary(CODEC_PRIVATE, headers);
+                write_audio(writer, channels, rate);
+            }
+            Codec::WebVtt => {
+                writer.write_string(CODEC_ID, "D_WEBVTT/SUBTITLES");
+            }
+        }
+
+        writer.end_master();
+    }
+}
+
+fn write_video(writer: &mut Writer, width: u32, height: u32) {
+    writer.start_master(VIDEO);
+    writer.write_uint(PIXEL_WIDTH, u64::from(width));
+    writer.write_uint(PIXEL_HEIGHT, u64::from(height));
+    writer.end_master();
+}
+
+fn write_audio(writer: &mut Writer, channels: u32, rate: u32) {
+    writer.start_master(AUDIO);
+    writer.write_float(SAMPLING_FREQUENCY, f64::from(rate));
+    writer.write_uint(CHANNELS, u64::from(channels));
+    writer.end_master();
+}
+
+// Values that are only known at the end. Until then their elements are replaced by
+// Void elements of the same size, so that the header can be rewritten in place.
+#[derive(Debug, Default)]
+struct HeaderInfo {
+    segment_size: Option<u64>,
+    duration: Option<u64>,
+    cues_position: Option<u64>,
+}
+
+fn write_optional<F: FnOnce(&mut Writer)>(writer: &mut Writer, present: bool, f: F) {
+    let mut element = Writer::new();
+    f(&mut element);
+    if present {
+        writer.write_raw(&element.into_inner());
+    } else {
+        writer.write_void(element.len());
+    }
+}
+
+fn write_seek(writer: &mut Writer, id: u32, position: u64) {
+    let mut seek_id = Vec::new();
+    ebml::write_id(&mut seek_id, id);
+
+    writer.start_master(SEEK);
+    writer.write_binary(SEEK_ID, &seek_id);
+    writer.write_uint_fixed(SEEK_POSITION, position);
+    writer.end_master();
+}
+
+// Returns the header and the offset of the segment data inside it
+fn write_header(tracks: &[(u64, Codec, StreamInfo)], info: &HeaderInfo) -> (Vec<u8>, u64) {
+    let mut info_element = Writer::new();
+    info_element.start_master(INFO);
+    info_element.write_uint(TIMESTAMP_SCALE, TIMESTAMP_SCALE_NS);
+    write_optional(&mut info_element, info.duration.is_some(), |w| {
+        w.write_float(DURATION, info.duration.unwrap_or(0) as f64)
+    });
+    info_element.write_string(MUXING_APP, "gst-plugin-rs");
+    info_element.write_string(WRITING_APP, "gst-plugin-rs");
+    info_element.end_master();
+
+    let mut tracks_element = Writer::new();
+    tracks_element.start_master(TRACKS);
+    for &(number, ref codec, ref stream_info) in tracks {
+        codec.write_track_entry(&mut tracks_element, number, stream_info);
+    }
+    tracks_element.end_master();
+
+    // The seek head has the same size independent of the positions
+    let write_seek_head = |writer: &mut Writer, info_position: u64, tracks_position: u64| {
+        writer.start_master(SEEK_HEAD);
+        write_seek(writer, INFO, info_position);
+        write_seek(writer, TRACKS, tracks_position);
+        write_optional(writer, info.cues_position.is_some(), |w| {
+            write_seek(w, CUES, info.cues_position.unwrap_or(0))
+        });
+        writer.end_master();
+    };
+    let mut seek_head = Writer::new();
+    write_seek_head(&mut seek_head, 0, 0);
+    let info_position = seek_head.len() as u64;
+    let tracks_position = info_position + info_element.len() as u64;
+
+    let mut writer = Writer::new();
+    writer.start_master(EBML);
+    writer.write_uint(EBML_VERSION, 1);
+    writer.write_uint(EBML_READ_VERSION, 1);
+    writer.write_uint(EBML_MAX_ID_LENGTH, 4);
+    writer.write_uint(EBML_MAX_SIZE_LENGTH, 8);
+    writer.write_string(DOC_TYPE, "webm");
+    writer.write_uint(DOC_TYPE_VERSION, 4);
+    writer.write_uint(DOC_TYPE_READ_VERSION, 2);
+    writer.end_master();
+
+    writer.write_fixed_size(SEGMENT, info.segment_size);
+    let segment_data_start = writer.len() as u64;
+
+    write_seek_head(&mut writer, info_position, tracks_position);
+    writer.write_raw(&info_element.into_inner());
+    writer.write_raw(&tracks_element.into_inner());
+
+    (writer.into_inner(), segment_data_start)
+}
+
+struct Block {
+    // Running times in nanoseconds
+    pts: u64,
+    dts: i64,
+    end: u64,
+    keyframe: bool,
+    buffer: gst::Buffer,
+}
+
+struct Stream {
+    sinkpad: gst::Pad,
+    codec: Option<Codec>,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    queue: VecDeque<Block>,
+    track_number: u64,
+    // Language and role from the tag events
+    info: StreamInfo,
+    eos: bool,
+}
+
+impl Stream {
+    fn new(sinkpad: gst::Pad) -> Stream {
+        Stream {
+            sinkpad: sinkpad,
+            codec: None,
+            segment: gst::FormattedSegment::new(),
+            queue: VecDeque::new(),
+            track_number: 0,
+            info: StreamInfo::default(),
+            eos: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.codec = None;
+        self.segment = gst::FormattedSegment::new();
+        self.queue.clear();
+        self.track_number = 0;
+        self.info = StreamInfo::default();
+        self.eos = false;
+    }
+}
+
+struct Cluster {
+    // In milliseconds
+    time: u64,
+    pts: u64,
+    writer: Writer,
+    // Whether the cluster starts with a keyframe
+    keyframe: bool,
+    // Whether anything of the cluster was output already, only in live mode
+    pushed: bool,
+}
+
+struct CuePoint {
+    time: u64,
+    track: u64,
+    position: u64,
+}
+
+struct State {
+    streams: Vec<Stream>,
+    pad_count: u32,
+    started: bool,
+    live: bool,
+    seekable: bool,
+    tracks: Vec<(u64, Codec, StreamInfo)>,
+    segment_data_start: u64,
+    // Number of bytes output so far
+    position: u64,
+    cluster: Option<Cluster>,
+    cues: Vec<CuePoint>,
+    // DTS of the last written block
+    last_dts: Option<i64>,
+    end_time: u64,
+    finished: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            streams: Vec::new(),
+            pad_count: 0,
+            started: false,
+            live: DEFAULT_LIVE,
+            seekable: false,
+            tracks: Vec::new(),
+            segment_data_start: 0,
+            position: 0,
+            cluster: None,
+            cues: Vec::new(),
+            last_dts: None,
+            end_time: 0,
+            finished: false,
+        }
+    }
+}
+
+impl State {
+    fn output(&mut self, data: Vec<u8>, pts: Option<u64>, flags: gst::BufferFlags) -> gst::Buffer {
+        self.position += data.len() as u64;
+
+        let mut buffer = gst::Buffer::from_mut_slice(data).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime(pts));
+            buffer.set_flags(flags);
+        }
+        buffer
+    }
+
+    fn finish_cluster(&mut self, output: &mut Vec<gst::Buffer>) {
+        let mut cluster = match self.cluster.take() {
+            None => return,
+            Some(cluster) => cluster,
+        };
+
+        if self.live {
+            return;
+        }
+
+        cluster.writer.end_master();
+        let flags = if cluster.keyframe {
+            gst::BufferFlags::empty()
+        } else {
+            gst::BufferFlags::DELTA_UNIT
+        };
+        let buffer = self.output(cluster.writer.into_inner(), Some(cluster.pts), flags);
+        output.push(buffer);
+    }
+}
+
+struct WebmMux {
+    cat: gst::DebugCategory,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    // Serializes pushing downstream so that the output is in the same order as it was
+    // written, without keeping the state locked while pushing
+    output_lock: Mutex<()>,
+}
+
+fn dimensions(s: &gst::StructureRef) -> Option<(u32, u32)> {
+    let width = s.get::<i32>("width")?;
+    let height = s.get::<i32>("height")?;
+    if width <= 0 || height <= 0 {
+        return None;
+    }
+    Some((width as u32, height as u32))
+}
+
+fn stream_headers(s: &gst::StructureRef) -> Option<Vec<Vec<u8>>> {
+    let streamheader = s.get::<gst::Array>("streamheader")?;
+    streamheader
+        .as_slice()
+        .iter()
+        .map(|v| {
+            let buffer = v.get::<gst::Buffer>()?;
+            let map = buffer.map_readable()?;
+            Some(map.as_slice().to_vec())
+        })
+        .collect()
+}
+
+fn opus_head(channels: u8, rate: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(19);
+    data.extend_from_slice(b"OpusHead");
+    data.push(1);
+    data.push(channels);
+    data.write_u16::<LittleEndian>(0).unwrap();
+    data.write_u32::<LittleEndian>(rate).unwrap();
+    data.write_i16::<LittleEndian>(0).unwrap();
+    data.push(0);
+    data
+}
+
+// Xiph lacing of the three Vorbis headers as stored in the CodecPrivate
+fn xiph_lace(headers: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = vec![(headers.len() - 1) as u8];
+    for header in &headers[..headers.len() - 1] {
+        let mut len = header.len();
+        while len >= 255 {
+            data.push(255);
+            len -= 255;
+        }
+        data.push(len as u8);
+    }
+    for header in headers {
+        data.extend_from_slice(header);
+    }
+    data
+}
+
+// Plain text has to be escaped to be used as WebVTT cue text
+fn escape_webvtt(text: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(text.len());
+    for &c in text {
+        match c {
+            b'&' => escaped.extend_from_slice(b"&amp;"),
+            b'<' => escaped.extend_from_slice(b"&lt;"),
+            b'>' => escaped.extend_from_slice(b"&gt;"),
+            // Empty lines would end the cue
+            b'\r' => (),
+            b'\n' if escaped.last() == Some(&b'\n') => (),
+            _ => escaped.push(c),
+        }
+    }
+    while escaped.last() == Some(&b'\n') {
+        escaped.pop();
+    }
+    escaped
+}
+
+fn codec_from_caps(caps: &gst::CapsRef) -> Option<Codec> {
+    let s = caps.get_structure(0)?;
+
+    match s.get_name() {
+        "video/x-vp8" => {
+            let (width, height) = dimensions(s)?;
+            Some(Codec::Vp8 {
+                width: width,
+                height: height,
+            })
+        }
+        "video/x-vp9" => {
+            let (width, height) = dimensions(s)?;
+            Some(Codec::Vp9 {
+                width: width,
+                height: height,
+            })
+        }
+        "video/x-av1" => {
+            let (width, height) = dimensions(s)?;
+            let buffer = s.get::<gst::Buffer>("codec_data")?;
+            let map = buffer.map_readable()?;
+            Some(Codec::Av1 {
+                width: width,
+                height: height,
+                av1c: map.as_slice().to_vec(),
+            })
+        }
+        "audio/x-opus" => {
+            let opus_head = match stream_headers(s) {
+                Some(ref headers) if !headers.is_empty() && headers[0].starts_with(b"OpusHead") => {
+                    headers[0].clone()
+                }
+                _ => {
+                    // Without stream headers only mono and stereo can be signalled
+                    let channels = s.get::<i32>("channels")?;
+                    let rate = s.get::<i32>("rate").unwrap_or(48_000);
+                    let family = s.get::<i32>("channel-mapping-family").unwrap_or(0);
+                    if family != 0 || channels < 1 || channels > 2 || rate <= 0 {
+                        return None;
+                    }
+                    opus_head(channels as u8, rate as u32)
+                }
+            };
+            if opus_head.len() < 19 {
+                return None;
+            }
+
+            let channels = u32::from(opus_head[9]);
+            let pre_skip = Cursor::new(&opus_head[10..12])
+                .read_u16::<LittleEndian>()
+                .unwrap();
+            Some(Codec::Opus {
+                channels: channels,
+                pre_skip: pre_skip,
+                opus_head: opus_head,
+            })
+        }
+        "audio/x-vorbis" => {
+            let channels = s.get::<i32>("channels")?;
+            let rate = s.get::<i32>("rate")?;
+            let headers = stream_headers(s)?;
+            if channels <= 0 || rate <= 0 || headers.len() != 3 {
+                return None;
+            }
+            Some(Codec::Vorbis {
+                channels: channels as u32,
+                rate: rate as u32,
+                headers: xiph_lace(&headers),
+            })
+        }
+        "text/x-raw" if s.get::<&str>("format") == Some("utf8") => Some(Codec::WebVtt),
+        _ => None,
+    }
+}
+
+impl WebmMux {
+    fn new(_element: &Element, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rswebmmux",
+                gst::DebugColorFlags::empty(),
+                "Rust WebM muxer",
+            ),
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+            output_lock: Mutex::new(()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "WebM muxer",
+            "Codec/Muxer",
+            "Muxes VP8, VP9, AV1, Opus, Vorbis and text streams into WebM",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("video/webm", &[]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let mut caps = gst::Caps::new_simple(
+            "video/x-vp8",
+            &[
+                ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            ],
+        );
+        caps.get_mut().unwrap().append(gst::Caps::new_simple(
+            "video/x-vp9",
+            &[
+                ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            ],
+        ));
+        caps.get_mut().unwrap().append(gst::Caps::new_simple(
+            "video/x-av1",
+            &[
+                ("stream-format", &"obu-stream"),
+                ("alignment", &"tu"),
+                ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            ],
+        ));
+        let sink_pad_template = gst::PadTemplate::new(
+            "video_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let mut caps = gst::Caps::new_simple(
+            "audio/x-opus",
+            &[
+                ("channel-mapping-family", &gst::IntRange::<i32>::new(0, 255)),
+                ("channels", &gst::IntRange::<i32>::new(1, 255)),
+            ],
+        );
+        caps.get_mut().unwrap().append(gst::Caps::new_simple(
+            "audio/x-vorbis",
+            &[
+                ("channels", &gst::IntRange::<i32>::new(1, 255)),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            ],
+        ));
+        let sink_pad_template = gst::PadTemplate::new(
+            "audio_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        // WebM only allows WebVTT subtitles, plain text is written as WebVTT cues
+        let caps = gst::Caps::new_simple("text/x-raw", &[("format", &"utf8")]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "subtitle_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+        srcpad.set_event_function(|pad, parent, event| {
+            WebmMux::catch_panic_pad_function(
+                parent,
+                || false,
+                |webmmux, element| webmmux.src_event(pad, element, event),
+            )
+        });
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let webmmux = element.get_impl().downcast_ref::<WebmMux>().unwrap();
+        element.catch_panic(fallback, |element| f(webmmux, element))
+    }
+
+    fn start_output(&self, element: &Element, state: &mut State, output: &mut Vec<gst::Buffer>) {
+        let settings = *self.settings.lock().unwrap();
+
+        let mut tracks = Vec::new();
+        for stream in &mut state.streams {
+            // Sparse streams get a track as soon as their codec is known
+            let sparse = stream.codec.as_ref().map(|c| c.is_sparse()).unwrap_or(false);
+            if stream.queue.is_empty() && !sparse {
+                continue;
+            }
+            stream.track_number = tracks.len() as u64 + 1;
+            tracks.push((
+                stream.track_number,
+                stream.codec.clone().unwrap(),
+                stream.info.clone(),
+            ));
+        }
+
+        let (header, segment_data_start) = write_header(&tracks, &HeaderInfo::default());
+        state.tracks = tracks;
+        state.segment_data_start = segment_data_start;
+        state.live = settings.live;
+        state.started = true;
+
+        // The header is only rewritten at the end if downstream can seek back to it
+        if !settings.live {
+            let mut query = gst::Query::new_seeking(gst::Format::Bytes);
+            if self.srcpad.peer_query(query.get_mut().unwrap()) {
+                state.seekable = match query.view() {
+                    gst::QueryView::Seeking(ref q) => q.get_result().0,
+                    _ => unreachable!(),
+                };
+            }
+        }
+        if !settings.live && !state.seekable {
+            gst_warning!(
+                self.cat,
+                obj: element,
+                "Downstream is not seekable, duration and cues won't be referenced"
+            );
+        }
+
+        let header = state.output(header, None, gst::BufferFlags::HEADER);
+
+        let caps = if settings.live {
+            // Allows clients to join the stream at any cluster that starts with a keyframe
+            gst::Caps::new_simple(
+                "video/webm",
+                &[("streamheader", &gst::Array::new(&[&header]))],
+            )
+        } else {
+            gst::Caps::new_simple("video/webm", &[])
+        };
+
+        self.srcpad
+            .push_event(gst::Event::new_stream_start(&element.get_name()).build());
+        self.srcpad.push_event(gst::Event::new_caps(&caps).build());
+        let segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+        self.srcpad
+            .push_event(gst::Event::new_segment(&segment).build());
+
+        output.push(header);
+    }
+
+    // Outputs queued blocks in order of their DTS for as long as every stream that is not
+    // EOS yet has data queued
+    fn drain(
+        &self,
+        element: &Element,
+        state: &mut State,
+    ) -> Result<Vec<gst::Buffer>, gst::FlowReturn> {
+        let settings = *self.settings.lock().unwrap();
+        let mut output = Vec::new();
+
+        loop {
+            let waiting = state.streams.iter().any(|s| {
+                let sparse = s.codec.as_ref().map(|c| c.is_sparse()).unwrap_or(false);
+                !s.eos && !sparse && s.queue.is_empty()
+            });
+            if waiting {
+                break;
+            }
+
+            let idx = match state
+                .streams
+                .iter()
+                .enumerate()
+                .filter(|&(_, s)| !s.queue.is_empty())
+                .min_by_key(|&(_, s)| s.queue[0].dts)
+            {
+                None => break,
+                Some((idx, _)) => idx,
+            };
+
+            if !state.started {
+                self.start_output(element, state, &mut output);
+            }
+
+            let (track, sparse, mut block) = {
+                let stream = &mut state.streams[idx];
+                let block = stream.queue.pop_front().unwrap();
+                (
+                    stream.track_number,
+                    stream.codec.as_ref().unwrap().is_sparse(),
+                    block,
+                )
+            };
+
+            // Sparse streams without caps before the start have no track
+            if track == 0 {
+                gst_debug!(self.cat, obj: element, "Dropping block without track");
+                continue;
+            }
+
+            // Sparse streams are not waited for, so their data can be late. It is shown as soon
+            // as possible then.
+            if let Some(last_dts) = state.last_dts {
+                if sparse && block.dts < last_dts {
+                    let shift = (last_dts - block.dts) as u64;
+                    gst_debug!(self.cat, obj: element, "Late sparse block by {} ns", shift);
+                    block.dts = last_dts;
+                    block.pts += shift;
+                    block.end += shift;
+                }
+            }
+            state.last_dts = Some(cmp::max(block.dts, state.last_dts.unwrap_or(block.dts)));
+
+            self.write_block(element, state, &settings, track, block, &mut output)?;
+        }
+
+        Ok(output)
+    }
+
+    fn write_block(
+        &self,
+        element: &Element,
+        state: &mut State,
+        settings: &Settings,
+        track: u64,
+        block: Block,
+        output: &mut Vec<gst::Buffer>,
+    ) -> Result<(), gst::FlowReturn> {
+        let has_video = state.tracks.iter().any(|&(_, ref codec, _)| codec.is_video());
+        let (is_video, sparse) = state
+            .tracks
+            .iter()
+            .find(|&&(number, _, _)| number == track)
+            .map(|&(_, ref codec, _)| (codec.is_video(), codec.is_sparse()))
+            .unwrap();
+        let time = block.pts / TIMESTAMP_SCALE_NS;
+        // Clusters should start with a video keyframe if there is video, and never with
+        // sparse data
+        let keyframe = block.keyframe && !sparse && (is_video || !has_video);
+
+        let new_cluster = match state.cluster {
+            None => true,
+            Some(ref cluster) => {
+                let relative = time as i64 - cluster.time as i64;
+                relative < i64::from(i16::MIN) || relative > i64::from(i16::MAX)
+                    || (keyframe
+                        && time >= cluster.time + settings.cluster_duration / TIMESTAMP_SCALE_NS)
+            }
+        };
+
+        if new_cluster {
+            state.finish_cluster(output);
+
+            if keyframe && !state.live {
+                let position = state.position - state.segment_data_start;
+                state.cues.push(CuePoint {
+                    time: time,
+                    track: track,
+                    position: position,
+                });
+            }
+
+            let mut writer = Writer::new();
+            if state.live {
+                writer.start_unknown_size(CLUSTER);
+            } else {
+                writer.start_master(CLUSTER);
+            }
+            writer.write_uint(TIMESTAMP, time);
+
+            gst_debug!(self.cat, obj: element, "Starting cluster at {} ms", time);
+            state.cluster = Some(Cluster {
+                time: time,
+                pts: block.pts,
+                writer: writer,
+                keyframe: keyframe,
+                pushed: false,
+            });
+        }
+
+        let map = match block.buffer.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return Err(gst::FlowReturn::Error);
+            }
+            Some(map) => map,
+        };
+
+        let (data, flags) = {
+            let cluster = state.cluster.as_mut().unwrap();
+
+            let mut header = Vec::new();
+            ebml::write_size(&mut header, track);
+            header
+                .write_i16::<BigEndian>((time as i64 - cluster.time as i64) as i16)
+                .unwrap();
+
+            if sparse {
+                // Subtitles need a duration, which is only possible in block groups
+                header.push(0x00);
+
+                let mut group = Writer::new();
+                group.start_master(BLOCK_GROUP);
+                group.write_header(BLOCK, (header.len() + map.get_size()) as u64);
+                group.write_raw(&header);
+                group.write_raw(map.as_slice());
+                if block.end > block.pts {
+                    group.write_uint(BLOCK_DURATION, block.end / TIMESTAMP_SCALE_NS - time);
+                }
+                group.end_master();
+                cluster.writer.write_raw(&group.into_inner());
+            } else {
+                header.push(if block.keyframe { 0x80 } else { 0x00 });
+
+                cluster
+                    .writer
+                    .write_header(SIMPLE_BLOCK, (header.len() + map.get_size()) as u64);
+                cluster.writer.write_raw(&header);
+                cluster.writer.write_raw(map.as_slice());
+            }
+
+            if !state.live {
+                (None, gst::BufferFlags::empty())
+            } else {
+                // Output everything right away, only the start of a cluster with a
+                // keyframe is a point where clients can start decoding
+                let flags = if !cluster.pushed && cluster.keyframe {
+                    gst::BufferFlags::empty()
+                } else {
+                    gst::BufferFlags::DELTA_UNIT
+                };
+                cluster.pushed = true;
+                (
+                    Some(mem::replace(&mut cluster.writer, Writer::new()).into_inner()),
+                    flags,
+                )
+            }
+        };
+
+        if let Some(data) = data {
+            let buffer = state.output(data, Some(block.pts), flags);
+            output.push(buffer);
+        }
+
+        state.end_time = cmp::max(state.end_time, block.end);
+
+        Ok(())
+    }
+
+    fn push_all(&self, output: Vec<gst::Buffer>) -> gst::FlowReturn {
+        for buffer in output {
+            let flow_ret = self.srcpad.push(buffer);
+            if flow_ret != gst::FlowReturn::Ok {
+                return flow_ret;
+            }
+        }
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let _output_lock = self.output_lock.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        {
+            let stream = match state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                None => return gst::FlowReturn::Error,
+                Some(stream) => stream,
+            };
+
+            if stream.codec.is_none() {
+                gst_element_error!(
+                    element,
+                    gst::CoreError::Negotiation,
+                    ["Buffer before caps on {}", pad.get_name()]
+                );
+                return gst::FlowReturn::NotNegotiated;
+            }
+
+            // Opus and Vorbis headers are already in the CodecPrivate
+            if buffer.get_flags().contains(gst::BufferFlags::HEADER) {
+                return gst::FlowReturn::Ok;
+            }
+
+            let buffer = if stream.codec == Some(Codec::WebVtt) {
+                let text = match buffer.map_readable() {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Failed,
+                            ["Failed to map buffer"]
+                        );
+                        return gst::FlowReturn::Error;
+                    }
+                    Some(map) => escape_webvtt(map.as_slice()),
+                };
+
+                let mut cue = gst::Buffer::from_mut_slice(text).unwrap();
+                {
+                    let cue = cue.get_mut().unwrap();
+                    cue.set_pts(buffer.get_pts());
+                    cue.set_duration(buffer.get_duration());
+                }
+                cue
+            } else {
+                buffer
+            };
+
+            let pts = match stream.segment.to_running_time(buffer.get_pts()).0 {
+                None => {
+                    gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(pts) => pts,
+            };
+
+            // The DTS can be before the segment start and thus have a negative running time,
+            // so compute it relative to the PTS instead
+            let dts = match (buffer.get_pts().0, buffer.get_dts().0) {
+                (Some(buffer_pts), Some(buffer_dts)) => {
+                    pts as i64 - (buffer_pts as i64 - buffer_dts as i64)
+                }
+                _ => pts as i64,
+            };
+
+            stream.queue.push_back(Block {
+                pts: pts,
+                dts: dts,
+                end: pts + buffer.get_duration().0.unwrap_or(0),
+                keyframe: !buffer.get_flags().contains(gst::BufferFlags::DELTA_UNIT),
+                buffer: buffer,
+            });
+        }
+
+        let output = match self.drain(element, &mut state) {
+            Ok(output) => output,
+            Err(flow_ret) => return flow_ret,
+        };
+        drop(state);
+
+        self.push_all(output)
+    }
+
+    fn finish(&self, element: &Element) -> gst::FlowReturn {
+        let _output_lock = self.output_lock.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        if state.finished {
+            return gst::FlowReturn::Ok;
+        }
+        state.finished = true;
+
+        let mut output = match self.drain(element, &mut state) {
+            Ok(output) => output,
+            Err(flow_ret) => return flow_ret,
+        };
+
+        if !state.started {
+            gst_element_error!(element, gst::StreamError::Mux, ["No data to mux"]);
+            return gst::FlowReturn::Error;
+        }
+
+        state.finish_cluster(&mut output);
+
+        if state.live {
+            drop(state);
+            return self.push_all(output);
+        }
+
+        let cues_position = if state.cues.is_empty() {
+            None
+        } else {
+            let position = state.position - state.segment_data_start;
+
+            let mut writer = Writer::new();
+            writer.start_master(CUES);
+            for cue in &state.cues {
+                writer.start_master(CUE_POINT);
+                writer.write_uint(CUE_TIME, cue.time);
+                writer.start_master(CUE_TRACK_POSITIONS);
+                writer.write_uint(CUE_TRACK, cue.track);
+                writer.write_uint(CUE_CLUSTER_POSITION, cue.position);
+                writer.end_master();
+                writer.end_master();
+            }
+            writer.end_master();
+
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "Writing {} cue points",
+                state.cues.len()
+            );
+            let buffer = state.output(writer.into_inner(), None, gst::BufferFlags::empty());
+            output.push(buffer);
+
+            Some(position)
+        };
+
+        let header = if state.seekable {
+            let info = HeaderInfo {
+                segment_size: Some(state.position - state.segment_data_start),
+                duration: Some(state.end_time / TIMESTAMP_SCALE_NS),
+                cues_position: cues_position,
+            };
+            Some(write_header(&state.tracks, &info).0)
+        } else {
+            None
+        };
+        drop(state);
+
+        let flow_ret = self.push_all(output);
+        if flow_ret != gst::FlowReturn::Ok {
+            return flow_ret;
+        }
+
+        if let Some(header) = header {
+            // Seek back and rewrite the header with the final sizes, duration and cues
+            let segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+            self.srcpad
+                .push_event(gst::Event::new_segment(&segment).build());
+            return self.srcpad
+                .push(gst::Buffer::from_mut_slice(header).unwrap());
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let codec = match codec_from_caps(e.get_caps()) {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Negotiation,
+                            ["Unsupported caps {:?}", e.get_caps()]
+                        );
+                        return false;
+                    }
+                    Some(codec) => codec,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                let started = state.started;
+                let stream = match state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    None => return false,
+                    Some(stream) => stream,
+                };
+
+                if (started || !stream.queue.is_empty()) && stream.codec.is_some()
+                    && stream.codec.as_ref() != Some(&codec)
+                {
+                    gst_element_error!(
+                        element,
+                        gst::CoreError::Negotiation,
+                        ["Caps changes are not supported"]
+                    );
+                    return false;
+                }
+
+                gst_debug!(self.cat, obj: pad, "Configured codec {:?}", codec);
+                stream.codec = Some(codec);
+                true
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    stream.segment = segment;
+                }
+                true
+            }
+            EventView::Tag(e) => {
+                let mut state = self.state.lock().unwrap();
+                let started = state.started;
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    // The track entries are written once the output started
+                    if started {
+                        gst_debug!(self.cat, obj: pad, "Ignoring tags after header was written");
+                    } else {
+                        stream.info.update(e.get_tag());
+                        gst_debug!(self.cat, obj: pad, "Stream info {:?}", stream.info);
+                    }
+                }
+                drop(state);
+
+                self.srcpad.push_event(event)
+            }
+            // The output has its own stream-start
+            EventView::StreamStart(..) => true,
+            // Sparse streams only signal that there is no data, which has no meaning downstream
+            EventView::Gap(..) => true,
+            EventView::Eos(..) => {
+                let output_lock = self.output_lock.lock().unwrap();
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    stream.eos = true;
+                }
+
+                if state.streams.iter().all(|s| s.eos) {
+                    drop(state);
+                    drop(output_lock);
+
+                    gst_debug!(self.cat, obj: element, "All streams are EOS, finishing");
+                    let flow_ret = self.finish(element);
+                    if flow_ret != gst::FlowReturn::Ok {
+                        gst_debug!(self.cat, obj: element, "Failed to finish: {:?}", flow_ret);
+                    }
+                    self.srcpad.push_event(event);
+                } else {
+                    // Other streams might have been waiting for this one
+                    let output = match self.drain(element, &mut state) {
+                        Ok(output) => output,
+                        Err(_) => return false,
+                    };
+                    drop(state);
+                    self.push_all(output);
+                }
+                true
+            }
+            _ => self.srcpad.push_event(event),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Seek(..) => {
+                gst_debug!(self.cat, obj: pad, "Seeking is not supported");
+                false
+            }
+            _ => pad.event_default(Some(&element.clone().upcast()), event),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for WebmMux {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Boolean("live", ..) => {
+                settings.live = value.get().unwrap();
+            }
+            Property::UInt64("cluster-duration", ..) => {
+                settings.cluster_duration = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Boolean("live", ..) => Ok(settings.live.to_value()),
+            Property::UInt64("cluster-duration", ..) => Ok(settings.cluster_duration.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for WebmMux {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        if transition == gst::StateChange::PausedToReady {
+            let mut state = self.state.lock().unwrap();
+            let streams = mem::replace(&mut state.streams, Vec::new());
+            let pad_count = state.pad_count;
+            *state = State::default();
+            state.streams = streams;
+            state.pad_count = pad_count;
+            for stream in &mut state.streams {
+                stream.reset();
+            }
+        }
+
+        ret
+    }
+
+    fn request_new_pad(
+        &self,
+        element: &Element,
+        templ: &gst::PadTemplate,
+        _name: Option<String>,
+        _caps: Option<&gst::CapsRef>,
+    ) -> Option<gst::Pad> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.started {
+            gst_error!(self.cat, obj: element, "Can't request pads after muxing started");
+            return None;
+        }
+
+        let id = state.pad_count;
+        state.pad_count += 1;
+
+        let name = if *templ == element.get_pad_template("video_%u").unwrap() {
+            format!("video_{}", id)
+        } else if *templ == element.get_pad_template("audio_%u").unwrap() {
+            format!("audio_{}", id)
+        } else {
+            format!("subtitle_{}", id)
+        };
+        let sinkpad = gst::Pad::new_from_template(templ, name.as_str());
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            WebmMux::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |webmmux, element| webmmux.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            WebmMux::catch_panic_pad_function(
+                parent,
+                || false,
+                |webmmux, element| webmmux.sink_event(pad, element, event),
+            )
+        });
+
+        sinkpad.set_active(true).unwrap();
+        element.add_pad(&sinkpad).unwrap();
+
+        state.streams.push(Stream::new(sinkpad.clone()));
+
+        Some(sinkpad)
+    }
+
+    fn release_pad(&self, element: &Element, pad: &gst::Pad) {
+        let mut state = self.state.lock().unwrap();
+
+        let pos = match state.streams.iter().position(|s| s.sinkpad == *pad) {
+            None => return,
+            Some(pos) => pos,
+        };
+        let stream = state.streams.remove(pos);
+        drop(state);
+
+        stream.sinkpad.set_active(false).unwrap();
+        element.remove_pad(&stream.sinkpad).unwrap();
+    }
+}
+
+struct WebmMuxStatic;
+
+impl ImplTypeStatic<Element> for WebmMuxStatic {
+    fn get_name(&self) -> &str {
+        "WebmMux"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        WebmMux::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        WebmMux::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let webmmux_static = WebmMuxStatic;
+    register_type(webmmux_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_webvtt() {
+        assert_eq!(escape_webvtt(b"Hello\r\nWorld"), b"Hello\nWorld".to_vec());
+        assert_eq!(escape_webvtt(b"a\n\n\nb\n"), b"a\nb".to_vec());
+        assert_eq!(
+            escape_webvtt(b"<i>Tom & Jerry</i>"),
+            b"&lt;i&gt;Tom &amp; Jerry&lt;/i&gt;".to_vec()
+        );
+    }
+}
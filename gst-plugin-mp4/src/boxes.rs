@@ -0,0 +1,1027 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Writing of ISO base media file format (MP4) boxes for non-fragmented files
+
+use std::cmp;
+use std::i32;
+use std::u32;
+
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::Cursor;
+
+use gst_plugin::tags::StreamInfo;
+
+pub const MOVIE_TIMESCALE: u32 = 1000;
+pub const MDAT_HEADER_SIZE: u64 = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpusHeader {
+    pub channels: u8,
+    pub pre_skip: u16,
+    pub input_rate: u32,
+    pub output_gain: i16,
+    pub channel_mapping_family: u8,
+    pub stream_count: u8,
+    pub coupled_count: u8,
+    pub channel_mapping: Vec<u8>,
+}
+
+impl OpusHeader {
+    // Parses an OpusHead packet as defined in RFC 7845
+    pub fn from_opus_head(data: &[u8]) -> Option<OpusHeader> {
+        if data.len() < 19 || &data[0..8] != b"OpusHead" || data[8] & 0xf0 != 0 {
+            return None;
+        }
+
+        let mut cursor = Cursor::new(&data[9..]);
+        let channels = cursor.read_u8().ok()?;
+        let pre_skip = cursor.read_u16::<LittleEndian>().ok()?;
+        let input_rate = cursor.read_u32::<LittleEndian>().ok()?;
+        let output_gain = cursor.read_i16::<LittleEndian>().ok()?;
+        let channel_mapping_family = cursor.read_u8().ok()?;
+
+        if channels == 0 {
+            return None;
+        }
+
+        let (stream_count, coupled_count, channel_mapping) = if channel_mapping_family == 0 {
+            (1, channels - 1, Vec::new())
+        } else {
+            let stream_count = cursor.read_u8().ok()?;
+            let coupled_count = cursor.read_u8().ok()?;
+            let pos = 9 + cursor.position() as usize;
+            if data.len() < pos + channels as usize {
+                return None;
+            }
+            (
+                stream_count,
+                coupled_count,
+                data[pos..(pos + channels as usize)].to_vec(),
+            )
+        };
+
+        Some(OpusHeader {
+            channels: channels,
+            pre_skip: pre_skip,
+            input_rate: input_rate,
+            output_gain: output_gain,
+            channel_mapping_family: channel_mapping_family,
+            stream_count: stream_count,
+            coupled_count: coupled_count,
+            channel_mapping: channel_mapping,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Codec {
+    H264 {
+        width: u16,
+        height: u16,
+        avcc: Vec<u8>,
+    },
+    H265 {
+        width: u16,
+        height: u16,
+        hvcc: Vec<u8>,
+        // Parameter sets in-band instead of only in the hvcC
+        hev1: bool,
+    },
+    Aac {
+        channels: u16,
+        rate: u32,
+        audio_specific_config: Vec<u8>,
+    },
+    Opus(OpusHeader),
+    // 3GPP timed text, see `tx3g_sample()`
+    Tx3g,
+}
+
+impl Codec {
+    pub fn is_video(&self) -> bool {
+        match *self {
+            Codec::H264 { .. } | Codec::H265 { .. } => true,
+            Codec::Aac { .. } | Codec::Opus(..) | Codec::Tx3g => false,
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        *self == Codec::Tx3g
+    }
+
+    pub fn timescale(&self) -> u32 {
+        match *self {
+            Codec::H264 { .. } | Codec::H265 { .. } => 90_000,
+            Codec::Aac { rate, .. } => rate,
+            Codec::Opus(..) => 48_000,
+            Codec::Tx3g => 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Offset relative to the start of the mdat payload
+    pub offset: u64,
+    pub size: u32,
+    /// Decoding and presentation timestamp in the track timescale. The DTS can be negative.
+    pub dts: i64,
+    pub pts: i64,
+    pub duration: u32,
+    pub sync: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub id: u32,
+    pub codec: Codec,
+    pub samples: Vec<Sample>,
+    /// Language and role from the tags of the stream
+    pub info: StreamInfo,
+}
+
+impl Track {
+    fn timescale(&self) -> u32 {
+        self.codec.timescale()
+    }
+
+    fn media_duration(&self) -> u64 {
+        self.samples.iter().map(|s| u64::from(s.duration)).sum()
+    }
+
+    // Earliest presentation timestamp and end of presentation in the track timescale
+    fn presentation_range(&self) -> Option<(i64, i64)> {
+        let start = self.samples.iter().map(|s| s.pts).min()?;
+        let end = self.samples
+            .iter()
+            .map(|s| s.pts + i64::from(s.duration))
+            .max()?;
+        Some((start, end))
+    }
+}
+
+/// A tx3g sample with the UTF-8 `text`, which is truncated if it is longer than 65535 bytes.
+/// Without text it clears the previous one.
+pub fn tx3g_sample(text: &[u8]) -> Vec<u8> {
+    let mut len = cmp::min(text.len(), 0xffff);
+    // Don't cut UTF-8 sequences
+    while len < text.len() && text[len] & 0xc0 == 0x80 {
+        len -= 1;
+    }
+
+    let mut v = Vec::with_capacity(2 + len);
+    v.write_u16::<BigEndian>(len as u16).unwrap();
+    v.extend_from_slice(&text[..len]);
+    v
+}
+
+fn rescale(value: i64, from: u32, to: u32) -> i64 {
+    value * i64::from(to) / i64::from(from)
+}
+
+fn write_box<F: FnOnce(&mut Vec<u8>)>(v: &mut Vec<u8>, fourcc: &[u8; 4], content: F) {
+    let pos = v.len();
+    v.write_u32::<BigEndian>(0).unwrap();
+    v.extend_from_slice(fourcc);
+    content(v);
+    let size = (v.len() - pos) as u32;
+    (&mut v[pos..(pos + 4)])
+        .write_u32::<BigEndian>(size)
+        .unwrap();
+}
+
+fn write_full_box<F: FnOnce(&mut Vec<u8>)>(
+    v: &mut Vec<u8>,
+    fourcc: &[u8; 4],
+    version: u8,
+    flags: u32,
+    content: F,
+) {
+    write_box(v, fourcc, |v| {
+        v.write_u32::<BigEndian>((u32::from(version) << 24) | (flags & 0x00ff_ffff))
+            .unwrap();
+        content(v);
+    });
+}
+
+fn write_matrix(v: &mut Vec<u8>) {
+    for value in &[0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+        v.write_u32::<BigEndian>(*value).unwrap();
+    }
+}
+
+fn needs_version_1(values: &[u64]) -> bool {
+    values.iter().any(|v| *v > u64::from(u32::MAX))
+}
+
+pub fn write_ftyp() -> Vec<u8> {
+    let mut v = Vec::new();
+    write_box(&mut v, b"ftyp", |v| {
+        v.extend_from_slice(b"isom");
+        v.write_u32::<BigEndian>(0x200).unwrap();
+        for brand in &[b"isom", b"iso2", b"avc1", b"mp41"] {
+            v.extend_from_slice(*brand);
+        }
+    });
+    v
+}
+
+/// Header of an mdat box with a 64 bit size field, `size` being the size of its payload.
+pub fn write_mdat_header(size: u64) -> Vec<u8> {
+    let mut v = Vec::with_capacity(MDAT_HEADER_SIZE as usize);
+    v.write_u32::<BigEndian>(1).unwrap();
+    v.extend_from_slice(b"mdat");
+    v.write_u64::<BigEndian>(size + MDAT_HEADER_SIZE).unwrap();
+    v
+}
+
+/// Writes the moov box for `tracks`, with the mdat payload starting at `data_offset` in the file.
+pub fn write_moov(tracks: &[Track], data_offset: u64) -> Vec<u8> {
+    let presentation_start = tracks
+        .iter()
+        .filter_map(|t| {
+            t.presentation_range()
+                .map(|(start, _)| rescale(start, t.timescale(), MOVIE_TIMESCALE))
+        })
+        .min()
+        .unwrap_or(0);
+
+    let edits = tracks
+        .iter()
+        .map(|t| Edits::new(t, presentation_start))
+        .collect::<Vec<_>>();
+    let movie_duration = edits.iter().map(|e| e.duration()).max().unwrap_or(0);
+
+    let mut v = Vec::new();
+    write_box(&mut v, b"moov", |v| {
+        let next_track_id = tracks.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+        write_mvhd(v, movie_duration, next_track_id);
+
+        for (track, edits) in tracks.iter().zip(edits.iter()) {
+            write_trak(v, track, edits, data_offset);
+        }
+    });
+    v
+}
+
+fn write_mvhd(v: &mut Vec<u8>, duration: u64, next_track_id: u32) {
+    let version = if needs_version_1(&[duration]) { 1 } else { 0 };
+    write_full_box(v, b"mvhd", version, 0, |v| {
+        if version == 1 {
+            v.write_u64::<BigEndian>(0).unwrap();
+            v.write_u64::<BigEndian>(0).unwrap();
+            v.write_u32::<BigEndian>(MOVIE_TIMESCALE).unwrap();
+            v.write_u64::<BigEndian>(duration).unwrap();
+        } else {
+            v.write_u32::<BigEndian>(0).unwrap();
+            v.write_u32::<BigEndian>(0).unwrap();
+            v.write_u32::<BigEndian>(MOVIE_TIMESCALE).unwrap();
+            v.write_u32::<BigEndian>(duration as u32).unwrap();
+        }
+        // Rate and volume
+        v.write_u32::<BigEndian>(0x0001_0000).unwrap();
+        v.write_u16::<BigEndian>(0x0100).unwrap();
+        v.extend_from_slice(&[0; 10]);
+        write_matrix(v);
+        v.extend_from_slice(&[0; 24]);
+        v.write_u32::<BigEndian>(next_track_id).unwrap();
+    });
+}
+
+// Edit list of a track, mapping the media timeline to the movie timeline
+struct Edits {
+    // Time before the first sample is presented, in the movie timescale
+    empty_duration: u64,
+    // First presented media time, in the track timescale. Non-zero if the first sample is not
+    // presented first, e.g. because of B-frames and the resulting negative DTS
+    media_time: i64,
+    // Duration of the presented media, in the movie timescale
+    media_duration: u64,
+}
+
+impl Edits {
+    fn new(track: &Track, presentation_start: i64) -> Edits {
+        let timescale = track.timescale();
+        let (start, end) = match track.presentation_range() {
+            None => {
+                return Edits {
+                    empty_duration: 0,
+                    media_time: 0,
+                    media_duration: 0,
+                }
+            }
+            Some(range) => range,
+        };
+
+        let first_dts = track.samples[0].dts;
+        let start_movie = rescale(start, timescale, MOVIE_TIMESCALE);
+
+        Edits {
+            empty_duration: cmp::max(start_movie - presentation_start, 0) as u64,
+            media_time: cmp::max(start - first_dts, 0),
+            media_duration: cmp::max(rescale(end - start, timescale, MOVIE_TIMESCALE), 0) as u64,
+        }
+    }
+
+    fn is_needed(&self) -> bool {
+        self.empty_duration != 0 || self.media_time != 0
+    }
+
+    fn duration(&self) -> u64 {
+        self.empty_duration + self.media_duration
+    }
+}
+
+fn write_trak(v: &mut Vec<u8>, track: &Track, edits: &Edits, data_offset: u64) {
+    write_box(v, b"trak", |v| {
+        write_tkhd(v, track, edits.duration());
+        if edits.is_needed() {
+            write_edts(v, edits);
+        }
+        write_box(v, b"mdia", |v| {
+            write_mdhd(v, track);
+            write_hdlr(v, track);
+            write_box(v, b"minf", |v| {
+                if track.codec.is_video() {
+                    write_full_box(v, b"vmhd", 0, 1, |v| {
+                        v.extend_from_slice(&[0; 8]);
+                    });
+                } else if track.codec.is_text() {
+                    write_full_box(v, b"nmhd", 0, 0, |_| {});
+                } else {
+                    write_full_box(v, b"smhd", 0, 0, |v| {
+                        v.extend_from_slice(&[0; 4]);
+                    });
+                }
+                write_box(v, b"dinf", |v| {
+                    write_full_box(v, b"dref", 0, 0, |v| {
+                        v.write_u32::<BigEndian>(1).unwrap();
+                        // Media data is in the same file
+                        write_full_box(v, b"url ", 0, 1, |_| {});
+                    });
+                });
+                write_stbl(v, track, data_offset);
+            });
+        });
+        if let Some(role) = track.info.role {
+            write_box(v, b"udta", |v| {
+                write_full_box(v, b"kind", 0, 0, |v| {
+                    v.extend_from_slice(b"urn:mpeg:dash:role:2011\0");
+                    v.extend_from_slice(role.as_str().as_bytes());
+                    v.push(0);
+                });
+            });
+        }
+    });
+}
+
+fn write_tkhd(v: &mut Vec<u8>, track: &Track, duration: u64) {
+    let version = if needs_version_1(&[duration]) { 1 } else { 0 };
+    // Track enabled and in movie
+    write_full_box(v, b"tkhd", version, 0x3, |v| {
+        if version == 1 {
+            v.write_u64::<BigEndian>(0).unwrap();
+            v.write_u64::<BigEndian>(0).unwrap();
+            v.write_u32::<BigEndian>(track.id).unwrap();
+            v.write_u32::<BigEndian>(0).unwrap();
+            v.write_u64::<BigEndian>(duration).unwrap();
+        } else {
+            v.write_u32::<BigEndian>(0).unwrap();
+            v.write_u32::<BigEndian>(0).unwrap();
+            v.write_u32::<BigEndian>(track.id).unwrap();
+            v.write_u32::<BigEndian>(0).unwrap();
+            v.write_u32::<BigEndian>(duration as u32).unwrap();
+        }
+        v.extend_from_slice(&[0; 8]);
+        // Layer and alternate group
+        v.write_u16::<BigEndian>(0).unwrap();
+        v.write_u16::<BigEndian>(0).unwrap();
+        // Volume
+        let volume = if track.codec.is_video() || track.codec.is_text() {
+            0
+        } else {
+            0x0100
+        };
+        v.write_u16::<BigEndian>(volume).unwrap();
+        v.write_u16::<BigEndian>(0).unwrap();
+        write_matrix(v);
+
+        let (width, height) = match track.codec {
+            Codec::H264 { width, height, .. } | Codec::H265 { width, height, .. } => {
+                (width, height)
+            }
+            _ => (0, 0),
+        };
+        v.write_u32::<BigEndian>(u32::from(width) << 16).unwrap();
+        v.write_u32::<BigEndian>(u32::from(height) << 16).unwrap();
+    });
+}
+
+fn write_edts(v: &mut Vec<u8>, edits: &Edits) {
+    let version = if needs_version_1(&[
+        edits.empty_duration,
+        edits.media_duration,
+        edits.media_time as u64,
+    ]) {
+        1
+    } else {
+        0
+    };
+
+    let mut entries = Vec::new();
+    if edits.empty_duration != 0 {
+        entries.push((edits.empty_duration, -1));
+    }
+    entries.push((edits.media_duration, edits.media_time));
+
+    write_box(v, b"edts", |v| {
+        write_full_box(v, b"elst", version, 0, |v| {
+            v.write_u32::<BigEndian>(entries.len() as u32).unwrap();
+            for &(duration, media_time) in &entries {
+                if version == 1 {
+                    v.write_u64::<BigEndian>(duration).unwrap();
+                    v.write_i64::<BigEndian>(media_time).unwrap();
+                } else {
+                    v.write_u32::<BigEndian>(duration as u32).unwrap();
+                    v.write_i32::<BigEndian>(media_time as i32).unwrap();
+                }
+                // Media rate 1.0
+                v.write_u16::<BigEndian>(1).unwrap();
+                v.write_u16::<BigEndian>(0).unwrap();
+            }
+        });
+    });
+}
+
+fn write_mdhd(v: &mut Vec<u8>, track: &Track) {
+    let duration = track.media_duration();
+    let version = if needs_version_1(&[duration]) { 1 } else { 0 };
+    write_full_box(v, b"mdhd", version, 0, |v| {
+        if version == 1 {
+            v.write_u64::<BigEndian>(0).unwrap();
+            v.write_u64::<BigEndian>(0).unwrap();
+            v.write_u32::<BigEndian>(track.timescale()).unwrap();
+            v.write_u64::<BigEndian>(duration).unwrap();
+        } else {
+            v.write_u32::<BigEndian>(0).unwrap();
+            v.write_u32::<BigEndian>(0).unwrap();
+            v.write_u32::<BigEndian>(track.timescale()).unwrap();
+            v.write_u32::<BigEndian>(duration as u32).unwrap();
+        }
+        v.write_u16::<BigEndian>(pack_language(&track.info)).unwrap();
+        v.write_u16::<BigEndian>(0).unwrap();
+    });
+}
+
+// ISO 639-2/T code as three 5 bit values of the letters minus 0x60, "und" if unknown
+fn pack_language(info: &StreamInfo) -> u16 {
+    let language = info.language_iso_639_2t().unwrap_or_else(|| String::from("und"));
+    language
+        .bytes()
+        .fold(0, |acc, c| (acc << 5) | u16::from(c - 0x60))
+}
+
+fn write_hdlr(v: &mut Vec<u8>, track: &Track) {
+    write_full_box(v, b"hdlr", 0, 0, |v| {
+        v.write_u32::<BigEndian>(0).unwrap();
+        let (handler_type, name): (&[u8; 4], &[u8]) = if track.codec.is_video() {
+            (b"vide", b"VideoHandler\0")
+        } else if track.codec.is_text() {
+            (b"sbtl", b"SubtitleHandler\0")
+        } else {
+            (b"soun", b"SoundHandler\0")
+        };
+        v.extend_from_slice(handler_type);
+        v.extend_from_slice(&[0; 12]);
+        v.extend_from_slice(name);
+    });
+}
+
+fn write_stbl(v: &mut Vec<u8>, track: &Track, data_offset: u64) {
+    write_box(v, b"stbl", |v| {
+        write_stsd(v, track);
+        write_stts(v, track);
+        write_ctts(v, track);
+        if track.samples.iter().any(|s| !s.sync) {
+            write_stss(v, track);
+        }
+        write_stsz(v, track);
+        write_stsc_stco(v, track, data_offset);
+    });
+}
+
+fn write_stsd(v: &mut Vec<u8>, track: &Track) {
+    write_full_box(v, b"stsd", 0, 0, |v| {
+        v.write_u32::<BigEndian>(1).unwrap();
+
+        match track.codec {
+            Codec::H264 {
+                width,
+                height,
+                ref avcc,
+            } => write_visual_sample_entry(v, b"avc1", width, height, b"avcC", avcc),
+            Codec::H265 {
+                width,
+                height,
+                ref hvcc,
+                hev1,
+            } => write_visual_sample_entry(
+                v,
+                if hev1 { b"hev1" } else { b"hvc1" },
+                width,
+                height,
+                b"hvcC",
+                hvcc,
+            ),
+            Codec::Aac {
+                channels,
+                rate,
+                ref audio_specific_config,
+            } => write_audio_sample_entry(v, b"mp4a", channels, rate, |v| {
+                write_esds(v, track.id, audio_specific_config)
+            }),
+            Codec::Opus(ref header) => {
+                write_audio_sample_entry(v, b"Opus", u16::from(header.channels), 48_000, |v| {
+                    write_dops(v, header)
+                })
+            }
+            Codec::Tx3g => write_tx3g_sample_entry(v),
+        }
+    });
+}
+
+// 3GPP TS 26.245 text sample entry with white, bottom centered text in the default font
+fn write_tx3g_sample_entry(v: &mut Vec<u8>) {
+    write_box(v, b"tx3g", |v| {
+        v.extend_from_slice(&[0; 6]);
+        v.write_u16::<BigEndian>(1).unwrap();
+
+        // Display flags, horizontal and vertical justification and background color
+        v.write_u32::<BigEndian>(0).unwrap();
+        v.write_i8(1).unwrap();
+        v.write_i8(-1).unwrap();
+        v.extend_from_slice(&[0, 0, 0, 0]);
+        // Default text box covering the whole track
+        v.extend_from_slice(&[0; 8]);
+        // Default style: start and end character, font ID, face style flags, font size and
+        // text color
+        v.write_u16::<BigEndian>(0).unwrap();
+        v.write_u16::<BigEndian>(0).unwrap();
+        v.write_u16::<BigEndian>(1).unwrap();
+        v.push(0);
+        v.push(18);
+        v.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+
+        write_box(v, b"ftab", |v| {
+            v.write_u16::<BigEndian>(1).unwrap();
+            v.write_u16::<BigEndian>(1).unwrap();
+            v.push(10);
+            v.extend_from_slice(b"Sans-Serif");
+        });
+    });
+}
+
+fn write_visual_sample_entry(
+    v: &mut Vec<u8>,
+    fourcc: &[u8; 4],
+    width: u16,
+    height: u16,
+    config_fourcc: &[u8; 4],
+    config: &[u8],
+) {
+    write_box(v, fourcc, |v| {
+        v.extend_from_slice(&[0; 6]);
+        // Data reference index
+        v.write_u16::<BigEndian>(1).unwrap();
+        v.extend_from_slice(&[0; 16]);
+        v.write_u16::<BigEndian>(width).unwrap();
+        v.write_u16::<BigEndian>(height).unwrap();
+        // 72 dpi
+        v.write_u32::<BigEndian>(0x0048_0000).unwrap();
+        v.write_u32::<BigEndian>(0x0048_0000).unwrap();
+        v.write_u32::<BigEndian>(0).unwrap();
+        // Frame count
+        v.write_u16::<BigEndian>(1).unwrap();
+        // Compressor name
+        v.extend_from_slice(&[0; 32]);
+        // Depth
+        v.write_u16::<BigEndian>(0x0018).unwrap();
+        v.write_i16::<BigEndian>(-1).unwrap();
+
+        write_box(v, config_fourcc, |v| v.extend_from_slice(config));
+    });
+}
+
+fn write_audio_sample_entry<F: FnOnce(&mut Vec<u8>)>(
+    v: &mut Vec<u8>,
+    fourcc: &[u8; 4],
+    channels: u16,
+    rate: u32,
+    config: F,
+) {
+    write_box(v, fourcc, |v| {
+        v.extend_from_slice(&[0; 6]);
+        // Data reference index
+        v.write_u16::<BigEndian>(1).unwrap();
+        v.extend_from_slice(&[0; 8]);
+        v.write_u16::<BigEndian>(channels).unwrap();
+        // Sample size
+        v.write_u16::<BigEndian>(16).unwrap();
+        v.write_u32::<BigEndian>(0).unwrap();
+        // 16.16 fixed point, only the codec configuration has the real rate if it doesn't fit
+        v.write_u32::<BigEndian>(if rate <= 0xffff { rate << 16 } else { 0 })
+            .unwrap();
+
+        config(v);
+    });
+}
+
+fn write_descriptor<F: FnOnce(&mut Vec<u8>)>(v: &mut Vec<u8>, tag: u8, content: F) {
+    let mut data = Vec::new();
+    content(&mut data);
+
+    v.push(tag);
+    // Always use the 4 byte length encoding for simplicity
+    let len = data.len() as u32;
+    v.push(0x80 | ((len >> 21) & 0x7f) as u8);
+    v.push(0x80 | ((len >> 14) & 0x7f) as u8);
+    v.push(0x80 | ((len >> 7) & 0x7f) as u8);
+    v.push((len & 0x7f) as u8);
+    v.extend_from_slice(&data);
+}
+
+fn write_esds(v: &mut Vec<u8>, track_id: u32, audio_specific_config: &[u8]) {
+    write_full_box(v, b"esds", 0, 0, |v| {
+        // ES_Descriptor
+        write_descriptor(v, 0x03, |v| {
+            v.write_u16::<BigEndian>(cmp::min(track_id, 0xffff) as u16)
+                .unwrap();
+            v.push(0);
+
+            // DecoderConfigDescriptor
+            write_descriptor(v, 0x04, |v| {
+                // MPEG-4 audio, audio stream
+                v.push(0x40);
+                v.push((0x05 << 2) | 0x01);
+                // Buffer size, max and average bitrate
+                v.extend_from_slice(&[0; 3]);
+                v.write_u32::<BigEndian>(0).unwrap();
+                v.write_u32::<BigEndian>(0).unwrap();
+
+                // DecoderSpecificInfo
+                write_descriptor(v, 0x05, |v| v.extend_from_slice(audio_specific_config));
+            });
+
+            // SLConfigDescriptor, predefined for MP4
+            write_descriptor(v, 0x06, |v| v.push(0x02));
+        });
+    });
+}
+
+fn write_dops(v: &mut Vec<u8>, header: &OpusHeader) {
+    write_box(v, b"dOps", |v| {
+        v.push(0);
+        v.push(header.channels);
+        v.write_u16::<BigEndian>(header.pre_skip).unwrap();
+        v.write_u32::<BigEndian>(header.input_rate).unwrap();
+        v.write_i16::<BigEndian>(header.output_gain).unwrap();
+        v.push(header.channel_mapping_family);
+        if header.channel_mapping_family != 0 {
+            v.push(header.stream_count);
+            v.push(header.coupled_count);
+            v.extend_from_slice(&header.channel_mapping);
+        }
+    });
+}
+
+// Run-length encodes `values`
+fn runs<T: PartialEq + Copy, I: Iterator<Item = T>>(values: I) -> Vec<(u32, T)> {
+    let mut runs: Vec<(u32, T)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some(&mut (ref mut count, ref last)) if *last == value => {
+                *count += 1;
+                continue;
+            }
+            _ => (),
+        }
+        runs.push((1, value));
+    }
+    runs
+}
+
+fn write_stts(v: &mut Vec<u8>, track: &Track) {
+    let entries = runs(track.samples.iter().map(|s| s.duration));
+    write_full_box(v, b"stts", 0, 0, |v| {
+        v.write_u32::<BigEndian>(entries.len() as u32).unwrap();
+        for &(count, duration) in &entries {
+            v.write_u32::<BigEndian>(count).unwrap();
+            v.write_u32::<BigEndian>(duration).unwrap();
+        }
+    });
+}
+
+fn write_ctts(v: &mut Vec<u8>, track: &Track) {
+    if track.samples.iter().all(|s| s.pts == s.dts) {
+        return;
+    }
+
+    let offsets = track.samples.iter().map(|s| {
+        let offset = s.pts - s.dts;
+        cmp::max(cmp::min(offset, i64::from(i32::MAX)), i64::from(i32::MIN)) as i32
+    });
+    let entries = runs(offsets);
+    // Version 1 allows negative composition offsets
+    let version = if entries.iter().any(|&(_, offset)| offset < 0) {
+        1
+    } else {
+        0
+    };
+
+    write_full_box(v, b"ctts", version, 0, |v| {
+        v.write_u32::<BigEndian>(entries.len() as u32).unwrap();
+        for &(count, offset) in &entries {
+            v.write_u32::<BigEndian>(count).unwrap();
+            v.write_i32::<BigEndian>(offset).unwrap();
+        }
+    });
+}
+
+fn write_stss(v: &mut Vec<u8>, track: &Track) {
+    let sync_samples = track
+        .samples
+        .iter()
+        .enumerate()
+        .filter(|&(_, s)| s.sync)
+        .map(|(idx, _)| idx as u32 + 1)
+        .collect::<Vec<_>>();
+
+    write_full_box(v, b"stss", 0, 0, |v| {
+        v.write_u32::<BigEndian>(sync_samples.len() as u32).unwrap();
+        for idx in sync_samples {
+            v.write_u32::<BigEndian>(idx).unwrap();
+        }
+    });
+}
+
+fn write_stsz(v: &mut Vec<u8>, track: &Track) {
+    write_full_box(v, b"stsz", 0, 0, |v| {
+        // No constant sample size
+        v.write_u32::<BigEndian>(0).unwrap();
+        v.write_u32::<BigEndian>(track.samples.len() as u32).unwrap();
+        for sample in &track.samples {
+            v.write_u32::<BigEndian>(sample.size).unwrap();
+        }
+    });
+}
+
+// Consecutive samples of a track that directly follow each other in the file form a chunk
+fn chunks(track: &Track) -> Vec<(u64, u32)> {
+    let mut chunks: Vec<(u64, u32)> = Vec::new();
+    let mut next_offset = None;
+
+    for sample in &track.samples {
+        if next_offset == Some(sample.offset) {
+            chunks.last_mut().unwrap().1 += 1;
+        } else {
+            chunks.push((sample.offset, 1));
+        }
+        next_offset = Some(sample.offset + u64::from(sample.size));
+    }
+
+    chunks
+}
+
+fn write_stsc_stco(v: &mut Vec<u8>, track: &Track, data_offset: u64) {
+    let chunks = chunks(track);
+    let entries = runs(chunks.iter().map(|&(_, count)| count));
+
+    write_full_box(v, b"stsc", 0, 0, |v| {
+        v.write_u32::<BigEndian>(entries.len() as u32).unwrap();
+        let mut first_chunk = 1;
+        for &(count, samples_per_chunk) in &entries {
+            v.write_u32::<BigEndian>(first_chunk).unwrap();
+            v.write_u32::<BigEndian>(samples_per_chunk).unwrap();
+            // Sample description index
+            v.write_u32::<BigEndian>(1).unwrap();
+            first_chunk += count;
+        }
+    });
+
+    let offsets = chunks
+        .iter()
+        .map(|&(offset, _)| offset + data_offset)
+        .collect::<Vec<_>>();
+    if needs_version_1(&offsets) {
+        write_full_box(v, b"co64", 0, 0, |v| {
+            v.write_u32::<BigEndian>(offsets.len() as u32).unwrap();
+            for offset in offsets {
+                v.write_u64::<BigEndian>(offset).unwrap();
+            }
+        });
+    } else {
+        write_full_box(v, b"stco", 0, 0, |v| {
+            v.write_u32::<BigEndian>(offsets.len() as u32).unwrap();
+            for offset in offsets {
+                v.write_u32::<BigEndian>(offset as u32).unwrap();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gst_plugin::tags::TrackRole;
+
+    fn find_box<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+        let mut data = data;
+        'path: for fourcc in path {
+            let mut pos = 0;
+            while pos + 8 <= data.len() {
+                let size = Cursor::new(&data[pos..]).read_u32::<BigEndian>().unwrap() as usize;
+                if &data[(pos + 4)..(pos + 8)] == *fourcc {
+                    data = &data[(pos + 8)..(pos + size)];
+                    continue 'path;
+                }
+                pos += size;
+            }
+            return None;
+        }
+        Some(data)
+    }
+
+    fn video_track() -> Track {
+        // IPBB with the B-frames referencing the P-frame, resulting in a DTS before the PTS
+        let samples = [(0, 3000, true), (3000, 9000, false), (6000, 6000, false)]
+            .iter()
+            .enumerate()
+            .map(|(idx, &(dts, pts, sync))| Sample {
+                offset: idx as u64 * 100,
+                size: 100,
+                dts: dts - 3000,
+                pts: pts - 3000,
+                duration: 3000,
+                sync: sync,
+            })
+            .collect();
+
+        Track {
+            id: 1,
+            codec: Codec::H264 {
+                width: 320,
+                height: 240,
+                avcc: vec![1, 2, 3],
+            },
+            samples: samples,
+            info: StreamInfo::default(),
+        }
+    }
+
+    #[test]
+    fn test_opus_head() {
+        let head = [
+            b'O', b'p', b'u', b's', b'H', b'e', b'a', b'd', 1, 2, 0x38, 0x01, 0x80, 0xbb, 0, 0, 0,
+            0, 0,
+        ];
+        let header = OpusHeader::from_opus_head(&head).unwrap();
+        assert_eq!(header.channels, 2);
+        assert_eq!(header.pre_skip, 312);
+        assert_eq!(header.input_rate, 48_000);
+        assert_eq!(header.channel_mapping_family, 0);
+        assert_eq!(header.coupled_count, 1);
+
+        assert_eq!(OpusHeader::from_opus_head(&head[..10]), None);
+    }
+
+    #[test]
+    fn test_edit_list() {
+        let track = video_track();
+        let moov = write_moov(&[track], 48);
+
+        let elst = find_box(&moov, &[b"moov", b"trak", b"edts", b"elst"]).unwrap();
+        // Version/flags, one entry, 9000 / 90000 * 1000 duration starting at media time 3000
+        assert_eq!(
+            elst,
+            &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 100, 0, 0, 0x0b, 0xb8, 0, 1, 0, 0][..]
+        );
+
+        let ctts = find_box(
+            &moov,
+            &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"ctts"],
+        ).unwrap();
+        assert_eq!(&ctts[4..8], &[0, 0, 0, 3][..]);
+
+        let stss = find_box(
+            &moov,
+            &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stss"],
+        ).unwrap();
+        assert_eq!(stss, &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1][..]);
+
+        // All samples are in a single chunk right after the mdat header
+        let stco = find_box(
+            &moov,
+            &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stco"],
+        ).unwrap();
+        assert_eq!(stco, &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 48][..]);
+    }
+
+    #[test]
+    fn test_empty_edit() {
+        let mut audio = Track {
+            id: 2,
+            codec: Codec::Aac {
+                channels: 2,
+                rate: 48_000,
+                audio_specific_config: vec![0x11, 0x90],
+            },
+            samples: Vec::new(),
+            info: StreamInfo {
+                language: Some(String::from("de")),
+                role: Some(TrackRole::Description),
+            },
+        };
+        for i in 0..10 {
+            audio.samples.push(Sample {
+                offset: 1000 + i * 10,
+                size: 10,
+                dts: 4800 + i as i64 * 1024,
+                pts: 4800 + i as i64 * 1024,
+                duration: 1024,
+                sync: true,
+            });
+        }
+
+        let moov = write_moov(&[video_track(), audio], 0);
+
+        let trak = find_box(&moov, &[b"moov"]).unwrap();
+        let audio_trak = {
+            // Skip mvhd and the video trak
+            let mvhd_size = Cursor::new(trak).read_u32::<BigEndian>().unwrap() as usize;
+            let video_size = Cursor::new(&trak[mvhd_size..])
+                .read_u32::<BigEndian>()
+                .unwrap() as usize;
+            &trak[(mvhd_size + video_size)..]
+        };
+        let elst = find_box(audio_trak, &[b"trak", b"edts", b"elst"]).unwrap();
+        // Audio starts 100ms after the video
+        assert_eq!(&elst[4..8], &[0, 0, 0, 2][..]);
+        assert_eq!(&elst[8..16], &[0, 0, 0, 100, 0xff, 0xff, 0xff, 0xff][..]);
+        assert!(find_box(audio_trak, &[b"trak", b"mdia", b"minf", b"stbl", b"ctts"]).is_none());
+        assert!(find_box(audio_trak, &[b"trak", b"mdia", b"minf", b"stbl", b"stss"]).is_none());
+
+        // Language "deu" and the description role
+        let mdhd = find_box(audio_trak, &[b"trak", b"mdia", b"mdhd"]).unwrap();
+        assert_eq!(&mdhd[20..22], &[0x10, 0xb5][..]);
+        let kind = find_box(audio_trak, &[b"trak", b"udta", b"kind"]).unwrap();
+        assert_eq!(&kind[4..], &b"urn:mpeg:dash:role:2011\0description\0"[..]);
+
+        let mdhd = find_box(&moov, &[b"moov", b"trak", b"mdia", b"mdhd"]).unwrap();
+        assert_eq!(&mdhd[20..22], &[0x55, 0xc4][..]);
+        assert!(find_box(&moov, &[b"moov", b"trak", b"udta"]).is_none());
+    }
+
+    #[test]
+    fn test_tx3g() {
+        assert_eq!(tx3g_sample(b""), vec![0, 0]);
+        assert_eq!(tx3g_sample(b"Hi"), vec![0, 2, b'H', b'i']);
+        // Truncated before the multi-byte character that doesn't fit anymore
+        let mut text = vec![b'a'; 0xfffe];
+        text.extend_from_slice("ä".as_bytes());
+        assert_eq!(tx3g_sample(&text).len(), 2 + 0xfffe);
+
+        let track = Track {
+            id: 3,
+            codec: Codec::Tx3g,
+            samples: vec![
+                Sample {
+                    offset: 0,
+                    size: 4,
+                    dts: 0,
+                    pts: 0,
+                    duration: 1500,
+                    sync: true,
+                },
+            ],
+            info: StreamInfo::default(),
+        };
+        let moov = write_moov(&[track], 0);
+
+        let hdlr = find_box(&moov, &[b"moov", b"trak", b"mdia", b"hdlr"]).unwrap();
+        assert_eq!(&hdlr[8..12], b"sbtl");
+        assert!(find_box(&moov, &[b"moov", b"trak", b"mdia", b"minf", b"nmhd"]).is_some());
+        let stsd = find_box(
+            &moov,
+            &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"],
+        ).unwrap();
+        let tx3g = find_box(&stsd[8..], &[b"tx3g"]).unwrap();
+        let ftab = find_box(&tx3g[38..], &[b"ftab"]).unwrap();
+        assert_eq!(&ftab[5..], b"Sans-Serif");
+    }
+}
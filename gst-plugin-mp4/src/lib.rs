@@ -0,0 +1,65 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate byteorder;
+extern crate glib;
+#[macro_use]
+extern crate gst_plugin;
+extern crate gst_plugin_simple;
+#[macro_use]
+extern crate gstreamer as gst;
+
+use gst_plugin_simple::demuxer::*;
+use gst_plugin::registration::*;
+
+mod boxes;
+mod parse;
+
+mod mp4demux;
+mod mp4mux;
+
+use mp4demux::Mp4Demux;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    let mut caps = gst::Caps::new_simple("video/quicktime", &[]);
+    caps.get_mut()
+        .unwrap()
+        .append(gst::Caps::new_simple("audio/x-m4a", &[]));
+
+    if !demuxer_register(
+        plugin,
+        DemuxerInfo {
+            name: "rsmp4demux".into(),
+            long_name: "MP4 Demuxer".into(),
+            description: "Demuxes MP4 Files".into(),
+            classification: "Codec/Demuxer".into(),
+            author: "Sebastian Dröge <sebastian@centricular.com>".into(),
+            rank: RANK_NONE,
+            create_instance: Mp4Demux::new_boxed,
+            input_caps: caps,
+            output_caps: gst::Caps::new_any(),
+        },
+    ) {
+        return false;
+    }
+
+    ElementRegistration::new(plugin)
+        .element("rsmp4mux", RANK_NONE, mp4mux::get_type())
+        .register()
+}
+
+plugin_define!(
+    "rsmp4",
+    "Rust MP4 Plugin",
+    plugin_init,
+    "MIT/X11",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2018-01-22"
+);
@@ -0,0 +1,717 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::u64;
+
+use gst_plugin::adapter::*;
+use gst_plugin::element::*;
+use gst_plugin::sandbox;
+use gst_plugin_simple::demuxer::*;
+use gst_plugin_simple::error::*;
+
+use glib;
+use gst;
+
+use boxes::Codec;
+use parse::{self, Movie, TrackInfo};
+
+// GST_BUFFER_OFFSET_NONE
+const BUFFER_OFFSET_NONE: u64 = u64::MAX;
+
+#[derive(Debug)]
+enum State {
+    Stopped,
+    // Looking for the moov box
+    Boxes,
+    Skipping { skip_left: u64 },
+    Streaming,
+}
+
+#[derive(Debug)]
+struct StreamingState {
+    // All samples of all tracks as (offset, track, sample) ordered by offset
+    order: Vec<(u64, usize, usize)>,
+    next: usize,
+    // Next sample of each track, earlier samples are skipped after seeking
+    track_next: Vec<usize>,
+    discont: Vec<bool>,
+    stop: gst::ClockTime,
+    last_position: gst::ClockTime,
+}
+
+impl StreamingState {
+    fn new(movie: &Movie) -> StreamingState {
+        let mut order = Vec::new();
+        for (track_idx, track) in movie.tracks.iter().enumerate() {
+            for (sample_idx, sample) in track.samples.iter().enumerate() {
+                order.push((sample.offset, track_idx, sample_idx));
+            }
+        }
+        order.sort();
+
+        StreamingState {
+            order: order,
+            next: 0,
+            track_next: vec![0; movie.tracks.len()],
+            discont: vec![true; movie.tracks.len()],
+            stop: gst::CLOCK_TIME_NONE,
+            last_position: gst::CLOCK_TIME_NONE,
+        }
+    }
+}
+
+pub struct Mp4Demux {
+    cat: gst::DebugCategory,
+    state: State,
+    adapter: Adapter,
+    // Offset of the first byte in the adapter
+    offset: u64,
+    // Set after seeking until data from the new offset arrives
+    seeking: bool,
+    pending: VecDeque<HandleBufferResult>,
+    movie: Option<Movie>,
+    // Only in >= State::Streaming
+    streaming_state: Option<StreamingState>,
+}
+
+fn rescale(value: u64, from: u32, to: u64) -> u64 {
+    let from = u64::from(from);
+    value / from * to + value % from * to / from
+}
+
+// Negative times are clipped to zero
+fn to_time(value: i64, timescale: u32) -> gst::ClockTime {
+    gst::ClockTime::from_nseconds(rescale(cmp::max(value, 0) as u64, timescale, gst::SECOND_VAL))
+}
+
+fn to_timescale(time: u64, timescale: u32) -> i64 {
+    let timescale = u64::from(timescale);
+    (time / gst::SECOND_VAL * timescale + time % gst::SECOND_VAL * timescale / gst::SECOND_VAL)
+        as i64
+}
+
+fn codec_to_caps(codec: &Codec) -> gst::Caps {
+    match *codec {
+        Codec::H264 {
+            width,
+            height,
+            ref avcc,
+        } => gst::Caps::new_simple(
+            "video/x-h264",
+            &[
+                ("stream-format", &"avc"),
+                ("alignment", &"au"),
+                ("width", &i32::from(width)),
+                ("height", &i32::from(height)),
+                (
+                    "codec_data",
+                    &gst::Buffer::from_mut_slice(avcc.clone()).unwrap(),
+                ),
+            ],
+        ),
+        Codec::H265 {
+            width,
+            height,
+            ref hvcc,
+            hev1,
+        } => gst::Caps::new_simple(
+            "video/x-h265",
+            &[
+                ("stream-format", &if hev1 { "hev1" } else { "hvc1" }),
+                ("alignment", &"au"),
+                ("width", &i32::from(width)),
+                ("height", &i32::from(height)),
+                (
+                    "codec_data",
+                    &gst::Buffer::from_mut_slice(hvcc.clone()).unwrap(),
+                ),
+            ],
+        ),
+        Codec::Aac {
+            channels,
+            rate,
+            ref audio_specific_config,
+        } => gst::Caps::new_simple(
+            "audio/mpeg",
+            &[
+                ("mpegversion", &4i32),
+                ("framed", &true),
+                ("stream-format", &"raw"),
+                ("channels", &i32::from(channels)),
+                ("rate", &(rate as i32)),
+                (
+                    "codec_data",
+                    &gst::Buffer::from_mut_slice(audio_specific_config.clone()).unwrap(),
+                ),
+            ],
+        ),
+        Codec::Opus(ref header) if header.channel_mapping_family == 0 => gst::Caps::new_simple(
+            "audio/x-opus",
+            &[
+                ("channels", &i32::from(header.channels)),
+                ("rate", &48_000i32),
+                ("channel-mapping-family", &0i32),
+            ],
+        ),
+        Codec::Opus(ref header) => {
+            let mapping = header
+                .channel_mapping
+                .iter()
+                .map(|&c| i32::from(c))
+                .collect::<Vec<_>>();
+            let mapping = mapping
+                .iter()
+                .map(|c| c as &glib::ToSendValue)
+                .collect::<Vec<_>>();
+
+            gst::Caps::new_simple(
+                "audio/x-opus",
+                &[
+                    ("channels", &i32::from(header.channels)),
+                    ("rate", &48_000i32),
+                    (
+                        "channel-mapping-family",
+                        &i32::from(header.channel_mapping_family),
+                    ),
+                    ("stream-count", &i32::from(header.stream_count)),
+                    ("coupled-count", &i32::from(header.coupled_count)),
+                    ("channel-mapping", &gst::Array::new(&mapping)),
+                ],
+            )
+        }
+    }
+}
+
+// Index of the last sample presented at or before `time`, or of the last sync sample at or
+// before `time` if `sync_only`
+fn sample_for_time(track: &TrackInfo, time: i64, sync_only: bool) -> usize {
+    let mut res = 0;
+    for (idx, sample) in track.samples.iter().enumerate() {
+        if sync_only && !sample.sync {
+            continue;
+        }
+        let pts = sample.pts + track.edit_offset;
+        if pts > time {
+            if sync_only {
+                break;
+            }
+            continue;
+        }
+        res = idx;
+    }
+    res
+}
+
+impl Mp4Demux {
+    pub fn new(_demuxer: &Element) -> Mp4Demux {
+        Mp4Demux {
+            cat: gst::DebugCategory::new(
+                "rsmp4demux",
+                gst::DebugColorFlags::empty(),
+                "Rust MP4 demuxer",
+            ),
+            state: State::Stopped,
+            adapter: Adapter::new(),
+            offset: 0,
+            seeking: false,
+            pending: VecDeque::new(),
+            movie: None,
+            streaming_state: None,
+        }
+    }
+
+    pub fn new_boxed(demuxer: &Element) -> Box<DemuxerImpl> {
+        Box::new(Self::new(demuxer))
+    }
+
+    // Drops all data and waits for data starting at `offset`
+    fn continue_from_offset(&mut self, offset: u64) {
+        self.adapter.clear();
+        self.offset = offset;
+        self.seeking = true;
+    }
+
+    fn flush(&mut self, size: usize) {
+        self.adapter.flush(size).unwrap();
+        self.offset += size as u64;
+    }
+
+    fn handle_moov(
+        &mut self,
+        demuxer: &Element,
+        header: &parse::BoxHeader,
+        size: u64,
+    ) -> Result<HandleBufferResult, FlowError> {
+        if let Err(err) = sandbox::reserve(size as usize) {
+            return Err(FlowError::Error(gst_error_msg!(
+                gst::StreamError::Demux,
+                ["moov box too big: {}", err]
+            )));
+        }
+
+        if (self.adapter.get_available() as u64) < size {
+            return Ok(HandleBufferResult::NeedMoreData);
+        }
+
+        let buffer = self.adapter.get_buffer(size as usize).unwrap();
+        self.offset += size;
+
+        let movie = {
+            let map = buffer.map_readable().unwrap();
+            match parse::parse_moov(&map.as_slice()[(header.header_size as usize)..]) {
+                Ok(movie) => movie,
+                Err(err) => {
+                    return Err(FlowError::Error(gst_error_msg!(
+                        gst::StreamError::Demux,
+                        ["Failed to parse moov box: {}", err]
+                    )));
+                }
+            }
+        };
+
+        for &(id, ref entry_type) in &movie.unsupported {
+            gst_warning!(
+                self.cat,
+                obj: demuxer,
+                "Skipping track {} with unsupported sample entry {}",
+                id,
+                String::from_utf8_lossy(entry_type)
+            );
+        }
+
+        if movie.tracks.is_empty() {
+            return Err(FlowError::Error(gst_error_msg!(
+                gst::StreamError::CodecNotFound,
+                ["No supported tracks"]
+            )));
+        }
+
+        for (idx, track) in movie.tracks.iter().enumerate() {
+            let caps = codec_to_caps(&track.codec);
+            gst_debug!(
+                self.cat,
+                obj: demuxer,
+                "Track {} with {} samples and caps {}",
+                track.id,
+                track.samples.len(),
+                caps
+            );
+
+            self.pending.push_back(HandleBufferResult::StreamAdded(Stream::new(
+                idx as u32,
+                caps,
+                format!("{}", track.id),
+            )));
+        }
+        self.pending.push_back(HandleBufferResult::HaveAllStreams);
+
+        self.streaming_state = Some(StreamingState::new(&movie));
+        self.movie = Some(movie);
+        self.state = State::Streaming;
+
+        Ok(self.pending.pop_front().unwrap())
+    }
+
+    fn handle_sample(&mut self, demuxer: &Element) -> Result<HandleBufferResult, FlowError> {
+        loop {
+            let (offset, track_idx, sample_idx, sample, n_samples, edit_offset, timescale) = {
+                let streaming_state = self.streaming_state.as_mut().unwrap();
+                let movie = self.movie.as_ref().unwrap();
+
+                let (offset, track_idx, sample_idx) =
+                    match streaming_state.order.get(streaming_state.next) {
+                        None => return Ok(HandleBufferResult::Eos(None)),
+                        Some(&entry) => entry,
+                    };
+
+                // Already output or skipped because of a seek
+                if sample_idx < streaming_state.track_next[track_idx] {
+                    streaming_state.next += 1;
+                    continue;
+                }
+
+                let track = &movie.tracks[track_idx];
+                (
+                    offset,
+                    track_idx,
+                    sample_idx,
+                    track.samples[sample_idx],
+                    track.samples.len(),
+                    track.edit_offset,
+                    track.timescale,
+                )
+            };
+
+            let pts = to_time(sample.pts + edit_offset, timescale);
+            {
+                let streaming_state = self.streaming_state.as_mut().unwrap();
+                if streaming_state.stop.is_some() && pts >= streaming_state.stop {
+                    gst_debug!(
+                        self.cat,
+                        obj: demuxer,
+                        "Track {} reached stop {}",
+                        track_idx,
+                        streaming_state.stop
+                    );
+                    streaming_state.track_next[track_idx] = n_samples;
+                    streaming_state.next += 1;
+                    continue;
+                }
+            }
+
+            // The sample data is before the current position, e.g. because the moov box was
+            // after the mdat box
+            if offset < self.offset {
+                gst_debug!(self.cat, obj: demuxer, "Continuing from offset {}", offset);
+                self.continue_from_offset(offset);
+                return Ok(HandleBufferResult::NeedDataFromOffset(offset));
+            }
+
+            if offset > self.offset {
+                let skip = cmp::min(self.adapter.get_available() as u64, offset - self.offset);
+                self.flush(skip as usize);
+                if offset > self.offset {
+                    return Ok(HandleBufferResult::NeedMoreData);
+                }
+            }
+
+            if let Err(err) = sandbox::reserve(sample.size as usize) {
+                return Err(FlowError::Error(gst_error_msg!(
+                    gst::StreamError::Demux,
+                    ["Sample too big: {}", err]
+                )));
+            }
+
+            if self.adapter.get_available() < sample.size as usize {
+                return Ok(HandleBufferResult::NeedMoreData);
+            }
+
+            let mut buffer = self.adapter.get_buffer(sample.size as usize).unwrap();
+            self.offset += u64::from(sample.size);
+
+            let streaming_state = self.streaming_state.as_mut().unwrap();
+            streaming_state.next += 1;
+            streaming_state.track_next[track_idx] = sample_idx + 1;
+
+            {
+                let buffer = buffer.get_mut().unwrap();
+                buffer.set_pts(pts);
+                // Decoders can interpolate a DTS before the start
+                if sample.dts + edit_offset >= 0 {
+                    buffer.set_dts(to_time(sample.dts + edit_offset, timescale));
+                }
+                buffer.set_duration(to_time(i64::from(sample.duration), timescale));
+                buffer.set_offset(offset);
+
+                let mut flags = gst::BufferFlags::empty();
+                if !sample.sync {
+                    flags |= gst::BufferFlags::DELTA_UNIT;
+                }
+                if streaming_state.discont[track_idx] {
+                    flags |= gst::BufferFlags::DISCONT;
+                    streaming_state.discont[track_idx] = false;
+                }
+                buffer.set_flags(flags);
+            }
+
+            streaming_state.last_position = streaming_state
+                .last_position
+                .map(|last| cmp::max(last.into(), pts))
+                .unwrap_or(pts);
+
+            gst_trace!(
+                self.cat,
+                obj: demuxer,
+                "Outputting sample {} of track {}: {:?}",
+                sample_idx,
+                track_idx,
+                buffer
+            );
+
+            return Ok(HandleBufferResult::BufferForStream(track_idx as u32, buffer));
+        }
+    }
+
+    fn update_state(&mut self, demuxer: &Element) -> Result<HandleBufferResult, FlowError> {
+        if let Some(res) = self.pending.pop_front() {
+            return Ok(res);
+        }
+
+        match self.state {
+            State::Stopped => unreachable!(),
+            State::Boxes => {
+                let mut data = [0u8; 16];
+                let available = cmp::min(self.adapter.get_available(), data.len());
+                self.adapter.peek_into(&mut data[..available]).unwrap();
+
+                let header = match parse::parse_box_header(&data[..available]) {
+                    Ok(Some(header)) => header,
+                    Ok(None) => return Ok(HandleBufferResult::NeedMoreData),
+                    Err(err) => {
+                        return Err(FlowError::Error(gst_error_msg!(
+                            gst::StreamError::Demux,
+                            ["Invalid box at offset {}: {}", self.offset, err]
+                        )));
+                    }
+                };
+
+                gst_trace!(
+                    self.cat,
+                    obj: demuxer,
+                    "Found {} box at offset {} with size {:?}",
+                    String::from_utf8_lossy(&header.fourcc),
+                    self.offset,
+                    header.size
+                );
+
+                match (&header.fourcc, header.size) {
+                    (b"moov", Some(size)) => self.handle_moov(demuxer, &header, size),
+                    (b"moov", None) | (b"mdat", None) => Err(FlowError::Error(gst_error_msg!(
+                        gst::StreamError::Demux,
+                        ["No moov box before the end of the file"]
+                    ))),
+                    // Skip the media data without reading it and come back once the sample
+                    // tables are known
+                    (b"mdat", Some(size)) => {
+                        let offset = self.offset + size;
+                        gst_debug!(
+                            self.cat,
+                            obj: demuxer,
+                            "mdat box before moov box, continuing from offset {}",
+                            offset
+                        );
+                        self.continue_from_offset(offset);
+                        Ok(HandleBufferResult::NeedDataFromOffset(offset))
+                    }
+                    (_, Some(size)) => {
+                        self.state = State::Skipping { skip_left: size };
+                        Ok(HandleBufferResult::Again)
+                    }
+                    (_, None) => Ok(HandleBufferResult::Eos(None)),
+                }
+            }
+            State::Skipping { skip_left: 0 } => {
+                self.state = State::Boxes;
+                Ok(HandleBufferResult::Again)
+            }
+            State::Skipping { skip_left } => {
+                let skip = cmp::min(self.adapter.get_available() as u64, skip_left);
+                if skip == 0 {
+                    return Ok(HandleBufferResult::NeedMoreData);
+                }
+                self.flush(skip as usize);
+                self.state = State::Skipping {
+                    skip_left: skip_left - skip,
+                };
+
+                Ok(HandleBufferResult::Again)
+            }
+            State::Streaming => self.handle_sample(demuxer),
+        }
+    }
+}
+
+impl DemuxerImpl for Mp4Demux {
+    fn start(
+        &mut self,
+        _demuxer: &Element,
+        _upstream_size: Option<u64>,
+        _random_access: bool,
+    ) -> Result<(), gst::ErrorMessage> {
+        self.state = State::Boxes;
+        self.offset = 0;
+        self.seeking = false;
+
+        Ok(())
+    }
+
+    fn stop(&mut self, _demuxer: &Element) -> Result<(), gst::ErrorMessage> {
+        self.state = State::Stopped;
+        self.adapter.clear();
+        self.pending.clear();
+        self.movie = None;
+        self.streaming_state = None;
+
+        Ok(())
+    }
+
+    fn seek(
+        &mut self,
+        demuxer: &Element,
+        start: gst::ClockTime,
+        stop: gst::ClockTime,
+    ) -> Result<SeekResult, gst::ErrorMessage> {
+        let start = start.0.unwrap_or(0);
+
+        let offset = {
+            let (movie, streaming_state) =
+                match (self.movie.as_ref(), self.streaming_state.as_mut()) {
+                    (Some(movie), Some(streaming_state)) => (movie, streaming_state),
+                    _ => return Ok(SeekResult::TooEarly),
+                };
+
+            // Start from the sync sample before the seek position in all video tracks, and
+            // from the sample presented at the earliest of these in all other tracks
+            let target = movie
+                .tracks
+                .iter()
+                .filter(|track| track.codec.is_video() && !track.samples.is_empty())
+                .map(|track| {
+                    let idx = sample_for_time(track, to_timescale(start, track.timescale), true);
+                    to_time(track.samples[idx].pts + track.edit_offset, track.timescale)
+                        .0
+                        .unwrap()
+                })
+                .min()
+                .unwrap_or(start);
+
+            gst_debug!(
+                self.cat,
+                obj: demuxer,
+                "Seeking to {} for seek position {}",
+                gst::ClockTime::from_nseconds(target),
+                gst::ClockTime::from_nseconds(start)
+            );
+
+            let mut offset = None;
+            for (idx, track) in movie.tracks.iter().enumerate() {
+                let sample_idx = if track.samples.is_empty() {
+                    0
+                } else if track.codec.is_video() {
+                    sample_for_time(track, to_timescale(start, track.timescale), true)
+                } else {
+                    let idx = sample_for_time(track, to_timescale(target, track.timescale), false);
+                    let sample = &track.samples[idx];
+                    let end = to_time(
+                        sample.pts + track.edit_offset + i64::from(sample.duration),
+                        track.timescale,
+                    );
+                    // The seek position is after the end of the track
+                    if end.0.unwrap() <= start {
+                        track.samples.len()
+                    } else {
+                        idx
+                    }
+                };
+
+                streaming_state.track_next[idx] = sample_idx;
+                streaming_state.discont[idx] = true;
+                if let Some(sample) = track.samples.get(sample_idx) {
+                    offset = Some(cmp::min(offset.unwrap_or(u64::MAX), sample.offset));
+                }
+            }
+
+            let offset = match offset {
+                None => return Ok(SeekResult::Eos),
+                Some(offset) => offset,
+            };
+
+            streaming_state.next = streaming_state
+                .order
+                .iter()
+                .position(|&(o, _, _)| o >= offset)
+                .unwrap_or(0);
+            streaming_state.stop = stop;
+            streaming_state.last_position = gst::ClockTime::from_nseconds(start);
+
+            offset
+        };
+
+        self.pending.clear();
+        self.state = State::Streaming;
+        self.continue_from_offset(offset);
+
+        Ok(SeekResult::Ok(offset))
+    }
+
+    fn handle_buffer(
+        &mut self,
+        demuxer: &Element,
+        buffer: Option<gst::Buffer>,
+    ) -> Result<HandleBufferResult, FlowError> {
+        if let Some(buffer) = buffer {
+            let offset = buffer.get_offset();
+            let expected = self.offset + self.adapter.get_available() as u64;
+
+            if offset != BUFFER_OFFSET_NONE && offset != expected {
+                // Data from before the seek was handled upstream
+                if self.seeking {
+                    gst_trace!(self.cat, obj: demuxer, "Dropping buffer at offset {}", offset);
+                    return Ok(HandleBufferResult::NeedMoreData);
+                }
+
+                gst_debug!(
+                    self.cat,
+                    obj: demuxer,
+                    "Discontinuity from offset {} to {}",
+                    expected,
+                    offset
+                );
+                self.adapter.clear();
+                self.offset = offset;
+            }
+
+            self.seeking = false;
+            self.adapter.push(buffer);
+        }
+
+        self.update_state(demuxer)
+    }
+
+    fn end_of_stream(&mut self, _demuxer: &Element) -> Result<(), gst::ErrorMessage> {
+        if self.movie.is_none() {
+            return Err(gst_error_msg!(
+                gst::StreamError::Demux,
+                ["No moov box found"]
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn is_seekable(&self, _demuxer: &Element) -> bool {
+        self.streaming_state.is_some()
+    }
+
+    fn get_position(&self, _demuxer: &Element) -> gst::ClockTime {
+        if let Some(StreamingState { last_position, .. }) = self.streaming_state {
+            return last_position;
+        }
+
+        gst::CLOCK_TIME_NONE
+    }
+
+    fn get_duration(&self, _demuxer: &Element) -> gst::ClockTime {
+        let movie = match self.movie {
+            None => return gst::CLOCK_TIME_NONE,
+            Some(ref movie) => movie,
+        };
+
+        if movie.duration != 0 {
+            return gst::ClockTime::from_nseconds(rescale(
+                movie.duration,
+                movie.timescale,
+                gst::SECOND_VAL,
+            ));
+        }
+
+        // Files without a movie duration, e.g. from interrupted recordings
+        movie
+            .tracks
+            .iter()
+            .filter_map(|track| {
+                track.samples.iter().map(|s| s.pts + i64::from(s.duration)).max().map(|end| {
+                    to_time(end + track.edit_offset, track.timescale)
+                })
+            })
+            .max()
+            .unwrap_or(gst::CLOCK_TIME_NONE)
+    }
+}
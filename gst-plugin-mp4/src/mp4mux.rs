@@ -0,0 +1,976 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::tags::StreamInfo;
+
+use std::cmp;
+use std::env;
+use std::fs;
+use std::i32;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use boxes::{self, Codec, OpusHeader, Sample, Track};
+
+const DEFAULT_FASTSTART: bool = false;
+const FASTSTART_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    faststart: bool,
+    faststart_file: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            faststart: DEFAULT_FASTSTART,
+            faststart_file: None,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 2] = [
+    Property::Boolean(
+        "faststart",
+        "Faststart",
+        "Write the moov before the media data, buffering the media data in a temporary file",
+        DEFAULT_FASTSTART,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "faststart-file",
+        "Faststart File",
+        "Temporary file for buffering the media data (default: in the temporary directory)",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+struct Stream {
+    sinkpad: gst::Pad,
+    track_id: u32,
+    codec: Option<Codec>,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    samples: Vec<Sample>,
+    // Language and role from the tag events
+    info: StreamInfo,
+    eos: bool,
+}
+
+impl Stream {
+    fn new(sinkpad: gst::Pad, track_id: u32) -> Stream {
+        Stream {
+            sinkpad: sinkpad,
+            track_id: track_id,
+            codec: None,
+            segment: gst::FormattedSegment::new(),
+            samples: Vec::new(),
+            info: StreamInfo::default(),
+            eos: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.codec = None;
+        self.segment = gst::FormattedSegment::new();
+        self.samples.clear();
+        self.info = StreamInfo::default();
+        self.eos = false;
+    }
+}
+
+enum Output {
+    // Header was not written yet
+    None,
+    // Media data is pushed downstream directly, the mdat size is fixed up at the end
+    Downstream,
+    // Media data is buffered in a temporary file until the moov is written
+    Faststart(PathBuf, fs::File),
+}
+
+struct State {
+    streams: Vec<Stream>,
+    pad_count: u32,
+    output: Output,
+    // Size of the mdat payload so far
+    mdat_size: u64,
+    finished: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            streams: Vec::new(),
+            pad_count: 0,
+            output: Output::None,
+            mdat_size: 0,
+            finished: false,
+        }
+    }
+}
+
+struct Mp4Mux {
+    cat: gst::DebugCategory,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    // Serializes pushing downstream so that the output is in the same order as the sample
+    // offsets, without keeping the state locked while pushing
+    output_lock: Mutex<()>,
+}
+
+fn codec_data(s: &gst::StructureRef) -> Option<Vec<u8>> {
+    let buffer = s.get::<gst::Buffer>("codec_data")?;
+    let map = buffer.map_readable()?;
+    Some(map.as_slice().to_vec())
+}
+
+fn dimensions(s: &gst::StructureRef) -> Option<(u16, u16)> {
+    let width = s.get::<i32>("width")?;
+    let height = s.get::<i32>("height")?;
+    if width <= 0 || height <= 0 || width > 0xffff || height > 0xffff {
+        return None;
+    }
+    Some((width as u16, height as u16))
+}
+
+fn codec_from_caps(caps: &gst::CapsRef) -> Option<Codec> {
+    let s = caps.get_structure(0)?;
+
+    match s.get_name() {
+        "video/x-h264" => {
+            let (width, height) = dimensions(s)?;
+            Some(Codec::H264 {
+                width: width,
+                height: height,
+                avcc: codec_data(s)?,
+            })
+        }
+        "video/x-h265" => {
+            let (width, height) = dimensions(s)?;
+            Some(Codec::H265 {
+                width: width,
+                height: height,
+                hvcc: codec_data(s)?,
+                hev1: s.get::<&str>("stream-format") == Some("hev1"),
+            })
+        }
+        "audio/mpeg" => {
+            let channels = s.get::<i32>("channels")?;
+            let rate = s.get::<i32>("rate")?;
+            if channels <= 0 || channels > 0xffff || rate <= 0 {
+                return None;
+            }
+            Some(Codec::Aac {
+                channels: channels as u16,
+                rate: rate as u32,
+                audio_specific_config: codec_data(s)?,
+            })
+        }
+        "audio/x-opus" => {
+            let header = s.get::<gst::Array>("streamheader")
+                .and_then(|streamheader| {
+                    streamheader
+                        .as_slice()
+                        .get(0)
+                        .and_then(|v| v.get::<gst::Buffer>())
+                })
+                .and_then(|buffer| {
+                    let map = buffer.map_readable()?;
+                    OpusHeader::from_opus_head(map.as_slice())
+                });
+            if header.is_some() {
+                return header.map(Codec::Opus);
+            }
+
+            // Without stream headers only mono and stereo can be signalled
+            let channels = s.get::<i32>("channels")?;
+            let rate = s.get::<i32>("rate").unwrap_or(48_000);
+            let family = s.get::<i32>("channel-mapping-family").unwrap_or(0);
+            if family != 0 || channels < 1 || channels > 2 {
+                return None;
+            }
+            Some(Codec::Opus(OpusHeader {
+                channels: channels as u8,
+                pre_skip: 0,
+                input_rate: rate as u32,
+                output_gain: 0,
+                channel_mapping_family: 0,
+                stream_count: 1,
+                coupled_count: channels as u8 - 1,
+                channel_mapping: Vec::new(),
+            }))
+        }
+        "text/x-raw" if s.get::<&str>("format") == Some("utf8") => Some(Codec::Tx3g),
+        _ => None,
+    }
+}
+
+// Converts a signed running time in nanoseconds to the timescale without overflowing
+fn to_timescale(time: i64, timescale: u32) -> i64 {
+    let timescale = i64::from(timescale);
+    let secs = time / gst::SECOND_VAL as i64;
+    let rem = time % gst::SECOND_VAL as i64;
+    secs * timescale + rem * timescale / gst::SECOND_VAL as i64
+}
+
+impl Mp4Mux {
+    fn new(_element: &Element, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rsmp4mux",
+                gst::DebugColorFlags::empty(),
+                "Rust MP4 muxer",
+            ),
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+            output_lock: Mutex::new(()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "MP4 muxer",
+            "Codec/Muxer",
+            "Muxes H.264, H.265, AAC, Opus and text streams into progressive MP4 files",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("video/quicktime", &[("variant", &"iso")]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let mut caps = gst::Caps::new_simple(
+            "video/x-h264",
+            &[
+                ("stream-format", &"avc"),
+                ("alignment", &"au"),
+                ("width", &gst::IntRange::<i32>::new(1, 0xffff)),
+                ("height", &gst::IntRange::<i32>::new(1, 0xffff)),
+            ],
+        );
+        caps.get_mut().unwrap().append(gst::Caps::new_simple(
+            "video/x-h265",
+            &[
+                ("stream-format", &gst::List::new(&[&"hvc1", &"hev1"])),
+                ("alignment", &"au"),
+                ("width", &gst::IntRange::<i32>::new(1, 0xffff)),
+                ("height", &gst::IntRange::<i32>::new(1, 0xffff)),
+            ],
+        ));
+        let sink_pad_template = gst::PadTemplate::new(
+            "video_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let mut caps = gst::Caps::new_simple(
+            "audio/mpeg",
+            &[
+                ("mpegversion", &4i32),
+                ("stream-format", &"raw"),
+                ("channels", &gst::IntRange::<i32>::new(1, 0xffff)),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            ],
+        );
+        caps.get_mut().unwrap().append(gst::Caps::new_simple(
+            "audio/x-opus",
+            &[
+                ("channel-mapping-family", &gst::IntRange::<i32>::new(0, 255)),
+                ("channels", &gst::IntRange::<i32>::new(1, 255)),
+            ],
+        ));
+        let sink_pad_template = gst::PadTemplate::new(
+            "audio_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple("text/x-raw", &[("format", &"utf8")]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "subtitle_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+        srcpad.set_event_function(|pad, parent, event| {
+            Mp4Mux::catch_panic_pad_function(
+                parent,
+                || false,
+                |mp4mux, element| mp4mux.src_event(pad, element, event),
+            )
+        });
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let mp4mux = element.get_impl().downcast_ref::<Mp4Mux>().unwrap();
+        element.catch_panic(fallback, |element| f(mp4mux, element))
+    }
+
+    fn start_output(
+        &self,
+        element: &Element,
+        state: &mut State,
+    ) -> Result<Option<gst::Buffer>, gst::ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        self.srcpad
+            .push_event(gst::Event::new_stream_start(&element.get_name()).build());
+        self.srcpad.push_event(
+            gst::Event::new_caps(&gst::Caps::new_simple(
+                "video/quicktime",
+                &[("variant", &"iso")],
+            )).build(),
+        );
+        let segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+        self.srcpad
+            .push_event(gst::Event::new_segment(&segment).build());
+
+        if settings.faststart {
+            let path = match settings.faststart_file {
+                Some(ref path) => PathBuf::from(path),
+                None => {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default();
+                    env::temp_dir().join(format!(
+                        "{}-{}{:09}.mdat",
+                        element.get_name(),
+                        now.as_secs(),
+                        now.subsec_nanos()
+                    ))
+                }
+            };
+
+            gst_debug!(self.cat, obj: element, "Buffering media data in {:?}", path);
+
+            let file = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
+                .map_err(|err| {
+                    gst_error_msg!(
+                        gst::ResourceError::OpenReadWrite,
+                        ["Failed to open faststart file {:?}: {}", path, err]
+                    )
+                })?;
+
+            state.output = Output::Faststart(path, file);
+            Ok(None)
+        } else {
+            let mut header = boxes::write_ftyp();
+            // The size is fixed up at the end
+            header.extend_from_slice(&boxes::write_mdat_header(0));
+
+            state.output = Output::Downstream;
+            Ok(Some(gst::Buffer::from_mut_slice(header).unwrap()))
+        }
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let _output_lock = self.output_lock.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        let started = match state.output {
+            Output::None => false,
+            _ => true,
+        };
+
+        let header = if !started {
+            match self.start_output(element, &mut state) {
+                Ok(header) => header,
+                Err(msg) => {
+                    element.post_error_message(&msg);
+                    return gst::FlowReturn::Error;
+                }
+            }
+        } else {
+            None
+        };
+
+        let offset = state.mdat_size;
+        let buffer = {
+            let stream = match state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                None => return gst::FlowReturn::Error,
+                Some(stream) => stream,
+            };
+
+            let (timescale, is_text) = match stream.codec {
+                None => {
+                    gst_element_error!(
+                        element,
+                        gst::CoreError::Negotiation,
+                        ["Buffer before caps on {}", pad.get_name()]
+                    );
+                    return gst::FlowReturn::NotNegotiated;
+                }
+                Some(ref codec) => (codec.timescale(), codec.is_text()),
+            };
+
+            let pts = match stream.segment.to_running_time(buffer.get_pts()).0 {
+                None => {
+                    gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(pts) => pts,
+            };
+
+            // The DTS can be before the segment start and thus have a negative running time,
+            // so compute it relative to the PTS instead
+            let dts = match (buffer.get_pts().0, buffer.get_dts().0) {
+                (Some(buffer_pts), Some(buffer_dts)) => {
+                    pts as i64 - (buffer_pts as i64 - buffer_dts as i64)
+                }
+                _ => pts as i64,
+            };
+
+            let pts = to_timescale(pts as i64, timescale);
+            let dts = to_timescale(dts, timescale);
+            let duration = buffer
+                .get_duration()
+                .0
+                .map(|duration| to_timescale(duration as i64, timescale))
+                .unwrap_or(0);
+
+            // Text streams are sparse, but the samples of a track have no gaps between them and
+            // each text stays visible until the next sample. An empty sample is inserted to clear
+            // the text at its end if there is a gap until the next one.
+            let (buffer, sample_offset) = if is_text {
+                let mut data = Vec::new();
+                let mut sample_offset = offset;
+
+                // Texts without duration are shown until the next one
+                let last = stream
+                    .samples
+                    .last()
+                    .map(|last| (last.duration, last.dts + i64::from(last.duration)));
+                let gap_start = match last {
+                    Some((duration, end)) if duration > 0 && end < dts => Some(end),
+                    _ => None,
+                };
+                if let Some(gap_start) = gap_start {
+                    data.extend_from_slice(&boxes::tx3g_sample(&[]));
+                    stream.samples.push(Sample {
+                        offset: offset,
+                        size: data.len() as u32,
+                        dts: gap_start,
+                        pts: gap_start,
+                        duration: 0,
+                        sync: true,
+                    });
+                    sample_offset += data.len() as u64;
+                }
+
+                {
+                    let map = match buffer.map_readable() {
+                        None => {
+                            gst_element_error!(
+                                element,
+                                gst::CoreError::Failed,
+                                ["Failed to map buffer"]
+                            );
+                            return gst::FlowReturn::Error;
+                        }
+                        Some(map) => map,
+                    };
+                    data.extend_from_slice(&boxes::tx3g_sample(map.as_slice()));
+                }
+
+                let mut sample_buffer = gst::Buffer::from_mut_slice(data).unwrap();
+                {
+                    let sample_buffer = sample_buffer.get_mut().unwrap();
+                    sample_buffer.set_pts(buffer.get_pts());
+                    sample_buffer.set_duration(buffer.get_duration());
+                }
+                (sample_buffer, sample_offset)
+            } else {
+                (buffer, offset)
+            };
+
+            if let Some(last) = stream.samples.last_mut() {
+                if dts < last.dts {
+                    gst_element_error!(
+                        element,
+                        gst::StreamError::Format,
+                        ["Decreasing DTS on {}", pad.get_name()]
+                    );
+                    return gst::FlowReturn::Error;
+                }
+                last.duration = cmp::min(dts - last.dts, i64::from(u32::max_value())) as u32;
+            }
+
+            stream.samples.push(Sample {
+                offset: sample_offset,
+                size: (buffer.get_size() as u64 - (sample_offset - offset)) as u32,
+                dts: dts,
+                pts: pts,
+                duration: cmp::min(cmp::max(duration, 0), i64::from(u32::max_value())) as u32,
+                sync: !buffer.get_flags().contains(gst::BufferFlags::DELTA_UNIT),
+            });
+
+            buffer
+        };
+        state.mdat_size += buffer.get_size() as u64;
+
+        if let Output::Faststart(_, ref mut file) = state.output {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            if let Err(err) = file.write_all(map.as_slice()) {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::Write,
+                    ["Failed to write to faststart file: {}", err]
+                );
+                return gst::FlowReturn::Error;
+            }
+
+            return gst::FlowReturn::Ok;
+        }
+        drop(state);
+
+        if let Some(header) = header {
+            let flow_ret = self.srcpad.push(header);
+            if flow_ret != gst::FlowReturn::Ok {
+                return flow_ret;
+            }
+        }
+
+        self.srcpad.push(buffer)
+    }
+
+    fn finish(&self, element: &Element) -> Result<(), gst::FlowReturn> {
+        let _output_lock = self.output_lock.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        if state.finished {
+            return Ok(());
+        }
+        state.finished = true;
+
+        let tracks = state
+            .streams
+            .iter()
+            .filter(|s| !s.samples.is_empty())
+            .map(|s| Track {
+                id: s.track_id,
+                codec: s.codec.clone().unwrap(),
+                samples: s.samples.clone(),
+                info: s.info.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        if tracks.is_empty() {
+            gst_element_error!(element, gst::StreamError::Mux, ["No data to mux"]);
+            return Err(gst::FlowReturn::Error);
+        }
+
+        let mdat_size = state.mdat_size;
+        let ftyp = boxes::write_ftyp();
+        let output = mem::replace(&mut state.output, Output::None);
+        drop(state);
+
+        match output {
+            Output::None => unreachable!(),
+            Output::Downstream => {
+                let data_offset = ftyp.len() as u64 + boxes::MDAT_HEADER_SIZE;
+                let moov = boxes::write_moov(&tracks, data_offset);
+                gst_debug!(self.cat, obj: element, "Writing moov of {} bytes", moov.len());
+                self.push(gst::Buffer::from_mut_slice(moov).unwrap())?;
+
+                // Seek back and rewrite the mdat header with the final size
+                let mut segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+                segment.set_start(gst::format::Bytes(Some(ftyp.len() as u64)));
+                self.srcpad
+                    .push_event(gst::Event::new_segment(&segment).build());
+                let header = boxes::write_mdat_header(mdat_size);
+                self.push(gst::Buffer::from_mut_slice(header).unwrap())?;
+            }
+            Output::Faststart(path, mut file) => {
+                // The moov size depends on the chunk offsets, which depend on the moov size
+                let mut moov_size = 0;
+                let moov = loop {
+                    let data_offset = (ftyp.len() + moov_size) as u64 + boxes::MDAT_HEADER_SIZE;
+                    let moov = boxes::write_moov(&tracks, data_offset);
+                    if moov.len() == moov_size {
+                        break moov;
+                    }
+                    moov_size = moov.len();
+                };
+                gst_debug!(self.cat, obj: element, "Writing moov of {} bytes", moov.len());
+
+                let res = self.push_faststart(element, ftyp, moov, mdat_size, &mut file);
+                drop(file);
+                if let Err(err) = fs::remove_file(&path) {
+                    gst_warning!(
+                        self.cat,
+                        obj: element,
+                        "Failed to remove faststart file {:?}: {}",
+                        path,
+                        err
+                    );
+                }
+                res?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn push_faststart(
+        &self,
+        element: &Element,
+        ftyp: Vec<u8>,
+        moov: Vec<u8>,
+        mdat_size: u64,
+        file: &mut fs::File,
+    ) -> Result<(), gst::FlowReturn> {
+        let mut header = ftyp;
+        header.extend_from_slice(&moov);
+        header.extend_from_slice(&boxes::write_mdat_header(mdat_size));
+        self.push(gst::Buffer::from_mut_slice(header).unwrap())?;
+
+        if let Err(err) = file.seek(SeekFrom::Start(0)) {
+            gst_element_error!(
+                element,
+                gst::ResourceError::Seek,
+                ["Failed to seek faststart file: {}", err]
+            );
+            return Err(gst::FlowReturn::Error);
+        }
+
+        loop {
+            let mut data = vec![0; FASTSTART_CHUNK_SIZE];
+            let len = match file.read(&mut data) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(err) => {
+                    gst_element_error!(
+                        element,
+                        gst::ResourceError::Read,
+                        ["Failed to read faststart file: {}", err]
+                    );
+                    return Err(gst::FlowReturn::Error);
+                }
+            };
+            data.truncate(len);
+            self.push(gst::Buffer::from_mut_slice(data).unwrap())?;
+        }
+
+        Ok(())
+    }
+
+    fn push(&self, buffer: gst::Buffer) -> Result<(), gst::FlowReturn> {
+        match self.srcpad.push(buffer) {
+            gst::FlowReturn::Ok => Ok(()),
+            flow_ret => Err(flow_ret),
+        }
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let codec = match codec_from_caps(e.get_caps()) {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Negotiation,
+                            ["Unsupported caps {:?}", e.get_caps()]
+                        );
+                        return false;
+                    }
+                    Some(codec) => codec,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                let stream = match state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    None => return false,
+                    Some(stream) => stream,
+                };
+
+                if !stream.samples.is_empty() && stream.codec.as_ref() != Some(&codec) {
+                    gst_element_error!(
+                        element,
+                        gst::CoreError::Negotiation,
+                        ["Caps changes are not supported"]
+                    );
+                    return false;
+                }
+
+                gst_debug!(self.cat, obj: pad, "Configured codec {:?}", codec);
+                stream.codec = Some(codec);
+                true
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    stream.segment = segment;
+                }
+                true
+            }
+            EventView::Tag(e) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    stream.info.update(e.get_tag());
+                    gst_debug!(self.cat, obj: pad, "Stream info {:?}", stream.info);
+                }
+                drop(state);
+
+                self.srcpad.push_event(event)
+            }
+            // The output has its own stream-start
+            EventView::StreamStart(..) => true,
+            // Sparse streams only signal that there is no data, which has no meaning downstream
+            EventView::Gap(..) => true,
+            EventView::Eos(..) => {
+                let all_eos = {
+                    let mut state = self.state.lock().unwrap();
+                    if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                        stream.eos = true;
+                    }
+                    state.streams.iter().all(|s| s.eos)
+                };
+
+                if all_eos {
+                    gst_debug!(self.cat, obj: element, "All streams are EOS, finishing");
+                    if let Err(flow_ret) = self.finish(element) {
+                        gst_debug!(self.cat, obj: element, "Failed to finish: {:?}", flow_ret);
+                    }
+                    self.srcpad.push_event(event);
+                }
+                true
+            }
+            _ => self.srcpad.push_event(event),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Seek(..) => {
+                gst_debug!(self.cat, obj: pad, "Seeking is not supported");
+                false
+            }
+            _ => pad.event_default(Some(&element.clone().upcast()), event),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for Mp4Mux {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Boolean("faststart", ..) => {
+                settings.faststart = value.get().unwrap();
+            }
+            Property::String("faststart-file", ..) => {
+                settings.faststart_file = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Boolean("faststart", ..) => Ok(settings.faststart.to_value()),
+            Property::String("faststart-file", ..) => Ok(settings.faststart_file.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for Mp4Mux {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        if transition == gst::StateChange::PausedToReady {
+            let mut state = self.state.lock().unwrap();
+            if let Output::Faststart(ref path, _) = state.output {
+                let _ = fs::remove_file(path);
+            }
+            state.output = Output::None;
+            state.mdat_size = 0;
+            state.finished = false;
+            for stream in &mut state.streams {
+                stream.reset();
+            }
+        }
+
+        ret
+    }
+
+    fn request_new_pad(
+        &self,
+        element: &Element,
+        templ: &gst::PadTemplate,
+        _name: Option<String>,
+        _caps: Option<&gst::CapsRef>,
+    ) -> Option<gst::Pad> {
+        let mut state = self.state.lock().unwrap();
+
+        match state.output {
+            Output::None => (),
+            _ => {
+                gst_error!(self.cat, obj: element, "Can't request pads after muxing started");
+                return None;
+            }
+        }
+
+        let id = state.pad_count;
+        state.pad_count += 1;
+
+        let name = if *templ == element.get_pad_template("video_%u").unwrap() {
+            format!("video_{}", id)
+        } else if *templ == element.get_pad_template("audio_%u").unwrap() {
+            format!("audio_{}", id)
+        } else {
+            format!("subtitle_{}", id)
+        };
+        let sinkpad = gst::Pad::new_from_template(templ, name.as_str());
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            Mp4Mux::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |mp4mux, element| mp4mux.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            Mp4Mux::catch_panic_pad_function(
+                parent,
+                || false,
+                |mp4mux, element| mp4mux.sink_event(pad, element, event),
+            )
+        });
+
+        sinkpad.set_active(true).unwrap();
+        element.add_pad(&sinkpad).unwrap();
+
+        state.streams.push(Stream::new(sinkpad.clone(), id + 1));
+
+        Some(sinkpad)
+    }
+
+    fn release_pad(&self, element: &Element, pad: &gst::Pad) {
+        let mut state = self.state.lock().unwrap();
+
+        let pos = match state.streams.iter().position(|s| s.sinkpad == *pad) {
+            None => return,
+            Some(pos) => pos,
+        };
+        let stream = state.streams.remove(pos);
+        drop(state);
+
+        stream.sinkpad.set_active(false).unwrap();
+        element.remove_pad(&stream.sinkpad).unwrap();
+    }
+}
+
+struct Mp4MuxStatic;
+
+impl ImplTypeStatic<Element> for Mp4MuxStatic {
+    fn get_name(&self) -> &str {
+        "Mp4Mux"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        Mp4Mux::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        Mp4Mux::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let mp4mux_static = Mp4MuxStatic;
+    register_type(mp4mux_static)
+}
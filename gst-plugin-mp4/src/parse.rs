@@ -0,0 +1,896 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Parsing of ISO base media file format (MP4) boxes for non-fragmented files
+
+use std::cmp;
+use std::io::{self, Cursor, Read};
+use std::iter;
+use std::mem;
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use gst_plugin::sandbox;
+
+use boxes::{Codec, OpusHeader, Sample};
+
+// Timestamps are converted to nanoseconds by the demuxer, which must not overflow
+const NSECONDS: u32 = 1_000_000_000;
+
+// Without per-sample sizes nothing in the moov limits the number of samples. 2^24 samples are
+// more than 77 hours of video at 60fps or 99 hours of AAC at 48kHz.
+const MAX_FIXED_SIZE_SAMPLES: usize = 1 << 24;
+
+fn invalid<T>(msg: &str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+fn skip(cursor: &mut Cursor<&[u8]>, len: u64) {
+    let pos = cursor.position();
+    cursor.set_position(pos + len);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoxHeader {
+    pub fourcc: [u8; 4],
+    pub header_size: u64,
+    /// Size including the header, `None` if the box extends to the end of the file
+    pub size: Option<u64>,
+}
+
+/// Parses the box header at the start of `data`, or returns `None` if more data is needed.
+pub fn parse_box_header(data: &[u8]) -> io::Result<Option<BoxHeader>> {
+    if data.len() < 8 {
+        return Ok(None);
+    }
+
+    let mut cursor = Cursor::new(data);
+    let size = cursor.read_u32::<BigEndian>()?;
+    let mut fourcc = [0; 4];
+    cursor.read_exact(&mut fourcc)?;
+
+    let (header_size, size) = match size {
+        0 => (8, None),
+        1 => {
+            if data.len() < 16 {
+                return Ok(None);
+            }
+            (16, Some(cursor.read_u64::<BigEndian>()?))
+        }
+        size => (8, Some(u64::from(size))),
+    };
+
+    match size {
+        Some(size) if size < header_size => invalid("Invalid box size"),
+        _ => Ok(Some(BoxHeader {
+            fourcc: fourcc,
+            header_size: header_size,
+            size: size,
+        })),
+    }
+}
+
+// Splits `data` into the fourccs and payloads of the contained boxes
+fn children(data: &[u8]) -> io::Result<Vec<([u8; 4], &[u8])>> {
+    let mut children = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        // Some writers pad the end of container boxes with a few zero bytes
+        let header = match parse_box_header(&data[pos..])? {
+            None => break,
+            Some(header) => header,
+        };
+
+        let size = match header.size {
+            None => data.len() - pos,
+            Some(size) if size <= (data.len() - pos) as u64 => size as usize,
+            Some(_) => return invalid("Truncated box"),
+        };
+
+        children.push((
+            header.fourcc,
+            &data[(pos + header.header_size as usize)..(pos + size)],
+        ));
+        pos += size;
+    }
+
+    Ok(children)
+}
+
+fn find<'a>(children: &[([u8; 4], &'a [u8])], fourcc: &[u8; 4]) -> Option<&'a [u8]> {
+    children
+        .iter()
+        .find(|&&(ref f, _)| f == fourcc)
+        .map(|&(_, data)| data)
+}
+
+fn require<'a>(children: &[([u8; 4], &'a [u8])], fourcc: &[u8; 4]) -> io::Result<&'a [u8]> {
+    match find(children, fourcc) {
+        Some(data) => Ok(data),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No {} box", String::from_utf8_lossy(fourcc)),
+        )),
+    }
+}
+
+// Returns the version and a cursor positioned after the version and flags
+fn full_box(data: &[u8]) -> io::Result<(u8, Cursor<&[u8]>)> {
+    let mut cursor = Cursor::new(data);
+    let version_flags = cursor.read_u32::<BigEndian>()?;
+    Ok(((version_flags >> 24) as u8, cursor))
+}
+
+// Reads the entry count of a table and checks that the table fits into the box
+fn entry_count(cursor: &mut Cursor<&[u8]>, entry_size: u64) -> io::Result<usize> {
+    let count = u64::from(cursor.read_u32::<BigEndian>()?);
+    let remaining = cursor.get_ref().len() as u64 - cursor.position();
+    if count * entry_size > remaining {
+        return invalid("Truncated table");
+    }
+    Ok(count as usize)
+}
+
+// Returns `None` on overflow
+fn rescale(value: u64, from: u32, to: u32) -> Option<u64> {
+    let (from, to) = (u64::from(from), u64::from(to));
+    (value / from)
+        .checked_mul(to)
+        .and_then(|v| v.checked_add(value % from * to / from))
+}
+
+fn to_i64(value: u64) -> Option<i64> {
+    if value <= i64::max_value() as u64 {
+        Some(value as i64)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackInfo {
+    pub id: u32,
+    pub codec: Codec,
+    pub timescale: u32,
+    /// Samples in decoding order, with absolute offsets in the file and the first DTS being 0
+    pub samples: Vec<Sample>,
+    /// Offset from the media timeline to the presentation timeline according to the edit list,
+    /// in the track timescale
+    pub edit_offset: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Movie {
+    pub timescale: u32,
+    pub duration: u64,
+    pub tracks: Vec<TrackInfo>,
+    /// Track ids and sample entry types of tracks that were skipped
+    pub unsupported: Vec<(u32, [u8; 4])>,
+}
+
+/// Parses the payload of a moov box.
+pub fn parse_moov(data: &[u8]) -> io::Result<Movie> {
+    let children = children(data)?;
+    let (timescale, duration) = parse_mvhd(require(&children, b"mvhd")?)?;
+
+    let mut tracks = Vec::new();
+    let mut unsupported = Vec::new();
+    for &(ref fourcc, data) in &children {
+        if fourcc != b"trak" {
+            continue;
+        }
+
+        match parse_trak(data, timescale)? {
+            Ok(track) => tracks.push(track),
+            Err(skipped) => unsupported.push(skipped),
+        }
+    }
+
+    Ok(Movie {
+        timescale: timescale,
+        duration: duration,
+        tracks: tracks,
+        unsupported: unsupported,
+    })
+}
+
+fn parse_mvhd(data: &[u8]) -> io::Result<(u32, u64)> {
+    let (version, mut cursor) = full_box(data)?;
+
+    let (timescale, duration) = if version == 1 {
+        skip(&mut cursor, 16);
+        (
+            cursor.read_u32::<BigEndian>()?,
+            cursor.read_u64::<BigEndian>()?,
+        )
+    } else {
+        skip(&mut cursor, 8);
+        (
+            cursor.read_u32::<BigEndian>()?,
+            u64::from(cursor.read_u32::<BigEndian>()?),
+        )
+    };
+
+    if timescale == 0 {
+        return invalid("Invalid movie timescale");
+    }
+    if rescale(duration, timescale, NSECONDS).is_none() {
+        return invalid("Invalid movie duration");
+    }
+
+    Ok((timescale, duration))
+}
+
+// Returns the track id and sample entry type instead if the codec is not supported
+fn parse_trak(data: &[u8], movie_timescale: u32) -> io::Result<Result<TrackInfo, (u32, [u8; 4])>> {
+    let children = children(data)?;
+
+    let id = {
+        let (version, mut cursor) = full_box(require(&children, b"tkhd")?)?;
+        skip(&mut cursor, if version == 1 { 16 } else { 8 });
+        cursor.read_u32::<BigEndian>()?
+    };
+
+    let mdia = self::children(require(&children, b"mdia")?)?;
+    let timescale = {
+        let (version, mut cursor) = full_box(require(&mdia, b"mdhd")?)?;
+        skip(&mut cursor, if version == 1 { 16 } else { 8 });
+        cursor.read_u32::<BigEndian>()?
+    };
+    if timescale == 0 {
+        return invalid("Invalid track timescale");
+    }
+
+    let minf = self::children(require(&mdia, b"minf")?)?;
+    let stbl = self::children(require(&minf, b"stbl")?)?;
+
+    let (entry_type, codec) = parse_stsd(require(&stbl, b"stsd")?)?;
+    let codec = match codec {
+        Some(codec) => codec,
+        None => return Ok(Err((id, entry_type))),
+    };
+
+    let edit_offset = match find(&children, b"edts") {
+        Some(edts) => match find(&self::children(edts)?, b"elst") {
+            Some(elst) => parse_elst(elst, movie_timescale, timescale)?,
+            None => 0,
+        },
+        None => 0,
+    };
+
+    let samples = parse_samples(&stbl)?;
+
+    // The presentation timestamps of all samples have to stay representable in nanoseconds
+    let first = samples.iter().map(|s| cmp::min(s.pts, s.dts)).min().unwrap_or(0);
+    let end = samples
+        .iter()
+        .map(|s| cmp::max(s.pts, s.dts) + i64::from(s.duration))
+        .max()
+        .unwrap_or(0);
+    let in_range = |pts: i64| match pts.checked_add(edit_offset) {
+        Some(pts) if pts < 0 => true,
+        Some(pts) => rescale(pts as u64, timescale, NSECONDS).and_then(to_i64).is_some(),
+        None => false,
+    };
+    if !in_range(first) || !in_range(end) {
+        return invalid("Timestamps out of range");
+    }
+
+    Ok(Ok(TrackInfo {
+        id: id,
+        codec: codec,
+        timescale: timescale,
+        samples: samples,
+        edit_offset: edit_offset,
+    }))
+}
+
+// Empty edits delay the presentation, the first non-empty edit selects the first presented
+// media time. Further edits are not supported and the remaining media is presented as is.
+fn parse_elst(data: &[u8], movie_timescale: u32, timescale: u32) -> io::Result<i64> {
+    let (version, mut cursor) = full_box(data)?;
+    let count = entry_count(&mut cursor, if version == 1 { 20 } else { 12 })?;
+
+    let mut offset = 0;
+    for _ in 0..count {
+        let (duration, media_time) = if version == 1 {
+            (
+                cursor.read_u64::<BigEndian>()?,
+                cursor.read_i64::<BigEndian>()?,
+            )
+        } else {
+            (
+                u64::from(cursor.read_u32::<BigEndian>()?),
+                i64::from(cursor.read_i32::<BigEndian>()?),
+            )
+        };
+        skip(&mut cursor, 4);
+
+        let next = if media_time == -1 {
+            rescale(duration, movie_timescale, timescale)
+                .and_then(to_i64)
+                .and_then(|duration| offset.checked_add(duration))
+        } else {
+            offset.checked_sub(media_time)
+        };
+
+        match next {
+            None => return invalid("Invalid edit list"),
+            Some(next) if media_time == -1 => offset = next,
+            Some(next) => return Ok(next),
+        }
+    }
+
+    Ok(offset)
+}
+
+// Returns the type of the first sample entry and the codec if it is supported
+fn parse_stsd(data: &[u8]) -> io::Result<([u8; 4], Option<Codec>)> {
+    let (_, mut cursor) = full_box(data)?;
+    cursor.read_u32::<BigEndian>()?;
+    let entries = children(&data[cursor.position() as usize..])?;
+    let (entry_type, entry) = match entries.first() {
+        Some(&(entry_type, entry)) => (entry_type, entry),
+        None => return invalid("No sample entry"),
+    };
+
+    let codec = match &entry_type {
+        b"avc1" | b"avc3" | b"hvc1" | b"hev1" => {
+            if entry.len() < 78 {
+                return invalid("Truncated visual sample entry");
+            }
+            let mut cursor = Cursor::new(&entry[24..]);
+            let width = cursor.read_u16::<BigEndian>()?;
+            let height = cursor.read_u16::<BigEndian>()?;
+            let children = children(&entry[78..])?;
+
+            if &entry_type[0..3] == b"avc" {
+                find(&children, b"avcC").map(|avcc| Codec::H264 {
+                    width: width,
+                    height: height,
+                    avcc: avcc.to_vec(),
+                })
+            } else {
+                find(&children, b"hvcC").map(|hvcc| Codec::H265 {
+                    width: width,
+                    height: height,
+                    hvcc: hvcc.to_vec(),
+                    hev1: &entry_type == b"hev1",
+                })
+            }
+        }
+        b"mp4a" | b"Opus" => {
+            if entry.len() < 28 {
+                return invalid("Truncated audio sample entry");
+            }
+            let mut cursor = Cursor::new(&entry[8..]);
+            let version = cursor.read_u16::<BigEndian>()?;
+            skip(&mut cursor, 6);
+            let mut channels = cursor.read_u16::<BigEndian>()?;
+            skip(&mut cursor, 6);
+            let mut rate = cursor.read_u32::<BigEndian>()? >> 16;
+
+            // QuickTime sound sample description versions
+            let children_offset = match version {
+                0 => 28,
+                1 => 44,
+                2 => {
+                    let mut cursor = Cursor::new(&entry[28..]);
+                    skip(&mut cursor, 4);
+                    rate = cursor.read_f64::<BigEndian>()? as u32;
+                    channels = cursor.read_u32::<BigEndian>()? as u16;
+                    64
+                }
+                _ => return Ok((entry_type, None)),
+            };
+            if entry.len() < children_offset {
+                return invalid("Truncated audio sample entry");
+            }
+            let children = children(&entry[children_offset..])?;
+
+            if &entry_type == b"mp4a" {
+                match find(&children, b"esds") {
+                    Some(esds) => parse_esds(esds, channels, rate)?,
+                    None => None,
+                }
+            } else {
+                match find(&children, b"dOps") {
+                    Some(dops) => Some(Codec::Opus(parse_dops(dops)?)),
+                    None => None,
+                }
+            }
+        }
+        _ => None,
+    };
+
+    Ok((entry_type, codec))
+}
+
+fn read_descriptor(cursor: &mut Cursor<&[u8]>) -> io::Result<(u8, u64)> {
+    let tag = cursor.read_u8()?;
+    let mut len = 0;
+    for _ in 0..4 {
+        let b = cursor.read_u8()?;
+        len = (len << 7) | u64::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok((tag, len))
+}
+
+// Sampling rate from the AudioSpecificConfig, for rates that don't fit into the sample entry
+fn aac_rate(audio_specific_config: &[u8]) -> Option<u32> {
+    const RATES: [u32; 13] = [
+        96_000, 88_200, 64_000, 48_000, 44_100, 32_000, 24_000, 22_050, 16_000, 12_000, 11_025,
+        8_000, 7_350,
+    ];
+
+    if audio_specific_config.len() < 2 {
+        return None;
+    }
+    let index = ((audio_specific_config[0] & 0x07) << 1) | (audio_specific_config[1] >> 7);
+    if index == 0x0f {
+        if audio_specific_config.len() < 5 {
+            return None;
+        }
+        let rate = (u32::from(audio_specific_config[1] & 0x7f) << 17)
+            | (u32::from(audio_specific_config[2]) << 9)
+            | (u32::from(audio_specific_config[3]) << 1)
+            | (u32::from(audio_specific_config[4]) >> 7);
+        return Some(rate);
+    }
+    RATES.get(index as usize).cloned()
+}
+
+fn parse_esds(data: &[u8], channels: u16, rate: u32) -> io::Result<Option<Codec>> {
+    let (_, mut cursor) = full_box(data)?;
+
+    // ES_Descriptor
+    if read_descriptor(&mut cursor)?.0 != 0x03 {
+        return invalid("No ES descriptor");
+    }
+    skip(&mut cursor, 2);
+    let flags = cursor.read_u8()?;
+    if flags & 0x80 != 0 {
+        skip(&mut cursor, 2);
+    }
+    if flags & 0x40 != 0 {
+        let len = cursor.read_u8()?;
+        skip(&mut cursor, u64::from(len));
+    }
+    if flags & 0x20 != 0 {
+        skip(&mut cursor, 2);
+    }
+
+    // DecoderConfigDescriptor
+    if read_descriptor(&mut cursor)?.0 != 0x04 {
+        return invalid("No decoder config descriptor");
+    }
+    let object_type = cursor.read_u8()?;
+    skip(&mut cursor, 12);
+
+    // MPEG-4 and MPEG-2 AAC
+    match object_type {
+        0x40 | 0x66 | 0x67 | 0x68 => (),
+        _ => return Ok(None),
+    }
+
+    // DecoderSpecificInfo
+    let (tag, len) = read_descriptor(&mut cursor)?;
+    if tag != 0x05 {
+        return invalid("No decoder specific info");
+    }
+    let mut audio_specific_config = vec![0; len as usize];
+    cursor.read_exact(&mut audio_specific_config)?;
+
+    let rate = match rate {
+        0 => match aac_rate(&audio_specific_config) {
+            Some(rate) => rate,
+            None => return invalid("Unknown sampling rate"),
+        },
+        rate => rate,
+    };
+
+    Ok(Some(Codec::Aac {
+        channels: channels,
+        rate: rate,
+        audio_specific_config: audio_specific_config,
+    }))
+}
+
+fn parse_dops(data: &[u8]) -> io::Result<OpusHeader> {
+    let mut cursor = Cursor::new(data);
+    if cursor.read_u8()? != 0 {
+        return invalid("Unsupported dOps version");
+    }
+    let channels = cursor.read_u8()?;
+    let pre_skip = cursor.read_u16::<BigEndian>()?;
+    let input_rate = cursor.read_u32::<BigEndian>()?;
+    let output_gain = cursor.read_i16::<BigEndian>()?;
+    let channel_mapping_family = cursor.read_u8()?;
+
+    if channels == 0 {
+        return invalid("Invalid channel count");
+    }
+
+    let (stream_count, coupled_count, channel_mapping) = if channel_mapping_family == 0 {
+        (1, channels - 1, Vec::new())
+    } else {
+        let stream_count = cursor.read_u8()?;
+        let coupled_count = cursor.read_u8()?;
+        let mut channel_mapping = vec![0; channels as usize];
+        cursor.read_exact(&mut channel_mapping)?;
+        (stream_count, coupled_count, channel_mapping)
+    };
+
+    Ok(OpusHeader {
+        channels: channels,
+        pre_skip: pre_skip,
+        input_rate: input_rate,
+        output_gain: output_gain,
+        channel_mapping_family: channel_mapping_family,
+        stream_count: stream_count,
+        coupled_count: coupled_count,
+        channel_mapping: channel_mapping,
+    })
+}
+
+fn parse_samples(stbl: &[([u8; 4], &[u8])]) -> io::Result<Vec<Sample>> {
+    let stts = {
+        let (_, mut cursor) = full_box(require(stbl, b"stts")?)?;
+        let count = entry_count(&mut cursor, 8)?;
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            entries.push((
+                cursor.read_u32::<BigEndian>()?,
+                cursor.read_u32::<BigEndian>()?,
+            ));
+        }
+        entries
+    };
+
+    let ctts = match find(stbl, b"ctts") {
+        None => Vec::new(),
+        Some(data) => {
+            let (_, mut cursor) = full_box(data)?;
+            let count = entry_count(&mut cursor, 8)?;
+            let mut entries = Vec::with_capacity(count);
+            for _ in 0..count {
+                // Version 0 offsets are unsigned but are commonly written as signed values
+                entries.push((
+                    cursor.read_u32::<BigEndian>()?,
+                    cursor.read_i32::<BigEndian>()?,
+                ));
+            }
+            entries
+        }
+    };
+
+    // All samples are sync samples without stss
+    let stss = match find(stbl, b"stss") {
+        None => None,
+        Some(data) => {
+            let (_, mut cursor) = full_box(data)?;
+            let count = entry_count(&mut cursor, 4)?;
+            let mut entries = Vec::with_capacity(count);
+            for _ in 0..count {
+                entries.push(cursor.read_u32::<BigEndian>()?);
+            }
+            Some(entries)
+        }
+    };
+
+    let (sample_size, sample_count, sizes) = {
+        let (_, mut cursor) = full_box(require(stbl, b"stsz")?)?;
+        let sample_size = cursor.read_u32::<BigEndian>()?;
+        if sample_size == 0 {
+            let count = entry_count(&mut cursor, 4)?;
+            let mut sizes = Vec::with_capacity(count);
+            for _ in 0..count {
+                sizes.push(cursor.read_u32::<BigEndian>()?);
+            }
+            (0, count, sizes)
+        } else {
+            (sample_size, cursor.read_u32::<BigEndian>()? as usize, Vec::new())
+        }
+    };
+
+    let stsc = {
+        let (_, mut cursor) = full_box(require(stbl, b"stsc")?)?;
+        let count = entry_count(&mut cursor, 12)?;
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            let first_chunk = cursor.read_u32::<BigEndian>()?;
+            let samples_per_chunk = cursor.read_u32::<BigEndian>()?;
+            skip(&mut cursor, 4);
+            entries.push((first_chunk, samples_per_chunk));
+        }
+        entries
+    };
+
+    let chunk_offsets = match (find(stbl, b"stco"), find(stbl, b"co64")) {
+        (Some(data), _) => {
+            let (_, mut cursor) = full_box(data)?;
+            let count = entry_count(&mut cursor, 4)?;
+            let mut offsets = Vec::with_capacity(count);
+            for _ in 0..count {
+                offsets.push(u64::from(cursor.read_u32::<BigEndian>()?));
+            }
+            offsets
+        }
+        (None, Some(data)) => {
+            let (_, mut cursor) = full_box(data)?;
+            let count = entry_count(&mut cursor, 8)?;
+            let mut offsets = Vec::with_capacity(count);
+            for _ in 0..count {
+                offsets.push(cursor.read_u64::<BigEndian>()?);
+            }
+            offsets
+        }
+        (None, None) => return invalid("No chunk offsets"),
+    };
+
+    // Number of samples in each chunk according to the stsc runs
+    let mut chunk_samples = Vec::with_capacity(chunk_offsets.len());
+    let mut stsc_idx = 0;
+    for chunk_idx in 0..chunk_offsets.len() {
+        let chunk = chunk_idx as u32 + 1;
+        while stsc_idx + 1 < stsc.len() && stsc[stsc_idx + 1].0 <= chunk {
+            stsc_idx += 1;
+        }
+        chunk_samples.push(match stsc.get(stsc_idx) {
+            Some(&(first_chunk, samples_per_chunk)) if first_chunk <= chunk => samples_per_chunk,
+            _ => 0,
+        });
+    }
+
+    if chunk_samples.iter().map(|&n| u64::from(n)).sum::<u64>() < sample_count as u64 {
+        return invalid("Not enough chunks");
+    }
+    if sample_size != 0 && sample_count > MAX_FIXED_SIZE_SAMPLES {
+        return invalid("Too many samples");
+    }
+    if stts.iter().map(|&(count, _)| u64::from(count)).sum::<u64>() < sample_count as u64 {
+        return invalid("Not enough sample durations");
+    }
+    if let Err(err) = sandbox::reserve(sample_count * mem::size_of::<Sample>()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string()));
+    }
+
+    let mut durations = stts
+        .iter()
+        .flat_map(|&(count, delta)| iter::repeat(delta).take(count as usize));
+    let mut composition_offsets = ctts
+        .iter()
+        .flat_map(|&(count, offset)| iter::repeat(offset).take(count as usize))
+        .chain(iter::repeat(0));
+    let mut sync_samples = stss.as_ref().map(|stss| stss.iter().peekable());
+
+    let mut samples = Vec::with_capacity(sample_count);
+    let mut dts = 0i64;
+    'chunks: for (&chunk_offset, &samples_per_chunk) in chunk_offsets.iter().zip(&chunk_samples) {
+        let mut offset = chunk_offset;
+        for _ in 0..samples_per_chunk {
+            let idx = samples.len();
+            if idx >= sample_count {
+                break 'chunks;
+            }
+
+            let size = if sample_size != 0 {
+                sample_size
+            } else {
+                sizes[idx]
+            };
+            let duration = durations.next().unwrap();
+            let composition_offset = composition_offsets.next().unwrap();
+            let sync = match sync_samples {
+                None => true,
+                Some(ref mut sync_samples) => {
+                    while sync_samples.peek().map(|&&n| n < idx as u32 + 1) == Some(true) {
+                        sync_samples.next();
+                    }
+                    sync_samples.peek() == Some(&&(idx as u32 + 1))
+                }
+            };
+
+            samples.push(Sample {
+                offset: offset,
+                size: size,
+                dts: dts,
+                pts: dts + i64::from(composition_offset),
+                duration: duration,
+                sync: sync,
+            });
+
+            offset = match offset.checked_add(u64::from(size)) {
+                Some(offset) => offset,
+                None => return invalid("Invalid chunk offset"),
+            };
+            dts += i64::from(duration);
+        }
+    }
+
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::WriteBytesExt;
+    use boxes::{write_moov, Track};
+    use gst_plugin::tags::StreamInfo;
+
+    #[test]
+    fn test_box_header() {
+        assert_eq!(parse_box_header(&[0, 0, 0, 8, b'f', b'r']).unwrap(), None);
+        assert_eq!(
+            parse_box_header(&[0, 0, 0, 16, b'f', b'r', b'e', b'e']).unwrap(),
+            Some(BoxHeader {
+                fourcc: *b"free",
+                header_size: 8,
+                size: Some(16),
+            })
+        );
+        assert_eq!(
+            parse_box_header(&[0, 0, 0, 1, b'm', b'd', b'a', b't', 0, 0, 0, 1]).unwrap(),
+            None
+        );
+        assert_eq!(
+            parse_box_header(&[0, 0, 0, 1, b'm', b'd', b'a', b't', 0, 0, 0, 1, 0, 0, 0, 0])
+                .unwrap(),
+            Some(BoxHeader {
+                fourcc: *b"mdat",
+                header_size: 16,
+                size: Some(1 << 32),
+            })
+        );
+        assert!(parse_box_header(&[0, 0, 0, 4, b'f', b'r', b'e', b'e']).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        // IPBB with the B-frames referencing the P-frame
+        let video = Track {
+            id: 1,
+            codec: Codec::H264 {
+                width: 320,
+                height: 240,
+                avcc: vec![1, 2, 3],
+            },
+            samples: [(0, 3000, true), (3000, 9000, false), (6000, 6000, false)]
+                .iter()
+                .enumerate()
+                .map(|(idx, &(dts, pts, sync))| Sample {
+                    offset: idx as u64 * 100,
+                    size: 100,
+                    dts: dts - 3000,
+                    pts: pts - 3000,
+                    duration: 3000,
+                    sync: sync,
+                })
+                .collect(),
+            info: StreamInfo::default(),
+        };
+        let audio = Track {
+            id: 2,
+            codec: Codec::Aac {
+                channels: 2,
+                rate: 48_000,
+                audio_specific_config: vec![0x11, 0x90],
+            },
+            samples: (0..4)
+                .map(|i| Sample {
+                    offset: 300 + i * 10,
+                    size: 10,
+                    dts: 4800 + i as i64 * 1024,
+                    pts: 4800 + i as i64 * 1024,
+                    duration: 1024,
+                    sync: true,
+                })
+                .collect(),
+            info: StreamInfo::default(),
+        };
+
+        let moov = write_moov(&[video.clone(), audio.clone()], 40);
+        let header = parse_box_header(&moov).unwrap().unwrap();
+        assert_eq!(&header.fourcc, b"moov");
+        assert_eq!(header.size, Some(moov.len() as u64));
+
+        let movie = parse_moov(&moov[8..]).unwrap();
+        assert!(movie.unsupported.is_empty());
+        assert_eq!(movie.tracks.len(), 2);
+
+        let parsed_video = &movie.tracks[0];
+        assert_eq!(parsed_video.id, 1);
+        assert_eq!(parsed_video.codec, video.codec);
+        assert_eq!(parsed_video.timescale, 90_000);
+        assert_eq!(parsed_video.edit_offset, -3000);
+        for (parsed, sample) in parsed_video.samples.iter().zip(video.samples.iter()) {
+            assert_eq!(parsed.offset, sample.offset + 40);
+            assert_eq!(parsed.size, sample.size);
+            assert_eq!(parsed.pts + parsed_video.edit_offset, sample.pts);
+            assert_eq!(parsed.duration, sample.duration);
+            assert_eq!(parsed.sync, sample.sync);
+        }
+
+        // Audio starts 100ms after the video
+        let parsed_audio = &movie.tracks[1];
+        assert_eq!(parsed_audio.codec, audio.codec);
+        assert_eq!(parsed_audio.edit_offset, 4800);
+        assert_eq!(parsed_audio.samples.len(), 4);
+        assert_eq!(parsed_audio.samples[3].offset, 370);
+        assert_eq!(parsed_audio.samples[3].pts, 3 * 1024);
+    }
+
+    // Overwrites `value` at `offset` in the payload of the first box of type `fourcc`
+    fn patch(moov: &mut [u8], fourcc: &[u8; 4], offset: usize, value: u32) {
+        let pos = moov.windows(4).position(|w| w == fourcc).unwrap() + 4 + offset;
+        (&mut moov[pos..(pos + 4)])
+            .write_u32::<BigEndian>(value)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_hostile_sample_count() {
+        let track = Track {
+            id: 1,
+            codec: Codec::H264 {
+                width: 320,
+                height: 240,
+                avcc: vec![1, 2, 3],
+            },
+            samples: vec![
+                Sample {
+                    offset: 0,
+                    size: 100,
+                    dts: 0,
+                    pts: 0,
+                    duration: 3000,
+                    sync: true,
+                },
+            ],
+            info: StreamInfo::default(),
+        };
+        let mut moov = write_moov(&[track], 40);
+        assert_eq!(parse_moov(&moov[8..]).unwrap().tracks[0].samples.len(), 1);
+
+        // Fixed sample size with 2^32 - 1 samples and as many sample durations
+        patch(&mut moov, b"stsz", 4, 1);
+        patch(&mut moov, b"stsz", 8, 0xffff_ffff);
+        patch(&mut moov, b"stts", 8, 0xffff_ffff);
+        assert!(parse_moov(&moov[8..]).is_err());
+
+        // The single chunk claims to contain all of them
+        patch(&mut moov, b"stsc", 12, 0xffff_ffff);
+        assert!(parse_moov(&moov[8..]).is_err());
+    }
+
+    #[test]
+    fn test_hostile_edit_list() {
+        fn elst(entries: &[(u64, i64)]) -> Vec<u8> {
+            let mut v = vec![1, 0, 0, 0];
+            v.write_u32::<BigEndian>(entries.len() as u32).unwrap();
+            for &(duration, media_time) in entries {
+                v.write_u64::<BigEndian>(duration).unwrap();
+                v.write_i64::<BigEndian>(media_time).unwrap();
+                v.write_u32::<BigEndian>(0x0001_0000).unwrap();
+            }
+            v
+        }
+
+        assert_eq!(
+            parse_elst(&elst(&[(1000, -1), (0, 0)]), 1000, 90_000).unwrap(),
+            90_000
+        );
+        assert!(parse_elst(&elst(&[(u64::max_value(), -1)]), 1, 90_000).is_err());
+        assert!(parse_elst(&elst(&[(1 << 62, -1), (1 << 62, -1)]), 1, 1).is_err());
+        assert!(parse_elst(&elst(&[(0, i64::min_value())]), 1, 1).is_err());
+    }
+}
@@ -0,0 +1,371 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use std::cmp;
+
+pub const PACKET_SIZE: usize = 188;
+const HEADER_SIZE: usize = 4;
+const SYNC_BYTE: u8 = 0x47;
+
+pub const PAT_PID: u16 = 0x0000;
+
+pub const STREAM_TYPE_AAC_ADTS: u8 = 0x0f;
+pub const STREAM_TYPE_H264: u8 = 0x1b;
+pub const STREAM_TYPE_H265: u8 = 0x24;
+
+pub const AUDIO_TYPE_UNDEFINED: u8 = 0x00;
+pub const AUDIO_TYPE_HEARING_IMPAIRED: u8 = 0x02;
+pub const AUDIO_TYPE_VISUAL_IMPAIRED_COMMENTARY: u8 = 0x03;
+
+const DESCRIPTOR_ISO_639_LANGUAGE: u8 = 0x0a;
+
+const TABLE_ID_PAT: u8 = 0x00;
+const TABLE_ID_PMT: u8 = 0x02;
+const TRANSPORT_STREAM_ID: u16 = 1;
+
+// Timestamps are 33 bit in 90kHz units
+const TIMESTAMP_MASK: u64 = 0x1_ffff_ffff;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamInfo {
+    pub pid: u16,
+    pub stream_type: u8,
+    /// ISO 639-2 code for the ISO_639_language_descriptor
+    pub language: Option<String>,
+    pub audio_type: u8,
+}
+
+// CRC-32/MPEG-2 as used for the PSI sections
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &b in data {
+        crc ^= u32::from(b) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// adaptation_field_control values
+const PAYLOAD_ONLY: u8 = 0x10;
+const ADAPTATION_ONLY: u8 = 0x20;
+const ADAPTATION_AND_PAYLOAD: u8 = 0x30;
+
+fn write_packet_header(out: &mut Vec<u8>, pid: u16, start: bool, control: u8, cc: u8) {
+    out.push(SYNC_BYTE);
+    out.push(if start { 0x40 } else { 0x00 } | ((pid >> 8) as u8 & 0x1f));
+    out.push(pid as u8);
+    out.push(control | (cc & 0x0f));
+}
+
+// Writes a complete section into a single packet, padded with 0xff
+fn write_section(
+    out: &mut Vec<u8>,
+    pid: u16,
+    cc: &mut u8,
+    table_id: u8,
+    id: u16,
+    version: u8,
+    body: &[u8],
+) {
+    let mut section = Vec::with_capacity(12 + body.len());
+    section.push(table_id);
+    // section_syntax_indicator, reserved bits and the length including the CRC
+    section
+        .write_u16::<BigEndian>(0xb000 | (5 + body.len() as u16 + 4))
+        .unwrap();
+    section.write_u16::<BigEndian>(id).unwrap();
+    section.push(0xc1 | ((version & 0x1f) << 1));
+    // section_number and last_section_number
+    section.push(0);
+    section.push(0);
+    section.extend_from_slice(body);
+    let crc = crc32(&section);
+    section.write_u32::<BigEndian>(crc).unwrap();
+
+    assert!(section.len() < PACKET_SIZE - HEADER_SIZE);
+
+    let start = out.len();
+    write_packet_header(out, pid, true, PAYLOAD_ONLY, *cc);
+    *cc = (*cc + 1) & 0x0f;
+    // pointer_field
+    out.push(0);
+    out.extend_from_slice(&section);
+    out.resize(start + PACKET_SIZE, 0xff);
+}
+
+pub fn write_pat(out: &mut Vec<u8>, cc: &mut u8, program_number: u16, pmt_pid: u16) {
+    let mut body = Vec::with_capacity(4);
+    body.write_u16::<BigEndian>(program_number).unwrap();
+    body.write_u16::<BigEndian>(0xe000 | pmt_pid).unwrap();
+    write_section(
+        out,
+        PAT_PID,
+        cc,
+        TABLE_ID_PAT,
+        TRANSPORT_STREAM_ID,
+        0,
+        &body,
+    );
+}
+
+pub fn write_pmt(
+    out: &mut Vec<u8>,
+    cc: &mut u8,
+    program_number: u16,
+    pmt_pid: u16,
+    version: u8,
+    pcr_pid: u16,
+    streams: &[StreamInfo],
+) {
+    let mut body = Vec::with_capacity(4 + 5 * streams.len());
+    body.write_u16::<BigEndian>(0xe000 | pcr_pid).unwrap();
+    // No program descriptors
+    body.write_u16::<BigEndian>(0xf000).unwrap();
+    for stream in streams {
+        body.push(stream.stream_type);
+        body.write_u16::<BigEndian>(0xe000 | stream.pid).unwrap();
+
+        if stream.language.is_some() || stream.audio_type != AUDIO_TYPE_UNDEFINED {
+            let language = stream.language.as_ref().map(|l| l.as_str()).unwrap_or("und");
+            body.write_u16::<BigEndian>(0xf000 | 6).unwrap();
+            body.push(DESCRIPTOR_ISO_639_LANGUAGE);
+            body.push(4);
+            body.extend_from_slice(&language.as_bytes()[..3]);
+            body.push(stream.audio_type);
+        } else {
+            body.write_u16::<BigEndian>(0xf000).unwrap();
+        }
+    }
+    write_section(
+        out,
+        pmt_pid,
+        cc,
+        TABLE_ID_PMT,
+        program_number,
+        version,
+        &body,
+    );
+}
+
+fn write_pcr(out: &mut Vec<u8>, pcr: u64) {
+    let base = (pcr / 300) & TIMESTAMP_MASK;
+    let ext = pcr % 300;
+    out.write_u32::<BigEndian>((base >> 1) as u32).unwrap();
+    out.push((((base & 1) << 7) as u8) | 0x7e | ((ext >> 8) as u8));
+    out.push(ext as u8);
+}
+
+// Writes a packet that only carries the PCR (in 27MHz units) in its adaptation field.
+// The continuity counter is not incremented for packets without payload.
+pub fn write_pcr_packet(out: &mut Vec<u8>, pid: u16, cc: u8, pcr: u64) {
+    let start = out.len();
+    write_packet_header(out, pid, false, ADAPTATION_ONLY, cc);
+    out.push((PACKET_SIZE - HEADER_SIZE - 1) as u8);
+    // PCR_flag
+    out.push(0x10);
+    write_pcr(out, pcr);
+    out.resize(start + PACKET_SIZE, 0xff);
+}
+
+fn write_timestamp(out: &mut Vec<u8>, prefix: u8, ts: u64) {
+    let ts = ts & TIMESTAMP_MASK;
+    out.push((prefix << 4) | (((ts >> 30) as u8 & 0x07) << 1) | 1);
+    out.write_u16::<BigEndian>((((ts >> 15) & 0x7fff) << 1) as u16 | 1)
+        .unwrap();
+    out.write_u16::<BigEndian>(((ts & 0x7fff) << 1) as u16 | 1)
+        .unwrap();
+}
+
+// Packetizes one access unit into a PES packet spread over as many TS packets as needed.
+// Timestamps are in 90kHz units.
+pub fn write_pes(
+    out: &mut Vec<u8>,
+    pid: u16,
+    cc: &mut u8,
+    stream_id: u8,
+    pts: u64,
+    dts: Option<u64>,
+    random_access: bool,
+    data: &[u8],
+) {
+    let mut header = Vec::with_capacity(19);
+    header.extend_from_slice(&[0x00, 0x00, 0x01, stream_id]);
+    let header_data_length = if dts.is_some() { 10 } else { 5 };
+    let pes_length = 3 + header_data_length + data.len();
+    // Video streams can use 0 for unbounded PES packets
+    header
+        .write_u16::<BigEndian>(if pes_length > 0xffff {
+            0
+        } else {
+            pes_length as u16
+        })
+        .unwrap();
+    // Marker bits and data_alignment_indicator
+    header.push(0x84);
+    header.push(if dts.is_some() { 0xc0 } else { 0x80 });
+    header.push(header_data_length as u8);
+    match dts {
+        Some(dts) => {
+            write_timestamp(&mut header, 0x3, pts);
+            write_timestamp(&mut header, 0x1, dts);
+        }
+        None => write_timestamp(&mut header, 0x2, pts),
+    }
+
+    let mut header = &header[..];
+    let mut data = data;
+    let mut first = true;
+    while !header.is_empty() || !data.is_empty() {
+        let start = out.len();
+        let remaining = header.len() + data.len();
+
+        // The first packet signals random access points, the last one is padded with
+        // stuffing bytes in the adaptation field
+        let adaptation_flags = if first && random_access { 0x40 } else { 0x00 };
+        let min_adaptation = if adaptation_flags != 0 { 2 } else { 0 };
+        let space = PACKET_SIZE - HEADER_SIZE - min_adaptation;
+        let adaptation_length = if remaining < space {
+            PACKET_SIZE - HEADER_SIZE - remaining
+        } else {
+            min_adaptation
+        };
+
+        let control = if adaptation_length > 0 {
+            ADAPTATION_AND_PAYLOAD
+        } else {
+            PAYLOAD_ONLY
+        };
+        write_packet_header(out, pid, first, control, *cc);
+        *cc = (*cc + 1) & 0x0f;
+
+        if adaptation_length > 0 {
+            // The length byte itself is not included
+            out.push((adaptation_length - 1) as u8);
+            if adaptation_length > 1 {
+                out.push(adaptation_flags);
+                let new_len = out.len() + adaptation_length - 2;
+                out.resize(new_len, 0xff);
+            }
+        }
+
+        let mut payload = PACKET_SIZE - (out.len() - start);
+        let n = cmp::min(payload, header.len());
+        out.extend_from_slice(&header[..n]);
+        header = &header[n..];
+        payload -= n;
+
+        let n = cmp::min(payload, data.len());
+        out.extend_from_slice(&data[..n]);
+        data = &data[n..];
+
+        assert_eq!(out.len() - start, PACKET_SIZE);
+        first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pat() {
+        let mut out = Vec::new();
+        let mut cc = 0;
+        write_pat(&mut out, &mut cc, 1, 0x1000);
+        assert_eq!(out.len(), PACKET_SIZE);
+        assert_eq!(cc, 1);
+        assert_eq!(
+            &out[..21],
+            &[
+                0x47, 0x40, 0x00, 0x10, 0x00, 0x00, 0xb0, 0x0d, 0x00, 0x01, 0xc1, 0x00, 0x00, 0x00,
+                0x01, 0xf0, 0x00, 0x2a, 0xb1, 0x04, 0xb2,
+            ][..]
+        );
+        assert!(out[21..].iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn test_pmt_language() {
+        let streams = [
+            StreamInfo {
+                pid: 0x100,
+                stream_type: STREAM_TYPE_H264,
+                language: None,
+                audio_type: AUDIO_TYPE_UNDEFINED,
+            },
+            StreamInfo {
+                pid: 0x101,
+                stream_type: STREAM_TYPE_AAC_ADTS,
+                language: Some(String::from("ger")),
+                audio_type: AUDIO_TYPE_VISUAL_IMPAIRED_COMMENTARY,
+            },
+        ];
+
+        let mut out = Vec::new();
+        let mut cc = 0;
+        write_pmt(&mut out, &mut cc, 1, 0x1000, 0, 0x100, &streams);
+        assert_eq!(out.len(), PACKET_SIZE);
+
+        // Header, pointer field, section header, PCR PID and program info length
+        let es = &out[17..];
+        assert_eq!(&es[..5], &[0x1b, 0xe1, 0x00, 0xf0, 0x00][..]);
+        assert_eq!(
+            &es[5..16],
+            &[0x0f, 0xe1, 0x01, 0xf0, 0x06, 0x0a, 0x04, b'g', b'e', b'r', 0x03][..]
+        );
+    }
+
+    #[test]
+    fn test_pes() {
+        for &len in &[1usize, 100, 168, 169, 170, 183, 184, 1000, 70_000] {
+            for &random_access in &[false, true] {
+                let data = vec![0xaa; len];
+                let mut out = Vec::new();
+                let mut cc = 15;
+                write_pes(
+                    &mut out,
+                    0x100,
+                    &mut cc,
+                    0xe0,
+                    3600,
+                    Some(0),
+                    random_access,
+                    &data,
+                );
+                assert_eq!(out.len() % PACKET_SIZE, 0);
+
+                // Extract the payloads again
+                let mut payload = Vec::new();
+                for (i, packet) in out.chunks(PACKET_SIZE).enumerate() {
+                    assert_eq!(packet[0], SYNC_BYTE);
+                    assert_eq!(packet[1] & 0x40 != 0, i == 0);
+                    assert_eq!(packet[3] & 0x0f, ((i + 15) & 0x0f) as u8);
+                    let mut offset = HEADER_SIZE;
+                    if packet[3] & 0x20 != 0 {
+                        if i == 0 && random_access {
+                            assert_eq!(packet[5], 0x40);
+                        }
+                        offset += 1 + packet[4] as usize;
+                    }
+                    payload.extend_from_slice(&packet[offset..]);
+                }
+                assert_eq!(&payload[0..4], &[0x00, 0x00, 0x01, 0xe0]);
+                assert_eq!(payload[8], 10);
+                assert_eq!(&payload[19..], &data[..]);
+            }
+        }
+    }
+}
@@ -0,0 +1,753 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::docs::*;
+use gst_plugin::tags::{self, TrackRole};
+
+use std::cmp;
+use std::i32;
+use std::sync::Mutex;
+use std::u32;
+
+use ts::{self, StreamInfo};
+
+const PROGRAM_NUMBER: u16 = 1;
+const PMT_PID: u16 = 0x1000;
+const FIRST_STREAM_PID: u16 = 0x100;
+
+// PTS and DTS are ahead of the PCR by this much to give decoders time for buffering
+const PTS_DELAY: u64 = 700 * gst::MSECOND_VAL;
+// PAT and PMT are repeated at least this often, and in front of every video keyframe
+const SI_INTERVAL: u64 = 100 * gst::MSECOND_VAL;
+
+const DEFAULT_PCR_INTERVAL: u32 = 40;
+const DEFAULT_ALIGNMENT: u32 = 7;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    pcr_interval: u32,
+    alignment: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            pcr_interval: DEFAULT_PCR_INTERVAL,
+            alignment: DEFAULT_ALIGNMENT,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 2] = [
+    Property::UInt(
+        "pcr-interval",
+        "PCR Interval",
+        "Interval between PCRs in milliseconds",
+        (1, 100),
+        DEFAULT_PCR_INTERVAL,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "alignment",
+        "Alignment",
+        "Number of TS packets per output buffer, e.g. 7 for UDP (0 = one buffer per input buffer)",
+        (0, u32::MAX),
+        DEFAULT_ALIGNMENT,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+static PROPERTY_DOCS: [PropertyDocs; 2] = [
+    PropertyDocs {
+        name: "pcr-interval",
+        description: "The PCR is sent on the first video stream, or the first stream if there is \
+                      no video. MPEG-TS requires at least one PCR every 100ms.",
+        values: &[],
+    },
+    PropertyDocs {
+        name: "alignment",
+        description: "Output buffers contain at most this many 188 byte TS packets.",
+        values: &[
+            ("0", "all packets for one input buffer are pushed as a single buffer"),
+            ("7", "1316 bytes per buffer, which fits into a single UDP or SRT packet"),
+        ],
+    },
+];
+
+struct Stream {
+    sinkpad: gst::Pad,
+    pid: u16,
+    stream_id: u8,
+    is_video: bool,
+    stream_type: Option<u8>,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    // Language and role from the tag events
+    info: tags::StreamInfo,
+    cc: u8,
+    eos: bool,
+}
+
+impl Stream {
+    fn audio_type(&self) -> u8 {
+        match self.info.role {
+            Some(TrackRole::Caption) => ts::AUDIO_TYPE_HEARING_IMPAIRED,
+            Some(TrackRole::Description) => ts::AUDIO_TYPE_VISUAL_IMPAIRED_COMMENTARY,
+            _ => ts::AUDIO_TYPE_UNDEFINED,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.stream_type = None;
+        self.segment = gst::FormattedSegment::new();
+        self.info = tags::StreamInfo::default();
+        self.cc = 0;
+        self.eos = false;
+    }
+}
+
+struct State {
+    streams: Vec<Stream>,
+    pad_count: u32,
+    started: bool,
+    pat_cc: u8,
+    pmt_cc: u8,
+    pmt_version: u8,
+    pmt_streams: Vec<StreamInfo>,
+    pcr_pid: u16,
+    // Running times in nanoseconds
+    last_si: Option<u64>,
+    last_pcr: Option<u64>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            streams: Vec::new(),
+            pad_count: 0,
+            started: false,
+            pat_cc: 0,
+            pmt_cc: 0,
+            pmt_version: 0,
+            pmt_streams: Vec::new(),
+            pcr_pid: 0,
+            last_si: None,
+            last_pcr: None,
+        }
+    }
+}
+
+impl State {
+    fn reset(&mut self) {
+        self.started = false;
+        self.pat_cc = 0;
+        self.pmt_cc = 0;
+        self.pmt_version = 0;
+        self.pmt_streams.clear();
+        self.pcr_pid = 0;
+        self.last_si = None;
+        self.last_pcr = None;
+        for stream in &mut self.streams {
+            stream.reset();
+        }
+    }
+
+    // Updates the program with all streams that have caps by now and their languages and
+    // roles. Returns true if the PMT changed and has to be sent again.
+    fn update_program(&mut self) -> bool {
+        let streams = self.streams
+            .iter()
+            .filter_map(|s| {
+                s.stream_type.map(|stream_type| StreamInfo {
+                    pid: s.pid,
+                    stream_type: stream_type,
+                    language: s.info.language_iso_639_2b(),
+                    audio_type: s.audio_type(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if streams == self.pmt_streams {
+            return false;
+        }
+
+        // The PCR is carried on the first video stream if there is any
+        self.pcr_pid = self.streams
+            .iter()
+            .filter(|s| s.stream_type.is_some())
+            .find(|s| s.is_video)
+            .map(|s| s.pid)
+            .unwrap_or(streams[0].pid);
+        if !self.pmt_streams.is_empty() {
+            self.pmt_version = (self.pmt_version + 1) & 0x1f;
+        }
+        self.pmt_streams = streams;
+
+        true
+    }
+}
+
+struct TsMux {
+    cat: gst::DebugCategory,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    // Serializes pushing downstream so that the output is in the same order as the
+    // continuity counters, without keeping the state locked while pushing
+    output_lock: Mutex<()>,
+}
+
+fn stream_type_from_caps(caps: &gst::CapsRef) -> Option<u8> {
+    let s = caps.get_structure(0)?;
+
+    match s.get_name() {
+        "video/x-h264" => Some(ts::STREAM_TYPE_H264),
+        "video/x-h265" => Some(ts::STREAM_TYPE_H265),
+        "audio/mpeg" => Some(ts::STREAM_TYPE_AAC_ADTS),
+        _ => None,
+    }
+}
+
+fn to_90khz(time: u64) -> u64 {
+    (time + PTS_DELAY) * 9 / 100_000
+}
+
+impl TsMux {
+    fn new(_element: &Element, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rstsmux",
+                gst::DebugColorFlags::empty(),
+                "Rust MPEG-TS muxer",
+            ),
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+            output_lock: Mutex::new(()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "MPEG-TS muxer",
+            "Codec/Muxer",
+            "Muxes H.264, H.265 and AAC streams into MPEG-TS with low latency",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+        klass.set_documentation(&ElementDocs {
+            long_description: Some(
+                "Every input buffer is muxed and pushed downstream right away without waiting \
+                 for the other streams, which keeps the latency minimal for contribution over \
+                 UDP or SRT. PAT and PMT are repeated in front of every video keyframe and at \
+                 least every 100ms. Streams can be added while muxing, which results in a new \
+                 PMT version. The language and track role of the streams from their tags are \
+                 signalled with ISO 639 language descriptors.",
+            ),
+            examples: &[
+                "videotestsrc is-live=true ! x264enc tune=zerolatency ! rstsmux name=mux ! \
+                 udpsink host=127.0.0.1 port=5000 audiotestsrc is-live=true ! avenc_aac ! mux.",
+            ],
+            doc_uri: None,
+            properties: &PROPERTY_DOCS,
+        });
+
+        let caps = gst::Caps::new_simple(
+            "video/mpegts",
+            &[("systemstream", &true), ("packetsize", &188i32)],
+        );
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let mut caps = gst::Caps::new_simple(
+            "video/x-h264",
+            &[("stream-format", &"byte-stream"), ("alignment", &"au")],
+        );
+        caps.get_mut().unwrap().append(gst::Caps::new_simple(
+            "video/x-h265",
+            &[("stream-format", &"byte-stream"), ("alignment", &"au")],
+        ));
+        let sink_pad_template = gst::PadTemplate::new(
+            "video_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple(
+            "audio/mpeg",
+            &[
+                ("mpegversion", &gst::List::new(&[&2i32, &4i32])),
+                ("stream-format", &"adts"),
+                ("channels", &gst::IntRange::<i32>::new(1, 8)),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            ],
+        );
+        let sink_pad_template = gst::PadTemplate::new(
+            "audio_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+        srcpad.set_event_function(|pad, parent, event| {
+            TsMux::catch_panic_pad_function(
+                parent,
+                || false,
+                |tsmux, element| tsmux.src_event(pad, element, event),
+            )
+        });
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let tsmux = element.get_impl().downcast_ref::<TsMux>().unwrap();
+        element.catch_panic(fallback, |element| f(tsmux, element))
+    }
+
+    fn start_output(&self, element: &Element) {
+        self.srcpad
+            .push_event(gst::Event::new_stream_start(&element.get_name()).build());
+        self.srcpad.push_event(
+            gst::Event::new_caps(&gst::Caps::new_simple(
+                "video/mpegts",
+                &[("systemstream", &true), ("packetsize", &188i32)],
+            )).build(),
+        );
+        // Output buffers are timestamped with the running time so that sinks can
+        // synchronize on them
+        let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+        self.srcpad
+            .push_event(gst::Event::new_segment(&segment).build());
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let settings = *self.settings.lock().unwrap();
+        let _output_lock = self.output_lock.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        let idx = match state.streams.iter().position(|s| s.sinkpad == *pad) {
+            None => return gst::FlowReturn::Error,
+            Some(idx) => idx,
+        };
+
+        if state.streams[idx].stream_type.is_none() {
+            gst_element_error!(
+                element,
+                gst::CoreError::Negotiation,
+                ["Buffer before caps on {}", pad.get_name()]
+            );
+            return gst::FlowReturn::NotNegotiated;
+        }
+
+        let pts = match state.streams[idx]
+            .segment
+            .to_running_time(buffer.get_pts())
+            .0
+        {
+            None => {
+                gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(pts) => pts,
+        };
+
+        // The DTS can be before the segment start and thus have a negative running time,
+        // so compute it relative to the PTS instead
+        let dts = match (buffer.get_pts().0, buffer.get_dts().0) {
+            (Some(buffer_pts), Some(buffer_dts)) if buffer_dts < buffer_pts => {
+                Some(cmp::max(pts as i64 - (buffer_pts - buffer_dts) as i64, 0) as u64)
+            }
+            _ => None,
+        };
+        let now = dts.unwrap_or(pts);
+
+        if !state.started {
+            self.start_output(element);
+            state.started = true;
+        }
+
+        let program_changed = state.update_program();
+        let has_video = state.pmt_streams.iter().any(|s| {
+            s.stream_type == ts::STREAM_TYPE_H264 || s.stream_type == ts::STREAM_TYPE_H265
+        });
+        let random_access = !buffer.get_flags().contains(gst::BufferFlags::DELTA_UNIT)
+            && (state.streams[idx].is_video || !has_video);
+
+        let mut out = Vec::new();
+
+        let si_due = program_changed || (random_access && state.streams[idx].is_video)
+            || state.last_si.map(|last| now >= last + SI_INTERVAL).unwrap_or(true);
+        if si_due {
+            if program_changed {
+                gst_debug!(
+                    self.cat,
+                    obj: element,
+                    "Program changed: {:?}, PCR PID {}",
+                    state.pmt_streams,
+                    state.pcr_pid
+                );
+            }
+
+            let mut pat_cc = state.pat_cc;
+            ts::write_pat(&mut out, &mut pat_cc, PROGRAM_NUMBER, PMT_PID);
+            state.pat_cc = pat_cc;
+
+            let mut pmt_cc = state.pmt_cc;
+            ts::write_pmt(
+                &mut out,
+                &mut pmt_cc,
+                PROGRAM_NUMBER,
+                PMT_PID,
+                state.pmt_version,
+                state.pcr_pid,
+                &state.pmt_streams,
+            );
+            state.pmt_cc = pmt_cc;
+            state.last_si = Some(now);
+        }
+
+        let pcr_interval = u64::from(settings.pcr_interval) * gst::MSECOND_VAL;
+        let pcr_due = state
+            .last_pcr
+            .map(|last| now >= last + pcr_interval)
+            .unwrap_or(true);
+        if pcr_due {
+            let pcr_pid = state.pcr_pid;
+            let cc = state
+                .streams
+                .iter()
+                .find(|s| s.pid == pcr_pid)
+                .map(|s| s.cc)
+                .unwrap_or(0);
+            ts::write_pcr_packet(&mut out, pcr_pid, cc, now * 27 / 1000);
+            state.last_pcr = Some(now);
+        }
+
+        {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            let stream = &mut state.streams[idx];
+            ts::write_pes(
+                &mut out,
+                stream.pid,
+                &mut stream.cc,
+                stream.stream_id,
+                to_90khz(pts),
+                dts.map(to_90khz),
+                random_access,
+                map.as_slice(),
+            );
+        }
+        drop(state);
+
+        let chunk_size = if settings.alignment == 0 {
+            out.len()
+        } else {
+            settings.alignment as usize * ts::PACKET_SIZE
+        };
+
+        for (i, chunk) in out.chunks(chunk_size).enumerate() {
+            let mut outbuf = gst::Buffer::from_mut_slice(chunk.to_vec()).unwrap();
+            {
+                let outbuf = outbuf.get_mut().unwrap();
+                outbuf.set_pts(gst::ClockTime(Some(now)));
+                if i > 0 || !random_access {
+                    outbuf.set_flags(gst::BufferFlags::DELTA_UNIT);
+                }
+            }
+
+            let flow_ret = self.srcpad.push(outbuf);
+            if flow_ret != gst::FlowReturn::Ok {
+                return flow_ret;
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let stream_type = match stream_type_from_caps(e.get_caps()) {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Negotiation,
+                            ["Unsupported caps {:?}", e.get_caps()]
+                        );
+                        return false;
+                    }
+                    Some(stream_type) => stream_type,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    gst_debug!(self.cat, obj: pad, "Configured stream type {}", stream_type);
+                    stream.stream_type = Some(stream_type);
+                }
+                true
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    stream.segment = segment;
+                }
+                true
+            }
+            EventView::Tag(e) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    // Changes are announced with a new PMT version with the next buffer
+                    stream.info.update(e.get_tag());
+                    gst_debug!(self.cat, obj: pad, "Stream info {:?}", stream.info);
+                }
+                drop(state);
+
+                self.srcpad.push_event(event)
+            }
+            // The output has its own stream-start and segment
+            EventView::StreamStart(..) => true,
+            EventView::Eos(..) => {
+                // Only forward EOS once all streams are finished
+                let all_eos = {
+                    let mut state = self.state.lock().unwrap();
+                    if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                        stream.eos = true;
+                    }
+                    state.streams.iter().all(|s| s.eos)
+                };
+
+                if all_eos {
+                    gst_debug!(self.cat, obj: element, "All streams are EOS");
+                    self.srcpad.push_event(event);
+                }
+                true
+            }
+            _ => self.srcpad.push_event(event),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Seek(..) => {
+                gst_debug!(self.cat, obj: pad, "Seeking is not supported");
+                false
+            }
+            _ => pad.event_default(Some(&element.clone().upcast()), event),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for TsMux {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("pcr-interval", ..) => {
+                settings.pcr_interval = value.get().unwrap();
+            }
+            Property::UInt("alignment", ..) => {
+                settings.alignment = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("pcr-interval", ..) => Ok(settings.pcr_interval.to_value()),
+            Property::UInt("alignment", ..) => Ok(settings.alignment.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for TsMux {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        if transition == gst::StateChange::PausedToReady {
+            self.state.lock().unwrap().reset();
+        }
+
+        ret
+    }
+
+    fn request_new_pad(
+        &self,
+        element: &Element,
+        templ: &gst::PadTemplate,
+        _name: Option<String>,
+        _caps: Option<&gst::CapsRef>,
+    ) -> Option<gst::Pad> {
+        let mut state = self.state.lock().unwrap();
+
+        let id = state.pad_count;
+        if id >= 0x20 {
+            gst_error!(self.cat, obj: element, "Too many streams");
+            return None;
+        }
+        state.pad_count += 1;
+
+        let is_video = *templ == element.get_pad_template("video_%u").unwrap();
+        let name = if is_video {
+            format!("video_{}", id)
+        } else {
+            format!("audio_{}", id)
+        };
+        let sinkpad = gst::Pad::new_from_template(templ, name.as_str());
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            TsMux::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |tsmux, element| tsmux.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            TsMux::catch_panic_pad_function(
+                parent,
+                || false,
+                |tsmux, element| tsmux.sink_event(pad, element, event),
+            )
+        });
+
+        sinkpad.set_active(true).unwrap();
+        element.add_pad(&sinkpad).unwrap();
+
+        // Streams that are added while muxing are announced with a new PMT version
+        state.streams.push(Stream {
+            sinkpad: sinkpad.clone(),
+            pid: FIRST_STREAM_PID + id as u16,
+            stream_id: if is_video {
+                0xe0 + (id as u8 & 0x0f)
+            } else {
+                0xc0 + id as u8
+            },
+            is_video: is_video,
+            stream_type: None,
+            segment: gst::FormattedSegment::new(),
+            info: tags::StreamInfo::default(),
+            cc: 0,
+            eos: false,
+        });
+
+        Some(sinkpad)
+    }
+
+    fn release_pad(&self, element: &Element, pad: &gst::Pad) {
+        let mut state = self.state.lock().unwrap();
+
+        let pos = match state.streams.iter().position(|s| s.sinkpad == *pad) {
+            None => return,
+            Some(pos) => pos,
+        };
+        let stream = state.streams.remove(pos);
+        drop(state);
+
+        stream.sinkpad.set_active(false).unwrap();
+        element.remove_pad(&stream.sinkpad).unwrap();
+    }
+}
+
+struct TsMuxStatic;
+
+impl ImplTypeStatic<Element> for TsMuxStatic {
+    fn get_name(&self) -> &str {
+        "TsMux"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        TsMux::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        TsMux::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let tsmux_static = TsMuxStatic;
+    register_type(tsmux_static)
+}
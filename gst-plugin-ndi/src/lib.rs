@@ -0,0 +1,52 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate glib;
+#[macro_use]
+extern crate gst_plugin;
+#[macro_use]
+extern crate gstreamer as gst;
+extern crate gstreamer_video as gst_video;
+#[macro_use]
+extern crate lazy_static;
+
+use gst_plugin::registration::*;
+
+mod ndisys;
+mod receiver;
+
+mod ndisink;
+mod ndisrc;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    // Fails if the CPU is not supported by the SDK
+    if !unsafe { ndisys::NDIlib_initialize() } {
+        gst_error!(
+            gst::DebugCategory::new("rsndi", gst::DebugColorFlags::empty(), "NDI plugin"),
+            "Failed to initialize the NDI SDK"
+        );
+        return false;
+    }
+
+    ElementRegistration::new(plugin)
+        .element("ndisink", RANK_NONE, ndisink::get_type())
+        .element("ndivideosrc", RANK_NONE, ndisrc::get_video_type())
+        .element("ndiaudiosrc", RANK_NONE, ndisrc::get_audio_type())
+        .register()
+}
+
+plugin_define!(
+    "rsndi",
+    "Rust NDI Plugin",
+    plugin_init,
+    "MIT/X11",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2018-01-22"
+);
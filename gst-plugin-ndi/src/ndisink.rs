@@ -0,0 +1,357 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_sink::*;
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::i32;
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, Mutex, Weak};
+
+use ndisys::*;
+
+const DEFAULT_NDI_NAME: &str = "GStreamer NDI Sink";
+
+static PROPERTIES: [Property; 1] = [Property::String(
+    "ndi-name",
+    "NDI Name",
+    "Name of the NDI sender, a video and an audio sink with the same name form one sender",
+    Some(DEFAULT_NDI_NAME),
+    PropertyMutability::ReadWrite,
+)];
+
+struct SendInstance(NDIlib_send_instance_t);
+
+// The SDK allows using a sender from any thread
+unsafe impl Send for SendInstance {}
+
+impl Drop for SendInstance {
+    fn drop(&mut self) {
+        unsafe { NDIlib_send_destroy(self.0) }
+    }
+}
+
+lazy_static! {
+    // Senders by NDI name, shared by the video and the audio sink
+    static ref SENDERS: Mutex<HashMap<String, Weak<Mutex<SendInstance>>>> =
+        Mutex::new(HashMap::new());
+}
+
+fn get_sender(name: &str) -> Result<Arc<Mutex<SendInstance>>, String> {
+    let mut senders = SENDERS.lock().unwrap();
+
+    if let Some(sender) = senders.get(name).and_then(|sender| sender.upgrade()) {
+        return Ok(sender);
+    }
+
+    let c_name = CString::new(name).map_err(|_| format!("Invalid NDI name '{}'", name))?;
+    let create = NDIlib_send_create_t {
+        p_ndi_name: c_name.as_ptr(),
+        p_groups: ptr::null(),
+        // Synchronization against the clock is done by the base class
+        clock_video: false,
+        clock_audio: false,
+    };
+
+    let instance = unsafe { NDIlib_send_create(&create) };
+    if instance.is_null() {
+        return Err(format!("Failed to create NDI sender '{}'", name));
+    }
+
+    let sender = Arc::new(Mutex::new(SendInstance(instance)));
+    senders.insert(String::from(name), Arc::downgrade(&sender));
+
+    Ok(sender)
+}
+
+fn get_fourcc(format: gst_video::VideoFormat) -> Option<NDIlib_FourCC_type_e> {
+    match format {
+        gst_video::VideoFormat::Uyvy => Some(NDIlib_FourCC_type_UYVY),
+        gst_video::VideoFormat::Bgra => Some(NDIlib_FourCC_type_BGRA),
+        gst_video::VideoFormat::Bgrx => Some(NDIlib_FourCC_type_BGRX),
+        gst_video::VideoFormat::Rgba => Some(NDIlib_FourCC_type_RGBA),
+        gst_video::VideoFormat::Rgbx => Some(NDIlib_FourCC_type_RGBX),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Format {
+    Video(gst_video::VideoInfo),
+    Audio { rate: i32, channels: i32 },
+}
+
+struct State {
+    sender: Arc<Mutex<SendInstance>>,
+    format: Option<Format>,
+}
+
+struct NdiSink {
+    cat: gst::DebugCategory,
+    ndi_name: Mutex<String>,
+    state: Mutex<Option<State>>,
+}
+
+impl NdiSink {
+    fn new(_sink: &BaseSink) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "ndisink",
+                gst::DebugColorFlags::empty(),
+                "NDI sink",
+            ),
+            ndi_name: Mutex::new(String::from(DEFAULT_NDI_NAME)),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseSinkClass) {
+        klass.set_metadata(
+            "NDI Sink",
+            "Sink/Audio/Video/Network",
+            "Sends raw video or audio as NDI sender",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let mut caps = gst::Caps::new_simple(
+            "video/x-raw",
+            &[
+                (
+                    "format",
+                    &gst::List::new(&[
+                        &gst_video::VideoFormat::Uyvy.to_string(),
+                        &gst_video::VideoFormat::Bgra.to_string(),
+                        &gst_video::VideoFormat::Bgrx.to_string(),
+                        &gst_video::VideoFormat::Rgba.to_string(),
+                        &gst_video::VideoFormat::Rgbx.to_string(),
+                    ]),
+                ),
+                ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                (
+                    "framerate",
+                    &gst::FractionRange::new(
+                        gst::Fraction::new(1, i32::MAX),
+                        gst::Fraction::new(i32::MAX, 1),
+                    ),
+                ),
+            ],
+        );
+        caps.get_mut().unwrap().append(gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &"F32LE"),
+                ("layout", &"interleaved"),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            ],
+        ));
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &BaseSink) -> Box<BaseSinkImpl<BaseSink>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseSink> for NdiSink {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("ndi-name", ..) => {
+                *self.ndi_name.lock().unwrap() =
+                    value.get().unwrap_or_else(|| String::from(DEFAULT_NDI_NAME));
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("ndi-name", ..) => Ok(self.ndi_name.lock().unwrap().to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseSink> for NdiSink {}
+
+impl BaseSinkImpl<BaseSink> for NdiSink {
+    fn start(&self, element: &BaseSink) -> bool {
+        let name = self.ndi_name.lock().unwrap().clone();
+
+        let sender = match get_sender(&name) {
+            Ok(sender) => sender,
+            Err(err) => {
+                gst_element_error!(element, gst::ResourceError::OpenWrite, ["{}", err]);
+                return false;
+            }
+        };
+
+        gst_debug!(self.cat, obj: element, "Sending as '{}'", name);
+
+        *self.state.lock().unwrap() = Some(State {
+            sender: sender,
+            format: None,
+        });
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseSink) -> bool {
+        *self.state.lock().unwrap() = None;
+
+        true
+    }
+
+    fn set_caps(&self, element: &BaseSink, caps: &gst::CapsRef) -> bool {
+        let s = match caps.get_structure(0) {
+            None => return false,
+            Some(s) => s,
+        };
+
+        let format = if s.get_name() == "audio/x-raw" {
+            match (s.get::<i32>("rate"), s.get::<i32>("channels")) {
+                (Some(rate), Some(channels)) => Format::Audio {
+                    rate: rate,
+                    channels: channels,
+                },
+                _ => return false,
+            }
+        } else {
+            match gst_video::VideoInfo::from_caps(caps) {
+                None => return false,
+                Some(info) => Format::Video(info),
+            }
+        };
+
+        gst_debug!(self.cat, obj: element, "Configured for {:?}", format);
+
+        match *self.state.lock().unwrap() {
+            None => false,
+            Some(ref mut state) => {
+                state.format = Some(format);
+                true
+            }
+        }
+    }
+
+    fn render(&self, element: &BaseSink, buffer: &gst::BufferRef) -> gst::FlowReturn {
+        let state = self.state.lock().unwrap();
+        let (sender, format) = match *state {
+            Some(State {
+                ref sender,
+                format: Some(ref format),
+            }) => (sender, format),
+            Some(_) => {
+                gst_element_error!(element, gst::CoreError::Negotiation, ["No caps set"]);
+                return gst::FlowReturn::NotNegotiated;
+            }
+            None => return gst::FlowReturn::Flushing,
+        };
+
+        let map = match buffer.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map,
+        };
+        let data = map.as_slice();
+
+        gst_trace!(self.cat, obj: element, "Sending {:?}", buffer);
+
+        let sender = sender.lock().unwrap();
+        match *format {
+            Format::Video(ref info) => {
+                if data.len() < info.size() {
+                    gst_element_error!(element, gst::StreamError::Format, ["Buffer too small"]);
+                    return gst::FlowReturn::Error;
+                }
+
+                let (fps, par) = (info.fps(), info.par());
+                let aspect_ratio = (info.width() as f32 * *par.numer() as f32)
+                    / (info.height() as f32 * *par.denom() as f32);
+                let frame = NDIlib_video_frame_v2_t {
+                    xres: info.width() as i32,
+                    yres: info.height() as i32,
+                    FourCC: get_fourcc(info.format()).unwrap(),
+                    frame_rate_N: *fps.numer(),
+                    frame_rate_D: *fps.denom(),
+                    picture_aspect_ratio: aspect_ratio,
+                    frame_format_type: NDIlib_frame_format_type_progressive,
+                    timecode: NDIlib_send_timecode_synthesize,
+                    p_data: data.as_ptr() as *mut u8,
+                    line_stride_in_bytes: info.stride()[0],
+                    p_metadata: ptr::null(),
+                    timestamp: 0,
+                };
+
+                // Copies the frame before returning
+                unsafe { NDIlib_send_send_video_v2(sender.0, &frame) };
+            }
+            Format::Audio { rate, channels } => {
+                let frame = NDIlib_audio_frame_interleaved_32f_t {
+                    sample_rate: rate,
+                    no_channels: channels,
+                    no_samples: (data.len() / (channels as usize * mem::size_of::<f32>())) as i32,
+                    timecode: NDIlib_send_timecode_synthesize,
+                    p_data: data.as_ptr() as *mut f32,
+                };
+
+                unsafe { NDIlib_util_send_send_audio_interleaved_32f(sender.0, &frame) };
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct NdiSinkStatic;
+
+impl ImplTypeStatic<BaseSink> for NdiSinkStatic {
+    fn get_name(&self) -> &str {
+        "NdiSink"
+    }
+
+    fn new(&self, element: &BaseSink) -> Box<BaseSinkImpl<BaseSink>> {
+        NdiSink::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseSinkClass) {
+        NdiSink::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let ndisink_static = NdiSinkStatic;
+    register_type(ndisink_static)
+}
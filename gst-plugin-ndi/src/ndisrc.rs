@@ -0,0 +1,415 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_src::*;
+use gst_plugin::idle::*;
+
+use std::i32;
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+use std::u32;
+
+use ndisys::*;
+use receiver::{Frame, Kind, Receiver, ReceiverSettings};
+
+const DEFAULT_NDI_NAME: Option<&str> = None;
+const DEFAULT_URL_ADDRESS: Option<&str> = None;
+const DEFAULT_BANDWIDTH: i32 = NDIlib_recv_bandwidth_highest;
+const DEFAULT_CONNECT_TIMEOUT: u32 = 10_000;
+
+// Maximum time the streaming thread blocks without checking for flushing
+const POLL_INTERVAL: u64 = 100;
+
+impl Default for ReceiverSettings {
+    fn default() -> Self {
+        ReceiverSettings {
+            ndi_name: DEFAULT_NDI_NAME.map(String::from),
+            url_address: DEFAULT_URL_ADDRESS.map(String::from),
+            bandwidth: DEFAULT_BANDWIDTH,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 5] = [
+    Property::String(
+        "ndi-name",
+        "NDI Name",
+        "Name of the NDI sender to receive from, looked up on the network if no URL address \
+         is set",
+        DEFAULT_NDI_NAME,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "url-address",
+        "URL Address",
+        "Address and port of the NDI sender to receive from",
+        DEFAULT_URL_ADDRESS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Int(
+        "bandwidth",
+        "Bandwidth",
+        "Requested stream quality: -10 metadata only, 0 lowest (preview), 10 audio only, \
+         100 highest",
+        (-10, 100),
+        DEFAULT_BANDWIDTH,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "connect-timeout",
+        "Connect Timeout",
+        "Time in milliseconds to wait for the NDI sender to be found",
+        (0, u32::MAX),
+        DEFAULT_CONNECT_TIMEOUT,
+        PropertyMutability::ReadWrite,
+    ),
+    IDLE_WHEN_UNLINKED_PROPERTY,
+];
+
+// Behaviour:
+//
+// - The video and the audio source of the same NDI sender share one receiver, the settings of
+//   whichever source starts first are used for it
+// - Frames received while the element is not running are dropped by the receiver
+// - Timestamps are the arrival time of the first frame plus the distance of the NDI timestamps
+//   to it, see Receiver::get_running_time()
+// - The latency is the duration of one frame, which is the minimum time a frame is late when
+//   it arrives
+// - With idle-when-unlinked no frames are pushed while the src pad is unlinked, and frames
+//   received in the meantime are dropped once it is linked again
+struct State {
+    receiver: Receiver,
+    frames: mpsc::Receiver<Frame>,
+    caps: Option<gst::Caps>,
+}
+
+struct NdiSrc {
+    cat: gst::DebugCategory,
+    kind: Kind,
+    settings: Mutex<ReceiverSettings>,
+    state: Mutex<Option<State>>,
+    latency: Mutex<Option<gst::ClockTime>>,
+    flushing: Mutex<bool>,
+    idle: Idle,
+}
+
+impl NdiSrc {
+    fn new(_src: &BaseSrc, kind: Kind) -> Self {
+        let (name, description) = match kind {
+            Kind::Video => ("ndivideosrc", "NDI video source"),
+            Kind::Audio => ("ndiaudiosrc", "NDI audio source"),
+        };
+
+        Self {
+            cat: gst::DebugCategory::new(name, gst::DebugColorFlags::empty(), description),
+            kind: kind,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+            latency: Mutex::new(None),
+            flushing: Mutex::new(false),
+            idle: Idle::new(),
+        }
+    }
+
+    fn class_init(klass: &mut BaseSrcClass, kind: Kind) {
+        let caps = match kind {
+            Kind::Video => {
+                klass.set_metadata(
+                    "NDI Video Source",
+                    "Source/Video/Network",
+                    "Receives video from an NDI sender",
+                    "Sebastian Dröge <sebastian@centricular.com>",
+                );
+
+                gst::Caps::new_simple(
+                    "video/x-raw",
+                    &[
+                        (
+                            "format",
+                            &gst::List::new(&[
+                                &gst_video::VideoFormat::Uyvy.to_string(),
+                                &gst_video::VideoFormat::Bgra.to_string(),
+                                &gst_video::VideoFormat::Bgrx.to_string(),
+                                &gst_video::VideoFormat::Rgba.to_string(),
+                                &gst_video::VideoFormat::Rgbx.to_string(),
+                            ]),
+                        ),
+                        ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                        ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                        (
+                            "framerate",
+                            &gst::FractionRange::new(
+                                gst::Fraction::new(0, 1),
+                                gst::Fraction::new(i32::MAX, 1),
+                            ),
+                        ),
+                    ],
+                )
+            }
+            Kind::Audio => {
+                klass.set_metadata(
+                    "NDI Audio Source",
+                    "Source/Audio/Network",
+                    "Receives audio from an NDI sender",
+                    "Sebastian Dröge <sebastian@centricular.com>",
+                );
+
+                gst::Caps::new_simple(
+                    "audio/x-raw",
+                    &[
+                        ("format", &"F32LE"),
+                        ("layout", &"interleaved"),
+                        ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                        ("channels", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                    ],
+                )
+            }
+        };
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &BaseSrc, kind: Kind) -> Box<BaseSrcImpl<BaseSrc>> {
+        // Timestamped from the NDI timestamps in create()
+        element.set_live_source(false);
+
+        let imp = Self::new(element, kind);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseSrc> for NdiSrc {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("ndi-name", ..) => {
+                settings.ndi_name = value.get();
+            }
+            Property::String("url-address", ..) => {
+                settings.url_address = value.get();
+            }
+            Property::Int("bandwidth", ..) => {
+                settings.bandwidth = value.get().unwrap();
+            }
+            Property::UInt("connect-timeout", ..) => {
+                settings.connect_timeout = value.get().unwrap();
+            }
+            Property::Boolean("idle-when-unlinked", ..) => {
+                self.idle.set_enabled(value.get().unwrap());
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("ndi-name", ..) => Ok(settings.ndi_name.to_value()),
+            Property::String("url-address", ..) => Ok(settings.url_address.to_value()),
+            Property::Int("bandwidth", ..) => Ok(settings.bandwidth.to_value()),
+            Property::UInt("connect-timeout", ..) => Ok(settings.connect_timeout.to_value()),
+            Property::Boolean("idle-when-unlinked", ..) => Ok(self.idle.is_enabled().to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseSrc> for NdiSrc {}
+
+impl BaseSrcImpl<BaseSrc> for NdiSrc {
+    fn start(&self, element: &BaseSrc) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let (receiver, frames) = match Receiver::connect(&settings, self.kind) {
+            Ok(res) => res,
+            Err(err) => {
+                gst_element_error!(element, gst::ResourceError::OpenRead, ["{}", err]);
+                return false;
+            }
+        };
+
+        gst_debug!(self.cat, obj: element, "Connected with settings {:?}", settings);
+
+        *self.state.lock().unwrap() = Some(State {
+            receiver: receiver,
+            frames: frames,
+            caps: None,
+        });
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseSrc) -> bool {
+        *self.state.lock().unwrap() = None;
+        *self.latency.lock().unwrap() = None;
+
+        true
+    }
+
+    fn create(
+        &self,
+        element: &BaseSrc,
+        _offset: u64,
+        _length: u32,
+    ) -> Result<gst::Buffer, gst::FlowReturn> {
+        let srcpad = element.get_static_pad("src").unwrap();
+        let idled = self.idle.is_enabled() && !srcpad.is_linked();
+        self.idle.wait_linked(self.cat, element)?;
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return Err(gst::FlowReturn::Flushing),
+            Some(ref mut state) => state,
+        };
+
+        // Frames queued up while idling are outdated by now
+        if idled {
+            loop {
+                match state.frames.try_recv() {
+                    Ok(Frame::Buffer(..)) => (),
+                    Ok(Frame::Error(err)) => {
+                        gst_element_error!(element, gst::ResourceError::Read, ["{}", err]);
+                        return Err(gst::FlowReturn::Error);
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+
+        let (caps, mut buffer, ndi_time) = loop {
+            if *self.flushing.lock().unwrap() {
+                gst_debug!(self.cat, obj: element, "Flushing");
+                return Err(gst::FlowReturn::Flushing);
+            }
+
+            match state
+                .frames
+                .recv_timeout(Duration::from_millis(POLL_INTERVAL))
+            {
+                Ok(Frame::Buffer(caps, buffer, ndi_time)) => break (caps, buffer, ndi_time),
+                Ok(Frame::Error(err)) => {
+                    gst_element_error!(element, gst::ResourceError::Read, ["{}", err]);
+                    return Err(gst::FlowReturn::Error);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    gst_element_error!(element, gst::ResourceError::Read, ["Receiver stopped"]);
+                    return Err(gst::FlowReturn::Error);
+                }
+            }
+        };
+
+        if state.caps.as_ref() != Some(&caps) {
+            gst_debug!(self.cat, obj: element, "Received caps {}", caps);
+
+            // Sent directly so that they apply to this buffer
+            srcpad.push_event(gst::Event::new_caps(&caps).build());
+            state.caps = Some(caps);
+
+            let latency = buffer.get_duration();
+            if *self.latency.lock().unwrap() != Some(latency) {
+                *self.latency.lock().unwrap() = Some(latency);
+                element.post_latency_message();
+            }
+        }
+
+        {
+            let element = element.clone().upcast::<gst::Element>();
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(state.receiver.get_running_time(&element, ndi_time));
+        }
+
+        gst_log!(self.cat, obj: element, "Produced buffer {:?}", buffer);
+
+        Ok(buffer)
+    }
+
+    fn get_latency(&self, _element: &BaseSrc) -> Option<(gst::ClockTime, gst::ClockTime)> {
+        self.latency
+            .lock()
+            .unwrap()
+            .map(|latency| (latency, gst::CLOCK_TIME_NONE))
+    }
+
+    fn unlock(&self, _element: &BaseSrc) -> bool {
+        *self.flushing.lock().unwrap() = true;
+        self.idle.unlock();
+
+        true
+    }
+
+    fn unlock_stop(&self, _element: &BaseSrc) -> bool {
+        *self.flushing.lock().unwrap() = false;
+        self.idle.unlock_stop();
+
+        true
+    }
+}
+
+struct NdiVideoSrcStatic;
+
+impl ImplTypeStatic<BaseSrc> for NdiVideoSrcStatic {
+    fn get_name(&self) -> &str {
+        "NdiVideoSrc"
+    }
+
+    fn new(&self, element: &BaseSrc) -> Box<BaseSrcImpl<BaseSrc>> {
+        NdiSrc::init(element, Kind::Video)
+    }
+
+    fn class_init(&self, klass: &mut BaseSrcClass) {
+        NdiSrc::class_init(klass, Kind::Video);
+    }
+}
+
+struct NdiAudioSrcStatic;
+
+impl ImplTypeStatic<BaseSrc> for NdiAudioSrcStatic {
+    fn get_name(&self) -> &str {
+        "NdiAudioSrc"
+    }
+
+    fn new(&self, element: &BaseSrc) -> Box<BaseSrcImpl<BaseSrc>> {
+        NdiSrc::init(element, Kind::Audio)
+    }
+
+    fn class_init(&self, klass: &mut BaseSrcClass) {
+        NdiSrc::class_init(klass, Kind::Audio);
+    }
+}
+
+pub fn get_video_type() -> glib::Type {
+    let ndivideosrc_static = NdiVideoSrcStatic;
+    register_type(ndivideosrc_static)
+}
+
+pub fn get_audio_type() -> glib::Type {
+    let ndiaudiosrc_static = NdiAudioSrcStatic;
+    register_type(ndiaudiosrc_static)
+}
@@ -0,0 +1,193 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Declarations of the parts of the NDI SDK (Processing.NDI.Lib.h, version 3) that are used by
+// the elements. The SDK itself is not redistributable and has to be installed separately.
+
+#![allow(non_camel_case_types, non_upper_case_globals, dead_code)]
+
+use std::os::raw::{c_char, c_void};
+
+pub type NDIlib_find_instance_t = *mut c_void;
+pub type NDIlib_recv_instance_t = *mut c_void;
+pub type NDIlib_send_instance_t = *mut c_void;
+
+pub type NDIlib_frame_type_e = i32;
+pub const NDIlib_frame_type_none: NDIlib_frame_type_e = 0;
+pub const NDIlib_frame_type_video: NDIlib_frame_type_e = 1;
+pub const NDIlib_frame_type_audio: NDIlib_frame_type_e = 2;
+pub const NDIlib_frame_type_metadata: NDIlib_frame_type_e = 3;
+pub const NDIlib_frame_type_error: NDIlib_frame_type_e = 4;
+pub const NDIlib_frame_type_status_change: NDIlib_frame_type_e = 100;
+
+pub type NDIlib_recv_bandwidth_e = i32;
+pub const NDIlib_recv_bandwidth_metadata_only: NDIlib_recv_bandwidth_e = -10;
+pub const NDIlib_recv_bandwidth_audio_only: NDIlib_recv_bandwidth_e = 10;
+pub const NDIlib_recv_bandwidth_lowest: NDIlib_recv_bandwidth_e = 0;
+pub const NDIlib_recv_bandwidth_highest: NDIlib_recv_bandwidth_e = 100;
+
+pub type NDIlib_recv_color_format_e = i32;
+pub const NDIlib_recv_color_format_BGRX_BGRA: NDIlib_recv_color_format_e = 0;
+pub const NDIlib_recv_color_format_UYVY_BGRA: NDIlib_recv_color_format_e = 1;
+pub const NDIlib_recv_color_format_RGBX_RGBA: NDIlib_recv_color_format_e = 2;
+pub const NDIlib_recv_color_format_UYVY_RGBA: NDIlib_recv_color_format_e = 3;
+
+pub type NDIlib_frame_format_type_e = i32;
+pub const NDIlib_frame_format_type_progressive: NDIlib_frame_format_type_e = 1;
+pub const NDIlib_frame_format_type_interleaved: NDIlib_frame_format_type_e = 0;
+
+pub type NDIlib_FourCC_type_e = u32;
+
+// Little-endian FourCCs, i.e. "UYVY" is 0x59565955
+pub const NDIlib_FourCC_type_UYVY: NDIlib_FourCC_type_e = 0x5956_5955;
+pub const NDIlib_FourCC_type_BGRA: NDIlib_FourCC_type_e = 0x4152_4742;
+pub const NDIlib_FourCC_type_BGRX: NDIlib_FourCC_type_e = 0x5852_4742;
+pub const NDIlib_FourCC_type_RGBA: NDIlib_FourCC_type_e = 0x4142_4752;
+pub const NDIlib_FourCC_type_RGBX: NDIlib_FourCC_type_e = 0x5842_4752;
+
+// Timecode to pass when sending to let the SDK generate it
+pub const NDIlib_send_timecode_synthesize: i64 = ::std::i64::MAX;
+// Timestamp of received frames if the sender didn't provide one
+pub const NDIlib_recv_timestamp_undefined: i64 = ::std::i64::MAX;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct NDIlib_source_t {
+    pub p_ndi_name: *const c_char,
+    pub p_url_address: *const c_char,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct NDIlib_find_create_t {
+    pub show_local_sources: bool,
+    pub p_groups: *const c_char,
+    pub p_extra_ips: *const c_char,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct NDIlib_recv_create_v3_t {
+    pub source_to_connect_to: NDIlib_source_t,
+    pub color_format: NDIlib_recv_color_format_e,
+    pub bandwidth: NDIlib_recv_bandwidth_e,
+    pub allow_video_fields: bool,
+    pub p_ndi_recv_name: *const c_char,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct NDIlib_send_create_t {
+    pub p_ndi_name: *const c_char,
+    pub p_groups: *const c_char,
+    pub clock_video: bool,
+    pub clock_audio: bool,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct NDIlib_video_frame_v2_t {
+    pub xres: i32,
+    pub yres: i32,
+    pub FourCC: NDIlib_FourCC_type_e,
+    pub frame_rate_N: i32,
+    pub frame_rate_D: i32,
+    pub picture_aspect_ratio: f32,
+    pub frame_format_type: NDIlib_frame_format_type_e,
+    pub timecode: i64,
+    pub p_data: *mut u8,
+    pub line_stride_in_bytes: i32,
+    pub p_metadata: *const c_char,
+    pub timestamp: i64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct NDIlib_audio_frame_v2_t {
+    pub sample_rate: i32,
+    pub no_channels: i32,
+    pub no_samples: i32,
+    pub timecode: i64,
+    pub p_data: *mut f32,
+    pub channel_stride_in_bytes: i32,
+    pub p_metadata: *const c_char,
+    pub timestamp: i64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct NDIlib_audio_frame_interleaved_32f_t {
+    pub sample_rate: i32,
+    pub no_channels: i32,
+    pub no_samples: i32,
+    pub timecode: i64,
+    pub p_data: *mut f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct NDIlib_metadata_frame_t {
+    pub length: i32,
+    pub timecode: i64,
+    pub p_data: *mut c_char,
+}
+
+#[link(name = "ndi")]
+extern "C" {
+    pub fn NDIlib_initialize() -> bool;
+
+    pub fn NDIlib_find_create_v2(
+        p_create_settings: *const NDIlib_find_create_t,
+    ) -> NDIlib_find_instance_t;
+    pub fn NDIlib_find_destroy(p_instance: NDIlib_find_instance_t);
+    pub fn NDIlib_find_wait_for_sources(
+        p_instance: NDIlib_find_instance_t,
+        timeout_in_ms: u32,
+    ) -> bool;
+    pub fn NDIlib_find_get_current_sources(
+        p_instance: NDIlib_find_instance_t,
+        p_no_sources: *mut u32,
+    ) -> *const NDIlib_source_t;
+
+    pub fn NDIlib_recv_create_v3(
+        p_create_settings: *const NDIlib_recv_create_v3_t,
+    ) -> NDIlib_recv_instance_t;
+    pub fn NDIlib_recv_destroy(p_instance: NDIlib_recv_instance_t);
+    pub fn NDIlib_recv_capture_v2(
+        p_instance: NDIlib_recv_instance_t,
+        p_video_data: *mut NDIlib_video_frame_v2_t,
+        p_audio_data: *mut NDIlib_audio_frame_v2_t,
+        p_metadata: *mut NDIlib_metadata_frame_t,
+        timeout_in_ms: u32,
+    ) -> NDIlib_frame_type_e;
+    pub fn NDIlib_recv_free_video_v2(
+        p_instance: NDIlib_recv_instance_t,
+        p_video_data: *const NDIlib_video_frame_v2_t,
+    );
+    pub fn NDIlib_recv_free_audio_v2(
+        p_instance: NDIlib_recv_instance_t,
+        p_audio_data: *const NDIlib_audio_frame_v2_t,
+    );
+    pub fn NDIlib_recv_free_metadata(
+        p_instance: NDIlib_recv_instance_t,
+        p_metadata: *const NDIlib_metadata_frame_t,
+    );
+
+    pub fn NDIlib_send_create(
+        p_create_settings: *const NDIlib_send_create_t,
+    ) -> NDIlib_send_instance_t;
+    pub fn NDIlib_send_destroy(p_instance: NDIlib_send_instance_t);
+    pub fn NDIlib_send_send_video_v2(
+        p_instance: NDIlib_send_instance_t,
+        p_video_data: *const NDIlib_video_frame_v2_t,
+    );
+    pub fn NDIlib_util_send_send_audio_interleaved_32f(
+        p_instance: NDIlib_send_instance_t,
+        p_audio_data: *const NDIlib_audio_frame_interleaved_32f_t,
+    );
+}
@@ -0,0 +1,551 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::sync::{mpsc, Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use ndisys::*;
+
+// Maximum time the capture thread blocks without checking for stopping
+const POLL_INTERVAL: u32 = 100;
+
+// Difference between a mapped timestamp and the arrival time after which the mapping is
+// considered broken, e.g. because the sender was restarted
+const RESYNC_THRESHOLD: u64 = gst::SECOND_VAL;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Video,
+    Audio,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReceiverSettings {
+    pub ndi_name: Option<String>,
+    pub url_address: Option<String>,
+    pub bandwidth: i32,
+    pub connect_timeout: u32,
+}
+
+pub enum Frame {
+    // Caps, buffer and NDI timestamp in nanoseconds if the sender provided one
+    Buffer(gst::Caps, gst::Buffer, Option<u64>),
+    Error(String),
+}
+
+struct RecvInstance(NDIlib_recv_instance_t);
+
+// The SDK allows using a receiver from any thread
+unsafe impl Send for RecvInstance {}
+unsafe impl Sync for RecvInstance {}
+
+impl Drop for RecvInstance {
+    fn drop(&mut self) {
+        unsafe { NDIlib_recv_destroy(self.0) }
+    }
+}
+
+struct ReceiverInner {
+    key: String,
+    instance: RecvInstance,
+    video: Mutex<Option<mpsc::Sender<Frame>>>,
+    audio: Mutex<Option<mpsc::Sender<Frame>>>,
+    // First NDI timestamp and the clock time it was received at, shared by the video and audio
+    // source so that both map to the same time
+    observation: Mutex<Option<(u64, u64)>>,
+    stop: AtomicBool,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl ReceiverInner {
+    fn slot(&self, kind: Kind) -> &Mutex<Option<mpsc::Sender<Frame>>> {
+        match kind {
+            Kind::Video => &self.video,
+            Kind::Audio => &self.audio,
+        }
+    }
+}
+
+lazy_static! {
+    // Receivers by NDI name or URL address, so that the video and the audio source of the same
+    // sender share one connection
+    static ref RECEIVERS: Mutex<HashMap<String, Weak<ReceiverInner>>> =
+        Mutex::new(HashMap::new());
+}
+
+// Handle of one source on a shared receiver, disconnects the source when dropped
+pub struct Receiver {
+    inner: Arc<ReceiverInner>,
+    kind: Kind,
+}
+
+impl Receiver {
+    /// Connects a source of `kind` to the NDI sender selected by the settings. The settings of
+    /// the first source connecting to a sender apply to the shared receiver.
+    pub fn connect(
+        settings: &ReceiverSettings,
+        kind: Kind,
+    ) -> Result<(Receiver, mpsc::Receiver<Frame>), String> {
+        let key = match (&settings.url_address, &settings.ndi_name) {
+            (&Some(ref address), _) if !address.is_empty() => address.clone(),
+            (_, &Some(ref name)) if !name.is_empty() => name.clone(),
+            _ => return Err(String::from("Neither NDI name nor URL address set")),
+        };
+
+        let mut receivers = RECEIVERS.lock().unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        if let Some(inner) = receivers.get(&key).and_then(|inner| inner.upgrade()) {
+            {
+                let mut slot = inner.slot(kind).lock().unwrap();
+                if slot.is_some() {
+                    return Err(format!("Already receiving {:?} from '{}'", kind, key));
+                }
+                *slot = Some(sender);
+            }
+
+            return Ok((
+                Receiver {
+                    inner: inner,
+                    kind: kind,
+                },
+                receiver,
+            ));
+        }
+
+        let instance = create_receiver(settings)?;
+        let inner = Arc::new(ReceiverInner {
+            key: key.clone(),
+            instance: instance,
+            video: Mutex::new(None),
+            audio: Mutex::new(None),
+            observation: Mutex::new(None),
+            stop: AtomicBool::new(false),
+            thread: Mutex::new(None),
+        });
+        *inner.slot(kind).lock().unwrap() = Some(sender);
+
+        let thread = {
+            let inner = inner.clone();
+            thread::spawn(move || capture(&inner))
+        };
+        *inner.thread.lock().unwrap() = Some(thread);
+
+        receivers.insert(key, Arc::downgrade(&inner));
+
+        Ok((
+            Receiver {
+                inner: inner,
+                kind: kind,
+            },
+            receiver,
+        ))
+    }
+
+    /// Maps the NDI timestamp of a frame to the running time of `element`.
+    ///
+    /// The first frame is timestamped with its arrival time, the following ones keep their
+    /// distance in NDI time to it, so that network jitter doesn't end up in the timestamps.
+    pub fn get_running_time(
+        &self,
+        element: &gst::Element,
+        ndi_time: Option<u64>,
+    ) -> gst::ClockTime {
+        let (now, base_time) = match (element.get_clock(), element.get_base_time().0) {
+            (Some(clock), Some(base_time)) => match clock.get_time().0 {
+                Some(now) => (now, base_time),
+                None => return gst::CLOCK_TIME_NONE,
+            },
+            _ => return gst::CLOCK_TIME_NONE,
+        };
+
+        let time = match ndi_time {
+            None => now,
+            Some(ndi_time) => {
+                let mut observation = self.inner.observation.lock().unwrap();
+                map_ndi_time(&mut *observation, ndi_time, now)
+            }
+        };
+
+        gst::ClockTime::from_nseconds(time.saturating_sub(base_time))
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let mut receivers = RECEIVERS.lock().unwrap();
+
+        *self.inner.slot(self.kind).lock().unwrap() = None;
+        let other = match self.kind {
+            Kind::Video => Kind::Audio,
+            Kind::Audio => Kind::Video,
+        };
+        if self.inner.slot(other).lock().unwrap().is_some() {
+            return;
+        }
+
+        receivers.remove(&self.inner.key);
+        self.inner.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.inner.thread.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Returns the clock time for `ndi_time`, starting a new mapping if there is none yet or if it
+// is too far off the arrival time `now`
+fn map_ndi_time(observation: &mut Option<(u64, u64)>, ndi_time: u64, now: u64) -> u64 {
+    if let Some((first_ndi_time, first_time)) = *observation {
+        if ndi_time >= first_ndi_time {
+            let time = first_time + (ndi_time - first_ndi_time);
+            if time + RESYNC_THRESHOLD > now && time < now + RESYNC_THRESHOLD {
+                return time;
+            }
+        }
+    }
+
+    *observation = Some((ndi_time, now));
+    now
+}
+
+fn find_source(name: &str, timeout: u32) -> Result<(CString, CString), String> {
+    let create = NDIlib_find_create_t {
+        show_local_sources: true,
+        p_groups: ptr::null(),
+        p_extra_ips: ptr::null(),
+    };
+
+    let find = unsafe { NDIlib_find_create_v2(&create) };
+    if find.is_null() {
+        return Err(String::from("Failed to create NDI finder"));
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(timeout as u64);
+    let mut res = Err(format!("NDI source '{}' not found", name));
+
+    while Instant::now() < deadline {
+        let sources = unsafe {
+            NDIlib_find_wait_for_sources(find, POLL_INTERVAL);
+
+            let mut no_sources = 0;
+            let sources = NDIlib_find_get_current_sources(find, &mut no_sources);
+            if sources.is_null() {
+                &[][..]
+            } else {
+                slice::from_raw_parts(sources, no_sources as usize)
+            }
+        };
+
+        let source = sources.iter().find(|source| {
+            !source.p_ndi_name.is_null()
+                && unsafe { CStr::from_ptr(source.p_ndi_name) }.to_bytes() == name.as_bytes()
+        });
+
+        if let Some(source) = source {
+            res = unsafe {
+                Ok((
+                    CStr::from_ptr(source.p_ndi_name).to_owned(),
+                    CStr::from_ptr(source.p_url_address).to_owned(),
+                ))
+            };
+            break;
+        }
+    }
+
+    unsafe { NDIlib_find_destroy(find) };
+
+    res
+}
+
+fn create_receiver(settings: &ReceiverSettings) -> Result<RecvInstance, String> {
+    let to_cstring = |s: &Option<String>| match *s {
+        Some(ref s) if !s.is_empty() => CString::new(s.as_str())
+            .map(Some)
+            .map_err(|_| format!("Invalid string '{}'", s)),
+        _ => Ok(None),
+    };
+
+    let name = to_cstring(&settings.ndi_name)?;
+    let address = to_cstring(&settings.url_address)?;
+
+    // Without address the sender has to be discovered by name first
+    let (name, address) = match (name, address) {
+        (name, Some(address)) => (name, address),
+        (Some(name), None) => {
+            let (name, address) = find_source(name.to_str().unwrap(), settings.connect_timeout)?;
+            (Some(name), address)
+        }
+        (None, None) => unreachable!(),
+    };
+
+    let create = NDIlib_recv_create_v3_t {
+        source_to_connect_to: NDIlib_source_t {
+            p_ndi_name: name
+                .as_ref()
+                .map(|name| name.as_ptr())
+                .unwrap_or(ptr::null()),
+            p_url_address: address.as_ptr(),
+        },
+        color_format: NDIlib_recv_color_format_UYVY_BGRA,
+        bandwidth: settings.bandwidth,
+        allow_video_fields: false,
+        p_ndi_recv_name: ptr::null(),
+    };
+
+    let instance = unsafe { NDIlib_recv_create_v3(&create) };
+    if instance.is_null() {
+        return Err(format!("Failed to connect to {:?}", address));
+    }
+
+    Ok(RecvInstance(instance))
+}
+
+fn get_ndi_time(timestamp: i64) -> Option<u64> {
+    if timestamp == NDIlib_recv_timestamp_undefined || timestamp < 0 {
+        None
+    } else {
+        // In 100ns units
+        Some(timestamp as u64 * 100)
+    }
+}
+
+fn video_frame_to_buffer(
+    frame: &NDIlib_video_frame_v2_t,
+) -> Result<(gst::Caps, gst::Buffer), String> {
+    let format = match frame.FourCC {
+        NDIlib_FourCC_type_UYVY => gst_video::VideoFormat::Uyvy,
+        NDIlib_FourCC_type_BGRA => gst_video::VideoFormat::Bgra,
+        NDIlib_FourCC_type_BGRX => gst_video::VideoFormat::Bgrx,
+        NDIlib_FourCC_type_RGBA => gst_video::VideoFormat::Rgba,
+        NDIlib_FourCC_type_RGBX => gst_video::VideoFormat::Rgbx,
+        fourcc => return Err(format!("Unsupported video format 0x{:08x}", fourcc)),
+    };
+
+    let (width, height) = (frame.xres as u32, frame.yres as u32);
+    let (par_n, par_d) = get_pixel_aspect_ratio(frame.picture_aspect_ratio, width, height);
+    let info = gst_video::VideoInfo::new(format, width, height)
+        .fps(gst::Fraction::new(frame.frame_rate_N, frame.frame_rate_D))
+        .par(gst::Fraction::new(par_n, par_d))
+        .build()
+        .ok_or_else(|| format!("Invalid video frame {}x{}", width, height))?;
+
+    let src_stride = frame.line_stride_in_bytes as usize;
+    let dest_stride = info.stride()[0] as usize;
+    let src = unsafe { slice::from_raw_parts(frame.p_data, src_stride * height as usize) };
+
+    let mut buffer = gst::Buffer::with_size(info.size()).unwrap();
+    {
+        let buffer = buffer.get_mut().unwrap();
+        if frame.frame_rate_N > 0 && frame.frame_rate_D > 0 {
+            buffer.set_duration(gst::ClockTime::from_nseconds(
+                gst::SECOND_VAL * frame.frame_rate_D as u64 / frame.frame_rate_N as u64,
+            ));
+        }
+
+        let mut map = buffer.map_writable().unwrap();
+        let dest = map.as_mut_slice();
+        let row_size = ::std::cmp::min(src_stride, dest_stride);
+        for (dest, src) in dest.chunks_mut(dest_stride).zip(src.chunks(src_stride)) {
+            dest[..row_size].copy_from_slice(&src[..row_size]);
+        }
+    }
+
+    Ok((info.to_caps().unwrap(), buffer))
+}
+
+// NDI only signals the display aspect ratio, 0 meaning square pixels
+fn get_pixel_aspect_ratio(aspect_ratio: f32, width: u32, height: u32) -> (i32, i32) {
+    if aspect_ratio <= 0.0 || width == 0 {
+        return (1, 1);
+    }
+
+    let par_n = (aspect_ratio as f64 * height as f64 * 1000.0).round() as i32;
+    let par_d = width as i32 * 1000;
+    let gcd = gcd(par_n, par_d);
+
+    if gcd == 0 {
+        (1, 1)
+    } else {
+        (par_n / gcd, par_d / gcd)
+    }
+}
+
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn audio_frame_to_buffer(
+    frame: &NDIlib_audio_frame_v2_t,
+) -> Result<(gst::Caps, gst::Buffer), String> {
+    if frame.sample_rate <= 0 || frame.no_channels <= 0 || frame.no_samples < 0 {
+        return Err(String::from("Invalid audio frame"));
+    }
+
+    let channels = frame.no_channels as usize;
+    let samples = frame.no_samples as usize;
+    let channel_stride = frame.channel_stride_in_bytes as usize / mem::size_of::<f32>();
+    let src = unsafe { slice::from_raw_parts(frame.p_data, channel_stride * channels) };
+
+    let caps = gst::Caps::new_simple(
+        "audio/x-raw",
+        &[
+            ("format", &"F32LE"),
+            ("layout", &"interleaved"),
+            ("rate", &frame.sample_rate),
+            ("channels", &frame.no_channels),
+        ],
+    );
+
+    let mut buffer = gst::Buffer::with_size(samples * channels * mem::size_of::<f32>()).unwrap();
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_duration(gst::ClockTime::from_nseconds(
+            samples as u64 * gst::SECOND_VAL / frame.sample_rate as u64,
+        ));
+
+        let mut map = buffer.map_writable().unwrap();
+        interleave(src, channel_stride, channels, samples, map.as_mut_slice());
+    }
+
+    Ok((caps, buffer))
+}
+
+// Converts planar samples with `stride` samples between channels to interleaved
+// little-endian bytes
+fn interleave(src: &[f32], stride: usize, channels: usize, samples: usize, dest: &mut [u8]) {
+    for (i, dest) in dest
+        .chunks_mut(mem::size_of::<f32>())
+        .take(samples * channels)
+        .enumerate()
+    {
+        let (sample, channel) = (i / channels, i % channels);
+        let bits = src[channel * stride + sample].to_bits();
+        dest[0] = bits as u8;
+        dest[1] = (bits >> 8) as u8;
+        dest[2] = (bits >> 16) as u8;
+        dest[3] = (bits >> 24) as u8;
+    }
+}
+
+fn send_frame(
+    inner: &ReceiverInner,
+    kind: Kind,
+    res: Result<(gst::Caps, gst::Buffer), String>,
+    ndi_time: Option<u64>,
+) {
+    let slot = inner.slot(kind).lock().unwrap();
+    if let Some(ref sender) = *slot {
+        let frame = match res {
+            Ok((caps, buffer)) => Frame::Buffer(caps, buffer, ndi_time),
+            Err(err) => Frame::Error(err),
+        };
+        let _ = sender.send(frame);
+    }
+}
+
+fn capture(inner: &ReceiverInner) {
+    let instance = inner.instance.0;
+
+    while !inner.stop.load(Ordering::SeqCst) {
+        unsafe {
+            let mut video: NDIlib_video_frame_v2_t = mem::zeroed();
+            let mut audio: NDIlib_audio_frame_v2_t = mem::zeroed();
+            let mut metadata: NDIlib_metadata_frame_t = mem::zeroed();
+
+            match NDIlib_recv_capture_v2(
+                instance,
+                &mut video,
+                &mut audio,
+                &mut metadata,
+                POLL_INTERVAL,
+            ) {
+                NDIlib_frame_type_video => {
+                    let res = video_frame_to_buffer(&video);
+                    let ndi_time = get_ndi_time(video.timestamp);
+                    NDIlib_recv_free_video_v2(instance, &video);
+                    send_frame(inner, Kind::Video, res, ndi_time);
+                }
+                NDIlib_frame_type_audio => {
+                    let res = audio_frame_to_buffer(&audio);
+                    let ndi_time = get_ndi_time(audio.timestamp);
+                    NDIlib_recv_free_audio_v2(instance, &audio);
+                    send_frame(inner, Kind::Audio, res, ndi_time);
+                }
+                NDIlib_frame_type_metadata => {
+                    NDIlib_recv_free_metadata(instance, &metadata);
+                }
+                NDIlib_frame_type_error => {
+                    let err = format!("Connection to '{}' failed", inner.key);
+                    send_frame(inner, Kind::Video, Err(err.clone()), None);
+                    send_frame(inner, Kind::Audio, Err(err), None);
+                    return;
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_ndi_time() {
+        let mut observation = None;
+        assert_eq!(map_ndi_time(&mut observation, 1_000, 5_000_000), 5_000_000);
+        // Jitter in the arrival time doesn't matter
+        assert_eq!(map_ndi_time(&mut observation, 41_000, 5_045_000), 5_040_000);
+        assert_eq!(map_ndi_time(&mut observation, 81_000, 5_070_000), 5_080_000);
+
+        // A sender restart starts a new mapping
+        assert_eq!(map_ndi_time(&mut observation, 500, 6_000_000), 6_000_000);
+        assert_eq!(observation, Some((500, 6_000_000)));
+        let now = 6_000_000 + 2 * gst::SECOND_VAL;
+        assert_eq!(map_ndi_time(&mut observation, 1_000, now), now);
+    }
+
+    #[test]
+    fn test_pixel_aspect_ratio() {
+        assert_eq!(get_pixel_aspect_ratio(0.0, 1920, 1080), (1, 1));
+        assert_eq!(get_pixel_aspect_ratio(16.0 / 9.0, 1920, 1080), (1, 1));
+        assert_eq!(get_pixel_aspect_ratio(4.0 / 3.0, 720, 576), (16, 15));
+    }
+
+    #[test]
+    fn test_interleave() {
+        let src = [1.0f32, 2.0, 0.0, 3.0, 4.0, 0.0];
+        let mut dest = [0u8; 16];
+        interleave(&src, 3, 2, 2, &mut dest);
+
+        let samples = dest
+            .chunks(4)
+            .map(|b| {
+                f32::from_bits(
+                    b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(samples, vec![1.0, 3.0, 2.0, 4.0]);
+    }
+}
@@ -0,0 +1,51 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate base64;
+extern crate glib;
+extern crate glib_sys as glib_ffi;
+#[macro_use]
+extern crate gst_plugin;
+#[macro_use]
+extern crate gstreamer as gst;
+extern crate gstreamer_sys as gst_ffi;
+extern crate gstreamer_video as gst_video;
+extern crate gstreamer_video_sys as gst_video_ffi;
+extern crate rand;
+extern crate reqwest;
+extern crate sha1;
+extern crate xml;
+
+use gst_plugin::registration::*;
+
+mod metadata;
+mod roi;
+mod soap;
+
+mod onvifmetadataoverlay;
+mod onvifmetadataparse;
+mod onvifptz;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    ElementRegistration::new(plugin)
+        .element("onvifptz", RANK_NONE, onvifptz::get_type())
+        .element("onvifmetadataparse", RANK_NONE, onvifmetadataparse::get_type())
+        .element("onvifmetadataoverlay", RANK_NONE, onvifmetadataoverlay::get_type())
+        .register()
+}
+
+plugin_define!(
+    "rsonvif",
+    "Rust ONVIF Plugin",
+    plugin_init,
+    "MIT/X11",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2018-01-22"
+);
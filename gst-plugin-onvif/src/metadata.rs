@@ -0,0 +1,367 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Parser for the video analytics part of ONVIF metadata streams
+//
+// Only the objects of the frames with their bounding box and most likely class are extracted,
+// events and PTZ status are ignored.
+
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+
+/// Bounding box in the normalized ONVIF coordinate system: x from -1 (left) to 1 (right), y from
+/// -1 (bottom) to 1 (top)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub left: f64,
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub id: u32,
+    pub bbox: BoundingBox,
+    pub class: Option<String>,
+    pub likelihood: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub utc_time: Option<String>,
+    pub objects: Vec<Object>,
+}
+
+// Maps frame coordinates to normalized coordinates, x' = x * scale + translate
+#[derive(Debug, Clone, Copy)]
+struct Transformation {
+    translate: (f64, f64),
+    scale: (f64, f64),
+}
+
+impl Default for Transformation {
+    fn default() -> Self {
+        Transformation {
+            translate: (0.0, 0.0),
+            scale: (1.0, 1.0),
+        }
+    }
+}
+
+impl Transformation {
+    fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            x * self.scale.0 + self.translate.0,
+            y * self.scale.1 + self.translate.1,
+        )
+    }
+}
+
+struct PartialObject {
+    id: u32,
+    bbox: Option<(f64, f64, f64, f64)>,
+    class: Option<String>,
+    likelihood: Option<f64>,
+}
+
+impl PartialObject {
+    // Keeps the class candidate with the highest likelihood
+    fn add_class(&mut self, class: Option<String>, likelihood: Option<f64>) {
+        let class = match class {
+            None => return,
+            Some(class) => class,
+        };
+
+        let better = match (self.likelihood, likelihood) {
+            (_, None) => self.class.is_none(),
+            (None, Some(_)) => true,
+            (Some(current), Some(likelihood)) => likelihood > current,
+        };
+
+        if better {
+            self.class = Some(class);
+            self.likelihood = likelihood;
+        }
+    }
+}
+
+fn get_attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|attr| attr.name.local_name == name)
+        .map(|attr| attr.value.as_str())
+}
+
+fn get_point(attributes: &[OwnedAttribute]) -> Option<(f64, f64)> {
+    match (get_attribute(attributes, "x"), get_attribute(attributes, "y")) {
+        (Some(x), Some(y)) => match (x.trim().parse(), y.trim().parse()) {
+            (Ok(x), Ok(y)) => Some((x, y)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn get_bbox(attributes: &[OwnedAttribute]) -> Option<(f64, f64, f64, f64)> {
+    let mut values = [0.0; 4];
+    for (value, name) in values
+        .iter_mut()
+        .zip(["left", "top", "right", "bottom"].iter())
+    {
+        *value = match get_attribute(attributes, name).map(|v| v.trim().parse()) {
+            Some(Ok(v)) => v,
+            _ => return None,
+        };
+    }
+
+    Some((values[0], values[1], values[2], values[3]))
+}
+
+/// Returns the length of the first complete MetadataStream document in `data`, if any
+pub fn find_document_end(data: &str) -> Option<usize> {
+    let mut pos = 0;
+    while let Some(idx) = data[pos..].find("</") {
+        let start = pos + idx + 2;
+        let end = match data[start..].find('>') {
+            None => return None,
+            Some(end) => start + end,
+        };
+
+        let name = data[start..end].trim_right();
+        if name.rsplit(':').next() == Some("MetadataStream") {
+            return Some(end + 1);
+        }
+        pos = end + 1;
+    }
+
+    None
+}
+
+/// Parses all video analytics frames of a MetadataStream document
+///
+/// Objects without a bounding box, e.g. the ones only announcing that an object disappeared,
+/// are skipped.
+pub fn parse_frames(data: &str) -> Result<Vec<Frame>, String> {
+    let mut frames = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+    let mut frame = None;
+    let mut transformation = Transformation::default();
+    let mut object: Option<PartialObject> = None;
+    let mut candidate: (Option<String>, Option<f64>) = (None, None);
+
+    for event in EventReader::from_str(data) {
+        match event.map_err(|err| format!("Invalid metadata: {}", err))? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let parent = path.last().cloned().unwrap_or_default();
+                match (parent.as_str(), name.local_name.as_str()) {
+                    ("VideoAnalytics", "Frame") => {
+                        frame = Some(Frame {
+                            utc_time: get_attribute(&attributes, "UtcTime").map(String::from),
+                            objects: Vec::new(),
+                        });
+                        transformation = Transformation::default();
+                    }
+                    ("Transformation", "Translate") => {
+                        if let Some(translate) = get_point(&attributes) {
+                            transformation.translate = translate;
+                        }
+                    }
+                    ("Transformation", "Scale") => {
+                        if let Some(scale) = get_point(&attributes) {
+                            transformation.scale = scale;
+                        }
+                    }
+                    ("Frame", "Object") => {
+                        let id = get_attribute(&attributes, "ObjectId")
+                            .and_then(|id| id.trim().parse().ok())
+                            .unwrap_or(0);
+                        object = Some(PartialObject {
+                            id: id,
+                            bbox: None,
+                            class: None,
+                            likelihood: None,
+                        });
+                    }
+                    ("Shape", "BoundingBox") => {
+                        if let Some(ref mut object) = object {
+                            object.bbox = get_bbox(&attributes);
+                        }
+                    }
+                    // ONVIF 1.0 style <Type Likelihood="...">class</Type>
+                    ("Class", "Type") => {
+                        let likelihood = get_attribute(&attributes, "Likelihood")
+                            .and_then(|l| l.trim().parse().ok());
+                        candidate = (None, likelihood);
+                    }
+                    ("Class", "ClassCandidate") => {
+                        candidate = (None, None);
+                    }
+                    _ => (),
+                }
+
+                path.push(name.local_name);
+            }
+            XmlEvent::Characters(text) => {
+                let current = path.last().map(|p| p.as_str()).unwrap_or("");
+                let parent = if path.len() >= 2 {
+                    path[path.len() - 2].as_str()
+                } else {
+                    ""
+                };
+
+                match (parent, current) {
+                    ("Class", "Type") | ("ClassCandidate", "Type") => {
+                        candidate.0 = Some(String::from(text.trim()));
+                    }
+                    ("ClassCandidate", "Likelihood") => {
+                        candidate.1 = text.trim().parse().ok();
+                    }
+                    _ => (),
+                }
+            }
+            XmlEvent::EndElement { name } => {
+                path.pop();
+                let parent = path.last().map(|p| p.as_str()).unwrap_or("");
+
+                match (parent, name.local_name.as_str()) {
+                    ("Class", "Type") | ("Class", "ClassCandidate") => {
+                        if let Some(ref mut object) = object {
+                            let (class, likelihood) = candidate;
+                            object.add_class(class, likelihood);
+                        }
+                        candidate = (None, None);
+                    }
+                    ("Frame", "Object") => {
+                        let object = object.take().unwrap();
+                        if let (Some(frame), Some((left, top, right, bottom))) =
+                            (frame.as_mut(), object.bbox)
+                        {
+                            let (left, top) = transformation.apply(left, top);
+                            let (right, bottom) = transformation.apply(right, bottom);
+
+                            // Depending on the transformation the corners can be swapped
+                            frame.objects.push(Object {
+                                id: object.id,
+                                bbox: BoundingBox {
+                                    left: left.min(right),
+                                    top: top.max(bottom),
+                                    right: left.max(right),
+                                    bottom: top.min(bottom),
+                                },
+                                class: object.class,
+                                likelihood: object.likelihood,
+                            });
+                        }
+                    }
+                    ("VideoAnalytics", "Frame") => {
+                        if let Some(frame) = frame.take() {
+                            frames.push(frame);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(frames)
+}
+
+/// Converts a normalized bounding box to x, y, width and height in pixels, clipped to the frame
+pub fn to_pixels(bbox: &BoundingBox, width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let to_x = |x: f64| {
+        ((x + 1.0) / 2.0 * f64::from(width))
+            .round()
+            .max(0.0)
+            .min(f64::from(width))
+    };
+    let to_y = |y: f64| {
+        ((1.0 - y) / 2.0 * f64::from(height))
+            .round()
+            .max(0.0)
+            .min(f64::from(height))
+    };
+
+    let (left, right) = (to_x(bbox.left), to_x(bbox.right));
+    let (top, bottom) = (to_y(bbox.top), to_y(bbox.bottom));
+
+    (
+        left as u32,
+        top as u32,
+        (right - left) as u32,
+        (bottom - top) as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METADATA: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <tt:MetadataStream xmlns:tt=\"http://www.onvif.org/ver10/schema\">\
+        <tt:VideoAnalytics>\
+        <tt:Frame UtcTime=\"2018-03-01T12:24:57.321Z\">\
+        <tt:Transformation>\
+        <tt:Translate x=\"-1.0\" y=\"1.0\"/>\
+        <tt:Scale x=\"0.003125\" y=\"-0.00416667\"/>\
+        </tt:Transformation>\
+        <tt:Object ObjectId=\"12\">\
+        <tt:Appearance>\
+        <tt:Shape>\
+        <tt:BoundingBox left=\"160.0\" top=\"120.0\" right=\"320.0\" bottom=\"360.0\"/>\
+        <tt:CenterOfGravity x=\"240.0\" y=\"240.0\"/>\
+        </tt:Shape>\
+        <tt:Class>\
+        <tt:ClassCandidate><tt:Type>Vehicle</tt:Type>\
+        <tt:Likelihood>0.3</tt:Likelihood></tt:ClassCandidate>\
+        <tt:ClassCandidate><tt:Type>Human</tt:Type>\
+        <tt:Likelihood>0.8</tt:Likelihood></tt:ClassCandidate>\
+        </tt:Class>\
+        </tt:Appearance>\
+        </tt:Object>\
+        <tt:Object ObjectId=\"13\"/>\
+        </tt:Frame>\
+        </tt:VideoAnalytics>\
+        </tt:MetadataStream>";
+
+    #[test]
+    fn test_parse_frames() {
+        let end = find_document_end(METADATA).unwrap();
+        assert_eq!(end, METADATA.len());
+        assert_eq!(find_document_end(&METADATA[..end - 1]), None);
+
+        let frames = parse_frames(METADATA).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            frames[0].utc_time,
+            Some(String::from("2018-03-01T12:24:57.321Z"))
+        );
+        assert_eq!(frames[0].objects.len(), 1);
+
+        let object = &frames[0].objects[0];
+        assert_eq!(object.id, 12);
+        assert_eq!(object.class, Some(String::from("Human")));
+        assert_eq!(object.likelihood, Some(0.8));
+        assert_eq!(to_pixels(&object.bbox, 640, 480), (160, 120, 160, 240));
+    }
+
+    #[test]
+    fn test_to_pixels() {
+        let bbox = BoundingBox {
+            left: -1.5,
+            top: 1.0,
+            right: 0.0,
+            bottom: 0.0,
+        };
+        assert_eq!(to_pixels(&bbox, 640, 480), (0, 0, 320, 240));
+    }
+}
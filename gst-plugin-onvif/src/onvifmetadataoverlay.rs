@@ -0,0 +1,424 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::sync::Mutex;
+use std::{i32, u32, u64};
+
+use roi;
+
+// Draws the outlines of the GstVideoRegionOfInterestMeta of the video buffers, e.g. from
+// onvifmetadataparse, onto the video.
+//
+// The regions of a buffer replace the previously drawn regions. If no new regions arrive, the
+// last ones are drawn for hold-time and then removed.
+
+const DEFAULT_COLOR: u32 = 0xffff_0000;
+const DEFAULT_LINE_WIDTH: u32 = 2;
+const DEFAULT_HOLD_TIME: u64 = gst::SECOND_VAL;
+
+static PROPERTIES: [Property; 3] = [
+    Property::UInt(
+        "color",
+        "Color",
+        "Color of the outlines as ARGB",
+        (0, u32::MAX),
+        DEFAULT_COLOR,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "line-width",
+        "Line Width",
+        "Width of the outlines in pixels",
+        (1, 100),
+        DEFAULT_LINE_WIDTH,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "hold-time",
+        "Hold Time",
+        "Time in nanoseconds the last regions are drawn if no new ones arrive",
+        (0, u64::MAX),
+        DEFAULT_HOLD_TIME,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    color: u32,
+    line_width: u32,
+    hold_time: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            color: DEFAULT_COLOR,
+            line_width: DEFAULT_LINE_WIDTH,
+            hold_time: DEFAULT_HOLD_TIME,
+        }
+    }
+}
+
+// x, y, width and height in pixels
+type Rect = (u32, u32, u32, u32);
+
+// Memory layout of the color for the supported formats
+fn pixel(format: gst_video::VideoFormat, argb: u32) -> Option<[u8; 4]> {
+    use gst_video::VideoFormat::*;
+
+    let a = (argb >> 24) as u8;
+    let r = (argb >> 16) as u8;
+    let g = (argb >> 8) as u8;
+    let b = argb as u8;
+
+    match format {
+        Rgbx | Rgba => Some([r, g, b, a]),
+        Bgrx | Bgra => Some([b, g, r, a]),
+        Xrgb | Argb => Some([a, r, g, b]),
+        Xbgr | Abgr => Some([a, b, g, r]),
+        _ => None,
+    }
+}
+
+// Draws the outline of the rectangle, clipped to the frame
+fn draw_rect(
+    data: &mut [u8],
+    stride: usize,
+    (width, height): (u32, u32),
+    (x, y, w, h): Rect,
+    line_width: u32,
+    pixel: &[u8; 4],
+) {
+    if w == 0 || h == 0 || x >= width || y >= height {
+        return;
+    }
+
+    let x_end = x.saturating_add(w).min(width);
+    let y_end = y.saturating_add(h).min(height);
+
+    for row in y..y_end {
+        let line = &mut data[(row as usize * stride)..];
+        let horizontal = row < y.saturating_add(line_width)
+            || row.saturating_add(line_width) >= y.saturating_add(h);
+
+        for col in x..x_end {
+            let vertical = col < x.saturating_add(line_width)
+                || col.saturating_add(line_width) >= x.saturating_add(w);
+            if horizontal || vertical {
+                let offset = col as usize * 4;
+                line[offset..(offset + 4)].copy_from_slice(pixel);
+            }
+        }
+    }
+}
+
+struct State {
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    info: Option<gst_video::VideoInfo>,
+    rects: Vec<Rect>,
+    // Running time of the buffer the current regions were attached to
+    rects_running_time: Option<u64>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            segment: gst::FormattedSegment::new(),
+            info: None,
+            rects: Vec::new(),
+            rects_running_time: None,
+        }
+    }
+}
+
+struct OnvifMetadataOverlay {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl OnvifMetadataOverlay {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "onvifmetadataoverlay",
+                gst::DebugColorFlags::empty(),
+                "ONVIF metadata overlay",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "ONVIF Metadata Overlay",
+            "Filter/Editor/Video",
+            "Draws the bounding boxes of detected objects onto the video",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "video/x-raw",
+            &[
+                (
+                    "format",
+                    &gst::List::new(&[
+                        &gst_video::VideoFormat::Rgbx.to_string(),
+                        &gst_video::VideoFormat::Bgrx.to_string(),
+                        &gst_video::VideoFormat::Xrgb.to_string(),
+                        &gst_video::VideoFormat::Xbgr.to_string(),
+                        &gst_video::VideoFormat::Rgba.to_string(),
+                        &gst_video::VideoFormat::Bgra.to_string(),
+                        &gst_video::VideoFormat::Argb.to_string(),
+                        &gst_video::VideoFormat::Abgr.to_string(),
+                    ]),
+                ),
+                ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                (
+                    "framerate",
+                    &gst::FractionRange::new(
+                        gst::Fraction::new(0, 1),
+                        gst::Fraction::new(i32::MAX, 1),
+                    ),
+                ),
+            ],
+        );
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseTransform> for OnvifMetadataOverlay {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("color", ..) => {
+                settings.color = value.get().unwrap();
+            }
+            Property::UInt("line-width", ..) => {
+                settings.line_width = value.get().unwrap();
+            }
+            Property::UInt64("hold-time", ..) => {
+                settings.hold_time = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("color", ..) => Ok(settings.color.to_value()),
+            Property::UInt("line-width", ..) => Ok(settings.line_width.to_value()),
+            Property::UInt64("hold-time", ..) => Ok(settings.hold_time.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for OnvifMetadataOverlay {}
+
+impl BaseTransformImpl<BaseTransform> for OnvifMetadataOverlay {
+    fn start(&self, _element: &BaseTransform) -> bool {
+        *self.state.lock().unwrap() = State::default();
+
+        true
+    }
+
+    fn set_caps(&self, _element: &BaseTransform, incaps: &gst::Caps, _outcaps: &gst::Caps) -> bool {
+        let info = match gst_video::VideoInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        self.state.lock().unwrap().info = Some(info);
+        true
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let mut state = self.state.lock().unwrap();
+                state.segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Ok(segment) => segment,
+                    Err(_) => gst::FormattedSegment::new(),
+                };
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.rects.clear();
+                state.rects_running_time = None;
+            }
+            _ => (),
+        }
+
+        element.parent_sink_event(event)
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        let info = match state.info {
+            None => {
+                gst_element_error!(element, gst::CoreError::Negotiation, ["Have no caps"]);
+                return gst::FlowReturn::NotNegotiated;
+            }
+            Some(ref info) => info.clone(),
+        };
+
+        let running_time = state.segment.to_running_time(buf.get_pts()).0;
+        let rois = roi::get_roi_metas(buf);
+        if !rois.is_empty() {
+            state.rects = rois
+                .iter()
+                .map(|roi| (roi.x, roi.y, roi.width, roi.height))
+                .collect();
+            state.rects_running_time = running_time;
+        }
+
+        let expired = match (running_time, state.rects_running_time) {
+            (Some(running_time), Some(rects_running_time)) => {
+                running_time >= rects_running_time.saturating_add(settings.hold_time)
+            }
+            _ => false,
+        };
+        if expired && !state.rects.is_empty() {
+            gst_debug!(self.cat, obj: element, "No new regions, removing old ones");
+            state.rects.clear();
+        }
+
+        if state.rects.is_empty() {
+            return gst::FlowReturn::Ok;
+        }
+
+        let pixel = match pixel(info.format(), settings.color) {
+            None => return gst::FlowReturn::NotNegotiated,
+            Some(pixel) => pixel,
+        };
+
+        let mut map = match buf.map_writable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map,
+        };
+
+        let stride = info.stride()[0] as usize;
+        for rect in &state.rects {
+            gst_trace!(self.cat, obj: element, "Drawing {:?}", rect);
+            draw_rect(
+                map.as_mut_slice(),
+                stride,
+                (info.width(), info.height()),
+                *rect,
+                settings.line_width,
+                &pixel,
+            );
+        }
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct OnvifMetadataOverlayStatic;
+
+impl ImplTypeStatic<BaseTransform> for OnvifMetadataOverlayStatic {
+    fn get_name(&self) -> &str {
+        "OnvifMetadataOverlay"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        OnvifMetadataOverlay::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        OnvifMetadataOverlay::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let onvifmetadataoverlay_static = OnvifMetadataOverlayStatic;
+    register_type(onvifmetadataoverlay_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_drawn(data: &[u8], x: usize, y: usize, color: &[u8; 4]) -> bool {
+        data[((y * 8 + x) * 4)..((y * 8 + x + 1) * 4)] == color[..]
+    }
+
+    #[test]
+    fn test_draw_rect() {
+        let mut data = vec![0u8; 8 * 4 * 6];
+        let color = [1, 2, 3, 4];
+
+        draw_rect(&mut data, 8 * 4, (8, 6), (1, 1, 4, 3), 1, &color);
+
+        for y in 0..6 {
+            for x in 0..8 {
+                let outline = (x >= 1 && x <= 4 && (y == 1 || y == 3))
+                    || (y >= 1 && y <= 3 && (x == 1 || x == 4));
+                assert_eq!(is_drawn(&data, x, y, &color), outline, "pixel {} {}", x, y);
+            }
+        }
+
+        // Clipped at the frame boundaries without panicking
+        draw_rect(&mut data, 8 * 4, (8, 6), (6, 4, 10, 10), 2, &color);
+        assert!(is_drawn(&data, 7, 5, &color));
+
+        assert_eq!(
+            pixel(gst_video::VideoFormat::Bgra, 0x8010_2030),
+            Some([0x30, 0x20, 0x10, 0x80])
+        );
+    }
+}
@@ -0,0 +1,581 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::meta;
+
+use std::collections::VecDeque;
+use std::str;
+use std::sync::Mutex;
+use std::{i64, u64};
+
+use metadata::{self, Frame};
+use roi::{self, Roi};
+
+// The video on the "sink" pad is passed through to the "src" pad. The ONVIF metadata stream of
+// the same camera, e.g. from the metadata track of its RTSP stream, is received on the
+// "meta_sink" pad. Every detected object of the video analytics frames is attached as
+// GstVideoRegionOfInterestMeta to the first video buffer with the same or a later running time.
+// The region is in pixels of the video, its type is the class of the object or "object" and its
+// id the ONVIF object id. These are the metas consumed by onvifmetadataoverlay and the
+// auto-follow mode of onvifptz.
+//
+// The remaining information of every object is attached as structure meta (see
+// `gst_plugin::meta`) with the same id:
+//
+//   "onvif-object, id=(int)..., likelihood=(double)..., utc-time=(string)..."
+//
+// likelihood and utc-time are only set if the camera provided them.
+//
+// The metadata can be split arbitrarily over the buffers, it is accumulated until a complete
+// MetadataStream document is available. All frames of a document get the running time of the
+// buffer that completed it.
+
+const DEFAULT_OFFSET: i64 = 0;
+const DEFAULT_MAX_LATENESS: u64 = gst::SECOND_VAL;
+// Upper bounds for queued frames if the video stalls and for incomplete documents
+const MAX_QUEUED: usize = 1000;
+const MAX_PENDING: usize = 1024 * 1024;
+
+static PROPERTIES: [Property; 2] = [
+    Property::Int64(
+        "offset",
+        "Offset",
+        "Offset in nanoseconds added to the running times of the metadata",
+        (i64::MIN, i64::MAX),
+        DEFAULT_OFFSET,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "max-lateness",
+        "Max Lateness",
+        "Maximum time in nanoseconds metadata can be late before it is dropped",
+        (0, u64::MAX),
+        DEFAULT_MAX_LATENESS,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    offset: i64,
+    max_lateness: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            offset: DEFAULT_OFFSET,
+            max_lateness: DEFAULT_MAX_LATENESS,
+        }
+    }
+}
+
+struct State {
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    meta_segment: gst::FormattedSegment<gst::ClockTime>,
+    size: Option<(u32, u32)>,
+    // Received data that does not form a complete document yet
+    pending: String,
+    // Frames with the running time of the video buffer they belong to
+    queue: VecDeque<(u64, Frame)>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            segment: gst::FormattedSegment::new(),
+            meta_segment: gst::FormattedSegment::new(),
+            size: None,
+            pending: String::new(),
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+fn add_object_metas(
+    buffer: &mut gst::BufferRef,
+    object: &metadata::Object,
+    utc_time: Option<&str>,
+    size: (u32, u32),
+) {
+    let (x, y, width, height) = metadata::to_pixels(&object.bbox, size.0, size.1);
+    let id = object.id as i32;
+
+    roi::add_roi_meta(
+        buffer,
+        &Roi {
+            roi_type: object.class.clone().unwrap_or_else(|| String::from("object")),
+            id: id,
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        },
+    );
+
+    let mut s = gst::Structure::new("onvif-object", &[("id", &id)]);
+    {
+        let s = s.get_mut().unwrap();
+        if let Some(likelihood) = object.likelihood {
+            s.set("likelihood", &likelihood);
+        }
+        if let Some(utc_time) = utc_time {
+            s.set("utc-time", &utc_time);
+        }
+    }
+    meta::add_structure_meta(buffer, s);
+}
+
+struct OnvifMetadataParse {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl OnvifMetadataParse {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "onvifmetadataparse",
+                gst::DebugColorFlags::empty(),
+                "ONVIF metadata parser",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "ONVIF Metadata Parser",
+            "Video/Metadata",
+            "Attaches the detected objects of an ONVIF metadata stream to the video by timestamp",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("video/x-raw", &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let caps = gst::Caps::new_simple("application/x-onvif-metadata", &[]);
+        let meta_sink_pad_template = gst::PadTemplate::new(
+            "meta_sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(meta_sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        meta::register();
+
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+        let templ = element.get_pad_template("meta_sink").unwrap();
+        let meta_sinkpad = gst::Pad::new_from_template(&templ, "meta_sink");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            OnvifMetadataParse::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |parse, element| parse.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            OnvifMetadataParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |parse, element| parse.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            OnvifMetadataParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |parse, element| parse.sink_query(pad, element, query),
+            )
+        });
+
+        meta_sinkpad.set_chain_function(|pad, parent, buffer| {
+            OnvifMetadataParse::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |parse, element| parse.meta_sink_chain(pad, element, buffer),
+            )
+        });
+        meta_sinkpad.set_event_function(|pad, parent, event| {
+            OnvifMetadataParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |parse, element| parse.meta_sink_event(pad, element, event),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            OnvifMetadataParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |parse, element| parse.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            OnvifMetadataParse::catch_panic_pad_function(
+                parent,
+                || false,
+                |parse, element| parse.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+        element.add_pad(&meta_sinkpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let parse = element
+            .get_impl()
+            .downcast_ref::<OnvifMetadataParse>()
+            .unwrap();
+        element.catch_panic(fallback, |element| f(parse, element))
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        _element: &Element,
+        mut buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let max_lateness = self.settings.lock().unwrap().max_lateness;
+
+        let mut frames = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(running_time) = state.segment.to_running_time(buffer.get_pts()).0 {
+                while state
+                    .queue
+                    .front()
+                    .map(|&(rt, _)| rt <= running_time)
+                    .unwrap_or(false)
+                {
+                    let (rt, frame) = state.queue.pop_front().unwrap();
+                    if running_time - rt > max_lateness {
+                        gst_debug!(
+                            self.cat,
+                            obj: pad,
+                            "Dropping frame {:?} for {}, too late for {}",
+                            frame.utc_time,
+                            gst::ClockTime::from(rt),
+                            gst::ClockTime::from(running_time)
+                        );
+                        continue;
+                    }
+
+                    let size = match state.size {
+                        None => {
+                            gst_warning!(self.cat, obj: pad, "No video size, dropping frame");
+                            continue;
+                        }
+                        Some(size) => size,
+                    };
+
+                    frames.push((frame, size));
+                }
+            }
+        }
+
+        if !frames.is_empty() {
+            let buffer = buffer.make_mut();
+            for (frame, size) in frames {
+                let utc_time = frame.utc_time.as_ref().map(|t| t.as_str());
+                for object in &frame.objects {
+                    gst_log!(self.cat, obj: pad, "Attaching object {:?}", object);
+                    add_object_metas(buffer, object, utc_time, size);
+                }
+            }
+        }
+
+        gst_log!(self.cat, obj: pad, "Pushing buffer {:?}", buffer);
+        self.srcpad.push(buffer)
+    }
+
+    fn meta_sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let offset = self.settings.lock().unwrap().offset;
+
+        let mut state = self.state.lock().unwrap();
+
+        {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            match str::from_utf8(map.as_slice()) {
+                Err(_) => {
+                    gst_warning!(self.cat, obj: pad, "Dropping invalid UTF-8 metadata");
+                    return gst::FlowReturn::Ok;
+                }
+                Ok(data) => state.pending.push_str(data),
+            }
+        }
+
+        // Metadata without timestamp is attached to the next video buffer
+        let running_time = state
+            .meta_segment
+            .to_running_time(buffer.get_pts())
+            .0
+            .unwrap_or(0);
+        let running_time = if offset < 0 {
+            running_time.saturating_sub((-offset) as u64)
+        } else {
+            running_time.saturating_add(offset as u64)
+        };
+
+        while let Some(end) = metadata::find_document_end(&state.pending) {
+            let document = state.pending.drain(..end).collect::<String>();
+
+            let frames = match metadata::parse_frames(&document) {
+                Err(err) => {
+                    gst_warning!(self.cat, obj: pad, "Dropping metadata: {}", err);
+                    continue;
+                }
+                Ok(frames) => frames,
+            };
+
+            for frame in frames {
+                gst_log!(
+                    self.cat,
+                    obj: pad,
+                    "Queueing frame {:?} with {} objects for {}",
+                    frame.utc_time,
+                    frame.objects.len(),
+                    gst::ClockTime::from(running_time)
+                );
+
+                if state.queue.len() >= MAX_QUEUED {
+                    gst_warning!(self.cat, obj: pad, "Too many frames queued, dropping oldest");
+                    state.queue.pop_front();
+                }
+
+                // Keep the queue sorted even if the offset changed in between
+                let idx = state
+                    .queue
+                    .iter()
+                    .rposition(|&(rt, _)| rt <= running_time)
+                    .map(|idx| idx + 1)
+                    .unwrap_or(0);
+                state.queue.insert(idx, (running_time, frame));
+            }
+        }
+
+        if state.pending.len() > MAX_PENDING {
+            gst_warning!(self.cat, obj: pad, "No complete document found, dropping data");
+            state.pending.clear();
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let size = e.get_caps().get_structure(0).and_then(|s| {
+                    match (s.get::<i32>("width"), s.get::<i32>("height")) {
+                        (Some(width), Some(height)) if width > 0 && height > 0 => {
+                            Some((width as u32, height as u32))
+                        }
+                        _ => None,
+                    }
+                });
+
+                self.state.lock().unwrap().size = size;
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                self.state.lock().unwrap().segment = segment;
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.segment = gst::FormattedSegment::new();
+                state.queue.clear();
+            }
+            _ => (),
+        }
+
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.srcpad.push_event(event)
+    }
+
+    fn meta_sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        // Events of the metadata stream are never forwarded, only its content ends up in the
+        // video stream
+        match event.view() {
+            EventView::Segment(e) => {
+                if let Ok(segment) = e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    self.state.lock().unwrap().meta_segment = segment;
+                }
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.meta_segment = gst::FormattedSegment::new();
+                state.pending.clear();
+            }
+            _ => (),
+        }
+
+        true
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, _element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding query {:?}", query);
+        self.srcpad.peer_query(query)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, _element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding query {:?}", query);
+        self.sinkpad.peer_query(query)
+    }
+}
+
+impl ObjectImpl<Element> for OnvifMetadataParse {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int64("offset", ..) => {
+                settings.offset = value.get().unwrap();
+            }
+            Property::UInt64("max-lateness", ..) => {
+                settings.max_lateness = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int64("offset", ..) => Ok(settings.offset.to_value()),
+            Property::UInt64("max-lateness", ..) => Ok(settings.max_lateness.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for OnvifMetadataParse {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct OnvifMetadataParseStatic;
+
+impl ImplTypeStatic<Element> for OnvifMetadataParseStatic {
+    fn get_name(&self) -> &str {
+        "OnvifMetadataParse"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        OnvifMetadataParse::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        OnvifMetadataParse::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let onvifmetadataparse_static = OnvifMetadataParseStatic;
+    register_type(onvifmetadataparse_static)
+}
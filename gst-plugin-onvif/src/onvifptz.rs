@@ -0,0 +1,650 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use reqwest::Client;
+
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use roi::{self, Roi};
+use soap::{self, Credentials};
+
+// Passes the stream through and controls the PTZ service of an ONVIF camera.
+//
+// Commands are sent with the "move", "absolute-move", "stop" and "goto-preset" action signals.
+// With auto-follow enabled, the GstVideoRegionOfInterestMeta of the buffers, e.g. from
+// onvifmetadataparse or a tracker, make the camera pan and tilt towards the center of the
+// largest region of the buffer with a speed proportional to its distance from the center of the
+// frame. The camera stops once the region is inside the dead zone, or if no region was received
+// for a second.
+//
+// The SOAP requests are done from a separate thread so that the stream is never blocked.
+
+const DEFAULT_LOCATION: Option<&str> = None;
+const DEFAULT_USER: Option<&str> = None;
+const DEFAULT_PASSWORD: Option<&str> = None;
+const DEFAULT_PROFILE_TOKEN: Option<&str> = None;
+const DEFAULT_AUTO_FOLLOW: bool = false;
+const DEFAULT_DEAD_ZONE: f64 = 0.1;
+const DEFAULT_SPEED: f64 = 0.5;
+
+const REQUEST_TIMEOUT: u64 = 5;
+const ROI_TIMEOUT: u64 = gst::SECOND_VAL;
+// Minimum velocity change before a new move command is sent in auto-follow mode
+const VELOCITY_THRESHOLD: f64 = 0.05;
+
+static PROPERTIES: [Property; 7] = [
+    Property::String(
+        "location",
+        "Location",
+        "URL of the PTZ service of the camera",
+        DEFAULT_LOCATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "user",
+        "User",
+        "User name for authentication",
+        DEFAULT_USER,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "password",
+        "Password",
+        "Password for authentication",
+        DEFAULT_PASSWORD,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "profile-token",
+        "Profile Token",
+        "Token of the media profile with the PTZ configuration",
+        DEFAULT_PROFILE_TOKEN,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "auto-follow",
+        "Auto Follow",
+        "Follow the region of interest metas of the buffers",
+        DEFAULT_AUTO_FOLLOW,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "dead-zone",
+        "Dead Zone",
+        "Distance from the frame center, relative to the frame size, in which the camera stops",
+        (0.0, 1.0),
+        DEFAULT_DEAD_ZONE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "speed",
+        "Speed",
+        "Maximum pan/tilt velocity in auto-follow mode",
+        (0.0, 1.0),
+        DEFAULT_SPEED,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    profile_token: Option<String>,
+    auto_follow: bool,
+    dead_zone: f64,
+    speed: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: DEFAULT_LOCATION.map(String::from),
+            user: DEFAULT_USER.map(String::from),
+            password: DEFAULT_PASSWORD.map(String::from),
+            profile_token: DEFAULT_PROFILE_TOKEN.map(String::from),
+            auto_follow: DEFAULT_AUTO_FOLLOW,
+            dead_zone: DEFAULT_DEAD_ZONE,
+            speed: DEFAULT_SPEED,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    // Pan, tilt and zoom velocities in -1.0..1.0
+    ContinuousMove(f64, f64, f64),
+    // Pan and tilt in -1.0..1.0, zoom in 0.0..1.0
+    AbsoluteMove(f64, f64, f64),
+    Stop,
+    GotoPreset(String),
+}
+
+impl Command {
+    // SOAP action and body of the command
+    fn to_soap(&self, profile_token: &str) -> (String, String) {
+        let profile_token = soap::escape(profile_token);
+
+        let (name, body) = match *self {
+            Command::ContinuousMove(pan, tilt, zoom) => (
+                "ContinuousMove",
+                format!(
+                    "<tptz:ContinuousMove><tptz:ProfileToken>{}</tptz:ProfileToken>\
+                     <tptz:Velocity><tt:PanTilt x=\"{:.3}\" y=\"{:.3}\"/>\
+                     <tt:Zoom x=\"{:.3}\"/></tptz:Velocity></tptz:ContinuousMove>",
+                    profile_token, pan, tilt, zoom
+                ),
+            ),
+            Command::AbsoluteMove(pan, tilt, zoom) => (
+                "AbsoluteMove",
+                format!(
+                    "<tptz:AbsoluteMove><tptz:ProfileToken>{}</tptz:ProfileToken>\
+                     <tptz:Position><tt:PanTilt x=\"{:.3}\" y=\"{:.3}\"/>\
+                     <tt:Zoom x=\"{:.3}\"/></tptz:Position></tptz:AbsoluteMove>",
+                    profile_token, pan, tilt, zoom
+                ),
+            ),
+            Command::Stop => (
+                "Stop",
+                format!(
+                    "<tptz:Stop><tptz:ProfileToken>{}</tptz:ProfileToken>\
+                     <tptz:PanTilt>true</tptz:PanTilt><tptz:Zoom>true</tptz:Zoom></tptz:Stop>",
+                    profile_token
+                ),
+            ),
+            Command::GotoPreset(ref preset) => (
+                "GotoPreset",
+                format!(
+                    "<tptz:GotoPreset><tptz:ProfileToken>{}</tptz:ProfileToken>\
+                     <tptz:PresetToken>{}</tptz:PresetToken></tptz:GotoPreset>",
+                    profile_token,
+                    soap::escape(preset)
+                ),
+            ),
+        };
+
+        (format!("{}/{}", soap::PTZ_NS, name), body)
+    }
+}
+
+// Pan/tilt velocity towards the center of the region, or None inside the dead zone. The
+// image y axis points down, the ONVIF tilt axis up.
+fn follow_velocity(
+    (x, y, w, h): (u32, u32, u32, u32),
+    (width, height): (u32, u32),
+    dead_zone: f64,
+    speed: f64,
+) -> Option<(f64, f64)> {
+    let center_x = f64::from(x) + f64::from(w) / 2.0;
+    let center_y = f64::from(y) + f64::from(h) / 2.0;
+    let dx = (2.0 * center_x / f64::from(width) - 1.0).max(-1.0).min(1.0);
+    let dy = (2.0 * center_y / f64::from(height) - 1.0).max(-1.0).min(1.0);
+
+    if dx.abs() <= dead_zone && dy.abs() <= dead_zone {
+        return None;
+    }
+
+    Some((dx * speed, -dy * speed))
+}
+
+struct State {
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    size: Option<(u32, u32)>,
+    // Running time of the last buffer
+    running_time: Option<u64>,
+    // Current auto-follow velocity, None if stopped
+    velocity: Option<(f64, f64)>,
+    last_roi: Option<u64>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            segment: gst::FormattedSegment::new(),
+            size: None,
+            running_time: None,
+            velocity: None,
+            last_roi: None,
+        }
+    }
+}
+
+struct OnvifPtz {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    sender: Mutex<Option<mpsc::Sender<Command>>>,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl OnvifPtz {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "onvifptz",
+                gst::DebugColorFlags::empty(),
+                "ONVIF PTZ control",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+            sender: Mutex::new(None),
+            thread: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "ONVIF PTZ",
+            "Generic",
+            "Controls the pan, tilt and zoom of an ONVIF camera, optionally following regions \
+             of interest",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.add_action_signal(
+            "move",
+            &[glib::Type::F64, glib::Type::F64, glib::Type::F64],
+            glib::Type::Bool,
+            |args| {
+                let element = args[0].get::<BaseTransform>().unwrap();
+                let pan = args[1].get::<f64>().unwrap();
+                let tilt = args[2].get::<f64>().unwrap();
+                let zoom = args[3].get::<f64>().unwrap();
+
+                let ptz = element.get_impl().downcast_ref::<OnvifPtz>().unwrap();
+                Some(
+                    ptz.send(&element, Command::ContinuousMove(pan, tilt, zoom))
+                        .to_value(),
+                )
+            },
+        );
+
+        klass.add_action_signal(
+            "absolute-move",
+            &[glib::Type::F64, glib::Type::F64, glib::Type::F64],
+            glib::Type::Bool,
+            |args| {
+                let element = args[0].get::<BaseTransform>().unwrap();
+                let pan = args[1].get::<f64>().unwrap();
+                let tilt = args[2].get::<f64>().unwrap();
+                let zoom = args[3].get::<f64>().unwrap();
+
+                let ptz = element.get_impl().downcast_ref::<OnvifPtz>().unwrap();
+                Some(
+                    ptz.send(&element, Command::AbsoluteMove(pan, tilt, zoom))
+                        .to_value(),
+                )
+            },
+        );
+
+        klass.add_action_signal("stop", &[], glib::Type::Bool, |args| {
+            let element = args[0].get::<BaseTransform>().unwrap();
+
+            let ptz = element.get_impl().downcast_ref::<OnvifPtz>().unwrap();
+            Some(ptz.send(&element, Command::Stop).to_value())
+        });
+
+        klass.add_action_signal(
+            "goto-preset",
+            &[glib::Type::String],
+            glib::Type::Bool,
+            |args| {
+                let element = args[0].get::<BaseTransform>().unwrap();
+                let preset = args[1].get::<String>().unwrap_or_default();
+
+                let ptz = element.get_impl().downcast_ref::<OnvifPtz>().unwrap();
+                Some(ptz.send(&element, Command::GotoPreset(preset)).to_value())
+            },
+        );
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, true, true);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    fn send(&self, element: &BaseTransform, command: Command) -> bool {
+        gst_debug!(self.cat, obj: element, "Sending {:?}", command);
+
+        match *self.sender.lock().unwrap() {
+            None => {
+                gst_warning!(self.cat, obj: element, "Not started, dropping {:?}", command);
+                false
+            }
+            Some(ref sender) => sender.send(command).is_ok(),
+        }
+    }
+
+    fn follow(&self, element: &BaseTransform, roi: &Roi) {
+        let rect = (roi.x, roi.y, roi.width, roi.height);
+
+        let settings = self.settings.lock().unwrap().clone();
+        let command = {
+            let mut state = self.state.lock().unwrap();
+            let size = match state.size {
+                None => return,
+                Some(size) => size,
+            };
+            state.last_roi = state.running_time;
+
+            let velocity = follow_velocity(rect, size, settings.dead_zone, settings.speed);
+            match (velocity, state.velocity) {
+                (None, None) => None,
+                (None, Some(_)) => {
+                    state.velocity = None;
+                    Some(Command::Stop)
+                }
+                (Some((pan, tilt)), current) => {
+                    let changed = current
+                        .map(|(current_pan, current_tilt)| {
+                            (pan - current_pan).abs() >= VELOCITY_THRESHOLD
+                                || (tilt - current_tilt).abs() >= VELOCITY_THRESHOLD
+                        })
+                        .unwrap_or(true);
+
+                    if changed {
+                        state.velocity = Some((pan, tilt));
+                        Some(Command::ContinuousMove(pan, tilt, 0.0))
+                    } else {
+                        None
+                    }
+                }
+            }
+        };
+
+        if let Some(command) = command {
+            self.send(element, command);
+        }
+    }
+}
+
+// Runs the SOAP requests until the sender is dropped
+fn run_commands(
+    cat: gst::DebugCategory,
+    element: BaseTransform,
+    receiver: mpsc::Receiver<Command>,
+    client: Client,
+    settings: Settings,
+) {
+    let location = settings.location.unwrap();
+    let profile_token = settings.profile_token.unwrap();
+    let credentials = settings.user.map(|user| Credentials {
+        user: user,
+        password: settings.password.unwrap_or_default(),
+    });
+
+    for command in receiver.iter() {
+        let (action, body) = command.to_soap(&profile_token);
+
+        match soap::call(&client, &location, &action, &body, credentials.as_ref()) {
+            Ok(_) => gst_debug!(cat, obj: &element, "{:?} done", command),
+            Err(err) => {
+                gst_element_warning!(element, gst::ResourceError::Write, ["{}", err]);
+            }
+        }
+    }
+}
+
+impl ObjectImpl<BaseTransform> for OnvifPtz {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => {
+                settings.location = value.get();
+            }
+            Property::String("user", ..) => {
+                settings.user = value.get();
+            }
+            Property::String("password", ..) => {
+                settings.password = value.get();
+            }
+            Property::String("profile-token", ..) => {
+                settings.profile_token = value.get();
+            }
+            Property::Boolean("auto-follow", ..) => {
+                settings.auto_follow = value.get().unwrap();
+            }
+            Property::Double("dead-zone", ..) => {
+                settings.dead_zone = value.get().unwrap();
+            }
+            Property::Double("speed", ..) => {
+                settings.speed = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => Ok(settings.location.to_value()),
+            Property::String("user", ..) => Ok(settings.user.to_value()),
+            Property::String("password", ..) => Ok(settings.password.to_value()),
+            Property::String("profile-token", ..) => Ok(settings.profile_token.to_value()),
+            Property::Boolean("auto-follow", ..) => Ok(settings.auto_follow.to_value()),
+            Property::Double("dead-zone", ..) => Ok(settings.dead_zone.to_value()),
+            Property::Double("speed", ..) => Ok(settings.speed.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for OnvifPtz {}
+
+impl BaseTransformImpl<BaseTransform> for OnvifPtz {
+    fn start(&self, element: &BaseTransform) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+        *self.state.lock().unwrap() = State::default();
+
+        if settings.location.is_none() || settings.profile_token.is_none() {
+            gst_element_error!(
+                element,
+                gst::ResourceError::Settings,
+                ["location and profile-token have to be set"]
+            );
+            return false;
+        }
+
+        let mut builder = Client::builder();
+        builder.timeout(Duration::from_secs(REQUEST_TIMEOUT));
+        let client = match builder.build() {
+            Err(err) => {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::OpenWrite,
+                    ["Failed to create HTTP client: {}", err]
+                );
+                return false;
+            }
+            Ok(client) => client,
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        let cat = self.cat;
+        let element_clone = element.clone();
+        let handle = thread::spawn(move || {
+            run_commands(cat, element_clone, receiver, client, settings);
+        });
+
+        *self.sender.lock().unwrap() = Some(sender);
+        *self.thread.lock().unwrap() = Some(handle);
+
+        true
+    }
+
+    fn stop(&self, element: &BaseTransform) -> bool {
+        let moving = self.state.lock().unwrap().velocity.is_some();
+        if moving {
+            self.send(element, Command::Stop);
+        }
+
+        // Pending commands are still sent before the thread finishes
+        *self.sender.lock().unwrap() = None;
+        if let Some(handle) = self.thread.lock().unwrap().take() {
+            let _ = handle.join();
+        }
+
+        true
+    }
+
+    fn set_caps(&self, _element: &BaseTransform, incaps: &gst::Caps, _outcaps: &gst::Caps) -> bool {
+        let size = incaps.get_structure(0).and_then(|s| {
+            match (s.get::<i32>("width"), s.get::<i32>("height")) {
+                (Some(width), Some(height)) if width > 0 && height > 0 => {
+                    Some((width as u32, height as u32))
+                }
+                _ => None,
+            }
+        });
+
+        self.state.lock().unwrap().size = size;
+        true
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: gst::Event) -> bool {
+        if let gst::EventView::Segment(e) = event.view() {
+            let mut state = self.state.lock().unwrap();
+            state.segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                Ok(segment) => segment,
+                Err(_) => gst::FormattedSegment::new(),
+            };
+        }
+
+        element.parent_sink_event(event)
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let running_time = {
+            let mut state = self.state.lock().unwrap();
+            let running_time = state.segment.to_running_time(buf.get_pts()).0;
+            if running_time.is_some() {
+                state.running_time = running_time;
+            }
+            running_time
+        };
+
+        let auto_follow = self.settings.lock().unwrap().auto_follow;
+        if auto_follow {
+            let largest = roi::get_roi_metas(buf)
+                .into_iter()
+                .max_by_key(|roi| u64::from(roi.width) * u64::from(roi.height));
+            if let Some(roi) = largest {
+                gst_log!(self.cat, obj: element, "Following {:?}", roi);
+                self.follow(element, &roi);
+            }
+        }
+
+        let stop = {
+            let mut state = self.state.lock().unwrap();
+
+            // Stop if the tracked region disappeared
+            let lost = match (running_time, state.last_roi) {
+                (Some(running_time), Some(last_roi)) => running_time >= last_roi + ROI_TIMEOUT,
+                _ => false,
+            };
+
+            if lost && state.velocity.is_some() {
+                state.velocity = None;
+                state.last_roi = None;
+                true
+            } else {
+                false
+            }
+        };
+
+        if stop {
+            gst_debug!(self.cat, obj: element, "Lost region of interest, stopping");
+            self.send(element, Command::Stop);
+        }
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct OnvifPtzStatic;
+
+impl ImplTypeStatic<BaseTransform> for OnvifPtzStatic {
+    fn get_name(&self) -> &str {
+        "OnvifPtz"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        OnvifPtz::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        OnvifPtz::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let onvifptz_static = OnvifPtzStatic;
+    register_type(onvifptz_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_follow_velocity() {
+        // Centered region is inside the dead zone
+        assert_eq!(follow_velocity((300, 200, 40, 80), (640, 480), 0.1, 0.5), None);
+        // Region in the top right corner
+        assert_eq!(
+            follow_velocity((560, 0, 80, 48), (640, 480), 0.1, 0.5),
+            Some((0.4375, 0.45))
+        );
+
+        let (action, body) = Command::GotoPreset(String::from("1")).to_soap("Profile&1");
+        assert_eq!(action, "http://www.onvif.org/ver20/ptz/wsdl/GotoPreset");
+        assert_eq!(
+            body,
+            "<tptz:GotoPreset><tptz:ProfileToken>Profile&amp;1</tptz:ProfileToken>\
+             <tptz:PresetToken>1</tptz:PresetToken></tptz:GotoPreset>"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Access to the GstVideoRegionOfInterestMeta of video buffers, which carry the detected objects
+// between onvifmetadataparse, onvifmetadataoverlay and onvifptz. Other elements that handle
+// regions of interest, e.g. encoders, can use them too.
+
+use std::ptr;
+
+use glib_ffi;
+use gst_ffi;
+use gst_video_ffi;
+
+use glib::translate::*;
+use gst;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Roi {
+    pub roi_type: String,
+    pub id: i32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Attaches the region of interest to the buffer
+pub fn add_roi_meta(buffer: &mut gst::BufferRef, roi: &Roi) {
+    unsafe {
+        let meta = gst_video_ffi::gst_video_buffer_add_video_region_of_interest_meta(
+            buffer.as_mut_ptr(),
+            roi.roi_type.to_glib_none().0,
+            roi.x,
+            roi.y,
+            roi.width,
+            roi.height,
+        );
+        assert!(!meta.is_null(), "Failed to add meta, buffer not writable");
+        (*meta).id = roi.id;
+    }
+}
+
+// Returns all regions of interest attached to the buffer
+pub fn get_roi_metas(buffer: &gst::BufferRef) -> Vec<Roi> {
+    let mut rois = Vec::new();
+
+    unsafe {
+        let api = gst_video_ffi::gst_video_region_of_interest_meta_api_get_type();
+        let mut state = ptr::null_mut();
+        loop {
+            let meta = gst_ffi::gst_buffer_iterate_meta(
+                buffer.as_ptr() as *mut gst_ffi::GstBuffer,
+                &mut state,
+            );
+            if meta.is_null() {
+                break;
+            }
+            if (*(*meta).info).api != api {
+                continue;
+            }
+
+            let meta = &*(meta as *const gst_video_ffi::GstVideoRegionOfInterestMeta);
+            rois.push(Roi {
+                roi_type: from_glib_none(glib_ffi::g_quark_to_string(meta.roi_type)),
+                id: meta.id,
+                x: meta.x,
+                y: meta.y,
+                width: meta.w,
+                height: meta.h,
+            });
+        }
+    }
+
+    rois
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roi_meta() {
+        gst::init().unwrap();
+
+        let roi = Roi {
+            roi_type: String::from("Human"),
+            id: 5,
+            x: 10,
+            y: 20,
+            width: 30,
+            height: 40,
+        };
+
+        let mut buffer = gst::Buffer::with_size(4).unwrap();
+        assert!(get_roi_metas(&buffer).is_empty());
+        add_roi_meta(buffer.get_mut().unwrap(), &roi);
+        assert_eq!(get_roi_metas(&buffer), vec![roi]);
+    }
+}
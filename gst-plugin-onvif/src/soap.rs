@@ -0,0 +1,209 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Minimal ONVIF SOAP 1.2 client
+//
+// Requests are authenticated with a WS-Security UsernameToken with password digest, which all
+// ONVIF conformant devices have to support:
+//
+//   digest = base64(sha1(nonce + created + password))
+//
+// with the nonce being random bytes and created the current UTC time.
+
+use base64;
+use rand;
+use reqwest::Client;
+use reqwest::header::Headers;
+use sha1::Sha1;
+
+use std::fmt::Write;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const PTZ_NS: &str = "http://www.onvif.org/ver20/ptz/wsdl";
+
+const SCHEMA_NS: &str = "http://www.onvif.org/ver10/schema";
+const WSSE_NS: &str =
+    "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd";
+const WSU_NS: &str =
+    "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd";
+const PASSWORD_DIGEST: &str =
+    "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-username-token-profile-1.0\
+     #PasswordDigest";
+const BASE64_BINARY: &str =
+    "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-soap-message-security-1.0\
+     #Base64Binary";
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Formats seconds since the UNIX epoch as xs:dateTime in UTC
+fn format_created(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let secs = secs % 86_400;
+
+    // Civil date from days since 1970-01-01, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+fn security_header(credentials: &Credentials, nonce: &[u8], created: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(nonce);
+    sha1.update(created.as_bytes());
+    sha1.update(credentials.password.as_bytes());
+    let digest = base64::encode(&sha1.digest().bytes());
+
+    format!(
+        "<wsse:Security xmlns:wsse=\"{}\" xmlns:wsu=\"{}\">\
+         <wsse:UsernameToken>\
+         <wsse:Username>{}</wsse:Username>\
+         <wsse:Password Type=\"{}\">{}</wsse:Password>\
+         <wsse:Nonce EncodingType=\"{}\">{}</wsse:Nonce>\
+         <wsu:Created>{}</wsu:Created>\
+         </wsse:UsernameToken>\
+         </wsse:Security>",
+        WSSE_NS,
+        WSU_NS,
+        escape(&credentials.user),
+        PASSWORD_DIGEST,
+        digest,
+        BASE64_BINARY,
+        base64::encode(nonce),
+        created
+    )
+}
+
+// Wraps the body in a SOAP envelope with the tptz and tt namespace prefixes declared
+pub fn envelope(body: &str, credentials: Option<&Credentials>) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    write!(
+        xml,
+        "<s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\" \
+         xmlns:tptz=\"{}\" xmlns:tt=\"{}\">",
+        PTZ_NS, SCHEMA_NS
+    ).unwrap();
+
+    if let Some(credentials) = credentials {
+        let nonce = rand::random::<[u8; 16]>();
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        write!(
+            xml,
+            "<s:Header>{}</s:Header>",
+            security_header(credentials, &nonce, &format_created(created))
+        ).unwrap();
+    }
+
+    write!(xml, "<s:Body>{}</s:Body></s:Envelope>", body).unwrap();
+
+    xml
+}
+
+// Sends the request and returns the response body. SOAP faults are returned as errors.
+pub fn call(
+    client: &Client,
+    url: &str,
+    action: &str,
+    body: &str,
+    credentials: Option<&Credentials>,
+) -> Result<String, String> {
+    let mut headers = Headers::new();
+    headers.set_raw(
+        "Content-Type",
+        format!(
+            "application/soap+xml; charset=utf-8; action=\"{}\"",
+            action
+        ),
+    );
+
+    let mut response = client
+        .post(url)
+        .headers(headers)
+        .body(envelope(body, credentials))
+        .send()
+        .map_err(|err| format!("Failed to send {} to {}: {}", action, url, err))?;
+
+    let mut content = String::new();
+    response
+        .read_to_string(&mut content)
+        .map_err(|err| format!("Failed to read response from {}: {}", url, err))?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "{} failed with {}: {}",
+            action,
+            response.status(),
+            content
+        ));
+    }
+
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_security_header() {
+        assert_eq!(format_created(1_516_616_430), "2018-01-22T10:20:30Z");
+        assert_eq!(format_created(951_782_400), "2000-02-29T00:00:00Z");
+
+        let credentials = Credentials {
+            user: String::from("admin<"),
+            password: String::from("secret"),
+        };
+        let nonce = (0..16).collect::<Vec<u8>>();
+        let header = security_header(&credentials, &nonce, "2018-01-22T10:20:30Z");
+
+        assert!(header.contains("<wsse:Username>admin&lt;</wsse:Username>"));
+        assert!(header.contains(">kMS5+dK2lETVJjtV0AVzfYbuybI=</wsse:Password>"));
+        assert!(header.contains(">AAECAwQFBgcICQoLDA0ODw==</wsse:Nonce>"));
+        assert!(header.contains("<wsu:Created>2018-01-22T10:20:30Z</wsu:Created>"));
+    }
+}
@@ -0,0 +1,616 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Google congestion control (draft-ietf-rmcat-gcc-02) based on transport-wide congestion control
+// feedback (draft-holmer-rmcat-transport-wide-cc-extensions-01)
+//
+// The sender remembers the send time and size of every packet with a transport-wide sequence
+// number. The receiver reports the arrival times of these packets in RTCP feedback, from which
+// two estimates are derived:
+//
+//  - delay based: the one-way delay variation between groups of packets is fed into a trendline
+//    filter. A rising trend means that queues are building up (overuse), which decreases the
+//    bitrate to 85% of the received bitrate. Otherwise the bitrate is increased by up to 8% per
+//    second.
+//  - loss based: more than 10% loss decreases the bitrate, less than 2% allows increasing it.
+//
+// The estimate is the minimum of both.
+
+use std::collections::{HashMap, VecDeque};
+
+use rtp;
+
+pub const RTCP_RTPFB: u8 = 205;
+pub const TWCC_FMT: u8 = 15;
+
+// Sent packets remembered for matching feedback
+const MAX_SENT_PACKETS: usize = 10_000;
+// Packets sent within this many microseconds are grouped together
+const BURST_TIME: i64 = 5_000;
+const TRENDLINE_WINDOW: usize = 20;
+const TRENDLINE_SMOOTHING: f64 = 0.9;
+const TRENDLINE_GAIN: f64 = 4.0;
+const MAX_DELTAS: usize = 60;
+// Time in milliseconds the trend has to be above the threshold for overuse
+const OVERUSE_TIME: f64 = 10.0;
+const INITIAL_THRESHOLD: f64 = 12.5;
+const THRESHOLD_UP: f64 = 0.0087;
+const THRESHOLD_DOWN: f64 = 0.039;
+// Window for measuring the received bitrate in microseconds
+const RECEIVED_RATE_WINDOW: i64 = 500_000;
+// Minimum time in microseconds between two decreases
+const DECREASE_INTERVAL: i64 = 200_000;
+
+fn read_u16(data: &[u8]) -> u16 {
+    (u16::from(data[0]) << 8) | u16::from(data[1])
+}
+
+/// Parses the feedback control information of a transport-wide congestion control feedback
+/// packet. Returns the sequence numbers of all reported packets with their arrival time in
+/// microseconds, or `None` if the packet was lost.
+pub fn parse_feedback(fci: &[u8]) -> Option<Vec<(u16, Option<i64>)>> {
+    if fci.len() < 8 {
+        return None;
+    }
+
+    let base_seqnum = read_u16(fci);
+    let count = read_u16(&fci[2..]) as usize;
+    // 24 bit signed, in multiples of 64ms
+    let mut reference = (i32::from(fci[4]) << 16) | (i32::from(fci[5]) << 8) | i32::from(fci[6]);
+    if reference & 0x0080_0000 != 0 {
+        reference -= 0x0100_0000;
+    }
+
+    let mut symbols = Vec::with_capacity(count);
+    let mut offset = 8;
+    while symbols.len() < count {
+        if fci.len() < offset + 2 {
+            return None;
+        }
+        let chunk = read_u16(&fci[offset..]);
+        offset += 2;
+
+        let remaining = count - symbols.len();
+        if chunk & 0x8000 == 0 {
+            // Run length chunk
+            let symbol = ((chunk >> 13) & 0x3) as u8;
+            let run = (chunk & 0x1fff) as usize;
+            symbols.extend((0..run.min(remaining)).map(|_| symbol));
+        } else if chunk & 0x4000 == 0 {
+            // Status vector chunk with 14 one-bit symbols
+            symbols.extend((0..14.min(remaining)).map(|i| ((chunk >> (13 - i)) & 0x1) as u8));
+        } else {
+            // Status vector chunk with 7 two-bit symbols
+            symbols.extend((0..7.min(remaining)).map(|i| ((chunk >> (12 - 2 * i)) & 0x3) as u8));
+        }
+    }
+
+    let mut time = i64::from(reference) * 64_000;
+    let mut packets = Vec::with_capacity(count);
+    for (i, symbol) in symbols.into_iter().enumerate() {
+        let seqnum = base_seqnum.wrapping_add(i as u16);
+        let delta = match symbol {
+            0 => {
+                packets.push((seqnum, None));
+                continue;
+            }
+            1 => {
+                let delta = *fci.get(offset)?;
+                offset += 1;
+                i64::from(delta)
+            }
+            2 => {
+                if fci.len() < offset + 2 {
+                    return None;
+                }
+                let delta = read_u16(&fci[offset..]) as i16;
+                offset += 2;
+                i64::from(delta)
+            }
+            _ => return None,
+        };
+
+        // Deltas are in multiples of 250µs
+        time += delta * 250;
+        packets.push((seqnum, Some(time)));
+    }
+
+    Some(packets)
+}
+
+/// Parses all transport-wide congestion control feedback packets of an RTCP compound packet.
+pub fn parse_rtcp(data: &[u8]) -> Vec<Vec<(u16, Option<i64>)>> {
+    let mut feedback = Vec::new();
+
+    let mut offset = 0;
+    while data.len() >= offset + 4 {
+        if data[offset] >> 6 != rtp::VERSION {
+            break;
+        }
+
+        let fmt = data[offset] & 0x1f;
+        let pt = data[offset + 1];
+        let len = 4 * (read_u16(&data[offset + 2..]) as usize + 1);
+        if data.len() < offset + len {
+            break;
+        }
+
+        // Header and the SSRCs of the sender and media source come before the FCI
+        if pt == RTCP_RTPFB && fmt == TWCC_FMT && len >= 12 {
+            if let Some(packets) = parse_feedback(&data[offset + 12..offset + len]) {
+                feedback.push(packets);
+            }
+        }
+
+        offset += len;
+    }
+
+    feedback
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    Normal,
+    Overuse,
+    Underuse,
+}
+
+// Linear regression over the smoothed accumulated delay variation
+struct Trendline {
+    accumulated: f64,
+    smoothed: f64,
+    // Arrival time and smoothed delay in milliseconds
+    samples: VecDeque<(f64, f64)>,
+    num_deltas: usize,
+    first_arrival: Option<i64>,
+    trend: f64,
+}
+
+impl Trendline {
+    fn new() -> Self {
+        Trendline {
+            accumulated: 0.0,
+            smoothed: 0.0,
+            samples: VecDeque::new(),
+            num_deltas: 0,
+            first_arrival: None,
+            trend: 0.0,
+        }
+    }
+
+    fn update(&mut self, delay: f64, arrival: i64) -> f64 {
+        let first_arrival = *self.first_arrival.get_or_insert(arrival);
+
+        self.num_deltas = (self.num_deltas + 1).min(MAX_DELTAS);
+        self.accumulated += delay;
+        self.smoothed =
+            TRENDLINE_SMOOTHING * self.smoothed + (1.0 - TRENDLINE_SMOOTHING) * self.accumulated;
+
+        self.samples
+            .push_back(((arrival - first_arrival) as f64 / 1000.0, self.smoothed));
+        if self.samples.len() > TRENDLINE_WINDOW {
+            self.samples.pop_front();
+        }
+
+        if self.samples.len() == TRENDLINE_WINDOW {
+            let n = self.samples.len() as f64;
+            let mean_x = self.samples.iter().map(|&(x, _)| x).sum::<f64>() / n;
+            let mean_y = self.samples.iter().map(|&(_, y)| y).sum::<f64>() / n;
+            let (num, denom) = self.samples
+                .iter()
+                .fold((0.0, 0.0), |(num, denom), &(x, y)| {
+                    (
+                        num + (x - mean_x) * (y - mean_y),
+                        denom + (x - mean_x) * (x - mean_x),
+                    )
+                });
+            if denom != 0.0 {
+                self.trend = num / denom;
+            }
+        }
+
+        self.trend
+    }
+}
+
+// Compares the trend against an adaptive threshold
+struct OveruseDetector {
+    threshold: f64,
+    last_update: Option<i64>,
+    // Time in milliseconds the trend was above the threshold, negative if it is not
+    time_over: f64,
+    count_over: u32,
+    prev_trend: f64,
+}
+
+impl OveruseDetector {
+    fn new() -> Self {
+        OveruseDetector {
+            threshold: INITIAL_THRESHOLD,
+            last_update: None,
+            time_over: -1.0,
+            count_over: 0,
+            prev_trend: 0.0,
+        }
+    }
+
+    fn detect(&mut self, trend: f64, num_deltas: usize, delta: f64, now: i64) -> Usage {
+        let modified_trend = num_deltas as f64 * trend * TRENDLINE_GAIN;
+
+        let usage = if modified_trend > self.threshold {
+            if self.time_over < 0.0 {
+                self.time_over = delta / 2.0;
+            } else {
+                self.time_over += delta;
+            }
+            self.count_over += 1;
+
+            if self.time_over > OVERUSE_TIME && self.count_over > 1 && trend >= self.prev_trend {
+                self.time_over = 0.0;
+                self.count_over = 0;
+                Usage::Overuse
+            } else {
+                Usage::Normal
+            }
+        } else if modified_trend < -self.threshold {
+            self.time_over = -1.0;
+            self.count_over = 0;
+            Usage::Underuse
+        } else {
+            self.time_over = -1.0;
+            self.count_over = 0;
+            Usage::Normal
+        };
+
+        self.prev_trend = trend;
+        self.update_threshold(modified_trend, now);
+
+        usage
+    }
+
+    fn update_threshold(&mut self, modified_trend: f64, now: i64) {
+        let last_update = *self.last_update.get_or_insert(now);
+        self.last_update = Some(now);
+
+        // Ignore spikes, e.g. caused by route changes
+        let abs = modified_trend.abs();
+        if abs > self.threshold + 15.0 {
+            return;
+        }
+
+        let k = if abs < self.threshold {
+            THRESHOLD_DOWN
+        } else {
+            THRESHOLD_UP
+        };
+        let elapsed = ((now - last_update) as f64 / 1000.0).min(100.0);
+        self.threshold += k * (abs - self.threshold) * elapsed;
+        self.threshold = self.threshold.max(6.0).min(600.0);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RateControlState {
+    Increase,
+    Hold,
+    Decrease,
+}
+
+struct PacketGroup {
+    send_first: i64,
+    send_last: i64,
+    arrival_last: i64,
+}
+
+pub struct Estimator {
+    min_bitrate: u32,
+    max_bitrate: u32,
+    // Send time in microseconds and size of all packets without feedback yet
+    sent: HashMap<u16, (i64, usize)>,
+    sent_order: VecDeque<u16>,
+    // Arrival time and size of the acknowledged packets in the received bitrate window
+    received: VecDeque<(i64, usize)>,
+    current_group: Option<PacketGroup>,
+    prev_group: Option<PacketGroup>,
+    trendline: Trendline,
+    detector: OveruseDetector,
+    state: RateControlState,
+    last_increase: Option<i64>,
+    last_decrease: Option<i64>,
+    delay_bitrate: f64,
+    loss_bitrate: f64,
+}
+
+impl Estimator {
+    pub fn new(bitrate: u32, min_bitrate: u32, max_bitrate: u32) -> Self {
+        Estimator {
+            min_bitrate: min_bitrate,
+            max_bitrate: max_bitrate,
+            sent: HashMap::new(),
+            sent_order: VecDeque::new(),
+            received: VecDeque::new(),
+            current_group: None,
+            prev_group: None,
+            trendline: Trendline::new(),
+            detector: OveruseDetector::new(),
+            state: RateControlState::Increase,
+            last_increase: None,
+            last_decrease: None,
+            delay_bitrate: f64::from(bitrate),
+            loss_bitrate: f64::from(bitrate),
+        }
+    }
+
+    pub fn estimate(&self) -> u32 {
+        let bitrate = self.delay_bitrate.min(self.loss_bitrate);
+        (bitrate as u32).max(self.min_bitrate).min(self.max_bitrate)
+    }
+
+    pub fn set_bitrate(&mut self, bitrate: u32) {
+        self.delay_bitrate = f64::from(bitrate);
+        self.loss_bitrate = f64::from(bitrate);
+    }
+
+    pub fn set_limits(&mut self, min_bitrate: u32, max_bitrate: u32) {
+        self.min_bitrate = min_bitrate;
+        self.max_bitrate = max_bitrate;
+    }
+
+    /// Remembers a sent packet, `time` is in microseconds and `size` in bytes.
+    pub fn on_packet_sent(&mut self, seqnum: u16, time: i64, size: usize) {
+        if self.sent.insert(seqnum, (time, size)).is_none() {
+            self.sent_order.push_back(seqnum);
+        }
+        while self.sent_order.len() > MAX_SENT_PACKETS {
+            let seqnum = self.sent_order.pop_front().unwrap();
+            self.sent.remove(&seqnum);
+        }
+    }
+
+    /// Updates the estimate with the packets of a feedback packet, `now` is the current time in
+    /// microseconds on the same clock as the send times.
+    pub fn on_feedback(&mut self, packets: &[(u16, Option<i64>)], now: i64) {
+        let mut lost = 0;
+        let mut total = 0;
+        let mut usage = None;
+
+        for &(seqnum, arrival) in packets {
+            let (send_time, size) = match self.sent.remove(&seqnum) {
+                // Not sent by us or reported already
+                None => continue,
+                Some(sent) => sent,
+            };
+
+            total += 1;
+            let arrival = match arrival {
+                None => {
+                    lost += 1;
+                    continue;
+                }
+                Some(arrival) => arrival,
+            };
+
+            self.received.push_back((arrival, size));
+            if let Some(u) = self.on_packet_arrived(send_time, arrival) {
+                usage = Some(u);
+            }
+        }
+        {
+            let sent = &self.sent;
+            self.sent_order.retain(|seqnum| sent.contains_key(seqnum));
+        }
+
+        if let Some(&(last_arrival, _)) = self.received.back() {
+            while self.received
+                .front()
+                .map(|&(arrival, _)| arrival < last_arrival - RECEIVED_RATE_WINDOW)
+                .unwrap_or(false)
+            {
+                self.received.pop_front();
+            }
+        }
+
+        if let Some(usage) = usage {
+            self.update_delay_bitrate(usage, now);
+        }
+
+        if total > 0 {
+            self.update_loss_bitrate(f64::from(lost) / f64::from(total));
+        }
+    }
+
+    // Groups packets sent in bursts and returns the usage if a group was completed
+    fn on_packet_arrived(&mut self, send_time: i64, arrival: i64) -> Option<Usage> {
+        if let Some(ref mut group) = self.current_group {
+            if send_time - group.send_first <= BURST_TIME {
+                group.send_last = group.send_last.max(send_time);
+                group.arrival_last = group.arrival_last.max(arrival);
+                return None;
+            }
+        }
+
+        let completed = self.current_group.take();
+        self.current_group = Some(PacketGroup {
+            send_first: send_time,
+            send_last: send_time,
+            arrival_last: arrival,
+        });
+
+        let completed = completed?;
+        let usage = if let Some(ref prev) = self.prev_group {
+            let send_delta = (completed.send_last - prev.send_last) as f64 / 1000.0;
+            let arrival_delta = (completed.arrival_last - prev.arrival_last) as f64 / 1000.0;
+            let trend = self.trendline
+                .update(arrival_delta - send_delta, completed.arrival_last);
+
+            Some(self.detector.detect(
+                trend,
+                self.trendline.num_deltas,
+                send_delta,
+                completed.arrival_last,
+            ))
+        } else {
+            None
+        };
+        self.prev_group = Some(completed);
+
+        usage
+    }
+
+    // Bits per second of the acknowledged packets
+    fn received_bitrate(&self) -> Option<f64> {
+        let first = self.received.front()?.0;
+        let last = self.received.back()?.0;
+        if last - first < RECEIVED_RATE_WINDOW / 4 {
+            return None;
+        }
+
+        let bytes = self.received.iter().map(|&(_, size)| size).sum::<usize>();
+        Some(bytes as f64 * 8.0 * 1_000_000.0 / (last - first) as f64)
+    }
+
+    fn update_delay_bitrate(&mut self, usage: Usage, now: i64) {
+        let received = self.received_bitrate();
+
+        match usage {
+            Usage::Overuse => {
+                let recently = self.last_decrease
+                    .map(|last| now - last < DECREASE_INTERVAL)
+                    .unwrap_or(false);
+                if !recently {
+                    let bitrate = received.unwrap_or(self.delay_bitrate) * 0.85;
+                    self.delay_bitrate = self.delay_bitrate.min(bitrate);
+                    self.last_decrease = Some(now);
+                }
+                self.state = RateControlState::Decrease;
+            }
+            Usage::Underuse => {
+                // Queues are draining, wait until the delay is stable again
+                self.state = RateControlState::Hold;
+            }
+            Usage::Normal => {
+                if self.state == RateControlState::Increase {
+                    let elapsed = self.last_increase
+                        .map(|last| ((now - last) as f64 / 1_000_000.0).min(1.0))
+                        .unwrap_or(0.0);
+                    let mut bitrate = self.delay_bitrate * 1.08f64.powf(elapsed);
+                    // Don't go arbitrarily far above what actually gets through
+                    if let Some(received) = received {
+                        bitrate = bitrate.min(1.5 * received + 10_000.0);
+                    }
+                    self.delay_bitrate = self.delay_bitrate.max(bitrate);
+                }
+                self.state = RateControlState::Increase;
+            }
+        }
+
+        self.last_increase = Some(now);
+        self.delay_bitrate = self.delay_bitrate
+            .max(f64::from(self.min_bitrate))
+            .min(f64::from(self.max_bitrate));
+    }
+
+    fn update_loss_bitrate(&mut self, loss: f64) {
+        if loss > 0.1 {
+            self.loss_bitrate *= 1.0 - 0.5 * loss;
+        } else if loss < 0.02 {
+            self.loss_bitrate = (self.loss_bitrate * 1.05).max(self.delay_bitrate);
+        }
+
+        self.loss_bitrate = self.loss_bitrate
+            .max(f64::from(self.min_bitrate))
+            .min(f64::from(self.max_bitrate));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_feedback() {
+        let mut rtcp = vec![0x8f, RTCP_RTPFB, 0x00, 0x06];
+        // Sender and media source SSRC
+        rtcp.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2]);
+        // Base sequence number 100, 4 packets, reference time 64ms, feedback count 0
+        rtcp.extend_from_slice(&[0x00, 100, 0x00, 0x04, 0x00, 0x00, 0x01, 0x00]);
+        // Two-bit status vector: small delta, not received, large delta, small delta
+        rtcp.extend_from_slice(&[0xd2, 0x40]);
+        // Deltas: 1ms, -2ms, 2ms
+        rtcp.extend_from_slice(&[4, 0xff, 0xf8, 8]);
+        rtcp.extend_from_slice(&[0, 0]);
+
+        let feedback = parse_rtcp(&rtcp);
+        assert_eq!(
+            feedback,
+            vec![vec![
+                (100, Some(65_000)),
+                (101, None),
+                (102, Some(63_000)),
+                (103, Some(65_000)),
+            ]]
+        );
+    }
+
+    // One 1200 byte packet every 10ms with feedback every 100ms, the one-way delay is given by
+    // `delay` for each packet
+    fn simulate<F: Fn(u16) -> i64>(
+        estimator: &mut Estimator,
+        seqnum: &mut u16,
+        packets: u16,
+        delay: F,
+    ) {
+        let mut feedback = Vec::new();
+        for _ in 0..packets {
+            let send_time = i64::from(*seqnum) * 10_000;
+            estimator.on_packet_sent(*seqnum, send_time, 1200);
+            feedback.push((*seqnum, Some(send_time + delay(*seqnum))));
+            *seqnum += 1;
+
+            if feedback.len() == 10 {
+                estimator.on_feedback(&feedback, send_time + 20_000);
+                feedback.clear();
+            }
+        }
+    }
+
+    #[test]
+    fn test_estimator() {
+        let mut estimator = Estimator::new(1_000_000, 10_000, 10_000_000);
+        let mut seqnum = 0;
+
+        // Constant delay
+        simulate(&mut estimator, &mut seqnum, 300, |_| 20_000);
+        let stable = estimator.estimate();
+        assert!(stable > 1_000_000, "{}", stable);
+
+        // Queues building up
+        simulate(&mut estimator, &mut seqnum, 100, |seqnum| {
+            20_000 + (i64::from(seqnum) - 300) * 2_000
+        });
+        let congested = estimator.estimate();
+        assert!(congested < stable, "{} >= {}", congested, stable);
+
+        // Half of the packets lost
+        let mut estimator = Estimator::new(300_000, 10_000, 10_000_000);
+        for seqnum in 0..10 {
+            estimator.on_packet_sent(seqnum, i64::from(seqnum) * 10_000, 1200);
+        }
+        let feedback = (0..10)
+            .map(|seqnum| {
+                if seqnum % 2 == 0 {
+                    (seqnum, None)
+                } else {
+                    (seqnum, Some(i64::from(seqnum) * 10_000))
+                }
+            })
+            .collect::<Vec<_>>();
+        estimator.on_feedback(&feedback, 100_000);
+        assert_eq!(estimator.estimate(), 225_000);
+    }
+}
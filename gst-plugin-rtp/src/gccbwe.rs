@@ -0,0 +1,477 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+use std::time::Instant;
+use std::u32;
+
+use gcc::{self, Estimator};
+use rtp::RtpPacket;
+use rtphdrext;
+
+const DEFAULT_ESTIMATED_BITRATE: u32 = 2_048_000;
+const DEFAULT_MIN_BITRATE: u32 = 1_000;
+const DEFAULT_MAX_BITRATE: u32 = 8_192_000;
+
+static PROPERTIES: [Property; 3] = [
+    Property::UInt(
+        "estimated-bitrate",
+        "Estimated Bitrate",
+        "Currently estimated bitrate in bits per second, setting it changes the estimate",
+        (1, u32::MAX),
+        DEFAULT_ESTIMATED_BITRATE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "min-bitrate",
+        "Minimal Bitrate",
+        "Minimal bitrate in bits per second to estimate",
+        (1, u32::MAX),
+        DEFAULT_MIN_BITRATE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "max-bitrate",
+        "Maximal Bitrate",
+        "Maximal bitrate in bits per second to estimate",
+        (1, u32::MAX),
+        DEFAULT_MAX_BITRATE,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    estimated_bitrate: u32,
+    min_bitrate: u32,
+    max_bitrate: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            estimated_bitrate: DEFAULT_ESTIMATED_BITRATE,
+            min_bitrate: DEFAULT_MIN_BITRATE,
+            max_bitrate: DEFAULT_MAX_BITRATE,
+        }
+    }
+}
+
+struct State {
+    // Send times are relative to this
+    start: Instant,
+    // ID of the transport-wide sequence number header extension from the caps
+    twcc_id: Option<u8>,
+    estimator: Estimator,
+}
+
+impl State {
+    fn new(settings: &Settings) -> Self {
+        State {
+            start: Instant::now(),
+            twcc_id: None,
+            estimator: Estimator::new(
+                settings.estimated_bitrate,
+                settings.min_bitrate,
+                settings.max_bitrate,
+            ),
+        }
+    }
+
+    // Microseconds since start
+    fn now(&self) -> i64 {
+        let elapsed = self.start.elapsed();
+        elapsed.as_secs() as i64 * 1_000_000 + i64::from(elapsed.subsec_nanos() / 1_000)
+    }
+}
+
+struct GccBwe {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    rtcp_sinkpad: gst::Pad,
+    rtcp_srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl GccBwe {
+    fn new(
+        _element: &Element,
+        sinkpad: gst::Pad,
+        srcpad: gst::Pad,
+        rtcp_sinkpad: gst::Pad,
+        rtcp_srcpad: gst::Pad,
+    ) -> Self {
+        let settings = Settings::default();
+
+        Self {
+            cat: gst::DebugCategory::new(
+                "rtpgccbwe",
+                gst::DebugColorFlags::empty(),
+                "Google congestion control bandwidth estimator",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            rtcp_sinkpad: rtcp_sinkpad,
+            rtcp_srcpad: rtcp_srcpad,
+            settings: Mutex::new(settings),
+            state: Mutex::new(State::new(&settings)),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Google Congestion Control bandwidth estimator",
+            "Network/WebRTC/RTP/Filter",
+            "Estimates the available bandwidth from transport-wide congestion control feedback",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("application/x-rtp", &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let caps = gst::Caps::new_simple("application/x-rtcp", &[]);
+        let rtcp_sink_pad_template = gst::PadTemplate::new(
+            "rtcp_sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(rtcp_sink_pad_template);
+
+        let rtcp_src_pad_template = gst::PadTemplate::new(
+            "rtcp_src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(rtcp_src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+        let templ = element.get_pad_template("rtcp_sink").unwrap();
+        let rtcp_sinkpad = gst::Pad::new_from_template(&templ, "rtcp_sink");
+        let templ = element.get_pad_template("rtcp_src").unwrap();
+        let rtcp_srcpad = gst::Pad::new_from_template(&templ, "rtcp_src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            GccBwe::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |bwe, element| bwe.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            GccBwe::catch_panic_pad_function(
+                parent,
+                || false,
+                |bwe, element| bwe.sink_event(pad, element, event),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            GccBwe::catch_panic_pad_function(
+                parent,
+                || false,
+                |bwe, element| bwe.src_event(pad, element, event),
+            )
+        });
+
+        rtcp_sinkpad.set_chain_function(|pad, parent, buffer| {
+            GccBwe::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |bwe, element| bwe.rtcp_sink_chain(pad, element, buffer),
+            )
+        });
+        rtcp_sinkpad.set_event_function(|pad, parent, event| {
+            GccBwe::catch_panic_pad_function(
+                parent,
+                || false,
+                |bwe, element| bwe.rtcp_sink_event(pad, element, event),
+            )
+        });
+        rtcp_srcpad.set_event_function(|pad, parent, event| {
+            GccBwe::catch_panic_pad_function(
+                parent,
+                || false,
+                |bwe, element| bwe.rtcp_src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+        element.add_pad(&rtcp_sinkpad).unwrap();
+        element.add_pad(&rtcp_srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad, rtcp_sinkpad, rtcp_srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let bwe = element.get_impl().downcast_ref::<GccBwe>().unwrap();
+        element.catch_panic(fallback, |element| f(bwe, element))
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            let mut state = self.state.lock().unwrap();
+            let twcc_seqnum = state.twcc_id.and_then(|twcc_id| {
+                let packet = RtpPacket::parse(map.as_slice())?;
+                let (profile, data) = packet.extension?;
+                rtphdrext::parse(profile, data)?
+                    .into_iter()
+                    .find(|&(id, data)| id == twcc_id && data.len() == 2)
+                    .map(|(_, data)| (u16::from(data[0]) << 8) | u16::from(data[1]))
+            });
+
+            // Packets without transport-wide sequence number are not considered for the
+            // estimation
+            if let Some(seqnum) = twcc_seqnum {
+                let now = state.now();
+                gst_trace!(
+                    self.cat,
+                    obj: pad,
+                    "Sending packet {} with {} bytes",
+                    seqnum,
+                    map.get_size()
+                );
+                state.estimator.on_packet_sent(seqnum, now, map.get_size());
+            }
+        }
+
+        self.srcpad.push(buffer)
+    }
+
+    fn rtcp_sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        // New estimate if it changed
+        let bitrate = {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            let feedback = gcc::parse_rtcp(map.as_slice());
+            if feedback.is_empty() {
+                None
+            } else {
+                let mut state = self.state.lock().unwrap();
+                let old_bitrate = state.estimator.estimate();
+
+                let now = state.now();
+                for packets in &feedback {
+                    gst_trace!(
+                        self.cat,
+                        obj: pad,
+                        "Received feedback for {} packets",
+                        packets.len()
+                    );
+                    state.estimator.on_feedback(packets, now);
+                }
+
+                let bitrate = state.estimator.estimate();
+                if bitrate != old_bitrate {
+                    Some(bitrate)
+                } else {
+                    None
+                }
+            }
+        };
+
+        if let Some(bitrate) = bitrate {
+            gst_debug!(self.cat, obj: pad, "Estimated bitrate {}", bitrate);
+            self.settings.lock().unwrap().estimated_bitrate = bitrate;
+            self.notify(&element.clone().upcast(), "estimated-bitrate");
+        }
+
+        self.rtcp_srcpad.push(buffer)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        if let EventView::Caps(e) = event.view() {
+            let twcc_id = e.get_caps()
+                .get_structure(0)
+                .and_then(|s| rtphdrext::find_id(s, rtphdrext::TRANSPORT_CC_URI));
+
+            if twcc_id.is_none() {
+                gst_warning!(
+                    self.cat,
+                    obj: pad,
+                    "No transport-wide sequence numbers negotiated, can't estimate bitrate"
+                );
+            }
+
+            self.state.lock().unwrap().twcc_id = twcc_id;
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn rtcp_sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.rtcp_srcpad.push_event(event)
+    }
+
+    fn rtcp_src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.rtcp_sinkpad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for GccBwe {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("estimated-bitrate", ..) => {
+                settings.estimated_bitrate = value.get().unwrap();
+                self.state
+                    .lock()
+                    .unwrap()
+                    .estimator
+                    .set_bitrate(settings.estimated_bitrate);
+            }
+            Property::UInt("min-bitrate", ..) => {
+                settings.min_bitrate = value.get().unwrap();
+                self.state
+                    .lock()
+                    .unwrap()
+                    .estimator
+                    .set_limits(settings.min_bitrate, settings.max_bitrate);
+            }
+            Property::UInt("max-bitrate", ..) => {
+                settings.max_bitrate = value.get().unwrap();
+                self.state
+                    .lock()
+                    .unwrap()
+                    .estimator
+                    .set_limits(settings.min_bitrate, settings.max_bitrate);
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("estimated-bitrate", ..) => Ok(settings.estimated_bitrate.to_value()),
+            Property::UInt("min-bitrate", ..) => Ok(settings.min_bitrate.to_value()),
+            Property::UInt("max-bitrate", ..) => Ok(settings.max_bitrate.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for GccBwe {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if transition == gst::StateChange::ReadyToPaused {
+            let settings = *self.settings.lock().unwrap();
+            *self.state.lock().unwrap() = State::new(&settings);
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct GccBweStatic;
+
+impl ImplTypeStatic<Element> for GccBweStatic {
+    fn get_name(&self) -> &str {
+        "RtpGccBwe"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        GccBwe::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        GccBwe::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let gccbwe_static = GccBweStatic;
+    register_type(gccbwe_static)
+}
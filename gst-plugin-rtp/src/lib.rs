@@ -0,0 +1,58 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate glib;
+#[macro_use]
+extern crate gst_plugin;
+#[macro_use]
+extern crate gstreamer as gst;
+
+use gst_plugin::registration::*;
+
+mod gcc;
+mod opus;
+mod red;
+mod rtp;
+mod rtphdrext;
+mod rtx;
+mod ulpfec;
+
+mod gccbwe;
+mod opusdepay;
+mod opuspay;
+mod reddec;
+mod redenc;
+mod rtxreceive;
+mod rtxsend;
+mod ulpfecdec;
+mod ulpfecenc;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    ElementRegistration::new(plugin)
+        .element("rtpgccbwe", RANK_NONE, gccbwe::get_type())
+        .element("rtpopusdepay2", RANK_NONE, opusdepay::get_type())
+        .element("rtpopuspay2", RANK_NONE, opuspay::get_type())
+        .element("rtpreddec2", RANK_NONE, reddec::get_type())
+        .element("rtpredenc2", RANK_NONE, redenc::get_type())
+        .element("rtprtxreceive2", RANK_NONE, rtxreceive::get_type())
+        .element("rtprtxsend2", RANK_NONE, rtxsend::get_type())
+        .element("rtpulpfecdec2", RANK_NONE, ulpfecdec::get_type())
+        .element("rtpulpfecenc2", RANK_NONE, ulpfecenc::get_type())
+        .register()
+}
+
+plugin_define!(
+    "rsrtp",
+    "Rust RTP Plugin",
+    plugin_init,
+    "MIT/X11",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2018-01-22"
+);
@@ -0,0 +1,84 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Opus packet helpers (RFC 6716 and RFC 7587)
+
+use gst;
+
+// The RTP clock rate of Opus is always 48kHz, independent of the actual sample rate
+pub const CLOCK_RATE: u32 = 48_000;
+
+// Frame size in 48kHz samples of the configuration in the TOC byte
+fn frame_samples(toc: u8) -> u32 {
+    let config = toc >> 3;
+    match config {
+        // SILK-only: 10, 20, 40, 60ms
+        0...11 => [480, 960, 1920, 2880][(config % 4) as usize],
+        // Hybrid: 10, 20ms
+        12...15 => [480, 960][(config % 2) as usize],
+        // CELT-only: 2.5, 5, 10, 20ms
+        _ => [120, 240, 480, 960][(config % 4) as usize],
+    }
+}
+
+/// Number of 48kHz samples in the packet, or `None` if it is invalid.
+pub fn packet_samples(data: &[u8]) -> Option<u32> {
+    if data.is_empty() {
+        return None;
+    }
+
+    let frames = match data[0] & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => {
+            if data.len() < 2 {
+                return None;
+            }
+            u32::from(data[1] & 0x3f)
+        }
+    };
+
+    let samples = frames * frame_samples(data[0]);
+    // At most 120ms per packet
+    if frames == 0 || samples > 5760 {
+        return None;
+    }
+
+    Some(samples)
+}
+
+/// Whether the packet is a DTX packet, i.e. contains no audio data but only the TOC byte.
+pub fn is_dtx(data: &[u8]) -> bool {
+    data.len() <= 2
+}
+
+pub fn samples_to_time(samples: u64) -> gst::ClockTime {
+    gst::ClockTime::from_nseconds(samples * gst::SECOND_VAL / u64::from(CLOCK_RATE))
+}
+
+pub fn time_to_samples(time: u64) -> u64 {
+    time * u64::from(CLOCK_RATE) / gst::SECOND_VAL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_samples() {
+        // CELT 20ms, 1 frame
+        assert_eq!(packet_samples(&[31 << 3, 0, 0]), Some(960));
+        // SILK 60ms, 3 frames: too long
+        assert_eq!(packet_samples(&[(3 << 3) | 3, 3]), None);
+        // SILK 20ms, 3 frames in code 3 packet
+        assert_eq!(packet_samples(&[(1 << 3) | 3, 3]), Some(2880));
+        // Hybrid 10ms, 2 frames
+        assert_eq!(packet_samples(&[(12 << 3) | 2]), Some(960));
+        assert_eq!(packet_samples(&[]), None);
+    }
+}
@@ -0,0 +1,480 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use glib::value::ToSendValue;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::mem;
+use std::sync::Mutex;
+
+use opus;
+use rtp::{self, RtpPacket};
+use rtphdrext::{self, HeaderExtensions};
+
+static PROPERTIES: [Property; 2] = [
+    Property::String(
+        "mid",
+        "MID",
+        "Media identification received with the MID header extension",
+        None,
+        PropertyMutability::Readable,
+    ),
+    Property::String(
+        "rid",
+        "RID",
+        "RTP stream identifier received with the RID header extension",
+        None,
+        PropertyMutability::Readable,
+    ),
+];
+
+// Packet loss and DTX handling:
+//
+// Packets lost in the jitterbuffer are signalled with "GstRTPPacketLost" events, which are
+// converted to gap events of the same time range and mark their seqnum as handled. Decoders
+// with in-band FEC enabled (e.g. opusdec with use-inband-fec=true) recover the lost audio from
+// the next packet then. If no such event was received for a sequence number gap, the next
+// buffer is marked as DISCONT.
+//
+// With DTX the sender does not send packets during silence, which is detected by a jump in the
+// RTP timestamps without a sequence number gap. A gap event is sent for the silence then.
+#[derive(Default)]
+struct State {
+    last_seqnum: Option<u16>,
+    // Expected RTP timestamp and PTS of the next packet
+    next_timestamp: Option<u32>,
+    next_pts: gst::ClockTime,
+    discont: bool,
+    // From the "extmap-<id>" fields of the caps
+    extensions: HeaderExtensions,
+}
+
+struct OpusDepay {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl OpusDepay {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rtpopusdepay2",
+                gst::DebugColorFlags::empty(),
+                "RTP Opus depayloader",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "RTP Opus depayloader",
+            "Codec/Depayloader/Network/RTP",
+            "Extracts Opus audio from RTP packets (RFC 7587)",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "application/x-rtp",
+            &[
+                ("media", &"audio"),
+                ("clock-rate", &(opus::CLOCK_RATE as i32)),
+                (
+                    "encoding-name",
+                    &gst::List::new(&[&"OPUS", &"X-GST-OPUS-DRAFT-SPITTKA-00", &"MULTIOPUS"]),
+                ),
+            ],
+        );
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple("audio/x-opus", &[]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            OpusDepay::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |depay, element| depay.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            OpusDepay::catch_panic_pad_function(
+                parent,
+                || false,
+                |depay, element| depay.sink_event(pad, element, event),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            OpusDepay::catch_panic_pad_function(
+                parent,
+                || false,
+                |depay, element| depay.src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let depay = element.get_impl().downcast_ref::<OpusDepay>().unwrap();
+        element.catch_panic(fallback, |element| f(depay, element))
+    }
+
+    fn get_src_caps(&self, caps: &gst::CapsRef) -> Option<gst::Caps> {
+        let s = caps.get_structure(0)?;
+        let encoding_name = s.get::<&str>("encoding-name")?;
+
+        if encoding_name == "MULTIOPUS" {
+            let parse_param = |name| s.get::<&str>(name).and_then(|v| v.parse::<i32>().ok());
+
+            let channels = parse_param("encoding-params")?;
+            let stream_count = parse_param("num_streams")?;
+            let coupled_count = parse_param("coupled_streams")?;
+            let mapping = s.get::<&str>("channel_mapping")?
+                .split(',')
+                .map(|c| c.trim().parse::<i32>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            if mapping.len() != channels as usize {
+                return None;
+            }
+            let mapping = mapping
+                .iter()
+                .map(|c| c as &ToSendValue)
+                .collect::<Vec<_>>();
+
+            Some(gst::Caps::new_simple(
+                "audio/x-opus",
+                &[
+                    ("rate", &(opus::CLOCK_RATE as i32)),
+                    ("channels", &channels),
+                    ("channel-mapping-family", &1i32),
+                    ("stream-count", &stream_count),
+                    ("coupled-count", &coupled_count),
+                    ("channel-mapping", &gst::Array::new(&mapping)),
+                ],
+            ))
+        } else {
+            // Opus always can be decoded as stereo, only use mono if the sender said so
+            let channels = match s.get::<&str>("sprop-stereo") {
+                Some("0") => 1i32,
+                _ => 2i32,
+            };
+
+            Some(gst::Caps::new_simple(
+                "audio/x-opus",
+                &[
+                    ("rate", &(opus::CLOCK_RATE as i32)),
+                    ("channels", &channels),
+                    ("channel-mapping-family", &0i32),
+                ],
+            ))
+        }
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let map = match buffer.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map,
+        };
+
+        let packet = match RtpPacket::parse(map.as_slice()) {
+            None => {
+                gst_warning!(self.cat, obj: pad, "Dropping invalid RTP packet");
+                return gst::FlowReturn::Ok;
+            }
+            Some(packet) => packet,
+        };
+
+        let samples = match opus::packet_samples(packet.payload) {
+            None => {
+                gst_warning!(self.cat, obj: pad, "Dropping invalid Opus packet");
+                return gst::FlowReturn::Ok;
+            }
+            Some(samples) => samples,
+        };
+
+        let pts = buffer.get_pts();
+        let mut gap = None;
+        let mut state = self.state.lock().unwrap();
+
+        let (mid_changed, rid_changed) = {
+            let extensions = &mut state.extensions;
+            let get = |extensions: &HeaderExtensions, uri: &str| {
+                extensions.get_value(uri).map(String::from)
+            };
+
+            let mid = get(extensions, rtphdrext::MID_URI);
+            let rid = get(extensions, rtphdrext::RID_URI);
+            extensions.read(&packet);
+
+            (
+                get(extensions, rtphdrext::MID_URI) != mid,
+                get(extensions, rtphdrext::RID_URI) != rid,
+            )
+        };
+
+        if let Some(last_seqnum) = state.last_seqnum {
+            let diff = rtp::seqnum_diff(last_seqnum, packet.seqnum);
+            if diff <= 0 {
+                gst_debug!(self.cat, obj: pad, "Dropping late or duplicate packet {}", packet.seqnum);
+                return gst::FlowReturn::Ok;
+            } else if diff > 1 {
+                gst_debug!(self.cat, obj: pad, "Lost {} packets", diff - 1);
+                state.discont = true;
+            } else if let Some(next_timestamp) = state.next_timestamp {
+                let silence = rtp::timestamp_diff(next_timestamp, packet.timestamp);
+                if silence > 0 && state.next_pts.is_some() {
+                    gst_debug!(self.cat, obj: pad, "DTX for {} samples", silence);
+                    gap = Some((state.next_pts, opus::samples_to_time(silence as u64)));
+                }
+            }
+        }
+
+        let duration = opus::samples_to_time(u64::from(samples));
+        state.last_seqnum = Some(packet.seqnum);
+        state.next_timestamp = Some(packet.timestamp.wrapping_add(samples));
+        state.next_pts = pts + duration;
+
+        let mut outbuf = gst::Buffer::from_mut_slice(packet.payload.to_vec()).unwrap();
+        {
+            let outbuf = outbuf.get_mut().unwrap();
+            outbuf.set_pts(pts);
+            outbuf.set_duration(duration);
+            if state.discont || buffer.get_flags().contains(gst::BufferFlags::DISCONT) {
+                outbuf.set_flags(gst::BufferFlags::DISCONT);
+                state.discont = false;
+            }
+        }
+        drop(state);
+        drop(map);
+
+        if mid_changed {
+            self.notify(&element.clone().upcast(), "mid");
+        }
+        if rid_changed {
+            self.notify(&element.clone().upcast(), "rid");
+        }
+
+        if let Some((gap_pts, gap_duration)) = gap {
+            self.srcpad
+                .push_event(gst::Event::new_gap(gap_pts, gap_duration).build());
+        }
+
+        gst_log!(self.cat, obj: pad, "Pushing buffer {:?}", outbuf);
+        self.srcpad.push(outbuf)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let src_caps = match self.get_src_caps(e.get_caps()) {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Negotiation,
+                            ["Invalid caps {:?}", e.get_caps()]
+                        );
+                        return false;
+                    }
+                    Some(src_caps) => src_caps,
+                };
+
+                let s = e.get_caps().get_structure(0).unwrap();
+                match HeaderExtensions::from_structure(s) {
+                    Err(err) => {
+                        gst_element_error!(element, gst::CoreError::Negotiation, ["{}", err]);
+                        return false;
+                    }
+                    Ok(extensions) => self.state.lock().unwrap().extensions = extensions,
+                }
+
+                gst_debug!(self.cat, obj: pad, "Setting caps {:?}", src_caps);
+                return self.srcpad.push_event(gst::Event::new_caps(&src_caps).build());
+            }
+            EventView::CustomDownstream(..) => {
+                let lost = event.get_structure().and_then(|s| {
+                    if s.get_name() != "GstRTPPacketLost" {
+                        return None;
+                    }
+                    Some((
+                        s.get::<u32>("seqnum")? as u16,
+                        s.get::<u64>("timestamp")?,
+                        s.get::<u64>("duration")?,
+                    ))
+                });
+
+                if let Some((seqnum, timestamp, duration)) = lost {
+                    gst_debug!(
+                        self.cat,
+                        obj: pad,
+                        "Packet {} lost at {} with duration {}",
+                        seqnum,
+                        gst::ClockTime::from(timestamp),
+                        gst::ClockTime::from(duration)
+                    );
+
+                    {
+                        let mut state = self.state.lock().unwrap();
+                        // The jitterbuffer gave up on the packet, don't mark the next one as
+                        // DISCONT and don't consider the gap DTX. Events for multiple lost
+                        // packets only contain the first seqnum, the next packet is DISCONT then.
+                        let newer = state
+                            .last_seqnum
+                            .map(|last_seqnum| rtp::seqnum_diff(last_seqnum, seqnum) > 0)
+                            .unwrap_or(true);
+                        if newer {
+                            state.last_seqnum = Some(seqnum);
+                        }
+
+                        // The RTP timestamps continue after the lost time range
+                        let end = gst::ClockTime::from(timestamp + duration);
+                        let next_pts = state.next_pts;
+                        if let Some(ref mut next_timestamp) = state.next_timestamp {
+                            if next_pts.is_some() && end > next_pts {
+                                let lost_samples = opus::time_to_samples((end - next_pts).unwrap());
+                                *next_timestamp = next_timestamp.wrapping_add(lost_samples as u32);
+                            }
+                        }
+                        state.next_pts = end;
+                    }
+
+                    return self.srcpad.push_event(
+                        gst::Event::new_gap(timestamp.into(), duration.into()).build(),
+                    );
+                }
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                let extensions = mem::replace(&mut state.extensions, HeaderExtensions::default());
+                *state = State {
+                    extensions: extensions,
+                    ..State::default()
+                };
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for OpusDepay {
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let state = self.state.lock().unwrap();
+
+        match *prop {
+            Property::String("mid", ..) => {
+                Ok(state.extensions.get_value(rtphdrext::MID_URI).to_value())
+            }
+            Property::String("rid", ..) => {
+                Ok(state.extensions.get_value(rtphdrext::RID_URI).to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for OpusDepay {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        if transition == gst::StateChange::PausedToReady {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        ret
+    }
+}
+
+struct OpusDepayStatic;
+
+impl ImplTypeStatic<Element> for OpusDepayStatic {
+    fn get_name(&self) -> &str {
+        "RtpOpusDepay2"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        OpusDepay::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        OpusDepay::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let opusdepay_static = OpusDepayStatic;
+    register_type(opusdepay_static)
+}
@@ -0,0 +1,572 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+use std::u32;
+
+use opus;
+use rtp::{self, RtpPacket};
+use rtphdrext::{self, HeaderExtensions};
+
+const DEFAULT_PT: u32 = 96;
+const DEFAULT_SSRC: u32 = u32::MAX;
+const DEFAULT_TIMESTAMP_OFFSET: u32 = u32::MAX;
+const DEFAULT_SEQNUM_OFFSET: i32 = -1;
+const DEFAULT_DTX: bool = false;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    pt: u32,
+    ssrc: u32,
+    timestamp_offset: u32,
+    seqnum_offset: i32,
+    dtx: bool,
+    extmap: Option<gst::Structure>,
+    mid: Option<String>,
+    rid: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            pt: DEFAULT_PT,
+            ssrc: DEFAULT_SSRC,
+            timestamp_offset: DEFAULT_TIMESTAMP_OFFSET,
+            seqnum_offset: DEFAULT_SEQNUM_OFFSET,
+            dtx: DEFAULT_DTX,
+            extmap: None,
+            mid: None,
+            rid: None,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 8] = [
+    Property::UInt(
+        "pt",
+        "Payload Type",
+        "Payload type of the packets",
+        (96, 127),
+        DEFAULT_PT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "ssrc",
+        "SSRC",
+        "SSRC of the packets (4294967295 = random)",
+        (0, u32::MAX),
+        DEFAULT_SSRC,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "timestamp-offset",
+        "Timestamp Offset",
+        "Offset to add to all RTP timestamps (4294967295 = random)",
+        (0, u32::MAX),
+        DEFAULT_TIMESTAMP_OFFSET,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Int(
+        "seqnum-offset",
+        "Sequence Number Offset",
+        "Offset to add to all sequence numbers (-1 = random)",
+        (-1, 65535),
+        DEFAULT_SEQNUM_OFFSET,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "dtx",
+        "DTX",
+        "Don't send DTX packets and set the marker bit on the first packet after silence",
+        DEFAULT_DTX,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boxed(
+        "extmap",
+        "Extension Map",
+        "RTP header extensions to add, as \"extmap-<id>\" fields with the extension URI",
+        gst::Structure::static_type,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "mid",
+        "MID",
+        "Media identification for bundling, sent with the MID header extension",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "rid",
+        "RID",
+        "RTP stream identifier for simulcast, sent with the RID header extension",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+struct State {
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    ssrc: u32,
+    timestamp_offset: u32,
+    seqnum: u16,
+    // Set for the first packet and the first packet after DTX
+    marker: bool,
+    extensions: HeaderExtensions,
+}
+
+impl State {
+    fn new(settings: &Settings, extensions: HeaderExtensions) -> Self {
+        State {
+            segment: gst::FormattedSegment::new(),
+            ssrc: if settings.ssrc == u32::MAX {
+                rtp::pseudo_random_u32()
+            } else {
+                settings.ssrc
+            },
+            timestamp_offset: if settings.timestamp_offset == u32::MAX {
+                rtp::pseudo_random_u32()
+            } else {
+                settings.timestamp_offset
+            },
+            seqnum: if settings.seqnum_offset < 0 {
+                rtp::pseudo_random_u32() as u16
+            } else {
+                settings.seqnum_offset as u16
+            },
+            marker: true,
+            extensions: extensions,
+        }
+    }
+}
+
+struct OpusPay {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl OpusPay {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rtpopuspay2",
+                gst::DebugColorFlags::empty(),
+                "RTP Opus payloader",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "RTP Opus payloader",
+            "Codec/Payloader/Network/RTP",
+            "Payloads Opus audio into RTP packets (RFC 7587)",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let mut caps = gst::Caps::new_simple(
+            "audio/x-opus",
+            &[
+                ("channel-mapping-family", &0i32),
+                ("channels", &gst::IntRange::<i32>::new(1, 2)),
+            ],
+        );
+        caps.get_mut().unwrap().append(gst::Caps::new_simple(
+            "audio/x-opus",
+            &[
+                ("channel-mapping-family", &1i32),
+                ("channels", &gst::IntRange::<i32>::new(3, 255)),
+            ],
+        ));
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple(
+            "application/x-rtp",
+            &[
+                ("media", &"audio"),
+                ("payload", &gst::IntRange::<i32>::new(96, 127)),
+                ("clock-rate", &(opus::CLOCK_RATE as i32)),
+                ("encoding-name", &gst::List::new(&[&"OPUS", &"MULTIOPUS"])),
+            ],
+        );
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            OpusPay::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |pay, element| pay.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            OpusPay::catch_panic_pad_function(
+                parent,
+                || false,
+                |pay, element| pay.sink_event(pad, element, event),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            OpusPay::catch_panic_pad_function(
+                parent,
+                || false,
+                |pay, element| pay.src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let pay = element.get_impl().downcast_ref::<OpusPay>().unwrap();
+        element.catch_panic(fallback, |element| f(pay, element))
+    }
+
+    fn get_src_caps(&self, caps: &gst::CapsRef) -> Option<gst::Caps> {
+        let s = caps.get_structure(0)?;
+        let channels = s.get::<i32>("channels").unwrap_or(2);
+        let family = s.get::<i32>("channel-mapping-family").unwrap_or(0);
+
+        let pt = self.settings.lock().unwrap().pt;
+        let state = self.state.lock().unwrap();
+        let state = state.as_ref()?;
+
+        let mut src_caps = gst::Caps::new_simple(
+            "application/x-rtp",
+            &[
+                ("media", &"audio"),
+                ("payload", &(pt as i32)),
+                ("clock-rate", &(opus::CLOCK_RATE as i32)),
+                ("ssrc", &state.ssrc),
+                ("timestamp-offset", &state.timestamp_offset),
+                ("seqnum-offset", &u32::from(state.seqnum)),
+            ],
+        );
+
+        {
+            let s_out = src_caps.get_mut().unwrap().get_mut_structure(0).unwrap();
+
+            if family == 0 && channels <= 2 {
+                // Opus is always signalled as stereo, the actual number of channels is a hint
+                s_out.set("encoding-name", &"OPUS");
+                s_out.set("encoding-params", &"2");
+                s_out.set("sprop-stereo", &if channels == 2 { "1" } else { "0" });
+            } else {
+                // Surround signalling as used by WebRTC implementations
+                let stream_count = s.get::<i32>("stream-count")?;
+                let coupled_count = s.get::<i32>("coupled-count")?;
+                let mapping = s.get::<gst::Array>("channel-mapping")?
+                    .as_slice()
+                    .iter()
+                    .map(|v| v.get::<i32>().map(|c| c.to_string()))
+                    .collect::<Option<Vec<_>>>()?;
+
+                s_out.set("encoding-name", &"MULTIOPUS");
+                s_out.set("encoding-params", &channels.to_string());
+                s_out.set("num_streams", &stream_count.to_string());
+                s_out.set("coupled_streams", &coupled_count.to_string());
+                s_out.set("channel_mapping", &mapping.join(","));
+            }
+
+            state.extensions.add_to_structure(s_out);
+        }
+
+        Some(src_caps)
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let (pt, dtx) = {
+            let settings = self.settings.lock().unwrap();
+            (settings.pt, settings.dtx)
+        };
+
+        let map = match buffer.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map,
+        };
+        let data = map.as_slice();
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return gst::FlowReturn::Flushing,
+            Some(ref mut state) => state,
+        };
+
+        if dtx && opus::is_dtx(data) {
+            gst_log!(self.cat, obj: pad, "Not sending DTX packet");
+            state.marker = true;
+            return gst::FlowReturn::Ok;
+        }
+
+        let running_time = state.segment.to_running_time(buffer.get_pts());
+        let timestamp = match running_time.0 {
+            None => {
+                gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(running_time) => state
+                .timestamp_offset
+                .wrapping_add(opus::time_to_samples(running_time) as u32),
+        };
+
+        let mut packet = RtpPacket::new(
+            pt as u8,
+            state.seqnum,
+            timestamp,
+            state.ssrc,
+            data,
+        );
+        packet.marker = state.marker;
+        let extension = state.extensions.write(&packet);
+        packet.extension = extension.as_ref().map(|&(profile, ref data)| (profile, &data[..]));
+        state.marker = false;
+        state.seqnum = state.seqnum.wrapping_add(1);
+
+        let mut outbuf = gst::Buffer::from_mut_slice(packet.write()).unwrap();
+        {
+            let outbuf = outbuf.get_mut().unwrap();
+            outbuf.set_pts(buffer.get_pts());
+            outbuf.set_duration(buffer.get_duration());
+        }
+        drop(state_guard);
+        drop(map);
+
+        gst_log!(self.cat, obj: pad, "Pushing packet {:?}", outbuf);
+        self.srcpad.push(outbuf)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let src_caps = match self.get_src_caps(e.get_caps()) {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Negotiation,
+                            ["Invalid caps {:?}", e.get_caps()]
+                        );
+                        return false;
+                    }
+                    Some(src_caps) => src_caps,
+                };
+
+                gst_debug!(self.cat, obj: pad, "Setting caps {:?}", src_caps);
+                return self.srcpad.push_event(gst::Event::new_caps(&src_caps).build());
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.segment = segment;
+                }
+            }
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.segment = gst::FormattedSegment::new();
+                    state.marker = true;
+                }
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for OpusPay {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("pt", ..) => {
+                settings.pt = value.get().unwrap();
+            }
+            Property::UInt("ssrc", ..) => {
+                settings.ssrc = value.get().unwrap();
+            }
+            Property::UInt("timestamp-offset", ..) => {
+                settings.timestamp_offset = value.get().unwrap();
+            }
+            Property::Int("seqnum-offset", ..) => {
+                settings.seqnum_offset = value.get().unwrap();
+            }
+            Property::Boolean("dtx", ..) => {
+                settings.dtx = value.get().unwrap();
+            }
+            Property::Boxed("extmap", ..) => {
+                settings.extmap = value.get();
+            }
+            Property::String("mid", ..) => {
+                settings.mid = value.get();
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state
+                        .extensions
+                        .set_value(rtphdrext::MID_URI, settings.mid.as_ref().map(String::as_str));
+                }
+            }
+            Property::String("rid", ..) => {
+                settings.rid = value.get();
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state
+                        .extensions
+                        .set_value(rtphdrext::RID_URI, settings.rid.as_ref().map(String::as_str));
+                }
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("pt", ..) => Ok(settings.pt.to_value()),
+            Property::UInt("ssrc", ..) => Ok(settings.ssrc.to_value()),
+            Property::UInt("timestamp-offset", ..) => Ok(settings.timestamp_offset.to_value()),
+            Property::Int("seqnum-offset", ..) => Ok(settings.seqnum_offset.to_value()),
+            Property::Boolean("dtx", ..) => Ok(settings.dtx.to_value()),
+            Property::Boxed("extmap", ..) => Ok(settings.extmap.to_value()),
+            Property::String("mid", ..) => Ok(settings.mid.to_value()),
+            Property::String("rid", ..) => Ok(settings.rid.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for OpusPay {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if transition == gst::StateChange::ReadyToPaused {
+            let settings = self.settings.lock().unwrap().clone();
+
+            let mut extensions = match settings.extmap {
+                None => HeaderExtensions::default(),
+                Some(ref extmap) => match HeaderExtensions::from_structure(extmap) {
+                    Err(err) => {
+                        gst_element_error!(element, gst::LibraryError::Settings, ["{}", err]);
+                        return gst::StateChangeReturn::Failure;
+                    }
+                    Ok(extensions) => extensions,
+                },
+            };
+            extensions.set_value(rtphdrext::MID_URI, settings.mid.as_ref().map(String::as_str));
+            extensions.set_value(rtphdrext::RID_URI, settings.rid.as_ref().map(String::as_str));
+
+            *self.state.lock().unwrap() = Some(State::new(&settings, extensions));
+        }
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        if transition == gst::StateChange::PausedToReady {
+            *self.state.lock().unwrap() = None;
+        }
+
+        ret
+    }
+}
+
+struct OpusPayStatic;
+
+impl ImplTypeStatic<Element> for OpusPayStatic {
+    fn get_name(&self) -> &str {
+        "RtpOpusPay2"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        OpusPay::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        OpusPay::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let opuspay_static = OpusPayStatic;
+    register_type(opuspay_static)
+}
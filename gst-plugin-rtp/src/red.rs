@@ -0,0 +1,141 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// RTP payload for redundant audio data (RFC 2198)
+
+pub const MAX_TIMESTAMP_OFFSET: u32 = 0x3fff;
+pub const MAX_BLOCK_LENGTH: usize = 0x3ff;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedBlock<'a> {
+    pub payload_type: u8,
+    pub timestamp_offset: u16,
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedPayload<'a> {
+    // Oldest block first
+    pub redundant: Vec<RedBlock<'a>>,
+    pub primary_payload_type: u8,
+    pub primary: &'a [u8],
+}
+
+impl<'a> RedPayload<'a> {
+    /// Parses the payload of a RED packet, returns `None` if it is invalid.
+    pub fn parse(data: &'a [u8]) -> Option<RedPayload<'a>> {
+        let mut headers = Vec::new();
+        let mut offset = 0;
+
+        // 4 byte headers for the redundant blocks, terminated by the 1 byte primary header
+        loop {
+            let first = *data.get(offset)?;
+            if first & 0x80 == 0 {
+                offset += 1;
+                break;
+            }
+            if data.len() < offset + 4 {
+                return None;
+            }
+
+            let header = &data[offset..offset + 4];
+            let timestamp_offset = (u16::from(header[1]) << 6) | (u16::from(header[2]) >> 2);
+            let length = ((usize::from(header[2]) & 0x03) << 8) | usize::from(header[3]);
+            headers.push((first & 0x7f, timestamp_offset, length));
+            offset += 4;
+        }
+        let primary_payload_type = data[offset - 1] & 0x7f;
+
+        let mut redundant = Vec::with_capacity(headers.len());
+        for (payload_type, timestamp_offset, length) in headers {
+            if data.len() < offset + length {
+                return None;
+            }
+            redundant.push(RedBlock {
+                payload_type: payload_type,
+                timestamp_offset: timestamp_offset,
+                data: &data[offset..offset + length],
+            });
+            offset += length;
+        }
+
+        Some(RedPayload {
+            redundant: redundant,
+            primary_payload_type: primary_payload_type,
+            primary: &data[offset..],
+        })
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let len = self.redundant.iter().map(|b| 4 + b.data.len()).sum::<usize>() + 1
+            + self.primary.len();
+        let mut data = Vec::with_capacity(len);
+
+        for block in &self.redundant {
+            assert!(u32::from(block.timestamp_offset) <= MAX_TIMESTAMP_OFFSET);
+            assert!(block.data.len() <= MAX_BLOCK_LENGTH);
+
+            data.push(0x80 | block.payload_type);
+            data.push((block.timestamp_offset >> 6) as u8);
+            data.push(((block.timestamp_offset << 2) as u8) | (block.data.len() >> 8) as u8);
+            data.push(block.data.len() as u8);
+        }
+        data.push(self.primary_payload_type & 0x7f);
+
+        for block in &self.redundant {
+            data.extend_from_slice(block.data);
+        }
+        data.extend_from_slice(self.primary);
+
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let payload = RedPayload {
+            redundant: vec![
+                RedBlock {
+                    payload_type: 111,
+                    timestamp_offset: 1920,
+                    data: &[1, 2, 3],
+                },
+                RedBlock {
+                    payload_type: 111,
+                    timestamp_offset: 960,
+                    data: &[4; 300],
+                },
+            ],
+            primary_payload_type: 111,
+            primary: &[5, 6],
+        };
+
+        let data = payload.write();
+        assert_eq!(data.len(), 8 + 1 + 3 + 300 + 2);
+        assert_eq!(RedPayload::parse(&data), Some(payload));
+    }
+
+    #[test]
+    fn test_primary_only() {
+        let payload = RedPayload::parse(&[96, 1, 2]).unwrap();
+        assert!(payload.redundant.is_empty());
+        assert_eq!(payload.primary_payload_type, 96);
+        assert_eq!(payload.primary, &[1, 2]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(RedPayload::parse(&[]), None);
+        assert_eq!(RedPayload::parse(&[0x80 | 96, 0, 0]), None);
+        assert_eq!(RedPayload::parse(&[0x80 | 96, 0, 0, 4, 96, 1]), None);
+    }
+}
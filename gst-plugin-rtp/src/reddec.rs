@@ -0,0 +1,359 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use red::RedPayload;
+use rtp::{self, RtpPacket};
+
+const DEFAULT_PT: u32 = 0;
+
+// Number of sequence numbers to remember for not outputting redundant blocks of packets that
+// were already received
+const HISTORY_SIZE: usize = 64;
+
+static PROPERTIES: [Property; 1] = [
+    Property::UInt(
+        "pt",
+        "Payload Type",
+        "Payload type of the RED packets",
+        (0, 127),
+        DEFAULT_PT,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+struct RedDec {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    pt: Mutex<u32>,
+    // Newest sequence number first
+    history: Mutex<VecDeque<u16>>,
+}
+
+impl RedDec {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rtpreddec2",
+                gst::DebugColorFlags::empty(),
+                "RTP RED decoder",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            pt: Mutex::new(DEFAULT_PT),
+            history: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "RTP RED decoder",
+            "Codec/Depayloader/Network/RTP",
+            "Decodes redundant audio data from RTP packets (RFC 2198)",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("application/x-rtp", &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            RedDec::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |dec, element| dec.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            RedDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |dec, element| dec.sink_event(pad, element, event),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            RedDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |dec, element| dec.src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let dec = element.get_impl().downcast_ref::<RedDec>().unwrap();
+        element.catch_panic(fallback, |element| f(dec, element))
+    }
+
+    fn is_duplicate(&self, seqnum: u16) -> bool {
+        let mut history = self.history.lock().unwrap();
+
+        let newest = match history.front() {
+            None => return false,
+            Some(newest) => *newest,
+        };
+
+        // Too old to know if it was received already
+        if rtp::seqnum_diff(newest, seqnum) <= -(HISTORY_SIZE as i32) {
+            return true;
+        }
+
+        history.contains(&seqnum)
+    }
+
+    fn add_to_history(&self, seqnum: u16) {
+        let mut history = self.history.lock().unwrap();
+
+        if history.contains(&seqnum) {
+            return;
+        }
+
+        let is_newest = history
+            .front()
+            .map(|newest| rtp::seqnum_diff(*newest, seqnum) > 0)
+            .unwrap_or(true);
+        if is_newest {
+            history.push_front(seqnum);
+        } else {
+            history.push_back(seqnum);
+        }
+        history.truncate(HISTORY_SIZE);
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let pt = *self.pt.lock().unwrap();
+
+        let mut outbufs = Vec::new();
+        {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            let packet = match RtpPacket::parse(map.as_slice()) {
+                None => {
+                    gst_warning!(self.cat, obj: pad, "Dropping invalid RTP packet");
+                    return gst::FlowReturn::Ok;
+                }
+                Some(packet) => packet,
+            };
+
+            if pt == 0 || u32::from(packet.payload_type) != pt {
+                self.add_to_history(packet.seqnum);
+                drop(map);
+
+                gst_log!(self.cat, obj: pad, "Passing through packet {:?}", buffer);
+                return self.srcpad.push(buffer);
+            }
+
+            let red_payload = match RedPayload::parse(packet.payload) {
+                None => {
+                    gst_warning!(self.cat, obj: pad, "Dropping invalid RED packet");
+                    return gst::FlowReturn::Ok;
+                }
+                Some(red_payload) => red_payload,
+            };
+
+            // Redundant blocks are for the directly preceding packets, oldest first
+            let num_blocks = red_payload.redundant.len();
+            for (i, block) in red_payload.redundant.iter().enumerate() {
+                let seqnum = packet.seqnum.wrapping_sub((num_blocks - i) as u16);
+                if self.is_duplicate(seqnum) {
+                    continue;
+                }
+
+                let mut block_packet = RtpPacket::new(
+                    block.payload_type,
+                    seqnum,
+                    packet
+                        .timestamp
+                        .wrapping_sub(u32::from(block.timestamp_offset)),
+                    packet.ssrc,
+                    block.data,
+                );
+                block_packet.csrcs = packet.csrcs.clone();
+
+                gst_debug!(
+                    self.cat,
+                    obj: pad,
+                    "Recovered packet {} from redundant block",
+                    seqnum
+                );
+
+                let mut outbuf = gst::Buffer::from_mut_slice(block_packet.write()).unwrap();
+                {
+                    let outbuf = outbuf.get_mut().unwrap();
+                    outbuf.set_pts(buffer.get_pts());
+                    outbuf.set_dts(buffer.get_dts());
+                }
+                self.add_to_history(seqnum);
+                outbufs.push(outbuf);
+            }
+
+            if !self.is_duplicate(packet.seqnum) {
+                let mut primary_packet = packet.clone();
+                primary_packet.payload_type = red_payload.primary_payload_type;
+                primary_packet.payload = red_payload.primary;
+
+                let mut outbuf = gst::Buffer::from_mut_slice(primary_packet.write()).unwrap();
+                {
+                    let outbuf = outbuf.get_mut().unwrap();
+                    outbuf.set_pts(buffer.get_pts());
+                    outbuf.set_dts(buffer.get_dts());
+                    outbuf.set_duration(buffer.get_duration());
+                    outbuf.set_flags(buffer.get_flags());
+                }
+                self.add_to_history(packet.seqnum);
+                outbufs.push(outbuf);
+            }
+        }
+
+        for outbuf in outbufs {
+            gst_log!(self.cat, obj: pad, "Pushing packet {:?}", outbuf);
+            let ret = self.srcpad.push(outbuf);
+            if ret != gst::FlowReturn::Ok {
+                return ret;
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        if let EventView::FlushStop(..) = event.view() {
+            self.history.lock().unwrap().clear();
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for RedDec {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt("pt", ..) => {
+                *self.pt.lock().unwrap() = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt("pt", ..) => Ok(self.pt.lock().unwrap().to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for RedDec {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        if transition == gst::StateChange::PausedToReady {
+            self.history.lock().unwrap().clear();
+        }
+
+        ret
+    }
+}
+
+struct RedDecStatic;
+
+impl ImplTypeStatic<Element> for RedDecStatic {
+    fn get_name(&self) -> &str {
+        "RtpRedDec2"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        RedDec::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        RedDec::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let reddec_static = RedDecStatic;
+    register_type(reddec_static)
+}
@@ -0,0 +1,369 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use red::{self, RedBlock, RedPayload};
+use rtp::{self, RtpPacket};
+
+const DEFAULT_PT: u32 = 0;
+const DEFAULT_DISTANCE: u32 = 0;
+const DEFAULT_ALLOW_NO_RED_BLOCKS: bool = false;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    pt: u32,
+    distance: u32,
+    allow_no_red_blocks: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            pt: DEFAULT_PT,
+            distance: DEFAULT_DISTANCE,
+            allow_no_red_blocks: DEFAULT_ALLOW_NO_RED_BLOCKS,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 3] = [
+    Property::UInt(
+        "pt",
+        "Payload Type",
+        "Payload type of the RED packets",
+        (0, 127),
+        DEFAULT_PT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "distance",
+        "Distance",
+        "Number of previous packets to include as redundant blocks (0 = disabled)",
+        (0, 8),
+        DEFAULT_DISTANCE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "allow-no-red-blocks",
+        "Allow No RED Blocks",
+        "Whether to send RED packets without any redundant blocks",
+        DEFAULT_ALLOW_NO_RED_BLOCKS,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+struct HistoryEntry {
+    seqnum: u16,
+    timestamp: u32,
+    payload_type: u8,
+    payload: Vec<u8>,
+}
+
+struct RedEnc {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    // Newest packet first
+    history: Mutex<VecDeque<HistoryEntry>>,
+}
+
+impl RedEnc {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rtpredenc2",
+                gst::DebugColorFlags::empty(),
+                "RTP RED encoder",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            history: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "RTP RED encoder",
+            "Codec/Payloader/Network/RTP",
+            "Encodes redundant audio data into RTP packets (RFC 2198)",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("application/x-rtp", &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            RedEnc::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |enc, element| enc.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            RedEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |enc, element| enc.sink_event(pad, element, event),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            RedEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |enc, element| enc.src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let enc = element.get_impl().downcast_ref::<RedEnc>().unwrap();
+        element.catch_panic(fallback, |element| f(enc, element))
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+
+        if settings.pt == 0 || settings.distance == 0 {
+            gst_log!(self.cat, obj: pad, "Passing through packet {:?}", buffer);
+            return self.srcpad.push(buffer);
+        }
+
+        let outbuf = {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            let packet = match RtpPacket::parse(map.as_slice()) {
+                None => {
+                    gst_warning!(self.cat, obj: pad, "Dropping invalid RTP packet");
+                    return gst::FlowReturn::Ok;
+                }
+                Some(packet) => packet,
+            };
+
+            let mut history = self.history.lock().unwrap();
+
+            let outbuf = {
+                // Only consecutive previous packets can be included, the receiver infers their
+                // sequence numbers from the position of the blocks
+                let mut blocks = Vec::new();
+                let mut expected_seqnum = packet.seqnum.wrapping_sub(1);
+                for entry in history.iter() {
+                    let timestamp_offset = rtp::timestamp_diff(entry.timestamp, packet.timestamp);
+                    if entry.seqnum != expected_seqnum || timestamp_offset < 0
+                        || timestamp_offset > i64::from(red::MAX_TIMESTAMP_OFFSET)
+                        || entry.payload.len() > red::MAX_BLOCK_LENGTH
+                    {
+                        break;
+                    }
+
+                    blocks.push(RedBlock {
+                        payload_type: entry.payload_type,
+                        timestamp_offset: timestamp_offset as u16,
+                        data: &entry.payload,
+                    });
+                    expected_seqnum = expected_seqnum.wrapping_sub(1);
+                }
+                blocks.reverse();
+
+                if blocks.is_empty() && !settings.allow_no_red_blocks {
+                    None
+                } else {
+                    let red_payload = RedPayload {
+                        redundant: blocks,
+                        primary_payload_type: packet.payload_type,
+                        primary: packet.payload,
+                    }.write();
+
+                    let mut red_packet = packet.clone();
+                    red_packet.payload_type = settings.pt as u8;
+                    red_packet.payload = &red_payload;
+
+                    let mut outbuf = gst::Buffer::from_mut_slice(red_packet.write()).unwrap();
+                    {
+                        let outbuf = outbuf.get_mut().unwrap();
+                        outbuf.set_pts(buffer.get_pts());
+                        outbuf.set_dts(buffer.get_dts());
+                        outbuf.set_duration(buffer.get_duration());
+                        outbuf.set_flags(buffer.get_flags());
+                    }
+                    Some(outbuf)
+                }
+            };
+
+            history.push_front(HistoryEntry {
+                seqnum: packet.seqnum,
+                timestamp: packet.timestamp,
+                payload_type: packet.payload_type,
+                payload: packet.payload.to_vec(),
+            });
+            history.truncate(settings.distance as usize);
+
+            outbuf
+        };
+
+        match outbuf {
+            None => {
+                gst_log!(self.cat, obj: pad, "No redundant blocks, pushing {:?}", buffer);
+                self.srcpad.push(buffer)
+            }
+            Some(outbuf) => {
+                gst_log!(self.cat, obj: pad, "Pushing RED packet {:?}", outbuf);
+                self.srcpad.push(outbuf)
+            }
+        }
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        if let EventView::FlushStop(..) = event.view() {
+            self.history.lock().unwrap().clear();
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for RedEnc {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("pt", ..) => {
+                settings.pt = value.get().unwrap();
+            }
+            Property::UInt("distance", ..) => {
+                settings.distance = value.get().unwrap();
+            }
+            Property::Boolean("allow-no-red-blocks", ..) => {
+                settings.allow_no_red_blocks = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("pt", ..) => Ok(settings.pt.to_value()),
+            Property::UInt("distance", ..) => Ok(settings.distance.to_value()),
+            Property::Boolean("allow-no-red-blocks", ..) => {
+                Ok(settings.allow_no_red_blocks.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for RedEnc {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        if transition == gst::StateChange::PausedToReady {
+            self.history.lock().unwrap().clear();
+        }
+
+        ret
+    }
+}
+
+struct RedEncStatic;
+
+impl ImplTypeStatic<Element> for RedEncStatic {
+    fn get_name(&self) -> &str {
+        "RtpRedEnc2"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        RedEnc::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        RedEnc::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let redenc_static = RedEncStatic;
+    register_type(redenc_static)
+}
@@ -0,0 +1,220 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Minimal RTP packet parsing and writing (RFC 3550)
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const HEADER_LEN: usize = 12;
+pub const VERSION: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtpPacket<'a> {
+    pub marker: bool,
+    pub payload_type: u8,
+    pub seqnum: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    pub csrcs: Vec<u32>,
+    // Profile specific identifier and extension data, a multiple of 4 bytes
+    pub extension: Option<(u16, &'a [u8])>,
+    pub payload: &'a [u8],
+}
+
+fn read_u16(data: &[u8]) -> u16 {
+    (u16::from(data[0]) << 8) | u16::from(data[1])
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    (u32::from(data[0]) << 24) | (u32::from(data[1]) << 16) | (u32::from(data[2]) << 8)
+        | u32::from(data[3])
+}
+
+fn write_u16(data: &mut Vec<u8>, v: u16) {
+    data.push((v >> 8) as u8);
+    data.push(v as u8);
+}
+
+fn write_u32(data: &mut Vec<u8>, v: u32) {
+    data.push((v >> 24) as u8);
+    data.push((v >> 16) as u8);
+    data.push((v >> 8) as u8);
+    data.push(v as u8);
+}
+
+impl<'a> RtpPacket<'a> {
+    pub fn new(payload_type: u8, seqnum: u16, timestamp: u32, ssrc: u32, payload: &'a [u8]) -> Self {
+        RtpPacket {
+            marker: false,
+            payload_type: payload_type,
+            seqnum: seqnum,
+            timestamp: timestamp,
+            ssrc: ssrc,
+            csrcs: Vec::new(),
+            extension: None,
+            payload: payload,
+        }
+    }
+
+    /// Parses an RTP packet, returns `None` if the packet is invalid.
+    pub fn parse(data: &'a [u8]) -> Option<RtpPacket<'a>> {
+        if data.len() < HEADER_LEN || data[0] >> 6 != VERSION {
+            return None;
+        }
+
+        let padding = data[0] & 0x20 != 0;
+        let has_extension = data[0] & 0x10 != 0;
+        let csrc_count = (data[0] & 0x0f) as usize;
+
+        let mut offset = HEADER_LEN;
+        if data.len() < offset + 4 * csrc_count {
+            return None;
+        }
+        let csrcs = (0..csrc_count)
+            .map(|i| read_u32(&data[offset + 4 * i..]))
+            .collect();
+        offset += 4 * csrc_count;
+
+        let extension = if has_extension {
+            if data.len() < offset + 4 {
+                return None;
+            }
+            let id = read_u16(&data[offset..]);
+            let len = 4 * read_u16(&data[offset + 2..]) as usize;
+            offset += 4;
+            if data.len() < offset + len {
+                return None;
+            }
+            let ext = &data[offset..offset + len];
+            offset += len;
+            Some((id, ext))
+        } else {
+            None
+        };
+
+        let mut end = data.len();
+        if padding {
+            let padding_len = data[end - 1] as usize;
+            if padding_len == 0 || end < offset + padding_len {
+                return None;
+            }
+            end -= padding_len;
+        }
+
+        Some(RtpPacket {
+            marker: data[1] & 0x80 != 0,
+            payload_type: data[1] & 0x7f,
+            seqnum: read_u16(&data[2..]),
+            timestamp: read_u32(&data[4..]),
+            ssrc: read_u32(&data[8..]),
+            csrcs: csrcs,
+            extension: extension,
+            payload: &data[offset..end],
+        })
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        assert!(self.csrcs.len() <= 15);
+        assert!(self.payload_type <= 0x7f);
+
+        let ext_len = self.extension.map(|(_, ext)| 4 + ext.len()).unwrap_or(0);
+        let mut data =
+            Vec::with_capacity(HEADER_LEN + 4 * self.csrcs.len() + ext_len + self.payload.len());
+
+        let extension_bit = if self.extension.is_some() { 0x10 } else { 0 };
+        let marker_bit = if self.marker { 0x80 } else { 0 };
+        data.push((VERSION << 6) | extension_bit | self.csrcs.len() as u8);
+        data.push(marker_bit | self.payload_type);
+        write_u16(&mut data, self.seqnum);
+        write_u32(&mut data, self.timestamp);
+        write_u32(&mut data, self.ssrc);
+        for csrc in &self.csrcs {
+            write_u32(&mut data, *csrc);
+        }
+        if let Some((id, ext)) = self.extension {
+            assert_eq!(ext.len() % 4, 0);
+            write_u16(&mut data, id);
+            write_u16(&mut data, (ext.len() / 4) as u16);
+            data.extend_from_slice(ext);
+        }
+        data.extend_from_slice(self.payload);
+
+        data
+    }
+}
+
+/// Difference between two sequence numbers, taking wraparounds into account.
+pub fn seqnum_diff(from: u16, to: u16) -> i32 {
+    i32::from(to.wrapping_sub(from) as i16)
+}
+
+/// Difference between two RTP timestamps, taking wraparounds into account.
+pub fn timestamp_diff(from: u32, to: u32) -> i64 {
+    i64::from(to.wrapping_sub(from) as i32)
+}
+
+// Not cryptographically secure, but good enough for initial sequence numbers, timestamps and
+// SSRCs as long as it differs between instances
+pub fn pseudo_random_u32() -> u32 {
+    use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+    static COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() ^ u64::from(d.subsec_nanos()))
+        .unwrap_or(0);
+    let counter = COUNTER.fetch_add(1, Ordering::Relaxed) as u64;
+
+    // splitmix64 finalizer
+    let mut z = now.wrapping_add(counter.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (z ^ (z >> 31)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let payload = [1u8, 2, 3, 4, 5];
+        let ext = [0xbeu8, 0xde, 0x00, 0x01];
+        let mut packet = RtpPacket::new(96, 65535, 0x1234_5678, 0xdead_beef, &payload);
+        packet.marker = true;
+        packet.csrcs = vec![1, 2];
+        packet.extension = Some((0xbede, &ext));
+
+        let data = packet.write();
+        assert_eq!(data.len(), HEADER_LEN + 8 + 8 + payload.len());
+        assert_eq!(RtpPacket::parse(&data), Some(packet));
+    }
+
+    #[test]
+    fn test_padding() {
+        let mut data = RtpPacket::new(8, 1, 2, 3, &[1, 2, 3]).write();
+        data[0] |= 0x20;
+        data.extend_from_slice(&[0, 0, 3]);
+
+        let packet = RtpPacket::parse(&data).unwrap();
+        assert_eq!(packet.payload, &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(RtpPacket::parse(&[0x80, 0x60, 0x00]), None);
+        assert_eq!(RtpPacket::parse(&[0u8; 12]), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(seqnum_diff(65535, 1), 2);
+        assert_eq!(seqnum_diff(1, 65535), -2);
+        assert_eq!(timestamp_diff(0xffff_fff0, 0x10), 0x20);
+    }
+}
@@ -0,0 +1,405 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// RTP header extensions (RFC 8285)
+//
+// Extensions are negotiated with "extmap-<id>" fields in the caps, like in SDP, which contain
+// the URI of the extension. Payloaders and depayloaders keep a `HeaderExtensions` set for
+// writing and reading all of them.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gst;
+
+use rtp::{self, RtpPacket};
+
+pub const ONE_BYTE_PROFILE: u16 = 0xbede;
+// The lower 4 bits are application specific
+pub const TWO_BYTE_PROFILE: u16 = 0x1000;
+
+pub const TRANSPORT_CC_URI: &str =
+    "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01";
+pub const ABS_SEND_TIME_URI: &str = "http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time";
+pub const MID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:mid";
+pub const RID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id";
+pub const REPAIRED_RID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:repaired-rtp-stream-id";
+
+/// Parses the extension elements of an RTP header extension, returns `None` if the extension
+/// does not use one of the RFC 8285 formats or is invalid.
+pub fn parse(profile: u16, data: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let one_byte = if profile == ONE_BYTE_PROFILE {
+        true
+    } else if profile & 0xfff0 == TWO_BYTE_PROFILE {
+        false
+    } else {
+        return None;
+    };
+
+    let mut elements = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        // Padding
+        if data[offset] == 0 {
+            offset += 1;
+            continue;
+        }
+
+        let (id, len, header_len) = if one_byte {
+            let id = data[offset] >> 4;
+            // Reserved for future extensions, stop processing
+            if id == 15 {
+                break;
+            }
+            (id, (data[offset] & 0x0f) as usize + 1, 1)
+        } else {
+            let len = *data.get(offset + 1)? as usize;
+            (data[offset], len, 2)
+        };
+
+        offset += header_len;
+        if data.len() < offset + len {
+            return None;
+        }
+        elements.push((id, &data[offset..offset + len]));
+        offset += len;
+    }
+
+    Some(elements)
+}
+
+/// Writes the given extension elements into an RTP header extension, using the one-byte format
+/// if possible. The result is padded to a multiple of 4 bytes.
+pub fn write(elements: &[(u8, &[u8])]) -> (u16, Vec<u8>) {
+    let one_byte = elements
+        .iter()
+        .all(|&(id, data)| id >= 1 && id <= 14 && !data.is_empty() && data.len() <= 16);
+
+    let mut ext = Vec::new();
+    for &(id, data) in elements {
+        assert!(id != 0 && data.len() <= 255);
+
+        if one_byte {
+            ext.push((id << 4) | (data.len() - 1) as u8);
+        } else {
+            ext.push(id);
+            ext.push(data.len() as u8);
+        }
+        ext.extend_from_slice(data);
+    }
+
+    while ext.len() % 4 != 0 {
+        ext.push(0);
+    }
+
+    if one_byte {
+        (ONE_BYTE_PROFILE, ext)
+    } else {
+        (TWO_BYTE_PROFILE, ext)
+    }
+}
+
+pub trait HeaderExtension: Send {
+    fn uri(&self) -> &'static str;
+
+    /// Data of the extension element for the outgoing packet `packet`, or `None` if the
+    /// extension should not be included.
+    fn write(&mut self, packet: &RtpPacket) -> Option<Vec<u8>>;
+
+    /// Reads the extension element of an incoming packet, returns `false` if it is invalid.
+    fn read(&mut self, data: &[u8]) -> bool;
+
+    /// The value of string based extensions like the MID.
+    fn get_value(&self) -> Option<&str> {
+        None
+    }
+
+    fn set_value(&mut self, _value: Option<&str>) {}
+}
+
+/// Transport-wide sequence numbers for congestion control, counted over all packets of the
+/// extension.
+pub struct TransportCc {
+    seqnum: u16,
+}
+
+impl TransportCc {
+    pub fn new() -> Self {
+        TransportCc {
+            seqnum: rtp::pseudo_random_u32() as u16,
+        }
+    }
+}
+
+impl HeaderExtension for TransportCc {
+    fn uri(&self) -> &'static str {
+        TRANSPORT_CC_URI
+    }
+
+    fn write(&mut self, _packet: &RtpPacket) -> Option<Vec<u8>> {
+        let seqnum = self.seqnum;
+        self.seqnum = self.seqnum.wrapping_add(1);
+        Some(vec![(seqnum >> 8) as u8, seqnum as u8])
+    }
+
+    fn read(&mut self, data: &[u8]) -> bool {
+        if data.len() != 2 {
+            return false;
+        }
+        self.seqnum = (u16::from(data[0]) << 8) | u16::from(data[1]);
+        true
+    }
+}
+
+/// Send time of the packet as 6.18 fixed point NTP time in seconds.
+pub struct AbsSendTime;
+
+// Seconds between 1900-01-01 and 1970-01-01
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+pub fn abs_send_time(time: SystemTime) -> u32 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() + NTP_UNIX_OFFSET;
+    let frac = (u64::from(since_epoch.subsec_nanos()) << 18) / 1_000_000_000;
+
+    (((secs << 18) | frac) & 0x00ff_ffff) as u32
+}
+
+impl HeaderExtension for AbsSendTime {
+    fn uri(&self) -> &'static str {
+        ABS_SEND_TIME_URI
+    }
+
+    fn write(&mut self, _packet: &RtpPacket) -> Option<Vec<u8>> {
+        let time = abs_send_time(SystemTime::now());
+        Some(vec![(time >> 16) as u8, (time >> 8) as u8, time as u8])
+    }
+
+    fn read(&mut self, data: &[u8]) -> bool {
+        data.len() == 3
+    }
+}
+
+/// RTCP SDES items like the MID or RID that are sent as header extensions for bundling.
+pub struct SdesItem {
+    uri: &'static str,
+    value: Option<String>,
+}
+
+impl SdesItem {
+    pub fn new(uri: &'static str) -> Self {
+        SdesItem {
+            uri: uri,
+            value: None,
+        }
+    }
+}
+
+impl HeaderExtension for SdesItem {
+    fn uri(&self) -> &'static str {
+        self.uri
+    }
+
+    fn write(&mut self, _packet: &RtpPacket) -> Option<Vec<u8>> {
+        self.value.as_ref().map(|value| value.as_bytes().to_vec())
+    }
+
+    fn read(&mut self, data: &[u8]) -> bool {
+        match String::from_utf8(data.to_vec()) {
+            Err(_) => false,
+            Ok(value) => {
+                self.value = Some(value);
+                true
+            }
+        }
+    }
+
+    fn get_value(&self) -> Option<&str> {
+        self.value.as_ref().map(String::as_str)
+    }
+
+    fn set_value(&mut self, value: Option<&str>) {
+        self.value = value.map(String::from);
+    }
+}
+
+/// Creates the implementation of the extension with the given URI.
+pub fn create(uri: &str) -> Option<Box<HeaderExtension>> {
+    match uri {
+        TRANSPORT_CC_URI => Some(Box::new(TransportCc::new())),
+        ABS_SEND_TIME_URI => Some(Box::new(AbsSendTime)),
+        MID_URI => Some(Box::new(SdesItem::new(MID_URI))),
+        RID_URI => Some(Box::new(SdesItem::new(RID_URI))),
+        REPAIRED_RID_URI => Some(Box::new(SdesItem::new(REPAIRED_RID_URI))),
+        _ => None,
+    }
+}
+
+/// ID of the extension with the given URI from the "extmap-<id>" fields of `s`.
+pub fn find_id(s: &gst::StructureRef, uri: &str) -> Option<u8> {
+    s.iter()
+        .filter(|&(_, value)| value.get::<String>().map(|v| v == uri).unwrap_or(false))
+        .filter_map(|(field, _)| {
+            if field.starts_with("extmap-") {
+                field["extmap-".len()..].parse::<u8>().ok()
+            } else {
+                None
+            }
+        })
+        .find(|&id| id != 0)
+}
+
+/// All extensions negotiated for a stream, with their IDs.
+#[derive(Default)]
+pub struct HeaderExtensions {
+    extensions: Vec<(u8, Box<HeaderExtension>)>,
+}
+
+impl HeaderExtensions {
+    /// Creates the extensions from the "extmap-<id>" fields of `s`. Extensions that are not
+    /// implemented are ignored, invalid IDs are an error.
+    pub fn from_structure(s: &gst::StructureRef) -> Result<Self, String> {
+        let mut extensions = Vec::new();
+
+        for (field, value) in s.iter() {
+            if !field.starts_with("extmap-") {
+                continue;
+            }
+
+            let id = match field["extmap-".len()..].parse::<u8>() {
+                Ok(id) if id != 0 => id,
+                _ => return Err(format!("Invalid extension ID in '{}'", field)),
+            };
+            let uri = match value.get::<String>() {
+                None => return Err(format!("Invalid extension URI for ID {}", id)),
+                Some(uri) => uri,
+            };
+
+            if let Some(ext) = create(&uri) {
+                extensions.push((id, ext));
+            }
+        }
+
+        extensions.sort_by_key(|&(id, _)| id);
+
+        Ok(HeaderExtensions {
+            extensions: extensions,
+        })
+    }
+
+    /// Sets the value of the string based extension with the given URI.
+    pub fn set_value(&mut self, uri: &str, value: Option<&str>) {
+        for &mut (_, ref mut ext) in &mut self.extensions {
+            if ext.uri() == uri {
+                ext.set_value(value);
+            }
+        }
+    }
+
+    pub fn get_value(&self, uri: &str) -> Option<&str> {
+        self.extensions
+            .iter()
+            .find(|&&(_, ref ext)| ext.uri() == uri)
+            .and_then(|&(_, ref ext)| ext.get_value())
+    }
+
+    /// Adds the "extmap-<id>" fields for all extensions to `s`.
+    pub fn add_to_structure(&self, s: &mut gst::StructureRef) {
+        for &(id, ref ext) in &self.extensions {
+            s.set(&format!("extmap-{}", id), &ext.uri());
+        }
+    }
+
+    /// Header extension for the outgoing packet `packet`.
+    pub fn write(&mut self, packet: &RtpPacket) -> Option<(u16, Vec<u8>)> {
+        let elements = self.extensions
+            .iter_mut()
+            .filter_map(|&mut (id, ref mut ext)| ext.write(packet).map(|data| (id, data)))
+            .collect::<Vec<_>>();
+
+        if elements.is_empty() {
+            return None;
+        }
+
+        let elements = elements
+            .iter()
+            .map(|&(id, ref data)| (id, &data[..]))
+            .collect::<Vec<_>>();
+        Some(write(&elements))
+    }
+
+    /// Reads all known extension elements of the incoming packet `packet`.
+    pub fn read(&mut self, packet: &RtpPacket) {
+        let elements = match packet.extension.and_then(|(profile, data)| parse(profile, data)) {
+            None => return,
+            Some(elements) => elements,
+        };
+
+        for (id, data) in elements {
+            for &mut (ext_id, ref mut ext) in &mut self.extensions {
+                if ext_id == id {
+                    ext.read(data);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_byte() {
+        let (profile, data) = write(&[(1, &[1, 2]), (14, &[3])]);
+        assert_eq!(profile, ONE_BYTE_PROFILE);
+        assert_eq!(data, vec![0x11, 1, 2, 0xe0, 3, 0, 0, 0]);
+
+        let elements = parse(profile, &data).unwrap();
+        assert_eq!(elements, vec![(1, &[1u8, 2][..]), (14, &[3u8][..])]);
+    }
+
+    #[test]
+    fn test_two_byte() {
+        let long = [7u8; 20];
+        let (profile, data) = write(&[(1, &[1]), (2, &long)]);
+        assert_eq!(profile, TWO_BYTE_PROFILE);
+        assert_eq!(data.len(), 2 + 1 + 2 + 20 + 3);
+
+        let elements = parse(profile, &data).unwrap();
+        assert_eq!(elements, vec![(1, &[1u8][..]), (2, &long[..])]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(parse(0x1234, &[]), None);
+        assert_eq!(parse(ONE_BYTE_PROFILE, &[0x13, 1]), None);
+        assert_eq!(parse(TWO_BYTE_PROFILE, &[1, 4, 1, 2]), None);
+    }
+
+    #[test]
+    fn test_abs_send_time() {
+        let time = UNIX_EPOCH + ::std::time::Duration::new(1, 500_000_000);
+        let expected = (((NTP_UNIX_OFFSET + 1) << 18) | (1 << 17)) & 0x00ff_ffff;
+        assert_eq!(abs_send_time(time), expected as u32);
+    }
+
+    #[test]
+    fn test_sdes_roundtrip() {
+        let payload = [0u8; 4];
+        let packet = RtpPacket::new(96, 0, 0, 0, &payload);
+
+        let mut mid = SdesItem::new(MID_URI);
+        assert_eq!(mid.write(&packet), None);
+        mid.set_value(Some("audio"));
+        let data = mid.write(&packet).unwrap();
+
+        let mut received = SdesItem::new(MID_URI);
+        assert!(received.read(&data));
+        assert_eq!(received.get_value(), Some("audio"));
+    }
+}
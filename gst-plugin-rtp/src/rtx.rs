@@ -0,0 +1,93 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// RTP retransmission payload format (RFC 4588)
+
+use std::collections::HashMap;
+
+use gst;
+
+use rtp::RtpPacket;
+
+/// Creates the retransmission packet for `packet`, keeping everything but the payload type,
+/// SSRC and sequence number and prepending the original sequence number to the payload.
+pub fn create(packet: &RtpPacket, payload_type: u8, ssrc: u32, seqnum: u16) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(2 + packet.payload.len());
+    payload.push((packet.seqnum >> 8) as u8);
+    payload.push(packet.seqnum as u8);
+    payload.extend_from_slice(packet.payload);
+
+    let mut rtx_packet = packet.clone();
+    rtx_packet.payload_type = payload_type;
+    rtx_packet.ssrc = ssrc;
+    rtx_packet.seqnum = seqnum;
+    rtx_packet.payload = &payload;
+
+    rtx_packet.write()
+}
+
+/// Restores the original packet from the retransmission packet `packet`. Returns `None` if the
+/// payload does not contain the original sequence number.
+pub fn restore(packet: &RtpPacket, payload_type: u8, ssrc: u32) -> Option<Vec<u8>> {
+    let mut orig_packet = packet.clone();
+    orig_packet.payload_type = payload_type;
+    orig_packet.ssrc = ssrc;
+    orig_packet.seqnum = original_seqnum(packet)?;
+    orig_packet.payload = &packet.payload[2..];
+
+    Some(orig_packet.write())
+}
+
+/// Original sequence number of the retransmission packet `packet`.
+pub fn original_seqnum(packet: &RtpPacket) -> Option<u16> {
+    if packet.payload.len() < 2 {
+        return None;
+    }
+
+    Some((u16::from(packet.payload[0]) << 8) | u16::from(packet.payload[1]))
+}
+
+/// Parses a structure mapping SSRCs or payload types, given as field names, to the
+/// corresponding unsigned integer values. Invalid fields are ignored.
+pub fn parse_map(s: &gst::Structure) -> HashMap<u32, u32> {
+    s.iter()
+        .filter_map(|(field, value)| Some((field.parse::<u32>().ok()?, value.get::<u32>()?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let payload = [1u8, 2, 3];
+        let mut packet = RtpPacket::new(96, 1234, 5678, 0x1111_1111, &payload);
+        packet.marker = true;
+
+        let rtx_data = create(&packet, 97, 0x2222_2222, 1);
+        let rtx_packet = RtpPacket::parse(&rtx_data).unwrap();
+        assert_eq!(rtx_packet.payload_type, 97);
+        assert_eq!(rtx_packet.ssrc, 0x2222_2222);
+        assert_eq!(rtx_packet.seqnum, 1);
+        assert_eq!(rtx_packet.timestamp, 5678);
+        assert!(rtx_packet.marker);
+        assert_eq!(rtx_packet.payload, &[0x04, 0xd2, 1, 2, 3]);
+        assert_eq!(original_seqnum(&rtx_packet), Some(1234));
+
+        let orig_data = restore(&rtx_packet, 96, 0x1111_1111).unwrap();
+        assert_eq!(RtpPacket::parse(&orig_data), Some(packet));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let packet = RtpPacket::new(97, 1, 0, 0, &[0]);
+        assert_eq!(original_seqnum(&packet), None);
+        assert_eq!(restore(&packet, 96, 0), None);
+    }
+}
@@ -0,0 +1,472 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::u32;
+
+use rtp::RtpPacket;
+use rtx;
+
+// Requests that were not answered after this many further requests are forgotten
+const MAX_PENDING_REQUESTS: usize = 256;
+
+static PROPERTIES: [Property; 5] = [
+    Property::Boxed(
+        "ssrc-map",
+        "SSRC Map",
+        "Map of SSRCs to their retransmission SSRCs, all others are associated based on the requested packets",
+        gst::Structure::static_type,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boxed(
+        "payload-type-map",
+        "Payload Type Map",
+        "Map of payload types to their retransmission payload types",
+        gst::Structure::static_type,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "num-rtx-requests",
+        "Number of Retransmission Requests",
+        "Number of retransmission requests sent upstream",
+        (0, u32::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+    Property::UInt(
+        "num-rtx-packets",
+        "Number of Retransmission Packets",
+        "Number of retransmission packets received",
+        (0, u32::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+    Property::UInt(
+        "num-rtx-assoc-packets",
+        "Number of Associated Retransmission Packets",
+        "Number of retransmission packets that answered a request",
+        (0, u32::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+];
+
+#[derive(Debug, Clone, Default)]
+struct Settings {
+    ssrc_map: Option<gst::Structure>,
+    payload_type_map: Option<gst::Structure>,
+}
+
+#[derive(Default)]
+struct State {
+    // Retransmission SSRC to original SSRC, from the settings or learned from requests
+    ssrc_assoc: HashMap<u32, u32>,
+    // Retransmission payload type to original payload type
+    payload_types: HashMap<u32, u32>,
+    // Sequence number and SSRC of requested packets, oldest first
+    pending_requests: VecDeque<(u16, u32)>,
+    num_rtx_requests: u32,
+    num_rtx_packets: u32,
+    num_rtx_assoc_packets: u32,
+}
+
+impl State {
+    fn reset(&mut self, settings: &Settings) {
+        *self = State {
+            num_rtx_requests: self.num_rtx_requests,
+            num_rtx_packets: self.num_rtx_packets,
+            num_rtx_assoc_packets: self.num_rtx_assoc_packets,
+            ..State::default()
+        };
+
+        // Both maps are given in the same direction as for the sender
+        if let Some(ref ssrc_map) = settings.ssrc_map {
+            for (ssrc, rtx_ssrc) in rtx::parse_map(ssrc_map) {
+                self.ssrc_assoc.insert(rtx_ssrc, ssrc);
+            }
+        }
+        if let Some(ref payload_type_map) = settings.payload_type_map {
+            for (pt, rtx_pt) in rtx::parse_map(payload_type_map) {
+                self.payload_types.insert(rtx_pt, pt);
+            }
+        }
+    }
+
+    // Removes the request for the given packet and returns the SSRC it was requested for
+    fn take_request(&mut self, seqnum: u16, ssrc: Option<u32>) -> Option<u32> {
+        let pos = self
+            .pending_requests
+            .iter()
+            .position(|&(s, r)| s == seqnum && ssrc.map(|ssrc| ssrc == r).unwrap_or(true))?;
+        self.pending_requests.remove(pos).map(|(_, ssrc)| ssrc)
+    }
+}
+
+enum Output {
+    PassThrough,
+    Drop,
+    // Restored packet and whether it answered a pending request
+    Restored(gst::Buffer, bool),
+}
+
+struct RtxReceive {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl RtxReceive {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rtprtxreceive2",
+                gst::DebugColorFlags::empty(),
+                "RTP retransmission receiver",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "RTP retransmission receiver",
+            "Codec/Network/RTP",
+            "Restores the original packets from retransmitted RTP packets (RFC 4588)",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("application/x-rtp", &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            RtxReceive::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |rtx, element| rtx.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            RtxReceive::catch_panic_pad_function(
+                parent,
+                || false,
+                |rtx, element| rtx.sink_event(pad, element, event),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            RtxReceive::catch_panic_pad_function(
+                parent,
+                || false,
+                |rtx, element| rtx.src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let rtx = element.get_impl().downcast_ref::<RtxReceive>().unwrap();
+        element.catch_panic(fallback, |element| f(rtx, element))
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let output = {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            let packet = match RtpPacket::parse(map.as_slice()) {
+                None => {
+                    gst_warning!(self.cat, obj: pad, "Dropping invalid RTP packet");
+                    return gst::FlowReturn::Ok;
+                }
+                Some(packet) => packet,
+            };
+
+            let mut state = self.state.lock().unwrap();
+            let pt = state
+                .payload_types
+                .get(&u32::from(packet.payload_type))
+                .cloned();
+            match pt {
+                None => Output::PassThrough,
+                Some(pt) => self.restore_packet(pad, &mut state, &packet, pt as u8, &buffer),
+            }
+        };
+
+        match output {
+            Output::PassThrough => self.srcpad.push(buffer),
+            Output::Drop => gst::FlowReturn::Ok,
+            Output::Restored(outbuf, associated) => {
+                self.notify(&element.clone().upcast(), "num-rtx-packets");
+                if associated {
+                    self.notify(&element.clone().upcast(), "num-rtx-assoc-packets");
+                }
+
+                self.srcpad.push(outbuf)
+            }
+        }
+    }
+
+    fn restore_packet(
+        &self,
+        pad: &gst::Pad,
+        state: &mut State,
+        packet: &RtpPacket,
+        pt: u8,
+        buffer: &gst::Buffer,
+    ) -> Output {
+        let seqnum = match rtx::original_seqnum(packet) {
+            None => {
+                gst_warning!(self.cat, obj: pad, "Dropping invalid retransmission packet");
+                return Output::Drop;
+            }
+            Some(seqnum) => seqnum,
+        };
+
+        // Unknown retransmission SSRCs are associated with the SSRC for which this sequence
+        // number was requested
+        let known_ssrc = state.ssrc_assoc.get(&packet.ssrc).cloned();
+        let requested_ssrc = state.take_request(seqnum, known_ssrc);
+        let ssrc = match known_ssrc.or(requested_ssrc) {
+            None => {
+                gst_debug!(
+                    self.cat,
+                    obj: pad,
+                    "Dropping retransmission packet {} of unknown SSRC {:08x}",
+                    seqnum,
+                    packet.ssrc
+                );
+                return Output::Drop;
+            }
+            Some(ssrc) => ssrc,
+        };
+
+        if known_ssrc.is_none() {
+            gst_debug!(
+                self.cat,
+                obj: pad,
+                "Associated retransmission SSRC {:08x} with SSRC {:08x}",
+                packet.ssrc,
+                ssrc
+            );
+            state.ssrc_assoc.insert(packet.ssrc, ssrc);
+        }
+
+        let data = rtx::restore(packet, pt, ssrc).unwrap();
+        let mut outbuf = gst::Buffer::from_mut_slice(data).unwrap();
+        {
+            let outbuf = outbuf.get_mut().unwrap();
+            outbuf.set_pts(buffer.get_pts());
+            outbuf.set_dts(buffer.get_dts());
+            outbuf.set_flags(buffer.get_flags());
+        }
+
+        state.num_rtx_packets += 1;
+        if requested_ssrc.is_some() {
+            state.num_rtx_assoc_packets += 1;
+        }
+
+        gst_log!(
+            self.cat,
+            obj: pad,
+            "Restored packet {} of SSRC {:08x}",
+            seqnum,
+            ssrc
+        );
+
+        Output::Restored(outbuf, requested_ssrc.is_some())
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        if let EventView::FlushStop(..) = event.view() {
+            self.state.lock().unwrap().pending_requests.clear();
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        // Remember the requests from the jitterbuffer for associating the retransmission
+        // packets, the request itself is handled by the sender
+        if let EventView::CustomUpstream(..) = event.view() {
+            let request = event.get_structure().and_then(|s| {
+                if s.get_name() != "GstRTPRetransmissionRequest" {
+                    return None;
+                }
+                Some((s.get::<u32>("seqnum")?, s.get::<u32>("ssrc")?))
+            });
+
+            if let Some((seqnum, ssrc)) = request {
+                gst_trace!(
+                    self.cat,
+                    obj: pad,
+                    "Retransmission of packet {} of SSRC {:08x} requested",
+                    seqnum,
+                    ssrc
+                );
+
+                {
+                    let mut state = self.state.lock().unwrap();
+                    state.pending_requests.push_back((seqnum as u16, ssrc));
+                    while state.pending_requests.len() > MAX_PENDING_REQUESTS {
+                        state.pending_requests.pop_front();
+                    }
+                    state.num_rtx_requests += 1;
+                }
+
+                self.notify(&element.clone().upcast(), "num-rtx-requests");
+            }
+        }
+
+        self.sinkpad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for RtxReceive {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Boxed("ssrc-map", ..) => {
+                settings.ssrc_map = value.get();
+            }
+            Property::Boxed("payload-type-map", ..) => {
+                settings.payload_type_map = value.get();
+            }
+            _ => unimplemented!(),
+        }
+
+        self.state.lock().unwrap().reset(&settings);
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Boxed("ssrc-map", ..) => Ok(settings.ssrc_map.to_value()),
+            Property::Boxed("payload-type-map", ..) => Ok(settings.payload_type_map.to_value()),
+            Property::UInt("num-rtx-requests", ..) => {
+                Ok(self.state.lock().unwrap().num_rtx_requests.to_value())
+            }
+            Property::UInt("num-rtx-packets", ..) => {
+                Ok(self.state.lock().unwrap().num_rtx_packets.to_value())
+            }
+            Property::UInt("num-rtx-assoc-packets", ..) => {
+                Ok(self.state.lock().unwrap().num_rtx_assoc_packets.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for RtxReceive {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if transition == gst::StateChange::ReadyToPaused {
+            let settings = self.settings.lock().unwrap().clone();
+            let mut state = self.state.lock().unwrap();
+            state.num_rtx_requests = 0;
+            state.num_rtx_packets = 0;
+            state.num_rtx_assoc_packets = 0;
+            state.reset(&settings);
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct RtxReceiveStatic;
+
+impl ImplTypeStatic<Element> for RtxReceiveStatic {
+    fn get_name(&self) -> &str {
+        "RtpRtxReceive2"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        RtxReceive::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        RtxReceive::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let rtxreceive_static = RtxReceiveStatic;
+    register_type(rtxreceive_static)
+}
@@ -0,0 +1,521 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::u32;
+
+use rtp::{self, RtpPacket};
+use rtx;
+
+const DEFAULT_MAX_SIZE_PACKETS: u32 = 100;
+const DEFAULT_MAX_SIZE_TIME: u32 = 0;
+
+static PROPERTIES: [Property; 6] = [
+    Property::Boxed(
+        "ssrc-map",
+        "SSRC Map",
+        "Map of SSRCs to their retransmission SSRCs, random SSRCs are used for all others",
+        gst::Structure::static_type,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boxed(
+        "payload-type-map",
+        "Payload Type Map",
+        "Map of payload types to their retransmission payload types, only packets with a payload type in this map are retransmitted",
+        gst::Structure::static_type,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "max-size-packets",
+        "Maximum Size in Packets",
+        "Maximum number of packets per SSRC kept for retransmission (0 = unlimited)",
+        (0, u32::MAX),
+        DEFAULT_MAX_SIZE_PACKETS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "max-size-time",
+        "Maximum Size in Time",
+        "Maximum age in milliseconds of packets kept for retransmission (0 = unlimited)",
+        (0, u32::MAX),
+        DEFAULT_MAX_SIZE_TIME,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "num-rtx-requests",
+        "Number of Retransmission Requests",
+        "Number of retransmission requests received",
+        (0, u32::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+    Property::UInt(
+        "num-rtx-packets",
+        "Number of Retransmission Packets",
+        "Number of retransmission packets sent",
+        (0, u32::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    ssrc_map: Option<gst::Structure>,
+    payload_type_map: Option<gst::Structure>,
+    max_size_packets: u32,
+    max_size_time: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            ssrc_map: None,
+            payload_type_map: None,
+            max_size_packets: DEFAULT_MAX_SIZE_PACKETS,
+            max_size_time: DEFAULT_MAX_SIZE_TIME,
+        }
+    }
+}
+
+struct HistoryEntry {
+    seqnum: u16,
+    pts: gst::ClockTime,
+    data: Vec<u8>,
+}
+
+struct Stream {
+    rtx_ssrc: u32,
+    rtx_seqnum: u16,
+    // Newest packet last
+    history: VecDeque<HistoryEntry>,
+}
+
+#[derive(Default)]
+struct State {
+    streams: HashMap<u32, Stream>,
+    // Parsed from the settings whenever they change
+    ssrc_map: HashMap<u32, u32>,
+    payload_type_map: HashMap<u32, u32>,
+    // Retransmission packets to be sent before the next media packet
+    pending: Vec<gst::Buffer>,
+    num_rtx_requests: u32,
+    num_rtx_packets: u32,
+}
+
+struct RtxSend {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl RtxSend {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rtprtxsend2",
+                gst::DebugColorFlags::empty(),
+                "RTP retransmission sender",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "RTP retransmission sender",
+            "Codec/Network/RTP",
+            "Retransmits RTP packets on request (RFC 4588)",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("application/x-rtp", &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            RtxSend::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |rtx, element| rtx.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            RtxSend::catch_panic_pad_function(
+                parent,
+                || false,
+                |rtx, element| rtx.sink_event(pad, element, event),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            RtxSend::catch_panic_pad_function(
+                parent,
+                || false,
+                |rtx, element| rtx.src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let rtx = element.get_impl().downcast_ref::<RtxSend>().unwrap();
+        element.catch_panic(fallback, |element| f(rtx, element))
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let pending = {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            let mut state = self.state.lock().unwrap();
+            self.store_packet(pad, &mut state, &settings, map.as_slice(), buffer.get_pts());
+            state.pending.drain(..).collect::<Vec<_>>()
+        };
+
+        self.push_pending(pending, buffer)
+    }
+
+    fn store_packet(
+        &self,
+        pad: &gst::Pad,
+        state: &mut State,
+        settings: &Settings,
+        data: &[u8],
+        pts: gst::ClockTime,
+    ) {
+        let packet = match RtpPacket::parse(data) {
+            None => {
+                gst_warning!(self.cat, obj: pad, "Not storing invalid RTP packet");
+                return;
+            }
+            Some(packet) => packet,
+        };
+
+        if !state
+            .payload_type_map
+            .contains_key(&u32::from(packet.payload_type))
+        {
+            gst_trace!(
+                self.cat,
+                obj: pad,
+                "Not storing packet with payload type {}",
+                packet.payload_type
+            );
+            return;
+        }
+
+        let cat = self.cat;
+        let ssrc_map = &state.ssrc_map;
+        let stream = state.streams.entry(packet.ssrc).or_insert_with(|| {
+            let rtx_ssrc = ssrc_map
+                .get(&packet.ssrc)
+                .cloned()
+                .unwrap_or_else(rtp::pseudo_random_u32);
+            gst_debug!(
+                cat,
+                obj: pad,
+                "New SSRC {:08x}, retransmitting with SSRC {:08x}",
+                packet.ssrc,
+                rtx_ssrc
+            );
+            Stream {
+                rtx_ssrc: rtx_ssrc,
+                rtx_seqnum: rtp::pseudo_random_u32() as u16,
+                history: VecDeque::new(),
+            }
+        });
+
+        stream.history.push_back(HistoryEntry {
+            seqnum: packet.seqnum,
+            pts: pts,
+            data: data.to_vec(),
+        });
+
+        if settings.max_size_packets != 0 {
+            while stream.history.len() > settings.max_size_packets as usize {
+                stream.history.pop_front();
+            }
+        }
+
+        if let (Some(pts), true) = (pts.0, settings.max_size_time != 0) {
+            let max_age = u64::from(settings.max_size_time) * gst::MSECOND_VAL;
+            while stream
+                .history
+                .front()
+                .and_then(|entry| entry.pts.0)
+                .map(|oldest| oldest + max_age < pts)
+                .unwrap_or(false)
+            {
+                stream.history.pop_front();
+            }
+        }
+    }
+
+    fn push_pending(&self, pending: Vec<gst::Buffer>, buffer: gst::Buffer) -> gst::FlowReturn {
+        for rtx_buffer in pending {
+            gst_log!(self.cat, obj: &self.srcpad, "Pushing retransmission {:?}", rtx_buffer);
+            let ret = self.srcpad.push(rtx_buffer);
+            if ret != gst::FlowReturn::Ok {
+                return ret;
+            }
+        }
+
+        self.srcpad.push(buffer)
+    }
+
+    fn handle_retransmission_request(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        seqnum: u16,
+        ssrc: u32,
+    ) -> bool {
+        {
+            let mut state = self.state.lock().unwrap();
+            let state = &mut *state;
+
+            let stream = match state.streams.get_mut(&ssrc) {
+                // Not our stream, maybe someone further upstream can handle it
+                None => return false,
+                Some(stream) => stream,
+            };
+
+            state.num_rtx_requests += 1;
+
+            let rtx_buffer = match stream.history.iter().rev().find(|e| e.seqnum == seqnum) {
+                None => None,
+                Some(entry) => RtpPacket::parse(&entry.data).and_then(|packet| {
+                    let rtx_pt = *state
+                        .payload_type_map
+                        .get(&u32::from(packet.payload_type))?;
+                    let data =
+                        rtx::create(&packet, rtx_pt as u8, stream.rtx_ssrc, stream.rtx_seqnum);
+
+                    let mut rtx_buffer = gst::Buffer::from_mut_slice(data).unwrap();
+                    rtx_buffer.get_mut().unwrap().set_pts(entry.pts);
+                    Some(rtx_buffer)
+                }),
+            };
+
+            match rtx_buffer {
+                None => {
+                    gst_debug!(
+                        self.cat,
+                        obj: pad,
+                        "Packet {} of SSRC {:08x} not in history anymore",
+                        seqnum,
+                        ssrc
+                    );
+                }
+                Some(rtx_buffer) => {
+                    gst_debug!(
+                        self.cat,
+                        obj: pad,
+                        "Retransmitting packet {} of SSRC {:08x}",
+                        seqnum,
+                        ssrc
+                    );
+                    stream.rtx_seqnum = stream.rtx_seqnum.wrapping_add(1);
+                    state.pending.push(rtx_buffer);
+                    state.num_rtx_packets += 1;
+                }
+            }
+        }
+
+        self.notify(&element.clone().upcast(), "num-rtx-requests");
+        self.notify(&element.clone().upcast(), "num-rtx-packets");
+
+        true
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        if let EventView::FlushStop(..) = event.view() {
+            let mut state = self.state.lock().unwrap();
+            state.streams.clear();
+            state.pending.clear();
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        // Retransmission requests from the receiver's jitterbuffer
+        if let EventView::CustomUpstream(..) = event.view() {
+            let request = event.get_structure().and_then(|s| {
+                if s.get_name() != "GstRTPRetransmissionRequest" {
+                    return None;
+                }
+                Some((s.get::<u32>("seqnum")?, s.get::<u32>("ssrc")?))
+            });
+
+            if let Some((seqnum, ssrc)) = request {
+                if self.handle_retransmission_request(pad, element, seqnum as u16, ssrc) {
+                    return true;
+                }
+            }
+        }
+
+        self.sinkpad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for RtxSend {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Boxed("ssrc-map", ..) => {
+                settings.ssrc_map = value.get();
+                self.state.lock().unwrap().ssrc_map =
+                    settings.ssrc_map.as_ref().map(rtx::parse_map).unwrap_or_default();
+            }
+            Property::Boxed("payload-type-map", ..) => {
+                settings.payload_type_map = value.get();
+                self.state.lock().unwrap().payload_type_map = settings
+                    .payload_type_map
+                    .as_ref()
+                    .map(rtx::parse_map)
+                    .unwrap_or_default();
+            }
+            Property::UInt("max-size-packets", ..) => {
+                settings.max_size_packets = value.get().unwrap();
+            }
+            Property::UInt("max-size-time", ..) => {
+                settings.max_size_time = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Boxed("ssrc-map", ..) => Ok(settings.ssrc_map.to_value()),
+            Property::Boxed("payload-type-map", ..) => Ok(settings.payload_type_map.to_value()),
+            Property::UInt("max-size-packets", ..) => Ok(settings.max_size_packets.to_value()),
+            Property::UInt("max-size-time", ..) => Ok(settings.max_size_time.to_value()),
+            Property::UInt("num-rtx-requests", ..) => {
+                Ok(self.state.lock().unwrap().num_rtx_requests.to_value())
+            }
+            Property::UInt("num-rtx-packets", ..) => {
+                Ok(self.state.lock().unwrap().num_rtx_packets.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for RtxSend {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if transition == gst::StateChange::ReadyToPaused {
+            let mut state = self.state.lock().unwrap();
+            state.streams.clear();
+            state.pending.clear();
+            state.num_rtx_requests = 0;
+            state.num_rtx_packets = 0;
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct RtxSendStatic;
+
+impl ImplTypeStatic<Element> for RtxSendStatic {
+    fn get_name(&self) -> &str {
+        "RtpRtxSend2"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        RtxSend::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        RtxSend::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let rtxsend_static = RtxSendStatic;
+    register_type(rtxsend_static)
+}
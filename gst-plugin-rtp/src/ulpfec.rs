@@ -0,0 +1,281 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Generic forward error correction with a single protection level (RFC 5109)
+
+use rtp::{self, HEADER_LEN};
+
+const FEC_HEADER_LEN: usize = 10;
+const LEVEL_HEADER_LEN_SHORT: usize = 4;
+const LEVEL_HEADER_LEN_LONG: usize = 8;
+
+/// Maximum number of packets a single FEC packet can protect.
+pub const MAX_PROTECTED: usize = 48;
+
+fn read_u16(data: &[u8]) -> u16 {
+    (u16::from(data[0]) << 8) | u16::from(data[1])
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    (u32::from(data[0]) << 24) | (u32::from(data[1]) << 16) | (u32::from(data[2]) << 8)
+        | u32::from(data[3])
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FecPayload<'a> {
+    // P, X and CC recovery bits
+    pub flags_recovery: u8,
+    // M and PT recovery bits
+    pub marker_pt_recovery: u8,
+    pub seqnum_base: u16,
+    pub timestamp_recovery: u32,
+    pub length_recovery: u16,
+    // Most significant bit for the packet at `seqnum_base`
+    pub mask: u64,
+    pub long_mask: bool,
+    pub payload: &'a [u8],
+}
+
+impl<'a> FecPayload<'a> {
+    /// Parses the payload of a FEC packet, returns `None` if it is invalid.
+    pub fn parse(data: &'a [u8]) -> Option<FecPayload<'a>> {
+        if data.len() < FEC_HEADER_LEN + LEVEL_HEADER_LEN_SHORT {
+            return None;
+        }
+
+        // Extension bit must not be set
+        if data[0] & 0x80 != 0 {
+            return None;
+        }
+
+        let long_mask = data[0] & 0x40 != 0;
+        let level = &data[FEC_HEADER_LEN..];
+        let (mask, level_header_len) = if long_mask {
+            if level.len() < LEVEL_HEADER_LEN_LONG {
+                return None;
+            }
+            let mask = level[2..8]
+                .iter()
+                .fold(0u64, |mask, b| (mask << 8) | u64::from(*b));
+            (mask << 16, LEVEL_HEADER_LEN_LONG)
+        } else {
+            (u64::from(read_u16(&level[2..])) << 48, LEVEL_HEADER_LEN_SHORT)
+        };
+
+        let protection_length = read_u16(level) as usize;
+        let payload = &level[level_header_len..];
+        if payload.len() < protection_length {
+            return None;
+        }
+
+        Some(FecPayload {
+            flags_recovery: data[0] & 0x3f,
+            marker_pt_recovery: data[1],
+            seqnum_base: read_u16(&data[2..]),
+            timestamp_recovery: read_u32(&data[4..]),
+            length_recovery: read_u16(&data[8..]),
+            mask: mask,
+            long_mask: long_mask,
+            payload: &payload[..protection_length],
+        })
+    }
+
+    /// Generates the FEC payload protecting the given RTP packets, which must all be valid and
+    /// within `MAX_PROTECTED` sequence numbers after the first one.
+    pub fn generate(packets: &[&[u8]]) -> Vec<u8> {
+        assert!(!packets.is_empty());
+
+        let seqnum_base = read_u16(&packets[0][2..]);
+        let protection_length = packets
+            .iter()
+            .map(|p| p.len() - HEADER_LEN)
+            .max()
+            .unwrap();
+
+        let mut flags = 0u8;
+        let mut marker_pt = 0u8;
+        let mut timestamp = 0u32;
+        let mut length = 0u16;
+        let mut mask = 0u64;
+        let mut payload = vec![0u8; protection_length];
+
+        for packet in packets {
+            let offset = rtp::seqnum_diff(seqnum_base, read_u16(&packet[2..]));
+            assert!(offset >= 0 && (offset as usize) < MAX_PROTECTED);
+
+            flags ^= packet[0];
+            marker_pt ^= packet[1];
+            timestamp ^= read_u32(&packet[4..]);
+            length ^= (packet.len() - HEADER_LEN) as u16;
+            mask |= 1 << (63 - offset);
+            for (p, d) in payload.iter_mut().zip(&packet[HEADER_LEN..]) {
+                *p ^= *d;
+            }
+        }
+
+        FecPayload {
+            flags_recovery: flags & 0x3f,
+            marker_pt_recovery: marker_pt,
+            seqnum_base: seqnum_base,
+            timestamp_recovery: timestamp,
+            length_recovery: length,
+            mask: mask,
+            long_mask: mask & 0x0000_ffff_ffff_ffff != 0,
+            payload: &payload,
+        }.write()
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let level_header_len = if self.long_mask {
+            LEVEL_HEADER_LEN_LONG
+        } else {
+            LEVEL_HEADER_LEN_SHORT
+        };
+        let mut data = Vec::with_capacity(FEC_HEADER_LEN + level_header_len + self.payload.len());
+
+        let long_mask_bit = if self.long_mask { 0x40 } else { 0 };
+        data.push(long_mask_bit | (self.flags_recovery & 0x3f));
+        data.push(self.marker_pt_recovery);
+        data.extend_from_slice(&[(self.seqnum_base >> 8) as u8, self.seqnum_base as u8]);
+        data.extend_from_slice(&[
+            (self.timestamp_recovery >> 24) as u8,
+            (self.timestamp_recovery >> 16) as u8,
+            (self.timestamp_recovery >> 8) as u8,
+            self.timestamp_recovery as u8,
+        ]);
+        data.extend_from_slice(&[(self.length_recovery >> 8) as u8, self.length_recovery as u8]);
+
+        let protection_length = self.payload.len() as u16;
+        data.extend_from_slice(&[(protection_length >> 8) as u8, protection_length as u8]);
+        let mask_bytes = if self.long_mask { 6 } else { 2 };
+        for i in 0..mask_bytes {
+            data.push((self.mask >> (56 - 8 * i)) as u8);
+        }
+
+        data.extend_from_slice(self.payload);
+
+        data
+    }
+
+    /// Sequence numbers of all packets protected by this FEC packet.
+    pub fn protected_seqnums(&self) -> Vec<u16> {
+        let count = if self.long_mask { 48 } else { 16 };
+        (0..count)
+            .filter(|i| self.mask & (1 << (63 - i)) != 0)
+            .map(|i| self.seqnum_base.wrapping_add(i as u16))
+            .collect()
+    }
+
+    /// Recovers the packet with sequence number `seqnum` and the given SSRC from all other
+    /// protected packets. Returns `None` if the result is invalid.
+    pub fn recover(&self, seqnum: u16, ssrc: u32, packets: &[&[u8]]) -> Option<Vec<u8>> {
+        let mut flags = self.flags_recovery;
+        let mut marker_pt = self.marker_pt_recovery;
+        let mut timestamp = self.timestamp_recovery;
+        let mut length = self.length_recovery;
+        let mut payload = self.payload.to_vec();
+
+        for packet in packets {
+            if packet.len() < HEADER_LEN || packet.len() - HEADER_LEN > payload.len() {
+                return None;
+            }
+
+            flags ^= packet[0];
+            marker_pt ^= packet[1];
+            timestamp ^= read_u32(&packet[4..]);
+            length ^= (packet.len() - HEADER_LEN) as u16;
+            for (p, d) in payload.iter_mut().zip(&packet[HEADER_LEN..]) {
+                *p ^= *d;
+            }
+        }
+
+        let length = length as usize;
+        if length > payload.len() {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity(HEADER_LEN + length);
+        data.push((rtp::VERSION << 6) | (flags & 0x3f));
+        data.push(marker_pt);
+        data.extend_from_slice(&[(seqnum >> 8) as u8, seqnum as u8]);
+        data.extend_from_slice(&[
+            (timestamp >> 24) as u8,
+            (timestamp >> 16) as u8,
+            (timestamp >> 8) as u8,
+            timestamp as u8,
+        ]);
+        data.extend_from_slice(&[
+            (ssrc >> 24) as u8,
+            (ssrc >> 16) as u8,
+            (ssrc >> 8) as u8,
+            ssrc as u8,
+        ]);
+        data.extend_from_slice(&payload[..length]);
+
+        // Make sure that the result is at least a valid RTP packet
+        rtp::RtpPacket::parse(&data)?;
+
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rtp::RtpPacket;
+
+    #[test]
+    fn test_recover() {
+        let payloads: [&[u8]; 3] = [&[1, 2, 3, 4], &[5, 6], &[7, 8, 9, 10, 11, 12]];
+        let packets = payloads
+            .iter()
+            .enumerate()
+            .map(|(i, payload)| {
+                let seqnum = 65534u16.wrapping_add(i as u16);
+                let mut packet = RtpPacket::new(96, seqnum, 1000 * i as u32, 1234, payload);
+                packet.marker = i == 1;
+                packet.write()
+            })
+            .collect::<Vec<_>>();
+
+        let fec_data = FecPayload::generate(&packets.iter().map(|p| &p[..]).collect::<Vec<_>>());
+        let fec = FecPayload::parse(&fec_data).unwrap();
+        assert!(!fec.long_mask);
+        assert_eq!(fec.seqnum_base, 65534);
+        assert_eq!(fec.protected_seqnums(), vec![65534, 65535, 0]);
+
+        for missing in 0..3 {
+            let others = packets
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != missing)
+                .map(|(_, p)| &p[..])
+                .collect::<Vec<_>>();
+            let seqnum = 65534u16.wrapping_add(missing as u16);
+            assert_eq!(
+                fec.recover(seqnum, 1234, &others).as_ref(),
+                Some(&packets[missing])
+            );
+        }
+    }
+
+    #[test]
+    fn test_long_mask() {
+        let payload = [1u8, 2, 3];
+        let packets = [
+            RtpPacket::new(96, 100, 0, 1, &payload).write(),
+            RtpPacket::new(96, 120, 0, 1, &payload).write(),
+        ];
+
+        let fec_data = FecPayload::generate(&[&packets[0], &packets[1]]);
+        let fec = FecPayload::parse(&fec_data).unwrap();
+        assert!(fec.long_mask);
+        assert_eq!(fec.protected_seqnums(), vec![100, 120]);
+        assert_eq!(fec.recover(120, 1, &[&packets[0]]), Some(packets[1].clone()));
+    }
+}
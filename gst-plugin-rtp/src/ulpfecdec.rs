@@ -0,0 +1,410 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::u32;
+
+use rtp::RtpPacket;
+use ulpfec::{self, FecPayload};
+
+const DEFAULT_PT: u32 = 0;
+
+// Media packets are kept for as long as FEC packets could refer to them
+const MEDIA_HISTORY_SIZE: usize = 2 * ulpfec::MAX_PROTECTED;
+const FEC_HISTORY_SIZE: usize = 16;
+
+static PROPERTIES: [Property; 3] = [
+    Property::UInt(
+        "pt",
+        "Payload Type",
+        "Payload type of the FEC packets (0 = disabled)",
+        (0, 127),
+        DEFAULT_PT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "recovered",
+        "Recovered",
+        "Number of recovered packets",
+        (0, u32::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+    Property::UInt(
+        "unrecovered",
+        "Unrecovered",
+        "Number of lost packets that could not be recovered",
+        (0, u32::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+];
+
+#[derive(Default)]
+struct State {
+    ssrc: Option<u32>,
+    // Newest packet last
+    media: VecDeque<(u16, Vec<u8>)>,
+    fec: VecDeque<Vec<u8>>,
+    recovered: u32,
+    unrecovered: u32,
+}
+
+impl State {
+    fn get_media(&self, seqnum: u16) -> Option<&[u8]> {
+        self.media
+            .iter()
+            .rev()
+            .find(|&&(s, _)| s == seqnum)
+            .map(|&(_, ref data)| &data[..])
+    }
+
+    fn add_media(&mut self, seqnum: u16, data: Vec<u8>) {
+        self.media.push_back((seqnum, data));
+        while self.media.len() > MEDIA_HISTORY_SIZE {
+            self.media.pop_front();
+        }
+    }
+
+    fn add_fec(&mut self, data: Vec<u8>) {
+        self.fec.push_back(data);
+        while self.fec.len() > FEC_HISTORY_SIZE {
+            self.fec.pop_front();
+        }
+    }
+
+    // Tries to recover the packet from any FEC packet that protects it, for which all other
+    // protected packets were received
+    fn recover(&self, seqnum: u16) -> Option<Vec<u8>> {
+        let ssrc = self.ssrc?;
+
+        for fec_data in self.fec.iter().rev() {
+            let fec = match RtpPacket::parse(fec_data).and_then(|p| FecPayload::parse(p.payload)) {
+                None => continue,
+                Some(fec) => fec,
+            };
+
+            let protected = fec.protected_seqnums();
+            if !protected.contains(&seqnum) {
+                continue;
+            }
+
+            let others = protected
+                .iter()
+                .filter(|s| **s != seqnum)
+                .map(|s| self.get_media(*s))
+                .collect::<Option<Vec<_>>>();
+
+            if let Some(others) = others {
+                if let Some(data) = fec.recover(seqnum, ssrc, &others) {
+                    return Some(data);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+struct UlpFecDec {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    pt: Mutex<u32>,
+    state: Mutex<State>,
+}
+
+impl UlpFecDec {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rtpulpfecdec2",
+                gst::DebugColorFlags::empty(),
+                "RTP ULPFEC decoder",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            pt: Mutex::new(DEFAULT_PT),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "RTP ULPFEC decoder",
+            "Codec/Depayloader/Network/RTP",
+            "Recovers lost packets from forward error correction packets (RFC 5109)",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("application/x-rtp", &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            UlpFecDec::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |dec, element| dec.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            UlpFecDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |dec, element| dec.sink_event(pad, element, event),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            UlpFecDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |dec, element| dec.src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let dec = element.get_impl().downcast_ref::<UlpFecDec>().unwrap();
+        element.catch_panic(fallback, |element| f(dec, element))
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let pt = *self.pt.lock().unwrap();
+        if pt == 0 {
+            gst_log!(self.cat, obj: pad, "Passing through packet {:?}", buffer);
+            return self.srcpad.push(buffer);
+        }
+
+        {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+            let data = map.as_slice();
+
+            let packet = match RtpPacket::parse(data) {
+                None => {
+                    gst_warning!(self.cat, obj: pad, "Dropping invalid RTP packet");
+                    return gst::FlowReturn::Ok;
+                }
+                Some(packet) => packet,
+            };
+
+            let mut state = self.state.lock().unwrap();
+            if state.ssrc != Some(packet.ssrc) {
+                gst_debug!(self.cat, obj: pad, "New SSRC {:08x}", packet.ssrc);
+                *state = State {
+                    ssrc: Some(packet.ssrc),
+                    recovered: state.recovered,
+                    unrecovered: state.unrecovered,
+                    ..State::default()
+                };
+            }
+
+            // FEC packets are only stored, the upstream jitterbuffer already took their sequence
+            // numbers into account
+            if u32::from(packet.payload_type) == pt {
+                gst_trace!(self.cat, obj: pad, "Storing FEC packet {}", packet.seqnum);
+                state.add_fec(data.to_vec());
+                return gst::FlowReturn::Ok;
+            }
+
+            state.add_media(packet.seqnum, data.to_vec());
+        }
+
+        self.srcpad.push(buffer)
+    }
+
+    fn handle_packet_lost(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        seqnum: u16,
+        timestamp: u64,
+    ) -> bool {
+        let recovered = {
+            let mut state = self.state.lock().unwrap();
+            let recovered = state.recover(seqnum);
+            match recovered {
+                Some(ref data) => {
+                    state.recovered += 1;
+                    state.add_media(seqnum, data.clone());
+                }
+                None => state.unrecovered += 1,
+            }
+            recovered
+        };
+
+        match recovered {
+            None => {
+                self.notify(&element.clone().upcast(), "unrecovered");
+                false
+            }
+            Some(data) => {
+                gst_debug!(self.cat, obj: pad, "Recovered packet {}", seqnum);
+                self.notify(&element.clone().upcast(), "recovered");
+
+                let mut outbuf = gst::Buffer::from_mut_slice(data).unwrap();
+                outbuf.get_mut().unwrap().set_pts(timestamp.into());
+                let _ = self.srcpad.push(outbuf);
+                true
+            }
+        }
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::CustomDownstream(..) if *self.pt.lock().unwrap() != 0 => {
+                let lost = event.get_structure().and_then(|s| {
+                    if s.get_name() != "GstRTPPacketLost" {
+                        return None;
+                    }
+                    Some((s.get::<u32>("seqnum")?, s.get::<u64>("timestamp")?))
+                });
+
+                if let Some((seqnum, timestamp)) = lost {
+                    // Replace the event with the recovered packet if possible
+                    if self.handle_packet_lost(pad, element, seqnum as u16, timestamp) {
+                        return true;
+                    }
+                }
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.media.clear();
+                state.fec.clear();
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for UlpFecDec {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt("pt", ..) => {
+                *self.pt.lock().unwrap() = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt("pt", ..) => Ok(self.pt.lock().unwrap().to_value()),
+            Property::UInt("recovered", ..) => Ok(self.state.lock().unwrap().recovered.to_value()),
+            Property::UInt("unrecovered", ..) => {
+                Ok(self.state.lock().unwrap().unrecovered.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for UlpFecDec {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if transition == gst::StateChange::ReadyToPaused {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct UlpFecDecStatic;
+
+impl ImplTypeStatic<Element> for UlpFecDecStatic {
+    fn get_name(&self) -> &str {
+        "RtpUlpFecDec2"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        UlpFecDec::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        UlpFecDec::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let ulpfecdec_static = UlpFecDecStatic;
+    register_type(ulpfecdec_static)
+}
@@ -0,0 +1,450 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::cmp;
+use std::sync::Mutex;
+
+use rtp::RtpPacket;
+use ulpfec::{self, FecPayload};
+
+const DEFAULT_PT: u32 = 0;
+const DEFAULT_PERCENTAGE: u32 = 0;
+const DEFAULT_MAX_PERCENTAGE: u32 = 50;
+const DEFAULT_LOSS_PERCENTAGE: u32 = 0;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    pt: u32,
+    percentage: u32,
+    max_percentage: u32,
+    loss_percentage: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            pt: DEFAULT_PT,
+            percentage: DEFAULT_PERCENTAGE,
+            max_percentage: DEFAULT_MAX_PERCENTAGE,
+            loss_percentage: DEFAULT_LOSS_PERCENTAGE,
+        }
+    }
+}
+
+impl Settings {
+    // Protecting against a given loss needs about twice as many FEC packets, as each one can
+    // only recover a single packet of the ones it protects
+    fn get_overhead(&self) -> u32 {
+        cmp::min(
+            cmp::max(self.percentage, 2 * self.loss_percentage),
+            self.max_percentage,
+        )
+    }
+}
+
+static PROPERTIES: [Property; 4] = [
+    Property::UInt(
+        "pt",
+        "Payload Type",
+        "Payload type of the FEC packets (0 = disabled)",
+        (0, 127),
+        DEFAULT_PT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "percentage",
+        "Percentage",
+        "Minimum FEC overhead in percent of the media packets",
+        (0, 100),
+        DEFAULT_PERCENTAGE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "max-percentage",
+        "Maximum Percentage",
+        "Maximum FEC overhead in percent of the media packets",
+        (0, 100),
+        DEFAULT_MAX_PERCENTAGE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "loss-percentage",
+        "Loss Percentage",
+        "Measured packet loss in percent, e.g. from RTCP receiver reports, the overhead is twice this value within the configured bounds",
+        (0, 100),
+        DEFAULT_LOSS_PERCENTAGE,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+struct State {
+    // FEC packets are inserted into the sequence number space of the media packets
+    seqnum_shift: u16,
+    // In percent of a FEC packet
+    credit: u32,
+    // Media packets since the last FEC packet, with their output sequence numbers
+    pending: Vec<Vec<u8>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            seqnum_shift: 0,
+            credit: 0,
+            pending: Vec::new(),
+        }
+    }
+}
+
+struct UlpFecEnc {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl UlpFecEnc {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rtpulpfecenc2",
+                gst::DebugColorFlags::empty(),
+                "RTP ULPFEC encoder",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "RTP ULPFEC encoder",
+            "Codec/Payloader/Network/RTP",
+            "Generates forward error correction packets (RFC 5109)",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("application/x-rtp", &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            UlpFecEnc::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |enc, element| enc.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            UlpFecEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |enc, element| enc.sink_event(pad, element, event),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            UlpFecEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |enc, element| enc.src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let enc = element.get_impl().downcast_ref::<UlpFecEnc>().unwrap();
+        element.catch_panic(fallback, |element| f(enc, element))
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        if (settings.pt == 0 || settings.get_overhead() == 0) && state.seqnum_shift == 0 {
+            drop(state);
+            gst_log!(self.cat, obj: pad, "Passing through packet {:?}", buffer);
+            return self.srcpad.push(buffer);
+        }
+
+        let mut data = {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+            map.as_slice().to_vec()
+        };
+
+        let (seqnum, timestamp, ssrc) = match RtpPacket::parse(&data) {
+            None => {
+                gst_warning!(self.cat, obj: pad, "Dropping invalid RTP packet");
+                return gst::FlowReturn::Ok;
+            }
+            Some(packet) => (
+                packet.seqnum.wrapping_add(state.seqnum_shift),
+                packet.timestamp,
+                packet.ssrc,
+            ),
+        };
+        data[2] = (seqnum >> 8) as u8;
+        data[3] = seqnum as u8;
+
+        let mut fec_data = None;
+        if settings.pt != 0 && settings.get_overhead() != 0 {
+            // Only packets of the same stream can be protected together
+            let same_ssrc = state
+                .pending
+                .first()
+                .and_then(|p| RtpPacket::parse(p))
+                .map(|p| p.ssrc == ssrc)
+                .unwrap_or(true);
+            if !same_ssrc {
+                state.pending.clear();
+            }
+
+            state.pending.push(data.clone());
+            if state.pending.len() > ulpfec::MAX_PROTECTED {
+                state.pending.remove(0);
+            }
+
+            state.credit += settings.get_overhead();
+            if state.credit >= 100 {
+                state.credit -= 100;
+
+                let fec_payload = {
+                    let packets = state.pending.iter().map(|p| &p[..]).collect::<Vec<_>>();
+                    FecPayload::generate(&packets)
+                };
+                state.pending.clear();
+
+                gst_trace!(
+                    self.cat,
+                    obj: pad,
+                    "Generating FEC packet {} after {}",
+                    seqnum.wrapping_add(1),
+                    seqnum
+                );
+
+                fec_data = Some(
+                    RtpPacket::new(
+                        settings.pt as u8,
+                        seqnum.wrapping_add(1),
+                        timestamp,
+                        ssrc,
+                        &fec_payload,
+                    ).write(),
+                );
+                state.seqnum_shift = state.seqnum_shift.wrapping_add(1);
+            }
+        }
+        drop(state);
+
+        let mut outbuf = gst::Buffer::from_mut_slice(data).unwrap();
+        {
+            let outbuf = outbuf.get_mut().unwrap();
+            outbuf.set_pts(buffer.get_pts());
+            outbuf.set_dts(buffer.get_dts());
+            outbuf.set_duration(buffer.get_duration());
+            outbuf.set_flags(buffer.get_flags());
+        }
+
+        gst_log!(self.cat, obj: pad, "Pushing packet {:?}", outbuf);
+        let ret = self.srcpad.push(outbuf);
+        if ret != gst::FlowReturn::Ok {
+            return ret;
+        }
+
+        if let Some(fec_data) = fec_data {
+            let mut fec_buf = gst::Buffer::from_mut_slice(fec_data).unwrap();
+            {
+                let fec_buf = fec_buf.get_mut().unwrap();
+                fec_buf.set_pts(buffer.get_pts());
+                fec_buf.set_dts(buffer.get_dts());
+            }
+
+            gst_log!(self.cat, obj: pad, "Pushing FEC packet {:?}", fec_buf);
+            return self.srcpad.push(fec_buf);
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        if let EventView::FlushStop(..) = event.view() {
+            let mut state = self.state.lock().unwrap();
+            state.credit = 0;
+            state.pending.clear();
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        // Loss statistics from the receiver, in the same units as in RTCP receiver reports
+        if let EventView::CustomUpstream(..) = event.view() {
+            let fraction_lost = event.get_structure().and_then(|s| {
+                if s.get_name() != "GstRTPPacketLossStats" {
+                    return None;
+                }
+                s.get::<u32>("fraction-lost")
+            });
+
+            if let Some(fraction_lost) = fraction_lost {
+                let loss_percentage = cmp::min(fraction_lost, 255) * 100 / 256;
+                gst_debug!(
+                    self.cat,
+                    obj: pad,
+                    "Measured packet loss {}%",
+                    loss_percentage
+                );
+
+                self.settings.lock().unwrap().loss_percentage = loss_percentage;
+                self.notify(&element.clone().upcast(), "loss-percentage");
+            }
+        }
+
+        self.sinkpad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for UlpFecEnc {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("pt", ..) => {
+                settings.pt = value.get().unwrap();
+            }
+            Property::UInt("percentage", ..) => {
+                settings.percentage = value.get().unwrap();
+            }
+            Property::UInt("max-percentage", ..) => {
+                settings.max_percentage = value.get().unwrap();
+            }
+            Property::UInt("loss-percentage", ..) => {
+                settings.loss_percentage = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("pt", ..) => Ok(settings.pt.to_value()),
+            Property::UInt("percentage", ..) => Ok(settings.percentage.to_value()),
+            Property::UInt("max-percentage", ..) => Ok(settings.max_percentage.to_value()),
+            Property::UInt("loss-percentage", ..) => Ok(settings.loss_percentage.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for UlpFecEnc {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        if transition == gst::StateChange::PausedToReady {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        ret
+    }
+}
+
+struct UlpFecEncStatic;
+
+impl ImplTypeStatic<Element> for UlpFecEncStatic {
+    fn get_name(&self) -> &str {
+        "RtpUlpFecEnc2"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        UlpFecEnc::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        UlpFecEnc::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let ulpfecenc_static = UlpFecEncStatic;
+    register_type(ulpfecenc_static)
+}
@@ -0,0 +1,532 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+
+use rlua::{FromLua, Function, Lua, Table, ToLua};
+
+// Scripting interface:
+//
+// The Lua script is loaded when the element starts and has to define a global function
+//
+//   function process(data, meta)
+//
+// that is called for every buffer. data is a table with the bytes of the buffer (1-based, one
+// number per byte) that can be modified in place but must keep its length. meta is a table with
+// the pts, dts, duration and offset of the buffer (fields are missing if not set) and the
+// clock_time and running_time of the element's clock when the buffer is processed.
+//
+// If process returns a table, its pts, dts, duration and offset fields replace the ones of the
+// buffer. Negative values unset them.
+//
+// The "parameters" property is available to the script as the global params table, e.g.
+// "params, gain=(double)0.5" results in params.gain == 0.5.
+//
+// Lua states can't be shared between threads so the script runs on a separate thread that
+// the buffers are passed to.
+
+const DEFAULT_SCRIPT: Option<&'static str> = None;
+const DEFAULT_LOCATION: Option<&'static str> = None;
+const DEFAULT_PARAMETERS: Option<&'static str> = None;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    script: Option<String>,
+    location: Option<String>,
+    parameters: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            script: DEFAULT_SCRIPT.map(String::from),
+            location: DEFAULT_LOCATION.map(String::from),
+            parameters: DEFAULT_PARAMETERS.map(String::from),
+        }
+    }
+}
+
+static PROPERTIES: [Property; 3] = [
+    Property::String(
+        "script",
+        "Script",
+        "Lua source of the script, ignored if a location is set",
+        DEFAULT_SCRIPT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "location",
+        "Location",
+        "Location of the Lua script file",
+        DEFAULT_LOCATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "parameters",
+        "Parameters",
+        "Structure with parameters passed to the script as the params table",
+        DEFAULT_PARAMETERS,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+enum Param {
+    Int(i64),
+    Double(f64),
+    Bool(bool),
+    String(String),
+}
+
+fn parse_parameters(parameters: &str) -> Result<Vec<(String, Param)>, String> {
+    let s = match gst::Structure::from_string(parameters) {
+        None => return Err(format!("Invalid parameters '{}'", parameters)),
+        Some(s) => s,
+    };
+
+    let mut params = Vec::new();
+    for (name, value) in s.iter() {
+        let param = if let Some(v) = value.get::<i32>() {
+            Param::Int(i64::from(v))
+        } else if let Some(v) = value.get::<u32>() {
+            Param::Int(i64::from(v))
+        } else if let Some(v) = value.get::<i64>() {
+            Param::Int(v)
+        } else if let Some(v) = value.get::<f64>() {
+            Param::Double(v)
+        } else if let Some(v) = value.get::<bool>() {
+            Param::Bool(v)
+        } else if let Some(v) = value.get::<String>() {
+            Param::String(v)
+        } else {
+            return Err(format!("Unsupported type of parameter '{}'", name));
+        };
+        params.push((String::from(name), param));
+    }
+
+    Ok(params)
+}
+
+struct Job {
+    data: Vec<u8>,
+    meta: HashMap<String, i64>,
+}
+
+struct Output {
+    data: Vec<u8>,
+    meta: Option<HashMap<String, i64>>,
+}
+
+struct Worker {
+    jobs: mpsc::Sender<Job>,
+    outputs: mpsc::Receiver<Result<Output, String>>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Worker {
+    fn spawn(script: String, params: Vec<(String, Param)>) -> Result<Worker, String> {
+        let (jobs_sender, jobs) = mpsc::channel::<Job>();
+        let (outputs, outputs_receiver) = mpsc::channel();
+        let (init_sender, init_receiver) = mpsc::channel();
+
+        let thread = thread::Builder::new()
+            .name("scriptfilter".into())
+            .spawn(move || {
+                let lua = Lua::new();
+
+                let process = (|| -> ::rlua::Result<Function> {
+                    let globals = lua.globals();
+
+                    // The params table is created from an empty map and filled afterwards
+                    globals.set("params", HashMap::<String, i64>::new())?;
+                    let table = globals.get::<_, Table>("params")?;
+                    for (name, param) in params {
+                        match param {
+                            Param::Int(v) => table.set(name, v)?,
+                            Param::Double(v) => table.set(name, v)?,
+                            Param::Bool(v) => table.set(name, v)?,
+                            Param::String(v) => table.set(name, v)?,
+                        }
+                    }
+
+                    lua.exec::<()>(&script, Some("script"))?;
+                    globals.get::<_, Function>("process")
+                })();
+
+                let process = match process {
+                    Err(err) => {
+                        let _ = init_sender.send(Err(err.to_string()));
+                        return;
+                    }
+                    Ok(process) => {
+                        let _ = init_sender.send(Ok(()));
+                        process
+                    }
+                };
+
+                for job in jobs {
+                    let output = (|| -> ::rlua::Result<Output> {
+                        let data = job.data.to_lua(&lua)?;
+                        let meta = process
+                            .call::<_, Option<HashMap<String, i64>>>((data.clone(), job.meta))?;
+                        let data = Vec::<u8>::from_lua(data, &lua)?;
+
+                        Ok(Output {
+                            data: data,
+                            meta: meta,
+                        })
+                    })();
+
+                    if outputs.send(output.map_err(|err| err.to_string())).is_err() {
+                        break;
+                    }
+                }
+            })
+            .map_err(|err| format!("Failed to spawn thread: {}", err))?;
+
+        match init_receiver.recv() {
+            Ok(Ok(())) => (),
+            Ok(Err(err)) => {
+                let _ = thread.join();
+                return Err(err);
+            }
+            Err(_) => {
+                let _ = thread.join();
+                return Err("Script thread panicked".into());
+            }
+        }
+
+        Ok(Worker {
+            jobs: jobs_sender,
+            outputs: outputs_receiver,
+            thread: thread,
+        })
+    }
+
+    fn process(&self, job: Job) -> Result<Output, String> {
+        self.jobs
+            .send(job)
+            .map_err(|_| String::from("Script thread stopped"))?;
+        self.outputs
+            .recv()
+            .map_err(|_| String::from("Script thread stopped"))?
+    }
+
+    fn stop(self) {
+        drop(self.jobs);
+        let _ = self.thread.join();
+    }
+}
+
+fn to_clock_time(meta: &HashMap<String, i64>, name: &str) -> Option<gst::ClockTime> {
+    meta.get(name).map(|&v| {
+        if v < 0 {
+            gst::CLOCK_TIME_NONE
+        } else {
+            gst::ClockTime(Some(v as u64))
+        }
+    })
+}
+
+struct ScriptFilter {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    worker: Mutex<Option<Worker>>,
+}
+
+impl ScriptFilter {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "scriptfilter",
+                gst::DebugColorFlags::empty(),
+                "Lua script filter",
+            ),
+            settings: Mutex::new(Default::default()),
+            worker: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Script Filter",
+            "Filter",
+            "Runs a Lua script on every buffer",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    fn load_script(settings: &Settings) -> Result<String, gst::ErrorMessage> {
+        if let Some(ref location) = settings.location {
+            let mut script = String::new();
+            File::open(location)
+                .and_then(|mut file| file.read_to_string(&mut script))
+                .map_err(|err| {
+                    gst_error_msg!(
+                        gst::ResourceError::OpenRead,
+                        ["Failed to read script '{}': {}", location, err]
+                    )
+                })?;
+            return Ok(script);
+        }
+
+        settings.script.clone().ok_or_else(|| {
+            gst_error_msg!(gst::ResourceError::Settings, ["No script set"])
+        })
+    }
+}
+
+impl ObjectImpl<BaseTransform> for ScriptFilter {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("script", ..) => {
+                settings.script = value.get();
+            }
+            Property::String("location", ..) => {
+                settings.location = value.get();
+            }
+            Property::String("parameters", ..) => {
+                settings.parameters = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("script", ..) => Ok(settings.script.to_value()),
+            Property::String("location", ..) => Ok(settings.location.to_value()),
+            Property::String("parameters", ..) => Ok(settings.parameters.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for ScriptFilter {}
+
+impl BaseTransformImpl<BaseTransform> for ScriptFilter {
+    fn start(&self, element: &BaseTransform) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let script = match Self::load_script(&settings) {
+            Err(err) => {
+                element.post_error_message(&err);
+                return false;
+            }
+            Ok(script) => script,
+        };
+
+        let params = match settings.parameters {
+            None => Vec::new(),
+            Some(ref parameters) => match parse_parameters(parameters) {
+                Err(err) => {
+                    gst_element_error!(element, gst::ResourceError::Settings, ["{}", err]);
+                    return false;
+                }
+                Ok(params) => params,
+            },
+        };
+
+        let worker = match Worker::spawn(script, params) {
+            Err(err) => {
+                gst_element_error!(
+                    element,
+                    gst::LibraryError::Init,
+                    ["Failed to load script: {}", err]
+                );
+                return false;
+            }
+            Ok(worker) => worker,
+        };
+
+        gst_debug!(self.cat, obj: element, "Loaded script");
+        *self.worker.lock().unwrap() = Some(worker);
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseTransform) -> bool {
+        if let Some(worker) = self.worker.lock().unwrap().take() {
+            worker.stop();
+        }
+
+        true
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let mut meta = HashMap::new();
+        {
+            let mut insert = |name: &str, value: Option<u64>| {
+                if let Some(value) = value {
+                    meta.insert(String::from(name), value as i64);
+                }
+            };
+
+            insert("pts", buf.get_pts().0);
+            insert("dts", buf.get_dts().0);
+            insert("duration", buf.get_duration().0);
+            let offset = buf.get_offset();
+            if offset != u64::max_value() {
+                insert("offset", Some(offset));
+            }
+
+            let clock_time = element.get_clock().and_then(|clock| clock.get_time().0);
+            let base_time = element.get_base_time().0;
+            insert("clock_time", clock_time);
+            if let (Some(clock_time), Some(base_time)) = (clock_time, base_time) {
+                if clock_time >= base_time {
+                    insert("running_time", Some(clock_time - base_time));
+                }
+            }
+        }
+
+        let data = match buf.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map.as_slice().to_vec(),
+        };
+        let size = data.len();
+
+        let output = {
+            let worker = self.worker.lock().unwrap();
+            let worker = match *worker {
+                None => return gst::FlowReturn::Flushing,
+                Some(ref worker) => worker,
+            };
+
+            worker.process(Job {
+                data: data,
+                meta: meta,
+            })
+        };
+
+        let output = match output {
+            Err(err) => {
+                gst_element_error!(element, gst::LibraryError::Failed, ["Script failed: {}", err]);
+                return gst::FlowReturn::Error;
+            }
+            Ok(output) => output,
+        };
+
+        if output.data.len() != size {
+            gst_element_error!(
+                element,
+                gst::LibraryError::Failed,
+                [
+                    "Script changed the buffer size from {} to {}",
+                    size,
+                    output.data.len()
+                ]
+            );
+            return gst::FlowReturn::Error;
+        }
+
+        {
+            let mut map = match buf.map_writable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+            map.as_mut_slice().copy_from_slice(&output.data);
+        }
+
+        if let Some(ref meta) = output.meta {
+            if let Some(pts) = to_clock_time(meta, "pts") {
+                buf.set_pts(pts);
+            }
+            if let Some(dts) = to_clock_time(meta, "dts") {
+                buf.set_dts(dts);
+            }
+            if let Some(duration) = to_clock_time(meta, "duration") {
+                buf.set_duration(duration);
+            }
+            if let Some(&offset) = meta.get("offset") {
+                buf.set_offset(if offset < 0 {
+                    u64::max_value()
+                } else {
+                    offset as u64
+                });
+            }
+        }
+
+        gst_trace!(self.cat, obj: element, "Processed buffer {:?}", buf);
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct ScriptFilterStatic;
+
+impl ImplTypeStatic<BaseTransform> for ScriptFilterStatic {
+    fn get_name(&self) -> &str {
+        "ScriptFilter"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        ScriptFilter::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        ScriptFilter::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let scriptfilter_static = ScriptFilterStatic;
+    register_type(scriptfilter_static)
+}
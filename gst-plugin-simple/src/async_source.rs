@@ -0,0 +1,481 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::sync::Mutex;
+
+use url::Url;
+
+use futures::{Future, Sink, Stream};
+use futures::stream;
+use futures::sync::{mpsc, oneshot};
+use tokio::runtime::Runtime;
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::object::*;
+use gst_plugin::properties::*;
+use gst_plugin::element::*;
+use gst_plugin::registration::*;
+use gst_plugin::base_src::*;
+use gst_plugin::uri_handler::*;
+use gst_plugin::idle::*;
+use error::*;
+
+pub use gst_plugin::base_src::BaseSrc;
+
+use UriValidator;
+
+// Number of buffers that are produced ahead of the streaming thread
+const QUEUE_SIZE: usize = 16;
+
+lazy_static! {
+    // Shared by all async sources, so that their IO is driven by a single reactor instead of
+    // one blocking thread per element
+    static ref RUNTIME: Mutex<Runtime> = Mutex::new(Runtime::new().unwrap());
+}
+
+pub type BufferStream = Box<Stream<Item = gst::Buffer, Error = FlowError> + Send>;
+
+pub trait AsyncSourceImpl: Send + 'static {
+    fn uri_validator(&self) -> Box<UriValidator>;
+
+    /// Returns the stream of buffers for `uri`.
+    ///
+    /// The stream is polled on a tokio runtime shared by all async sources and must not block.
+    /// It is dropped when stopping.
+    fn start(&mut self, src: &BaseSrc, uri: Url) -> Result<BufferStream, gst::ErrorMessage>;
+    fn stop(&mut self, src: &BaseSrc) -> Result<(), gst::ErrorMessage>;
+
+    /// Called for the properties from `AsyncSourceInfo::properties`.
+    fn set_property(&mut self, _src: &BaseSrc, _property: &Property, _value: &glib::Value) {
+        unimplemented!()
+    }
+
+    fn get_property(&self, _src: &BaseSrc, _property: &Property) -> Result<glib::Value, ()> {
+        unimplemented!()
+    }
+}
+
+enum Item {
+    Buffer(Result<gst::Buffer, FlowError>),
+    Unlock,
+}
+
+type ItemStream = stream::Wait<Box<Stream<Item = Item, Error = ()> + Send>>;
+
+struct Unlock {
+    flushing: bool,
+    sender: Option<mpsc::UnboundedSender<()>>,
+}
+
+struct AsyncSource {
+    cat: gst::DebugCategory,
+    uri: Mutex<(Option<Url>, bool)>,
+    uri_validator: Box<UriValidator>,
+    imp: Mutex<Box<AsyncSourceImpl>>,
+    items: Mutex<Option<ItemStream>>,
+    unlock: Mutex<Unlock>,
+    cancel: Mutex<Option<oneshot::Sender<()>>>,
+    properties: &'static [Property<'static>],
+    idle: Idle,
+}
+
+static PROPERTIES: [Property; 2] = [
+    Property::String(
+        "uri",
+        "URI",
+        "URI to read from",
+        None,
+        PropertyMutability::ReadWrite,
+    ),
+    IDLE_WHEN_UNLINKED_PROPERTY,
+];
+
+impl AsyncSource {
+    fn new(source: &BaseSrc, source_info: &AsyncSourceInfo) -> Self {
+        let source_impl = (source_info.create_instance)(source);
+
+        Self {
+            cat: gst::DebugCategory::new(
+                "rsasyncsource",
+                gst::DebugColorFlags::empty(),
+                "Rust async source base class",
+            ),
+            uri: Mutex::new((None, false)),
+            uri_validator: source_impl.uri_validator(),
+            imp: Mutex::new(source_impl),
+            items: Mutex::new(None),
+            unlock: Mutex::new(Unlock {
+                flushing: false,
+                sender: None,
+            }),
+            cancel: Mutex::new(None),
+            properties: source_info.properties,
+            idle: Idle::new(),
+        }
+    }
+
+    fn class_init(klass: &mut BaseSrcClass, source_info: &AsyncSourceInfo) {
+        klass.set_metadata(
+            &source_info.long_name,
+            &source_info.classification,
+            &source_info.description,
+            &source_info.author,
+        );
+
+        let caps = gst::Caps::new_any();
+        let pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(pad_template);
+
+        let mut properties = PROPERTIES.to_vec();
+        properties.extend_from_slice(source_info.properties);
+        klass.install_properties(&properties);
+    }
+
+    fn init(element: &BaseSrc, source_info: &AsyncSourceInfo) -> Box<BaseSrcImpl<BaseSrc>> {
+        if source_info.live {
+            element.set_live_source(true);
+        }
+
+        let imp = Self::new(element, source_info);
+        Box::new(imp)
+    }
+
+    fn get_uri(&self, _element: &glib::Object) -> Option<String> {
+        let uri_storage = &self.uri.lock().unwrap();
+        uri_storage.0.as_ref().map(|uri| String::from(uri.as_str()))
+    }
+
+    fn set_uri(&self, element: &glib::Object, uri_str: Option<String>) -> Result<(), glib::Error> {
+        let src = element.clone().dynamic_cast::<BaseSrc>().unwrap();
+
+        let uri_storage = &mut self.uri.lock().unwrap();
+
+        gst_debug!(self.cat, obj: &src, "Setting URI {:?}", uri_str);
+
+        if uri_storage.1 {
+            return Err(
+                UriError::new(gst::URIError::BadState, "Already started".to_string()).into(),
+            );
+        }
+
+        uri_storage.0 = None;
+
+        if let Some(uri_str) = uri_str {
+            match Url::parse(uri_str.as_str()) {
+                Ok(uri) => {
+                    try!((self.uri_validator)(&uri).map_err(|e| e.into()));
+                    uri_storage.0 = Some(uri);
+                    Ok(())
+                }
+                Err(err) => Err(UriError::new(
+                    gst::URIError::BadUri,
+                    format!("Failed to parse URI '{}': {}", uri_str, err),
+                ).into()),
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    // Polls the stream on the shared runtime and returns the blocking end for the streaming
+    // thread, which also wakes up on unlock
+    fn spawn_stream(&self, stream: BufferStream) -> ItemStream {
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let (unlock_sender, unlock_receiver) = mpsc::unbounded();
+        let (cancel_sender, cancel_receiver) = oneshot::channel();
+
+        let forward = stream
+            .then(|res| Ok::<_, ()>(res))
+            .forward(sender.sink_map_err(|_| ()))
+            .map(|_| ())
+            .select(cancel_receiver.then(|_| Ok::<_, ()>(())))
+            .map(|_| ())
+            .map_err(|_| ());
+        RUNTIME.lock().unwrap().executor().spawn(forward);
+
+        *self.cancel.lock().unwrap() = Some(cancel_sender);
+        self.unlock.lock().unwrap().sender = Some(unlock_sender);
+
+        let items: Box<Stream<Item = Item, Error = ()> + Send> = Box::new(
+            receiver
+                .map(Item::Buffer)
+                .select(unlock_receiver.map(|_| Item::Unlock)),
+        );
+
+        items.wait()
+    }
+}
+
+impl ObjectImpl<BaseSrc> for AsyncSource {
+    fn set_property(&self, obj: &glib::Object, id: u32, value: &glib::Value) {
+        if id as usize >= PROPERTIES.len() {
+            let src = obj.clone().dynamic_cast::<BaseSrc>().unwrap();
+            let prop = &self.properties[id as usize - PROPERTIES.len()];
+            self.imp.lock().unwrap().set_property(&src, prop, value);
+            return;
+        }
+
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("uri", ..) => {
+                self.set_uri(obj, value.get()).unwrap();
+            }
+            Property::Boolean("idle-when-unlinked", ..) => {
+                self.idle.set_enabled(value.get().unwrap());
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        if id as usize >= PROPERTIES.len() {
+            let src = obj.clone().dynamic_cast::<BaseSrc>().unwrap();
+            let prop = &self.properties[id as usize - PROPERTIES.len()];
+            return self.imp.lock().unwrap().get_property(&src, prop);
+        }
+
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("uri", ..) => Ok(self.get_uri(obj).to_value()),
+            Property::Boolean("idle-when-unlinked", ..) => Ok(self.idle.is_enabled().to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseSrc> for AsyncSource {}
+
+impl BaseSrcImpl<BaseSrc> for AsyncSource {
+    fn start(&self, src: &BaseSrc) -> bool {
+        gst_debug!(self.cat, obj: src, "Starting");
+
+        // Don't keep the URI locked while we call start later
+        let uri = match *self.uri.lock().unwrap() {
+            (Some(ref uri), ref mut started) => {
+                *started = true;
+                uri.clone()
+            }
+            (None, _) => {
+                gst_error!(self.cat, obj: src, "No URI given");
+                gst_element_error!(src, gst::ResourceError::OpenRead, ["No URI given"]);
+                return false;
+            }
+        };
+
+        let stream = {
+            let source_impl = &mut self.imp.lock().unwrap();
+            match source_impl.start(src, uri) {
+                Ok(stream) => stream,
+                Err(ref msg) => {
+                    gst_error!(self.cat, obj: src, "Failed to start: {:?}", msg);
+
+                    self.uri.lock().unwrap().1 = false;
+                    src.post_error_message(msg);
+                    return false;
+                }
+            }
+        };
+
+        let items = self.spawn_stream(stream);
+        *self.items.lock().unwrap() = Some(items);
+
+        gst_trace!(self.cat, obj: src, "Started successfully");
+        true
+    }
+
+    fn stop(&self, src: &BaseSrc) -> bool {
+        gst_debug!(self.cat, obj: src, "Stopping");
+
+        // Dropping the sender cancels polling of the stream
+        let _ = self.cancel.lock().unwrap().take();
+        self.unlock.lock().unwrap().sender = None;
+        *self.items.lock().unwrap() = None;
+
+        let source_impl = &mut self.imp.lock().unwrap();
+        match source_impl.stop(src) {
+            Ok(..) => {
+                gst_trace!(self.cat, obj: src, "Stopped successfully");
+                self.uri.lock().unwrap().1 = false;
+                true
+            }
+            Err(ref msg) => {
+                gst_error!(self.cat, obj: src, "Failed to stop: {:?}", msg);
+
+                src.post_error_message(msg);
+                false
+            }
+        }
+    }
+
+    fn query(&self, src: &BaseSrc, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        match query.view_mut() {
+            QueryView::Scheduling(ref mut q) => {
+                q.set(gst::SchedulingFlags::SEQUENTIAL, 1, -1, 0);
+                q.add_scheduling_modes(&[gst::PadMode::Push]);
+                return true;
+            }
+            _ => (),
+        }
+
+        BaseSrcBase::parent_query(src, query)
+    }
+
+    fn create(
+        &self,
+        src: &BaseSrc,
+        _offset: u64,
+        _length: u32,
+    ) -> Result<gst::Buffer, gst::FlowReturn> {
+        // The stream is not polled further once the queue is full, so the IO idles too
+        self.idle.wait_linked(self.cat, src)?;
+
+        let mut items = self.items.lock().unwrap();
+        let items = match *items {
+            None => return Err(gst::FlowReturn::Flushing),
+            Some(ref mut items) => items,
+        };
+
+        loop {
+            if self.unlock.lock().unwrap().flushing {
+                gst_debug!(self.cat, obj: src, "Flushing");
+                return Err(gst::FlowReturn::Flushing);
+            }
+
+            match items.next() {
+                // The stream was cancelled or ended
+                None | Some(Err(_)) => return Err(gst::FlowReturn::Eos),
+                Some(Ok(Item::Unlock)) => continue,
+                Some(Ok(Item::Buffer(Ok(buffer)))) => {
+                    gst_trace!(self.cat, obj: src, "Produced buffer {:?}", buffer);
+                    return Ok(buffer);
+                }
+                Some(Ok(Item::Buffer(Err(flow_error)))) => {
+                    gst_error!(self.cat, obj: src, "Failed to produce buffer: {:?}", flow_error);
+                    match flow_error {
+                        FlowError::NotNegotiated(ref msg) | FlowError::Error(ref msg) => {
+                            src.post_error_message(msg);
+                        }
+                        _ => (),
+                    }
+                    return Err(flow_error.into());
+                }
+            }
+        }
+    }
+
+    fn is_seekable(&self, _src: &BaseSrc) -> bool {
+        false
+    }
+
+    fn unlock(&self, src: &BaseSrc) -> bool {
+        gst_debug!(self.cat, obj: src, "Unlocking");
+
+        self.idle.unlock();
+
+        let mut unlock = self.unlock.lock().unwrap();
+        unlock.flushing = true;
+        if let Some(ref sender) = unlock.sender {
+            let _ = sender.unbounded_send(());
+        }
+
+        true
+    }
+
+    fn unlock_stop(&self, src: &BaseSrc) -> bool {
+        gst_debug!(self.cat, obj: src, "Stop unlocking");
+
+        self.idle.unlock_stop();
+        self.unlock.lock().unwrap().flushing = false;
+        true
+    }
+}
+
+impl URIHandlerImpl for AsyncSource {
+    fn get_uri(&self, element: &gst::URIHandler) -> Option<String> {
+        AsyncSource::get_uri(self, &element.clone().upcast())
+    }
+
+    fn set_uri(&self, element: &gst::URIHandler, uri: Option<String>) -> Result<(), glib::Error> {
+        AsyncSource::set_uri(self, &element.clone().upcast(), uri)
+    }
+}
+
+pub struct AsyncSourceInfo {
+    pub name: String,
+    pub long_name: String,
+    pub description: String,
+    pub classification: String,
+    pub author: String,
+    pub rank: u32,
+    pub create_instance: fn(&BaseSrc) -> Box<AsyncSourceImpl>,
+    pub protocols: Vec<String>,
+    pub live: bool,
+    pub properties: &'static [Property<'static>],
+}
+
+struct AsyncSourceStatic {
+    name: String,
+    source_info: AsyncSourceInfo,
+}
+
+impl ImplTypeStatic<BaseSrc> for AsyncSourceStatic {
+    fn get_name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn new(&self, element: &BaseSrc) -> Box<BaseSrcImpl<BaseSrc>> {
+        AsyncSource::init(element, &self.source_info)
+    }
+
+    fn class_init(&self, klass: &mut BaseSrcClass) {
+        AsyncSource::class_init(klass, &self.source_info);
+    }
+
+    fn type_init(&self, token: &TypeInitToken, type_: glib::Type) {
+        register_uri_handler(token, type_, self);
+    }
+}
+
+impl URIHandlerImplStatic<BaseSrc> for AsyncSourceStatic {
+    fn get_impl<'a>(&self, imp: &'a Box<BaseSrcImpl<BaseSrc>>) -> &'a URIHandlerImpl {
+        imp.downcast_ref::<AsyncSource>().unwrap()
+    }
+
+    fn get_type(&self) -> gst::URIType {
+        gst::URIType::Src
+    }
+
+    fn get_protocols(&self) -> Vec<String> {
+        self.source_info.protocols.clone()
+    }
+}
+
+pub fn async_source_register(plugin: &gst::Plugin, source_info: AsyncSourceInfo) -> bool {
+    let name = source_info.name.clone();
+    let rank = source_info.rank;
+
+    let source_static = AsyncSourceStatic {
+        name: format!("AsyncSource-{}", name),
+        source_info: source_info,
+    };
+
+    let type_ = register_type(source_static);
+    element_register(plugin, &name, rank, type_)
+}
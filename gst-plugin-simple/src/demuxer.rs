@@ -6,17 +6,22 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use std::u32;
 use std::u64;
 use std::collections::BTreeMap;
 
+use gst_plugin::properties::*;
 use gst_plugin::object::*;
 use gst_plugin::element::*;
+use gst_plugin::registration::*;
+use gst_plugin::sandbox::{Sandbox, SandboxSettings};
 
 use error::*;
+use index::SeekIndex;
 
+use glib;
 use gst;
 use gst::prelude::*;
 use gst_base;
@@ -34,7 +39,8 @@ pub enum SeekResult {
 pub enum HandleBufferResult {
     NeedMoreData,
     Again,
-    // NeedDataFromOffset(u64),
+    // Upstream is asked to continue from this byte offset
+    NeedDataFromOffset(u64),
     StreamAdded(Stream),
     HaveAllStreams,
     StreamChanged(Stream),
@@ -71,6 +77,12 @@ pub trait DemuxerImpl: Send + 'static {
     fn is_seekable(&self, demuxer: &Element) -> bool;
     fn get_position(&self, demuxer: &Element) -> gst::ClockTime;
     fn get_duration(&self, demuxer: &Element) -> gst::ClockTime;
+
+    // Index built while parsing, loaded from the index file or upstream on start and saved to
+    // the index file on stop
+    fn get_seek_index(&mut self) -> Option<&mut SeekIndex> {
+        None
+    }
 }
 
 #[derive(Debug)]
@@ -108,9 +120,65 @@ pub struct Demuxer {
     flow_combiner: Mutex<UniqueFlowCombiner>,
     group_id: Mutex<gst::GroupId>,
     srcpads: Mutex<BTreeMap<u32, gst::Pad>>,
-    imp: Mutex<Box<DemuxerImpl>>,
+    // Segment to push on all source pads before the next buffer after a seek
+    pending_segment: Mutex<Option<gst::FormattedSegment<gst::ClockTime>>>,
+    settings: Mutex<Settings>,
+    sandbox: Mutex<Option<Sandbox>>,
+    // Shared with the sandbox thread while it is handling a buffer
+    imp: Arc<Mutex<Box<DemuxerImpl>>>,
+}
+
+const DEFAULT_SANDBOX: bool = false;
+const DEFAULT_SANDBOX_MEMORY_BUDGET: u64 = 0;
+const DEFAULT_INDEX_LOCATION: Option<&str> = None;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    sandbox: bool,
+    sandbox_memory_budget: u64,
+    index_location: Option<String>,
 }
 
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            sandbox: DEFAULT_SANDBOX,
+            sandbox_memory_budget: DEFAULT_SANDBOX_MEMORY_BUDGET,
+            index_location: DEFAULT_INDEX_LOCATION.map(String::from),
+        }
+    }
+}
+
+static PROPERTIES: [Property; 3] = [
+    Property::Boolean(
+        "sandbox",
+        "Sandbox",
+        "Parse the input in a thread without filesystem and network access",
+        DEFAULT_SANDBOX,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "sandbox-memory-budget",
+        "Sandbox Memory Budget",
+        "Soft budget of bytes the parser can reserve per buffer in the sandbox (0 = unlimited)",
+        (0, u64::MAX),
+        DEFAULT_SANDBOX_MEMORY_BUDGET,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "index-location",
+        "Index Location",
+        "Sidecar file to load the seek index from on start and to store it to on stop",
+        DEFAULT_INDEX_LOCATION,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+// Elements and the demuxer implementation are only used from the sandbox thread while the
+// streaming thread is blocked waiting for it
+struct AssertSend<T>(T);
+unsafe impl<T> Send for AssertSend<T> {}
+
 #[derive(Default)]
 pub struct UniqueFlowCombiner(gst_base::FlowCombiner);
 
@@ -123,6 +191,10 @@ impl UniqueFlowCombiner {
         self.0.clear();
     }
 
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
     fn update_flow(&mut self, flow_ret: gst::FlowReturn) -> gst::FlowReturn {
         self.0.update_flow(flow_ret)
     }
@@ -143,7 +215,10 @@ impl Demuxer {
             flow_combiner: Mutex::new(Default::default()),
             group_id: Mutex::new(gst::util_group_id_next()),
             srcpads: Mutex::new(BTreeMap::new()),
-            imp: Mutex::new((demuxer_info.create_instance)(element)),
+            pending_segment: Mutex::new(None),
+            settings: Mutex::new(Default::default()),
+            sandbox: Mutex::new(None),
+            imp: Arc::new(Mutex::new((demuxer_info.create_instance)(element))),
         }
     }
 
@@ -170,6 +245,8 @@ impl Demuxer {
             &demuxer_info.output_caps,
         );
         klass.add_pad_template(pad_template);
+
+        klass.install_properties(&PROPERTIES);
     }
 
     fn init(element: &Element, demuxer_info: &DemuxerInfo) -> Box<ElementImpl<Element>> {
@@ -259,6 +336,76 @@ impl Demuxer {
         }
     }
 
+    fn push_pending_segment(&self, _element: &Element) {
+        let segment = match self.pending_segment.lock().unwrap().take() {
+            None => return,
+            Some(segment) => segment,
+        };
+
+        let srcpads = self.srcpads.lock().unwrap();
+        for (_, pad) in srcpads.iter().by_ref() {
+            pad.push_event(gst::Event::new_segment(&segment).build());
+        }
+    }
+
+    // Non-flushing seek in bytes, which can also be sent from the streaming thread
+    fn seek_upstream(&self, element: &Element, offset: u64) -> bool {
+        gst_debug!(self.cat, obj: element, "Seeking upstream to offset {}", offset);
+
+        let seek = gst::Event::new_seek(
+            1.0,
+            gst::SeekFlags::ACCURATE,
+            gst::SeekType::Set,
+            gst::format::Bytes(Some(offset)),
+            gst::SeekType::None,
+            gst::format::Bytes(None),
+        ).build();
+
+        self.sinkpad.push_event(seek)
+    }
+
+    // An index provided by upstream is preferred over the index file, as it belongs to the
+    // actual data
+    fn load_index(&self, element: &Element, location: Option<&String>, index: &mut SeekIndex) {
+        if let Some(upstream_index) = SeekIndex::query_peer(&self.sinkpad) {
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "Using index with {} entries from upstream",
+                upstream_index.len()
+            );
+            index.merge(&upstream_index);
+            return;
+        }
+
+        let location = match location {
+            None => return,
+            Some(location) => location,
+        };
+
+        match SeekIndex::load(location) {
+            Ok(file_index) => {
+                gst_debug!(
+                    self.cat,
+                    obj: element,
+                    "Loaded index with {} entries from {}",
+                    file_index.len(),
+                    location
+                );
+                index.merge(&file_index);
+            }
+            Err(err) => {
+                gst_debug!(
+                    self.cat,
+                    obj: element,
+                    "Failed to load index from {}: {}",
+                    location,
+                    err
+                );
+            }
+        }
+    }
+
     fn remove_all_streams(&self, element: &Element) {
         self.flow_combiner.lock().unwrap().clear();
         let mut srcpads = self.srcpads.lock().unwrap();
@@ -292,6 +439,34 @@ impl Demuxer {
     }
 
     fn start(&self, element: &Element, upstream_size: Option<u64>, random_access: bool) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+        if settings.sandbox {
+            let sandbox_settings = SandboxSettings {
+                memory_budget: if settings.sandbox_memory_budget == 0 {
+                    None
+                } else {
+                    Some(settings.sandbox_memory_budget as usize)
+                },
+                ..Default::default()
+            };
+
+            match Sandbox::new(sandbox_settings) {
+                Ok(sandbox) => {
+                    gst_debug!(self.cat, obj: element, "Parsing in sandbox");
+                    *self.sandbox.lock().unwrap() = Some(sandbox);
+                }
+                Err(err) => {
+                    gst_error!(self.cat, obj: element, "Failed to create sandbox: {}", err);
+                    gst_element_error!(
+                        element,
+                        gst::CoreError::Failed,
+                        ["Failed to create sandbox: {}", err]
+                    );
+                    return false;
+                }
+            }
+        }
+
         let demuxer_impl = &mut self.imp.lock().unwrap();
 
         gst_debug!(
@@ -305,6 +480,9 @@ impl Demuxer {
         match demuxer_impl.start(element, upstream_size, random_access) {
             Ok(..) => {
                 gst_trace!(self.cat, obj: element, "Successfully started",);
+                if let Some(index) = demuxer_impl.get_seek_index() {
+                    self.load_index(element, settings.index_location.as_ref(), index);
+                }
                 true
             }
             Err(ref msg) => {
@@ -316,9 +494,29 @@ impl Demuxer {
     }
 
     fn stop(&self, element: &Element) -> bool {
+        gst_debug!(self.cat, obj: element, "Stopping");
+
+        // Joins the sandbox thread
+        *self.sandbox.lock().unwrap() = None;
+
         let demuxer_impl = &mut self.imp.lock().unwrap();
 
-        gst_debug!(self.cat, obj: element, "Stopping");
+        let index_location = self.settings.lock().unwrap().index_location.clone();
+        if let (Some(index_location), Some(index)) =
+            (index_location, demuxer_impl.get_seek_index())
+        {
+            if !index.is_empty() {
+                if let Err(err) = index.save(&index_location) {
+                    gst_warning!(
+                        self.cat,
+                        obj: element,
+                        "Failed to store index to {}: {}",
+                        index_location,
+                        err
+                    );
+                }
+            }
+        }
 
         match demuxer_impl.stop(element) {
             Ok(..) => {
@@ -372,6 +570,34 @@ impl Demuxer {
         }
     }
 
+    fn handle_buffer(
+        &self,
+        element: &Element,
+        buffer: Option<gst::Buffer>,
+    ) -> Result<HandleBufferResult, FlowError> {
+        let sandbox = self.sandbox.lock().unwrap();
+
+        match *sandbox {
+            None => self.imp.lock().unwrap().handle_buffer(element, buffer),
+            Some(ref sandbox) => {
+                let call = AssertSend((element.clone(), self.imp.clone(), buffer));
+                let res = sandbox.run(move || {
+                    let (element, imp, buffer) = call.0;
+                    let res = imp.lock().unwrap().handle_buffer(&element, buffer);
+                    AssertSend(res)
+                });
+
+                match res {
+                    Ok(res) => res.0,
+                    Err(err) => Err(FlowError::Error(gst_error_msg!(
+                        gst::StreamError::Demux,
+                        ["Failed to parse in sandbox: {}", err]
+                    ))),
+                }
+            }
+        }
+    }
+
     fn sink_chain(
         _pad: &gst::Pad,
         parent: &Option<gst::Object>,
@@ -385,12 +611,12 @@ impl Demuxer {
             .unwrap();
         let demuxer = element.get_impl().downcast_ref::<Demuxer>().unwrap();
 
-        let mut res = {
-            let demuxer_impl = &mut demuxer.imp.lock().unwrap();
+        demuxer.push_pending_segment(&element);
 
+        let mut res = {
             gst_trace!(demuxer.cat, obj: &element, "Handling buffer {:?}", buffer);
 
-            match demuxer_impl.handle_buffer(&element, Some(buffer)) {
+            match demuxer.handle_buffer(&element, Some(buffer)) {
                 Ok(res) => res,
                 Err(flow_error) => {
                     gst_error!(
@@ -441,6 +667,17 @@ impl Demuxer {
                     demuxer.stream_eos(&element, index);
                     return gst::FlowReturn::Eos;
                 }
+                HandleBufferResult::NeedDataFromOffset(offset) => {
+                    if !demuxer.seek_upstream(&element, offset) {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Demux,
+                            ["Upstream is not seekable, can't continue from offset {}", offset]
+                        );
+                        return gst::FlowReturn::Error;
+                    }
+                    return gst::FlowReturn::Ok;
+                }
                 HandleBufferResult::Again => {
                     // nothing, just call again
                 }
@@ -449,8 +686,7 @@ impl Demuxer {
             gst_trace!(demuxer.cat, obj: &element, "Calling again");
 
             res = {
-                let demuxer_impl = &mut demuxer.imp.lock().unwrap();
-                match demuxer_impl.handle_buffer(&element, None) {
+                match demuxer.handle_buffer(&element, None) {
                     Ok(res) => res,
                     Err(flow_error) => {
                         gst_error!(
@@ -502,7 +738,12 @@ impl Demuxer {
                 }
                 pad.event_default(parent.as_ref(), event)
             }
-            EventView::Segment(..) => pad.event_default(parent.as_ref(), event),
+            // The byte segment from upstream is replaced by the time segments on the source pads
+            EventView::Segment(..) => true,
+            EventView::FlushStop(..) => {
+                demuxer.flow_combiner.lock().unwrap().reset();
+                pad.event_default(parent.as_ref(), event)
+            }
             _ => pad.event_default(parent.as_ref(), event),
         }
     }
@@ -518,6 +759,14 @@ impl Demuxer {
             .unwrap();
         let demuxer = element.get_impl().downcast_ref::<Demuxer>().unwrap();
 
+        if SeekIndex::is_query(query) {
+            let demuxer_impl = &mut demuxer.imp.lock().unwrap();
+            return match demuxer_impl.get_seek_index() {
+                Some(index) => index.handle_query(query),
+                None => false,
+            };
+        }
+
         match query.view_mut() {
             QueryView::Position(ref mut q) => {
                 let fmt = q.get_format();
@@ -578,13 +827,75 @@ impl Demuxer {
     fn src_event(pad: &gst::Pad, parent: &Option<gst::Object>, event: gst::Event) -> bool {
         use gst::EventView;
 
-        match event.view() {
-            EventView::Seek(..) => {
-                // TODO: Implement
-                false
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let demuxer = element.get_impl().downcast_ref::<Demuxer>().unwrap();
+
+        let seek = match event.view() {
+            EventView::Seek(ref e) => Some(e.get()),
+            _ => None,
+        };
+        let (rate, flags, start_type, start, stop_type, stop) = match seek {
+            None => return pad.event_default(parent.as_ref(), event),
+            Some(seek) => seek,
+        };
+
+        let (start, stop) = match (start, stop) {
+            (gst::GenericFormattedValue::Time(start), gst::GenericFormattedValue::Time(stop))
+                if rate == 1.0 && start_type == gst::SeekType::Set
+                    && stop_type != gst::SeekType::End =>
+            {
+                if stop_type == gst::SeekType::None {
+                    (start, gst::CLOCK_TIME_NONE)
+                } else {
+                    (start, stop)
+                }
             }
-            _ => pad.event_default(parent.as_ref(), event),
+            _ => {
+                gst_debug!(demuxer.cat, obj: &element, "Unsupported seek {:?}", event);
+                return false;
+            }
+        };
+
+        if !demuxer.imp.lock().unwrap().is_seekable(&element) {
+            gst_debug!(demuxer.cat, obj: &element, "Not seekable");
+            return false;
+        }
+
+        let mut offset = 0;
+        if !demuxer.seek(&element, start, stop, &mut offset) {
+            return false;
         }
+
+        // Already at EOS
+        if offset == u64::MAX {
+            return true;
+        }
+
+        let mut segment = gst::FormattedSegment::<gst::ClockTime>::new();
+        segment.set_start(start);
+        segment.set_stop(stop);
+        segment.set_time(start);
+        segment.set_position(start);
+        *demuxer.pending_segment.lock().unwrap() = Some(segment);
+
+        // Flushes from upstream are forwarded downstream, the segment is sent before the
+        // next buffer
+        let seek = gst::Event::new_seek(
+            1.0,
+            flags,
+            gst::SeekType::Set,
+            gst::format::Bytes(Some(offset)),
+            gst::SeekType::None,
+            gst::format::Bytes(None),
+        ).seqnum(event.get_seqnum())
+            .build();
+
+        demuxer.sinkpad.push_event(seek)
     }
 
     fn seek(
@@ -631,7 +942,47 @@ impl Demuxer {
     }
 }
 
-impl ObjectImpl<Element> for Demuxer {}
+impl ObjectImpl<Element> for Demuxer {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::Boolean("sandbox", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.sandbox = value.get().unwrap();
+            }
+            Property::UInt64("sandbox-memory-budget", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.sandbox_memory_budget = value.get().unwrap();
+            }
+            Property::String("index-location", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.index_location = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::Boolean("sandbox", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.sandbox.to_value())
+            }
+            Property::UInt64("sandbox-memory-budget", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.sandbox_memory_budget.to_value())
+            }
+            Property::String("index-location", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.index_location.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
 
 impl ElementImpl<Element> for Demuxer {
     fn change_state(
@@ -691,7 +1042,7 @@ impl ImplTypeStatic<Element> for DemuxerStatic {
     }
 }
 
-pub fn demuxer_register(plugin: &gst::Plugin, demuxer_info: DemuxerInfo) {
+pub fn demuxer_register(plugin: &gst::Plugin, demuxer_info: DemuxerInfo) -> bool {
     let name = demuxer_info.name.clone();
     let rank = demuxer_info.rank;
 
@@ -701,5 +1052,5 @@ pub fn demuxer_register(plugin: &gst::Plugin, demuxer_info: DemuxerInfo) {
     };
 
     let type_ = register_type(demuxer_static);
-    gst::Element::register(plugin, &name, rank, type_);
+    element_register(plugin, &name, rank, type_)
 }
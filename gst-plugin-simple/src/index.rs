@@ -0,0 +1,238 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Seek index mapping stream times to byte offsets.
+//!
+//! Demuxers add an entry for every position they can resume parsing from, e.g. keyframes, while
+//! parsing. Seeking then only needs a binary search instead of scanning the file again. The
+//! index can be stored in a sidecar file next to the media and loaded again the next time, and
+//! elements can provide it to each other with a custom `seek-index` query.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use gst;
+
+/// Name of the custom query structure.
+pub const QUERY_NAME: &str = "seek-index";
+// Field of the query structure containing the serialized index
+const QUERY_FIELD: &str = "index";
+
+const HEADER: &str = "# seek index v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Stream time in nanoseconds.
+    pub time: u64,
+    /// Byte offset to resume parsing from.
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SeekIndex {
+    // Sorted by time, and as such also by offset
+    entries: Vec<IndexEntry>,
+}
+
+impl SeekIndex {
+    pub fn new() -> SeekIndex {
+        SeekIndex {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    pub fn last(&self) -> Option<IndexEntry> {
+        self.entries.last().cloned()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Adds an entry, keeping the index sorted. Entries for an already known time are ignored,
+    /// which allows calling this again for data that was parsed before a seek.
+    pub fn add(&mut self, time: u64, offset: u64) {
+        let entry = IndexEntry {
+            time: time,
+            offset: offset,
+        };
+
+        // Data is usually parsed in order, so this is the common case
+        if self.entries.last().map(|last| last.time < time).unwrap_or(true) {
+            self.entries.push(entry);
+            return;
+        }
+
+        if let Err(idx) = self.entries.binary_search_by_key(&time, |e| e.time) {
+            self.entries.insert(idx, entry);
+        }
+    }
+
+    /// Adds all entries of another index.
+    pub fn merge(&mut self, other: &SeekIndex) {
+        for entry in &other.entries {
+            self.add(entry.time, entry.offset);
+        }
+    }
+
+    /// Returns the last entry at or before `time`.
+    pub fn lookup_time(&self, time: u64) -> Option<IndexEntry> {
+        match self.entries.binary_search_by_key(&time, |e| e.time) {
+            Ok(idx) => Some(self.entries[idx]),
+            Err(0) => None,
+            Err(idx) => Some(self.entries[idx - 1]),
+        }
+    }
+
+    /// Returns the last entry at or before `offset`.
+    pub fn lookup_offset(&self, offset: u64) -> Option<IndexEntry> {
+        match self.entries.binary_search_by_key(&offset, |e| e.offset) {
+            Ok(idx) => Some(self.entries[idx]),
+            Err(0) => None,
+            Err(idx) => Some(self.entries[idx - 1]),
+        }
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "{}", HEADER)?;
+        for entry in &self.entries {
+            writeln!(w, "{} {}", entry.time, entry.offset)?;
+        }
+        w.flush()
+    }
+
+    pub fn read<R: BufRead>(r: R) -> io::Result<SeekIndex> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut lines = r.lines();
+        match lines.next() {
+            Some(Ok(ref line)) if line == HEADER => (),
+            Some(Err(err)) => return Err(err),
+            _ => return Err(invalid("Not a seek index")),
+        }
+
+        let mut index = SeekIndex::new();
+        for line in lines {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut fields = line.split(' ').map(|f| f.parse::<u64>());
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(Ok(time)), Some(Ok(offset)), None) => index.add(time, offset),
+                _ => return Err(invalid("Invalid seek index entry")),
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Stores the index in a sidecar file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = File::create(path)?;
+        self.write(BufWriter::new(file))
+    }
+
+    /// Loads an index from a sidecar file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<SeekIndex> {
+        let file = File::open(path)?;
+        SeekIndex::read(BufReader::new(file))
+    }
+
+    fn serialize(&self) -> String {
+        let mut data = Vec::new();
+        self.write(&mut data).unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    /// Creates a query asking for the seek index of an element.
+    pub fn new_query() -> gst::Query {
+        gst::Query::new_custom(gst::Structure::new_empty(QUERY_NAME))
+    }
+
+    pub fn is_query(query: &gst::QueryRef) -> bool {
+        query
+            .get_structure()
+            .map(|s| s.get_name() == QUERY_NAME)
+            .unwrap_or(false)
+    }
+
+    /// Answers a query created by `new_query()`, to be called from the query function of
+    /// elements that have an index.
+    pub fn handle_query(&self, query: &mut gst::QueryRef) -> bool {
+        if !SeekIndex::is_query(query) || self.is_empty() {
+            return false;
+        }
+
+        let data = self.serialize();
+        query.get_mut_structure().set(QUERY_FIELD, &data);
+        true
+    }
+
+    /// Retrieves the index from an answered query.
+    pub fn from_query(query: &gst::QueryRef) -> Option<SeekIndex> {
+        query
+            .get_structure()
+            .and_then(|s| s.get::<String>(QUERY_FIELD))
+            .and_then(|data| SeekIndex::read(data.as_bytes()).ok())
+    }
+
+    /// Asks the peer of `pad` for its seek index.
+    pub fn query_peer(pad: &gst::Pad) -> Option<SeekIndex> {
+        let mut query = SeekIndex::new_query();
+        if !pad.peer_query(query.get_mut().unwrap()) {
+            return None;
+        }
+
+        SeekIndex::from_query(&query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_and_persistence() {
+        let mut index = SeekIndex::new();
+        index.add(2_000, 300);
+        index.add(0, 13);
+        index.add(1_000, 150);
+        index.add(1_000, 150);
+        assert_eq!(index.len(), 3);
+
+        assert_eq!(index.lookup_time(0).map(|e| e.offset), Some(13));
+        assert_eq!(index.lookup_time(1_500).map(|e| e.offset), Some(150));
+        assert_eq!(index.lookup_time(5_000).map(|e| e.offset), Some(300));
+        assert_eq!(index.lookup_offset(12), None);
+        assert_eq!(index.lookup_offset(299).map(|e| e.time), Some(1_000));
+
+        let mut data = Vec::new();
+        index.write(&mut data).unwrap();
+        assert_eq!(
+            String::from_utf8(data.clone()).unwrap(),
+            "# seek index v1\n0 13\n1000 150\n2000 300\n"
+        );
+        assert_eq!(SeekIndex::read(&data[..]).unwrap(), index);
+        assert!(SeekIndex::read(&b"0 13\n"[..]).is_err());
+    }
+}
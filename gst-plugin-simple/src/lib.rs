@@ -12,11 +12,17 @@ extern crate gst_plugin;
 extern crate gstreamer as gst;
 extern crate gstreamer_base as gst_base;
 
+extern crate futures;
+#[macro_use]
+extern crate lazy_static;
+extern crate tokio;
 extern crate url;
 
 pub mod source;
+pub mod async_source;
 pub mod sink;
 pub mod demuxer;
+pub mod index;
 pub mod error;
 
 pub type UriValidator = Fn(&url::Url) -> Result<(), error::UriError> + Send + Sync + 'static;
@@ -18,6 +18,7 @@ use gst_base::prelude::*;
 use gst_plugin::object::*;
 use gst_plugin::properties::*;
 use gst_plugin::element::*;
+use gst_plugin::registration::*;
 use gst_plugin::base_sink::*;
 use gst_plugin::uri_handler::*;
 use error::*;
@@ -32,6 +33,15 @@ pub trait SinkImpl: Send + 'static {
     fn start(&mut self, sink: &BaseSink, uri: Url) -> Result<(), gst::ErrorMessage>;
     fn stop(&mut self, sink: &BaseSink) -> Result<(), gst::ErrorMessage>;
     fn render(&mut self, sink: &BaseSink, buffer: &gst::BufferRef) -> Result<(), FlowError>;
+
+    /// Called for the properties from `SinkInfo::properties`.
+    fn set_property(&mut self, _sink: &BaseSink, _property: &Property, _value: &glib::Value) {
+        unimplemented!()
+    }
+
+    fn get_property(&self, _sink: &BaseSink, _property: &Property) -> Result<glib::Value, ()> {
+        unimplemented!()
+    }
 }
 
 struct Sink {
@@ -39,6 +49,7 @@ struct Sink {
     uri: Mutex<(Option<Url>, bool)>,
     uri_validator: Box<UriValidator>,
     imp: Mutex<Box<SinkImpl>>,
+    properties: &'static [Property<'static>],
 }
 
 static PROPERTIES: [Property; 1] = [
@@ -64,6 +75,7 @@ impl Sink {
             uri: Mutex::new((None, false)),
             uri_validator: sink_impl.uri_validator(),
             imp: Mutex::new(sink_impl),
+            properties: sink_info.properties,
         }
     }
 
@@ -84,7 +96,9 @@ impl Sink {
         );
         klass.add_pad_template(pad_template);
 
-        klass.install_properties(&PROPERTIES);
+        let mut properties = PROPERTIES.to_vec();
+        properties.extend_from_slice(sink_info.properties);
+        klass.install_properties(&properties);
     }
 
     fn init(element: &BaseSink, sink_info: &SinkInfo) -> Box<BaseSinkImpl<BaseSink>> {
@@ -134,6 +148,13 @@ impl Sink {
 
 impl ObjectImpl<BaseSink> for Sink {
     fn set_property(&self, obj: &glib::Object, id: u32, value: &glib::Value) {
+        if id as usize >= PROPERTIES.len() {
+            let sink = obj.clone().dynamic_cast::<BaseSink>().unwrap();
+            let prop = &self.properties[id as usize - PROPERTIES.len()];
+            self.imp.lock().unwrap().set_property(&sink, prop, value);
+            return;
+        }
+
         let prop = &PROPERTIES[id as usize];
 
         match *prop {
@@ -145,6 +166,12 @@ impl ObjectImpl<BaseSink> for Sink {
     }
 
     fn get_property(&self, obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        if id as usize >= PROPERTIES.len() {
+            let sink = obj.clone().dynamic_cast::<BaseSink>().unwrap();
+            let prop = &self.properties[id as usize - PROPERTIES.len()];
+            return self.imp.lock().unwrap().get_property(&sink, prop);
+        }
+
         let prop = &PROPERTIES[id as usize];
 
         match *prop {
@@ -249,6 +276,7 @@ pub struct SinkInfo {
     pub rank: u32,
     pub create_instance: fn(&BaseSink) -> Box<SinkImpl>,
     pub protocols: Vec<String>,
+    pub properties: &'static [Property<'static>],
 }
 
 struct SinkStatic {
@@ -288,7 +316,7 @@ impl URIHandlerImplStatic<BaseSink> for SinkStatic {
     }
 }
 
-pub fn sink_register(plugin: &gst::Plugin, sink_info: SinkInfo) {
+pub fn sink_register(plugin: &gst::Plugin, sink_info: SinkInfo) -> bool {
     let name = sink_info.name.clone();
     let rank = sink_info.rank;
 
@@ -298,5 +326,5 @@ pub fn sink_register(plugin: &gst::Plugin, sink_info: SinkInfo) {
     };
 
     let type_ = register_type(sink_static);
-    gst::Element::register(plugin, &name, rank, type_);
+    element_register(plugin, &name, rank, type_)
 }
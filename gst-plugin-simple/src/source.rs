@@ -20,8 +20,10 @@ use gst_base::prelude::*;
 use gst_plugin::object::*;
 use gst_plugin::properties::*;
 use gst_plugin::element::*;
+use gst_plugin::registration::*;
 use gst_plugin::base_src::*;
 use gst_plugin::uri_handler::*;
+use gst_plugin::idle::*;
 use error::*;
 
 pub use gst_plugin::base_src::BaseSrc;
@@ -49,6 +51,15 @@ pub trait SourceImpl: Send + 'static {
         start: u64,
         stop: Option<u64>,
     ) -> Result<(), gst::ErrorMessage>;
+
+    /// Called for the properties from `SourceInfo::properties`.
+    fn set_property(&mut self, _src: &BaseSrc, _property: &Property, _value: &glib::Value) {
+        unimplemented!()
+    }
+
+    fn get_property(&self, _src: &BaseSrc, _property: &Property) -> Result<glib::Value, ()> {
+        unimplemented!()
+    }
 }
 
 struct Source {
@@ -57,9 +68,11 @@ struct Source {
     uri_validator: Box<UriValidator>,
     imp: Mutex<Box<SourceImpl>>,
     push_only: bool,
+    properties: &'static [Property<'static>],
+    idle: Idle,
 }
 
-static PROPERTIES: [Property; 1] = [
+static PROPERTIES: [Property; 2] = [
     Property::String(
         "uri",
         "URI",
@@ -67,6 +80,7 @@ static PROPERTIES: [Property; 1] = [
         None,
         PropertyMutability::ReadWrite,
     ),
+    IDLE_WHEN_UNLINKED_PROPERTY,
 ];
 
 impl Source {
@@ -83,6 +97,8 @@ impl Source {
             uri_validator: source_impl.uri_validator(),
             imp: Mutex::new(source_impl),
             push_only: source_info.push_only,
+            properties: source_info.properties,
+            idle: Idle::new(),
         }
     }
 
@@ -103,7 +119,9 @@ impl Source {
         );
         klass.add_pad_template(pad_template);
 
-        klass.install_properties(&PROPERTIES);
+        let mut properties = PROPERTIES.to_vec();
+        properties.extend_from_slice(source_info.properties);
+        klass.install_properties(&properties);
     }
 
     fn init(element: &BaseSrc, source_info: &SourceInfo) -> Box<BaseSrcImpl<BaseSrc>> {
@@ -153,21 +171,38 @@ impl Source {
 
 impl ObjectImpl<BaseSrc> for Source {
     fn set_property(&self, obj: &glib::Object, id: u32, value: &glib::Value) {
+        if id as usize >= PROPERTIES.len() {
+            let src = obj.clone().dynamic_cast::<BaseSrc>().unwrap();
+            let prop = &self.properties[id as usize - PROPERTIES.len()];
+            self.imp.lock().unwrap().set_property(&src, prop, value);
+            return;
+        }
+
         let prop = &PROPERTIES[id as usize];
 
         match *prop {
             Property::String("uri", ..) => {
                 self.set_uri(obj, value.get()).unwrap();
             }
+            Property::Boolean("idle-when-unlinked", ..) => {
+                self.idle.set_enabled(value.get().unwrap());
+            }
             _ => unimplemented!(),
         }
     }
 
     fn get_property(&self, obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        if id as usize >= PROPERTIES.len() {
+            let src = obj.clone().dynamic_cast::<BaseSrc>().unwrap();
+            let prop = &self.properties[id as usize - PROPERTIES.len()];
+            return self.imp.lock().unwrap().get_property(&src, prop);
+        }
+
         let prop = &PROPERTIES[id as usize];
 
         match *prop {
             Property::String("uri", ..) => Ok(self.get_uri(obj).to_value()),
+            Property::Boolean("idle-when-unlinked", ..) => Ok(self.idle.is_enabled().to_value()),
             _ => unimplemented!(),
         }
     }
@@ -250,6 +285,10 @@ impl BaseSrcImpl<BaseSrc> for Source {
         length: u32,
         buffer: &mut gst::BufferRef,
     ) -> gst::FlowReturn {
+        if let Err(ret) = self.idle.wait_linked(self.cat, src) {
+            return ret;
+        }
+
         let source_impl = &mut self.imp.lock().unwrap();
 
         gst_trace!(
@@ -311,6 +350,18 @@ impl BaseSrcImpl<BaseSrc> for Source {
         let source_impl = &self.imp.lock().unwrap();
         source_impl.get_size(src)
     }
+
+    fn unlock(&self, src: &BaseSrc) -> bool {
+        gst_debug!(self.cat, obj: src, "Unlocking");
+        self.idle.unlock();
+        true
+    }
+
+    fn unlock_stop(&self, src: &BaseSrc) -> bool {
+        gst_debug!(self.cat, obj: src, "Stop unlocking");
+        self.idle.unlock_stop();
+        true
+    }
 }
 
 impl URIHandlerImpl for Source {
@@ -333,6 +384,7 @@ pub struct SourceInfo {
     pub create_instance: fn(&BaseSrc) -> Box<SourceImpl>,
     pub protocols: Vec<String>,
     pub push_only: bool,
+    pub properties: &'static [Property<'static>],
 }
 
 struct SourceStatic {
@@ -372,7 +424,7 @@ impl URIHandlerImplStatic<BaseSrc> for SourceStatic {
     }
 }
 
-pub fn source_register(plugin: &gst::Plugin, source_info: SourceInfo) {
+pub fn source_register(plugin: &gst::Plugin, source_info: SourceInfo) -> bool {
     let name = source_info.name.clone();
     let rank = source_info.rank;
 
@@ -382,5 +434,5 @@ pub fn source_register(plugin: &gst::Plugin, source_info: SourceInfo) {
     };
 
     let type_ = register_type(source_static);
-    gst::Element::register(plugin, &name, rank, type_);
+    element_register(plugin, &name, rank, type_)
 }
@@ -0,0 +1,503 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::adapter::*;
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use sodiumoxide::crypto::secretstream::{Key, Pull, Stream, Tag};
+
+use std::sync::Mutex;
+
+use stream;
+
+// Decrypts streams produced by sodiumenc. Every chunk is authenticated before it is output, a
+// modified stream or a wrong key results in an error. The caps stored in the stream are set on
+// the source pad before the first data.
+//
+// A stream that ends before the final chunk was received is reported as error at EOS, all data
+// before is output nonetheless.
+
+const DEFAULT_KEY: Option<&str> = None;
+const DEFAULT_KEY_FILE: Option<&str> = None;
+
+static PROPERTIES: [Property; 2] = [
+    Property::String(
+        "key",
+        "Key",
+        "The 32 byte key as hex string",
+        DEFAULT_KEY,
+        PropertyMutability::Writable,
+    ),
+    Property::String(
+        "key-file",
+        "Key File",
+        "File containing the 32 byte key, either raw or as hex string (ignored if key is set)",
+        DEFAULT_KEY_FILE,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    key: Option<String>,
+    key_file: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            key: DEFAULT_KEY.map(String::from),
+            key_file: DEFAULT_KEY_FILE.map(String::from),
+        }
+    }
+}
+
+struct State {
+    key: Key,
+    adapter: Adapter,
+    // Set once the header was parsed
+    stream: Option<Stream<Pull>>,
+    have_caps: bool,
+    finished: bool,
+}
+
+enum Output {
+    Caps(gst::Caps),
+    Segment,
+    Buffer(gst::Buffer),
+}
+
+struct SodiumDec {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl SodiumDec {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "sodiumdec",
+                gst::DebugColorFlags::empty(),
+                "Sodium stream decrypter",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Sodium Decrypter",
+            "Decoder/Decryptor",
+            "Decrypts streams encrypted by sodiumenc",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(stream::CAPS_NAME, &[]);
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_any();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            SodiumDec::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |dec, element| dec.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            SodiumDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |dec, element| dec.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            SodiumDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |dec, element| dec.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            SodiumDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |dec, element| dec.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            SodiumDec::catch_panic_pad_function(
+                parent,
+                || false,
+                |dec, element| dec.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let dec = element.get_impl().downcast_ref::<SodiumDec>().unwrap();
+        element.catch_panic(fallback, |element| f(dec, element))
+    }
+
+    // Decrypts all complete chunks in the adapter
+    fn decrypt(state: &mut State, output: &mut Vec<Output>) -> Result<(), gst::ErrorMessage> {
+        if state.stream.is_none() {
+            if state.adapter.get_available() < stream::HEADER_SIZE {
+                return Ok(());
+            }
+
+            let header = stream::parse_header(state.adapter.peek(stream::HEADER_SIZE).unwrap())
+                .map_err(|err| gst_error_msg!(gst::StreamError::WrongType, ["{}", err]))?;
+            state.adapter.flush(stream::HEADER_SIZE).unwrap();
+
+            let pull = Stream::init_pull(&header, &state.key).map_err(|_| {
+                gst_error_msg!(gst::StreamError::Decrypt, ["Invalid stream header"])
+            })?;
+            state.stream = Some(pull);
+        }
+
+        while state.adapter.get_available() >= 4 {
+            let size = stream::parse_chunk_size(state.adapter.peek(4).unwrap());
+            if size > stream::MAX_CHUNK_SIZE {
+                return Err(gst_error_msg!(
+                    gst::StreamError::Decrypt,
+                    ["Invalid chunk size {}", size]
+                ));
+            }
+            if state.adapter.get_available() < 4 + size {
+                break;
+            }
+
+            if state.finished {
+                return Err(gst_error_msg!(
+                    gst::StreamError::Decrypt,
+                    ["Data after the final chunk"]
+                ));
+            }
+
+            let (plaintext, tag) = {
+                let chunk = &state.adapter.peek(4 + size).unwrap()[4..];
+                state
+                    .stream
+                    .as_mut()
+                    .unwrap()
+                    .pull(chunk, None)
+                    .map_err(|_| {
+                        gst_error_msg!(
+                            gst::StreamError::DecryptNokey,
+                            ["Failed to decrypt, wrong key or corrupted stream"]
+                        )
+                    })?
+            };
+            state.adapter.flush(4 + size).unwrap();
+
+            if !state.have_caps {
+                if tag != Tag::Push {
+                    return Err(gst_error_msg!(
+                        gst::StreamError::Decrypt,
+                        ["Stream does not start with caps"]
+                    ));
+                }
+                state.have_caps = true;
+
+                // Streams of inputs without caps have no caps either
+                let caps = String::from_utf8(plaintext)
+                    .ok()
+                    .and_then(|caps| gst::Caps::from_string(&caps));
+                if let Some(caps) = caps {
+                    output.push(Output::Caps(caps));
+                }
+                output.push(Output::Segment);
+                continue;
+            }
+
+            if tag == Tag::Final {
+                state.finished = true;
+            }
+            if !plaintext.is_empty() {
+                output.push(Output::Buffer(gst::Buffer::from_slice(plaintext).unwrap()));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn push_output(&self, pad: &gst::Pad, output: Vec<Output>) -> gst::FlowReturn {
+        for output in output {
+            match output {
+                Output::Caps(caps) => {
+                    gst_debug!(self.cat, obj: pad, "Setting caps {:?}", caps);
+                    self.srcpad.push_event(gst::Event::new_caps(&caps).build());
+                }
+                Output::Segment => {
+                    // Positions in the output can't be mapped to the input
+                    let segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+                    self.srcpad
+                        .push_event(gst::Event::new_segment(&segment).build());
+                }
+                Output::Buffer(buffer) => {
+                    gst_log!(self.cat, obj: pad, "Pushing buffer {:?}", buffer);
+                    let flow_ret = self.srcpad.push(buffer);
+                    if flow_ret != gst::FlowReturn::Ok {
+                        return flow_ret;
+                    }
+                }
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let mut output = Vec::new();
+        let res = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match *state_guard {
+                None => return gst::FlowReturn::Flushing,
+                Some(ref mut state) => state,
+            };
+
+            state.adapter.push(buffer);
+            Self::decrypt(state, &mut output)
+        };
+
+        // Everything that was decrypted before the error is still output
+        let flow_ret = self.push_output(pad, output);
+
+        if let Err(err) = res {
+            element.post_error_message(&err);
+            return gst::FlowReturn::Error;
+        }
+
+        flow_ret
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            // Caps and segment are sent once the caps are decrypted
+            EventView::Caps(..) | EventView::Segment(..) => return true,
+            EventView::Eos(..) => {
+                let finished = self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .map(|state| state.finished)
+                    .unwrap_or(true);
+
+                if !finished {
+                    gst_element_error!(
+                        element,
+                        gst::StreamError::Decrypt,
+                        ["Stream ended before the final chunk, it was truncated"]
+                    );
+                }
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Seek(..) => false,
+            _ => self.sinkpad.push_event(event),
+        }
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            QueryView::Position(..) | QueryView::Duration(..) | QueryView::Seeking(..) => false,
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+
+    fn start(&self, element: &Element) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let key = match stream::load_key(
+            settings.key.as_ref().map(|k| k.as_str()),
+            settings.key_file.as_ref().map(|k| k.as_str()),
+        ) {
+            Err(err) => {
+                gst_element_error!(element, gst::ResourceError::Settings, ["{}", err]);
+                return false;
+            }
+            Ok(key) => key,
+        };
+
+        *self.state.lock().unwrap() = Some(State {
+            key: key,
+            adapter: Adapter::new(),
+            stream: None,
+            have_caps: false,
+            finished: false,
+        });
+
+        true
+    }
+}
+
+impl ObjectImpl<Element> for SodiumDec {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("key", ..) => {
+                settings.key = value.get();
+            }
+            Property::String("key-file", ..) => {
+                settings.key_file = value.get();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("key-file", ..) => Ok(settings.key_file.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for SodiumDec {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                if !self.start(element) {
+                    return gst::StateChangeReturn::Failure;
+                }
+            }
+            gst::StateChange::PausedToReady => {
+                *self.state.lock().unwrap() = None;
+            }
+            _ => (),
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct SodiumDecStatic;
+
+impl ImplTypeStatic<Element> for SodiumDecStatic {
+    fn get_name(&self) -> &str {
+        "SodiumDec"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        SodiumDec::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        SodiumDec::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let sodiumdec_static = SodiumDecStatic;
+    register_type(sodiumdec_static)
+}
@@ -0,0 +1,506 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use sodiumoxide::crypto::secretstream::{self, Push, Stream, Tag};
+
+use std::sync::Mutex;
+
+use stream;
+
+// Encrypts the incoming stream with a libsodium secretstream, see the stream module for the
+// format. The data is collected into chunks of block-size bytes, each chunk is encrypted and
+// authenticated separately so that the decrypter can verify and output the data progressively.
+//
+// The output is a plain byte stream, timestamps of the input are not preserved. The caps of the
+// input are stored encrypted in the stream and restored by sodiumdec.
+
+const DEFAULT_KEY: Option<&str> = None;
+const DEFAULT_KEY_FILE: Option<&str> = None;
+const DEFAULT_BLOCK_SIZE: u32 = 64 * 1024;
+
+static PROPERTIES: [Property; 3] = [
+    Property::String(
+        "key",
+        "Key",
+        "The 32 byte key as hex string",
+        DEFAULT_KEY,
+        PropertyMutability::Writable,
+    ),
+    Property::String(
+        "key-file",
+        "Key File",
+        "File containing the 32 byte key, either raw or as hex string (ignored if key is set)",
+        DEFAULT_KEY_FILE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "block-size",
+        "Block Size",
+        "Size of the separately encrypted chunks in bytes",
+        (
+            1,
+            stream::MAX_CHUNK_SIZE as u32 - secretstream::ABYTES as u32,
+        ),
+        DEFAULT_BLOCK_SIZE,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    key: Option<String>,
+    key_file: Option<String>,
+    block_size: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            key: DEFAULT_KEY.map(String::from),
+            key_file: DEFAULT_KEY_FILE.map(String::from),
+            block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+}
+
+struct State {
+    stream: Stream<Push>,
+    // Header that still has to be sent before the first chunk
+    header: Option<Vec<u8>>,
+    caps: Option<gst::Caps>,
+    // Plaintext that does not fill a complete chunk yet
+    pending: Vec<u8>,
+    finished: bool,
+}
+
+struct SodiumEnc {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl SodiumEnc {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "sodiumenc",
+                gst::DebugColorFlags::empty(),
+                "Sodium stream encrypter",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Sodium Encrypter",
+            "Encoder/Encryptor",
+            "Encrypts streams with libsodium secretstream",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple(stream::CAPS_NAME, &[]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            SodiumEnc::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |enc, element| enc.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            SodiumEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |enc, element| enc.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            SodiumEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |enc, element| enc.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            SodiumEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |enc, element| enc.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            SodiumEnc::catch_panic_pad_function(
+                parent,
+                || false,
+                |enc, element| enc.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let enc = element.get_impl().downcast_ref::<SodiumEnc>().unwrap();
+        element.catch_panic(fallback, |element| f(enc, element))
+    }
+
+    // Encrypts all complete chunks, or everything if final is set, and returns the output
+    fn encrypt(&self, element: &Element, state: &mut State, final_: bool) -> Option<Vec<u8>> {
+        let block_size = self.settings.lock().unwrap().block_size as usize;
+        let mut output = state.header.take().unwrap_or_default();
+
+        if !output.is_empty() {
+            let caps = state
+                .caps
+                .as_ref()
+                .map(|caps| caps.to_string())
+                .unwrap_or_default();
+            match state.stream.push(caps.as_bytes(), None, Tag::Push) {
+                Ok(chunk) => output.extend_from_slice(&stream::write_chunk(&chunk)),
+                Err(_) => {
+                    gst_element_error!(element, gst::StreamError::Encode, ["Failed to encrypt"]);
+                    return None;
+                }
+            }
+        }
+
+        let mut offset = 0;
+        while state.pending.len() - offset >= block_size
+            || (final_ && !state.finished && state.pending.len() - offset < block_size)
+        {
+            let end = (offset + block_size).min(state.pending.len());
+            let tag = if final_ && end == state.pending.len() {
+                state.finished = true;
+                Tag::Final
+            } else {
+                Tag::Message
+            };
+
+            match state.stream.push(&state.pending[offset..end], None, tag) {
+                Ok(chunk) => output.extend_from_slice(&stream::write_chunk(&chunk)),
+                Err(_) => {
+                    gst_element_error!(element, gst::StreamError::Encode, ["Failed to encrypt"]);
+                    return None;
+                }
+            }
+            offset = end;
+        }
+        state.pending.drain(..offset);
+
+        Some(output)
+    }
+
+    fn push_output(&self, output: Vec<u8>) -> gst::FlowReturn {
+        if output.is_empty() {
+            return gst::FlowReturn::Ok;
+        }
+
+        let buffer = gst::Buffer::from_slice(output).unwrap();
+        gst_log!(self.cat, obj: &self.srcpad, "Pushing buffer {:?}", buffer);
+        self.srcpad.push(buffer)
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let output = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match *state_guard {
+                None => return gst::FlowReturn::Flushing,
+                Some(ref mut state) => state,
+            };
+
+            if state.finished {
+                gst_element_error!(element, gst::StreamError::Failed, ["Data after EOS"]);
+                return gst::FlowReturn::Error;
+            }
+
+            {
+                let map = match buffer.map_readable() {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Failed,
+                            ["Failed to map buffer"]
+                        );
+                        return gst::FlowReturn::Error;
+                    }
+                    Some(map) => map,
+                };
+                state.pending.extend_from_slice(map.as_slice());
+            }
+
+            match self.encrypt(element, state, false) {
+                None => return gst::FlowReturn::Error,
+                Some(output) => output,
+            }
+        };
+
+        self.push_output(output)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::StreamStart(..) => {
+                if !self.srcpad.push_event(event.clone()) {
+                    return false;
+                }
+
+                // The output is a byte stream independent of the input format
+                let caps = gst::Caps::new_simple(stream::CAPS_NAME, &[]);
+                let segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+                return self.srcpad.push_event(gst::Event::new_caps(&caps).build())
+                    && self
+                        .srcpad
+                        .push_event(gst::Event::new_segment(&segment).build());
+            }
+            EventView::Caps(e) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    if state.header.is_some() {
+                        state.caps = Some(e.get_caps().to_owned());
+                    } else {
+                        gst_warning!(self.cat, obj: pad, "Ignoring caps change {:?}", event);
+                    }
+                }
+                return true;
+            }
+            EventView::Segment(..) => return true,
+            EventView::Eos(..) => {
+                let output = match *self.state.lock().unwrap() {
+                    None => None,
+                    Some(ref mut state) => self.encrypt(element, state, true),
+                };
+
+                if let Some(output) = output {
+                    let flow_ret = self.push_output(output);
+                    if flow_ret != gst::FlowReturn::Ok {
+                        gst_debug!(self.cat, obj: pad, "Failed to push last chunk: {:?}", flow_ret);
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            // Positions in the encrypted stream can't be mapped to the input
+            EventView::Seek(..) => false,
+            _ => self.sinkpad.push_event(event),
+        }
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            QueryView::Position(..) | QueryView::Duration(..) | QueryView::Seeking(..) => false,
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+
+    fn start(&self, element: &Element) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let key = match stream::load_key(
+            settings.key.as_ref().map(|k| k.as_str()),
+            settings.key_file.as_ref().map(|k| k.as_str()),
+        ) {
+            Err(err) => {
+                gst_element_error!(element, gst::ResourceError::Settings, ["{}", err]);
+                return false;
+            }
+            Ok(key) => key,
+        };
+
+        let (stream, header) = match Stream::init_push(&key) {
+            Err(_) => {
+                gst_element_error!(
+                    element,
+                    gst::LibraryError::Init,
+                    ["Failed to initialize encryption"]
+                );
+                return false;
+            }
+            Ok(res) => res,
+        };
+
+        *self.state.lock().unwrap() = Some(State {
+            stream: stream,
+            header: Some(stream::write_header(&header)),
+            caps: None,
+            pending: Vec::new(),
+            finished: false,
+        });
+
+        true
+    }
+}
+
+impl ObjectImpl<Element> for SodiumEnc {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("key", ..) => {
+                settings.key = value.get();
+            }
+            Property::String("key-file", ..) => {
+                settings.key_file = value.get();
+            }
+            Property::UInt("block-size", ..) => {
+                settings.block_size = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("key-file", ..) => Ok(settings.key_file.to_value()),
+            Property::UInt("block-size", ..) => Ok(settings.block_size.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for SodiumEnc {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                if !self.start(element) {
+                    return gst::StateChangeReturn::Failure;
+                }
+            }
+            gst::StateChange::PausedToReady => {
+                *self.state.lock().unwrap() = None;
+            }
+            _ => (),
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct SodiumEncStatic;
+
+impl ImplTypeStatic<Element> for SodiumEncStatic {
+    fn get_name(&self) -> &str {
+        "SodiumEnc"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        SodiumEnc::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        SodiumEnc::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let sodiumenc_static = SodiumEncStatic;
+    register_type(sodiumenc_static)
+}
@@ -0,0 +1,132 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Format of the encrypted streams:
+//
+//   "RSSODIUM" | version (u8) | secretstream header
+//
+// followed by chunks of
+//
+//   ciphertext length (u32 BE) | secretstream ciphertext
+//
+// The first chunk contains the caps of the plaintext as string and is tagged Push, the following
+// ones contain the data and are tagged Message. The last chunk is tagged Final, a stream without
+// it was truncated.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use gst_plugin::bytes::parse_hex;
+
+use sodiumoxide::crypto::secretstream::{self, Header, Key};
+
+use std::fs::File;
+use std::io::Read;
+
+pub const CAPS_NAME: &str = "application/x-sodium-encrypted";
+
+const MAGIC: &[u8] = b"RSSODIUM";
+const VERSION: u8 = 1;
+
+pub const HEADER_SIZE: usize = 8 + 1 + secretstream::HEADERBYTES;
+// Upper bound for the size of a single chunk to detect corrupted streams early
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024 * 1024;
+
+pub fn write_header(header: &Header) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_SIZE);
+    data.extend_from_slice(MAGIC);
+    data.push(VERSION);
+    data.extend_from_slice(&header.0);
+    data
+}
+
+pub fn parse_header(data: &[u8]) -> Result<Header, String> {
+    if data.len() < HEADER_SIZE || &data[0..8] != MAGIC {
+        return Err(String::from("Not a sodium encrypted stream"));
+    }
+
+    if data[8] != VERSION {
+        return Err(format!("Unsupported version {}", data[8]));
+    }
+
+    Ok(Header::from_slice(&data[9..HEADER_SIZE]).unwrap())
+}
+
+pub fn write_chunk(ciphertext: &[u8]) -> Vec<u8> {
+    let mut data = vec![0; 4 + ciphertext.len()];
+    BigEndian::write_u32(&mut data[0..4], ciphertext.len() as u32);
+    data[4..].copy_from_slice(ciphertext);
+    data
+}
+
+pub fn parse_chunk_size(data: &[u8]) -> usize {
+    BigEndian::read_u32(&data[0..4]) as usize
+}
+
+// The key file contains either the raw key or the key as hex string
+fn parse_key_file(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() == secretstream::KEYBYTES {
+        return Some(data.to_vec());
+    }
+
+    ::std::str::from_utf8(data).ok().and_then(parse_hex)
+}
+
+/// Loads the key from the hex string or, if not set, the key file
+pub fn load_key(key: Option<&str>, key_file: Option<&str>) -> Result<Key, String> {
+    let data = match (key, key_file) {
+        (Some(key), _) => parse_hex(key).ok_or_else(|| String::from("Key is not hex encoded"))?,
+        (None, Some(key_file)) => {
+            let mut data = Vec::new();
+            File::open(key_file)
+                .and_then(|mut f| f.read_to_end(&mut data))
+                .map_err(|err| format!("Failed to read key file {}: {}", key_file, err))?;
+            parse_key_file(&data).ok_or_else(|| format!("Invalid key file {}", key_file))?
+        }
+        (None, None) => return Err(String::from("key or key-file has to be set")),
+    };
+
+    Key::from_slice(&data).ok_or_else(|| {
+        format!(
+            "Key has {} bytes instead of {}",
+            data.len(),
+            secretstream::KEYBYTES
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        let raw = [0x55; 32];
+        assert_eq!(parse_key_file(&raw), Some(raw.to_vec()));
+        let hex = "55".repeat(32);
+        assert_eq!(parse_key_file(hex.as_bytes()), Some(raw.to_vec()));
+
+        assert!(load_key(Some(&hex), None).is_ok());
+        assert!(load_key(Some("5555"), None).is_err());
+        assert!(load_key(None, None).is_err());
+    }
+
+    #[test]
+    fn test_header() {
+        let key = secretstream::gen_key();
+        let (_, header) = secretstream::Stream::init_push(&key).unwrap();
+
+        let data = write_header(&header);
+        assert_eq!(data.len(), HEADER_SIZE);
+        assert_eq!(parse_header(&data).unwrap().0, header.0);
+        assert!(parse_header(&data[1..]).is_err());
+
+        let chunk = write_chunk(&[1, 2, 3]);
+        assert_eq!(parse_chunk_size(&chunk), 3);
+        assert_eq!(&chunk[4..], &[1, 2, 3]);
+    }
+}
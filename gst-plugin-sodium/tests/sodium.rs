@@ -0,0 +1,91 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate gst_plugin;
+extern crate gstreamer as gst;
+
+use gst_plugin::test::*;
+
+use std::time::Duration;
+
+const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+const OTHER_KEY: &str = "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+fn new_harness(enc_key: &str, dec_key: &str) -> Harness {
+    init();
+
+    let mut h = Harness::new_parse(&format!(
+        "sodiumenc key={} block-size=16 ! sodiumdec key={}",
+        enc_key, dec_key
+    ));
+    h.set_src_caps_str("application/x-test, foo=(int)1");
+    h
+}
+
+#[test]
+fn test_roundtrip() {
+    let mut h = new_harness(KEY, KEY);
+
+    let data = (0..100).map(|i| i as u8).collect::<Vec<_>>();
+    for chunk in data.chunks(30) {
+        let buffer = gst::Buffer::from_slice(chunk.to_vec()).unwrap();
+        assert_eq!(h.push(buffer), gst::FlowReturn::Ok);
+    }
+    assert!(h.push_event(gst::Event::new_eos().build()));
+
+    let mut output = Vec::new();
+    while let Some(buffer) = h.try_pull() {
+        output.extend_from_slice(buffer.map_readable().unwrap().as_slice());
+    }
+    assert_eq!(output, data);
+
+    let event = h.pull_event().unwrap();
+    assert_eq!(event.get_type(), gst::EventType::StreamStart);
+    let event = h.pull_event().unwrap();
+    match event.view() {
+        gst::EventView::Caps(e) => assert_eq!(
+            e.get_caps().to_owned(),
+            gst::Caps::from_string("application/x-test, foo=(int)1").unwrap()
+        ),
+        _ => panic!("Unexpected event {:?}", event),
+    }
+    let event = h.pull_event().unwrap();
+    assert_eq!(event.get_type(), gst::EventType::Segment);
+}
+
+#[test]
+fn test_wrong_key() {
+    let mut h = new_harness(KEY, OTHER_KEY);
+
+    let buffer = gst::Buffer::from_slice(vec![0u8; 64]).unwrap();
+    assert_eq!(h.push(buffer), gst::FlowReturn::Error);
+    assert!(h.try_pull().is_none());
+}
+
+#[test]
+fn test_pipeline_roundtrip() {
+    init();
+
+    let mut p = TestPipeline::new(
+        "audiotestsrc num-buffers=5 ! audio/x-raw,format=S16LE,rate=8000,channels=1",
+        &format!("sodiumenc key={} ! sodiumdec key={}", KEY, KEY),
+    );
+    p.start();
+
+    let mut size = 0;
+    while let Some(buffer) = p.pull_buffer(Duration::from_secs(5)) {
+        size += buffer.get_size();
+    }
+    // audiotestsrc outputs 1024 samples per buffer by default
+    assert_eq!(size, 5 * 1024 * 2);
+    p.assert_caps(&gst::Caps::new_simple(
+        "audio/x-raw",
+        &[("format", &"S16LE"), ("rate", &8000i32), ("channels", &1i32)],
+    ));
+    p.stop();
+}
@@ -0,0 +1,78 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate futures;
+extern crate glib;
+#[macro_use]
+extern crate gst_plugin;
+extern crate gst_plugin_simple;
+#[macro_use]
+extern crate gstreamer as gst;
+extern crate gstreamer_base as gst_base;
+extern crate tokio;
+extern crate url;
+
+use gst_plugin_simple::async_source::*;
+use gst_plugin_simple::sink::*;
+use gst_plugin::registration::*;
+
+mod socket;
+mod tcpclientsrc;
+mod tcpserversink;
+
+use tcpclientsrc::TcpClientSrc;
+use tcpserversink::TcpServerSink;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    let source_registered = async_source_register(
+        plugin,
+        AsyncSourceInfo {
+            name: "rstcpclientsrc".into(),
+            long_name: "TCP Client Source".into(),
+            description: "Receives data from a TCP server, optionally reconnecting".into(),
+            classification: "Source/Network".into(),
+            author: "Sebastian Dröge <sebastian@centricular.com>".into(),
+            rank: RANK_NONE,
+            create_instance: TcpClientSrc::new_boxed,
+            protocols: vec!["tcp".into()],
+            live: true,
+            properties: &tcpclientsrc::PROPERTIES,
+        },
+    );
+
+    let sink_registered = sink_register(
+        plugin,
+        SinkInfo {
+            name: "rstcpserversink".into(),
+            long_name: "TCP Server Sink".into(),
+            description: "Sends data to all connected TCP clients".into(),
+            classification: "Sink/Network".into(),
+            author: "Sebastian Dröge <sebastian@centricular.com>".into(),
+            rank: RANK_NONE,
+            create_instance: TcpServerSink::new_boxed,
+            protocols: vec!["tcp".into()],
+            properties: &tcpserversink::PROPERTIES,
+        },
+    );
+
+    source_registered && sink_registered
+}
+
+plugin_define!(
+    "rstcp",
+    "Rust TCP Plugin",
+    plugin_init,
+    "1.0",
+    "MIT/X11",
+    "rstcp",
+    "rstcp",
+    "https://github.com/sdroege/rsplugin",
+    "2018-01-15"
+);
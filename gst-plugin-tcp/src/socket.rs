@@ -0,0 +1,54 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+use url::{Host, Url};
+
+use gst;
+use gst_plugin_simple::error::*;
+
+/// Address and port of a `tcp://host:port` URI.
+pub fn get_socket_addr(uri: &Url) -> Result<SocketAddr, String> {
+    let port = uri.port()
+        .ok_or_else(|| format!("No port in URI '{}'", uri.as_str()))?;
+
+    match uri.host() {
+        Some(Host::Ipv4(addr)) => Ok(SocketAddr::new(IpAddr::V4(addr), port)),
+        Some(Host::Ipv6(addr)) => Ok(SocketAddr::new(IpAddr::V6(addr), port)),
+        Some(Host::Domain(domain)) => (domain, port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| format!("Failed to resolve '{}'", domain)),
+        None => Err(format!("No host in URI '{}'", uri.as_str())),
+    }
+}
+
+pub fn validate_uri(uri: &Url) -> Result<(), UriError> {
+    get_socket_addr(uri)
+        .map(|_| ())
+        .map_err(|err| UriError::new(gst::URIError::BadUri, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_addr() {
+        let uri = Url::parse("tcp://127.0.0.1:4953").unwrap();
+        assert_eq!(get_socket_addr(&uri), Ok("127.0.0.1:4953".parse().unwrap()));
+
+        let uri = Url::parse("tcp://[::1]:4953").unwrap();
+        assert_eq!(get_socket_addr(&uri), Ok("[::1]:4953".parse().unwrap()));
+
+        let uri = Url::parse("tcp://127.0.0.1").unwrap();
+        assert!(get_socket_addr(&uri).is_err());
+    }
+}
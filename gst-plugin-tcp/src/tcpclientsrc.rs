@@ -0,0 +1,372 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use std::u32;
+
+use futures::{Async, Future, Poll, Stream};
+use tokio::io::AsyncRead;
+use tokio::net::{ConnectFuture, TcpStream};
+use tokio::timer::Delay;
+use url::Url;
+
+use gst_plugin::properties::*;
+use gst_plugin_simple::error::*;
+use gst_plugin_simple::async_source::*;
+use gst_plugin_simple::UriValidator;
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_base::prelude::*;
+
+use socket;
+
+const DEFAULT_BLOCKSIZE: u32 = 4096;
+const DEFAULT_READ_TIMEOUT: u32 = 0;
+const DEFAULT_RECONNECT: bool = false;
+const DEFAULT_MIN_BACKOFF: u32 = 100;
+const DEFAULT_MAX_BACKOFF: u32 = 10_000;
+const DEFAULT_MAX_RETRIES: u32 = 0;
+
+pub static PROPERTIES: [Property; 6] = [
+    Property::UInt(
+        "blocksize",
+        "Blocksize",
+        "Maximum size of the buffers read from the connection",
+        (1, u32::MAX),
+        DEFAULT_BLOCKSIZE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "read-timeout",
+        "Read Timeout",
+        "Time in milliseconds without data after which the connection is considered lost \
+         (0 = disabled)",
+        (0, u32::MAX),
+        DEFAULT_READ_TIMEOUT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "reconnect",
+        "Reconnect",
+        "Reconnect when connecting fails or the connection is lost instead of erroring out",
+        DEFAULT_RECONNECT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "min-backoff",
+        "Minimum Backoff",
+        "Time in milliseconds to wait before the first reconnection attempt",
+        (0, u32::MAX),
+        DEFAULT_MIN_BACKOFF,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "max-backoff",
+        "Maximum Backoff",
+        "Upper limit in milliseconds for the doubling wait between reconnection attempts",
+        (0, u32::MAX),
+        DEFAULT_MAX_BACKOFF,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "max-retries",
+        "Maximum Retries",
+        "Number of failed reconnection attempts in a row before erroring out (0 = unlimited)",
+        (0, u32::MAX),
+        DEFAULT_MAX_RETRIES,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    blocksize: u32,
+    read_timeout: u32,
+    reconnect: bool,
+    min_backoff: u32,
+    max_backoff: u32,
+    max_retries: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            blocksize: DEFAULT_BLOCKSIZE,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            reconnect: DEFAULT_RECONNECT,
+            min_backoff: DEFAULT_MIN_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+}
+
+// Wait before the reconnection attempt after `retries` failed ones: doubles every time,
+// starting at min_backoff and capped at max_backoff
+fn get_backoff(settings: &Settings, retries: u32) -> Duration {
+    let backoff = (settings.min_backoff as u64) << cmp::min(retries, 32);
+    let backoff = cmp::min(backoff, cmp::max(settings.max_backoff, settings.min_backoff) as u64);
+
+    Duration::new(backoff / 1000, (backoff % 1000) as u32 * 1_000_000)
+}
+
+// Behaviour:
+//
+// - The connection is established on the shared runtime once the stream is first polled
+// - EOF ends the stream, unless reconnect is enabled
+// - With reconnect, failed connection attempts and lost connections are retried after the
+//   backoff. Data already read is not repeated, the stream just continues on the new
+//   connection
+// - Lost connections are reported as error without reconnect, same as exceeding max-retries
+enum ConnectionState {
+    Connecting(ConnectFuture),
+    Connected(TcpStream, Option<Delay>),
+    Waiting(Delay),
+}
+
+enum Transition {
+    Connected(TcpStream),
+    Connect,
+    Failed(String),
+}
+
+struct ClientStream {
+    cat: gst::DebugCategory,
+    addr: SocketAddr,
+    settings: Settings,
+    state: ConnectionState,
+    retries: u32,
+}
+
+impl ClientStream {
+    fn read_timeout(settings: &Settings) -> Option<Delay> {
+        if settings.read_timeout == 0 {
+            return None;
+        }
+
+        let timeout = settings.read_timeout as u64;
+        Some(Delay::new(
+            Instant::now() + Duration::new(timeout / 1000, (timeout % 1000) as u32 * 1_000_000),
+        ))
+    }
+
+    fn retry(&mut self, msg: String) -> Result<(), FlowError> {
+        if !self.settings.reconnect
+            || (self.settings.max_retries != 0 && self.retries >= self.settings.max_retries)
+        {
+            return Err(FlowError::Error(gst_error_msg!(
+                gst::ResourceError::Read,
+                ["{}", msg]
+            )));
+        }
+
+        let backoff = get_backoff(&self.settings, self.retries);
+        self.retries += 1;
+
+        gst_warning!(
+            self.cat,
+            "{}, reconnecting to {} in {:?} (attempt {})",
+            msg,
+            self.addr,
+            backoff,
+            self.retries
+        );
+
+        self.state = ConnectionState::Waiting(Delay::new(Instant::now() + backoff));
+
+        Ok(())
+    }
+}
+
+impl Stream for ClientStream {
+    type Item = gst::Buffer;
+    type Error = FlowError;
+
+    fn poll(&mut self) -> Poll<Option<gst::Buffer>, FlowError> {
+        loop {
+            // Decided while the state is borrowed, applied afterwards
+            let transition = match self.state {
+                ConnectionState::Connecting(ref mut connect) => match connect.poll() {
+                    Ok(Async::Ready(stream)) => Transition::Connected(stream),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        Transition::Failed(format!("Failed to connect to {}: {}", self.addr, err))
+                    }
+                },
+                ConnectionState::Connected(ref mut stream, ref mut timeout) => {
+                    let mut buf = vec![0; self.settings.blocksize as usize];
+                    match stream.poll_read(&mut buf) {
+                        Ok(Async::Ready(0)) => {
+                            if !self.settings.reconnect {
+                                gst_debug!(self.cat, "Connection to {} closed", self.addr);
+                                return Ok(Async::Ready(None));
+                            }
+                            Transition::Failed(format!("Connection to {} closed", self.addr))
+                        }
+                        Ok(Async::Ready(len)) => {
+                            buf.truncate(len);
+                            *timeout = ClientStream::read_timeout(&self.settings);
+                            return Ok(Async::Ready(Some(
+                                gst::Buffer::from_mut_slice(buf).unwrap(),
+                            )));
+                        }
+                        Ok(Async::NotReady) => match *timeout {
+                            None => return Ok(Async::NotReady),
+                            Some(ref mut timeout) => match timeout.poll() {
+                                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                                Ok(Async::Ready(())) => Transition::Failed(format!(
+                                    "Read timeout on connection to {}",
+                                    self.addr
+                                )),
+                                Err(err) => {
+                                    Transition::Failed(format!("Read timeout failed: {}", err))
+                                }
+                            },
+                        },
+                        Err(err) => Transition::Failed(format!(
+                            "Failed to read from {}: {}",
+                            self.addr, err
+                        )),
+                    }
+                }
+                ConnectionState::Waiting(ref mut delay) => match delay.poll() {
+                    Ok(Async::Ready(())) => Transition::Connect,
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        Transition::Failed(format!("Reconnection timer failed: {}", err))
+                    }
+                },
+            };
+
+            match transition {
+                Transition::Connected(stream) => {
+                    gst_debug!(self.cat, "Connected to {}", self.addr);
+                    self.retries = 0;
+                    let timeout = ClientStream::read_timeout(&self.settings);
+                    self.state = ConnectionState::Connected(stream, timeout);
+                }
+                Transition::Connect => {
+                    self.state = ConnectionState::Connecting(TcpStream::connect(&self.addr));
+                }
+                Transition::Failed(msg) => self.retry(msg)?,
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpClientSrc {
+    cat: gst::DebugCategory,
+    settings: Settings,
+}
+
+impl TcpClientSrc {
+    pub fn new(src: &BaseSrc) -> TcpClientSrc {
+        // There are no timestamps on a byte stream, use the running time of arrival
+        src.set_do_timestamp(true);
+
+        TcpClientSrc {
+            cat: gst::DebugCategory::new(
+                "rstcpclientsrc",
+                gst::DebugColorFlags::empty(),
+                "Rust TCP client source",
+            ),
+            settings: Settings::default(),
+        }
+    }
+
+    pub fn new_boxed(src: &BaseSrc) -> Box<AsyncSourceImpl> {
+        Box::new(TcpClientSrc::new(src))
+    }
+}
+
+impl AsyncSourceImpl for TcpClientSrc {
+    fn uri_validator(&self) -> Box<UriValidator> {
+        Box::new(socket::validate_uri)
+    }
+
+    fn start(&mut self, src: &BaseSrc, uri: Url) -> Result<BufferStream, gst::ErrorMessage> {
+        let addr = socket::get_socket_addr(&uri)
+            .map_err(|err| gst_error_msg!(gst::ResourceError::Settings, ["{}", err]))?;
+
+        gst_debug!(self.cat, obj: src, "Connecting to {}", addr);
+
+        Ok(Box::new(ClientStream {
+            cat: self.cat,
+            addr: addr,
+            settings: self.settings.clone(),
+            state: ConnectionState::Connecting(TcpStream::connect(&addr)),
+            retries: 0,
+        }))
+    }
+
+    fn stop(&mut self, src: &BaseSrc) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(self.cat, obj: src, "Stopped");
+        Ok(())
+    }
+
+    fn set_property(&mut self, _src: &BaseSrc, property: &Property, value: &glib::Value) {
+        match *property {
+            Property::UInt("blocksize", ..) => {
+                self.settings.blocksize = value.get().unwrap();
+            }
+            Property::UInt("read-timeout", ..) => {
+                self.settings.read_timeout = value.get().unwrap();
+            }
+            Property::Boolean("reconnect", ..) => {
+                self.settings.reconnect = value.get().unwrap();
+            }
+            Property::UInt("min-backoff", ..) => {
+                self.settings.min_backoff = value.get().unwrap();
+            }
+            Property::UInt("max-backoff", ..) => {
+                self.settings.max_backoff = value.get().unwrap();
+            }
+            Property::UInt("max-retries", ..) => {
+                self.settings.max_retries = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _src: &BaseSrc, property: &Property) -> Result<glib::Value, ()> {
+        match *property {
+            Property::UInt("blocksize", ..) => Ok(self.settings.blocksize.to_value()),
+            Property::UInt("read-timeout", ..) => Ok(self.settings.read_timeout.to_value()),
+            Property::Boolean("reconnect", ..) => Ok(self.settings.reconnect.to_value()),
+            Property::UInt("min-backoff", ..) => Ok(self.settings.min_backoff.to_value()),
+            Property::UInt("max-backoff", ..) => Ok(self.settings.max_backoff.to_value()),
+            Property::UInt("max-retries", ..) => Ok(self.settings.max_retries.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut settings = Settings::default();
+        assert_eq!(get_backoff(&settings, 0), Duration::from_millis(100));
+        assert_eq!(get_backoff(&settings, 1), Duration::from_millis(200));
+        assert_eq!(get_backoff(&settings, 6), Duration::from_millis(6400));
+        assert_eq!(get_backoff(&settings, 7), Duration::from_millis(10_000));
+        assert_eq!(get_backoff(&settings, 1000), Duration::from_millis(10_000));
+
+        // The maximum never lowers the first wait
+        settings.min_backoff = 500;
+        settings.max_backoff = 100;
+        assert_eq!(get_backoff(&settings, 3), Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::u32;
+
+use url::Url;
+
+use gst_plugin::properties::*;
+use gst_plugin_simple::error::*;
+use gst_plugin_simple::sink::*;
+use gst_plugin_simple::UriValidator;
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use socket;
+
+const DEFAULT_MAX_CONNECTIONS: u32 = 0;
+
+pub static PROPERTIES: [Property; 2] = [
+    Property::UInt(
+        "max-connections",
+        "Maximum Connections",
+        "Maximum number of connected clients, further clients are disconnected right away \
+         (0 = unlimited)",
+        (0, u32::MAX),
+        DEFAULT_MAX_CONNECTIONS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "connection-count",
+        "Connection Count",
+        "Number of currently connected clients",
+        (0, u32::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    max_connections: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        }
+    }
+}
+
+// Behaviour:
+//
+// - The listening socket is bound in start() and polled for new clients before every buffer,
+//   so clients only receive data that is rendered after they connected
+// - Every buffer is written completely to every client. A client that can't keep up blocks
+//   the streaming thread, a client that fails is disconnected
+// - Without any client, buffers are dropped
+#[derive(Debug)]
+enum StreamingState {
+    Stopped,
+    Started {
+        listener: TcpListener,
+        clients: Vec<(TcpStream, SocketAddr)>,
+    },
+}
+
+#[derive(Debug)]
+pub struct TcpServerSink {
+    streaming_state: StreamingState,
+    cat: gst::DebugCategory,
+    settings: Settings,
+}
+
+impl TcpServerSink {
+    pub fn new(_sink: &BaseSink) -> TcpServerSink {
+        TcpServerSink {
+            streaming_state: StreamingState::Stopped,
+            cat: gst::DebugCategory::new(
+                "rstcpserversink",
+                gst::DebugColorFlags::empty(),
+                "Rust TCP server sink",
+            ),
+            settings: Settings::default(),
+        }
+    }
+
+    pub fn new_boxed(sink: &BaseSink) -> Box<SinkImpl> {
+        Box::new(TcpServerSink::new(sink))
+    }
+}
+
+// Accepts all pending clients without blocking
+fn accept_clients(
+    cat: gst::DebugCategory,
+    settings: &Settings,
+    sink: &BaseSink,
+    listener: &TcpListener,
+    clients: &mut Vec<(TcpStream, SocketAddr)>,
+) -> io::Result<()> {
+    loop {
+        let (stream, addr) = match listener.accept() {
+            Ok(client) => client,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let max_connections = settings.max_connections as usize;
+        if max_connections != 0 && clients.len() >= max_connections {
+            gst_warning!(
+                cat,
+                obj: sink,
+                "Rejecting client {}, already {} connected",
+                addr,
+                clients.len()
+            );
+            continue;
+        }
+
+        // Accepted sockets inherit the non-blocking mode of the listener on some platforms
+        if let Err(err) = stream.set_nonblocking(false) {
+            gst_warning!(cat, obj: sink, "Failed to set up client {}: {}", addr, err);
+            continue;
+        }
+
+        gst_debug!(cat, obj: sink, "Client {} connected", addr);
+        clients.push((stream, addr));
+    }
+}
+
+impl SinkImpl for TcpServerSink {
+    fn uri_validator(&self) -> Box<UriValidator> {
+        Box::new(socket::validate_uri)
+    }
+
+    fn start(&mut self, sink: &BaseSink, uri: Url) -> Result<(), gst::ErrorMessage> {
+        if let StreamingState::Started { .. } = self.streaming_state {
+            return Err(gst_error_msg!(
+                gst::LibraryError::Failed,
+                ["Sink already started"]
+            ));
+        }
+
+        let addr = socket::get_socket_addr(&uri)
+            .map_err(|err| gst_error_msg!(gst::ResourceError::Settings, ["{}", err]))?;
+
+        let listener = TcpListener::bind(&addr)
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .map_err(|err| {
+                gst_error_msg!(
+                    gst::ResourceError::OpenWrite,
+                    ["Failed to listen on {}: {}", addr, err]
+                )
+            })?;
+
+        gst_debug!(self.cat, obj: sink, "Listening on {}", addr);
+
+        self.streaming_state = StreamingState::Started {
+            listener: listener,
+            clients: Vec::new(),
+        };
+
+        Ok(())
+    }
+
+    fn stop(&mut self, _sink: &BaseSink) -> Result<(), gst::ErrorMessage> {
+        self.streaming_state = StreamingState::Stopped;
+        Ok(())
+    }
+
+    fn render(&mut self, sink: &BaseSink, buffer: &gst::BufferRef) -> Result<(), FlowError> {
+        let cat = self.cat;
+
+        gst_trace!(cat, obj: sink, "Rendering {:?}", buffer);
+
+        let (listener, clients) = match self.streaming_state {
+            StreamingState::Started {
+                ref listener,
+                ref mut clients,
+            } => (listener, clients),
+            StreamingState::Stopped => {
+                return Err(FlowError::Error(gst_error_msg!(
+                    gst::LibraryError::Failed,
+                    ["Not started yet"]
+                )));
+            }
+        };
+
+        accept_clients(cat, &self.settings, sink, listener, clients).map_err(|err| {
+            FlowError::Error(gst_error_msg!(
+                gst::ResourceError::Write,
+                ["Failed to accept clients: {}", err]
+            ))
+        })?;
+
+        let map = match buffer.map_readable() {
+            None => {
+                return Err(FlowError::Error(gst_error_msg!(
+                    gst::LibraryError::Failed,
+                    ["Failed to map buffer"]
+                )));
+            }
+            Some(map) => map,
+        };
+
+        // Writing only needs a shared reference to the socket
+        clients.retain(|&(ref stream, ref addr)| match (&*stream).write_all(map.as_slice()) {
+            Ok(_) => true,
+            Err(err) => {
+                gst_debug!(cat, obj: sink, "Client {} disconnected: {}", addr, err);
+                false
+            }
+        });
+
+        Ok(())
+    }
+
+    fn set_property(&mut self, _sink: &BaseSink, property: &Property, value: &glib::Value) {
+        match *property {
+            Property::UInt("max-connections", ..) => {
+                self.settings.max_connections = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _sink: &BaseSink, property: &Property) -> Result<glib::Value, ()> {
+        match *property {
+            Property::UInt("max-connections", ..) => Ok(self.settings.max_connections.to_value()),
+            Property::UInt("connection-count", ..) => {
+                let count = match self.streaming_state {
+                    StreamingState::Started { ref clients, .. } => clients.len() as u32,
+                    StreamingState::Stopped => 0,
+                };
+                Ok(count.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
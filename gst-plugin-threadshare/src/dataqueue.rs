@@ -0,0 +1,186 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use futures::{Async, Poll, Stream};
+use futures::task;
+
+use gst;
+
+#[derive(Debug)]
+pub enum DataQueueItem {
+    Buffer(gst::Buffer),
+    Event(gst::Event),
+}
+
+impl DataQueueItem {
+    fn size(&self) -> (u32, u32) {
+        match *self {
+            DataQueueItem::Buffer(ref buffer) => (1, buffer.get_size() as u32),
+            DataQueueItem::Event(_) => (0, 0),
+        }
+    }
+
+    fn timestamp(&self) -> Option<u64> {
+        match *self {
+            DataQueueItem::Buffer(ref buffer) => buffer.get_dts_or_pts().0,
+            DataQueueItem::Event(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DataQueueState {
+    Started,
+    Stopped,
+}
+
+struct DataQueueInner {
+    state: DataQueueState,
+    queue: VecDeque<DataQueueItem>,
+    cur_size_buffers: u32,
+    cur_size_bytes: u32,
+    max_size_buffers: Option<u32>,
+    max_size_bytes: Option<u32>,
+    max_size_time: Option<u64>,
+    current_task: Option<task::Task>,
+}
+
+impl DataQueueInner {
+    fn wake(&mut self) {
+        if let Some(task) = self.current_task.take() {
+            task.notify();
+        }
+    }
+
+    fn is_full(&self, item: &DataQueueItem) -> bool {
+        let (count, bytes) = item.size();
+
+        if let Some(max) = self.max_size_buffers {
+            if count > 0 && self.cur_size_buffers >= max {
+                return true;
+            }
+        }
+        if let Some(max) = self.max_size_bytes {
+            if bytes > 0 && self.cur_size_bytes + bytes > max && self.cur_size_bytes > 0 {
+                return true;
+            }
+        }
+        if let Some(max) = self.max_size_time {
+            let first = self.queue.iter().filter_map(|item| item.timestamp()).next();
+            if let (Some(first), Some(last)) = (first, item.timestamp()) {
+                if last > first && last - first >= max {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+/// Queue of buffers and serialized events that is drained by a stream on an IO context.
+#[derive(Clone)]
+pub struct DataQueue(Arc<Mutex<DataQueueInner>>);
+
+impl DataQueue {
+    pub fn new(
+        max_size_buffers: Option<u32>,
+        max_size_bytes: Option<u32>,
+        max_size_time: Option<u64>,
+    ) -> DataQueue {
+        DataQueue(Arc::new(Mutex::new(DataQueueInner {
+            state: DataQueueState::Stopped,
+            queue: VecDeque::new(),
+            cur_size_buffers: 0,
+            cur_size_bytes: 0,
+            max_size_buffers: max_size_buffers,
+            max_size_bytes: max_size_bytes,
+            max_size_time: max_size_time,
+            current_task: None,
+        })))
+    }
+
+    pub fn start(&self) {
+        let mut inner = self.0.lock().unwrap();
+        inner.state = DataQueueState::Started;
+        inner.wake();
+    }
+
+    /// Stops the queue, which ends all streams and drops all queued items.
+    pub fn stop(&self) {
+        let mut inner = self.0.lock().unwrap();
+        inner.state = DataQueueState::Stopped;
+        inner.wake();
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.0.lock().unwrap();
+        inner.queue.clear();
+        inner.cur_size_buffers = 0;
+        inner.cur_size_bytes = 0;
+    }
+
+    /// Queues the item, or returns it back if the queue is full or stopped.
+    pub fn push(&self, item: DataQueueItem) -> Result<(), DataQueueItem> {
+        let mut inner = self.0.lock().unwrap();
+
+        if inner.state == DataQueueState::Stopped || inner.is_full(&item) {
+            return Err(item);
+        }
+
+        let (count, bytes) = item.size();
+        inner.cur_size_buffers += count;
+        inner.cur_size_bytes += bytes;
+        inner.queue.push_back(item);
+        inner.wake();
+
+        Ok(())
+    }
+
+    /// Stream of all queued items, which ends once the queue is stopped.
+    pub fn stream(&self) -> DataQueueStream {
+        DataQueueStream(self.clone())
+    }
+}
+
+pub struct DataQueueStream(DataQueue);
+
+impl Stream for DataQueueStream {
+    type Item = DataQueueItem;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<DataQueueItem>, ()> {
+        let mut inner = (self.0).0.lock().unwrap();
+
+        match inner.state {
+            DataQueueState::Stopped => {
+                inner.queue.clear();
+                inner.cur_size_buffers = 0;
+                inner.cur_size_bytes = 0;
+                return Ok(Async::Ready(None));
+            }
+            DataQueueState::Started => (),
+        }
+
+        match inner.queue.pop_front() {
+            None => {
+                inner.current_task = Some(task::current());
+                Ok(Async::NotReady)
+            }
+            Some(item) => {
+                let (count, bytes) = item.size();
+                inner.cur_size_buffers -= count;
+                inner.cur_size_bytes -= bytes;
+                Ok(Async::Ready(Some(item)))
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+
+use futures::{Future, Stream};
+use futures::sync::{mpsc, oneshot};
+use tokio::executor::current_thread;
+use tokio::runtime::current_thread::Runtime;
+
+use gst;
+
+lazy_static! {
+    static ref CONTEXTS: Mutex<HashMap<String, Weak<IOContextInner>>> = Mutex::new(HashMap::new());
+    static ref CONTEXT_CAT: gst::DebugCategory = gst::DebugCategory::new(
+        "ts-context",
+        gst::DebugColorFlags::empty(),
+        "Thread-sharing Context",
+    );
+}
+
+type BoxFuture = Box<Future<Item = (), Error = ()> + Send + 'static>;
+
+// Thread running a single-threaded tokio runtime, shared by all elements with the same context
+// name. All sockets are registered with the reactor of the thread that first polls them.
+struct IOContextInner {
+    name: String,
+    sender: Mutex<Option<mpsc::UnboundedSender<BoxFuture>>>,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl Drop for IOContextInner {
+    fn drop(&mut self) {
+        gst_debug!(CONTEXT_CAT, "Shutting down context {}", self.name);
+
+        // Closing the channel lets the thread finish
+        let _ = self.sender.lock().unwrap().take();
+
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            // The last reference might be dropped from a future running on the context
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct IOContext(Arc<IOContextInner>);
+
+impl IOContext {
+    /// Returns the context with the given name, starting its thread if it does not exist yet.
+    pub fn new(name: &str) -> io::Result<Self> {
+        let mut contexts = CONTEXTS.lock().unwrap();
+        if let Some(context) = contexts.get(name).and_then(|c| c.upgrade()) {
+            return Ok(IOContext(context));
+        }
+
+        gst_debug!(CONTEXT_CAT, "Starting context {}", name);
+
+        let (sender, receiver) = mpsc::unbounded::<BoxFuture>();
+        let thread = thread::Builder::new()
+            .name(format!("ts-{}", name))
+            .spawn(move || {
+                let mut runtime = Runtime::new().unwrap();
+                let _ = runtime.block_on(receiver.for_each(|future| {
+                    current_thread::spawn(future);
+                    Ok(())
+                }));
+            })?;
+
+        let context = Arc::new(IOContextInner {
+            name: name.into(),
+            sender: Mutex::new(Some(sender)),
+            thread: Mutex::new(Some(thread)),
+        });
+        contexts.insert(name.into(), Arc::downgrade(&context));
+
+        Ok(IOContext(context))
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        if let Some(ref sender) = *self.0.sender.lock().unwrap() {
+            let _ = sender.unbounded_send(Box::new(future));
+        }
+    }
+
+    /// Spawns the future, which is cancelled once the returned handle is dropped.
+    pub fn spawn_cancellable<F>(&self, future: F) -> CancelHandle
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+
+        self.spawn(
+            future
+                .select(receiver.then(|_| Ok(())))
+                .map(|_| ())
+                .map_err(|_| ()),
+        );
+
+        CancelHandle(Some(sender))
+    }
+}
+
+pub struct CancelHandle(Option<oneshot::Sender<()>>);
+
+impl Drop for CancelHandle {
+    fn drop(&mut self) {
+        if let Some(sender) = self.0.take() {
+            let _ = sender.send(());
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+#[macro_use]
+extern crate futures;
+extern crate glib;
+#[macro_use]
+extern crate gst_plugin;
+#[macro_use]
+extern crate gstreamer as gst;
+#[macro_use]
+extern crate lazy_static;
+extern crate tokio;
+
+use gst_plugin::registration::*;
+
+mod dataqueue;
+mod iocontext;
+mod srcpad;
+
+mod proxy;
+mod queue;
+mod tcpclientsrc;
+mod udpsrc;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    ElementRegistration::new(plugin)
+        .element("ts-udpsrc", RANK_NONE, udpsrc::get_type())
+        .element("ts-tcpclientsrc", RANK_NONE, tcpclientsrc::get_type())
+        .element("ts-queue", RANK_NONE, queue::get_type())
+        .element("ts-proxysink", RANK_NONE, proxy::get_sink_type())
+        .element("ts-proxysrc", RANK_NONE, proxy::get_src_type())
+        .register()
+}
+
+plugin_define!(
+    "rsthreadshare",
+    "Thread-sharing elements",
+    plugin_init,
+    "MIT/X11",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2018-01-22"
+);
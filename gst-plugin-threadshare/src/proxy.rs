@@ -0,0 +1,723 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::{u32, u64};
+
+use dataqueue::*;
+use iocontext::*;
+use srcpad::*;
+
+const DEFAULT_PROXY_CONTEXT: &str = "";
+const DEFAULT_MAX_SIZE_BUFFERS: u32 = 200;
+const DEFAULT_MAX_SIZE_BYTES: u32 = 1024 * 1024;
+const DEFAULT_MAX_SIZE_TIME: u64 = gst::SECOND_VAL;
+const DEFAULT_CONTEXT: &str = "";
+
+// State shared between the proxysink and proxysrc with the same proxy context name. The queue is
+// owned by the proxysrc and only available while it is at least in Paused.
+struct SharedContext {
+    queue: Option<DataQueue>,
+    last_ret: Arc<Mutex<gst::FlowReturn>>,
+    // Incremented whenever the proxysrc starts a new queue so that the proxysink knows when to
+    // resend its sticky events
+    generation: u64,
+    sink_pad: Option<gst::Pad>,
+    has_sink: bool,
+    has_src: bool,
+}
+
+lazy_static! {
+    static ref PROXY_CONTEXTS: Mutex<HashMap<String, Weak<Mutex<SharedContext>>>> =
+        Mutex::new(HashMap::new());
+}
+
+fn get_shared_context(name: &str) -> Arc<Mutex<SharedContext>> {
+    let mut contexts = PROXY_CONTEXTS.lock().unwrap();
+    if let Some(shared) = contexts.get(name).and_then(|s| s.upgrade()) {
+        return shared;
+    }
+
+    let shared = Arc::new(Mutex::new(SharedContext {
+        queue: None,
+        last_ret: Arc::new(Mutex::new(gst::FlowReturn::Ok)),
+        generation: 0,
+        sink_pad: None,
+        has_sink: false,
+        has_src: false,
+    }));
+    contexts.insert(name.into(), Arc::downgrade(&shared));
+
+    shared
+}
+
+fn catch_panic_pad_function<E, T, F: FnOnce(&E, &Element) -> T, G: FnOnce() -> T>(
+    parent: &Option<gst::Object>,
+    fallback: G,
+    f: F,
+) -> T
+where
+    E: 'static,
+{
+    let element = parent
+        .as_ref()
+        .cloned()
+        .unwrap()
+        .downcast::<Element>()
+        .unwrap();
+    let imp = element.get_impl().downcast_ref::<E>().unwrap();
+    element.catch_panic(fallback, |element| f(imp, element))
+}
+
+static SINK_PROPERTIES: [Property; 1] = [
+    Property::String(
+        "proxy-context",
+        "Proxy Context",
+        "Name of the proxy context to connect to the matching ts-proxysrc",
+        Some(DEFAULT_PROXY_CONTEXT),
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+struct SinkState {
+    shared: Arc<Mutex<SharedContext>>,
+    generation: u64,
+    sticky_events: Vec<gst::Event>,
+}
+
+struct ProxySink {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    proxy_context: Mutex<String>,
+    state: Mutex<Option<SinkState>>,
+}
+
+impl ProxySink {
+    fn new(_element: &Element, sinkpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "ts-proxysink",
+                gst::DebugColorFlags::empty(),
+                "Thread-sharing proxy sink",
+            ),
+            sinkpad: sinkpad,
+            proxy_context: Mutex::new(DEFAULT_PROXY_CONTEXT.into()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Thread-sharing proxy sink",
+            "Sink/Generic",
+            "Thread-sharing proxy sink",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&SINK_PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |sink: &ProxySink, element| sink.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            catch_panic_pad_function(
+                parent,
+                || false,
+                |sink: &ProxySink, element| sink.sink_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+
+        let imp = Self::new(element, sinkpad);
+        Box::new(imp)
+    }
+
+    // Returns the queue of the proxysrc, if any, after queueing the sticky events again if the
+    // proxysrc has started a new queue since the last call.
+    fn get_queue(&self) -> Option<(DataQueue, Arc<Mutex<gst::FlowReturn>>)> {
+        let mut state = self.state.lock().unwrap();
+        let state = state.as_mut()?;
+
+        let (queue, last_ret, generation) = {
+            let shared = state.shared.lock().unwrap();
+            (
+                shared.queue.clone()?,
+                shared.last_ret.clone(),
+                shared.generation,
+            )
+        };
+
+        if generation != state.generation {
+            state.generation = generation;
+            for event in &state.sticky_events {
+                let _ = queue.push(DataQueueItem::Event(event.clone()));
+            }
+        }
+
+        Some((queue, last_ret))
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let (queue, last_ret) = match self.get_queue() {
+            None => {
+                gst_debug!(self.cat, obj: element, "No proxysrc, dropping buffer");
+                return gst::FlowReturn::Ok;
+            }
+            Some(queue) => queue,
+        };
+
+        let last_ret = *last_ret.lock().unwrap();
+        if last_ret != gst::FlowReturn::Ok {
+            gst_debug!(self.cat, obj: pad, "Returning {:?}", last_ret);
+            return last_ret;
+        }
+
+        if let Err(DataQueueItem::Buffer(buffer)) = queue.push(DataQueueItem::Buffer(buffer)) {
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "Queue is full, dropping buffer {:?}",
+                buffer
+            );
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        // Flushing does not cross the proxy, only the queued data is dropped
+        match event.view() {
+            EventView::FlushStart(..) => {
+                if let Some((queue, _)) = self.get_queue() {
+                    queue.clear();
+                }
+                return true;
+            }
+            EventView::FlushStop(..) => return true,
+            EventView::StreamStart(..) | EventView::Caps(..) | EventView::Segment(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    let type_ = event.get_type();
+                    state.sticky_events.retain(|e| e.get_type() != type_);
+                    state.sticky_events.push(event.clone());
+                }
+            }
+            _ => (),
+        }
+
+        if !event.get_type().is_serialized() {
+            return true;
+        }
+
+        match self.get_queue() {
+            None => true,
+            Some((queue, _)) => queue.push(DataQueueItem::Event(event)).is_ok(),
+        }
+    }
+
+    fn prepare(&self, element: &Element) -> Result<(), gst::ErrorMessage> {
+        let proxy_context = self.proxy_context.lock().unwrap().clone();
+        let shared = get_shared_context(&proxy_context);
+
+        {
+            let mut shared = shared.lock().unwrap();
+            if shared.has_sink {
+                return Err(gst_error_msg!(
+                    gst::ResourceError::OpenWrite,
+                    ["Proxy context '{}' already has a sink", proxy_context]
+                ));
+            }
+            shared.has_sink = true;
+            shared.sink_pad = Some(self.sinkpad.clone());
+        }
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Using proxy context '{}'",
+            proxy_context
+        );
+
+        *self.state.lock().unwrap() = Some(SinkState {
+            shared: shared,
+            generation: 0,
+            sticky_events: Vec::new(),
+        });
+
+        Ok(())
+    }
+
+    fn unprepare(&self) {
+        if let Some(state) = self.state.lock().unwrap().take() {
+            let mut shared = state.shared.lock().unwrap();
+            shared.has_sink = false;
+            shared.sink_pad = None;
+        }
+    }
+}
+
+impl ObjectImpl<Element> for ProxySink {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &SINK_PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("proxy-context", ..) => {
+                *self.proxy_context.lock().unwrap() = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_PROXY_CONTEXT.into());
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &SINK_PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("proxy-context", ..) => {
+                Ok(self.proxy_context.lock().unwrap().to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for ProxySink {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if transition == gst::StateChange::NullToReady {
+            if let Err(err) = self.prepare(element) {
+                element.post_error_message(&err);
+                return gst::StateChangeReturn::Failure;
+            }
+        }
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        if transition == gst::StateChange::ReadyToNull {
+            self.unprepare();
+        }
+
+        ret
+    }
+}
+
+struct ProxySinkStatic;
+
+impl ImplTypeStatic<Element> for ProxySinkStatic {
+    fn get_name(&self) -> &str {
+        "TsProxySink"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        ProxySink::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        ProxySink::class_init(klass);
+    }
+}
+
+pub fn get_sink_type() -> glib::Type {
+    let proxysink_static = ProxySinkStatic;
+    register_type(proxysink_static)
+}
+
+#[derive(Debug, Clone)]
+struct SrcSettings {
+    proxy_context: String,
+    max_size_buffers: u32,
+    max_size_bytes: u32,
+    max_size_time: u64,
+    context: String,
+}
+
+impl Default for SrcSettings {
+    fn default() -> Self {
+        SrcSettings {
+            proxy_context: DEFAULT_PROXY_CONTEXT.into(),
+            max_size_buffers: DEFAULT_MAX_SIZE_BUFFERS,
+            max_size_bytes: DEFAULT_MAX_SIZE_BYTES,
+            max_size_time: DEFAULT_MAX_SIZE_TIME,
+            context: DEFAULT_CONTEXT.into(),
+        }
+    }
+}
+
+static SRC_PROPERTIES: [Property; 5] = [
+    Property::String(
+        "proxy-context",
+        "Proxy Context",
+        "Name of the proxy context to connect to the matching ts-proxysink",
+        Some(DEFAULT_PROXY_CONTEXT),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "max-size-buffers",
+        "Max Size Buffers",
+        "Maximum number of buffers to queue (0=unlimited)",
+        (0, u32::MAX),
+        DEFAULT_MAX_SIZE_BUFFERS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "max-size-bytes",
+        "Max Size Bytes",
+        "Maximum number of bytes to queue (0=unlimited)",
+        (0, u32::MAX),
+        DEFAULT_MAX_SIZE_BYTES,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "max-size-time",
+        "Max Size Time",
+        "Maximum number of nanoseconds to queue (0=unlimited)",
+        (0, u64::MAX - 1),
+        DEFAULT_MAX_SIZE_TIME,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "context",
+        "Context",
+        "Name of the thread-sharing context",
+        Some(DEFAULT_CONTEXT),
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+struct SrcState {
+    context: IOContext,
+    queue: DataQueue,
+    shared: Arc<Mutex<SharedContext>>,
+    task: Option<CancelHandle>,
+}
+
+struct ProxySrc {
+    cat: gst::DebugCategory,
+    srcpad: gst::Pad,
+    settings: Mutex<SrcSettings>,
+    state: Mutex<Option<SrcState>>,
+}
+
+impl ProxySrc {
+    fn new(_element: &Element, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "ts-proxysrc",
+                gst::DebugColorFlags::empty(),
+                "Thread-sharing proxy source",
+            ),
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Thread-sharing proxy source",
+            "Source/Generic",
+            "Thread-sharing proxy source",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&SRC_PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        srcpad.set_event_function(|pad, parent, event| {
+            catch_panic_pad_function(
+                parent,
+                || false,
+                |src: &ProxySrc, element| src.src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, srcpad);
+        Box::new(imp)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        // Upstream events are forwarded upstream of the proxysink, but seeking across the proxy
+        // is not supported
+        if let EventView::Seek(..) = event.view() {
+            return false;
+        }
+
+        let sink_pad = match *self.state.lock().unwrap() {
+            None => return false,
+            Some(ref state) => state.shared.lock().unwrap().sink_pad.clone(),
+        };
+
+        match sink_pad {
+            None => false,
+            Some(sink_pad) => sink_pad.push_event(event),
+        }
+    }
+
+    fn prepare(&self, element: &Element) -> Result<(), gst::ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let context = IOContext::new(&settings.context).map_err(|err| {
+            gst_error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Failed to create IO context: {}", err]
+            )
+        })?;
+
+        let shared = get_shared_context(&settings.proxy_context);
+        {
+            let mut shared = shared.lock().unwrap();
+            if shared.has_src {
+                return Err(gst_error_msg!(
+                    gst::ResourceError::OpenRead,
+                    ["Proxy context '{}' already has a source", settings.proxy_context]
+                ));
+            }
+            shared.has_src = true;
+        }
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Using proxy context '{}' and context {}",
+            settings.proxy_context,
+            context.get_name()
+        );
+
+        let queue = DataQueue::new(
+            Some(settings.max_size_buffers).and_then(|v| if v == 0 { None } else { Some(v) }),
+            Some(settings.max_size_bytes).and_then(|v| if v == 0 { None } else { Some(v) }),
+            Some(settings.max_size_time).and_then(|v| if v == 0 { None } else { Some(v) }),
+        );
+
+        *self.state.lock().unwrap() = Some(SrcState {
+            context: context,
+            queue: queue,
+            shared: shared,
+            task: None,
+        });
+
+        Ok(())
+    }
+
+    fn start(&self, element: &Element) {
+        gst_debug!(self.cat, obj: element, "Starting");
+
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.queue.start();
+
+            let last_ret = {
+                let mut shared = state.shared.lock().unwrap();
+                *shared.last_ret.lock().unwrap() = gst::FlowReturn::Ok;
+                shared.queue = Some(state.queue.clone());
+                shared.generation += 1;
+                shared.last_ret.clone()
+            };
+
+            state.task = Some(state.context.spawn_cancellable(forward_queue(
+                &state.queue,
+                &self.srcpad,
+                last_ret,
+            )));
+        }
+    }
+
+    fn stop(&self, element: &Element) {
+        gst_debug!(self.cat, obj: element, "Stopping");
+
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            {
+                let mut shared = state.shared.lock().unwrap();
+                shared.queue = None;
+                *shared.last_ret.lock().unwrap() = gst::FlowReturn::Ok;
+            }
+
+            state.queue.stop();
+            state.queue.clear();
+            state.task = None;
+        }
+    }
+
+    fn unprepare(&self) {
+        if let Some(state) = self.state.lock().unwrap().take() {
+            state.shared.lock().unwrap().has_src = false;
+        }
+    }
+}
+
+impl ObjectImpl<Element> for ProxySrc {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &SRC_PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("proxy-context", ..) => {
+                settings.proxy_context = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_PROXY_CONTEXT.into());
+            }
+            Property::UInt("max-size-buffers", ..) => {
+                settings.max_size_buffers = value.get().unwrap();
+            }
+            Property::UInt("max-size-bytes", ..) => {
+                settings.max_size_bytes = value.get().unwrap();
+            }
+            Property::UInt64("max-size-time", ..) => {
+                settings.max_size_time = value.get().unwrap();
+            }
+            Property::String("context", ..) => {
+                settings.context = value.get().unwrap_or_else(|| DEFAULT_CONTEXT.into());
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &SRC_PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("proxy-context", ..) => Ok(settings.proxy_context.to_value()),
+            Property::UInt("max-size-buffers", ..) => Ok(settings.max_size_buffers.to_value()),
+            Property::UInt("max-size-bytes", ..) => Ok(settings.max_size_bytes.to_value()),
+            Property::UInt64("max-size-time", ..) => Ok(settings.max_size_time.to_value()),
+            Property::String("context", ..) => Ok(settings.context.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for ProxySrc {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::NullToReady => {
+                if let Err(err) = self.prepare(element) {
+                    element.post_error_message(&err);
+                    return gst::StateChangeReturn::Failure;
+                }
+            }
+            gst::StateChange::PausedToReady => {
+                self.stop(element);
+            }
+            _ => (),
+        }
+
+        let mut ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                self.start(element);
+                ret = gst::StateChangeReturn::NoPreroll;
+            }
+            gst::StateChange::PlayingToPaused => {
+                ret = gst::StateChangeReturn::NoPreroll;
+            }
+            gst::StateChange::ReadyToNull => {
+                self.unprepare();
+            }
+            _ => (),
+        }
+
+        ret
+    }
+}
+
+struct ProxySrcStatic;
+
+impl ImplTypeStatic<Element> for ProxySrcStatic {
+    fn get_name(&self) -> &str {
+        "TsProxySrc"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        ProxySrc::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        ProxySrc::class_init(klass);
+    }
+}
+
+pub fn get_src_type() -> glib::Type {
+    let proxysrc_static = ProxySrcStatic;
+    register_type(proxysrc_static)
+}
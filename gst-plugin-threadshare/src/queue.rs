@@ -0,0 +1,446 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::{Arc, Mutex};
+use std::{u32, u64};
+
+use dataqueue::*;
+use iocontext::*;
+use srcpad::*;
+
+const DEFAULT_MAX_SIZE_BUFFERS: u32 = 200;
+const DEFAULT_MAX_SIZE_BYTES: u32 = 1024 * 1024;
+const DEFAULT_MAX_SIZE_TIME: u64 = gst::SECOND_VAL;
+const DEFAULT_CONTEXT: &str = "";
+
+#[derive(Debug, Clone)]
+struct Settings {
+    max_size_buffers: u32,
+    max_size_bytes: u32,
+    max_size_time: u64,
+    context: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            max_size_buffers: DEFAULT_MAX_SIZE_BUFFERS,
+            max_size_bytes: DEFAULT_MAX_SIZE_BYTES,
+            max_size_time: DEFAULT_MAX_SIZE_TIME,
+            context: DEFAULT_CONTEXT.into(),
+        }
+    }
+}
+
+static PROPERTIES: [Property; 4] = [
+    Property::UInt(
+        "max-size-buffers",
+        "Max Size Buffers",
+        "Maximum number of buffers to queue (0=unlimited)",
+        (0, u32::MAX),
+        DEFAULT_MAX_SIZE_BUFFERS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "max-size-bytes",
+        "Max Size Bytes",
+        "Maximum number of bytes to queue (0=unlimited)",
+        (0, u32::MAX),
+        DEFAULT_MAX_SIZE_BYTES,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "max-size-time",
+        "Max Size Time",
+        "Maximum number of nanoseconds to queue (0=unlimited)",
+        (0, u64::MAX - 1),
+        DEFAULT_MAX_SIZE_TIME,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "context",
+        "Context",
+        "Name of the thread-sharing context",
+        Some(DEFAULT_CONTEXT),
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+struct State {
+    context: IOContext,
+    queue: DataQueue,
+    last_ret: Arc<Mutex<gst::FlowReturn>>,
+    task: Option<CancelHandle>,
+}
+
+impl State {
+    fn start_task(&mut self, srcpad: &gst::Pad) {
+        *self.last_ret.lock().unwrap() = gst::FlowReturn::Ok;
+        self.queue.start();
+        self.task = Some(self.context.spawn_cancellable(forward_queue(
+            &self.queue,
+            srcpad,
+            self.last_ret.clone(),
+        )));
+    }
+
+    fn stop_task(&mut self) {
+        *self.last_ret.lock().unwrap() = gst::FlowReturn::Flushing;
+        self.queue.stop();
+        self.queue.clear();
+        self.task = None;
+    }
+}
+
+struct Queue {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl Queue {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "ts-queue",
+                gst::DebugColorFlags::empty(),
+                "Thread-sharing queue",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Thread-sharing queue",
+            "Generic",
+            "Simple data queue that pushes from a thread-sharing context",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        Queue::set_pad_functions(&sinkpad, &srcpad);
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let queue = element.get_impl().downcast_ref::<Queue>().unwrap();
+        element.catch_panic(fallback, |element| f(queue, element))
+    }
+
+    fn set_pad_functions(sinkpad: &gst::Pad, srcpad: &gst::Pad) {
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            Queue::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |queue, element| queue.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            Queue::catch_panic_pad_function(
+                parent,
+                || false,
+                |queue, element| queue.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            Queue::catch_panic_pad_function(
+                parent,
+                || false,
+                |queue, element| queue.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            Queue::catch_panic_pad_function(
+                parent,
+                || false,
+                |queue, element| queue.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            Queue::catch_panic_pad_function(
+                parent,
+                || false,
+                |queue, element| queue.src_query(pad, element, query),
+            )
+        });
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let (queue, last_ret) = match *self.state.lock().unwrap() {
+            None => return gst::FlowReturn::Flushing,
+            Some(ref state) => (state.queue.clone(), state.last_ret.clone()),
+        };
+
+        let last_ret = *last_ret.lock().unwrap();
+        if last_ret != gst::FlowReturn::Ok {
+            gst_debug!(self.cat, obj: pad, "Returning {:?}", last_ret);
+            return last_ret;
+        }
+
+        // The queue is leaky: blocking here would block the upstream context thread
+        if let Err(DataQueueItem::Buffer(buffer)) = queue.push(DataQueueItem::Buffer(buffer)) {
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "Queue is full, dropping buffer {:?}",
+                buffer
+            );
+        }
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::FlushStart(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.stop_task();
+                }
+                return self.srcpad.push_event(event);
+            }
+            EventView::FlushStop(..) => {
+                let res = self.srcpad.push_event(event);
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.start_task(&self.srcpad);
+                }
+                return res;
+            }
+            _ => (),
+        }
+
+        if !event.get_type().is_serialized() {
+            return self.srcpad.push_event(event);
+        }
+
+        let queue = match *self.state.lock().unwrap() {
+            None => return false,
+            Some(ref state) => state.queue.clone(),
+        };
+
+        // Events are never considered for the queue limits, this only fails when stopped
+        queue.push(DataQueueItem::Event(event)).is_ok()
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, _element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        self.srcpad.peer_query(query)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, _element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        self.sinkpad.peer_query(query)
+    }
+
+    fn prepare(&self, element: &Element) -> Result<(), gst::ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let context = IOContext::new(&settings.context).map_err(|err| {
+            gst_error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Failed to create IO context: {}", err]
+            )
+        })?;
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Using context {}",
+            context.get_name()
+        );
+
+        let queue = DataQueue::new(
+            Some(settings.max_size_buffers).and_then(|v| if v == 0 { None } else { Some(v) }),
+            Some(settings.max_size_bytes).and_then(|v| if v == 0 { None } else { Some(v) }),
+            Some(settings.max_size_time).and_then(|v| if v == 0 { None } else { Some(v) }),
+        );
+
+        *self.state.lock().unwrap() = Some(State {
+            context: context,
+            queue: queue,
+            last_ret: Arc::new(Mutex::new(gst::FlowReturn::Flushing)),
+            task: None,
+        });
+
+        Ok(())
+    }
+}
+
+impl ObjectImpl<Element> for Queue {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("max-size-buffers", ..) => {
+                settings.max_size_buffers = value.get().unwrap();
+            }
+            Property::UInt("max-size-bytes", ..) => {
+                settings.max_size_bytes = value.get().unwrap();
+            }
+            Property::UInt64("max-size-time", ..) => {
+                settings.max_size_time = value.get().unwrap();
+            }
+            Property::String("context", ..) => {
+                settings.context = value.get().unwrap_or_else(|| DEFAULT_CONTEXT.into());
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("max-size-buffers", ..) => Ok(settings.max_size_buffers.to_value()),
+            Property::UInt("max-size-bytes", ..) => Ok(settings.max_size_bytes.to_value()),
+            Property::UInt64("max-size-time", ..) => Ok(settings.max_size_time.to_value()),
+            Property::String("context", ..) => Ok(settings.context.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for Queue {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::NullToReady => {
+                if let Err(err) = self.prepare(element) {
+                    element.post_error_message(&err);
+                    return gst::StateChangeReturn::Failure;
+                }
+            }
+            gst::StateChange::PausedToReady => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.stop_task();
+                }
+            }
+            _ => (),
+        }
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.start_task(&self.srcpad);
+                }
+            }
+            gst::StateChange::ReadyToNull => {
+                *self.state.lock().unwrap() = None;
+            }
+            _ => (),
+        }
+
+        ret
+    }
+}
+
+struct QueueStatic;
+
+impl ImplTypeStatic<Element> for QueueStatic {
+    fn get_name(&self) -> &str {
+        "TsQueue"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        Queue::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        Queue::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let queue_static = QueueStatic;
+    register_type(queue_static)
+}
@@ -0,0 +1,140 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::sync::{Arc, Mutex};
+
+use futures::{Future, Stream};
+
+use gst;
+use gst::prelude::*;
+
+use dataqueue::*;
+
+/// Pushes buffers from a source element's IO context, sending the initial stream-start, caps
+/// and segment events before the first buffer.
+pub struct SrcPadPusher {
+    pad: gst::Pad,
+    caps: Option<gst::Caps>,
+    need_initial_events: bool,
+}
+
+impl SrcPadPusher {
+    pub fn new(pad: &gst::Pad, caps: Option<gst::Caps>) -> Self {
+        SrcPadPusher {
+            pad: pad.clone(),
+            caps: caps,
+            need_initial_events: true,
+        }
+    }
+
+    pub fn get_pad(&self) -> &gst::Pad {
+        &self.pad
+    }
+
+    /// Current running time of the element, or `CLOCK_TIME_NONE` without a clock.
+    pub fn get_running_time(&self) -> gst::ClockTime {
+        let element = match self.pad.get_parent_element() {
+            None => return gst::CLOCK_TIME_NONE,
+            Some(element) => element,
+        };
+
+        let now = match element.get_clock() {
+            None => return gst::CLOCK_TIME_NONE,
+            Some(clock) => clock.get_time(),
+        };
+        let base_time = element.get_base_time();
+
+        match (now.0, base_time.0) {
+            (Some(now), Some(base_time)) if now >= base_time => {
+                gst::ClockTime(Some(now - base_time))
+            }
+            _ => gst::CLOCK_TIME_NONE,
+        }
+    }
+
+    fn push_initial_events(&mut self) {
+        self.need_initial_events = false;
+
+        let element = self.pad.get_parent_element().unwrap();
+        let stream_id = self.pad.create_stream_id(&element, None).unwrap();
+        self.pad
+            .push_event(gst::Event::new_stream_start(&stream_id).build());
+
+        if let Some(ref caps) = self.caps {
+            self.pad.push_event(gst::Event::new_caps(caps).build());
+        }
+
+        let segment = gst::FormattedSegment::<gst::ClockTime>::default();
+        self.pad.push_event(gst::Event::new_segment(&segment).build());
+    }
+
+    pub fn push(&mut self, buffer: gst::Buffer) -> gst::FlowReturn {
+        if self.need_initial_events {
+            self.push_initial_events();
+        }
+
+        self.pad.push(buffer)
+    }
+
+    /// Posts an error message for fatal flow returns and sends EOS, like `GstBaseSrc` does.
+    pub fn handle_flow_return(&mut self, ret: gst::FlowReturn) {
+        match ret {
+            gst::FlowReturn::Ok | gst::FlowReturn::Flushing => (),
+            gst::FlowReturn::Eos => {
+                self.pad.push_event(gst::Event::new_eos().build());
+            }
+            _ => {
+                post_flow_error(&self.pad, ret);
+                self.pad.push_event(gst::Event::new_eos().build());
+            }
+        }
+    }
+}
+
+fn post_flow_error(pad: &gst::Pad, ret: gst::FlowReturn) {
+    if let Some(element) = pad.get_parent_element() {
+        gst_element_error!(
+            element,
+            gst::StreamError::Failed,
+            ("Internal data flow error."),
+            ["streaming task paused, reason {:?}", ret]
+        );
+    }
+}
+
+/// Future that pushes all items of the queue to the pad until the queue is stopped or
+/// downstream returns an error, which is then stored in `last_ret`.
+pub fn forward_queue(
+    queue: &DataQueue,
+    pad: &gst::Pad,
+    last_ret: Arc<Mutex<gst::FlowReturn>>,
+) -> Box<Future<Item = (), Error = ()> + Send> {
+    let pad = pad.clone();
+
+    Box::new(queue.stream().for_each(move |item| {
+        let ret = match item {
+            DataQueueItem::Buffer(buffer) => pad.push(buffer),
+            DataQueueItem::Event(event) => {
+                pad.push_event(event);
+                gst::FlowReturn::Ok
+            }
+        };
+
+        if ret == gst::FlowReturn::Ok {
+            return Ok(());
+        }
+
+        match ret {
+            gst::FlowReturn::Flushing | gst::FlowReturn::Eos => (),
+            _ => post_flow_error(&pad, ret),
+        }
+        *last_ret.lock().unwrap() = ret;
+
+        Err(())
+    }))
+}
@@ -0,0 +1,369 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::{u16, u32};
+
+use futures::{future, stream, Async, Future, Stream};
+use tokio::io::AsyncRead;
+use tokio::net;
+
+use iocontext::*;
+use srcpad::*;
+
+const DEFAULT_ADDRESS: Option<&str> = Some("127.0.0.1");
+const DEFAULT_PORT: u32 = 5000;
+const DEFAULT_BLOCKSIZE: u32 = 4096;
+const DEFAULT_CONTEXT: &str = "";
+
+#[derive(Debug, Clone)]
+struct Settings {
+    address: Option<String>,
+    port: u32,
+    caps: Option<gst::Caps>,
+    blocksize: u32,
+    context: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            address: DEFAULT_ADDRESS.map(String::from),
+            port: DEFAULT_PORT,
+            caps: None,
+            blocksize: DEFAULT_BLOCKSIZE,
+            context: DEFAULT_CONTEXT.into(),
+        }
+    }
+}
+
+static PROPERTIES: [Property; 5] = [
+    Property::String(
+        "address",
+        "Address",
+        "Address to connect to",
+        DEFAULT_ADDRESS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "port",
+        "Port",
+        "Port to connect to",
+        (0, u16::MAX as u32),
+        DEFAULT_PORT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boxed(
+        "caps",
+        "Caps",
+        "Caps to use",
+        gst::Caps::static_type,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "blocksize",
+        "Blocksize",
+        "Size in bytes to read per buffer",
+        (1, u32::MAX),
+        DEFAULT_BLOCKSIZE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "context",
+        "Context",
+        "Name of the thread-sharing context",
+        Some(DEFAULT_CONTEXT),
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+struct State {
+    context: IOContext,
+    addr: SocketAddr,
+    task: Option<CancelHandle>,
+}
+
+struct TcpClientSrc {
+    cat: gst::DebugCategory,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl TcpClientSrc {
+    fn new(_element: &Element, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "ts-tcpclientsrc",
+                gst::DebugColorFlags::empty(),
+                "Thread-sharing TCP client source",
+            ),
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Thread-sharing TCP client source",
+            "Source/Network",
+            "Receives data as a client over the network via TCP",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, srcpad);
+        Box::new(imp)
+    }
+
+    fn prepare(&self, element: &Element) -> Result<(), gst::ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let context = IOContext::new(&settings.context).map_err(|err| {
+            gst_error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Failed to create IO context: {}", err]
+            )
+        })?;
+
+        let addr: IpAddr = match settings.address {
+            None => {
+                return Err(gst_error_msg!(
+                    gst::ResourceError::Settings,
+                    ["No address set"]
+                ))
+            }
+            Some(ref addr) => addr.parse().map_err(|err| {
+                gst_error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Invalid address '{}': {}", addr, err]
+                )
+            })?,
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Connecting to {}:{} in context {}",
+            addr,
+            settings.port,
+            context.get_name()
+        );
+
+        *self.state.lock().unwrap() = Some(State {
+            context: context,
+            addr: SocketAddr::new(addr, settings.port as u16),
+            task: None,
+        });
+
+        Ok(())
+    }
+
+    fn start(&self, element: &Element) {
+        let settings = self.settings.lock().unwrap().clone();
+        let mut state = self.state.lock().unwrap();
+        let state = match *state {
+            None => return,
+            Some(ref mut state) => state,
+        };
+
+        gst_debug!(self.cat, obj: element, "Starting");
+
+        // The connection is only established from the IO context so that the socket is
+        // registered with the reactor of that thread
+        let blocksize = settings.blocksize as usize;
+        let packets = net::TcpStream::connect(&state.addr)
+            .map(move |mut socket| {
+                stream::poll_fn(move || -> io::Result<Async<Option<Vec<u8>>>> {
+                    let mut buf = vec![0; blocksize];
+                    let len = try_ready!(socket.poll_read(&mut buf));
+                    if len == 0 {
+                        return Ok(Async::Ready(None));
+                    }
+                    buf.truncate(len);
+                    Ok(Async::Ready(Some(buf)))
+                })
+            })
+            .flatten_stream();
+
+        let mut pusher = SrcPadPusher::new(&self.srcpad, settings.caps);
+        let element_clone = element.clone();
+        let cat = self.cat.clone();
+        let cat_eos = self.cat.clone();
+        let srcpad = self.srcpad.clone();
+        let future = packets
+            .map_err(move |err| {
+                gst_element_error!(
+                    element_clone,
+                    gst::ResourceError::Read,
+                    ["Failed to read: {}", err]
+                );
+            })
+            .for_each(move |data| {
+                let mut buffer = gst::Buffer::from_mut_slice(data).unwrap();
+                buffer
+                    .get_mut()
+                    .unwrap()
+                    .set_pts(pusher.get_running_time());
+
+                match pusher.push(buffer) {
+                    gst::FlowReturn::Ok => future::ok(()),
+                    ret => {
+                        gst_debug!(cat, obj: pusher.get_pad(), "Stopping: {:?}", ret);
+                        pusher.handle_flow_return(ret);
+                        future::err(())
+                    }
+                }
+            })
+            .and_then(move |_| {
+                gst_debug!(cat_eos, obj: &srcpad, "Connection closed");
+                srcpad.push_event(gst::Event::new_eos().build());
+                Ok(())
+            });
+
+        state.task = Some(state.context.spawn_cancellable(future));
+    }
+
+    fn stop(&self, element: &Element) {
+        gst_debug!(self.cat, obj: element, "Stopping");
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.task = None;
+        }
+    }
+}
+
+impl ObjectImpl<Element> for TcpClientSrc {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("address", ..) => {
+                settings.address = value.get();
+            }
+            Property::UInt("port", ..) => {
+                settings.port = value.get().unwrap();
+            }
+            Property::Boxed("caps", ..) => {
+                settings.caps = value.get();
+            }
+            Property::UInt("blocksize", ..) => {
+                settings.blocksize = value.get().unwrap();
+            }
+            Property::String("context", ..) => {
+                settings.context = value.get().unwrap_or_else(|| DEFAULT_CONTEXT.into());
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("address", ..) => Ok(settings.address.to_value()),
+            Property::UInt("port", ..) => Ok(settings.port.to_value()),
+            Property::Boxed("caps", ..) => Ok(settings.caps.to_value()),
+            Property::UInt("blocksize", ..) => Ok(settings.blocksize.to_value()),
+            Property::String("context", ..) => Ok(settings.context.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for TcpClientSrc {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::NullToReady => {
+                if let Err(err) = self.prepare(element) {
+                    element.post_error_message(&err);
+                    return gst::StateChangeReturn::Failure;
+                }
+            }
+            gst::StateChange::PlayingToPaused => {
+                self.stop(element);
+            }
+            _ => (),
+        }
+
+        let mut ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        match transition {
+            gst::StateChange::ReadyToPaused | gst::StateChange::PlayingToPaused => {
+                ret = gst::StateChangeReturn::NoPreroll;
+            }
+            gst::StateChange::PausedToPlaying => {
+                self.start(element);
+            }
+            gst::StateChange::ReadyToNull => {
+                *self.state.lock().unwrap() = None;
+            }
+            _ => (),
+        }
+
+        ret
+    }
+}
+
+struct TcpClientSrcStatic;
+
+impl ImplTypeStatic<Element> for TcpClientSrcStatic {
+    fn get_name(&self) -> &str {
+        "TsTcpClientSrc"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        TcpClientSrc::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        TcpClientSrc::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let tcpclientsrc_static = TcpClientSrcStatic;
+    register_type(tcpclientsrc_static)
+}
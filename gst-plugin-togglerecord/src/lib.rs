@@ -24,21 +24,24 @@ extern crate gst_plugin;
 extern crate gstreamer as gst;
 extern crate gstreamer_video as gst_video;
 
+use gst_plugin::registration::*;
+
 mod togglerecord;
 
 fn plugin_init(plugin: &gst::Plugin) -> bool {
-    togglerecord::register(plugin);
-    true
+    ElementRegistration::new(plugin)
+        .element("togglerecord", RANK_NONE, togglerecord::get_type())
+        .register()
 }
 
 plugin_define!(
-    b"togglerecord\0",
-    b"Toggle Record Plugin\0",
+    "togglerecord",
+    "Toggle Record Plugin",
     plugin_init,
-    b"0.1.0\0",
-    b"LGPL\0",
-    b"togglerecord\0",
-    b"togglerecord\0",
-    b"https://github.com/sdroege/gst-plugin-rs\0",
-    b"2017-12-04\0"
+    env!("CARGO_PKG_VERSION"),
+    "LGPL",
+    "togglerecord",
+    "togglerecord",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2017-12-04"
 );
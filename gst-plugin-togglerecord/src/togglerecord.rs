@@ -1396,8 +1396,7 @@ impl ImplTypeStatic<Element> for ToggleRecordStatic {
     }
 }
 
-pub fn register(plugin: &gst::Plugin) {
+pub fn get_type() -> glib::Type {
     let togglerecord_static = ToggleRecordStatic;
-    let type_ = register_type(togglerecord_static);
-    gst::Element::register(plugin, "togglerecord", 0, type_);
+    register_type(togglerecord_static)
 }
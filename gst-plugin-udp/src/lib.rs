@@ -0,0 +1,81 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate futures;
+extern crate glib;
+#[macro_use]
+extern crate gst_plugin;
+extern crate gst_plugin_simple;
+#[macro_use]
+extern crate gstreamer as gst;
+extern crate gstreamer_base as gst_base;
+#[cfg(target_os = "linux")]
+extern crate libc;
+extern crate net2;
+extern crate tokio;
+extern crate url;
+
+use gst_plugin_simple::async_source::*;
+use gst_plugin_simple::sink::*;
+use gst_plugin::registration::*;
+
+mod socket;
+mod udpsrc;
+mod udpsink;
+
+use udpsrc::UdpSrc;
+use udpsink::UdpSink;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    let source_registered = async_source_register(
+        plugin,
+        AsyncSourceInfo {
+            name: "rsudpsrc".into(),
+            long_name: "UDP Source".into(),
+            description: "Receives UDP packets, optionally from a multicast group".into(),
+            classification: "Source/Network".into(),
+            author: "Sebastian Dröge <sebastian@centricular.com>".into(),
+            rank: RANK_NONE,
+            create_instance: UdpSrc::new_boxed,
+            protocols: vec!["udp".into()],
+            live: true,
+            properties: &udpsrc::PROPERTIES,
+        },
+    );
+
+    let sink_registered = sink_register(
+        plugin,
+        SinkInfo {
+            name: "rsudpsink".into(),
+            long_name: "UDP Sink".into(),
+            description: "Sends buffers as UDP packets".into(),
+            classification: "Sink/Network".into(),
+            author: "Sebastian Dröge <sebastian@centricular.com>".into(),
+            rank: RANK_NONE,
+            create_instance: UdpSink::new_boxed,
+            protocols: vec!["udp".into()],
+            properties: &udpsink::PROPERTIES,
+        },
+    );
+
+    source_registered && sink_registered
+}
+
+plugin_define!(
+    "rsudp",
+    "Rust UDP Plugin",
+    plugin_init,
+    "1.0",
+    "MIT/X11",
+    "rsudp",
+    "rsudp",
+    "https://github.com/sdroege/rsplugin",
+    "2018-01-15"
+);
@@ -0,0 +1,187 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Socket setup shared by the source and the sink, for the options that can't be set with the
+// standard library alone
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+
+use net2::{UdpBuilder, UdpSocketExt};
+use url::{Host, Url};
+
+/// Address and port of a `udp://host:port` URI.
+pub fn get_socket_addr(uri: &Url) -> Result<SocketAddr, String> {
+    let port = uri
+        .port()
+        .ok_or_else(|| format!("No port in URI '{}'", uri.as_str()))?;
+
+    match uri.host() {
+        Some(Host::Ipv4(addr)) => Ok(SocketAddr::new(IpAddr::V4(addr), port)),
+        Some(Host::Ipv6(addr)) => Ok(SocketAddr::new(IpAddr::V6(addr), port)),
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_left_matches('[').trim_right_matches(']');
+            (domain, port)
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .ok_or_else(|| format!("Failed to resolve '{}'", domain))
+        }
+        None => Err(format!("No host in URI '{}'", uri.as_str())),
+    }
+}
+
+/// Parses an optional address property, empty strings are the same as unset.
+pub fn parse_addr(addr: &Option<String>) -> Result<Option<IpAddr>, String> {
+    match *addr {
+        Some(ref addr) if !addr.is_empty() => addr
+            .parse()
+            .map(Some)
+            .map_err(|err| format!("Invalid address '{}': {}", addr, err)),
+        _ => Ok(None),
+    }
+}
+
+/// Binds a new socket, optionally allowing other sockets to bind to the same address.
+pub fn bind(addr: &SocketAddr, reuse: bool) -> io::Result<UdpSocket> {
+    let builder = match *addr {
+        SocketAddr::V4(..) => UdpBuilder::new_v4()?,
+        SocketAddr::V6(..) => UdpBuilder::new_v6()?,
+    };
+
+    builder.reuse_address(reuse)?;
+    builder.bind(addr)
+}
+
+/// Sets the kernel receive or send buffer size, 0 keeps the system default.
+pub fn set_buffer_size(socket: &UdpSocket, size: u32, recv: bool) -> io::Result<()> {
+    match (size, recv) {
+        (0, _) => Ok(()),
+        (size, true) => socket.set_recv_buffer_size(size as usize),
+        (size, false) => socket.set_send_buffer_size(size as usize),
+    }
+}
+
+/// Joins `group`, only for packets from `source` if given (source-specific multicast). The
+/// interface is selected by its address for IPv4, IPv6 always uses the default interface.
+pub fn join_multicast(
+    socket: &UdpSocket,
+    group: &IpAddr,
+    iface: Option<&IpAddr>,
+    source: Option<&IpAddr>,
+) -> io::Result<()> {
+    let iface_v4 = match iface {
+        None => Ipv4Addr::new(0, 0, 0, 0),
+        Some(&IpAddr::V4(iface)) => iface,
+        Some(&IpAddr::V6(..)) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "IPv6 multicast interface addresses are not supported",
+            ))
+        }
+    };
+
+    match (*group, source) {
+        (IpAddr::V4(ref group), None) => socket.join_multicast_v4(group, &iface_v4),
+        (IpAddr::V4(ref group), Some(&IpAddr::V4(ref source))) => {
+            join_source_multicast_v4(socket, group, &iface_v4, source)
+        }
+        (IpAddr::V6(ref group), None) => socket.join_multicast_v6(group, 0),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Source-specific multicast is only supported for IPv4",
+        )),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn join_source_multicast_v4(
+    socket: &UdpSocket,
+    group: &Ipv4Addr,
+    iface: &Ipv4Addr,
+    source: &Ipv4Addr,
+) -> io::Result<()> {
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+    use libc;
+
+    // struct ip_mreq_source from <netinet/in.h>, not provided by libc
+    #[repr(C)]
+    struct IpMreqSource {
+        imr_multiaddr: libc::in_addr,
+        imr_interface: libc::in_addr,
+        imr_sourceaddr: libc::in_addr,
+    }
+    const IP_ADD_SOURCE_MEMBERSHIP: libc::c_int = 39;
+
+    let to_in_addr = |addr: &Ipv4Addr| libc::in_addr {
+        s_addr: u32::from(*addr).to_be(),
+    };
+    let mreq = IpMreqSource {
+        imr_multiaddr: to_in_addr(group),
+        imr_interface: to_in_addr(iface),
+        imr_sourceaddr: to_in_addr(source),
+    };
+
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            IP_ADD_SOURCE_MEMBERSHIP,
+            &mreq as *const _ as *const libc::c_void,
+            mem::size_of::<IpMreqSource>() as libc::socklen_t,
+        )
+    };
+
+    if res == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn join_source_multicast_v4(
+    _socket: &UdpSocket,
+    _group: &Ipv4Addr,
+    _iface: &Ipv4Addr,
+    _source: &Ipv4Addr,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Source-specific multicast not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_addr() {
+        let uri = Url::parse("udp://239.1.2.3:5004").unwrap();
+        assert_eq!(get_socket_addr(&uri), Ok("239.1.2.3:5004".parse().unwrap()));
+
+        let uri = Url::parse("udp://[ff0e::1]:5004").unwrap();
+        assert_eq!(get_socket_addr(&uri), Ok("[ff0e::1]:5004".parse().unwrap()));
+
+        let uri = Url::parse("udp://239.1.2.3").unwrap();
+        assert!(get_socket_addr(&uri).is_err());
+    }
+
+    #[test]
+    fn test_parse_addr() {
+        assert_eq!(parse_addr(&None), Ok(None));
+        assert_eq!(parse_addr(&Some(String::new())), Ok(None));
+        assert_eq!(
+            parse_addr(&Some(String::from("10.0.0.1"))),
+            Ok(Some("10.0.0.1".parse().unwrap()))
+        );
+        assert!(parse_addr(&Some(String::from("foo"))).is_err());
+    }
+}
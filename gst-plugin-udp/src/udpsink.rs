@@ -0,0 +1,302 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::u32;
+
+use net2::UdpSocketExt;
+use url::Url;
+
+use gst_plugin::properties::*;
+use gst_plugin_simple::error::*;
+use gst_plugin_simple::sink::*;
+use gst_plugin_simple::UriValidator;
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use socket;
+
+const DEFAULT_MULTICAST_IFACE: Option<&str> = None;
+const DEFAULT_MULTICAST_TTL: u32 = 1;
+const DEFAULT_MULTICAST_LOOP: bool = true;
+const DEFAULT_BIND_PORT: u32 = 0;
+const DEFAULT_BUFFER_SIZE: u32 = 0;
+const DEFAULT_REUSE: bool = true;
+
+pub static PROPERTIES: [Property; 6] = [
+    Property::String(
+        "multicast-iface",
+        "Multicast Interface",
+        "Address of the IPv4 interface from which multicast packets are sent",
+        DEFAULT_MULTICAST_IFACE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "multicast-ttl",
+        "Multicast TTL",
+        "Time to live (hop limit for IPv6) of multicast packets",
+        (0, 255),
+        DEFAULT_MULTICAST_TTL,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "multicast-loop",
+        "Multicast Loop",
+        "Deliver multicast packets to receivers on the same host",
+        DEFAULT_MULTICAST_LOOP,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "bind-port",
+        "Bind Port",
+        "Local port to send from (0 = any)",
+        (0, 65535),
+        DEFAULT_BIND_PORT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "buffer-size",
+        "Buffer Size",
+        "Size of the kernel send buffer in bytes (0 = system default)",
+        (0, u32::MAX),
+        DEFAULT_BUFFER_SIZE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "reuse",
+        "Reuse",
+        "Allow other sockets to bind to the same local address and port",
+        DEFAULT_REUSE,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    multicast_iface: Option<String>,
+    multicast_ttl: u32,
+    multicast_loop: bool,
+    bind_port: u32,
+    buffer_size: u32,
+    reuse: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            multicast_iface: DEFAULT_MULTICAST_IFACE.map(String::from),
+            multicast_ttl: DEFAULT_MULTICAST_TTL,
+            multicast_loop: DEFAULT_MULTICAST_LOOP,
+            bind_port: DEFAULT_BIND_PORT,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            reuse: DEFAULT_REUSE,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum StreamingState {
+    Stopped,
+    Started { socket: UdpSocket, addr: SocketAddr },
+}
+
+#[derive(Debug)]
+pub struct UdpSink {
+    streaming_state: StreamingState,
+    cat: gst::DebugCategory,
+    settings: Settings,
+}
+
+impl UdpSink {
+    pub fn new(_sink: &BaseSink) -> UdpSink {
+        UdpSink {
+            streaming_state: StreamingState::Stopped,
+            cat: gst::DebugCategory::new(
+                "rsudpsink",
+                gst::DebugColorFlags::empty(),
+                "Rust UDP sink",
+            ),
+            settings: Settings::default(),
+        }
+    }
+
+    pub fn new_boxed(sink: &BaseSink) -> Box<SinkImpl> {
+        Box::new(UdpSink::new(sink))
+    }
+
+    fn create_socket(&self, addr: &SocketAddr) -> Result<UdpSocket, gst::ErrorMessage> {
+        let settings = &self.settings;
+
+        let iface = socket::parse_addr(&settings.multicast_iface)
+            .map_err(|err| gst_error_msg!(gst::ResourceError::Settings, ["{}", err]))?;
+
+        let bind_addr = match *addr {
+            SocketAddr::V4(..) => SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+                settings.bind_port as u16,
+            ),
+            SocketAddr::V6(..) => SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)),
+                settings.bind_port as u16,
+            ),
+        };
+
+        let sock = socket::bind(&bind_addr, settings.reuse).map_err(|err| {
+            gst_error_msg!(
+                gst::ResourceError::OpenWrite,
+                ["Failed to bind socket to {}: {}", bind_addr, err]
+            )
+        })?;
+
+        socket::set_buffer_size(&sock, settings.buffer_size, false).map_err(|err| {
+            gst_error_msg!(
+                gst::ResourceError::Settings,
+                ["Failed to set buffer size: {}", err]
+            )
+        })?;
+
+        let res = match (addr.ip(), iface) {
+            (IpAddr::V4(ip), iface) if ip.is_multicast() => sock
+                .set_multicast_ttl_v4(settings.multicast_ttl)
+                .and_then(|_| sock.set_multicast_loop_v4(settings.multicast_loop))
+                .and_then(|_| match iface {
+                    Some(IpAddr::V4(ref iface)) => sock.set_multicast_if_v4(iface),
+                    _ => Ok(()),
+                }),
+            (IpAddr::V6(ip), _) if ip.is_multicast() => sock
+                .set_multicast_hops_v6(settings.multicast_ttl)
+                .and_then(|_| sock.set_multicast_loop_v6(settings.multicast_loop)),
+            _ => Ok(()),
+        };
+        res.map_err(|err| {
+            gst_error_msg!(
+                gst::ResourceError::Settings,
+                ["Failed to configure multicast: {}", err]
+            )
+        })?;
+
+        Ok(sock)
+    }
+}
+
+fn validate_uri(uri: &Url) -> Result<(), UriError> {
+    socket::get_socket_addr(uri)
+        .map(|_| ())
+        .map_err(|err| UriError::new(gst::URIError::BadUri, err))
+}
+
+impl SinkImpl for UdpSink {
+    fn uri_validator(&self) -> Box<UriValidator> {
+        Box::new(validate_uri)
+    }
+
+    fn start(&mut self, sink: &BaseSink, uri: Url) -> Result<(), gst::ErrorMessage> {
+        if let StreamingState::Started { .. } = self.streaming_state {
+            return Err(gst_error_msg!(
+                gst::LibraryError::Failed,
+                ["Sink already started"]
+            ));
+        }
+
+        let addr = socket::get_socket_addr(&uri)
+            .map_err(|err| gst_error_msg!(gst::ResourceError::Settings, ["{}", err]))?;
+
+        let socket = self.create_socket(&addr)?;
+
+        gst_debug!(self.cat, obj: sink, "Sending to {}", addr);
+
+        self.streaming_state = StreamingState::Started {
+            socket: socket,
+            addr: addr,
+        };
+
+        Ok(())
+    }
+
+    fn stop(&mut self, _sink: &BaseSink) -> Result<(), gst::ErrorMessage> {
+        self.streaming_state = StreamingState::Stopped;
+        Ok(())
+    }
+
+    fn render(&mut self, sink: &BaseSink, buffer: &gst::BufferRef) -> Result<(), FlowError> {
+        let cat = self.cat;
+
+        gst_trace!(cat, obj: sink, "Rendering {:?}", buffer);
+
+        let (socket, addr) = match self.streaming_state {
+            StreamingState::Started {
+                ref socket,
+                ref addr,
+            } => (socket, addr),
+            StreamingState::Stopped => {
+                return Err(FlowError::Error(gst_error_msg!(
+                    gst::LibraryError::Failed,
+                    ["Not started yet"]
+                )));
+            }
+        };
+
+        let map = match buffer.map_readable() {
+            None => {
+                return Err(FlowError::Error(gst_error_msg!(
+                    gst::LibraryError::Failed,
+                    ["Failed to map buffer"]
+                )));
+            }
+            Some(map) => map,
+        };
+
+        socket.send_to(map.as_slice(), addr).map_err(|err| {
+            gst_error!(cat, obj: sink, "Failed to send: {}", err);
+            FlowError::Error(gst_error_msg!(
+                gst::ResourceError::Write,
+                ["Failed to send to {}: {}", addr, err]
+            ))
+        })?;
+
+        Ok(())
+    }
+
+    fn set_property(&mut self, _sink: &BaseSink, property: &Property, value: &glib::Value) {
+        match *property {
+            Property::String("multicast-iface", ..) => {
+                self.settings.multicast_iface = value.get();
+            }
+            Property::UInt("multicast-ttl", ..) => {
+                self.settings.multicast_ttl = value.get().unwrap();
+            }
+            Property::Boolean("multicast-loop", ..) => {
+                self.settings.multicast_loop = value.get().unwrap();
+            }
+            Property::UInt("bind-port", ..) => {
+                self.settings.bind_port = value.get().unwrap();
+            }
+            Property::UInt("buffer-size", ..) => {
+                self.settings.buffer_size = value.get().unwrap();
+            }
+            Property::Boolean("reuse", ..) => {
+                self.settings.reuse = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _sink: &BaseSink, property: &Property) -> Result<glib::Value, ()> {
+        match *property {
+            Property::String("multicast-iface", ..) => Ok(self.settings.multicast_iface.to_value()),
+            Property::UInt("multicast-ttl", ..) => Ok(self.settings.multicast_ttl.to_value()),
+            Property::Boolean("multicast-loop", ..) => Ok(self.settings.multicast_loop.to_value()),
+            Property::UInt("bind-port", ..) => Ok(self.settings.bind_port.to_value()),
+            Property::UInt("buffer-size", ..) => Ok(self.settings.buffer_size.to_value()),
+            Property::Boolean("reuse", ..) => Ok(self.settings.reuse.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
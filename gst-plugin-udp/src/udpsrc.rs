@@ -0,0 +1,263 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::u32;
+
+use futures::{stream, Async};
+use tokio::net;
+use tokio::reactor::Handle;
+use url::Url;
+
+use gst_plugin::properties::*;
+use gst_plugin_simple::error::*;
+use gst_plugin_simple::async_source::*;
+use gst_plugin_simple::UriValidator;
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_base::prelude::*;
+
+use socket;
+
+const DEFAULT_MULTICAST_IFACE: Option<&str> = None;
+const DEFAULT_MULTICAST_SOURCE: Option<&str> = None;
+const DEFAULT_BUFFER_SIZE: u32 = 0;
+const DEFAULT_REUSE: bool = true;
+const DEFAULT_MTU: u32 = 1500;
+
+pub static PROPERTIES: [Property; 5] = [
+    Property::String(
+        "multicast-iface",
+        "Multicast Interface",
+        "Address of the IPv4 interface on which the multicast group is joined",
+        DEFAULT_MULTICAST_IFACE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "multicast-source",
+        "Multicast Source",
+        "Only receive multicast packets sent from this address (source-specific multicast)",
+        DEFAULT_MULTICAST_SOURCE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "buffer-size",
+        "Buffer Size",
+        "Size of the kernel receive buffer in bytes (0 = system default)",
+        (0, u32::MAX),
+        DEFAULT_BUFFER_SIZE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "reuse",
+        "Reuse",
+        "Allow other sockets to bind to the same address and port",
+        DEFAULT_REUSE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "mtu",
+        "MTU",
+        "Maximum size of a received packet, larger packets are truncated",
+        (1, 65535),
+        DEFAULT_MTU,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    multicast_iface: Option<String>,
+    multicast_source: Option<String>,
+    buffer_size: u32,
+    reuse: bool,
+    mtu: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            multicast_iface: DEFAULT_MULTICAST_IFACE.map(String::from),
+            multicast_source: DEFAULT_MULTICAST_SOURCE.map(String::from),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            reuse: DEFAULT_REUSE,
+            mtu: DEFAULT_MTU,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UdpSrc {
+    cat: gst::DebugCategory,
+    settings: Settings,
+}
+
+impl UdpSrc {
+    pub fn new(src: &BaseSrc) -> UdpSrc {
+        // Packets are timestamped with the running time when they were received
+        src.set_do_timestamp(true);
+
+        UdpSrc {
+            cat: gst::DebugCategory::new(
+                "rsudpsrc",
+                gst::DebugColorFlags::empty(),
+                "Rust UDP source",
+            ),
+            settings: Settings::default(),
+        }
+    }
+
+    pub fn new_boxed(src: &BaseSrc) -> Box<AsyncSourceImpl> {
+        Box::new(UdpSrc::new(src))
+    }
+
+    fn create_socket(&self, addr: &SocketAddr) -> Result<net::UdpSocket, gst::ErrorMessage> {
+        let settings = &self.settings;
+
+        let iface = socket::parse_addr(&settings.multicast_iface)
+            .map_err(|err| gst_error_msg!(gst::ResourceError::Settings, ["{}", err]))?;
+        let source = socket::parse_addr(&settings.multicast_source)
+            .map_err(|err| gst_error_msg!(gst::ResourceError::Settings, ["{}", err]))?;
+
+        // Multicast groups are joined on the any address of the same family
+        let bind_addr = match addr.ip() {
+            IpAddr::V4(ip) if ip.is_multicast() => {
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), addr.port())
+            }
+            IpAddr::V6(ip) if ip.is_multicast() => SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)),
+                addr.port(),
+            ),
+            _ => *addr,
+        };
+
+        let sock = socket::bind(&bind_addr, settings.reuse).map_err(|err| {
+            gst_error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Failed to bind socket to {}: {}", bind_addr, err]
+            )
+        })?;
+
+        socket::set_buffer_size(&sock, settings.buffer_size, true).map_err(|err| {
+            gst_error_msg!(
+                gst::ResourceError::Settings,
+                ["Failed to set buffer size: {}", err]
+            )
+        })?;
+
+        if addr.ip().is_multicast() {
+            socket::join_multicast(&sock, &addr.ip(), iface.as_ref(), source.as_ref()).map_err(
+                |err| {
+                    gst_error_msg!(
+                        gst::ResourceError::OpenRead,
+                        ["Failed to join multicast group {}: {}", addr.ip(), err]
+                    )
+                },
+            )?;
+        } else if source.is_some() {
+            return Err(gst_error_msg!(
+                gst::ResourceError::Settings,
+                [
+                    "Multicast source set for non-multicast address {}",
+                    addr.ip()
+                ]
+            ));
+        }
+
+        net::UdpSocket::from_std(sock, &Handle::default()).map_err(|err| {
+            gst_error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Failed to register socket: {}", err]
+            )
+        })
+    }
+}
+
+fn validate_uri(uri: &Url) -> Result<(), UriError> {
+    socket::get_socket_addr(uri)
+        .map(|_| ())
+        .map_err(|err| UriError::new(gst::URIError::BadUri, err))
+}
+
+impl AsyncSourceImpl for UdpSrc {
+    fn uri_validator(&self) -> Box<UriValidator> {
+        Box::new(validate_uri)
+    }
+
+    fn start(&mut self, src: &BaseSrc, uri: Url) -> Result<BufferStream, gst::ErrorMessage> {
+        let addr = socket::get_socket_addr(&uri)
+            .map_err(|err| gst_error_msg!(gst::ResourceError::Settings, ["{}", err]))?;
+
+        let mut sock = self.create_socket(&addr)?;
+
+        gst_debug!(self.cat, obj: src, "Listening on {}", addr);
+
+        let mtu = self.settings.mtu as usize;
+        let packets = stream::poll_fn(move || {
+            let mut buf = vec![0; mtu];
+            let len = match sock.poll_recv(&mut buf) {
+                Ok(Async::Ready(len)) => len,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => {
+                    return Err(FlowError::Error(gst_error_msg!(
+                        gst::ResourceError::Read,
+                        ["Failed to receive: {}", err]
+                    )))
+                }
+            };
+            buf.truncate(len);
+
+            Ok(Async::Ready(Some(
+                gst::Buffer::from_mut_slice(buf).unwrap(),
+            )))
+        });
+
+        Ok(Box::new(packets))
+    }
+
+    fn stop(&mut self, src: &BaseSrc) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(self.cat, obj: src, "Stopped");
+        Ok(())
+    }
+
+    fn set_property(&mut self, _src: &BaseSrc, property: &Property, value: &glib::Value) {
+        match *property {
+            Property::String("multicast-iface", ..) => {
+                self.settings.multicast_iface = value.get();
+            }
+            Property::String("multicast-source", ..) => {
+                self.settings.multicast_source = value.get();
+            }
+            Property::UInt("buffer-size", ..) => {
+                self.settings.buffer_size = value.get().unwrap();
+            }
+            Property::Boolean("reuse", ..) => {
+                self.settings.reuse = value.get().unwrap();
+            }
+            Property::UInt("mtu", ..) => {
+                self.settings.mtu = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _src: &BaseSrc, property: &Property) -> Result<glib::Value, ()> {
+        match *property {
+            Property::String("multicast-iface", ..) => Ok(self.settings.multicast_iface.to_value()),
+            Property::String("multicast-source", ..) => {
+                Ok(self.settings.multicast_source.to_value())
+            }
+            Property::UInt("buffer-size", ..) => Ok(self.settings.buffer_size.to_value()),
+            Property::Boolean("reuse", ..) => Ok(self.settings.reuse.to_value()),
+            Property::UInt("mtu", ..) => Ok(self.settings.mtu.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
@@ -0,0 +1,390 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::sync::Mutex;
+use std::thread;
+use std::u64;
+
+const DEFAULT_LOCATION: Option<&str> = None;
+const DEFAULT_INTERVAL: u64 = 5 * gst::SECOND_VAL;
+const DEFAULT_RESUME: bool = true;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: Option<String>,
+    interval: u64,
+    resume: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: DEFAULT_LOCATION.map(String::from),
+            interval: DEFAULT_INTERVAL,
+            resume: DEFAULT_RESUME,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 3] = [
+    Property::String(
+        "location",
+        "Location",
+        "File to store the bookmark in",
+        DEFAULT_LOCATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "interval",
+        "Interval",
+        "Interval in nanoseconds of stream time between bookmark updates",
+        (0, u64::MAX),
+        DEFAULT_INTERVAL,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "resume",
+        "Resume",
+        "Seek to the bookmarked position when starting",
+        DEFAULT_RESUME,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+struct State {
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    position: gst::ClockTime,
+    last_saved_position: gst::ClockTime,
+    // Position loaded from the bookmark file that still has to be seeked to
+    resume_position: gst::ClockTime,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            segment: gst::FormattedSegment::new(),
+            position: gst::CLOCK_TIME_NONE,
+            last_saved_position: gst::CLOCK_TIME_NONE,
+            resume_position: gst::CLOCK_TIME_NONE,
+        }
+    }
+}
+
+// Bookmark file format:
+//
+// A single GstStructure in its string serialization, e.g.
+// "bookmark, position=(guint64)12000000000, duration=(guint64)60000000000;"
+// with the position being in stream time. The duration is only stored if known.
+struct Bookmark {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl Bookmark {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "bookmark",
+                gst::DebugColorFlags::empty(),
+                "Stream position bookmarking",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Bookmark",
+            "Generic",
+            "Stores the current stream position and resumes from it on the next start",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, true, true);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    fn load(&self, element: &BaseTransform, location: &str) -> Option<gst::ClockTime> {
+        let mut contents = String::new();
+        if let Err(err) = File::open(location).and_then(|mut f| f.read_to_string(&mut contents)) {
+            gst_debug!(self.cat, obj: element, "No bookmark at {}: {}", location, err);
+            return None;
+        }
+
+        let s = match gst::Structure::from_string(contents.trim()) {
+            Some(s) if s.get_name() == "bookmark" => s,
+            _ => {
+                gst_warning!(self.cat, obj: element, "Invalid bookmark in {}", location);
+                return None;
+            }
+        };
+
+        let position = s.get::<u64>("position").map(gst::ClockTime::from);
+        gst_debug!(self.cat, obj: element, "Loaded bookmark {:?}", position);
+
+        position
+    }
+
+    fn save(&self, element: &BaseTransform, location: &str, position: gst::ClockTime) {
+        let mut s = gst::Structure::new("bookmark", &[("position", &position.unwrap())]);
+
+        let duration = element
+            .get_static_pad("sink")
+            .and_then(|pad| pad.peer_query_duration::<gst::ClockTime>())
+            .and_then(|duration| duration.0);
+        if let Some(duration) = duration {
+            s.get_mut().unwrap().set("duration", &duration);
+        }
+
+        // Write to a temporary file first so that the bookmark is never left half-written
+        let tmp_location = format!("{}.tmp", location);
+        let res = File::create(&tmp_location)
+            .and_then(|mut f| f.write_all(s.to_string().as_bytes()))
+            .and_then(|_| fs::rename(&tmp_location, location));
+
+        match res {
+            Ok(_) => gst_log!(self.cat, obj: element, "Saved bookmark {}", position),
+            Err(err) => {
+                gst_element_warning!(
+                    element,
+                    gst::ResourceError::Write,
+                    ["Failed to write bookmark to {}: {}", location, err]
+                );
+            }
+        }
+    }
+
+    fn remove(&self, element: &BaseTransform, location: &str) {
+        gst_debug!(self.cat, obj: element, "Stream finished, removing bookmark");
+        let _ = fs::remove_file(location);
+    }
+
+    fn seek_to(&self, element: &BaseTransform, position: gst::ClockTime) {
+        gst_info!(self.cat, obj: element, "Resuming from {}", position);
+
+        let sinkpad = match element.get_static_pad("sink") {
+            None => return,
+            Some(sinkpad) => sinkpad,
+        };
+
+        // Flushing seeks can't be sent from the streaming thread
+        let seek = gst::Event::new_seek(
+            1.0,
+            gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE,
+            gst::SeekType::Set,
+            position,
+            gst::SeekType::None,
+            gst::CLOCK_TIME_NONE,
+        ).build();
+        thread::spawn(move || {
+            sinkpad.push_event(seek);
+        });
+    }
+}
+
+impl ObjectImpl<BaseTransform> for Bookmark {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("location", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.location = value.get();
+            }
+            Property::UInt64("interval", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.interval = value.get().unwrap();
+            }
+            Property::Boolean("resume", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.resume = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("location", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.location.to_value())
+            }
+            Property::UInt64("interval", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.interval.to_value())
+            }
+            Property::Boolean("resume", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.resume.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for Bookmark {}
+
+impl BaseTransformImpl<BaseTransform> for Bookmark {
+    fn start(&self, element: &BaseTransform) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+        let mut state = self.state.lock().unwrap();
+        *state = State::default();
+
+        if settings.resume {
+            if let Some(ref location) = settings.location {
+                if let Some(position) = self.load(element, location) {
+                    state.resume_position = position;
+                }
+            }
+        }
+
+        true
+    }
+
+    fn stop(&self, element: &BaseTransform) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+        let state = self.state.lock().unwrap();
+
+        if let Some(ref location) = settings.location {
+            if state.position.is_some() && state.position != state.last_saved_position {
+                self.save(element, location, state.position);
+            }
+        }
+
+        true
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let mut state = self.state.lock().unwrap();
+                state.segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Ok(segment) => segment,
+                    Err(_) => {
+                        gst_warning!(self.cat, obj: element, "Not a time segment, not bookmarking");
+                        gst::FormattedSegment::new()
+                    }
+                };
+            }
+            EventView::Eos(..) => {
+                let settings = self.settings.lock().unwrap().clone();
+                let mut state = self.state.lock().unwrap();
+                if let Some(ref location) = settings.location {
+                    self.remove(element, location);
+                }
+                state.position = gst::CLOCK_TIME_NONE;
+            }
+            _ => (),
+        }
+
+        element.parent_sink_event(event)
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let settings = self.settings.lock().unwrap().clone();
+        let mut state = self.state.lock().unwrap();
+
+        if state.resume_position.is_some() {
+            let position = state.resume_position;
+            state.resume_position = gst::CLOCK_TIME_NONE;
+            drop(state);
+
+            self.seek_to(element, position);
+            // Same as GST_BASE_TRANSFORM_FLOW_DROPPED
+            return gst::FlowReturn::CustomSuccess;
+        }
+
+        let position = state.segment.to_stream_time(buf.get_pts());
+        if position.is_none() {
+            return gst::FlowReturn::Ok;
+        }
+        state.position = position;
+
+        let location = match settings.location {
+            None => return gst::FlowReturn::Ok,
+            Some(location) => location,
+        };
+
+        let due = match state.last_saved_position.0 {
+            None => true,
+            Some(last) => {
+                let position = position.unwrap();
+                position < last || position - last >= settings.interval
+            }
+        };
+
+        if due {
+            state.last_saved_position = position;
+            drop(state);
+            self.save(element, &location, position);
+        }
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct BookmarkStatic;
+
+impl ImplTypeStatic<BaseTransform> for BookmarkStatic {
+    fn get_name(&self) -> &str {
+        "Bookmark"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        Bookmark::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        Bookmark::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let bookmark_static = BookmarkStatic;
+    register_type(bookmark_static)
+}
@@ -0,0 +1,707 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_audio;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use byte_slice_cast::*;
+
+use std::i32;
+use std::sync::Mutex;
+use std::u64;
+
+const DEFAULT_BLACK_PIXEL_THRESHOLD: u32 = 32;
+const DEFAULT_BLACK_RATIO: f64 = 0.98;
+const DEFAULT_BLACK_DURATION: u64 = 2 * gst::SECOND_VAL;
+const DEFAULT_FREEZE_THRESHOLD: f64 = 1.0;
+const DEFAULT_FREEZE_DURATION: u64 = 2 * gst::SECOND_VAL;
+const DEFAULT_SILENCE_THRESHOLD: f64 = -60.0;
+const DEFAULT_SILENCE_DURATION: u64 = 2 * gst::SECOND_VAL;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    black_pixel_threshold: u32,
+    black_ratio: f64,
+    black_duration: u64,
+    freeze_threshold: f64,
+    freeze_duration: u64,
+    silence_threshold: f64,
+    silence_duration: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            black_pixel_threshold: DEFAULT_BLACK_PIXEL_THRESHOLD,
+            black_ratio: DEFAULT_BLACK_RATIO,
+            black_duration: DEFAULT_BLACK_DURATION,
+            freeze_threshold: DEFAULT_FREEZE_THRESHOLD,
+            freeze_duration: DEFAULT_FREEZE_DURATION,
+            silence_threshold: DEFAULT_SILENCE_THRESHOLD,
+            silence_duration: DEFAULT_SILENCE_DURATION,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 7] = [
+    Property::UInt(
+        "black-pixel-threshold",
+        "Black Pixel Threshold",
+        "Luma value up to which a pixel is considered black",
+        (0, 255),
+        DEFAULT_BLACK_PIXEL_THRESHOLD,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "black-ratio",
+        "Black Ratio",
+        "Ratio of black pixels from which on a frame is considered black",
+        (0.0, 1.0),
+        DEFAULT_BLACK_RATIO,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "black-duration",
+        "Black Duration",
+        "Duration in nanoseconds of black frames after which a message is posted",
+        (0, u64::MAX),
+        DEFAULT_BLACK_DURATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "freeze-threshold",
+        "Freeze Threshold",
+        "Mean absolute luma difference to the previous frame below which a frame is considered unchanged",
+        (0.0, 255.0),
+        DEFAULT_FREEZE_THRESHOLD,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "freeze-duration",
+        "Freeze Duration",
+        "Duration in nanoseconds of unchanged frames after which a message is posted",
+        (0, u64::MAX),
+        DEFAULT_FREEZE_DURATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "silence-threshold",
+        "Silence Threshold",
+        "Level in dB below which audio is considered silent",
+        (-200.0, 0.0),
+        DEFAULT_SILENCE_THRESHOLD,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "silence-duration",
+        "Silence Duration",
+        "Duration in nanoseconds of silence after which a message is posted",
+        (0, u64::MAX),
+        DEFAULT_SILENCE_DURATION,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transition {
+    // Start timestamp of the condition
+    Start(u64),
+    // Start and end timestamp of the condition
+    Stop(u64, u64),
+}
+
+// Tracks for how long a condition holds and reports once it held for at least
+// `min_duration`, and again once it doesn't hold anymore
+#[derive(Debug, Default)]
+struct Detector {
+    since: Option<u64>,
+    active: bool,
+}
+
+impl Detector {
+    fn update(
+        &mut self,
+        condition: bool,
+        pts: u64,
+        end: u64,
+        min_duration: u64,
+    ) -> Option<Transition> {
+        if condition {
+            let since = *self.since.get_or_insert(pts);
+            if !self.active && end.saturating_sub(since) >= min_duration {
+                self.active = true;
+                return Some(Transition::Start(since));
+            }
+            None
+        } else {
+            self.finish(pts)
+        }
+    }
+
+    fn finish(&mut self, end: u64) -> Option<Transition> {
+        let since = self.since.take();
+        if self.active {
+            self.active = false;
+            since.map(|since| Transition::Stop(since, end))
+        } else {
+            None
+        }
+    }
+}
+
+enum Format {
+    Video {
+        info: gst_video::VideoInfo,
+        // Luma plane of the previous frame, without padding
+        previous: Option<Vec<u8>>,
+        black: Detector,
+        freeze: Detector,
+    },
+    Audio {
+        info: gst_audio::AudioInfo,
+        silence: Detector,
+    },
+}
+
+struct State {
+    format: Format,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    // End of the last buffer, used for closing open conditions on EOS
+    position: gst::ClockTime,
+}
+
+struct BrokenSignalDetect {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+// Calls `func` with every row of the luma plane
+fn for_each_luma_row<F: FnMut(&[u8])>(info: &gst_video::VideoInfo, data: &[u8], mut func: F) {
+    let width = info.width() as usize;
+    let stride = info.stride()[0] as usize;
+    let offset = info.offset()[0];
+
+    for row in 0..(info.height() as usize) {
+        let start = offset + row * stride;
+        func(&data[start..start + width]);
+    }
+}
+
+fn black_ratio(info: &gst_video::VideoInfo, data: &[u8], threshold: u32) -> f64 {
+    let mut black = 0;
+    for_each_luma_row(info, data, |row| {
+        black += row.iter().filter(|&&y| u32::from(y) <= threshold).count();
+    });
+
+    black as f64 / (info.width() as f64 * info.height() as f64)
+}
+
+// Returns the mean absolute difference to the previous luma plane and replaces it with the
+// luma plane of the current frame
+fn luma_difference(
+    info: &gst_video::VideoInfo,
+    data: &[u8],
+    previous: &mut Option<Vec<u8>>,
+) -> Option<f64> {
+    let mut current = Vec::with_capacity(info.width() as usize * info.height() as usize);
+    for_each_luma_row(info, data, |row| current.extend_from_slice(row));
+
+    let diff = previous.as_ref().map(|previous| {
+        let sum = previous
+            .iter()
+            .zip(current.iter())
+            .map(|(&a, &b)| u64::from((i32::from(a) - i32::from(b)).abs() as u32))
+            .sum::<u64>();
+        sum as f64 / current.len() as f64
+    });
+    *previous = Some(current);
+
+    diff
+}
+
+fn rms_db<F: Fn(&T) -> f64, T>(samples: &[T], normalize: F) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+
+    let sum = samples
+        .iter()
+        .map(|s| {
+            let s = normalize(s);
+            s * s
+        })
+        .sum::<f64>();
+    let rms = (sum / samples.len() as f64).sqrt();
+
+    20.0 * rms.log10()
+}
+
+impl BrokenSignalDetect {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "brokensignaldetect",
+                gst::DebugColorFlags::empty(),
+                "Broken signal detector",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Broken Signal Detector",
+            "Filter/Analyzer/Audio/Video",
+            "Detects black frames, frozen video and silent audio",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let mut caps = gst::Caps::new_simple(
+            "video/x-raw",
+            &[
+                (
+                    "format",
+                    &gst::List::new(&[
+                        &gst_video::VideoFormat::I420.to_string(),
+                        &gst_video::VideoFormat::Yv12.to_string(),
+                        &gst_video::VideoFormat::Nv12.to_string(),
+                        &gst_video::VideoFormat::Nv21.to_string(),
+                        &gst_video::VideoFormat::Y42b.to_string(),
+                        &gst_video::VideoFormat::Y444.to_string(),
+                        &gst_video::VideoFormat::Gray8.to_string(),
+                    ]),
+                ),
+                ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                (
+                    "framerate",
+                    &gst::FractionRange::new(
+                        gst::Fraction::new(0, 1),
+                        gst::Fraction::new(i32::MAX, 1),
+                    ),
+                ),
+            ],
+        );
+        caps.get_mut().unwrap().append(gst::Caps::new_simple(
+            "audio/x-raw",
+            &[
+                (
+                    "format",
+                    &gst::List::new(&[
+                        &gst_audio::AUDIO_FORMAT_F32.to_string(),
+                        &gst_audio::AUDIO_FORMAT_S16.to_string(),
+                    ]),
+                ),
+                ("rate", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("layout", &"interleaved"),
+            ],
+        ));
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, true, true);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    // Message posted whenever a condition starts or stops:
+    //
+    // "brokensignaldetect, type=(string){black,freeze,silence}, event=(string){start,stop},
+    //  timestamp=(guint64)..., running-time=(guint64)..., duration=(guint64)..."
+    //
+    // with the stream time and running time of the start of the condition. The duration is
+    // only set for stop messages.
+    fn post_transition(
+        &self,
+        element: &BaseTransform,
+        segment: &gst::FormattedSegment<gst::ClockTime>,
+        type_: &str,
+        transition: Transition,
+    ) {
+        let (event, start, end) = match transition {
+            Transition::Start(start) => ("start", start, None),
+            Transition::Stop(start, end) => ("stop", start, Some(end)),
+        };
+
+        let start_time = gst::ClockTime::from_nseconds(start);
+        gst_debug!(self.cat, obj: element, "{} {} at {}", type_, event, start_time);
+
+        let mut s = gst::Structure::new(
+            "brokensignaldetect",
+            &[
+                ("type", &type_),
+                ("event", &event),
+                ("timestamp", &segment.to_stream_time(start_time).0.unwrap_or(start)),
+                ("running-time", &segment.to_running_time(start_time).0.unwrap_or(start)),
+            ],
+        );
+        if let Some(end) = end {
+            s.get_mut().unwrap().set("duration", &(end - start));
+        }
+
+        let _ = element.post_message(&gst::Message::new_element(s).src(Some(element)).build());
+    }
+}
+
+impl ObjectImpl<BaseTransform> for BrokenSignalDetect {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("black-pixel-threshold", ..) => {
+                settings.black_pixel_threshold = value.get().unwrap();
+            }
+            Property::Double("black-ratio", ..) => {
+                settings.black_ratio = value.get().unwrap();
+            }
+            Property::UInt64("black-duration", ..) => {
+                settings.black_duration = value.get().unwrap();
+            }
+            Property::Double("freeze-threshold", ..) => {
+                settings.freeze_threshold = value.get().unwrap();
+            }
+            Property::UInt64("freeze-duration", ..) => {
+                settings.freeze_duration = value.get().unwrap();
+            }
+            Property::Double("silence-threshold", ..) => {
+                settings.silence_threshold = value.get().unwrap();
+            }
+            Property::UInt64("silence-duration", ..) => {
+                settings.silence_duration = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("black-pixel-threshold", ..) => {
+                Ok(settings.black_pixel_threshold.to_value())
+            }
+            Property::Double("black-ratio", ..) => Ok(settings.black_ratio.to_value()),
+            Property::UInt64("black-duration", ..) => Ok(settings.black_duration.to_value()),
+            Property::Double("freeze-threshold", ..) => Ok(settings.freeze_threshold.to_value()),
+            Property::UInt64("freeze-duration", ..) => Ok(settings.freeze_duration.to_value()),
+            Property::Double("silence-threshold", ..) => {
+                Ok(settings.silence_threshold.to_value())
+            }
+            Property::UInt64("silence-duration", ..) => Ok(settings.silence_duration.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for BrokenSignalDetect {}
+
+impl BaseTransformImpl<BaseTransform> for BrokenSignalDetect {
+    fn set_caps(&self, element: &BaseTransform, incaps: &gst::Caps, outcaps: &gst::Caps) -> bool {
+        if incaps != outcaps {
+            return false;
+        }
+
+        let is_video = incaps
+            .get_structure(0)
+            .map(|s| s.get_name() == "video/x-raw")
+            .unwrap_or(false);
+
+        let format = if is_video {
+            match gst_video::VideoInfo::from_caps(incaps) {
+                None => return false,
+                Some(info) => Format::Video {
+                    info: info,
+                    previous: None,
+                    black: Detector::default(),
+                    freeze: Detector::default(),
+                },
+            }
+        } else {
+            match gst_audio::AudioInfo::from_caps(incaps) {
+                None => return false,
+                Some(info) => Format::Audio {
+                    info: info,
+                    silence: Detector::default(),
+                },
+            }
+        };
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", incaps);
+
+        let mut state = self.state.lock().unwrap();
+        let segment = state
+            .take()
+            .map(|state| state.segment)
+            .unwrap_or_else(gst::FormattedSegment::new);
+        *state = Some(State {
+            format: format,
+            segment: segment,
+            position: gst::CLOCK_TIME_NONE,
+        });
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseTransform) -> bool {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        true
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(ref mut state) = *state {
+                    state.segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                        Ok(segment) => segment,
+                        Err(_) => {
+                            gst_warning!(self.cat, obj: element, "Not a time segment");
+                            gst::FormattedSegment::new()
+                        }
+                    };
+                }
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(ref mut state) = *state {
+                    match state.format {
+                        Format::Video {
+                            ref mut previous,
+                            ref mut black,
+                            ref mut freeze,
+                            ..
+                        } => {
+                            *previous = None;
+                            *black = Detector::default();
+                            *freeze = Detector::default();
+                        }
+                        Format::Audio {
+                            ref mut silence, ..
+                        } => {
+                            *silence = Detector::default();
+                        }
+                    }
+                    state.position = gst::CLOCK_TIME_NONE;
+                }
+            }
+            EventView::Eos(..) => {
+                // Close all conditions that are still ongoing
+                let mut state = self.state.lock().unwrap();
+                if let Some(ref mut state) = *state {
+                    if let Some(position) = state.position.0 {
+                        let segment = &state.segment;
+                        match state.format {
+                            Format::Video {
+                                ref mut black,
+                                ref mut freeze,
+                                ..
+                            } => {
+                                if let Some(t) = black.finish(position) {
+                                    self.post_transition(element, segment, "black", t);
+                                }
+                                if let Some(t) = freeze.finish(position) {
+                                    self.post_transition(element, segment, "freeze", t);
+                                }
+                            }
+                            Format::Audio {
+                                ref mut silence, ..
+                            } => {
+                                if let Some(t) = silence.finish(position) {
+                                    self.post_transition(element, segment, "silence", t);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        element.parent_sink_event(event)
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return gst::FlowReturn::NotNegotiated,
+            Some(ref mut state) => state,
+        };
+
+        let pts = match buf.get_pts().0 {
+            None => {
+                gst_log!(self.cat, obj: element, "Buffer without timestamp, skipping");
+                return gst::FlowReturn::Ok;
+            }
+            Some(pts) => pts,
+        };
+        let end = pts + buf.get_duration().0.unwrap_or(0);
+        state.position = gst::ClockTime::from_nseconds(end);
+
+        let map = match buf.map_readable() {
+            None => return gst::FlowReturn::Error,
+            Some(map) => map,
+        };
+
+        let segment = &state.segment;
+        match state.format {
+            Format::Video {
+                ref info,
+                ref mut previous,
+                ref mut black,
+                ref mut freeze,
+            } => {
+                let data = map.as_slice();
+
+                let ratio = black_ratio(info, data, settings.black_pixel_threshold);
+                gst_trace!(self.cat, obj: element, "Black pixel ratio {}", ratio);
+                if let Some(t) = black.update(
+                    ratio >= settings.black_ratio,
+                    pts,
+                    end,
+                    settings.black_duration,
+                ) {
+                    self.post_transition(element, segment, "black", t);
+                }
+
+                // The first frame can't be compared against anything
+                if let Some(diff) = luma_difference(info, data, previous) {
+                    gst_trace!(self.cat, obj: element, "Luma difference {}", diff);
+                    if let Some(t) = freeze.update(
+                        diff < settings.freeze_threshold,
+                        pts,
+                        end,
+                        settings.freeze_duration,
+                    ) {
+                        self.post_transition(element, segment, "freeze", t);
+                    }
+                }
+            }
+            Format::Audio {
+                ref info,
+                ref mut silence,
+            } => {
+                let level = match info.format() {
+                    gst_audio::AUDIO_FORMAT_F32 => {
+                        let data = map.as_slice().as_slice_of::<f32>().unwrap();
+                        rms_db(data, |&s| f64::from(s))
+                    }
+                    gst_audio::AUDIO_FORMAT_S16 => {
+                        let data = map.as_slice().as_slice_of::<i16>().unwrap();
+                        rms_db(data, |&s| f64::from(s) / 32768.0)
+                    }
+                    _ => return gst::FlowReturn::NotNegotiated,
+                };
+                gst_trace!(self.cat, obj: element, "Level {} dB", level);
+
+                if let Some(t) = silence.update(
+                    level < settings.silence_threshold,
+                    pts,
+                    end,
+                    settings.silence_duration,
+                ) {
+                    self.post_transition(element, segment, "silence", t);
+                }
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct BrokenSignalDetectStatic;
+
+impl ImplTypeStatic<BaseTransform> for BrokenSignalDetectStatic {
+    fn get_name(&self) -> &str {
+        "BrokenSignalDetect"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        BrokenSignalDetect::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        BrokenSignalDetect::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let brokensignaldetect_static = BrokenSignalDetectStatic;
+    register_type(brokensignaldetect_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detector() {
+        let mut detector = Detector::default();
+
+        // Condition holds for less than the minimum duration
+        assert_eq!(detector.update(true, 0, 10, 30), None);
+        assert_eq!(detector.update(true, 10, 20, 30), None);
+        assert_eq!(detector.update(false, 20, 30, 30), None);
+
+        // Condition holds long enough, start is reported once with the first timestamp
+        assert_eq!(detector.update(true, 30, 40, 30), None);
+        assert_eq!(detector.update(true, 40, 50, 30), None);
+        assert_eq!(
+            detector.update(true, 50, 60, 30),
+            Some(Transition::Start(30))
+        );
+        assert_eq!(detector.update(true, 60, 70, 30), None);
+        assert_eq!(
+            detector.update(false, 70, 80, 30),
+            Some(Transition::Stop(30, 70))
+        );
+        assert_eq!(detector.update(false, 80, 90, 30), None);
+
+        // Ongoing condition is closed at the end
+        assert_eq!(detector.update(true, 90, 130, 30), Some(Transition::Start(90)));
+        assert_eq!(detector.finish(130), Some(Transition::Stop(90, 130)));
+        assert_eq!(detector.finish(140), None);
+    }
+}
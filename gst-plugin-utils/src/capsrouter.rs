@@ -0,0 +1,456 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+
+// Routing behaviour:
+//
+// Each request src pad carries an optional caps filter, given as the caps when requesting
+// the pad or set later with the "set-filter" action signal. Whenever new caps arrive on the
+// sink pad, the first src pad (in request order) whose filter intersects with the caps is
+// selected and all following data is pushed there. If no filter matches, the always
+// "fallback" src pad is selected.
+//
+// When switching pads, the sticky events (stream-start, segment, tags) are re-sent on the
+// newly selected pad. EOS and flushing events are forwarded to all src pads.
+struct Route {
+    srcpad: gst::Pad,
+    filter: Option<gst::Caps>,
+}
+
+#[derive(Default)]
+struct State {
+    routes: Vec<Route>,
+    pad_count: u32,
+    active_pad: Option<gst::Pad>,
+}
+
+struct CapsRouter {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    fallback_srcpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl CapsRouter {
+    fn new(_element: &Element, sinkpad: gst::Pad, fallback_srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "capsrouter",
+                gst::DebugColorFlags::empty(),
+                "Caps based stream router",
+            ),
+            sinkpad: sinkpad,
+            fallback_srcpad: fallback_srcpad,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Caps Router",
+            "Generic",
+            "Routes streams to the first src pad with matching caps filter",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "fallback",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src_%u",
+            gst::PadDirection::Src,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.add_action_signal(
+            "set-filter",
+            &[gst::Pad::static_type(), gst::Caps::static_type()],
+            glib::Type::Bool,
+            |args| {
+                let element = args[0].get::<Element>().unwrap();
+                let pad = args[1].get::<gst::Pad>().unwrap();
+                let filter = args[2].get::<gst::Caps>();
+
+                let router = element.get_impl().downcast_ref::<CapsRouter>().unwrap();
+                Some(router.set_filter(&element, &pad, filter).to_value())
+            },
+        );
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("fallback").unwrap();
+        let fallback_srcpad = gst::Pad::new_from_template(&templ, "fallback");
+
+        CapsRouter::set_sink_pad_functions(&sinkpad);
+        CapsRouter::set_src_pad_functions(&fallback_srcpad);
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&fallback_srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, fallback_srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let router = element.get_impl().downcast_ref::<CapsRouter>().unwrap();
+        element.catch_panic(fallback, |element| f(router, element))
+    }
+
+    fn set_sink_pad_functions(sinkpad: &gst::Pad) {
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            CapsRouter::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |router, element| router.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            CapsRouter::catch_panic_pad_function(
+                parent,
+                || false,
+                |router, element| router.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_iterate_internal_links_function(|pad, parent| {
+            CapsRouter::catch_panic_pad_function(
+                parent,
+                || gst::Iterator::from_vec(vec![]),
+                |router, element| router.iterate_internal_links(pad, element),
+            )
+        });
+    }
+
+    fn set_src_pad_functions(srcpad: &gst::Pad) {
+        srcpad.set_event_function(|pad, parent, event| {
+            CapsRouter::catch_panic_pad_function(
+                parent,
+                || false,
+                |router, element| router.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_iterate_internal_links_function(|pad, parent| {
+            CapsRouter::catch_panic_pad_function(
+                parent,
+                || gst::Iterator::from_vec(vec![]),
+                |router, element| router.iterate_internal_links(pad, element),
+            )
+        });
+    }
+
+    fn get_all_srcpads(&self) -> Vec<gst::Pad> {
+        let state = self.state.lock().unwrap();
+        state
+            .routes
+            .iter()
+            .map(|r| r.srcpad.clone())
+            .chain(Some(self.fallback_srcpad.clone()))
+            .collect()
+    }
+
+    fn select_srcpad(&self, state: &State, caps: &gst::CapsRef) -> gst::Pad {
+        state
+            .routes
+            .iter()
+            .find(|r| {
+                r.filter
+                    .as_ref()
+                    .map(|filter| filter.can_intersect(caps))
+                    .unwrap_or(false)
+            })
+            .map(|r| r.srcpad.clone())
+            .unwrap_or_else(|| self.fallback_srcpad.clone())
+    }
+
+    fn set_filter(&self, element: &Element, pad: &gst::Pad, filter: Option<gst::Caps>) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        match state.routes.iter_mut().find(|r| &r.srcpad == pad) {
+            None => {
+                gst_warning!(self.cat, obj: element, "Unknown pad {:?}", pad.get_name());
+                false
+            }
+            Some(route) => {
+                gst_debug!(self.cat, obj: pad, "Setting filter {:?}", filter);
+                route.filter = filter;
+                // Takes effect with the next caps
+                true
+            }
+        }
+    }
+
+    fn switch_to_pad(&self, element: &Element, srcpad: &gst::Pad, caps_event: gst::Event) -> bool {
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Routing stream to pad {:?}",
+            srcpad.get_name()
+        );
+
+        if let Some(event) = self.sinkpad.get_sticky_event(gst::EventType::StreamStart, 0) {
+            srcpad.push_event(event);
+        }
+
+        let res = srcpad.push_event(caps_event);
+
+        if let Some(event) = self.sinkpad.get_sticky_event(gst::EventType::Segment, 0) {
+            srcpad.push_event(event);
+        }
+        if let Some(event) = self.sinkpad.get_sticky_event(gst::EventType::Tag, 0) {
+            srcpad.push_event(event);
+        }
+
+        res
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let srcpad = match self.state.lock().unwrap().active_pad {
+            None => {
+                gst_element_error!(
+                    element,
+                    gst::CoreError::Negotiation,
+                    ["No caps received before buffer"]
+                );
+                return gst::FlowReturn::NotNegotiated;
+            }
+            Some(ref srcpad) => srcpad.clone(),
+        };
+
+        gst_log!(
+            self.cat,
+            obj: pad,
+            "Pushing buffer {:?} to pad {:?}",
+            buffer,
+            srcpad.get_name()
+        );
+        srcpad.push(buffer)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let (srcpad, changed) = {
+                    let mut state = self.state.lock().unwrap();
+                    let srcpad = self.select_srcpad(&state, e.get_caps());
+                    let changed = state.active_pad.as_ref() != Some(&srcpad);
+                    state.active_pad = Some(srcpad.clone());
+                    (srcpad, changed)
+                };
+
+                if changed {
+                    return self.switch_to_pad(element, &srcpad, event);
+                }
+
+                return srcpad.push_event(event);
+            }
+            EventView::Eos(..) | EventView::FlushStart(..) | EventView::FlushStop(..) => {
+                let mut res = false;
+                for srcpad in self.get_all_srcpads() {
+                    res |= srcpad.push_event(event.clone());
+                }
+                return res;
+            }
+            _ => (),
+        }
+
+        let active_pad = self.state.lock().unwrap().active_pad.clone();
+        match active_pad {
+            Some(srcpad) => srcpad.push_event(event),
+            // Sticky events are re-sent once a pad is selected
+            None => event.is_sticky(),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn iterate_internal_links(&self, pad: &gst::Pad, _element: &Element) -> gst::Iterator<gst::Pad> {
+        if pad == &self.sinkpad {
+            match self.state.lock().unwrap().active_pad {
+                None => gst::Iterator::from_vec(vec![]),
+                Some(ref srcpad) => gst::Iterator::from_vec(vec![srcpad.clone()]),
+            }
+        } else {
+            gst::Iterator::from_vec(vec![self.sinkpad.clone()])
+        }
+    }
+}
+
+impl ObjectImpl<Element> for CapsRouter {}
+
+impl ElementImpl<Element> for CapsRouter {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        match transition {
+            gst::StateChange::PausedToReady => {
+                self.state.lock().unwrap().active_pad = None;
+            }
+            _ => (),
+        }
+
+        ret
+    }
+
+    fn request_new_pad(
+        &self,
+        element: &Element,
+        templ: &gst::PadTemplate,
+        name: Option<String>,
+        caps: Option<&gst::CapsRef>,
+    ) -> Option<gst::Pad> {
+        let mut state = self.state.lock().unwrap();
+
+        let name = match name {
+            Some(name) => {
+                // Make sure automatically named pads don't clash with this one later
+                let index = if name.starts_with("src_") {
+                    name[4..].parse::<u32>().ok()
+                } else {
+                    None
+                };
+                if let Some(index) = index {
+                    if index >= state.pad_count {
+                        state.pad_count = index + 1;
+                    }
+                }
+                name
+            }
+            None => {
+                let name = format!("src_{}", state.pad_count);
+                state.pad_count += 1;
+                name
+            }
+        };
+
+        let srcpad = gst::Pad::new_from_template(templ, name.as_str());
+        CapsRouter::set_src_pad_functions(&srcpad);
+        srcpad.set_active(true).unwrap();
+        if element.add_pad(&srcpad).is_err() {
+            gst_warning!(self.cat, obj: element, "Failed to add pad {}", name);
+            let _ = srcpad.set_active(false);
+            return None;
+        }
+
+        let filter = caps.map(|caps| caps.to_owned());
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Requested pad {} with filter {:?}",
+            name,
+            filter
+        );
+
+        state.routes.push(Route {
+            srcpad: srcpad.clone(),
+            filter: filter,
+        });
+
+        Some(srcpad)
+    }
+
+    fn release_pad(&self, element: &Element, pad: &gst::Pad) {
+        let mut state = self.state.lock().unwrap();
+
+        let pos = match state.routes.iter().position(|r| &r.srcpad == pad) {
+            None => return,
+            Some(pos) => pos,
+        };
+        let route = state.routes.remove(pos);
+
+        // Route the stream again based on the current caps, including the sticky events
+        let mut switch = None;
+        if state.active_pad.as_ref() == Some(&route.srcpad) {
+            let caps_event = self.sinkpad.get_sticky_event(gst::EventType::Caps, 0);
+            let srcpad = match caps_event.as_ref().map(|e| e.view()) {
+                Some(gst::EventView::Caps(e)) => self.select_srcpad(&state, e.get_caps()),
+                _ => self.fallback_srcpad.clone(),
+            };
+            state.active_pad = Some(srcpad.clone());
+            switch = caps_event.map(|caps_event| (srcpad, caps_event));
+        }
+        drop(state);
+
+        if let Some((srcpad, caps_event)) = switch {
+            self.switch_to_pad(element, &srcpad, caps_event);
+        }
+
+        route.srcpad.set_active(false).unwrap();
+        element.remove_pad(&route.srcpad).unwrap();
+    }
+}
+
+struct CapsRouterStatic;
+
+impl ImplTypeStatic<Element> for CapsRouterStatic {
+    fn get_name(&self) -> &str {
+        "CapsRouter"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        CapsRouter::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        CapsRouter::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let capsrouter_static = CapsRouterStatic;
+    register_type(capsrouter_static)
+}
@@ -0,0 +1,509 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::bin::*;
+
+use std::sync::Mutex;
+use std::u64;
+
+const DEFAULT_MAX_DURATION: u64 = 0;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    max_duration: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            max_duration: DEFAULT_MAX_DURATION,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 1] = [
+    Property::UInt64(
+        "max-duration",
+        "Maximum Duration",
+        "Maximum stream duration to inspect before posting the report in nanoseconds (0=until EOS)",
+        (0, u64::MAX),
+        DEFAULT_MAX_DURATION,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug)]
+struct StreamInfo {
+    stream_id: Option<String>,
+    caps: Option<gst::Caps>,
+    tags: Option<gst::TagList>,
+    start: gst::ClockTime,
+    end: gst::ClockTime,
+    n_bytes: u64,
+    n_buffers: u64,
+    eos: bool,
+}
+
+impl Default for StreamInfo {
+    fn default() -> Self {
+        Self {
+            stream_id: None,
+            caps: None,
+            tags: None,
+            start: gst::CLOCK_TIME_NONE,
+            end: gst::CLOCK_TIME_NONE,
+            n_bytes: 0,
+            n_buffers: 0,
+            eos: false,
+        }
+    }
+}
+
+impl StreamInfo {
+    fn get_duration(&self) -> gst::ClockTime {
+        if self.start.is_some() && self.end.is_some() && self.end >= self.start {
+            self.end - self.start
+        } else {
+            gst::CLOCK_TIME_NONE
+        }
+    }
+
+    fn get_bitrate(&self) -> Option<u32> {
+        match self.get_duration().0 {
+            Some(duration) if duration > 0 => Some(
+                (self.n_bytes as f64 * 8.0 * gst::SECOND_VAL as f64 / duration as f64) as u32,
+            ),
+            _ => None,
+        }
+    }
+
+    fn to_structure(&self) -> gst::Structure {
+        let mut s = gst::Structure::new(
+            "stream",
+            &[
+                ("n-buffers", &self.n_buffers),
+                ("n-bytes", &self.n_bytes),
+            ],
+        );
+
+        {
+            let s = s.get_mut().unwrap();
+            if let Some(ref stream_id) = self.stream_id {
+                s.set("stream-id", stream_id);
+            }
+            if let Some(ref caps) = self.caps {
+                s.set("caps", caps);
+            }
+            if let Some(ref tags) = self.tags {
+                s.set("tags", tags);
+            }
+            if let Some(duration) = self.get_duration().0 {
+                s.set("duration", &duration);
+            }
+            if let Some(bitrate) = self.get_bitrate() {
+                s.set("bitrate", &bitrate);
+            }
+        }
+
+        s
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    container_caps: Option<gst::Caps>,
+    streams: Vec<StreamInfo>,
+    sinks: Vec<gst::Element>,
+    report_posted: bool,
+}
+
+struct Discover {
+    cat: gst::DebugCategory,
+    sinkpad: gst::GhostPad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl Discover {
+    fn new(_bin: &Bin, sinkpad: gst::GhostPad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "discover",
+                gst::DebugColorFlags::empty(),
+                "Stream topology discoverer",
+            ),
+            sinkpad: sinkpad,
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut BinClass) {
+        klass.set_metadata(
+            "Stream Discoverer",
+            "Sink/Analyzer",
+            "Inspects a stream and posts a report about its topology",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(bin: &Bin) -> Box<BinImpl<Bin>> {
+        let typefind = gst::ElementFactory::make("typefind", None).unwrap();
+        let parsebin = gst::ElementFactory::make("parsebin", None).unwrap();
+
+        bin.add_many(&[&typefind, &parsebin]).unwrap();
+        typefind.link(&parsebin).unwrap();
+
+        let templ = bin.get_pad_template("sink").unwrap();
+        let sinkpad = gst::GhostPad::new_from_template(
+            "sink",
+            &typefind.get_static_pad("sink").unwrap(),
+            &templ,
+        ).unwrap();
+        bin.add_pad(&sinkpad).unwrap();
+
+        typefind
+            .connect("have-type", false, |args| {
+                let typefind = args[0].get::<gst::Element>().unwrap();
+                let caps = args[2].get::<gst::Caps>().unwrap();
+
+                Discover::with_discover(&typefind, |discover, bin| {
+                    gst_debug!(discover.cat, obj: bin, "Found container type {}", caps);
+                    discover.state.lock().unwrap().container_caps = Some(caps);
+                });
+
+                None
+            })
+            .unwrap();
+
+        parsebin.connect_pad_added(|parsebin, pad| {
+            Discover::with_discover(parsebin, |discover, bin| discover.pad_added(bin, pad));
+        });
+
+        let imp = Self::new(bin, sinkpad);
+        Box::new(imp)
+    }
+
+    // All callbacks are connected on our own children, so we can always get back to the
+    // bin from the child's parent
+    fn with_discover<F: FnOnce(&Self, &Bin)>(child: &gst::Element, f: F) {
+        let bin = match child.get_parent().and_then(|p| p.downcast::<Bin>().ok()) {
+            None => return,
+            Some(bin) => bin,
+        };
+        let discover = bin.get_impl().downcast_ref::<Discover>().unwrap();
+        bin.catch_panic(|| (), |bin| f(discover, bin));
+    }
+
+    fn pad_added(&self, bin: &Bin, pad: &gst::Pad) {
+        gst_debug!(self.cat, obj: bin, "New stream pad {:?}", pad.get_name());
+
+        let fakesink = match gst::ElementFactory::make("fakesink", None) {
+            None => {
+                gst_element_error!(
+                    bin,
+                    gst::CoreError::MissingPlugin,
+                    ["Failed to create fakesink"]
+                );
+                return;
+            }
+            Some(fakesink) => fakesink,
+        };
+        fakesink.set_property("sync", &false).unwrap();
+        fakesink.set_property("async", &false).unwrap();
+
+        let index = {
+            let mut state = self.state.lock().unwrap();
+            state.streams.push(StreamInfo::default());
+            state.sinks.push(fakesink.clone());
+            state.streams.len() - 1
+        };
+
+        let sinkpad = fakesink.get_static_pad("sink").unwrap();
+        sinkpad.add_probe(
+            gst::PadProbeType::BUFFER | gst::PadProbeType::EVENT_DOWNSTREAM,
+            move |pad, probe_info| {
+                let fakesink = match pad.get_parent_element() {
+                    None => return gst::PadProbeReturn::Ok,
+                    Some(fakesink) => fakesink,
+                };
+
+                let mut ret = gst::PadProbeReturn::Ok;
+                Discover::with_discover(&fakesink, |discover, bin| {
+                    ret = discover.handle_probe(bin, index, probe_info);
+                });
+                ret
+            },
+        );
+
+        bin.add(&fakesink).unwrap();
+        fakesink.sync_state_with_parent().unwrap();
+
+        if pad.link(&sinkpad) != gst::PadLinkReturn::Ok {
+            gst_element_error!(
+                bin,
+                gst::CoreError::Negotiation,
+                ["Failed to link stream pad {:?}", pad.get_name()]
+            );
+        }
+    }
+
+    fn handle_probe(
+        &self,
+        bin: &Bin,
+        index: usize,
+        probe_info: &gst::PadProbeInfo,
+    ) -> gst::PadProbeReturn {
+        use gst::EventView;
+
+        let max_duration = self.settings.lock().unwrap().max_duration;
+        let mut state = self.state.lock().unwrap();
+
+        if state.report_posted {
+            return gst::PadProbeReturn::Drop;
+        }
+
+        match probe_info.data {
+            Some(gst::PadProbeData::Buffer(ref buffer)) => {
+                let stream = &mut state.streams[index];
+
+                let ts = if buffer.get_pts().is_some() {
+                    buffer.get_pts()
+                } else {
+                    buffer.get_dts()
+                };
+
+                if ts.is_some() {
+                    if stream.start.is_none() || ts < stream.start {
+                        stream.start = ts;
+                    }
+
+                    let end = if buffer.get_duration().is_some() {
+                        ts + buffer.get_duration()
+                    } else {
+                        ts
+                    };
+                    if stream.end.is_none() || end > stream.end {
+                        stream.end = end;
+                    }
+                }
+
+                stream.n_bytes += buffer.get_size() as u64;
+                stream.n_buffers += 1;
+            }
+            Some(gst::PadProbeData::Event(ref event)) => match event.view() {
+                EventView::StreamStart(e) => {
+                    state.streams[index].stream_id = Some(String::from(e.get_stream_id()));
+                }
+                EventView::Caps(e) => {
+                    gst_debug!(self.cat, obj: bin, "Stream {} has caps {}", index, e.get_caps());
+                    state.streams[index].caps = Some(e.get_caps().to_owned());
+                }
+                EventView::Tag(e) => {
+                    let stream = &mut state.streams[index];
+                    let tags = e.get_tag();
+                    stream.tags = Some(match stream.tags.take() {
+                        None => tags.to_owned(),
+                        Some(old_tags) => old_tags.merge(tags, gst::TagMergeMode::Replace),
+                    });
+                }
+                EventView::Eos(..) => {
+                    state.streams[index].eos = true;
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+
+        if max_duration > 0 && !state.streams.is_empty()
+            && state.streams.iter().all(|s| {
+                s.eos || s.get_duration().map(|d| d >= max_duration).unwrap_or(false)
+            }) {
+            gst_debug!(self.cat, obj: bin, "Inspected maximum duration");
+            let msg = self.create_report(bin, &mut state);
+            drop(state);
+            if let Some(msg) = msg {
+                bin.post_message(&msg);
+            }
+            return gst::PadProbeReturn::Drop;
+        }
+
+        gst::PadProbeReturn::Ok
+    }
+
+    fn create_report(&self, bin: &Bin, state: &mut State) -> Option<gst::Message> {
+        if state.report_posted {
+            return None;
+        }
+        state.report_posted = true;
+
+        let duration = self.sinkpad
+            .peer_query_duration::<gst::ClockTime>()
+            .and_then(|d| d.0)
+            .or_else(|| {
+                state
+                    .streams
+                    .iter()
+                    .filter_map(|s| s.get_duration().0)
+                    .max()
+            });
+
+        let streams = state
+            .streams
+            .iter()
+            .map(|s| s.to_structure().to_send_value())
+            .collect::<Vec<_>>();
+
+        let mut s = gst::Structure::new(
+            "discover-report",
+            &[
+                ("n-streams", &(streams.len() as u32)),
+                ("streams", &gst::Array::from_owned(streams)),
+            ],
+        );
+
+        {
+            let s = s.get_mut().unwrap();
+            if let Some(ref caps) = state.container_caps {
+                s.set("container-caps", caps);
+            }
+            if let Some(duration) = duration {
+                s.set("duration", &duration);
+            }
+        }
+
+        gst_debug!(self.cat, obj: bin, "Posting report {}", s);
+
+        Some(gst::Message::new_element(s).src(Some(bin)).build())
+    }
+}
+
+impl ObjectImpl<Bin> for Discover {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt64("max-duration", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.max_duration = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt64("max-duration", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.max_duration.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Bin> for Discover {
+    fn change_state(&self, bin: &Bin, transition: gst::StateChange) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: bin, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                *self.state.lock().unwrap() = State::default();
+            }
+            _ => (),
+        }
+
+        let ret = bin.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        match transition {
+            gst::StateChange::PausedToReady => {
+                let sinks = {
+                    let mut state = self.state.lock().unwrap();
+                    let sinks = state.sinks.drain(..).collect::<Vec<_>>();
+                    *state = State::default();
+                    sinks
+                };
+
+                for sink in sinks {
+                    let _ = sink.set_state(gst::State::Null);
+                    let _ = bin.remove(&sink);
+                }
+            }
+            _ => (),
+        }
+
+        ret
+    }
+}
+
+impl BinImpl<Bin> for Discover {
+    fn handle_message(&self, bin: &Bin, message: gst::Message) {
+        use gst::MessageView;
+
+        match message.view() {
+            MessageView::Eos(..) => {
+                let mut state = self.state.lock().unwrap();
+                if !state.streams.is_empty() && state.streams.iter().all(|s| s.eos) {
+                    gst_debug!(self.cat, obj: bin, "All streams are EOS");
+                    let msg = self.create_report(bin, &mut state);
+                    drop(state);
+                    if let Some(msg) = msg {
+                        bin.post_message(&msg);
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        bin.parent_handle_message(message)
+    }
+}
+
+struct DiscoverStatic;
+
+impl ImplTypeStatic<Bin> for DiscoverStatic {
+    fn get_name(&self) -> &str {
+        "Discover"
+    }
+
+    fn new(&self, bin: &Bin) -> Box<BinImpl<Bin>> {
+        Discover::init(bin)
+    }
+
+    fn class_init(&self, klass: &mut BinClass) {
+        Discover::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let discover_static = DiscoverStatic;
+    register_type(discover_static)
+}
@@ -0,0 +1,623 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::bin::*;
+
+use serde_json;
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::Mutex;
+
+const DEFAULT_LOCATION: Option<&str> = None;
+const DEFAULT_EDL: Option<&str> = None;
+const DEFAULT_VIDEO: bool = true;
+const DEFAULT_AUDIO: bool = true;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: Option<String>,
+    edl: Option<String>,
+    video: bool,
+    audio: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: DEFAULT_LOCATION.map(String::from),
+            edl: DEFAULT_EDL.map(String::from),
+            video: DEFAULT_VIDEO,
+            audio: DEFAULT_AUDIO,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 4] = [
+    Property::String(
+        "location",
+        "Location",
+        "JSON file containing the edit decision list",
+        DEFAULT_LOCATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "edl",
+        "EDL",
+        "Edit decision list as JSON string, used instead of the location if set",
+        DEFAULT_EDL,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "video",
+        "Video",
+        "Output the video of the cuts",
+        DEFAULT_VIDEO,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "audio",
+        "Audio",
+        "Output the audio of the cuts",
+        DEFAULT_AUDIO,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+// EDL format:
+//
+// {
+//   "cuts": [
+//     { "uri": "file:///a.mp4", "in": 10.0, "out": 15.5 },
+//     { "uri": "file:///b.mp4", "in": 0.0, "out": 4.0, "crossfade": 0.5 }
+//   ]
+// }
+//
+// All times are in seconds. "in" and "out" are positions in the source, and the cuts are
+// played back-to-back in the given order. A crossfade overlaps the cut with the end of the
+// previous cut by the given duration and blends between both.
+#[derive(Debug, Clone, Deserialize)]
+struct Cut {
+    uri: String,
+    #[serde(rename = "in")]
+    inpoint: f64,
+    out: f64,
+    #[serde(default)]
+    crossfade: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Edl {
+    cuts: Vec<Cut>,
+}
+
+// A cut placed on the timeline, all values in nanoseconds
+#[derive(Debug, Clone)]
+struct TimelineCut {
+    uri: String,
+    start: u64,
+    inpoint: u64,
+    duration: u64,
+    crossfade: u64,
+    priority: u32,
+}
+
+fn seconds_to_ns(seconds: f64) -> u64 {
+    (seconds * gst::SECOND_VAL as f64).round() as u64
+}
+
+fn create_timeline(edl: &Edl) -> Result<Vec<TimelineCut>, String> {
+    if edl.cuts.is_empty() {
+        return Err(String::from("EDL contains no cuts"));
+    }
+
+    let mut timeline: Vec<TimelineCut> = Vec::with_capacity(edl.cuts.len());
+    let mut position = 0;
+
+    for (i, cut) in edl.cuts.iter().enumerate() {
+        if cut.inpoint < 0.0 || cut.out <= cut.inpoint {
+            return Err(format!("Invalid in/out points for cut {}", i));
+        }
+
+        let inpoint = seconds_to_ns(cut.inpoint);
+        let duration = seconds_to_ns(cut.out) - inpoint;
+        let crossfade = match timeline.last() {
+            None => 0,
+            Some(prev) => {
+                let crossfade = seconds_to_ns(cut.crossfade.max(0.0));
+                if crossfade > duration || crossfade > prev.duration - prev.crossfade {
+                    return Err(format!("Crossfade of cut {} longer than the cuts", i));
+                }
+                crossfade
+            }
+        };
+
+        let start = position - crossfade;
+        position = start + duration;
+
+        // Neighbouring cuts need different priorities so that they can overlap
+        timeline.push(TimelineCut {
+            uri: cut.uri.clone(),
+            start: start,
+            inpoint: inpoint,
+            duration: duration,
+            crossfade: crossfade,
+            priority: 1 + (i % 2) as u32,
+        });
+    }
+
+    Ok(timeline)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MediaType {
+    Video,
+    Audio,
+}
+
+impl MediaType {
+    fn get_caps(&self) -> gst::Caps {
+        match *self {
+            MediaType::Video => gst::Caps::new_simple("video/x-raw", &[]),
+            MediaType::Audio => gst::Caps::new_simple("audio/x-raw", &[]),
+        }
+    }
+
+    fn get_pad_name(&self) -> &'static str {
+        match *self {
+            MediaType::Video => "video_src",
+            MediaType::Audio => "audio_src",
+        }
+    }
+
+    fn get_mixer_name(&self) -> &'static str {
+        match *self {
+            MediaType::Video => "compositor",
+            MediaType::Audio => "audiomixer",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    compositions: Vec<gst::Element>,
+    n_pads: usize,
+}
+
+struct EdlBin {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl EdlBin {
+    fn new(_bin: &Bin) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "edlbin",
+                gst::DebugColorFlags::empty(),
+                "Edit decision list playback",
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut BinClass) {
+        klass.set_metadata(
+            "EDL Playback Bin",
+            "Generic/Bin/Source",
+            "Plays back the cuts of an edit decision list",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        for media_type in &[MediaType::Video, MediaType::Audio] {
+            let src_pad_template = gst::PadTemplate::new(
+                media_type.get_pad_name(),
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &media_type.get_caps(),
+            );
+            klass.add_pad_template(src_pad_template);
+        }
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(bin: &Bin) -> Box<BinImpl<Bin>> {
+        let imp = Self::new(bin);
+        Box::new(imp)
+    }
+
+    // All callbacks are connected on our own children, so we can always get back to the
+    // bin from the child's parent
+    fn with_edlbin<F: FnOnce(&Self, &Bin)>(child: &gst::Element, f: F) {
+        let bin = match child.get_parent().and_then(|p| p.downcast::<Bin>().ok()) {
+            None => return,
+            Some(bin) => bin,
+        };
+        let edlbin = bin.get_impl().downcast_ref::<EdlBin>().unwrap();
+        bin.catch_panic(|| (), |bin| f(edlbin, bin));
+    }
+
+    fn load_edl(&self, settings: &Settings) -> Result<Edl, String> {
+        let json = match (&settings.edl, &settings.location) {
+            (&Some(ref edl), _) => edl.clone(),
+            (&None, &Some(ref location)) => {
+                let mut json = String::new();
+                File::open(location)
+                    .and_then(|mut f| f.read_to_string(&mut json))
+                    .map_err(|err| format!("Failed to read {}: {}", location, err))?;
+                json
+            }
+            (&None, &None) => return Err(String::from("No EDL or location set")),
+        };
+
+        serde_json::from_str(&json).map_err(|err| format!("Failed to parse EDL: {}", err))
+    }
+
+    fn create_composition(
+        &self,
+        bin: &Bin,
+        media_type: MediaType,
+        timeline: &[TimelineCut],
+    ) -> Result<gst::Element, String> {
+        let make = |factory: &str| {
+            gst::ElementFactory::make(factory, None)
+                .ok_or_else(|| format!("Failed to create {}", factory))
+        };
+
+        let composition = make("nlecomposition")?;
+        let caps = media_type.get_caps();
+
+        for (i, cut) in timeline.iter().enumerate() {
+            let source = make("nleurisource")?;
+            source.set_property("uri", &cut.uri).unwrap();
+            source.set_property("start", &cut.start).unwrap();
+            source.set_property("inpoint", &cut.inpoint).unwrap();
+            source.set_property("duration", &cut.duration).unwrap();
+            source.set_property("priority", &cut.priority).unwrap();
+            source.set_property("caps", &caps).unwrap();
+            composition
+                .clone()
+                .dynamic_cast::<gst::Bin>()
+                .unwrap()
+                .add(&source)
+                .map_err(|_| String::from("Failed to add source"))?;
+
+            if cut.crossfade > 0 {
+                // Sources are linked to the operation's inputs by priority
+                let incoming_first = cut.priority < timeline[i - 1].priority;
+                let operation = self.create_crossfade(media_type, cut, incoming_first)?;
+                composition
+                    .clone()
+                    .dynamic_cast::<gst::Bin>()
+                    .unwrap()
+                    .add(&operation)
+                    .map_err(|_| String::from("Failed to add crossfade"))?;
+            }
+        }
+
+        composition.connect_pad_added(move |composition, pad| {
+            EdlBin::with_edlbin(composition, |edlbin, bin| {
+                edlbin.pad_added(bin, media_type, pad)
+            });
+        });
+
+        bin.add(&composition)
+            .map_err(|_| String::from("Failed to add composition"))?;
+
+        composition.emit("commit", &[&true]).unwrap();
+
+        Ok(composition)
+    }
+
+    fn create_crossfade(
+        &self,
+        media_type: MediaType,
+        cut: &TimelineCut,
+        incoming_first: bool,
+    ) -> Result<gst::Element, String> {
+        let operation = gst::ElementFactory::make("nleoperation", None)
+            .ok_or_else(|| String::from("Failed to create nleoperation"))?;
+        let mixer = gst::ElementFactory::make(media_type.get_mixer_name(), None)
+            .ok_or_else(|| format!("Failed to create {}", media_type.get_mixer_name()))?;
+
+        operation
+            .clone()
+            .dynamic_cast::<gst::Bin>()
+            .unwrap()
+            .add(&mixer)
+            .map_err(|_| String::from("Failed to add mixer"))?;
+        operation.set_property("start", &cut.start).unwrap();
+        operation.set_property("duration", &cut.crossfade).unwrap();
+        operation.set_property("priority", &0u32).unwrap();
+        operation.set_property("sinks", &2i32).unwrap();
+
+        // Inside the operation stream time starts at zero at the start of the crossfade.
+        // Update the blending of both inputs before every output buffer is produced.
+        let crossfade = cut.crossfade;
+        mixer.get_static_pad("src").unwrap().add_probe(
+            gst::PadProbeType::BUFFER,
+            move |pad, probe_info| {
+                let pts = match probe_info.data {
+                    Some(gst::PadProbeData::Buffer(ref buffer)) => buffer.get_pts(),
+                    _ => return gst::PadProbeReturn::Ok,
+                };
+
+                let position = pad.get_sticky_event(gst::EventType::Segment, 0)
+                    .and_then(|event| match event.view() {
+                        gst::EventView::Segment(e) => e.get_segment()
+                            .clone()
+                            .downcast::<gst::ClockTime>()
+                            .ok()
+                            .and_then(|segment| segment.to_stream_time(pts).0),
+                        _ => None,
+                    });
+
+                if let (Some(position), Some(mixer)) = (position, pad.get_parent_element()) {
+                    let progress = (position as f64 / crossfade as f64).max(0.0).min(1.0);
+                    EdlBin::update_crossfade(&mixer, media_type, incoming_first, progress);
+                }
+
+                gst::PadProbeReturn::Ok
+            },
+        );
+
+        Ok(operation)
+    }
+
+    fn update_crossfade(
+        mixer: &gst::Element,
+        media_type: MediaType,
+        incoming_first: bool,
+        progress: f64,
+    ) {
+        let mut sinkpads = mixer.get_sink_pads();
+        if sinkpads.len() != 2 {
+            return;
+        }
+        sinkpads.sort_by_key(|pad| pad.get_name());
+
+        let (incoming, outgoing) = if incoming_first {
+            (&sinkpads[0], &sinkpads[1])
+        } else {
+            (&sinkpads[1], &sinkpads[0])
+        };
+
+        match media_type {
+            MediaType::Video => {
+                // The incoming cut is blended on top of the outgoing one
+                let _ = outgoing.set_property("zorder", &0u32);
+                let _ = outgoing.set_property("alpha", &1.0f64);
+                let _ = incoming.set_property("zorder", &1u32);
+                let _ = incoming.set_property("alpha", &progress);
+            }
+            MediaType::Audio => {
+                let _ = outgoing.set_property("volume", &(1.0 - progress));
+                let _ = incoming.set_property("volume", &progress);
+            }
+        }
+    }
+
+    fn pad_added(&self, bin: &Bin, media_type: MediaType, pad: &gst::Pad) {
+        gst_debug!(
+            self.cat,
+            obj: bin,
+            "Composition pad {:?} for {:?} added",
+            pad.get_name(),
+            media_type
+        );
+
+        let templ = bin.get_pad_template(media_type.get_pad_name()).unwrap();
+        let ghostpad =
+            gst::GhostPad::new_from_template(media_type.get_pad_name(), pad, &templ).unwrap();
+        ghostpad.set_active(true).unwrap();
+        bin.add_pad(&ghostpad).unwrap();
+
+        let all_pads_added = {
+            let mut state = self.state.lock().unwrap();
+            state.n_pads += 1;
+            state.n_pads == state.compositions.len()
+        };
+
+        if all_pads_added {
+            bin.no_more_pads();
+        }
+    }
+
+    fn setup(&self, bin: &Bin) -> Result<(), String> {
+        let settings = self.settings.lock().unwrap().clone();
+        let edl = self.load_edl(&settings)?;
+        let timeline = create_timeline(&edl)?;
+
+        gst_debug!(self.cat, obj: bin, "Created timeline {:?}", timeline);
+
+        let mut media_types = Vec::new();
+        if settings.video {
+            media_types.push(MediaType::Video);
+        }
+        if settings.audio {
+            media_types.push(MediaType::Audio);
+        }
+
+        for media_type in media_types {
+            let composition = self.create_composition(bin, media_type, &timeline)?;
+            self.state.lock().unwrap().compositions.push(composition);
+        }
+
+        Ok(())
+    }
+
+    fn teardown(&self, bin: &Bin) {
+        let compositions = {
+            let mut state = self.state.lock().unwrap();
+            let compositions = state.compositions.drain(..).collect::<Vec<_>>();
+            *state = State::default();
+            compositions
+        };
+
+        for composition in compositions {
+            let _ = composition.set_state(gst::State::Null);
+            let _ = bin.remove(&composition);
+        }
+
+        for pad in bin.get_src_pads() {
+            let _ = bin.remove_pad(&pad);
+        }
+    }
+}
+
+impl ObjectImpl<Bin> for EdlBin {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("location", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.location = value.get();
+            }
+            Property::String("edl", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.edl = value.get();
+            }
+            Property::Boolean("video", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.video = value.get().unwrap();
+            }
+            Property::Boolean("audio", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.audio = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => Ok(settings.location.to_value()),
+            Property::String("edl", ..) => Ok(settings.edl.to_value()),
+            Property::Boolean("video", ..) => Ok(settings.video.to_value()),
+            Property::Boolean("audio", ..) => Ok(settings.audio.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Bin> for EdlBin {
+    fn change_state(&self, bin: &Bin, transition: gst::StateChange) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: bin, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::NullToReady => if let Err(err) = self.setup(bin) {
+                gst_element_error!(bin, gst::ResourceError::Settings, ["{}", err]);
+                self.teardown(bin);
+                return gst::StateChangeReturn::Failure;
+            },
+            _ => (),
+        }
+
+        let ret = bin.parent_change_state(transition);
+
+        match transition {
+            gst::StateChange::ReadyToNull => {
+                self.teardown(bin);
+            }
+            _ => (),
+        }
+
+        ret
+    }
+}
+
+impl BinImpl<Bin> for EdlBin {}
+
+struct EdlBinStatic;
+
+impl ImplTypeStatic<Bin> for EdlBinStatic {
+    fn get_name(&self) -> &str {
+        "EdlBin"
+    }
+
+    fn new(&self, bin: &Bin) -> Box<BinImpl<Bin>> {
+        EdlBin::init(bin)
+    }
+
+    fn class_init(&self, klass: &mut BinClass) {
+        EdlBin::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let edlbin_static = EdlBinStatic;
+    register_type(edlbin_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeline() {
+        let edl: Edl = serde_json::from_str(
+            r#"{
+                "cuts": [
+                    { "uri": "file:///a", "in": 1.0, "out": 3.0 },
+                    { "uri": "file:///b", "in": 0.0, "out": 2.0, "crossfade": 0.5 },
+                    { "uri": "file:///c", "in": 5.0, "out": 6.0 }
+                ]
+            }"#,
+        ).unwrap();
+
+        let timeline = create_timeline(&edl).unwrap();
+        let positions = timeline
+            .iter()
+            .map(|c| (c.start, c.inpoint, c.duration, c.crossfade, c.priority))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            vec![
+                (0, gst::SECOND_VAL, 2 * gst::SECOND_VAL, 0, 1),
+                (
+                    3 * gst::SECOND_VAL / 2,
+                    0,
+                    2 * gst::SECOND_VAL,
+                    gst::SECOND_VAL / 2,
+                    2
+                ),
+                (7 * gst::SECOND_VAL / 2, 5 * gst::SECOND_VAL, gst::SECOND_VAL, 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_timeline() {
+        let edl: Edl = serde_json::from_str(
+            r#"{
+                "cuts": [
+                    { "uri": "file:///a", "in": 1.0, "out": 1.5 },
+                    { "uri": "file:///b", "in": 0.0, "out": 2.0, "crossfade": 1.0 }
+                ]
+            }"#,
+        ).unwrap();
+        assert!(create_timeline(&edl).is_err());
+
+        let edl: Edl = serde_json::from_str(r#"{ "cuts": [] }"#).unwrap();
+        assert!(create_timeline(&edl).is_err());
+    }
+}
@@ -0,0 +1,698 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::bin::*;
+
+use std::cmp;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::u64;
+
+const DEFAULT_URI: Option<&str> = None;
+const DEFAULT_FALLBACK_URI: Option<&str> = None;
+const DEFAULT_VIDEO: bool = true;
+const DEFAULT_AUDIO: bool = true;
+const DEFAULT_TIMEOUT: u64 = gst::SECOND_VAL;
+const DEFAULT_RETRY_DELAY: u64 = gst::SECOND_VAL;
+const DEFAULT_MAX_RETRY_DELAY: u64 = 30 * gst::SECOND_VAL;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    uri: Option<String>,
+    fallback_uri: Option<String>,
+    video: bool,
+    audio: bool,
+    timeout: u64,
+    retry_delay: u64,
+    max_retry_delay: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            uri: DEFAULT_URI.map(String::from),
+            fallback_uri: DEFAULT_FALLBACK_URI.map(String::from),
+            video: DEFAULT_VIDEO,
+            audio: DEFAULT_AUDIO,
+            timeout: DEFAULT_TIMEOUT,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            max_retry_delay: DEFAULT_MAX_RETRY_DELAY,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 7] = [
+    Property::String(
+        "uri",
+        "URI",
+        "URI of the main source",
+        DEFAULT_URI,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "fallback-uri",
+        "Fallback URI",
+        "URI of an image to show while the main source has no video (default: black)",
+        DEFAULT_FALLBACK_URI,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "video",
+        "Video",
+        "Output the video of the source",
+        DEFAULT_VIDEO,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "audio",
+        "Audio",
+        "Output the audio of the source",
+        DEFAULT_AUDIO,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "timeout",
+        "Timeout",
+        "Time in nanoseconds without data from the main source after which the fallback is used",
+        (0, u64::MAX),
+        DEFAULT_TIMEOUT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "retry-delay",
+        "Retry Delay",
+        "Time in nanoseconds to wait before restarting the main source after the first failure",
+        (0, u64::MAX),
+        DEFAULT_RETRY_DELAY,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "max-retry-delay",
+        "Maximum Retry Delay",
+        "Maximum time in nanoseconds to wait before restarting the main source",
+        (0, u64::MAX),
+        DEFAULT_MAX_RETRY_DELAY,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+// Delay before the given retry, doubling with every failed retry
+fn get_retry_delay(settings: &Settings, n_retries: u32) -> u64 {
+    let mut delay = settings.retry_delay;
+    for _ in 0..n_retries {
+        if delay >= settings.max_retry_delay {
+            break;
+        }
+        delay = delay.saturating_mul(2);
+    }
+
+    cmp::min(delay, settings.max_retry_delay)
+}
+
+fn is_from(object: &gst::Object, element: &gst::Element) -> bool {
+    let element = element.clone().upcast::<gst::Object>();
+    let mut object = Some(object.clone());
+
+    while let Some(o) = object {
+        if o == element {
+            return true;
+        }
+        object = o.get_parent();
+    }
+
+    false
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MediaType {
+    Video,
+    Audio,
+}
+
+impl MediaType {
+    fn get_caps(&self) -> gst::Caps {
+        match *self {
+            MediaType::Video => gst::Caps::new_simple("video/x-raw", &[]),
+            MediaType::Audio => gst::Caps::new_simple("audio/x-raw", &[]),
+        }
+    }
+
+    fn get_pad_name(&self) -> &'static str {
+        match *self {
+            MediaType::Video => "video",
+            MediaType::Audio => "audio",
+        }
+    }
+
+    fn from_caps(caps: &gst::Caps) -> Option<MediaType> {
+        let s = caps.get_structure(0)?;
+        if s.get_name().starts_with("video/") {
+            Some(MediaType::Video)
+        } else if s.get_name().starts_with("audio/") {
+            Some(MediaType::Audio)
+        } else {
+            None
+        }
+    }
+}
+
+// Behaviour:
+//
+// The main source is a uridecodebin for the configured URI. Each of its raw video and audio
+// streams is passed through a fallbackswitch, which switches to a live fallback stream if the
+// main source stops producing data: a black test pattern or the frozen image from the fallback
+// URI for video, and silence for audio. The main source is expected to be live, e.g. a network
+// stream, so that its timestamps are comparable to the ones of the fallback streams.
+//
+// The state of the main source is managed separately from the bin. Errors and EOS of the main
+// source don't affect the rest of the pipeline but restart it after the retry delay, which
+// doubles with every failure up to the maximum retry delay and is reset once the source
+// produces streams again.
+#[derive(Default)]
+struct State {
+    source: Option<gst::Element>,
+    switches: Vec<(MediaType, gst::Element)>,
+    // Number of restarts since the source last produced streams
+    n_retries: u32,
+    retry_pending: bool,
+}
+
+struct FallbackSrc {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl FallbackSrc {
+    fn new(_bin: &Bin) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "fallbacksrc",
+                gst::DebugColorFlags::empty(),
+                "Source with fallback",
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut BinClass) {
+        klass.set_metadata(
+            "Fallback Source",
+            "Generic/Bin/Source",
+            "Plays a URI and falls back to an image or test stream while it fails",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        for media_type in &[MediaType::Video, MediaType::Audio] {
+            let src_pad_template = gst::PadTemplate::new(
+                media_type.get_pad_name(),
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &media_type.get_caps(),
+            );
+            klass.add_pad_template(src_pad_template);
+        }
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(bin: &Bin) -> Box<BinImpl<Bin>> {
+        let imp = Self::new(bin);
+        Box::new(imp)
+    }
+
+    // All callbacks are connected on our own children, so we can always get back to the
+    // bin from the child's parent
+    fn with_fallbacksrc<F: FnOnce(&Self, &Bin)>(child: &gst::Element, f: F) {
+        let bin = match child.get_parent().and_then(|p| p.downcast::<Bin>().ok()) {
+            None => return,
+            Some(bin) => bin,
+        };
+        let fallbacksrc = bin.get_impl().downcast_ref::<FallbackSrc>().unwrap();
+        bin.catch_panic(|| (), |bin| f(fallbacksrc, bin));
+    }
+
+    fn create_fallback(
+        &self,
+        bin: &Bin,
+        media_type: MediaType,
+        settings: &Settings,
+    ) -> Result<gst::Element, String> {
+        let make = |factory: &str| {
+            gst::ElementFactory::make(factory, None)
+                .ok_or_else(|| format!("Failed to create {}", factory))
+        };
+
+        let elements = match (media_type, settings.fallback_uri.as_ref()) {
+            (MediaType::Video, Some(uri)) => {
+                // The image is repeated and synchronized to the clock to behave like a live
+                // source
+                let decodebin = make("uridecodebin")?;
+                let convert = make("videoconvert")?;
+                let freeze = make("imagefreeze")?;
+                let identity = make("identity")?;
+                decodebin.set_property("uri", uri).unwrap();
+                identity.set_property("sync", &true).unwrap();
+
+                let sinkpad = convert.get_static_pad("sink").unwrap();
+                decodebin.connect_pad_added(move |_, pad| {
+                    if !sinkpad.is_linked() {
+                        let _ = pad.link(&sinkpad);
+                    }
+                });
+
+                bin.add(&decodebin)
+                    .map_err(|_| String::from("Failed to add fallback"))?;
+                vec![convert, freeze, identity]
+            }
+            (MediaType::Video, None) => {
+                let src = make("videotestsrc")?;
+                src.set_property("is-live", &true).unwrap();
+                src.set_property_from_str("pattern", "black");
+                vec![src]
+            }
+            (MediaType::Audio, _) => {
+                let src = make("audiotestsrc")?;
+                src.set_property("is-live", &true).unwrap();
+                src.set_property_from_str("wave", "silence");
+                vec![src]
+            }
+        };
+
+        for element in &elements {
+            bin.add(element)
+                .map_err(|_| String::from("Failed to add fallback"))?;
+        }
+        let elements = elements.iter().collect::<Vec<_>>();
+        gst::Element::link_many(&elements).map_err(|_| String::from("Failed to link fallback"))?;
+
+        Ok(elements[elements.len() - 1].clone())
+    }
+
+    fn create_source(&self, bin: &Bin, uri: &str) -> Result<gst::Element, String> {
+        let source = gst::ElementFactory::make("uridecodebin", None)
+            .ok_or_else(|| String::from("Failed to create uridecodebin"))?;
+        source.set_property("uri", &uri).unwrap();
+        // Started and stopped by us so that failures don't affect the bin
+        source.set_locked_state(true);
+
+        source.connect_pad_added(|source, pad| {
+            FallbackSrc::with_fallbacksrc(source, |fallbacksrc, bin| {
+                fallbacksrc.pad_added(bin, pad)
+            });
+        });
+
+        bin.add(&source)
+            .map_err(|_| String::from("Failed to add source"))?;
+
+        Ok(source)
+    }
+
+    fn pad_added(&self, bin: &Bin, pad: &gst::Pad) {
+        let media_type = pad.get_current_caps()
+            .and_then(|caps| MediaType::from_caps(&caps));
+
+        let switch = {
+            let mut state = self.state.lock().unwrap();
+            state.n_retries = 0;
+            state
+                .switches
+                .iter()
+                .find(|&&(t, _)| Some(t) == media_type)
+                .map(|&(_, ref switch)| switch.clone())
+        };
+
+        let sinkpad = match switch {
+            None => {
+                gst_debug!(self.cat, obj: bin, "Ignoring pad {:?}", pad.get_name());
+                return;
+            }
+            Some(switch) => switch.get_static_pad("sink").unwrap(),
+        };
+
+        if sinkpad.is_linked() {
+            gst_debug!(self.cat, obj: bin, "Ignoring additional pad {:?}", pad.get_name());
+            return;
+        }
+
+        gst_debug!(self.cat, obj: bin, "Linking pad {:?}", pad.get_name());
+
+        // The fallback is used until the source is restarted after EOS
+        pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, |pad, probe_info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = probe_info.data {
+                if event.get_type() == gst::EventType::Eos {
+                    if let Some(source) = pad.get_parent_element() {
+                        FallbackSrc::with_fallbacksrc(&source, |fallbacksrc, bin| {
+                            gst_info!(fallbacksrc.cat, obj: bin, "Source finished");
+                            fallbacksrc.schedule_retry(bin);
+                        });
+                    }
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+
+        if pad.link(&sinkpad) != gst::PadLinkReturn::Ok {
+            gst_warning!(self.cat, obj: bin, "Failed to link pad {:?}", pad.get_name());
+        }
+    }
+
+    fn start_source(&self, bin: &Bin) {
+        let source = match self.state.lock().unwrap().source.clone() {
+            None => return,
+            Some(source) => source,
+        };
+
+        if source.sync_state_with_parent().is_err() {
+            gst_warning!(self.cat, obj: bin, "Failed to start source");
+            self.schedule_retry(bin);
+        }
+    }
+
+    fn schedule_retry(&self, bin: &Bin) {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let (source, delay) = {
+            let mut state = self.state.lock().unwrap();
+            if state.retry_pending {
+                return;
+            }
+            let source = match state.source.take() {
+                None => return,
+                Some(source) => source,
+            };
+
+            let delay = get_retry_delay(&settings, state.n_retries);
+            state.n_retries += 1;
+            state.retry_pending = true;
+            (source, delay)
+        };
+
+        gst_info!(
+            self.cat,
+            obj: bin,
+            "Restarting source in {}",
+            gst::ClockTime::from(delay)
+        );
+
+        // The source can't be shut down from its own streaming threads
+        let element = bin.clone().upcast::<gst::Element>();
+        thread::spawn(move || {
+            let _ = source.set_state(gst::State::Null);
+            thread::sleep(Duration::new(
+                delay / gst::SECOND_VAL,
+                (delay % gst::SECOND_VAL) as u32,
+            ));
+
+            let bin = element.downcast::<Bin>().unwrap();
+            let fallbacksrc = bin.get_impl().downcast_ref::<FallbackSrc>().unwrap();
+            bin.catch_panic(|| (), |bin| fallbacksrc.restart_source(bin, source));
+        });
+    }
+
+    fn restart_source(&self, bin: &Bin, old_source: gst::Element) {
+        let _ = bin.remove(&old_source);
+
+        let uri = self.settings.lock().unwrap().uri.clone();
+        {
+            let mut state = self.state.lock().unwrap();
+            // Shut down in the meantime
+            if !state.retry_pending {
+                return;
+            }
+            state.retry_pending = false;
+        }
+
+        let uri = match uri {
+            None => return,
+            Some(uri) => uri,
+        };
+
+        match self.create_source(bin, &uri) {
+            Err(err) => {
+                gst_element_error!(bin, gst::ResourceError::Failed, ["{}", err]);
+            }
+            Ok(source) => {
+                self.state.lock().unwrap().source = Some(source);
+                self.start_source(bin);
+            }
+        }
+    }
+
+    fn setup(&self, bin: &Bin) -> Result<(), String> {
+        let settings = self.settings.lock().unwrap().clone();
+        let uri = match settings.uri {
+            None => return Err(String::from("No URI set")),
+            Some(ref uri) => uri.clone(),
+        };
+
+        let mut media_types = Vec::new();
+        if settings.video {
+            media_types.push(MediaType::Video);
+        }
+        if settings.audio {
+            media_types.push(MediaType::Audio);
+        }
+        if media_types.is_empty() {
+            return Err(String::from("Neither video nor audio enabled"));
+        }
+
+        for media_type in media_types {
+            let switch = gst::ElementFactory::make("fallbackswitch", None)
+                .ok_or_else(|| String::from("Failed to create fallbackswitch"))?;
+            switch.set_property("timeout", &settings.timeout).unwrap();
+            bin.add(&switch)
+                .map_err(|_| String::from("Failed to add fallbackswitch"))?;
+            self.state
+                .lock()
+                .unwrap()
+                .switches
+                .push((media_type, switch.clone()));
+
+            let fallback = self.create_fallback(bin, media_type, &settings)?;
+            let fallback_pad = fallback.get_static_pad("src").unwrap();
+            if fallback_pad.link(&switch.get_static_pad("fallback").unwrap())
+                != gst::PadLinkReturn::Ok
+            {
+                return Err(String::from("Failed to link fallback"));
+            }
+
+            let templ = bin.get_pad_template(media_type.get_pad_name()).unwrap();
+            let ghostpad = gst::GhostPad::new_from_template(
+                media_type.get_pad_name(),
+                &switch.get_static_pad("src").unwrap(),
+                &templ,
+            ).unwrap();
+            bin.add_pad(&ghostpad).unwrap();
+        }
+        bin.no_more_pads();
+
+        let source = self.create_source(bin, &uri)?;
+        self.state.lock().unwrap().source = Some(source);
+
+        Ok(())
+    }
+
+    fn teardown(&self, bin: &Bin) {
+        let state = {
+            let mut state = self.state.lock().unwrap();
+            ::std::mem::replace(&mut *state, State::default())
+        };
+
+        if let Some(source) = state.source {
+            let _ = source.set_state(gst::State::Null);
+        }
+
+        for element in bin.get_children() {
+            let _ = element.set_state(gst::State::Null);
+            let _ = bin.remove(&element);
+        }
+
+        for pad in bin.get_src_pads() {
+            let _ = bin.remove_pad(&pad);
+        }
+    }
+}
+
+impl ObjectImpl<Bin> for FallbackSrc {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("uri", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.uri = value.get();
+            }
+            Property::String("fallback-uri", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.fallback_uri = value.get();
+            }
+            Property::Boolean("video", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.video = value.get().unwrap();
+            }
+            Property::Boolean("audio", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.audio = value.get().unwrap();
+            }
+            Property::UInt64("timeout", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.timeout = value.get().unwrap();
+            }
+            Property::UInt64("retry-delay", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.retry_delay = value.get().unwrap();
+            }
+            Property::UInt64("max-retry-delay", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.max_retry_delay = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("uri", ..) => Ok(settings.uri.to_value()),
+            Property::String("fallback-uri", ..) => Ok(settings.fallback_uri.to_value()),
+            Property::Boolean("video", ..) => Ok(settings.video.to_value()),
+            Property::Boolean("audio", ..) => Ok(settings.audio.to_value()),
+            Property::UInt64("timeout", ..) => Ok(settings.timeout.to_value()),
+            Property::UInt64("retry-delay", ..) => Ok(settings.retry_delay.to_value()),
+            Property::UInt64("max-retry-delay", ..) => Ok(settings.max_retry_delay.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Bin> for FallbackSrc {
+    fn change_state(&self, bin: &Bin, transition: gst::StateChange) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: bin, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::NullToReady => if let Err(err) = self.setup(bin) {
+                gst_element_error!(bin, gst::ResourceError::Settings, ["{}", err]);
+                self.teardown(bin);
+                return gst::StateChangeReturn::Failure;
+            },
+            gst::StateChange::PausedToReady => {
+                let source = self.state.lock().unwrap().source.clone();
+                if let Some(source) = source {
+                    let _ = source.set_state(gst::State::Ready);
+                }
+            }
+            _ => (),
+        }
+
+        let ret = bin.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        match transition {
+            gst::StateChange::ReadyToPaused
+            | gst::StateChange::PausedToPlaying
+            | gst::StateChange::PlayingToPaused => {
+                self.start_source(bin);
+            }
+            gst::StateChange::ReadyToNull => {
+                self.teardown(bin);
+            }
+            _ => (),
+        }
+
+        ret
+    }
+}
+
+impl BinImpl<Bin> for FallbackSrc {
+    fn handle_message(&self, bin: &Bin, message: gst::Message) {
+        use gst::MessageView;
+
+        match message.view() {
+            MessageView::Error(ref err) => {
+                let from_source = {
+                    let state = self.state.lock().unwrap();
+                    match (message.get_src(), state.source.as_ref()) {
+                        (Some(src), Some(source)) => is_from(&src, source),
+                        _ => false,
+                    }
+                };
+
+                // Errors of the main source are handled by restarting it
+                if from_source {
+                    gst_warning!(self.cat, obj: bin, "Source failed: {}", err.get_error());
+                    self.schedule_retry(bin);
+                    return;
+                }
+            }
+            _ => (),
+        }
+
+        bin.parent_handle_message(message)
+    }
+}
+
+struct FallbackSrcStatic;
+
+impl ImplTypeStatic<Bin> for FallbackSrcStatic {
+    fn get_name(&self) -> &str {
+        "FallbackSrc"
+    }
+
+    fn new(&self, bin: &Bin) -> Box<BinImpl<Bin>> {
+        FallbackSrc::init(bin)
+    }
+
+    fn class_init(&self, klass: &mut BinClass) {
+        FallbackSrc::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let fallbacksrc_static = FallbackSrcStatic;
+    register_type(fallbacksrc_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let settings = Settings {
+            retry_delay: gst::SECOND_VAL,
+            max_retry_delay: 10 * gst::SECOND_VAL,
+            ..Settings::default()
+        };
+
+        let delays = (0..6)
+            .map(|n| get_retry_delay(&settings, n) / gst::SECOND_VAL)
+            .collect::<Vec<_>>();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+
+        let settings = Settings {
+            retry_delay: u64::MAX / 2 + 1,
+            max_retry_delay: u64::MAX,
+            ..Settings::default()
+        };
+        assert_eq!(get_retry_delay(&settings, 5), u64::MAX);
+    }
+}
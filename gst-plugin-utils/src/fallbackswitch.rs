@@ -0,0 +1,646 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+use std::u64;
+
+const DEFAULT_TIMEOUT: u64 = gst::SECOND_VAL;
+const DEFAULT_HEALTH_CHECK_WINDOW: u64 = 2 * gst::SECOND_VAL;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    timeout: u64,
+    health_check_window: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            timeout: DEFAULT_TIMEOUT,
+            health_check_window: DEFAULT_HEALTH_CHECK_WINDOW,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 3] = [
+    Property::UInt64(
+        "timeout",
+        "Timeout",
+        "Time in nanoseconds without primary data after which the fallback is used",
+        (0, u64::MAX),
+        DEFAULT_TIMEOUT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "health-check-window",
+        "Health Check Window",
+        "Time in nanoseconds the primary stream has to produce data without interruption \
+         before switching back to it",
+        (0, u64::MAX),
+        DEFAULT_HEALTH_CHECK_WINDOW,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "fallback-active",
+        "Fallback Active",
+        "Whether the fallback stream is currently output",
+        false,
+        PropertyMutability::Readable,
+    ),
+];
+
+// Switching behaviour:
+//
+// The "sink" pad is passed through to the src pad as long as it produces data. Once the
+// running time of the "fallback" pad is more than the timeout ahead of the end of the last
+// primary data, the output switches to the fallback stream at its next keyframe. Both streams
+// are expected to be synchronized to the same clock, e.g. by coming from live sources.
+//
+// Primary data that arrives while the fallback is active is dropped. Once the primary stream
+// produced data for the health check window without interruptions longer than the timeout,
+// the output switches back to it at its next keyframe. EOS on the primary pad switches to the
+// fallback until a new stream is started on it.
+//
+// All output is in a single time segment starting at running time 0, and data before the
+// running time that was already output is dropped.
+#[derive(Debug, Default)]
+struct Switch {
+    fallback_active: bool,
+    // Running time up to which the primary stream has data
+    primary_position: Option<u64>,
+    // Running time since which the primary stream has data without interruptions
+    primary_healthy_since: Option<u64>,
+    primary_eos: bool,
+}
+
+impl Switch {
+    // Returns whether primary data at the running time is output
+    fn handle_primary(
+        &mut self,
+        settings: &Settings,
+        running_time: u64,
+        end: u64,
+        keyframe: bool,
+    ) -> bool {
+        let interrupted = self
+            .primary_position
+            .map_or(true, |position| running_time > position + settings.timeout);
+        if interrupted || self.primary_healthy_since.is_none() {
+            self.primary_healthy_since = Some(running_time);
+        }
+        self.primary_position = Some(end);
+
+        if self.fallback_active {
+            let healthy_since = self.primary_healthy_since.unwrap();
+            if !keyframe || running_time < healthy_since + settings.health_check_window {
+                return false;
+            }
+            self.fallback_active = false;
+        }
+
+        true
+    }
+
+    // Returns whether fallback data at the running time is output
+    fn handle_fallback(&mut self, settings: &Settings, running_time: u64, keyframe: bool) -> bool {
+        if !self.fallback_active {
+            let timed_out = self.primary_eos
+                || running_time >= self.primary_position.unwrap_or(0) + settings.timeout;
+            if !timed_out || !keyframe {
+                return false;
+            }
+            self.fallback_active = true;
+            self.primary_healthy_since = None;
+        }
+
+        true
+    }
+}
+
+struct State {
+    switch: Switch,
+    primary_segment: gst::FormattedSegment<gst::ClockTime>,
+    fallback_segment: gst::FormattedSegment<gst::ClockTime>,
+    // Running time up to which data was output
+    position: gst::ClockTime,
+    fallback_eos: bool,
+    eos_sent: bool,
+    stream_started: bool,
+    output_caps: Option<gst::Caps>,
+    segment_pending: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            switch: Switch::default(),
+            primary_segment: gst::FormattedSegment::new(),
+            fallback_segment: gst::FormattedSegment::new(),
+            position: gst::CLOCK_TIME_NONE,
+            fallback_eos: false,
+            eos_sent: false,
+            stream_started: false,
+            output_caps: None,
+            segment_pending: true,
+        }
+    }
+}
+
+struct FallbackSwitch {
+    cat: gst::DebugCategory,
+    primary_pad: gst::Pad,
+    fallback_pad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    // Serializes the output of both streams, taken before the state
+    src_lock: Mutex<()>,
+}
+
+impl FallbackSwitch {
+    fn new(
+        _element: &Element,
+        primary_pad: gst::Pad,
+        fallback_pad: gst::Pad,
+        srcpad: gst::Pad,
+    ) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "fallbackswitch",
+                gst::DebugColorFlags::empty(),
+                "Fallback switch",
+            ),
+            primary_pad: primary_pad,
+            fallback_pad: fallback_pad,
+            srcpad: srcpad,
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+            src_lock: Mutex::new(()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Fallback Switch",
+            "Generic",
+            "Switches to a fallback stream if the primary stream stops producing data",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "fallback",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let primary_pad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("fallback").unwrap();
+        let fallback_pad = gst::Pad::new_from_template(&templ, "fallback");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        primary_pad.set_chain_function(|pad, parent, buffer| {
+            FallbackSwitch::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |fallbackswitch, element| fallbackswitch.sink_chain(pad, element, buffer, true),
+            )
+        });
+        primary_pad.set_event_function(|pad, parent, event| {
+            FallbackSwitch::catch_panic_pad_function(
+                parent,
+                || false,
+                |fallbackswitch, element| fallbackswitch.sink_event(pad, element, event, true),
+            )
+        });
+
+        fallback_pad.set_chain_function(|pad, parent, buffer| {
+            FallbackSwitch::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |fallbackswitch, element| fallbackswitch.sink_chain(pad, element, buffer, false),
+            )
+        });
+        fallback_pad.set_event_function(|pad, parent, event| {
+            FallbackSwitch::catch_panic_pad_function(
+                parent,
+                || false,
+                |fallbackswitch, element| fallbackswitch.sink_event(pad, element, event, false),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            FallbackSwitch::catch_panic_pad_function(
+                parent,
+                || false,
+                |fallbackswitch, element| fallbackswitch.src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&primary_pad).unwrap();
+        element.add_pad(&fallback_pad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, primary_pad, fallback_pad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let fallbackswitch = element.get_impl().downcast_ref::<FallbackSwitch>().unwrap();
+        element.catch_panic(fallback, |element| f(fallbackswitch, element))
+    }
+
+    // Events to push before the next buffer with the given caps
+    fn prepare_output(&self, state: &mut State, caps: Option<gst::Caps>) -> Vec<gst::Event> {
+        let mut events = Vec::new();
+
+        if caps.is_some() && caps != state.output_caps {
+            let caps = caps.unwrap();
+            events.push(gst::Event::new_caps(&caps).build());
+            state.output_caps = Some(caps);
+        }
+
+        if state.segment_pending {
+            let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+            events.push(gst::Event::new_segment(&segment).build());
+            state.segment_pending = false;
+        }
+
+        events
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        mut buffer: gst::Buffer,
+        primary: bool,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let pts = buffer.get_pts();
+        if pts.is_none() {
+            gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+            return gst::FlowReturn::Error;
+        }
+        let keyframe = !buffer.get_flags().contains(gst::BufferFlags::DELTA_UNIT);
+        let duration = buffer.get_duration();
+
+        let settings = *self.settings.lock().unwrap();
+        let _src_guard = self.src_lock.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        let (running_time, end, dts) = {
+            let segment = if primary {
+                &state.primary_segment
+            } else {
+                &state.fallback_segment
+            };
+
+            let running_time = match segment.to_running_time(pts).0 {
+                None => {
+                    gst_log!(self.cat, obj: pad, "Dropping buffer outside segment");
+                    return gst::FlowReturn::Ok;
+                }
+                Some(running_time) => running_time,
+            };
+            let end = if duration.is_some() {
+                segment.to_running_time(pts + duration).unwrap_or(running_time)
+            } else {
+                running_time
+            };
+
+            (running_time, end, segment.to_running_time(buffer.get_dts()))
+        };
+
+        let was_fallback_active = state.switch.fallback_active;
+        let mut output = if primary {
+            state.switch.handle_primary(&settings, running_time, end, keyframe)
+        } else {
+            state.switch.handle_fallback(&settings, running_time, keyframe)
+        };
+        let switched = was_fallback_active != state.switch.fallback_active;
+
+        if output && state.position.0.map_or(false, |position| running_time < position) {
+            gst_log!(self.cat, obj: pad, "Dropping buffer before output position");
+            output = false;
+        }
+
+        let events = if output {
+            state.position = end.into();
+            self.prepare_output(&mut state, pad.get_current_caps())
+        } else {
+            Vec::new()
+        };
+        drop(state);
+
+        if switched {
+            gst_info!(
+                self.cat,
+                obj: element,
+                "Switched to {} stream at {}",
+                if primary { "primary" } else { "fallback" },
+                gst::ClockTime::from(running_time)
+            );
+            self.notify(&element.clone().upcast(), "fallback-active");
+        }
+
+        if !output {
+            return gst::FlowReturn::Ok;
+        }
+
+        for event in events {
+            self.srcpad.push_event(event);
+        }
+
+        {
+            let buffer = buffer.make_mut();
+            buffer.set_pts(gst::ClockTime::from(running_time));
+            buffer.set_dts(dts);
+        }
+
+        gst_log!(self.cat, obj: &self.srcpad, "Pushing buffer {:?}", buffer);
+        self.srcpad.push(buffer)
+    }
+
+    fn sink_event(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        event: gst::Event,
+        primary: bool,
+    ) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::StreamStart(..) => {
+                // Only the first stream start is forwarded
+                let mut state = self.state.lock().unwrap();
+                if primary {
+                    // The primary stream was restarted after EOS
+                    state.switch.primary_eos = false;
+                }
+                if state.stream_started {
+                    return true;
+                }
+                state.stream_started = true;
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                // Output has its own segment
+                let mut state = self.state.lock().unwrap();
+                if primary {
+                    state.primary_segment = segment;
+                } else {
+                    state.fallback_segment = segment;
+                }
+                return true;
+            }
+            EventView::Caps(..) => {
+                // Sent before the next buffer
+                return true;
+            }
+            EventView::FlushStart(..) if !primary => return true,
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                if !primary {
+                    state.fallback_segment = gst::FormattedSegment::new();
+                    state.fallback_eos = false;
+                    return true;
+                }
+
+                state.primary_segment = gst::FormattedSegment::new();
+                state.switch.primary_position = None;
+                state.switch.primary_healthy_since = None;
+                state.switch.primary_eos = false;
+                state.position = gst::CLOCK_TIME_NONE;
+                state.eos_sent = false;
+                state.segment_pending = true;
+            }
+            EventView::Eos(..) => {
+                let _src_guard = self.src_lock.lock().unwrap();
+                let mut state = self.state.lock().unwrap();
+                let switched = primary && !state.switch.fallback_active;
+                if primary {
+                    gst_debug!(self.cat, obj: pad, "Primary stream finished");
+                    state.switch.primary_eos = true;
+                    state.switch.fallback_active = true;
+                } else {
+                    gst_debug!(self.cat, obj: pad, "Fallback stream finished");
+                    state.fallback_eos = true;
+                }
+
+                let send_eos = state.switch.primary_eos && state.fallback_eos && !state.eos_sent;
+                state.eos_sent = state.eos_sent || send_eos;
+                drop(state);
+
+                if switched {
+                    gst_info!(self.cat, obj: element, "Switched to fallback stream on EOS");
+                    self.notify(&element.clone().upcast(), "fallback-active");
+                }
+
+                if !send_eos {
+                    return true;
+                }
+            }
+            _ => {
+                // Everything else only from the primary stream
+                if !primary {
+                    return true;
+                }
+            }
+        }
+
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        // Both streams have to follow, the fallback can be needed at any time
+        self.fallback_pad.push_event(event.clone());
+        self.primary_pad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for FallbackSwitch {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt64("timeout", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.timeout = value.get().unwrap();
+            }
+            Property::UInt64("health-check-window", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.health_check_window = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt64("timeout", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.timeout.to_value())
+            }
+            Property::UInt64("health-check-window", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.health_check_window.to_value())
+            }
+            Property::Boolean("fallback-active", ..) => {
+                let state = self.state.lock().unwrap();
+                Ok(state.switch.fallback_active.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for FallbackSwitch {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct FallbackSwitchStatic;
+
+impl ImplTypeStatic<Element> for FallbackSwitchStatic {
+    fn get_name(&self) -> &str {
+        "FallbackSwitch"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        FallbackSwitch::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        FallbackSwitch::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let fallbackswitch_static = FallbackSwitchStatic;
+    register_type(fallbackswitch_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    fn test_switch() {
+        let settings = Settings {
+            timeout: 100 * MS,
+            health_check_window: 500 * MS,
+        };
+        let mut switch = Switch::default();
+
+        // Primary has data, fallback is not output
+        assert!(switch.handle_primary(&settings, 0, 40 * MS, true));
+        assert!(!switch.handle_fallback(&settings, 40 * MS, true));
+        assert!(switch.handle_primary(&settings, 40 * MS, 80 * MS, false));
+        assert!(!switch.handle_fallback(&settings, 160 * MS, true));
+
+        // Primary stopped, switch at the next fallback keyframe
+        assert!(!switch.handle_fallback(&settings, 200 * MS, false));
+        assert!(switch.handle_fallback(&settings, 240 * MS, true));
+        assert!(switch.fallback_active);
+        assert!(switch.handle_fallback(&settings, 280 * MS, false));
+
+        // Primary is back but has to be healthy for the window first
+        for i in 0..11 {
+            let running_time = 1000 * MS + i * 40 * MS;
+            assert!(!switch.handle_primary(&settings, running_time, running_time + 40 * MS, true));
+        }
+
+        // An interruption restarts the health check
+        for i in 0..13 {
+            let running_time = 1600 * MS + i * 40 * MS;
+            assert!(!switch.handle_primary(&settings, running_time, running_time + 40 * MS, true));
+        }
+        assert!(switch.fallback_active);
+
+        // Switch back at the next primary keyframe after the window
+        assert!(!switch.handle_primary(&settings, 2120 * MS, 2160 * MS, false));
+        assert!(switch.handle_primary(&settings, 2160 * MS, 2200 * MS, true));
+        assert!(!switch.fallback_active);
+        assert!(!switch.handle_fallback(&settings, 2200 * MS, true));
+    }
+}
@@ -0,0 +1,829 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use glib::prelude::*;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::pad::*;
+
+use std::collections::HashMap;
+use std::{f64, i64, u64};
+use std::sync::{Condvar, Mutex, Once, ONCE_INIT};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT: u64 = gst::SECOND_VAL;
+const DEFAULT_FILL_GAPS: bool = true;
+const DEFAULT_PAD_TIMEOUT: i64 = -1;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    timeout: u64,
+    fill_gaps: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            timeout: DEFAULT_TIMEOUT,
+            fill_gaps: DEFAULT_FILL_GAPS,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 3] = [
+    Property::UInt64(
+        "timeout",
+        "Timeout",
+        "Time each stream waits for the other streams to have data, unless set on its sink pad \
+         (0=unlimited)",
+        (0, u64::MAX),
+        DEFAULT_TIMEOUT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "fill-gaps",
+        "Fill Gaps",
+        "Send a gap event from the common start to the first data of streams starting later",
+        DEFAULT_FILL_GAPS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "start-running-time",
+        "Start Running Time",
+        "Common running time at which all streams start",
+        (0, u64::MAX),
+        u64::MAX,
+        PropertyMutability::Readable,
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct PadSettings {
+    timeout: i64,
+}
+
+impl Default for PadSettings {
+    fn default() -> Self {
+        PadSettings {
+            timeout: DEFAULT_PAD_TIMEOUT,
+        }
+    }
+}
+
+static PAD_PROPERTIES: [Property; 1] = [
+    Property::Int64(
+        "timeout",
+        "Timeout",
+        "Time this stream waits for the other streams to have data \
+         (-1=timeout of the element, 0=unlimited)",
+        (-1, i64::MAX),
+        DEFAULT_PAD_TIMEOUT,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+struct GroupSyncPad {
+    settings: Mutex<PadSettings>,
+}
+
+impl GroupSyncPad {
+    fn class_init(klass: &mut PadClass) {
+        klass.install_properties(&PAD_PROPERTIES);
+    }
+
+    fn init(_pad: &Pad) -> Box<PadImpl<Pad>> {
+        let imp = GroupSyncPad {
+            settings: Mutex::new(Default::default()),
+        };
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<Pad> for GroupSyncPad {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PAD_PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int64("timeout", ..) => settings.timeout = value.get().unwrap(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PAD_PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int64("timeout", ..) => Ok(settings.timeout.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl PadImpl<Pad> for GroupSyncPad {}
+
+struct GroupSyncPadStatic;
+
+impl ImplTypeStatic<Pad> for GroupSyncPadStatic {
+    fn get_name(&self) -> &str {
+        "GroupSyncPad"
+    }
+
+    fn new(&self, pad: &Pad) -> Box<PadImpl<Pad>> {
+        GroupSyncPad::init(pad)
+    }
+
+    fn class_init(&self, klass: &mut PadClass) {
+        GroupSyncPad::class_init(klass);
+    }
+}
+
+fn get_pad_type() -> glib::Type {
+    static ONCE: Once = ONCE_INIT;
+    static mut TYPE: glib::Type = glib::Type::Invalid;
+
+    ONCE.call_once(|| {
+        let groupsync_pad_static = GroupSyncPadStatic;
+        unsafe {
+            TYPE = register_type(groupsync_pad_static);
+        }
+    });
+
+    unsafe { TYPE }
+}
+
+fn get_pad_settings(pad: &gst::Pad) -> PadSettings {
+    let pad = pad.clone().downcast::<Pad>().unwrap();
+    let imp = pad.get_impl().downcast_ref::<GroupSyncPad>().unwrap();
+    let settings = *imp.settings.lock().unwrap();
+    settings
+}
+
+#[derive(Clone)]
+struct Stream {
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+}
+
+struct StreamState {
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    first_running_time: gst::ClockTime,
+    started: bool,
+    eos: bool,
+    flushing: bool,
+}
+
+impl Default for StreamState {
+    fn default() -> Self {
+        Self {
+            segment: gst::FormattedSegment::new(),
+            first_running_time: gst::CLOCK_TIME_NONE,
+            started: false,
+            eos: false,
+            flushing: false,
+        }
+    }
+}
+
+// Synchronization behaviour:
+//
+// Each stream blocks on its first buffer (or gap) until all other streams received their first
+// data too, or until the timeout since its first data expired. The timeout can be set for each
+// stream with the "timeout" property of its sink pad, otherwise the one of the element is used.
+// The common start running time is then the maximum of the first running times of all streams
+// that have data by then.
+//
+// All data ending before the common start is dropped. Streams that start later, including
+// streams that did not have data in time, get a gap event from the common start to their first
+// data if fill-gaps is enabled.
+struct State {
+    streams: HashMap<gst::Pad, StreamState>,
+    start_running_time: gst::ClockTime,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            streams: HashMap::new(),
+            start_running_time: gst::CLOCK_TIME_NONE,
+        }
+    }
+}
+
+impl State {
+    fn try_decide_start(&mut self, timed_out: bool) -> bool {
+        if self.start_running_time.is_some() {
+            return true;
+        }
+
+        let all_have_data = self.streams
+            .values()
+            .all(|s| s.first_running_time.is_some() || s.eos);
+        if !all_have_data && !timed_out {
+            return false;
+        }
+
+        self.start_running_time = self.streams
+            .values()
+            .filter(|s| s.first_running_time.is_some())
+            .map(|s| s.first_running_time)
+            .max()
+            .unwrap_or(gst::CLOCK_TIME_NONE);
+
+        self.start_running_time.is_some()
+    }
+}
+
+#[derive(Debug)]
+enum HandleResult {
+    Pass(Option<gst::Event>),
+    Drop,
+    Flushing,
+}
+
+struct GroupSync {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    cond: Condvar,
+    pads: Mutex<(HashMap<gst::Pad, Stream>, u32)>,
+}
+
+impl GroupSync {
+    fn new(_element: &Element) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "groupsync",
+                gst::DebugColorFlags::empty(),
+                "Stream group synchronizer",
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+            cond: Condvar::new(),
+            pads: Mutex::new((HashMap::new(), 0)),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Group Sync",
+            "Generic",
+            "Aligns the start of multiple streams to a common running time",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let src_pad_template = gst::PadTemplate::new(
+            "src_%u",
+            gst::PadDirection::Src,
+            gst::PadPresence::Sometimes,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let groupsync = element.get_impl().downcast_ref::<GroupSync>().unwrap();
+        element.catch_panic(fallback, |element| f(groupsync, element))
+    }
+
+    fn set_pad_functions(sinkpad: &gst::Pad, srcpad: &gst::Pad) {
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            GroupSync::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |groupsync, element| groupsync.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            GroupSync::catch_panic_pad_function(
+                parent,
+                || false,
+                |groupsync, element| groupsync.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            GroupSync::catch_panic_pad_function(
+                parent,
+                || false,
+                |groupsync, element| groupsync.sink_query(pad, element, query),
+            )
+        });
+        sinkpad.set_iterate_internal_links_function(|pad, parent| {
+            GroupSync::catch_panic_pad_function(
+                parent,
+                || gst::Iterator::from_vec(vec![]),
+                |groupsync, element| groupsync.iterate_internal_links(pad, element),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            GroupSync::catch_panic_pad_function(
+                parent,
+                || false,
+                |groupsync, element| groupsync.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            GroupSync::catch_panic_pad_function(
+                parent,
+                || false,
+                |groupsync, element| groupsync.src_query(pad, element, query),
+            )
+        });
+        srcpad.set_iterate_internal_links_function(|pad, parent| {
+            GroupSync::catch_panic_pad_function(
+                parent,
+                || gst::Iterator::from_vec(vec![]),
+                |groupsync, element| groupsync.iterate_internal_links(pad, element),
+            )
+        });
+    }
+
+    fn get_stream(&self, element: &Element, pad: &gst::Pad) -> Option<Stream> {
+        match self.pads.lock().unwrap().0.get(pad) {
+            None => {
+                gst_element_error!(
+                    element,
+                    gst::CoreError::Pad,
+                    ["Unknown pad {:?}", pad.get_name()]
+                );
+                None
+            }
+            Some(stream) => Some(stream.clone()),
+        }
+    }
+
+    // Waits until the common start is known and decides what to do with data at
+    // pts/duration. Returns a gap event to send before the data if needed.
+    fn handle_data(
+        &self,
+        pad: &gst::Pad,
+        pts: gst::ClockTime,
+        duration: gst::ClockTime,
+    ) -> HandleResult {
+        let settings = *self.settings.lock().unwrap();
+        let timeout = match get_pad_settings(pad).timeout {
+            timeout if timeout < 0 => settings.timeout,
+            timeout => timeout as u64,
+        };
+        let mut state = self.state.lock().unwrap();
+
+        let (running_time, running_time_end) = {
+            let stream_state = state.streams.get_mut(pad).unwrap();
+            if stream_state.flushing {
+                return HandleResult::Flushing;
+            }
+
+            let running_time = stream_state.segment.to_running_time(pts);
+            let running_time_end = if duration.is_some() {
+                stream_state.segment.to_running_time(pts + duration)
+            } else {
+                running_time
+            };
+
+            if stream_state.first_running_time.is_none() {
+                gst_debug!(self.cat, obj: pad, "First running time {}", running_time);
+                stream_state.first_running_time = running_time;
+            }
+
+            (running_time, running_time_end)
+        };
+
+        let deadline = if timeout > 0 {
+            Some(
+                Instant::now()
+                    + Duration::new(
+                        timeout / gst::SECOND_VAL,
+                        (timeout % gst::SECOND_VAL) as u32,
+                    ),
+            )
+        } else {
+            None
+        };
+
+        loop {
+            if state.streams.get(pad).map(|s| s.flushing).unwrap_or(true) {
+                return HandleResult::Flushing;
+            }
+
+            let timed_out = deadline.map(|d| Instant::now() >= d).unwrap_or(false);
+            if timed_out {
+                gst_debug!(self.cat, obj: pad, "Timed out waiting for other streams");
+            }
+
+            if state.try_decide_start(timed_out) {
+                self.cond.notify_all();
+                break;
+            }
+
+            state = match deadline {
+                None => self.cond.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    let timeout = if deadline > now {
+                        deadline - now
+                    } else {
+                        Duration::from_millis(0)
+                    };
+                    self.cond.wait_timeout(state, timeout).unwrap().0
+                }
+            };
+        }
+
+        let start = state.start_running_time;
+        let stream_state = state.streams.get_mut(pad).unwrap();
+
+        if running_time_end.is_some() && running_time_end <= start && running_time < start {
+            gst_log!(
+                self.cat,
+                obj: pad,
+                "Dropping data at {} before start {}",
+                running_time,
+                start
+            );
+            return HandleResult::Drop;
+        }
+
+        if stream_state.started {
+            return HandleResult::Pass(None);
+        }
+        stream_state.started = true;
+
+        if !settings.fill_gaps || running_time.is_none() || running_time <= start {
+            return HandleResult::Pass(None);
+        }
+
+        // Only rate 1.0 segments are accepted, so running time differences map 1:1 to
+        // stream time differences
+        let gap_duration = running_time - start;
+        let gap_pts = pts - gap_duration;
+        gst_debug!(
+            self.cat,
+            obj: pad,
+            "Filling gap from {} with duration {}",
+            gap_pts,
+            gap_duration
+        );
+
+        HandleResult::Pass(Some(gst::Event::new_gap(gap_pts, gap_duration).build()))
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let stream = match self.get_stream(element, pad) {
+            None => return gst::FlowReturn::Error,
+            Some(stream) => stream,
+        };
+
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let pts = buffer.get_pts();
+        if pts.is_none() {
+            gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+            return gst::FlowReturn::Error;
+        }
+
+        match self.handle_data(pad, pts, buffer.get_duration()) {
+            HandleResult::Flushing => gst::FlowReturn::Flushing,
+            HandleResult::Drop => gst::FlowReturn::Ok,
+            HandleResult::Pass(gap) => {
+                if let Some(gap) = gap {
+                    stream.srcpad.push_event(gap);
+                }
+                stream.srcpad.push(buffer)
+            }
+        }
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        let stream = match self.get_stream(element, pad) {
+            None => return false,
+            Some(stream) => stream,
+        };
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::FlushStart(..) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream_state) = state.streams.get_mut(pad) {
+                    stream_state.flushing = true;
+                }
+                self.cond.notify_all();
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream_state) = state.streams.get_mut(pad) {
+                    stream_state.flushing = false;
+                    stream_state.eos = false;
+                }
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(segment) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            [
+                                "Only Time segments supported, got {:?}",
+                                segment.get_format()
+                            ]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                if (segment.get_rate() - 1.0).abs() > f64::EPSILON {
+                    gst_element_error!(
+                        element,
+                        gst::StreamError::Format,
+                        [
+                            "Only rate==1.0 segments supported, got {:?}",
+                            segment.get_rate()
+                        ]
+                    );
+                    return false;
+                }
+
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream_state) = state.streams.get_mut(pad) {
+                    gst_debug!(self.cat, obj: pad, "Got new Segment {:?}", segment);
+                    stream_state.segment = segment;
+                }
+            }
+            EventView::Gap(e) => {
+                let (pts, duration) = e.get();
+                match self.handle_data(pad, pts, duration) {
+                    HandleResult::Flushing | HandleResult::Drop => return true,
+                    HandleResult::Pass(gap) => if let Some(gap) = gap {
+                        stream.srcpad.push_event(gap);
+                    },
+                }
+            }
+            EventView::Eos(..) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream_state) = state.streams.get_mut(pad) {
+                    stream_state.eos = true;
+                }
+                // Streams without data that are EOS are not waited for anymore
+                self.cond.notify_all();
+            }
+            _ => (),
+        }
+
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        stream.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        let stream = match self.get_stream(element, pad) {
+            None => return false,
+            Some(stream) => stream,
+        };
+
+        gst_log!(self.cat, obj: pad, "Forwarding query {:?}", query);
+        stream.srcpad.peer_query(query)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        let stream = match self.get_stream(element, pad) {
+            None => return false,
+            Some(stream) => stream,
+        };
+
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        stream.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        let stream = match self.get_stream(element, pad) {
+            None => return false,
+            Some(stream) => stream,
+        };
+
+        gst_log!(self.cat, obj: pad, "Forwarding query {:?}", query);
+        stream.sinkpad.peer_query(query)
+    }
+
+    fn iterate_internal_links(&self, pad: &gst::Pad, element: &Element) -> gst::Iterator<gst::Pad> {
+        let stream = match self.get_stream(element, pad) {
+            None => return gst::Iterator::from_vec(vec![]),
+            Some(stream) => stream,
+        };
+
+        if pad == &stream.srcpad {
+            gst::Iterator::from_vec(vec![stream.sinkpad.clone()])
+        } else {
+            gst::Iterator::from_vec(vec![stream.srcpad.clone()])
+        }
+    }
+}
+
+impl ObjectImpl<Element> for GroupSync {
+    fn set_property(&self, obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let element = obj.clone().downcast::<Element>().unwrap();
+
+        match *prop {
+            Property::UInt64("timeout", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                let timeout = value.get().unwrap();
+                gst_debug!(
+                    self.cat,
+                    obj: &element,
+                    "Setting timeout from {} to {}",
+                    settings.timeout,
+                    timeout
+                );
+                settings.timeout = timeout;
+            }
+            Property::Boolean("fill-gaps", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.fill_gaps = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt64("timeout", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.timeout.to_value())
+            }
+            Property::Boolean("fill-gaps", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.fill_gaps.to_value())
+            }
+            Property::UInt64("start-running-time", ..) => {
+                let state = self.state.lock().unwrap();
+                Ok(state.start_running_time.unwrap_or(u64::MAX).to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for GroupSync {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                let mut state = self.state.lock().unwrap();
+                state.start_running_time = gst::CLOCK_TIME_NONE;
+                for stream_state in state.streams.values_mut() {
+                    *stream_state = StreamState::default();
+                }
+            }
+            gst::StateChange::PausedToReady => {
+                let mut state = self.state.lock().unwrap();
+                for stream_state in state.streams.values_mut() {
+                    stream_state.flushing = true;
+                }
+                self.cond.notify_all();
+            }
+            _ => (),
+        }
+
+        element.parent_change_state(transition)
+    }
+
+    fn request_new_pad(
+        &self,
+        element: &Element,
+        _templ: &gst::PadTemplate,
+        _name: Option<String>,
+        _caps: Option<&gst::CapsRef>,
+    ) -> Option<gst::Pad> {
+        let mut pads = self.pads.lock().unwrap();
+        let (ref mut pads, ref mut pad_count) = *pads;
+
+        let id = *pad_count;
+        *pad_count += 1;
+
+        let templ = element.get_pad_template("sink_%u").unwrap();
+        let sinkpad = Pad::new_from_template(get_pad_type(), &templ, &format!("sink_{}", id))
+            .upcast::<gst::Pad>();
+
+        let templ = element.get_pad_template("src_%u").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, format!("src_{}", id).as_str());
+
+        GroupSync::set_pad_functions(&sinkpad, &srcpad);
+
+        // New streams joining after the start was decided simply start late
+        self.state
+            .lock()
+            .unwrap()
+            .streams
+            .insert(sinkpad.clone(), StreamState::default());
+
+        sinkpad.set_active(true).unwrap();
+        srcpad.set_active(true).unwrap();
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let stream = Stream {
+            sinkpad: sinkpad.clone(),
+            srcpad: srcpad,
+        };
+
+        pads.insert(stream.sinkpad.clone(), stream.clone());
+        pads.insert(stream.srcpad.clone(), stream);
+
+        Some(sinkpad)
+    }
+
+    fn release_pad(&self, element: &Element, pad: &gst::Pad) {
+        let mut pads = self.pads.lock().unwrap();
+        let (ref mut pads, _) = *pads;
+
+        let stream = match pads.get(pad) {
+            None => return,
+            Some(stream) => stream.clone(),
+        };
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.streams.remove(&stream.sinkpad);
+            // Others might be waiting for this stream
+            self.cond.notify_all();
+        }
+
+        stream.srcpad.set_active(false).unwrap();
+        stream.sinkpad.set_active(false).unwrap();
+
+        element.remove_pad(&stream.sinkpad).unwrap();
+        element.remove_pad(&stream.srcpad).unwrap();
+
+        pads.remove(&stream.sinkpad).unwrap();
+        pads.remove(&stream.srcpad).unwrap();
+    }
+}
+
+struct GroupSyncStatic;
+
+impl ImplTypeStatic<Element> for GroupSyncStatic {
+    fn get_name(&self) -> &str {
+        "GroupSync"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        GroupSync::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        GroupSync::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let groupsync_static = GroupSyncStatic;
+    register_type(groupsync_static)
+}
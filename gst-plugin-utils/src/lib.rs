@@ -0,0 +1,75 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate byte_slice_cast;
+extern crate glib;
+#[macro_use]
+extern crate gst_plugin;
+#[macro_use]
+extern crate gstreamer as gst;
+extern crate gstreamer_audio as gst_audio;
+extern crate gstreamer_sys as gst_ffi;
+extern crate gstreamer_video as gst_video;
+extern crate gstreamer_video_sys as gst_video_ffi;
+extern crate image;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+use gst_plugin::registration::*;
+
+mod bookmark;
+mod brokensignaldetect;
+mod capsrouter;
+mod discover;
+mod edlbin;
+mod fallbacksrc;
+mod fallbackswitch;
+mod groupsync;
+mod livesync;
+mod metachannel;
+mod metarecv;
+mod metasend;
+mod netprobe;
+mod splicer;
+mod splitmuxsink;
+mod spritegen;
+mod uriplaylistbin;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    ElementRegistration::new(plugin)
+        .element("bookmark", RANK_NONE, bookmark::get_type())
+        .element("brokensignaldetect", RANK_NONE, brokensignaldetect::get_type())
+        .element("capsrouter", RANK_NONE, capsrouter::get_type())
+        .element("discover", RANK_NONE, discover::get_type())
+        .element("edlbin", RANK_NONE, edlbin::get_type())
+        .element("fallbacksrc", RANK_NONE, fallbacksrc::get_type())
+        .element("fallbackswitch", RANK_NONE, fallbackswitch::get_type())
+        .element("groupsync", RANK_NONE, groupsync::get_type())
+        .element("livesync", RANK_NONE, livesync::get_type())
+        .element("metarecv", RANK_NONE, metarecv::get_type())
+        .element("metasend", RANK_NONE, metasend::get_type())
+        .element("netprobe", RANK_NONE, netprobe::get_type())
+        .element("splicer", RANK_NONE, splicer::get_type())
+        .element("rssplitmuxsink", RANK_NONE, splitmuxsink::get_type())
+        .element("spritegen", RANK_NONE, spritegen::get_type())
+        .element("uriplaylistbin", RANK_NONE, uriplaylistbin::get_type())
+        .register()
+}
+
+plugin_define!(
+    "rsutils",
+    "Rust Utility Plugin",
+    plugin_init,
+    "MIT/X11",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2018-01-15"
+);
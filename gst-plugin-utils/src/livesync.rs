@@ -0,0 +1,887 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_audio;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::task::start_pad_task;
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::u64;
+
+const DEFAULT_LATENCY: u64 = 100 * gst::MSECOND_VAL;
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    latency: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            latency: DEFAULT_LATENCY,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 5] = [
+    Property::UInt64(
+        "latency",
+        "Latency",
+        "Time in nanoseconds input data is waited for before the previous data is repeated",
+        (0, u64::MAX),
+        DEFAULT_LATENCY,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "in",
+        "In",
+        "Number of input buffers",
+        (0, u64::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+    Property::UInt64(
+        "out",
+        "Out",
+        "Number of output buffers",
+        (0, u64::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+    Property::UInt64(
+        "drop",
+        "Drop",
+        "Number of dropped input buffers",
+        (0, u64::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+    Property::UInt64(
+        "duplicate",
+        "Duplicate",
+        "Number of inserted repeated or silent buffers",
+        (0, u64::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+];
+
+// Output behaviour:
+//
+// Input buffers are queued with their running time and are output from a separate thread.
+// Starting with the first buffer, every output buffer is waited for on the clock until its
+// running time plus the latency. If the next input buffer belongs to that time, it is output.
+// Otherwise the previous video frame is repeated, or silence of the same duration as the
+// previous audio buffer is inserted. Input buffers whose time was already covered by the output
+// are dropped, so the output is always continuous and paced by the clock.
+//
+// All output is in a single time segment with the running time as timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    // The buffer ends before the output position
+    Late,
+    // The buffer is output at the output position
+    Current,
+    // The buffer belongs to a later output position
+    Future,
+}
+
+fn get_slot(running_time: u64, duration: u64, position: u64) -> Slot {
+    if running_time + duration <= position {
+        Slot::Late
+    } else if running_time < position + duration / 2 + 1 {
+        Slot::Current
+    } else {
+        Slot::Future
+    }
+}
+
+enum Item {
+    Buffer {
+        buffer: gst::Buffer,
+        running_time: u64,
+        duration: u64,
+    },
+    Event(gst::Event),
+}
+
+struct State {
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    // Caps of the queued data
+    in_audio_info: Option<gst_audio::AudioInfo>,
+    in_video_info: Option<gst_video::VideoInfo>,
+    // Caps of the output data
+    out_audio_info: Option<gst_audio::AudioInfo>,
+    queue: VecDeque<Item>,
+    // Running time of the next output buffer
+    position: Option<u64>,
+    last: Option<(gst::Buffer, u64)>,
+    clock_id: Option<gst::ClockId>,
+    playing: bool,
+    flushing: bool,
+    eos: bool,
+    segment_pending: bool,
+    num_in: u64,
+    num_out: u64,
+    num_drop: u64,
+    num_duplicate: u64,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            segment: gst::FormattedSegment::new(),
+            in_audio_info: None,
+            in_video_info: None,
+            out_audio_info: None,
+            queue: VecDeque::new(),
+            position: None,
+            last: None,
+            clock_id: None,
+            playing: false,
+            flushing: true,
+            eos: false,
+            segment_pending: true,
+            num_in: 0,
+            num_out: 0,
+            num_drop: 0,
+            num_duplicate: 0,
+        }
+    }
+}
+
+impl State {
+    fn reset(&mut self) {
+        self.segment = gst::FormattedSegment::new();
+        self.queue.clear();
+        self.position = None;
+        self.last = None;
+        self.eos = false;
+        self.segment_pending = true;
+    }
+
+    fn unschedule(&mut self) {
+        if let Some(clock_id) = self.clock_id.take() {
+            clock_id.unschedule();
+        }
+    }
+
+    // Running time of the first queued buffer, or of the next output buffer
+    fn get_start(&self) -> Option<u64> {
+        self.position.or_else(|| {
+            self.queue
+                .iter()
+                .filter_map(|item| match *item {
+                    Item::Buffer { running_time, .. } => Some(running_time),
+                    Item::Event(..) => None,
+                })
+                .next()
+        })
+    }
+
+    fn get_duration(&self, buffer: &gst::Buffer) -> Option<u64> {
+        if let Some(duration) = buffer.get_duration().0 {
+            return Some(duration);
+        }
+
+        if let Some(ref info) = self.in_audio_info {
+            let frames = (buffer.get_size() / info.bpf() as usize) as u64;
+            return Some(frames * gst::SECOND_VAL / u64::from(info.rate()));
+        }
+
+        if let Some(ref info) = self.in_video_info {
+            let fps = info.fps();
+            if *fps.numer() > 0 && *fps.denom() > 0 {
+                return Some(gst::SECOND_VAL * *fps.denom() as u64 / *fps.numer() as u64);
+            }
+        }
+
+        None
+    }
+}
+
+fn create_silence(info: &gst_audio::AudioInfo, size: usize) -> gst::Buffer {
+    let format_info = info.format_info();
+    let width = (format_info.width() / 8) as usize;
+    let silence = format_info.silence();
+
+    let mut data = vec![0; size];
+    for sample in data.chunks_mut(width) {
+        let n = sample.len();
+        sample.copy_from_slice(&silence[..n]);
+    }
+
+    gst::Buffer::from_mut_slice(data).unwrap()
+}
+
+struct LiveSync {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+impl LiveSync {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "livesync",
+                gst::DebugColorFlags::empty(),
+                "Live stream synchronizer",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+            cond: Condvar::new(),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Live Synchronizer",
+            "Generic",
+            "Outputs a continuous live stream by repeating or dropping input data",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            LiveSync::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |livesync, element| livesync.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            LiveSync::catch_panic_pad_function(
+                parent,
+                || false,
+                |livesync, element| livesync.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            LiveSync::catch_panic_pad_function(
+                parent,
+                || false,
+                |livesync, element| livesync.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_activatemode_function(|pad, parent, mode, active| {
+            LiveSync::catch_panic_pad_function(
+                parent,
+                || false,
+                |livesync, _element| livesync.src_activatemode(pad, mode, active),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            LiveSync::catch_panic_pad_function(
+                parent,
+                || false,
+                |livesync, element| livesync.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            LiveSync::catch_panic_pad_function(
+                parent,
+                || false,
+                |livesync, element| livesync.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let livesync = element.get_impl().downcast_ref::<LiveSync>().unwrap();
+        element.catch_panic(fallback, |element| f(livesync, element))
+    }
+
+    fn start_task(pad: &gst::Pad) -> bool {
+        let task_pad = pad.clone();
+        start_pad_task(pad, move || {
+            let ret = LiveSync::catch_panic_pad_function(
+                &task_pad.get_parent(),
+                || gst::FlowReturn::Error,
+                |livesync, element| livesync.src_loop(element),
+            );
+            if ret != gst::FlowReturn::Ok {
+                let _ = task_pad.pause_task();
+            }
+        })
+    }
+
+    fn src_activatemode(&self, pad: &gst::Pad, mode: gst::PadMode, active: bool) -> bool {
+        if mode != gst::PadMode::Push {
+            return false;
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.flushing = !active;
+            state.unschedule();
+            self.cond.notify_all();
+        }
+
+        if active {
+            LiveSync::start_task(pad)
+        } else {
+            pad.stop_task().is_ok()
+        }
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let latency = self.settings.lock().unwrap().latency;
+        let mut state = self.state.lock().unwrap();
+
+        let running_time = match state.segment.to_running_time(buffer.get_pts()).0 {
+            None => {
+                gst_log!(self.cat, obj: pad, "Dropping buffer outside segment");
+                return gst::FlowReturn::Ok;
+            }
+            Some(running_time) => running_time,
+        };
+        let duration = match state.get_duration(&buffer) {
+            None => {
+                gst_element_error!(
+                    element,
+                    gst::StreamError::Format,
+                    ["Buffer without duration"]
+                );
+                return gst::FlowReturn::Error;
+            }
+            Some(duration) => duration,
+        };
+
+        // Block upstream while the queue is longer than the latency, upstream is faster than
+        // the output otherwise
+        loop {
+            if state.flushing {
+                return gst::FlowReturn::Flushing;
+            }
+            if state.eos {
+                return gst::FlowReturn::Eos;
+            }
+
+            let start = state.get_start();
+            if start.map_or(true, |start| running_time <= start + latency + duration) {
+                break;
+            }
+
+            state = self.cond.wait(state).unwrap();
+        }
+
+        state.num_in += 1;
+        state.queue.push_back(Item::Buffer {
+            buffer: buffer,
+            running_time: running_time,
+            duration: duration,
+        });
+        self.cond.notify_all();
+
+        gst::FlowReturn::Ok
+    }
+
+    fn src_loop(&self, element: &Element) -> gst::FlowReturn {
+        let latency = self.settings.lock().unwrap().latency;
+        let mut state = self.state.lock().unwrap();
+
+        // Wait for data and for being allowed to output
+        loop {
+            if state.flushing {
+                return gst::FlowReturn::Flushing;
+            }
+
+            let has_event = match state.queue.front() {
+                Some(&Item::Event(..)) => true,
+                _ => false,
+            };
+            if has_event {
+                return self.forward_event(state);
+            }
+
+            let can_output = state.playing && (state.position.is_some() || !state.queue.is_empty());
+            if can_output {
+                break;
+            }
+
+            state = self.cond.wait(state).unwrap();
+        }
+
+        let position = match state.position {
+            Some(position) => position,
+            None => match state.queue.front() {
+                Some(&Item::Buffer { running_time, .. }) => running_time,
+                _ => unreachable!(),
+            },
+        };
+
+        let clock_id = match (element.get_clock(), element.get_base_time().0) {
+            (Some(clock), Some(base_time)) => {
+                clock.new_single_shot_id(gst::ClockTime::from(base_time + position + latency))
+            }
+            _ => None,
+        };
+        let clock_id = match clock_id {
+            None => {
+                gst_warning!(self.cat, obj: element, "No clock to wait on");
+                state = self.cond.wait(state).unwrap();
+                return if state.flushing {
+                    gst::FlowReturn::Flushing
+                } else {
+                    gst::FlowReturn::Ok
+                };
+            }
+            Some(clock_id) => clock_id,
+        };
+
+        state.clock_id = Some(clock_id.clone());
+        drop(state);
+
+        gst_log!(
+            self.cat,
+            obj: element,
+            "Waiting for output at {}",
+            gst::ClockTime::from(position)
+        );
+        let (clock_ret, _) = clock_id.wait();
+
+        let mut state = self.state.lock().unwrap();
+        state.clock_id = None;
+        if state.flushing {
+            return gst::FlowReturn::Flushing;
+        }
+        if clock_ret == gst::ClockReturn::Unscheduled {
+            return gst::FlowReturn::Ok;
+        }
+
+        // Drop everything that was already covered by the output
+        loop {
+            let slot = match state.queue.front() {
+                Some(&Item::Buffer {
+                    running_time,
+                    duration,
+                    ..
+                }) => get_slot(running_time, duration, position),
+                _ => break,
+            };
+            if slot != Slot::Late {
+                break;
+            }
+
+            gst_debug!(self.cat, obj: element, "Dropping late buffer");
+            state.queue.pop_front();
+            state.num_drop += 1;
+            self.cond.notify_all();
+        }
+
+        let is_current = match state.queue.front() {
+            Some(&Item::Buffer {
+                running_time,
+                duration,
+                ..
+            }) => get_slot(running_time, duration, position) == Slot::Current,
+            _ => false,
+        };
+
+        let (mut buffer, duration) = if is_current {
+            match state.queue.pop_front() {
+                Some(Item::Buffer {
+                    buffer, duration, ..
+                }) => {
+                    self.cond.notify_all();
+                    state.last = Some((buffer.clone(), duration));
+                    (buffer, duration)
+                }
+                _ => unreachable!(),
+            }
+        } else {
+            let last = state.last.clone();
+            let (last_buffer, duration) = match last {
+                // Nothing output yet, the next buffer starts the output
+                None => return gst::FlowReturn::Ok,
+                Some(last) => last,
+            };
+
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "No input for {}, repeating previous data",
+                gst::ClockTime::from(position)
+            );
+            state.num_duplicate += 1;
+
+            let buffer = match state.out_audio_info {
+                Some(ref info) => {
+                    let mut buffer = create_silence(info, last_buffer.get_size());
+                    buffer.get_mut().unwrap().set_flags(gst::BufferFlags::GAP);
+                    buffer
+                }
+                None => last_buffer,
+            };
+            (buffer, duration)
+        };
+
+        {
+            let buffer = buffer.make_mut();
+            buffer.set_pts(gst::ClockTime::from(position));
+            buffer.set_dts(gst::CLOCK_TIME_NONE);
+            buffer.set_duration(gst::ClockTime::from(duration));
+        }
+
+        state.position = Some(position + duration);
+        state.num_out += 1;
+        let segment_pending = state.segment_pending;
+        state.segment_pending = false;
+        drop(state);
+
+        if segment_pending {
+            let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+            self.srcpad.push_event(gst::Event::new_segment(&segment).build());
+        }
+
+        gst_log!(self.cat, obj: &self.srcpad, "Pushing buffer {:?}", buffer);
+        self.srcpad.push(buffer)
+    }
+
+    // Forwards the serialized event at the front of the queue
+    fn forward_event(&self, mut state: ::std::sync::MutexGuard<State>) -> gst::FlowReturn {
+        use gst::EventView;
+
+        let event = match state.queue.pop_front() {
+            Some(Item::Event(event)) => event,
+            _ => unreachable!(),
+        };
+        self.cond.notify_all();
+
+        let mut events = Vec::new();
+        let mut eos = false;
+        match event.view() {
+            EventView::Caps(e) => {
+                state.out_audio_info = gst_audio::AudioInfo::from_caps(e.get_caps());
+            }
+            EventView::Eos(..) => {
+                eos = true;
+                if state.segment_pending {
+                    state.segment_pending = false;
+                    let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+                    events.push(gst::Event::new_segment(&segment).build());
+                }
+            }
+            _ => (),
+        }
+        drop(state);
+
+        events.push(event);
+        for event in events {
+            self.srcpad.push_event(event);
+        }
+
+        if eos {
+            gst::FlowReturn::Eos
+        } else {
+            gst::FlowReturn::Ok
+        }
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                // Output has its own segment
+                self.state.lock().unwrap().segment = segment;
+                return true;
+            }
+            EventView::Gap(..) => {
+                // Gaps are filled by the output
+                return true;
+            }
+            EventView::FlushStart(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.flushing = true;
+                state.unschedule();
+                self.cond.notify_all();
+                drop(state);
+
+                let ret = self.srcpad.push_event(event);
+                let _ = self.srcpad.pause_task();
+                return ret;
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.reset();
+                state.flushing = false;
+                drop(state);
+
+                let ret = self.srcpad.push_event(event);
+                LiveSync::start_task(&self.srcpad);
+                return ret;
+            }
+            _ => (),
+        }
+
+        if !event.is_serialized() {
+            return self.srcpad.push_event(event);
+        }
+
+        // Serialized events are output in order with the buffers
+        let mut state = self.state.lock().unwrap();
+        if state.flushing {
+            return false;
+        }
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let caps = e.get_caps();
+                state.in_audio_info = gst_audio::AudioInfo::from_caps(caps);
+                state.in_video_info = gst_video::VideoInfo::from_caps(caps);
+            }
+            EventView::Eos(..) => {
+                state.eos = true;
+            }
+            _ => (),
+        }
+
+        state.queue.push_back(Item::Event(event));
+        self.cond.notify_all();
+
+        true
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+
+        let is_latency = match query.view() {
+            QueryView::Latency(..) => true,
+            _ => false,
+        };
+        if is_latency {
+            if !self.sinkpad.peer_query(query) {
+                return false;
+            }
+
+            // Always live, the output is waited for on the clock
+            let latency = gst::ClockTime::from(self.settings.lock().unwrap().latency);
+            if let QueryView::Latency(ref mut q) = query.view_mut() {
+                let (_, min, max) = q.get_result();
+                q.set(true, min + latency, max + latency);
+            }
+            return true;
+        }
+
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for LiveSync {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt64("latency", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.latency = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::UInt64("latency", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.latency.to_value())
+            }
+            Property::UInt64("in", ..) => Ok(self.state.lock().unwrap().num_in.to_value()),
+            Property::UInt64("out", ..) => Ok(self.state.lock().unwrap().num_out.to_value()),
+            Property::UInt64("drop", ..) => Ok(self.state.lock().unwrap().num_drop.to_value()),
+            Property::UInt64("duplicate", ..) => {
+                Ok(self.state.lock().unwrap().num_duplicate.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for LiveSync {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                *self.state.lock().unwrap() = State::default();
+            }
+            gst::StateChange::PausedToPlaying => {
+                let mut state = self.state.lock().unwrap();
+                state.playing = true;
+                self.cond.notify_all();
+            }
+            gst::StateChange::PlayingToPaused => {
+                let mut state = self.state.lock().unwrap();
+                state.playing = false;
+                state.unschedule();
+                self.cond.notify_all();
+            }
+            _ => (),
+        }
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        // Output only happens in Playing, like for live sources
+        match transition {
+            gst::StateChange::ReadyToPaused | gst::StateChange::PlayingToPaused => {
+                gst::StateChangeReturn::NoPreroll
+            }
+            _ => ret,
+        }
+    }
+}
+
+struct LiveSyncStatic;
+
+impl ImplTypeStatic<Element> for LiveSyncStatic {
+    fn get_name(&self) -> &str {
+        "LiveSync"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        LiveSync::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        LiveSync::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let livesync_static = LiveSyncStatic;
+    register_type(livesync_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot() {
+        let ms = gst::MSECOND_VAL;
+
+        assert_eq!(get_slot(0, 40 * ms, 40 * ms), Slot::Late);
+        assert_eq!(get_slot(10 * ms, 40 * ms, 40 * ms), Slot::Current);
+        assert_eq!(get_slot(40 * ms, 40 * ms, 40 * ms), Slot::Current);
+        assert_eq!(get_slot(60 * ms, 40 * ms, 40 * ms), Slot::Current);
+        assert_eq!(get_slot(61 * ms, 40 * ms, 40 * ms), Slot::Future);
+        assert_eq!(get_slot(80 * ms, 40 * ms, 40 * ms), Slot::Future);
+    }
+}
@@ -0,0 +1,256 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Metadata side-channel framing between metasend and metarecv
+//
+// Every buffer meta is sent as one buffer of the channel stream: the big endian u64 running
+// time of the media buffer it belongs to, followed by the meta serialized as a structure. The
+// channel has its own caps so that it can be transported over anything that handles arbitrary
+// buffers, e.g. rtpgstpay, SRT or a WebRTC data channel.
+//
+// Supported are GstVideoTimeCodeMeta, serialized as a "timecode" structure, and the structure
+// metas of gst_plugin::meta, which are sent as is. Their structure name selects them, so
+// "timecode" can't be used as name of a structure meta.
+
+use std::ptr;
+
+use gst;
+use gst_ffi;
+use gst_video_ffi;
+
+use gst_plugin::meta;
+
+pub const CAPS_NAME: &str = "application/x-meta-channel";
+
+const HEADER_SIZE: usize = 8;
+
+const TIMECODE_NAME: &str = "timecode";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub running_time: u64,
+    pub structure: String,
+}
+
+impl Packet {
+    pub fn write(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_SIZE + self.structure.len());
+        for i in (0..HEADER_SIZE).rev() {
+            data.push((self.running_time >> (8 * i)) as u8);
+        }
+        data.extend_from_slice(self.structure.as_bytes());
+        data
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Packet> {
+        if data.len() < HEADER_SIZE {
+            return None;
+        }
+
+        let running_time = data[..HEADER_SIZE]
+            .iter()
+            .fold(0u64, |acc, b| (acc << 8) | u64::from(*b));
+        let structure = String::from_utf8(data[HEADER_SIZE..].to_vec()).ok()?;
+
+        Some(Packet {
+            running_time: running_time,
+            structure: structure,
+        })
+    }
+}
+
+// Parses a comma separated list of structure names
+pub fn parse_names(names: &str) -> Vec<String> {
+    names
+        .split(',')
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn get_timecode_metas(buffer: &gst::BufferRef) -> Vec<gst::Structure> {
+    let mut structures = Vec::new();
+
+    unsafe {
+        let api = gst_video_ffi::gst_video_time_code_meta_api_get_type();
+        let mut state = ptr::null_mut();
+        loop {
+            let meta = gst_ffi::gst_buffer_iterate_meta(
+                buffer.as_ptr() as *mut gst_ffi::GstBuffer,
+                &mut state,
+            );
+            if meta.is_null() {
+                break;
+            }
+            if (*(*meta).info).api != api {
+                continue;
+            }
+
+            let tc = &(*(meta as *const gst_video_ffi::GstVideoTimeCodeMeta)).tc;
+            structures.push(gst::Structure::new(
+                TIMECODE_NAME,
+                &[
+                    ("fps-n", &tc.config.fps_n),
+                    ("fps-d", &tc.config.fps_d),
+                    ("flags", &(tc.config.flags as u32)),
+                    ("hours", &tc.hours),
+                    ("minutes", &tc.minutes),
+                    ("seconds", &tc.seconds),
+                    ("frames", &tc.frames),
+                    ("field-count", &tc.field_count),
+                ],
+            ));
+        }
+    }
+
+    structures
+}
+
+fn add_timecode_meta(buffer: &mut gst::BufferRef, s: &gst::StructureRef) -> bool {
+    let (fps_n, fps_d, flags) = match (
+        s.get::<u32>("fps-n"),
+        s.get::<u32>("fps-d"),
+        s.get::<u32>("flags"),
+    ) {
+        (Some(fps_n), Some(fps_d), Some(flags)) => (fps_n, fps_d, flags),
+        _ => return false,
+    };
+    let (hours, minutes, seconds, frames, field_count) = match (
+        s.get::<u32>("hours"),
+        s.get::<u32>("minutes"),
+        s.get::<u32>("seconds"),
+        s.get::<u32>("frames"),
+        s.get::<u32>("field-count"),
+    ) {
+        (Some(hours), Some(minutes), Some(seconds), Some(frames), Some(field_count)) => {
+            (hours, minutes, seconds, frames, field_count)
+        }
+        _ => return false,
+    };
+
+    unsafe {
+        !gst_video_ffi::gst_video_buffer_add_video_time_code_meta_full(
+            buffer.as_mut_ptr(),
+            fps_n,
+            fps_d,
+            ptr::null_mut(),
+            flags as gst_video_ffi::GstVideoTimeCodeFlags,
+            hours,
+            minutes,
+            seconds,
+            frames,
+            field_count,
+        ).is_null()
+    }
+}
+
+// Serializes all metas of the buffer with one of the given names
+pub fn get_metas(buffer: &gst::BufferRef, names: &[String]) -> Vec<gst::Structure> {
+    let mut metas = Vec::new();
+    if names.iter().any(|name| name == TIMECODE_NAME) {
+        metas.extend(get_timecode_metas(buffer));
+    }
+    metas.extend(
+        meta::get_structure_metas(buffer)
+            .into_iter()
+            .filter(|s| s.get_name() != TIMECODE_NAME && names.iter().any(|n| n == s.get_name())),
+    );
+    metas
+}
+
+// Removes all metas of the buffer with one of the given names
+pub fn remove_metas(buffer: &mut gst::BufferRef, names: &[String]) {
+    for name in names {
+        if name == TIMECODE_NAME {
+            unsafe {
+                let ptr = buffer.as_mut_ptr();
+                let api = gst_video_ffi::gst_video_time_code_meta_api_get_type();
+                loop {
+                    let meta = gst_ffi::gst_buffer_get_meta(ptr, api);
+                    if meta.is_null() {
+                        break;
+                    }
+                    gst_ffi::gst_buffer_remove_meta(ptr, meta);
+                }
+            }
+        } else {
+            meta::remove_structure_meta(buffer, name);
+        }
+    }
+}
+
+// Re-attaches a meta serialized with get_metas(), returns false if it is invalid
+pub fn add_meta(buffer: &mut gst::BufferRef, s: gst::Structure) -> bool {
+    if s.get_name() == TIMECODE_NAME {
+        add_timecode_meta(buffer, &s)
+    } else {
+        meta::add_structure_meta(buffer, s);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet() {
+        let packet = Packet {
+            running_time: 0x0102_0304_0506_0708,
+            structure: String::from("timecode, frames=(uint)12;"),
+        };
+
+        let data = packet.write();
+        assert_eq!(&data[..8], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(Packet::parse(&data), Some(packet));
+        assert_eq!(Packet::parse(&data[..7]), None);
+
+        assert_eq!(parse_names(" timecode,,klv "), vec!["timecode", "klv"]);
+    }
+
+    #[test]
+    fn test_metas() {
+        gst::init().unwrap();
+
+        let timecode = gst::Structure::new(
+            "timecode",
+            &[
+                ("fps-n", &25u32),
+                ("fps-d", &1u32),
+                ("flags", &0u32),
+                ("hours", &1u32),
+                ("minutes", &2u32),
+                ("seconds", &3u32),
+                ("frames", &4u32),
+                ("field-count", &0u32),
+            ],
+        );
+        let klv = gst::Structure::new("klv", &[("data", &"0102")]);
+        let other = gst::Structure::new("other", &[]);
+
+        let mut buffer = gst::Buffer::with_size(4).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            assert!(add_meta(buffer, timecode.clone()));
+            assert!(add_meta(buffer, klv.clone()));
+            assert!(add_meta(buffer, other));
+            assert!(!add_meta(buffer, gst::Structure::new("timecode", &[])));
+        }
+
+        let names = parse_names("timecode,klv");
+        let metas = get_metas(&buffer, &names);
+        assert_eq!(metas.len(), 2);
+        assert!(metas.contains(&timecode));
+        assert!(metas.contains(&klv));
+
+        remove_metas(buffer.get_mut().unwrap(), &names);
+        assert!(get_metas(&buffer, &names).is_empty());
+        assert_eq!(get_metas(&buffer, &parse_names("other")).len(), 1);
+    }
+}
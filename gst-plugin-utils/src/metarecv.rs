@@ -0,0 +1,461 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::meta;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::{i64, u64};
+
+use metachannel::{self, Packet};
+
+// The media on the "sink" pad is passed through to the "src" pad. Metas received from metasend on
+// the "meta_sink" pad are queued and re-attached to the first media buffer with the same or a
+// later running time. Metas that arrive after their media buffer are attached to the next one,
+// unless they are more than max-lateness late.
+//
+// If the running times of the media and the channel differ on the receiver side, e.g. because
+// of different latencies of the transports, the offset property can be used to compensate.
+
+const DEFAULT_OFFSET: i64 = 0;
+const DEFAULT_MAX_LATENESS: u64 = gst::SECOND_VAL;
+// Upper bound for queued metadata if the media stalls
+const MAX_QUEUED: usize = 1000;
+
+static PROPERTIES: [Property; 2] = [
+    Property::Int64(
+        "offset",
+        "Offset",
+        "Offset in nanoseconds added to the running times of the metadata",
+        (i64::MIN, i64::MAX),
+        DEFAULT_OFFSET,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "max-lateness",
+        "Max Lateness",
+        "Maximum time in nanoseconds metadata can be late before it is dropped",
+        (0, u64::MAX),
+        DEFAULT_MAX_LATENESS,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    offset: i64,
+    max_lateness: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            offset: DEFAULT_OFFSET,
+            max_lateness: DEFAULT_MAX_LATENESS,
+        }
+    }
+}
+
+struct State {
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    // Metadata with the running time of the media buffer it belongs to
+    queue: VecDeque<(u64, gst::Structure)>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            segment: gst::FormattedSegment::new(),
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+struct MetaRecv {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl MetaRecv {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "metarecv",
+                gst::DebugColorFlags::empty(),
+                "Metadata side-channel receiver",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Metadata Receiver",
+            "Generic",
+            "Re-attaches metadata from a separate channel to a stream by timestamp",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let caps = gst::Caps::new_simple(metachannel::CAPS_NAME, &[]);
+        let meta_sink_pad_template = gst::PadTemplate::new(
+            "meta_sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(meta_sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        meta::register();
+
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+        let templ = element.get_pad_template("meta_sink").unwrap();
+        let meta_sinkpad = gst::Pad::new_from_template(&templ, "meta_sink");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            MetaRecv::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |metarecv, element| metarecv.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            MetaRecv::catch_panic_pad_function(
+                parent,
+                || false,
+                |metarecv, element| metarecv.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            MetaRecv::catch_panic_pad_function(
+                parent,
+                || false,
+                |metarecv, element| metarecv.sink_query(pad, element, query),
+            )
+        });
+
+        meta_sinkpad.set_chain_function(|pad, parent, buffer| {
+            MetaRecv::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |metarecv, element| metarecv.meta_sink_chain(pad, element, buffer),
+            )
+        });
+        meta_sinkpad.set_event_function(|pad, parent, event| {
+            MetaRecv::catch_panic_pad_function(
+                parent,
+                || false,
+                |metarecv, element| metarecv.meta_sink_event(pad, element, event),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            MetaRecv::catch_panic_pad_function(
+                parent,
+                || false,
+                |metarecv, element| metarecv.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            MetaRecv::catch_panic_pad_function(
+                parent,
+                || false,
+                |metarecv, element| metarecv.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+        element.add_pad(&meta_sinkpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let metarecv = element.get_impl().downcast_ref::<MetaRecv>().unwrap();
+        element.catch_panic(fallback, |element| f(metarecv, element))
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        _element: &Element,
+        mut buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let max_lateness = self.settings.lock().unwrap().max_lateness;
+
+        let mut metas = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(running_time) = state.segment.to_running_time(buffer.get_pts()).0 {
+                while state
+                    .queue
+                    .front()
+                    .map(|&(rt, _)| rt <= running_time)
+                    .unwrap_or(false)
+                {
+                    let (rt, s) = state.queue.pop_front().unwrap();
+                    if running_time - rt > max_lateness {
+                        gst_debug!(
+                            self.cat,
+                            obj: pad,
+                            "Dropping metadata {} for {}, too late for {}",
+                            s.to_string(),
+                            gst::ClockTime::from(rt),
+                            gst::ClockTime::from(running_time)
+                        );
+                        continue;
+                    }
+
+                    metas.push(s);
+                }
+            }
+        }
+
+        if !metas.is_empty() {
+            let buffer = buffer.make_mut();
+            for s in metas {
+                gst_log!(self.cat, obj: pad, "Attaching metadata {}", s.to_string());
+                if !metachannel::add_meta(buffer, s) {
+                    gst_warning!(self.cat, obj: pad, "Dropping invalid metadata");
+                }
+            }
+        }
+
+        gst_log!(self.cat, obj: pad, "Pushing buffer {:?}", buffer);
+        self.srcpad.push(buffer)
+    }
+
+    fn meta_sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let packet = {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+            Packet::parse(map.as_slice())
+        };
+
+        let (running_time, s) = match packet
+            .and_then(|p| gst::Structure::from_string(&p.structure).map(|s| (p.running_time, s)))
+        {
+            None => {
+                gst_warning!(self.cat, obj: pad, "Dropping invalid metadata packet");
+                return gst::FlowReturn::Ok;
+            }
+            Some(res) => res,
+        };
+
+        let offset = self.settings.lock().unwrap().offset;
+        let running_time = if offset < 0 {
+            running_time.saturating_sub((-offset) as u64)
+        } else {
+            running_time.saturating_add(offset as u64)
+        };
+
+        gst_log!(
+            self.cat,
+            obj: pad,
+            "Queueing metadata {} for {}",
+            s.to_string(),
+            gst::ClockTime::from(running_time)
+        );
+
+        let mut state = self.state.lock().unwrap();
+        if state.queue.len() >= MAX_QUEUED {
+            gst_warning!(self.cat, obj: pad, "Too much metadata queued, dropping oldest");
+            state.queue.pop_front();
+        }
+
+        // Keep the queue sorted even if the channel is reordered
+        let idx = state
+            .queue
+            .iter()
+            .rposition(|&(rt, _)| rt <= running_time)
+            .map(|idx| idx + 1)
+            .unwrap_or(0);
+        state.queue.insert(idx, (running_time, s));
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                self.state.lock().unwrap().segment = segment;
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.segment = gst::FormattedSegment::new();
+                state.queue.clear();
+            }
+            _ => (),
+        }
+
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.srcpad.push_event(event)
+    }
+
+    fn meta_sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        // Events of the channel are never forwarded, the metadata is only a side-channel of
+        // the media stream
+        true
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, _element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding query {:?}", query);
+        self.srcpad.peer_query(query)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, _element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding query {:?}", query);
+        self.sinkpad.peer_query(query)
+    }
+}
+
+impl ObjectImpl<Element> for MetaRecv {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int64("offset", ..) => {
+                settings.offset = value.get().unwrap();
+            }
+            Property::UInt64("max-lateness", ..) => {
+                settings.max_lateness = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int64("offset", ..) => Ok(settings.offset.to_value()),
+            Property::UInt64("max-lateness", ..) => Ok(settings.max_lateness.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for MetaRecv {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct MetaRecvStatic;
+
+impl ImplTypeStatic<Element> for MetaRecvStatic {
+    fn get_name(&self) -> &str {
+        "MetaRecv"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        MetaRecv::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        MetaRecv::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let metarecv_static = MetaRecvStatic;
+    register_type(metarecv_static)
+}
@@ -0,0 +1,418 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::meta;
+
+use std::sync::Mutex;
+
+use metachannel::{self, Packet};
+
+// The media on the "sink" pad is passed through to the "src" pad. The selected buffer metas of
+// every media buffer, e.g. timecodes, KLV data or analytics results, are serialized and sent on
+// the "meta_src" pad together with the running time of the buffer. See metachannel for the
+// supported metas.
+//
+// The metadata is sent before the media buffer it belongs to, so that it does not have to wait
+// for the media to be encoded, muxed or sent, and can be re-attached to the media buffers on the
+// receiver side with metarecv.
+
+const DEFAULT_METAS: &str = "timecode,klv";
+const DEFAULT_STRIP: bool = false;
+
+static PROPERTIES: [Property; 2] = [
+    Property::String(
+        "metas",
+        "Metas",
+        "Comma separated names of the buffer metas to send",
+        Some(DEFAULT_METAS),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "strip",
+        "Strip",
+        "Remove the sent metas from the media buffers",
+        DEFAULT_STRIP,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    metas: String,
+    strip: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            metas: DEFAULT_METAS.into(),
+            strip: DEFAULT_STRIP,
+        }
+    }
+}
+
+struct State {
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    meta_started: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            segment: gst::FormattedSegment::new(),
+            meta_started: false,
+        }
+    }
+}
+
+struct MetaSend {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    meta_srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl MetaSend {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad, meta_srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "metasend",
+                gst::DebugColorFlags::empty(),
+                "Metadata side-channel sender",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            meta_srcpad: meta_srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Metadata Sender",
+            "Generic",
+            "Sends the buffer metas of a stream on a separate low-latency channel",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let caps = gst::Caps::new_simple(metachannel::CAPS_NAME, &[]);
+        let meta_src_pad_template = gst::PadTemplate::new(
+            "meta_src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(meta_src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        meta::register();
+
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+        let templ = element.get_pad_template("meta_src").unwrap();
+        let meta_srcpad = gst::Pad::new_from_template(&templ, "meta_src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            MetaSend::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |metasend, element| metasend.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            MetaSend::catch_panic_pad_function(
+                parent,
+                || false,
+                |metasend, element| metasend.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            MetaSend::catch_panic_pad_function(
+                parent,
+                || false,
+                |metasend, element| metasend.sink_query(pad, element, query),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            MetaSend::catch_panic_pad_function(
+                parent,
+                || false,
+                |metasend, element| metasend.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            MetaSend::catch_panic_pad_function(
+                parent,
+                || false,
+                |metasend, element| metasend.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+        element.add_pad(&meta_srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad, meta_srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let metasend = element.get_impl().downcast_ref::<MetaSend>().unwrap();
+        element.catch_panic(fallback, |element| f(metasend, element))
+    }
+
+    fn push_metas(&self, element: &Element, running_time: u64, metas: Vec<gst::Structure>) {
+        if metas.is_empty() {
+            return;
+        }
+
+        let start = {
+            let mut state = self.state.lock().unwrap();
+            let start = !state.meta_started;
+            state.meta_started = true;
+            start
+        };
+
+        if start {
+            let stream_id = format!("{}-meta", element.get_name());
+            self.meta_srcpad
+                .push_event(gst::Event::new_stream_start(&stream_id).build());
+            let caps = gst::Caps::new_simple(metachannel::CAPS_NAME, &[]);
+            self.meta_srcpad
+                .push_event(gst::Event::new_caps(&caps).build());
+            // Timestamps of the channel are running times
+            let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+            self.meta_srcpad
+                .push_event(gst::Event::new_segment(&segment).build());
+        }
+
+        for s in metas {
+            let packet = Packet {
+                running_time: running_time,
+                structure: s.to_string(),
+            };
+
+            let mut buffer = gst::Buffer::from_mut_slice(packet.write()).unwrap();
+            buffer.get_mut().unwrap().set_pts(running_time.into());
+
+            gst_log!(
+                self.cat,
+                obj: &self.meta_srcpad,
+                "Sending {} at {}",
+                packet.structure,
+                gst::ClockTime::from(running_time)
+            );
+
+            // The channel is optional, the media flow does not depend on it
+            match self.meta_srcpad.push(buffer) {
+                gst::FlowReturn::Ok | gst::FlowReturn::NotLinked | gst::FlowReturn::Flushing => (),
+                ret => {
+                    gst_warning!(
+                        self.cat,
+                        obj: element,
+                        "Failed to send metadata: {:?}",
+                        ret
+                    );
+                }
+            }
+        }
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        mut buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let settings = self.settings.lock().unwrap().clone();
+        let names = metachannel::parse_names(&settings.metas);
+
+        let running_time = {
+            let state = self.state.lock().unwrap();
+            state.segment.to_running_time(buffer.get_pts())
+        };
+
+        let metas = metachannel::get_metas(&buffer, &names);
+        if !metas.is_empty() {
+            if let Some(running_time) = running_time.0 {
+                self.push_metas(element, running_time, metas);
+            } else {
+                gst_debug!(self.cat, obj: pad, "Not sending metas of buffer without timestamp");
+            }
+
+            if settings.strip {
+                metachannel::remove_metas(buffer.make_mut(), &names);
+            }
+        }
+
+        gst_log!(self.cat, obj: pad, "Pushing buffer {:?}", buffer);
+        self.srcpad.push(buffer)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        let forward_meta = match event.view() {
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                self.state.lock().unwrap().segment = segment;
+                false
+            }
+            EventView::FlushStart(..) => true,
+            EventView::FlushStop(..) => {
+                self.state.lock().unwrap().segment = gst::FormattedSegment::new();
+                true
+            }
+            EventView::Eos(..) => self.state.lock().unwrap().meta_started,
+            _ => false,
+        };
+
+        if forward_meta {
+            self.meta_srcpad.push_event(event.clone());
+        }
+
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, _element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding query {:?}", query);
+        self.srcpad.peer_query(query)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, _element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding query {:?}", query);
+        self.sinkpad.peer_query(query)
+    }
+}
+
+impl ObjectImpl<Element> for MetaSend {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("metas", ..) => {
+                settings.metas = value.get().unwrap_or_else(|| DEFAULT_METAS.into());
+            }
+            Property::Boolean("strip", ..) => {
+                settings.strip = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("metas", ..) => Ok(settings.metas.to_value()),
+            Property::Boolean("strip", ..) => Ok(settings.strip.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for MetaSend {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct MetaSendStatic;
+
+impl ImplTypeStatic<Element> for MetaSendStatic {
+    fn get_name(&self) -> &str {
+        "MetaSend"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        MetaSend::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        MetaSend::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let metasend_static = MetaSendStatic;
+    register_type(metasend_static)
+}
@@ -0,0 +1,631 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::u32;
+use std::u64;
+
+const DEFAULT_LOCATION: Option<&str> = None;
+const DEFAULT_INTERVAL: u64 = 5 * gst::SECOND_VAL;
+const DEFAULT_PROBE_SIZE: u32 = 64 * 1024;
+const DEFAULT_TIMEOUT: u32 = 2000;
+
+// Payload size of the UDP probe packets, small enough to not be fragmented on common links
+const UDP_PACKET_SIZE: usize = 1200;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: Option<String>,
+    interval: u64,
+    probe_size: u32,
+    timeout: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: DEFAULT_LOCATION.map(String::from),
+            interval: DEFAULT_INTERVAL,
+            probe_size: DEFAULT_PROBE_SIZE,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 4] = [
+    Property::String(
+        "location",
+        "Location",
+        "Endpoint to probe, either http://host[:port]/path or udp://host:port of a UDP echo server",
+        DEFAULT_LOCATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "interval",
+        "Interval",
+        "Interval in nanoseconds between probes",
+        (1, u64::MAX),
+        DEFAULT_INTERVAL,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "probe-size",
+        "Probe Size",
+        "Number of bytes to transfer per probe",
+        (1, u32::MAX),
+        DEFAULT_PROBE_SIZE,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "timeout",
+        "Timeout",
+        "Timeout in milliseconds after which a probe is considered failed",
+        (1, u32::MAX),
+        DEFAULT_TIMEOUT,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Endpoint {
+    Http {
+        host: String,
+        port: u16,
+        path: String,
+    },
+    Udp {
+        host: String,
+        port: u16,
+    },
+}
+
+impl Endpoint {
+    fn parse(location: &str) -> Option<Endpoint> {
+        let (http, rest) = if location.starts_with("http://") {
+            (true, &location["http://".len()..])
+        } else if location.starts_with("udp://") {
+            (false, &location["udp://".len()..])
+        } else {
+            return None;
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/"),
+        };
+
+        // IPv6 addresses are enclosed in brackets
+        let (host, port) = match authority.rfind(':') {
+            Some(idx) if !authority[idx..].contains(']') => {
+                (&authority[..idx], Some(authority[idx + 1..].parse::<u16>().ok()?))
+            }
+            _ => (authority, None),
+        };
+        let host = host.trim_left_matches('[').trim_right_matches(']');
+        if host.is_empty() {
+            return None;
+        }
+
+        if http {
+            Some(Endpoint::Http {
+                host: host.into(),
+                port: port.unwrap_or(80),
+                path: path.into(),
+            })
+        } else {
+            Some(Endpoint::Udp {
+                host: host.into(),
+                port: port?,
+            })
+        }
+    }
+
+    fn resolve(&self) -> io::Result<SocketAddr> {
+        let (host, port) = match *self {
+            Endpoint::Http {
+                ref host, port, ..
+            } => (host, port),
+            Endpoint::Udp { ref host, port } => (host, port),
+        };
+
+        (host.as_str(), port).to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Can't resolve {}", host))
+        })
+    }
+}
+
+// Result of a single probe
+#[derive(Debug)]
+struct Measurement {
+    rtt: Duration,
+    // Bits per second
+    throughput: u64,
+    // Only known for UDP
+    packet_loss: Option<f64>,
+}
+
+fn duration_to_nseconds(duration: Duration) -> u64 {
+    duration.as_secs() * gst::SECOND_VAL + u64::from(duration.subsec_nanos())
+}
+
+fn throughput(bytes: usize, duration: Duration) -> u64 {
+    match duration_to_nseconds(duration) {
+        0 => 0,
+        nseconds => (bytes as f64 * 8.0 * gst::SECOND_VAL as f64 / nseconds as f64) as u64,
+    }
+}
+
+// The RTT is the time needed for the TCP handshake, the throughput is measured over the
+// response to a ranged GET request of `size` bytes
+fn probe_http(
+    host: &str,
+    path: &str,
+    addr: &SocketAddr,
+    size: u32,
+    timeout: Duration,
+) -> io::Result<Measurement> {
+    let start = Instant::now();
+    let mut stream = TcpStream::connect_timeout(addr, timeout)?;
+    let rtt = start.elapsed();
+
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Range: bytes=0-{}\r\n\
+         User-Agent: GStreamer netprobe\r\n\
+         Connection: close\r\n\r\n",
+        path,
+        host,
+        size - 1
+    )?;
+
+    let start = Instant::now();
+    let mut response = Vec::new();
+    let mut header_len = None;
+    let mut buf = [0; 8192];
+    loop {
+        let len = stream.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..len]);
+
+        if header_len.is_none() {
+            header_len = response
+                .windows(4)
+                .position(|w| w == b"\r\n\r\n")
+                .map(|pos| pos + 4);
+        }
+        if let Some(header_len) = header_len {
+            if response.len() - header_len >= size as usize {
+                break;
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+
+    let header_len = header_len.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Incomplete HTTP response")
+    })?;
+    let status_ok = {
+        let status_line = String::from_utf8_lossy(&response[..header_len]);
+        let mut parts = status_line.split_whitespace();
+        parts.next();
+        match parts.next() {
+            Some("200") | Some("206") => true,
+            _ => false,
+        }
+    };
+    if !status_ok {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP request failed"));
+    }
+
+    let bytes = cmp::min(response.len() - header_len, size as usize);
+    Ok(Measurement {
+        rtt: rtt,
+        throughput: throughput(bytes, elapsed),
+        packet_loss: None,
+    })
+}
+
+// Sends a burst of numbered packets totalling `size` bytes and waits for the echo server
+// to return them
+fn probe_udp(addr: &SocketAddr, size: u32, timeout: Duration) -> io::Result<Measurement> {
+    let bind_addr = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(bind_addr)?;
+    socket.connect(addr)?;
+    socket.set_read_timeout(Some(timeout))?;
+
+    let n_packets = cmp::max(1, (size as usize + UDP_PACKET_SIZE - 1) / UDP_PACKET_SIZE);
+    let mut packet = vec![0; UDP_PACKET_SIZE];
+    let mut send_times = Vec::with_capacity(n_packets);
+
+    let start = Instant::now();
+    for i in 0..n_packets {
+        packet[0] = (i >> 24) as u8;
+        packet[1] = (i >> 16) as u8;
+        packet[2] = (i >> 8) as u8;
+        packet[3] = i as u8;
+        send_times.push(Instant::now());
+        socket.send(&packet)?;
+    }
+
+    let mut received = vec![false; n_packets];
+    let mut n_received = 0;
+    let mut rtt_sum = Duration::from_secs(0);
+    let mut last_received = start;
+    let mut buf = vec![0; UDP_PACKET_SIZE];
+    while n_received < n_packets && start.elapsed() < timeout {
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                break;
+            }
+            Err(err) => return Err(err),
+        };
+        if len < 4 {
+            continue;
+        }
+
+        let i = (buf[0] as usize) << 24 | (buf[1] as usize) << 16 | (buf[2] as usize) << 8
+            | buf[3] as usize;
+        if i >= n_packets || received[i] {
+            continue;
+        }
+
+        last_received = Instant::now();
+        received[i] = true;
+        n_received += 1;
+        rtt_sum += last_received.duration_since(send_times[i]);
+    }
+
+    if n_received == 0 {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "No echo received"));
+    }
+
+    Ok(Measurement {
+        rtt: rtt_sum / n_received as u32,
+        throughput: throughput(
+            n_received * UDP_PACKET_SIZE,
+            last_received.duration_since(start),
+        ),
+        packet_loss: Some(1.0 - n_received as f64 / n_packets as f64),
+    })
+}
+
+fn probe(endpoint: &Endpoint, size: u32, timeout: Duration) -> io::Result<Measurement> {
+    let addr = endpoint.resolve()?;
+
+    match *endpoint {
+        Endpoint::Http {
+            ref host, ref path, ..
+        } => probe_http(host, path, &addr, size, timeout),
+        Endpoint::Udp { .. } => probe_udp(&addr, size, timeout),
+    }
+}
+
+// Message posted after every probe:
+//
+// "network-quality, location=(string)..., reachable=(boolean)true, rtt=(guint64)...,
+//  throughput=(guint64)..., packet-loss=(double)..."
+//
+// with the RTT in nanoseconds and the throughput in bits per second. Only the location and
+// reachable fields are set if the probe failed, packet-loss is only set for UDP endpoints.
+fn create_message(
+    element: &gst::Element,
+    location: &str,
+    res: &io::Result<Measurement>,
+) -> gst::Message {
+    let mut s = gst::Structure::new(
+        "network-quality",
+        &[("location", &location), ("reachable", &res.is_ok())],
+    );
+
+    if let Ok(ref measurement) = *res {
+        let s = s.get_mut().unwrap();
+        s.set("rtt", &duration_to_nseconds(measurement.rtt));
+        s.set("throughput", &measurement.throughput);
+        if let Some(packet_loss) = measurement.packet_loss {
+            s.set("packet-loss", &packet_loss);
+        }
+    }
+
+    gst::Message::new_element(s).src(Some(element)).build()
+}
+
+struct State {
+    // Set to true to stop the probe thread
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            stop: Arc::new((Mutex::new(false), Condvar::new())),
+            thread: None,
+        }
+    }
+}
+
+struct NetProbe {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl NetProbe {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "netprobe",
+                gst::DebugColorFlags::empty(),
+                "Network quality probe",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Network Probe",
+            "Generic",
+            "Periodically measures the throughput and RTT to a network endpoint",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, true, true);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    fn run(
+        cat: gst::DebugCategory,
+        element: gst::Element,
+        endpoint: Endpoint,
+        settings: Settings,
+        stop: Arc<(Mutex<bool>, Condvar)>,
+    ) {
+        let location = settings.location.unwrap();
+        let timeout = Duration::from_millis(u64::from(settings.timeout));
+        let interval = Duration::new(
+            settings.interval / gst::SECOND_VAL,
+            (settings.interval % gst::SECOND_VAL) as u32,
+        );
+
+        let &(ref lock, ref cond) = &*stop;
+
+        loop {
+            let res = probe(&endpoint, settings.probe_size, timeout);
+            match res {
+                Ok(ref measurement) => {
+                    gst_debug!(cat, obj: &element, "Probed {}: {:?}", location, measurement);
+                }
+                Err(ref err) => {
+                    gst_debug!(cat, obj: &element, "Probing {} failed: {}", location, err);
+                }
+            }
+
+            if *lock.lock().unwrap() {
+                break;
+            }
+            element.post_message(&create_message(&element, &location, &res));
+
+            // Wait for the next interval unless stopped in the meantime
+            let deadline = Instant::now() + interval;
+            let mut stopped = lock.lock().unwrap();
+            while !*stopped {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                stopped = cond.wait_timeout(stopped, deadline - now).unwrap().0;
+            }
+            if *stopped {
+                break;
+            }
+        }
+    }
+}
+
+impl ObjectImpl<BaseTransform> for NetProbe {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => {
+                settings.location = value.get();
+            }
+            Property::UInt64("interval", ..) => {
+                settings.interval = value.get().unwrap();
+            }
+            Property::UInt("probe-size", ..) => {
+                settings.probe_size = value.get().unwrap();
+            }
+            Property::UInt("timeout", ..) => {
+                settings.timeout = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => Ok(settings.location.to_value()),
+            Property::UInt64("interval", ..) => Ok(settings.interval.to_value()),
+            Property::UInt("probe-size", ..) => Ok(settings.probe_size.to_value()),
+            Property::UInt("timeout", ..) => Ok(settings.timeout.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for NetProbe {}
+
+impl BaseTransformImpl<BaseTransform> for NetProbe {
+    fn start(&self, element: &BaseTransform) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let endpoint = match settings.location.as_ref().map(|l| Endpoint::parse(l)) {
+            Some(Some(endpoint)) => endpoint,
+            Some(None) => {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::Settings,
+                    ["Invalid location {:?}", settings.location]
+                );
+                return false;
+            }
+            None => {
+                gst_element_error!(element, gst::ResourceError::Settings, ["No location set"]);
+                return false;
+            }
+        };
+
+        gst_debug!(self.cat, obj: element, "Starting to probe {:?}", endpoint);
+
+        let mut state = self.state.lock().unwrap();
+        *state = State::default();
+
+        let cat = self.cat;
+        let element = element.clone().upcast::<gst::Element>();
+        let stop = state.stop.clone();
+        state.thread = Some(thread::spawn(move || {
+            NetProbe::run(cat, element, endpoint, settings, stop);
+        }));
+
+        true
+    }
+
+    fn stop(&self, element: &BaseTransform) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        {
+            let &(ref lock, ref cond) = &*state.stop;
+            *lock.lock().unwrap() = true;
+            cond.notify_one();
+        }
+
+        // A probe that is currently running finishes at the latest after the timeout
+        if let Some(thread) = state.thread.take() {
+            gst_debug!(self.cat, obj: element, "Waiting for probe thread");
+            let _ = thread.join();
+        }
+
+        true
+    }
+
+    fn transform_ip(&self, _element: &BaseTransform, _buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        gst::FlowReturn::Ok
+    }
+}
+
+struct NetProbeStatic;
+
+impl ImplTypeStatic<BaseTransform> for NetProbeStatic {
+    fn get_name(&self) -> &str {
+        "NetProbe"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        NetProbe::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        NetProbe::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let netprobe_static = NetProbeStatic;
+    register_type(netprobe_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(
+            Endpoint::parse("http://example.com/probe.bin"),
+            Some(Endpoint::Http {
+                host: "example.com".into(),
+                port: 80,
+                path: "/probe.bin".into(),
+            })
+        );
+        assert_eq!(
+            Endpoint::parse("http://[::1]:8080"),
+            Some(Endpoint::Http {
+                host: "::1".into(),
+                port: 8080,
+                path: "/".into(),
+            })
+        );
+        assert_eq!(
+            Endpoint::parse("udp://127.0.0.1:7"),
+            Some(Endpoint::Udp {
+                host: "127.0.0.1".into(),
+                port: 7,
+            })
+        );
+        assert_eq!(Endpoint::parse("udp://127.0.0.1"), None);
+        assert_eq!(Endpoint::parse("http://:80/"), None);
+        assert_eq!(Endpoint::parse("ftp://example.com/"), None);
+    }
+}
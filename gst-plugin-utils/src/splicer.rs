@@ -0,0 +1,760 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::{Condvar, Mutex};
+
+// Splicing behaviour:
+//
+// The "program" sink pad is passed through to the src pad until a splice out point is
+// scheduled, either with a serialized custom downstream event on the "program" pad (e.g. from
+// an SCTE-35 parser) or with the "splice-out" action signal. The event structure is
+//
+//   "splice, out-of-network=(boolean)true, running-time=(guint64)..., duration=(guint64)..."
+//
+// with the running time of the splice point and the duration of the break being optional.
+// Without running time the splice happens at the next keyframe, with out-of-network=false it
+// schedules the splice in point (the return to the program).
+//
+// At the first program keyframe at or after the out point, the output switches to the "ad"
+// pad, starting with its first keyframe. The ad buffers are retimestamped to continue
+// seamlessly from the running time of the splice point. Program data is dropped during the
+// break, paced by the ad content. The program is returned to at the first keyframe at or after
+// the in point, or after the ad content is finished if no in point is known.
+//
+// All output is in a single time segment starting at running time 0. Once an ad pad returned
+// EOS it has to be flushed, e.g. by seeking the ad source, before it can be used for the next
+// break.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Program,
+    Ad,
+}
+
+struct State {
+    mode: Mode,
+    program_segment: gst::FormattedSegment<gst::ClockTime>,
+    ad_segment: gst::FormattedSegment<gst::ClockTime>,
+    // Scheduled splice points in program running time
+    out_point: gst::ClockTime,
+    in_point: gst::ClockTime,
+    break_duration: gst::ClockTime,
+    // Output running time of the start of the current break and ad running time of the first
+    // ad keyframe
+    splice_running_time: gst::ClockTime,
+    ad_base: gst::ClockTime,
+    // Output running time up to which ad content was pushed
+    ad_position: gst::ClockTime,
+    ad_done: bool,
+    ad_eos: bool,
+    ad_pushing: bool,
+    flushing: bool,
+    ad_flushing: bool,
+    output_caps: Option<gst::Caps>,
+    segment_pending: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            mode: Mode::Program,
+            program_segment: gst::FormattedSegment::new(),
+            ad_segment: gst::FormattedSegment::new(),
+            out_point: gst::CLOCK_TIME_NONE,
+            in_point: gst::CLOCK_TIME_NONE,
+            break_duration: gst::CLOCK_TIME_NONE,
+            splice_running_time: gst::CLOCK_TIME_NONE,
+            ad_base: gst::CLOCK_TIME_NONE,
+            ad_position: gst::CLOCK_TIME_NONE,
+            ad_done: false,
+            ad_eos: false,
+            ad_pushing: false,
+            flushing: false,
+            ad_flushing: false,
+            output_caps: None,
+            segment_pending: true,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 1] = [
+    Property::Boolean(
+        "in-break",
+        "In Break",
+        "Whether ad content is currently output",
+        false,
+        PropertyMutability::Readable,
+    ),
+];
+
+struct Splicer {
+    cat: gst::DebugCategory,
+    program_pad: gst::Pad,
+    ad_pad: gst::Pad,
+    srcpad: gst::Pad,
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+impl Splicer {
+    fn new(_element: &Element, program_pad: gst::Pad, ad_pad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "splicer",
+                gst::DebugColorFlags::empty(),
+                "Program/ad splicer",
+            ),
+            program_pad: program_pad,
+            ad_pad: ad_pad,
+            srcpad: srcpad,
+            state: Mutex::new(State::default()),
+            cond: Condvar::new(),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Splicer",
+            "Generic",
+            "Splices ad content into a program stream on keyframes",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "program",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "ad",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.add_action_signal(
+            "splice-out",
+            &[glib::Type::U64, glib::Type::U64],
+            glib::Type::Bool,
+            |args| {
+                let element = args[0].get::<Element>().unwrap();
+                let running_time = args[1].get::<u64>().unwrap();
+                let duration = args[2].get::<u64>().unwrap();
+
+                let splicer = element.get_impl().downcast_ref::<Splicer>().unwrap();
+                Some(
+                    splicer
+                        .schedule_splice(&element, true, running_time.into(), duration.into())
+                        .to_value(),
+                )
+            },
+        );
+
+        klass.add_action_signal(
+            "splice-in",
+            &[glib::Type::U64],
+            glib::Type::Bool,
+            |args| {
+                let element = args[0].get::<Element>().unwrap();
+                let running_time = args[1].get::<u64>().unwrap();
+
+                let splicer = element.get_impl().downcast_ref::<Splicer>().unwrap();
+                Some(
+                    splicer
+                        .schedule_splice(&element, false, running_time.into(), gst::CLOCK_TIME_NONE)
+                        .to_value(),
+                )
+            },
+        );
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("program").unwrap();
+        let program_pad = gst::Pad::new_from_template(&templ, "program");
+        let templ = element.get_pad_template("ad").unwrap();
+        let ad_pad = gst::Pad::new_from_template(&templ, "ad");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        program_pad.set_chain_function(|pad, parent, buffer| {
+            Splicer::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |splicer, element| splicer.program_chain(pad, element, buffer),
+            )
+        });
+        program_pad.set_event_function(|pad, parent, event| {
+            Splicer::catch_panic_pad_function(
+                parent,
+                || false,
+                |splicer, element| splicer.program_event(pad, element, event),
+            )
+        });
+
+        ad_pad.set_chain_function(|pad, parent, buffer| {
+            Splicer::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |splicer, element| splicer.ad_chain(pad, element, buffer),
+            )
+        });
+        ad_pad.set_event_function(|pad, parent, event| {
+            Splicer::catch_panic_pad_function(
+                parent,
+                || false,
+                |splicer, element| splicer.ad_event(pad, element, event),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            Splicer::catch_panic_pad_function(
+                parent,
+                || false,
+                |splicer, element| splicer.src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&program_pad).unwrap();
+        element.add_pad(&ad_pad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, program_pad, ad_pad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let splicer = element.get_impl().downcast_ref::<Splicer>().unwrap();
+        element.catch_panic(fallback, |element| f(splicer, element))
+    }
+
+    fn schedule_splice(
+        &self,
+        element: &Element,
+        out_of_network: bool,
+        running_time: gst::ClockTime,
+        duration: gst::ClockTime,
+    ) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        if out_of_network {
+            if state.mode == Mode::Ad {
+                gst_warning!(self.cat, obj: element, "Already in a break");
+                return false;
+            }
+
+            gst_info!(
+                self.cat,
+                obj: element,
+                "Scheduling splice out at {} with duration {}",
+                running_time,
+                duration
+            );
+
+            // Splice at the next keyframe if no running time is given
+            state.out_point = if running_time.is_some() {
+                running_time
+            } else {
+                gst::ClockTime::from(0)
+            };
+            state.break_duration = duration;
+            state.in_point = match (running_time.0, duration.0) {
+                (Some(running_time), Some(duration)) => (running_time + duration).into(),
+                _ => gst::CLOCK_TIME_NONE,
+            };
+        } else {
+            gst_info!(self.cat, obj: element, "Scheduling splice in at {}", running_time);
+
+            if state.mode == Mode::Program {
+                // Cancels a pending break
+                state.out_point = gst::CLOCK_TIME_NONE;
+                state.break_duration = gst::CLOCK_TIME_NONE;
+                return true;
+            }
+
+            state.in_point = if running_time.is_some() {
+                running_time
+            } else {
+                gst::ClockTime::from(0)
+            };
+            self.cond.notify_all();
+        }
+
+        true
+    }
+
+    // Events to push before the next buffer with the given caps
+    fn prepare_output(&self, state: &mut State, caps: Option<gst::Caps>) -> Vec<gst::Event> {
+        let mut events = Vec::new();
+
+        if caps.is_some() && caps != state.output_caps {
+            let caps = caps.unwrap();
+            events.push(gst::Event::new_caps(&caps).build());
+            state.output_caps = Some(caps);
+        }
+
+        if state.segment_pending {
+            let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+            events.push(gst::Event::new_segment(&segment).build());
+            state.segment_pending = false;
+        }
+
+        events
+    }
+
+    fn push_output(
+        &self,
+        events: Vec<gst::Event>,
+        mut buffer: gst::Buffer,
+        pts: gst::ClockTime,
+        dts: gst::ClockTime,
+    ) -> gst::FlowReturn {
+        for event in events {
+            self.srcpad.push_event(event);
+        }
+
+        {
+            let buffer = buffer.make_mut();
+            buffer.set_pts(pts);
+            buffer.set_dts(dts);
+        }
+
+        gst_log!(self.cat, obj: &self.srcpad, "Pushing buffer {:?}", buffer);
+        self.srcpad.push(buffer)
+    }
+
+    fn program_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let pts = buffer.get_pts();
+        if pts.is_none() {
+            gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+            return gst::FlowReturn::Error;
+        }
+        let keyframe = !buffer.get_flags().contains(gst::BufferFlags::DELTA_UNIT);
+
+        let mut state = self.state.lock().unwrap();
+        if state.flushing {
+            return gst::FlowReturn::Flushing;
+        }
+
+        let running_time = match state.program_segment.to_running_time(pts).0 {
+            None => {
+                gst_log!(self.cat, obj: pad, "Dropping buffer outside segment");
+                return gst::FlowReturn::Ok;
+            }
+            Some(running_time) => running_time,
+        };
+
+        if state.mode == Mode::Program {
+            let splice_out = keyframe && state.out_point.0.map_or(false, |out| running_time >= out);
+
+            if splice_out && state.ad_eos {
+                gst_warning!(self.cat, obj: element, "No ad content available, skipping break");
+                state.out_point = gst::CLOCK_TIME_NONE;
+                state.in_point = gst::CLOCK_TIME_NONE;
+                state.break_duration = gst::CLOCK_TIME_NONE;
+            } else if splice_out {
+                gst_info!(self.cat, obj: element, "Splicing out at {}", running_time);
+
+                state.mode = Mode::Ad;
+                state.out_point = gst::CLOCK_TIME_NONE;
+                state.splice_running_time = running_time.into();
+                state.ad_position = running_time.into();
+                state.ad_base = gst::CLOCK_TIME_NONE;
+                state.ad_done = false;
+                if state.in_point.is_none() {
+                    if let Some(duration) = state.break_duration.0 {
+                        state.in_point = (running_time + duration).into();
+                    }
+                }
+                self.cond.notify_all();
+                drop(state);
+
+                self.notify(&element.clone().upcast(), "in-break");
+                return gst::FlowReturn::Ok;
+            }
+        }
+
+        if state.mode == Mode::Ad {
+            loop {
+                if state.flushing {
+                    return gst::FlowReturn::Flushing;
+                }
+
+                let return_point = if state.in_point.is_some() {
+                    state.in_point
+                } else if state.ad_done {
+                    state.ad_position
+                } else {
+                    gst::CLOCK_TIME_NONE
+                };
+
+                if return_point.0.map_or(false, |point| running_time >= point) {
+                    if !keyframe {
+                        return gst::FlowReturn::Ok;
+                    }
+
+                    // Wait for a pending ad buffer to be pushed before taking over again
+                    while state.ad_pushing && !state.flushing {
+                        state = self.cond.wait(state).unwrap();
+                    }
+                    if state.flushing {
+                        return gst::FlowReturn::Flushing;
+                    }
+
+                    gst_info!(self.cat, obj: element, "Splicing in at {}", running_time);
+
+                    state.mode = Mode::Program;
+                    state.ad_done = true;
+                    state.in_point = gst::CLOCK_TIME_NONE;
+                    state.break_duration = gst::CLOCK_TIME_NONE;
+                    self.cond.notify_all();
+                    break;
+                }
+
+                // Drop program data that is already covered by the ad content, or all
+                // until the in point if the ad content finished early
+                if state.ad_done || state.ad_position.0.map_or(false, |pos| running_time <= pos) {
+                    gst_log!(self.cat, obj: pad, "Dropping program buffer during break");
+                    return gst::FlowReturn::Ok;
+                }
+
+                state = self.cond.wait(state).unwrap();
+            }
+
+            if state.mode == Mode::Program {
+                drop(state);
+                self.notify(&element.clone().upcast(), "in-break");
+                state = self.state.lock().unwrap();
+            }
+        }
+
+        let dts = state.program_segment.to_running_time(buffer.get_dts());
+        let events = self.prepare_output(&mut state, self.program_pad.get_current_caps());
+        drop(state);
+
+        self.push_output(events, buffer, running_time.into(), dts)
+    }
+
+    fn ad_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let pts = buffer.get_pts();
+        if pts.is_none() {
+            gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+            return gst::FlowReturn::Error;
+        }
+        let keyframe = !buffer.get_flags().contains(gst::BufferFlags::DELTA_UNIT);
+
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.flushing || state.ad_flushing {
+                return gst::FlowReturn::Flushing;
+            }
+            if state.ad_eos {
+                return gst::FlowReturn::Eos;
+            }
+            if state.mode == Mode::Ad && !state.ad_done {
+                break;
+            }
+
+            gst_log!(self.cat, obj: pad, "Waiting for break");
+            state = self.cond.wait(state).unwrap();
+        }
+
+        let running_time = match state.ad_segment.to_running_time(pts).0 {
+            None => return gst::FlowReturn::Ok,
+            Some(running_time) => running_time,
+        };
+
+        if state.ad_base.is_none() {
+            if !keyframe {
+                gst_log!(self.cat, obj: pad, "Waiting for ad keyframe");
+                return gst::FlowReturn::Ok;
+            }
+            state.ad_base = running_time.into();
+        }
+
+        let ad_base = state.ad_base.unwrap();
+        let splice_running_time = state.splice_running_time.unwrap();
+        let to_output = |running_time: u64| {
+            if running_time < ad_base {
+                gst::CLOCK_TIME_NONE
+            } else {
+                (splice_running_time + running_time - ad_base).into()
+            }
+        };
+
+        let out_running_time = to_output(running_time);
+        if out_running_time.is_none() {
+            return gst::FlowReturn::Ok;
+        }
+
+        if state.in_point.0.map_or(false, |point| out_running_time.unwrap() >= point) {
+            gst_debug!(self.cat, obj: pad, "Reached splice in point, ad content done");
+            state.ad_done = true;
+            state.ad_eos = true;
+            self.cond.notify_all();
+            return gst::FlowReturn::Eos;
+        }
+
+        let dts = match state.ad_segment.to_running_time(buffer.get_dts()).0 {
+            None => gst::CLOCK_TIME_NONE,
+            Some(dts) => to_output(dts),
+        };
+
+        state.ad_pushing = true;
+        state.ad_position = (out_running_time.unwrap() + buffer.get_duration().unwrap_or(0)).into();
+        let events = self.prepare_output(&mut state, self.ad_pad.get_current_caps());
+        drop(state);
+
+        let ret = self.push_output(events, buffer, out_running_time, dts);
+
+        let mut state = self.state.lock().unwrap();
+        state.ad_pushing = false;
+        self.cond.notify_all();
+
+        ret
+    }
+
+    fn program_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                // Output has its own segment
+                self.state.lock().unwrap().program_segment = segment;
+                return true;
+            }
+            EventView::Caps(..) => {
+                // Sent before the next buffer
+                return true;
+            }
+            EventView::CustomDownstream(..) => {
+                let splice = event
+                    .get_structure()
+                    .and_then(|s| if s.get_name() == "splice" { Some(s) } else { None })
+                    .map(|s| {
+                        (
+                            s.get::<bool>("out-of-network").unwrap_or(true),
+                            s.get::<u64>("running-time")
+                                .map(gst::ClockTime::from)
+                                .unwrap_or(gst::CLOCK_TIME_NONE),
+                            s.get::<u64>("duration")
+                                .map(gst::ClockTime::from)
+                                .unwrap_or(gst::CLOCK_TIME_NONE),
+                        )
+                    });
+
+                if let Some((out_of_network, running_time, duration)) = splice {
+                    return self.schedule_splice(element, out_of_network, running_time, duration);
+                }
+            }
+            EventView::FlushStart(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.flushing = true;
+                self.cond.notify_all();
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                let was_in_break = state.mode == Mode::Ad;
+                state.flushing = false;
+                state.mode = Mode::Program;
+                state.program_segment = gst::FormattedSegment::new();
+                state.out_point = gst::CLOCK_TIME_NONE;
+                state.in_point = gst::CLOCK_TIME_NONE;
+                state.break_duration = gst::CLOCK_TIME_NONE;
+                state.ad_done = was_in_break || state.ad_done;
+                state.segment_pending = true;
+                drop(state);
+
+                if was_in_break {
+                    self.notify(&element.clone().upcast(), "in-break");
+                }
+            }
+            EventView::Eos(..) => {
+                let mut state = self.state.lock().unwrap();
+                while state.ad_pushing && !state.flushing {
+                    state = self.cond.wait(state).unwrap();
+                }
+                state.mode = Mode::Program;
+                state.ad_done = true;
+                let events = self.prepare_output(&mut state, self.program_pad.get_current_caps());
+                self.cond.notify_all();
+                drop(state);
+
+                for event in events {
+                    self.srcpad.push_event(event);
+                }
+            }
+            _ => (),
+        }
+
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.srcpad.push_event(event)
+    }
+
+    fn ad_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        // Events of the ad stream are never forwarded, the output continues the program
+        match event.view() {
+            EventView::Segment(e) => match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                Err(_) => {
+                    gst_element_error!(
+                        element,
+                        gst::StreamError::Format,
+                        ["Only Time segments supported"]
+                    );
+                    return false;
+                }
+                Ok(segment) => {
+                    self.state.lock().unwrap().ad_segment = segment;
+                }
+            },
+            EventView::Eos(..) => {
+                let mut state = self.state.lock().unwrap();
+                gst_debug!(self.cat, obj: pad, "Ad content finished");
+                state.ad_eos = true;
+                if state.mode == Mode::Ad {
+                    state.ad_done = true;
+                }
+                self.cond.notify_all();
+            }
+            EventView::FlushStart(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.ad_flushing = true;
+                self.cond.notify_all();
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.ad_flushing = false;
+                state.ad_eos = false;
+                state.ad_segment = gst::FormattedSegment::new();
+                if state.mode == Mode::Ad {
+                    // Continue the break with the new ad content
+                    state.ad_base = gst::CLOCK_TIME_NONE;
+                    state.splice_running_time = state.ad_position;
+                }
+            }
+            _ => (),
+        }
+
+        true
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.program_pad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for Splicer {
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::Boolean("in-break", ..) => {
+                let state = self.state.lock().unwrap();
+                Ok((state.mode == Mode::Ad).to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for Splicer {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                *self.state.lock().unwrap() = State::default();
+            }
+            gst::StateChange::PausedToReady => {
+                // Unblock the streaming threads before the pads are deactivated
+                let mut state = self.state.lock().unwrap();
+                state.flushing = true;
+                state.ad_flushing = true;
+                self.cond.notify_all();
+            }
+            _ => (),
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct SplicerStatic;
+
+impl ImplTypeStatic<Element> for SplicerStatic {
+    fn get_name(&self) -> &str {
+        "Splicer"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        Splicer::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        Splicer::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let splicer_static = SplicerStatic;
+    register_type(splicer_static)
+}
@@ -0,0 +1,718 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::bin::*;
+
+use std::sync::Mutex;
+use std::u64;
+
+const DEFAULT_LOCATION: &str = "video%05d.mp4";
+const DEFAULT_MAX_SIZE_TIME: u64 = 0;
+const DEFAULT_MAX_SIZE_BYTES: u64 = 0;
+const DEFAULT_MUXER: &str = "rsmp4mux";
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: String,
+    max_size_time: u64,
+    max_size_bytes: u64,
+    muxer: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: DEFAULT_LOCATION.into(),
+            max_size_time: DEFAULT_MAX_SIZE_TIME,
+            max_size_bytes: DEFAULT_MAX_SIZE_BYTES,
+            muxer: DEFAULT_MUXER.into(),
+        }
+    }
+}
+
+static PROPERTIES: [Property; 4] = [
+    Property::String(
+        "location",
+        "Location",
+        "Location of the files to write, %d is replaced by the file index (e.g. video%05d.mp4)",
+        Some(DEFAULT_LOCATION),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "max-size-time",
+        "Max Size Time",
+        "Duration in nanoseconds after which a new file is started at the next keyframe (0 = off)",
+        (0, u64::MAX),
+        DEFAULT_MAX_SIZE_TIME,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "max-size-bytes",
+        "Max Size Bytes",
+        "Amount of media data after which a new file is started at the next keyframe (0 = off)",
+        (0, u64::MAX),
+        DEFAULT_MAX_SIZE_BYTES,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "muxer",
+        "Muxer",
+        "Name of the muxer factory to use, e.g. rsmp4mux or rswebmmux",
+        Some(DEFAULT_MUXER),
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+// Replaces printf-style %d / %05d placeholders with the file index
+fn format_location(location: &str, index: u32) -> String {
+    let mut res = String::with_capacity(location.len());
+    let mut chars = location.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            res.push(c);
+            continue;
+        }
+
+        let mut width = String::new();
+        while let Some(&c) = chars.peek() {
+            if !c.is_digit(10) {
+                break;
+            }
+            width.push(c);
+            chars.next();
+        }
+
+        match chars.next() {
+            Some('d') | Some('u') => {
+                let n = width.parse::<usize>().unwrap_or(0);
+                if width.starts_with('0') {
+                    res.push_str(&format!("{:01$}", index, n));
+                } else {
+                    res.push_str(&format!("{:1$}", index, n));
+                }
+            }
+            Some('%') if width.is_empty() => res.push('%'),
+            Some(c) => {
+                res.push('%');
+                res.push_str(&width);
+                res.push(c);
+            }
+            None => {
+                res.push('%');
+                res.push_str(&width);
+            }
+        }
+    }
+
+    res
+}
+
+// Whether the current file is full and a new one should be started at this keyframe
+fn need_new_fragment(settings: &Settings, start: u64, n_bytes: u64, running_time: u64) -> bool {
+    if settings.max_size_time > 0 && running_time >= start.saturating_add(settings.max_size_time) {
+        return true;
+    }
+
+    settings.max_size_bytes > 0 && n_bytes >= settings.max_size_bytes
+}
+
+struct Stream {
+    sinkpad: gst::Pad,
+    is_video: bool,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    eos: bool,
+}
+
+// Muxer and sink writing the current file, with the muxer's pads for each of our sinkpads
+struct Fragment {
+    index: u32,
+    location: String,
+    muxer: gst::Element,
+    sink: gst::Element,
+    pads: Vec<(gst::Pad, gst::Pad)>,
+}
+
+// Behaviour:
+//
+// Each of the request sinkpads is forwarded to a request pad of the muxer for the current
+// file. The first file is started with the first buffer. Once the configured duration or amount
+// of data is reached, the current file is finished by sending EOS to the muxer and a new muxer
+// and sink for the next file are created at the next video keyframe, or the next buffer if
+// there is no video. The sticky events of all streams are replayed to the new muxer. A
+// "splitmuxsink-fragment-closed" element message with the location is posted for every file
+// once it is finished, including the last one right before EOS.
+struct State {
+    streams: Vec<Stream>,
+    pad_count: u32,
+    fragment: Option<Fragment>,
+    n_fragments: u32,
+    fragment_start: u64,
+    fragment_bytes: u64,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            streams: Vec::new(),
+            pad_count: 0,
+            fragment: None,
+            n_fragments: 0,
+            fragment_start: 0,
+            fragment_bytes: 0,
+        }
+    }
+}
+
+struct SplitMuxSink {
+    cat: gst::DebugCategory,
+    // Serializes data flow of all streams to the current fragment
+    fragment_lock: Mutex<()>,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl SplitMuxSink {
+    fn new(_bin: &Bin) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rssplitmuxsink",
+                gst::DebugColorFlags::empty(),
+                "Rotating recording sink",
+            ),
+            fragment_lock: Mutex::new(()),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut BinClass) {
+        klass.set_metadata(
+            "Split Muxing Sink",
+            "Generic/Bin/Muxer/Sink",
+            "Muxes streams into a series of files that are split at keyframes",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        for name in &["video_%u", "audio_%u"] {
+            let sink_pad_template = gst::PadTemplate::new(
+                name,
+                gst::PadDirection::Sink,
+                gst::PadPresence::Request,
+                &caps,
+            );
+            klass.add_pad_template(sink_pad_template);
+        }
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(bin: &Bin) -> Box<BinImpl<Bin>> {
+        let imp = Self::new(bin);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Bin) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let bin = parent.as_ref().cloned().unwrap().downcast::<Bin>().unwrap();
+        let splitmuxsink = bin.get_impl().downcast_ref::<SplitMuxSink>().unwrap();
+        bin.catch_panic(fallback, |bin| f(splitmuxsink, bin))
+    }
+
+    fn open_fragment(&self, bin: &Bin, settings: &Settings) -> Result<(), String> {
+        let make = |factory: &str| {
+            gst::ElementFactory::make(factory, None)
+                .ok_or_else(|| format!("Failed to create {}", factory))
+        };
+
+        let muxer = make(&settings.muxer)?;
+        let sink = make("filesink")?;
+
+        let (index, streams) = {
+            let state = self.state.lock().unwrap();
+            let streams = state
+                .streams
+                .iter()
+                .map(|s| (s.sinkpad.clone(), s.is_video, s.eos))
+                .collect::<Vec<_>>();
+            (state.n_fragments, streams)
+        };
+
+        let location = format_location(&settings.location, index);
+        gst_info!(self.cat, obj: bin, "Starting file {}", location);
+
+        sink.set_property("location", &location).unwrap();
+        sink.set_property("sync", &false).unwrap();
+        sink.set_property("async", &false).unwrap();
+
+        bin.add_many(&[&muxer, &sink])
+            .map_err(|_| String::from("Failed to add muxer"))?;
+        muxer
+            .link(&sink)
+            .map_err(|_| String::from("Failed to link muxer"))?;
+
+        let mut pads = Vec::with_capacity(streams.len());
+        for &(ref sinkpad, is_video, _) in &streams {
+            let templ = if is_video { "video_%u" } else { "audio_%u" };
+            let muxpad = muxer
+                .get_request_pad(templ)
+                .ok_or_else(|| format!("Failed to request {} pad from muxer", templ))?;
+            pads.push((sinkpad.clone(), muxpad));
+        }
+
+        sink.sync_state_with_parent()
+            .map_err(|_| String::from("Failed to start sink"))?;
+        muxer
+            .sync_state_with_parent()
+            .map_err(|_| String::from("Failed to start muxer"))?;
+
+        // The new muxer needs the same stream configuration as the previous one
+        for (&(ref sinkpad, _, eos), &(_, ref muxpad)) in streams.iter().zip(pads.iter()) {
+            for event_type in &[
+                gst::EventType::StreamStart,
+                gst::EventType::Caps,
+                gst::EventType::Segment,
+            ] {
+                if let Some(event) = sinkpad.get_sticky_event(*event_type, 0) {
+                    muxpad.send_event(event);
+                }
+            }
+            if eos {
+                muxpad.send_event(gst::Event::new_eos().build());
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.n_fragments += 1;
+        state.fragment_bytes = 0;
+        state.fragment = Some(Fragment {
+            index: index,
+            location: location,
+            muxer: muxer,
+            sink: sink,
+            pads: pads,
+        });
+
+        Ok(())
+    }
+
+    fn close_fragment(&self, bin: &Bin, fragment: Fragment) {
+        gst_info!(self.cat, obj: bin, "Finishing file {}", fragment.location);
+
+        // The muxer writes its headers once all its pads are EOS
+        for &(_, ref muxpad) in &fragment.pads {
+            muxpad.send_event(gst::Event::new_eos().build());
+        }
+
+        let _ = fragment.muxer.set_state(gst::State::Null);
+        let _ = fragment.sink.set_state(gst::State::Null);
+        let _ = bin.remove_many(&[&fragment.muxer, &fragment.sink]);
+
+        self.post_fragment_closed(bin, fragment.index, &fragment.location);
+    }
+
+    fn post_fragment_closed(&self, bin: &Bin, index: u32, location: &str) {
+        let s = gst::Structure::new(
+            "splitmuxsink-fragment-closed",
+            &[("location", &location), ("fragment-id", &index)],
+        );
+
+        gst_debug!(self.cat, obj: bin, "Posting {}", s);
+        let _ = bin.post_message(&gst::Message::new_element(s).src(Some(bin)).build());
+    }
+
+    fn teardown_fragment(&self, bin: &Bin) {
+        let fragment = self.state.lock().unwrap().fragment.take();
+
+        if let Some(fragment) = fragment {
+            let _ = fragment.muxer.set_state(gst::State::Null);
+            let _ = fragment.sink.set_state(gst::State::Null);
+            let _ = bin.remove_many(&[&fragment.muxer, &fragment.sink]);
+        }
+    }
+
+    fn get_muxpad(&self, pad: &gst::Pad) -> Option<gst::Pad> {
+        let state = self.state.lock().unwrap();
+        state.fragment.as_ref().and_then(|f| {
+            f.pads
+                .iter()
+                .find(|&&(ref sinkpad, _)| sinkpad == pad)
+                .map(|&(_, ref muxpad)| muxpad.clone())
+        })
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, bin: &Bin, buffer: gst::Buffer) -> gst::FlowReturn {
+        gst_log!(self.cat, obj: pad, "Handling buffer {:?}", buffer);
+
+        let _fragment_lock = self.fragment_lock.lock().unwrap();
+        let settings = self.settings.lock().unwrap().clone();
+
+        let keyframe = !buffer.get_flags().contains(gst::BufferFlags::DELTA_UNIT);
+
+        let (old_fragment, need_open) = {
+            let mut state = self.state.lock().unwrap();
+            let has_video = state.streams.iter().any(|s| s.is_video);
+
+            let (is_video, running_time) = match state.streams.iter().find(|s| s.sinkpad == *pad) {
+                None => return gst::FlowReturn::Error,
+                Some(stream) => (
+                    stream.is_video,
+                    stream.segment.to_running_time(buffer.get_pts()).0,
+                ),
+            };
+
+            if state.fragment.is_none() {
+                state.fragment_start = running_time.unwrap_or(0);
+                (None, true)
+            } else {
+                match running_time {
+                    Some(running_time)
+                        if keyframe
+                            && (is_video || !has_video)
+                            && need_new_fragment(
+                                &settings,
+                                state.fragment_start,
+                                state.fragment_bytes,
+                                running_time,
+                            ) =>
+                    {
+                        state.fragment_start = running_time;
+                        (state.fragment.take(), true)
+                    }
+                    _ => (None, false),
+                }
+            }
+        };
+
+        if let Some(fragment) = old_fragment {
+            self.close_fragment(bin, fragment);
+        }
+
+        if need_open {
+            if let Err(err) = self.open_fragment(bin, &settings) {
+                gst_element_error!(bin, gst::ResourceError::OpenWrite, ["{}", err]);
+                return gst::FlowReturn::Error;
+            }
+        }
+
+        let muxpad = match self.get_muxpad(pad) {
+            None => return gst::FlowReturn::Error,
+            Some(muxpad) => muxpad,
+        };
+
+        self.state.lock().unwrap().fragment_bytes += buffer.get_size() as u64;
+
+        muxpad.chain(buffer)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, bin: &Bin, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        let _fragment_lock = self.fragment_lock.lock().unwrap();
+
+        let mut all_eos = false;
+        match event.view() {
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            bin,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    stream.segment = segment;
+                }
+            }
+            EventView::Eos(..) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    stream.eos = true;
+                }
+                all_eos = state.streams.iter().all(|s| s.eos);
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(stream) = state.streams.iter_mut().find(|s| s.sinkpad == *pad) {
+                    stream.eos = false;
+                }
+            }
+            _ => (),
+        }
+
+        // Sticky events are stored on our pad and replayed when the next file is started
+        let ret = match self.get_muxpad(pad) {
+            None => true,
+            Some(muxpad) => muxpad.send_event(event),
+        };
+
+        // Without any file there is no sink that could post EOS
+        if all_eos && self.state.lock().unwrap().fragment.is_none() {
+            let _ = bin.post_message(&gst::Message::new_eos().src(Some(bin)).build());
+        }
+
+        ret
+    }
+}
+
+impl ObjectImpl<Bin> for SplitMuxSink {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::String("location", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.location = value.get().unwrap_or_else(|| DEFAULT_LOCATION.into());
+            }
+            Property::UInt64("max-size-time", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.max_size_time = value.get().unwrap();
+            }
+            Property::UInt64("max-size-bytes", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.max_size_bytes = value.get().unwrap();
+            }
+            Property::String("muxer", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.muxer = value.get().unwrap_or_else(|| DEFAULT_MUXER.into());
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => Ok(settings.location.to_value()),
+            Property::UInt64("max-size-time", ..) => Ok(settings.max_size_time.to_value()),
+            Property::UInt64("max-size-bytes", ..) => Ok(settings.max_size_bytes.to_value()),
+            Property::String("muxer", ..) => Ok(settings.muxer.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Bin> for SplitMuxSink {
+    fn change_state(&self, bin: &Bin, transition: gst::StateChange) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: bin, "Changing state {:?}", transition);
+
+        let ret = bin.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        match transition {
+            gst::StateChange::PausedToReady => {
+                let _fragment_lock = self.fragment_lock.lock().unwrap();
+                self.teardown_fragment(bin);
+
+                let mut state = self.state.lock().unwrap();
+                state.n_fragments = 0;
+                state.fragment_start = 0;
+                state.fragment_bytes = 0;
+                for stream in &mut state.streams {
+                    stream.segment = gst::FormattedSegment::new();
+                    stream.eos = false;
+                }
+            }
+            _ => (),
+        }
+
+        ret
+    }
+
+    fn request_new_pad(
+        &self,
+        bin: &Bin,
+        templ: &gst::PadTemplate,
+        _name: Option<String>,
+        _caps: Option<&gst::CapsRef>,
+    ) -> Option<gst::Pad> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.fragment.is_some() {
+            gst_error!(self.cat, obj: bin, "Can't request pads after recording started");
+            return None;
+        }
+
+        let id = state.pad_count;
+        state.pad_count += 1;
+
+        let is_video = *templ == bin.get_pad_template("video_%u").unwrap();
+        let name = if is_video {
+            format!("video_{}", id)
+        } else {
+            format!("audio_{}", id)
+        };
+        let sinkpad = gst::Pad::new_from_template(templ, name.as_str());
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            SplitMuxSink::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |splitmuxsink, bin| splitmuxsink.sink_chain(pad, bin, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            SplitMuxSink::catch_panic_pad_function(
+                parent,
+                || false,
+                |splitmuxsink, bin| splitmuxsink.sink_event(pad, bin, event),
+            )
+        });
+
+        sinkpad.set_active(true).unwrap();
+        bin.add_pad(&sinkpad).unwrap();
+
+        state.streams.push(Stream {
+            sinkpad: sinkpad.clone(),
+            is_video: is_video,
+            segment: gst::FormattedSegment::new(),
+            eos: false,
+        });
+
+        Some(sinkpad)
+    }
+
+    fn release_pad(&self, bin: &Bin, pad: &gst::Pad) {
+        let mut state = self.state.lock().unwrap();
+
+        let pos = match state.streams.iter().position(|s| s.sinkpad == *pad) {
+            None => return,
+            Some(pos) => pos,
+        };
+        let stream = state.streams.remove(pos);
+        drop(state);
+
+        stream.sinkpad.set_active(false).unwrap();
+        bin.remove_pad(&stream.sinkpad).unwrap();
+    }
+}
+
+impl BinImpl<Bin> for SplitMuxSink {
+    fn handle_message(&self, bin: &Bin, message: gst::Message) {
+        use gst::MessageView;
+
+        match message.view() {
+            // Finished files are not the end of the stream
+            MessageView::Eos(..) => {
+                let last_fragment = {
+                    let state = self.state.lock().unwrap();
+                    if !state.streams.is_empty() && state.streams.iter().all(|s| s.eos) {
+                        state
+                            .fragment
+                            .as_ref()
+                            .map(|f| (f.index, f.location.clone()))
+                    } else {
+                        None
+                    }
+                };
+
+                match last_fragment {
+                    None => {
+                        gst_debug!(self.cat, obj: bin, "Dropping EOS of finished file");
+                        return;
+                    }
+                    Some((index, location)) => {
+                        self.post_fragment_closed(bin, index, &location);
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        bin.parent_handle_message(message)
+    }
+}
+
+struct SplitMuxSinkStatic;
+
+impl ImplTypeStatic<Bin> for SplitMuxSinkStatic {
+    fn get_name(&self) -> &str {
+        "SplitMuxSink"
+    }
+
+    fn new(&self, bin: &Bin) -> Box<BinImpl<Bin>> {
+        SplitMuxSink::init(bin)
+    }
+
+    fn class_init(&self, klass: &mut BinClass) {
+        SplitMuxSink::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let splitmuxsink_static = SplitMuxSinkStatic;
+    register_type(splitmuxsink_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_location() {
+        assert_eq!(format_location("video%05d.mp4", 3), "video00003.mp4");
+        assert_eq!(format_location("video%d.mkv", 12), "video12.mkv");
+        assert_eq!(format_location("video%3d.mkv", 12), "video 12.mkv");
+        assert_eq!(format_location("100%%-%d", 1), "100%-1");
+        assert_eq!(format_location("video.mp4", 1), "video.mp4");
+        assert_eq!(format_location("video%s%", 1), "video%s%");
+    }
+
+    #[test]
+    fn test_need_new_fragment() {
+        let settings = Settings {
+            max_size_time: 10 * gst::SECOND_VAL,
+            ..Settings::default()
+        };
+        assert!(!need_new_fragment(
+            &settings,
+            gst::SECOND_VAL,
+            0,
+            10 * gst::SECOND_VAL
+        ));
+        assert!(need_new_fragment(
+            &settings,
+            gst::SECOND_VAL,
+            0,
+            11 * gst::SECOND_VAL
+        ));
+
+        let settings = Settings {
+            max_size_bytes: 1000,
+            ..Settings::default()
+        };
+        assert!(!need_new_fragment(&settings, 0, 999, u64::MAX));
+        assert!(need_new_fragment(&settings, 0, 1000, 0));
+
+        let settings = Settings::default();
+        assert!(!need_new_fragment(&settings, 0, u64::MAX, u64::MAX));
+    }
+}
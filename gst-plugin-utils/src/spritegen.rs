@@ -0,0 +1,817 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use image;
+
+use std::fs;
+use std::io::Write;
+use std::sync::Mutex;
+
+// Thumbnail generation:
+//
+// Every `interval` nanoseconds of stream time one frame is downscaled into the next tile of a
+// sprite sheet, filling it row by row. Once all tiles are used, or on EOS, the sheet is encoded
+// as JPEG and pushed downstream with the timestamp of its first tile, e.g. to a multifilesink or
+// the image pad of dashrssink. All sheets are also referenced from a WebVTT track with one cue
+// per tile of the form "sprite00000.jpg#xywh=0,0,160,90", which is what most web players use for
+// scrub previews.
+const DEFAULT_INTERVAL: u64 = 5 * 1_000_000_000;
+const DEFAULT_TILE_WIDTH: u32 = 160;
+const DEFAULT_TILE_HEIGHT: u32 = 90;
+const DEFAULT_COLUMNS: u32 = 5;
+const DEFAULT_ROWS: u32 = 5;
+const DEFAULT_QUALITY: u32 = 85;
+const DEFAULT_VTT_LOCATION: Option<&str> = None;
+const DEFAULT_SHEET_LOCATION: &str = "sprite%05d.jpg";
+
+#[derive(Debug, Clone)]
+struct Settings {
+    interval: u64,
+    tile_width: u32,
+    tile_height: u32,
+    columns: u32,
+    rows: u32,
+    quality: u32,
+    vtt_location: Option<String>,
+    sheet_location: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            interval: DEFAULT_INTERVAL,
+            tile_width: DEFAULT_TILE_WIDTH,
+            tile_height: DEFAULT_TILE_HEIGHT,
+            columns: DEFAULT_COLUMNS,
+            rows: DEFAULT_ROWS,
+            quality: DEFAULT_QUALITY,
+            vtt_location: DEFAULT_VTT_LOCATION.map(String::from),
+            sheet_location: String::from(DEFAULT_SHEET_LOCATION),
+        }
+    }
+}
+
+static PROPERTIES: [Property; 8] = [
+    Property::UInt64(
+        "interval",
+        "Interval",
+        "Stream time in nanoseconds between two thumbnails",
+        (1, u64::MAX),
+        DEFAULT_INTERVAL,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "tile-width",
+        "Tile Width",
+        "Width of a single thumbnail",
+        (1, 4096),
+        DEFAULT_TILE_WIDTH,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "tile-height",
+        "Tile Height",
+        "Height of a single thumbnail",
+        (1, 4096),
+        DEFAULT_TILE_HEIGHT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "columns",
+        "Columns",
+        "Number of thumbnails per row of a sheet",
+        (1, 64),
+        DEFAULT_COLUMNS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "rows",
+        "Rows",
+        "Number of thumbnail rows per sheet",
+        (1, 64),
+        DEFAULT_ROWS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "quality",
+        "Quality",
+        "JPEG quality of the sheets",
+        (1, 100),
+        DEFAULT_QUALITY,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "vtt-location",
+        "WebVTT Location",
+        "Location of the WebVTT thumbnail track, or none to not write it",
+        DEFAULT_VTT_LOCATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "sheet-location",
+        "Sheet Location",
+        "URI of the sheets in the WebVTT track, %05d is replaced by the sheet number",
+        Some(DEFAULT_SHEET_LOCATION),
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cue {
+    start: u64,
+    end: u64,
+    uri: String,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+// Sheet that is currently filled, with the layout it was started with
+struct Sheet {
+    data: Vec<u8>,
+    columns: u32,
+    rows: u32,
+    tile_width: u32,
+    tile_height: u32,
+    pts: gst::ClockTime,
+    // Stream time of each captured tile
+    tiles: Vec<u64>,
+}
+
+impl Sheet {
+    fn new(settings: &Settings, pts: gst::ClockTime) -> Self {
+        let width = settings.columns * settings.tile_width;
+        let height = settings.rows * settings.tile_height;
+
+        Sheet {
+            data: vec![0; (width * height * 3) as usize],
+            columns: settings.columns,
+            rows: settings.rows,
+            tile_width: settings.tile_width,
+            tile_height: settings.tile_height,
+            pts: pts,
+            tiles: Vec::new(),
+        }
+    }
+
+    fn width(&self) -> u32 {
+        self.columns * self.tile_width
+    }
+
+    fn height(&self) -> u32 {
+        self.rows * self.tile_height
+    }
+
+    fn is_full(&self) -> bool {
+        self.tiles.len() as u32 == self.columns * self.rows
+    }
+
+    fn tile_position(&self, tile: u32) -> (u32, u32) {
+        (
+            (tile % self.columns) * self.tile_width,
+            (tile / self.columns) * self.tile_height,
+        )
+    }
+
+    fn add_tile(&mut self, info: &gst_video::VideoInfo, data: &[u8], time: u64) {
+        let (x, y) = self.tile_position(self.tiles.len() as u32);
+        let stride = self.width() as usize * 3;
+        let offset = y as usize * stride + x as usize * 3;
+        scale_rgb(
+            &data[info.offset()[0]..],
+            (info.width() as usize, info.height() as usize),
+            info.stride()[0] as usize,
+            &mut self.data[offset..],
+            (self.tile_width as usize, self.tile_height as usize),
+            stride,
+        );
+        self.tiles.push(time);
+    }
+
+    // One cue per tile, each lasting until the next one
+    fn cues(&self, uri: &str, interval: u64) -> Vec<Cue> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| {
+                let end = self.tiles.get(i + 1).cloned().unwrap_or(start + interval);
+                let (x, y) = self.tile_position(i as u32);
+                Cue {
+                    start: start,
+                    end: end,
+                    uri: String::from(uri),
+                    x: x,
+                    y: y,
+                    width: self.tile_width,
+                    height: self.tile_height,
+                }
+            })
+            .collect()
+    }
+}
+
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    // Stream time from which on the next thumbnail is taken
+    next_capture: Option<u64>,
+    sheet: Option<Sheet>,
+    sheet_number: u32,
+    cues: Vec<Cue>,
+    srccaps: Option<gst::Caps>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            info: None,
+            segment: gst::FormattedSegment::new(),
+            next_capture: None,
+            sheet: None,
+            sheet_number: 0,
+            cues: Vec::new(),
+            srccaps: None,
+        }
+    }
+}
+
+struct SpriteGen {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+fn format_location(template: &str, number: u32) -> String {
+    let start = match template.find('%') {
+        None => return String::from(template),
+        Some(start) => start,
+    };
+    let end = match template[start..].find('d') {
+        None => return String::from(template),
+        Some(end) => start + end,
+    };
+
+    let format = &template[(start + 1)..end];
+    let width = if format.is_empty() {
+        Some(0)
+    } else if format.starts_with('0') {
+        format[1..].parse::<usize>().ok()
+    } else {
+        None
+    };
+
+    match width {
+        None => String::from(template),
+        Some(width) => format!(
+            "{}{:0width$}{}",
+            &template[..start],
+            number,
+            &template[(end + 1)..],
+            width = width
+        ),
+    }
+}
+
+// Box filter downscaling of packed RGB into a tile of a larger image
+fn scale_rgb(
+    src: &[u8],
+    (src_width, src_height): (usize, usize),
+    src_stride: usize,
+    dst: &mut [u8],
+    (dst_width, dst_height): (usize, usize),
+    dst_stride: usize,
+) {
+    for ty in 0..dst_height {
+        let y0 = ty * src_height / dst_height;
+        let y1 = ((ty + 1) * src_height / dst_height).max(y0 + 1);
+
+        for tx in 0..dst_width {
+            let x0 = tx * src_width / dst_width;
+            let x1 = ((tx + 1) * src_width / dst_width).max(x0 + 1);
+
+            let mut sum = [0u32; 3];
+            for y in y0..y1 {
+                let row = &src[(y * src_stride + x0 * 3)..(y * src_stride + x1 * 3)];
+                for pixel in row.chunks(3) {
+                    for (s, &p) in sum.iter_mut().zip(pixel) {
+                        *s += u32::from(p);
+                    }
+                }
+            }
+
+            let count = ((y1 - y0) * (x1 - x0)) as u32;
+            let out = &mut dst[(ty * dst_stride + tx * 3)..(ty * dst_stride + tx * 3 + 3)];
+            for (o, &s) in out.iter_mut().zip(&sum) {
+                *o = ((s + count / 2) / count) as u8;
+            }
+        }
+    }
+}
+
+fn format_vtt_time(time: u64) -> String {
+    let ms = time / 1_000_000;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        (ms / 60_000) % 60,
+        (ms / 1_000) % 60,
+        ms % 1_000
+    )
+}
+
+fn render_vtt(cues: &[Cue]) -> String {
+    let mut vtt = String::from("WEBVTT\n");
+    for cue in cues {
+        vtt.push_str(&format!(
+            "\n{} --> {}\n{}#xywh={},{},{},{}\n",
+            format_vtt_time(cue.start),
+            format_vtt_time(cue.end),
+            cue.uri,
+            cue.x,
+            cue.y,
+            cue.width,
+            cue.height
+        ));
+    }
+    vtt
+}
+
+impl SpriteGen {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "spritegen",
+                gst::DebugColorFlags::empty(),
+                "Thumbnail sprite sheet generator",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Thumbnail Sprite Generator",
+            "Filter/Video",
+            "Collects thumbnails in JPEG sprite sheets and writes a WebVTT thumbnail track",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple(
+            "video/x-raw",
+            &[
+                ("format", &"RGB"),
+                ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
+                (
+                    "framerate",
+                    &gst::FractionRange::new(
+                        gst::Fraction::new(0, 1),
+                        gst::Fraction::new(i32::MAX, 1),
+                    ),
+                ),
+            ],
+        );
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let caps = gst::Caps::new_simple("image/jpeg", &[]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            SpriteGen::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |spritegen, element| spritegen.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            SpriteGen::catch_panic_pad_function(
+                parent,
+                || false,
+                |spritegen, element| spritegen.sink_event(pad, element, event),
+            )
+        });
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let spritegen = element.get_impl().downcast_ref::<SpriteGen>().unwrap();
+        element.catch_panic(fallback, |element| f(spritegen, element))
+    }
+
+    fn write_vtt(&self, element: &Element, location: &str, cues: &[Cue]) -> Result<(), String> {
+        gst_debug!(self.cat, obj: element, "Writing WebVTT track {}", location);
+
+        // Players might read the track at any time, so replace it atomically
+        let tmp_location = format!("{}.tmp", location);
+        fs::File::create(&tmp_location)
+            .and_then(|mut f| f.write_all(render_vtt(cues).as_bytes()))
+            .and_then(|_| fs::rename(&tmp_location, location))
+            .map_err(|err| format!("Failed to write {}: {}", location, err))
+    }
+
+    // Encodes the current sheet and updates the WebVTT track. Returns the caps and buffer to push
+    fn finish_sheet(
+        &self,
+        element: &Element,
+        settings: &Settings,
+        state: &mut State,
+    ) -> Result<Option<(Option<gst::Caps>, gst::Buffer)>, gst::FlowReturn> {
+        let sheet = match state.sheet.take() {
+            None => return Ok(None),
+            Some(sheet) => sheet,
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Finishing sheet {} with {} tiles",
+            state.sheet_number,
+            sheet.tiles.len()
+        );
+
+        let mut data = Vec::new();
+        let res = {
+            let mut encoder =
+                image::jpeg::JPEGEncoder::new_with_quality(&mut data, settings.quality as u8);
+            encoder.encode(
+                &sheet.data,
+                sheet.width(),
+                sheet.height(),
+                image::ColorType::RGB(8),
+            )
+        };
+        if let Err(err) = res {
+            gst_element_error!(
+                element,
+                gst::LibraryError::Encode,
+                ["Failed to encode sheet: {}", err]
+            );
+            return Err(gst::FlowReturn::Error);
+        }
+
+        let uri = format_location(&settings.sheet_location, state.sheet_number);
+        state.sheet_number += 1;
+        let cues = sheet.cues(&uri, settings.interval);
+        let duration = cues.last().map(|c| c.end).unwrap_or(0) - cues[0].start;
+        state.cues.extend(cues);
+
+        if let Some(ref location) = settings.vtt_location {
+            if let Err(err) = self.write_vtt(element, location, &state.cues) {
+                gst_element_error!(element, gst::ResourceError::Write, ["{}", err]);
+                return Err(gst::FlowReturn::Error);
+            }
+        }
+
+        let caps = gst::Caps::new_simple(
+            "image/jpeg",
+            &[
+                ("width", &(sheet.width() as i32)),
+                ("height", &(sheet.height() as i32)),
+                ("framerate", &gst::Fraction::new(0, 1)),
+                ("columns", &(sheet.columns as i32)),
+                ("rows", &(sheet.rows as i32)),
+            ],
+        );
+        let caps = if state.srccaps.as_ref() != Some(&caps) {
+            state.srccaps = Some(caps.clone());
+            Some(caps)
+        } else {
+            None
+        };
+
+        let mut buffer = gst::Buffer::from_mut_slice(data).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(sheet.pts);
+            buffer.set_duration(gst::ClockTime::from_nseconds(duration));
+        }
+
+        Ok(Some((caps, buffer)))
+    }
+
+    fn push_sheet(&self, sheet: Option<(Option<gst::Caps>, gst::Buffer)>) -> gst::FlowReturn {
+        match sheet {
+            None => gst::FlowReturn::Ok,
+            Some((caps, buffer)) => {
+                if let Some(caps) = caps {
+                    self.srcpad.push_event(gst::Event::new_caps(&caps).build());
+                }
+                self.srcpad.push(buffer)
+            }
+        }
+    }
+
+    fn sink_chain(&self, pad: &gst::Pad, element: &Element, buffer: gst::Buffer) -> gst::FlowReturn {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let sheet = {
+            let mut state = self.state.lock().unwrap();
+
+            let info = match state.info {
+                None => {
+                    gst_element_error!(
+                        element,
+                        gst::CoreError::Negotiation,
+                        ["No caps received before buffer"]
+                    );
+                    return gst::FlowReturn::NotNegotiated;
+                }
+                Some(ref info) => info.clone(),
+            };
+
+            let pts = buffer.get_pts();
+            let time = match state.segment.to_stream_time(pts).0 {
+                None => {
+                    gst_log!(self.cat, obj: pad, "Buffer outside segment, skipping");
+                    return gst::FlowReturn::Ok;
+                }
+                Some(time) => time,
+            };
+
+            if state.next_capture.map(|next| time < next).unwrap_or(false) {
+                return gst::FlowReturn::Ok;
+            }
+            state.next_capture = Some(time + settings.interval);
+
+            gst_log!(
+                self.cat,
+                obj: pad,
+                "Taking thumbnail at {}",
+                gst::ClockTime::from_nseconds(time)
+            );
+
+            let map = match buffer.map_readable() {
+                None => return gst::FlowReturn::Error,
+                Some(map) => map,
+            };
+
+            if state.sheet.is_none() {
+                state.sheet = Some(Sheet::new(&settings, pts));
+            }
+            let full = {
+                let sheet = state.sheet.as_mut().unwrap();
+                sheet.add_tile(&info, map.as_slice(), time);
+                sheet.is_full()
+            };
+
+            if !full {
+                return gst::FlowReturn::Ok;
+            }
+
+            match self.finish_sheet(element, &settings, &mut state) {
+                Err(flow) => return flow,
+                Ok(sheet) => sheet,
+            }
+        };
+
+        self.push_sheet(sheet)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let caps = e.get_caps();
+                let info = match gst_video::VideoInfo::from_caps(caps) {
+                    None => return false,
+                    Some(info) => info,
+                };
+
+                gst_debug!(self.cat, obj: pad, "Configured for caps {}", caps);
+                self.state.lock().unwrap().info = Some(info);
+
+                // Our own caps are sent before the first sheet
+                return true;
+            }
+            EventView::Segment(e) => {
+                let mut state = self.state.lock().unwrap();
+                state.segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Ok(segment) => segment,
+                    Err(_) => {
+                        gst_warning!(self.cat, obj: element, "Not a time segment");
+                        gst::FormattedSegment::new()
+                    }
+                };
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.sheet = None;
+                state.next_capture = None;
+            }
+            EventView::Eos(..) => {
+                let settings = self.settings.lock().unwrap().clone();
+                let sheet = {
+                    let mut state = self.state.lock().unwrap();
+                    self.finish_sheet(element, &settings, &mut state)
+                };
+                if let Ok(sheet) = sheet {
+                    self.push_sheet(sheet);
+                }
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for SpriteGen {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt64("interval", ..) => {
+                settings.interval = value.get().unwrap();
+            }
+            Property::UInt("tile-width", ..) => {
+                settings.tile_width = value.get().unwrap();
+            }
+            Property::UInt("tile-height", ..) => {
+                settings.tile_height = value.get().unwrap();
+            }
+            Property::UInt("columns", ..) => {
+                settings.columns = value.get().unwrap();
+            }
+            Property::UInt("rows", ..) => {
+                settings.rows = value.get().unwrap();
+            }
+            Property::UInt("quality", ..) => {
+                settings.quality = value.get().unwrap();
+            }
+            Property::String("vtt-location", ..) => {
+                settings.vtt_location = value.get();
+            }
+            Property::String("sheet-location", ..) => {
+                settings.sheet_location = value
+                    .get()
+                    .unwrap_or_else(|| String::from(DEFAULT_SHEET_LOCATION));
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt64("interval", ..) => Ok(settings.interval.to_value()),
+            Property::UInt("tile-width", ..) => Ok(settings.tile_width.to_value()),
+            Property::UInt("tile-height", ..) => Ok(settings.tile_height.to_value()),
+            Property::UInt("columns", ..) => Ok(settings.columns.to_value()),
+            Property::UInt("rows", ..) => Ok(settings.rows.to_value()),
+            Property::UInt("quality", ..) => Ok(settings.quality.to_value()),
+            Property::String("vtt-location", ..) => Ok(settings.vtt_location.to_value()),
+            Property::String("sheet-location", ..) => Ok(settings.sheet_location.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for SpriteGen {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        let ret = element.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        match transition {
+            gst::StateChange::PausedToReady => {
+                *self.state.lock().unwrap() = State::default();
+            }
+            _ => (),
+        }
+
+        ret
+    }
+}
+
+struct SpriteGenStatic;
+
+impl ImplTypeStatic<Element> for SpriteGenStatic {
+    fn get_name(&self) -> &str {
+        "SpriteGen"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        SpriteGen::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        SpriteGen::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let spritegen_static = SpriteGenStatic;
+    register_type(spritegen_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_and_vtt() {
+        // 4x2 image with a red left half and a blue right half, scaled into the second tile
+        // of a 4x1 sheet
+        let mut src = Vec::new();
+        for _ in 0..2 {
+            src.extend_from_slice(&[255, 0, 0, 255, 0, 0, 0, 0, 255, 0, 0, 255]);
+        }
+        let mut dst = vec![0u8; 4 * 3];
+        scale_rgb(&src, (4, 2), 12, &mut dst[6..], (2, 1), 12);
+        assert_eq!(dst, vec![0, 0, 0, 0, 0, 0, 255, 0, 0, 0, 0, 255]);
+
+        let cues = vec![
+            Cue {
+                start: 0,
+                end: 5_000_000_000,
+                uri: format_location(DEFAULT_SHEET_LOCATION, 0),
+                x: 0,
+                y: 0,
+                width: 160,
+                height: 90,
+            },
+            Cue {
+                start: 5_000_000_000,
+                end: 3_723_004_000_000,
+                uri: format_location(DEFAULT_SHEET_LOCATION, 0),
+                x: 160,
+                y: 0,
+                width: 160,
+                height: 90,
+            },
+        ];
+        assert_eq!(
+            render_vtt(&cues),
+            "WEBVTT\n\n\
+             00:00:00.000 --> 00:00:05.000\nsprite00000.jpg#xywh=0,0,160,90\n\n\
+             00:00:05.000 --> 01:02:03.004\nsprite00000.jpg#xywh=160,0,160,90\n"
+        );
+    }
+}
@@ -0,0 +1,725 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::bin::*;
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::u32;
+
+const DEFAULT_ITERATIONS: u32 = 1;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    uris: Vec<String>,
+    iterations: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            uris: Vec::new(),
+            iterations: DEFAULT_ITERATIONS,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 5] = [
+    Property::Boxed(
+        "uris",
+        "URIs",
+        "URIs to play, can be extended while playing until the next item is prepared",
+        Vec::<String>::static_type,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "iterations",
+        "Iterations",
+        "Number of times the playlist is played (0 = forever)",
+        (0, u32::MAX),
+        DEFAULT_ITERATIONS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "current-uri-index",
+        "Current URI Index",
+        "Index of the URI that is currently played",
+        (0, u32::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+    Property::String(
+        "current-uri",
+        "Current URI",
+        "URI that is currently played",
+        None,
+        PropertyMutability::Readable,
+    ),
+    Property::UInt(
+        "current-iteration",
+        "Current Iteration",
+        "Number of completed iterations of the playlist",
+        (0, u32::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+];
+
+// Index and iteration of the item after the given one, if any
+fn get_next_item(settings: &Settings, index: u32, iteration: u32) -> Option<(u32, u32)> {
+    let n_uris = settings.uris.len() as u32;
+
+    if index + 1 < n_uris {
+        Some((index + 1, iteration))
+    } else if n_uris > 0 && (settings.iterations == 0 || iteration + 1 < settings.iterations) {
+        Some((0, iteration + 1))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MediaType {
+    Video,
+    Audio,
+}
+
+impl MediaType {
+    fn get_caps(&self) -> gst::Caps {
+        match *self {
+            MediaType::Video => gst::Caps::new_simple("video/x-raw", &[]),
+            MediaType::Audio => gst::Caps::new_simple("audio/x-raw", &[]),
+        }
+    }
+
+    fn get_pad_name(&self) -> &'static str {
+        match *self {
+            MediaType::Video => "video",
+            MediaType::Audio => "audio",
+        }
+    }
+
+    fn from_caps(caps: &gst::Caps) -> Option<MediaType> {
+        let s = caps.get_structure(0)?;
+        if s.get_name().starts_with("video/") {
+            Some(MediaType::Video)
+        } else if s.get_name().starts_with("audio/") {
+            Some(MediaType::Audio)
+        } else {
+            None
+        }
+    }
+}
+
+struct Item {
+    index: u32,
+    iteration: u32,
+    uri: String,
+    decodebin: gst::Element,
+    // Decoded pads linked to the concat request pads, and sinks for unused streams
+    pads: Vec<gst::Pad>,
+    concat_pads: Vec<(gst::Element, gst::Pad)>,
+    fakesinks: Vec<gst::Element>,
+    n_eos: usize,
+    ready: bool,
+}
+
+// Behaviour:
+//
+// Every item of the playlist is decoded by a uridecodebin, and the streams of all items are
+// played back-to-back by one concat element per media type, which also takes care of
+// adjusting the segments. The streams of the first item define the output pads, all further
+// items are expected to have the same streams.
+//
+// While an item is played, the next one is already prepared: its uridecodebin is started and
+// its pads are linked to concat, which keeps them waiting until the current item is finished.
+// The "about-to-finish" signal is emitted right before, with the index of the current item,
+// so that URIs can still be appended. EOS of the current item is held back until the next item
+// has all its pads, to prevent concat from forwarding EOS in the meantime. Once the next item
+// is played, a "uriplaylistbin-item-started" element message with its URI is posted.
+//
+// concat drops all events of the waiting pads, including the stream tags of the next items with
+// e.g. their language. These are sent again after concat switched to the next item.
+//
+// Seeking is not supported.
+#[derive(Default)]
+struct State {
+    concats: Vec<(MediaType, gst::Element)>,
+    // The current item first, followed by the prepared next item
+    items: VecDeque<Item>,
+    // The next item is being prepared
+    next_pending: bool,
+    pads_exposed: bool,
+    flushing: bool,
+}
+
+struct UriPlaylistBin {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+impl UriPlaylistBin {
+    fn new(_bin: &Bin) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "uriplaylistbin",
+                gst::DebugColorFlags::empty(),
+                "Gapless URI playlist",
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+            cond: Condvar::new(),
+        }
+    }
+
+    fn class_init(klass: &mut BinClass) {
+        klass.set_metadata(
+            "URI Playlist Bin",
+            "Generic/Bin/Source",
+            "Plays a list of URIs back-to-back without gaps",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        for media_type in &[MediaType::Video, MediaType::Audio] {
+            let src_pad_template = gst::PadTemplate::new(
+                media_type.get_pad_name(),
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &media_type.get_caps(),
+            );
+            klass.add_pad_template(src_pad_template);
+        }
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.add_signal("about-to-finish", &[u32::static_type()], glib::Type::Unit);
+    }
+
+    fn init(bin: &Bin) -> Box<BinImpl<Bin>> {
+        let imp = Self::new(bin);
+        Box::new(imp)
+    }
+
+    fn with_uriplaylistbin<F: FnOnce(&Self, &Bin)>(child: &gst::Element, f: F) {
+        let bin = match child.get_parent().and_then(|p| p.downcast::<Bin>().ok()) {
+            None => return,
+            Some(bin) => bin,
+        };
+        let uriplaylistbin = bin.get_impl().downcast_ref::<UriPlaylistBin>().unwrap();
+        bin.catch_panic(|| (), |bin| f(uriplaylistbin, bin));
+    }
+
+    fn create_item(&self, bin: &Bin, index: u32, iteration: u32) -> Result<(), String> {
+        let uri = match self.settings.lock().unwrap().uris.get(index as usize) {
+            None => return Err(format!("No URI {}", index)),
+            Some(uri) => uri.clone(),
+        };
+
+        gst_debug!(self.cat, obj: bin, "Preparing item {}: {}", index, uri);
+
+        let decodebin = gst::ElementFactory::make("uridecodebin", None)
+            .ok_or_else(|| String::from("Failed to create uridecodebin"))?;
+        decodebin.set_property("uri", &uri).unwrap();
+
+        decodebin.connect_pad_added(|decodebin, pad| {
+            UriPlaylistBin::with_uriplaylistbin(decodebin, |uriplaylistbin, bin| {
+                uriplaylistbin.pad_added(bin, decodebin, pad)
+            });
+        });
+        decodebin.connect_no_more_pads(|decodebin| {
+            UriPlaylistBin::with_uriplaylistbin(decodebin, |uriplaylistbin, bin| {
+                uriplaylistbin.item_ready(bin, decodebin)
+            });
+        });
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.next_pending = false;
+            state.items.push_back(Item {
+                index: index,
+                iteration: iteration,
+                uri: uri,
+                decodebin: decodebin.clone(),
+                pads: Vec::new(),
+                concat_pads: Vec::new(),
+                fakesinks: Vec::new(),
+                n_eos: 0,
+                ready: false,
+            });
+        }
+
+        bin.add(&decodebin)
+            .map_err(|_| String::from("Failed to add uridecodebin"))?;
+        decodebin
+            .sync_state_with_parent()
+            .map_err(|_| String::from("Failed to start uridecodebin"))?;
+
+        Ok(())
+    }
+
+    // Creates the concat and the source pad for a media type of the first item
+    fn create_output(&self, bin: &Bin, media_type: MediaType) -> Result<gst::Element, String> {
+        let concat = gst::ElementFactory::make("concat", None)
+            .ok_or_else(|| String::from("Failed to create concat"))?;
+        bin.add(&concat)
+            .map_err(|_| String::from("Failed to add concat"))?;
+        concat
+            .sync_state_with_parent()
+            .map_err(|_| String::from("Failed to start concat"))?;
+
+        self.state
+            .lock()
+            .unwrap()
+            .concats
+            .push((media_type, concat.clone()));
+
+        // concat sends a new segment when switching to the next item, the tags of the item are
+        // sent after it, right before its first buffer
+        let srcpad = concat.get_static_pad("src").unwrap();
+        let switched = AtomicBool::new(false);
+        srcpad.add_probe(
+            gst::PadProbeType::EVENT_DOWNSTREAM | gst::PadProbeType::BUFFER,
+            move |pad, probe_info| {
+                match probe_info.data {
+                    Some(gst::PadProbeData::Event(ref event))
+                        if event.get_type() == gst::EventType::Segment =>
+                    {
+                        switched.store(true, Ordering::SeqCst);
+                    }
+                    Some(gst::PadProbeData::Buffer(..))
+                        if switched.swap(false, Ordering::SeqCst) =>
+                    {
+                        let tags = pad.get_parent_element()
+                            .and_then(|concat| concat.get_property("active-pad").ok())
+                            .and_then(|active_pad| active_pad.get::<gst::Pad>())
+                            .and_then(|active_pad| active_pad.get_peer())
+                            .and_then(|pad| pad.get_sticky_event(gst::EventType::Tag, 0));
+                        if let Some(tags) = tags {
+                            pad.push_event(tags);
+                        }
+                    }
+                    _ => (),
+                }
+                gst::PadProbeReturn::Ok
+            },
+        );
+
+        let templ = bin.get_pad_template(media_type.get_pad_name()).unwrap();
+        let ghostpad =
+            gst::GhostPad::new_from_template(media_type.get_pad_name(), &srcpad, &templ).unwrap();
+        ghostpad.set_active(true).unwrap();
+        bin.add_pad(&ghostpad).unwrap();
+
+        Ok(concat)
+    }
+
+    fn pad_added(&self, bin: &Bin, decodebin: &gst::Element, pad: &gst::Pad) {
+        let media_type = pad.get_current_caps()
+            .and_then(|caps| MediaType::from_caps(&caps));
+
+        let (concat, pads_exposed) = {
+            let state = self.state.lock().unwrap();
+            let concat = state
+                .concats
+                .iter()
+                .find(|&&(t, _)| Some(t) == media_type)
+                .map(|&(_, ref concat)| concat.clone());
+            (concat, state.pads_exposed)
+        };
+
+        let concat = match (concat, media_type) {
+            (Some(concat), _) => Some(concat),
+            (None, Some(media_type)) if !pads_exposed => {
+                match self.create_output(bin, media_type) {
+                    Err(err) => {
+                        gst_element_error!(bin, gst::CoreError::Failed, ["{}", err]);
+                        return;
+                    }
+                    Ok(concat) => Some(concat),
+                }
+            }
+            _ => None,
+        };
+
+        let concat = match concat {
+            Some(concat) => concat,
+            None => {
+                gst_debug!(self.cat, obj: bin, "Discarding pad {:?}", pad.get_name());
+
+                let fakesink = gst::ElementFactory::make("fakesink", None).unwrap();
+                fakesink.set_property("async", &false).unwrap();
+                bin.add(&fakesink).unwrap();
+                let _ = fakesink.sync_state_with_parent();
+                let _ = pad.link(&fakesink.get_static_pad("sink").unwrap());
+
+                let mut state = self.state.lock().unwrap();
+                if let Some(item) = state.items.iter_mut().find(|i| i.decodebin == *decodebin) {
+                    item.fakesinks.push(fakesink);
+                }
+                return;
+            }
+        };
+
+        let sinkpad = match concat.get_request_pad("sink_%u") {
+            None => {
+                gst_element_error!(bin, gst::CoreError::Pad, ["Failed to request concat pad"]);
+                return;
+            }
+            Some(sinkpad) => sinkpad,
+        };
+
+        pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, |pad, probe_info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = probe_info.data {
+                if event.get_type() == gst::EventType::Eos {
+                    if let Some(decodebin) = pad.get_parent_element() {
+                        UriPlaylistBin::with_uriplaylistbin(&decodebin, |uriplaylistbin, bin| {
+                            uriplaylistbin.handle_eos(bin, &decodebin)
+                        });
+                    }
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(item) = state.items.iter_mut().find(|i| i.decodebin == *decodebin) {
+                item.pads.push(pad.clone());
+                item.concat_pads.push((concat, sinkpad.clone()));
+            }
+        }
+
+        if pad.link(&sinkpad) != gst::PadLinkReturn::Ok {
+            gst_element_error!(
+                bin,
+                gst::CoreError::Negotiation,
+                ["Failed to link pad {:?}", pad.get_name()]
+            );
+        }
+    }
+
+    fn item_ready(&self, bin: &Bin, decodebin: &gst::Element) {
+        let (expose, prepare_next) = {
+            let mut state = self.state.lock().unwrap();
+
+            let pos = match state.items.iter().position(|i| i.decodebin == *decodebin) {
+                None => return,
+                Some(pos) => pos,
+            };
+
+            if state.items[pos].pads.is_empty() {
+                let uri = state.items[pos].uri.clone();
+                drop(state);
+                gst_element_error!(
+                    bin,
+                    gst::StreamError::Demux,
+                    ["No audio or video streams in {}", uri]
+                );
+                return;
+            }
+            state.items[pos].ready = true;
+
+            let expose = !state.pads_exposed;
+            state.pads_exposed = true;
+
+            let prepare_next = pos == 0 && state.items.len() == 1;
+            if prepare_next {
+                state.next_pending = true;
+            }
+            self.cond.notify_all();
+
+            (expose, prepare_next)
+        };
+
+        if expose {
+            bin.no_more_pads();
+        }
+
+        if prepare_next {
+            self.prepare_next(bin);
+        }
+    }
+
+    fn prepare_next(&self, bin: &Bin) {
+        let current = {
+            let state = self.state.lock().unwrap();
+            state.items.front().map(|i| (i.index, i.iteration))
+        };
+
+        let (index, iteration) = match current {
+            None => return,
+            Some(current) => current,
+        };
+
+        // Handlers can still extend the playlist here
+        let _ = bin.emit("about-to-finish", &[&index]);
+
+        let next = {
+            let settings = self.settings.lock().unwrap();
+            get_next_item(&settings, index, iteration)
+        };
+
+        let res = match next {
+            None => Ok(()),
+            Some((index, iteration)) => self.create_item(bin, index, iteration),
+        };
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.next_pending = false;
+            self.cond.notify_all();
+        }
+
+        if let Err(err) = res {
+            gst_element_error!(bin, gst::ResourceError::Failed, ["{}", err]);
+        }
+    }
+
+    fn handle_eos(&self, bin: &Bin, decodebin: &gst::Element) {
+        let mut state = self.state.lock().unwrap();
+
+        // Only the current item can finish, and without the next item's pads concat would
+        // forward the EOS downstream
+        loop {
+            if state.flushing {
+                return;
+            }
+
+            let is_current = match state.items.front() {
+                None => return,
+                Some(item) => item.decodebin == *decodebin,
+            };
+            let next_ready = !state.next_pending
+                && state.items.get(1).map(|i| i.ready).unwrap_or(true);
+
+            if is_current && next_ready {
+                break;
+            }
+            state = self.cond.wait(state).unwrap();
+        }
+
+        let finished = {
+            let item = state.items.front_mut().unwrap();
+            item.n_eos += 1;
+            item.n_eos >= item.pads.len()
+        };
+
+        if !finished {
+            return;
+        }
+
+        let item = state.items.pop_front().unwrap();
+        gst_debug!(self.cat, obj: bin, "Finished item {}: {}", item.index, item.uri);
+
+        let next = state.items.front().map(|i| (i.index, i.uri.clone(), i.ready));
+        let prepare_next = match next {
+            Some((_, _, ready)) => ready,
+            None => false,
+        };
+        if prepare_next {
+            state.next_pending = true;
+        }
+        self.cond.notify_all();
+        drop(state);
+
+        if let Some((index, uri, _)) = next {
+            let s = gst::Structure::new(
+                "uriplaylistbin-item-started",
+                &[("uri-index", &index), ("uri", &uri)],
+            );
+            let _ = bin.post_message(&gst::Message::new_element(s).src(Some(bin)).build());
+        }
+
+        // The item can't be shut down from its own streaming thread
+        let element = bin.clone().upcast::<gst::Element>();
+        thread::spawn(move || {
+            let _ = item.decodebin.set_state(gst::State::Null);
+            for (concat, sinkpad) in item.concat_pads {
+                concat.release_request_pad(&sinkpad);
+            }
+
+            let bin = element.downcast::<Bin>().unwrap();
+            let _ = bin.remove(&item.decodebin);
+            for fakesink in item.fakesinks {
+                let _ = fakesink.set_state(gst::State::Null);
+                let _ = bin.remove(&fakesink);
+            }
+        });
+
+        if prepare_next {
+            self.prepare_next(bin);
+        }
+    }
+
+    fn setup(&self, bin: &Bin) -> Result<(), String> {
+        if self.settings.lock().unwrap().uris.is_empty() {
+            return Err(String::from("No URIs set"));
+        }
+
+        *self.state.lock().unwrap() = State::default();
+
+        self.create_item(bin, 0, 0)
+    }
+
+    fn teardown(&self, bin: &Bin) {
+        *self.state.lock().unwrap() = State::default();
+
+        for element in bin.get_children() {
+            let _ = element.set_state(gst::State::Null);
+            let _ = bin.remove(&element);
+        }
+
+        for pad in bin.get_src_pads() {
+            let _ = bin.remove_pad(&pad);
+        }
+    }
+}
+
+impl ObjectImpl<Bin> for UriPlaylistBin {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::Boxed("uris", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.uris = value.get().unwrap_or_else(Vec::new);
+            }
+            Property::UInt("iterations", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.iterations = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::Boxed("uris", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.uris.to_value())
+            }
+            Property::UInt("iterations", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.iterations.to_value())
+            }
+            Property::UInt("current-uri-index", ..) => {
+                let state = self.state.lock().unwrap();
+                Ok(state.items.front().map(|i| i.index).unwrap_or(0).to_value())
+            }
+            Property::String("current-uri", ..) => {
+                let state = self.state.lock().unwrap();
+                Ok(state.items.front().map(|i| i.uri.clone()).to_value())
+            }
+            Property::UInt("current-iteration", ..) => {
+                let state = self.state.lock().unwrap();
+                Ok(state
+                    .items
+                    .front()
+                    .map(|i| i.iteration)
+                    .unwrap_or(0)
+                    .to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Bin> for UriPlaylistBin {
+    fn change_state(&self, bin: &Bin, transition: gst::StateChange) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: bin, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused => if let Err(err) = self.setup(bin) {
+                gst_element_error!(bin, gst::ResourceError::Settings, ["{}", err]);
+                self.teardown(bin);
+                return gst::StateChangeReturn::Failure;
+            },
+            gst::StateChange::PausedToReady => {
+                // Unblock streaming threads waiting for the next item
+                let mut state = self.state.lock().unwrap();
+                state.flushing = true;
+                self.cond.notify_all();
+            }
+            _ => (),
+        }
+
+        let ret = bin.parent_change_state(transition);
+        if ret == gst::StateChangeReturn::Failure {
+            return ret;
+        }
+
+        match transition {
+            gst::StateChange::PausedToReady => {
+                self.teardown(bin);
+            }
+            _ => (),
+        }
+
+        ret
+    }
+}
+
+impl BinImpl<Bin> for UriPlaylistBin {}
+
+struct UriPlaylistBinStatic;
+
+impl ImplTypeStatic<Bin> for UriPlaylistBinStatic {
+    fn get_name(&self) -> &str {
+        "UriPlaylistBin"
+    }
+
+    fn new(&self, bin: &Bin) -> Box<BinImpl<Bin>> {
+        UriPlaylistBin::init(bin)
+    }
+
+    fn class_init(&self, klass: &mut BinClass) {
+        UriPlaylistBin::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let uriplaylistbin_static = UriPlaylistBinStatic;
+    register_type(uriplaylistbin_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_item() {
+        let mut settings = Settings {
+            uris: vec![String::from("file:///a"), String::from("file:///b")],
+            iterations: 2,
+        };
+
+        assert_eq!(get_next_item(&settings, 0, 0), Some((1, 0)));
+        assert_eq!(get_next_item(&settings, 1, 0), Some((0, 1)));
+        assert_eq!(get_next_item(&settings, 0, 1), Some((1, 1)));
+        assert_eq!(get_next_item(&settings, 1, 1), None);
+
+        settings.iterations = 0;
+        assert_eq!(get_next_item(&settings, 1, 100), Some((0, 101)));
+
+        settings.uris.clear();
+        assert_eq!(get_next_item(&settings, 0, 0), None);
+    }
+}
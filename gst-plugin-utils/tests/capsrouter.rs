@@ -0,0 +1,71 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate glib;
+use glib::prelude::*;
+
+extern crate gst_plugin;
+extern crate gstreamer as gst;
+use gst::prelude::*;
+
+use gst_plugin::test::*;
+
+fn new_router() -> gst::Element {
+    init();
+
+    gst::ElementFactory::make("capsrouter", None).unwrap()
+}
+
+#[test]
+fn test_matching_filter() {
+    let router = new_router();
+
+    let templ = router.get_pad_template("src_%u").unwrap();
+    let caps = gst::Caps::new_simple("video/x-raw", &[]);
+    let srcpad = router.request_pad(&templ, None, Some(&caps)).unwrap();
+    assert_eq!(srcpad.get_name(), "src_0");
+
+    let mut h = Harness::new_with_element(&router, Some("sink"), Some("src_0"));
+    h.set_src_caps_str("video/x-raw, format=(string)RGB, width=(int)2, height=(int)2");
+
+    let buffer = gst::Buffer::from_slice(vec![0u8; 12]).unwrap();
+    assert!(h.push_and_pull(buffer).is_some());
+}
+
+#[test]
+fn test_fallback() {
+    let router = new_router();
+
+    let srcpad = router.get_request_pad("src_%u").unwrap();
+    let caps = gst::Caps::new_simple("video/x-raw", &[]);
+    assert!(
+        router
+            .emit("set-filter", &[&srcpad, &caps])
+            .unwrap()
+            .and_then(|v| v.get::<bool>())
+            .unwrap()
+    );
+
+    let mut h = Harness::new_with_element(&router, Some("sink"), Some("fallback"));
+    h.set_src_caps_str("audio/x-raw, format=(string)S16LE, rate=(int)8000, channels=(int)1");
+
+    let buffer = gst::Buffer::from_slice(vec![0u8; 16]).unwrap();
+    assert!(h.push_and_pull(buffer).is_some());
+}
+
+#[test]
+fn test_pad_names() {
+    let router = new_router();
+
+    let templ = router.get_pad_template("src_%u").unwrap();
+    let srcpad = router.request_pad(&templ, Some("src_5"), None).unwrap();
+    assert_eq!(srcpad.get_name(), "src_5");
+    let srcpad = router.request_pad(&templ, None, None).unwrap();
+    assert_eq!(srcpad.get_name(), "src_6");
+    assert!(router.request_pad(&templ, Some("src_5"), None).is_none());
+}
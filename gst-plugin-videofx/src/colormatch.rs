@@ -0,0 +1,566 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::sync::Mutex;
+
+use rgb;
+
+// Color matching behaviour:
+//
+// The video on the "sink" pad is passed through to the "src" pad with a per-channel lookup table
+// applied that maps its histogram to the histogram of the video on the "reference" pad. The
+// reference video is only analyzed and never output.
+//
+// The histograms are estimated continuously from the latest frames of both streams, the lookup
+// table is smoothed over time so that the correction follows lighting changes without
+// flickering. Until the first reference frame arrived the video is passed through unchanged.
+
+const DEFAULT_SMOOTHING: f64 = 0.95;
+const DEFAULT_SUBSAMPLE: u32 = 4;
+
+static PROPERTIES: [Property; 2] = [
+    Property::Double(
+        "smoothing",
+        "Smoothing",
+        "Weight of the previous lookup table when updating it for a new frame",
+        (0.0, 0.999),
+        DEFAULT_SMOOTHING,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "subsample",
+        "Subsample",
+        "Only every n-th pixel horizontally and vertically is used for the histograms",
+        (1, 64),
+        DEFAULT_SUBSAMPLE,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    smoothing: f64,
+    subsample: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            smoothing: DEFAULT_SMOOTHING,
+            subsample: DEFAULT_SUBSAMPLE,
+        }
+    }
+}
+
+type Histogram = [[u32; 256]; 3];
+type Lut = [[f32; 256]; 3];
+
+fn histogram(info: &gst_video::VideoInfo, data: &[u8], subsample: usize) -> Histogram {
+    let mut histogram = [[0; 256]; 3];
+    let (bpp, offsets) = rgb::layout(info.format()).unwrap();
+    let stride = info.stride()[0] as usize;
+    let width = info.width() as usize;
+
+    let mut row = 0;
+    while row < info.height() as usize {
+        let line = &data[(row * stride)..];
+        let mut col = 0;
+        while col < width {
+            let pixel = &line[(col * bpp)..];
+            for (c, &offset) in offsets.iter().enumerate() {
+                histogram[c][pixel[offset] as usize] += 1;
+            }
+            col += subsample;
+        }
+        row += subsample;
+    }
+
+    histogram
+}
+
+// Maps every value to the first reference value with the same or a higher cumulative frequency
+fn matching_lut(histogram: &[u32; 256], reference: &[u32; 256]) -> [f32; 256] {
+    let cdf = |histogram: &[u32; 256]| {
+        let total = histogram.iter().map(|&n| u64::from(n)).sum::<u64>().max(1) as f64;
+        let mut cdf = [0.0; 256];
+        let mut sum = 0;
+        for (c, &n) in cdf.iter_mut().zip(histogram.iter()) {
+            sum += u64::from(n);
+            *c = sum as f64 / total;
+        }
+        cdf
+    };
+
+    let cdf_in = cdf(histogram);
+    let cdf_ref = cdf(reference);
+
+    let mut lut = [0.0; 256];
+    let mut j = 0;
+    for (l, &c) in lut.iter_mut().zip(cdf_in.iter()) {
+        while j < 255 && cdf_ref[j] < c {
+            j += 1;
+        }
+        *l = j as f32;
+    }
+
+    lut
+}
+
+fn apply_lut(info: &gst_video::VideoInfo, data: &mut [u8], lut: &Lut) {
+    let (bpp, offsets) = rgb::layout(info.format()).unwrap();
+    let stride = info.stride()[0] as usize;
+    let width = info.width() as usize;
+
+    let mut tables = [[0u8; 256]; 3];
+    for (table, lut) in tables.iter_mut().zip(lut.iter()) {
+        for (t, &l) in table.iter_mut().zip(lut.iter()) {
+            *t = l.round().max(0.0).min(255.0) as u8;
+        }
+    }
+
+    for row in data.chunks_mut(stride).take(info.height() as usize) {
+        for pixel in row[..(width * bpp)].chunks_mut(bpp) {
+            for (table, &offset) in tables.iter().zip(offsets.iter()) {
+                pixel[offset] = table[pixel[offset] as usize];
+            }
+        }
+    }
+}
+
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    reference_info: Option<gst_video::VideoInfo>,
+    // Histogram of the latest reference frame
+    reference: Option<Histogram>,
+    lut: Option<Lut>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            info: None,
+            reference_info: None,
+            reference: None,
+            lut: None,
+        }
+    }
+}
+
+struct ColorMatch {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl ColorMatch {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "colormatch",
+                gst::DebugColorFlags::empty(),
+                "Histogram matching color correction",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Color Match",
+            "Filter/Effect/Video",
+            "Corrects the colors of a video to match a reference video",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = rgb::caps();
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        let reference_pad_template = gst::PadTemplate::new(
+            "reference",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(reference_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("reference").unwrap();
+        let reference_pad = gst::Pad::new_from_template(&templ, "reference");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            ColorMatch::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |colormatch, element| colormatch.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            ColorMatch::catch_panic_pad_function(
+                parent,
+                || false,
+                |colormatch, element| colormatch.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            ColorMatch::catch_panic_pad_function(
+                parent,
+                || false,
+                |colormatch, element| colormatch.sink_query(pad, element, query),
+            )
+        });
+
+        reference_pad.set_chain_function(|pad, parent, buffer| {
+            ColorMatch::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |colormatch, element| colormatch.reference_chain(pad, element, buffer),
+            )
+        });
+        reference_pad.set_event_function(|pad, parent, event| {
+            ColorMatch::catch_panic_pad_function(
+                parent,
+                || false,
+                |colormatch, element| colormatch.reference_event(pad, element, event),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            ColorMatch::catch_panic_pad_function(
+                parent,
+                || false,
+                |colormatch, element| colormatch.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            ColorMatch::catch_panic_pad_function(
+                parent,
+                || false,
+                |colormatch, element| colormatch.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&reference_pad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let colormatch = element.get_impl().downcast_ref::<ColorMatch>().unwrap();
+        element.catch_panic(fallback, |element| f(colormatch, element))
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        mut buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+
+        {
+            let mut state = self.state.lock().unwrap();
+            let info = match state.info {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Negotiation, ["Have no caps"]);
+                    return gst::FlowReturn::NotNegotiated;
+                }
+                Some(ref info) => info.clone(),
+            };
+
+            let reference = state.reference;
+            let reference = match reference {
+                None => {
+                    gst_log!(self.cat, obj: pad, "No reference yet, passing through");
+                    drop(state);
+                    return self.srcpad.push(buffer);
+                }
+                Some(reference) => reference,
+            };
+
+            let histogram = {
+                let map = match buffer.map_readable() {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Failed,
+                            ["Failed to map buffer"]
+                        );
+                        return gst::FlowReturn::Error;
+                    }
+                    Some(map) => map,
+                };
+                histogram(&info, map.as_slice(), settings.subsample as usize)
+            };
+
+            let mut new_lut = [[0.0; 256]; 3];
+            for (c, lut) in new_lut.iter_mut().enumerate() {
+                *lut = matching_lut(&histogram[c], &reference[c]);
+            }
+
+            let lut = match state.lut {
+                None => new_lut,
+                Some(mut lut) => {
+                    let smoothing = settings.smoothing as f32;
+                    for (lut, new_lut) in lut.iter_mut().zip(new_lut.iter()) {
+                        for (l, &n) in lut.iter_mut().zip(new_lut.iter()) {
+                            *l = smoothing * *l + (1.0 - smoothing) * n;
+                        }
+                    }
+                    lut
+                }
+            };
+            state.lut = Some(lut);
+            drop(state);
+
+            let buffer = buffer.make_mut();
+            let mut map = match buffer.map_writable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+            apply_lut(&info, map.as_mut_slice(), &lut);
+        }
+
+        gst_log!(self.cat, obj: pad, "Pushing buffer {:?}", buffer);
+        self.srcpad.push(buffer)
+    }
+
+    fn reference_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let subsample = self.settings.lock().unwrap().subsample as usize;
+
+        let info = match self.state.lock().unwrap().reference_info {
+            None => {
+                gst_element_error!(element, gst::CoreError::Negotiation, ["Have no caps"]);
+                return gst::FlowReturn::NotNegotiated;
+            }
+            Some(ref info) => info.clone(),
+        };
+
+        let histogram = {
+            let map = match buffer.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+            histogram(&info, map.as_slice(), subsample)
+        };
+
+        gst_trace!(self.cat, obj: pad, "Updated reference histogram");
+        self.state.lock().unwrap().reference = Some(histogram);
+
+        gst::FlowReturn::Ok
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let info = match gst_video::VideoInfo::from_caps(e.get_caps()) {
+                    None => return false,
+                    Some(info) => info,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                state.info = Some(info);
+                state.lut = None;
+            }
+            EventView::FlushStop(..) => {
+                self.state.lock().unwrap().lut = None;
+            }
+            _ => (),
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn reference_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        // Events of the reference stream are never forwarded. After EOS the last reference
+        // histogram stays in use.
+        if let EventView::Caps(e) = event.view() {
+            let info = match gst_video::VideoInfo::from_caps(e.get_caps()) {
+                None => return false,
+                Some(info) => info,
+            };
+
+            self.state.lock().unwrap().reference_info = Some(info);
+        }
+
+        true
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, _element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding query {:?}", query);
+        self.srcpad.peer_query(query)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, _element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding query {:?}", query);
+        self.sinkpad.peer_query(query)
+    }
+}
+
+impl ObjectImpl<Element> for ColorMatch {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Double("smoothing", ..) => {
+                settings.smoothing = value.get().unwrap();
+            }
+            Property::UInt("subsample", ..) => {
+                settings.subsample = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Double("smoothing", ..) => Ok(settings.smoothing.to_value()),
+            Property::UInt("subsample", ..) => Ok(settings.subsample.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for ColorMatch {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct ColorMatchStatic;
+
+impl ImplTypeStatic<Element> for ColorMatchStatic {
+    fn get_name(&self) -> &str {
+        "ColorMatch"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        ColorMatch::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        ColorMatch::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let colormatch_static = ColorMatchStatic;
+    register_type(colormatch_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_lut() {
+        // The reference is the same distribution shifted up by 10
+        let mut histogram = [0; 256];
+        let mut reference = [0; 256];
+        for i in 50..150 {
+            histogram[i] = 1 + (i % 7) as u32;
+            reference[i + 10] = 1 + (i % 7) as u32;
+        }
+
+        let lut = matching_lut(&histogram, &reference);
+        for i in 50..150 {
+            assert_eq!(lut[i], (i + 10) as f32);
+        }
+        // Identical distributions map to themselves
+        let lut = matching_lut(&histogram, &histogram);
+        for i in 50..150 {
+            assert_eq!(lut[i], i as f32);
+        }
+    }
+}
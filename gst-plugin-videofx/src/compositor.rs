@@ -0,0 +1,972 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::aggregator::*;
+use gst_plugin::aggregator_pad::*;
+use gst_plugin::child_proxy::*;
+
+use std::{cmp, i32, u32};
+use std::sync::{Mutex, Once, ONCE_INIT};
+
+use rgb;
+use videotestsrc::frame_to_time;
+
+// Compositor:
+//
+// Mixes any number of video streams into one. Each input is scaled to the width and height of
+// its pad with nearest neighbour sampling, placed at xpos/ypos and blended with its alpha over
+// the inputs with a lower zorder, on a black background. Inputs can have any size and any of
+// the packed RGB formats and are converted to the output format while blending. The pads are
+// exposed via GstChildProxy, so their properties can be set as sink_0::xpos=10 from
+// gst-launch-1.0.
+//
+// Output frames are produced at the output framerate once all inputs have a frame queued. Each
+// output frame uses the newest frame of every input that starts before the end of the output
+// frame, so inputs with a lower framerate have their frames repeated. Once an input is EOS its
+// last frame stays visible until its end time.
+//
+// The output size defaults to the bounding box of all inputs and the output framerate to the
+// highest input framerate.
+
+const DEFAULT_WIDTH: i32 = 320;
+const DEFAULT_HEIGHT: i32 = 240;
+const DEFAULT_FPS: (i32, i32) = (30, 1);
+
+const DEFAULT_XPOS: i32 = 0;
+const DEFAULT_YPOS: i32 = 0;
+const DEFAULT_PAD_WIDTH: i32 = 0;
+const DEFAULT_PAD_HEIGHT: i32 = 0;
+const DEFAULT_ALPHA: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy)]
+struct PadSettings {
+    xpos: i32,
+    ypos: i32,
+    width: i32,
+    height: i32,
+    alpha: f64,
+    zorder: u32,
+}
+
+impl Default for PadSettings {
+    fn default() -> Self {
+        PadSettings {
+            xpos: DEFAULT_XPOS,
+            ypos: DEFAULT_YPOS,
+            width: DEFAULT_PAD_WIDTH,
+            height: DEFAULT_PAD_HEIGHT,
+            alpha: DEFAULT_ALPHA,
+            zorder: 0,
+        }
+    }
+}
+
+static PAD_PROPERTIES: [Property; 6] = [
+    Property::Int(
+        "xpos",
+        "X Position",
+        "Horizontal position of the input in the output frame",
+        (i32::MIN, i32::MAX),
+        DEFAULT_XPOS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Int(
+        "ypos",
+        "Y Position",
+        "Vertical position of the input in the output frame",
+        (i32::MIN, i32::MAX),
+        DEFAULT_YPOS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Int(
+        "width",
+        "Width",
+        "Width the input is scaled to (0 = input width)",
+        (0, i32::MAX),
+        DEFAULT_PAD_WIDTH,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Int(
+        "height",
+        "Height",
+        "Height the input is scaled to (0 = input height)",
+        (0, i32::MAX),
+        DEFAULT_PAD_HEIGHT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "alpha",
+        "Alpha",
+        "Opacity of the input",
+        (0.0, 1.0),
+        DEFAULT_ALPHA,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "zorder",
+        "Z-Order",
+        "Stacking order of the input, higher values are on top (defaults to the pad number)",
+        (0, u32::MAX),
+        0,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+// Layout of a frame in one of the packed RGB formats
+#[derive(Debug, Clone, Copy)]
+struct Geometry {
+    width: usize,
+    height: usize,
+    stride: usize,
+    bpp: usize,
+    // Offsets of the R, G and B components and of the alpha component, if any
+    offsets: [usize; 3],
+    alpha: Option<usize>,
+}
+
+impl Geometry {
+    fn from_info(info: &gst_video::VideoInfo) -> Geometry {
+        use gst_video::VideoFormat::*;
+
+        let (bpp, offsets) = rgb::layout(info.format()).unwrap();
+        let alpha = match info.format() {
+            Rgba | Bgra | Argb | Abgr => Some(6 - offsets.iter().sum::<usize>()),
+            _ => None,
+        };
+
+        Geometry {
+            width: info.width() as usize,
+            height: info.height() as usize,
+            stride: info.stride()[0] as usize,
+            bpp: bpp,
+            offsets: offsets,
+            alpha: alpha,
+        }
+    }
+}
+
+// Position, size and opacity of an input in the output frame
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    x: i32,
+    y: i32,
+    width: usize,
+    height: usize,
+    alpha: f64,
+}
+
+impl Placement {
+    fn new(settings: &PadSettings, info: &gst_video::VideoInfo) -> Placement {
+        Placement {
+            x: settings.xpos,
+            y: settings.ypos,
+            width: if settings.width > 0 {
+                settings.width as usize
+            } else {
+                info.width() as usize
+            },
+            height: if settings.height > 0 {
+                settings.height as usize
+            } else {
+                info.height() as usize
+            },
+            alpha: settings.alpha,
+        }
+    }
+}
+
+// Fills the frame with opaque black
+fn clear(out: &mut [u8], geometry: &Geometry) {
+    for b in out.iter_mut() {
+        *b = 0;
+    }
+
+    if let Some(alpha) = geometry.alpha {
+        for row in out.chunks_mut(geometry.stride).take(geometry.height) {
+            for pixel in row[..(geometry.width * geometry.bpp)].chunks_mut(geometry.bpp) {
+                pixel[alpha] = 255;
+            }
+        }
+    }
+}
+
+// Scales the input to its placement and blends it over `out`, converting between the formats
+fn blend(
+    input: &[u8],
+    in_geometry: &Geometry,
+    out: &mut [u8],
+    out_geometry: &Geometry,
+    placement: &Placement,
+) {
+    let alpha = (placement.alpha * 255.0).round() as u32;
+    if alpha == 0 || placement.width == 0 || placement.height == 0 {
+        return;
+    }
+
+    // Visible part of the input in output coordinates
+    let x0 = cmp::max(placement.x as i64, 0);
+    let x1 = cmp::min(
+        placement.x as i64 + placement.width as i64,
+        out_geometry.width as i64,
+    );
+    let y0 = cmp::max(placement.y as i64, 0);
+    let y1 = cmp::min(
+        placement.y as i64 + placement.height as i64,
+        out_geometry.height as i64,
+    );
+    if x0 >= x1 || y0 >= y1 {
+        return;
+    }
+
+    // Input column for each visible output column
+    let columns = (x0..x1)
+        .map(|x| (x - placement.x as i64) as usize * in_geometry.width / placement.width)
+        .collect::<Vec<_>>();
+
+    for y in y0..y1 {
+        let in_y = (y - placement.y as i64) as usize * in_geometry.height / placement.height;
+        let in_row = &input[(in_y * in_geometry.stride)..];
+        let out_row = &mut out[(y as usize * out_geometry.stride)..];
+
+        for (x, &in_x) in (x0 as usize..x1 as usize).zip(columns.iter()) {
+            let src = &in_row[(in_x * in_geometry.bpp)..((in_x + 1) * in_geometry.bpp)];
+            let dst = &mut out_row[(x * out_geometry.bpp)..((x + 1) * out_geometry.bpp)];
+
+            let a = match in_geometry.alpha {
+                Some(idx) => alpha * src[idx] as u32 / 255,
+                None => alpha,
+            };
+
+            for c in 0..3 {
+                let s = src[in_geometry.offsets[c]] as u32;
+                let d = dst[out_geometry.offsets[c]] as u32;
+                dst[out_geometry.offsets[c]] = ((s * a + d * (255 - a) + 127) / 255) as u8;
+            }
+            if let Some(idx) = out_geometry.alpha {
+                let d = dst[idx] as u32;
+                dst[idx] = (a + (d * (255 - a) + 127) / 255) as u8;
+            }
+        }
+    }
+}
+
+struct PadState {
+    info: Option<gst_video::VideoInfo>,
+    // Current frame of the input and its end running time, if known
+    buffer: Option<gst::Buffer>,
+    end: Option<u64>,
+}
+
+impl Default for PadState {
+    fn default() -> Self {
+        PadState {
+            info: None,
+            buffer: None,
+            end: None,
+        }
+    }
+}
+
+struct CompositorPad {
+    settings: Mutex<PadSettings>,
+    state: Mutex<PadState>,
+}
+
+impl CompositorPad {
+    fn class_init(klass: &mut AggregatorPadClass) {
+        klass.install_properties(&PAD_PROPERTIES);
+    }
+
+    fn init(_pad: &AggregatorPad) -> Box<AggregatorPadImpl<AggregatorPad>> {
+        let imp = CompositorPad {
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+        };
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<AggregatorPad> for CompositorPad {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PAD_PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int("xpos", ..) => settings.xpos = value.get().unwrap(),
+            Property::Int("ypos", ..) => settings.ypos = value.get().unwrap(),
+            Property::Int("width", ..) => settings.width = value.get().unwrap(),
+            Property::Int("height", ..) => settings.height = value.get().unwrap(),
+            Property::Double("alpha", ..) => settings.alpha = value.get().unwrap(),
+            Property::UInt("zorder", ..) => settings.zorder = value.get().unwrap(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PAD_PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int("xpos", ..) => Ok(settings.xpos.to_value()),
+            Property::Int("ypos", ..) => Ok(settings.ypos.to_value()),
+            Property::Int("width", ..) => Ok(settings.width.to_value()),
+            Property::Int("height", ..) => Ok(settings.height.to_value()),
+            Property::Double("alpha", ..) => Ok(settings.alpha.to_value()),
+            Property::UInt("zorder", ..) => Ok(settings.zorder.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl AggregatorPadImpl<AggregatorPad> for CompositorPad {
+    fn flush(&self, pad: &AggregatorPad, aggregator: &gst::Element) -> gst::FlowReturn {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.buffer = None;
+            state.end = None;
+        }
+
+        pad.parent_flush(aggregator)
+    }
+}
+
+struct CompositorPadStatic;
+
+impl ImplTypeStatic<AggregatorPad> for CompositorPadStatic {
+    fn get_name(&self) -> &str {
+        "RsCompositorPad"
+    }
+
+    fn new(&self, pad: &AggregatorPad) -> Box<AggregatorPadImpl<AggregatorPad>> {
+        CompositorPad::init(pad)
+    }
+
+    fn class_init(&self, klass: &mut AggregatorPadClass) {
+        CompositorPad::class_init(klass);
+    }
+}
+
+fn get_pad_type() -> glib::Type {
+    static ONCE: Once = ONCE_INIT;
+    static mut TYPE: glib::Type = glib::Type::Invalid;
+
+    ONCE.call_once(|| {
+        let compositor_pad_static = CompositorPadStatic;
+        unsafe {
+            TYPE = register_type(compositor_pad_static);
+        }
+    });
+
+    unsafe { TYPE }
+}
+
+fn with_pad<T, F: FnOnce(&CompositorPad) -> T>(pad: &gst::Pad, f: F) -> T {
+    let pad = pad.clone().downcast::<AggregatorPad>().unwrap();
+    let imp = pad.get_impl().downcast_ref::<CompositorPad>().unwrap();
+    f(imp)
+}
+
+// Input frame for the current output frame
+struct Layer {
+    buffer: gst::Buffer,
+    geometry: Geometry,
+    placement: Placement,
+    zorder: u32,
+}
+
+struct State {
+    out_info: Option<gst_video::VideoInfo>,
+    // Timestamp of the first frame after the last flush or framerate change and the number of
+    // frames produced since then
+    base: Option<u64>,
+    frame: u64,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            out_info: None,
+            base: None,
+            frame: 0,
+        }
+    }
+}
+
+struct Compositor {
+    cat: gst::DebugCategory,
+    state: Mutex<State>,
+    // Number of the next requested pad, also used as its default zorder
+    next_pad: Mutex<u32>,
+}
+
+impl Compositor {
+    fn new(_element: &Aggregator) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rscompositor",
+                gst::DebugColorFlags::empty(),
+                "Rust video compositor",
+            ),
+            state: Mutex::new(Default::default()),
+            next_pad: Mutex::new(0),
+        }
+    }
+
+    fn class_init(klass: &mut AggregatorClass) {
+        klass.set_metadata(
+            "Compositor",
+            "Filter/Editor/Video/Compositor",
+            "Composites multiple video streams into one",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = rgb::caps();
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink_%u",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+    }
+
+    fn init(element: &Aggregator) -> Box<AggregatorImpl<Aggregator>> {
+        // Notify GstChildProxy about new and removed inputs
+        element.connect_pad_added(|element, pad| {
+            if pad.get_direction() == gst::PadDirection::Sink {
+                let _ = element.emit("child-added", &[pad, &pad.get_name()]);
+            }
+        });
+        element.connect_pad_removed(|element, pad| {
+            if pad.get_direction() == gst::PadDirection::Sink {
+                let _ = element.emit("child-removed", &[pad, &pad.get_name()]);
+            }
+        });
+
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    // Bounding box of all inputs and their highest framerate
+    fn preferred_output(&self, element: &Aggregator) -> (i32, i32, gst::Fraction) {
+        let mut size = None;
+        let mut fps: Option<gst::Fraction> = None;
+
+        for pad in element.get_sink_pads() {
+            let (settings, info) = match with_pad(&pad, |cpad| {
+                let settings = *cpad.settings.lock().unwrap();
+                cpad.state
+                    .lock()
+                    .unwrap()
+                    .info
+                    .clone()
+                    .map(|info| (settings, info))
+            }) {
+                None => continue,
+                Some(input) => input,
+            };
+
+            let placement = Placement::new(&settings, &info);
+            let right = cmp::max(placement.x as i64 + placement.width as i64, 1);
+            let bottom = cmp::max(placement.y as i64 + placement.height as i64, 1);
+            let (width, height) = size.unwrap_or((1, 1));
+            size = Some((cmp::max(width, right), cmp::max(height, bottom)));
+
+            let input_fps = info.fps();
+            if *input_fps.numer() > 0 && *input_fps.denom() > 0 {
+                let higher = fps.map(|fps| {
+                    *input_fps.numer() as i64 * *fps.denom() as i64
+                        > *fps.numer() as i64 * *input_fps.denom() as i64
+                }).unwrap_or(true);
+                if higher {
+                    fps = Some(input_fps);
+                }
+            }
+        }
+
+        let (width, height) = size.map(|(width, height)| {
+            (
+                cmp::min(width, i32::MAX as i64) as i32,
+                cmp::min(height, i32::MAX as i64) as i32,
+            )
+        }).unwrap_or((DEFAULT_WIDTH, DEFAULT_HEIGHT));
+
+        (
+            width,
+            height,
+            fps.unwrap_or_else(|| gst::Fraction::new(DEFAULT_FPS.0, DEFAULT_FPS.1)),
+        )
+    }
+
+    // Updates the current frame of the input for an output frame ending at `out_end` and
+    // returns whether the input still contributes to the output
+    fn update_input(
+        &self,
+        pad: &gst::Pad,
+        cpad: &CompositorPad,
+        out_start: u64,
+        out_end: u64,
+    ) -> bool {
+        let segment = match pad.get_segment().downcast::<gst::ClockTime>() {
+            Ok(segment) => segment,
+            Err(_) => {
+                gst_error!(self.cat, obj: pad, "Only Time segments supported");
+                return false;
+            }
+        };
+
+        let mut state = cpad.state.lock().unwrap();
+
+        while let Some(buffer) = pad.peek_buffer() {
+            let start = match segment.to_running_time(buffer.get_pts()).0 {
+                None => {
+                    gst_debug!(self.cat, obj: pad, "Dropping buffer outside segment");
+                    pad.drop_buffer();
+                    continue;
+                }
+                Some(start) => start,
+            };
+
+            if start >= out_end {
+                break;
+            }
+
+            state.end = buffer.get_duration().0.map(|duration| start + duration);
+            state.buffer = Some(buffer);
+            pad.drop_buffer();
+        }
+
+        if pad.is_eos() && state.end.map(|end| end <= out_start).unwrap_or(true) {
+            state.buffer = None;
+            state.end = None;
+        }
+
+        state.buffer.is_some() || !pad.is_eos()
+    }
+}
+
+impl ObjectImpl<Aggregator> for Compositor {}
+
+impl ElementImpl<Aggregator> for Compositor {}
+
+impl AggregatorImpl<Aggregator> for Compositor {
+    fn start(&self, element: &Aggregator) -> bool {
+        *self.state.lock().unwrap() = Default::default();
+
+        element.parent_start()
+    }
+
+    fn stop(&self, element: &Aggregator) -> bool {
+        *self.state.lock().unwrap() = Default::default();
+
+        element.parent_stop()
+    }
+
+    fn flush(&self, element: &Aggregator) -> gst::FlowReturn {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.base = None;
+            state.frame = 0;
+        }
+
+        element.parent_flush()
+    }
+
+    fn sink_event(&self, element: &Aggregator, pad: &gst::Pad, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        if let EventView::Caps(e) = event.view() {
+            let info = match gst_video::VideoInfo::from_caps(e.get_caps()) {
+                None => return false,
+                Some(info) => info,
+            };
+
+            gst_debug!(self.cat, obj: pad, "Got caps {:?}", e.get_caps());
+            with_pad(pad, |cpad| cpad.state.lock().unwrap().info = Some(info));
+
+            // Output size and framerate might have to change
+            element.get_src_pad().mark_reconfigure();
+            return true;
+        }
+
+        element.parent_sink_event(pad, event)
+    }
+
+    fn create_new_pad(
+        &self,
+        _element: &Aggregator,
+        templ: &gst::PadTemplate,
+        req_name: Option<String>,
+        _caps: Option<&gst::CapsRef>,
+    ) -> Option<gst::Pad> {
+        let (name, number) = {
+            let mut next_pad = self.next_pad.lock().unwrap();
+            let number = req_name
+                .as_ref()
+                .and_then(|name| {
+                    if name.starts_with("sink_") {
+                        name["sink_".len()..].parse::<u32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(*next_pad);
+            *next_pad = cmp::max(*next_pad, number + 1);
+
+            (format!("sink_{}", number), number)
+        };
+
+        let pad = glib::Object::new(
+            get_pad_type(),
+            &[
+                ("name", &name),
+                ("direction", &gst::PadDirection::Sink),
+                ("template", templ),
+                ("zorder", &number),
+            ],
+        );
+
+        pad.ok().and_then(|pad| pad.downcast::<gst::Pad>().ok())
+    }
+
+    fn fixate_src_caps(&self, element: &Aggregator, mut caps: gst::Caps) -> gst::Caps {
+        let (width, height, fps) = self.preferred_output(element);
+
+        {
+            let caps = caps.make_mut();
+            for i in 0..caps.get_size() {
+                let s = caps.get_mut_structure(i).unwrap();
+                s.fixate_field_nearest_int("width", width);
+                s.fixate_field_nearest_int("height", height);
+                s.fixate_field_nearest_fraction("framerate", fps);
+            }
+        }
+
+        element.parent_fixate_src_caps(caps)
+    }
+
+    fn negotiated_src_caps(&self, element: &Aggregator, caps: &gst::Caps) -> bool {
+        let info = match gst_video::VideoInfo::from_caps(caps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        if *info.fps().numer() <= 0 || *info.fps().denom() <= 0 {
+            gst_error!(self.cat, obj: element, "Variable framerate not supported");
+            return false;
+        }
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", caps);
+
+        {
+            let mut state = self.state.lock().unwrap();
+
+            // Continue from the current position with the new framerate
+            if let (Some(base), Some(old_info)) = (state.base, state.out_info.as_ref()) {
+                let old_fps = (
+                    *old_info.fps().numer() as u64,
+                    *old_info.fps().denom() as u64,
+                );
+                state.base = Some(base + frame_to_time(state.frame, old_fps));
+                state.frame = 0;
+            }
+            state.out_info = Some(info);
+        }
+
+        element.parent_negotiated_src_caps(caps)
+    }
+
+    fn get_next_time(&self, element: &Aggregator) -> gst::ClockTime {
+        let segment = match element
+            .get_src_pad()
+            .get_segment()
+            .downcast::<gst::ClockTime>()
+        {
+            Ok(segment) => segment,
+            Err(_) => return gst::CLOCK_TIME_NONE,
+        };
+
+        let state = self.state.lock().unwrap();
+        let fps = match state.out_info {
+            None => return gst::CLOCK_TIME_NONE,
+            Some(ref info) => (*info.fps().numer() as u64, *info.fps().denom() as u64),
+        };
+
+        let base = state
+            .base
+            .unwrap_or_else(|| segment.get_start().0.unwrap_or(0));
+        segment.to_running_time(gst::ClockTime::from_nseconds(
+            base + frame_to_time(state.frame, fps),
+        ))
+    }
+
+    fn aggregate(&self, element: &Aggregator, _timeout: bool) -> gst::FlowReturn {
+        let segment = match element
+            .get_src_pad()
+            .get_segment()
+            .downcast::<gst::ClockTime>()
+        {
+            Ok(segment) => segment,
+            Err(_) => {
+                gst_element_error!(element, gst::StreamError::Format, ["Only Time segments"]);
+                return gst::FlowReturn::Error;
+            }
+        };
+
+        let (out_info, pts, end) = {
+            let mut state = self.state.lock().unwrap();
+            let out_info = match state.out_info {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Negotiation, ["Have no caps"]);
+                    return gst::FlowReturn::NotNegotiated;
+                }
+                Some(ref info) => info.clone(),
+            };
+            let fps = (
+                *out_info.fps().numer() as u64,
+                *out_info.fps().denom() as u64,
+            );
+
+            if state.base.is_none() {
+                state.base = Some(segment.get_start().0.unwrap_or(0));
+            }
+            let base = state.base.unwrap();
+
+            (
+                out_info,
+                base + frame_to_time(state.frame, fps),
+                base + frame_to_time(state.frame + 1, fps),
+            )
+        };
+
+        if segment.get_stop().0.map(|stop| pts >= stop).unwrap_or(false) {
+            gst_debug!(self.cat, obj: element, "At the end of the segment");
+            return gst::FlowReturn::Eos;
+        }
+
+        let (out_start, out_end) = match (
+            segment.to_running_time(gst::ClockTime::from_nseconds(pts)).0,
+            segment.to_running_time(gst::ClockTime::from_nseconds(end)).0,
+        ) {
+            (Some(out_start), Some(out_end)) => (out_start, out_end),
+            _ => return gst::FlowReturn::Eos,
+        };
+
+        let mut active = false;
+        let mut layers = Vec::new();
+        for pad in element.get_sink_pads() {
+            let layer = with_pad(&pad, |cpad| {
+                active |= self.update_input(&pad, cpad, out_start, out_end);
+
+                let settings = *cpad.settings.lock().unwrap();
+                let state = cpad.state.lock().unwrap();
+                match (state.buffer.as_ref(), state.info.as_ref()) {
+                    (Some(buffer), Some(info)) => Some(Layer {
+                        buffer: buffer.clone(),
+                        geometry: Geometry::from_info(info),
+                        placement: Placement::new(&settings, info),
+                        zorder: settings.zorder,
+                    }),
+                    _ => None,
+                }
+            });
+
+            if let Some(layer) = layer {
+                layers.push(layer);
+            }
+        }
+
+        if !active {
+            gst_debug!(self.cat, obj: element, "All inputs are EOS");
+            return gst::FlowReturn::Eos;
+        }
+
+        layers.sort_by_key(|layer| layer.zorder);
+
+        let out_geometry = Geometry::from_info(&out_info);
+        let mut outbuf = gst::Buffer::with_size(out_info.size()).unwrap();
+        {
+            let outbuf = outbuf.get_mut().unwrap();
+            outbuf.set_pts(gst::ClockTime::from_nseconds(pts));
+            outbuf.set_duration(gst::ClockTime::from_nseconds(end - pts));
+
+            let mut map = outbuf.map_writable().unwrap();
+            let data = map.as_mut_slice();
+            clear(data, &out_geometry);
+
+            for layer in &layers {
+                let in_map = match layer.buffer.map_readable() {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Failed,
+                            ["Failed to map buffer"]
+                        );
+                        return gst::FlowReturn::Error;
+                    }
+                    Some(map) => map,
+                };
+
+                blend(
+                    in_map.as_slice(),
+                    &layer.geometry,
+                    data,
+                    &out_geometry,
+                    &layer.placement,
+                );
+            }
+        }
+
+        self.state.lock().unwrap().frame += 1;
+
+        gst_log!(self.cat, obj: element, "Producing buffer {:?}", outbuf);
+
+        element.finish_buffer(outbuf)
+    }
+}
+
+impl ChildProxyImpl for Compositor {
+    fn get_child_by_index(&self, element: &gst::ChildProxy, index: u32) -> Option<glib::Object> {
+        let element = element.clone().dynamic_cast::<gst::Element>().unwrap();
+        element
+            .get_sink_pads()
+            .into_iter()
+            .nth(index as usize)
+            .map(|pad| pad.upcast())
+    }
+
+    fn get_children_count(&self, element: &gst::ChildProxy) -> u32 {
+        let element = element.clone().dynamic_cast::<gst::Element>().unwrap();
+        element.get_sink_pads().len() as u32
+    }
+}
+
+struct CompositorStatic;
+
+impl ImplTypeStatic<Aggregator> for CompositorStatic {
+    fn get_name(&self) -> &str {
+        "RsCompositor"
+    }
+
+    fn new(&self, element: &Aggregator) -> Box<AggregatorImpl<Aggregator>> {
+        Compositor::init(element)
+    }
+
+    fn class_init(&self, klass: &mut AggregatorClass) {
+        Compositor::class_init(klass);
+    }
+
+    fn type_init(&self, token: &TypeInitToken, type_: glib::Type) {
+        register_child_proxy(token, type_, self);
+    }
+}
+
+impl ChildProxyImplStatic<Aggregator> for CompositorStatic {
+    fn get_impl<'a>(&self, imp: &'a Box<AggregatorImpl<Aggregator>>) -> &'a ChildProxyImpl {
+        imp.downcast_ref::<Compositor>().unwrap()
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    // The pad type must exist before the first pad is requested
+    get_pad_type();
+
+    let compositor_static = CompositorStatic;
+    register_type(compositor_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geometry(width: usize, height: usize, bpp: usize, alpha: Option<usize>) -> Geometry {
+        Geometry {
+            width: width,
+            height: height,
+            stride: width * bpp,
+            bpp: bpp,
+            offsets: [0, 1, 2],
+            alpha: alpha,
+        }
+    }
+
+    #[test]
+    fn test_blend() {
+        // 4x4 RGBA output, opaque black
+        let out_geometry = geometry(4, 4, 4, Some(3));
+        let mut out = vec![0; 4 * 4 * 4];
+        clear(&mut out, &out_geometry);
+        assert_eq!(&out[..8], &[0, 0, 0, 255, 0, 0, 0, 255]);
+
+        // 1x1 BGR input, scaled to 2x2 and partly outside the frame
+        let in_geometry = Geometry {
+            offsets: [2, 1, 0],
+            ..geometry(1, 1, 3, None)
+        };
+        let placement = Placement {
+            x: -1,
+            y: 3,
+            width: 2,
+            height: 2,
+            alpha: 1.0,
+        };
+        blend(&[30, 20, 10], &in_geometry, &mut out, &out_geometry, &placement);
+        assert_eq!(&out[48..56], &[10, 20, 30, 255, 0, 0, 0, 255]);
+        assert_eq!(&out[..4], &[0, 0, 0, 255]);
+
+        // 2x1 RGBA input with half transparent pixel and half alpha on the pad
+        let in_geometry = geometry(2, 1, 4, Some(3));
+        let placement = Placement {
+            x: 2,
+            y: 0,
+            width: 2,
+            height: 1,
+            alpha: 0.5,
+        };
+        blend(
+            &[200, 100, 0, 255, 200, 100, 0, 128],
+            &in_geometry,
+            &mut out,
+            &out_geometry,
+            &placement,
+        );
+        assert_eq!(&out[8..16], &[100, 50, 0, 255, 50, 25, 0, 255]);
+
+        // Fully transparent inputs change nothing
+        let before = out.clone();
+        let placement = Placement {
+            alpha: 0.0,
+            ..placement
+        };
+        blend(
+            &[255; 8],
+            &in_geometry,
+            &mut out,
+            &out_geometry,
+            &placement,
+        );
+        assert_eq!(out, before);
+    }
+}
@@ -0,0 +1,515 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::f64::consts::PI;
+use std::sync::Mutex;
+use std::{i32, u32};
+
+use rgb;
+
+// Reprojection:
+//
+// Every output pixel is a ray from the center of a virtual pinhole camera with the given
+// horizontal field of view, which is rotated by the pitch and then the yaw. The longitude and
+// latitude of the ray select the position in the equirectangular input, which is sampled
+// bilinearly and wraps around horizontally.
+//
+// The source positions and weights are only calculated when the parameters change, per frame
+// only the weighted sum over the four neighbouring pixels is calculated in fixed point.
+
+const DEFAULT_WIDTH: u32 = 1280;
+const DEFAULT_HEIGHT: u32 = 720;
+const DEFAULT_FOV: f64 = 90.0;
+const DEFAULT_YAW: f64 = 0.0;
+const DEFAULT_PITCH: f64 = 0.0;
+
+static PROPERTIES: [Property; 5] = [
+    Property::UInt(
+        "width",
+        "Width",
+        "Width of the output (can't be changed in PLAYING or PAUSED state)",
+        (1, i32::MAX as u32),
+        DEFAULT_WIDTH,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "height",
+        "Height",
+        "Height of the output (can't be changed in PLAYING or PAUSED state)",
+        (1, i32::MAX as u32),
+        DEFAULT_HEIGHT,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "fov",
+        "Field of View",
+        "Horizontal field of view in degrees",
+        (1.0, 179.0),
+        DEFAULT_FOV,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "yaw",
+        "Yaw",
+        "Rotation of the view to the right in degrees",
+        (-180.0, 180.0),
+        DEFAULT_YAW,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Double(
+        "pitch",
+        "Pitch",
+        "Rotation of the view upwards in degrees",
+        (-90.0, 90.0),
+        DEFAULT_PITCH,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Settings {
+    width: u32,
+    height: u32,
+    fov: f64,
+    yaw: f64,
+    pitch: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            fov: DEFAULT_FOV,
+            yaw: DEFAULT_YAW,
+            pitch: DEFAULT_PITCH,
+        }
+    }
+}
+
+// Byte offsets of the four neighbouring input pixels and their weights, which sum up to 1 << 16
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sample {
+    offsets: [usize; 4],
+    weights: [u32; 4],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Geometry {
+    in_width: usize,
+    in_height: usize,
+    in_stride: usize,
+    bpp: usize,
+    out_width: usize,
+    out_height: usize,
+}
+
+fn compute_map(geometry: &Geometry, fov: f64, yaw: f64, pitch: f64) -> Vec<Sample> {
+    let (in_width, in_height) = (geometry.in_width as f64, geometry.in_height as f64);
+    let (out_width, out_height) = (geometry.out_width as f64, geometry.out_height as f64);
+
+    let focal = (out_width / 2.0) / (fov.to_radians() / 2.0).tan();
+    let (sin_yaw, cos_yaw) = yaw.to_radians().sin_cos();
+    let (sin_pitch, cos_pitch) = pitch.to_radians().sin_cos();
+
+    let mut map = Vec::with_capacity(geometry.out_width * geometry.out_height);
+    for y in 0..geometry.out_height {
+        for x in 0..geometry.out_width {
+            // Camera looks along z, with x to the right and y upwards
+            let dx = x as f64 + 0.5 - out_width / 2.0;
+            let dy = out_height / 2.0 - (y as f64 + 0.5);
+            let dz = focal;
+
+            let (dy, dz) = (dy * cos_pitch + dz * sin_pitch, dz * cos_pitch - dy * sin_pitch);
+            let (dx, dz) = (dx * cos_yaw + dz * sin_yaw, dz * cos_yaw - dx * sin_yaw);
+
+            let lon = dx.atan2(dz);
+            let lat = (dy / (dx * dx + dy * dy + dz * dz).sqrt()).asin();
+
+            let u = (lon / (2.0 * PI) + 0.5) * in_width - 0.5;
+            let v = (0.5 - lat / PI) * in_height - 0.5;
+
+            let u0 = u.floor();
+            let v0 = v.floor();
+            let fx = ((u - u0) * 256.0).round() as u32;
+            let fy = ((v - v0) * 256.0).round() as u32;
+
+            let wrap = |u: f64| {
+                let width = geometry.in_width as i64;
+                (((u as i64) % width + width) % width) as usize
+            };
+            let clamp = |v: f64| (v.max(0.0) as usize).min(geometry.in_height - 1);
+            let (x0, x1) = (wrap(u0), wrap(u0 + 1.0));
+            let (y0, y1) = (clamp(v0), clamp(v0 + 1.0));
+
+            let row0 = y0 * geometry.in_stride;
+            let row1 = y1 * geometry.in_stride;
+            map.push(Sample {
+                offsets: [
+                    row0 + x0 * geometry.bpp,
+                    row0 + x1 * geometry.bpp,
+                    row1 + x0 * geometry.bpp,
+                    row1 + x1 * geometry.bpp,
+                ],
+                weights: [
+                    (256 - fx) * (256 - fy),
+                    fx * (256 - fy),
+                    (256 - fx) * fy,
+                    fx * fy,
+                ],
+            });
+        }
+    }
+
+    map
+}
+
+fn remap(
+    map: &[Sample],
+    bpp: usize,
+    input: &[u8],
+    out: &mut [u8],
+    out_width: usize,
+    out_stride: usize,
+) {
+    for (row, samples) in out.chunks_mut(out_stride).zip(map.chunks(out_width)) {
+        for (pixel, sample) in row[..(out_width * bpp)].chunks_mut(bpp).zip(samples.iter()) {
+            // All components are calculated the same way so that this vectorizes well
+            for (c, p) in pixel.iter_mut().enumerate() {
+                let sum = sample.weights[0] * u32::from(input[sample.offsets[0] + c])
+                    + sample.weights[1] * u32::from(input[sample.offsets[1] + c])
+                    + sample.weights[2] * u32::from(input[sample.offsets[2] + c])
+                    + sample.weights[3] * u32::from(input[sample.offsets[3] + c]);
+                *p = ((sum + (1 << 15)) >> 16) as u8;
+            }
+        }
+    }
+}
+
+struct State {
+    in_info: gst_video::VideoInfo,
+    out_info: gst_video::VideoInfo,
+    // Map and the parameters it was computed for
+    map: Option<(f64, f64, f64, Vec<Sample>)>,
+}
+
+struct Equirect2Rect {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl Equirect2Rect {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "equirect2rect",
+                gst::DebugColorFlags::empty(),
+                "Equirectangular to rectilinear reprojection",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Equirectangular to rectilinear",
+            "Filter/Converter/Video",
+            "Extracts a rectilinear view from equirectangular 360° video",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = rgb::caps();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::NeverInPlace, false, false);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseTransform> for Equirect2Rect {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("width", ..) => {
+                if self.state.lock().unwrap().is_none() {
+                    settings.width = value.get().unwrap();
+                }
+            }
+            Property::UInt("height", ..) => {
+                if self.state.lock().unwrap().is_none() {
+                    settings.height = value.get().unwrap();
+                }
+            }
+            Property::Double("fov", ..) => {
+                settings.fov = value.get().unwrap();
+            }
+            Property::Double("yaw", ..) => {
+                settings.yaw = value.get().unwrap();
+            }
+            Property::Double("pitch", ..) => {
+                settings.pitch = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::UInt("width", ..) => Ok(settings.width.to_value()),
+            Property::UInt("height", ..) => Ok(settings.height.to_value()),
+            Property::Double("fov", ..) => Ok(settings.fov.to_value()),
+            Property::Double("yaw", ..) => Ok(settings.yaw.to_value()),
+            Property::Double("pitch", ..) => Ok(settings.pitch.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for Equirect2Rect {}
+
+impl BaseTransformImpl<BaseTransform> for Equirect2Rect {
+    fn transform_caps(
+        &self,
+        _element: &BaseTransform,
+        direction: gst::PadDirection,
+        caps: &gst::Caps,
+        filter: Option<&gst::Caps>,
+    ) -> gst::Caps {
+        let settings = *self.settings.lock().unwrap();
+
+        // The output size is given by the properties, any input size can be reprojected
+        let mut other_caps = caps.clone();
+        {
+            let other_caps = other_caps.make_mut();
+            for i in 0..other_caps.get_size() {
+                let s = other_caps.get_mut_structure(i).unwrap();
+                if direction == gst::PadDirection::Sink {
+                    s.set("width", &(settings.width as i32));
+                    s.set("height", &(settings.height as i32));
+                } else {
+                    s.set("width", &gst::IntRange::<i32>::new(1, i32::MAX));
+                    s.set("height", &gst::IntRange::<i32>::new(1, i32::MAX));
+                }
+            }
+        }
+
+        match filter {
+            None => other_caps,
+            Some(filter) => filter.intersect_with_mode(&other_caps, gst::CapsIntersectMode::First),
+        }
+    }
+
+    fn get_unit_size(&self, _element: &BaseTransform, caps: &gst::Caps) -> Option<usize> {
+        gst_video::VideoInfo::from_caps(caps).map(|info| info.size())
+    }
+
+    fn set_caps(&self, element: &BaseTransform, incaps: &gst::Caps, outcaps: &gst::Caps) -> bool {
+        let (in_info, out_info) = match (
+            gst_video::VideoInfo::from_caps(incaps),
+            gst_video::VideoInfo::from_caps(outcaps),
+        ) {
+            (Some(in_info), Some(out_info)) => (in_info, out_info),
+            _ => return false,
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Reprojecting {}x{} to {}x{}",
+            in_info.width(),
+            in_info.height(),
+            out_info.width(),
+            out_info.height()
+        );
+
+        *self.state.lock().unwrap() = Some(State {
+            in_info: in_info,
+            out_info: out_info,
+            map: None,
+        });
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseTransform) -> bool {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        true
+    }
+
+    fn transform(
+        &self,
+        element: &BaseTransform,
+        inbuf: &gst::Buffer,
+        outbuf: &mut gst::BufferRef,
+    ) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return gst::FlowReturn::NotNegotiated,
+            Some(ref mut state) => state,
+        };
+
+        let (bpp, _) = rgb::layout(state.in_info.format()).unwrap();
+        let geometry = Geometry {
+            in_width: state.in_info.width() as usize,
+            in_height: state.in_info.height() as usize,
+            in_stride: state.in_info.stride()[0] as usize,
+            bpp: bpp,
+            out_width: state.out_info.width() as usize,
+            out_height: state.out_info.height() as usize,
+        };
+
+        let outdated = match state.map {
+            None => true,
+            Some((fov, yaw, pitch, _)) => {
+                fov != settings.fov || yaw != settings.yaw || pitch != settings.pitch
+            }
+        };
+        if outdated {
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "Calculating map for fov {} yaw {} pitch {}",
+                settings.fov,
+                settings.yaw,
+                settings.pitch
+            );
+            let map = compute_map(&geometry, settings.fov, settings.yaw, settings.pitch);
+            state.map = Some((settings.fov, settings.yaw, settings.pitch, map));
+        }
+        let map = &state.map.as_ref().unwrap().3;
+
+        let in_map = match inbuf.map_readable() {
+            None => return gst::FlowReturn::Error,
+            Some(map) => map,
+        };
+        let mut out_map = match outbuf.map_writable() {
+            None => return gst::FlowReturn::Error,
+            Some(map) => map,
+        };
+
+        remap(
+            map,
+            bpp,
+            in_map.as_slice(),
+            out_map.as_mut_slice(),
+            geometry.out_width,
+            state.out_info.stride()[0] as usize,
+        );
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct Equirect2RectStatic;
+
+impl ImplTypeStatic<BaseTransform> for Equirect2RectStatic {
+    fn get_name(&self) -> &str {
+        "Equirect2Rect"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        Equirect2Rect::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        Equirect2Rect::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let equirect2rect_static = Equirect2RectStatic;
+    register_type(equirect2rect_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map() {
+        let geometry = Geometry {
+            in_width: 360,
+            in_height: 180,
+            in_stride: 360 * 4,
+            bpp: 4,
+            out_width: 2,
+            out_height: 2,
+        };
+
+        // Looking straight ahead, the top left pixel is up and left of the center of the input
+        let map = compute_map(&geometry, 90.0, 0.0, 0.0);
+        let (x, y) = (map[0].offsets[0] % (360 * 4) / 4, map[0].offsets[0] / (360 * 4));
+        assert!(x >= 145 && x < 165, "{}", x);
+        assert!(y >= 60 && y < 75, "{}", y);
+        assert_eq!(map[0].weights.iter().sum::<u32>(), 1 << 16);
+
+        // Looking backwards, the view wraps around the edges
+        let map = compute_map(&geometry, 90.0, 180.0, 0.0);
+        let x_left = map[0].offsets[0] % (360 * 4) / 4;
+        let x_right = map[1].offsets[0] % (360 * 4) / 4;
+        assert!(x_left > 300, "{}", x_left);
+        assert!(x_right < 60, "{}", x_right);
+
+        // Looking up, the view is in the upper half
+        let map = compute_map(&geometry, 90.0, 0.0, 60.0);
+        assert!(map[3].offsets[0] / (360 * 4) < 65);
+    }
+
+    #[test]
+    fn test_remap() {
+        let sample = Sample {
+            offsets: [0, 3, 6, 9],
+            weights: [1 << 14, 1 << 14, 1 << 14, 1 << 14],
+        };
+        let input = [0, 10, 20, 100, 110, 120, 200, 210, 220, 40, 50, 60];
+        let mut out = [0u8; 3];
+        remap(&[sample], 3, &input, &mut out, 1, 3);
+        assert_eq!(out, [85, 95, 105]);
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![crate_type = "cdylib"]
+
+extern crate glib;
+extern crate glib_sys as glib_ffi;
+#[macro_use]
+extern crate gst_plugin;
+#[macro_use]
+extern crate gstreamer as gst;
+extern crate gstreamer_base as gst_base;
+extern crate gstreamer_sys as gst_ffi;
+extern crate gstreamer_video as gst_video;
+extern crate gstreamer_video_sys as gst_video_ffi;
+extern crate qrcode;
+
+use gst_plugin::registration::*;
+
+mod qr;
+mod rgb;
+mod timecode;
+
+mod colormatch;
+mod compositor;
+mod equirect2rect;
+mod originalbuffer;
+mod qrdetect;
+mod qroverlay;
+mod scenechange;
+mod stereopack;
+mod timecodecheck;
+mod timecodeoverlay;
+mod timecodestamper;
+mod videocompare;
+mod videotestsrc;
+
+fn plugin_init(plugin: &gst::Plugin) -> bool {
+    ElementRegistration::new(plugin)
+        .element("colormatch", RANK_NONE, colormatch::get_type())
+        .element("equirect2rect", RANK_NONE, equirect2rect::get_type())
+        .element("stereopack", RANK_NONE, stereopack::get_type())
+        .element("anaglyph", RANK_NONE, stereopack::get_anaglyph_type())
+        .element("rsvideotestsrc", RANK_NONE, videotestsrc::get_type())
+        .element("rscompositor", RANK_NONE, compositor::get_type())
+        .element("rstimecodestamper", RANK_NONE, timecodestamper::get_type())
+        .element("timecodeoverlay", RANK_NONE, timecodeoverlay::get_type())
+        .element("timecodecheck", RANK_NONE, timecodecheck::get_type())
+        .element("qroverlay", RANK_NONE, qroverlay::get_type())
+        .element("qrdetect", RANK_NONE, qrdetect::get_type())
+        .element("scenechange", RANK_NONE, scenechange::get_type())
+        .element("videocompare", RANK_NONE, videocompare::get_type())
+        .element(
+            "originalbuffersave",
+            RANK_NONE,
+            originalbuffer::get_save_type(),
+        )
+        .element(
+            "originalbufferrestore",
+            RANK_NONE,
+            originalbuffer::get_restore_type(),
+        )
+        .register()
+}
+
+plugin_define!(
+    "rsvideofx",
+    "Rust VideoFx Plugin",
+    plugin_init,
+    "MIT/X11",
+    "https://github.com/sdroege/gst-plugin-rs",
+    "2018-01-22"
+);
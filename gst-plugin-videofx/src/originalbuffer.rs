@@ -0,0 +1,658 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use glib::translate::*;
+use glib_ffi;
+use gst;
+use gst::prelude::*;
+use gst_ffi;
+use gst_video;
+use gst_video_ffi;
+
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::{ptr, usize};
+use std::sync::{Mutex, Once, ONCE_INIT};
+
+// Original buffer save and restore:
+//
+// originalbuffersave attaches a GstRsOriginalBufferMeta with the buffer and its caps to a copy
+// of every buffer, which shares the memory of the original. The meta has no tags, so it is kept
+// by converters, scalers and other filters. originalbufferrestore replaces every buffer with
+// the original buffer of its meta and outputs the original caps, e.g.
+//
+// originalbuffersave ! videoconvert ! videoscale ! <analysis> ! originalbufferrestore ! <encoder>
+//
+// The original buffer keeps its memory and metas. The metas added by the analysis are copied
+// to it, except for metas that refer to the memory of the buffer. Metas tagged with the frame
+// size, e.g. regions of interest, are scaled from the size of the analysed frames to the
+// original size.
+
+#[repr(C)]
+struct OriginalBufferMeta {
+    parent: gst_ffi::GstMeta,
+    original: *mut gst_ffi::GstBuffer,
+    caps: *mut gst_ffi::GstCaps,
+}
+
+unsafe extern "C" fn original_buffer_meta_init(
+    meta: *mut gst_ffi::GstMeta,
+    _params: glib_ffi::gpointer,
+    _buffer: *mut gst_ffi::GstBuffer,
+) -> glib_ffi::gboolean {
+    let meta = &mut *(meta as *mut OriginalBufferMeta);
+    meta.original = ptr::null_mut();
+    meta.caps = ptr::null_mut();
+    glib_ffi::GTRUE
+}
+
+unsafe extern "C" fn original_buffer_meta_free(
+    meta: *mut gst_ffi::GstMeta,
+    _buffer: *mut gst_ffi::GstBuffer,
+) {
+    let meta = &mut *(meta as *mut OriginalBufferMeta);
+    if !meta.original.is_null() {
+        gst_ffi::gst_mini_object_unref(meta.original as *mut gst_ffi::GstMiniObject);
+        meta.original = ptr::null_mut();
+    }
+    if !meta.caps.is_null() {
+        gst_ffi::gst_mini_object_unref(meta.caps as *mut gst_ffi::GstMiniObject);
+        meta.caps = ptr::null_mut();
+    }
+}
+
+unsafe extern "C" fn original_buffer_meta_transform(
+    dest: *mut gst_ffi::GstBuffer,
+    meta: *mut gst_ffi::GstMeta,
+    _buffer: *mut gst_ffi::GstBuffer,
+    _type_: glib_ffi::GQuark,
+    _data: glib_ffi::gpointer,
+) -> glib_ffi::gboolean {
+    // Whatever happens to the buffer, the original stays the same
+    let meta = &*(meta as *const OriginalBufferMeta);
+    add_meta(
+        gst::BufferRef::from_mut_ptr(dest),
+        &from_glib_none(meta.original),
+        &from_glib_none(meta.caps),
+    );
+    glib_ffi::GTRUE
+}
+
+fn get_meta_info() -> *const gst_ffi::GstMetaInfo {
+    static ONCE: Once = ONCE_INIT;
+    static mut INFO: *const gst_ffi::GstMetaInfo = 0 as *const gst_ffi::GstMetaInfo;
+
+    ONCE.call_once(|| unsafe {
+        let mut tags = [ptr::null()];
+        let api = gst_ffi::gst_meta_api_type_register(
+            b"GstRsOriginalBufferMetaAPI\0".as_ptr() as *const _,
+            tags.as_mut_ptr(),
+        );
+        INFO = gst_ffi::gst_meta_register(
+            api,
+            b"GstRsOriginalBufferMeta\0".as_ptr() as *const _,
+            ::std::mem::size_of::<OriginalBufferMeta>(),
+            Some(original_buffer_meta_init),
+            Some(original_buffer_meta_free),
+            Some(original_buffer_meta_transform),
+        );
+    });
+
+    unsafe { INFO }
+}
+
+fn add_meta(buffer: &mut gst::BufferRef, original: &gst::Buffer, caps: &gst::Caps) {
+    unsafe {
+        let meta = gst_ffi::gst_buffer_add_meta(
+            buffer.as_mut_ptr(),
+            get_meta_info(),
+            ptr::null_mut(),
+        ) as *mut OriginalBufferMeta;
+        (*meta).original = original.to_glib_full();
+        (*meta).caps = caps.to_glib_full();
+    }
+}
+
+fn get_meta(buffer: &gst::BufferRef) -> Option<(gst::Buffer, gst::Caps)> {
+    unsafe {
+        let meta = gst_ffi::gst_buffer_get_meta(
+            buffer.as_ptr() as *mut _,
+            (*get_meta_info()).api,
+        ) as *const OriginalBufferMeta;
+        if meta.is_null() || (*meta).original.is_null() || (*meta).caps.is_null() {
+            return None;
+        }
+
+        Some((from_glib_none((*meta).original), from_glib_none((*meta).caps)))
+    }
+}
+
+// Copies the metas of the analysed buffer to the original buffer. `infos` are the video infos
+// of both buffers, if both are video.
+fn copy_metas(
+    buffer: &gst::BufferRef,
+    original: &mut gst::BufferRef,
+    infos: Option<(&gst_video::VideoInfo, &gst_video::VideoInfo)>,
+) {
+    unsafe {
+        let memory_tag = glib_ffi::g_quark_from_static_string(b"memory\0".as_ptr() as *const _);
+        let size_tag = glib_ffi::g_quark_from_static_string(b"size\0".as_ptr() as *const _);
+        let copy = glib_ffi::g_quark_from_static_string(b"gst-copy\0".as_ptr() as *const _);
+
+        let mut iter_state = ptr::null_mut();
+        loop {
+            let meta = gst_ffi::gst_buffer_iterate_meta(buffer.as_ptr() as *mut _, &mut iter_state);
+            if meta.is_null() {
+                break;
+            }
+
+            let info = (*meta).info;
+            let api = (*info).api;
+            if info == get_meta_info()
+                || gst_ffi::gst_meta_api_type_has_tag(api, memory_tag) != glib_ffi::GFALSE
+            {
+                continue;
+            }
+
+            let transform = match (*info).transform_func {
+                None => continue,
+                Some(transform) => transform,
+            };
+
+            if gst_ffi::gst_meta_api_type_has_tag(api, size_tag) != glib_ffi::GFALSE {
+                let (in_info, out_info) = match infos {
+                    None => continue,
+                    Some(infos) => infos,
+                };
+
+                let mut data = gst_video_ffi::GstVideoMetaTransform {
+                    in_info: in_info.to_glib_none().0 as *mut _,
+                    out_info: out_info.to_glib_none().0 as *mut _,
+                };
+                transform(
+                    original.as_mut_ptr(),
+                    meta,
+                    buffer.as_ptr() as *mut _,
+                    gst_video_ffi::gst_video_meta_transform_scale_get_quark(),
+                    &mut data as *mut _ as glib_ffi::gpointer,
+                );
+            } else {
+                let mut data = gst_ffi::GstMetaTransformCopy {
+                    region: glib_ffi::GFALSE,
+                    offset: 0,
+                    size: usize::MAX,
+                };
+                transform(
+                    original.as_mut_ptr(),
+                    meta,
+                    buffer.as_ptr() as *mut _,
+                    copy,
+                    &mut data as *mut _ as glib_ffi::gpointer,
+                );
+            }
+        }
+    }
+}
+
+fn add_pad_templates(klass: &mut ElementClass) {
+    let caps = gst::Caps::new_any();
+    let sink_pad_template = gst::PadTemplate::new(
+        "sink",
+        gst::PadDirection::Sink,
+        gst::PadPresence::Always,
+        &caps,
+    );
+    klass.add_pad_template(sink_pad_template);
+
+    let src_pad_template = gst::PadTemplate::new(
+        "src",
+        gst::PadDirection::Src,
+        gst::PadPresence::Always,
+        &caps,
+    );
+    klass.add_pad_template(src_pad_template);
+}
+
+struct OriginalBufferSave {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+}
+
+impl OriginalBufferSave {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "originalbuffersave",
+                gst::DebugColorFlags::empty(),
+                "Original buffer save",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Original Buffer Save",
+            "Generic",
+            "Attaches the original buffer to every buffer for originalbufferrestore",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        add_pad_templates(klass);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        // The meta API has to be registered before the first buffer is copied
+        get_meta_info();
+
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            OriginalBufferSave::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |save, element| save.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            OriginalBufferSave::catch_panic_pad_function(
+                parent,
+                || false,
+                |save, element| save.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            OriginalBufferSave::catch_panic_pad_function(
+                parent,
+                || false,
+                |save, element| save.sink_query(pad, element, query),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            OriginalBufferSave::catch_panic_pad_function(
+                parent,
+                || false,
+                |save, element| save.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            OriginalBufferSave::catch_panic_pad_function(
+                parent,
+                || false,
+                |save, element| save.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let save = element
+            .get_impl()
+            .downcast_ref::<OriginalBufferSave>()
+            .unwrap();
+        element.catch_panic(fallback, |element| f(save, element))
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let caps = match pad.get_current_caps() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Negotiation, ["Have no caps"]);
+                return gst::FlowReturn::NotNegotiated;
+            }
+            Some(caps) => caps,
+        };
+
+        // Shares the memory with the original buffer
+        let mut outbuf = buffer.copy();
+        add_meta(outbuf.get_mut().unwrap(), &buffer, &caps);
+
+        gst_log!(self.cat, obj: pad, "Pushing buffer {:?}", outbuf);
+        self.srcpad.push(outbuf)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, _element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding query {:?}", query);
+        self.srcpad.peer_query(query)
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, _element: &Element, query: &mut gst::QueryRef) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding query {:?}", query);
+        self.sinkpad.peer_query(query)
+    }
+}
+
+impl ObjectImpl<Element> for OriginalBufferSave {}
+
+impl ElementImpl<Element> for OriginalBufferSave {}
+
+struct OriginalBufferSaveStatic;
+
+impl ImplTypeStatic<Element> for OriginalBufferSaveStatic {
+    fn get_name(&self) -> &str {
+        "OriginalBufferSave"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        OriginalBufferSave::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        OriginalBufferSave::class_init(klass);
+    }
+}
+
+pub fn get_save_type() -> glib::Type {
+    let originalbuffersave_static = OriginalBufferSaveStatic;
+    register_type(originalbuffersave_static)
+}
+
+struct State {
+    // Video info of the analysed buffers
+    info: Option<gst_video::VideoInfo>,
+    // Caps of the original buffers that were last sent downstream and their video info
+    original_caps: Option<gst::Caps>,
+    original_info: Option<gst_video::VideoInfo>,
+    // Sent after the caps of the first original buffer, which must come first
+    pending_segment: Option<gst::Event>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            info: None,
+            original_caps: None,
+            original_info: None,
+            pending_segment: None,
+        }
+    }
+}
+
+struct OriginalBufferRestore {
+    cat: gst::DebugCategory,
+    sinkpad: gst::Pad,
+    srcpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl OriginalBufferRestore {
+    fn new(_element: &Element, sinkpad: gst::Pad, srcpad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "originalbufferrestore",
+                gst::DebugColorFlags::empty(),
+                "Original buffer restore",
+            ),
+            sinkpad: sinkpad,
+            srcpad: srcpad,
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass) {
+        klass.set_metadata(
+            "Original Buffer Restore",
+            "Generic",
+            "Restores the original buffers saved by originalbuffersave",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        add_pad_templates(klass);
+    }
+
+    fn init(element: &Element) -> Box<ElementImpl<Element>> {
+        get_meta_info();
+
+        let templ = element.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::new_from_template(&templ, "sink");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            OriginalBufferRestore::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |restore, element| restore.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            OriginalBufferRestore::catch_panic_pad_function(
+                parent,
+                || false,
+                |restore, element| restore.sink_event(pad, element, event),
+            )
+        });
+        sinkpad.set_query_function(|pad, parent, query| {
+            OriginalBufferRestore::catch_panic_pad_function(
+                parent,
+                || false,
+                |restore, element| restore.sink_query(pad, element, query),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            OriginalBufferRestore::catch_panic_pad_function(
+                parent,
+                || false,
+                |restore, element| restore.src_event(pad, element, event),
+            )
+        });
+        srcpad.set_query_function(|pad, parent, query| {
+            OriginalBufferRestore::catch_panic_pad_function(
+                parent,
+                || false,
+                |restore, element| restore.src_query(pad, element, query),
+            )
+        });
+
+        element.add_pad(&sinkpad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, sinkpad, srcpad);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let restore = element
+            .get_impl()
+            .downcast_ref::<OriginalBufferRestore>()
+            .unwrap();
+        element.catch_panic(fallback, |element| f(restore, element))
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let (original, caps) = match get_meta(&buffer) {
+            None => {
+                gst_element_error!(
+                    element,
+                    gst::StreamError::Failed,
+                    ["Buffer has no original buffer, originalbuffersave missing upstream?"]
+                );
+                return gst::FlowReturn::Error;
+            }
+            Some(original) => original,
+        };
+
+        let (events, info, original_info) = {
+            let mut state = self.state.lock().unwrap();
+
+            let mut events = Vec::new();
+            if state.original_caps.as_ref() != Some(&caps) {
+                gst_debug!(self.cat, obj: pad, "Original caps changed to {}", caps);
+                state.original_info = gst_video::VideoInfo::from_caps(&caps);
+                state.original_caps = Some(caps.clone());
+                events.push(gst::Event::new_caps(&caps).build());
+            }
+            if let Some(segment) = state.pending_segment.take() {
+                events.push(segment);
+            }
+
+            (events, state.info.clone(), state.original_info.clone())
+        };
+
+        for event in events {
+            self.srcpad.push_event(event);
+        }
+
+        let mut outbuf = original.copy();
+        {
+            let infos = match (info.as_ref(), original_info.as_ref()) {
+                (Some(info), Some(original_info)) => Some((info, original_info)),
+                _ => None,
+            };
+            copy_metas(&buffer, outbuf.get_mut().unwrap(), infos);
+        }
+
+        gst_log!(self.cat, obj: pad, "Pushing original buffer {:?}", outbuf);
+        self.srcpad.push(outbuf)
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            // The caps of the original buffers are sent instead
+            EventView::Caps(e) => {
+                gst_debug!(self.cat, obj: pad, "Got caps {}", e.get_caps());
+                self.state.lock().unwrap().info = gst_video::VideoInfo::from_caps(e.get_caps());
+                return true;
+            }
+            EventView::Segment(..) => {
+                let mut state = self.state.lock().unwrap();
+                if state.original_caps.is_none() {
+                    state.pending_segment = Some(event.clone());
+                    return true;
+                }
+            }
+            _ => (),
+        }
+
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.srcpad.push_event(event)
+    }
+
+    fn sink_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+        match query.view_mut() {
+            // Any caps can be restored, and the analysed buffers are not sent downstream
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            QueryView::Allocation(..) => false,
+            _ => self.srcpad.peer_query(query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.sinkpad.push_event(event)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(self.cat, obj: pad, "Handling query {:?}", query);
+        match query.view_mut() {
+            QueryView::Caps(..) | QueryView::AcceptCaps(..) => {
+                pad.query_default(Some(&element.clone().upcast()), query)
+            }
+            _ => self.sinkpad.peer_query(query),
+        }
+    }
+}
+
+impl ObjectImpl<Element> for OriginalBufferRestore {}
+
+impl ElementImpl<Element> for OriginalBufferRestore {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        if let gst::StateChange::ReadyToPaused = transition {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct OriginalBufferRestoreStatic;
+
+impl ImplTypeStatic<Element> for OriginalBufferRestoreStatic {
+    fn get_name(&self) -> &str {
+        "OriginalBufferRestore"
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        OriginalBufferRestore::init(element)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        OriginalBufferRestore::class_init(klass);
+    }
+}
+
+pub fn get_restore_type() -> glib::Type {
+    let originalbufferrestore_static = OriginalBufferRestoreStatic;
+    register_type(originalbufferrestore_static)
+}
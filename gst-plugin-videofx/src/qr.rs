@@ -0,0 +1,288 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// QR codes for qroverlay and qrdetect.
+//
+// The codes are always version 3 with error correction level M, which has room for 42 bytes and
+// only a single block of codewords. Decoding only has to handle this one layout: the modules are
+// read in the standard zig-zag order, every mask is tried and the one for which the Reed-Solomon
+// syndromes are all zero is used. Damaged codes are rejected instead of being corrected.
+
+use qrcode::{EcLevel, QrCode, Version};
+
+use rgb;
+use gst_video;
+
+pub const SIZE: usize = 29;
+// Light modules around the code required by readers
+pub const QUIET_ZONE: usize = 4;
+
+const CODEWORDS: usize = 70;
+const DATA_CODEWORDS: usize = 44;
+
+/// Modules of the QR code for `data` in rows, `true` for dark modules
+pub fn encode(data: &[u8]) -> Option<Vec<bool>> {
+    QrCode::with_version(data, Version::Normal(3), EcLevel::M)
+        .ok()
+        .map(|code| {
+            assert_eq!(code.width(), SIZE);
+            code.to_vec()
+        })
+}
+
+// Finder patterns with separators and format information, timing patterns and the
+// alignment pattern
+fn is_function(row: usize, col: usize) -> bool {
+    (row < 9 && col < 9)
+        || (row < 9 && col >= SIZE - 8)
+        || (row >= SIZE - 8 && col < 9)
+        || row == 6
+        || col == 6
+        || (row >= 20 && row <= 24 && col >= 20 && col <= 24)
+}
+
+fn is_masked(mask: u8, row: usize, col: usize) -> bool {
+    let (i, j) = (row, col);
+    match mask {
+        0 => (i + j) % 2 == 0,
+        1 => i % 2 == 0,
+        2 => j % 3 == 0,
+        3 => (i + j) % 3 == 0,
+        4 => (i / 2 + j / 3) % 2 == 0,
+        5 => (i * j) % 2 + (i * j) % 3 == 0,
+        6 => ((i * j) % 2 + (i * j) % 3) % 2 == 0,
+        _ => ((i + j) % 2 + (i * j) % 3) % 2 == 0,
+    }
+}
+
+// Data module positions in placement order, bottom right to the left in two module wide
+// columns alternating upwards and downwards, skipping the vertical timing pattern
+fn data_positions() -> Vec<(usize, usize)> {
+    let mut positions = Vec::with_capacity(CODEWORDS * 8 + 7);
+    let mut col = SIZE as isize - 1;
+    let mut upwards = true;
+
+    while col > 0 {
+        if col == 6 {
+            col -= 1;
+        }
+        for i in 0..SIZE {
+            let row = if upwards { SIZE - 1 - i } else { i };
+            for c in &[col as usize, col as usize - 1] {
+                if !is_function(row, *c) {
+                    positions.push((row, *c));
+                }
+            }
+        }
+        upwards = !upwards;
+        col -= 2;
+    }
+
+    positions
+}
+
+// Multiplication in GF(256) with the QR code polynomial x^8 + x^4 + x^3 + x^2 + 1
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut res = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            res ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1d;
+        }
+        b >>= 1;
+    }
+    res
+}
+
+// All roots of the generator polynomial are roots of the codeword polynomial of intact codes
+fn syndromes_zero(codewords: &[u8]) -> bool {
+    let mut alpha = 1u8;
+    for _ in 0..(CODEWORDS - DATA_CODEWORDS) {
+        let s = codewords.iter().fold(0, |s, &c| gf_mul(s, alpha) ^ c);
+        if s != 0 {
+            return false;
+        }
+        alpha = gf_mul(alpha, 2);
+    }
+    true
+}
+
+// Byte mode data of the data codewords
+fn parse_data(codewords: &[u8]) -> Option<Vec<u8>> {
+    if codewords[0] >> 4 != 0b0100 {
+        return None;
+    }
+
+    let len = ((codewords[0] & 0x0f) << 4 | codewords[1] >> 4) as usize;
+    if len + 2 > DATA_CODEWORDS {
+        return None;
+    }
+
+    Some(
+        (0..len)
+            .map(|i| codewords[i + 1] << 4 | codewords[i + 2] >> 4)
+            .collect(),
+    )
+}
+
+/// Data of the QR code given by its modules in rows, `true` for dark modules
+pub fn decode(modules: &[bool]) -> Option<Vec<u8>> {
+    assert_eq!(modules.len(), SIZE * SIZE);
+
+    let positions = data_positions();
+    for mask in 0..8 {
+        let mut codewords = [0u8; CODEWORDS];
+        for (i, &(row, col)) in positions.iter().take(CODEWORDS * 8).enumerate() {
+            if modules[row * SIZE + col] != is_masked(mask, row, col) {
+                codewords[i / 8] |= 0x80 >> (i % 8);
+            }
+        }
+
+        if syndromes_zero(&codewords) {
+            return parse_data(&codewords[..DATA_CODEWORDS]);
+        }
+    }
+
+    None
+}
+
+/// Draws the modules including the quiet zone with the top left corner at x/y, clipped to the
+/// frame
+pub fn draw(
+    info: &gst_video::VideoInfo,
+    data: &mut [u8],
+    modules: &[bool],
+    x: i32,
+    y: i32,
+    module_size: usize,
+) {
+    let (bpp, offsets) = rgb::layout(info.format()).unwrap();
+    let stride = info.stride()[0] as usize;
+    let full_size = ((SIZE + 2 * QUIET_ZONE) * module_size) as i64;
+
+    let left = clamp(x as i64, 0, info.width() as i64);
+    let right = clamp(x as i64 + full_size, 0, info.width() as i64);
+    let top = clamp(y as i64, 0, info.height() as i64);
+    let bottom = clamp(y as i64 + full_size, 0, info.height() as i64);
+
+    for py in top..bottom {
+        let line = &mut data[(py as usize * stride)..];
+        let row = ((py - y as i64) as usize / module_size).wrapping_sub(QUIET_ZONE);
+        for px in left..right {
+            let col = ((px - x as i64) as usize / module_size).wrapping_sub(QUIET_ZONE);
+            let dark = row < SIZE && col < SIZE && modules[row * SIZE + col];
+
+            let pixel = &mut line[(px as usize * bpp)..((px as usize + 1) * bpp)];
+            for p in pixel.iter_mut() {
+                *p = 255;
+            }
+            if dark {
+                for &offset in &offsets {
+                    pixel[offset] = 0;
+                }
+            }
+        }
+    }
+}
+
+/// Samples the modules of a code drawn by `draw()` at the same position, or `None` if the code
+/// is not completely inside the frame
+pub fn sample(
+    info: &gst_video::VideoInfo,
+    data: &[u8],
+    x: i32,
+    y: i32,
+    module_size: usize,
+) -> Option<Vec<bool>> {
+    let (bpp, offsets) = rgb::layout(info.format()).unwrap();
+    let stride = info.stride()[0] as usize;
+    let full_size = ((SIZE + 2 * QUIET_ZONE) * module_size) as i64;
+
+    if x < 0 || y < 0 || x as i64 + full_size > info.width() as i64
+        || y as i64 + full_size > info.height() as i64
+    {
+        return None;
+    }
+
+    let mut modules = Vec::with_capacity(SIZE * SIZE);
+    for row in 0..SIZE {
+        let py = y as usize + (QUIET_ZONE + row) * module_size + module_size / 2;
+        for col in 0..SIZE {
+            let px = x as usize + (QUIET_ZONE + col) * module_size + module_size / 2;
+            let pixel = &data[(py * stride + px * bpp)..];
+            let sum = offsets.iter().map(|&o| pixel[o] as u32).sum::<u32>();
+            modules.push(sum < 3 * 128);
+        }
+    }
+
+    Some(modules)
+}
+
+/// Data encoded by qroverlay: frame counter and clock time in nanoseconds, as
+/// "<counter>:<time>"
+pub fn format_payload(counter: u64, time: u64) -> Vec<u8> {
+    format!("{}:{}", counter, time).into_bytes()
+}
+
+pub fn parse_payload(data: &[u8]) -> Option<(u64, u64)> {
+    let s = match ::std::str::from_utf8(data) {
+        Err(_) => return None,
+        Ok(s) => s,
+    };
+
+    let mut parts = s.splitn(2, ':');
+    let counter = parts.next().and_then(|s| s.parse::<u64>().ok());
+    let time = parts.next().and_then(|s| s.parse::<u64>().ok());
+    match (counter, time) {
+        (Some(counter), Some(time)) => Some((counter, time)),
+        _ => None,
+    }
+}
+
+fn clamp(v: i64, min: i64, max: i64) -> i64 {
+    if v < min {
+        min
+    } else if v > max {
+        max
+    } else {
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::u64;
+
+    #[test]
+    fn test_layout() {
+        // 70 codewords and 7 remainder bits
+        assert_eq!(data_positions().len(), CODEWORDS * 8 + 7);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let data = format_payload(u64::MAX, u64::MAX);
+        assert_eq!(data.len(), 41);
+        let mut modules = encode(&data).unwrap();
+        assert_eq!(decode(&modules).unwrap(), data);
+        assert_eq!(parse_payload(&data), Some((u64::MAX, u64::MAX)));
+        assert_eq!(parse_payload(b"12"), None);
+
+        let modules_short = encode(b"1:2").unwrap();
+        assert_eq!(decode(&modules_short).unwrap(), b"1:2");
+
+        // A flipped data module is detected
+        modules[(SIZE - 1) * SIZE + SIZE - 1] = !modules[(SIZE - 1) * SIZE + SIZE - 1];
+        assert_eq!(decode(&modules), None);
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::i32;
+use std::sync::Mutex;
+
+use qr;
+use qroverlay::{DEFAULT_MODULE_SIZE, DEFAULT_XPOS, DEFAULT_YPOS};
+use rgb;
+
+// QR code detector for latency measurements:
+//
+// Reads the QR codes drawn by qroverlay back from the frames and posts a "qrdetect" element
+// message for every frame with a code:
+//
+// "qrdetect, running-time=(guint64)..., counter=(guint64)..., timestamp=(guint64)...,
+//  latency=(gint64)..., lost=(guint64)..."
+//
+// latency is the current clock time, or the running time of the frame without clock, minus the
+// timestamp in the code. lost is the number of frames between this and the previously detected
+// frame that did not arrive. xpos, ypos and module-size have to be the same as on qroverlay.
+
+static PROPERTIES: [Property; 3] = [
+    Property::Int(
+        "xpos",
+        "X Position",
+        "Horizontal position of the QR code in the frame",
+        (i32::MIN, i32::MAX),
+        DEFAULT_XPOS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Int(
+        "ypos",
+        "Y Position",
+        "Vertical position of the QR code in the frame",
+        (i32::MIN, i32::MAX),
+        DEFAULT_YPOS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "module-size",
+        "Module Size",
+        "Size of a QR code module in pixels",
+        (1, 64),
+        DEFAULT_MODULE_SIZE,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    xpos: i32,
+    ypos: i32,
+    module_size: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            xpos: DEFAULT_XPOS,
+            ypos: DEFAULT_YPOS,
+            module_size: DEFAULT_MODULE_SIZE,
+        }
+    }
+}
+
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    // Counter of the last detected frame
+    last_counter: Option<u64>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            info: None,
+            segment: gst::FormattedSegment::new(),
+            last_counter: None,
+        }
+    }
+}
+
+struct QrDetect {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl QrDetect {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "qrdetect",
+                gst::DebugColorFlags::empty(),
+                "QR code detector",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "QR Code Detector",
+            "Filter/Analyzer/Video",
+            "Reads the QR codes of qroverlay and measures the latency",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = rgb::caps();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, true, true);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseTransform> for QrDetect {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int("xpos", ..) => settings.xpos = value.get().unwrap(),
+            Property::Int("ypos", ..) => settings.ypos = value.get().unwrap(),
+            Property::UInt("module-size", ..) => settings.module_size = value.get().unwrap(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int("xpos", ..) => Ok(settings.xpos.to_value()),
+            Property::Int("ypos", ..) => Ok(settings.ypos.to_value()),
+            Property::UInt("module-size", ..) => Ok(settings.module_size.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for QrDetect {}
+
+impl BaseTransformImpl<BaseTransform> for QrDetect {
+    fn start(&self, _element: &BaseTransform) -> bool {
+        *self.state.lock().unwrap() = Default::default();
+
+        true
+    }
+
+    fn set_caps(&self, element: &BaseTransform, incaps: &gst::Caps, outcaps: &gst::Caps) -> bool {
+        if incaps != outcaps {
+            return false;
+        }
+
+        let info = match gst_video::VideoInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", incaps);
+        self.state.lock().unwrap().info = Some(info);
+
+        true
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let mut state = self.state.lock().unwrap();
+                state.segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Ok(segment) => segment,
+                    Err(_) => {
+                        gst_warning!(self.cat, obj: element, "Not a time segment");
+                        gst::FormattedSegment::new()
+                    }
+                };
+            }
+            EventView::FlushStop(..) => {
+                self.state.lock().unwrap().last_counter = None;
+            }
+            _ => (),
+        }
+
+        element.parent_sink_event(event)
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+
+        let (info, running_time) = {
+            let state = self.state.lock().unwrap();
+            let info = match state.info {
+                None => return gst::FlowReturn::NotNegotiated,
+                Some(ref info) => info.clone(),
+            };
+            (info, state.segment.to_running_time(buf.get_pts()).0)
+        };
+
+        // Take the clock time as early as possible
+        let now = match element.get_clock() {
+            Some(clock) => clock.get_time().0,
+            None => running_time,
+        };
+
+        let payload = {
+            let map = match buf.map_readable() {
+                None => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+                Some(map) => map,
+            };
+
+            qr::sample(
+                &info,
+                map.as_slice(),
+                settings.xpos,
+                settings.ypos,
+                settings.module_size as usize,
+            ).and_then(|modules| qr::decode(&modules))
+                .and_then(|data| qr::parse_payload(&data))
+        };
+
+        let (counter, timestamp) = match payload {
+            None => {
+                gst_debug!(self.cat, obj: element, "No QR code found");
+                return gst::FlowReturn::Ok;
+            }
+            Some(payload) => payload,
+        };
+
+        let lost = {
+            let mut state = self.state.lock().unwrap();
+            let lost = match state.last_counter {
+                Some(last) if counter > last => counter - last - 1,
+                _ => 0,
+            };
+            state.last_counter = Some(counter);
+            lost
+        };
+
+        let latency = now.map(|now| now as i64 - timestamp as i64).unwrap_or(0);
+        gst_log!(
+            self.cat,
+            obj: element,
+            "Frame {} with latency {} ns, {} lost",
+            counter,
+            latency,
+            lost
+        );
+
+        let s = gst::Structure::new(
+            "qrdetect",
+            &[
+                ("running-time", &running_time.unwrap_or(0)),
+                ("counter", &counter),
+                ("timestamp", &timestamp),
+                ("latency", &latency),
+                ("lost", &lost),
+            ],
+        );
+        let _ = element.post_message(&gst::Message::new_element(s).src(Some(element)).build());
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct QrDetectStatic;
+
+impl ImplTypeStatic<BaseTransform> for QrDetectStatic {
+    fn get_name(&self) -> &str {
+        "QrDetect"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        QrDetect::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        QrDetect::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let qrdetect_static = QrDetectStatic;
+    register_type(qrdetect_static)
+}
@@ -0,0 +1,301 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::i32;
+use std::sync::Mutex;
+
+use qr;
+use rgb;
+
+// QR code overlay for latency measurements:
+//
+// Draws a QR code with a frame counter and the current clock time into every frame, which is
+// read back by qrdetect further downstream or in another pipeline. The difference between the
+// clock time there and the one in the code is the latency between both elements, so both have
+// to use the same clock or clocks that are synchronized, e.g. via NTP or PTP. Without clock the
+// running time of the frame is used instead.
+//
+// The code has a size of 37 modules including the quiet zone and is drawn at xpos/ypos with
+// module-size pixels per module. qrdetect needs the same settings and the video must not be
+// scaled or cropped in between.
+
+pub const DEFAULT_XPOS: i32 = 16;
+pub const DEFAULT_YPOS: i32 = 16;
+pub const DEFAULT_MODULE_SIZE: u32 = 4;
+
+static PROPERTIES: [Property; 3] = [
+    Property::Int(
+        "xpos",
+        "X Position",
+        "Horizontal position of the QR code in the frame",
+        (i32::MIN, i32::MAX),
+        DEFAULT_XPOS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Int(
+        "ypos",
+        "Y Position",
+        "Vertical position of the QR code in the frame",
+        (i32::MIN, i32::MAX),
+        DEFAULT_YPOS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "module-size",
+        "Module Size",
+        "Size of a QR code module in pixels",
+        (1, 64),
+        DEFAULT_MODULE_SIZE,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    xpos: i32,
+    ypos: i32,
+    module_size: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            xpos: DEFAULT_XPOS,
+            ypos: DEFAULT_YPOS,
+            module_size: DEFAULT_MODULE_SIZE,
+        }
+    }
+}
+
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    counter: u64,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            info: None,
+            segment: gst::FormattedSegment::new(),
+            counter: 0,
+        }
+    }
+}
+
+struct QrOverlay {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl QrOverlay {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "qroverlay",
+                gst::DebugColorFlags::empty(),
+                "QR code overlay",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "QR Code Overlay",
+            "Filter/Editor/Video",
+            "Draws a QR code with a frame counter and timestamp into every frame",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = rgb::caps();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseTransform> for QrOverlay {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int("xpos", ..) => settings.xpos = value.get().unwrap(),
+            Property::Int("ypos", ..) => settings.ypos = value.get().unwrap(),
+            Property::UInt("module-size", ..) => settings.module_size = value.get().unwrap(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int("xpos", ..) => Ok(settings.xpos.to_value()),
+            Property::Int("ypos", ..) => Ok(settings.ypos.to_value()),
+            Property::UInt("module-size", ..) => Ok(settings.module_size.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for QrOverlay {}
+
+impl BaseTransformImpl<BaseTransform> for QrOverlay {
+    fn start(&self, _element: &BaseTransform) -> bool {
+        *self.state.lock().unwrap() = Default::default();
+
+        true
+    }
+
+    fn set_caps(&self, element: &BaseTransform, incaps: &gst::Caps, outcaps: &gst::Caps) -> bool {
+        if incaps != outcaps {
+            return false;
+        }
+
+        let info = match gst_video::VideoInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", incaps);
+        self.state.lock().unwrap().info = Some(info);
+
+        true
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        if let EventView::Segment(e) = event.view() {
+            let mut state = self.state.lock().unwrap();
+            state.segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                Ok(segment) => segment,
+                Err(_) => {
+                    gst_warning!(self.cat, obj: element, "Not a time segment");
+                    gst::FormattedSegment::new()
+                }
+            };
+        }
+
+        element.parent_sink_event(event)
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+
+        let (info, counter, running_time) = {
+            let mut state = self.state.lock().unwrap();
+            let info = match state.info {
+                None => return gst::FlowReturn::NotNegotiated,
+                Some(ref info) => info.clone(),
+            };
+            let counter = state.counter;
+            state.counter += 1;
+            let running_time = state.segment.to_running_time(buf.get_pts()).0;
+
+            (info, counter, running_time)
+        };
+
+        let time = match element.get_clock() {
+            Some(clock) => clock.get_time().0,
+            None => running_time,
+        };
+        let time = match time {
+            None => {
+                gst_debug!(self.cat, obj: element, "No time for frame {}", counter);
+                return gst::FlowReturn::Ok;
+            }
+            Some(time) => time,
+        };
+
+        let modules = match qr::encode(&qr::format_payload(counter, time)) {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to encode QR code"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(modules) => modules,
+        };
+
+        let mut map = match buf.map_writable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map,
+        };
+
+        gst_trace!(self.cat, obj: element, "Drawing frame {} at {}", counter, time);
+        qr::draw(
+            &info,
+            map.as_mut_slice(),
+            &modules,
+            settings.xpos,
+            settings.ypos,
+            settings.module_size as usize,
+        );
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct QrOverlayStatic;
+
+impl ImplTypeStatic<BaseTransform> for QrOverlayStatic {
+    fn get_name(&self) -> &str {
+        "QrOverlay"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        QrOverlay::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        QrOverlay::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let qroverlay_static = QrOverlayStatic;
+    register_type(qroverlay_static)
+}
@@ -0,0 +1,61 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Packed RGB formats supported by the elements of this plugin
+
+use gst;
+use gst_video;
+
+use std::i32;
+
+/// Bytes per pixel and the offsets of the R, G and B components
+pub fn layout(format: gst_video::VideoFormat) -> Option<(usize, [usize; 3])> {
+    use gst_video::VideoFormat::*;
+
+    match format {
+        Rgbx | Rgba => Some((4, [0, 1, 2])),
+        Bgrx | Bgra => Some((4, [2, 1, 0])),
+        Xrgb | Argb => Some((4, [1, 2, 3])),
+        Xbgr | Abgr => Some((4, [3, 2, 1])),
+        Rgb => Some((3, [0, 1, 2])),
+        Bgr => Some((3, [2, 1, 0])),
+        _ => None,
+    }
+}
+
+pub fn caps() -> gst::Caps {
+    gst::Caps::new_simple(
+        "video/x-raw",
+        &[
+            (
+                "format",
+                &gst::List::new(&[
+                    &gst_video::VideoFormat::Rgbx.to_string(),
+                    &gst_video::VideoFormat::Bgrx.to_string(),
+                    &gst_video::VideoFormat::Xrgb.to_string(),
+                    &gst_video::VideoFormat::Xbgr.to_string(),
+                    &gst_video::VideoFormat::Rgba.to_string(),
+                    &gst_video::VideoFormat::Bgra.to_string(),
+                    &gst_video::VideoFormat::Argb.to_string(),
+                    &gst_video::VideoFormat::Abgr.to_string(),
+                    &gst_video::VideoFormat::Rgb.to_string(),
+                    &gst_video::VideoFormat::Bgr.to_string(),
+                ]),
+            ),
+            ("width", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            ("height", &gst::IntRange::<i32>::new(1, i32::MAX)),
+            (
+                "framerate",
+                &gst::FractionRange::new(
+                    gst::Fraction::new(0, 1),
+                    gst::Fraction::new(i32::MAX, 1),
+                ),
+            ),
+        ],
+    )
+}
@@ -0,0 +1,515 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use glib::translate::*;
+use glib_ffi;
+use gst;
+use gst::prelude::*;
+use gst_ffi;
+use gst_video;
+use gst_video_ffi;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::{ptr, u64};
+use std::sync::{Mutex, Once, ONCE_INIT};
+
+use rgb;
+
+// Scene change detection:
+//
+// Every frame is compared with the previous one. The score of a frame is the difference between
+// the luma histograms of both frames, from 0.0 for identical histograms to 1.0 for completely
+// disjoint ones, which is robust against motion inside a scene. The mean absolute luma
+// difference of both frames is measured as well. A frame is a cut if its score is at least
+// threshold and the previous cut is at least min-interval ago.
+//
+// Every frame gets a GstSceneChangeMeta with the score, the mean difference and whether it is a
+// cut. The meta has no tags, so encoders copy it to the encoded frames. For cuts a "scenechange"
+// element message is posted:
+//
+// "scenechange, running-time=(guint64)..., score=(double)..., difference=(double)..."
+//
+// With force-keyframes a downstream force-key-unit event is sent before every cut, so that an
+// encoder after this element starts a new GOP there. Segmenting sinks like hlssink and dashsink
+// start their segments at keyframes and will then split at scene changes.
+
+const DEFAULT_THRESHOLD: f64 = 0.4;
+const DEFAULT_MIN_INTERVAL: u64 = gst::SECOND_VAL;
+const DEFAULT_FORCE_KEYFRAMES: bool = false;
+
+// Only every n-th pixel horizontally and vertically is used
+const SUBSAMPLE: usize = 4;
+const BINS: usize = 64;
+
+static PROPERTIES: [Property; 3] = [
+    Property::Double(
+        "threshold",
+        "Threshold",
+        "Minimum histogram difference of a cut",
+        (0.0, 1.0),
+        DEFAULT_THRESHOLD,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "min-interval",
+        "Minimum Interval",
+        "Minimum time between two cuts in nanoseconds",
+        (0, u64::MAX),
+        DEFAULT_MIN_INTERVAL,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "force-keyframes",
+        "Force Keyframes",
+        "Request a keyframe from downstream encoders at every cut",
+        DEFAULT_FORCE_KEYFRAMES,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    threshold: f64,
+    min_interval: u64,
+    force_keyframes: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            threshold: DEFAULT_THRESHOLD,
+            min_interval: DEFAULT_MIN_INTERVAL,
+            force_keyframes: DEFAULT_FORCE_KEYFRAMES,
+        }
+    }
+}
+
+#[repr(C)]
+struct SceneChangeMeta {
+    parent: gst_ffi::GstMeta,
+    score: f64,
+    difference: f64,
+    cut: glib_ffi::gboolean,
+}
+
+unsafe extern "C" fn scene_change_meta_init(
+    meta: *mut gst_ffi::GstMeta,
+    _params: glib_ffi::gpointer,
+    _buffer: *mut gst_ffi::GstBuffer,
+) -> glib_ffi::gboolean {
+    let meta = &mut *(meta as *mut SceneChangeMeta);
+    meta.score = 0.0;
+    meta.difference = 0.0;
+    meta.cut = glib_ffi::GFALSE;
+    glib_ffi::GTRUE
+}
+
+unsafe extern "C" fn scene_change_meta_transform(
+    dest: *mut gst_ffi::GstBuffer,
+    meta: *mut gst_ffi::GstMeta,
+    _buffer: *mut gst_ffi::GstBuffer,
+    _type_: glib_ffi::GQuark,
+    _data: glib_ffi::gpointer,
+) -> glib_ffi::gboolean {
+    // The values don't depend on the memory layout of the frame, so every transformation
+    // keeps them
+    let meta = &*(meta as *const SceneChangeMeta);
+    add_meta(
+        gst::BufferRef::from_mut_ptr(dest),
+        meta.score,
+        meta.difference,
+        from_glib(meta.cut),
+    );
+    glib_ffi::GTRUE
+}
+
+fn get_meta_info() -> *const gst_ffi::GstMetaInfo {
+    static ONCE: Once = ONCE_INIT;
+    static mut INFO: *const gst_ffi::GstMetaInfo = 0 as *const gst_ffi::GstMetaInfo;
+
+    ONCE.call_once(|| unsafe {
+        let mut tags = [ptr::null()];
+        let api = gst_ffi::gst_meta_api_type_register(
+            b"GstSceneChangeMetaAPI\0".as_ptr() as *const _,
+            tags.as_mut_ptr(),
+        );
+        INFO = gst_ffi::gst_meta_register(
+            api,
+            b"GstSceneChangeMeta\0".as_ptr() as *const _,
+            ::std::mem::size_of::<SceneChangeMeta>(),
+            Some(scene_change_meta_init),
+            None,
+            Some(scene_change_meta_transform),
+        );
+    });
+
+    unsafe { INFO }
+}
+
+fn add_meta(buffer: &mut gst::BufferRef, score: f64, difference: f64, cut: bool) {
+    unsafe {
+        let meta = gst_ffi::gst_buffer_add_meta(
+            buffer.as_mut_ptr(),
+            get_meta_info(),
+            ptr::null_mut(),
+        ) as *mut SceneChangeMeta;
+        (*meta).score = score;
+        (*meta).difference = difference;
+        (*meta).cut = cut.to_glib();
+    }
+}
+
+// Subsampled luma of a frame
+fn luma(info: &gst_video::VideoInfo, data: &[u8]) -> Vec<u8> {
+    let (bpp, offsets) = rgb::layout(info.format()).unwrap();
+    let stride = info.stride()[0] as usize;
+    let (width, height) = (info.width() as usize, info.height() as usize);
+
+    let mut luma = Vec::with_capacity((width / SUBSAMPLE + 1) * (height / SUBSAMPLE + 1));
+    for row in (0..height).filter(|row| row % SUBSAMPLE == 0) {
+        let line = &data[(row * stride)..];
+        for col in (0..width).filter(|col| col % SUBSAMPLE == 0) {
+            let pixel = &line[(col * bpp)..];
+            let (r, g, b) = (
+                u32::from(pixel[offsets[0]]),
+                u32::from(pixel[offsets[1]]),
+                u32::from(pixel[offsets[2]]),
+            );
+            // BT.601 weights
+            luma.push(((77 * r + 150 * g + 29 * b) >> 8) as u8);
+        }
+    }
+
+    luma
+}
+
+// Histogram difference and mean absolute difference of two frames, both between 0.0 and 1.0
+fn compare(previous: &[u8], current: &[u8]) -> (f64, f64) {
+    assert_eq!(previous.len(), current.len());
+    if current.is_empty() {
+        return (0.0, 0.0);
+    }
+
+    let mut histograms = [[0i64; BINS]; 2];
+    let mut sum = 0u64;
+    for (&p, &c) in previous.iter().zip(current.iter()) {
+        histograms[0][p as usize * BINS / 256] += 1;
+        histograms[1][c as usize * BINS / 256] += 1;
+        sum += (i32::from(p) - i32::from(c)).abs() as u64;
+    }
+
+    let n = current.len() as f64;
+    let histogram_difference = histograms[0]
+        .iter()
+        .zip(histograms[1].iter())
+        .map(|(a, b)| (a - b).abs())
+        .sum::<i64>() as f64 / (2.0 * n);
+
+    (histogram_difference, sum as f64 / (255.0 * n))
+}
+
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    previous: Option<Vec<u8>>,
+    last_cut: Option<u64>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            info: None,
+            segment: gst::FormattedSegment::new(),
+            previous: None,
+            last_cut: None,
+        }
+    }
+}
+
+struct SceneChange {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl SceneChange {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "scenechange",
+                gst::DebugColorFlags::empty(),
+                "Scene change detection",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Scene Change Detection",
+            "Filter/Analyzer/Video",
+            "Detects cuts between scenes of a video",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = rgb::caps();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        // The meta API has to be registered before the first buffer is copied
+        get_meta_info();
+
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseTransform> for SceneChange {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Double("threshold", ..) => settings.threshold = value.get().unwrap(),
+            Property::UInt64("min-interval", ..) => settings.min_interval = value.get().unwrap(),
+            Property::Boolean("force-keyframes", ..) => {
+                settings.force_keyframes = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Double("threshold", ..) => Ok(settings.threshold.to_value()),
+            Property::UInt64("min-interval", ..) => Ok(settings.min_interval.to_value()),
+            Property::Boolean("force-keyframes", ..) => Ok(settings.force_keyframes.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for SceneChange {}
+
+impl BaseTransformImpl<BaseTransform> for SceneChange {
+    fn start(&self, _element: &BaseTransform) -> bool {
+        *self.state.lock().unwrap() = Default::default();
+
+        true
+    }
+
+    fn set_caps(&self, element: &BaseTransform, incaps: &gst::Caps, outcaps: &gst::Caps) -> bool {
+        if incaps != outcaps {
+            return false;
+        }
+
+        let info = match gst_video::VideoInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", incaps);
+
+        let mut state = self.state.lock().unwrap();
+        state.info = Some(info);
+        // Frames of different sizes can't be compared
+        state.previous = None;
+
+        true
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let mut state = self.state.lock().unwrap();
+                state.segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Ok(segment) => segment,
+                    Err(_) => {
+                        gst_warning!(self.cat, obj: element, "Not a time segment");
+                        gst::FormattedSegment::new()
+                    }
+                };
+            }
+            EventView::FlushStop(..) => {
+                let mut state = self.state.lock().unwrap();
+                state.previous = None;
+                state.last_cut = None;
+            }
+            _ => (),
+        }
+
+        element.parent_sink_event(event)
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+
+        let (score, difference, cut, running_time, stream_time) = {
+            let mut state = self.state.lock().unwrap();
+            let info = match state.info {
+                None => return gst::FlowReturn::NotNegotiated,
+                Some(ref info) => info.clone(),
+            };
+
+            let current = {
+                let map = match buf.map_readable() {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Failed,
+                            ["Failed to map buffer"]
+                        );
+                        return gst::FlowReturn::Error;
+                    }
+                    Some(map) => map,
+                };
+                luma(&info, map.as_slice())
+            };
+
+            let (score, difference) = state
+                .previous
+                .as_ref()
+                .map(|previous| compare(previous, &current))
+                .unwrap_or((0.0, 0.0));
+            state.previous = Some(current);
+
+            let running_time = state.segment.to_running_time(buf.get_pts()).0;
+            let stream_time = state.segment.to_stream_time(buf.get_pts()).0;
+
+            let cut = score >= settings.threshold
+                && match (state.last_cut, running_time) {
+                    (Some(last_cut), Some(running_time)) => {
+                        running_time >= last_cut + settings.min_interval
+                    }
+                    _ => true,
+                };
+            if cut {
+                state.last_cut = running_time;
+            }
+
+            (score, difference, cut, running_time, stream_time)
+        };
+
+        gst_trace!(
+            self.cat,
+            obj: element,
+            "Score {} difference {} at {:?}",
+            score,
+            difference,
+            running_time
+        );
+
+        add_meta(buf, score, difference, cut);
+
+        if !cut {
+            return gst::FlowReturn::Ok;
+        }
+
+        gst_debug!(self.cat, obj: element, "Cut at {:?}", running_time);
+
+        let s = gst::Structure::new(
+            "scenechange",
+            &[
+                ("running-time", &running_time.unwrap_or(0)),
+                ("score", &score),
+                ("difference", &difference),
+            ],
+        );
+        let _ = element.post_message(&gst::Message::new_element(s).src(Some(element)).build());
+
+        if settings.force_keyframes {
+            let event: gst::Event = unsafe {
+                from_glib_full(gst_video_ffi::gst_video_event_new_downstream_force_key_unit(
+                    buf.get_pts().to_glib(),
+                    gst::ClockTime(stream_time).to_glib(),
+                    gst::ClockTime(running_time).to_glib(),
+                    glib_ffi::GFALSE,
+                    0,
+                ))
+            };
+            element.get_static_pad("src").unwrap().push_event(event);
+        }
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct SceneChangeStatic;
+
+impl ImplTypeStatic<BaseTransform> for SceneChangeStatic {
+    fn get_name(&self) -> &str {
+        "SceneChange"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        SceneChange::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        SceneChange::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let scenechange_static = SceneChangeStatic;
+    register_type(scenechange_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let dark = vec![16u8; 100];
+        let bright = vec![235u8; 100];
+        assert_eq!(compare(&dark, &dark), (0.0, 0.0));
+
+        let (score, difference) = compare(&dark, &bright);
+        assert_eq!(score, 1.0);
+        assert!((difference - 219.0 / 255.0).abs() < 1e-9);
+
+        // Motion: same histogram, different pixels
+        let gradient = (0..100).map(|i| i as u8 * 2).collect::<Vec<_>>();
+        let mut moved = gradient.clone();
+        moved.reverse();
+        let (score, difference) = compare(&gradient, &moved);
+        assert_eq!(score, 0.0);
+        assert!(difference > 0.2);
+    }
+}
@@ -0,0 +1,735 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+use rgb;
+
+// Stereo behaviour:
+//
+// The "left" pad drives the output, every frame on it is combined with the frame of the "right"
+// pad that is closest in running time at or before it. The left stream waits until the right
+// stream has caught up, the right stream waits when it is too far ahead. Once the right stream
+// is EOS its last frame is reused.
+//
+// Both inputs need the same format and size. stereopack outputs both views in one frame with
+// the multiview-mode set accordingly, anaglyph outputs a red-cyan anaglyph with the red
+// channel from the left view and green and blue from the right view.
+
+// Maximum number of frames queued on the right pad
+const MAX_QUEUED: usize = 8;
+
+const DEFAULT_LAYOUT: &str = "side-by-side";
+
+static PROPERTIES: [Property; 1] = [Property::String(
+    "layout",
+    "Layout",
+    "Frame packing of the two views (side-by-side, top-bottom)",
+    Some(DEFAULT_LAYOUT),
+    PropertyMutability::ReadWrite,
+)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    SideBySide,
+    TopBottom,
+    Anaglyph,
+}
+
+impl Layout {
+    fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "side-by-side" => Some(Layout::SideBySide),
+            "top-bottom" => Some(Layout::TopBottom),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Layout::SideBySide => "side-by-side",
+            Layout::TopBottom => "top-bottom",
+            Layout::Anaglyph => "anaglyph",
+        }
+    }
+
+    // Output size for views of the given size
+    fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match *self {
+            Layout::SideBySide => (2 * width, height),
+            Layout::TopBottom => (width, 2 * height),
+            Layout::Anaglyph => (width, height),
+        }
+    }
+}
+
+// Geometry of the views, all with the same packed format
+#[derive(Debug, Clone, Copy)]
+struct Geometry {
+    width: usize,
+    height: usize,
+    bpp: usize,
+    // Offsets of the R, G and B components
+    offsets: [usize; 3],
+}
+
+// Combines both views into `out`, all strides are in bytes
+fn combine(
+    layout: Layout,
+    geometry: &Geometry,
+    left: (&[u8], usize),
+    right: (&[u8], usize),
+    out: (&mut [u8], usize),
+) {
+    let (left, left_stride) = left;
+    let (right, right_stride) = right;
+    let (out, out_stride) = out;
+    let row_len = geometry.width * geometry.bpp;
+
+    for row in 0..geometry.height {
+        let l = &left[(row * left_stride)..(row * left_stride + row_len)];
+        let r = &right[(row * right_stride)..(row * right_stride + row_len)];
+
+        match layout {
+            Layout::SideBySide => {
+                let o = &mut out[(row * out_stride)..(row * out_stride + 2 * row_len)];
+                o[..row_len].copy_from_slice(l);
+                o[row_len..].copy_from_slice(r);
+            }
+            Layout::TopBottom => {
+                let offset = row * out_stride;
+                out[offset..(offset + row_len)].copy_from_slice(l);
+                let offset = (geometry.height + row) * out_stride;
+                out[offset..(offset + row_len)].copy_from_slice(r);
+            }
+            Layout::Anaglyph => {
+                let o = &mut out[(row * out_stride)..(row * out_stride + row_len)];
+                o.copy_from_slice(r);
+                let red = geometry.offsets[0];
+                for (o, l) in o.chunks_mut(geometry.bpp).zip(l.chunks(geometry.bpp)) {
+                    o[red] = l[red];
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Settings {
+    layout: Layout,
+}
+
+struct State {
+    left_info: Option<gst_video::VideoInfo>,
+    right_info: Option<gst_video::VideoInfo>,
+    out_info: Option<gst_video::VideoInfo>,
+    right_segment: gst::FormattedSegment<gst::ClockTime>,
+    left_segment: gst::FormattedSegment<gst::ClockTime>,
+    // Running time and frame, oldest first
+    right_queue: VecDeque<(u64, gst::Buffer)>,
+    right_eos: bool,
+    flushing: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            left_info: None,
+            right_info: None,
+            out_info: None,
+            right_segment: gst::FormattedSegment::new(),
+            left_segment: gst::FormattedSegment::new(),
+            right_queue: VecDeque::new(),
+            right_eos: false,
+            flushing: false,
+        }
+    }
+}
+
+struct StereoPack {
+    cat: gst::DebugCategory,
+    left_pad: gst::Pad,
+    srcpad: gst::Pad,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+impl StereoPack {
+    fn new(_element: &Element, left_pad: gst::Pad, srcpad: gst::Pad, layout: Layout) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "stereopack",
+                gst::DebugColorFlags::empty(),
+                "Stereo video packing",
+            ),
+            left_pad: left_pad,
+            srcpad: srcpad,
+            settings: Mutex::new(Settings { layout: layout }),
+            state: Mutex::new(State::default()),
+            cond: Condvar::new(),
+        }
+    }
+
+    fn class_init(klass: &mut ElementClass, anaglyph: bool) {
+        if anaglyph {
+            klass.set_metadata(
+                "Anaglyph",
+                "Filter/Effect/Video",
+                "Combines a left and a right view into a red-cyan anaglyph",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            );
+        } else {
+            klass.set_metadata(
+                "Stereo Pack",
+                "Filter/Video",
+                "Packs a left and a right view into one frame",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            );
+        }
+
+        let caps = rgb::caps();
+
+        let left_pad_template = gst::PadTemplate::new(
+            "left",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(left_pad_template);
+
+        let right_pad_template = gst::PadTemplate::new(
+            "right",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(right_pad_template);
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        if !anaglyph {
+            klass.install_properties(&PROPERTIES);
+        }
+    }
+
+    fn init(element: &Element, layout: Layout) -> Box<ElementImpl<Element>> {
+        let templ = element.get_pad_template("left").unwrap();
+        let left_pad = gst::Pad::new_from_template(&templ, "left");
+        let templ = element.get_pad_template("right").unwrap();
+        let right_pad = gst::Pad::new_from_template(&templ, "right");
+        let templ = element.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::new_from_template(&templ, "src");
+
+        left_pad.set_chain_function(|pad, parent, buffer| {
+            StereoPack::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |stereo, element| stereo.left_chain(pad, element, buffer),
+            )
+        });
+        left_pad.set_event_function(|pad, parent, event| {
+            StereoPack::catch_panic_pad_function(
+                parent,
+                || false,
+                |stereo, element| stereo.left_event(pad, element, event),
+            )
+        });
+
+        right_pad.set_chain_function(|pad, parent, buffer| {
+            StereoPack::catch_panic_pad_function(
+                parent,
+                || gst::FlowReturn::Error,
+                |stereo, element| stereo.right_chain(pad, element, buffer),
+            )
+        });
+        right_pad.set_event_function(|pad, parent, event| {
+            StereoPack::catch_panic_pad_function(
+                parent,
+                || false,
+                |stereo, element| stereo.right_event(pad, element, event),
+            )
+        });
+
+        srcpad.set_event_function(|pad, parent, event| {
+            StereoPack::catch_panic_pad_function(
+                parent,
+                || false,
+                |stereo, element| stereo.src_event(pad, element, event),
+            )
+        });
+
+        element.add_pad(&left_pad).unwrap();
+        element.add_pad(&right_pad).unwrap();
+        element.add_pad(&srcpad).unwrap();
+
+        let imp = Self::new(element, left_pad, srcpad, layout);
+        Box::new(imp)
+    }
+
+    fn catch_panic_pad_function<T, F: FnOnce(&Self, &Element) -> T, G: FnOnce() -> T>(
+        parent: &Option<gst::Object>,
+        fallback: G,
+        f: F,
+    ) -> T {
+        let element = parent
+            .as_ref()
+            .cloned()
+            .unwrap()
+            .downcast::<Element>()
+            .unwrap();
+        let stereo = element.get_impl().downcast_ref::<StereoPack>().unwrap();
+        element.catch_panic(fallback, |element| f(stereo, element))
+    }
+
+    fn running_time(
+        &self,
+        element: &Element,
+        segment: &gst::FormattedSegment<gst::ClockTime>,
+        buffer: &gst::Buffer,
+    ) -> Option<u64> {
+        let running_time = segment.to_running_time(buffer.get_pts()).0;
+        if running_time.is_none() {
+            gst_element_error!(element, gst::StreamError::Format, ["Buffer without PTS"]);
+        }
+        running_time
+    }
+
+    fn left_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let layout = self.settings.lock().unwrap().layout;
+
+        let mut state = self.state.lock().unwrap();
+        let running_time = match self.running_time(element, &state.left_segment, &buffer) {
+            None => return gst::FlowReturn::Error,
+            Some(running_time) => running_time,
+        };
+
+        // Wait until the right stream has a frame at or after this one
+        loop {
+            if state.flushing {
+                return gst::FlowReturn::Flushing;
+            }
+
+            // Keep only the newest frame at or before the running time
+            while state.right_queue.len() > 1 && state.right_queue[1].0 <= running_time {
+                state.right_queue.pop_front();
+                self.cond.notify_all();
+            }
+
+            let caught_up = state
+                .right_queue
+                .back()
+                .map(|&(time, _)| time >= running_time)
+                .unwrap_or(false);
+            if caught_up || state.right_eos || state.right_queue.len() >= MAX_QUEUED {
+                break;
+            }
+
+            gst_trace!(self.cat, obj: pad, "Waiting for right view at {}", running_time);
+            state = self.cond.wait(state).unwrap();
+        }
+
+        let right = match state.right_queue.front() {
+            None => {
+                gst_debug!(self.cat, obj: pad, "No right view, dropping frame");
+                return gst::FlowReturn::Ok;
+            }
+            Some(&(_, ref right)) => right.clone(),
+        };
+
+        let (left_info, right_info, out_info) = match (
+            state.left_info.clone(),
+            state.right_info.clone(),
+            state.out_info.clone(),
+        ) {
+            (Some(left_info), Some(right_info), Some(out_info)) => {
+                (left_info, right_info, out_info)
+            }
+            _ => {
+                gst_element_error!(element, gst::CoreError::Negotiation, ["Have no caps"]);
+                return gst::FlowReturn::NotNegotiated;
+            }
+        };
+        drop(state);
+
+        if left_info.format() != right_info.format() || left_info.width() != right_info.width()
+            || left_info.height() != right_info.height()
+        {
+            gst_element_error!(
+                element,
+                gst::CoreError::Negotiation,
+                ["Left and right view have different formats"]
+            );
+            return gst::FlowReturn::NotNegotiated;
+        }
+
+        let (bpp, offsets) = rgb::layout(left_info.format()).unwrap();
+        let geometry = Geometry {
+            width: left_info.width() as usize,
+            height: left_info.height() as usize,
+            bpp: bpp,
+            offsets: offsets,
+        };
+
+        let out_stride = out_info.stride()[0] as usize;
+        let mut data = vec![0; out_stride * out_info.height() as usize];
+        {
+            let (left_map, right_map) = match (buffer.map_readable(), right.map_readable()) {
+                (Some(left_map), Some(right_map)) => (left_map, right_map),
+                _ => {
+                    gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                    return gst::FlowReturn::Error;
+                }
+            };
+
+            combine(
+                layout,
+                &geometry,
+                (left_map.as_slice(), left_info.stride()[0] as usize),
+                (right_map.as_slice(), right_info.stride()[0] as usize),
+                (&mut data, out_stride),
+            );
+        }
+
+        let mut outbuf = gst::Buffer::from_mut_slice(data).unwrap();
+        {
+            let outbuf = outbuf.get_mut().unwrap();
+            outbuf.set_pts(buffer.get_pts());
+            outbuf.set_dts(buffer.get_dts());
+            outbuf.set_duration(buffer.get_duration());
+            outbuf.set_flags(buffer.get_flags());
+        }
+
+        gst_log!(self.cat, obj: pad, "Pushing buffer {:?}", outbuf);
+        self.srcpad.push(outbuf)
+    }
+
+    fn right_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &Element,
+        buffer: gst::Buffer,
+    ) -> gst::FlowReturn {
+        let mut state = self.state.lock().unwrap();
+        let running_time = match self.running_time(element, &state.right_segment, &buffer) {
+            None => return gst::FlowReturn::Error,
+            Some(running_time) => running_time,
+        };
+
+        while state.right_queue.len() >= MAX_QUEUED && !state.flushing {
+            gst_trace!(self.cat, obj: pad, "Waiting for left view");
+            state = self.cond.wait(state).unwrap();
+        }
+        if state.flushing {
+            return gst::FlowReturn::Flushing;
+        }
+
+        state.right_queue.push_back((running_time, buffer));
+        self.cond.notify_all();
+
+        gst::FlowReturn::Ok
+    }
+
+    fn set_flushing(&self, flushing: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.flushing = flushing;
+        if !flushing {
+            state.right_queue.clear();
+            state.right_eos = false;
+        }
+        self.cond.notify_all();
+    }
+
+    fn left_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let info = match gst_video::VideoInfo::from_caps(e.get_caps()) {
+                    None => return false,
+                    Some(info) => info,
+                };
+
+                let layout = self.settings.lock().unwrap().layout;
+                let (width, height) = layout.output_size(info.width(), info.height());
+
+                let mut caps = e.get_caps().to_owned();
+                {
+                    let s = caps.get_mut().unwrap().get_mut_structure(0).unwrap();
+                    s.set("width", &(width as i32));
+                    s.set("height", &(height as i32));
+                    if layout != Layout::Anaglyph {
+                        s.set("multiview-mode", &layout.name());
+                    }
+                }
+
+                let out_info = match gst_video::VideoInfo::from_caps(&caps) {
+                    None => {
+                        gst_element_error!(
+                            element,
+                            gst::CoreError::Negotiation,
+                            ["Invalid output caps {:?}", caps]
+                        );
+                        return false;
+                    }
+                    Some(out_info) => out_info,
+                };
+
+                {
+                    let mut state = self.state.lock().unwrap();
+                    state.left_info = Some(info);
+                    state.out_info = Some(out_info);
+                }
+
+                gst_debug!(self.cat, obj: pad, "Setting caps {:?}", caps);
+                self.srcpad.push_event(gst::Event::new_caps(&caps).build())
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                self.state.lock().unwrap().left_segment = segment;
+                self.srcpad.push_event(event)
+            }
+            EventView::FlushStart(..) => {
+                self.set_flushing(true);
+                self.srcpad.push_event(event)
+            }
+            EventView::FlushStop(..) => {
+                self.set_flushing(false);
+                self.srcpad.push_event(event)
+            }
+            _ => self.srcpad.push_event(event),
+        }
+    }
+
+    // Events of the right view are not forwarded
+    fn right_event(&self, pad: &gst::Pad, element: &Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(self.cat, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let info = match gst_video::VideoInfo::from_caps(e.get_caps()) {
+                    None => return false,
+                    Some(info) => info,
+                };
+
+                self.state.lock().unwrap().right_info = Some(info);
+            }
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(_) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            ["Only Time segments supported"]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                self.state.lock().unwrap().right_segment = segment;
+            }
+            EventView::Eos(..) => {
+                self.state.lock().unwrap().right_eos = true;
+                self.cond.notify_all();
+            }
+            EventView::FlushStart(..) => {
+                self.set_flushing(true);
+            }
+            EventView::FlushStop(..) => {
+                self.set_flushing(false);
+            }
+            _ => (),
+        }
+
+        true
+    }
+
+    fn src_event(&self, pad: &gst::Pad, _element: &Element, event: gst::Event) -> bool {
+        gst_log!(self.cat, obj: pad, "Forwarding event {:?}", event);
+        self.left_pad.push_event(event)
+    }
+}
+
+impl ObjectImpl<Element> for StereoPack {
+    fn set_property(&self, obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let element = obj.clone().downcast::<Element>().unwrap();
+
+        match *prop {
+            Property::String("layout", ..) => {
+                let name = value.get::<String>().unwrap_or_else(|| DEFAULT_LAYOUT.into());
+                match Layout::from_name(&name) {
+                    None => {
+                        gst_warning!(self.cat, obj: &element, "Unknown layout '{}'", name);
+                    }
+                    Some(layout) => {
+                        self.settings.lock().unwrap().layout = layout;
+                    }
+                }
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("layout", ..) => Ok(settings.layout.name().to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<Element> for StereoPack {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: gst::StateChange,
+    ) -> gst::StateChangeReturn {
+        gst_trace!(self.cat, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                *self.state.lock().unwrap() = State::default();
+            }
+            gst::StateChange::PausedToReady => {
+                // Wake up the streaming threads
+                self.set_flushing(true);
+            }
+            _ => (),
+        }
+
+        element.parent_change_state(transition)
+    }
+}
+
+struct StereoPackStatic {
+    anaglyph: bool,
+}
+
+impl ImplTypeStatic<Element> for StereoPackStatic {
+    fn get_name(&self) -> &str {
+        if self.anaglyph {
+            "Anaglyph"
+        } else {
+            "StereoPack"
+        }
+    }
+
+    fn new(&self, element: &Element) -> Box<ElementImpl<Element>> {
+        let layout = if self.anaglyph {
+            Layout::Anaglyph
+        } else {
+            Layout::from_name(DEFAULT_LAYOUT).unwrap()
+        };
+        StereoPack::init(element, layout)
+    }
+
+    fn class_init(&self, klass: &mut ElementClass) {
+        StereoPack::class_init(klass, self.anaglyph);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let stereopack_static = StereoPackStatic { anaglyph: false };
+    register_type(stereopack_static)
+}
+
+pub fn get_anaglyph_type() -> glib::Type {
+    let anaglyph_static = StereoPackStatic { anaglyph: true };
+    register_type(anaglyph_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combine() {
+        // 2x2 RGB views with a stride of 8 bytes
+        let geometry = Geometry {
+            width: 2,
+            height: 2,
+            bpp: 3,
+            offsets: [0, 1, 2],
+        };
+        let left = (0..16).collect::<Vec<u8>>();
+        let right = (100..116).collect::<Vec<u8>>();
+
+        let mut out = vec![0; 12 * 2];
+        combine(
+            Layout::SideBySide,
+            &geometry,
+            (&left, 8),
+            (&right, 8),
+            (&mut out, 12),
+        );
+        assert_eq!(&out[..12], &[0, 1, 2, 3, 4, 5, 100, 101, 102, 103, 104, 105]);
+        assert_eq!(&out[12..18], &[8, 9, 10, 11, 12, 13]);
+
+        let mut out = vec![0; 8 * 4];
+        combine(
+            Layout::TopBottom,
+            &geometry,
+            (&left, 8),
+            (&right, 8),
+            (&mut out, 8),
+        );
+        assert_eq!(&out[8..14], &[8, 9, 10, 11, 12, 13]);
+        assert_eq!(&out[16..22], &[100, 101, 102, 103, 104, 105]);
+
+        let mut out = vec![0; 8 * 2];
+        combine(
+            Layout::Anaglyph,
+            &geometry,
+            (&left, 8),
+            (&right, 8),
+            (&mut out, 8),
+        );
+        assert_eq!(&out[..6], &[0, 101, 102, 3, 104, 105]);
+    }
+}
@@ -0,0 +1,288 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use std::ptr;
+
+use gst;
+use gst_ffi;
+use gst_video_ffi;
+
+// SMPTE time codes and their GstVideoTimeCodeMeta on buffers. Drop-frame time codes skip the
+// first frame numbers of every minute except every tenth minute, 2 frames at 29.97 fps and 4 at
+// 59.94 fps.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeCode {
+    pub hours: u32,
+    pub minutes: u32,
+    pub seconds: u32,
+    pub frames: u32,
+    pub drop_frame: bool,
+}
+
+impl TimeCode {
+    pub fn parse(s: &str) -> Result<TimeCode, String> {
+        let s = s.trim();
+        if s.len() != 11 || !s.is_ascii() {
+            return Err(format!("Invalid time code {:?}", s));
+        }
+
+        let drop_frame = match &s[8..9] {
+            ";" | "." => true,
+            ":" => false,
+            _ => return Err(format!("Invalid time code {:?}", s)),
+        };
+
+        let mut values = [0u32; 4];
+        for (i, value) in values.iter_mut().enumerate() {
+            let part = &s[(3 * i)..(3 * i + 2)];
+            *value = part
+                .parse::<u32>()
+                .map_err(|_| format!("Invalid time code {:?}", s))?;
+        }
+
+        Ok(TimeCode {
+            hours: values[0],
+            minutes: values[1],
+            seconds: values[2],
+            frames: values[3],
+            drop_frame: drop_frame,
+        })
+    }
+
+    // Whether this is a time code that can occur at the nominal integer frame rate
+    pub fn is_valid(&self, fps: u32) -> bool {
+        if self.hours >= 24 || self.minutes >= 60 || self.seconds >= 60 || self.frames >= fps {
+            return false;
+        }
+
+        if self.drop_frame {
+            if fps % 30 != 0 {
+                return false;
+            }
+            if self.seconds == 0 && self.minutes % 10 != 0 && self.frames < fps / 15 {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    // Number of the frame since 00:00:00:00, fps is the nominal integer frame rate
+    pub fn to_frames(&self, fps: u32) -> u64 {
+        let fps = fps as u64;
+        let total_minutes = 60 * self.hours as u64 + self.minutes as u64;
+        let frames = (60 * total_minutes + self.seconds as u64) * fps + self.frames as u64;
+
+        if self.drop_frame {
+            frames - (fps / 15) * (total_minutes - total_minutes / 10)
+        } else {
+            frames
+        }
+    }
+
+    pub fn from_frames(frames: u64, fps: u32, drop_frame: bool) -> TimeCode {
+        let fps = fps as u64;
+        let mut frames = frames;
+
+        if drop_frame {
+            let dropped = fps / 15;
+            let frames_per_minute = 60 * fps - dropped;
+            let frames_per_10_minutes = 600 * fps - 9 * dropped;
+
+            let tens = frames / frames_per_10_minutes;
+            let rem = frames % frames_per_10_minutes;
+            frames += 9 * dropped * tens;
+            if rem >= dropped {
+                frames += dropped * ((rem - dropped) / frames_per_minute);
+            }
+        }
+
+        TimeCode {
+            hours: (frames / (3600 * fps)) as u32,
+            minutes: ((frames / (60 * fps)) % 60) as u32,
+            seconds: ((frames / fps) % 60) as u32,
+            frames: (frames % fps) as u32,
+            drop_frame: drop_frame,
+        }
+    }
+
+    // The time code `frames` frames later, wrapping around after 24 hours
+    pub fn add_frames(&self, frames: u64, fps: u32) -> TimeCode {
+        let per_day = frames_per_day(fps, self.drop_frame);
+        TimeCode::from_frames((self.to_frames(fps) + frames) % per_day, fps, self.drop_frame)
+    }
+}
+
+impl fmt::Display for TimeCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{}{:02}",
+            self.hours,
+            self.minutes,
+            self.seconds,
+            if self.drop_frame { ';' } else { ':' },
+            self.frames
+        )
+    }
+}
+
+pub fn frames_per_day(fps: u32, drop_frame: bool) -> u64 {
+    TimeCode {
+        hours: 24,
+        minutes: 0,
+        seconds: 0,
+        frames: 0,
+        drop_frame: drop_frame,
+    }.to_frames(fps)
+}
+
+// Integer frame rate the time code counts in, e.g. 30 for 30000/1001
+pub fn nominal_fps(fps: gst::Fraction) -> u32 {
+    let (numer, denom) = (*fps.numer() as u32, *fps.denom() as u32);
+    (numer + denom / 2) / denom
+}
+
+// Drop-frame time codes are only defined for the NTSC frame rates
+pub fn is_drop_frame_rate(fps: gst::Fraction) -> bool {
+    *fps.denom() == 1001 && (*fps.numer() == 30_000 || *fps.numer() == 60_000)
+}
+
+// Conversion between running time and frame numbers for the actual frame rate, rounded to the
+// nearest frame
+pub fn time_to_frames(time: u64, fps: gst::Fraction) -> u64 {
+    let (numer, denom) = (*fps.numer() as u64, *fps.denom() as u64);
+    let unit = denom * gst::SECOND_VAL;
+    time / unit * numer + ((time % unit) * numer + unit / 2) / unit
+}
+
+// Time code of the first GstVideoTimeCodeMeta of the buffer and its frame rate
+pub fn get_buffer_timecode(buffer: &gst::BufferRef) -> Option<(TimeCode, gst::Fraction)> {
+    unsafe {
+        let meta = gst_ffi::gst_buffer_get_meta(
+            buffer.as_ptr() as *mut gst_ffi::GstBuffer,
+            gst_video_ffi::gst_video_time_code_meta_api_get_type(),
+        ) as *const gst_video_ffi::GstVideoTimeCodeMeta;
+        if meta.is_null() {
+            return None;
+        }
+
+        let tc = &(*meta).tc;
+        if tc.config.fps_n == 0 || tc.config.fps_d == 0 {
+            return None;
+        }
+        let drop_frame = tc.config.flags & gst_video_ffi::GST_VIDEO_TIME_CODE_FLAGS_DROP_FRAME != 0;
+
+        Some((
+            TimeCode {
+                hours: tc.hours,
+                minutes: tc.minutes,
+                seconds: tc.seconds,
+                frames: tc.frames,
+                drop_frame: drop_frame,
+            },
+            gst::Fraction::new(tc.config.fps_n as i32, tc.config.fps_d as i32),
+        ))
+    }
+}
+
+// Replaces all GstVideoTimeCodeMeta of the buffer with one for `timecode`
+pub fn set_buffer_timecode(buffer: &mut gst::BufferRef, timecode: &TimeCode, fps: gst::Fraction) {
+    unsafe {
+        let ptr = buffer.as_mut_ptr();
+        let api = gst_video_ffi::gst_video_time_code_meta_api_get_type();
+
+        loop {
+            let meta = gst_ffi::gst_buffer_get_meta(ptr, api);
+            if meta.is_null() {
+                break;
+            }
+            gst_ffi::gst_buffer_remove_meta(ptr, meta);
+        }
+
+        let flags = if timecode.drop_frame {
+            gst_video_ffi::GST_VIDEO_TIME_CODE_FLAGS_DROP_FRAME
+        } else {
+            gst_video_ffi::GST_VIDEO_TIME_CODE_FLAGS_NONE
+        };
+
+        gst_video_ffi::gst_video_buffer_add_video_time_code_meta_full(
+            ptr,
+            *fps.numer() as u32,
+            *fps.denom() as u32,
+            ptr::null_mut(),
+            flags,
+            timecode.hours,
+            timecode.minutes,
+            timecode.seconds,
+            timecode.frames,
+            0,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_frame() {
+        let tc = TimeCode::parse("00:01:00;02").unwrap();
+        assert!(tc.drop_frame);
+        assert!(tc.is_valid(30));
+        assert_eq!(tc.to_frames(30), 1800);
+        assert_eq!(TimeCode::from_frames(1800, 30, true), tc);
+        assert_eq!(
+            TimeCode::from_frames(1799, 30, true).to_string(),
+            "00:00:59;29"
+        );
+        assert_eq!(
+            TimeCode::from_frames(17982, 30, true).to_string(),
+            "00:10:00;00"
+        );
+
+        for frames in 0..40000 {
+            let tc = TimeCode::from_frames(frames, 30, true);
+            assert!(tc.is_valid(30));
+            assert_eq!(tc.to_frames(30), frames);
+            assert_eq!(TimeCode::from_frames(frames, 60, true).to_frames(60), frames);
+        }
+
+        // Dropped frame numbers
+        assert!(!TimeCode::parse("00:01:00;00").unwrap().is_valid(30));
+        assert!(!TimeCode::parse("00:01:00;03").unwrap().is_valid(60));
+        assert!(TimeCode::parse("00:10:00;00").unwrap().is_valid(30));
+    }
+
+    #[test]
+    fn test_add_frames() {
+        let tc = TimeCode::parse("00:00:59;29").unwrap();
+        assert_eq!(tc.add_frames(1, 30).to_string(), "00:01:00;02");
+
+        let tc = TimeCode::parse("23:59:59:24").unwrap();
+        assert!(tc.is_valid(25));
+        assert!(!tc.is_valid(24));
+        assert_eq!(tc.add_frames(2, 25).to_string(), "00:00:00:01");
+    }
+
+    #[test]
+    fn test_rates() {
+        assert_eq!(nominal_fps(gst::Fraction::new(30_000, 1001)), 30);
+        assert_eq!(nominal_fps(gst::Fraction::new(25, 1)), 25);
+        assert_eq!(nominal_fps(gst::Fraction::new(24_000, 1001)), 24);
+        assert!(is_drop_frame_rate(gst::Fraction::new(60_000, 1001)));
+        assert!(!is_drop_frame_rate(gst::Fraction::new(24_000, 1001)));
+
+        let fps = gst::Fraction::new(30_000, 1001);
+        assert_eq!(time_to_frames(0, fps), 0);
+        assert_eq!(time_to_frames(1_001_000_000, fps), 30);
+        assert_eq!(time_to_frames(33_366_666, fps), 1);
+    }
+}
@@ -0,0 +1,415 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::u64;
+use std::sync::Mutex;
+
+use timecode::{self, TimeCode};
+
+// Time code validation for ingest QC:
+//
+// Every frame is expected to carry the time code following the one of the previous frame. For
+// each frame where this is not the case a "timecodecheck" element message is posted:
+//
+// "timecodecheck, running-time=(guint64)..., reason=(string)..., timecode=(string)...,
+//  expected=(string)..."
+//
+// with reason "discontinuity" for a valid but unexpected time code, "invalid" for a time code
+// that can't occur at the frame rate (e.g. a dropped frame number) and "missing" for a frame
+// without time code. timecode is empty for missing time codes and expected is empty for the
+// first frame.
+//
+// With repair enabled the time code of such frames is replaced with the expected one, so that
+// the output has a continuous sequence. Otherwise checking continues from the new time code.
+
+const DEFAULT_REPAIR: bool = false;
+
+static PROPERTIES: [Property; 2] = [
+    Property::Boolean(
+        "repair",
+        "Repair",
+        "Replace discontinuous, invalid and missing time codes with the expected ones",
+        DEFAULT_REPAIR,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt64(
+        "errors",
+        "Errors",
+        "Number of frames with discontinuous, invalid or missing time code",
+        (0, u64::MAX),
+        0,
+        PropertyMutability::Readable,
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Error {
+    Discontinuity,
+    Invalid,
+    Missing,
+}
+
+impl Error {
+    fn to_str(&self) -> &'static str {
+        match *self {
+            Error::Discontinuity => "discontinuity",
+            Error::Invalid => "invalid",
+            Error::Missing => "missing",
+        }
+    }
+}
+
+// Tracks the sequence of time codes. Returns the error of the current frame, if any, and the
+// time code it should have.
+#[derive(Debug, Default)]
+struct Checker {
+    previous: Option<TimeCode>,
+}
+
+impl Checker {
+    fn check(
+        &mut self,
+        tc: Option<TimeCode>,
+        fps: u32,
+        repair: bool,
+    ) -> (Option<Error>, Option<TimeCode>) {
+        let expected = self.previous.map(|previous| previous.add_frames(1, fps));
+
+        let error = match tc {
+            None => Some(Error::Missing),
+            Some(tc) if !tc.is_valid(fps) => Some(Error::Invalid),
+            Some(tc) if expected.map(|expected| expected != tc).unwrap_or(false) => {
+                Some(Error::Discontinuity)
+            }
+            Some(_) => None,
+        };
+
+        // Without repairing, continue with the new time code if it can be counted from
+        let current = match (error, repair) {
+            (None, _) => tc,
+            (Some(_), true) => expected,
+            (Some(Error::Discontinuity), false) => tc,
+            (Some(_), false) => expected,
+        };
+        if current.is_some() {
+            self.previous = current;
+        }
+
+        (error, expected)
+    }
+}
+
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    checker: Checker,
+    errors: u64,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            info: None,
+            segment: gst::FormattedSegment::new(),
+            checker: Checker::default(),
+            errors: 0,
+        }
+    }
+}
+
+struct TimeCodeCheck {
+    cat: gst::DebugCategory,
+    repair: Mutex<bool>,
+    state: Mutex<State>,
+}
+
+impl TimeCodeCheck {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "timecodecheck",
+                gst::DebugColorFlags::empty(),
+                "Time code validation",
+            ),
+            repair: Mutex::new(DEFAULT_REPAIR),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Time Code Check",
+            "Filter/Analyzer/Video",
+            "Validates and optionally repairs the time code sequence of a video stream",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("video/x-raw", &[]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseTransform> for TimeCodeCheck {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::Boolean("repair", ..) => {
+                *self.repair.lock().unwrap() = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+
+        match *prop {
+            Property::Boolean("repair", ..) => Ok(self.repair.lock().unwrap().to_value()),
+            Property::UInt64("errors", ..) => Ok(self.state.lock().unwrap().errors.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for TimeCodeCheck {}
+
+impl BaseTransformImpl<BaseTransform> for TimeCodeCheck {
+    fn start(&self, _element: &BaseTransform) -> bool {
+        *self.state.lock().unwrap() = Default::default();
+
+        true
+    }
+
+    fn set_caps(&self, element: &BaseTransform, incaps: &gst::Caps, outcaps: &gst::Caps) -> bool {
+        if incaps != outcaps {
+            return false;
+        }
+
+        let info = match gst_video::VideoInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        if *info.fps().numer() <= 0 || *info.fps().denom() <= 0 {
+            gst_error!(self.cat, obj: element, "Variable framerate not supported");
+            return false;
+        }
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", incaps);
+        self.state.lock().unwrap().info = Some(info);
+
+        true
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let mut state = self.state.lock().unwrap();
+                state.segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Ok(segment) => segment,
+                    Err(_) => {
+                        gst_warning!(self.cat, obj: element, "Not a time segment");
+                        gst::FormattedSegment::new()
+                    }
+                };
+            }
+            // Time codes after a seek are not expected to continue
+            EventView::FlushStop(..) => {
+                self.state.lock().unwrap().checker = Checker::default();
+            }
+            _ => (),
+        }
+
+        element.parent_sink_event(event)
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let repair = *self.repair.lock().unwrap();
+
+        let (running_time, tc, fps, error, expected) = {
+            let mut state = self.state.lock().unwrap();
+            let fps = match state.info {
+                None => return gst::FlowReturn::NotNegotiated,
+                Some(ref info) => info.fps(),
+            };
+
+            let tc = timecode::get_buffer_timecode(buf).map(|(tc, _)| tc);
+            let (error, expected) = state
+                .checker
+                .check(tc, timecode::nominal_fps(fps), repair);
+            if error.is_some() {
+                state.errors += 1;
+            }
+
+            let running_time = state
+                .segment
+                .to_running_time(buf.get_pts())
+                .0
+                .unwrap_or(0);
+
+            (running_time, tc, fps, error, expected)
+        };
+
+        let error = match error {
+            None => return gst::FlowReturn::Ok,
+            Some(error) => error,
+        };
+
+        let tc_str = tc.map(|tc| tc.to_string()).unwrap_or_default();
+        let expected_str = expected.map(|tc| tc.to_string()).unwrap_or_default();
+        gst_warning!(
+            self.cat,
+            obj: element,
+            "Time code {:?} at {}: got {}, expected {}",
+            error,
+            running_time,
+            tc_str,
+            expected_str
+        );
+
+        let s = gst::Structure::new(
+            "timecodecheck",
+            &[
+                ("running-time", &running_time),
+                ("reason", &error.to_str()),
+                ("timecode", &tc_str),
+                ("expected", &expected_str),
+            ],
+        );
+        let _ = element.post_message(&gst::Message::new_element(s).src(Some(element)).build());
+
+        if repair {
+            if let Some(expected) = expected {
+                timecode::set_buffer_timecode(buf, &expected, fps);
+            }
+        }
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct TimeCodeCheckStatic;
+
+impl ImplTypeStatic<BaseTransform> for TimeCodeCheckStatic {
+    fn get_name(&self) -> &str {
+        "TimeCodeCheck"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        TimeCodeCheck::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        TimeCodeCheck::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let timecodecheck_static = TimeCodeCheckStatic;
+    register_type(timecodecheck_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tc(s: &str) -> Option<TimeCode> {
+        Some(TimeCode::parse(s).unwrap())
+    }
+
+    #[test]
+    fn test_check() {
+        let mut checker = Checker::default();
+        assert_eq!(checker.check(tc("00:00:00:00"), 25, false), (None, None));
+        assert_eq!(
+            checker.check(tc("00:00:00:01"), 25, false),
+            (None, tc("00:00:00:01"))
+        );
+
+        // Jump, checking continues from the new time code
+        assert_eq!(
+            checker.check(tc("00:00:01:00"), 25, false),
+            (Some(Error::Discontinuity), tc("00:00:00:02"))
+        );
+        assert_eq!(
+            checker.check(tc("00:00:01:01"), 25, false),
+            (None, tc("00:00:01:01"))
+        );
+
+        // Invalid and missing time codes are skipped
+        assert_eq!(
+            checker.check(tc("00:00:01:30"), 25, false),
+            (Some(Error::Invalid), tc("00:00:01:02"))
+        );
+        assert_eq!(
+            checker.check(None, 25, false),
+            (Some(Error::Missing), tc("00:00:01:03"))
+        );
+        assert_eq!(
+            checker.check(tc("00:00:01:04"), 25, false),
+            (None, tc("00:00:01:04"))
+        );
+    }
+
+    #[test]
+    fn test_repair() {
+        let mut checker = Checker::default();
+        assert_eq!(checker.check(tc("00:00:59;29"), 30, true), (None, None));
+
+        // Dropped frame number
+        assert_eq!(
+            checker.check(tc("00:01:00;00"), 30, true),
+            (Some(Error::Invalid), tc("00:01:00;02"))
+        );
+        // Repairing continues the original sequence
+        assert_eq!(
+            checker.check(tc("00:01:00;02"), 30, true),
+            (Some(Error::Discontinuity), tc("00:01:00;03"))
+        );
+        assert_eq!(
+            checker.check(tc("00:01:00;04"), 30, true),
+            (None, tc("00:01:00;04"))
+        );
+    }
+}
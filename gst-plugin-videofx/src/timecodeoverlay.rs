@@ -0,0 +1,371 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::i32;
+use std::sync::Mutex;
+
+use rgb;
+use timecode;
+
+// Time code overlay:
+//
+// Burns the time code of the GstVideoTimeCodeMeta of every frame into the frame, as white
+// digits of a built-in 5x7 pixel font on a black box with its top left corner at xpos/ypos.
+// Each font pixel is drawn as a square of scale x scale pixels. Frames without time code are
+// passed through unchanged.
+
+const DEFAULT_XPOS: i32 = 16;
+const DEFAULT_YPOS: i32 = 16;
+const DEFAULT_SCALE: u32 = 3;
+
+static PROPERTIES: [Property; 3] = [
+    Property::Int(
+        "xpos",
+        "X Position",
+        "Horizontal position of the time code in the frame",
+        (i32::MIN, i32::MAX),
+        DEFAULT_XPOS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Int(
+        "ypos",
+        "Y Position",
+        "Vertical position of the time code in the frame",
+        (i32::MIN, i32::MAX),
+        DEFAULT_YPOS,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "scale",
+        "Scale",
+        "Size of a font pixel in video pixels",
+        (1, 32),
+        DEFAULT_SCALE,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    xpos: i32,
+    ypos: i32,
+    scale: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            xpos: DEFAULT_XPOS,
+            ypos: DEFAULT_YPOS,
+            scale: DEFAULT_SCALE,
+        }
+    }
+}
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+// Rows of the glyphs for 0-9, ':' and ';', the highest of the 5 bits is the leftmost pixel
+static FONT: [[u8; GLYPH_HEIGHT]; 12] = [
+    [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+    [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+    [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+    [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+    [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+    [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+    [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+    [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x04, 0x08],
+];
+
+fn glyph(c: char) -> Option<&'static [u8; GLYPH_HEIGHT]> {
+    match c {
+        '0'...'9' => Some(&FONT[c as usize - '0' as usize]),
+        ':' => Some(&FONT[10]),
+        ';' => Some(&FONT[11]),
+        _ => None,
+    }
+}
+
+// Whether the pixel at x/y of the box around `text`, in font pixels, is set. The box has a
+// border of one font pixel and one font pixel between the glyphs.
+fn text_pixel(text: &[u8], x: usize, y: usize) -> bool {
+    if x == 0 || y == 0 || y > GLYPH_HEIGHT {
+        return false;
+    }
+
+    let (index, col) = ((x - 1) / (GLYPH_WIDTH + 1), (x - 1) % (GLYPH_WIDTH + 1));
+    if col == GLYPH_WIDTH {
+        return false;
+    }
+
+    text.get(index)
+        .and_then(|&c| glyph(c as char))
+        .map(|rows| rows[y - 1] & (0x10 >> col) != 0)
+        .unwrap_or(false)
+}
+
+// Size of the box around `text` in font pixels
+fn text_size(text: &[u8]) -> (usize, usize) {
+    (text.len() * (GLYPH_WIDTH + 1) + 1, GLYPH_HEIGHT + 2)
+}
+
+fn render(
+    info: &gst_video::VideoInfo,
+    data: &mut [u8],
+    text: &str,
+    xpos: i32,
+    ypos: i32,
+    scale: usize,
+) {
+    let (bpp, offsets) = rgb::layout(info.format()).unwrap();
+    let stride = info.stride()[0] as usize;
+    let (width, height) = (info.width() as i64, info.height() as i64);
+
+    let text = text.as_bytes();
+    let (box_width, box_height) = text_size(text);
+
+    let left = clamp(xpos as i64, 0, width);
+    let right = clamp(xpos as i64 + (box_width * scale) as i64, 0, width);
+    let top = clamp(ypos as i64, 0, height);
+    let bottom = clamp(ypos as i64 + (box_height * scale) as i64, 0, height);
+
+    for y in top..bottom {
+        let line = &mut data[(y as usize * stride)..];
+        let text_y = (y - ypos as i64) as usize / scale;
+        for x in left..right {
+            let text_x = (x - xpos as i64) as usize / scale;
+            let pixel = &mut line[(x as usize * bpp)..((x as usize + 1) * bpp)];
+
+            // White, and opaque for formats with alpha
+            for p in pixel.iter_mut() {
+                *p = 255;
+            }
+            if !text_pixel(text, text_x, text_y) {
+                for &offset in &offsets {
+                    pixel[offset] = 0;
+                }
+            }
+        }
+    }
+}
+
+fn clamp(v: i64, min: i64, max: i64) -> i64 {
+    if v < min {
+        min
+    } else if v > max {
+        max
+    } else {
+        v
+    }
+}
+
+struct TimeCodeOverlay {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    info: Mutex<Option<gst_video::VideoInfo>>,
+}
+
+impl TimeCodeOverlay {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "timecodeoverlay",
+                gst::DebugColorFlags::empty(),
+                "Time code overlay",
+            ),
+            settings: Mutex::new(Default::default()),
+            info: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Time Code Overlay",
+            "Filter/Editor/Video",
+            "Burns the time codes of video frames into the frames",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = rgb::caps();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseTransform> for TimeCodeOverlay {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int("xpos", ..) => settings.xpos = value.get().unwrap(),
+            Property::Int("ypos", ..) => settings.ypos = value.get().unwrap(),
+            Property::UInt("scale", ..) => settings.scale = value.get().unwrap(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::Int("xpos", ..) => Ok(settings.xpos.to_value()),
+            Property::Int("ypos", ..) => Ok(settings.ypos.to_value()),
+            Property::UInt("scale", ..) => Ok(settings.scale.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for TimeCodeOverlay {}
+
+impl BaseTransformImpl<BaseTransform> for TimeCodeOverlay {
+    fn stop(&self, _element: &BaseTransform) -> bool {
+        *self.info.lock().unwrap() = None;
+
+        true
+    }
+
+    fn set_caps(&self, element: &BaseTransform, incaps: &gst::Caps, outcaps: &gst::Caps) -> bool {
+        if incaps != outcaps {
+            return false;
+        }
+
+        let info = match gst_video::VideoInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", incaps);
+        *self.info.lock().unwrap() = Some(info);
+
+        true
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let settings = *self.settings.lock().unwrap();
+
+        let info = match *self.info.lock().unwrap() {
+            None => return gst::FlowReturn::NotNegotiated,
+            Some(ref info) => info.clone(),
+        };
+
+        let tc = match timecode::get_buffer_timecode(buf) {
+            None => {
+                gst_trace!(self.cat, obj: element, "Frame without time code");
+                return gst::FlowReturn::Ok;
+            }
+            Some((tc, _)) => tc,
+        };
+
+        let mut map = match buf.map_writable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map,
+        };
+
+        render(
+            &info,
+            map.as_mut_slice(),
+            &tc.to_string(),
+            settings.xpos,
+            settings.ypos,
+            settings.scale as usize,
+        );
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct TimeCodeOverlayStatic;
+
+impl ImplTypeStatic<BaseTransform> for TimeCodeOverlayStatic {
+    fn get_name(&self) -> &str {
+        "TimeCodeOverlay"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        TimeCodeOverlay::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        TimeCodeOverlay::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let timecodeoverlay_static = TimeCodeOverlayStatic;
+    register_type(timecodeoverlay_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_pixel() {
+        let text = b"1:";
+        assert_eq!(text_size(text), (13, 9));
+
+        // Border
+        assert!(!text_pixel(text, 0, 3));
+        assert!(!text_pixel(text, 3, 0));
+        assert!(!text_pixel(text, 3, 8));
+
+        // Top row of "1" is 0x04, its middle pixel
+        assert!(text_pixel(text, 3, 1));
+        assert!(!text_pixel(text, 2, 1));
+        // Gap between the glyphs
+        assert!(!text_pixel(text, 6, 2));
+        // Second row of ":" is 0x0c
+        assert!(text_pixel(text, 8, 2));
+        assert!(text_pixel(text, 9, 2));
+        assert!(!text_pixel(text, 10, 2));
+        // Past the end
+        assert!(!text_pixel(text, 13, 2));
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_transform::*;
+
+use std::sync::Mutex;
+
+use timecode::{self, TimeCode};
+
+// Time code stamper:
+//
+// Attaches a SMPTE time code to every frame as GstVideoTimeCodeMeta. The first frame gets
+// first-timecode, later frames the time code matching their running time relative to the first
+// frame. Dropped frames therefore leave a gap in the time codes instead of shifting all later
+// ones. At 29.97 and 59.94 fps drop-frame time codes are used unless disabled.
+//
+// Frames that already have a time code keep it unless override-existing is set.
+
+const DEFAULT_FIRST_TIMECODE: &str = "00:00:00:00";
+const DEFAULT_DROP_FRAME: bool = true;
+const DEFAULT_OVERRIDE_EXISTING: bool = false;
+
+static PROPERTIES: [Property; 3] = [
+    Property::String(
+        "first-timecode",
+        "First Time Code",
+        "Time code of the first frame as HH:MM:SS:FF",
+        Some(DEFAULT_FIRST_TIMECODE),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "drop-frame",
+        "Drop Frame",
+        "Use drop-frame time codes at 29.97 and 59.94 fps",
+        DEFAULT_DROP_FRAME,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "override-existing",
+        "Override Existing",
+        "Replace time codes that are already attached to the frames",
+        DEFAULT_OVERRIDE_EXISTING,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    first_timecode: String,
+    drop_frame: bool,
+    override_existing: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            first_timecode: DEFAULT_FIRST_TIMECODE.into(),
+            drop_frame: DEFAULT_DROP_FRAME,
+            override_existing: DEFAULT_OVERRIDE_EXISTING,
+        }
+    }
+}
+
+struct State {
+    fps: Option<gst::Fraction>,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    // Running time and time code of the first frame
+    first: Option<(u64, TimeCode)>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            fps: None,
+            segment: gst::FormattedSegment::new(),
+            first: None,
+        }
+    }
+}
+
+struct TimeCodeStamper {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl TimeCodeStamper {
+    fn new(_transform: &BaseTransform) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rstimecodestamper",
+                gst::DebugColorFlags::empty(),
+                "Time code stamper",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut BaseTransformClass) {
+        klass.set_metadata(
+            "Time Code Stamper",
+            "Filter/Video",
+            "Attaches SMPTE time codes to video frames",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_simple("video/x-raw", &[]);
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+
+    fn init(element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseTransform> for TimeCodeStamper {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("first-timecode", ..) => {
+                settings.first_timecode = value
+                    .get()
+                    .unwrap_or_else(|| DEFAULT_FIRST_TIMECODE.into());
+            }
+            Property::Boolean("drop-frame", ..) => {
+                settings.drop_frame = value.get().unwrap();
+            }
+            Property::Boolean("override-existing", ..) => {
+                settings.override_existing = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("first-timecode", ..) => Ok(settings.first_timecode.to_value()),
+            Property::Boolean("drop-frame", ..) => Ok(settings.drop_frame.to_value()),
+            Property::Boolean("override-existing", ..) => {
+                Ok(settings.override_existing.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseTransform> for TimeCodeStamper {}
+
+impl BaseTransformImpl<BaseTransform> for TimeCodeStamper {
+    fn start(&self, element: &BaseTransform) -> bool {
+        let first_timecode = self.settings.lock().unwrap().first_timecode.clone();
+        if let Err(err) = TimeCode::parse(&first_timecode) {
+            gst_element_error!(element, gst::LibraryError::Settings, ["{}", err]);
+            return false;
+        }
+
+        *self.state.lock().unwrap() = Default::default();
+
+        true
+    }
+
+    fn set_caps(&self, element: &BaseTransform, incaps: &gst::Caps, outcaps: &gst::Caps) -> bool {
+        if incaps != outcaps {
+            return false;
+        }
+
+        let info = match gst_video::VideoInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        let fps = info.fps();
+        if *fps.numer() <= 0 || *fps.denom() <= 0 {
+            gst_error!(self.cat, obj: element, "Variable framerate not supported");
+            return false;
+        }
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", incaps);
+
+        let mut state = self.state.lock().unwrap();
+        if state.fps != Some(fps) {
+            // Continue counting at the new rate from the next frame on
+            state.first = None;
+        }
+        state.fps = Some(fps);
+
+        true
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        if let EventView::Segment(e) = event.view() {
+            let mut state = self.state.lock().unwrap();
+            state.segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                Ok(segment) => segment,
+                Err(_) => {
+                    gst_warning!(self.cat, obj: element, "Not a time segment");
+                    gst::FormattedSegment::new()
+                }
+            };
+        }
+
+        element.parent_sink_event(event)
+    }
+
+    fn transform_ip(&self, element: &BaseTransform, buf: &mut gst::BufferRef) -> gst::FlowReturn {
+        let settings = self.settings.lock().unwrap().clone();
+
+        if !settings.override_existing && timecode::get_buffer_timecode(buf).is_some() {
+            gst_trace!(self.cat, obj: element, "Keeping existing time code");
+            return gst::FlowReturn::Ok;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let fps = match state.fps {
+            None => return gst::FlowReturn::NotNegotiated,
+            Some(fps) => fps,
+        };
+
+        let running_time = match state.segment.to_running_time(buf.get_pts()).0 {
+            None => {
+                gst_debug!(self.cat, obj: element, "Frame without running time");
+                return gst::FlowReturn::Ok;
+            }
+            Some(running_time) => running_time,
+        };
+
+        let nominal_fps = timecode::nominal_fps(fps);
+        let drop_frame = settings.drop_frame && timecode::is_drop_frame_rate(fps);
+
+        let (first_running_time, first_timecode) = match state.first {
+            Some(first) => first,
+            None => {
+                let mut tc = TimeCode::parse(&settings.first_timecode).unwrap();
+                tc.drop_frame = drop_frame;
+                if !tc.is_valid(nominal_fps) {
+                    gst_element_error!(
+                        element,
+                        gst::LibraryError::Settings,
+                        ["Invalid first time code {} for {} fps", tc, fps]
+                    );
+                    return gst::FlowReturn::Error;
+                }
+                state.first = Some((running_time, tc));
+                (running_time, tc)
+            }
+        };
+
+        // Frames before the first one are counted backwards from it
+        let tc = if running_time >= first_running_time {
+            let frames = timecode::time_to_frames(running_time - first_running_time, fps);
+            first_timecode.add_frames(frames, nominal_fps)
+        } else {
+            let frames = timecode::time_to_frames(first_running_time - running_time, fps);
+            let per_day = timecode::frames_per_day(nominal_fps, drop_frame);
+            first_timecode.add_frames(per_day - frames % per_day, nominal_fps)
+        };
+        drop(state);
+
+        gst_trace!(self.cat, obj: element, "Stamping {} at {}", tc, running_time);
+        timecode::set_buffer_timecode(buf, &tc, fps);
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct TimeCodeStamperStatic;
+
+impl ImplTypeStatic<BaseTransform> for TimeCodeStamperStatic {
+    fn get_name(&self) -> &str {
+        "RsTimeCodeStamper"
+    }
+
+    fn new(&self, element: &BaseTransform) -> Box<BaseTransformImpl<BaseTransform>> {
+        TimeCodeStamper::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseTransformClass) {
+        TimeCodeStamper::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let timecodestamper_static = TimeCodeStamperStatic;
+    register_type(timecodestamper_static)
+}
@@ -0,0 +1,705 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_video;
+
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::aggregator::*;
+use gst_plugin::aggregator_pad::*;
+
+use std::f64;
+use std::sync::Mutex;
+
+use rgb;
+
+// Video comparison for encoder regression tests:
+//
+// Compares the frames of the test_sink input, e.g. the output of an encoder and decoder, with
+// the frames of the ref_sink input at the same running time and posts a "videocompare" element
+// message for every compared frame:
+//
+// "videocompare, running-time=(guint64)..., psnr=(double)..., ssim=(double)...,
+//  ms-ssim=(double)..."
+//
+// All scores are calculated on the luma of the frames, which must have the same size. The PSNR
+// is capped at 100 dB for identical frames. SSIM uses 8x8 windows every 4 pixels and MS-SSIM the
+// usual 5 scales, or fewer for small frames.
+//
+// A test frame is compared with every reference frame it overlaps with, so test inputs with a
+// lower framerate have their frames repeated. Reference frames without test frame are counted as
+// unmatched. Once the reference input is EOS a "videocompare-summary" message is posted:
+//
+// "videocompare-summary, frames=(guint64)..., unmatched=(guint64)..., psnr=(double)...,
+//  ssim=(double)..., ms-ssim=(double)..., min-ssim=(double)..."
+//
+// with the PSNR of the mean squared error over all frames and the mean of the SSIM scores.
+//
+// The reference frames are forwarded with their running time as timestamp, so that the element
+// can be placed before a sink.
+
+const MAX_PSNR: f64 = 100.0;
+
+const WINDOW: usize = 8;
+const WINDOW_STEP: usize = 4;
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+// Luma of a frame
+#[derive(Debug, Clone)]
+struct Plane {
+    width: usize,
+    height: usize,
+    data: Vec<f64>,
+}
+
+impl Plane {
+    fn from_frame(info: &gst_video::VideoInfo, data: &[u8]) -> Plane {
+        let (bpp, offsets) = rgb::layout(info.format()).unwrap();
+        let width = info.width() as usize;
+        let height = info.height() as usize;
+        let stride = info.stride()[0] as usize;
+
+        let mut luma = Vec::with_capacity(width * height);
+        for row in data.chunks(stride).take(height) {
+            for pixel in row[..(width * bpp)].chunks(bpp) {
+                luma.push(
+                    0.299 * pixel[offsets[0]] as f64 + 0.587 * pixel[offsets[1]] as f64
+                        + 0.114 * pixel[offsets[2]] as f64,
+                );
+            }
+        }
+
+        Plane {
+            width: width,
+            height: height,
+            data: luma,
+        }
+    }
+
+    // Half the size, averaging 2x2 pixels
+    fn downsample(&self) -> Plane {
+        let width = self.width / 2;
+        let height = self.height / 2;
+
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let top = &self.data[(2 * y * self.width)..];
+            let bottom = &self.data[((2 * y + 1) * self.width)..];
+            for x in 0..width {
+                data.push((top[2 * x] + top[2 * x + 1] + bottom[2 * x] + bottom[2 * x + 1]) / 4.0);
+            }
+        }
+
+        Plane {
+            width: width,
+            height: height,
+            data: data,
+        }
+    }
+}
+
+fn mse(a: &Plane, b: &Plane) -> f64 {
+    let sum = a.data
+        .iter()
+        .zip(b.data.iter())
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f64>();
+    sum / a.data.len() as f64
+}
+
+fn psnr(mse: f64) -> f64 {
+    if mse <= 0.0 {
+        MAX_PSNR
+    } else {
+        f64::min(10.0 * (255.0 * 255.0 / mse).log10(), MAX_PSNR)
+    }
+}
+
+// Mean SSIM and mean contrast-structure term over all windows. Planes smaller than a window
+// are treated as a single window.
+fn ssim(a: &Plane, b: &Plane) -> (f64, f64) {
+    let window_width = if a.width < WINDOW { a.width } else { WINDOW };
+    let window_height = if a.height < WINDOW { a.height } else { WINDOW };
+    let n = (window_width * window_height) as f64;
+
+    let mut sum_ssim = 0.0;
+    let mut sum_cs = 0.0;
+    let mut windows = 0;
+
+    let mut y = 0;
+    while y + window_height <= a.height {
+        let mut x = 0;
+        while x + window_width <= a.width {
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for wy in y..(y + window_height) {
+                let row = wy * a.width;
+                for idx in (row + x)..(row + x + window_width) {
+                    let (pa, pb) = (a.data[idx], b.data[idx]);
+                    sa += pa;
+                    sb += pb;
+                    saa += pa * pa;
+                    sbb += pb * pb;
+                    sab += pa * pb;
+                }
+            }
+
+            let (ma, mb) = (sa / n, sb / n);
+            let va = saa / n - ma * ma;
+            let vb = sbb / n - mb * mb;
+            let cov = sab / n - ma * mb;
+
+            let l = (2.0 * ma * mb + C1) / (ma * ma + mb * mb + C1);
+            let cs = (2.0 * cov + C2) / (va + vb + C2);
+            sum_ssim += l * cs;
+            sum_cs += cs;
+            windows += 1;
+
+            x += WINDOW_STEP;
+        }
+        y += WINDOW_STEP;
+    }
+
+    (sum_ssim / windows as f64, sum_cs / windows as f64)
+}
+
+// Product of the contrast-structure terms of the coarser scales and the SSIM of the coarsest
+// scale, weighted with the weights of the scales that fit into the frame
+fn ms_ssim(a: &Plane, b: &Plane) -> f64 {
+    let mut a = a.clone();
+    let mut b = b.clone();
+    let mut score = 1.0;
+    let mut weights = 0.0;
+
+    for (i, &weight) in MS_SSIM_WEIGHTS.iter().enumerate() {
+        let (s, cs) = ssim(&a, &b);
+        weights += weight;
+
+        let last = i == MS_SSIM_WEIGHTS.len() - 1 || a.width / 2 < WINDOW
+            || a.height / 2 < WINDOW;
+        if last {
+            score *= f64::max(s, 0.0).powf(weight);
+            break;
+        }
+
+        score *= f64::max(cs, 0.0).powf(weight);
+        a = a.downsample();
+        b = b.downsample();
+    }
+
+    score.powf(1.0 / weights)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Alignment {
+    // The test frame ends before the reference frame
+    Early,
+    // The test frame starts after the reference frame, which has no test frame
+    Late,
+    // The frames overlap, the test frame is still needed for the next reference frame if it
+    // ends after the reference frame
+    Overlap { keep: bool },
+}
+
+fn align(ref_start: u64, ref_end: u64, test_start: u64, test_end: u64) -> Alignment {
+    if test_end <= ref_start {
+        Alignment::Early
+    } else if test_start >= ref_end {
+        Alignment::Late
+    } else {
+        Alignment::Overlap {
+            keep: test_end > ref_end,
+        }
+    }
+}
+
+// Running time and end running time of a buffer. Buffers without duration last 1ns.
+fn buffer_times(pad: &gst::Pad, buffer: &gst::Buffer) -> Option<(u64, u64)> {
+    let segment = match pad.get_segment().downcast::<gst::ClockTime>() {
+        Ok(segment) => segment,
+        Err(_) => return None,
+    };
+
+    segment.to_running_time(buffer.get_pts()).0.map(|start| {
+        let duration = buffer.get_duration().0.unwrap_or(1);
+        (start, start + duration)
+    })
+}
+
+struct State {
+    reference_caps: Option<gst::Caps>,
+    reference_info: Option<gst_video::VideoInfo>,
+    test_info: Option<gst_video::VideoInfo>,
+    frames: u64,
+    unmatched: u64,
+    sum_mse: f64,
+    sum_ssim: f64,
+    sum_ms_ssim: f64,
+    min_ssim: f64,
+    summary_posted: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            reference_caps: None,
+            reference_info: None,
+            test_info: None,
+            frames: 0,
+            unmatched: 0,
+            sum_mse: 0.0,
+            sum_ssim: 0.0,
+            sum_ms_ssim: 0.0,
+            min_ssim: 1.0,
+            summary_posted: false,
+        }
+    }
+}
+
+struct VideoCompare {
+    cat: gst::DebugCategory,
+    reference_pad: gst::Pad,
+    test_pad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl VideoCompare {
+    fn new(_element: &Aggregator, reference_pad: gst::Pad, test_pad: gst::Pad) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "videocompare",
+                gst::DebugColorFlags::empty(),
+                "Video comparison",
+            ),
+            reference_pad: reference_pad,
+            test_pad: test_pad,
+            state: Mutex::new(Default::default()),
+        }
+    }
+
+    fn class_init(klass: &mut AggregatorClass) {
+        klass.set_metadata(
+            "Video Compare",
+            "Filter/Analyzer/Video",
+            "Measures PSNR, SSIM and MS-SSIM of a video stream against a reference stream",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = rgb::caps();
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        let reference_pad_template = gst::PadTemplate::new(
+            "ref_sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(reference_pad_template);
+
+        let test_pad_template = gst::PadTemplate::new(
+            "test_sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(test_pad_template);
+    }
+
+    fn init(element: &Aggregator) -> Box<AggregatorImpl<Aggregator>> {
+        let new_pad = |name: &str| {
+            let templ = element.get_pad_template(name).unwrap();
+            let pad = glib::Object::new(
+                AggregatorPad::glib_type(),
+                &[
+                    ("name", &name),
+                    ("direction", &gst::PadDirection::Sink),
+                    ("template", &templ),
+                ],
+            ).unwrap()
+                .downcast::<gst::Pad>()
+                .unwrap();
+            element.add_pad(&pad).unwrap();
+            pad
+        };
+
+        let reference_pad = new_pad("ref_sink");
+        let test_pad = new_pad("test_sink");
+
+        let imp = Self::new(element, reference_pad, test_pad);
+        Box::new(imp)
+    }
+
+    fn post_summary(&self, element: &Aggregator) {
+        let s = {
+            let mut state = self.state.lock().unwrap();
+            if state.summary_posted {
+                return;
+            }
+            state.summary_posted = true;
+
+            let frames = state.frames;
+            let (psnr, ssim, ms_ssim, min_ssim) = if frames > 0 {
+                (
+                    psnr(state.sum_mse / frames as f64),
+                    state.sum_ssim / frames as f64,
+                    state.sum_ms_ssim / frames as f64,
+                    state.min_ssim,
+                )
+            } else {
+                (0.0, 0.0, 0.0, 0.0)
+            };
+
+            gst_info!(
+                self.cat,
+                obj: element,
+                "Compared {} frames ({} unmatched): PSNR {:.3}, SSIM {:.5}, MS-SSIM {:.5}",
+                frames,
+                state.unmatched,
+                psnr,
+                ssim,
+                ms_ssim
+            );
+
+            gst::Structure::new(
+                "videocompare-summary",
+                &[
+                    ("frames", &frames),
+                    ("unmatched", &state.unmatched),
+                    ("psnr", &psnr),
+                    ("ssim", &ssim),
+                    ("ms-ssim", &ms_ssim),
+                    ("min-ssim", &min_ssim),
+                ],
+            )
+        };
+
+        let _ = element.post_message(&gst::Message::new_element(s).src(Some(element)).build());
+    }
+
+    fn compare(
+        &self,
+        element: &Aggregator,
+        reference: &gst::Buffer,
+        test: &gst::Buffer,
+        running_time: u64,
+    ) -> gst::FlowReturn {
+        let (reference_info, test_info) = {
+            let state = self.state.lock().unwrap();
+            match (state.reference_info.clone(), state.test_info.clone()) {
+                (Some(reference_info), Some(test_info)) => (reference_info, test_info),
+                _ => {
+                    gst_element_error!(element, gst::CoreError::Negotiation, ["Have no caps"]);
+                    return gst::FlowReturn::NotNegotiated;
+                }
+            }
+        };
+
+        if reference_info.width() != test_info.width()
+            || reference_info.height() != test_info.height()
+        {
+            gst_element_error!(
+                element,
+                gst::CoreError::Negotiation,
+                [
+                    "Frame sizes differ: {}x{} and {}x{}",
+                    reference_info.width(),
+                    reference_info.height(),
+                    test_info.width(),
+                    test_info.height()
+                ]
+            );
+            return gst::FlowReturn::NotNegotiated;
+        }
+
+        let (reference_map, test_map) = match (reference.map_readable(), test.map_readable()) {
+            (Some(reference_map), Some(test_map)) => (reference_map, test_map),
+            _ => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+        };
+
+        let a = Plane::from_frame(&reference_info, reference_map.as_slice());
+        let b = Plane::from_frame(&test_info, test_map.as_slice());
+
+        let frame_mse = mse(&a, &b);
+        let frame_psnr = psnr(frame_mse);
+        let (frame_ssim, _) = ssim(&a, &b);
+        let frame_ms_ssim = ms_ssim(&a, &b);
+
+        gst_log!(
+            self.cat,
+            obj: element,
+            "Frame at {}: PSNR {:.3}, SSIM {:.5}, MS-SSIM {:.5}",
+            running_time,
+            frame_psnr,
+            frame_ssim,
+            frame_ms_ssim
+        );
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.frames += 1;
+            state.sum_mse += frame_mse;
+            state.sum_ssim += frame_ssim;
+            state.sum_ms_ssim += frame_ms_ssim;
+            state.min_ssim = f64::min(state.min_ssim, frame_ssim);
+        }
+
+        let s = gst::Structure::new(
+            "videocompare",
+            &[
+                ("running-time", &running_time),
+                ("psnr", &frame_psnr),
+                ("ssim", &frame_ssim),
+                ("ms-ssim", &frame_ms_ssim),
+            ],
+        );
+        let _ = element.post_message(&gst::Message::new_element(s).src(Some(element)).build());
+
+        gst::FlowReturn::Ok
+    }
+}
+
+impl ObjectImpl<Aggregator> for VideoCompare {}
+
+impl ElementImpl<Aggregator> for VideoCompare {}
+
+impl AggregatorImpl<Aggregator> for VideoCompare {
+    fn start(&self, element: &Aggregator) -> bool {
+        *self.state.lock().unwrap() = Default::default();
+
+        element.parent_start()
+    }
+
+    fn stop(&self, element: &Aggregator) -> bool {
+        *self.state.lock().unwrap() = Default::default();
+
+        element.parent_stop()
+    }
+
+    fn sink_event(&self, element: &Aggregator, pad: &gst::Pad, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        if let EventView::Caps(e) = event.view() {
+            let info = match gst_video::VideoInfo::from_caps(e.get_caps()) {
+                None => return false,
+                Some(info) => info,
+            };
+
+            gst_debug!(self.cat, obj: pad, "Got caps {:?}", e.get_caps());
+
+            let mut state = self.state.lock().unwrap();
+            if *pad == self.reference_pad {
+                state.reference_caps = Some(e.get_caps().to_owned());
+                state.reference_info = Some(info);
+
+                // The output has the caps of the reference input
+                element.get_src_pad().mark_reconfigure();
+            } else {
+                state.test_info = Some(info);
+            }
+
+            return true;
+        }
+
+        element.parent_sink_event(pad, event)
+    }
+
+    fn update_src_caps(
+        &self,
+        _element: &Aggregator,
+        caps: &gst::Caps,
+    ) -> Result<gst::Caps, gst::FlowReturn> {
+        let state = self.state.lock().unwrap();
+        let reference_caps = match state.reference_caps {
+            // Wait for the caps of the reference input
+            None => return Err(gst::FlowReturn::CustomSuccess),
+            Some(ref caps) => caps,
+        };
+
+        let caps = reference_caps.intersect(caps);
+        if caps.is_empty() {
+            Err(gst::FlowReturn::NotNegotiated)
+        } else {
+            Ok(caps)
+        }
+    }
+
+    fn aggregate(&self, element: &Aggregator, _timeout: bool) -> gst::FlowReturn {
+        let reference = match self.reference_pad.peek_buffer() {
+            None if self.reference_pad.is_eos() => {
+                gst_debug!(self.cat, obj: element, "Reference input is EOS");
+                self.post_summary(element);
+                return gst::FlowReturn::Eos;
+            }
+            None => return gst::FlowReturn::Ok,
+            Some(buffer) => buffer,
+        };
+
+        let (ref_start, ref_end) = match buffer_times(&self.reference_pad, &reference) {
+            None => {
+                gst_debug!(self.cat, obj: element, "Dropping reference buffer outside segment");
+                self.reference_pad.drop_buffer();
+                return gst::FlowReturn::Ok;
+            }
+            Some(times) => times,
+        };
+
+        let mut test = None;
+        loop {
+            let buffer = match self.test_pad.peek_buffer() {
+                // Wait for more test frames unless there won't be any
+                None if !self.test_pad.is_eos() => return gst::FlowReturn::Ok,
+                None => break,
+                Some(buffer) => buffer,
+            };
+
+            let (test_start, test_end) = match buffer_times(&self.test_pad, &buffer) {
+                None => {
+                    gst_debug!(self.cat, obj: element, "Dropping test buffer outside segment");
+                    self.test_pad.drop_buffer();
+                    continue;
+                }
+                Some(times) => times,
+            };
+
+            match align(ref_start, ref_end, test_start, test_end) {
+                Alignment::Early => {
+                    gst_debug!(self.cat, obj: element, "Dropping test frame at {}", test_start);
+                    self.test_pad.drop_buffer();
+                }
+                Alignment::Late => break,
+                Alignment::Overlap { keep } => {
+                    if !keep {
+                        self.test_pad.drop_buffer();
+                    }
+                    test = Some(buffer);
+                    break;
+                }
+            }
+        }
+
+        self.reference_pad.drop_buffer();
+
+        match test {
+            Some(test) => {
+                let ret = self.compare(element, &reference, &test, ref_start);
+                if ret != gst::FlowReturn::Ok {
+                    return ret;
+                }
+            }
+            None => {
+                gst_debug!(self.cat, obj: element, "No test frame at {}", ref_start);
+                self.state.lock().unwrap().unmatched += 1;
+            }
+        }
+
+        let mut outbuf = reference;
+        {
+            let outbuf = outbuf.make_mut();
+            outbuf.set_pts(gst::ClockTime::from_nseconds(ref_start));
+            outbuf.set_dts(gst::CLOCK_TIME_NONE);
+        }
+
+        element.finish_buffer(outbuf)
+    }
+}
+
+struct VideoCompareStatic;
+
+impl ImplTypeStatic<Aggregator> for VideoCompareStatic {
+    fn get_name(&self) -> &str {
+        "VideoCompare"
+    }
+
+    fn new(&self, element: &Aggregator) -> Box<AggregatorImpl<Aggregator>> {
+        VideoCompare::init(element)
+    }
+
+    fn class_init(&self, klass: &mut AggregatorClass) {
+        VideoCompare::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let videocompare_static = VideoCompareStatic;
+    register_type(videocompare_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plane(width: usize, height: usize, f: &Fn(usize, usize) -> f64) -> Plane {
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(f(x, y));
+            }
+        }
+
+        Plane {
+            width: width,
+            height: height,
+            data: data,
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let a = plane(64, 48, &|x, y| ((x * 7 + y * 13) % 256) as f64);
+
+        // Identical frames
+        assert_eq!(psnr(mse(&a, &a)), MAX_PSNR);
+        assert!((ssim(&a, &a).0 - 1.0).abs() < 1e-9);
+        assert!((ms_ssim(&a, &a) - 1.0).abs() < 1e-9);
+
+        // Constant offset of 10: MSE 100, only the luminance term changes
+        let b = plane(64, 48, &|x, y| a.data[y * 64 + x] + 10.0);
+        assert!((psnr(mse(&a, &b)) - 28.1308).abs() < 1e-3);
+        let (s, cs) = ssim(&a, &b);
+        assert!(s < 1.0 && s > 0.9);
+        assert!((cs - 1.0).abs() < 1e-9);
+
+        // Noise lowers the structural similarity much more
+        let c = plane(64, 48, &|x, y| if (x ^ y) & 1 == 0 { 0.0 } else { 255.0 });
+        assert!(ssim(&a, &c).0 < 0.5);
+        assert!(ms_ssim(&a, &c) < ms_ssim(&a, &b));
+    }
+
+    #[test]
+    fn test_downsample() {
+        let a = plane(5, 3, &|x, y| (x + 10 * y) as f64);
+        let b = a.downsample();
+        assert_eq!((b.width, b.height), (2, 1));
+        assert_eq!(b.data, vec![5.5, 7.5]);
+    }
+
+    #[test]
+    fn test_align() {
+        assert_eq!(align(40, 80, 0, 40), Alignment::Early);
+        assert_eq!(align(40, 80, 80, 120), Alignment::Late);
+        assert_eq!(align(40, 80, 40, 80), Alignment::Overlap { keep: false });
+        // Lower test framerate, the test frame is used again for the next reference frame
+        assert_eq!(align(40, 80, 40, 120), Alignment::Overlap { keep: true });
+        assert_eq!(align(40, 80, 30, 70), Alignment::Overlap { keep: false });
+    }
+}
@@ -0,0 +1,639 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+use gst_base::prelude::*;
+use gst_video;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_src::*;
+use gst_plugin::idle::*;
+
+use std::{f64, u32};
+use std::sync::Mutex;
+
+use rgb;
+
+// Video test source:
+//
+// Produces one of the test patterns in any of the packed RGB formats. Like rsaudiotestsrc the
+// source is driven by the number of the next frame, from which the timestamps are calculated,
+// so that they don't drift, and which also drives the animation of the moving ball.
+//
+// With is-live the base class waits for the clock to reach the timestamp of each frame before
+// pushing it, so frames are produced in real time. With a framerate of 0/1 a single frame is
+// produced.
+//
+// With idle-when-unlinked no frames are produced while the src pad is unlinked.
+//
+// Width, height and framerate are negotiated with downstream and default to 320x240 at 30fps.
+
+const DEFAULT_PATTERN: &str = "smpte";
+const DEFAULT_FOREGROUND_COLOR: u32 = 0xff_ff_ff_ff;
+const DEFAULT_BACKGROUND_COLOR: u32 = 0xff_00_00_00;
+const DEFAULT_IS_LIVE: bool = false;
+
+const DEFAULT_WIDTH: i32 = 320;
+const DEFAULT_HEIGHT: i32 = 240;
+const DEFAULT_FPS: (i32, i32) = (30, 1);
+
+// Size of the squares of the checkers pattern in pixels
+const CHECKER_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pattern {
+    Smpte,
+    Checkers,
+    Ball,
+    SolidColor,
+}
+
+impl Pattern {
+    fn from_str(s: &str) -> Option<Pattern> {
+        match s {
+            "smpte" => Some(Pattern::Smpte),
+            "checkers" => Some(Pattern::Checkers),
+            "ball" => Some(Pattern::Ball),
+            "solid-color" => Some(Pattern::SolidColor),
+            _ => None,
+        }
+    }
+
+    fn to_str(&self) -> &'static str {
+        match *self {
+            Pattern::Smpte => "smpte",
+            Pattern::Checkers => "checkers",
+            Pattern::Ball => "ball",
+            Pattern::SolidColor => "solid-color",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    pattern: Pattern,
+    foreground_color: u32,
+    background_color: u32,
+    is_live: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            pattern: Pattern::from_str(DEFAULT_PATTERN).unwrap(),
+            foreground_color: DEFAULT_FOREGROUND_COLOR,
+            background_color: DEFAULT_BACKGROUND_COLOR,
+            is_live: DEFAULT_IS_LIVE,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 5] = [
+    Property::String(
+        "pattern",
+        "Pattern",
+        "Test pattern to produce (smpte, checkers, ball or solid-color)",
+        Some(DEFAULT_PATTERN),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "foreground-color",
+        "Foreground Color",
+        "Color of the checkers, the ball and solid-color as 0xAARRGGBB",
+        (0, u32::MAX),
+        DEFAULT_FOREGROUND_COLOR,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::UInt(
+        "background-color",
+        "Background Color",
+        "Color of the checkers and behind the ball as 0xAARRGGBB",
+        (0, u32::MAX),
+        DEFAULT_BACKGROUND_COLOR,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "is-live",
+        "Is Live",
+        "Synchronize the produced frames to the clock",
+        DEFAULT_IS_LIVE,
+        PropertyMutability::ReadWrite,
+    ),
+    IDLE_WHEN_UNLINKED_PROPERTY,
+];
+
+// RGBA
+type Color = [u8; 4];
+
+fn color_from_argb(argb: u32) -> Color {
+    [
+        (argb >> 16) as u8,
+        (argb >> 8) as u8,
+        argb as u8,
+        (argb >> 24) as u8,
+    ]
+}
+
+// 75% color bars, followed by the reverse blue bars and the -I/white/+Q/PLUGE row. Colors are
+// full range RGB, so the sub-black PLUGE bar is clamped to black.
+const SMPTE_BARS: [Color; 7] = [
+    [191, 191, 191, 255],
+    [191, 191, 0, 255],
+    [0, 191, 191, 255],
+    [0, 191, 0, 255],
+    [191, 0, 191, 255],
+    [191, 0, 0, 255],
+    [0, 0, 191, 255],
+];
+const SMPTE_REVERSE_BARS: [Color; 7] = [
+    [0, 0, 191, 255],
+    [0, 0, 0, 255],
+    [191, 0, 191, 255],
+    [0, 0, 0, 255],
+    [0, 191, 191, 255],
+    [0, 0, 0, 255],
+    [191, 191, 191, 255],
+];
+// End of each part of the bottom row in bar widths
+const SMPTE_BOTTOM: [(f64, Color); 8] = [
+    (1.25, [0, 33, 76, 255]),
+    (2.5, [255, 255, 255, 255]),
+    (3.75, [50, 0, 106, 255]),
+    (5.0, [0, 0, 0, 255]),
+    (5.0 + 1.0 / 3.0, [0, 0, 0, 255]),
+    (5.0 + 2.0 / 3.0, [0, 0, 0, 255]),
+    (6.0, [10, 10, 10, 255]),
+    (7.0, [0, 0, 0, 255]),
+];
+
+fn smpte_color(x: usize, y: usize, width: usize, height: usize) -> Color {
+    let bars = x as f64 * 7.0 / width as f64;
+
+    if y * 3 < height * 2 {
+        SMPTE_BARS[bars as usize]
+    } else if y * 4 < height * 3 {
+        SMPTE_REVERSE_BARS[bars as usize]
+    } else {
+        SMPTE_BOTTOM
+            .iter()
+            .find(|&&(end, _)| bars < end)
+            .map(|&(_, color)| color)
+            .unwrap_or(SMPTE_BOTTOM[7].1)
+    }
+}
+
+struct Frame {
+    width: usize,
+    height: usize,
+    number: u64,
+}
+
+fn pattern_color(
+    pattern: Pattern,
+    frame: &Frame,
+    x: usize,
+    y: usize,
+    settings: &Settings,
+) -> Color {
+    let foreground = color_from_argb(settings.foreground_color);
+    let background = color_from_argb(settings.background_color);
+
+    match pattern {
+        Pattern::Smpte => smpte_color(x, y, frame.width, frame.height),
+        Pattern::Checkers => if (x / CHECKER_SIZE + y / CHECKER_SIZE) % 2 == 0 {
+            foreground
+        } else {
+            background
+        },
+        Pattern::Ball => {
+            // Moves on a figure eight through the frame, once every 4 seconds at 30fps
+            let radius = frame.width.min(frame.height) as f64 / 10.0;
+            let t = frame.number as f64 * 2.0 * f64::consts::PI / 120.0;
+            let cx = frame.width as f64 / 2.0 + (frame.width as f64 / 2.0 - radius) * t.sin();
+            let cy =
+                frame.height as f64 / 2.0 + (frame.height as f64 / 2.0 - radius) * (2.0 * t).sin();
+
+            let (dx, dy) = (x as f64 + 0.5 - cx, y as f64 + 0.5 - cy);
+            if dx * dx + dy * dy <= radius * radius {
+                foreground
+            } else {
+                background
+            }
+        }
+        Pattern::SolidColor => foreground,
+    }
+}
+
+fn render(info: &gst_video::VideoInfo, data: &mut [u8], number: u64, settings: &Settings) {
+    let (bpp, offsets) = rgb::layout(info.format()).unwrap();
+    let stride = info.stride()[0] as usize;
+    let frame = Frame {
+        width: info.width() as usize,
+        height: info.height() as usize,
+        number: number,
+    };
+    // Alpha or padding byte of the 4 byte formats
+    let alpha = if bpp == 4 {
+        Some(6 - offsets.iter().sum::<usize>())
+    } else {
+        None
+    };
+
+    for (y, row) in data.chunks_mut(stride).take(frame.height).enumerate() {
+        for (x, pixel) in row[..(frame.width * bpp)].chunks_mut(bpp).enumerate() {
+            let color = pattern_color(settings.pattern, &frame, x, y, settings);
+            for (&offset, &c) in offsets.iter().zip(color.iter()) {
+                pixel[offset] = c;
+            }
+            if let Some(alpha) = alpha {
+                pixel[alpha] = color[3];
+            }
+        }
+    }
+}
+
+// Start time of a frame, without overflowing for long running streams
+pub fn frame_to_time(number: u64, fps: (u64, u64)) -> u64 {
+    let (numer, denom) = fps;
+    let t = number * denom;
+    t / numer * gst::SECOND_VAL + t % numer * gst::SECOND_VAL / numer
+}
+
+// First frame starting at or after `time`
+fn time_to_frame(time: u64, fps: (u64, u64)) -> u64 {
+    let (numer, denom) = fps;
+    let t = time / gst::SECOND_VAL * numer
+        + (time % gst::SECOND_VAL * numer + gst::SECOND_VAL - 1) / gst::SECOND_VAL;
+    (t + denom - 1) / denom
+}
+
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    // Number of the next frame and of the first frame after the segment
+    frame: u64,
+    frame_stop: Option<u64>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            info: None,
+            frame: 0,
+            frame_stop: None,
+        }
+    }
+}
+
+struct VideoTestSrc {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    idle: Idle,
+}
+
+impl VideoTestSrc {
+    fn new(_src: &BaseSrc) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "rsvideotestsrc",
+                gst::DebugColorFlags::empty(),
+                "Rust video test source",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+            idle: Idle::new(),
+        }
+    }
+
+    fn class_init(klass: &mut BaseSrcClass) {
+        klass.set_metadata(
+            "Video Test Source",
+            "Source/Video",
+            "Produces SMPTE bars, checkers, moving ball or solid color test video",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = rgb::caps();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &BaseSrc) -> Box<BaseSrcImpl<BaseSrc>> {
+        element.set_live(DEFAULT_IS_LIVE);
+        element.set_format(gst::Format::Time);
+
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+}
+
+impl ObjectImpl<BaseSrc> for VideoTestSrc {
+    fn set_property(&self, obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let element = obj.clone().downcast::<BaseSrc>().unwrap();
+
+        match *prop {
+            Property::String("pattern", ..) => {
+                let pattern = value.get::<String>();
+                match pattern.as_ref().and_then(|pattern| Pattern::from_str(pattern)) {
+                    Some(pattern) => self.settings.lock().unwrap().pattern = pattern,
+                    None => {
+                        gst_warning!(self.cat, obj: &element, "Invalid pattern {:?}", pattern);
+                    }
+                }
+            }
+            Property::UInt("foreground-color", ..) => {
+                self.settings.lock().unwrap().foreground_color = value.get().unwrap();
+            }
+            Property::UInt("background-color", ..) => {
+                self.settings.lock().unwrap().background_color = value.get().unwrap();
+            }
+            Property::Boolean("is-live", ..) => {
+                let is_live = value.get().unwrap();
+                self.settings.lock().unwrap().is_live = is_live;
+                element.set_live(is_live);
+            }
+            Property::Boolean("idle-when-unlinked", ..) => {
+                self.idle.set_enabled(value.get().unwrap());
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("pattern", ..) => Ok(settings.pattern.to_str().to_value()),
+            Property::UInt("foreground-color", ..) => Ok(settings.foreground_color.to_value()),
+            Property::UInt("background-color", ..) => Ok(settings.background_color.to_value()),
+            Property::Boolean("is-live", ..) => Ok(settings.is_live.to_value()),
+            Property::Boolean("idle-when-unlinked", ..) => Ok(self.idle.is_enabled().to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseSrc> for VideoTestSrc {}
+
+impl BaseSrcImpl<BaseSrc> for VideoTestSrc {
+    fn start(&self, _element: &BaseSrc) -> bool {
+        *self.state.lock().unwrap() = Default::default();
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseSrc) -> bool {
+        *self.state.lock().unwrap() = Default::default();
+
+        true
+    }
+
+    fn unlock(&self, _element: &BaseSrc) -> bool {
+        self.idle.unlock();
+        true
+    }
+
+    fn unlock_stop(&self, _element: &BaseSrc) -> bool {
+        self.idle.unlock_stop();
+        true
+    }
+
+    fn fixate(&self, element: &BaseSrc, mut caps: gst::Caps) -> gst::Caps {
+        {
+            let caps = caps.make_mut();
+            for i in 0..caps.get_size() {
+                let s = caps.get_mut_structure(i).unwrap();
+                s.fixate_field_nearest_int("width", DEFAULT_WIDTH);
+                s.fixate_field_nearest_int("height", DEFAULT_HEIGHT);
+                s.fixate_field_nearest_fraction(
+                    "framerate",
+                    gst::Fraction::new(DEFAULT_FPS.0, DEFAULT_FPS.1),
+                );
+            }
+        }
+
+        element.parent_fixate(caps)
+    }
+
+    fn set_caps(&self, element: &BaseSrc, caps: &gst::CapsRef) -> bool {
+        let info = match gst_video::VideoInfo::from_caps(&caps.to_owned()) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", caps);
+
+        let mut state = self.state.lock().unwrap();
+
+        // Keep the current position if the framerate changes
+        let fps = |info: &gst_video::VideoInfo| {
+            let fps = info.fps();
+            if *fps.numer() > 0 && *fps.denom() > 0 {
+                Some((*fps.numer() as u64, *fps.denom() as u64))
+            } else {
+                None
+            }
+        };
+        if let (Some(old_fps), Some(new_fps)) = (state.info.as_ref().and_then(&fps), fps(&info)) {
+            state.frame = time_to_frame(frame_to_time(state.frame, old_fps), new_fps);
+            state.frame_stop = state
+                .frame_stop
+                .map(|stop| time_to_frame(frame_to_time(stop, old_fps), new_fps));
+        }
+        state.info = Some(info);
+
+        true
+    }
+
+    fn is_seekable(&self, _element: &BaseSrc) -> bool {
+        !self.settings.lock().unwrap().is_live
+    }
+
+    fn do_seek(&self, element: &BaseSrc, segment: &mut gst::Segment) -> bool {
+        let segment = match segment.downcast_ref::<gst::ClockTime>() {
+            None => return false,
+            Some(segment) => segment,
+        };
+
+        if segment.get_rate() < 0.0 {
+            gst_error!(self.cat, obj: element, "Reverse playback not supported");
+            return false;
+        }
+
+        let start = segment.get_start().0.unwrap_or(0);
+        let mut state = self.state.lock().unwrap();
+        let fps = match state.info {
+            Some(ref info) if *info.fps().numer() > 0 && *info.fps().denom() > 0 => {
+                (*info.fps().numer() as u64, *info.fps().denom() as u64)
+            }
+            // Initial seek before negotiation, or still frame
+            _ if start == 0 => return true,
+            _ => return false,
+        };
+
+        state.frame = time_to_frame(start, fps);
+        state.frame_stop = segment
+            .get_stop()
+            .0
+            .map(|stop| time_to_frame(stop, fps));
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Seeked to frame {} - {:?}",
+            state.frame,
+            state.frame_stop
+        );
+
+        true
+    }
+
+    fn create(
+        &self,
+        element: &BaseSrc,
+        _offset: u64,
+        _length: u32,
+    ) -> Result<gst::Buffer, gst::FlowReturn> {
+        self.idle.wait_linked(self.cat, element)?;
+
+        let settings = *self.settings.lock().unwrap();
+
+        let mut state = self.state.lock().unwrap();
+        let info = match state.info {
+            None => {
+                gst_element_error!(element, gst::CoreError::Negotiation, ["Have no caps yet"]);
+                return Err(gst::FlowReturn::NotNegotiated);
+            }
+            Some(ref info) => info.clone(),
+        };
+
+        let fps = info.fps();
+        let fps = if *fps.numer() > 0 && *fps.denom() > 0 {
+            Some((*fps.numer() as u64, *fps.denom() as u64))
+        } else {
+            None
+        };
+
+        let at_end = match (fps, state.frame_stop) {
+            (None, _) => state.frame > 0,
+            (Some(_), Some(stop)) => state.frame >= stop,
+            (Some(_), None) => false,
+        };
+        if at_end {
+            gst_debug!(self.cat, obj: element, "At the end of the segment");
+            return Err(gst::FlowReturn::Eos);
+        }
+
+        let mut buffer = gst::Buffer::with_size(info.size()).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+
+            match fps {
+                Some(fps) => {
+                    let pts = frame_to_time(state.frame, fps);
+                    let end = frame_to_time(state.frame + 1, fps);
+                    buffer.set_pts(gst::ClockTime::from_nseconds(pts));
+                    buffer.set_duration(gst::ClockTime::from_nseconds(end - pts));
+                }
+                None => buffer.set_pts(gst::ClockTime::from_nseconds(0)),
+            }
+            buffer.set_offset(state.frame);
+            buffer.set_offset_end(state.frame + 1);
+
+            let mut map = buffer.map_writable().unwrap();
+            render(&info, map.as_mut_slice(), state.frame, &settings);
+        }
+
+        state.frame += 1;
+
+        gst_log!(self.cat, obj: element, "Produced buffer {:?}", buffer);
+
+        Ok(buffer)
+    }
+}
+
+struct VideoTestSrcStatic;
+
+impl ImplTypeStatic<BaseSrc> for VideoTestSrcStatic {
+    fn get_name(&self) -> &str {
+        "RsVideoTestSrc"
+    }
+
+    fn new(&self, element: &BaseSrc) -> Box<BaseSrcImpl<BaseSrc>> {
+        VideoTestSrc::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseSrcClass) {
+        VideoTestSrc::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let videotestsrc_static = VideoTestSrcStatic;
+    register_type(videotestsrc_static)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        let settings = Settings::default();
+        let frame = Frame {
+            width: 70,
+            height: 40,
+            number: 0,
+        };
+
+        // White, yellow and blue bars, reverse blue bars, -I and +4% PLUGE bar
+        let color = |x, y| pattern_color(Pattern::Smpte, &frame, x, y, &settings);
+        assert_eq!(color(0, 0), [191, 191, 191, 255]);
+        assert_eq!(color(15, 26), [191, 191, 0, 255]);
+        assert_eq!(color(69, 0), [0, 0, 191, 255]);
+        assert_eq!(color(0, 27), [0, 0, 191, 255]);
+        assert_eq!(color(12, 30), [0, 33, 76, 255]);
+        assert_eq!(color(58, 39), [10, 10, 10, 255]);
+
+        let color = |x, y| pattern_color(Pattern::Checkers, &frame, x, y, &settings);
+        assert_eq!(color(7, 7), [255, 255, 255, 255]);
+        assert_eq!(color(8, 7), [0, 0, 0, 255]);
+        assert_eq!(color(8, 8), [255, 255, 255, 255]);
+
+        // The ball starts in the center
+        let color = |x, y| pattern_color(Pattern::Ball, &frame, x, y, &settings);
+        assert_eq!(color(35, 20), [255, 255, 255, 255]);
+        assert_eq!(color(0, 0), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_frame_times() {
+        let ntsc = (30_000, 1001);
+        assert_eq!(frame_to_time(1, ntsc), 33_366_666);
+        assert_eq!(time_to_frame(33_366_666, ntsc), 1);
+        assert_eq!(time_to_frame(33_366_667, ntsc), 2);
+
+        // No overflow after a year
+        let year = 3600 * 24 * 365;
+        assert_eq!(frame_to_time(30 * year, (30, 1)), year * gst::SECOND_VAL);
+        assert_eq!(time_to_frame(year * gst::SECOND_VAL, (30, 1)), 30 * year);
+    }
+}
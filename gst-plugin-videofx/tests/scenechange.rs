@@ -0,0 +1,85 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate gst_plugin;
+extern crate gstreamer as gst;
+use gst::prelude::*;
+
+use gst_plugin::fixtures::*;
+use gst_plugin::test::*;
+
+const WIDTH: usize = 32;
+const HEIGHT: usize = 16;
+
+fn get_cuts(bus: &gst::Bus) -> Vec<u64> {
+    let mut cuts = Vec::new();
+    while let Some(msg) = bus.pop() {
+        if let gst::MessageView::Element(e) = msg.view() {
+            let s = e.get_structure().unwrap();
+            if s.get_name() == "scenechange" {
+                cuts.push(s.get::<u64>("running-time").unwrap());
+            }
+        }
+    }
+    cuts
+}
+
+#[test]
+fn test_cut() {
+    init();
+
+    let mut h = Harness::new("scenechange");
+    let bus = gst::Bus::new();
+    h.get_element().set_bus(Some(&bus));
+    h.get_element().set_property("min-interval", &0u64).unwrap();
+    h.set_src_caps(video_caps(VideoFormat::Bgrx, WIDTH, HEIGHT, 25));
+
+    let frames = vec![
+        solid_color_frame(VideoFormat::Bgrx, WIDTH, HEIGHT, Rgb::BLACK),
+        solid_color_frame(VideoFormat::Bgrx, WIDTH, HEIGHT, Rgb::BLACK),
+        solid_color_frame(VideoFormat::Bgrx, WIDTH, HEIGHT, Rgb::WHITE),
+        solid_color_frame(VideoFormat::Bgrx, WIDTH, HEIGHT, Rgb::WHITE),
+    ];
+    for (n, mut frame) in frames.into_iter().enumerate() {
+        set_video_timestamps(&mut frame, 25, n as u64);
+        let output = h.push_and_pull(frame).unwrap();
+        assert_eq!(output.get_pts(), gst::ClockTime::from(n as u64 * 40 * gst::MSECOND_VAL));
+    }
+
+    // Only the change from black to white is a cut
+    assert_eq!(get_cuts(&bus), vec![80 * gst::MSECOND_VAL]);
+}
+
+#[test]
+fn test_min_interval() {
+    init();
+
+    let mut h = Harness::new("scenechange");
+    let bus = gst::Bus::new();
+    h.get_element().set_bus(Some(&bus));
+    h.get_element()
+        .set_property("min-interval", &(100 * gst::MSECOND_VAL))
+        .unwrap();
+    h.set_src_caps(video_caps(VideoFormat::Bgrx, WIDTH, HEIGHT, 25));
+
+    // Alternating frames, every one of them would be a cut without min-interval
+    for n in 0..6 {
+        let mut frame = if n % 2 == 0 {
+            smpte_bars_frame(VideoFormat::Bgrx, WIDTH, HEIGHT)
+        } else {
+            solid_color_frame(VideoFormat::Bgrx, WIDTH, HEIGHT, Rgb::BLACK)
+        };
+        set_video_timestamps(&mut frame, 25, n);
+        assert!(h.push_and_pull(frame).is_some());
+    }
+
+    assert_eq!(
+        get_cuts(&bus),
+        vec![40 * gst::MSECOND_VAL, 160 * gst::MSECOND_VAL]
+    );
+}
@@ -0,0 +1,124 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// WebSocket framing
+//
+// Every buffer is sent as one binary message. Unless the "timestamps" property is disabled on
+// both sides, the buffer data is prefixed with a 17 byte header, all fields big endian:
+//
+// +-------+-----+----------+
+// | flags | pts | duration |
+// |  u8   | u64 |   u64    |
+// +-------+-----+----------+
+//
+// Timestamps are in nanoseconds, u64::MAX means none. Browsers can read the header with a
+// DataView and use the remaining data as is.
+//
+// If caps are sent in-band, they are sent as a text message with the serialized caps before the
+// first buffer and whenever they change. New clients get the current caps and all stream
+// header buffers (HEADER flag) since then before anything else, so that e.g. a WebM or
+// fragmented MP4 stream can be joined at any time.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::net::{SocketAddr, TcpStream};
+
+use tungstenite::client::AutoStream;
+use tungstenite::stream::Stream;
+
+pub const HEADER_SIZE: usize = 17;
+
+pub const FLAG_DISCONT: u8 = 0x01;
+pub const FLAG_DELTA_UNIT: u8 = 0x02;
+pub const FLAG_HEADER: u8 = 0x04;
+
+const NONE: u64 = u64::max_value();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferHeader {
+    pub flags: u8,
+    pub pts: Option<u64>,
+    pub duration: Option<u64>,
+}
+
+impl BufferHeader {
+    pub fn write(&self, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE + payload.len()];
+        data[0] = self.flags;
+        BigEndian::write_u64(&mut data[1..9], self.pts.unwrap_or(NONE));
+        BigEndian::write_u64(&mut data[9..17], self.duration.unwrap_or(NONE));
+        data[HEADER_SIZE..].copy_from_slice(payload);
+        data
+    }
+
+    pub fn parse(data: &[u8]) -> Option<(BufferHeader, &[u8])> {
+        if data.len() < HEADER_SIZE {
+            return None;
+        }
+
+        let time = |data: &[u8]| match BigEndian::read_u64(data) {
+            NONE => None,
+            time => Some(time),
+        };
+
+        let header = BufferHeader {
+            flags: data[0],
+            pts: time(&data[1..9]),
+            duration: time(&data[9..17]),
+        };
+
+        Some((header, &data[HEADER_SIZE..]))
+    }
+}
+
+// Parses "[address:]port"
+pub fn parse_address(address: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = address.parse::<u16>() {
+        return Ok(SocketAddr::new("0.0.0.0".parse().unwrap(), port));
+    }
+
+    address
+        .parse::<SocketAddr>()
+        .map_err(|_| format!("Invalid address '{}'", address))
+}
+
+// The underlying TCP connection, for setting timeouts and shutting it down from another thread
+pub fn tcp_stream(stream: &AutoStream) -> &TcpStream {
+    match *stream {
+        Stream::Plain(ref stream) => stream,
+        Stream::Tls(ref stream) => stream.get_ref(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_header() {
+        let header = BufferHeader {
+            flags: FLAG_DISCONT | FLAG_HEADER,
+            pts: Some(1_000_000_000),
+            duration: None,
+        };
+
+        let data = header.write(&[1, 2, 3]);
+        assert_eq!(
+            data,
+            vec![
+                0x05, 0, 0, 0, 0, 0x3b, 0x9a, 0xca, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                0xff, 0xff, 1, 2, 3,
+            ]
+        );
+        assert_eq!(BufferHeader::parse(&data), Some((header, &[1u8, 2, 3][..])));
+        assert_eq!(BufferHeader::parse(&data[..16]), None);
+
+        assert_eq!(parse_address("8080"), Ok("0.0.0.0:8080".parse().unwrap()));
+        assert!(parse_address("localhost").is_err());
+    }
+}
@@ -0,0 +1,511 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_sink::*;
+
+use std::io;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use tungstenite::{self, Message, WebSocket};
+use tungstenite::client::AutoStream;
+use tungstenite::stream::Stream;
+use url::Url;
+
+use protocol::{self, BufferHeader, FLAG_DELTA_UNIT, FLAG_DISCONT, FLAG_HEADER};
+
+const DEFAULT_LOCATION: Option<&str> = None;
+const DEFAULT_ADDRESS: &str = "0.0.0.0:8080";
+const DEFAULT_CAPS_IN_BAND: bool = true;
+const DEFAULT_TIMESTAMPS: bool = true;
+
+// Clients that can't take a message for this long are disconnected
+const WRITE_TIMEOUT: u64 = 1000;
+// Maximum time the accept thread blocks without checking for stopping
+const POLL_INTERVAL: u64 = 100;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: Option<String>,
+    address: String,
+    caps_in_band: bool,
+    timestamps: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: DEFAULT_LOCATION.map(String::from),
+            address: String::from(DEFAULT_ADDRESS),
+            caps_in_band: DEFAULT_CAPS_IN_BAND,
+            timestamps: DEFAULT_TIMESTAMPS,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 4] = [
+    Property::String(
+        "location",
+        "Location",
+        "ws:// or wss:// URL to connect to, or none to accept clients on the address",
+        DEFAULT_LOCATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "address",
+        "Address",
+        "[address:]port to accept clients on if no location is set",
+        Some(DEFAULT_ADDRESS),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "caps-in-band",
+        "Caps In-Band",
+        "Send the caps as text message before the buffers",
+        DEFAULT_CAPS_IN_BAND,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "timestamps",
+        "Timestamps",
+        "Prefix every buffer with its flags and timestamps",
+        DEFAULT_TIMESTAMPS,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+struct Peer {
+    addr: String,
+    socket: WebSocket<AutoStream>,
+}
+
+// Shared between the streaming thread and the accept thread
+#[derive(Default)]
+struct Peers {
+    peers: Vec<Peer>,
+    // Serialized caps and stream headers, sent to every new client first
+    caps: Option<String>,
+    headers: Vec<Vec<u8>>,
+}
+
+impl Peers {
+    fn send_initial(&self, socket: &mut WebSocket<AutoStream>) -> Result<(), tungstenite::Error> {
+        if let Some(ref caps) = self.caps {
+            socket.write_message(Message::Text(caps.clone()))?;
+        }
+        for header in &self.headers {
+            socket.write_message(Message::Binary(header.clone()))?;
+        }
+
+        Ok(())
+    }
+
+    // Sends the message to all peers and removes the ones that failed
+    fn send(&mut self, message: &Message) -> Vec<(String, tungstenite::Error)> {
+        let mut failed = Vec::new();
+
+        let mut i = 0;
+        while i < self.peers.len() {
+            match self.peers[i].socket.write_message(message.clone()) {
+                Ok(()) => i += 1,
+                Err(err) => {
+                    let peer = self.peers.remove(i);
+                    failed.push((peer.addr, err));
+                }
+            }
+        }
+
+        failed
+    }
+}
+
+struct State {
+    peers: Arc<Mutex<Peers>>,
+    // Connected to a server instead of accepting clients
+    client: bool,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+struct WebSocketSink {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl WebSocketSink {
+    fn new(_sink: &BaseSink) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "websocketsink",
+                gst::DebugColorFlags::empty(),
+                "WebSocket sink",
+            ),
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut BaseSinkClass) {
+        klass.set_metadata(
+            "WebSocket Sink",
+            "Sink/Network",
+            "Sends buffers as binary WebSocket messages to a server or to all connected clients",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &BaseSink) -> Box<BaseSinkImpl<BaseSink>> {
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    fn connect(&self, element: &BaseSink, location: &str) -> Result<Peer, String> {
+        let url =
+            Url::parse(location).map_err(|err| format!("Invalid URL '{}': {}", location, err))?;
+        if url.scheme() != "ws" && url.scheme() != "wss" {
+            return Err(format!("Unsupported URL '{}'", location));
+        }
+
+        let (socket, _) = tungstenite::connect(url)
+            .map_err(|err| format!("Failed to connect to {}: {}", location, err))?;
+        protocol::tcp_stream(socket.get_ref())
+            .set_write_timeout(Some(Duration::from_millis(WRITE_TIMEOUT)))
+            .map_err(|err| err.to_string())?;
+
+        gst_debug!(self.cat, obj: element, "Connected to {}", location);
+
+        Ok(Peer {
+            addr: String::from(location),
+            socket: socket,
+        })
+    }
+
+    fn run_listener(
+        cat: gst::DebugCategory,
+        element: gst::Element,
+        listener: TcpListener,
+        peers: Arc<Mutex<Peers>>,
+        stop: Arc<AtomicBool>,
+    ) {
+        let timeout = Some(Duration::from_millis(WRITE_TIMEOUT));
+
+        while !stop.load(Ordering::SeqCst) {
+            let (stream, addr) = match listener.accept() {
+                Ok(res) => res,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL));
+                    continue;
+                }
+                Err(err) => {
+                    gst_warning!(cat, obj: &element, "Failed to accept client: {}", err);
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL));
+                    continue;
+                }
+            };
+
+            // The handshake blocks, but not longer than the timeout
+            let res = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_read_timeout(timeout))
+                .and_then(|_| stream.set_write_timeout(timeout));
+            if let Err(err) = res {
+                gst_warning!(cat, obj: &element, "Failed to set up client {}: {}", addr, err);
+                continue;
+            }
+
+            let stream: AutoStream = Stream::Plain(stream);
+            let mut socket = match tungstenite::accept(stream) {
+                Ok(socket) => socket,
+                Err(err) => {
+                    gst_debug!(cat, obj: &element, "Handshake with {} failed: {}", addr, err);
+                    continue;
+                }
+            };
+
+            // Nothing else is sent while the client is catching up
+            let mut peers = peers.lock().unwrap();
+            if let Err(err) = peers.send_initial(&mut socket) {
+                gst_debug!(cat, obj: &element, "Failed to send to {}: {}", addr, err);
+                continue;
+            }
+
+            gst_debug!(cat, obj: &element, "Client {} connected", addr);
+            peers.peers.push(Peer {
+                addr: addr.to_string(),
+                socket: socket,
+            });
+        }
+    }
+}
+
+impl ObjectImpl<BaseSink> for WebSocketSink {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => {
+                settings.location = value.get();
+            }
+            Property::String("address", ..) => {
+                settings.address = value.get().unwrap_or_else(|| String::from(DEFAULT_ADDRESS));
+            }
+            Property::Boolean("caps-in-band", ..) => {
+                settings.caps_in_band = value.get().unwrap();
+            }
+            Property::Boolean("timestamps", ..) => {
+                settings.timestamps = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => Ok(settings.location.to_value()),
+            Property::String("address", ..) => Ok(settings.address.to_value()),
+            Property::Boolean("caps-in-band", ..) => Ok(settings.caps_in_band.to_value()),
+            Property::Boolean("timestamps", ..) => Ok(settings.timestamps.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseSink> for WebSocketSink {}
+
+impl BaseSinkImpl<BaseSink> for WebSocketSink {
+    fn start(&self, element: &BaseSink) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+        let peers = Arc::new(Mutex::new(Peers::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        if let Some(ref location) = settings.location {
+            match self.connect(element, location) {
+                Ok(peer) => peers.lock().unwrap().peers.push(peer),
+                Err(err) => {
+                    gst_element_error!(element, gst::ResourceError::OpenWrite, ["{}", err]);
+                    return false;
+                }
+            }
+
+            *self.state.lock().unwrap() = Some(State {
+                peers: peers,
+                client: true,
+                stop: stop,
+                thread: None,
+            });
+
+            return true;
+        }
+
+        let listener = protocol::parse_address(&settings.address).and_then(|address| {
+            TcpListener::bind(address)
+                .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+                .map_err(|err| format!("Failed to listen on {}: {}", address, err))
+        });
+        let listener = match listener {
+            Ok(listener) => listener,
+            Err(err) => {
+                gst_element_error!(element, gst::ResourceError::OpenWrite, ["{}", err]);
+                return false;
+            }
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Accepting clients on {}",
+            settings.address
+        );
+
+        let thread = {
+            let cat = self.cat;
+            let element = element.clone().upcast::<gst::Element>();
+            let peers = peers.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                WebSocketSink::run_listener(cat, element, listener, peers, stop);
+            })
+        };
+
+        *self.state.lock().unwrap() = Some(State {
+            peers: peers,
+            client: false,
+            stop: stop,
+            thread: Some(thread),
+        });
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseSink) -> bool {
+        let state = self.state.lock().unwrap().take();
+
+        if let Some(state) = state {
+            state.stop.store(true, Ordering::SeqCst);
+            if let Some(thread) = state.thread {
+                let _ = thread.join();
+            }
+
+            let mut peers = state.peers.lock().unwrap();
+            for peer in &mut peers.peers {
+                let _ = peer.socket.close(None);
+                let _ = peer.socket.write_pending();
+            }
+        }
+
+        true
+    }
+
+    fn set_caps(&self, element: &BaseSink, caps: &gst::CapsRef) -> bool {
+        if !self.settings.lock().unwrap().caps_in_band {
+            return true;
+        }
+
+        let state = self.state.lock().unwrap();
+        let state = match *state {
+            None => return false,
+            Some(ref state) => state,
+        };
+
+        gst_debug!(self.cat, obj: element, "Sending caps {}", caps);
+
+        // Stream headers belong to the previous caps
+        let caps = caps.to_string();
+        let mut peers = state.peers.lock().unwrap();
+        peers.caps = Some(caps.clone());
+        peers.headers.clear();
+
+        for (addr, err) in peers.send(&Message::Text(caps)) {
+            if state.client {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::Write,
+                    ["Failed to send to {}: {}", addr, err]
+                );
+                return false;
+            }
+            gst_debug!(self.cat, obj: element, "Client {} disconnected: {}", addr, err);
+        }
+
+        true
+    }
+
+    fn render(&self, element: &BaseSink, buffer: &gst::BufferRef) -> gst::FlowReturn {
+        let timestamps = self.settings.lock().unwrap().timestamps;
+
+        let map = match buffer.map_readable() {
+            None => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                return gst::FlowReturn::Error;
+            }
+            Some(map) => map,
+        };
+
+        let buffer_flags = buffer.get_flags();
+        let mut flags = 0;
+        if buffer_flags.contains(gst::BufferFlags::DISCONT) {
+            flags |= FLAG_DISCONT;
+        }
+        if buffer_flags.contains(gst::BufferFlags::DELTA_UNIT) {
+            flags |= FLAG_DELTA_UNIT;
+        }
+        if buffer_flags.contains(gst::BufferFlags::HEADER) {
+            flags |= FLAG_HEADER;
+        }
+
+        let data = if timestamps {
+            BufferHeader {
+                flags: flags,
+                pts: buffer.get_pts().0,
+                duration: buffer.get_duration().0,
+            }.write(map.as_slice())
+        } else {
+            map.as_slice().to_vec()
+        };
+
+        let state = self.state.lock().unwrap();
+        let state = match *state {
+            None => return gst::FlowReturn::Flushing,
+            Some(ref state) => state,
+        };
+
+        let mut peers = state.peers.lock().unwrap();
+        if flags & FLAG_HEADER != 0 {
+            peers.headers.push(data.clone());
+        }
+
+        gst_trace!(
+            self.cat,
+            obj: element,
+            "Sending {} bytes to {} peers",
+            data.len(),
+            peers.peers.len()
+        );
+
+        for (addr, err) in peers.send(&Message::Binary(data)) {
+            if state.client {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::Write,
+                    ["Failed to send to {}: {}", addr, err]
+                );
+                return gst::FlowReturn::Error;
+            }
+            gst_debug!(self.cat, obj: element, "Client {} disconnected: {}", addr, err);
+        }
+
+        gst::FlowReturn::Ok
+    }
+}
+
+struct WebSocketSinkStatic;
+
+impl ImplTypeStatic<BaseSink> for WebSocketSinkStatic {
+    fn get_name(&self) -> &str {
+        "WebSocketSink"
+    }
+
+    fn new(&self, element: &BaseSink) -> Box<BaseSinkImpl<BaseSink>> {
+        WebSocketSink::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseSinkClass) {
+        WebSocketSink::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let websocketsink_static = WebSocketSinkStatic;
+    register_type(websocketsink_static)
+}
@@ -0,0 +1,569 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib;
+use gst;
+use gst::prelude::*;
+
+use gst_plugin::properties::*;
+use gst_plugin::object::*;
+use gst_plugin::element::*;
+use gst_plugin::base_src::*;
+
+use std::io;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use tungstenite::{self, Message, WebSocket};
+use tungstenite::client::AutoStream;
+use tungstenite::stream::Stream;
+use url::Url;
+
+use protocol::{self, BufferHeader, FLAG_DELTA_UNIT, FLAG_DISCONT, FLAG_HEADER};
+
+const DEFAULT_LOCATION: Option<&str> = None;
+const DEFAULT_ADDRESS: &str = "0.0.0.0:8080";
+const DEFAULT_TIMESTAMPS: bool = true;
+
+// Maximum time the socket thread and the streaming thread block without checking for stopping
+const POLL_INTERVAL: u64 = 100;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: Option<String>,
+    address: String,
+    caps: Option<gst::Caps>,
+    timestamps: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: DEFAULT_LOCATION.map(String::from),
+            address: String::from(DEFAULT_ADDRESS),
+            caps: None,
+            timestamps: DEFAULT_TIMESTAMPS,
+        }
+    }
+}
+
+static PROPERTIES: [Property; 4] = [
+    Property::String(
+        "location",
+        "Location",
+        "ws:// or wss:// URL to connect to, or none to accept a client on the address",
+        DEFAULT_LOCATION,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::String(
+        "address",
+        "Address",
+        "[address:]port to accept a client on if no location is set",
+        Some(DEFAULT_ADDRESS),
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boxed(
+        "caps",
+        "Caps",
+        "Caps of the received stream if they are not sent in-band",
+        gst::Caps::static_type,
+        PropertyMutability::ReadWrite,
+    ),
+    Property::Boolean(
+        "timestamps",
+        "Timestamps",
+        "Every buffer is prefixed with its flags and timestamps",
+        DEFAULT_TIMESTAMPS,
+        PropertyMutability::ReadWrite,
+    ),
+];
+
+enum Item {
+    Caps(gst::Caps),
+    Buffer(gst::Buffer),
+    Eos,
+    Error(String),
+    Unlock,
+}
+
+struct Unlock {
+    flushing: bool,
+    sender: Option<mpsc::Sender<Item>>,
+}
+
+struct State {
+    receiver: mpsc::Receiver<Item>,
+    // Connection of the current peer, shut down when stopping to wake up the socket thread
+    stream: Arc<Mutex<Option<TcpStream>>>,
+    stop: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+struct WebSocketSrc {
+    cat: gst::DebugCategory,
+    settings: Mutex<Settings>,
+    // Caps received in-band
+    caps: Mutex<Option<gst::Caps>>,
+    state: Mutex<Option<State>>,
+    unlock: Mutex<Unlock>,
+}
+
+fn parse_message(message: Message, timestamps: bool) -> Option<Item> {
+    match message {
+        Message::Text(caps) => gst::Caps::from_string(&caps).map(Item::Caps),
+        Message::Binary(data) => {
+            if !timestamps {
+                return Some(Item::Buffer(gst::Buffer::from_mut_slice(data).unwrap()));
+            }
+
+            let (header, payload) = BufferHeader::parse(&data)?;
+            let mut buffer = gst::Buffer::from_mut_slice(payload.to_vec()).unwrap();
+            {
+                let buffer = buffer.get_mut().unwrap();
+                if let Some(pts) = header.pts {
+                    buffer.set_pts(gst::ClockTime::from_nseconds(pts));
+                }
+                if let Some(duration) = header.duration {
+                    buffer.set_duration(gst::ClockTime::from_nseconds(duration));
+                }
+
+                let mut flags = gst::BufferFlags::empty();
+                if header.flags & FLAG_DISCONT != 0 {
+                    flags |= gst::BufferFlags::DISCONT;
+                }
+                if header.flags & FLAG_DELTA_UNIT != 0 {
+                    flags |= gst::BufferFlags::DELTA_UNIT;
+                }
+                if header.flags & FLAG_HEADER != 0 {
+                    flags |= gst::BufferFlags::HEADER;
+                }
+                buffer.set_flags(flags);
+            }
+
+            Some(Item::Buffer(buffer))
+        }
+        _ => None,
+    }
+}
+
+impl WebSocketSrc {
+    fn new(_src: &BaseSrc) -> Self {
+        Self {
+            cat: gst::DebugCategory::new(
+                "websocketsrc",
+                gst::DebugColorFlags::empty(),
+                "WebSocket source",
+            ),
+            settings: Mutex::new(Default::default()),
+            caps: Mutex::new(None),
+            state: Mutex::new(None),
+            unlock: Mutex::new(Unlock {
+                flushing: false,
+                sender: None,
+            }),
+        }
+    }
+
+    fn class_init(klass: &mut BaseSrcClass) {
+        klass.set_metadata(
+            "WebSocket Source",
+            "Source/Network",
+            "Receives buffers as binary WebSocket messages from a server or a connected client",
+            "Sebastian Dröge <sebastian@centricular.com>",
+        );
+
+        let caps = gst::Caps::new_any();
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        );
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    fn init(element: &BaseSrc) -> Box<BaseSrcImpl<BaseSrc>> {
+        element.set_live_source(true);
+
+        let imp = Self::new(element);
+        Box::new(imp)
+    }
+
+    fn connect(&self, element: &BaseSrc, location: &str) -> Result<WebSocket<AutoStream>, String> {
+        let url =
+            Url::parse(location).map_err(|err| format!("Invalid URL '{}': {}", location, err))?;
+        if url.scheme() != "ws" && url.scheme() != "wss" {
+            return Err(format!("Unsupported URL '{}'", location));
+        }
+
+        let (socket, _) = tungstenite::connect(url)
+            .map_err(|err| format!("Failed to connect to {}: {}", location, err))?;
+
+        gst_debug!(self.cat, obj: element, "Connected to {}", location);
+
+        Ok(socket)
+    }
+
+    // Forwards all messages of one peer to the streaming thread until the connection is closed.
+    // Returns false if the streaming thread is gone.
+    fn read_messages(
+        cat: gst::DebugCategory,
+        element: &gst::Element,
+        mut socket: WebSocket<AutoStream>,
+        timestamps: bool,
+        sender: &mpsc::Sender<Item>,
+        stop: &AtomicBool,
+    ) -> Result<bool, String> {
+        while !stop.load(Ordering::SeqCst) {
+            let message = match socket.read_message() {
+                Ok(message) => message,
+                Err(tungstenite::Error::Io(ref err))
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    continue;
+                }
+                Err(tungstenite::Error::ConnectionClosed(..)) => return Ok(true),
+                Err(err) => return Err(err.to_string()),
+            };
+
+            match parse_message(message, timestamps) {
+                None => gst_debug!(cat, obj: element, "Ignoring invalid message"),
+                Some(item) => {
+                    if sender.send(item).is_err() {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn run_client(
+        cat: gst::DebugCategory,
+        element: gst::Element,
+        socket: WebSocket<AutoStream>,
+        timestamps: bool,
+        sender: mpsc::Sender<Item>,
+        stop: Arc<AtomicBool>,
+    ) {
+        let res = WebSocketSrc::read_messages(cat, &element, socket, timestamps, &sender, &stop);
+        let item = match res {
+            Ok(true) => Item::Eos,
+            Ok(false) => return,
+            Err(err) => Item::Error(err),
+        };
+        let _ = sender.send(item);
+    }
+
+    // Accepts one client at a time, the stream continues with the next client once the current
+    // one disconnects
+    fn run_listener(
+        cat: gst::DebugCategory,
+        element: gst::Element,
+        listener: TcpListener,
+        current: Arc<Mutex<Option<TcpStream>>>,
+        timestamps: bool,
+        sender: mpsc::Sender<Item>,
+        stop: Arc<AtomicBool>,
+    ) {
+        let poll_interval = Duration::from_millis(POLL_INTERVAL);
+
+        while !stop.load(Ordering::SeqCst) {
+            let (stream, addr) = match listener.accept() {
+                Ok(res) => res,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(poll_interval);
+                    continue;
+                }
+                Err(err) => {
+                    gst_warning!(cat, obj: &element, "Failed to accept client: {}", err);
+                    thread::sleep(poll_interval);
+                    continue;
+                }
+            };
+
+            let res = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_read_timeout(Some(poll_interval)))
+                .and_then(|_| stream.try_clone());
+            match res {
+                Ok(clone) => *current.lock().unwrap() = Some(clone),
+                Err(err) => {
+                    gst_warning!(cat, obj: &element, "Failed to set up client {}: {}", addr, err);
+                    continue;
+                }
+            }
+
+            let stream: AutoStream = Stream::Plain(stream);
+            let socket = match tungstenite::accept(stream) {
+                Ok(socket) => socket,
+                Err(err) => {
+                    gst_debug!(cat, obj: &element, "Handshake with {} failed: {}", addr, err);
+                    continue;
+                }
+            };
+
+            gst_debug!(cat, obj: &element, "Client {} connected", addr);
+            let res =
+                WebSocketSrc::read_messages(cat, &element, socket, timestamps, &sender, &stop);
+            *current.lock().unwrap() = None;
+            match res {
+                Ok(true) => gst_debug!(cat, obj: &element, "Client {} disconnected", addr),
+                Ok(false) => return,
+                Err(err) => gst_debug!(cat, obj: &element, "Client {} failed: {}", addr, err),
+            }
+        }
+    }
+}
+
+impl ObjectImpl<BaseSrc> for WebSocketSrc {
+    fn set_property(&self, _obj: &glib::Object, id: u32, value: &glib::Value) {
+        let prop = &PROPERTIES[id as usize];
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => {
+                settings.location = value.get();
+            }
+            Property::String("address", ..) => {
+                settings.address = value.get().unwrap_or_else(|| String::from(DEFAULT_ADDRESS));
+            }
+            Property::Boxed("caps", ..) => {
+                settings.caps = value.get();
+            }
+            Property::Boolean("timestamps", ..) => {
+                settings.timestamps = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: u32) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id as usize];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property::String("location", ..) => Ok(settings.location.to_value()),
+            Property::String("address", ..) => Ok(settings.address.to_value()),
+            Property::Boxed("caps", ..) => Ok(settings.caps.to_value()),
+            Property::Boolean("timestamps", ..) => Ok(settings.timestamps.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl<BaseSrc> for WebSocketSrc {}
+
+impl BaseSrcImpl<BaseSrc> for WebSocketSrc {
+    fn start(&self, element: &BaseSrc) -> bool {
+        let settings = self.settings.lock().unwrap().clone();
+        let (sender, receiver) = mpsc::channel();
+        let current = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let cat = self.cat;
+        let timestamps = settings.timestamps;
+
+        let thread = if let Some(ref location) = settings.location {
+            let socket = match self.connect(element, location) {
+                Ok(socket) => socket,
+                Err(err) => {
+                    gst_element_error!(element, gst::ResourceError::OpenRead, ["{}", err]);
+                    return false;
+                }
+            };
+
+            let res = {
+                let stream = protocol::tcp_stream(socket.get_ref());
+                stream
+                    .set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL)))
+                    .and_then(|_| stream.try_clone())
+            };
+            match res {
+                Ok(stream) => *current.lock().unwrap() = Some(stream),
+                Err(err) => {
+                    gst_element_error!(element, gst::ResourceError::OpenRead, ["{}", err]);
+                    return false;
+                }
+            }
+
+            let element = element.clone().upcast::<gst::Element>();
+            let sender = sender.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                WebSocketSrc::run_client(cat, element, socket, timestamps, sender, stop);
+            })
+        } else {
+            let listener = protocol::parse_address(&settings.address).and_then(|address| {
+                TcpListener::bind(address)
+                    .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+                    .map_err(|err| format!("Failed to listen on {}: {}", address, err))
+            });
+            let listener = match listener {
+                Ok(listener) => listener,
+                Err(err) => {
+                    gst_element_error!(element, gst::ResourceError::OpenRead, ["{}", err]);
+                    return false;
+                }
+            };
+
+            gst_debug!(self.cat, obj: element, "Accepting a client on {}", settings.address);
+
+            let element = element.clone().upcast::<gst::Element>();
+            let current = current.clone();
+            let sender = sender.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                WebSocketSrc::run_listener(
+                    cat,
+                    element,
+                    listener,
+                    current,
+                    timestamps,
+                    sender,
+                    stop,
+                );
+            })
+        };
+
+        self.unlock.lock().unwrap().sender = Some(sender);
+        *self.state.lock().unwrap() = Some(State {
+            receiver: receiver,
+            stream: current,
+            stop: stop,
+            thread: thread,
+        });
+
+        true
+    }
+
+    fn stop(&self, _element: &BaseSrc) -> bool {
+        self.unlock.lock().unwrap().sender = None;
+        let state = self.state.lock().unwrap().take();
+
+        if let Some(state) = state {
+            state.stop.store(true, Ordering::SeqCst);
+            if let Some(ref stream) = *state.stream.lock().unwrap() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+            let _ = state.thread.join();
+        }
+        *self.caps.lock().unwrap() = None;
+
+        true
+    }
+
+    fn create(
+        &self,
+        element: &BaseSrc,
+        _offset: u64,
+        _length: u32,
+    ) -> Result<gst::Buffer, gst::FlowReturn> {
+        let state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            None => return Err(gst::FlowReturn::Flushing),
+            Some(ref state) => state,
+        };
+
+        loop {
+            if self.unlock.lock().unwrap().flushing {
+                gst_debug!(self.cat, obj: element, "Flushing");
+                return Err(gst::FlowReturn::Flushing);
+            }
+
+            let timeout = Duration::from_millis(POLL_INTERVAL);
+            match state.receiver.recv_timeout(timeout) {
+                Ok(Item::Caps(caps)) => {
+                    gst_debug!(self.cat, obj: element, "Received caps {}", caps);
+                    *self.caps.lock().unwrap() = Some(caps.clone());
+
+                    // Sent directly so that they apply to the following buffers
+                    let srcpad = element.get_static_pad("src").unwrap();
+                    srcpad.push_event(gst::Event::new_caps(&caps).build());
+                }
+                Ok(Item::Buffer(buffer)) => {
+                    gst_log!(self.cat, obj: element, "Produced buffer {:?}", buffer);
+                    return Ok(buffer);
+                }
+                Ok(Item::Eos) => {
+                    gst_debug!(self.cat, obj: element, "Connection closed");
+                    return Err(gst::FlowReturn::Eos);
+                }
+                Ok(Item::Error(err)) => {
+                    gst_element_error!(element, gst::ResourceError::Read, ["{}", err]);
+                    return Err(gst::FlowReturn::Error);
+                }
+                // Checked at the beginning of the loop, stale unlocks are ignored
+                Ok(Item::Unlock) | Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(gst::FlowReturn::Flushing);
+                }
+            }
+        }
+    }
+
+    fn get_caps(&self, element: &BaseSrc, filter: Option<&gst::CapsRef>) -> Option<gst::Caps> {
+        let caps = self.caps
+            .lock()
+            .unwrap()
+            .clone()
+            .or_else(|| self.settings.lock().unwrap().caps.clone());
+        let caps = match caps {
+            None => return element.parent_get_caps(filter),
+            Some(caps) => caps,
+        };
+
+        match filter {
+            None => Some(caps),
+            Some(filter) => Some(filter.intersect_with_mode(&caps, gst::CapsIntersectMode::First)),
+        }
+    }
+
+    fn unlock(&self, _element: &BaseSrc) -> bool {
+        let mut unlock = self.unlock.lock().unwrap();
+        unlock.flushing = true;
+        if let Some(ref sender) = unlock.sender {
+            let _ = sender.send(Item::Unlock);
+        }
+
+        true
+    }
+
+    fn unlock_stop(&self, _element: &BaseSrc) -> bool {
+        self.unlock.lock().unwrap().flushing = false;
+
+        true
+    }
+}
+
+struct WebSocketSrcStatic;
+
+impl ImplTypeStatic<BaseSrc> for WebSocketSrcStatic {
+    fn get_name(&self) -> &str {
+        "WebSocketSrc"
+    }
+
+    fn new(&self, element: &BaseSrc) -> Box<BaseSrcImpl<BaseSrc>> {
+        WebSocketSrc::init(element)
+    }
+
+    fn class_init(&self, klass: &mut BaseSrcClass) {
+        WebSocketSrc::class_init(klass);
+    }
+}
+
+pub fn get_type() -> glib::Type {
+    let websocketsrc_static = WebSocketSrcStatic;
+    register_type(websocketsrc_static)
+}
@@ -0,0 +1,793 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::ptr;
+
+use libc;
+
+use glib_ffi;
+use gobject_ffi;
+use gst_ffi;
+use gst_base_ffi;
+
+use glib;
+use glib::translate::*;
+use gst;
+use gst::prelude::*;
+
+use object::*;
+use element::*;
+use anyimpl::*;
+
+/// Subclass of `GstAggregator`.
+///
+/// Sink pads are always `GstAggregatorPad`s, either plain ones or instances of a subclass
+/// returned from `create_new_pad()`. The `AggregatorPadExt` trait gives access to the queued
+/// buffers of each of them.
+pub trait AggregatorImpl<T: AggregatorBase>
+    : AnyImpl + ObjectImpl<T> + ElementImpl<T> + Send + Sync + 'static {
+    fn start(&self, element: &T) -> bool {
+        element.parent_start()
+    }
+
+    fn stop(&self, element: &T) -> bool {
+        element.parent_stop()
+    }
+
+    fn flush(&self, element: &T) -> gst::FlowReturn {
+        element.parent_flush()
+    }
+
+    fn clip(&self, element: &T, pad: &gst::Pad, buffer: gst::Buffer) -> Option<gst::Buffer> {
+        element.parent_clip(pad, buffer)
+    }
+
+    fn sink_event(&self, element: &T, pad: &gst::Pad, event: gst::Event) -> bool {
+        element.parent_sink_event(pad, event)
+    }
+
+    fn sink_query(&self, element: &T, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+        element.parent_sink_query(pad, query)
+    }
+
+    fn src_event(&self, element: &T, event: gst::Event) -> bool {
+        element.parent_src_event(event)
+    }
+
+    fn src_query(&self, element: &T, query: &mut gst::QueryRef) -> bool {
+        element.parent_src_query(query)
+    }
+
+    /// Produces output from the queued buffers of the sink pads and pushes it with
+    /// `finish_buffer()`.
+    ///
+    /// Called once every sink pad that is not EOS has a buffer queued, or with `timeout` set
+    /// in live pipelines once the deadline of the next output buffer is reached.
+    ///
+    /// Must be implemented by direct subclasses of `Aggregator`, subclasses of e.g.
+    /// `AudioAggregator` can rely on the implementation of their base class.
+    fn aggregate(&self, element: &T, timeout: bool) -> gst::FlowReturn {
+        element.parent_aggregate(timeout)
+    }
+
+    /// Running time at which the next output buffer is due in live pipelines.
+    fn get_next_time(&self, element: &T) -> gst::ClockTime {
+        element.parent_get_next_time()
+    }
+
+    /// Creates a new sink pad for the given template. The pad must be a `GstAggregatorPad` and
+    /// is added to the element by the caller.
+    fn create_new_pad(
+        &self,
+        element: &T,
+        templ: &gst::PadTemplate,
+        req_name: Option<String>,
+        caps: Option<&gst::CapsRef>,
+    ) -> Option<gst::Pad> {
+        element.parent_create_new_pad(templ, req_name, caps)
+    }
+
+    fn update_src_caps(
+        &self,
+        element: &T,
+        caps: &gst::Caps,
+    ) -> Result<gst::Caps, gst::FlowReturn> {
+        element.parent_update_src_caps(caps)
+    }
+
+    fn fixate_src_caps(&self, element: &T, caps: gst::Caps) -> gst::Caps {
+        element.parent_fixate_src_caps(caps)
+    }
+
+    fn negotiated_src_caps(&self, element: &T, caps: &gst::Caps) -> bool {
+        element.parent_negotiated_src_caps(caps)
+    }
+}
+
+any_impl!(AggregatorBase, AggregatorImpl);
+
+/// Implemented for `Aggregator` and the wrappers of its subclasses, e.g. `AudioAggregator`.
+pub unsafe trait AggregatorBase: IsA<gst::Element> + ObjectType {
+    fn get_src_pad(&self) -> gst::Pad {
+        unsafe {
+            from_glib_none((*aggregator_ptr(self)).srcpad)
+        }
+    }
+
+    fn finish_buffer(&self, buffer: gst::Buffer) -> gst::FlowReturn {
+        unsafe {
+            from_glib(gst_base_ffi::gst_aggregator_finish_buffer(
+                aggregator_ptr(self),
+                buffer.into_ptr(),
+            ))
+        }
+    }
+
+    /// Latency added by the subclass on top of the upstream latency.
+    fn set_latency(&self, min: gst::ClockTime, max: gst::ClockTime) {
+        unsafe {
+            gst_base_ffi::gst_aggregator_set_latency(
+                aggregator_ptr(self),
+                min.to_glib(),
+                max.to_glib(),
+            );
+        }
+    }
+
+    fn parent_start(&self) -> bool {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            (*parent_klass)
+                .start
+                .map(|f| from_glib(f(aggregator_ptr(self))))
+                .unwrap_or(true)
+        }
+    }
+
+    fn parent_stop(&self) -> bool {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            (*parent_klass)
+                .stop
+                .map(|f| from_glib(f(aggregator_ptr(self))))
+                .unwrap_or(true)
+        }
+    }
+
+    fn parent_flush(&self) -> gst::FlowReturn {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            (*parent_klass)
+                .flush
+                .map(|f| from_glib(f(aggregator_ptr(self))))
+                .unwrap_or(gst::FlowReturn::Ok)
+        }
+    }
+
+    fn parent_clip(&self, pad: &gst::Pad, buffer: gst::Buffer) -> Option<gst::Buffer> {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            let pad: *mut gst_ffi::GstPad = pad.to_glib_none().0;
+            match (*parent_klass).clip {
+                None => Some(buffer),
+                Some(f) => from_glib_full(f(
+                    aggregator_ptr(self),
+                    pad as *mut gst_base_ffi::GstAggregatorPad,
+                    buffer.into_ptr(),
+                )),
+            }
+        }
+    }
+
+    fn parent_sink_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            let pad: *mut gst_ffi::GstPad = pad.to_glib_none().0;
+            (*parent_klass)
+                .sink_event
+                .map(|f| {
+                    from_glib(f(
+                        aggregator_ptr(self),
+                        pad as *mut gst_base_ffi::GstAggregatorPad,
+                        event.into_ptr(),
+                    ))
+                })
+                .unwrap_or(false)
+        }
+    }
+
+    fn parent_sink_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            let pad: *mut gst_ffi::GstPad = pad.to_glib_none().0;
+            (*parent_klass)
+                .sink_query
+                .map(|f| {
+                    from_glib(f(
+                        aggregator_ptr(self),
+                        pad as *mut gst_base_ffi::GstAggregatorPad,
+                        query.as_mut_ptr(),
+                    ))
+                })
+                .unwrap_or(false)
+        }
+    }
+
+    fn parent_src_event(&self, event: gst::Event) -> bool {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            (*parent_klass)
+                .src_event
+                .map(|f| from_glib(f(aggregator_ptr(self), event.into_ptr())))
+                .unwrap_or(false)
+        }
+    }
+
+    fn parent_src_query(&self, query: &mut gst::QueryRef) -> bool {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            (*parent_klass)
+                .src_query
+                .map(|f| from_glib(f(aggregator_ptr(self), query.as_mut_ptr())))
+                .unwrap_or(false)
+        }
+    }
+
+    fn parent_aggregate(&self, timeout: bool) -> gst::FlowReturn {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            (*parent_klass)
+                .aggregate
+                .map(|f| from_glib(f(aggregator_ptr(self), timeout.to_glib())))
+                .unwrap_or(gst::FlowReturn::Error)
+        }
+    }
+
+    fn parent_get_next_time(&self) -> gst::ClockTime {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            (*parent_klass)
+                .get_next_time
+                .map(|f| from_glib(f(aggregator_ptr(self))))
+                .unwrap_or(gst::CLOCK_TIME_NONE)
+        }
+    }
+
+    fn parent_create_new_pad(
+        &self,
+        templ: &gst::PadTemplate,
+        req_name: Option<String>,
+        caps: Option<&gst::CapsRef>,
+    ) -> Option<gst::Pad> {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            (*parent_klass).create_new_pad.and_then(|f| {
+                let caps = caps.map(|caps| caps.as_ptr()).unwrap_or(ptr::null());
+                from_glib_none(f(
+                    aggregator_ptr(self),
+                    templ.to_glib_none().0,
+                    req_name.to_glib_none().0,
+                    caps,
+                ) as *mut gst_ffi::GstPad)
+            })
+        }
+    }
+
+    fn parent_update_src_caps(&self, caps: &gst::Caps) -> Result<gst::Caps, gst::FlowReturn> {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            match (*parent_klass).update_src_caps {
+                None => Ok(caps.clone()),
+                Some(f) => {
+                    let mut ret = ptr::null_mut();
+                    let flow: gst::FlowReturn =
+                        from_glib(f(aggregator_ptr(self), caps.to_glib_none().0, &mut ret));
+                    if flow == gst::FlowReturn::Ok {
+                        Ok(from_glib_full(ret))
+                    } else {
+                        Err(flow)
+                    }
+                }
+            }
+        }
+    }
+
+    fn parent_fixate_src_caps(&self, caps: gst::Caps) -> gst::Caps {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            match (*parent_klass).fixate_src_caps {
+                None => caps.fixate(),
+                Some(f) => from_glib_full(f(aggregator_ptr(self), caps.into_ptr())),
+            }
+        }
+    }
+
+    fn parent_negotiated_src_caps(&self, caps: &gst::Caps) -> bool {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorClass;
+            (*parent_klass)
+                .negotiated_src_caps
+                .map(|f| from_glib(f(aggregator_ptr(self), caps.to_glib_none().0)))
+                .unwrap_or(true)
+        }
+    }
+}
+
+// Aggregator is not bound in gstreamer-base yet
+unsafe fn aggregator_ptr<T: AggregatorBase>(element: &T) -> *mut gst_base_ffi::GstAggregator {
+    let ptr: *mut gst_ffi::GstElement = element.to_glib_none().0;
+    ptr as *mut gst_base_ffi::GstAggregator
+}
+
+pub unsafe trait AggregatorClassExt<T: AggregatorBase>
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    fn override_vfuncs(&mut self, _: &ClassInitToken) {
+        unsafe {
+            let klass = &mut *(self as *const Self as *mut gst_base_ffi::GstAggregatorClass);
+            klass.start = Some(aggregator_start::<T>);
+            klass.stop = Some(aggregator_stop::<T>);
+            klass.flush = Some(aggregator_flush::<T>);
+            klass.clip = Some(aggregator_clip::<T>);
+            klass.sink_event = Some(aggregator_sink_event::<T>);
+            klass.sink_query = Some(aggregator_sink_query::<T>);
+            klass.src_event = Some(aggregator_src_event::<T>);
+            klass.src_query = Some(aggregator_src_query::<T>);
+            klass.aggregate = Some(aggregator_aggregate::<T>);
+            klass.get_next_time = Some(aggregator_get_next_time::<T>);
+            klass.create_new_pad = Some(aggregator_create_new_pad::<T>);
+            klass.update_src_caps = Some(aggregator_update_src_caps::<T>);
+            klass.fixate_src_caps = Some(aggregator_fixate_src_caps::<T>);
+            klass.negotiated_src_caps = Some(aggregator_negotiated_src_caps::<T>);
+        }
+
+        // GstAggregator handles pad requests itself and calls create_new_pad() from there
+        unsafe {
+            let klass = &mut *(self as *const Self as *mut gst_ffi::GstElementClass);
+            let parent_klass = &*((*(self as *const Self as *const ClassStruct<T>))
+                .get_parent_class() as *const gst_ffi::GstElementClass);
+            klass.request_new_pad = parent_klass.request_new_pad;
+            klass.release_pad = parent_klass.release_pad;
+        }
+    }
+}
+
+glib_wrapper! {
+    pub struct Aggregator(Object<InstanceStruct<Aggregator>>): [gst::Element => gst_ffi::GstElement,
+                                                                gst::Object => gst_ffi::GstObject];
+
+    match fn {
+        get_type => || get_type::<Aggregator>(),
+    }
+}
+
+unsafe impl AggregatorBase for Aggregator {}
+pub type AggregatorClass = ClassStruct<Aggregator>;
+
+// FIXME: Boilerplate
+unsafe impl AggregatorClassExt<Aggregator> for AggregatorClass {}
+unsafe impl ElementClassExt<Aggregator> for AggregatorClass {}
+
+#[macro_export]
+macro_rules! box_aggregator_impl(
+    ($name:ident) => {
+        box_element_impl!($name);
+
+        impl<T: AggregatorBase> AggregatorImpl<T> for Box<$name<T>> {
+            fn start(&self, element: &T) -> bool {
+                let imp: &$name<T> = self.as_ref();
+                imp.start(element)
+            }
+
+            fn stop(&self, element: &T) -> bool {
+                let imp: &$name<T> = self.as_ref();
+                imp.stop(element)
+            }
+
+            fn flush(&self, element: &T) -> gst::FlowReturn {
+                let imp: &$name<T> = self.as_ref();
+                imp.flush(element)
+            }
+
+            fn clip(&self, element: &T, pad: &gst::Pad, buffer: gst::Buffer) -> Option<gst::Buffer> {
+                let imp: &$name<T> = self.as_ref();
+                imp.clip(element, pad, buffer)
+            }
+
+            fn sink_event(&self, element: &T, pad: &gst::Pad, event: gst::Event) -> bool {
+                let imp: &$name<T> = self.as_ref();
+                imp.sink_event(element, pad, event)
+            }
+
+            fn sink_query(&self, element: &T, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+                let imp: &$name<T> = self.as_ref();
+                imp.sink_query(element, pad, query)
+            }
+
+            fn src_event(&self, element: &T, event: gst::Event) -> bool {
+                let imp: &$name<T> = self.as_ref();
+                imp.src_event(element, event)
+            }
+
+            fn src_query(&self, element: &T, query: &mut gst::QueryRef) -> bool {
+                let imp: &$name<T> = self.as_ref();
+                imp.src_query(element, query)
+            }
+
+            fn aggregate(&self, element: &T, timeout: bool) -> gst::FlowReturn {
+                let imp: &$name<T> = self.as_ref();
+                imp.aggregate(element, timeout)
+            }
+
+            fn get_next_time(&self, element: &T) -> gst::ClockTime {
+                let imp: &$name<T> = self.as_ref();
+                imp.get_next_time(element)
+            }
+
+            fn create_new_pad(&self, element: &T, templ: &gst::PadTemplate, req_name: Option<String>, caps: Option<&gst::CapsRef>) -> Option<gst::Pad> {
+                let imp: &$name<T> = self.as_ref();
+                imp.create_new_pad(element, templ, req_name, caps)
+            }
+
+            fn update_src_caps(&self, element: &T, caps: &gst::Caps) -> Result<gst::Caps, gst::FlowReturn> {
+                let imp: &$name<T> = self.as_ref();
+                imp.update_src_caps(element, caps)
+            }
+
+            fn fixate_src_caps(&self, element: &T, caps: gst::Caps) -> gst::Caps {
+                let imp: &$name<T> = self.as_ref();
+                imp.fixate_src_caps(element, caps)
+            }
+
+            fn negotiated_src_caps(&self, element: &T, caps: &gst::Caps) -> bool {
+                let imp: &$name<T> = self.as_ref();
+                imp.negotiated_src_caps(element, caps)
+            }
+        }
+    };
+);
+box_aggregator_impl!(AggregatorImpl);
+
+impl ObjectType for Aggregator {
+    const NAME: &'static str = "RsAggregator";
+    type GlibType = gst_base_ffi::GstAggregator;
+    type GlibClassType = gst_base_ffi::GstAggregatorClass;
+    type ImplType = Box<AggregatorImpl<Self>>;
+
+    fn glib_type() -> glib::Type {
+        unsafe { from_glib(gst_base_ffi::gst_aggregator_get_type()) }
+    }
+
+    fn class_init(token: &ClassInitToken, klass: &mut AggregatorClass) {
+        ElementClassExt::override_vfuncs(klass, token);
+        AggregatorClassExt::override_vfuncs(klass, token);
+    }
+
+    object_type_fns!();
+}
+
+unsafe extern "C" fn aggregator_start<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+) -> glib_ffi::gboolean
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, false, {
+        imp.start(&wrap)
+    }).to_glib()
+}
+
+unsafe extern "C" fn aggregator_stop<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+) -> glib_ffi::gboolean
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, false, {
+        imp.stop(&wrap)
+    }).to_glib()
+}
+
+unsafe extern "C" fn aggregator_flush<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+) -> gst_ffi::GstFlowReturn
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, gst::FlowReturn::Error, {
+        imp.flush(&wrap)
+    }).to_glib()
+}
+
+unsafe extern "C" fn aggregator_clip<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+    pad: *mut gst_base_ffi::GstAggregatorPad,
+    buffer: *mut gst_ffi::GstBuffer,
+) -> *mut gst_ffi::GstBuffer
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, None, {
+        imp.clip(
+            &wrap,
+            &from_glib_borrow(pad as *mut gst_ffi::GstPad),
+            from_glib_full(buffer),
+        )
+    }).map(|buffer| buffer.into_ptr())
+        .unwrap_or(ptr::null_mut())
+}
+
+unsafe extern "C" fn aggregator_sink_event<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+    pad: *mut gst_base_ffi::GstAggregatorPad,
+    event: *mut gst_ffi::GstEvent,
+) -> glib_ffi::gboolean
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, false, {
+        imp.sink_event(
+            &wrap,
+            &from_glib_borrow(pad as *mut gst_ffi::GstPad),
+            from_glib_full(event),
+        )
+    }).to_glib()
+}
+
+unsafe extern "C" fn aggregator_sink_query<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+    pad: *mut gst_base_ffi::GstAggregatorPad,
+    query: *mut gst_ffi::GstQuery,
+) -> glib_ffi::gboolean
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, false, {
+        imp.sink_query(
+            &wrap,
+            &from_glib_borrow(pad as *mut gst_ffi::GstPad),
+            gst::QueryRef::from_mut_ptr(query),
+        )
+    }).to_glib()
+}
+
+unsafe extern "C" fn aggregator_src_event<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+    event: *mut gst_ffi::GstEvent,
+) -> glib_ffi::gboolean
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, false, {
+        imp.src_event(&wrap, from_glib_full(event))
+    }).to_glib()
+}
+
+unsafe extern "C" fn aggregator_src_query<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+    query: *mut gst_ffi::GstQuery,
+) -> glib_ffi::gboolean
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, false, {
+        imp.src_query(&wrap, gst::QueryRef::from_mut_ptr(query))
+    }).to_glib()
+}
+
+unsafe extern "C" fn aggregator_aggregate<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+    timeout: glib_ffi::gboolean,
+) -> gst_ffi::GstFlowReturn
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, gst::FlowReturn::Error, {
+        imp.aggregate(&wrap, from_glib(timeout))
+    }).to_glib()
+}
+
+unsafe extern "C" fn aggregator_get_next_time<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+) -> gst_ffi::GstClockTime
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, gst::CLOCK_TIME_NONE, {
+        imp.get_next_time(&wrap)
+    }).to_glib()
+}
+
+unsafe extern "C" fn aggregator_create_new_pad<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+    templ: *mut gst_ffi::GstPadTemplate,
+    req_name: *const libc::c_char,
+    caps: *const gst_ffi::GstCaps,
+) -> *mut gst_base_ffi::GstAggregatorPad
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    let pad = panic_to_error!(&wrap, &element.panicked, None, {
+        let caps = if caps.is_null() {
+            None
+        } else {
+            Some(gst::CapsRef::from_ptr(caps))
+        };
+
+        imp.create_new_pad(&wrap, &from_glib_borrow(templ), from_glib_none(req_name), caps)
+    });
+
+    match pad {
+        None => ptr::null_mut(),
+        Some(pad) => {
+            // The caller adds the pad to the element and expects a floating reference
+            let pad_ptr: *mut gst_ffi::GstPad = pad.to_glib_full();
+            drop(pad);
+            gobject_ffi::g_object_force_floating(pad_ptr as *mut gobject_ffi::GObject);
+            pad_ptr as *mut gst_base_ffi::GstAggregatorPad
+        }
+    }
+}
+
+unsafe extern "C" fn aggregator_update_src_caps<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+    caps: *mut gst_ffi::GstCaps,
+    ret: *mut *mut gst_ffi::GstCaps,
+) -> gst_ffi::GstFlowReturn
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    *ret = ptr::null_mut();
+
+    panic_to_error!(&wrap, &element.panicked, gst::FlowReturn::Error, {
+        match imp.update_src_caps(&wrap, &from_glib_borrow(caps)) {
+            Ok(caps) => {
+                *ret = caps.into_ptr();
+                gst::FlowReturn::Ok
+            }
+            Err(flow) => flow,
+        }
+    }).to_glib()
+}
+
+unsafe extern "C" fn aggregator_fixate_src_caps<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+    caps: *mut gst_ffi::GstCaps,
+) -> *mut gst_ffi::GstCaps
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, gst::Caps::new_empty(), {
+        imp.fixate_src_caps(&wrap, from_glib_full(caps))
+    }).into_ptr()
+}
+
+unsafe extern "C" fn aggregator_negotiated_src_caps<T: AggregatorBase>(
+    ptr: *mut gst_base_ffi::GstAggregator,
+    caps: *mut gst_ffi::GstCaps,
+) -> glib_ffi::gboolean
+where
+    T::ImplType: AggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, false, {
+        imp.negotiated_src_caps(&wrap, &from_glib_borrow(caps))
+    }).to_glib()
+}
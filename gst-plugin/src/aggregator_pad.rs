@@ -0,0 +1,257 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib_ffi;
+use gobject_ffi;
+use gst_ffi;
+use gst_base_ffi;
+
+use glib;
+use glib::translate::*;
+use gst;
+use gst::prelude::*;
+
+use object::*;
+use anyimpl::*;
+
+/// Subclass of `GstAggregatorPad`, e.g. for pads with per-input properties.
+///
+/// Instances are created from `AggregatorImpl::create_new_pad()`.
+pub trait AggregatorPadImpl<T: AggregatorPadBase>
+    : AnyImpl + ObjectImpl<T> + Send + Sync + 'static {
+    fn flush(&self, pad: &T, aggregator: &gst::Element) -> gst::FlowReturn {
+        pad.parent_flush(aggregator)
+    }
+
+    fn skip_buffer(&self, pad: &T, aggregator: &gst::Element, buffer: &gst::Buffer) -> bool {
+        pad.parent_skip_buffer(aggregator, buffer)
+    }
+}
+
+any_impl!(AggregatorPadBase, AggregatorPadImpl);
+
+/// Implemented for `AggregatorPad` and the wrappers of its subclasses, e.g.
+/// `AudioAggregatorPad`.
+pub unsafe trait AggregatorPadBase: IsA<gst::Pad> + ObjectType {
+    fn parent_flush(&self, aggregator: &gst::Element) -> gst::FlowReturn {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorPadClass;
+            let pad: *mut gst_ffi::GstPad = self.to_glib_none().0;
+            let aggregator: *mut gst_ffi::GstElement = aggregator.to_glib_none().0;
+            (*parent_klass)
+                .flush
+                .map(|f| {
+                    from_glib(f(
+                        pad as *mut gst_base_ffi::GstAggregatorPad,
+                        aggregator as *mut gst_base_ffi::GstAggregator,
+                    ))
+                })
+                .unwrap_or(gst::FlowReturn::Ok)
+        }
+    }
+
+    fn parent_skip_buffer(&self, aggregator: &gst::Element, buffer: &gst::Buffer) -> bool {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_base_ffi::GstAggregatorPadClass;
+            let pad: *mut gst_ffi::GstPad = self.to_glib_none().0;
+            let aggregator: *mut gst_ffi::GstElement = aggregator.to_glib_none().0;
+            (*parent_klass)
+                .skip_buffer
+                .map(|f| {
+                    from_glib(f(
+                        pad as *mut gst_base_ffi::GstAggregatorPad,
+                        aggregator as *mut gst_base_ffi::GstAggregator,
+                        buffer.to_glib_none().0,
+                    ))
+                })
+                .unwrap_or(false)
+        }
+    }
+}
+
+/// Access to the buffers queued on the sink pads of an aggregator.
+///
+/// Only valid for pads that are `GstAggregatorPad`s, i.e. the sink pads of an aggregator and
+/// its source pad.
+pub trait AggregatorPadExt {
+    /// Removes the next queued buffer from the pad and returns it.
+    fn pop_buffer(&self) -> Option<gst::Buffer>;
+
+    /// Returns the next queued buffer without removing it from the pad.
+    fn peek_buffer(&self) -> Option<gst::Buffer>;
+
+    /// Drops the next queued buffer, returns `false` if there was none.
+    fn drop_buffer(&self) -> bool;
+
+    /// Whether the pad received EOS and has no buffers queued anymore.
+    fn is_eos(&self) -> bool;
+
+    /// Current segment of the pad.
+    fn get_segment(&self) -> gst::Segment;
+}
+
+impl<O: IsA<gst::Pad>> AggregatorPadExt for O {
+    fn pop_buffer(&self) -> Option<gst::Buffer> {
+        unsafe {
+            from_glib_full(gst_base_ffi::gst_aggregator_pad_pop_buffer(aggregator_pad_ptr(self)))
+        }
+    }
+
+    fn peek_buffer(&self) -> Option<gst::Buffer> {
+        unsafe {
+            from_glib_full(gst_base_ffi::gst_aggregator_pad_peek_buffer(aggregator_pad_ptr(self)))
+        }
+    }
+
+    fn drop_buffer(&self) -> bool {
+        unsafe {
+            from_glib(gst_base_ffi::gst_aggregator_pad_drop_buffer(aggregator_pad_ptr(self)))
+        }
+    }
+
+    fn is_eos(&self) -> bool {
+        unsafe {
+            from_glib(gst_base_ffi::gst_aggregator_pad_is_eos(aggregator_pad_ptr(self)))
+        }
+    }
+
+    fn get_segment(&self) -> gst::Segment {
+        unsafe {
+            let ptr = aggregator_pad_ptr(self);
+            let lock = &mut (*(ptr as *mut gst_ffi::GstObject)).lock;
+
+            // The segment is protected by the object lock
+            glib_ffi::g_mutex_lock(lock);
+            let segment = from_glib_none(&(*ptr).segment as *const gst_ffi::GstSegment);
+            glib_ffi::g_mutex_unlock(lock);
+
+            segment
+        }
+    }
+}
+
+unsafe fn aggregator_pad_ptr<O: IsA<gst::Pad>>(pad: &O) -> *mut gst_base_ffi::GstAggregatorPad {
+    let ptr: *mut gst_ffi::GstPad = pad.to_glib_none().0;
+    assert_ne!(
+        gobject_ffi::g_type_check_instance_is_a(
+            ptr as *mut gobject_ffi::GTypeInstance,
+            gst_base_ffi::gst_aggregator_pad_get_type(),
+        ),
+        glib_ffi::GFALSE
+    );
+    ptr as *mut gst_base_ffi::GstAggregatorPad
+}
+
+pub unsafe trait AggregatorPadClassExt<T: AggregatorPadBase>
+where
+    T::ImplType: AggregatorPadImpl<T>,
+{
+    fn override_vfuncs(&mut self, _: &ClassInitToken) {
+        unsafe {
+            let klass = &mut *(self as *const Self as *mut gst_base_ffi::GstAggregatorPadClass);
+            klass.flush = Some(aggregator_pad_flush::<T>);
+            klass.skip_buffer = Some(aggregator_pad_skip_buffer::<T>);
+        }
+    }
+}
+
+glib_wrapper! {
+    pub struct AggregatorPad(Object<InstanceStruct<AggregatorPad>>): [gst::Pad => gst_ffi::GstPad,
+                                                                      gst::Object => gst_ffi::GstObject];
+
+    match fn {
+        get_type => || get_type::<AggregatorPad>(),
+    }
+}
+
+unsafe impl AggregatorPadBase for AggregatorPad {}
+pub type AggregatorPadClass = ClassStruct<AggregatorPad>;
+
+// FIXME: Boilerplate
+unsafe impl AggregatorPadClassExt<AggregatorPad> for AggregatorPadClass {}
+
+#[macro_export]
+macro_rules! box_aggregator_pad_impl(
+    ($name:ident) => {
+        box_object_impl!($name);
+
+        impl<T: AggregatorPadBase> AggregatorPadImpl<T> for Box<$name<T>> {
+            fn flush(&self, pad: &T, aggregator: &gst::Element) -> gst::FlowReturn {
+                let imp: &$name<T> = self.as_ref();
+                imp.flush(pad, aggregator)
+            }
+
+            fn skip_buffer(&self, pad: &T, aggregator: &gst::Element, buffer: &gst::Buffer) -> bool {
+                let imp: &$name<T> = self.as_ref();
+                imp.skip_buffer(pad, aggregator, buffer)
+            }
+        }
+    };
+);
+box_aggregator_pad_impl!(AggregatorPadImpl);
+
+impl ObjectType for AggregatorPad {
+    const NAME: &'static str = "RsAggregatorPad";
+    type GlibType = gst_base_ffi::GstAggregatorPad;
+    type GlibClassType = gst_base_ffi::GstAggregatorPadClass;
+    type ImplType = Box<AggregatorPadImpl<Self>>;
+
+    fn glib_type() -> glib::Type {
+        unsafe { from_glib(gst_base_ffi::gst_aggregator_pad_get_type()) }
+    }
+
+    fn class_init(token: &ClassInitToken, klass: &mut AggregatorPadClass) {
+        AggregatorPadClassExt::override_vfuncs(klass, token);
+    }
+
+    object_type_fns!();
+}
+
+// Panics are reported as errors on the aggregator the pad belongs to
+unsafe extern "C" fn aggregator_pad_flush<T: AggregatorPadBase>(
+    ptr: *mut gst_base_ffi::GstAggregatorPad,
+    aggregator: *mut gst_base_ffi::GstAggregator,
+) -> gst_ffi::GstFlowReturn
+where
+    T::ImplType: AggregatorPadImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let pad = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let aggregator: gst::Element = from_glib_borrow(aggregator as *mut gst_ffi::GstElement);
+    let imp = &*pad.imp;
+
+    panic_to_error!(&aggregator, &pad.panicked, gst::FlowReturn::Error, {
+        imp.flush(&wrap, &aggregator)
+    }).to_glib()
+}
+
+unsafe extern "C" fn aggregator_pad_skip_buffer<T: AggregatorPadBase>(
+    ptr: *mut gst_base_ffi::GstAggregatorPad,
+    aggregator: *mut gst_base_ffi::GstAggregator,
+    buffer: *mut gst_ffi::GstBuffer,
+) -> glib_ffi::gboolean
+where
+    T::ImplType: AggregatorPadImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let pad = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let aggregator: gst::Element = from_glib_borrow(aggregator as *mut gst_ffi::GstElement);
+    let imp = &*pad.imp;
+
+    panic_to_error!(&aggregator, &pad.panicked, false, {
+        imp.skip_buffer(&wrap, &aggregator, &from_glib_borrow(buffer))
+    }).to_glib()
+}
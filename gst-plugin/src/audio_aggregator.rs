@@ -0,0 +1,191 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::ptr;
+
+use glib_ffi;
+use gst_ffi;
+use gst_audio_ffi;
+
+use glib;
+use glib::translate::*;
+use gst;
+use gst::prelude::*;
+
+use object::*;
+use element::*;
+use aggregator::*;
+use anyimpl::*;
+
+/// Subclass of `GstAudioAggregator`.
+///
+/// The base class takes care of timestamps, of inputs that start late or stop early and of
+/// producing output buffers of the right size. Subclasses only combine samples in
+/// `aggregate_one_buffer()`. Sink pads have to be `GstAudioAggregatorPad`s, e.g. instances of a
+/// subclass of `AudioAggregatorPad`.
+pub trait AudioAggregatorImpl<T: AudioAggregatorBase>
+    : AnyImpl + ObjectImpl<T> + ElementImpl<T> + AggregatorImpl<T> + Send + Sync + 'static
+    {
+    fn create_output_buffer(&self, element: &T, num_frames: u32) -> Option<gst::Buffer> {
+        element.parent_create_output_buffer(num_frames)
+    }
+
+    /// Combines `num_frames` frames of `inbuf` starting at frame `in_offset` into `outbuf`
+    /// starting at frame `out_offset`.
+    ///
+    /// `outbuf` initially contains silence. Returns `false` if nothing was written to it.
+    fn aggregate_one_buffer(
+        &self,
+        element: &T,
+        pad: &gst::Pad,
+        inbuf: &gst::Buffer,
+        in_offset: u32,
+        outbuf: &mut gst::BufferRef,
+        out_offset: u32,
+        num_frames: u32,
+    ) -> bool;
+}
+
+any_impl!(AudioAggregatorBase, AudioAggregatorImpl);
+
+pub unsafe trait AudioAggregatorBase: AggregatorBase {
+    fn parent_create_output_buffer(&self, num_frames: u32) -> Option<gst::Buffer> {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass =
+                (*klass).get_parent_class() as *const gst_audio_ffi::GstAudioAggregatorClass;
+            let ptr: *mut gst_ffi::GstElement = self.to_glib_none().0;
+            (*parent_klass).create_output_buffer.and_then(|f| {
+                from_glib_full(f(
+                    ptr as *mut gst_audio_ffi::GstAudioAggregator,
+                    num_frames,
+                ))
+            })
+        }
+    }
+}
+
+pub unsafe trait AudioAggregatorClassExt<T: AudioAggregatorBase>
+where
+    T::ImplType: AudioAggregatorImpl<T>,
+{
+    fn override_vfuncs(&mut self, _: &ClassInitToken) {
+        unsafe {
+            let klass = &mut *(self as *const Self as *mut gst_audio_ffi::GstAudioAggregatorClass);
+            klass.create_output_buffer = Some(audio_aggregator_create_output_buffer::<T>);
+            klass.aggregate_one_buffer = Some(audio_aggregator_aggregate_one_buffer::<T>);
+        }
+    }
+}
+
+glib_wrapper! {
+    pub struct AudioAggregator(Object<InstanceStruct<AudioAggregator>>): [gst::Element => gst_ffi::GstElement,
+                                                                          gst::Object => gst_ffi::GstObject];
+
+    match fn {
+        get_type => || get_type::<AudioAggregator>(),
+    }
+}
+
+unsafe impl AggregatorBase for AudioAggregator {}
+unsafe impl AudioAggregatorBase for AudioAggregator {}
+pub type AudioAggregatorClass = ClassStruct<AudioAggregator>;
+
+// FIXME: Boilerplate
+unsafe impl AudioAggregatorClassExt<AudioAggregator> for AudioAggregatorClass {}
+unsafe impl AggregatorClassExt<AudioAggregator> for AudioAggregatorClass {}
+unsafe impl ElementClassExt<AudioAggregator> for AudioAggregatorClass {}
+
+#[macro_export]
+macro_rules! box_audio_aggregator_impl(
+    ($name:ident) => {
+        box_aggregator_impl!($name);
+
+        impl<T: AudioAggregatorBase> AudioAggregatorImpl<T> for Box<$name<T>> {
+            fn create_output_buffer(&self, element: &T, num_frames: u32) -> Option<gst::Buffer> {
+                let imp: &$name<T> = self.as_ref();
+                imp.create_output_buffer(element, num_frames)
+            }
+
+            fn aggregate_one_buffer(&self, element: &T, pad: &gst::Pad, inbuf: &gst::Buffer, in_offset: u32, outbuf: &mut gst::BufferRef, out_offset: u32, num_frames: u32) -> bool {
+                let imp: &$name<T> = self.as_ref();
+                imp.aggregate_one_buffer(element, pad, inbuf, in_offset, outbuf, out_offset, num_frames)
+            }
+        }
+    };
+);
+box_audio_aggregator_impl!(AudioAggregatorImpl);
+
+impl ObjectType for AudioAggregator {
+    const NAME: &'static str = "RsAudioAggregator";
+    type GlibType = gst_audio_ffi::GstAudioAggregator;
+    type GlibClassType = gst_audio_ffi::GstAudioAggregatorClass;
+    type ImplType = Box<AudioAggregatorImpl<Self>>;
+
+    fn glib_type() -> glib::Type {
+        unsafe { from_glib(gst_audio_ffi::gst_audio_aggregator_get_type()) }
+    }
+
+    fn class_init(token: &ClassInitToken, klass: &mut AudioAggregatorClass) {
+        ElementClassExt::override_vfuncs(klass, token);
+        AggregatorClassExt::override_vfuncs(klass, token);
+        AudioAggregatorClassExt::override_vfuncs(klass, token);
+    }
+
+    object_type_fns!();
+}
+
+unsafe extern "C" fn audio_aggregator_create_output_buffer<T: AudioAggregatorBase>(
+    ptr: *mut gst_audio_ffi::GstAudioAggregator,
+    num_frames: u32,
+) -> *mut gst_ffi::GstBuffer
+where
+    T::ImplType: AudioAggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, None, {
+        imp.create_output_buffer(&wrap, num_frames)
+    }).map(|buffer| buffer.into_ptr())
+        .unwrap_or(ptr::null_mut())
+}
+
+unsafe extern "C" fn audio_aggregator_aggregate_one_buffer<T: AudioAggregatorBase>(
+    ptr: *mut gst_audio_ffi::GstAudioAggregator,
+    pad: *mut gst_audio_ffi::GstAudioAggregatorPad,
+    inbuf: *mut gst_ffi::GstBuffer,
+    in_offset: u32,
+    outbuf: *mut gst_ffi::GstBuffer,
+    out_offset: u32,
+    num_frames: u32,
+) -> glib_ffi::gboolean
+where
+    T::ImplType: AudioAggregatorImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+
+    panic_to_error!(&wrap, &element.panicked, false, {
+        imp.aggregate_one_buffer(
+            &wrap,
+            &from_glib_borrow(pad as *mut gst_ffi::GstPad),
+            &from_glib_borrow(inbuf),
+            in_offset,
+            gst::BufferRef::from_mut_ptr(outbuf),
+            out_offset,
+            num_frames,
+        )
+    }).to_glib()
+}
@@ -0,0 +1,52 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use gst_ffi;
+use gst_audio_ffi;
+
+use glib;
+use glib::translate::*;
+use gst;
+
+use object::*;
+use aggregator_pad::*;
+
+// Subclasses of GstAudioAggregatorPad only have the virtual methods of GstAggregatorPad that
+// are useful to override, so they are implemented with AggregatorPadImpl.
+
+glib_wrapper! {
+    pub struct AudioAggregatorPad(Object<InstanceStruct<AudioAggregatorPad>>): [gst::Pad => gst_ffi::GstPad,
+                                                                                gst::Object => gst_ffi::GstObject];
+
+    match fn {
+        get_type => || get_type::<AudioAggregatorPad>(),
+    }
+}
+
+unsafe impl AggregatorPadBase for AudioAggregatorPad {}
+pub type AudioAggregatorPadClass = ClassStruct<AudioAggregatorPad>;
+
+// FIXME: Boilerplate
+unsafe impl AggregatorPadClassExt<AudioAggregatorPad> for AudioAggregatorPadClass {}
+
+impl ObjectType for AudioAggregatorPad {
+    const NAME: &'static str = "RsAudioAggregatorPad";
+    type GlibType = gst_audio_ffi::GstAudioAggregatorPad;
+    type GlibClassType = gst_audio_ffi::GstAudioAggregatorPadClass;
+    type ImplType = Box<AggregatorPadImpl<Self>>;
+
+    fn glib_type() -> glib::Type {
+        unsafe { from_glib(gst_audio_ffi::gst_audio_aggregator_pad_get_type()) }
+    }
+
+    fn class_init(token: &ClassInitToken, klass: &mut AudioAggregatorPadClass) {
+        AggregatorPadClassExt::override_vfuncs(klass, token);
+    }
+
+    object_type_fns!();
+}
@@ -62,8 +62,33 @@ pub trait BaseSinkImpl<T: BaseSinkBase>
         gst::FlowReturn::Ok
     }
 
+    /// Latency added by the sink as `(min, max)`, e.g. for buffering before sending the data
+    /// over the network, which is added to the upstream latency in LATENCY queries.
+    ///
+    /// `max` can be `gst::CLOCK_TIME_NONE` if the sink can buffer without limit. Call
+    /// `post_latency_message()` whenever the returned value changes. This posts a LATENCY
+    /// message instead of sending a LATENCY event: the event carries the latency that the
+    /// pipeline configured for all sinks and only the pipeline sends it, while the message makes
+    /// the pipeline query the latency again and then send updated LATENCY events.
+    fn get_latency(&self, _element: &T) -> Option<(gst::ClockTime, gst::ClockTime)> {
+        None
+    }
+
     fn query(&self, element: &T, query: &mut gst::QueryRef) -> bool {
-        element.parent_query(query)
+        let res = element.parent_query(query);
+        if !res {
+            return res;
+        }
+
+        if let Some((our_min, our_max)) = self.get_latency(element) {
+            if let gst::QueryView::Latency(ref mut q) = query.view_mut() {
+                let (live, min, max) = q.get_result();
+                // Unlimited maximum latency stays unlimited
+                q.set(live, min + our_min, max + our_max);
+            }
+        }
+
+        res
     }
 
     fn event(&self, element: &T, event: gst::Event) -> bool {
@@ -235,6 +260,11 @@ macro_rules! box_base_sink_impl(
                 imp.prepare_list(element, list)
             }
 
+            fn get_latency(&self, element: &T) -> Option<(gst::ClockTime, gst::ClockTime)> {
+                let imp: &$name<T> = self.as_ref();
+                imp.get_latency(element)
+            }
+
             fn query(&self, element: &T, query: &mut gst::QueryRef) -> bool {
                 let imp: &$name<T> = self.as_ref();
                 BaseSinkImpl::query(imp, element, query)
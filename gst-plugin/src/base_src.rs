@@ -19,6 +19,7 @@ use glib::translate::*;
 use gst;
 use gst::prelude::*;
 use gst_base;
+use gst_base::prelude::*;
 
 use object::*;
 use element::*;
@@ -65,7 +66,41 @@ pub trait BaseSrcImpl<T: BaseSrcBase>
         element.parent_do_seek(segment)
     }
 
+    /// Start and end time of a buffer produced by `fill()`/`create()` for synchronizing
+    /// against the clock.
+    ///
+    /// For live sources (see `gst_base::BaseSrcExt::set_live()`) the base class waits until
+    /// the clock reaches the start time before pushing the buffer. The default implementation
+    /// uses the timestamp and duration of the buffer, which is correct for most sources that
+    /// timestamp their buffers with the running time of the capture. If the buffers are
+    /// timestamped by the base class (`set_do_timestamp(true)`), no times are returned and no
+    /// waiting happens.
+    fn get_times(
+        &self,
+        element: &T,
+        buffer: &gst::BufferRef,
+    ) -> (gst::ClockTime, gst::ClockTime) {
+        element.parent_get_times(buffer)
+    }
+
+    /// Latency of the source as `(min, max)`, used for answering LATENCY queries.
+    ///
+    /// `max` can be `gst::CLOCK_TIME_NONE` if the source can buffer without limit. `None`
+    /// keeps the base class' default answer. Call `post_latency_message()` whenever the
+    /// returned value changes, see `BaseSinkImpl::get_latency()` for why this is a message and
+    /// not a LATENCY event.
+    fn get_latency(&self, _element: &T) -> Option<(gst::ClockTime, gst::ClockTime)> {
+        None
+    }
+
     fn query(&self, element: &T, query: &mut gst::QueryRef) -> bool {
+        if let Some((min, max)) = self.get_latency(element) {
+            if let gst::QueryView::Latency(ref mut q) = query.view_mut() {
+                q.set(element.is_live(), min, max);
+                return true;
+            }
+        }
+
         element.parent_query(query)
     }
 
@@ -133,6 +168,37 @@ pub unsafe trait BaseSrcBase
         }
     }
 
+    fn parent_get_times(&self, buffer: &gst::BufferRef) -> (gst::ClockTime, gst::ClockTime) {
+        unsafe {
+            let klass = self.get_class();
+            let parent_klass = (*klass).get_parent_class() as *const gst_base_ffi::GstBaseSrcClass;
+            let mut start = gst_ffi::GST_CLOCK_TIME_NONE;
+            let mut end = gst_ffi::GST_CLOCK_TIME_NONE;
+
+            if let Some(f) = (*parent_klass).get_times {
+                f(
+                    self.to_glib_none().0,
+                    buffer.as_mut_ptr(),
+                    &mut start,
+                    &mut end,
+                );
+            }
+
+            (from_glib(start), from_glib(end))
+        }
+    }
+
+    /// Configures the source as live source producing buffers in `Time` format.
+    ///
+    /// With `do_timestamp` the base class timestamps all buffers with the current running
+    /// time, otherwise the buffers have to be timestamped by the source itself and are
+    /// synchronized against the clock according to `BaseSrcImpl::get_times()`.
+    fn set_live_source(&self, do_timestamp: bool) {
+        self.set_live(true);
+        self.set_format(gst::Format::Time);
+        self.set_do_timestamp(do_timestamp);
+    }
+
     fn parent_query(&self, query: &mut gst::QueryRef) -> bool {
         unsafe {
             let klass = self.get_class();
@@ -221,6 +287,7 @@ where
             klass.fill = Some(base_src_fill::<T>);
             klass.create = Some(base_src_create::<T>);
             klass.do_seek = Some(base_src_do_seek::<T>);
+            klass.get_times = Some(base_src_get_times::<T>);
             klass.query = Some(base_src_query::<T>);
             klass.event = Some(base_src_event::<T>);
             klass.get_caps = Some(base_src_get_caps::<T>);
@@ -302,6 +369,16 @@ macro_rules! box_base_src_impl(
                 imp.do_seek(element, segment)
             }
 
+            fn get_times(&self, element: &T, buffer: &gst::BufferRef) -> (gst::ClockTime, gst::ClockTime) {
+                let imp: &$name<T> = self.as_ref();
+                imp.get_times(element, buffer)
+            }
+
+            fn get_latency(&self, element: &T) -> Option<(gst::ClockTime, gst::ClockTime)> {
+                let imp: &$name<T> = self.as_ref();
+                imp.get_latency(element)
+            }
+
             fn query(&self, element: &T, query: &mut gst::QueryRef) -> bool {
                 let imp: &$name<T> = self.as_ref();
                 BaseSrcImpl::query(imp, element, query)
@@ -339,7 +416,7 @@ macro_rules! box_base_src_impl(
 
             fn unlock_stop(&self, element: &T) -> bool {
                 let imp: &$name<T> = self.as_ref();
-                imp.unlock(element)
+                imp.unlock_stop(element)
             }
         }
     };
@@ -507,6 +584,31 @@ where
     }).to_glib()
 }
 
+unsafe extern "C" fn base_src_get_times<T: BaseSrcBase>(
+    ptr: *mut gst_base_ffi::GstBaseSrc,
+    buffer: *mut gst_ffi::GstBuffer,
+    start: *mut gst_ffi::GstClockTime,
+    end: *mut gst_ffi::GstClockTime,
+) where
+    T::ImplType: BaseSrcImpl<T>,
+{
+    callback_guard!();
+    floating_reference_guard!(ptr);
+    let element = &*(ptr as *mut InstanceStruct<T>);
+    let wrap: T = from_glib_borrow(ptr as *mut InstanceStruct<T>);
+    let imp = &*element.imp;
+    let buffer = gst::BufferRef::from_ptr(buffer);
+
+    *start = gst_ffi::GST_CLOCK_TIME_NONE;
+    *end = gst_ffi::GST_CLOCK_TIME_NONE;
+
+    panic_to_error!(&wrap, &element.panicked, (), {
+        let (start_, end_) = imp.get_times(&wrap, buffer);
+        *start = start_.to_glib();
+        *end = end_.to_glib();
+    });
+}
+
 unsafe extern "C" fn base_src_query<T: BaseSrcBase>(
     ptr: *mut gst_base_ffi::GstBaseSrc,
     query_ptr: *mut gst_ffi::GstQuery,
@@ -62,8 +62,31 @@ pub trait BaseTransformImpl<T: BaseTransformBase>
         element.parent_accept_caps(direction, caps)
     }
 
+    /// Latency added by the transform as `(min, max)`, which is added to the upstream latency
+    /// in LATENCY queries.
+    ///
+    /// `max` can be `gst::CLOCK_TIME_NONE` if the transform can buffer without limit. Call
+    /// `post_latency_message()` whenever the returned value changes, see
+    /// `BaseSinkImpl::get_latency()` for why this is a message and not a LATENCY event.
+    fn get_latency(&self, _element: &T) -> Option<(gst::ClockTime, gst::ClockTime)> {
+        None
+    }
+
     fn query(&self, element: &T, direction: gst::PadDirection, query: &mut gst::QueryRef) -> bool {
-        element.parent_query(direction, query)
+        let res = element.parent_query(direction, query);
+        if !res || direction != gst::PadDirection::Src {
+            return res;
+        }
+
+        if let Some((our_min, our_max)) = self.get_latency(element) {
+            if let gst::QueryView::Latency(ref mut q) = query.view_mut() {
+                let (live, min, max) = q.get_result();
+                // Unlimited maximum latency stays unlimited
+                q.set(live, min + our_min, max + our_max);
+            }
+        }
+
+        res
     }
 
     fn transform_size(
@@ -355,6 +378,11 @@ macro_rules! box_base_transform_impl(
                 imp.accept_caps(element, direction, caps)
             }
 
+            fn get_latency(&self, element: &T) -> Option<(gst::ClockTime, gst::ClockTime)> {
+                let imp: &$name<T> = self.as_ref();
+                imp.get_latency(element)
+            }
+
             fn query(&self, element: &T, direction: gst::PadDirection, query: &mut gst::QueryRef) -> bool {
                 let imp: &$name<T> = self.as_ref();
                 BaseTransformImpl::query(imp, element, direction, query)
@@ -146,3 +146,35 @@ where
     T: WriteBytesExt,
 {
 }
+
+/// Parses a hex string like `"0x00ff1A"` as used for keys and IVs, the 0x prefix and
+/// surrounding whitespace are optional.
+pub fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+    let s = if s.starts_with("0x") || s.starts_with("0X") {
+        &s[2..]
+    } else {
+        s
+    };
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+
+    s.as_bytes()
+        .chunks(2)
+        .map(|c| u8::from_str_radix(::std::str::from_utf8(c).unwrap(), 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("0x00ff1A"), Some(vec![0x00, 0xff, 0x1a]));
+        assert_eq!(parse_hex(" 0102\n"), Some(vec![0x01, 0x02]));
+        assert_eq!(parse_hex("012"), None);
+        assert_eq!(parse_hex("0xzz"), None);
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib_ffi;
+use gobject_ffi;
+use gst_ffi;
+
+use glib;
+use glib::translate::*;
+use gst;
+
+use object::*;
+use anyimpl::*;
+
+/// Exposes children, e.g. the request pads of an element, so that their properties can be set
+/// with `name::property` syntax from `gst-launch-1.0`.
+///
+/// Lookup by name is handled by `GstChildProxy` itself based on the object names of the
+/// children.
+pub trait ChildProxyImpl: AnyImpl + Send + Sync + 'static {
+    fn get_child_by_index(&self, element: &gst::ChildProxy, index: u32) -> Option<glib::Object>;
+    fn get_children_count(&self, element: &gst::ChildProxy) -> u32;
+}
+
+any_impl!(ChildProxyImpl);
+
+pub trait ChildProxyImplStatic<T: ObjectType>: Send + Sync + 'static {
+    fn get_impl<'a>(&self, imp: &'a T::ImplType) -> &'a ChildProxyImpl;
+}
+
+struct ChildProxyStatic<T: ObjectType> {
+    imp_static: *const ChildProxyImplStatic<T>,
+}
+
+unsafe extern "C" fn child_proxy_get_child_by_index<T: ObjectType>(
+    child_proxy: *mut gst_ffi::GstChildProxy,
+    index: u32,
+) -> *mut gobject_ffi::GObject {
+    callback_guard!();
+    floating_reference_guard!(child_proxy);
+
+    let klass = &**(child_proxy as *const *const ClassStruct<T>);
+    let interface_static = klass.get_interface_static(gst_ffi::gst_child_proxy_get_type())
+        as *const ChildProxyStatic<T>;
+
+    let instance = &*(child_proxy as *const InstanceStruct<T>);
+    let imp = instance.get_impl();
+    let imp = (*(*interface_static).imp_static).get_impl(imp);
+
+    imp.get_child_by_index(&from_glib_borrow(child_proxy), index)
+        .to_glib_full()
+}
+
+unsafe extern "C" fn child_proxy_get_children_count<T: ObjectType>(
+    child_proxy: *mut gst_ffi::GstChildProxy,
+) -> u32 {
+    callback_guard!();
+    floating_reference_guard!(child_proxy);
+
+    let klass = &**(child_proxy as *const *const ClassStruct<T>);
+    let interface_static = klass.get_interface_static(gst_ffi::gst_child_proxy_get_type())
+        as *const ChildProxyStatic<T>;
+
+    let instance = &*(child_proxy as *const InstanceStruct<T>);
+    let imp = instance.get_impl();
+    let imp = (*(*interface_static).imp_static).get_impl(imp);
+
+    imp.get_children_count(&from_glib_borrow(child_proxy))
+}
+
+unsafe extern "C" fn child_proxy_init<T: ObjectType>(
+    iface: glib_ffi::gpointer,
+    iface_data: glib_ffi::gpointer,
+) {
+    callback_guard!();
+    let child_proxy_iface = &mut *(iface as *mut gst_ffi::GstChildProxyInterface);
+
+    let iface_type = (*(iface as *const gobject_ffi::GTypeInterface)).g_type;
+    let type_ = (*(iface as *const gobject_ffi::GTypeInterface)).g_instance_type;
+    let klass = &mut *(gobject_ffi::g_type_class_ref(type_) as *mut ClassStruct<T>);
+    let interfaces_static = &mut *(klass.interfaces_static as *mut Vec<_>);
+    interfaces_static.push((iface_type, iface_data));
+
+    child_proxy_iface.get_child_by_index = Some(child_proxy_get_child_by_index::<T>);
+    child_proxy_iface.get_children_count = Some(child_proxy_get_children_count::<T>);
+}
+
+pub fn register_child_proxy<T: ObjectType, I: ChildProxyImplStatic<T>>(
+    _: &TypeInitToken,
+    type_: glib::Type,
+    imp: &I,
+) {
+    unsafe {
+        let imp = imp as &ChildProxyImplStatic<T> as *const ChildProxyImplStatic<T>;
+        let interface_static = Box::new(ChildProxyStatic { imp_static: imp });
+
+        let iface_info = gobject_ffi::GInterfaceInfo {
+            interface_init: Some(child_proxy_init::<T>),
+            interface_finalize: None,
+            interface_data: Box::into_raw(interface_static) as glib_ffi::gpointer,
+        };
+        gobject_ffi::g_type_add_interface_static(
+            type_.to_glib(),
+            gst_ffi::gst_child_proxy_get_type(),
+            &iface_info,
+        );
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Documentation metadata for elements.
+//!
+//! The class metadata of an element only has a one line description, and properties only have
+//! their blurb. `ElementDocs` adds a long description, example launch lines and documentation
+//! for properties and their individual values. Everything is stored as additional element
+//! metadata via `ElementClassExt::set_documentation()`, which `gst-inspect-1.0` prints in the
+//! "Factory Details" section and documentation generators can read from the element factory.
+
+use std::fmt::Write;
+
+/// Metadata key for the long description of the element.
+pub const LONG_DESCRIPTION: &str = "long-description";
+/// Metadata key for example launch lines, one per line.
+pub const EXAMPLE_LAUNCH_LINES: &str = "example-launch-lines";
+/// Metadata key for the documentation URI, same as `GST_ELEMENT_METADATA_DOC_URI`.
+pub const DOC_URI: &str = "doc-uri";
+/// Prefix of the metadata keys for property documentation, followed by the property name.
+pub const PROPERTY_PREFIX: &str = "property-doc::";
+
+/// Returns the metadata key for the documentation of a property.
+pub fn property_key(name: &str) -> String {
+    format!("{}{}", PROPERTY_PREFIX, name)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PropertyDocs<'a> {
+    pub name: &'a str,
+    pub description: &'a str,
+    /// Values with a special meaning and their documentation, e.g. `("0", "unlimited")`.
+    pub values: &'a [(&'a str, &'a str)],
+}
+
+impl<'a> PropertyDocs<'a> {
+    fn to_text(&self) -> String {
+        let mut text = String::from(self.description);
+        for &(value, doc) in self.values {
+            write!(text, "\n  {}: {}", value, doc).unwrap();
+        }
+        text
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ElementDocs<'a> {
+    pub long_description: Option<&'a str>,
+    pub examples: &'a [&'a str],
+    pub doc_uri: Option<&'a str>,
+    pub properties: &'a [PropertyDocs<'a>],
+}
+
+impl<'a> ElementDocs<'a> {
+    /// Returns the metadata keys and values for the documentation.
+    pub fn to_metadata(&self) -> Vec<(String, String)> {
+        let mut metadata = Vec::new();
+
+        if let Some(long_description) = self.long_description {
+            metadata.push((
+                String::from(LONG_DESCRIPTION),
+                String::from(long_description),
+            ));
+        }
+
+        if !self.examples.is_empty() {
+            metadata.push((
+                String::from(EXAMPLE_LAUNCH_LINES),
+                self.examples.join("\n"),
+            ));
+        }
+
+        if let Some(doc_uri) = self.doc_uri {
+            metadata.push((String::from(DOC_URI), String::from(doc_uri)));
+        }
+
+        for property in self.properties {
+            metadata.push((property_key(property.name), property.to_text()));
+        }
+
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata() {
+        static PROPERTIES: [PropertyDocs; 1] = [
+            PropertyDocs {
+                name: "max-size",
+                description: "Maximum size of the queue",
+                values: &[("0", "unlimited"), ("1", "only the latest item")],
+            },
+        ];
+
+        let docs = ElementDocs {
+            long_description: Some("Queues items."),
+            examples: &["a ! queue ! b", "c ! queue max-size=0 ! d"],
+            doc_uri: None,
+            properties: &PROPERTIES,
+        };
+
+        assert_eq!(
+            docs.to_metadata(),
+            vec![
+                (
+                    String::from("long-description"),
+                    String::from("Queues items."),
+                ),
+                (
+                    String::from("example-launch-lines"),
+                    String::from("a ! queue ! b\nc ! queue max-size=0 ! d"),
+                ),
+                (
+                    String::from("property-doc::max-size"),
+                    String::from(
+                        "Maximum size of the queue\n  0: unlimited\n  1: only the latest item"
+                    ),
+                ),
+            ]
+        );
+    }
+}
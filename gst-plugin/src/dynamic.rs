@@ -0,0 +1,434 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Helpers for modifying running pipelines.
+//!
+//! All functions here block the calling thread until the pipeline modification is done or the
+//! timeout expired, and must not be called from a streaming thread of the affected part of the
+//! pipeline. On errors all elements are linked again the way they were before the call.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use glib::IsA;
+use gst;
+use gst::prelude::*;
+
+lazy_static! {
+    static ref CAT: gst::DebugCategory = {
+        gst::DebugCategory::new(
+            "rsdynamic",
+            gst::DebugColorFlags::empty(),
+            "Rust dynamic pipeline helpers",
+        )
+    };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DynamicError {
+    /// The pad was not blocked or the element was not drained in time
+    Timeout,
+    /// The element has no "sink"/"src" pad or the pad is not linked
+    NotLinked,
+    /// Adding or removing an element from the bin failed
+    Bin,
+    /// Linking the new element failed
+    Link(gst::PadLinkReturn),
+    /// Changing the state of the new element failed
+    StateChange,
+}
+
+impl fmt::Display for DynamicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DynamicError::Link(ret) => write!(f, "{}: {:?}", self.description(), ret),
+            _ => f.write_str(self.description()),
+        }
+    }
+}
+
+impl Error for DynamicError {
+    fn description(&self) -> &str {
+        match *self {
+            DynamicError::Timeout => "Timeout",
+            DynamicError::NotLinked => "Not linked",
+            DynamicError::Bin => "Failed to add or remove element",
+            DynamicError::Link(..) => "Failed to link",
+            DynamicError::StateChange => "Failed to change state",
+        }
+    }
+}
+
+// Boolean flag that is set from a pad probe and waited on from the application thread
+struct Signal {
+    flag: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl Signal {
+    fn new() -> Arc<Signal> {
+        Arc::new(Signal {
+            flag: Mutex::new(false),
+            cond: Condvar::new(),
+        })
+    }
+
+    fn set(&self) {
+        *self.flag.lock().unwrap() = true;
+        self.cond.notify_all();
+    }
+
+    fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut flag = self.flag.lock().unwrap();
+
+        while !*flag {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            flag = self.cond.wait_timeout(flag, deadline - now).unwrap().0;
+        }
+
+        true
+    }
+}
+
+/// Keeps a pad blocked until dropped.
+pub struct PadBlock {
+    pad: gst::Pad,
+    probe_id: Option<gst::PadProbeId>,
+}
+
+impl PadBlock {
+    pub fn get_pad(&self) -> &gst::Pad {
+        &self.pad
+    }
+}
+
+impl Drop for PadBlock {
+    fn drop(&mut self) {
+        if let Some(probe_id) = self.probe_id.take() {
+            gst_debug!(CAT, obj: &self.pad, "Unblocking pad");
+            self.pad.remove_probe(probe_id);
+        }
+    }
+}
+
+/// Blocks the pad once no data is flowing through it anymore.
+///
+/// The pad stays blocked until the returned `PadBlock` is dropped.
+pub fn block_pad(pad: &gst::Pad, timeout: Duration) -> Result<PadBlock, DynamicError> {
+    gst_debug!(CAT, obj: pad, "Blocking pad");
+
+    let blocked = Signal::new();
+    let blocked_clone = blocked.clone();
+    let probe_id = pad.add_probe(gst::PadProbeType::IDLE, move |_, _| {
+        blocked_clone.set();
+        gst::PadProbeReturn::Ok
+    });
+
+    let block = PadBlock {
+        pad: pad.clone(),
+        probe_id: probe_id,
+    };
+
+    if !blocked.wait(timeout) {
+        gst_warning!(CAT, obj: pad, "Timed out blocking pad");
+        return Err(DynamicError::Timeout);
+    }
+
+    gst_debug!(CAT, obj: pad, "Blocked pad");
+    Ok(block)
+}
+
+// Sends EOS into `sinkpad` and waits until it arrives at `end_pad`, where it is dropped
+fn drain_pads(sinkpad: &gst::Pad, end_pad: &gst::Pad, timeout: Duration) -> bool {
+    let drained = Signal::new();
+    let drained_clone = drained.clone();
+    let probe_id = end_pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+        match info.data {
+            Some(gst::PadProbeData::Event(ref event)) if event.get_type() == gst::EventType::Eos => {
+                drained_clone.set();
+                gst::PadProbeReturn::Drop
+            }
+            _ => gst::PadProbeReturn::Ok,
+        }
+    });
+
+    sinkpad.send_event(gst::Event::new_eos().build());
+    let res = drained.wait(timeout);
+
+    if let Some(probe_id) = probe_id {
+        end_pad.remove_probe(probe_id);
+    }
+
+    res
+}
+
+/// Sends EOS through an element and waits until all its pending data left its "src" pad.
+///
+/// The EOS event itself is not forwarded downstream. This is usually called with the
+/// element's upstream pad blocked.
+pub fn drain_element(element: &gst::Element, timeout: Duration) -> Result<(), DynamicError> {
+    let sinkpad = element
+        .get_static_pad("sink")
+        .ok_or(DynamicError::NotLinked)?;
+    let srcpad = element
+        .get_static_pad("src")
+        .ok_or(DynamicError::NotLinked)?;
+
+    gst_debug!(CAT, obj: element, "Draining element");
+
+    if !drain_pads(&sinkpad, &srcpad, timeout) {
+        gst_warning!(CAT, obj: element, "Timed out draining element");
+        return Err(DynamicError::Timeout);
+    }
+
+    gst_debug!(CAT, obj: element, "Drained element");
+    Ok(())
+}
+
+fn get_linked_pads(element: &gst::Element) -> Result<(gst::Pad, gst::Pad), DynamicError> {
+    let upstream = element
+        .get_static_pad("sink")
+        .and_then(|pad| pad.get_peer())
+        .ok_or(DynamicError::NotLinked)?;
+    let downstream = element
+        .get_static_pad("src")
+        .and_then(|pad| pad.get_peer())
+        .ok_or(DynamicError::NotLinked)?;
+
+    Ok((upstream, downstream))
+}
+
+// Links upstream ! element ! downstream, and undoes all linking on errors
+fn link_between(
+    upstream: &gst::Pad,
+    element: &gst::Element,
+    downstream: &gst::Pad,
+) -> Result<(), DynamicError> {
+    let sinkpad = element
+        .get_static_pad("sink")
+        .ok_or(DynamicError::NotLinked)?;
+    let srcpad = element
+        .get_static_pad("src")
+        .ok_or(DynamicError::NotLinked)?;
+
+    let ret = upstream.link(&sinkpad);
+    if ret != gst::PadLinkReturn::Ok {
+        return Err(DynamicError::Link(ret));
+    }
+
+    let ret = srcpad.link(downstream);
+    if ret != gst::PadLinkReturn::Ok {
+        upstream.unlink(&sinkpad);
+        return Err(DynamicError::Link(ret));
+    }
+
+    Ok(())
+}
+
+fn unlink_between(upstream: &gst::Pad, element: &gst::Element, downstream: &gst::Pad) {
+    if let Some(sinkpad) = element.get_static_pad("sink") {
+        upstream.unlink(&sinkpad);
+    }
+    if let Some(srcpad) = element.get_static_pad("src") {
+        srcpad.unlink(downstream);
+    }
+}
+
+// Flushes the EOS out of a drained element and links it again, so that it accepts data again.
+// Relinking makes upstream re-send its sticky events, including the segment removed by the
+// flush. The element is unlinked first so that the flush does not reach downstream.
+fn restore_element(upstream: &gst::Pad, element: &gst::Element, downstream: &gst::Pad) {
+    gst_debug!(CAT, obj: element, "Restoring element");
+
+    unlink_between(upstream, element, downstream);
+    if let Some(sinkpad) = element.get_static_pad("sink") {
+        sinkpad.send_event(gst::Event::new_flush_start().build());
+        sinkpad.send_event(gst::Event::new_flush_stop(false).build());
+    }
+
+    if let Err(err) = link_between(upstream, element, downstream) {
+        gst_error!(CAT, obj: element, "Failed to link again: {}", err);
+    }
+}
+
+/// Replaces `old` with `new` inside `bin`.
+///
+/// The upstream pad of `old` is blocked, `old` is drained and then removed from the bin and
+/// set to the `Null` state, and `new` is added and linked in its place. If anything fails,
+/// `old` is flushed and linked again so that it continues to handle data.
+pub fn replace_element<B: IsA<gst::Bin>>(
+    bin: &B,
+    old: &gst::Element,
+    new: &gst::Element,
+    timeout: Duration,
+) -> Result<(), DynamicError> {
+    gst_debug!(CAT, obj: old, "Replacing with {}", new.get_name());
+
+    let (upstream, downstream) = get_linked_pads(old)?;
+    let block = block_pad(&upstream, timeout)?;
+    if let Err(err) = drain_element(old, timeout) {
+        restore_element(&upstream, old, &downstream);
+        return Err(err);
+    }
+
+    unlink_between(&upstream, old, &downstream);
+    bin.add(new).map_err(|_| {
+        restore_element(&upstream, old, &downstream);
+        DynamicError::Bin
+    })?;
+
+    if let Err(err) = link_between(&upstream, new, &downstream) {
+        gst_warning!(CAT, obj: new, "Failed to link: {}", err);
+        let _ = bin.remove(new);
+        restore_element(&upstream, old, &downstream);
+        return Err(err);
+    }
+
+    if new.sync_state_with_parent().is_err() {
+        gst_warning!(CAT, obj: new, "Failed to change state");
+        let _ = new.set_state(gst::State::Null);
+        unlink_between(&upstream, new, &downstream);
+        let _ = bin.remove(new);
+        restore_element(&upstream, old, &downstream);
+        return Err(DynamicError::StateChange);
+    }
+
+    // Only get rid of the old element once the new one is in place
+    old.set_state(gst::State::Null);
+    let _ = bin.remove(old);
+
+    drop(block);
+
+    gst_debug!(CAT, obj: new, "Replaced {}", old.get_name());
+    Ok(())
+}
+
+/// Inserts `filter` after `srcpad` inside `bin`, between `srcpad` and its current peer.
+pub fn insert_filter<B: IsA<gst::Bin>>(
+    bin: &B,
+    srcpad: &gst::Pad,
+    filter: &gst::Element,
+    timeout: Duration,
+) -> Result<(), DynamicError> {
+    gst_debug!(CAT, obj: srcpad, "Inserting {}", filter.get_name());
+
+    let downstream = srcpad.get_peer().ok_or(DynamicError::NotLinked)?;
+    let block = block_pad(srcpad, timeout)?;
+
+    bin.add(filter).map_err(|_| DynamicError::Bin)?;
+
+    srcpad.unlink(&downstream);
+    if let Err(err) = link_between(srcpad, filter, &downstream) {
+        gst_warning!(CAT, obj: filter, "Failed to link: {}", err);
+        let _ = bin.remove(filter);
+        srcpad.link(&downstream);
+        return Err(err);
+    }
+
+    if filter.sync_state_with_parent().is_err() {
+        gst_warning!(CAT, obj: filter, "Failed to change state");
+        let _ = filter.set_state(gst::State::Null);
+        unlink_between(srcpad, filter, &downstream);
+        let _ = bin.remove(filter);
+        srcpad.link(&downstream);
+        return Err(DynamicError::StateChange);
+    }
+
+    drop(block);
+
+    gst_debug!(CAT, obj: filter, "Inserted");
+    Ok(())
+}
+
+/// Removes the branch linked to `srcpad`, e.g. a request pad of a `tee`.
+///
+/// The branch is drained with an EOS event first, which is not forwarded past its end. All
+/// elements downstream of `srcpad` that are only reachable through single "src" pads are
+/// then removed from `bin` and set to the `Null` state. The branch ends before the first
+/// element with more than one linked sink pad, e.g. a muxer, whose pad the branch was linked to
+/// is released if it is a request pad. If `srcpad` is a request pad, it is released as well.
+///
+/// Branches that are not drained within `timeout`, e.g. because their sink can't preroll, are
+/// removed nonetheless.
+pub fn remove_branch<B: IsA<gst::Bin>>(
+    bin: &B,
+    srcpad: &gst::Pad,
+    timeout: Duration,
+) -> Result<(), DynamicError> {
+    gst_debug!(CAT, obj: srcpad, "Removing branch");
+
+    let peer = srcpad.get_peer().ok_or(DynamicError::NotLinked)?;
+    let block = block_pad(srcpad, timeout)?;
+
+    // Find the last sink pad of the branch, and whether it belongs to an element that is
+    // shared with other branches
+    let mut elements = Vec::new();
+    let mut end_pad = peer.clone();
+    let mut shared = false;
+    while let Some(element) = end_pad.get_parent_element() {
+        let linked_sinkpads = element
+            .get_sink_pads()
+            .iter()
+            .filter(|pad| pad.is_linked())
+            .count();
+        if linked_sinkpads > 1 {
+            shared = true;
+            break;
+        }
+
+        let next = element
+            .get_static_pad("src")
+            .and_then(|pad| pad.get_peer());
+        elements.push(element);
+        match next {
+            None => break,
+            Some(pad) => end_pad = pad,
+        }
+    }
+
+    if !drain_pads(&peer, &end_pad, timeout) {
+        gst_warning!(CAT, obj: srcpad, "Timed out draining branch");
+    }
+
+    srcpad.unlink(&peer);
+    drop(block);
+
+    if shared {
+        if let Some(upstream) = end_pad.get_peer() {
+            upstream.unlink(&end_pad);
+        }
+        release_request_pad(&end_pad);
+    }
+
+    for element in elements {
+        element.set_state(gst::State::Null);
+        let _ = bin.remove(&element);
+    }
+
+    release_request_pad(srcpad);
+
+    gst_debug!(CAT, "Removed branch");
+    Ok(())
+}
+
+fn release_request_pad(pad: &gst::Pad) {
+    let is_request_pad = pad.get_pad_template()
+        .map(|templ| templ.get_property_presence() == gst::PadPresence::Request)
+        .unwrap_or(false);
+    if is_request_pad {
+        if let Some(parent) = pad.get_parent_element() {
+            parent.release_request_pad(pad);
+        }
+    }
+}
@@ -22,6 +22,7 @@ use gst::prelude::*;
 
 use object::*;
 use anyimpl::*;
+use docs::ElementDocs;
 
 pub trait ElementImpl<T: ElementBase>
     : ObjectImpl<T> + AnyImpl + Send + Sync + 'static {
@@ -101,12 +102,76 @@ pub unsafe trait ElementBase: IsA<gst::Element> + ObjectType {
         }
     }
 
+    /// Notifies the pipeline that the latency of this element changed, which makes it query
+    /// the latency again and distribute the new latency to all sinks.
+    fn post_latency_message(&self) -> bool {
+        let element: &gst::Element = self.upcast_ref();
+        element.post_message(&gst::Message::new_latency().src(Some(element)).build())
+    }
+
+    /// Starts an asynchronous state change, e.g. for waiting on a network connection.
+    ///
+    /// Posts an `AsyncStart` message and returns a handle for finishing the state change,
+    /// which can be moved to another thread. `change_state()` has to return
+    /// `gst::StateChangeReturn::Async` for the current transition afterwards.
+    fn start_async_state_change(&self) -> AsyncStateChange {
+        let element: &gst::Element = self.upcast_ref();
+        element.post_message(&gst::Message::new_async_start().src(Some(element)).build());
+
+        AsyncStateChange {
+            element: Some(element.clone()),
+        }
+    }
+
     fn catch_panic<T, F: FnOnce(&Self) -> T, G: FnOnce() -> T>(&self, fallback: G, f: F) -> T {
         let panicked = unsafe { &(*self.get_instance()).panicked };
         panic_to_error!(self, panicked, fallback(), { f(self) })
     }
 }
 
+/// Pending asynchronous state change, see `ElementBase::start_async_state_change()`.
+///
+/// The state change is aborted if this is dropped without calling `complete()`.
+pub struct AsyncStateChange {
+    element: Option<gst::Element>,
+}
+
+impl AsyncStateChange {
+    pub fn get_element(&self) -> &gst::Element {
+        self.element.as_ref().unwrap()
+    }
+
+    /// Finishes the state change, posts an `AsyncDone` message and continues with the next
+    /// pending state change, if any.
+    ///
+    /// The next state change happens from the calling thread, so no locks that are also taken
+    /// in `change_state()` must be held while calling this.
+    pub fn complete(mut self) -> gst::StateChangeReturn {
+        let element = self.element.take().unwrap();
+
+        let ret = element.continue_state(gst::StateChangeReturn::Success);
+        element.post_message(&gst::Message::new_async_done(gst::CLOCK_TIME_NONE)
+            .src(Some(&element))
+            .build());
+
+        ret
+    }
+
+    /// Aborts the state change. An error message should be posted before calling this.
+    pub fn abort(mut self) {
+        let element = self.element.take().unwrap();
+        element.abort_state();
+    }
+}
+
+impl Drop for AsyncStateChange {
+    fn drop(&mut self) {
+        if let Some(element) = self.element.take() {
+            element.abort_state();
+        }
+    }
+}
+
 pub unsafe trait ElementClassExt<T: ElementBase>
 where
     T::ImplType: ElementImpl<T>,
@@ -138,6 +203,24 @@ where
         }
     }
 
+    fn add_metadata(&mut self, key: &str, value: &str) {
+        unsafe {
+            gst_ffi::gst_element_class_add_metadata(
+                self as *const Self as *mut gst_ffi::GstElementClass,
+                key.to_glib_none().0,
+                value.to_glib_none().0,
+            );
+        }
+    }
+
+    /// Adds the long description, example launch lines and property documentation as
+    /// element metadata, see the `docs` module.
+    fn set_documentation(&mut self, docs: &ElementDocs) {
+        for (key, value) in docs.to_metadata() {
+            self.add_metadata(&key, &value);
+        }
+    }
+
     fn override_vfuncs(&mut self, _: &ClassInitToken) {
         unsafe {
             let klass = &mut *(self as *const Self as *mut gst_ffi::GstElementClass);
@@ -0,0 +1,346 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Generators for video frames and audio buffers with known content, for use in tests.
+//!
+//! Video frames use the default GStreamer layout for packed formats, i.e. rows are padded to
+//! a multiple of 4 bytes.
+
+use std::f64::consts::PI;
+use std::i16;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use gst;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    Rgbx,
+    Bgrx,
+    Xrgb,
+    Rgba,
+    Rgb,
+    Bgr,
+    Gray8,
+}
+
+impl VideoFormat {
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            VideoFormat::Rgbx => "RGBx",
+            VideoFormat::Bgrx => "BGRx",
+            VideoFormat::Xrgb => "xRGB",
+            VideoFormat::Rgba => "RGBA",
+            VideoFormat::Rgb => "RGB",
+            VideoFormat::Bgr => "BGR",
+            VideoFormat::Gray8 => "GRAY8",
+        }
+    }
+
+    pub fn pixel_stride(&self) -> usize {
+        match *self {
+            VideoFormat::Rgbx | VideoFormat::Bgrx | VideoFormat::Xrgb | VideoFormat::Rgba => 4,
+            VideoFormat::Rgb | VideoFormat::Bgr => 3,
+            VideoFormat::Gray8 => 1,
+        }
+    }
+
+    pub fn row_stride(&self, width: usize) -> usize {
+        (width * self.pixel_stride() + 3) & !3
+    }
+
+    pub fn frame_size(&self, width: usize, height: usize) -> usize {
+        self.row_stride(width) * height
+    }
+
+    fn write_pixel(&self, data: &mut [u8], color: Rgb) {
+        let Rgb(r, g, b) = color;
+        match *self {
+            VideoFormat::Rgbx => data[..4].copy_from_slice(&[r, g, b, 0xff]),
+            VideoFormat::Bgrx => data[..4].copy_from_slice(&[b, g, r, 0xff]),
+            VideoFormat::Xrgb => data[..4].copy_from_slice(&[0xff, r, g, b]),
+            VideoFormat::Rgba => data[..4].copy_from_slice(&[r, g, b, 0xff]),
+            VideoFormat::Rgb => data[..3].copy_from_slice(&[r, g, b]),
+            VideoFormat::Bgr => data[..3].copy_from_slice(&[b, g, r]),
+            VideoFormat::Gray8 => data[0] = color.luma(),
+        }
+    }
+
+    fn read_pixel(&self, data: &[u8]) -> Rgb {
+        match *self {
+            VideoFormat::Rgbx | VideoFormat::Rgba | VideoFormat::Rgb => {
+                Rgb(data[0], data[1], data[2])
+            }
+            VideoFormat::Bgrx | VideoFormat::Bgr => Rgb(data[2], data[1], data[0]),
+            VideoFormat::Xrgb => Rgb(data[1], data[2], data[3]),
+            VideoFormat::Gray8 => Rgb(data[0], data[0], data[0]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb(0, 0, 0);
+    pub const WHITE: Rgb = Rgb(255, 255, 255);
+
+    /// BT.601 luma, as used by the GRAY8 frames generated here
+    pub fn luma(&self) -> u8 {
+        let Rgb(r, g, b) = *self;
+        ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114 + 500) / 1000) as u8
+    }
+}
+
+/// The seven 75% bars of the SMPTE color bars pattern, from left to right.
+pub const SMPTE_BARS: [Rgb; 7] = [
+    Rgb(191, 191, 191),
+    Rgb(191, 191, 0),
+    Rgb(0, 191, 191),
+    Rgb(0, 191, 0),
+    Rgb(191, 0, 191),
+    Rgb(191, 0, 0),
+    Rgb(0, 0, 191),
+];
+
+pub fn video_caps(format: VideoFormat, width: usize, height: usize, fps: i32) -> gst::Caps {
+    gst::Caps::new_simple(
+        "video/x-raw",
+        &[
+            ("format", &format.to_str()),
+            ("width", &(width as i32)),
+            ("height", &(height as i32)),
+            ("framerate", &gst::Fraction::new(fps, 1)),
+        ],
+    )
+}
+
+/// Creates a frame where the color of each pixel is given by `f(x, y)`.
+pub fn video_frame<F: Fn(usize, usize) -> Rgb>(
+    format: VideoFormat,
+    width: usize,
+    height: usize,
+    f: F,
+) -> gst::Buffer {
+    let row_stride = format.row_stride(width);
+    let pixel_stride = format.pixel_stride();
+    let mut data = vec![0u8; format.frame_size(width, height)];
+
+    for (y, row) in data.chunks_mut(row_stride).enumerate() {
+        for x in 0..width {
+            format.write_pixel(&mut row[x * pixel_stride..], f(x, y));
+        }
+    }
+
+    gst::Buffer::from_mut_slice(data).unwrap()
+}
+
+pub fn solid_color_frame(
+    format: VideoFormat,
+    width: usize,
+    height: usize,
+    color: Rgb,
+) -> gst::Buffer {
+    video_frame(format, width, height, |_, _| color)
+}
+
+/// Creates a horizontal gradient from black on the left to white on the right.
+pub fn gradient_frame(format: VideoFormat, width: usize, height: usize) -> gst::Buffer {
+    video_frame(format, width, height, |x, _| {
+        let v = if width > 1 {
+            (x * 255 / (width - 1)) as u8
+        } else {
+            0
+        };
+        Rgb(v, v, v)
+    })
+}
+
+/// Creates a frame with the seven SMPTE color bars over the full height.
+pub fn smpte_bars_frame(format: VideoFormat, width: usize, height: usize) -> gst::Buffer {
+    video_frame(format, width, height, |x, _| SMPTE_BARS[x * SMPTE_BARS.len() / width])
+}
+
+/// Reads back the color of a pixel from a frame, e.g. for checking the output of a filter.
+pub fn get_pixel(
+    buffer: &gst::BufferRef,
+    format: VideoFormat,
+    width: usize,
+    x: usize,
+    y: usize,
+) -> Rgb {
+    let map = buffer.map_readable().unwrap();
+    let offset = y * format.row_stride(width) + x * format.pixel_stride();
+    format.read_pixel(&map.as_slice()[offset..])
+}
+
+pub fn audio_caps_s16(rate: i32, channels: i32) -> gst::Caps {
+    audio_caps("S16LE", rate, channels)
+}
+
+pub fn audio_caps_f64(rate: i32, channels: i32) -> gst::Caps {
+    audio_caps("F64LE", rate, channels)
+}
+
+fn audio_caps(format: &str, rate: i32, channels: i32) -> gst::Caps {
+    gst::Caps::new_simple(
+        "audio/x-raw",
+        &[
+            ("format", &format),
+            ("layout", &"interleaved"),
+            ("rate", &rate),
+            ("channels", &channels),
+        ],
+    )
+}
+
+// Interleaved samples of a sine wave with amplitude 1.0, the same on all channels
+fn sine_samples(rate: u32, channels: usize, freq: f64, offset: u64, n_samples: usize) -> Vec<f64> {
+    let mut samples = Vec::with_capacity(n_samples * channels);
+    for i in 0..n_samples {
+        let t = (offset + i as u64) as f64 / rate as f64;
+        let v = (2.0 * PI * freq * t).sin();
+        for _ in 0..channels {
+            samples.push(v);
+        }
+    }
+    samples
+}
+
+pub fn audio_buffer_f64(samples: &[f64]) -> gst::Buffer {
+    let mut data = vec![0u8; samples.len() * 8];
+    for (chunk, sample) in data.chunks_mut(8).zip(samples) {
+        LittleEndian::write_f64(chunk, *sample);
+    }
+    gst::Buffer::from_mut_slice(data).unwrap()
+}
+
+pub fn audio_buffer_s16(samples: &[i16]) -> gst::Buffer {
+    let mut data = vec![0u8; samples.len() * 2];
+    for (chunk, sample) in data.chunks_mut(2).zip(samples) {
+        LittleEndian::write_i16(chunk, *sample);
+    }
+    gst::Buffer::from_mut_slice(data).unwrap()
+}
+
+/// Creates a buffer with `n_samples` frames of a sine wave, starting at sample `offset`.
+///
+/// The buffer is timestamped according to `offset` and `rate`.
+pub fn sine_buffer_f64(
+    rate: u32,
+    channels: usize,
+    freq: f64,
+    offset: u64,
+    n_samples: usize,
+) -> gst::Buffer {
+    let samples = sine_samples(rate, channels, freq, offset, n_samples);
+    let mut buffer = audio_buffer_f64(&samples);
+    set_audio_timestamps(&mut buffer, rate, offset, n_samples);
+    buffer
+}
+
+/// Same as `sine_buffer_f64()` but with 16 bit integer samples at full scale.
+pub fn sine_buffer_s16(
+    rate: u32,
+    channels: usize,
+    freq: f64,
+    offset: u64,
+    n_samples: usize,
+) -> gst::Buffer {
+    let samples = sine_samples(rate, channels, freq, offset, n_samples)
+        .into_iter()
+        .map(|v| (v * i16::MAX as f64).round() as i16)
+        .collect::<Vec<_>>();
+    let mut buffer = audio_buffer_s16(&samples);
+    set_audio_timestamps(&mut buffer, rate, offset, n_samples);
+    buffer
+}
+
+pub fn silence_buffer_f64(
+    rate: u32,
+    channels: usize,
+    offset: u64,
+    n_samples: usize,
+) -> gst::Buffer {
+    let mut buffer = audio_buffer_f64(&vec![0.0; n_samples * channels]);
+    set_audio_timestamps(&mut buffer, rate, offset, n_samples);
+    buffer
+}
+
+fn set_audio_timestamps(buffer: &mut gst::Buffer, rate: u32, offset: u64, n_samples: usize) {
+    let pts = offset * gst::SECOND_VAL / rate as u64;
+    let end = (offset + n_samples as u64) * gst::SECOND_VAL / rate as u64;
+
+    let buffer = buffer.get_mut().unwrap();
+    buffer.set_pts(pts.into());
+    buffer.set_duration((end - pts).into());
+    buffer.set_offset(offset);
+    buffer.set_offset_end(offset + n_samples as u64);
+}
+
+/// Sets PTS and duration of the `n`-th frame of a stream with the given framerate.
+pub fn set_video_timestamps(buffer: &mut gst::Buffer, fps: u64, n: u64) {
+    let pts = n * gst::SECOND_VAL / fps;
+    let end = (n + 1) * gst::SECOND_VAL / fps;
+
+    let buffer = buffer.get_mut().unwrap();
+    buffer.set_pts(pts.into());
+    buffer.set_duration((end - pts).into());
+    buffer.set_offset(n);
+    buffer.set_offset_end(n + 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smpte_bars() {
+        gst::init().unwrap();
+
+        let buffer = smpte_bars_frame(VideoFormat::Bgrx, 70, 2);
+        assert_eq!(buffer.get_size(), 70 * 4 * 2);
+        for (i, color) in SMPTE_BARS.iter().enumerate() {
+            assert_eq!(get_pixel(&buffer, VideoFormat::Bgrx, 70, i * 10, 1), *color);
+        }
+    }
+
+    #[test]
+    fn test_row_padding() {
+        gst::init().unwrap();
+
+        let buffer = gradient_frame(VideoFormat::Rgb, 3, 2);
+        // 9 bytes per row, padded to 12
+        assert_eq!(buffer.get_size(), 24);
+        assert_eq!(get_pixel(&buffer, VideoFormat::Rgb, 3, 0, 1), Rgb::BLACK);
+        assert_eq!(get_pixel(&buffer, VideoFormat::Rgb, 3, 2, 1), Rgb::WHITE);
+
+        let buffer = solid_color_frame(VideoFormat::Gray8, 3, 2, Rgb::WHITE);
+        assert_eq!(buffer.get_size(), 8);
+        assert_eq!(get_pixel(&buffer, VideoFormat::Gray8, 3, 2, 1), Rgb::WHITE);
+    }
+
+    #[test]
+    fn test_sine() {
+        gst::init().unwrap();
+
+        let buffer = sine_buffer_s16(48000, 2, 12000.0, 48000, 4);
+        assert_eq!(buffer.get_pts(), gst::SECOND);
+        assert_eq!(buffer.get_size(), 4 * 2 * 2);
+
+        let map = buffer.map_readable().unwrap();
+        let samples = map.as_slice()
+            .chunks(2)
+            .map(LittleEndian::read_i16)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            samples,
+            vec![0, 0, i16::MAX, i16::MAX, 0, 0, -i16::MAX, -i16::MAX]
+        );
+    }
+}
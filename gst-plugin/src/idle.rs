@@ -0,0 +1,116 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use gst;
+use gst::prelude::*;
+
+use base_src::BaseSrc;
+use properties::*;
+
+// Relinking is not signalled to the source, so the pad is checked periodically
+const POLL_INTERVAL: u64 = 100;
+
+pub const IDLE_WHEN_UNLINKED_PROPERTY: Property = Property::Boolean(
+    "idle-when-unlinked",
+    "Idle When Unlinked",
+    "Stop producing data while the source pad is unlinked and resume once it is linked again",
+    false,
+    PropertyMutability::ReadWrite,
+);
+
+struct State {
+    enabled: bool,
+    flushing: bool,
+}
+
+/// Lets the streaming thread of a source wait while its src pad is unlinked instead of
+/// producing data that is dropped anyway.
+///
+/// The source has to call `unlock()` and `unlock_stop()` from the corresponding `BaseSrc`
+/// virtual methods so that waiting is interrupted when flushing or shutting down.
+pub struct Idle {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+impl Idle {
+    pub fn new() -> Idle {
+        Idle {
+            state: Mutex::new(State {
+                enabled: false,
+                flushing: false,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.state.lock().unwrap().enabled = enabled;
+        self.cond.notify_all();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.lock().unwrap().enabled
+    }
+
+    /// Blocks until the src pad of `src` is linked or idling is disabled.
+    ///
+    /// Returns `Err(gst::FlowReturn::Flushing)` if unlocked while waiting.
+    pub fn wait_linked(
+        &self,
+        cat: gst::DebugCategory,
+        src: &BaseSrc,
+    ) -> Result<(), gst::FlowReturn> {
+        let pad = match src.get_static_pad("src") {
+            None => return Ok(()),
+            Some(pad) => pad,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let mut idle = false;
+        loop {
+            if !state.enabled || pad.is_linked() {
+                if idle {
+                    gst_debug!(cat, obj: src, "Linked again, resuming");
+                }
+                return Ok(());
+            }
+            if state.flushing {
+                return Err(gst::FlowReturn::Flushing);
+            }
+
+            if !idle {
+                gst_debug!(cat, obj: src, "Unlinked, idling");
+                idle = true;
+            }
+
+            state = self.cond
+                .wait_timeout(state, Duration::from_millis(POLL_INTERVAL))
+                .unwrap()
+                .0;
+        }
+    }
+
+    pub fn unlock(&self) {
+        self.state.lock().unwrap().flushing = true;
+        self.cond.notify_all();
+    }
+
+    pub fn unlock_stop(&self) {
+        self.state.lock().unwrap().flushing = false;
+    }
+}
+
+impl Default for Idle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -9,6 +9,10 @@
 
 extern crate byteorder;
 extern crate gstreamer_base_sys as gst_base_ffi;
+#[cfg(feature = "v1_14")]
+extern crate gstreamer_audio_sys as gst_audio_ffi;
+#[cfg(feature = "test-harness")]
+extern crate gstreamer_check_sys as gst_check_ffi;
 #[macro_use]
 extern crate lazy_static;
 extern crate libc;
@@ -64,20 +68,47 @@ pub mod adapter;
 #[macro_use]
 pub mod plugin;
 pub mod bytes;
+pub mod registration;
+pub mod dynamic;
+pub mod task;
+pub mod tags;
+pub mod meta;
+pub mod sandbox;
 
 pub mod properties;
+pub mod docs;
 #[macro_use]
 pub mod object;
 #[macro_use]
 pub mod element;
 #[macro_use]
+pub mod pad;
+#[macro_use]
 pub mod bin;
 #[macro_use]
 pub mod pipeline;
 #[macro_use]
 pub mod base_src;
+pub mod idle;
 #[macro_use]
 pub mod base_sink;
 #[macro_use]
 pub mod base_transform;
+#[cfg(feature = "v1_14")]
+#[macro_use]
+pub mod aggregator;
+#[cfg(feature = "v1_14")]
+#[macro_use]
+pub mod aggregator_pad;
+#[cfg(feature = "v1_14")]
+#[macro_use]
+pub mod audio_aggregator;
+#[cfg(feature = "v1_14")]
+pub mod audio_aggregator_pad;
 pub mod uri_handler;
+pub mod child_proxy;
+
+#[cfg(feature = "test-harness")]
+pub mod test;
+#[cfg(feature = "test-harness")]
+pub mod fixtures;
@@ -0,0 +1,221 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Custom buffer meta carrying a `gst::Structure`.
+//!
+//! Elements can attach arbitrary data to buffers with this without registering their own
+//! `GstMeta` type, e.g. analysis results. A buffer can have multiple structure metas, they are
+//! distinguished by the name of their structure. The meta has no tags, so all elements copy it
+//! to their output buffers.
+//!
+//! From C the meta can be found by its API type `GstRsStructureMetaAPI`, the structure is the
+//! first field after the `GstMeta`.
+
+use std::ptr;
+use std::sync::{Once, ONCE_INIT};
+
+use glib_ffi;
+use gst_ffi;
+
+use glib::translate::*;
+use gst;
+
+#[repr(C)]
+struct StructureMeta {
+    parent: gst_ffi::GstMeta,
+    structure: *mut gst_ffi::GstStructure,
+}
+
+unsafe extern "C" fn structure_meta_init(
+    meta: *mut gst_ffi::GstMeta,
+    _params: glib_ffi::gpointer,
+    _buffer: *mut gst_ffi::GstBuffer,
+) -> glib_ffi::gboolean {
+    let meta = &mut *(meta as *mut StructureMeta);
+    meta.structure = ptr::null_mut();
+    glib_ffi::GTRUE
+}
+
+unsafe extern "C" fn structure_meta_free(
+    meta: *mut gst_ffi::GstMeta,
+    _buffer: *mut gst_ffi::GstBuffer,
+) {
+    let meta = &mut *(meta as *mut StructureMeta);
+    if !meta.structure.is_null() {
+        gst_ffi::gst_structure_free(meta.structure);
+        meta.structure = ptr::null_mut();
+    }
+}
+
+unsafe extern "C" fn structure_meta_transform(
+    dest: *mut gst_ffi::GstBuffer,
+    meta: *mut gst_ffi::GstMeta,
+    _buffer: *mut gst_ffi::GstBuffer,
+    _type_: glib_ffi::GQuark,
+    _data: glib_ffi::gpointer,
+) -> glib_ffi::gboolean {
+    // The structure does not depend on the buffer content, so every transformation keeps it
+    let meta = &*(meta as *const StructureMeta);
+    if meta.structure.is_null() {
+        return glib_ffi::GTRUE;
+    }
+
+    let new_meta =
+        gst_ffi::gst_buffer_add_meta(dest, get_meta_info(), ptr::null_mut()) as *mut StructureMeta;
+    if new_meta.is_null() {
+        return glib_ffi::GFALSE;
+    }
+    (*new_meta).structure = gst_ffi::gst_structure_copy(meta.structure);
+
+    glib_ffi::GTRUE
+}
+
+fn get_meta_info() -> *const gst_ffi::GstMetaInfo {
+    static ONCE: Once = ONCE_INIT;
+    static mut INFO: *const gst_ffi::GstMetaInfo = 0 as *const gst_ffi::GstMetaInfo;
+
+    ONCE.call_once(|| unsafe {
+        let mut tags = [ptr::null()];
+        let api = gst_ffi::gst_meta_api_type_register(
+            b"GstRsStructureMetaAPI\0".as_ptr() as *const _,
+            tags.as_mut_ptr(),
+        );
+        INFO = gst_ffi::gst_meta_register(
+            api,
+            b"GstRsStructureMeta\0".as_ptr() as *const _,
+            ::std::mem::size_of::<StructureMeta>(),
+            Some(structure_meta_init),
+            Some(structure_meta_free),
+            Some(structure_meta_transform),
+        );
+    });
+
+    unsafe { INFO }
+}
+
+/// Registers the meta. This has to be called before buffers with the meta are copied by other
+/// elements, e.g. from the element's init function.
+pub fn register() {
+    get_meta_info();
+}
+
+/// Attaches `structure` to the buffer.
+pub fn add_structure_meta(buffer: &mut gst::BufferRef, structure: gst::Structure) {
+    unsafe {
+        let meta = gst_ffi::gst_buffer_add_meta(
+            buffer.as_mut_ptr(),
+            get_meta_info(),
+            ptr::null_mut(),
+        ) as *mut StructureMeta;
+        assert!(!meta.is_null(), "Failed to add meta, buffer not writable");
+        (*meta).structure = structure.to_glib_full() as *mut gst_ffi::GstStructure;
+    }
+}
+
+/// Returns all structures attached to the buffer, in no particular order.
+pub fn get_structure_metas(buffer: &gst::BufferRef) -> Vec<gst::Structure> {
+    let mut structures = Vec::new();
+
+    unsafe {
+        let api = (*get_meta_info()).api;
+        let mut state = ptr::null_mut();
+        loop {
+            let meta = gst_ffi::gst_buffer_iterate_meta(
+                buffer.as_ptr() as *mut gst_ffi::GstBuffer,
+                &mut state,
+            );
+            if meta.is_null() {
+                break;
+            }
+            if (*(*meta).info).api != api {
+                continue;
+            }
+
+            let structure = (*(meta as *const StructureMeta)).structure;
+            if !structure.is_null() {
+                structures.push(from_glib_none(structure as *const gst_ffi::GstStructure));
+            }
+        }
+    }
+
+    structures
+}
+
+/// Returns the first structure with the given name attached to the buffer.
+pub fn get_structure_meta(buffer: &gst::BufferRef, name: &str) -> Option<gst::Structure> {
+    get_structure_metas(buffer)
+        .into_iter()
+        .find(|s| s.get_name() == name)
+}
+
+/// Removes all structures with the given name from the buffer.
+pub fn remove_structure_meta(buffer: &mut gst::BufferRef, name: &str) {
+    unsafe {
+        let api = (*get_meta_info()).api;
+        let ptr = buffer.as_mut_ptr();
+
+        // Removing invalidates the iterator state, so start again after every removal
+        'outer: loop {
+            let mut state = ptr::null_mut();
+            loop {
+                let meta = gst_ffi::gst_buffer_iterate_meta(ptr, &mut state);
+                if meta.is_null() {
+                    break 'outer;
+                }
+                if (*(*meta).info).api != api {
+                    continue;
+                }
+
+                let structure = (*(meta as *const StructureMeta)).structure;
+                if !structure.is_null()
+                    && gst_ffi::gst_structure_has_name(structure, name.to_glib_none().0)
+                        != glib_ffi::GFALSE
+                {
+                    gst_ffi::gst_buffer_remove_meta(ptr, meta);
+                    continue 'outer;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy() {
+        gst::init().unwrap();
+
+        let mut buffer = gst::Buffer::with_size(4).unwrap();
+        add_structure_meta(
+            buffer.get_mut().unwrap(),
+            gst::Structure::new("test-a", &[("value", &1i32)]),
+        );
+        add_structure_meta(
+            buffer.get_mut().unwrap(),
+            gst::Structure::new("test-b", &[("value", &2i32)]),
+        );
+
+        let copy = buffer.copy();
+        let structures = get_structure_metas(&copy);
+        assert_eq!(structures.len(), 2);
+        assert!(structures.iter().any(|s| s.get_name() == "test-a"));
+        assert_eq!(
+            get_structure_meta(&copy, "test-b").and_then(|s| s.get::<i32>("value")),
+            Some(2)
+        );
+        assert!(get_structure_meta(&copy, "test-c").is_none());
+
+        let mut copy = copy;
+        remove_structure_meta(copy.get_mut().unwrap(), "test-a");
+        let structures = get_structure_metas(&copy);
+        assert_eq!(structures.len(), 1);
+        assert_eq!(structures[0].get_name(), "test-b");
+    }
+}
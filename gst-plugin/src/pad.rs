@@ -0,0 +1,90 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use gst_ffi;
+
+use glib;
+use glib::translate::*;
+use gst;
+use gst::prelude::*;
+
+use object::*;
+use anyimpl::*;
+
+/// Subclass of `GstPad`, e.g. for request pads with per-pad properties.
+///
+/// `GstPad` has no virtual methods, the pad functions are set on the instances as usual.
+/// Instances are created with `Pad::new_from_template()`.
+pub trait PadImpl<T: PadBase>: AnyImpl + ObjectImpl<T> + Send + Sync + 'static {}
+
+any_impl!(PadBase, PadImpl);
+
+pub unsafe trait PadBase: IsA<gst::Pad> + ObjectType {}
+
+pub unsafe trait PadClassExt<T: PadBase>
+where
+    T::ImplType: PadImpl<T>,
+{
+}
+
+glib_wrapper! {
+    pub struct Pad(Object<InstanceStruct<Pad>>): [gst::Pad => gst_ffi::GstPad,
+                                                  gst::Object => gst_ffi::GstObject];
+
+    match fn {
+        get_type => || get_type::<Pad>(),
+    }
+}
+
+impl Pad {
+    /// Creates a new pad of the subclass `type_`, which has to be registered with `Pad` as
+    /// parent type.
+    pub fn new_from_template(type_: glib::Type, templ: &gst::PadTemplate, name: &str) -> Pad {
+        glib::Object::new(
+            type_,
+            &[
+                ("name", &name),
+                ("direction", &templ.get_property_direction()),
+                ("template", templ),
+            ],
+        ).unwrap()
+            .downcast::<Pad>()
+            .unwrap()
+    }
+}
+
+unsafe impl PadBase for Pad {}
+pub type PadClass = ClassStruct<Pad>;
+
+// FIXME: Boilerplate
+unsafe impl PadClassExt<Pad> for PadClass {}
+
+#[macro_export]
+macro_rules! box_pad_impl(
+    ($name:ident) => {
+        box_object_impl!($name);
+
+        impl<T: PadBase> PadImpl<T> for Box<$name<T>> {}
+    };
+);
+box_pad_impl!(PadImpl);
+
+impl ObjectType for Pad {
+    const NAME: &'static str = "RsPad";
+    type GlibType = gst_ffi::GstPad;
+    type GlibClassType = gst_ffi::GstPadClass;
+    type ImplType = Box<PadImpl<Self>>;
+
+    fn glib_type() -> glib::Type {
+        unsafe { from_glib(gst_ffi::gst_pad_get_type()) }
+    }
+
+    fn class_init(_token: &ClassInitToken, _klass: &mut PadClass) {}
+
+    object_type_fns!();
+}
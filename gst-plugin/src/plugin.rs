@@ -6,8 +6,58 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+/// Defines the plugin description and the entry point that GStreamer looks up when loading
+/// the plugin.
+///
+/// All string arguments are normal string literals (or macros expanding to string literals,
+/// like `env!()`) and are nul-terminated automatically.
+///
+/// ```ignore
+/// plugin_define!(
+///     "rsaudiofx",
+///     "Rust AudioFx Plugin",
+///     plugin_init,
+///     "1.0",
+///     "MIT/X11",
+///     "rsaudiofx",
+///     "rsaudiofx",
+///     "https://github.com/sdroege/gst-plugin-rs",
+///     "2016-12-08"
+/// );
+/// ```
+///
+/// The short form takes the version from `CARGO_PKG_VERSION` and the source module and package
+/// names from `CARGO_PKG_NAME` of the crate that invokes the macro:
+///
+/// ```ignore
+/// plugin_define!(
+///     "rsaudiofx",
+///     "Rust AudioFx Plugin",
+///     plugin_init,
+///     "MIT/X11",
+///     "https://github.com/sdroege/gst-plugin-rs",
+///     "2016-12-08"
+/// );
+/// ```
+///
+/// The license always has to be passed explicitly as GStreamer only accepts a fixed set of
+/// license strings, which generally does not match the SPDX expression from `Cargo.toml`.
 #[macro_export]
 macro_rules! plugin_define(
+    ($name:expr, $description:expr, $plugin_init:ident,
+     $license:expr, $origin:expr, $release_datetime:expr) => {
+        plugin_define!(
+            $name,
+            $description,
+            $plugin_init,
+            env!("CARGO_PKG_VERSION"),
+            $license,
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_NAME"),
+            $origin,
+            $release_datetime
+        );
+    };
     ($name:expr, $description:expr, $plugin_init:ident,
      $version:expr, $license:expr, $source:expr,
      $package:expr, $origin:expr, $release_datetime:expr) => {
@@ -27,15 +77,15 @@ macro_rules! plugin_define(
             pub static gst_plugin_desc: GstPluginDesc = GstPluginDesc($crate::gst_ffi::GstPluginDesc {
                 major_version: 1,
                 minor_version: 10,
-                name: $name as *const u8 as *const c_char,
-                description: $description as *const u8 as *const c_char,
+                name: concat!($name, "\0") as *const str as *const u8 as *const c_char,
+                description: concat!($description, "\0") as *const str as *const u8 as *const c_char,
                 plugin_init: Some(plugin_init_trampoline),
-                version: $version as *const u8 as *const c_char,
-                license: $license as *const u8 as *const c_char,
-                source: $source as *const u8 as *const c_char,
-                package: $package as *const u8 as *const c_char,
-                origin: $origin as *const u8 as *const c_char,
-                release_datetime: $release_datetime as *const u8 as *const c_char,
+                version: concat!($version, "\0") as *const str as *const u8 as *const c_char,
+                license: concat!($license, "\0") as *const str as *const u8 as *const c_char,
+                source: concat!($source, "\0") as *const str as *const u8 as *const c_char,
+                package: concat!($package, "\0") as *const str as *const u8 as *const c_char,
+                origin: concat!($origin, "\0") as *const str as *const u8 as *const c_char,
+                release_datetime: concat!($release_datetime, "\0") as *const str as *const u8 as *const c_char,
                 _gst_reserved: [0 as $crate::glib_ffi::gpointer; 4],
             });
 
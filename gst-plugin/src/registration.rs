@@ -0,0 +1,190 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::env;
+
+use glib;
+use gst;
+
+pub const RANK_NONE: u32 = 0;
+pub const RANK_MARGINAL: u32 = 64;
+pub const RANK_SECONDARY: u32 = 128;
+pub const RANK_PRIMARY: u32 = 256;
+
+// Comma-separated list of element-name:rank pairs, e.g.
+// GST_PLUGIN_RS_RANK="rsflvdemux:primary+1,rsfilesrc:0"
+const RANK_OVERRIDE_ENV: &str = "GST_PLUGIN_RS_RANK";
+
+lazy_static! {
+    static ref CAT: gst::DebugCategory = {
+        gst::DebugCategory::new(
+            "rsregistration",
+            gst::DebugColorFlags::empty(),
+            "Rust element registration",
+        )
+    };
+}
+
+fn parse_rank(rank: &str) -> Option<u32> {
+    let rank = rank.trim();
+
+    let (base, offset) = match rank.find(|c| c == '+' || c == '-') {
+        Some(idx) if idx > 0 => (&rank[..idx], Some(&rank[idx..])),
+        _ => (rank, None),
+    };
+
+    let base = match base.to_lowercase().as_str() {
+        "none" => RANK_NONE,
+        "marginal" => RANK_MARGINAL,
+        "secondary" => RANK_SECONDARY,
+        "primary" => RANK_PRIMARY,
+        // Offsets are only allowed for the named ranks
+        s if offset.is_none() => return s.parse::<u32>().ok(),
+        _ => return None,
+    };
+
+    match offset {
+        None => Some(base),
+        Some(offset) => {
+            let offset = if offset.starts_with('+') {
+                offset[1..].parse::<i64>().ok()
+            } else {
+                offset.parse::<i64>().ok()
+            };
+
+            offset.and_then(|offset| {
+                let rank = base as i64 + offset;
+                if rank < 0 || rank > u32::max_value() as i64 {
+                    None
+                } else {
+                    Some(rank as u32)
+                }
+            })
+        }
+    }
+}
+
+fn parse_rank_overrides(overrides: &str) -> Vec<(String, u32)> {
+    overrides
+        .split(',')
+        .filter_map(|entry| {
+            let mut split = entry.splitn(2, ':');
+            let name = split.next().map(|s| s.trim()).unwrap_or("");
+            let rank = split.next().and_then(parse_rank);
+
+            match rank {
+                Some(rank) if !name.is_empty() => Some((String::from(name), rank)),
+                _ => {
+                    gst_warning!(CAT, "Invalid rank override '{}'", entry);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn get_rank_override(name: &str) -> Option<u32> {
+    env::var(RANK_OVERRIDE_ENV).ok().and_then(|overrides| {
+        parse_rank_overrides(&overrides)
+            .into_iter()
+            .rev()
+            .find(|&(ref n, _)| n == name)
+            .map(|(_, rank)| rank)
+    })
+}
+
+// Registers a single element factory, taking rank overrides from the environment into account
+pub fn element_register(plugin: &gst::Plugin, name: &str, rank: u32, type_: glib::Type) -> bool {
+    let rank = match get_rank_override(name) {
+        Some(new_rank) => {
+            gst_info!(
+                CAT,
+                "Overriding rank of element '{}' from {} to {}",
+                name,
+                rank,
+                new_rank
+            );
+            new_rank
+        }
+        None => rank,
+    };
+
+    gst::Element::register(plugin, name, rank, type_)
+}
+
+struct ElementEntry {
+    name: String,
+    rank: u32,
+    type_: glib::Type,
+}
+
+pub struct ElementRegistration<'a> {
+    plugin: &'a gst::Plugin,
+    elements: Vec<ElementEntry>,
+}
+
+impl<'a> ElementRegistration<'a> {
+    pub fn new(plugin: &'a gst::Plugin) -> Self {
+        ElementRegistration {
+            plugin: plugin,
+            elements: Vec::new(),
+        }
+    }
+
+    pub fn element(mut self, name: &str, rank: u32, type_: glib::Type) -> Self {
+        self.elements.push(ElementEntry {
+            name: String::from(name),
+            rank: rank,
+            type_: type_,
+        });
+        self
+    }
+
+    // Registers all elements and returns false if any of them failed
+    pub fn register(self) -> bool {
+        let plugin = self.plugin;
+
+        self.elements.iter().fold(true, |res, entry| {
+            let registered = element_register(plugin, &entry.name, entry.rank, entry.type_);
+            if !registered {
+                gst_error!(CAT, "Failed to register element '{}'", entry.name);
+            }
+            res && registered
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rank() {
+        assert_eq!(parse_rank("0"), Some(RANK_NONE));
+        assert_eq!(parse_rank("300"), Some(300));
+        assert_eq!(parse_rank("primary"), Some(RANK_PRIMARY));
+        assert_eq!(parse_rank("PRIMARY+1"), Some(RANK_PRIMARY + 1));
+        assert_eq!(parse_rank("secondary-10"), Some(RANK_SECONDARY - 10));
+        assert_eq!(parse_rank("none-1"), None);
+        assert_eq!(parse_rank("10+1"), None);
+        assert_eq!(parse_rank("foo"), None);
+    }
+
+    #[test]
+    fn test_parse_rank_overrides() {
+        gst::init().unwrap();
+
+        assert_eq!(
+            parse_rank_overrides("rsflvdemux:primary+1, rsfilesrc:0,broken,:1"),
+            vec![
+                (String::from("rsflvdemux"), RANK_PRIMARY + 1),
+                (String::from("rsfilesrc"), RANK_NONE),
+            ]
+        );
+    }
+}
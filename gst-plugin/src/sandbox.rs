@@ -0,0 +1,549 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Opt-in sandbox for running parsing code on untrusted input.
+//!
+//! `Sandbox::new()` starts a worker thread that restricts itself once, `Sandbox::run()` runs the
+//! given code on it and waits for the result. The thread is joined when the sandbox is dropped.
+//! On Linux a seccomp filter is installed for that thread only, which allows
+//! just the syscalls needed for computing on memory and for reading and writing already open
+//! file descriptors. Everything else fails with `EPERM`, including opening files, creating
+//! sockets, creating threads or processes and io_uring. Filesystem and network syscalls can be
+//! allowed again via the settings. The rest of the process, including the streaming threads of
+//! the pipeline, is not affected.
+//!
+//! Parsers can additionally call `reserve()` before allocating memory for sizes read from the
+//! input. This is a cooperative soft budget, not an enforced limit: only memory passed to
+//! `reserve()` is counted. Inside a sandbox with a memory budget it fails once the budget is used
+//! up, outside of a sandbox it always succeeds.
+
+use std::cell::Cell;
+use std::error;
+use std::fmt;
+use std::panic;
+use std::sync::mpsc;
+use std::thread;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SandboxSettings {
+    /// Deny opening, creating, renaming and removing files and directories.
+    pub deny_filesystem: bool,
+    /// Deny creating, connecting and accepting sockets.
+    pub deny_network: bool,
+    /// Maximum number of bytes that can be passed to `reserve()` during a single
+    /// `Sandbox::run()`. Allocations that don't go through `reserve()` are not counted.
+    pub memory_budget: Option<usize>,
+}
+
+impl Default for SandboxSettings {
+    fn default() -> Self {
+        SandboxSettings {
+            deny_filesystem: true,
+            deny_network: true,
+            memory_budget: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SandboxError {
+    /// Syscall restrictions are not supported on this platform.
+    Unsupported,
+    /// Installing the syscall restrictions failed.
+    Setup(String),
+    /// More memory than the budget was reserved.
+    BudgetExceeded { requested: usize, available: usize },
+    /// The sandboxed code panicked.
+    Panicked,
+}
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SandboxError::Unsupported => write!(f, "Sandboxing not supported on this platform"),
+            SandboxError::Setup(ref msg) => write!(f, "Failed to set up sandbox: {}", msg),
+            SandboxError::BudgetExceeded {
+                requested,
+                available,
+            } => write!(
+                f,
+                "Memory budget exceeded: requested {} bytes, {} bytes available",
+                requested, available
+            ),
+            SandboxError::Panicked => write!(f, "Sandboxed code panicked"),
+        }
+    }
+}
+
+impl error::Error for SandboxError {
+    fn description(&self) -> &str {
+        match *self {
+            SandboxError::Unsupported => "Sandboxing not supported",
+            SandboxError::Setup(..) => "Failed to set up sandbox",
+            SandboxError::BudgetExceeded { .. } => "Memory budget exceeded",
+            SandboxError::Panicked => "Sandboxed code panicked",
+        }
+    }
+}
+
+thread_local!(static BUDGET: Cell<Option<usize>> = Cell::new(None));
+
+/// Reserves `size` bytes from the memory budget of the current sandbox.
+///
+/// Parsers should call this before allocating memory or waiting for data whose size comes from
+/// the input. Outside of a sandbox, or in a sandbox without memory budget, this always succeeds.
+pub fn reserve(size: usize) -> Result<(), SandboxError> {
+    BUDGET.with(|budget| match budget.get() {
+        None => Ok(()),
+        Some(available) if size <= available => {
+            budget.set(Some(available - size));
+            Ok(())
+        }
+        Some(available) => Err(SandboxError::BudgetExceeded {
+            requested: size,
+            available: available,
+        }),
+    })
+}
+
+type Job = Box<FnMut() + Send>;
+
+pub struct Sandbox {
+    settings: SandboxSettings,
+    // Dropped before joining the thread to let it exit
+    jobs: Option<mpsc::Sender<Job>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Sandbox {
+    /// Creates a sandbox with the given restrictions and starts its worker thread.
+    ///
+    /// Fails if the restrictions can't be applied, e.g. with `SandboxError::Unsupported` on a
+    /// platform without syscall filtering.
+    pub fn new(settings: SandboxSettings) -> Result<Sandbox, SandboxError> {
+        let (jobs, jobs_receiver) = mpsc::channel::<Job>();
+        let (setup, setup_receiver) = mpsc::channel();
+
+        let thread = thread::Builder::new()
+            .name("sandbox".into())
+            .spawn(move || {
+                let res = restrict(&settings);
+                let failed = res.is_err();
+                let _ = setup.send(res);
+                if failed {
+                    return;
+                }
+
+                for mut job in jobs_receiver.iter() {
+                    BUDGET.with(|budget| budget.set(settings.memory_budget));
+                    job();
+                }
+            })
+            .map_err(|err| SandboxError::Setup(err.to_string()))?;
+
+        // Fail early instead of on the first run
+        let res = setup_receiver
+            .recv()
+            .unwrap_or_else(|_| Err(SandboxError::Setup("Sandbox thread exited".into())));
+        if let Err(err) = res {
+            let _ = thread.join();
+            return Err(err);
+        }
+
+        Ok(Sandbox {
+            settings: settings,
+            jobs: Some(jobs),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn get_settings(&self) -> &SandboxSettings {
+        &self.settings
+    }
+
+    /// Runs `func` on the restricted thread and waits for its result.
+    ///
+    /// The memory budget for `reserve()` starts at the configured budget for every call. Panics
+    /// inside `func` are caught and returned as `SandboxError::Panicked`.
+    pub fn run<F, T>(&self, func: F) -> Result<T, SandboxError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+
+        let mut func = Some(func);
+        let job: Job = Box::new(move || {
+            let func = func.take().unwrap();
+            let res = panic::catch_unwind(panic::AssertUnwindSafe(func))
+                .map_err(|_| SandboxError::Panicked);
+            let _ = sender.send(res);
+        });
+
+        match self.jobs {
+            Some(ref jobs) if jobs.send(job).is_ok() => (),
+            _ => return Err(SandboxError::Panicked),
+        }
+
+        receiver.recv().unwrap_or(Err(SandboxError::Panicked))
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod seccomp {
+    use libc;
+    use std::io;
+
+    #[repr(C)]
+    struct SockFilter {
+        code: u16,
+        jt: u8,
+        jf: u8,
+        k: u32,
+    }
+
+    #[repr(C)]
+    struct SockFprog {
+        len: u16,
+        filter: *const SockFilter,
+    }
+
+    // BPF_LD | BPF_W | BPF_ABS
+    const BPF_LD_W_ABS: u16 = 0x20;
+    // BPF_JMP | BPF_JEQ | BPF_K
+    const BPF_JMP_JEQ_K: u16 = 0x15;
+    // BPF_JMP | BPF_JGE | BPF_K
+    #[cfg(target_arch = "x86_64")]
+    const BPF_JMP_JGE_K: u16 = 0x35;
+    // BPF_RET | BPF_K
+    const BPF_RET_K: u16 = 0x06;
+
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+    const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+    const SECCOMP_MODE_FILTER: libc::c_ulong = 2;
+    const PR_SET_SECCOMP: libc::c_int = 22;
+    const PR_SET_NO_NEW_PRIVS: libc::c_int = 38;
+
+    // Offsets into struct seccomp_data
+    const DATA_NR: u32 = 0;
+    const DATA_ARCH: u32 = 4;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    // Syscalls of the x32 ABI have this bit set and would bypass the numbers below
+    #[cfg(target_arch = "x86_64")]
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    // Computing on memory, reading and writing already open file descriptors, synchronization,
+    // time, signals and exiting the thread
+    const BASE: &[libc::c_long] = &[
+        libc::SYS_read,
+        libc::SYS_write,
+        libc::SYS_readv,
+        libc::SYS_writev,
+        libc::SYS_pread64,
+        libc::SYS_pwrite64,
+        libc::SYS_lseek,
+        libc::SYS_fstat,
+        libc::SYS_close,
+        libc::SYS_ppoll,
+        libc::SYS_brk,
+        libc::SYS_mmap,
+        libc::SYS_munmap,
+        libc::SYS_mremap,
+        libc::SYS_mprotect,
+        libc::SYS_madvise,
+        libc::SYS_futex,
+        libc::SYS_sched_yield,
+        libc::SYS_sched_getaffinity,
+        libc::SYS_nanosleep,
+        libc::SYS_clock_nanosleep,
+        libc::SYS_clock_gettime,
+        libc::SYS_clock_getres,
+        libc::SYS_gettimeofday,
+        libc::SYS_getpid,
+        libc::SYS_gettid,
+        libc::SYS_getrandom,
+        libc::SYS_rt_sigaction,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_sigaltstack,
+        libc::SYS_tgkill,
+        libc::SYS_restart_syscall,
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+    ];
+    #[cfg(target_arch = "x86_64")]
+    const BASE_LEGACY: &[libc::c_long] = &[libc::SYS_poll];
+    #[cfg(not(target_arch = "x86_64"))]
+    const BASE_LEGACY: &[libc::c_long] = &[];
+
+    const FILESYSTEM: &[libc::c_long] = &[
+        libc::SYS_openat,
+        libc::SYS_newfstatat,
+        libc::SYS_faccessat,
+        libc::SYS_readlinkat,
+        libc::SYS_getdents64,
+        libc::SYS_getcwd,
+        libc::SYS_fcntl,
+        libc::SYS_dup,
+        libc::SYS_dup3,
+        libc::SYS_linkat,
+        libc::SYS_symlinkat,
+        libc::SYS_unlinkat,
+        libc::SYS_renameat,
+        libc::SYS_mkdirat,
+        libc::SYS_mknodat,
+        libc::SYS_truncate,
+        libc::SYS_ftruncate,
+        libc::SYS_fsync,
+        libc::SYS_fdatasync,
+    ];
+    #[cfg(target_arch = "x86_64")]
+    const FILESYSTEM_LEGACY: &[libc::c_long] = &[
+        libc::SYS_open,
+        libc::SYS_creat,
+        libc::SYS_stat,
+        libc::SYS_lstat,
+        libc::SYS_access,
+        libc::SYS_readlink,
+        libc::SYS_dup2,
+        libc::SYS_link,
+        libc::SYS_symlink,
+        libc::SYS_unlink,
+        libc::SYS_rename,
+        libc::SYS_mkdir,
+        libc::SYS_rmdir,
+        libc::SYS_mknod,
+    ];
+    #[cfg(not(target_arch = "x86_64"))]
+    const FILESYSTEM_LEGACY: &[libc::c_long] = &[];
+
+    const NETWORK: &[libc::c_long] = &[
+        libc::SYS_socket,
+        libc::SYS_socketpair,
+        libc::SYS_connect,
+        libc::SYS_bind,
+        libc::SYS_listen,
+        libc::SYS_accept,
+        libc::SYS_accept4,
+        libc::SYS_sendto,
+        libc::SYS_recvfrom,
+        libc::SYS_sendmsg,
+        libc::SYS_recvmsg,
+        libc::SYS_shutdown,
+        libc::SYS_getsockname,
+        libc::SYS_getpeername,
+        libc::SYS_setsockopt,
+        libc::SYS_getsockopt,
+    ];
+
+    fn stmt(code: u16, k: u32) -> SockFilter {
+        SockFilter {
+            code: code,
+            jt: 0,
+            jf: 0,
+            k: k,
+        }
+    }
+
+    fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+        SockFilter {
+            code: code,
+            jt: jt,
+            jf: jf,
+            k: k,
+        }
+    }
+
+    // Only the listed syscalls are allowed, all others including any added by newer kernels fail
+    // with EPERM. Creating threads and processes is never allowed.
+    pub fn install(deny_filesystem: bool, deny_network: bool) -> Result<(), String> {
+        let mut allowed = Vec::new();
+        allowed.extend_from_slice(BASE);
+        allowed.extend_from_slice(BASE_LEGACY);
+        if !deny_filesystem {
+            allowed.extend_from_slice(FILESYSTEM);
+            allowed.extend_from_slice(FILESYSTEM_LEGACY);
+        }
+        if !deny_network {
+            allowed.extend_from_slice(NETWORK);
+        }
+
+        let deny = SECCOMP_RET_ERRNO | (libc::EPERM as u32);
+
+        let mut filter = Vec::new();
+        // Deny everything for foreign architectures, syscall numbers would be different
+        filter.push(stmt(BPF_LD_W_ABS, DATA_ARCH));
+        filter.push(jump(BPF_JMP_JEQ_K, AUDIT_ARCH, 1, 0));
+        filter.push(stmt(BPF_RET_K, deny));
+        filter.push(stmt(BPF_LD_W_ABS, DATA_NR));
+        #[cfg(target_arch = "x86_64")]
+        {
+            filter.push(jump(BPF_JMP_JGE_K, X32_SYSCALL_BIT, 0, 1));
+            filter.push(stmt(BPF_RET_K, deny));
+        }
+
+        // Each check jumps over the remaining checks and the deny return on a match
+        let n = allowed.len();
+        assert!(n < 256);
+        for (i, nr) in allowed.iter().enumerate() {
+            filter.push(jump(BPF_JMP_JEQ_K, *nr as u32, (n - i) as u8, 0));
+        }
+        filter.push(stmt(BPF_RET_K, deny));
+        filter.push(stmt(BPF_RET_K, SECCOMP_RET_ALLOW));
+
+        let prog = SockFprog {
+            len: filter.len() as u16,
+            filter: filter.as_ptr(),
+        };
+
+        unsafe {
+            if libc::prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(format!(
+                    "Failed to set no_new_privs: {}",
+                    io::Error::last_os_error()
+                ));
+            }
+
+            if libc::prctl(
+                PR_SET_SECCOMP,
+                SECCOMP_MODE_FILTER,
+                &prog as *const SockFprog,
+                0,
+                0,
+            ) != 0
+            {
+                return Err(format!(
+                    "Failed to install seccomp filter: {}",
+                    io::Error::last_os_error()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn restrict(settings: &SandboxSettings) -> Result<(), SandboxError> {
+    seccomp::install(settings.deny_filesystem, settings.deny_network).map_err(SandboxError::Setup)
+}
+
+#[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+fn restrict(settings: &SandboxSettings) -> Result<(), SandboxError> {
+    if settings.deny_filesystem || settings.deny_network {
+        Err(SandboxError::Unsupported)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process;
+    use std::thread;
+
+    fn settings(memory_budget: Option<usize>) -> SandboxSettings {
+        SandboxSettings {
+            deny_filesystem: false,
+            deny_network: false,
+            memory_budget: memory_budget,
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let sandbox = Sandbox::new(settings(None)).unwrap();
+
+        let data = vec![1u8, 2, 3];
+        let (data, sum) = sandbox
+            .run(move || {
+                let mut data = data;
+                data.push(4);
+                let sum = data.iter().map(|v| *v as u32).sum::<u32>();
+                (data, sum)
+            })
+            .unwrap();
+        assert_eq!(sum, 10);
+        assert_eq!(data.len(), 4);
+
+        // All runs happen on the same thread
+        let first = sandbox.run(|| thread::current().id()).unwrap();
+        assert_eq!(sandbox.run(|| thread::current().id()), Ok(first));
+        assert_ne!(first, thread::current().id());
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        assert_eq!(reserve(usize::max_value()), Ok(()));
+
+        let sandbox = Sandbox::new(settings(Some(100))).unwrap();
+        assert_eq!(sandbox.run(|| reserve(60)), Ok(Ok(())));
+        // The budget is reset for every run
+        assert_eq!(
+            sandbox.run(|| (reserve(60), reserve(60))),
+            Ok((
+                Ok(()),
+                Err(SandboxError::BudgetExceeded {
+                    requested: 60,
+                    available: 40,
+                })
+            ))
+        );
+    }
+
+    #[test]
+    fn test_panic() {
+        let sandbox = Sandbox::new(settings(None)).unwrap();
+        assert_eq!(
+            sandbox.run(|| panic!("parser bug")),
+            Err::<(), _>(SandboxError::Panicked)
+        );
+        assert_eq!(sandbox.run(|| 1), Ok(1));
+    }
+
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    #[test]
+    fn test_deny_filesystem() {
+        let sandbox = Sandbox::new(SandboxSettings::default()).unwrap();
+        let res = sandbox.run(|| fs::File::open("/dev/null").map_err(|err| err.raw_os_error()));
+        assert_eq!(res.unwrap().err(), Some(Some(::libc::EPERM)));
+
+        // Only the sandbox thread is restricted
+        assert!(fs::File::open("/dev/null").is_ok());
+    }
+
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    #[test]
+    fn test_deny_processes() {
+        // Even with filesystem and network access, threads and processes can't be created
+        let sandbox = Sandbox::new(settings(None)).unwrap();
+        let res = sandbox.run(|| {
+            (
+                process::Command::new("true").status().is_ok(),
+                thread::Builder::new().spawn(|| ()).is_ok(),
+            )
+        });
+        assert_eq!(res, Ok((false, false)));
+    }
+}
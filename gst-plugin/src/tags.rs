@@ -0,0 +1,351 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Language and track role tagging of streams.
+//!
+//! The language is stored in the standard `language-code` tag, the role of a track (e.g. audio
+//! description or commentary) in the custom `track-role` tag registered by `register()`. Muxers
+//! and adaptive streaming sinks should use `StreamInfo::from_tags()` to map both to their
+//! container or manifest specific representation.
+
+use std::ffi::CStr;
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::sync::{Once, ONCE_INIT};
+
+use glib_ffi;
+use gobject_ffi;
+use gst_ffi;
+
+use glib::translate::*;
+use gst;
+
+pub const TRACK_ROLE: &str = "track-role";
+
+/// Registers the custom tags of this module. Can safely be called multiple times.
+pub fn register() {
+    static REGISTER: Once = ONCE_INIT;
+
+    REGISTER.call_once(|| unsafe {
+        gst_ffi::gst_tag_register(
+            b"track-role\0".as_ptr() as *const _,
+            gst_ffi::GST_TAG_FLAG_META,
+            gobject_ffi::G_TYPE_STRING,
+            b"track role\0".as_ptr() as *const _,
+            b"role of the track, e.g. main, alternate, commentary or description\0".as_ptr()
+                as *const _,
+            None,
+        );
+    });
+}
+
+/// Track roles, named after the DASH role scheme (`urn:mpeg:dash:role:2011`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrackRole {
+    Main,
+    Alternate,
+    Commentary,
+    Description,
+    Caption,
+    Subtitle,
+    Dub,
+    Sign,
+}
+
+impl TrackRole {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            TrackRole::Main => "main",
+            TrackRole::Alternate => "alternate",
+            TrackRole::Commentary => "commentary",
+            TrackRole::Description => "description",
+            TrackRole::Caption => "caption",
+            TrackRole::Subtitle => "subtitle",
+            TrackRole::Dub => "dub",
+            TrackRole::Sign => "sign",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<TrackRole> {
+        match s {
+            "main" => Some(TrackRole::Main),
+            "alternate" => Some(TrackRole::Alternate),
+            "commentary" => Some(TrackRole::Commentary),
+            "description" => Some(TrackRole::Description),
+            "caption" => Some(TrackRole::Caption),
+            "subtitle" => Some(TrackRole::Subtitle),
+            "dub" => Some(TrackRole::Dub),
+            "sign" => Some(TrackRole::Sign),
+            _ => None,
+        }
+    }
+
+    /// Whether this is an accessibility track, e.g. for the HLS `CHARACTERISTICS` attribute.
+    pub fn is_accessibility(&self) -> bool {
+        match *self {
+            TrackRole::Description | TrackRole::Caption => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for TrackRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// ISO 639-1, ISO 639-2/T and ISO 639-2/B codes of a language
+type Language = (&'static str, &'static str, &'static str);
+
+// Common languages
+static LANGUAGES: &[Language] = &[
+    ("af", "afr", "afr"),
+    ("ar", "ara", "ara"),
+    ("bg", "bul", "bul"),
+    ("bn", "ben", "ben"),
+    ("bo", "bod", "tib"),
+    ("ca", "cat", "cat"),
+    ("cs", "ces", "cze"),
+    ("cy", "cym", "wel"),
+    ("da", "dan", "dan"),
+    ("de", "deu", "ger"),
+    ("el", "ell", "gre"),
+    ("en", "eng", "eng"),
+    ("es", "spa", "spa"),
+    ("et", "est", "est"),
+    ("eu", "eus", "baq"),
+    ("fa", "fas", "per"),
+    ("fi", "fin", "fin"),
+    ("fr", "fra", "fre"),
+    ("ga", "gle", "gle"),
+    ("gl", "glg", "glg"),
+    ("he", "heb", "heb"),
+    ("hi", "hin", "hin"),
+    ("hr", "hrv", "hrv"),
+    ("hu", "hun", "hun"),
+    ("hy", "hye", "arm"),
+    ("id", "ind", "ind"),
+    ("is", "isl", "ice"),
+    ("it", "ita", "ita"),
+    ("ja", "jpn", "jpn"),
+    ("ka", "kat", "geo"),
+    ("ko", "kor", "kor"),
+    ("la", "lat", "lat"),
+    ("lt", "lit", "lit"),
+    ("lv", "lav", "lav"),
+    ("mi", "mri", "mao"),
+    ("mk", "mkd", "mac"),
+    ("ms", "msa", "may"),
+    ("my", "mya", "bur"),
+    ("nb", "nob", "nob"),
+    ("nl", "nld", "dut"),
+    ("nn", "nno", "nno"),
+    ("no", "nor", "nor"),
+    ("pa", "pan", "pan"),
+    ("pl", "pol", "pol"),
+    ("pt", "por", "por"),
+    ("ro", "ron", "rum"),
+    ("ru", "rus", "rus"),
+    ("sk", "slk", "slo"),
+    ("sl", "slv", "slv"),
+    ("sq", "sqi", "alb"),
+    ("sr", "srp", "srp"),
+    ("sv", "swe", "swe"),
+    ("sw", "swa", "swa"),
+    ("ta", "tam", "tam"),
+    ("te", "tel", "tel"),
+    ("th", "tha", "tha"),
+    ("tr", "tur", "tur"),
+    ("uk", "ukr", "ukr"),
+    ("ur", "urd", "urd"),
+    ("vi", "vie", "vie"),
+    ("zh", "zho", "chi"),
+];
+
+/// Language and role of a stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamInfo {
+    /// ISO 639 language code
+    pub language: Option<String>,
+    pub role: Option<TrackRole>,
+}
+
+impl StreamInfo {
+    pub fn from_tags(tags: &gst::TagListRef) -> StreamInfo {
+        register();
+
+        StreamInfo {
+            language: get_string(tags, "language-code"),
+            role: get_string(tags, TRACK_ROLE).and_then(|role| TrackRole::from_name(&role)),
+        }
+    }
+
+    /// Updates `self` with all values that are set in `tags`.
+    pub fn update(&mut self, tags: &gst::TagListRef) {
+        let info = StreamInfo::from_tags(tags);
+        if info.language.is_some() {
+            self.language = info.language;
+        }
+        if info.role.is_some() {
+            self.role = info.role;
+        }
+    }
+
+    // Primary language subtag in lowercase and the matching table entry, if any
+    fn language_code(&self) -> Option<(String, Option<&'static Language>)> {
+        let language = self.language.as_ref()?;
+        let code = language
+            .split(|c| c == '-' || c == '_')
+            .next()
+            .unwrap()
+            .trim()
+            .to_lowercase();
+        if code.len() < 2 || code.len() > 3 || !code.chars().all(|c| c.is_ascii_lowercase()) {
+            return None;
+        }
+
+        let entry = LANGUAGES
+            .iter()
+            .find(|&&(a, t, b)| code == a || code == t || code == b);
+        Some((code, entry))
+    }
+
+    /// ISO 639-2/T code of the language, e.g. for the MP4 `mdhd` box.
+    pub fn language_iso_639_2t(&self) -> Option<String> {
+        match self.language_code()? {
+            (_, Some(&(_, t, _))) => Some(String::from(t)),
+            (ref code, None) if code.len() == 3 => Some(code.clone()),
+            _ => None,
+        }
+    }
+
+    /// ISO 639-2/B code of the language, e.g. for Matroska and MPEG-TS.
+    pub fn language_iso_639_2b(&self) -> Option<String> {
+        match self.language_code()? {
+            (_, Some(&(_, _, b))) => Some(String::from(b)),
+            (ref code, None) if code.len() == 3 => Some(code.clone()),
+            _ => None,
+        }
+    }
+
+    /// RFC 5646 primary language subtag, i.e. the ISO 639-1 code if there is one, e.g. for HLS
+    /// and DASH manifests.
+    pub fn language_rfc5646(&self) -> Option<String> {
+        match self.language_code()? {
+            (_, Some(&(a, _, _))) => Some(String::from(a)),
+            (code, None) => Some(code),
+        }
+    }
+
+    /// Whether this is the default track of its type, i.e. it has no role other than main.
+    pub fn is_default(&self) -> bool {
+        match self.role {
+            None | Some(TrackRole::Main) => true,
+            _ => false,
+        }
+    }
+
+    /// Creates a tag list with the language and role, e.g. for sending as tag event from
+    /// elements that have language or role properties.
+    pub fn to_tags(&self) -> gst::TagList {
+        register();
+
+        let mut tags = gst::TagList::new();
+        {
+            let tags = tags.get_mut().unwrap();
+            unsafe {
+                if let Some(ref language) = self.language {
+                    add_string(tags, "language-code", language);
+                }
+                if let Some(role) = self.role {
+                    add_string(tags, TRACK_ROLE, role.as_str());
+                }
+            }
+        }
+
+        tags
+    }
+}
+
+fn get_string(tags: &gst::TagListRef, tag: &str) -> Option<String> {
+    unsafe {
+        let mut value = ptr::null_mut();
+        let res: bool = from_glib(gst_ffi::gst_tag_list_get_string(
+            tags.as_ptr(),
+            tag.to_glib_none().0,
+            &mut value,
+        ));
+
+        if !res || value.is_null() {
+            return None;
+        }
+
+        let s = CStr::from_ptr(value).to_str().ok().map(String::from);
+        glib_ffi::g_free(value as glib_ffi::gpointer);
+        s
+    }
+}
+
+unsafe fn add_string(tags: &mut gst::TagListRef, tag: &str, value: &str) {
+    let mut gvalue: gobject_ffi::GValue = mem::zeroed();
+    gobject_ffi::g_value_init(&mut gvalue, gobject_ffi::G_TYPE_STRING);
+    gobject_ffi::g_value_set_string(&mut gvalue, value.to_glib_none().0);
+    gst_ffi::gst_tag_list_add_value(
+        tags.as_mut_ptr(),
+        gst_ffi::GST_TAG_MERGE_REPLACE,
+        tag.to_glib_none().0,
+        &gvalue,
+    );
+    gobject_ffi::g_value_unset(&mut gvalue);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        gst::init().unwrap();
+
+        let info = StreamInfo {
+            language: Some(String::from("deu")),
+            role: Some(TrackRole::Description),
+        };
+        let tags = info.to_tags();
+        assert_eq!(StreamInfo::from_tags(&tags), info);
+
+        let mut updated = StreamInfo {
+            language: Some(String::from("eng")),
+            role: None,
+        };
+        updated.update(&tags);
+        assert_eq!(updated, info);
+    }
+
+    #[test]
+    fn test_language_codes() {
+        let info = |language: &str| StreamInfo {
+            language: Some(String::from(language)),
+            role: None,
+        };
+
+        assert_eq!(info("de").language_iso_639_2t(), Some(String::from("deu")));
+        assert_eq!(info("de-AT").language_iso_639_2b(), Some(String::from("ger")));
+        assert_eq!(info("fre").language_iso_639_2t(), Some(String::from("fra")));
+        assert_eq!(info("FRA").language_rfc5646(), Some(String::from("fr")));
+        assert_eq!(info("nds").language_iso_639_2b(), Some(String::from("nds")));
+        assert_eq!(info("nds").language_rfc5646(), Some(String::from("nds")));
+        assert_eq!(info("xx").language_iso_639_2t(), None);
+        assert_eq!(info("xx").language_rfc5646(), Some(String::from("xx")));
+        assert_eq!(info("english").language_rfc5646(), None);
+        assert_eq!(StreamInfo::default().language_iso_639_2t(), None);
+        assert!(StreamInfo::default().is_default());
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Closure based wrappers around `GstTask` and pad tasks.
+//!
+//! Loop based elements, like pull mode demuxers or sources doing blocking network IO, should use
+//! these instead of managing their own threads so that the tasks take part in the usual stream
+//! lock and flushing handling.
+
+use std::mem;
+use std::sync::Arc;
+
+use glib_ffi;
+use gst_ffi;
+
+use glib::translate::*;
+use gst;
+use gst::prelude::*;
+
+lazy_static! {
+    static ref CAT: gst::DebugCategory = {
+        gst::DebugCategory::new(
+            "rstask",
+            gst::DebugColorFlags::empty(),
+            "Rust task helpers",
+        )
+    };
+}
+
+unsafe extern "C" fn task_trampoline<F: FnMut() + Send + 'static>(user_data: glib_ffi::gpointer) {
+    callback_guard!();
+    let func = &mut *(user_data as *mut F);
+    func();
+}
+
+unsafe extern "C" fn task_destroy<F: FnMut() + Send + 'static>(user_data: glib_ffi::gpointer) {
+    callback_guard!();
+    Box::from_raw(user_data as *mut F);
+}
+
+/// Starts a task on `pad` that calls `func` in a loop until the task is paused or stopped.
+///
+/// The closure is called with the stream lock of the pad taken and is dropped once the task is
+/// stopped.
+pub fn start_pad_task<F: FnMut() + Send + 'static>(pad: &gst::Pad, func: F) -> bool {
+    gst_debug!(CAT, obj: pad, "Starting task");
+
+    unsafe {
+        let func = Box::into_raw(Box::new(func));
+        from_glib(gst_ffi::gst_pad_start_task(
+            pad.to_glib_none().0,
+            Some(task_trampoline::<F>),
+            func as glib_ffi::gpointer,
+            Some(task_destroy::<F>),
+        ))
+    }
+}
+
+/// Sets an activate mode function on `pad` that runs `func` in a pad task while the pad is
+/// active in `mode`.
+///
+/// The task is started when the pad is activated and stopped when it is deactivated, which
+/// happens automatically during the `Paused` to `Ready` state change. Whenever `func` returns
+/// anything but `gst::FlowReturn::Ok` the task is paused, and it is up to `func` to handle the
+/// flow return, e.g. by pushing an EOS event or posting an error message before returning it.
+///
+/// For `gst::PadMode::Pull` sink pads, the activate function of the pad has to activate the pad
+/// in pull mode.
+pub fn set_pad_task_function<F>(pad: &gst::Pad, mode: gst::PadMode, func: F)
+where
+    F: Fn(&gst::Pad) -> gst::FlowReturn + Send + Sync + 'static,
+{
+    let func = Arc::new(func);
+
+    pad.set_activatemode_function(move |pad, _parent, activate_mode, active| {
+        if activate_mode != mode {
+            return true;
+        }
+
+        if active {
+            let func = func.clone();
+            let task_pad = pad.clone();
+            start_pad_task(pad, move || {
+                let ret = func(&task_pad);
+                if ret != gst::FlowReturn::Ok {
+                    gst_debug!(CAT, obj: &task_pad, "Pausing task: {:?}", ret);
+                    let _ = task_pad.pause_task();
+                }
+            })
+        } else {
+            gst_debug!(CAT, obj: pad, "Stopping task");
+            pad.stop_task().is_ok()
+        }
+    });
+}
+
+/// Standalone task with its own lock, for loops that are not bound to a pad.
+///
+/// The task is stopped and joined when dropped.
+pub struct Task {
+    task: *mut gst_ffi::GstTask,
+    lock: Box<glib_ffi::GRecMutex>,
+}
+
+unsafe impl Send for Task {}
+unsafe impl Sync for Task {}
+
+impl Task {
+    pub fn new<F: FnMut() + Send + 'static>(func: F) -> Self {
+        unsafe {
+            let mut lock: Box<glib_ffi::GRecMutex> = Box::new(mem::zeroed());
+            glib_ffi::g_rec_mutex_init(&mut *lock);
+
+            let func = Box::into_raw(Box::new(func));
+            let task = gst_ffi::gst_task_new(
+                Some(task_trampoline::<F>),
+                func as glib_ffi::gpointer,
+                Some(task_destroy::<F>),
+            );
+            gst_ffi::gst_object_ref_sink(task as *mut _);
+            gst_ffi::gst_task_set_lock(task, &mut *lock);
+
+            Task {
+                task: task,
+                lock: lock,
+            }
+        }
+    }
+
+    pub fn start(&self) -> bool {
+        unsafe { from_glib(gst_ffi::gst_task_start(self.task)) }
+    }
+
+    pub fn pause(&self) -> bool {
+        unsafe { from_glib(gst_ffi::gst_task_pause(self.task)) }
+    }
+
+    /// Stops the task after the current iteration. Use `join()` to wait for it.
+    pub fn stop(&self) -> bool {
+        unsafe { from_glib(gst_ffi::gst_task_stop(self.task)) }
+    }
+
+    /// Stops the task and waits until the current iteration is finished.
+    ///
+    /// Must not be called from the task itself.
+    pub fn join(&self) -> bool {
+        unsafe { from_glib(gst_ffi::gst_task_join(self.task)) }
+    }
+
+    pub fn get_state(&self) -> gst::TaskState {
+        unsafe { from_glib(gst_ffi::gst_task_get_state(self.task)) }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        unsafe {
+            gst_ffi::gst_task_join(self.task);
+            gst_ffi::gst_object_unref(self.task as *mut _);
+            glib_ffi::g_rec_mutex_clear(&mut *self.lock);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_task() {
+        gst::init().unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let mut count = 0;
+        let task = Task::new(move || {
+            count += 1;
+            let _ = sender.send(count);
+        });
+
+        assert!(task.start());
+        assert_eq!(receiver.recv().unwrap(), 1);
+        assert_eq!(receiver.recv().unwrap(), 2);
+        assert!(task.join());
+        assert_eq!(task.get_state(), gst::TaskState::Stopped);
+    }
+}
@@ -0,0 +1,523 @@
+// Copyright (C) 2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use glib::IsA;
+use glib::translate::*;
+use gst;
+use gst::prelude::*;
+use gst_check_ffi;
+
+use std::path::Path;
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+/// Thin wrapper around `GstHarness` for testing single elements.
+///
+/// The harness links a test src pad to the element's sink pad and a test sink pad to the
+/// element's src pad, which allows pushing buffers and events into the element and pulling
+/// whatever it outputs. By default the harness uses a `GstTestClock`, which can be advanced
+/// with `crank_single_clock_wait()` or `set_time()`.
+///
+/// ```ignore
+/// let mut h = Harness::new("rsaudioecho");
+/// h.set_src_caps_str("audio/x-raw,format=F64LE,rate=48000,channels=1,layout=interleaved");
+/// assert_eq!(h.push(buffer), gst::FlowReturn::Ok);
+/// let output = h.pull().unwrap();
+/// ```
+pub struct Harness(*mut gst_check_ffi::GstHarness);
+
+impl Harness {
+    /// Creates a new harness around a new element from the factory `factory_name`, using its
+    /// "sink" and "src" pads.
+    pub fn new(factory_name: &str) -> Harness {
+        assert_initialized();
+        unsafe {
+            let h = gst_check_ffi::gst_harness_new(factory_name.to_glib_none().0);
+            assert!(!h.is_null(), "Failed to create harness for '{}'", factory_name);
+            Harness(h)
+        }
+    }
+
+    /// Creates a new harness around an existing element. `None` for a pad name means the
+    /// element has no such pad.
+    pub fn new_with_element<P: IsA<gst::Element>>(
+        element: &P,
+        sink_pad_name: Option<&str>,
+        src_pad_name: Option<&str>,
+    ) -> Harness {
+        assert_initialized();
+        unsafe {
+            let h = gst_check_ffi::gst_harness_new_with_element(
+                element.to_glib_none().0,
+                sink_pad_name.to_glib_none().0,
+                src_pad_name.to_glib_none().0,
+            );
+            assert!(!h.is_null(), "Failed to create harness");
+            Harness(h)
+        }
+    }
+
+    /// Creates a new harness from a `gst-launch` style pipeline description, e.g.
+    /// `"capsfilter caps=video/x-raw ! rsvideofilter"`.
+    pub fn new_parse(launchline: &str) -> Harness {
+        assert_initialized();
+        unsafe {
+            let h = gst_check_ffi::gst_harness_new_parse(launchline.to_glib_none().0);
+            assert!(!h.is_null(), "Failed to create harness for '{}'", launchline);
+            Harness(h)
+        }
+    }
+
+    pub fn get_element(&self) -> gst::Element {
+        unsafe { from_glib_none((*self.0).element) }
+    }
+
+    /// Sets the caps on the harness src pad, i.e. the caps of the data pushed into the
+    /// element. Also sends stream-start and a time segment if this did not happen yet.
+    pub fn set_src_caps(&mut self, caps: gst::Caps) {
+        unsafe {
+            gst_check_ffi::gst_harness_set_src_caps(self.0, caps.into_ptr());
+        }
+    }
+
+    pub fn set_src_caps_str(&mut self, caps: &str) {
+        unsafe {
+            gst_check_ffi::gst_harness_set_src_caps_str(self.0, caps.to_glib_none().0);
+        }
+    }
+
+    /// Sets the caps accepted by the harness sink pad, i.e. the caps the element is allowed
+    /// to output.
+    pub fn set_sink_caps(&mut self, caps: gst::Caps) {
+        unsafe {
+            gst_check_ffi::gst_harness_set_sink_caps(self.0, caps.into_ptr());
+        }
+    }
+
+    pub fn set_sink_caps_str(&mut self, caps: &str) {
+        unsafe {
+            gst_check_ffi::gst_harness_set_sink_caps_str(self.0, caps.to_glib_none().0);
+        }
+    }
+
+    pub fn set_caps(&mut self, in_caps: gst::Caps, out_caps: gst::Caps) {
+        unsafe {
+            gst_check_ffi::gst_harness_set_caps(self.0, in_caps.into_ptr(), out_caps.into_ptr());
+        }
+    }
+
+    pub fn set_caps_str(&mut self, in_caps: &str, out_caps: &str) {
+        unsafe {
+            gst_check_ffi::gst_harness_set_caps_str(
+                self.0,
+                in_caps.to_glib_none().0,
+                out_caps.to_glib_none().0,
+            );
+        }
+    }
+
+    /// Sets the element to Playing. This is done automatically when creating the harness for
+    /// elements with both a sink and a src pad.
+    pub fn play(&mut self) {
+        unsafe {
+            gst_check_ffi::gst_harness_play(self.0);
+        }
+    }
+
+    pub fn push(&mut self, buffer: gst::Buffer) -> gst::FlowReturn {
+        unsafe { from_glib(gst_check_ffi::gst_harness_push(self.0, buffer.into_ptr())) }
+    }
+
+    /// Waits for up to 60 seconds for a buffer from the element.
+    pub fn pull(&mut self) -> Option<gst::Buffer> {
+        unsafe { from_glib_full(gst_check_ffi::gst_harness_pull(self.0)) }
+    }
+
+    /// Returns the next buffer from the element if there is one already.
+    pub fn try_pull(&mut self) -> Option<gst::Buffer> {
+        unsafe { from_glib_full(gst_check_ffi::gst_harness_try_pull(self.0)) }
+    }
+
+    pub fn push_and_pull(&mut self, buffer: gst::Buffer) -> Option<gst::Buffer> {
+        unsafe {
+            from_glib_full(gst_check_ffi::gst_harness_push_and_pull(
+                self.0,
+                buffer.into_ptr(),
+            ))
+        }
+    }
+
+    pub fn push_event(&mut self, event: gst::Event) -> bool {
+        unsafe { from_glib(gst_check_ffi::gst_harness_push_event(self.0, event.into_ptr())) }
+    }
+
+    /// Sends an event upstream from the harness sink pad, e.g. a seek or QoS event.
+    pub fn push_upstream_event(&mut self, event: gst::Event) -> bool {
+        unsafe {
+            from_glib(gst_check_ffi::gst_harness_push_upstream_event(
+                self.0,
+                event.into_ptr(),
+            ))
+        }
+    }
+
+    pub fn pull_event(&mut self) -> Option<gst::Event> {
+        unsafe { from_glib_full(gst_check_ffi::gst_harness_pull_event(self.0)) }
+    }
+
+    pub fn try_pull_event(&mut self) -> Option<gst::Event> {
+        unsafe { from_glib_full(gst_check_ffi::gst_harness_try_pull_event(self.0)) }
+    }
+
+    pub fn pull_upstream_event(&mut self) -> Option<gst::Event> {
+        unsafe { from_glib_full(gst_check_ffi::gst_harness_pull_upstream_event(self.0)) }
+    }
+
+    pub fn try_pull_upstream_event(&mut self) -> Option<gst::Event> {
+        unsafe { from_glib_full(gst_check_ffi::gst_harness_try_pull_upstream_event(self.0)) }
+    }
+
+    /// Number of buffers received by the harness sink pad so far.
+    pub fn buffers_received(&self) -> u32 {
+        unsafe { gst_check_ffi::gst_harness_buffers_received(self.0) }
+    }
+
+    /// Number of buffers currently queued in the harness sink pad.
+    pub fn buffers_in_queue(&self) -> u32 {
+        unsafe { gst_check_ffi::gst_harness_buffers_in_queue(self.0) }
+    }
+
+    pub fn events_received(&self) -> u32 {
+        unsafe { gst_check_ffi::gst_harness_events_received(self.0) }
+    }
+
+    /// Replaces the element's clock with a `GstTestClock`.
+    pub fn use_testclock(&mut self) {
+        unsafe {
+            gst_check_ffi::gst_harness_use_testclock(self.0);
+        }
+    }
+
+    pub fn use_systemclock(&mut self) {
+        unsafe {
+            gst_check_ffi::gst_harness_use_systemclock(self.0);
+        }
+    }
+
+    /// Advances the test clock to the next pending clock wait and releases it. Returns false
+    /// if there was no pending clock wait.
+    pub fn crank_single_clock_wait(&mut self) -> bool {
+        unsafe { from_glib(gst_check_ffi::gst_harness_crank_single_clock_wait(self.0)) }
+    }
+
+    /// Waits for `waits` clock waits to be pending, then advances the test clock and releases
+    /// all of them.
+    pub fn crank_multiple_clock_waits(&mut self, waits: u32) -> bool {
+        unsafe {
+            from_glib(gst_check_ffi::gst_harness_crank_multiple_clock_waits(
+                self.0,
+                waits,
+            ))
+        }
+    }
+
+    /// Sets the time of the test clock without releasing any clock waits.
+    pub fn set_time(&mut self, time: gst::ClockTime) -> bool {
+        unsafe { from_glib(gst_check_ffi::gst_harness_set_time(self.0, time.to_glib())) }
+    }
+
+    pub fn set_upstream_latency(&mut self, latency: gst::ClockTime) {
+        unsafe {
+            gst_check_ffi::gst_harness_set_upstream_latency(self.0, latency.to_glib());
+        }
+    }
+
+    pub fn query_latency(&self) -> gst::ClockTime {
+        unsafe { from_glib(gst_check_ffi::gst_harness_query_latency(self.0)) }
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        unsafe {
+            gst_check_ffi::gst_harness_teardown(self.0);
+        }
+    }
+}
+
+/// Initializes GStreamer and makes the plugins built by the current workspace available.
+///
+/// Call this from integration tests of plugin crates before creating any of their elements.
+pub fn init() {
+    use std::sync::{Once, ONCE_INIT};
+    static INIT: Once = ONCE_INIT;
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+
+        let dir = if cfg!(debug_assertions) {
+            "target/debug"
+        } else {
+            "target/release"
+        };
+
+        let mut path = Path::new(dir).to_path_buf();
+        if !path.exists() {
+            path = Path::new("..").join(dir);
+        }
+
+        gst::Registry::get().scan_path(&path);
+    });
+}
+
+/// Output of the element under test in a `TestPipeline`.
+#[derive(Debug)]
+pub enum TestOutput {
+    Buffer(gst::Buffer),
+    Event(gst::Event),
+}
+
+/// Pipeline of the form `<src> ! <element> ! fakesink` for end-to-end testing of elements.
+///
+/// Everything the element outputs is collected and can be retrieved with `pull_buffer()` and
+/// `pull_event()`. Errors posted on the bus make these functions panic.
+///
+/// ```ignore
+/// let mut p = TestPipeline::new("videotestsrc num-buffers=5", "rsvideofilter");
+/// p.start();
+/// let buffers = p.pull_buffers(5, Duration::from_secs(5));
+/// assert_timestamps(&buffers, 0.into(), gst::SECOND / 30);
+/// p.assert_caps(&gst::Caps::from_string("video/x-raw,format=GRAY8").unwrap());
+/// assert!(p.wait_eos(Duration::from_secs(5)));
+/// ```
+pub struct TestPipeline {
+    pipeline: gst::Element,
+    element: gst::Element,
+    receiver: mpsc::Receiver<TestOutput>,
+    caps: Option<gst::Caps>,
+    eos: bool,
+}
+
+impl TestPipeline {
+    /// Creates a pipeline from two `gst-launch` style descriptions for the source part and
+    /// the element under test. The element under test is named "test-element".
+    pub fn new(src: &str, element: &str) -> TestPipeline {
+        init();
+
+        let pipeline = gst::parse_launch(&format!(
+            "{} ! {} name=test-element ! fakesink name=test-sink sync=false async=false",
+            src, element
+        )).expect("Failed to create pipeline");
+
+        let bin = pipeline.clone().dynamic_cast::<gst::Bin>().unwrap();
+        let element = bin.get_by_name("test-element").unwrap();
+        let sink = bin.get_by_name("test-sink").unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        sink.get_static_pad("sink").unwrap().add_probe(
+            gst::PadProbeType::BUFFER | gst::PadProbeType::EVENT_DOWNSTREAM,
+            move |_, probe_info| {
+                let output = match probe_info.data {
+                    Some(gst::PadProbeData::Buffer(ref buffer)) => TestOutput::Buffer(buffer.clone()),
+                    Some(gst::PadProbeData::Event(ref event)) => TestOutput::Event(event.clone()),
+                    _ => return gst::PadProbeReturn::Ok,
+                };
+                let _ = sender.lock().unwrap().send(output);
+
+                gst::PadProbeReturn::Ok
+            },
+        );
+
+        TestPipeline {
+            pipeline: pipeline,
+            element: element,
+            receiver: receiver,
+            caps: None,
+            eos: false,
+        }
+    }
+
+    pub fn get_pipeline(&self) -> &gst::Element {
+        &self.pipeline
+    }
+
+    /// Returns the element under test.
+    pub fn get_element(&self) -> &gst::Element {
+        &self.element
+    }
+
+    pub fn start(&mut self) {
+        assert_ne!(
+            self.pipeline.set_state(gst::State::Playing),
+            gst::StateChangeReturn::Failure
+        );
+    }
+
+    pub fn stop(&mut self) {
+        assert_ne!(
+            self.pipeline.set_state(gst::State::Null),
+            gst::StateChangeReturn::Failure
+        );
+    }
+
+    fn check_bus(&self) {
+        let bus = self.pipeline.get_bus().unwrap();
+        while let Some(msg) = bus.pop() {
+            if let gst::MessageView::Error(err) = msg.view() {
+                panic!(
+                    "Error from {:?}: {} ({:?})",
+                    msg.get_src().map(|s| s.get_path_string()),
+                    err.get_error(),
+                    err.get_debug()
+                );
+            }
+        }
+    }
+
+    fn pull(&mut self, timeout: Duration) -> Option<TestOutput> {
+        self.check_bus();
+
+        let output = self.receiver.recv_timeout(timeout).ok();
+        if let Some(TestOutput::Event(ref event)) = output {
+            match event.view() {
+                gst::EventView::Caps(e) => self.caps = Some(e.get_caps().to_owned()),
+                gst::EventView::Eos(..) => self.eos = true,
+                _ => (),
+            }
+        }
+
+        output
+    }
+
+    /// Waits for the next buffer, skipping all events before it.
+    pub fn pull_buffer(&mut self, timeout: Duration) -> Option<gst::Buffer> {
+        loop {
+            match self.pull(timeout) {
+                None => return None,
+                Some(TestOutput::Buffer(buffer)) => return Some(buffer),
+                Some(TestOutput::Event(ref event)) if event.get_type() == gst::EventType::Eos => {
+                    return None
+                }
+                Some(TestOutput::Event(..)) => (),
+            }
+        }
+    }
+
+    /// Waits for `n` buffers and panics if they did not arrive in time.
+    pub fn pull_buffers(&mut self, n: usize, timeout: Duration) -> Vec<gst::Buffer> {
+        (0..n)
+            .map(|i| {
+                self.pull_buffer(timeout)
+                    .unwrap_or_else(|| panic!("Got only {} of {} buffers", i, n))
+            })
+            .collect()
+    }
+
+    /// Waits for the next event, skipping all buffers before it.
+    pub fn pull_event(&mut self, timeout: Duration) -> Option<gst::Event> {
+        loop {
+            match self.pull(timeout) {
+                None => return None,
+                Some(TestOutput::Event(event)) => return Some(event),
+                Some(TestOutput::Buffer(..)) => (),
+            }
+        }
+    }
+
+    /// Waits until EOS arrived at the sink, dropping all data before it.
+    pub fn wait_eos(&mut self, timeout: Duration) -> bool {
+        while !self.eos {
+            if self.pull(timeout).is_none() {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Returns the last caps the element under test output.
+    pub fn get_caps(&self) -> Option<&gst::Caps> {
+        self.caps.as_ref()
+    }
+
+    /// Asserts that the last caps output by the element are a subset of `caps`.
+    pub fn assert_caps(&self, caps: &gst::Caps) {
+        let current = self.caps.as_ref().expect("No caps received");
+        assert!(
+            current.is_subset(caps),
+            "Caps {} are not a subset of {}",
+            current.to_string(),
+            caps.to_string()
+        );
+    }
+}
+
+impl Drop for TestPipeline {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+}
+
+/// Asserts that the buffers have consecutive timestamps starting at `start`, each of them
+/// `duration` long.
+pub fn assert_timestamps(buffers: &[gst::Buffer], start: gst::ClockTime, duration: gst::ClockTime) {
+    let mut expected = start;
+    for (i, buffer) in buffers.iter().enumerate() {
+        assert_eq!(buffer.get_pts(), expected, "Wrong PTS for buffer {}", i);
+        assert_eq!(
+            buffer.get_duration(),
+            duration,
+            "Wrong duration for buffer {}",
+            i
+        );
+        expected = expected + duration;
+    }
+}
+
+fn assert_initialized() {
+    // Harnesses are only used from tests, so initialize GStreamer on demand
+    gst::init().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity() {
+        gst::init().unwrap();
+
+        let mut h = Harness::new("identity");
+        h.set_src_caps_str("application/x-test");
+
+        let mut buffer = gst::Buffer::from_slice(vec![1u8, 2, 3, 4]).unwrap();
+        buffer.get_mut().unwrap().set_pts(gst::SECOND);
+
+        let output = h.push_and_pull(buffer).unwrap();
+        assert_eq!(output.get_pts(), gst::SECOND);
+        assert_eq!(h.buffers_received(), 1);
+
+        // stream-start, caps, segment
+        assert_eq!(h.events_received(), 3);
+    }
+
+    #[test]
+    fn test_pipeline_identity() {
+        let mut p = TestPipeline::new(
+            "fakesrc num-buffers=3 sizetype=fixed sizemax=16 datarate=1600",
+            "identity",
+        );
+        p.start();
+
+        let buffers = p.pull_buffers(3, Duration::from_secs(5));
+        assert_timestamps(&buffers, 0.into(), 10 * gst::MSECOND);
+        assert!(p.wait_eos(Duration::from_secs(5)));
+        p.stop();
+    }
+}